@@ -350,6 +350,7 @@ async fn launch_workers(
                         listener,
                         framebuffer,
                         input_send,
+                        resolution_send: None,
                     },
                 )
                 .await?,
@@ -390,6 +391,7 @@ async fn launch_workers(
                         } else {
                             debug_worker_defs::TargetArch::Aarch64
                         },
+                        vtl: 0,
                     },
                 )
                 .await?,
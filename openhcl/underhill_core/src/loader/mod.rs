@@ -274,6 +274,10 @@ fn load_linux(params: LoadLinuxParams<'_>) -> Result<VpContext, Error> {
         with_pic: false,
         with_pit: false,
         with_psp: platform_config.general.psp_enabled,
+        with_s3: false,
+        with_s4: false,
+        pci_ecam: None,
+        ssdt: None,
         pm_base: crate::worker::PM_BASE,
         acpi_irq: crate::worker::SYSTEM_IRQ_ACPI,
     };
@@ -468,6 +472,10 @@ pub fn write_uefi_config(
             with_pic: false,                          // uefi never runs with pic or pit
             with_pit: false,
             with_psp: platform_config.general.psp_enabled,
+            with_s3: false,
+            with_s4: false,
+            pci_ecam: None,
+            ssdt: None,
             pm_base: crate::worker::PM_BASE,
             acpi_irq: crate::worker::SYSTEM_IRQ_ACPI,
         };
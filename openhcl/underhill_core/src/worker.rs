@@ -1948,6 +1948,10 @@ async fn new_underhill_vm(
                 with_pic: true,    // pcat always runs with pic and pit
                 with_pit: true,
                 with_psp: dps.general.psp_enabled,
+                with_s3: false,
+                with_s4: false,
+                pci_ecam: None,
+                ssdt: None,
                 pm_base: PM_BASE,
                 acpi_irq: SYSTEM_IRQ_ACPI,
             };
@@ -2533,6 +2537,8 @@ async fn new_underhill_vm(
                 register_layout,
                 guest_secret_key: platform_attestation_data.guest_secret_key,
                 logger: Some(GetTpmLoggerHandle.into_resource()),
+                pcr_banks: None,
+                pcr_seeds: Vec::new(),
             }
             .into_resource(),
         });
@@ -2547,6 +2553,9 @@ async fn new_underhill_vm(
                 pm_timer_assist: Some(Box::new(UnderhillPmTimerAssist {
                     partition: Arc::downgrade(&partition),
                 })),
+                wake_recv: None,
+                gpe_recv: None,
+                wake_request_send: None,
             });
 
     let devices = BaseChipsetDevices {
@@ -2912,6 +2921,7 @@ async fn new_underhill_vm(
         vmbus_device_handles.push(
             uidevices_resources::SynthVideoHandle {
                 framebuffer: video_core::SharedFramebufferHandle.into_resource(),
+                resolution_recv: None,
             }
             .into_resource(),
         );
@@ -3014,6 +3024,7 @@ async fn new_underhill_vm(
             client_notify_send: halt_notify_send,
             vtl_guest_memory: [Some(gm.vtl0()), gm.vtl1(), None],
             debugger_rpc,
+            vtl2_debugger_rpc: None,
         },
     )
     .context("failed to create partition unit")?;
@@ -3244,6 +3255,12 @@ enum HaltRequest {
             HaltReason::PowerOff => HaltRequest::PowerOff,
             HaltReason::Reset => HaltRequest::Reset,
             HaltReason::Hibernate => HaltRequest::Hibernate,
+            HaltReason::Suspend => {
+                // OpenHCL does not advertise S3 to the guest, so there is no
+                // way to resume from it.
+                tracing::warn!(CVM_ALLOWED, "unsupported guest suspend request");
+                continue;
+            }
             HaltReason::TripleFault { vp, registers } => {
                 tracing::info!(CVM_ALLOWED, vp, "triple fault");
                 let reg_state = build_vp_state(registers.as_deref());
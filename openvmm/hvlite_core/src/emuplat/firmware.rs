@@ -13,11 +13,22 @@
 #[derive(Debug)]
 pub struct MeshLogger {
     sender: Option<mesh::Sender<FirmwareEvent>>,
+    boot_success_send: Option<mesh::Sender<()>>,
 }
 
 impl MeshLogger {
     pub fn new(sender: Option<mesh::Sender<FirmwareEvent>>) -> Self {
-        Self { sender }
+        Self {
+            sender,
+            boot_success_send: None,
+        }
+    }
+
+    /// Additionally notifies `send` when the firmware reports a successful
+    /// boot.
+    pub fn with_boot_success_notify(mut self, send: mesh::Sender<()>) -> Self {
+        self.boot_success_send = Some(send);
+        self
     }
 
     fn send(&self, event: FirmwareEvent) {
@@ -30,7 +41,12 @@ fn send(&self, event: FirmwareEvent) {
 impl UefiLogger for MeshLogger {
     fn log_event(&self, event: UefiEvent) {
         let event = match event {
-            UefiEvent::BootSuccess(_) => FirmwareEvent::BootSuccess,
+            UefiEvent::BootSuccess(_) => {
+                if let Some(send) = &self.boot_success_send {
+                    send.send(());
+                }
+                FirmwareEvent::BootSuccess
+            }
             UefiEvent::BootFailure(_) => FirmwareEvent::BootFailed,
             UefiEvent::NoBootDevice => FirmwareEvent::NoBootDevice,
         };
@@ -0,0 +1,45 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use vmcore::non_volatile_store::NonVolatileStore;
+use vmcore::non_volatile_store::NonVolatileStoreError;
+
+/// A [`NonVolatileStore`] backed by a standalone host file.
+///
+/// The entire file holds the data. An empty file is treated as containing no
+/// data.
+pub struct FileNonVolatileStore(File);
+
+impl FileNonVolatileStore {
+    pub fn new(file: File) -> Self {
+        Self(file)
+    }
+}
+
+#[async_trait::async_trait]
+impl NonVolatileStore for FileNonVolatileStore {
+    async fn persist(&mut self, data: Vec<u8>) -> Result<(), NonVolatileStoreError> {
+        self.0.rewind().map_err(NonVolatileStoreError::new)?;
+        self.0
+            .write_all(&data)
+            .map_err(NonVolatileStoreError::new)?;
+        self.0
+            .set_len(data.len() as u64)
+            .map_err(NonVolatileStoreError::new)?;
+        self.0.sync_data().map_err(NonVolatileStoreError::new)?;
+        Ok(())
+    }
+
+    async fn restore(&mut self) -> Result<Option<Vec<u8>>, NonVolatileStoreError> {
+        let mut data = Vec::new();
+        self.0.rewind().map_err(NonVolatileStoreError::new)?;
+        self.0
+            .read_to_end(&mut data)
+            .map_err(NonVolatileStoreError::new)?;
+        Ok((!data.is_empty()).then_some(data))
+    }
+}
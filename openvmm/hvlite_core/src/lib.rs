@@ -12,6 +12,7 @@
 #![forbid(unsafe_code)]
 
 mod emuplat;
+mod file_non_volatile_store;
 mod partition;
 mod vmgs_non_volatile_store;
 mod worker;
@@ -2,10 +2,12 @@
 // Licensed under the MIT License.
 
 use crate::emuplat;
+use crate::file_non_volatile_store::FileNonVolatileStore;
 use crate::partition::BindHvliteVp;
 use crate::partition::HvlitePartition;
 use crate::vmgs_non_volatile_store::HvLiteVmgsNonVolatileStore;
 use crate::worker::rom::RomBuilder;
+use crate::worker::vp_affinity::VpThread;
 use acpi::dsdt;
 use anyhow::Context;
 use cfg_if::cfg_if;
@@ -13,6 +15,7 @@
 use debug_ptr::DebugPtr;
 use disk_backend::Disk;
 use disk_backend::resolve::ResolveDiskParameters;
+use e1000::E1000;
 use firmware_uefi::UefiCommandSet;
 use floppy_resources::FloppyDiskConfig;
 use futures::FutureExt;
@@ -27,14 +30,25 @@
 use hvlite_defs::config::Aarch64TopologyConfig;
 use hvlite_defs::config::ArchTopologyConfig;
 use hvlite_defs::config::Config;
+use hvlite_defs::config::CpuidOverride;
 use hvlite_defs::config::DeviceVtl;
+use hvlite_defs::config::E1000NicConfig;
 use hvlite_defs::config::GicConfig;
+use hvlite_defs::config::GuestAccessTraceConfig;
 use hvlite_defs::config::Hypervisor;
 use hvlite_defs::config::HypervisorConfig;
+use hvlite_defs::config::IoTraceConfig;
+use hvlite_defs::config::IvshmemConfig;
 use hvlite_defs::config::LoadMode;
 use hvlite_defs::config::MemoryConfig;
+use hvlite_defs::config::MemoryNumaPolicy;
+use hvlite_defs::config::NvmeSriovConfig;
+use hvlite_defs::config::PciSerialCardConfig;
+use hvlite_defs::config::PciSlotConfig;
 use hvlite_defs::config::ProcessorTopologyConfig;
 use hvlite_defs::config::SerialPipes;
+use hvlite_defs::config::SmbiosConfig;
+use hvlite_defs::config::VfioDeviceConfig;
 use hvlite_defs::config::VirtioBus;
 use hvlite_defs::config::VmbusConfig;
 use hvlite_defs::config::VpciDeviceConfig;
@@ -56,6 +70,7 @@
 use local_clock::LocalClockDelta;
 use membacking::GuestMemoryBuilder;
 use membacking::GuestMemoryManager;
+use membacking::NumaPolicy;
 use membacking::SharedMemoryBacking;
 use memory_range::MemoryRange;
 use mesh::MeshPayload;
@@ -66,17 +81,24 @@
 use mesh_worker::WorkerId;
 use mesh_worker::WorkerRpc;
 use missing_dev::MissingDevManifest;
+use net_backend::resolve::ResolveEndpointParams;
+use nvme::NvmeController;
+use nvme::NvmeControllerCaps;
+use nvme_resources::NamespaceDefinition;
 use pal_async::DefaultDriver;
 use pal_async::DefaultPool;
 use pal_async::local::block_with_io;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
 use pci_core::PciInterruptPin;
 use pci_core::msi::MsiInterruptSet;
 use scsi_core::ResolveScsiDeviceHandleParams;
 use scsidisk::SimpleScsiDisk;
 use scsidisk::atapi_scsi::AtapiScsiDisk;
+use serial_16550::pci::Serial16550PciCard;
 use serial_16550_resources::ComPort;
+use serial_core::resources::ResolveSerialBackendParams;
 use state_unit::SavedStateUnit;
 use state_unit::SpawnedUnit;
 use state_unit::StateUnits;
@@ -86,8 +108,10 @@
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 use storvsp::ScsiControllerDisk;
 use tracing_helpers::ErrorValueExt;
+use usb_core::ResolveUsbDeviceHandleParams;
 use virt::ProtoPartition;
 use virt::VpIndex;
 use virtio::LegacyWrapper;
@@ -102,6 +126,7 @@
 use vm_resource::kind::DiskHandleKind;
 use vm_resource::kind::KeyboardInputHandleKind;
 use vm_resource::kind::MouseInputHandleKind;
+use vm_resource::kind::UsbDeviceHandleKind;
 use vm_resource::kind::VirtioDeviceHandle;
 use vm_resource::kind::VmbusDeviceHandleKind;
 use vm_topology::memory::MemoryLayout;
@@ -116,6 +141,8 @@
 use vmbus_server::HvsockRelayChannel;
 use vmbus_server::VmbusServer;
 use vmbus_server::hvsock::HvsockRelay;
+use vmbus_server::hvsock::VmSocketBridge;
+use vmcore::non_volatile_store::NonVolatileStore;
 use vmcore::save_restore::SavedStateRoot;
 use vmcore::vm_task::VmTaskDriverSource;
 use vmcore::vm_task::thread::ThreadDriverBackend;
@@ -140,13 +167,17 @@
 use vmotherboard::BaseChipsetBuilderOutput;
 use vmotherboard::ChipsetDeviceHandle;
 use vmotherboard::ChipsetDevices;
+use vmotherboard::GuestAccessTrace;
+use vmotherboard::IoTrace;
 use vmotherboard::options::BaseChipsetDevices;
 use vmotherboard::options::BaseChipsetFoundation;
 use vmotherboard::options::BaseChipsetManifest;
 use vpci::bus::VpciBus;
+use vpci::bus::VpciHotPlugHandle;
 use watchdog_core::platform::BaseWatchdogPlatform;
 use watchdog_core::platform::WatchdogCallback;
 use watchdog_core::platform::WatchdogPlatform;
+use xhci::XhciController;
 
 const PM_BASE: u16 = 0x400;
 const SYSTEM_IRQ_ACPI: u32 = 9;
@@ -178,6 +209,12 @@ fn from_config(config: Config) -> Self {
             virtio_console_pci: config.virtio_console_pci,
             virtio_serial: config.virtio_serial,
             virtio_devices: config.virtio_devices,
+            usb_devices: config.usb_devices,
+            e1000_nics: config.e1000_nics,
+            pci_serial_cards: config.pci_serial_cards,
+            ivshmem_devices: config.ivshmem_devices,
+            nvme_sriov: config.nvme_sriov,
+            vfio_devices: config.vfio_devices,
             vmbus: config.vmbus,
             vtl2_vmbus: config.vtl2_vmbus,
             #[cfg(all(windows, feature = "virt_whp"))]
@@ -186,12 +223,26 @@ fn from_config(config: Config) -> Self {
             secure_boot_enabled: config.secure_boot_enabled,
             custom_uefi_vars: config.custom_uefi_vars,
             firmware_event_send: config.firmware_event_send,
+            guest_crash_send: config.guest_crash_send,
             debugger_rpc: config.debugger_rpc,
+            vtl2_debugger_rpc: config.vtl2_debugger_rpc,
             vmbus_devices: config.vmbus_devices,
             chipset_devices: config.chipset_devices,
             generation_id_recv: config.generation_id_recv,
             rtc_delta_milliseconds: config.rtc_delta_milliseconds,
             automatic_guest_reset: config.automatic_guest_reset,
+            enable_s3: config.enable_s3,
+            enable_s4: config.enable_s4,
+            resume_from_hibernate: config.resume_from_hibernate,
+            pci_hotplug_slots: config.pci_hotplug_slots,
+            pci_slots: config.pci_slots,
+            pci_bridges: config.pci_bridges,
+            pvpanic: config.pvpanic,
+            smbios: config.smbios,
+            vp_affinity: config.vp_affinity,
+            cpuid: config.cpuid,
+            io_trace: config.io_trace,
+            access_trace: config.access_trace,
         }
     }
 }
@@ -219,6 +270,12 @@ pub struct Manifest {
     virtio_console_pci: bool,
     virtio_serial: Option<SerialPipes>,
     virtio_devices: Vec<(VirtioBus, Resource<VirtioDeviceHandle>)>,
+    usb_devices: Vec<Resource<UsbDeviceHandleKind>>,
+    e1000_nics: Vec<E1000NicConfig>,
+    pci_serial_cards: Vec<PciSerialCardConfig>,
+    ivshmem_devices: Vec<IvshmemConfig>,
+    nvme_sriov: Option<NvmeSriovConfig>,
+    vfio_devices: Vec<VfioDeviceConfig>,
     vmbus: Option<VmbusConfig>,
     vtl2_vmbus: Option<VmbusConfig>,
     #[cfg(all(windows, feature = "virt_whp"))]
@@ -227,12 +284,26 @@ pub struct Manifest {
     secure_boot_enabled: bool,
     custom_uefi_vars: firmware_uefi_custom_vars::CustomVars,
     firmware_event_send: Option<mesh::Sender<get_resources::ged::FirmwareEvent>>,
+    guest_crash_send: Option<mesh::Sender<vmm_core_defs::GuestCrash>>,
     debugger_rpc: Option<mesh::Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
+    vtl2_debugger_rpc: Option<mesh::Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
     vmbus_devices: Vec<(DeviceVtl, Resource<VmbusDeviceHandleKind>)>,
     chipset_devices: Vec<ChipsetDeviceHandle>,
     generation_id_recv: Option<mesh::Receiver<[u8; 16]>>,
     rtc_delta_milliseconds: i64,
     automatic_guest_reset: bool,
+    enable_s3: bool,
+    enable_s4: bool,
+    resume_from_hibernate: bool,
+    pci_hotplug_slots: Vec<u8>,
+    pci_slots: Vec<PciSlotConfig>,
+    pci_bridges: u8,
+    pvpanic: bool,
+    smbios: SmbiosConfig,
+    vp_affinity: Vec<(u32, Vec<u32>)>,
+    cpuid: Vec<CpuidOverride>,
+    io_trace: Option<IoTraceConfig>,
+    access_trace: Option<GuestAccessTraceConfig>,
 }
 
 #[derive(Protobuf, SavedStateRoot)]
@@ -523,7 +594,9 @@ struct LoadedVmInner {
     processor_topology: ProcessorTopology,
     hypervisor_cfg: HypervisorConfig,
     vmbus_redirect: bool,
-    vmbus_devices: Vec<SpawnedUnit<ChannelUnit<dyn VmbusDevice>>>,
+    vmbus_devices: Vec<(Guid, SpawnedUnit<ChannelUnit<dyn VmbusDevice>>)>,
+    /// The configured VPCI devices, which can be revoked and offered again.
+    vpci_devices: Vec<(Guid, DeviceVtl, VpciHotPlugHandle)>,
 
     input_distributor: SpawnedUnit<InputDistributor>,
     vtl2_framebuffer_gpa_base: Option<u64>,
@@ -538,8 +611,9 @@ struct LoadedVmInner {
     virtio_mmio_irq: u32,
     /// ((device, function), interrupt)
     #[cfg_attr(not(guest_arch = "x86_64"), expect(dead_code))]
-    pci_legacy_interrupts: Vec<((u8, Option<u8>), u32)>,
+    pci_legacy_interrupts: Vec<((u8, Option<u8>, u8), u32)>,
     firmware_event_send: Option<mesh::Sender<get_resources::ged::FirmwareEvent>>,
+    guest_crash_send: Option<mesh::Sender<vmm_core_defs::GuestCrash>>,
 
     load_mode: LoadMode,
     igvm_file: Option<IgvmFile>,
@@ -549,9 +623,211 @@ struct LoadedVmInner {
 
     // relay halt messages, intercepting reset if configured.
     halt_recv: mesh::Receiver<HaltReason>,
+    /// used to halt the VPs on behalf of the client
+    halt_vps: Arc<Halt>,
     client_notify_send: mesh::Sender<HaltReason>,
     /// allow the guest to reset without notifying the client
     automatic_guest_reset: bool,
+    /// advertise S3 to the guest
+    enable_s3: bool,
+    /// advertise S4 to the guest
+    enable_s4: bool,
+    /// the BIOS GUID reported to UEFI, which must be stable across hibernation
+    bios_guid: Guid,
+    /// where to record the VM configuration when the guest hibernates
+    hibernate_store: Option<Box<dyn NonVolatileStore>>,
+    /// signaled when the firmware boots after resuming from hibernate, at
+    /// which point the hibernate state is cleared
+    hibernate_resumed_recv: Option<mesh::Receiver<()>>,
+    /// the guest's FACS, if the VMM built the ACPI tables
+    facs_gpa: Option<u64>,
+    /// set while the guest is in S3, waiting for a wake event
+    suspended: bool,
+    /// set while the guest is powered off, until the VM is reset or woken
+    powered_off: bool,
+    /// wake events to deliver to the PM device
+    pm_wake_send: mesh::Sender<chipset_resources::pm::WakeEvent>,
+    /// requests to wake the guest from S3 or power it on, from the PM device
+    /// or a scheduled wake
+    wake_request_send: mesh::Sender<chipset_resources::pm::WakeEvent>,
+    wake_request_recv: mesh::Receiver<chipset_resources::pm::WakeEvent>,
+    /// the task that requests a wake at the time scheduled by the client
+    scheduled_wake: Option<Task<()>>,
+    /// general-purpose events to deliver to the PM device, if there is one
+    pm_gpe_send: Option<mesh::Sender<u32>>,
+    /// PCI device numbers of the ACPI hot-plug slots
+    pci_hotplug_slots: Vec<u8>,
+    /// describe the pvpanic device in the SSDT
+    pvpanic: bool,
+    /// SMBIOS values reported by the firmware
+    smbios: SmbiosConfig,
+    /// sets the requested size of the virtio-mem device
+    virtio_mem_resize: Option<mesh::Sender<u64>>,
+    /// sets the target size, in pages, of the virtio-balloon device
+    virtio_balloon_target: Option<mesh::Sender<u32>>,
+    /// the VP backing threads, by VP index
+    vp_threads: Vec<VpThread>,
+    /// the host CPUs each VP is pinned to, or empty if it is not pinned
+    vp_affinity: Vec<Vec<u32>>,
+    /// the user-specified CPUID results
+    cpuid: Vec<CpuidOverride>,
+}
+
+/// Translates `gva` to a guest physical address by walking the page tables
+/// that `registers` point to.
+#[cfg(guest_arch = "x86_64")]
+fn translate_gva(
+    gm: &GuestMemory,
+    registers: &virt::vp::Registers,
+    gva: u64,
+) -> anyhow::Result<u64> {
+    use virt_support_x86emu::translate;
+
+    let registers = translate::TranslationRegisters {
+        cr0: registers.cr0,
+        cr4: registers.cr4,
+        efer: registers.efer,
+        cr3: registers.cr3,
+        rflags: registers.rflags,
+        ss: registers.ss.into(),
+        // Encrypted memory is not supported, so there is no risk of reading
+        // page tables from shared memory.
+        encryption_mode: translate::EncryptionMode::None,
+    };
+    let flags = translate::TranslateFlags {
+        validate_execute: false,
+        validate_read: false,
+        validate_write: false,
+        override_smap: false,
+        enforce_smap: false,
+        privilege_check: translate::TranslatePrivilegeCheck::None,
+        set_page_table_bits: false,
+    };
+    let result = translate::translate_gva_to_gpa(gm, gva, &registers, flags)
+        .with_context(|| format!("failed to translate {gva:#x}"))?;
+    Ok(result.gpa)
+}
+
+#[cfg(guest_arch = "aarch64")]
+fn translate_gva(
+    _gm: &GuestMemory,
+    _registers: &virt::vp::Registers,
+    _gva: u64,
+) -> anyhow::Result<u64> {
+    anyhow::bail!("address translation is not supported on aarch64")
+}
+
+/// Returns the guest physical address range of the virtio-mem device's
+/// memory, which is placed after all RAM and MMIO, aligned to 1GB.
+fn virtio_mem_range(mem_layout: &MemoryLayout, hotplug_size: u64) -> Option<MemoryRange> {
+    const ALIGNMENT: u64 = 1 << 30;
+    if hotplug_size == 0 {
+        return None;
+    }
+    let start = mem_layout.end_of_ram_or_mmio().next_multiple_of(ALIGNMENT);
+    Some(MemoryRange::new(
+        start..start + hotplug_size.next_multiple_of(virtio_mem::BLOCK_SIZE),
+    ))
+}
+
+/// Assigns devices to slots on the emulated PCI bus.
+///
+/// Devices with a fixed placement in the configuration get that slot. The
+/// others get function 0 of the next free device number, skipping the
+/// hot-plug slots and the device numbers used by fixed placements.
+struct PciSlotAllocator {
+    next_device: u8,
+    reserved: Vec<u8>,
+    fixed: Vec<(PciSlotConfig, bool)>,
+}
+
+impl PciSlotAllocator {
+    /// Creates an allocator for bus 0 and, through `bridges` bridges, buses
+    /// `1..=bridges`.
+    fn new(
+        first_device: u8,
+        hotplug_slots: &[u8],
+        bridges: u8,
+        fixed: Vec<PciSlotConfig>,
+    ) -> anyhow::Result<Self> {
+        for slot in &fixed {
+            anyhow::ensure!(
+                slot.bus <= bridges && slot.device_number < 32 && slot.function < 8,
+                "invalid PCI slot {:02x}:{:02x}.{} for {}",
+                slot.bus,
+                slot.device_number,
+                slot.function,
+                slot.device
+            );
+            anyhow::ensure!(
+                slot.bus != 0 || !hotplug_slots.contains(&slot.device_number),
+                "PCI slot {:02x} for {} is a hot-plug slot",
+                slot.device_number,
+                slot.device
+            );
+            anyhow::ensure!(
+                fixed.iter().any(|s| s.bus == slot.bus
+                    && s.device_number == slot.device_number
+                    && s.function == 0),
+                "PCI slot {:02x}:{:02x} for {} has no function 0",
+                slot.bus,
+                slot.device_number,
+                slot.device
+            );
+        }
+        Ok(Self {
+            next_device: first_device,
+            reserved: hotplug_slots
+                .iter()
+                .copied()
+                .chain(
+                    fixed
+                        .iter()
+                        .filter(|slot| slot.bus == 0)
+                        .map(|slot| slot.device_number),
+                )
+                .collect(),
+            fixed: fixed.into_iter().map(|slot| (slot, false)).collect(),
+        })
+    }
+
+    /// Returns the bus, device, and function number for the device `name`.
+    /// Devices without a fixed placement go on bus 0.
+    fn allocate(&mut self, name: &str) -> anyhow::Result<(u8, u8, u8)> {
+        if let Some((slot, used)) = self.fixed.iter_mut().find(|(slot, _)| slot.device == name) {
+            *used = true;
+            return Ok((slot.bus, slot.device_number, slot.function));
+        }
+        while self.reserved.contains(&self.next_device) {
+            self.next_device += 1;
+        }
+        anyhow::ensure!(self.next_device < 32, "no free PCI slot for {name}");
+        let device_number = self.next_device;
+        self.next_device += 1;
+        Ok((0, device_number, 0))
+    }
+
+    /// Fails if a fixed placement is for a device that was not added.
+    fn finish(self) -> anyhow::Result<()> {
+        if let Some((slot, _)) = self.fixed.iter().find(|(_, used)| !used) {
+            anyhow::bail!("no PCI device named {}", slot.device);
+        }
+        Ok(())
+    }
+}
+
+/// Delivers MSIs from devices on the emulated PCI bus directly to VTL0.
+#[cfg(guest_arch = "x86_64")]
+struct PartitionMsiTarget(Arc<dyn HvlitePartition>);
+
+#[cfg(guest_arch = "x86_64")]
+impl pci_core::msi::MsiInterruptTarget for PartitionMsiTarget {
+    fn new_interrupt(&self) -> Box<dyn pci_core::msi::MsiControl> {
+        let partition = self.0.clone();
+        Box::new(move |address: u64, data: u32| {
+            partition.request_msi(Vtl::Vtl0, virt::irqcon::MsiRequest { address, data })
+        })
+    }
 }
 
 fn choose_hypervisor() -> anyhow::Result<Hypervisor> {
@@ -677,6 +953,12 @@ async fn new(
                 guest_arch = "x86_64"
             ))]
             Hypervisor::MsHv => {
+                // The hypervisor handles the guest crash MSRs itself and does
+                // not report crashes to the VMM.
+                anyhow::ensure!(
+                    !cfg.hypervisor.with_hv || cfg.guest_crash_send.is_none(),
+                    "guest crash reporting through the Hyper-V crash registers is not supported with mshv"
+                );
                 Self::new_with_hypervisor(
                     driver_source,
                     &mut virt_mshv::LinuxMshv,
@@ -831,6 +1113,16 @@ async fn new_with_hypervisor<P, H>(
             );
         }
 
+        if let Some(range) = virtio_mem_range(&mem_layout, cfg.memory.hotplug_size) {
+            if range.end() > 1 << physical_address_size {
+                anyhow::bail!(
+                    "hot-pluggable memory ends at {:#x}, which exceeds the address width of {} bits",
+                    range.end(),
+                    physical_address_size
+                );
+            }
+        }
+
         // Place the alias map at the end of the address space. Newer versions
         // of OpenHCL support receiving this offset via devicetree (especially
         // important on ARM64 where the physical address width used here is not
@@ -840,11 +1132,30 @@ async fn new_with_hypervisor<P, H>(
                 .then_some(1 << (physical_address_size - 1))
         });
 
+        let (backing_file, backing_file_shared) = match &cfg.memory.backing_file {
+            Some(backing) => (
+                Some(
+                    backing
+                        .file
+                        .try_clone()
+                        .context("failed to clone memory backing file")?,
+                ),
+                backing.shared,
+            ),
+            None => (None, false),
+        };
+
         let mut memory_builder = GuestMemoryBuilder::new();
         memory_builder = memory_builder
             .existing_backing(shared_memory)
             .vtl0_alias_map(vtl0_alias_map)
             .prefetch_ram(cfg.memory.prefetch_memory)
+            .hugetlb_page_size(cfg.memory.hugetlb_page_size)
+            .backing_file(backing_file, backing_file_shared)
+            .numa_policy(cfg.memory.numa_policy.clone().map(|policy| match policy {
+                MemoryNumaPolicy::Interleave(nodes) => NumaPolicy::Interleave(nodes),
+                MemoryNumaPolicy::Bind(nodes) => NumaPolicy::Bind(nodes),
+            }))
             .x86_legacy_support(
                 matches!(cfg.load_mode, LoadMode::Pcat { .. }) || cfg.chipset.with_hyperv_vga,
             );
@@ -897,6 +1208,16 @@ async fn new_with_hypervisor<P, H>(
         )
         .context("failed to compute topology cpuid")?;
 
+        // Add in user-specified CPUID results last, so that they take
+        // precedence.
+        cpuid.extend(cfg.cpuid.iter().map(|leaf| {
+            let mut cpuid = virt::CpuidLeaf::new(leaf.function, leaf.result).masked(leaf.mask);
+            if let Some(index) = leaf.index {
+                cpuid = cpuid.indexed(index);
+            }
+            cpuid
+        }));
+
         let (partition, vps) = proto
             .build(virt::PartitionConfig {
                 mem_layout: &mem_layout,
@@ -1031,6 +1352,29 @@ async fn load(
         let vmgs_client: Option<&dyn HvLiteVmgsNonVolatileStore> =
             vmgs_client.as_ref().map(|x| x as _);
 
+        let mut hibernate_store = vmgs_client
+            .map(|vmgs| vmgs.as_non_volatile_store(vmgs::FileId::HIBERNATE_STATE, false))
+            .transpose()
+            .context("failed to instantiate hibernate state store")?;
+
+        // The hibernate state is only cleared once the guest has resumed, so
+        // that a failed resume can be retried.
+        let mut hibernate_resumed = None;
+        let bios_guid = if cfg.resume_from_hibernate {
+            let store = hibernate_store
+                .as_deref_mut()
+                .context("resuming from hibernate requires a vmgs file")?;
+            let state = super::hibernate::read(store)
+                .await?
+                .context("no hibernate state found in the vmgs file")?;
+            state.validate(cfg.memory.mem_size, processor_topology.vp_count())?;
+            tracing::info!("resuming from hibernate");
+            hibernate_resumed = Some(mesh::channel());
+            state.bios_guid
+        } else {
+            cfg.smbios.uuid.unwrap_or_else(Guid::new_random)
+        };
+
         let (halt_vps, halt_request_recv) = Halt::new();
         let halt_vps = Arc::new(halt_vps);
 
@@ -1048,9 +1392,12 @@ async fn load(
 
         let generation_id_recv = cfg.generation_id_recv.unwrap_or_else(|| mesh::channel().1);
 
-        let logger = Box::new(emuplat::firmware::MeshLogger::new(
-            cfg.firmware_event_send.clone(),
-        ));
+        let mut logger = emuplat::firmware::MeshLogger::new(cfg.firmware_event_send.clone());
+        let hibernate_resumed_recv = hibernate_resumed.map(|(send, recv)| {
+            logger = logger.with_boot_success_notify(send);
+            recv
+        });
+        let logger = Box::new(logger);
 
         let mapper = memory_manager.device_memory_mapper();
 
@@ -1058,7 +1405,7 @@ async fn load(
         let mut deps_hyperv_firmware_pcat = None;
         let mut deps_hyperv_firmware_uefi = None;
         match &cfg.load_mode {
-            LoadMode::Uefi { .. } => {
+            LoadMode::Uefi { nvram, .. } => {
                 let (watchdog_send, watchdog_recv) = mesh::channel();
                 deps_hyperv_firmware_uefi = Some(dev::HyperVFirmwareUefi {
                     config: firmware_uefi::UefiConfig {
@@ -1082,15 +1429,24 @@ async fn load(
                         use uefi_nvram_storage::in_memory::InMemoryNvram;
                         use vmm_core::emuplat::hcl_compat_uefi_nvram_storage::VmgsStorageBackendAdapter;
 
-                        match vmgs_client {
-                            Some(vmgs) => Box::new(HclCompatNvram::new(
+                        match (vmgs_client, nvram) {
+                            (Some(vmgs), _) => Box::new(HclCompatNvram::new(
                                 VmgsStorageBackendAdapter(
                                     vmgs.as_non_volatile_store(vmgs::FileId::BIOS_NVRAM, true)
                                         .context("failed to instantiate UEFI NVRAM store")?,
                                 ),
                                 None,
                             )),
-                            None => Box::new(InMemoryNvram::new()),
+                            // Keep the original file in the load mode so that
+                            // the store can be reopened after a restart.
+                            (None, Some(file)) => Box::new(HclCompatNvram::new(
+                                VmgsStorageBackendAdapter(Box::new(FileNonVolatileStore::new(
+                                    file.try_clone()
+                                        .context("failed to clone UEFI NVRAM file")?,
+                                ))),
+                                None,
+                            )),
+                            (None, None) => Box::new(InMemoryNvram::new()),
                         }
                     },
                     generation_id_recv,
@@ -1154,6 +1510,10 @@ async fn load(
                             with_pic: cfg.chipset.with_generic_pic,
                             with_pit: cfg.chipset.with_generic_pit,
                             with_psp: cfg.chipset.with_generic_psp,
+                            with_s3: false,
+                            with_s4: false,
+                            pci_ecam: None,
+                            ssdt: None,
                             pm_base: PM_BASE,
                             acpi_irq: SYSTEM_IRQ_ACPI,
                         };
@@ -1185,22 +1545,31 @@ async fn load(
                                 })
                             },
                             num_lock_enabled: false,
-                            // TODO: these are all very bogus values, and need to be swapped out with something better
-                            smbios: firmware_pcat::config::SmbiosConstants {
-                                bios_guid: Guid {
-                                    data1: 0xC4066C45,
-                                    data2: 0x503D,
-                                    data3: 0x40E8,
-                                    data4: [0xB1, 0x5C, 0x31, 0x26, 0x4E, 0x5F, 0xE1, 0xD9],
-                                },
-                                system_serial_number: "9583-9572-9874-4843-7295-1653-92".into(),
-                                base_board_serial_number: "9583-9572-9874-4843-7295-1653-92".into(),
-                                chassis_serial_number: "9583-9572-9874-4843-7295-1653-92".into(),
-                                chassis_asset_tag: "9583-9572-9874-4843-7295-1653-92".into(),
-                                bios_lock_string: "00000000000000000000000000000000".into(),
-                                processor_manufacturer: b"\0".to_vec(),
-                                processor_version: b"\0".to_vec(),
-                                cpu_info_bundle: None,
+                            // TODO: the defaults are all very bogus values, and need to be swapped out with something better
+                            smbios: {
+                                const DEFAULT_SERIAL: &str = "9583-9572-9874-4843-7295-1653-92";
+                                let smbios = &cfg.smbios;
+                                let string = |s: &Option<String>| {
+                                    s.as_deref().unwrap_or(DEFAULT_SERIAL).as_bytes().to_vec()
+                                };
+                                firmware_pcat::config::SmbiosConstants {
+                                    bios_guid: smbios.uuid.unwrap_or(Guid {
+                                        data1: 0xC4066C45,
+                                        data2: 0x503D,
+                                        data3: 0x40E8,
+                                        data4: [0xB1, 0x5C, 0x31, 0x26, 0x4E, 0x5F, 0xE1, 0xD9],
+                                    }),
+                                    system_serial_number: string(&smbios.system_serial_number),
+                                    base_board_serial_number: string(
+                                        &smbios.base_board_serial_number,
+                                    ),
+                                    chassis_serial_number: string(&smbios.chassis_serial_number),
+                                    chassis_asset_tag: string(&smbios.chassis_asset_tag),
+                                    bios_lock_string: "00000000000000000000000000000000".into(),
+                                    processor_manufacturer: b"\0".to_vec(),
+                                    processor_version: b"\0".to_vec(),
+                                    cpu_info_bundle: None,
+                                }
                             },
                         }
                     },
@@ -1267,8 +1636,8 @@ async fn load(
         let mut ide_drives = [[None, None], [None, None]];
         let mut storvsp_ide_disks = Vec::new();
         if cfg.chipset.with_hyperv_ide {
-            pci_legacy_interrupts.push(((7, None), 14));
-            pci_legacy_interrupts.push(((7, None), 15));
+            pci_legacy_interrupts.push(((7, None, 0), 14));
+            pci_legacy_interrupts.push(((7, None, 0), 15));
 
             for disk_cfg in cfg.ide_disks {
                 let path = disk_cfg.path;
@@ -1463,11 +1832,60 @@ async fn load(
         let pci_bus_id_generic = vmotherboard::BusId::new("generic");
         let pci_bus_id_piix4 = vmotherboard::BusId::new("i440bx");
 
+        let mut pci_slots =
+            PciSlotAllocator::new(10, &cfg.pci_hotplug_slots, cfg.pci_bridges, cfg.pci_slots)?;
+
+        // Bridge n leads to bus n + 1.
+        anyhow::ensure!(
+            cfg.pci_bridges == 0 || cfg.chipset.with_generic_pci_bus,
+            "PCI bridges require the generic PCI bus"
+        );
+        let mut pci_bridges = Vec::new();
+        let mut pci_bridge_devices = Vec::new();
+        for index in 0..cfg.pci_bridges {
+            let name = format!("pci-bridge-{index}");
+            let (bus_number, device_number, function) = pci_slots.allocate(&name)?;
+            anyhow::ensure!(bus_number == 0, "{name} must be on bus 0");
+            pci_bridge_devices.push(device_number);
+            pci_bridges.push(dev::GenericPciBridgeDeps {
+                name,
+                bdf: (0, device_number, function),
+                secondary_bus: index + 1,
+            });
+        }
+
+        let pci_ecam = cfg
+            .memory
+            .pci_ecam
+            .map(|ecam| {
+                // Each bus takes 1MB of ECAM space.
+                let buses = ecam.len() >> 20;
+                anyhow::ensure!(
+                    cfg.chipset.with_generic_pci_bus,
+                    "an ECAM window requires the generic PCI bus"
+                );
+                anyhow::ensure!(
+                    ecam.len() % (1 << 20) == 0 && (1..=256).contains(&buses),
+                    "invalid ECAM window {ecam}"
+                );
+                anyhow::ensure!(
+                    mem_layout
+                        .mmio()
+                        .first()
+                        .is_some_and(|low| low.start() == ecam.start() && low.contains(&ecam)),
+                    "ECAM window {ecam} must be at the start of the low MMIO gap"
+                );
+                Ok((ecam.start(), (buses - 1) as u8))
+            })
+            .transpose()?;
+
         let deps_generic_pci_bus =
             (cfg.chipset.with_generic_pci_bus).then_some(dev::GenericPciBusDeps {
                 bus_id: pci_bus_id_generic.clone(),
                 pio_addr: pci_bus::standard_x86_io_ports::ADDR_START,
                 pio_data: pci_bus::standard_x86_io_ports::DATA_START,
+                ecam: pci_ecam,
+                bridges: pci_bridges,
             });
 
         let deps_generic_pic = (cfg.chipset.with_generic_pic).then_some(dev::GenericPicDeps {});
@@ -1482,11 +1900,21 @@ async fn load(
                 vtl2_framebuffer_gpa_base,
             });
 
+        let (pm_wake_send, wake_recv) = mesh::channel();
+        let (pm_gpe_send, gpe_recv) = mesh::channel();
+        let (wake_request_send, wake_request_recv) = mesh::channel();
+        let pm_gpe_send = cfg
+            .chipset
+            .with_hyperv_power_management
+            .then_some(pm_gpe_send);
         let deps_hyperv_power_management =
             (cfg.chipset.with_hyperv_power_management).then_some(dev::HyperVPowerManagementDeps {
                 acpi_irq: SYSTEM_IRQ_ACPI,
                 pio_base: PM_BASE,
                 pm_timer_assist: None,
+                wake_recv: Some(wake_recv),
+                gpe_recv: Some(gpe_recv),
+                wake_request_send: Some(wake_request_send.clone()),
             });
 
         let deps_hyperv_vga = if cfg.chipset.with_hyperv_vga {
@@ -1579,6 +2007,20 @@ async fn load(
             }
         };
 
+        let io_trace = match cfg.io_trace {
+            None => None,
+            Some(IoTraceConfig::Record(file)) => Some(IoTrace::record(file)),
+            Some(IoTraceConfig::Compare(file)) => {
+                Some(IoTrace::compare(file).context("failed to load i/o trace")?)
+            }
+        };
+
+        let access_trace = cfg
+            .access_trace
+            .map(|GuestAccessTraceConfig { filter, file }| GuestAccessTrace::new(&filter, file))
+            .transpose()
+            .context("invalid guest access trace filter")?;
+
         let BaseChipsetBuilderOutput {
             mut chipset_builder,
             device_interfaces: base_chipset_device_interfaces,
@@ -1603,6 +2045,8 @@ async fn load(
         .with_expected_manifest(cfg.chipset.clone())
         .with_device_handles(cfg.chipset_devices)
         .with_trace_unknown_pio(true) // todo: add CLI param?
+        .with_io_trace(io_trace)
+        .with_access_trace(access_trace)
         .build(&driver_source, &state_units, &resolver)
         .await?;
 
@@ -1669,6 +2113,16 @@ async fn load(
             }
         };
 
+        // Devices behind a bridge share its slot's interrupts, swizzled by
+        // device number, so route all four pins of each bridge.
+        if let Some(pci_inta_line) = pci_inta_line {
+            for bridge in &pci_bridge_devices {
+                for pin in 0..4 {
+                    pci_legacy_interrupts.push(((*bridge, None, pin), pci_inta_line));
+                }
+            }
+        }
+
         let mut scsi_devices = Vec::new();
         let mut vtl0_hvsock_relay = None;
         #[cfg(windows)]
@@ -1720,6 +2174,10 @@ async fn load(
                     vtl2_hvsock_channel.relay_half,
                     vtl2_vmbus_cfg.vsock_path.map(Into::into),
                     vtl2_vmbus_cfg.vsock_listener,
+                    VmSocketBridge {
+                        connect_ports: vtl2_vmbus_cfg.vsock_host_connect_ports,
+                        listen_ports: vtl2_vmbus_cfg.vsock_host_listen_ports,
+                    },
                 )
                 .context("failed to create vtl2 hvsock relay")?;
 
@@ -1772,6 +2230,10 @@ async fn load(
                 hvsock_channel.relay_half,
                 vmbus_cfg.vsock_path.map(Into::into),
                 vmbus_cfg.vsock_listener,
+                VmSocketBridge {
+                    connect_ports: vmbus_cfg.vsock_host_connect_ports,
+                    listen_ports: vmbus_cfg.vsock_host_listen_ports,
+                },
             )
             .context("failed to create hvsock relay")?;
 
@@ -1795,11 +2257,12 @@ async fn add_virtio_vpci(
             vmbus_server: &Option<VmbusServerHandle>,
             mapper: &dyn guestmem::MemoryMapper,
             device_name: &str,
+            instance_id: Guid,
             chipset_builder: &mut vmotherboard::ChipsetBuilder<'_>,
             device: Box<dyn virtio::VirtioDevice>,
         ) -> anyhow::Result<()> {
             let (vpci_device_name, device_name, instance_id, device_id) =
-                make_ids(device_name, None);
+                make_ids(device_name, Some(instance_id));
 
             let mut msi_set = MsiInterruptSet::new();
             let device = chipset_builder
@@ -1846,6 +2309,8 @@ async fn add_virtio_vpci(
             Ok(())
         }
 
+        let mut vpci_devices = Vec::new();
+
         // Synthetic devices
         {
             // Arbitrary default
@@ -1897,6 +2362,11 @@ async fn add_virtio_vpci(
 
             if partition.supports_virtual_devices() {
                 if vmbus_server.is_some() {
+                    // Arbitrary but constant, so that the guest sees the
+                    // device at the same location across boots.
+                    const VIRTIO_SERIAL_VPCI_INSTANCE_ID: Guid =
+                        guid::guid!("44b7c4d5-9d1e-4f5c-8e7a-3f0b6a2d9c11");
+
                     let serial = VirtioSerialDevice::new(1, &gm);
                     vpci_serial = Some(serial.io());
                     add_virtio_vpci(
@@ -1905,6 +2375,7 @@ async fn add_virtio_vpci(
                         &vmbus_server,
                         &mapper,
                         "virtio-serial-vpci",
+                        VIRTIO_SERIAL_VPCI_INSTANCE_ID,
                         &mut chipset_builder,
                         Box::new(LegacyWrapper::new(&driver_source, serial, &gm)),
                     )
@@ -1926,7 +2397,7 @@ async fn add_virtio_vpci(
                         DeviceVtl::Vtl2 => Vtl::Vtl2,
                     };
 
-                    vmm_core::device_builder::build_vpci_device(
+                    let hot_plug = vmm_core::device_builder::build_vpci_device(
                         &driver_source,
                         &resolver,
                         &gm,
@@ -1952,10 +2423,11 @@ async fn add_virtio_vpci(
                         },
                     )
                     .await?;
+                    vpci_devices.push((dev_cfg.instance_id, dev_cfg.vtl, hot_plug));
                 }
 
                 #[cfg(all(windows, feature = "virt_whp"))]
-                for resource in cfg.vpci_resources {
+                for (index, resource) in cfg.vpci_resources.into_iter().enumerate() {
                     let vmbus = vmbus_server
                         .as_ref()
                         .context("vmbus must be enabled to assign devices")?
@@ -1965,8 +2437,17 @@ async fn add_virtio_vpci(
                     // TODO: abstract this behind the trait object properly.
                     let pd = partition.as_any();
                     let p = pd.downcast_ref::<virt_whp::WhpPartition>().unwrap();
-                    let (vpci_bus_name, device_name, instance_id, device_id) =
-                        make_ids("assigned-device", None);
+                    // Arbitrary but constant, varying the part of the ID that
+                    // determines the device ID.
+                    const BASE_INSTANCE_ID: Guid =
+                        guid::guid!("b41e6f3a-0000-4d8c-9a52-7c3e1f0d6b94");
+                    let (vpci_bus_name, device_name, instance_id, device_id) = make_ids(
+                        "assigned-device",
+                        Some(Guid {
+                            data2: index as u16,
+                            ..BASE_INSTANCE_ID
+                        }),
+                    );
 
                     let hv_device = Arc::new(
                         p.new_physical_device(Vtl::Vtl0, device_id, resource.0)
@@ -2036,7 +2517,6 @@ async fn add_virtio_vpci(
         // Construct virtio devices.
         //
         // TODO: allocate PCI and MMIO space better.
-        let mut pci_device_number = 10;
         if mem_layout.mmio().len() < 2 {
             anyhow::bail!("at least two mmio regions are required");
         }
@@ -2055,6 +2535,8 @@ async fn add_virtio_vpci(
                 VIRTIO_MMIO_IOAPIC_IRQ
             }
         };
+        let mut virtio_devices: Vec<(VirtioBus, String, Box<dyn virtio::VirtioDevice>)> =
+            Vec::new();
         for (bus, device) in cfg.virtio_devices.into_iter() {
             let id = device.id().to_string();
             let device = resolver
@@ -2066,6 +2548,52 @@ async fn add_virtio_vpci(
                     },
                 )
                 .await?;
+            virtio_devices.push((bus, id, device.0));
+        }
+
+        // Add the virtio-mem device for hot-pluggable memory. It needs the
+        // device memory mapper, so it is not created through the resolver.
+        let mut virtio_mem_resize = None;
+        if let Some(range) = virtio_mem_range(&mem_layout, cfg.memory.hotplug_size) {
+            let (send, recv) = mesh::channel();
+            let device = virtio_mem::VirtioMemDevice::new(
+                &driver_source,
+                gm.clone(),
+                &mapper,
+                range.start(),
+                range.len(),
+                recv,
+            )?;
+            // Use MMIO when there is no PCI bus, as with Linux direct boot.
+            let bus = if pci_inta_line.is_some() {
+                VirtioBus::Pci
+            } else {
+                VirtioBus::Mmio
+            };
+            virtio_devices.push((bus, "virtio-mem".to_owned(), Box::new(device)));
+            virtio_mem_resize = Some(send);
+        }
+
+        let mut virtio_balloon_target = None;
+        if cfg.memory.balloon {
+            let (send, recv) = mesh::channel();
+            let device = virtio_balloon::VirtioBalloonDevice::new(
+                &driver_source,
+                gm.clone(),
+                mem_layout.ram().iter().map(|range| range.range).collect(),
+                Box::new(memory_manager.ram_discard()),
+                recv,
+            );
+            let bus = if pci_inta_line.is_some() {
+                VirtioBus::Pci
+            } else {
+                VirtioBus::Mmio
+            };
+            virtio_devices.push((bus, "virtio-balloon".to_owned(), Box::new(device)));
+            virtio_balloon_target = Some(send);
+        }
+
+        for (bus, id, device) in virtio_devices {
             match bus {
                 VirtioBus::Mmio => {
                     let mmio_start = virtio_mmio_start - 0x1000;
@@ -2073,7 +2601,7 @@ async fn add_virtio_vpci(
                     let id = format!("{id}-{mmio_start}");
                     chipset_builder.arc_mutex_device(id).add(|services| {
                         VirtioMmioDevice::new(
-                            device.0,
+                            device,
                             services.new_line(IRQ_LINE_SET, "interrupt", virtio_mmio_irq),
                             partition.clone().into_doorbell_registration(Vtl::Vtl0),
                             mmio_start,
@@ -2085,9 +2613,11 @@ async fn add_virtio_vpci(
                 VirtioBus::Pci => {
                     let pci_inta_line = pci_inta_line.context("missing PCI INT#A line")?;
 
-                    let device_number = pci_device_number;
-                    pci_device_number += 1;
-                    pci_legacy_interrupts.push(((device_number, None), pci_inta_line));
+                    let (bus_number, device_number, function) =
+                        pci_slots.allocate(&format!("{id}-pci"))?;
+                    if bus_number == 0 {
+                        pci_legacy_interrupts.push(((device_number, None, 0), pci_inta_line));
+                    }
 
                     let bus = if cfg.chipset.with_piix4_pci_bus {
                         pci_bus_id_piix4.clone()
@@ -2097,11 +2627,11 @@ async fn add_virtio_vpci(
 
                     chipset_builder
                         .arc_mutex_device(format!("{id}-pci"))
-                        .with_pci_addr(0, device_number, 0)
+                        .with_pci_addr(bus_number, device_number, function)
                         .on_pci_bus(bus)
                         .try_add(|services| {
                             VirtioPciDevice::new(
-                                device.0,
+                                device,
                                 PciInterruptModel::IntX(
                                     PciInterruptPin::IntA,
                                     services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line),
@@ -2115,6 +2645,346 @@ async fn add_virtio_vpci(
             }
         }
 
+        if !cfg.usb_devices.is_empty() {
+            let pci_inta_line = pci_inta_line.context("missing PCI INT#A line")?;
+            let mut devices = Vec::new();
+            for device in cfg.usb_devices {
+                let device = resolver
+                    .resolve(
+                        device,
+                        ResolveUsbDeviceHandleParams {
+                            driver_source: &driver_source,
+                        },
+                    )
+                    .await?;
+                devices.push(device.0);
+            }
+
+            let (bus_number, device_number, function) = pci_slots.allocate("xhci")?;
+            if bus_number == 0 {
+                pci_legacy_interrupts.push(((device_number, None, 0), pci_inta_line));
+            }
+
+            let bus = if cfg.chipset.with_piix4_pci_bus {
+                pci_bus_id_piix4.clone()
+            } else {
+                pci_bus_id_generic.clone()
+            };
+
+            chipset_builder
+                .arc_mutex_device("xhci")
+                .with_pci_addr(bus_number, device_number, function)
+                .on_pci_bus(bus)
+                .try_add(|services| {
+                    XhciController::new(
+                        gm.clone(),
+                        services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line),
+                        &mut services.register_mmio(),
+                        devices,
+                    )
+                })?;
+        }
+
+        for (index, nic) in cfg.e1000_nics.into_iter().enumerate() {
+            let pci_inta_line = pci_inta_line.context("missing PCI INT#A line")?;
+            let endpoint = resolver
+                .resolve(
+                    nic.endpoint,
+                    ResolveEndpointParams {
+                        mac_address: nic.mac_address,
+                    },
+                )
+                .await?;
+
+            let (bus_number, device_number, function) =
+                pci_slots.allocate(&format!("e1000-{index}"))?;
+            if bus_number == 0 {
+                pci_legacy_interrupts.push(((device_number, None, 0), pci_inta_line));
+            }
+
+            let bus = if cfg.chipset.with_piix4_pci_bus {
+                pci_bus_id_piix4.clone()
+            } else {
+                pci_bus_id_generic.clone()
+            };
+
+            chipset_builder
+                .arc_mutex_device(format!("e1000-{index}"))
+                .with_pci_addr(bus_number, device_number, function)
+                .on_pci_bus(bus)
+                .add(|services| {
+                    E1000::new(
+                        &driver_source,
+                        gm.clone(),
+                        services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line),
+                        &mut services.register_mmio(),
+                        endpoint.0,
+                        nic.mac_address,
+                    )
+                })?;
+        }
+
+        for (index, card) in cfg.pci_serial_cards.into_iter().enumerate() {
+            let pci_inta_line = pci_inta_line.context("missing PCI INT#A line")?;
+            let mut ports = Vec::new();
+            for port in card.ports {
+                let port = resolver
+                    .resolve(
+                        port,
+                        ResolveSerialBackendParams {
+                            driver: Box::new(driver_source.simple()),
+                            _async_trait_workaround: &(),
+                        },
+                    )
+                    .await?;
+                ports.push(port.0.into_io());
+            }
+
+            let name = format!("serial-pci-{index}");
+            let (bus_number, device_number, function) = pci_slots.allocate(&name)?;
+            if bus_number == 0 {
+                pci_legacy_interrupts.push(((device_number, None, 0), pci_inta_line));
+            }
+
+            let bus = if cfg.chipset.with_piix4_pci_bus {
+                pci_bus_id_piix4.clone()
+            } else {
+                pci_bus_id_generic.clone()
+            };
+
+            chipset_builder
+                .arc_mutex_device(name.clone())
+                .with_pci_addr(bus_number, device_number, function)
+                .on_pci_bus(bus)
+                .try_add(|services| {
+                    Serial16550PciCard::new(
+                        &name,
+                        services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line),
+                        &mut services.register_mmio(),
+                        ports,
+                        card.wait_for_rts,
+                    )
+                })?;
+        }
+
+        for (index, ivshmem) in cfg.ivshmem_devices.into_iter().enumerate() {
+            let pci_inta_line = pci_inta_line.context("missing PCI INT#A line")?;
+
+            let (bus_number, device_number, function) =
+                pci_slots.allocate(&format!("ivshmem-{index}"))?;
+            if bus_number == 0 {
+                pci_legacy_interrupts.push(((device_number, None, 0), pci_inta_line));
+            }
+
+            let bus = if cfg.chipset.with_piix4_pci_bus {
+                pci_bus_id_piix4.clone()
+            } else {
+                pci_bus_id_generic.clone()
+            };
+
+            chipset_builder
+                .arc_mutex_device(format!("ivshmem-{index}"))
+                .with_pci_addr(bus_number, device_number, function)
+                .on_pci_bus(bus)
+                .try_add(|services| {
+                    ivshmem::IvshmemDevice::new(
+                        &driver_source,
+                        services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line),
+                        &mut services.register_mmio(),
+                        &mapper,
+                        ivshmem.file,
+                        ivshmem.size,
+                        ivshmem.doorbell,
+                    )
+                })?;
+        }
+
+        #[cfg(guest_arch = "x86_64")]
+        if let Some(nvme_sriov) = cfg.nvme_sriov {
+            anyhow::ensure!(
+                cfg.chipset.with_generic_pci_bus,
+                "NVMe virtual functions require the generic PCI bus"
+            );
+            let vf_count = nvme_sriov.virtual_functions.len();
+            anyhow::ensure!(
+                (1..=pci_core::capabilities::sriov::MAX_VFS.into()).contains(&vf_count),
+                "there must be between 1 and {} NVMe virtual functions",
+                pci_core::capabilities::sriov::MAX_VFS
+            );
+            let (bus_number, device_number, function) = pci_slots.allocate("nvme-sriov")?;
+            anyhow::ensure!(
+                function == 0,
+                "the NVMe physical function must be function 0"
+            );
+
+            // The controllers share one NVM subsystem.
+            let caps = NvmeControllerCaps {
+                msix_count: 64,
+                max_io_queues: 64,
+                subsystem_id: Guid::new_random(),
+            };
+            let msi_target = PartitionMsiTarget(partition.clone());
+
+            let mut virtual_functions = Vec::new();
+            chipset_builder
+                .arc_mutex_device("nvme-sriov")
+                .with_pci_addr(bus_number, device_number, 0)
+                .on_pci_bus(pci_bus_id_generic.clone())
+                .add(|services| {
+                    let mut msi_set = MsiInterruptSet::new();
+                    let (controller, vfs) = NvmeController::new_physical_function(
+                        &driver_source,
+                        gm.clone(),
+                        &mut msi_set,
+                        &mut services.register_mmio(),
+                        caps,
+                        vf_count as u16,
+                    );
+                    msi_set.connect(&msi_target);
+                    virtual_functions = vfs;
+                    controller
+                })?;
+
+            for (vf, namespaces) in virtual_functions
+                .into_iter()
+                .zip(nvme_sriov.virtual_functions)
+            {
+                let function = vf.function_offset() as u8;
+                let controller = chipset_builder
+                    .arc_mutex_device(format!("nvme-sriov-vf{}", vf.index()))
+                    .with_pci_addr(bus_number, device_number, function)
+                    .on_pci_bus(pci_bus_id_generic.clone())
+                    .add(|services| {
+                        let mut msi_set = MsiInterruptSet::new();
+                        let controller = NvmeController::new_virtual_function(
+                            &driver_source,
+                            gm.clone(),
+                            &mut msi_set,
+                            &mut services.register_mmio(),
+                            caps,
+                            vf,
+                        );
+                        msi_set.connect(&msi_target);
+                        controller
+                    })?;
+
+                let client = controller.lock().client();
+                for NamespaceDefinition {
+                    nsid,
+                    read_only,
+                    disk,
+                } in namespaces
+                {
+                    let disk = resolver
+                        .resolve(
+                            disk,
+                            ResolveDiskParameters {
+                                read_only,
+                                driver_source: &driver_source,
+                            },
+                        )
+                        .await
+                        .with_context(|| format!("failed to resolve nvme namespace {nsid}"))?;
+                    client.add_namespace(nsid, disk.0).await?;
+                }
+            }
+        }
+        #[cfg(not(guest_arch = "x86_64"))]
+        if cfg.nvme_sriov.is_some() {
+            anyhow::bail!("NVMe virtual functions require an x86-64 guest");
+        }
+
+        #[cfg(all(target_os = "linux", guest_arch = "x86_64"))]
+        for vfio in cfg.vfio_devices {
+            let vfio_device = vfio_assigned_device::VfioDevice {
+                pci_id: vfio.pci_id,
+                container: vfio.container.into(),
+                group: vfio.group.into(),
+                device: vfio.device.into(),
+            };
+            let ram = mem_layout.ram().iter().map(|range| range.range);
+
+            if let Some(instance_id) = vfio.vtl2_instance_id {
+                // Assign the device to VTL2 through VPCI. VTL2 drives the
+                // device with its own memory and VTL0's, so map both for DMA.
+                let vmbus = vtl2_vmbus_server
+                    .as_ref()
+                    .context("VTL2 vmbus must be enabled to assign devices to VTL2")?;
+                let ram = ram.chain(mem_layout.vtl2_range()).collect::<Vec<_>>();
+                let (vpci_bus_name, device_name, instance_id, device_id) =
+                    make_ids(&format!("vfio-{}", vfio_device.pci_id), Some(instance_id));
+                let hv_device = partition
+                    .new_virtual_device(Vtl::Vtl2, device_id)
+                    .context("failed to create virtual device")?;
+
+                let device = chipset_builder
+                    .arc_mutex_device(device_name)
+                    .with_external_pci()
+                    .try_add(|services| {
+                        vfio_assigned_device::VfioAssignedDevice::new(
+                            &driver_source,
+                            &mut services.register_mmio(),
+                            &mapper,
+                            hv_device.clone().target().as_ref(),
+                            &gm,
+                            &ram,
+                            vfio_device,
+                        )
+                    })?;
+
+                chipset_builder
+                    .arc_mutex_device(vpci_bus_name)
+                    .try_add_async(async |services| {
+                        VpciBus::new(
+                            &driver_source,
+                            instance_id,
+                            device,
+                            &mut services.register_mmio(),
+                            vmbus.control().as_ref(),
+                            hv_device.interrupt_mapper(),
+                        )
+                        .await
+                    })
+                    .await?;
+                continue;
+            }
+
+            let name = format!("vfio-{}", vfio_device.pci_id);
+            let (bus_number, device_number, function) = pci_slots.allocate(&name)?;
+
+            let bus = if cfg.chipset.with_piix4_pci_bus {
+                pci_bus_id_piix4.clone()
+            } else {
+                pci_bus_id_generic.clone()
+            };
+
+            let ram = ram.collect::<Vec<_>>();
+            chipset_builder
+                .arc_mutex_device(name)
+                .with_pci_addr(bus_number, device_number, function)
+                .on_pci_bus(bus)
+                .try_add(|services| {
+                    vfio_assigned_device::VfioAssignedDevice::new(
+                        &driver_source,
+                        &mut services.register_mmio(),
+                        &mapper,
+                        &PartitionMsiTarget(partition.clone()),
+                        &gm,
+                        &ram,
+                        vfio_device,
+                    )
+                })?;
+        }
+        #[cfg(not(all(target_os = "linux", guest_arch = "x86_64")))]
+        if !cfg.vfio_devices.is_empty() {
+            anyhow::bail!("vfio device assignment requires a Linux host and an x86-64 guest");
+        }
+
+        pci_slots.finish()?;
+        // The functions of a multi-function device share its routing entry.
+        pci_legacy_interrupts.sort();
+        pci_legacy_interrupts.dedup();
+
         let mut virt_serial_io = None;
         {
             if with_virtio_serial_mmio {
@@ -2204,7 +3074,8 @@ async fn add_virtio_vpci(
         assert!(virtio_mmio_start >= mem_layout.mmio()[1].start());
 
         let (chipset, devices) = chipset_builder.build()?;
-        let chipset = vmm_core::vmotherboard_adapter::ChipsetPlusSynic::new(synic.clone(), chipset);
+        let chipset = vmm_core::vmotherboard_adapter::ChipsetPlusSynic::new(synic.clone(), chipset)
+            .with_guest_crash_send(cfg.guest_crash_send.clone());
 
         // create a new channel to intercept guest resets
         let (halt_send, halt_recv) = mesh::channel();
@@ -2218,7 +3089,7 @@ async fn add_virtio_vpci(
             partition.clone().into_vm_partition(),
             PartitionUnitParams {
                 processor_topology: &processor_topology,
-                halt_vps,
+                halt_vps: halt_vps.clone(),
                 halt_request_recv,
                 client_notify_send: halt_send,
                 vtl_guest_memory: [
@@ -2227,12 +3098,13 @@ async fn add_virtio_vpci(
                     cfg.hypervisor.with_vtl2.is_some().then_some(&gm),
                 ],
                 debugger_rpc: cfg.debugger_rpc,
+                vtl2_debugger_rpc: cfg.vtl2_debugger_rpc,
             },
         )
         .context("failed to create partition unit")?;
 
         // Start the VP backing threads.
-        try_join_all(vps.into_iter().zip(vp_runners).enumerate().map(
+        let vp_threads = try_join_all(vps.into_iter().zip(vp_runners).enumerate().map(
             |(vp_index, (mut vp, runner))| {
                 let partition = partition.clone();
                 let chipset = chipset.clone();
@@ -2241,7 +3113,7 @@ async fn add_virtio_vpci(
                     .name(format!("vp-{}", vp_index))
                     .spawn(move || match vp.bind() {
                         Ok(mut vp) => {
-                            send.send(Ok(()));
+                            send.send(Ok(VpThread::current()));
                             block_on_vp(
                                 partition,
                                 VpIndex::new(vp_index as u32),
@@ -2263,6 +3135,17 @@ async fn add_virtio_vpci(
         ))
         .await?;
 
+        let mut vp_affinity = vec![Vec::new(); vp_threads.len()];
+        for (vp_index, cpus) in cfg.vp_affinity {
+            let thread = vp_threads
+                .get(vp_index as usize)
+                .with_context(|| format!("invalid vp {vp_index} for affinity"))?;
+            thread
+                .set_affinity(&cpus)
+                .with_context(|| format!("failed to set vp {vp_index} affinity"))?;
+            vp_affinity[vp_index as usize] = cpus;
+        }
+
         let mut this = LoadedVm {
             state_units,
             running: false,
@@ -2294,8 +3177,10 @@ async fn add_virtio_vpci(
                 #[cfg(windows)]
                 _kernel_vmnics: kernel_vmnics,
                 vmbus_devices,
+                vpci_devices,
                 chipset_cfg: cfg.chipset,
                 firmware_event_send: cfg.firmware_event_send,
+                guest_crash_send: cfg.guest_crash_send,
                 load_mode: cfg.load_mode,
                 virtio_mmio_count,
                 virtio_mmio_irq,
@@ -2305,8 +3190,30 @@ async fn add_virtio_vpci(
                 _vmgs_task: vmgs_task,
                 vmgs_client_inspect_handle,
                 halt_recv,
+                halt_vps,
                 client_notify_send,
                 automatic_guest_reset: cfg.automatic_guest_reset,
+                enable_s3: cfg.enable_s3,
+                enable_s4: cfg.enable_s4,
+                bios_guid,
+                hibernate_store,
+                hibernate_resumed_recv,
+                facs_gpa: None,
+                suspended: false,
+                powered_off: false,
+                pm_wake_send,
+                wake_request_send,
+                wake_request_recv,
+                scheduled_wake: None,
+                pm_gpe_send,
+                pci_hotplug_slots: cfg.pci_hotplug_slots,
+                pvpanic: cfg.pvpanic,
+                smbios: cfg.smbios,
+                virtio_mem_resize,
+                virtio_balloon_target,
+                vp_threads,
+                vp_affinity,
+                cpuid: cfg.cpuid,
             },
         };
 
@@ -2332,6 +3239,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
         } else {
             None
         };
+        let ssdt = self.build_ssdt(false);
         let acpi_builder = AcpiTablesBuilder {
             processor_topology: &self.processor_topology,
             mem_layout: &self.mem_layout,
@@ -2340,6 +3248,10 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             with_psp: self.chipset_cfg.with_generic_psp,
             with_pic: self.chipset_cfg.with_generic_pic,
             with_pit: self.chipset_cfg.with_generic_pit,
+            with_s3: self.enable_s3,
+            with_s4: self.enable_s4,
+            pci_ecam: self.memory_cfg.pci_ecam,
+            ssdt: ssdt.as_deref(),
             pm_base: PM_BASE,
             acpi_irq: SYSTEM_IRQ_ACPI,
         };
@@ -2368,8 +3280,11 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                 if custom_dsdt.is_none() && self.mem_layout.mmio().len() < 2 {
                     anyhow::bail!("at least two mmio regions are required");
                 }
-                let regs =
-                    super::vm_loaders::linux::load_linux_x86(&kernel_config, &self.gm, |gpa| {
+                let mut facs_gpa = None;
+                let regs = super::vm_loaders::linux::load_linux_x86(
+                    &kernel_config,
+                    &self.gm,
+                    |gpa| {
                         let tables = if let Some(dsdt) = custom_dsdt {
                             acpi_builder.build_acpi_tables_custom_dsdt(gpa, dsdt)
                         } else {
@@ -2382,16 +3297,22 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                                     self.virtio_mmio_count,
                                     self.virtio_mmio_irq,
                                     &self.pci_legacy_interrupts,
+                                    self.memory_cfg.pci_ecam,
                                 )
                             })
                         };
 
+                        facs_gpa = Some(tables.facs);
                         super::vm_loaders::linux::AcpiTables {
                             rdsp: tables.rdsp,
                             tables: tables.tables,
                         }
-                    })?;
+                    },
+                    &self.smbios,
+                    self.bios_guid,
+                )?;
 
+                self.facs_gpa = facs_gpa;
                 (regs, Vec::new())
             }
             #[cfg(guest_arch = "aarch64")]
@@ -2428,6 +3349,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                 enable_vpci_boot,
                 uefi_console_mode,
                 default_boot_always_attempt,
+                nvram: _,
             } => {
                 let madt = acpi_builder.build_madt();
                 let srat = acpi_builder.build_srat();
@@ -2443,6 +3365,8 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     serial: enable_serial,
                     uefi_console_mode,
                     default_boot_always_attempt,
+                    bios_guid: self.bios_guid,
+                    smbios: self.smbios.clone(),
                 };
                 let regs = super::vm_loaders::uefi::load_uefi(
                     firmware,
@@ -2453,6 +3377,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     &madt,
                     &srat,
                     pptt.as_deref(),
+                    self.build_ssdt(true).as_deref(),
                 )?;
 
                 (regs, Vec::new())
@@ -2543,6 +3468,214 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
 
         Ok(())
     }
+
+    /// Builds the SSDT describing the ACPI PCI hot-plug slots and the pvpanic
+    /// device, if either is configured. When the firmware provides the DSDT
+    /// (`firmware_dsdt`), the SSDT also advertises S3 if it is enabled.
+    fn build_ssdt(&self, firmware_dsdt: bool) -> Option<Vec<u8>> {
+        use chipset::pci_hotplug;
+
+        // The VMM-built DSDT advertises the sleep states itself.
+        let with_s3 = firmware_dsdt && self.enable_s3;
+        if self.pci_hotplug_slots.is_empty() && !with_s3 && !self.pvpanic {
+            return None;
+        }
+
+        #[cfg(guest_arch = "x86_64")]
+        let (mmio_base, irq) = (
+            pci_hotplug::PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_X64,
+            pci_hotplug::PCI_HOTPLUG_IOAPIC_IRQ,
+        );
+        #[cfg(guest_arch = "aarch64")]
+        let (mmio_base, irq) = (
+            pci_hotplug::PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_ARM,
+            *vmm_core::emuplat::gic::SPI_RANGE.start() + pci_hotplug::PCI_HOTPLUG_IRQ_NO,
+        );
+
+        let mut ssdt = acpi::ssdt::Ssdt::new();
+        if !self.pci_hotplug_slots.is_empty() {
+            ssdt.add_pci_hotplug(&acpi::ssdt::PciHotPlug {
+                bus: b"\\_SB.PCI0",
+                slots: &self.pci_hotplug_slots,
+                mmio_base,
+                irq,
+            });
+        }
+        if with_s3 {
+            ssdt.add_sleep_state(b"\\_S3", chipset::pm::SLEEP_TYPE_S3);
+        }
+        if self.pvpanic {
+            ssdt.add_pvpanic(chipset::pvpanic::PVPANIC_IO_PORT);
+        }
+        Some(ssdt.to_bytes())
+    }
+
+    /// Schedules a wake request after `delay`, replacing any pending one, or
+    /// cancels the pending one if `delay` is `None`.
+    ///
+    /// The request is delivered as an RTC alarm, waking the guest from S3 or
+    /// powering it on if it is powered off when the time comes.
+    fn schedule_wake(&mut self, delay: Option<Duration>) {
+        self.scheduled_wake = delay.map(|delay| {
+            tracing::info!(?delay, "scheduling wake");
+            let driver = self.driver_source.simple();
+            let send = self.wake_request_send.clone();
+            driver.clone().spawn("scheduled-wake", async move {
+                PolledTimer::new(&driver).sleep(delay).await;
+                send.send(chipset_resources::pm::WakeEvent::RtcAlarm);
+            })
+        });
+    }
+
+    /// Clears the hibernate state once the guest has resumed from it.
+    async fn clear_hibernate_state(&mut self) -> anyhow::Result<()> {
+        if let Some(store) = self.hibernate_store.as_deref_mut() {
+            tracing::info!("resumed from hibernate");
+            super::hibernate::clear(store).await?;
+        }
+        Ok(())
+    }
+
+    /// Records the configuration the guest will validate when it resumes from
+    /// hibernation, so that a later `resume_from_hibernate` boot can restore
+    /// it.
+    async fn save_hibernate_state(&mut self) -> anyhow::Result<()> {
+        let Some(store) = self.hibernate_store.as_deref_mut() else {
+            tracing::warn!("no vmgs file, guest will not be able to resume from hibernate");
+            return Ok(());
+        };
+        super::hibernate::persist(
+            store,
+            super::hibernate::HibernateState {
+                bios_guid: self.bios_guid,
+                mem_size: self.memory_cfg.mem_size,
+                proc_count: self.processor_topology.vp_count(),
+            },
+        )
+        .await
+    }
+
+    /// Asks the guest to grow or shrink its memory to `size` bytes, by
+    /// changing the requested size of the virtio-mem device.
+    fn set_memory_size(&self, size: u64) -> anyhow::Result<()> {
+        let resize = self
+            .virtio_mem_resize
+            .as_ref()
+            .context("memory hot-plug is not enabled")?;
+        let base = self.memory_cfg.mem_size;
+        let requested = size
+            .checked_sub(base)
+            .with_context(|| format!("memory size must be at least {base:#x}"))?;
+        if requested > self.memory_cfg.hotplug_size {
+            anyhow::bail!(
+                "memory size must be at most {:#x}",
+                base + self.memory_cfg.hotplug_size
+            );
+        }
+        if requested % virtio_mem::BLOCK_SIZE != 0 {
+            anyhow::bail!(
+                "hot-added memory must be a multiple of {:#x}",
+                virtio_mem::BLOCK_SIZE
+            );
+        }
+        resize.send(requested);
+        Ok(())
+    }
+
+    /// Asks the guest to inflate or deflate its balloon to `size` bytes.
+    fn set_balloon_size(&self, size: u64) -> anyhow::Result<()> {
+        let target = self
+            .virtio_balloon_target
+            .as_ref()
+            .context("balloon is not enabled")?;
+        if size > self.memory_cfg.mem_size {
+            anyhow::bail!(
+                "balloon size must be at most {:#x}",
+                self.memory_cfg.mem_size
+            );
+        }
+        let pages = size / virtio_balloon::BALLOON_PAGE_SIZE;
+        target.send(pages.try_into().context("balloon size too large")?);
+        Ok(())
+    }
+
+    /// Pins VP `vp_index`'s backing thread to the host CPUs in `cpus`, or
+    /// unpins it if `cpus` is empty.
+    fn set_vp_affinity(&mut self, vp_index: u32, cpus: Vec<u32>) -> anyhow::Result<()> {
+        let thread = self
+            .vp_threads
+            .get(vp_index as usize)
+            .with_context(|| format!("invalid vp {vp_index}"))?;
+        thread.set_affinity(&cpus)?;
+        self.vp_affinity[vp_index as usize] = cpus;
+        Ok(())
+    }
+
+    /// Enables or disables tracking of the VTL0 RAM pages written by the
+    /// guest.
+    fn set_dirty_tracking(&self, enable: bool) -> anyhow::Result<()> {
+        // VTL2 memory is mapped separately, so its writes would be missed.
+        if enable && self.mem_layout.vtl2_range().is_some() {
+            anyhow::bail!("dirty page tracking is not supported with VTL2 memory");
+        }
+        self.partition
+            .memory_mapper(Vtl::Vtl0)
+            .set_dirty_tracking(enable)
+    }
+
+    /// Gets and clears the dirty page bitmap of the RAM range `range`.
+    fn query_dirty_pages(&self, range: MemoryRange) -> anyhow::Result<Vec<u64>> {
+        if !self.mem_layout.ram().iter().any(|r| r.range == range) {
+            anyhow::bail!("{range} is not a ram range");
+        }
+        let mut bitmap = vec![0; (range.len() / HV_PAGE_SIZE).div_ceil(64) as usize];
+        self.partition
+            .memory_mapper(Vtl::Vtl0)
+            .query_and_clear_dirty(range.start(), range.len(), &mut bitmap)?;
+        Ok(bitmap)
+    }
+
+    /// Sets the BSP's initial register state to start executing at an S3
+    /// waking vector, in real mode.
+    #[cfg(guest_arch = "x86_64")]
+    async fn set_waking_regs(&mut self, waking_vector: u32) -> anyhow::Result<()> {
+        use loader::importer::SegmentRegister;
+        use loader::importer::X86Register;
+
+        let mut regs = vec![
+            X86Register::Cs(SegmentRegister {
+                base: (waking_vector & !0xf).into(),
+                limit: 0xffff,
+                selector: (waking_vector >> 4) as u16,
+                attributes: 0x9b,
+            }),
+            X86Register::Rip((waking_vector & 0xf).into()),
+        ];
+        regs.extend(
+            loader::common::compute_variable_mtrrs(
+                &self.mem_layout,
+                self.partition.caps().physical_address_width,
+            )
+            .context("failed to compute variable mtrrs")?,
+        );
+
+        let initial_regs = initial_regs(
+            &regs,
+            self.partition.caps(),
+            &self.processor_topology.vp_arch(VpIndex::BSP),
+        );
+        self.partition_unit
+            .set_initial_regs(Vtl::Vtl0, initial_regs)
+            .await
+            .context("failed to set waking register state")?;
+
+        Ok(())
+    }
+
+    #[cfg(not(guest_arch = "x86_64"))]
+    async fn set_waking_regs(&mut self, _waking_vector: u32) -> anyhow::Result<()> {
+        anyhow::bail!("S3 is not supported on this platform")
+    }
 }
 
 impl LoadedVm {
@@ -2574,6 +3707,8 @@ enum Event {
             WorkerRpc(Result<WorkerRpc<RestartState>, mesh::RecvError>),
             VmRpc(Result<VmRpc, mesh::RecvError>),
             Halt(Result<HaltReason, mesh::RecvError>),
+            WakeRequest(Result<chipset_resources::pm::WakeEvent, mesh::RecvError>),
+            HibernateResumed(Result<(), mesh::RecvError>),
         }
 
         // Start a task to handle state unit inspections by filtering the worker
@@ -2602,7 +3737,16 @@ enum Event {
                 let a = rpc_recv.recv().map(Event::VmRpc);
                 let b = worker_rpc.recv().map(Event::WorkerRpc);
                 let c = self.inner.halt_recv.recv().map(Event::Halt);
-                (a, b, c).race().await
+                let d = self.inner.wake_request_recv.recv().map(Event::WakeRequest);
+                let hibernate_resumed_recv = &mut self.inner.hibernate_resumed_recv;
+                let e = async move {
+                    match hibernate_resumed_recv {
+                        Some(recv) => recv.recv().await,
+                        None => std::future::pending().await,
+                    }
+                }
+                .map(Event::HibernateResumed);
+                (a, b, c, d, e).race().await
             };
 
             match event {
@@ -2613,7 +3757,11 @@ enum Event {
                         let mut stopped = false;
                         // First run the non-destructive operations.
                         let r = async {
-                            let shared_memory = self.inner.memory_manager.shared_memory_backing();
+                            let shared_memory =
+                                self.inner
+                                    .memory_manager
+                                    .shared_memory_backing()
+                                    .context("cannot restart a vm with copy-on-write ram")?;
                             if self.running {
                                 self.state_units.stop().await;
                                 stopped = true;
@@ -2654,6 +3802,13 @@ enum Event {
                         rpc.handle(async |()| self.inner.partition_unit.clear_halt().await)
                             .await
                     }
+                    VmRpc::Wake(rpc) => {
+                        rpc.handle_failable(async |event| self.wake(event).await)
+                            .await
+                    }
+                    VmRpc::ScheduleWake(rpc) => rpc.handle_sync(|delay| {
+                        self.inner.schedule_wake(delay);
+                    }),
                     VmRpc::Resume(rpc) => rpc.handle(async |()| self.resume().await).await,
                     VmRpc::Pause(rpc) => rpc.handle(async |()| self.pause().await).await,
                     VmRpc::Save(rpc) => {
@@ -2685,6 +3840,56 @@ enum Event {
                             );
                         }
                     }),
+                    VmRpc::Sci(rpc) => rpc.handle_failable_sync(|gpe| {
+                        let send = self
+                            .inner
+                            .pm_gpe_send
+                            .as_ref()
+                            .context("no ACPI power management device")?;
+                        if gpe >= u16::BITS {
+                            anyhow::bail!("invalid general-purpose event {gpe}");
+                        }
+                        send.send(gpe);
+                        Ok(())
+                    }),
+                    VmRpc::MachineCheck(rpc) => {
+                        rpc.handle_failable(async |vpindex| {
+                            if vpindex >= self.inner.processor_topology.vp_count() {
+                                anyhow::bail!("invalid VP {vpindex}");
+                            }
+                            self.inner
+                                .partition_unit
+                                .inject_machine_check(VpIndex::new(vpindex))
+                                .await
+                        })
+                        .await
+                    }
+                    VmRpc::VpRegisters(rpc) => {
+                        rpc.handle_failable(async |vpindex| {
+                            if vpindex >= self.inner.processor_topology.vp_count() {
+                                anyhow::bail!("invalid VP {vpindex}");
+                            }
+                            self.inner
+                                .partition_unit
+                                .registers(VpIndex::new(vpindex))
+                                .await
+                        })
+                        .await
+                    }
+                    VmRpc::TranslateGva(rpc) => {
+                        rpc.handle_failable(async |(vpindex, gva)| {
+                            if vpindex >= self.inner.processor_topology.vp_count() {
+                                anyhow::bail!("invalid VP {vpindex}");
+                            }
+                            let registers = self
+                                .inner
+                                .partition_unit
+                                .registers(VpIndex::new(vpindex))
+                                .await?;
+                            translate_gva(&self.inner.gm, &registers, gva)
+                        })
+                        .await
+                    }
                     VmRpc::AddVmbusDevice(rpc) => {
                         rpc.handle_failable(async |(vtl, resource)| {
                             let vmbus = match vtl {
@@ -2707,6 +3912,50 @@ enum Event {
                         })
                         .await
                     }
+                    VmRpc::RemoveVmbusDevice(rpc) => {
+                        rpc.handle_failable(async |instance_id| {
+                            let index = self
+                                .inner
+                                .vmbus_devices
+                                .iter()
+                                .position(|(id, _)| *id == instance_id)
+                                .with_context(|| {
+                                    format!("no vmbus device with instance id {instance_id}")
+                                })?;
+                            let (_, unit) = self.inner.vmbus_devices.remove(index);
+                            // Revoke the channel and drop the device, which
+                            // tears down any backing resources (e.g. network
+                            // endpoints).
+                            let device = unit.remove().await.revoke().await;
+                            drop(device);
+                            anyhow::Ok(())
+                        })
+                        .await
+                    }
+                    VmRpc::RevokeVpciDevice(rpc) => {
+                        rpc.handle_failable(async |instance_id| {
+                            let (_, hot_plug) = self.vpci_device(instance_id)?;
+                            hot_plug.revoke_device().await?;
+                            anyhow::Ok(())
+                        })
+                        .await
+                    }
+                    VmRpc::OfferVpciDevice(rpc) => {
+                        rpc.handle_failable(async |instance_id| {
+                            let (vtl, hot_plug) = self.vpci_device(instance_id)?;
+                            let vmbus = match vtl {
+                                DeviceVtl::Vtl0 => self.inner.vmbus_server.as_ref(),
+                                DeviceVtl::Vtl1 => None,
+                                DeviceVtl::Vtl2 => self.inner.vtl2_vmbus_server.as_ref(),
+                            }
+                            .context("no vmbus available")?;
+                            hot_plug
+                                .offer_device(&self.inner.driver_source, vmbus.control().as_ref())
+                                .await?;
+                            anyhow::Ok(())
+                        })
+                        .await
+                    }
                     VmRpc::ConnectHvsock(rpc) => {
                         let ((mut ctx, service_id, vtl), response) = rpc.split();
                         if let Some(relay) = self.hvsock_relay(vtl) {
@@ -2758,9 +4007,51 @@ enum Event {
                     VmRpc::WriteMemory(rpc) => rpc.handle_failable_sync(|(gpa, bytes)| {
                         self.inner.gm.write_at(gpa, bytes.as_slice())
                     }),
+                    VmRpc::SetMemorySize(rpc) => {
+                        rpc.handle_failable_sync(|size| self.inner.set_memory_size(size))
+                    }
+                    VmRpc::SetBalloonSize(rpc) => {
+                        rpc.handle_failable_sync(|size| self.inner.set_balloon_size(size))
+                    }
+                    VmRpc::SetVpAffinity(rpc) => rpc.handle_failable_sync(|(vp_index, cpus)| {
+                        self.inner.set_vp_affinity(vp_index, cpus)
+                    }),
+                    VmRpc::RamRanges(rpc) => rpc.handle_sync(|()| {
+                        let mem_layout = &self.inner.mem_layout;
+                        mem_layout
+                            .ram()
+                            .iter()
+                            .map(|r| r.range)
+                            .chain(mem_layout.vtl2_range())
+                            .collect()
+                    }),
+                    VmRpc::SetDirtyTracking(rpc) => {
+                        rpc.handle_failable_sync(|enable| self.inner.set_dirty_tracking(enable))
+                    }
+                    VmRpc::QueryDirtyPages(rpc) => {
+                        rpc.handle_failable_sync(|range| self.inner.query_dirty_pages(range))
+                    }
+                    VmRpc::IsRunning(rpc) => rpc.handle_sync(|()| self.running),
+                    VmRpc::PowerOff(rpc) => rpc.handle_sync(|()| {
+                        tracing::info!("powering off at client request");
+                        self.inner.halt_vps.halt(HaltReason::PowerOff);
+                    }),
                 },
                 Event::Halt(Err(_)) => break,
                 Event::Halt(Ok(reason)) => {
+                    if matches!(reason, HaltReason::Suspend) {
+                        tracing::info!("guest entered S3");
+                        self.inner.suspended = true;
+                    }
+                    if matches!(reason, HaltReason::PowerOff) {
+                        self.inner.powered_off = true;
+                    }
+                    if matches!(reason, HaltReason::Hibernate) {
+                        tracing::info!("guest entered S4");
+                        if let Err(err) = self.inner.save_hibernate_state().await {
+                            tracing::error!(?err, "failed to save hibernate state");
+                        }
+                    }
                     if matches!(reason, HaltReason::Reset) && self.inner.automatic_guest_reset {
                         tracing::info!("guest-initiated reset");
                         if let Err(err) = self.reset(true).await {
@@ -2771,6 +4062,28 @@ enum Event {
                         self.inner.client_notify_send.send(reason);
                     }
                 }
+                Event::WakeRequest(Err(_)) => break,
+                Event::WakeRequest(Ok(event)) => {
+                    let r = if self.inner.suspended {
+                        self.wake(event).await
+                    } else if self.inner.powered_off {
+                        self.power_on(event).await
+                    } else {
+                        tracing::info!(?event, "ignoring wake request, guest is running");
+                        Ok(())
+                    };
+                    if let Err(err) = r {
+                        tracing::error!(?err, "failed to wake guest");
+                    }
+                }
+                Event::HibernateResumed(r) => {
+                    self.inner.hibernate_resumed_recv = None;
+                    if r.is_ok() {
+                        if let Err(err) = self.inner.clear_hibernate_state().await {
+                            tracing::error!(?err, "failed to clear hibernate state");
+                        }
+                    }
+                }
             }
         }
 
@@ -2846,6 +4159,18 @@ async fn complete_reload_igvm(&mut self, complete: bool) -> anyhow::Result<()> {
         Ok(())
     }
 
+    /// Gets the VTL and hot-plug handle of the VPCI device with the given
+    /// instance ID.
+    fn vpci_device(&self, instance_id: Guid) -> anyhow::Result<(DeviceVtl, VpciHotPlugHandle)> {
+        let (_, vtl, hot_plug) = self
+            .inner
+            .vpci_devices
+            .iter()
+            .find(|(id, _, _)| *id == instance_id)
+            .with_context(|| format!("no vpci device with instance id {instance_id}"))?;
+        Ok((*vtl, hot_plug.clone()))
+    }
+
     /// Get the associated hvsock relay for a given vtl, if any.
     fn hvsock_relay(&self, vtl: DeviceVtl) -> Option<&HvsockRelay> {
         match vtl {
@@ -2911,19 +4236,46 @@ async fn serialize(
             vtl2_gfx: false,           // TODO
             virtio_console_pci: false, // TODO
             virtio_serial: self.inner.virtio_serial,
-            virtio_devices: vec![], // TODO
+            virtio_devices: vec![],   // TODO
+            usb_devices: vec![],      // TODO
+            e1000_nics: vec![],       // TODO
+            pci_serial_cards: vec![], // TODO
+            ivshmem_devices: vec![],  // TODO
+            nvme_sriov: None,         // TODO
+            vfio_devices: vec![],     // TODO
             #[cfg(all(windows, feature = "virt_whp"))]
             vpci_resources: vec![], // TODO
-            vmgs: None,             // TODO
+            vmgs: None,               // TODO
             secure_boot_enabled: false, // TODO
             custom_uefi_vars: Default::default(), // TODO
             firmware_event_send: self.inner.firmware_event_send,
+            guest_crash_send: self.inner.guest_crash_send,
             debugger_rpc: None,        // TODO
+            vtl2_debugger_rpc: None,   // TODO
             vmbus_devices: vec![],     // TODO
             chipset_devices: vec![],   // TODO
             generation_id_recv: None,  // TODO
             rtc_delta_milliseconds: 0, // TODO
             automatic_guest_reset: self.inner.automatic_guest_reset,
+            enable_s3: self.inner.enable_s3,
+            enable_s4: self.inner.enable_s4,
+            resume_from_hibernate: false,
+            pci_hotplug_slots: self.inner.pci_hotplug_slots.clone(),
+            pci_slots: vec![], // TODO
+            pci_bridges: 0,    // TODO
+            pvpanic: self.inner.pvpanic,
+            smbios: self.inner.smbios.clone(),
+            vp_affinity: self
+                .inner
+                .vp_affinity
+                .iter()
+                .enumerate()
+                .filter(|(_, cpus)| !cpus.is_empty())
+                .map(|(vp_index, cpus)| (vp_index as u32, cpus.clone()))
+                .collect(),
+            cpuid: self.inner.cpuid.clone(),
+            io_trace: None,
+            access_trace: None,
         };
         RestartState {
             hypervisor: self.inner.hypervisor,
@@ -2936,7 +4288,47 @@ async fn serialize(
         }
     }
 
+    /// Wakes the guest from S3.
+    ///
+    /// Devices lose power in S3, so they are reset, but guest memory is
+    /// preserved. Acting as the firmware, the VMM then starts the BSP at the
+    /// real-mode waking vector the guest left in the FACS.
+    async fn wake(&mut self, event: chipset_resources::pm::WakeEvent) -> anyhow::Result<()> {
+        if !self.inner.suspended {
+            anyhow::bail!("guest is not suspended");
+        }
+        // If the firmware built the ACPI tables, find the FACS the hard way.
+        let facs_gpa = match self.inner.facs_gpa {
+            Some(gpa) => gpa,
+            None => super::sleep::find_facs(&self.inner.gm, &self.inner.mem_layout)
+                .context("failed to locate FACS")?,
+        };
+        let waking_vector = super::sleep::waking_vector(&self.inner.gm, facs_gpa)?;
+
+        tracing::info!(?event, waking_vector, "waking guest from S3");
+        let resume = self.pause().await;
+        self.state_units.reset().await?;
+        self.inner.set_waking_regs(waking_vector).await?;
+        self.inner.pm_wake_send.send(event);
+        self.inner.suspended = false;
+        if resume {
+            self.resume().await;
+        }
+        Ok(())
+    }
+
+    /// Powers on the guest after it powered itself off, as if by the given
+    /// wake event.
+    async fn power_on(&mut self, event: chipset_resources::pm::WakeEvent) -> anyhow::Result<()> {
+        tracing::info!(?event, "powering on guest");
+        self.reset(true).await?;
+        self.inner.pm_wake_send.send(event);
+        Ok(())
+    }
+
     async fn reset(&mut self, reload_firmware: bool) -> anyhow::Result<()> {
+        self.inner.suspended = false;
+        self.inner.powered_off = false;
         let resume = self.pause().await;
 
         self.state_units.reset().await?;
@@ -2963,7 +4355,8 @@ fn add_devices_to_dsdt(
     serial_uarts: bool,
     virtio_mmio_count: usize,
     virtio_mmio_irq: u32,
-    pci_legacy_interrupts: &[((u8, Option<u8>), u32)], // ((device, function), interrupt)
+    pci_legacy_interrupts: &[((u8, Option<u8>, u8), u32)], // ((device, function, pin), interrupt)
+    pci_ecam: Option<MemoryRange>,
 ) {
     dsdt.add_apic();
 
@@ -2986,7 +4379,14 @@ fn add_devices_to_dsdt(
         mem_layout.mmio().len() >= 2,
         "the DSDT describes two MMIO regions"
     );
-    let low_mmio_gap = mem_layout.mmio()[0];
+    let mut low_mmio_gap = mem_layout.mmio()[0];
+    // The ECAM window is carved out of the start of the low gap, so that the
+    // guest does not place BARs over it.
+    if let Some(ecam) = pci_ecam {
+        if ecam.start() == low_mmio_gap.start() && ecam.end() <= low_mmio_gap.end() {
+            low_mmio_gap = MemoryRange::new(ecam.end()..low_mmio_gap.end());
+        }
+    }
     let mut high_mmio_space: std::ops::Range<u64> = mem_layout.mmio()[1].into();
     // Device(\_SB.VI00)
     // {
@@ -3019,6 +4419,9 @@ fn add_devices_to_dsdt(
     if cfg.with_generic_pci_bus || cfg.with_i440bx_host_pci_bridge {
         // TODO: actually plumb through legacy PCI interrupts
         dsdt.add_pci(low_mmio_gap, high_mmio_gap, pci_legacy_interrupts);
+        if let Some(ecam) = pci_ecam {
+            dsdt.add_pci_ecam(ecam);
+        }
     } else {
         dsdt.add_mmio_module(low_mmio_gap, high_mmio_gap);
     }
@@ -0,0 +1,149 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for resuming a guest that hibernated (entered S4) during a previous
+//! run of the VM.
+//!
+//! The guest stores its memory image on its own disk, so the VMM only needs to
+//! preserve the bits of configuration the guest checks on resume to make sure
+//! it is booting on the same "hardware". These are recorded in the VMGS when
+//! the guest hibernates and consumed by the next boot.
+
+use anyhow::Context as _;
+use guid::Guid;
+use mesh::payload::Protobuf;
+use vmcore::non_volatile_store::NonVolatileStore;
+
+/// The configuration preserved across guest hibernation.
+#[derive(Debug, Protobuf)]
+#[mesh(package = "openvmm.hibernate")]
+pub struct HibernateState {
+    /// The BIOS GUID reported to UEFI guests.
+    #[mesh(1)]
+    pub bios_guid: Guid,
+    /// The size of guest RAM, in bytes.
+    #[mesh(2)]
+    pub mem_size: u64,
+    /// The number of virtual processors.
+    #[mesh(3)]
+    pub proc_count: u32,
+}
+
+impl HibernateState {
+    /// Checks that the VM is configured the same way it was when the guest
+    /// hibernated.
+    pub fn validate(&self, mem_size: u64, proc_count: u32) -> anyhow::Result<()> {
+        if self.mem_size != mem_size {
+            anyhow::bail!(
+                "guest hibernated with {:#x} bytes of memory, but {:#x} are configured",
+                self.mem_size,
+                mem_size
+            );
+        }
+        if self.proc_count != proc_count {
+            anyhow::bail!(
+                "guest hibernated with {} processors, but {} are configured",
+                self.proc_count,
+                proc_count
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Writes `state` to `store`.
+pub async fn persist(
+    store: &mut dyn NonVolatileStore,
+    state: HibernateState,
+) -> anyhow::Result<()> {
+    store
+        .persist(mesh::payload::encode(state))
+        .await
+        .context("failed to write hibernate state")
+}
+
+/// Reads any hibernate state in `store`.
+///
+/// The state is left in place, so that resuming can be retried if the guest
+/// fails to resume. Call [`clear`] once the guest has resumed.
+pub async fn read(store: &mut dyn NonVolatileStore) -> anyhow::Result<Option<HibernateState>> {
+    let data = store
+        .restore()
+        .await
+        .context("failed to read hibernate state")?;
+    let Some(data) = data.filter(|data| !data.is_empty()) else {
+        return Ok(None);
+    };
+    let state = mesh::payload::decode(&data).context("invalid hibernate state")?;
+    Ok(Some(state))
+}
+
+/// Clears the hibernate state in `store`, so that the state is only used to
+/// resume the guest once.
+pub async fn clear(store: &mut dyn NonVolatileStore) -> anyhow::Result<()> {
+    store
+        .persist(Vec::new())
+        .await
+        .context("failed to clear hibernate state")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmgs_non_volatile_store::HvLiteVmgsNonVolatileStore;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use vmcore::non_volatile_store::EphemeralNonVolatileStore;
+
+    fn state() -> HibernateState {
+        HibernateState {
+            bios_guid: guid::guid!("0d7d5ab5-67c5-4a3b-b3b2-0f42ba5b5a3e"),
+            mem_size: 0x80000000,
+            proc_count: 4,
+        }
+    }
+
+    #[async_test]
+    async fn test_vmgs_round_trip(driver: DefaultDriver) {
+        let disk = disklayer_ram::ram_disk(4 * 1024 * 1024, false).unwrap();
+        let vmgs = vmgs::Vmgs::format_new(disk, None).await.unwrap();
+        let (client, _task) = vmgs_broker::spawn_vmgs_broker(driver, vmgs);
+        // Open the store the same way the VM worker does.
+        let mut store = client
+            .as_non_volatile_store(vmgs::FileId::HIBERNATE_STATE, false)
+            .unwrap();
+
+        assert!(read(store.as_mut()).await.unwrap().is_none());
+        persist(store.as_mut(), state()).await.unwrap();
+        let read_state = read(store.as_mut()).await.unwrap().unwrap();
+        assert_eq!(read_state.bios_guid, state().bios_guid);
+        assert_eq!(read_state.mem_size, state().mem_size);
+        assert_eq!(read_state.proc_count, state().proc_count);
+        read_state.validate(0x80000000, 4).unwrap();
+    }
+
+    /// The state survives until the guest has resumed, so a failed resume
+    /// can be retried.
+    #[async_test]
+    async fn test_state_kept_until_cleared() {
+        let mut store = EphemeralNonVolatileStore::new_boxed();
+        persist(store.as_mut(), state()).await.unwrap();
+
+        // A resume attempt that fails before the guest comes up.
+        let attempt = read(store.as_mut()).await.unwrap().unwrap();
+        attempt.validate(0x80000000, 4).unwrap();
+
+        // The retry still finds the state.
+        assert!(read(store.as_mut()).await.unwrap().is_some());
+
+        clear(store.as_mut()).await.unwrap();
+        assert!(read(store.as_mut()).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(state().validate(0x80000000, 4).is_ok());
+        assert!(state().validate(0x40000000, 4).is_err());
+        assert!(state().validate(0x80000000, 2).is_err());
+    }
+}
@@ -2,5 +2,8 @@
 // Licensed under the MIT License.
 
 pub mod dispatch;
+mod hibernate;
 mod rom;
+mod sleep;
 pub mod vm_loaders;
+mod vp_affinity;
@@ -0,0 +1,144 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for waking a guest from S3 when the firmware, rather than the VMM,
+//! built the ACPI tables.
+//!
+//! Neither the PCAT BIOS nor UEFI handle the resume path themselves, so the
+//! VMM needs the guest's waking vector. It lives in the FACS, which is found by
+//! walking the firmware's ACPI tables from the RSDP.
+
+use acpi_spec::Header;
+use acpi_spec::Rsdp;
+use acpi_spec::facs::Facs;
+use acpi_spec::fadt::Fadt;
+use anyhow::Context as _;
+use guestmem::GuestMemory;
+use std::mem::offset_of;
+use vm_topology::memory::MemoryLayout;
+use zerocopy::FromBytes;
+
+/// The BIOS read-only memory area, where PCAT places the RSDP.
+const BIOS_AREA: std::ops::Range<u64> = 0xe0000..0x100000;
+
+/// The RSDP is found on a 16-byte boundary.
+const RSDP_ALIGNMENT: usize = 16;
+
+/// The size of the chunks RAM is scanned in.
+const SCAN_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Locates the guest's FACS, returning its guest physical address.
+///
+/// The RSDP is searched for in the BIOS area first, and then in the rest of
+/// RAM below 4GB, which is where UEFI places it.
+pub fn find_facs(gm: &GuestMemory, mem_layout: &MemoryLayout) -> anyhow::Result<u64> {
+    let ranges = std::iter::once(BIOS_AREA).chain(
+        mem_layout
+            .ram()
+            .iter()
+            .map(|range| range.range.start()..range.range.end().min(1 << 32))
+            .filter(|range| !range.is_empty()),
+    );
+
+    let mut chunk = vec![0; SCAN_CHUNK_SIZE as usize];
+    for range in ranges {
+        for start in range.clone().step_by(SCAN_CHUNK_SIZE as usize) {
+            let len = (range.end - start).min(SCAN_CHUNK_SIZE) as usize;
+            let chunk = &mut chunk[..len];
+            if gm.read_at(start, chunk).is_err() {
+                continue;
+            }
+            for offset in (0..len).step_by(RSDP_ALIGNMENT) {
+                if let Some(rsdp) = parse_rsdp(&chunk[offset..]) {
+                    if let Some(facs) = facs_from_rsdp(gm, &rsdp)? {
+                        return Ok(facs);
+                    }
+                }
+            }
+        }
+    }
+    anyhow::bail!("could not locate the guest's ACPI tables")
+}
+
+fn parse_rsdp(data: &[u8]) -> Option<Rsdp> {
+    // The revision 1 RSDP is only 20 bytes, and is checksummed separately.
+    if data.len() < 20 || &data[..8] != b"RSD PTR " || checksum(&data[..20]) != 0 {
+        return None;
+    }
+    let mut bytes = [0; size_of::<Rsdp>()];
+    let len = data.len().min(bytes.len());
+    bytes[..len].copy_from_slice(&data[..len]);
+    Rsdp::read_from_bytes(&bytes).ok()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Walks the tables referenced by `rsdp` to find the FADT, and returns the
+/// FACS it points to.
+fn facs_from_rsdp(gm: &GuestMemory, rsdp: &Rsdp) -> anyhow::Result<Option<u64>> {
+    let (sdt, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt != 0 {
+        (rsdp.xsdt, 8)
+    } else {
+        (rsdp.rsdt.into(), 4)
+    };
+    let Ok(header) = gm.read_plain::<Header>(sdt) else {
+        return Ok(None);
+    };
+    let len = header.length.get() as usize;
+    if len < size_of::<Header>() {
+        return Ok(None);
+    }
+
+    let mut entries = vec![0; len - size_of::<Header>()];
+    gm.read_at(sdt + size_of::<Header>() as u64, &mut entries)
+        .context("failed to read system description table")?;
+    for entry in entries.chunks_exact(entry_size) {
+        let mut table = [0; 8];
+        table[..entry_size].copy_from_slice(entry);
+        let table = u64::from_le_bytes(table);
+        let header: Header = gm
+            .read_plain(table)
+            .context("failed to read ACPI table header")?;
+        if header.signature != *b"FACP" {
+            continue;
+        }
+
+        // The FADT's fields follow the header. Older FADTs are too short to
+        // contain X_FIRMWARE_CTRL.
+        let field = |offset: usize| table + (size_of::<Header>() + offset) as u64;
+        let x_firmware_ctrl = offset_of!(Fadt, x_firmware_ctrl);
+        let mut facs = 0;
+        if header.length.get() as usize >= size_of::<Header>() + x_firmware_ctrl + 8 {
+            facs = gm
+                .read_plain::<u64>(field(x_firmware_ctrl))
+                .context("failed to read FADT")?;
+        }
+        if facs == 0 {
+            facs = gm
+                .read_plain::<u32>(field(offset_of!(Fadt, facs)))
+                .context("failed to read FADT")?
+                .into();
+        }
+
+        let signature: [u8; 4] = gm
+            .read_plain(facs)
+            .context("failed to read FACS signature")?;
+        if facs == 0 || signature != *b"FACS" {
+            anyhow::bail!("FADT does not point to a valid FACS");
+        }
+        return Ok(Some(facs));
+    }
+    Ok(None)
+}
+
+/// Reads the guest's real-mode waking vector from the FACS at `facs_gpa`.
+pub fn waking_vector(gm: &GuestMemory, facs_gpa: u64) -> anyhow::Result<u32> {
+    let facs: Facs = gm.read_plain(facs_gpa).context("failed to read FACS")?;
+    let waking_vector = facs.firmware_waking_vector;
+    if waking_vector == 0 {
+        anyhow::bail!("guest did not set a waking vector");
+    }
+    Ok(waking_vector)
+}
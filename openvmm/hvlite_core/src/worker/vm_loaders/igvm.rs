@@ -1110,6 +1110,7 @@ enum ParameterAreaState {
                         | X86Register::Efer(_)
                         | X86Register::Pat(_)
                         | X86Register::Rbp(_)
+                        | X86Register::Rbx(_)
                         | X86Register::Rsi(_)
                         | X86Register::Rsp(_)
                         | X86Register::R8(_)
@@ -2,8 +2,12 @@
 // Licensed under the MIT License.
 
 use guestmem::GuestMemory;
+use hvdef::HV_PAGE_SIZE;
 use hvlite_defs::config::DEFAULT_MMIO_GAPS_AARCH64;
+use hvlite_defs::config::SmbiosConfig;
 use loader::importer::Aarch64Register;
+use loader::importer::BootPageAcceptance;
+use loader::importer::ImageLoad;
 use loader::importer::X86Register;
 use loader::linux::AcpiConfig;
 use loader::linux::CommandLineConfig;
@@ -32,6 +36,8 @@ pub enum Error {
     Loader(#[source] loader::linux::Error),
     #[error("device tree error")]
     Dt(#[source] DtError),
+    #[error("failed to import smbios tables")]
+    Smbios(#[source] anyhow::Error),
 }
 
 #[derive(Debug)]
@@ -54,12 +60,16 @@ pub fn load_linux_x86(
     cfg: &KernelConfig<'_>,
     gm: &GuestMemory,
     acpi_at_gpa: impl FnOnce(u64) -> AcpiTables,
+    smbios: &SmbiosConfig,
+    bios_guid: guid::Guid,
 ) -> Result<Vec<X86Register>, Error> {
     const GDT_BASE: u64 = 0x1000;
     const CR3_BASE: u64 = 0x4000;
     const ZERO_PAGE_BASE: u64 = 0x2000;
     const CMDLINE_BASE: u64 = 0x3000;
     const ACPI_BASE: u64 = 0xe0000;
+    // In the BIOS area that the guest scans for the entry point.
+    const SMBIOS_BASE: u64 = 0xf0000;
 
     let kaddr: u64 = 0x100000;
     let mut kernel_file = cfg.kernel;
@@ -124,6 +134,17 @@ pub fn load_linux_x86(
     )
     .map_err(Error::Loader)?;
 
+    let smbios_tables = super::smbios::build_smbios_tables(smbios, bios_guid, SMBIOS_BASE);
+    loader
+        .import_pages(
+            SMBIOS_BASE / HV_PAGE_SIZE,
+            (smbios_tables.len() as u64).div_ceil(HV_PAGE_SIZE),
+            "linux-smbios",
+            BootPageAcceptance::Exclusive,
+            &smbios_tables,
+        )
+        .map_err(Error::Smbios)?;
+
     Ok(loader.initial_regs())
 }
 
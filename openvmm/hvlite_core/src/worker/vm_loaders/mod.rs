@@ -6,4 +6,5 @@
 pub mod igvm;
 pub mod linux;
 pub mod pcat;
+pub mod smbios;
 pub mod uefi;
@@ -0,0 +1,180 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! SMBIOS tables for boot modes without firmware to build them.
+
+use guid::Guid;
+use hvlite_defs::config::SmbiosConfig;
+use zerocopy::IntoBytes;
+
+/// The size of the SMBIOS 3.0 entry point.
+const ENTRY_POINT_LEN: usize = 0x18;
+/// The offset of the structure table from the entry point.
+const TABLE_OFFSET: usize = 0x20;
+
+const TYPE_SYSTEM_INFORMATION: u8 = 1;
+const TYPE_BASEBOARD_INFORMATION: u8 = 2;
+const TYPE_SYSTEM_ENCLOSURE: u8 = 3;
+const TYPE_OEM_STRINGS: u8 = 11;
+const TYPE_END_OF_TABLE: u8 = 127;
+
+/// Builds an SMBIOS 3.0 entry point at `address`, followed by the structure
+/// table.
+///
+/// Guests without UEFI find the entry point by scanning `0xf0000..0x100000`,
+/// so `address` should be in that range and 16-byte aligned.
+pub fn build_smbios_tables(smbios: &SmbiosConfig, uuid: Guid, address: u64) -> Vec<u8> {
+    let mut table = Vec::new();
+    let mut handle = 0;
+    let mut add = |structure_type: u8, formatted: &[u8], strings: &[&str]| {
+        table.push(structure_type);
+        table.push((4 + formatted.len()) as u8);
+        table.extend_from_slice(&u16::to_le_bytes(handle));
+        table.extend_from_slice(formatted);
+        for s in strings {
+            table.extend_from_slice(s.as_bytes());
+            table.push(0);
+        }
+        if strings.is_empty() {
+            table.push(0);
+        }
+        table.push(0);
+        handle += 1;
+    };
+
+    // Strings are numbered from 1 in the order they follow the structure. An
+    // empty string cannot be represented, so it is reported as absent.
+    let mut system_strings = Vec::new();
+    let mut string = |value: &Option<String>| match value.as_deref() {
+        Some(value) if !value.is_empty() => {
+            system_strings.push(value);
+            system_strings.len() as u8
+        }
+        _ => 0,
+    };
+
+    let mut system = vec![
+        string(&smbios.system_manufacturer),
+        string(&smbios.system_product_name),
+        string(&smbios.system_version),
+        string(&smbios.system_serial_number),
+    ];
+    system.extend_from_slice(uuid.as_bytes());
+    // Wake-up type: power switch.
+    system.push(0x06);
+    system.push(string(&smbios.system_sku_number));
+    system.push(string(&smbios.system_family));
+    add(TYPE_SYSTEM_INFORMATION, &system, &system_strings);
+
+    if let Some(serial) = smbios.base_board_serial_number.as_deref() {
+        let (index, strings) = if serial.is_empty() {
+            (0, vec![])
+        } else {
+            (1, vec![serial])
+        };
+        // Manufacturer, product, version, and serial number.
+        add(TYPE_BASEBOARD_INFORMATION, &[0, 0, 0, index], &strings);
+    }
+
+    if smbios.chassis_serial_number.is_some() || smbios.chassis_asset_tag.is_some() {
+        let mut chassis_strings = Vec::new();
+        let mut string = |value: &Option<String>| match value.as_deref() {
+            Some(value) if !value.is_empty() => {
+                chassis_strings.push(value);
+                chassis_strings.len() as u8
+            }
+            _ => 0,
+        };
+        let chassis = [
+            // Manufacturer, type (other), and version.
+            0,
+            0x01,
+            0,
+            string(&smbios.chassis_serial_number),
+            string(&smbios.chassis_asset_tag),
+            // Boot-up, power supply, and thermal state (safe), and security
+            // status (none).
+            0x03,
+            0x03,
+            0x03,
+            0x03,
+        ];
+        add(TYPE_SYSTEM_ENCLOSURE, &chassis, &chassis_strings);
+    }
+
+    // Unlike other strings, each OEM string must be present, since the guest
+    // finds them by count.
+    let oem_strings = smbios
+        .oem_strings
+        .iter()
+        .map(|s| if s.is_empty() { " " } else { s.as_str() })
+        .collect::<Vec<_>>();
+    if !oem_strings.is_empty() {
+        add(TYPE_OEM_STRINGS, &[oem_strings.len() as u8], &oem_strings);
+    }
+
+    add(TYPE_END_OF_TABLE, &[], &[]);
+
+    let mut entry_point = [0; ENTRY_POINT_LEN];
+    entry_point[..5].copy_from_slice(b"_SM3_");
+    entry_point[6] = ENTRY_POINT_LEN as u8;
+    // SMBIOS 3.0.0, entry point revision 1.
+    entry_point[7] = 3;
+    entry_point[8] = 0;
+    entry_point[9] = 0;
+    entry_point[10] = 1;
+    entry_point[12..16].copy_from_slice(&(table.len() as u32).to_le_bytes());
+    entry_point[16..24].copy_from_slice(&(address + TABLE_OFFSET as u64).to_le_bytes());
+    entry_point[5] = 0u8.wrapping_sub(entry_point.iter().fold(0u8, |a, b| a.wrapping_add(*b)));
+
+    let mut tables = entry_point.to_vec();
+    tables.resize(TABLE_OFFSET, 0);
+    tables.extend_from_slice(&table);
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smbios_tables() {
+        let smbios = SmbiosConfig {
+            system_manufacturer: Some("Contoso".into()),
+            system_serial_number: Some(String::new()),
+            oem_strings: vec!["a=1".into(), "b=2".into()],
+            ..Default::default()
+        };
+        let tables = build_smbios_tables(&smbios, Guid::ZERO, 0xf0000);
+
+        assert_eq!(&tables[..5], b"_SM3_");
+        assert_eq!(
+            tables[..ENTRY_POINT_LEN]
+                .iter()
+                .fold(0u8, |a, b| a.wrapping_add(*b)),
+            0
+        );
+        let table_len = u32::from_le_bytes(tables[12..16].try_into().unwrap()) as usize;
+        assert_eq!(
+            u64::from_le_bytes(tables[16..24].try_into().unwrap()),
+            0xf0020
+        );
+        let table = &tables[TABLE_OFFSET..];
+        assert_eq!(table.len(), table_len);
+
+        // The system information, with only the manufacturer string.
+        assert_eq!(table[0], TYPE_SYSTEM_INFORMATION);
+        assert_eq!(table[1], 0x1b);
+        assert_eq!(&table[4..8], &[1, 0, 0, 0]);
+        let table = &table[0x1b..];
+        assert_eq!(&table[..9], b"Contoso\0\0");
+        let table = &table[9..];
+
+        // The OEM strings, with handle 1.
+        assert_eq!(&table[..5], &[TYPE_OEM_STRINGS, 5, 1, 0, 2]);
+        assert_eq!(&table[5..14], b"a=1\0b=2\0\0");
+        let table = &table[14..];
+
+        assert_eq!(table, &[TYPE_END_OF_TABLE, 4, 2, 0, 0, 0]);
+    }
+}
@@ -4,6 +4,7 @@
 use guestmem::GuestMemory;
 use guid::Guid;
 use hvdef::HV_PAGE_SIZE;
+use hvlite_defs::config::SmbiosConfig;
 use hvlite_defs::config::UefiConsoleMode;
 use loader::importer::Register;
 use loader::uefi::IMAGE_SIZE;
@@ -37,6 +38,8 @@ pub struct UefiLoadSettings {
     pub serial: bool,
     pub uefi_console_mode: Option<UefiConsoleMode>,
     pub default_boot_always_attempt: bool,
+    pub bios_guid: Guid,
+    pub smbios: SmbiosConfig,
 }
 
 /// Loads the UEFI firmware.
@@ -51,6 +54,7 @@ pub fn load_uefi(
     madt: &[u8],
     srat: &[u8],
     pptt: Option<&[u8]>,
+    ssdt: Option<&[u8]>,
 ) -> Result<Vec<Register>, Error> {
     if mem_layout.mmio().len() < 2 {
         return Err(Error::UnsupportedMmio);
@@ -123,7 +127,7 @@ pub fn load_uefi(
     .add_raw(config::BlobStructureType::Madt, madt)
     .add_raw(config::BlobStructureType::Srat, srat)
     .add_raw(config::BlobStructureType::MemoryMap, memory_map.as_bytes())
-    .add(&config::BiosGuid(Guid::new_random()))
+    .add(&config::BiosGuid(load_settings.bios_guid))
     .add(&config::Entropy(entropy))
     .add(&config::MmioRanges([
         config::Mmio {
@@ -147,6 +151,50 @@ pub fn load_uefi(
     })
     .add(&flags);
 
+    let smbios = &load_settings.smbios;
+    for (structure_type, value) in [
+        (
+            config::BlobStructureType::SmbiosSystemManufacturer,
+            &smbios.system_manufacturer,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemProductName,
+            &smbios.system_product_name,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemVersion,
+            &smbios.system_version,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemSkuNumber,
+            &smbios.system_sku_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemFamily,
+            &smbios.system_family,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemSerialNumber,
+            &smbios.system_serial_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosBaseSerialNumber,
+            &smbios.base_board_serial_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosChassisSerialNumber,
+            &smbios.chassis_serial_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosChassisAssetTag,
+            &smbios.chassis_asset_tag,
+        ),
+    ] {
+        if let Some(value) = value {
+            cfg.add_cstring(structure_type, value.as_bytes());
+        }
+    }
+
     #[cfg(guest_arch = "aarch64")]
     {
         cfg.add(&config::Gic {
@@ -159,6 +207,10 @@ pub fn load_uefi(
         cfg.add_raw(config::BlobStructureType::Pptt, pptt);
     }
 
+    if let Some(ssdt) = ssdt {
+        cfg.add_raw(config::BlobStructureType::Ssdt, ssdt);
+    }
+
     let mut loader = Loader::new(gm.clone(), mem_layout, hvdef::Vtl::Vtl0);
 
     loader::uefi::load(
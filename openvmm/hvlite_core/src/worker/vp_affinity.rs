@@ -0,0 +1,54 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pinning VP backing threads to host CPUs.
+
+/// A handle to a VP's backing thread, used to change its host CPU affinity.
+#[derive(Debug)]
+pub struct VpThread {
+    #[cfg(target_os = "linux")]
+    thread: pal::unix::pthread::Pthread,
+}
+
+impl VpThread {
+    /// Returns a handle to the current thread.
+    pub fn current() -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            thread: pal::unix::pthread::Pthread::current(),
+        }
+    }
+
+    /// Pins the thread to the host CPUs in `cpus`, or lets it run on any CPU
+    /// if `cpus` is empty.
+    #[cfg(target_os = "linux")]
+    pub fn set_affinity(&self, cpus: &[u32]) -> anyhow::Result<()> {
+        use pal::unix::affinity::CpuSet;
+        use pal::unix::affinity::max_procs;
+
+        let mut cpu_set = CpuSet::new();
+        if cpus.is_empty() {
+            for cpu in 0..max_procs() {
+                cpu_set.set(cpu);
+            }
+        } else {
+            for &cpu in cpus {
+                if cpu >= max_procs() {
+                    anyhow::bail!("invalid host cpu {cpu}");
+                }
+                cpu_set.set(cpu);
+            }
+        }
+        self.thread.set_affinity(&cpu_set)?;
+        Ok(())
+    }
+
+    /// Pins the thread to the host CPUs in `cpus`.
+    ///
+    /// Not supported on this platform.
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_affinity(&self, cpus: &[u32]) -> anyhow::Result<()> {
+        let _ = cpus;
+        anyhow::bail!("vp affinity is only supported on linux")
+    }
+}
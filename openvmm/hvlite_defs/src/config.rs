@@ -10,10 +10,14 @@
 use mesh::MeshPayload;
 use mesh::payload::Protobuf;
 use net_backend_resources::mac_address::MacAddress;
+use nvme_resources::NamespaceDefinition;
 use std::fmt;
 use std::fs::File;
 use vm_resource::Resource;
+use vm_resource::kind::NetEndpointHandleKind;
 use vm_resource::kind::PciDeviceHandleKind;
+use vm_resource::kind::SerialBackendHandle;
+use vm_resource::kind::UsbDeviceHandleKind;
 use vm_resource::kind::VirtioDeviceHandle;
 use vm_resource::kind::VmbusDeviceHandleKind;
 use vmgs_resources::VmgsResource;
@@ -41,6 +45,21 @@ pub struct Config {
     pub virtio_console_pci: bool,
     pub virtio_serial: Option<SerialPipes>,
     pub virtio_devices: Vec<(VirtioBus, Resource<VirtioDeviceHandle>)>,
+    /// devices attached to an xHCI controller, which is only present if this
+    /// is non-empty
+    pub usb_devices: Vec<Resource<UsbDeviceHandleKind>>,
+    /// emulated e1000e NICs, for guests without paravirtualized network
+    /// drivers
+    pub e1000_nics: Vec<E1000NicConfig>,
+    /// multi-port 16550 serial cards on the emulated PCI bus
+    pub pci_serial_cards: Vec<PciSerialCardConfig>,
+    /// ivshmem shared memory devices on the emulated PCI bus
+    pub ivshmem_devices: Vec<IvshmemConfig>,
+    /// an NVMe controller on the emulated PCI bus whose namespaces are
+    /// exposed through SR-IOV virtual functions
+    pub nvme_sriov: Option<NvmeSriovConfig>,
+    /// host PCI devices assigned through VFIO
+    pub vfio_devices: Vec<VfioDeviceConfig>,
     #[cfg(windows)]
     pub vpci_resources: Vec<virt_whp::device::DeviceHandle>,
     pub vmgs: Option<VmgsResource>,
@@ -48,7 +67,12 @@ pub struct Config {
     pub custom_uefi_vars: firmware_uefi_custom_vars::CustomVars,
     // TODO: move FirmwareEvent somewhere not GED-specific.
     pub firmware_event_send: Option<mesh::Sender<get_resources::ged::FirmwareEvent>>,
+    /// channel to report crashes signaled through the Hyper-V guest crash
+    /// registers
+    pub guest_crash_send: Option<mesh::Sender<vmm_core_defs::GuestCrash>>,
     pub debugger_rpc: Option<mesh::Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
+    /// A second debugger, which debugs VTL2 independently of `debugger_rpc`.
+    pub vtl2_debugger_rpc: Option<mesh::Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
     pub vmbus_devices: Vec<(DeviceVtl, Resource<VmbusDeviceHandleKind>)>,
     pub chipset_devices: Vec<ChipsetDeviceHandle>,
     pub generation_id_recv: Option<mesh::Receiver<[u8; 16]>>,
@@ -56,6 +80,35 @@ pub struct Config {
     pub rtc_delta_milliseconds: i64,
     /// allow the guest to reset without notifying the client
     pub automatic_guest_reset: bool,
+    /// advertise the S3 (suspend-to-RAM) sleep state to the guest
+    pub enable_s3: bool,
+    /// advertise the S4 (hibernate) sleep state to the guest
+    pub enable_s4: bool,
+    /// resume a guest that hibernated during a previous run, using the
+    /// configuration recorded in the VMGS
+    pub resume_from_hibernate: bool,
+    /// PCI device numbers of the slots that support ACPI hot-plug
+    pub pci_hotplug_slots: Vec<u8>,
+    /// fixed placements for devices on the emulated PCI bus
+    pub pci_slots: Vec<PciSlotConfig>,
+    /// the number of PCI-to-PCI bridges on bus 0 of the emulated PCI bus,
+    /// named `pci-bridge-<n>`. Bridge `n` leads to bus `n + 1`.
+    pub pci_bridges: u8,
+    /// describe the pvpanic device in the guest's ACPI tables. The device
+    /// itself is added to `chipset_devices`.
+    pub pvpanic: bool,
+    /// SMBIOS values reported by the firmware
+    pub smbios: SmbiosConfig,
+    /// host CPUs to pin VP backing threads to, by VP index
+    pub vp_affinity: Vec<(u32, Vec<u32>)>,
+    /// CPUID results to report to the guest, with later entries taking
+    /// precedence
+    pub cpuid: Vec<CpuidOverride>,
+    /// record the results of guest port I/O and MMIO accesses, or compare them
+    /// against an earlier recording
+    pub io_trace: Option<IoTraceConfig>,
+    /// trace guest port I/O, MMIO, and MSR accesses
+    pub access_trace: Option<GuestAccessTraceConfig>,
 }
 
 // ARM64 needs a larger low gap.
@@ -116,6 +169,9 @@ pub enum LoadMode {
         enable_vpci_boot: bool,
         uefi_console_mode: Option<UefiConsoleMode>,
         default_boot_always_attempt: bool,
+        /// A file to persist the UEFI variable store to, when there is no
+        /// VMGS.
+        nvram: Option<File>,
     },
     Pcat {
         firmware: RomFileLocation,
@@ -130,6 +186,29 @@ pub enum LoadMode {
     None,
 }
 
+/// SMBIOS values reported to the guest by the UEFI or PCAT firmware, or, for
+/// Linux direct boot on x86, in SMBIOS tables built by the loader.
+///
+/// Unset values keep the firmware's defaults. PCAT only supports the UUID and
+/// the serial numbers and asset tag; the other fields are ignored.
+#[derive(Debug, Clone, Default, MeshPayload)]
+pub struct SmbiosConfig {
+    /// The system UUID, which is also used as the BIOS GUID.
+    pub uuid: Option<Guid>,
+    pub system_manufacturer: Option<String>,
+    pub system_product_name: Option<String>,
+    pub system_version: Option<String>,
+    pub system_sku_number: Option<String>,
+    pub system_family: Option<String>,
+    pub system_serial_number: Option<String>,
+    pub base_board_serial_number: Option<String>,
+    pub chassis_serial_number: Option<String>,
+    pub chassis_asset_tag: Option<String>,
+    /// The strings of the type 11 (OEM strings) structure. Only supported for
+    /// Linux direct boot on x86, as neither firmware can report them.
+    pub oem_strings: Vec<String>,
+}
+
 #[derive(Debug, Clone, Copy, MeshPayload)]
 pub struct SerialInformation {
     pub io_port: u16,
@@ -173,6 +252,36 @@ pub struct VpciDeviceConfig {
     pub resource: Resource<PciDeviceHandleKind>,
 }
 
+/// A CPUID result, applied on top of the results the hypervisor reports.
+#[derive(Debug, Protobuf, Clone)]
+pub struct CpuidOverride {
+    pub function: u32,
+    /// The subleaf, or `None` to match any subleaf.
+    pub index: Option<u32>,
+    pub result: [u32; 4],
+    /// The bits of `result` to report.
+    pub mask: [u32; 4],
+}
+
+/// A trace of the results of guest port I/O and MMIO accesses.
+#[derive(Debug, MeshPayload)]
+pub enum IoTraceConfig {
+    /// Write the accesses to the file.
+    Record(File),
+    /// Compare the accesses against those recorded in the file, returning the
+    /// recorded data for reads.
+    Compare(File),
+}
+
+/// Tracing of guest port I/O, MMIO, and MSR accesses.
+#[derive(Debug, MeshPayload)]
+pub struct GuestAccessTraceConfig {
+    /// Which accesses to trace, such as `pio=0x60-0x64,dev=rtc`.
+    pub filter: String,
+    /// The file to write the trace to. If `None`, accesses are logged.
+    pub file: Option<File>,
+}
+
 #[derive(Debug, Protobuf)]
 pub struct ProcessorTopologyConfig {
     pub proc_count: u32,
@@ -223,13 +332,54 @@ pub enum ArchTopologyConfig {
 pub struct MemoryConfig {
     pub mem_size: u64,
     pub mmio_gaps: Vec<MemoryRange>,
+    /// The ECAM window of the emulated PCI bus, 1MB per bus starting at bus
+    /// 0, for access to extended config space. Must be at the start of the
+    /// low MMIO gap.
+    pub pci_ecam: Option<MemoryRange>,
     pub prefetch_memory: bool,
+    /// The amount of memory that can be hot-added at runtime via a virtio-mem
+    /// device, or zero to not add the device.
+    pub hotplug_size: u64,
+    /// Add a virtio-balloon device, so that memory can be reclaimed from the
+    /// guest at runtime.
+    pub balloon: bool,
+    /// Back guest RAM with huge pages of this size (2MB or 1GB) from
+    /// hugetlbfs, falling back to normal pages if they are not available.
+    pub hugetlb_page_size: Option<u64>,
+    /// Back guest RAM with a file instead of anonymous memory.
+    pub backing_file: Option<MemoryBackingFile>,
+    /// The host NUMA policy to allocate guest RAM with.
+    pub numa_policy: Option<MemoryNumaPolicy>,
+}
+
+#[derive(Debug, MeshPayload, Clone)]
+pub enum MemoryNumaPolicy {
+    /// Interleave guest RAM across these host nodes, or across all online
+    /// nodes if empty.
+    Interleave(Vec<u32>),
+    /// Allocate guest RAM only from these host nodes.
+    Bind(Vec<u32>),
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct MemoryBackingFile {
+    pub file: File,
+    /// Map the file directly, so that guest writes go to the file. Otherwise,
+    /// map it copy-on-write, so that guest writes are private to the VM. The
+    /// file must then be at least as large as RAM and must not change while
+    /// the VM is running.
+    pub shared: bool,
 }
 
 #[derive(Debug, MeshPayload, Default)]
 pub struct VmbusConfig {
     pub vsock_listener: Option<unix_socket::UnixListener>,
     pub vsock_path: Option<String>,
+    /// Guest vsock ports to bridge to the same port on the host's VM sockets.
+    pub vsock_host_connect_ports: Vec<u32>,
+    /// Host VM socket ports to listen on, paired with the guest vsock ports to
+    /// relay their connections to.
+    pub vsock_host_listen_ports: Vec<(u32, u32)>,
     pub vmbus_max_version: Option<u32>,
     #[cfg(windows)]
     pub vmbusproxy_handle: Option<vmbus_proxy::ProxyHandle>,
@@ -299,6 +449,63 @@ pub struct KernelVmNicConfig {
     pub switch_port_id: SwitchPortId,
 }
 
+#[derive(Debug, MeshPayload)]
+pub struct E1000NicConfig {
+    pub mac_address: MacAddress,
+    pub endpoint: Resource<NetEndpointHandleKind>,
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct PciSerialCardConfig {
+    /// The backends for each port. Cards have one, two, or four ports.
+    pub ports: Vec<Resource<SerialBackendHandle>>,
+    pub wait_for_rts: bool,
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct IvshmemConfig {
+    /// The file backing the shared memory, at least `size` bytes long.
+    pub file: File,
+    /// The size of the shared memory BAR. Must be a power of two.
+    pub size: u64,
+    /// A connection to the host peer for doorbell interrupts.
+    pub doorbell: Option<unix_socket::UnixStream>,
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct NvmeSriovConfig {
+    /// The namespaces of each virtual function. There can be at most 7
+    /// virtual functions, which follow the physical function on the same
+    /// device number.
+    pub virtual_functions: Vec<Vec<NamespaceDefinition>>,
+}
+
+/// A fixed placement for a device on the emulated PCI bus.
+#[derive(Debug, MeshPayload)]
+pub struct PciSlotConfig {
+    /// The device's name, such as `e1000-0` or `vfio-0000:01:00.0`.
+    pub device: String,
+    /// Bus 0, or the secondary bus of one of the PCI bridges.
+    pub bus: u8,
+    pub device_number: u8,
+    pub function: u8,
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct VfioDeviceConfig {
+    /// The device's PCI address, such as `0000:01:00.0`.
+    pub pci_id: String,
+    /// The VFIO container, with the type 1 IOMMU set.
+    pub container: File,
+    /// The device's IOMMU group, attached to the container.
+    pub group: File,
+    /// The VFIO device.
+    pub device: File,
+    /// If set, the device is assigned to VTL2 on a VPCI bus with this
+    /// instance ID. Otherwise, it is placed on VTL0's emulated PCI bus.
+    pub vtl2_instance_id: Option<Guid>,
+}
+
 #[derive(Clone, Debug, MeshPayload)]
 pub struct SwitchPortId {
     pub switch: Guid,
@@ -14,4 +14,6 @@
 #[derive(MeshPayload)]
 pub struct MeshHostParams {
     pub runner: WorkerHostRunner,
+    /// The log filter to follow, in `OPENVMM_LOG` syntax.
+    pub log_filter: Option<mesh::Cell<String>>,
 }
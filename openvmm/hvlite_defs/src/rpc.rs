@@ -4,7 +4,9 @@
 //! RPC types for communicating with the VM worker.
 
 use crate::config::DeviceVtl;
+use chipset_resources::pm::WakeEvent;
 use guid::Guid;
+use memory_range::MemoryRange;
 use mesh::CancelContext;
 use mesh::MeshPayload;
 use mesh::error::RemoteError;
@@ -13,6 +15,7 @@
 use mesh::rpc::Rpc;
 use std::fmt;
 use std::fs::File;
+use std::time::Duration;
 use vm_resource::Resource;
 use vm_resource::kind::VmbusDeviceHandleKind;
 
@@ -22,15 +25,59 @@ pub enum VmRpc {
     Resume(Rpc<(), bool>),
     Pause(Rpc<(), bool>),
     ClearHalt(Rpc<(), bool>),
+    Wake(FailableRpc<WakeEvent, ()>),
+    /// Requests a wake after the given delay, as if the RTC alarm fired,
+    /// replacing any earlier request. This wakes the guest from S3 or powers
+    /// it on if it has powered off. `None` cancels the request.
+    ScheduleWake(Rpc<Option<Duration>, ()>),
     Reset(FailableRpc<(), ()>),
     Nmi(Rpc<u32, ()>),
     AddVmbusDevice(FailableRpc<(DeviceVtl, Resource<VmbusDeviceHandleKind>), ()>),
+    RemoveVmbusDevice(FailableRpc<Guid, ()>),
+    /// Revokes the VPCI device with the given instance ID from the guest, as
+    /// when the host removes an SR-IOV virtual function.
+    RevokeVpciDevice(FailableRpc<Guid, ()>),
+    /// Offers a revoked VPCI device to the guest again.
+    OfferVpciDevice(FailableRpc<Guid, ()>),
     ConnectHvsock(FailableRpc<(CancelContext, Guid, DeviceVtl), unix_socket::UnixStream>),
     PulseSaveRestore(Rpc<(), Result<(), PulseSaveRestoreError>>),
     StartReloadIgvm(FailableRpc<File, ()>),
     CompleteReloadIgvm(FailableRpc<bool, ()>),
     ReadMemory(FailableRpc<(u64, usize), Vec<u8>>),
     WriteMemory(FailableRpc<(u64, Vec<u8>), ()>),
+    /// Sets the total size of guest memory, hot-adding or removing memory via
+    /// the virtio-mem device.
+    SetMemorySize(FailableRpc<u64, ()>),
+    /// Sets the target size of the virtio-balloon device, in bytes.
+    SetBalloonSize(FailableRpc<u64, ()>),
+    /// Pins a VP's backing thread to the given host CPUs, or unpins it if the
+    /// list is empty.
+    SetVpAffinity(FailableRpc<(u32, Vec<u32>), ()>),
+    /// Gets the guest physical address ranges of RAM, including VTL2 memory.
+    RamRanges(Rpc<(), Vec<MemoryRange>>),
+    /// Enables or disables tracking of the VTL0 RAM pages written by the
+    /// guest, so that RAM can be copied while the VM runs.
+    SetDirtyTracking(FailableRpc<bool, ()>),
+    /// Gets a bitmap of the pages in the given RAM range (one of the ranges
+    /// returned by `RamRanges`) written by the guest since dirty tracking was
+    /// enabled or the range was last queried, and marks them clean.
+    QueryDirtyPages(FailableRpc<MemoryRange, Vec<u64>>),
+    /// Gets whether the VM is running (as opposed to paused).
+    IsRunning(Rpc<(), bool>),
+    /// Halts the VM as if the guest had powered it off, without the guest's
+    /// involvement.
+    PowerOff(Rpc<(), ()>),
+    /// Latches the given general-purpose event (a GPE0 bit number) in the ACPI
+    /// power management device, asserting the SCI if the guest has enabled
+    /// that event.
+    Sci(FailableRpc<u32, ()>),
+    /// Injects a machine check exception into the given VP.
+    MachineCheck(FailableRpc<u32, ()>),
+    /// Gets the VTL0 general purpose and control registers of the given VP.
+    VpRegisters(FailableRpc<u32, virt::vp::Registers>),
+    /// Translates a guest virtual address to a guest physical address using
+    /// the current VTL0 paging state of the given VP.
+    TranslateGva(FailableRpc<(u32, u64), u64>),
 }
 
 #[derive(Debug, MeshPayload, thiserror::Error)]
@@ -55,14 +102,31 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             VmRpc::Resume(_) => "Resume",
             VmRpc::Pause(_) => "Pause",
             VmRpc::ClearHalt(_) => "ClearHalt",
+            VmRpc::Wake(_) => "Wake",
+            VmRpc::ScheduleWake(_) => "ScheduleWake",
             VmRpc::Nmi(_) => "Nmi",
             VmRpc::AddVmbusDevice(_) => "AddVmbusDevice",
+            VmRpc::RemoveVmbusDevice(_) => "RemoveVmbusDevice",
+            VmRpc::RevokeVpciDevice(_) => "RevokeVpciDevice",
+            VmRpc::OfferVpciDevice(_) => "OfferVpciDevice",
             VmRpc::ConnectHvsock(_) => "ConnectHvsock",
             VmRpc::PulseSaveRestore(_) => "PulseSaveRestore",
             VmRpc::StartReloadIgvm(_) => "StartReloadIgvm",
             VmRpc::CompleteReloadIgvm(_) => "CompleteReloadIgvm",
             VmRpc::ReadMemory(_) => "ReadMemory",
             VmRpc::WriteMemory(_) => "WriteMemory",
+            VmRpc::SetMemorySize(_) => "SetMemorySize",
+            VmRpc::SetBalloonSize(_) => "SetBalloonSize",
+            VmRpc::SetVpAffinity(_) => "SetVpAffinity",
+            VmRpc::RamRanges(_) => "RamRanges",
+            VmRpc::SetDirtyTracking(_) => "SetDirtyTracking",
+            VmRpc::QueryDirtyPages(_) => "QueryDirtyPages",
+            VmRpc::IsRunning(_) => "IsRunning",
+            VmRpc::PowerOff(_) => "PowerOff",
+            VmRpc::Sci(_) => "Sci",
+            VmRpc::MachineCheck(_) => "MachineCheck",
+            VmRpc::VpRegisters(_) => "VpRegisters",
+            VmRpc::TranslateGva(_) => "TranslateGva",
         };
         f.pad(s)
     }
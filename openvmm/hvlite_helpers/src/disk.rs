@@ -3,6 +3,8 @@
 
 //! Guest disk helpers.
 
+use anyhow::Context;
+use guid::Guid;
 use std::path::Path;
 use vm_resource::Resource;
 use vm_resource::kind::DiskHandleKind;
@@ -10,8 +12,10 @@
 /// Opens the resources needed for using a disk from a file at `path`.
 ///
 /// If the file ends with .vhd and is a fixed VHD1, it will be opened using
-/// the user-mode VHD parser. Otherwise, if the file ends with .vhd or
-/// .vhdx, the file will be opened using the kernel-mode VHD parser.
+/// the user-mode VHD parser. Otherwise, if the file ends with .vhd, .vhdx, or
+/// .avhdx, the file will be opened using the kernel-mode VHD parser on
+/// Windows, or the user-mode VHDX parser elsewhere. VHD Set (.vhds) files are
+/// only supported on Windows.
 pub fn open_disk_type(path: &Path, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
     Ok(match path.extension().and_then(|s| s.to_str()) {
         Some("vhd") => {
@@ -37,7 +41,7 @@ pub fn open_disk_type(path: &Path, read_only: bool) -> anyhow::Result<Resource<D
                 Err(err) => return Err(err.into()),
             }
         }
-        Some("vhdx") => {
+        Some("vhdx" | "avhdx") => {
             #[cfg(windows)]
             {
                 Resource::new(disk_vhdmp::OpenVhdmpDiskConfig(
@@ -45,8 +49,9 @@ pub fn open_disk_type(path: &Path, read_only: bool) -> anyhow::Result<Resource<D
                 ))
             }
             #[cfg(not(windows))]
-            anyhow::bail!("VHDX not supported on Linux");
+            open_vhdx(path, read_only)?
         }
+        Some("vhds") => open_vhd_set(path, None, read_only)?,
         Some("iso") if !read_only => {
             anyhow::bail!("iso file cannot be opened as read/write")
         }
@@ -86,9 +91,7 @@ pub fn create_disk_type(path: &Path, size: u64) -> anyhow::Result<Resource<DiskH
             disk_vhd1::Vhd1Disk::make_fixed(&file)?;
             Resource::new(disk_backend_resources::FixedVhd1DiskHandle(file))
         }
-        Some("vhdx") => {
-            anyhow::bail!("creating vhdx not supported")
-        }
+        Some("vhdx") => create_vhdx(path, size)?,
         Some("iso") => {
             anyhow::bail!("creating iso not supported")
         }
@@ -105,3 +108,90 @@ pub fn create_disk_type(path: &Path, size: u64) -> anyhow::Result<Resource<DiskH
         }
     })
 }
+
+/// Opens the resources needed for using a VHDX at `path` with the user-mode
+/// VHDX parser, including the chain of parents of a differencing VHDX.
+pub fn open_vhdx(path: &Path, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(path)?;
+
+    let info = disk_vhdx::info(&file)?;
+    let parent = if let Some(locator) = info.parent_locator {
+        let parent_path = locator
+            .parent_paths(path)
+            .into_iter()
+            .find(|path| path.exists())
+            .with_context(|| format!("could not locate parent of {}", path.display()))?;
+
+        let parent_file = std::fs::File::open(&parent_path)?;
+        let parent_info = disk_vhdx::info(&parent_file)
+            .with_context(|| format!("failed to open parent {}", parent_path.display()))?;
+        if !locator
+            .linkage()
+            .any(|guid| guid == parent_info.data_write_guid)
+        {
+            anyhow::bail!(
+                "parent {} has been modified since {} was created",
+                parent_path.display(),
+                path.display()
+            );
+        }
+        Some(open_vhdx(&parent_path, true)?)
+    } else {
+        None
+    };
+
+    Ok(Resource::new(disk_backend_resources::VhdxDiskHandle {
+        file,
+        parent,
+    }))
+}
+
+/// Opens the resources needed for using the VHD Set (.vhds) file at `path`,
+/// including its checkpoint chain.
+///
+/// If `snapshot` is set, the disk is opened read-only as of that checkpoint.
+/// Otherwise, the current state of the disk is opened.
+pub fn open_vhd_set(
+    path: &Path,
+    snapshot: Option<Guid>,
+    read_only: bool,
+) -> anyhow::Result<Resource<DiskHandleKind>> {
+    if snapshot.is_some() && !read_only {
+        anyhow::bail!("VHD Set checkpoints can only be opened read-only");
+    }
+    #[cfg(windows)]
+    {
+        let vhd = match snapshot {
+            Some(snapshot) => disk_vhdmp::VhdmpDisk::open_vhd_set_snapshot(path, snapshot)?,
+            None => disk_vhdmp::VhdmpDisk::open_vhd(path, read_only)?,
+        };
+        Ok(Resource::new(disk_vhdmp::OpenVhdmpDiskConfig(vhd)))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        anyhow::bail!(
+            "VHD Set files are only supported on Windows; open the checkpoint's .avhdx file instead"
+        )
+    }
+}
+
+/// Creates a dynamic VHDX at `path` and opens the resources needed for using
+/// it.
+pub fn create_vhdx(path: &Path, size: u64) -> anyhow::Result<Resource<DiskHandleKind>> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    disk_vhdx::VhdxDisk::create(&file, size)?;
+    Ok(Resource::new(disk_backend_resources::VhdxDiskHandle {
+        file,
+        parent: None,
+    }))
+}
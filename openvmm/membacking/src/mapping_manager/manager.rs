@@ -104,6 +104,9 @@ pub async fn new_remote_mapper(
 
     /// Adds an active mapping.
     ///
+    /// If `copy_on_write`, each VA mapper maps `mappable` privately, so
+    /// writes are not seen by other mappers or written back to `mappable`.
+    ///
     /// TODO: currently this will panic if the mapping overlaps an existing
     /// mapping. This needs to be fixed to allow this to overlap existing
     /// mappings, in which case the old ones will be split and replaced.
@@ -113,12 +116,14 @@ pub async fn add_mapping(
         mappable: Mappable,
         file_offset: u64,
         writable: bool,
+        copy_on_write: bool,
     ) {
         let params = MappingParams {
             range,
             mappable,
             file_offset,
             writable,
+            copy_on_write,
         };
 
         self.req_send
@@ -166,6 +171,7 @@ fn inspect_mappings(mappings: &Vec<Mapping>) -> impl '_ + Inspect {
                 inspect::adhoc(|req| {
                     req.respond()
                         .field("writable", mapping.params.writable)
+                        .field("copy_on_write", mapping.params.copy_on_write)
                         .hex("file_offset", mapping.params.file_offset);
                 }),
             );
@@ -189,6 +195,9 @@ pub struct MappingParams {
     pub file_offset: u64,
     /// Whether to map the memory as writable.
     pub writable: bool,
+    /// Whether to map the memory privately, so that writes are not written
+    /// back to `mappable`.
+    pub copy_on_write: bool,
 }
 
 struct Mappers {
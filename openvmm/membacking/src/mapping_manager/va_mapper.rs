@@ -110,19 +110,27 @@ async fn run(mut self, mut req_recv: mesh::Receiver<MapperRequest>) {
                     mappable,
                     writable,
                     file_offset,
+                    copy_on_write,
                 }) => {
                     tracing::debug!(%range, "mapping received for range");
 
-                    self.inner
-                        .mapping
-                        .map_file(
+                    if copy_on_write && writable {
+                        self.inner.mapping.map_file_copy_on_write(
+                            range.start() as usize,
+                            range.len() as usize,
+                            &mappable,
+                            file_offset,
+                        )
+                    } else {
+                        self.inner.mapping.map_file(
                             range.start() as usize,
                             range.len() as usize,
                             &mappable,
                             file_offset,
                             writable,
                         )
-                        .expect("oom mapping file");
+                    }
+                    .expect("oom mapping file");
 
                     self.wake_waiters(range, Some(writable));
                 }
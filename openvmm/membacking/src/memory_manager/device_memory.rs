@@ -116,6 +116,7 @@ fn map(
                 new_mapping.mappable.clone(),
                 new_mapping.file_offset,
                 new_mapping.writable,
+                false,
             ));
         }
         state.mappings.push(new_mapping);
@@ -169,6 +170,7 @@ fn map_to_guest(&mut self, gpa: u64, writable: bool) -> io::Result<()> {
                         mapping.mappable.clone(),
                         mapping.file_offset,
                         mapping.writable,
+                        false,
                     )
                     .await;
             }
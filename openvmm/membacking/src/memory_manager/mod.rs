@@ -23,6 +23,11 @@
 use memory_range::MemoryRange;
 use mesh::MeshPayload;
 use pal_async::DefaultPool;
+use sparse_mmap::SparseMapping;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::sync::Arc;
 use std::thread::JoinHandle;
 use thiserror::Error;
@@ -52,12 +57,18 @@ pub struct GuestMemoryManager {
 
     vtl0_alias_map_offset: Option<u64>,
     pin_mappings: bool,
+    /// The huge page size backing guest RAM, if huge pages are in use.
+    hugetlb_page_size: Option<u64>,
+    /// Whether guest RAM is mapped copy-on-write from a backing file.
+    copy_on_write: bool,
 }
 
 #[derive(Debug)]
 struct RamRegion {
     range: MemoryRange,
     handle: RegionHandle,
+    /// The offset of the region in the guest RAM allocation.
+    offset: u64,
 }
 
 /// Errors when attaching a partition to a [`GuestMemoryManager`].
@@ -69,6 +80,9 @@ pub enum PartitionAttachError {
     /// Failure to map memory into a partition.
     #[error("failed to attach partition to memory manager")]
     PartitionMapper(#[source] crate::partition_mapper::PartitionMapperError),
+    /// Copy-on-write RAM cannot be mapped from another process.
+    #[error("guest RAM mapped copy-on-write cannot be mapped from another process")]
+    CopyOnWrite,
 }
 
 /// Errors creating a [`GuestMemoryManager`].
@@ -80,6 +94,9 @@ pub enum MemoryBuildError {
     /// Couldn't allocate RAM.
     #[error("failed to allocate memory")]
     AllocationFailed(#[source] std::io::Error),
+    /// Couldn't back RAM with the provided file.
+    #[error("failed to back memory with file")]
+    BackingFile(#[source] std::io::Error),
     /// Couldn't allocate VA mapper.
     #[error("failed to create VA mapper")]
     VaMapper(#[source] VaMapperError),
@@ -98,6 +115,9 @@ pub struct GuestMemoryBuilder {
     prefetch_ram: bool,
     pin_mappings: bool,
     x86_legacy_support: bool,
+    hugetlb_page_size: Option<u64>,
+    backing_file: Option<(File, bool)>,
+    numa_policy: Option<sparse_mmap::NumaPolicy>,
 }
 
 impl GuestMemoryBuilder {
@@ -109,6 +129,9 @@ pub fn new() -> Self {
             pin_mappings: false,
             prefetch_ram: false,
             x86_legacy_support: false,
+            hugetlb_page_size: None,
+            backing_file: None,
+            numa_policy: None,
         }
     }
 
@@ -158,6 +181,51 @@ pub fn x86_legacy_support(mut self, enable: bool) -> Self {
         self
     }
 
+    /// Specify whether to allocate RAM from huge pages of the given size (2MB
+    /// or 1GB) from hugetlbfs. This reduces TLB pressure for large VMs.
+    ///
+    /// Huge pages are only used if every RAM range is aligned to the page size
+    /// and the host has enough of them available. Otherwise, a warning is
+    /// logged and RAM is allocated from normal pages. This has no effect if
+    /// an existing backing is provided.
+    pub fn hugetlb_page_size(mut self, page_size: Option<u64>) -> Self {
+        self.hugetlb_page_size = page_size;
+        self
+    }
+
+    /// Specifies a file to back RAM with, instead of anonymous memory.
+    ///
+    /// If `shared` is true, the file is mapped directly, so guest writes go to
+    /// the file and other processes mapping it can observe guest memory. The
+    /// file is extended to the size of RAM if it is smaller.
+    ///
+    /// Otherwise, the file is mapped copy-on-write, so guest RAM shares the
+    /// file's pages until the guest writes to them, and guest writes never
+    /// reach the file. Any number of VMs can share one such file, which must
+    /// be at least as large as RAM and must not change while it is in use.
+    /// Since the guest's writes are private to this memory manager's
+    /// mappings, guest RAM then cannot be accessed from other processes,
+    /// discarded, or handed to a new memory manager.
+    ///
+    /// This takes precedence over
+    /// [`hugetlb_page_size`](Self::hugetlb_page_size), but has no effect if an
+    /// existing backing is provided.
+    pub fn backing_file(mut self, file: Option<File>, shared: bool) -> Self {
+        self.backing_file = file.map(|file| (file, shared));
+        self
+    }
+
+    /// Specifies the host NUMA policy to allocate RAM with.
+    ///
+    /// This is only applied to newly allocated anonymous memory, so it has no
+    /// effect if an existing backing or a backing file is provided. If the
+    /// host does not support the policy, a warning is logged and RAM is
+    /// allocated using the default policy.
+    pub fn numa_policy(mut self, policy: Option<sparse_mmap::NumaPolicy>) -> Self {
+        self.numa_policy = policy;
+        self
+    }
+
     /// Builds the memory backing, allocating memory if existing memory was not
     /// provided by [`existing_backing`](Self::existing_backing).
     pub async fn build(
@@ -166,44 +234,6 @@ pub async fn build(
     ) -> Result<GuestMemoryManager, MemoryBuildError> {
         let ram_size = mem_layout.ram_size() + mem_layout.vtl2_range().map_or(0, |r| r.len());
 
-        let memory = if let Some(memory) = self.existing_mapping {
-            memory.guest_ram
-        } else {
-            sparse_mmap::alloc_shared_memory(
-                ram_size
-                    .try_into()
-                    .map_err(|_| MemoryBuildError::RamTooLarge(ram_size))?,
-            )
-            .map_err(MemoryBuildError::AllocationFailed)?
-            .into()
-        };
-
-        // Spawn a thread to handle memory requests.
-        //
-        // FUTURE: move this to a task once the GuestMemory deadlocks are resolved.
-        let (thread, spawner) = DefaultPool::spawn_on_thread("memory_manager");
-
-        let max_addr =
-            (mem_layout.end_of_ram_or_mmio()).max(mem_layout.vtl2_range().map_or(0, |r| r.end()));
-
-        let vtl0_alias_map_offset = if let Some(offset) = self.vtl0_alias_map {
-            if max_addr > offset {
-                return Err(MemoryBuildError::AliasMapWontFit);
-            }
-            Some(offset)
-        } else {
-            None
-        };
-
-        let mapping_manager = MappingManager::new(&spawner, max_addr);
-        let va_mapper = mapping_manager
-            .client()
-            .new_mapper()
-            .await
-            .map_err(MemoryBuildError::VaMapper)?;
-
-        let region_manager = RegionManager::new(&spawner, mapping_manager.client().clone());
-
         let mut ram_ranges = mem_layout
             .ram()
             .iter()
@@ -233,7 +263,7 @@ pub async fn build(
                 0xe8000,
                 0xec000,
                 0xf0000,
-                0x100000,
+                LEGACY_RAM_END,
                 ram_ranges[0].end(),
             ];
 
@@ -246,6 +276,84 @@ pub async fn build(
             );
         }
 
+        let mut hugetlb_page_size = None;
+        let mut copy_on_write = false;
+        let mut legacy_memory = None;
+        let memory = if let Some(memory) = self.existing_mapping {
+            memory.guest_ram
+        } else {
+            let size = ram_size
+                .try_into()
+                .map_err(|_| MemoryBuildError::RamTooLarge(ram_size))?;
+            let file_backed = self.backing_file.is_some();
+            let huge = self
+                .hugetlb_page_size
+                .filter(|_| !file_backed)
+                .and_then(|page_size| alloc_huge_ram(&ram_ranges, size, page_size));
+            let memory = if let Some((file, shared)) = self.backing_file {
+                let memory =
+                    alloc_file_ram(&file, size, shared).map_err(MemoryBuildError::BackingFile)?;
+                copy_on_write = !shared;
+                // The legacy x86 regions are unmapped and remapped as the guest
+                // changes their visibility, which would discard the guest's
+                // writes to a copy-on-write mapping. Give them a copy of the
+                // file's contents instead.
+                if copy_on_write && self.x86_legacy_support {
+                    let legacy = copy_file_ram(&file, LEGACY_RAM_END as usize)
+                        .map_err(MemoryBuildError::BackingFile)?;
+                    legacy_memory = Some(Mappable::from(legacy));
+                }
+                memory
+            } else if let Some(memory) = huge {
+                hugetlb_page_size = self.hugetlb_page_size;
+                memory
+            } else {
+                sparse_mmap::alloc_shared_memory(size)
+                    .map_err(MemoryBuildError::AllocationFailed)?
+            };
+            if let Some(policy) = self.numa_policy.filter(|_| !file_backed) {
+                #[cfg(unix)]
+                let mappable = std::os::unix::io::AsFd::as_fd(&memory);
+                #[cfg(windows)]
+                let mappable = std::os::windows::io::AsHandle::as_handle(&memory);
+                match sparse_mmap::set_shared_memory_numa_policy(mappable, size, &policy) {
+                    Ok(()) => tracing::info!(?policy, "guest RAM NUMA policy set"),
+                    Err(err) => tracing::warn!(
+                        ?policy,
+                        error = &err as &dyn std::error::Error,
+                        "failed to set guest RAM NUMA policy, using the default policy"
+                    ),
+                }
+            }
+            memory.into()
+        };
+
+        // Spawn a thread to handle memory requests.
+        //
+        // FUTURE: move this to a task once the GuestMemory deadlocks are resolved.
+        let (thread, spawner) = DefaultPool::spawn_on_thread("memory_manager");
+
+        let max_addr =
+            (mem_layout.end_of_ram_or_mmio()).max(mem_layout.vtl2_range().map_or(0, |r| r.end()));
+
+        let vtl0_alias_map_offset = if let Some(offset) = self.vtl0_alias_map {
+            if max_addr > offset {
+                return Err(MemoryBuildError::AliasMapWontFit);
+            }
+            Some(offset)
+        } else {
+            None
+        };
+
+        let mapping_manager = MappingManager::new(&spawner, max_addr);
+        let va_mapper = mapping_manager
+            .client()
+            .new_mapper()
+            .await
+            .map_err(MemoryBuildError::VaMapper)?;
+
+        let region_manager = RegionManager::new(&spawner, mapping_manager.client().clone());
+
         let mut ram_regions = Vec::new();
         let mut start = 0;
         for range in &ram_ranges {
@@ -255,12 +363,21 @@ pub async fn build(
                 .await
                 .expect("regions cannot overlap yet");
 
+            // The legacy regions start at zero, so their offset in the
+            // allocation is the same in the copy of the legacy range.
+            let (mappable, region_copy_on_write) = match &legacy_memory {
+                Some(legacy_memory) if range.end() <= LEGACY_RAM_END => {
+                    (legacy_memory.clone(), false)
+                }
+                _ => (memory.clone(), copy_on_write),
+            };
             region
                 .add_mapping(
                     MemoryRange::new(0..range.len()),
-                    memory.clone(),
+                    mappable,
                     start,
                     true,
+                    region_copy_on_write,
                 )
                 .await;
 
@@ -275,6 +392,7 @@ pub async fn build(
             ram_regions.push(RamRegion {
                 range: *range,
                 handle: region,
+                offset: start,
             });
             start += range.len();
         }
@@ -288,11 +406,101 @@ pub async fn build(
             va_mapper,
             vtl0_alias_map_offset,
             pin_mappings: self.pin_mappings,
+            hugetlb_page_size,
+            copy_on_write,
         };
         Ok(gm)
     }
 }
 
+/// The end of the x86 legacy RAM regions.
+const LEGACY_RAM_END: u64 = 0x100000;
+
+/// Allocates guest RAM of `size` bytes backed by `file`. See
+/// [`GuestMemoryBuilder::backing_file`].
+fn alloc_file_ram(
+    file: &File,
+    size: usize,
+    shared: bool,
+) -> std::io::Result<sparse_mmap::Mappable> {
+    let len = file.metadata()?.len();
+    if shared {
+        if len < size as u64 {
+            file.set_len(size as u64)?;
+        }
+        sparse_mmap::new_mappable_from_file(file, true, false)
+    } else {
+        // Pages past the end of the file cannot be mapped.
+        if len < size as u64 {
+            return Err(std::io::Error::other(format!(
+                "memory backing file is smaller than RAM ({len:#x} < {size:#x})"
+            )));
+        }
+        sparse_mmap::new_mappable_from_file(file, false, false)
+    }
+}
+
+/// Allocates `size` bytes of shared memory and initializes it with the start
+/// of `file`.
+fn copy_file_ram(file: &File, size: usize) -> std::io::Result<sparse_mmap::Mappable> {
+    sparse_mmap::initialize_try_copy();
+    let memory = sparse_mmap::alloc_shared_memory(size)?;
+    let mapping = SparseMapping::new(size)?;
+    mapping.map_file(0, size, &memory, 0, true)?;
+    let mut buf = vec![0; 0x100000];
+    let mut offset = 0;
+    let mut file = file;
+    file.seek(SeekFrom::Start(0))?;
+    while offset < size {
+        let len = buf.len().min(size - offset);
+        let n = file.read(&mut buf[..len])?;
+        if n == 0 {
+            break;
+        }
+        mapping
+            .write_at(offset, &buf[..n])
+            .map_err(std::io::Error::other)?;
+        offset += n;
+    }
+    Ok(memory)
+}
+
+/// Allocates guest RAM from huge pages of `page_size` bytes, returning `None`
+/// (after reporting why) if that is not possible.
+fn alloc_huge_ram(
+    ram_ranges: &[MemoryRange],
+    size: usize,
+    page_size: u64,
+) -> Option<sparse_mmap::Mappable> {
+    // Each range is mapped separately, so each must start and end on a huge
+    // page boundary.
+    if let Some(range) = ram_ranges
+        .iter()
+        .find(|range| range.start() % page_size != 0 || range.len() % page_size != 0)
+    {
+        tracing::warn!(
+            %range,
+            page_size,
+            "guest RAM range is not aligned to the huge page size, using normal pages"
+        );
+        return None;
+    }
+    match sparse_mmap::alloc_huge_shared_memory(size, page_size as usize) {
+        Ok(memory) => {
+            tracing::info!(page_size, "guest RAM is backed by huge pages");
+            Some(memory)
+        }
+        Err(err) => {
+            tracing::warn!(
+                page_size,
+                error = &err as &dyn std::error::Error,
+                "failed to allocate huge pages for guest RAM, using normal pages"
+            );
+            None
+        }
+    }
+}
+
 /// The backing objects used to transfer guest memory between processes.
 #[derive(Debug, MeshPayload)]
 pub struct SharedMemoryBacking {
@@ -347,6 +555,17 @@ pub fn ram_visibility_control(&self) -> RamVisibilityControl {
         }
     }
 
+    /// Returns an object for releasing guest RAM back to the host, such as
+    /// for a balloon device.
+    pub fn ram_discard(&self) -> RamDiscard {
+        RamDiscard {
+            guest_ram: self.guest_ram.clone(),
+            regions: self.ram_regions.clone(),
+            pinned: self.pin_mappings,
+            copy_on_write: self.copy_on_write,
+        }
+    }
+
     /// Returns the shared memory resources that can be used to reconstruct the
     /// memory backing.
     ///
@@ -354,9 +573,15 @@ pub fn ram_visibility_control(&self) -> RamVisibilityControl {
     /// new memory manager with the same memory state. Only one instance of this
     /// type should be managing a given memory backing at a time, though, or the
     /// guest may see unpredictable results.
-    pub fn shared_memory_backing(&self) -> SharedMemoryBacking {
+    ///
+    /// Returns `None` if guest RAM is mapped copy-on-write from a file, since
+    /// the guest's writes are only in this memory manager's mappings.
+    pub fn shared_memory_backing(&self) -> Option<SharedMemoryBacking> {
+        if self.copy_on_write {
+            return None;
+        }
         let guest_ram = self.guest_ram.clone();
-        SharedMemoryBacking { guest_ram }
+        Some(SharedMemoryBacking { guest_ram })
     }
 
     /// Attaches the guest memory to a partition, mapping it to the guest
@@ -376,6 +601,11 @@ pub async fn attach_partition(
         process: Option<RemoteProcess>,
     ) -> Result<(), PartitionAttachError> {
         let va_mapper = if let Some(process) = process {
+            // The other process's mappings would not see the guest's writes
+            // to this process's copy-on-write mappings.
+            if self.copy_on_write {
+                return Err(PartitionAttachError::CopyOnWrite);
+            }
             self.mapping_manager
                 .client()
                 .new_remote_mapper(process)
@@ -407,6 +637,46 @@ pub async fn attach_partition(
     }
 }
 
+/// A client to the [`GuestMemoryManager`] used to release guest RAM back to
+/// the host.
+pub struct RamDiscard {
+    guest_ram: Mappable,
+    regions: Arc<Vec<RamRegion>>,
+    pinned: bool,
+    copy_on_write: bool,
+}
+
+impl guestmem::DiscardRam for RamDiscard {
+    fn discard_ram(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+        // The partition still references pinned memory, so it cannot be
+        // released.
+        //
+        // Copy-on-write RAM is backed by a file that must not change, and the
+        // guest's private copies of its pages are not in `guest_ram`.
+        if self.pinned || self.copy_on_write {
+            return Err(std::io::ErrorKind::Unsupported.into());
+        }
+        #[cfg(unix)]
+        let mappable = std::os::fd::AsFd::as_fd(&self.guest_ram);
+        #[cfg(windows)]
+        let mappable = std::os::windows::io::AsHandle::as_handle(&self.guest_ram);
+
+        let range = MemoryRange::new(gpa..gpa + len);
+        for region in self.regions.iter() {
+            let start = range.start().max(region.range.start());
+            let end = range.end().min(region.range.end());
+            if start < end {
+                sparse_mmap::discard_shared_memory(
+                    mappable,
+                    region.offset + (start - region.range.start()),
+                    end - start,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
 /// A client to the [`GuestMemoryManager`] used to control the visibility of
 /// RAM regions.
 pub struct RamVisibilityControl {
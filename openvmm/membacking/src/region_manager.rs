@@ -157,6 +157,7 @@ struct RegionMappingParams {
     mappable: Mappable,
     file_offset: u64,
     writable: bool,
+    copy_on_write: bool,
 }
 
 fn range_within(outer: MemoryRange, inner: MemoryRange) -> MemoryRange {
@@ -403,6 +404,7 @@ async fn add_mapping(&mut self, id: RegionId, params: RegionMappingParams) {
                     params.mappable.clone(),
                     params.file_offset,
                     params.writable,
+                    params.copy_on_write,
                 )
                 .await;
 
@@ -460,6 +462,7 @@ async fn enable_region(&mut self, region: &mut Region) {
                     mapping.params.mappable.clone(),
                     mapping.params.file_offset,
                     mapping.params.writable && map_params.writable,
+                    mapping.params.copy_on_write,
                 )
                 .await;
         }
@@ -587,6 +590,10 @@ pub async fn unmap(&self) {
 
     /// Adds a mapping to the region.
     ///
+    /// If `copy_on_write`, guest writes are not written back to `mappable`.
+    /// The region must then stay mapped, since unmapping it discards the
+    /// guest's writes.
+    ///
     /// TODO: allow this to split+overwrite existing mappings.
     pub async fn add_mapping(
         &self,
@@ -594,6 +601,7 @@ pub async fn add_mapping(
         mappable: Mappable,
         file_offset: u64,
         writable: bool,
+        copy_on_write: bool,
     ) {
         let _ = self
             .req_send
@@ -606,6 +614,7 @@ pub async fn add_mapping(
                         mappable,
                         file_offset,
                         writable,
+                        copy_on_write,
                     },
                 ),
             )
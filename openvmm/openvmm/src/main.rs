@@ -6,6 +6,11 @@
 // Ensure openvmm_resources gets linked.
 extern crate openvmm_resources as _;
 
+// Count heap allocations when profiling with `--profile heap`.
+#[global_allocator]
+static GLOBAL: alloc_tracker::TrackingAllocator<std::alloc::System> =
+    alloc_tracker::TrackingAllocator::new(std::alloc::System);
+
 fn main() {
     openvmm_entry::hvlite_main()
 }
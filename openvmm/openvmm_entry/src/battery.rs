@@ -0,0 +1,164 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Code to change the emulated battery and AC power state at runtime.
+
+use chipset_resources::battery::HostBatteryUpdate;
+use get_resources::ged::GuestEmulationRequest;
+use parking_lot::Mutex;
+use std::fmt;
+use std::fmt::Display;
+use std::sync::Arc;
+
+/// Tracks the battery state reported to the guest and sends updates to the
+/// battery device, and to OpenHCL through the GED if VTL2 is enabled.
+///
+/// The device raises an ACPI notification for each update, so the guest
+/// rereads the state.
+#[derive(Clone)]
+pub(crate) struct BatteryControl {
+    chipset: mesh::Sender<HostBatteryUpdate>,
+    ged: Option<mesh::Sender<GuestEmulationRequest>>,
+    state: Arc<Mutex<HostBatteryUpdate>>,
+}
+
+impl BatteryControl {
+    /// Controls the chipset battery device, sending it the initial state.
+    pub fn new(chipset: mesh::Sender<HostBatteryUpdate>) -> Self {
+        let state = HostBatteryUpdate::default_present();
+        chipset.send(state);
+        Self {
+            chipset,
+            ged: None,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Also sends updates to OpenHCL's battery device. The GED sends the same
+    /// initial state when VTL2 connects.
+    pub fn set_ged(&mut self, ged: mesh::Sender<GuestEmulationRequest>) {
+        self.ged = Some(ged);
+    }
+
+    /// Returns the current battery state.
+    pub fn state(&self) -> HostBatteryUpdate {
+        *self.state.lock()
+    }
+
+    /// Modifies the battery state with `f` and sends the new state to the
+    /// guest, returning it.
+    pub fn update(&self, f: impl FnOnce(&mut HostBatteryUpdate)) -> HostBatteryUpdate {
+        let mut state = self.state.lock();
+        f(&mut state);
+        self.chipset.send(*state);
+        if let Some(ged) = &self.ged {
+            ged.send(GuestEmulationRequest::SetBatteryStatus(*state));
+        }
+        *state
+    }
+}
+
+#[derive(clap::Args)]
+pub(crate) struct BatteryCommand {
+    #[clap(subcommand)]
+    command: Option<BatterySubcommand>,
+}
+
+#[derive(clap::Subcommand)]
+enum BatterySubcommand {
+    /// Show the battery state. This is the default.
+    Status,
+    /// Connect or disconnect AC power.
+    Ac {
+        #[clap(value_enum)]
+        state: Switch,
+    },
+    /// Set the remaining charge, as a percentage of the battery's capacity.
+    Charge {
+        #[clap(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: u8,
+    },
+    /// Insert the battery.
+    Insert,
+    /// Remove the battery.
+    Remove,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum Switch {
+    On,
+    Off,
+}
+
+pub(crate) fn handle_battery(battery: &BatteryControl, command: BatteryCommand) {
+    let state = match command.command.unwrap_or(BatterySubcommand::Status) {
+        BatterySubcommand::Status => battery.state(),
+        BatterySubcommand::Ac { state } => {
+            battery.update(|s| s.set_ac_online(matches!(state, Switch::On)))
+        }
+        BatterySubcommand::Charge { percent } => battery.update(|s| s.set_charge_percent(percent)),
+        BatterySubcommand::Insert => battery.update(|s| s.set_battery_present(true)),
+        BatterySubcommand::Remove => battery.update(|s| s.set_battery_present(false)),
+    };
+    println!("{}", DisplayState(&state));
+}
+
+/// Displays a battery state as, for example, `ac online, 95%, charging`.
+struct DisplayState<'a>(&'a HostBatteryUpdate);
+
+impl Display for DisplayState<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0;
+        f.write_str(if state.ac_online {
+            "ac online"
+        } else {
+            "ac offline"
+        })?;
+        match state.charge_percent() {
+            Some(percent) => write!(f, ", {percent}%")?,
+            None => f.write_str(", no battery")?,
+        }
+        if state.charging {
+            f.write_str(", charging")?;
+        } else if state.discharging {
+            f.write_str(", discharging")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BatteryControl;
+    use super::DisplayState;
+    use chipset_resources::battery::HostBatteryUpdate;
+    use pal_async::async_test;
+
+    #[async_test]
+    async fn test_battery_update() {
+        let (send, mut recv) = mesh::channel();
+        let battery = BatteryControl::new(send);
+        assert_eq!(
+            recv.recv().await.unwrap(),
+            HostBatteryUpdate::default_present()
+        );
+
+        let state = battery.update(|s| s.set_ac_online(false));
+        assert_eq!(recv.recv().await.unwrap(), state);
+        assert_eq!(
+            DisplayState(&state).to_string(),
+            "ac offline, 95%, discharging"
+        );
+
+        let state = battery.update(|s| {
+            s.set_ac_online(true);
+            s.set_charge_percent(100);
+        });
+        assert_eq!(recv.recv().await.unwrap(), state);
+        assert_eq!(DisplayState(&state).to_string(), "ac online, 100%");
+
+        let state = battery.update(|s| s.set_battery_present(false));
+        assert_eq!(DisplayState(&state).to_string(), "ac online, no battery");
+        assert_eq!(battery.state(), state);
+    }
+}
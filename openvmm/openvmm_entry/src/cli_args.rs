@@ -21,16 +21,23 @@
 use anyhow::Context;
 use clap::Parser;
 use clap::ValueEnum;
+use hvlite_defs::config::CpuidOverride;
 use hvlite_defs::config::DEFAULT_PCAT_BOOT_ORDER;
 use hvlite_defs::config::DeviceVtl;
 use hvlite_defs::config::Hypervisor;
 use hvlite_defs::config::PcatBootDevice;
 use hvlite_defs::config::Vtl2BaseAddressType;
 use hvlite_defs::config::X2ApicConfig;
+use net_backend_resources::consomme::ConsommeDhcpOptions;
+use net_backend_resources::consomme::ConsommeStaticLease;
+use net_backend_resources::mac_address::MacAddress;
+use serial_debugcon_resources::DebugconTraceConfig;
+use serial_debugcon_resources::DebugconTraceLevel;
 use std::ffi::OsString;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use thiserror::Error;
 
 /// OpenVMM virtual machine monitor.
@@ -39,6 +46,12 @@
 /// versions.
 #[derive(Parser)]
 pub struct Options {
+    /// load the VM definition from a TOML (or JSON, with a .json extension)
+    /// file mapping option names to values. Options on the command line
+    /// override the file's values
+    #[clap(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
     /// processor count
     #[clap(short = 'p', long, value_name = "COUNT", default_value = "1")]
     pub processors: u32,
@@ -53,6 +66,44 @@ pub struct Options {
     )]
     pub memory: u64,
 
+    /// additional guest RAM that can be hot-added at runtime, via a virtio-mem
+    /// device
+    #[clap(long, value_name = "SIZE", value_parser = parse_memory)]
+    pub memory_hotplug: Option<u64>,
+
+    /// add a virtio-balloon device, with free page reporting, so that memory
+    /// can be reclaimed from the guest at runtime
+    #[clap(long)]
+    pub balloon: bool,
+
+    /// how to back guest RAM. `hugetlb[:2M|1G]` allocates it from hugetlbfs
+    /// huge pages (2M by default), falling back to normal pages with a
+    /// warning if they are not available. `file:<path>[,shared]` maps a file
+    /// copy-on-write or, with `shared`, maps the file directly so that guest
+    /// writes go to it
+    #[clap(long, value_name = "BACKING")]
+    pub memory_backing: Option<MemoryBackingCli>,
+
+    /// the size of the MMIO gap below 4GB, which can only be grown from the
+    /// default
+    #[clap(long, value_name = "SIZE", value_parser = parse_memory)]
+    pub mmio_gap_low: Option<u64>,
+
+    /// the size of the MMIO gap starting just below 64GB, which can only be
+    /// grown from the default. Use this to fit devices with large BARs
+    #[clap(long, value_name = "SIZE", value_parser = parse_memory)]
+    pub mmio_gap_high: Option<u64>,
+
+    /// add an ECAM window for buses 0 to BUSES - 1 of the emulated PCI bus,
+    /// taking 1MB per bus from the start of the low MMIO gap. Needed for
+    /// extended config space, and added automatically for --nvme-vf
+    #[clap(
+        long,
+        value_name = "BUSES",
+        value_parser = clap::value_parser!(u16).range(1..=256)
+    )]
+    pub pci_ecam: Option<u16>,
+
     /// use shared memory segment
     #[clap(short = 'M', long)]
     pub shared_memory: bool,
@@ -61,10 +112,36 @@ pub struct Options {
     #[clap(long)]
     pub prefetch: bool,
 
+    /// how to allocate guest RAM. `prefault` allocates all of it up front
+    /// (like --prefetch), `lazy` on first access (the default).
+    /// `interleave[:NODES]` spreads it across the given host NUMA nodes (all
+    /// online nodes by default), and `bind:NODES` allocates it only from the
+    /// given nodes, e.g. `bind:0-1`. Can be specified multiple times to
+    /// combine a NUMA policy with `prefault` or `lazy`
+    #[clap(long, value_name = "POLICY")]
+    pub mem_policy: Vec<MemPolicyCli>,
+
     /// start in paused state
     #[clap(short = 'P', long)]
     pub paused: bool,
 
+    /// resume the VM from a snapshot written by `openvmm save` or the
+    /// interactive console's `save` command, instead of booting. The rest of
+    /// the configuration must match the saved VM's
+    #[clap(long, value_name = "PATH", conflicts_with = "memory_backing")]
+    pub resume: Option<PathBuf>,
+
+    /// wait for a VM to be migrated from another OpenVMM instance at
+    /// `tcp:<host>:<port>`, or load one saved to `file:<path>`, and run it
+    /// instead of booting. The rest of the configuration must match the
+    /// source's
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        conflicts_with_all = ["memory_backing", "resume"]
+    )]
+    pub incoming: Option<MigrationAddressCli>,
+
     /// kernel image (when using linux direct boot)
     #[clap(short = 'k', long, value_name = "FILE", default_value = default_value_from_arch_env("OPENVMM_LINUX_DIRECT_KERNEL"))]
     pub kernel: OptionalPathBuf,
@@ -113,6 +190,17 @@ pub struct Options {
     #[clap(long, value_name = "PATH", requires("vtl2"))]
     pub vtl2_vsock_path: Option<String>,
 
+    /// bridge guest connections to the given vsock port to the same port on
+    /// the host's VM sockets (AF_VSOCK on Linux, Hyper-V sockets on Windows)
+    #[clap(long, value_name = "PORT")]
+    pub vsock_host_connect: Vec<u32>,
+
+    /// listen on the given host VM socket port (AF_VSOCK on Linux, Hyper-V
+    /// sockets on Windows) and relay connections to the guest's vsock port,
+    /// which defaults to the host port
+    #[clap(long, value_name = "HOST_PORT[:GUEST_PORT]", value_parser = parse_vsock_listen)]
+    pub vsock_host_listen: Vec<(u32, u32)>,
+
     /// the late map vtl0 ram access policy when vtl2 is enabled
     #[clap(long, requires("vtl2"), default_value = "halt")]
     pub late_map_vtl0_policy: Vtl0LateMapPolicyCli,
@@ -138,11 +226,19 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `vhdx:\<path\>[;create=<len>]`   dynamic or differencing VHDX, parsed in user mode
+        \<path\>: path to the VHDX file
+        <len>: create a new dynamic VHDX of this size, e.g.: `64G`
 
 flags:
     `ro`                           open disk as read-only
     `dvd`                          specifies that device is cd/dvd and it is read_only
     `vtl2`                         assign this disk to VTL2
+    `iops=<n>`                     limit the disk to <n> I/O operations per second
+    `bw=<n>`                       limit the disk to <n> MB/s of bandwidth
+    `burst=<n>`                    allow <n> I/O operations at once when idle (requires `iops`)
+    `sector=<512|4096>`            present this logical sector size to the guest
+    `physical-sector=<n>`          present this physical sector size to the guest
     `uh`                           relay this disk to VTL0 through Underhill
 "#)]
     #[clap(long, value_name = "FILE")]
@@ -161,14 +257,84 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `vhdx:\<path\>[;create=<len>]`   dynamic or differencing VHDX, parsed in user mode
+        \<path\>: path to the VHDX file
+        <len>: create a new dynamic VHDX of this size, e.g.: `64G`
 
 flags:
     `ro`                           open disk as read-only
     `vtl2`                         assign this disk to VTL2
+    `iops=<n>`                     limit the disk to <n> I/O operations per second
+    `bw=<n>`                       limit the disk to <n> MB/s of bandwidth
+    `burst=<n>`                    allow <n> I/O operations at once when idle (requires `iops`)
+    `sector=<512|4096>`            present this logical sector size to the guest
+    `physical-sector=<n>`          present this physical sector size to the guest
 "#)]
     #[clap(long)]
     pub nvme: Vec<DiskCli>,
 
+    /// attach a disk as an SR-IOV virtual function of an emulated NVMe
+    /// controller on the PCI bus
+    #[clap(long_help = r#"
+e.g: --nvme-vf file:/path/to/disk.img --nvme-vf mem:1G
+
+syntax: \<path\> | kind:<arg>[,flag,opt=arg,...], as for --nvme, except that
+the `vtl2` and `uh` flags are not supported
+
+Each disk is namespace 1 of its own virtual function, up to 7. The virtual
+functions stay hidden until the guest enables them through the physical
+function, e.g. with `echo 2 > /sys/bus/pci/devices/<pf>/sriov_numvfs`.
+
+Requires the PCI bus, so x86-64 Linux direct boot. A 1MB ECAM window is added
+at the start of the low MMIO gap so the guest can reach the SR-IOV capability
+in extended config space.
+"#)]
+    #[clap(long, value_name = "DISK")]
+    pub nvme_vf: Vec<DiskCli>,
+
+    /// attach a disk as a USB mass storage device, via an xHCI controller
+    #[clap(long_help = r#"
+e.g: --usb-storage file:/path/to/installer.iso,dvd
+
+syntax: \<path\> | kind:<arg>[,flag,opt=arg,...]
+
+valid disk kinds:
+    `mem:<len>`                    memory backed disk
+        <len>: length of ramdisk, e.g.: `1G`
+    `memdiff:<disk>`               memory backed diff disk
+        <disk>: lower disk, e.g.: `file:base.img`
+    `file:\<path\>`                  file-backed disk
+        \<path\>: path to file
+    `vhdx:\<path\>[;create=<len>]`   dynamic or differencing VHDX, parsed in user mode
+        \<path\>: path to the VHDX file
+        <len>: create a new dynamic VHDX of this size, e.g.: `64G`
+
+flags:
+    `ro`                           open disk as read-only
+    `dvd`                          specifies that device is cd/dvd and it is read_only
+    `iops=<n>`                     limit the disk to <n> I/O operations per second
+    `bw=<n>`                       limit the disk to <n> MB/s of bandwidth
+    `burst=<n>`                    allow <n> I/O operations at once when idle (requires `iops`)
+    `sector=<512|4096>`            present this logical sector size to the guest
+    `physical-sector=<n>`          present this physical sector size to the guest
+"#)]
+    #[clap(long, value_name = "FILE")]
+    pub usb_storage: Vec<DiskCli>,
+
+    /// pass through a host USB device via an xHCI controller (Linux only)
+    ///
+    /// Only HID-class devices are currently supported.
+    #[clap(long_help = r#"
+e.g: --usb-host 046d:c52b
+
+syntax: <vid>:<pid> | <bus>-<port>[.<port>...]
+
+    `<vid>:<pid>`                  the first device with this hex vendor and product ID
+    `<bus>-<port>[.<port>...]`     the device at this bus path, as in /sys/bus/usb/devices
+"#)]
+    #[clap(long, value_name = "DEVICE")]
+    pub usb_host: Vec<UsbHostCli>,
+
     /// number of sub-channels for the SCSI controller
     #[clap(long, value_name = "COUNT", default_value = "0")]
     pub scsi_sub_channels: u16,
@@ -177,10 +343,31 @@ pub struct Options {
     #[clap(long)]
     pub nic: bool,
 
-    /// expose a virtual NIC with the given backend (consomme | dio | tap | none)
+    /// expose a virtual NIC with the given backend (consomme | dio | tap |
+    /// macvtap | none)
     ///
     /// Prefix with `uh:` to add this NIC via Mana emulation through Underhill,
-    /// or `vtl2:` to assign this NIC to VTL2.
+    /// or `vtl2:` to assign this NIC to VTL2. Prefix with
+    /// `mac=aa:bb:cc:dd:ee:ff:` to use a fixed MAC address instead of a random
+    /// one.
+    ///
+    /// Prefix with `rate=<mbps>:`, `latency=<ms>:`, or `loss=<pct>:` to shape
+    /// the NIC's traffic, limiting its bandwidth, adding latency to each
+    /// packet, or randomly dropping the given percentage of packets.
+    ///
+    /// `macvtap:<ifname>` attaches the NIC to a pre-created macvtap interface,
+    /// placing the guest directly on the host's network. The NIC uses the
+    /// interface's MAC address by default. Only access to the interface's
+    /// device node (`/dev/tapN`) is required, not `CAP_NET_ADMIN`.
+    ///
+    /// `consomme:[<cidr>][,<option>...]` configures consomme's DHCP server
+    /// with comma-separated options: `dns=<ip>` replaces the host's DNS
+    /// servers, `search=<domain>` adds a DNS search domain, `lease=<mac>@<ip>`
+    /// assigns a fixed address to a MAC address, and `tftp=<server>` and
+    /// `bootfile=<file>` provide network boot options. `dns`, `search`, and
+    /// `lease` may be repeated. `pxe=<dir>` serves the files in a host
+    /// directory over TFTP and HTTP from the gateway address; combine it with
+    /// `bootfile` to network boot from it.
     #[clap(long)]
     pub net: Vec<NicConfigCli>,
 
@@ -219,11 +406,34 @@ pub struct Options {
     #[clap(long, default_value = "auto")]
     pub smt: SmtConfigCli,
 
+    /// pin a VP's backing thread to a list of host CPUs, e.g. `0:2-3,6`
+    /// (Linux only)
+    #[clap(long, value_name = "VP:CPUS", value_parser = parse_vcpu_pin)]
+    pub vcpu_pin: Vec<(u32, Vec<u32>)>,
+
     /// configure x2apic (auto | supported | off | on)
     #[cfg(guest_arch = "x86_64")]
     #[clap(long, default_value = "auto", value_parser = parse_x2apic)]
     pub x2apic: X2ApicConfig,
 
+    /// report the signature and brand string of a CPU model to the guest, and
+    /// hide the CPUID features introduced after it
+    #[cfg(guest_arch = "x86_64")]
+    #[clap(long, value_name = "MODEL")]
+    pub cpu_model: Option<CpuModelCli>,
+
+    /// hide CPUID features from the guest, e.g. `avx2,x2apic,invtsc`
+    #[cfg(guest_arch = "x86_64")]
+    #[clap(long, value_name = "FEATURES", value_delimiter = ',')]
+    pub cpuid_disable: Vec<String>,
+
+    /// set a CPUID register reported to the guest, as
+    /// `LEAF[:SUBLEAF]:REG=VALUE`, e.g. `0x7:0:ebx=0x1`. Applied after
+    /// `--cpu-model` and `--cpuid-disable`
+    #[cfg(guest_arch = "x86_64")]
+    #[clap(long, value_name = "LEAF[:SUBLEAF]:REG=VALUE", value_parser = parse_cpuid_set)]
+    pub cpuid_set: Vec<CpuidOverride>,
+
     /// use virtio console
     #[clap(long)]
     pub virtio_console: bool,
@@ -232,38 +442,43 @@ pub struct Options {
     #[clap(long, conflicts_with("virtio_console"))]
     pub virtio_console_pci: bool,
 
-    /// COM1 binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// COM1 binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[clap(long, value_name = "SERIAL")]
     pub com1: Option<SerialConfigCli>,
 
-    /// COM2 binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// COM2 binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[clap(long, value_name = "SERIAL")]
     pub com2: Option<SerialConfigCli>,
 
-    /// COM3 binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// COM3 binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[clap(long, value_name = "SERIAL")]
     pub com3: Option<SerialConfigCli>,
 
-    /// COM4 binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// COM4 binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[clap(long, value_name = "SERIAL")]
     pub com4: Option<SerialConfigCli>,
 
-    /// virtio serial binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// virtio serial binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[clap(long, value_name = "SERIAL")]
     pub virtio_serial: Option<SerialConfigCli>,
 
-    /// vmbus com1 serial binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// vmbus com1 serial binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[structopt(long, value_name = "SERIAL")]
     pub vmbus_com1_serial: Option<SerialConfigCli>,
 
-    /// vmbus com2 serial binding (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none)
+    /// vmbus com2 serial binding (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none)
     #[structopt(long, value_name = "SERIAL")]
     pub vmbus_com2_serial: Option<SerialConfigCli>,
 
-    /// debugcon binding (port:serial, where port is a u16, and serial is (console | stderr | listen=\<path\> | file=\<path\> (overwrites) | listen=tcp:\<ip\>:\<port\> | term[=\<program\>][,name=<windowtitle>] | none))
+    /// debugcon binding (port,serial or port,trace[=\<level\>][,target=\<name\>], where port is a u16, level is error | warn | info (default) | debug | trace, and serial is (console | stderr | listen=\<path\> | file=\<path\>[,maxsize=\<size\>][,rotate=\<n\>][,timestamp] (overwrites) | listen=tcp:\<ip\>:\<port\> | listen=ws:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\>[,retry=\<secs\>] | term[=\<program\>][,name=<windowtitle>] | pty | none))
     #[clap(long, value_name = "SERIAL")]
     pub debugcon: Option<DebugconSerialConfigCli>,
 
+    /// additional serial port beyond COM1-4 (N[,io=\<port\>,irq=\<irq\>]:serial, where N >= 5 is the port number and serial is as for --com1).
+    /// Ports with an IO port and IRQ are ISA UARTs; the rest are placed, in order, on PCI serial cards of up to four ports each.
+    #[clap(long = "serial", value_name = "N:SERIAL")]
+    pub extra_serial: Vec<ExtraSerialConfigCli>,
+
     /// boot UEFI firmware
     #[clap(long, short = 'e')]
     pub uefi: bool,
@@ -334,8 +549,59 @@ pub struct Options {
     #[clap(long, value_name = "PATH")]
     pub virtio_pmem: Option<String>,
 
+    /// add a virtio-snd audio device with a single output stream, whose audio
+    /// is discarded (null), written to a WAV file (wav:<path>), or played on
+    /// the host through PulseAudio (pulse[:<sink>], using pacat)
+    #[clap(long, value_name = "BACKEND")]
+    pub audio: Option<AudioCli>,
+
+    /// add an ivshmem PCI device exposing a host file as shared memory
+    #[clap(long_help = r#"
+e.g: --ivshmem /dev/shm/ivshmem,16M,doorbell=/tmp/ivshmem.sock
+
+syntax: <path>,<size>[,doorbell=<socket>]
+
+The file is created if it does not exist and grown to <size> bytes, which
+must be a power of two of at least 4K. The guest sees it through BAR2.
+
+options:
+    `doorbell=<socket>`     connect to a listening Unix socket to exchange
+                            doorbell interrupts with a host process: guest
+                            doorbell writes are sent as little-endian u32s,
+                            and each u32 received is ORed into the interrupt
+                            status register
+"#)]
+    #[clap(long, value_name = "PATH,SIZE")]
+    pub ivshmem: Vec<IvshmemCli>,
+
+    /// assign a host PCI device to the guest with VFIO (Linux only)
+    #[cfg(target_os = "linux")]
+    #[clap(long_help = r#"
+e.g: --vfio 0000:01:00.0
+e.g: --vfio 01:00.0,uh-nvme
+
+syntax: <address>[,options]
+
+The device is given by its PCI address or sysfs path, and must be bound to
+the vfio-pci driver, as must every other device in its IOMMU group. It is
+placed on the emulated PCI bus and must support MSI-X.
+
+All guest RAM is pinned and mapped for device DMA when the VM starts.
+
+options:
+    `vtl2`                  assign the device to VTL2 on a VPCI bus instead
+                            (requires --vtl2 and --no-alias-map)
+    `uh-nvme[=<nsid>]`      assign the NVMe device to VTL2, and have OpenHCL
+                            relay namespace <nsid> (default 1) to VTL0 as a
+                            SCSI disk; may be repeated
+    `uh-mana`               assign the MANA device to VTL2, and have OpenHCL
+                            relay its vports to VTL0 as synthetic NICs
+"#)]
+    #[clap(long, value_name = "PCI_ADDRESS")]
+    pub vfio: Vec<VfioCli>,
+
     /// expose a virtio network with the given backend (dio | vmnic | tap |
-    /// none)
+    /// macvtap | none)
     ///
     /// Prefix with `uh:` to add this NIC via Mana emulation through Underhill,
     /// or `vtl2:` to assign this NIC to VTL2.
@@ -346,6 +612,39 @@ pub struct Options {
     #[clap(long, value_name = "PATH")]
     pub log_file: Option<PathBuf>,
 
+    /// the format of log output, for this process and the worker processes.
+    /// `json` writes one JSON object per line. overrides OPENVMM_LOG_FORMAT.
+    #[clap(long, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// profile OpenVMM's own processes, including the worker processes, and
+    /// write the results when each process exits. `cpu:<hz>` samples the
+    /// stacks of every thread `hz` times a second, writing a pprof profile and
+    /// a flamegraph (Linux and macOS only); `heap` counts heap allocations;
+    /// `latency` records device I/O latency histograms in the inspect tree. can
+    /// be repeated.
+    #[clap(long, value_name = "KIND")]
+    pub profile: Vec<ProfileCli>,
+
+    /// the directory to write profiles to, instead of the current directory
+    #[clap(long, value_name = "PATH", requires("profile"))]
+    pub profile_dir: Option<PathBuf>,
+
+    /// serve Prometheus metrics, derived from the VM's inspect tree, over HTTP
+    /// on the specified address (e.g. 127.0.0.1:9100)
+    #[clap(long, value_name = "ADDR:PORT")]
+    pub metrics: Option<std::net::SocketAddr>,
+
+    /// serve the inspect tree as JSON over HTTP on the specified address, at
+    /// /inspect?path=<PATH>&depth=<DEPTH>
+    #[clap(long, value_name = "ADDR:PORT")]
+    pub inspect_http: Option<std::net::SocketAddr>,
+
+    /// serve a subset of the QEMU Machine Protocol (QMP) on the specified Unix
+    /// socket, for tooling written for QEMU
+    #[clap(long, value_name = "SOCKETPATH")]
+    pub qmp: Option<PathBuf>,
+
     /// run as a ttrpc server on the specified Unix socket
     #[clap(long, value_name = "SOCKETPATH")]
     pub ttrpc: Option<PathBuf>,
@@ -354,6 +653,10 @@ pub struct Options {
     #[clap(long, value_name = "SOCKETPATH", conflicts_with("ttrpc"))]
     pub grpc: Option<PathBuf>,
 
+    /// allow the ttrpc/grpc server to read, write, and search guest memory
+    #[clap(long)]
+    pub allow_guest_memory_access: bool,
+
     /// do not launch child processes
     #[clap(long)]
     pub single_process: bool,
@@ -371,6 +674,16 @@ pub struct Options {
     #[clap(long)]
     pub tpm: bool,
 
+    /// allocate only these vtpm PCR banks (comma separated list of sha1,
+    /// sha256, or sha384), and refuse guest requests to change them
+    #[clap(long, requires("tpm"), value_name = "BANKS", value_delimiter = ',')]
+    pub tpm_pcr_banks: Option<Vec<PcrBankCli>>,
+
+    /// extend the digest of DATA (in hex) into vtpm PCR INDEX each time the
+    /// TPM starts, before the firmware's measurements (may be repeated)
+    #[clap(long, requires("tpm"), value_name = "INDEX=DATA")]
+    pub tpm_pcr_seed: Vec<TpmPcrSeedCli>,
+
     /// the mesh worker host name.
     ///
     /// Used internally for debugging and diagnostics.
@@ -401,6 +714,9 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `vhdx:\<path\>[;create=<len>]`   dynamic or differencing VHDX, parsed in user mode
+        \<path\>: path to the VHDX file
+        <len>: create a new dynamic VHDX of this size, e.g.: `64G`
 
 flags:
     `fmt`                          reprovision the VMGS before boot
@@ -425,6 +741,32 @@ pub struct Options {
     #[clap(long, value_name = "PATH")]
     pub custom_uefi_json: Option<PathBuf>,
 
+    /// enroll the X.509 certificate (PEM or DER) at PATH as the secure boot
+    /// platform key (PK), replacing the keys from the template and custom
+    /// uefi json
+    #[clap(long, value_name = "PATH", requires("uefi"))]
+    pub secure_boot_pk: Option<PathBuf>,
+
+    /// enroll the X.509 certificate (PEM or DER) at PATH in the secure boot
+    /// KEK (can be repeated)
+    #[clap(long, value_name = "PATH", requires("uefi"))]
+    pub secure_boot_kek: Vec<PathBuf>,
+
+    /// enroll the X.509 certificate (PEM or DER) at PATH in the secure boot
+    /// db (can be repeated)
+    #[clap(long, value_name = "PATH", requires("uefi"))]
+    pub secure_boot_db: Vec<PathBuf>,
+
+    /// revoke an X.509 certificate at PATH, or an image with SHA-256 digest
+    /// HEX, via the secure boot dbx (can be repeated)
+    #[clap(long, value_name = "PATH|sha256:HEX", requires("uefi"))]
+    pub secure_boot_dbx: Vec<SecureBootDbxCli>,
+
+    /// persist the UEFI variable store to a standalone file, which is created
+    /// if it does not exist (use --vmgs to store it in a VMGS instead)
+    #[clap(long, value_name = "PATH", requires("uefi"), conflicts_with("vmgs"))]
+    pub uefi_nvram: Option<PathBuf>,
+
     /// the path to a named pipe (Windows) or Unix socket (Linux) to relay to the connected
     /// tty.
     ///
@@ -442,10 +784,54 @@ pub struct Options {
     #[clap(long, value_name = "PORT")]
     pub gdb: Option<u16>,
 
+    /// enable a second gdb debugger for VTL2, independent of --gdb
+    #[clap(long, value_name = "PORT", requires("vtl2"))]
+    pub gdb_vtl2: Option<u16>,
+
+    /// record the results of guest port I/O and MMIO accesses to a file, for
+    /// use with --io-trace-compare
+    #[clap(long, value_name = "PATH")]
+    pub io_trace_record: Option<PathBuf>,
+
+    /// compare guest port I/O and MMIO accesses against those recorded with
+    /// --io-trace-record, reporting where the guest diverges from the recording.
+    /// Reads return the recorded data. This is not deterministic replay
+    #[clap(long, value_name = "PATH", conflicts_with("io_trace_record"))]
+    pub io_trace_compare: Option<PathBuf>,
+
+    /// log guest port I/O, MMIO, and MSR accesses matching a filter, such as
+    /// `pio=0x60-0x64,mmio=0xfed00000-0xfed003ff,msr,dev=rtc`
+    #[clap(long, value_name = "FILTER")]
+    pub trace_guest_access: Option<String>,
+
+    /// write the accesses traced with --trace-guest-access to a file instead
+    /// of logging them
+    #[clap(long, value_name = "PATH", requires("trace_guest_access"))]
+    pub trace_guest_access_file: Option<PathBuf>,
+
     /// enable emulated MANA devices with the given network backend (see --net)
     #[clap(long)]
     pub mana: Vec<NicConfigCli>,
 
+    /// expose the given network backend (see --net) as an emulated MANA
+    /// virtual function, each on its own VPCI bus
+    #[clap(long_help = r#"
+e.g: --mana-vf consomme --mana-vf uh:consomme
+
+Each occurrence adds a virtual function with a single vport. Unlike the
+devices added with --mana, each virtual function can be revoked from the guest
+and offered again at runtime (see the `vf` console command), and supports
+function level reset, for testing virtual function removal and recovery
+without hardware.
+"#)]
+    #[clap(long, value_name = "NIC")]
+    pub mana_vf: Vec<NicConfigCli>,
+
+    /// expose an emulated Intel e1000e NIC with the given network backend
+    /// (see --net), for guests without paravirtualized network drivers
+    #[clap(long)]
+    pub e1000: Vec<NicConfigCli>,
+
     /// use a specific hypervisor interface
     #[clap(long, value_parser = parse_hypervisor)]
     pub hypervisor: Option<Hypervisor>,
@@ -481,11 +867,16 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `vhdx:\<path\>[;create=<len>]`   dynamic or differencing VHDX, parsed in user mode
+        \<path\>: path to the VHDX file
+        <len>: create a new dynamic VHDX of this size, e.g.: `64G`
 
 flags:
     `ro`                           open disk as read-only
     `s`                            attach drive to secondary ide channel
     `dvd`                          specifies that device is cd/dvd and it is read_only
+    `sector=512`                   present this logical sector size to the guest (IDE only supports 512)
+    `physical-sector=<n>`          present this physical sector size to the guest
 "#)]
     #[clap(long, value_name = "FILE")]
     pub ide: Vec<IdeDiskCli>,
@@ -504,6 +895,9 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `vhdx:\<path\>[;create=<len>]`   dynamic or differencing VHDX, parsed in user mode
+        \<path\>: path to the VHDX file
+        <len>: create a new dynamic VHDX of this size, e.g.: `64G`
 
 flags:
     `ro`                           open disk as read-only
@@ -523,6 +917,33 @@ pub struct Options {
     #[clap(long)]
     pub halt_on_reset: bool,
 
+    /// allow the guest to enter ACPI sleep states: a comma separated list of
+    /// s3 (suspend-to-RAM) and s4 (hibernate), or both if no list is given.
+    /// Wake a suspended guest with the `wake` interactive console command
+    #[clap(
+        long,
+        value_name = "STATES",
+        value_delimiter = ',',
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_values = ["s3", "s4"]
+    )]
+    pub allow_sleep_states: Option<Vec<SleepStateCli>>,
+
+    /// deprecated alias for --allow-sleep-states=s3
+    #[clap(long, hide = true, conflicts_with = "allow_sleep_states")]
+    pub s3: bool,
+
+    /// deprecated alias for --allow-sleep-states=s4
+    #[clap(long, hide = true, conflicts_with = "allow_sleep_states")]
+    pub s4: bool,
+
+    /// resume a guest that hibernated during a previous run. Requires a
+    /// persistent VMGS file, which records the configuration that must match
+    /// on resume
+    #[clap(long, requires("vmgs"))]
+    pub resume_from_hibernate: bool,
+
     /// write saved state .proto files to the specified path
     #[clap(long)]
     pub write_saved_state_proto: Option<PathBuf>,
@@ -535,10 +956,80 @@ pub struct Options {
     #[clap(long)]
     pub mcr: bool, // TODO MCR: support closed source CLI flags
 
-    /// expose a battery device
+    /// expose a battery device, whose state can be changed at runtime with the
+    /// `battery` console command
     #[clap(long)]
     pub battery: bool,
 
+    /// expose ACPI PCI hot-plug slots at the specified PCI device numbers
+    /// (comma separated; requires a PCI bus)
+    #[clap(
+        long,
+        value_name = "DEVICES",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u8).range(1..32)
+    )]
+    pub pci_hotplug_slots: Vec<u8>,
+
+    /// add PCI-to-PCI bridges to the emulated PCI bus
+    #[clap(long_help = r#"
+e.g: --pci-bridges 2 --pci-slot nvme-sriov=01:00.0
+
+Adds COUNT bridges on bus 0, named `pci-bridge-<n>` for `--pci-slot`. Bridge
+n leads to bus n + 1, where devices can be placed with `--pci-slot`. The
+bus numbers start out as firmware would assign them, and the guest may
+renumber them. Requires the generic PCI bus (Linux direct boot on x86).
+"#)]
+    #[clap(long, value_name = "COUNT", default_value = "0")]
+    pub pci_bridges: u8,
+
+    /// place a device on the emulated PCI bus at a fixed slot
+    #[clap(long_help = r#"
+e.g: --pci-slot e1000-0=00:05.0 --pci-slot ivshmem-0=05.1
+
+syntax: <device>=[<bus>:]<device number>.<function>
+
+Devices are named `<virtio device>-pci`, `xhci`, `e1000-<n>`,
+`serial-pci-<n>`, `ivshmem-<n>`, `nvme-sriov`, `pci-bridge-<n>`, or
+`vfio-<address>`, numbering each kind from 0 in command line order. The slot
+numbers are hexadecimal. Devices without a fixed slot are placed on bus 0 in
+order from device number 0x0a.
+Several devices can share a device number as functions of a multi-function
+device, which must include function 0. The NVMe virtual functions take the
+functions after `nvme-sriov`, which must be function 0.
+
+Buses other than 0 are the secondary buses of the bridges added with
+`--pci-bridges`; the bridges themselves must be on bus 0. VPCI devices are not
+on this bus; the guest derives their location from their instance IDs, which are
+constant across runs.
+"#)]
+    #[clap(long, value_name = "DEVICE=BDF")]
+    pub pci_slot: Vec<PciSlotCli>,
+
+    /// expose a QEMU-compatible pvpanic device (x86 only), which guests use
+    /// to report kernel panics
+    #[clap(long)]
+    pub pvpanic: bool,
+
+    /// what to do when the guest crashes, as reported via pvpanic or the
+    /// Hyper-V guest crash registers, or on a triple fault
+    #[clap(long, value_name = "ACTION", default_value = "none")]
+    pub on_guest_crash: GuestCrashActionCli,
+
+    /// the directory to write guest memory dumps to for
+    /// `--on-guest-crash dump`
+    #[clap(long, value_name = "DIR")]
+    pub guest_crash_dump_dir: Option<PathBuf>,
+
+    /// the format of guest memory dumps
+    #[clap(long, value_name = "FORMAT", default_value = "elf")]
+    pub guest_crash_dump_format: GuestCrashDumpFormatCli,
+
+    /// every `secs` seconds, briefly pause the VM and save its device state to
+    /// `dir`, keeping the most recent few dumps for post-mortem debugging
+    #[clap(long, value_name = "SECS:DIR")]
+    pub periodic_state_dump: Option<PeriodicStateDumpCli>,
+
     /// set the uefi console mode
     #[clap(long)]
     pub uefi_console_mode: Option<UefiConsoleModeCli>,
@@ -546,6 +1037,82 @@ pub struct Options {
     /// Perform a default boot even if boot entries exist and fail
     #[clap(long)]
     pub default_boot_always_attempt: bool,
+
+    /// set an SMBIOS value reported by the firmware (KEY=VALUE, where KEY is
+    /// uuid, manufacturer, product, version, sku, family, serial,
+    /// baseboard-serial, chassis-serial, chassis-asset-tag, or oem).
+    ///
+    /// PCAT only supports uuid and the serial numbers and asset tag. Each oem
+    /// value adds a string to the type 11 structure, which is only supported
+    /// for Linux direct boot on x86.
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub smbios: Vec<SmbiosCli>,
+
+    /// set the initial RTC time (an RFC 3339 timestamp such as
+    /// 2030-01-01T00:00:00Z, or now, now+SPAN, or now-SPAN where SPAN is a
+    /// duration such as 30d or 1y6mo)
+    #[clap(long, value_name = "TIME")]
+    pub rtc_base: Option<RtcBaseCli>,
+
+    /// keep the RTC in UTC (the default)
+    #[clap(long, overrides_with("rtc_localtime"))]
+    pub rtc_utc: bool,
+
+    /// keep the RTC in the host's local time zone, as expected by Windows
+    #[clap(long, overrides_with("rtc_utc"))]
+    pub rtc_localtime: bool,
+
+    /// how guest clocks behave when the VM is paused and resumed
+    #[clap(long, value_name = "POLICY", default_value = "freeze")]
+    pub clock_policy: ClockPolicyCli,
+
+    /// don't offer the timesync IC, so the host does not correct the guest's
+    /// clock
+    #[clap(long, conflicts_with("timesync_skew"))]
+    pub no_timesync: bool,
+
+    /// offset the time sent to the guest by the timesync IC by MS
+    /// milliseconds (may be negative), to test how guests handle clock skew
+    #[clap(long, value_name = "MS", allow_hyphen_values = true)]
+    pub timesync_skew: Option<i64>,
+
+    /// boot the EFI application at PATH (e.g. a kernel's EFI stub) from a
+    /// generated FAT boot volume, attached as the first VTL0 SCSI disk
+    #[clap(long, requires("uefi"), value_name = "PATH")]
+    pub uefi_boot_file: Option<PathBuf>,
+
+    /// set the UEFI boot order (comma separated list of disk, dvd, net, or
+    /// file:PATH), replacing any boot entries from the custom UEFI variables
+    ///
+    /// disk and dvd add an entry for each VTL0 SCSI disk or DVD, net for each
+    /// VTL0 VMBus NIC, and file:PATH for an EFI application searched for on
+    /// all file systems. Devices that are not listed are only tried by a
+    /// default boot.
+    ///
+    /// Passing duplicate device types is an error.
+    #[clap(long, requires("uefi"), value_name = "DEVICES")]
+    pub uefi_boot_order: Option<UefiBootOrderCli>,
+
+    /// act on a VM run by another OpenVMM instance instead of running one
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// A command to run instead of a VM.
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// save a snapshot of the VM run by an OpenVMM ttrpc server to a file, to
+    /// resume it from later with `--resume`. The VM keeps running afterwards
+    Save {
+        /// the ttrpc server's socket
+        #[clap(long, value_name = "SOCKETPATH")]
+        ttrpc: PathBuf,
+
+        /// the file to write the snapshot to, resolved relative to the current
+        /// directory
+        #[clap(long, value_name = "PATH")]
+        file: PathBuf,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -596,6 +1163,12 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
 #[derive(Copy, Clone, clap::ValueEnum)]
 pub enum VirtioBusCli {
     Auto,
@@ -610,7 +1183,7 @@ pub enum SecureBootTemplateCli {
     UefiCa,
 }
 
-fn parse_memory(s: &str) -> anyhow::Result<u64> {
+pub fn parse_memory(s: &str) -> anyhow::Result<u64> {
     || -> Option<u64> {
         let mut b = s.as_bytes();
         if s.ends_with('B') {
@@ -630,7 +1203,7 @@ fn parse_memory(s: &str) -> anyhow::Result<u64> {
             b = &b[..b.len() - 1]
         }
         let n: u64 = std::str::from_utf8(b).ok()?.parse().ok()?;
-        Some(n * multi.unwrap_or(1))
+        n.checked_mul(multi.unwrap_or(1))
     }()
     .with_context(|| format!("invalid memory size '{0}'", s))
 }
@@ -668,39 +1241,120 @@ pub enum DiskCliKind {
     },
     // prwrap:<kind>
     PersistentReservationsWrapper(Box<DiskCliKind>),
+    // prshared:<path>[;host=<id>]:<kind>
+    //
+    // Persistent reservations stored in <path>, shared with other VMs using
+    // the same file. The host ID defaults to a random value.
+    SharedPersistentReservations {
+        path: PathBuf,
+        host_id: Option<u64>,
+        disk: Box<DiskCliKind>,
+    },
+    // zstd:<path>
+    //
+    // A read-only image in the zstd seekable format.
+    Zstd(PathBuf),
     // file:<path>[;create=<len>]
     File {
         path: PathBuf,
         create_with_len: Option<u64>,
     },
-    // blob:<type>:<url>
+    // vhdx:<path>[;create=<len>]
+    Vhdx {
+        path: PathBuf,
+        create_with_len: Option<u64>,
+    },
+    // vhds:<path>[;checkpoint=<guid>]
+    //
+    // A Hyper-V VHD Set and its checkpoint chain (Windows only). If
+    // <guid> is set, the disk is opened read-only as of that checkpoint.
+    VhdSet {
+        path: PathBuf,
+        checkpoint: Option<guid::Guid>,
+    },
+    // blob:<type>:<url>, where <type> is flat, vhd1, page-flat, or page-vhd1
+    //
+    // page-* blobs are writable Azure page blobs. Set OPENVMM_BLOB_BEARER_TOKEN
+    // to authenticate with a bearer token instead of a SAS URL.
     Blob {
         kind: BlobKind,
         url: String,
     },
     // crypt:<cipher>:<key_file>:<kind>
+    //
+    // For luks2, the key file contains the passphrase, and `prompt` reads the
+    // passphrase from the terminal instead.
     Crypt {
         cipher: DiskCipher,
-        key_file: PathBuf,
+        key: DiskKey,
+        disk: Box<DiskCliKind>,
+    },
+    // verity:<hashtree>:<roothash>:<kind>
+    Verity {
+        hash_tree: PathBuf,
+        root_hash: Vec<u8>,
+        disk: Box<DiskCliKind>,
+    },
+    // cache:<size>[;flush=<ms>]:<kind>
+    //
+    // Caches up to <size> bytes of writes in memory, writing them back on
+    // guest flush, when the cache fills, and every <ms> milliseconds if set.
+    WriteBackCache {
+        cache_size: u64,
+        flush_interval_ms: Option<u64>,
         disk: Box<DiskCliKind>,
     },
+    // multipath:<kind>|<kind>[|<kind>...]
+    //
+    // Mirrors the disk across each path. Set `paths/<n>/failed` on the disk
+    // via inspect to fail a path and test failover.
+    Multipath(Vec<DiskCliKind>),
     // delay:<delay_ms>:<kind>
     DelayDiskWrapper {
         delay_ms: u64,
         disk: Box<DiskCliKind>,
     },
+    // <kind>,iops=<n>,bw=<n>,burst=<n>
+    Throttle {
+        iops: Option<u64>,
+        bytes_per_second: Option<u64>,
+        burst: Option<u64>,
+        disk: Box<DiskCliKind>,
+    },
+    // <kind>,sector=<n>,physical-sector=<n>
+    SectorSize {
+        sector_size: Option<u32>,
+        physical_sector_size: Option<u32>,
+        disk: Box<DiskCliKind>,
+    },
 }
 
 #[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
 pub enum DiskCipher {
     #[clap(name = "xts-aes-256")]
     XtsAes256,
+    /// A LUKS2 container, unlocked with a passphrase.
+    #[clap(name = "luks2")]
+    Luks2,
+}
+
+/// The source of the key (or passphrase) for an encrypted disk.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiskKey {
+    /// Read the key from a file.
+    File(PathBuf),
+    /// Prompt for a passphrase on the terminal.
+    Prompt,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum BlobKind {
     Flat,
     Vhd1,
+    /// A flat, writable Azure page blob.
+    PageFlat,
+    /// A fixed VHD1 stored in a writable Azure page blob.
+    PageVhd1,
 }
 
 fn parse_path_and_len(arg: &str) -> anyhow::Result<(PathBuf, Option<u64>)> {
@@ -778,6 +1432,27 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                     }
                 }
                 "prwrap" => DiskCliKind::PersistentReservationsWrapper(Box::new(arg.parse()?)),
+                "prshared" => {
+                    let (path_and_opts, kind) =
+                        arg.split_once(':').context("expected path[;opts]:kind")?;
+                    let (path, host_id) = match path_and_opts.split_once(';') {
+                        Some((path, host_id)) => {
+                            let Some(host_id) = host_id.strip_prefix("host=") else {
+                                anyhow::bail!("invalid syntax after ';', expected 'host=<id>'")
+                            };
+                            (
+                                path,
+                                Some(parse_number(host_id).context("invalid host id")?),
+                            )
+                        }
+                        None => (path_and_opts, None),
+                    };
+                    DiskCliKind::SharedPersistentReservations {
+                        path: path.into(),
+                        host_id,
+                        disk: Box::new(kind.parse()?),
+                    }
+                }
                 "file" => {
                     let (path, create_with_len) = parse_path_and_len(arg)?;
                     DiskCliKind::File {
@@ -785,11 +1460,41 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                         create_with_len,
                     }
                 }
+                "zstd" => DiskCliKind::Zstd(PathBuf::from(arg)),
+                "vhdx" => {
+                    let (path, create_with_len) = parse_path_and_len(arg)?;
+                    DiskCliKind::Vhdx {
+                        path,
+                        create_with_len,
+                    }
+                }
+                "vhds" => {
+                    let (path, checkpoint) = match arg.split_once(';') {
+                        Some((path, checkpoint)) => {
+                            let Some(checkpoint) = checkpoint.strip_prefix("checkpoint=") else {
+                                anyhow::bail!(
+                                    "invalid syntax after ';', expected 'checkpoint=<guid>'"
+                                )
+                            };
+                            (
+                                path,
+                                Some(checkpoint.parse().context("invalid checkpoint id")?),
+                            )
+                        }
+                        None => (arg, None),
+                    };
+                    DiskCliKind::VhdSet {
+                        path: path.into(),
+                        checkpoint,
+                    }
+                }
                 "blob" => {
                     let (blob_kind, url) = arg.split_once(':').context("expected kind:url")?;
                     let blob_kind = match blob_kind {
                         "flat" => BlobKind::Flat,
                         "vhd1" => BlobKind::Vhd1,
+                        "page-flat" => BlobKind::PageFlat,
+                        "page-vhd1" => BlobKind::PageVhd1,
                         _ => anyhow::bail!("unknown blob kind {blob_kind}"),
                     };
                     DiskCliKind::Blob {
@@ -802,13 +1507,58 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                         .split_once(':')
                         .and_then(|(cipher, arg)| Some((cipher, arg.split_once(':')?)))
                         .context("expected cipher:key_file:kind")?;
+                    let cipher = ValueEnum::from_str(cipher, false)
+                        .map_err(|err| anyhow::anyhow!("invalid cipher: {err}"))?;
+                    let key = match key {
+                        "prompt" if cipher == DiskCipher::Luks2 => DiskKey::Prompt,
+                        "prompt" => anyhow::bail!("only luks2 supports a passphrase prompt"),
+                        key => DiskKey::File(PathBuf::from(key)),
+                    };
                     DiskCliKind::Crypt {
-                        cipher: ValueEnum::from_str(cipher, false)
-                            .map_err(|err| anyhow::anyhow!("invalid cipher: {err}"))?,
-                        key_file: PathBuf::from(key),
+                        cipher,
+                        key,
+                        disk: Box::new(kind.parse()?),
+                    }
+                }
+                "verity" => {
+                    let (hash_tree, (root_hash, kind)) = arg
+                        .split_once(':')
+                        .and_then(|(hash_tree, arg)| Some((hash_tree, arg.split_once(':')?)))
+                        .context("expected hashtree:roothash:kind")?;
+                    DiskCliKind::Verity {
+                        hash_tree: PathBuf::from(hash_tree),
+                        root_hash: hex::decode(root_hash).context("invalid root hash")?,
+                        disk: Box::new(kind.parse()?),
+                    }
+                }
+                "cache" => {
+                    let (size_and_opts, kind) =
+                        arg.split_once(':').context("expected size[;opts]:kind")?;
+                    let (size, flush_interval_ms) = match size_and_opts.split_once(';') {
+                        Some((size, flush)) => {
+                            let Some(flush) = flush.strip_prefix("flush=") else {
+                                anyhow::bail!("invalid syntax after ';', expected 'flush=<ms>'")
+                            };
+                            (size, Some(flush.parse().context("invalid flush interval")?))
+                        }
+                        None => (size_and_opts, None),
+                    };
+                    DiskCliKind::WriteBackCache {
+                        cache_size: parse_memory(size)?,
+                        flush_interval_ms,
                         disk: Box::new(kind.parse()?),
                     }
                 }
+                "multipath" => {
+                    let paths = arg
+                        .split('|')
+                        .map(|kind| kind.parse())
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    if paths.len() < 2 {
+                        anyhow::bail!("expected at least two paths separated by '|'");
+                    }
+                    DiskCliKind::Multipath(paths)
+                }
                 kind => {
                     // here's a fun edge case: what if the user passes `--disk d:\path\to\disk.img`?
                     //
@@ -885,17 +1635,39 @@ impl FromStr for DiskCli {
 
     fn from_str(s: &str) -> anyhow::Result<Self> {
         let mut opts = s.split(',');
-        let kind = opts.next().unwrap().parse()?;
+        let mut kind = opts.next().unwrap().parse()?;
 
         let mut read_only = false;
         let mut is_dvd = false;
         let mut underhill = None;
         let mut vtl = DeviceVtl::Vtl0;
+        let mut iops = None;
+        let mut bytes_per_second = None;
+        let mut burst = None;
+        let mut sector_size = None;
+        let mut physical_sector_size = None;
         for opt in opts {
             let mut s = opt.split('=');
             let opt = s.next().unwrap();
+            let mut value = || -> anyhow::Result<u64> {
+                let v = s
+                    .next()
+                    .with_context(|| format!("missing value for '{opt}'"))?;
+                parse_number(v).with_context(|| format!("invalid value for '{opt}'"))
+            };
             match opt {
                 "ro" => read_only = true,
+                "iops" => iops = Some(value()?),
+                "bw" => {
+                    bytes_per_second = Some(
+                        value()?
+                            .checked_mul(1024 * 1024)
+                            .context("bandwidth too large")?,
+                    )
+                }
+                "burst" => burst = Some(value()?),
+                "sector" => sector_size = Some(value()?),
+                "physical-sector" => physical_sector_size = Some(value()?),
                 "dvd" => {
                     is_dvd = true;
                     read_only = true;
@@ -913,6 +1685,23 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
             anyhow::bail!("`uh` is incompatible with `vtl2`");
         }
 
+        if burst.is_some() && iops.is_none() {
+            anyhow::bail!("`burst` requires `iops`");
+        }
+
+        if sector_size.is_some() || physical_sector_size.is_some() {
+            kind = parse_sector_size(kind, sector_size, physical_sector_size)?;
+        }
+
+        if iops.is_some() || bytes_per_second.is_some() {
+            kind = DiskCliKind::Throttle {
+                iops,
+                bytes_per_second,
+                burst,
+                disk: Box::new(kind),
+            };
+        }
+
         Ok(DiskCli {
             vtl,
             kind,
@@ -923,6 +1712,33 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
     }
 }
 
+fn parse_sector_size(
+    kind: DiskCliKind,
+    sector_size: Option<u64>,
+    physical_sector_size: Option<u64>,
+) -> anyhow::Result<DiskCliKind> {
+    if let Some(sector_size) = sector_size {
+        if sector_size != 512 && sector_size != 4096 {
+            anyhow::bail!("`sector` must be 512 or 4096");
+        }
+    }
+    let physical_sector_size = physical_sector_size
+        .map(|n| {
+            if !n.is_power_of_two() || n < sector_size.unwrap_or(512) || n > 0x10000 {
+                anyhow::bail!(
+                    "`physical-sector` must be a power of two between the logical sector size and 64K"
+                );
+            }
+            Ok(n as u32)
+        })
+        .transpose()?;
+    Ok(DiskCliKind::SectorSize {
+        sector_size: sector_size.map(|n| n as u32),
+        physical_sector_size,
+        disk: Box::new(kind),
+    })
+}
+
 // <kind>[,ro,s]
 #[derive(Clone)]
 pub struct IdeDiskCli {
@@ -938,17 +1754,27 @@ impl FromStr for IdeDiskCli {
 
     fn from_str(s: &str) -> anyhow::Result<Self> {
         let mut opts = s.split(',');
-        let kind = opts.next().unwrap().parse()?;
+        let mut kind = opts.next().unwrap().parse()?;
 
         let mut read_only = false;
         let mut channel = None;
         let mut device = None;
         let mut is_dvd = false;
+        let mut sector_size = None;
+        let mut physical_sector_size = None;
         for opt in opts {
             let mut s = opt.split('=');
             let opt = s.next().unwrap();
+            let mut value = || -> anyhow::Result<u64> {
+                let v = s
+                    .next()
+                    .with_context(|| format!("missing value for '{opt}'"))?;
+                parse_number(v).with_context(|| format!("invalid value for '{opt}'"))
+            };
             match opt {
                 "ro" => read_only = true,
+                "sector" => sector_size = Some(value()?),
+                "physical-sector" => physical_sector_size = Some(value()?),
                 "p" => channel = Some(0),
                 "s" => channel = Some(1),
                 "0" => device = Some(0),
@@ -961,6 +1787,14 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
             }
         }
 
+        if sector_size.is_some_and(|n| n != 512) {
+            anyhow::bail!("ide disks only support 512-byte logical sectors");
+        }
+
+        if sector_size.is_some() || physical_sector_size.is_some() {
+            kind = parse_sector_size(kind, sector_size, physical_sector_size)?;
+        }
+
         Ok(IdeDiskCli {
             kind,
             read_only,
@@ -1002,10 +1836,77 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
     }
 }
 
+// cpu:<hz> | heap | latency
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProfileCli {
+    Cpu { hz: u32 },
+    Heap,
+    Latency,
+}
+
+impl FromStr for ProfileCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let profile = match s.split_once(':') {
+            Some(("cpu", hz)) => {
+                let hz = hz.parse().context("invalid sampling frequency")?;
+                if hz == 0 {
+                    anyhow::bail!("sampling frequency must be nonzero");
+                }
+                ProfileCli::Cpu { hz }
+            }
+            None if s == "heap" => ProfileCli::Heap,
+            None if s == "latency" => ProfileCli::Latency,
+            _ => anyhow::bail!("unknown profile kind: '{s}'"),
+        };
+        Ok(profile)
+    }
+}
+
+impl std::fmt::Display for ProfileCli {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileCli::Cpu { hz } => write!(f, "cpu:{hz}"),
+            ProfileCli::Heap => f.write_str("heap"),
+            ProfileCli::Latency => f.write_str("latency"),
+        }
+    }
+}
+
+// <secs>:<dir>
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeriodicStateDumpCli {
+    pub interval: Duration,
+    pub dir: PathBuf,
+}
+
+impl FromStr for PeriodicStateDumpCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (secs, dir) = s.split_once(':').context("expected <secs>:<dir>")?;
+        let secs: u64 = secs.parse().context("invalid interval")?;
+        if secs == 0 {
+            anyhow::bail!("interval must be nonzero");
+        }
+        if dir.is_empty() {
+            anyhow::bail!("missing directory");
+        }
+        Ok(Self {
+            interval: Duration::from_secs(secs),
+            dir: dir.into(),
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct DebugconSerialConfigCli {
     pub port: u16,
     pub serial: SerialConfigCli,
+    /// Emit output as tracing events instead of writing it to a serial
+    /// backend.
+    pub trace: Option<DebugconTraceConfig>,
 }
 
 impl FromStr for DebugconSerialConfigCli {
@@ -1020,42 +1921,184 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
             .map_err(|_| "could not parse port".to_owned())?
             .try_into()
             .map_err(|_| "port must be 16-bit")?;
+
+        let mut opts = serial.split(',');
+        if let Some(level) = opts
+            .next()
+            .and_then(|kind| kind.strip_prefix("trace"))
+            .filter(|level| level.is_empty() || level.starts_with('='))
+        {
+            let level = match level.strip_prefix('=').unwrap_or("info") {
+                "error" => DebugconTraceLevel::Error,
+                "warn" => DebugconTraceLevel::Warn,
+                "info" => DebugconTraceLevel::Info,
+                "debug" => DebugconTraceLevel::Debug,
+                "trace" => DebugconTraceLevel::Trace,
+                level => return Err(format!("invalid trace level: {level}")),
+            };
+            let mut target = "debugcon".to_owned();
+            for opt in opts {
+                let (k, v) = opt.split_once('=').unwrap_or((opt, ""));
+                match k {
+                    "target" => target = v.to_owned(),
+                    _ => return Err(format!("unknown trace option: {k}")),
+                }
+            }
+            return Ok(Self {
+                port,
+                serial: SerialConfigCli::None,
+                trace: Some(DebugconTraceConfig { level, target }),
+            });
+        }
+
         let serial: SerialConfigCli = serial.parse()?;
 
-        Ok(Self { port, serial })
+        Ok(Self {
+            port,
+            serial,
+            trace: None,
+        })
     }
 }
 
-/// (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | file=\<path\> | none)
+/// An additional serial port, beyond the four standard COM ports.
 #[derive(Clone, Debug, PartialEq)]
-pub enum SerialConfigCli {
-    None,
-    Console,
-    NewConsole(Option<PathBuf>, Option<String>),
-    Stderr,
-    Pipe(PathBuf),
-    Tcp(SocketAddr),
-    File(PathBuf),
+pub struct ExtraSerialConfigCli {
+    /// The port number, starting at 5.
+    pub index: u32,
+    /// The IO port and IRQ for an ISA UART, or `None` to place the port on a
+    /// PCI serial card.
+    pub isa: Option<(u16, u32)>,
+    pub serial: SerialConfigCli,
 }
 
-impl FromStr for SerialConfigCli {
+impl FromStr for ExtraSerialConfigCli {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let keyvalues = SerialConfigCli::parse_keyvalues(s)?;
-
-        let first_key = match keyvalues.first() {
-            Some(first_pair) => first_pair.0.as_str(),
-            None => Err("invalid serial configuration: no values supplied")?,
+        let Some((port, serial)) = s.split_once(':') else {
+            return Err("invalid format (missing colon between port and serial)".into());
         };
-        let first_value = keyvalues.first().unwrap().1.as_ref();
 
-        let ret = match first_key {
+        let mut options = port.split(',');
+        let index: u32 = options
+            .next()
+            .unwrap()
+            .parse()
+            .map_err(|_| "could not parse port number".to_owned())?;
+        if index < 5 {
+            return Err("port number must be at least 5 (use --com1-4 for the others)".into());
+        }
+
+        let mut io_port = None;
+        let mut irq = None;
+        for option in options {
+            match option.split_once('=') {
+                Some(("io", v)) => {
+                    io_port = Some(
+                        parse_number(v)
+                            .ok()
+                            .and_then(|v| u16::try_from(v).ok())
+                            .ok_or("io port must be a 16-bit number")?,
+                    );
+                }
+                Some(("irq", v)) => {
+                    irq = Some(
+                        parse_number(v)
+                            .ok()
+                            .and_then(|v| u32::try_from(v).ok())
+                            .ok_or("could not parse irq")?,
+                    );
+                }
+                _ => return Err(format!("unknown serial port option '{option}'")),
+            }
+        }
+        let isa = match (io_port, irq) {
+            (Some(io_port), Some(irq)) => Some((io_port, irq)),
+            (None, None) => None,
+            _ => return Err("io and irq must be specified together".into()),
+        };
+
+        let serial: SerialConfigCli = serial.parse()?;
+        Ok(Self { index, isa, serial })
+    }
+}
+
+/// (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | connect=tcp:\<ip\>:\<port\> | file=\<path\> | none)
+#[derive(Clone, Debug, PartialEq)]
+pub enum SerialConfigCli {
+    None,
+    Console,
+    NewConsole(Option<PathBuf>, Option<String>),
+    Stderr,
+    Pipe(PathBuf),
+    Tcp(SocketAddr),
+    TcpConnect { addr: SocketAddr, retry: Duration },
+    WebSocket(SocketAddr),
+    File(PathBuf, SerialFileOptions),
+    Pty,
+}
+
+/// Options for the `file=` serial backend.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SerialFileOptions {
+    /// Rotate the log once it reaches this size, in bytes.
+    pub max_size: Option<u64>,
+    /// The number of rotated logs to keep.
+    pub rotate: u32,
+    /// Prefix each line with a host timestamp.
+    pub timestamp: bool,
+}
+
+impl FromStr for SerialConfigCli {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keyvalues = SerialConfigCli::parse_keyvalues(s)?;
+
+        let first_key = match keyvalues.first() {
+            Some(first_pair) => first_pair.0.as_str(),
+            None => Err("invalid serial configuration: no values supplied")?,
+        };
+        let first_value = keyvalues.first().unwrap().1.as_ref();
+
+        let ret = match first_key {
             "none" => SerialConfigCli::None,
             "console" => SerialConfigCli::Console,
             "stderr" => SerialConfigCli::Stderr,
+            "pty" => SerialConfigCli::Pty,
             "file" => match first_value {
-                Some(path) => SerialConfigCli::File(path.into()),
+                Some(path) => {
+                    let mut options = SerialFileOptions::default();
+                    let mut rotate = None;
+                    for (key, value) in &keyvalues[1..] {
+                        match (key.as_str(), value) {
+                            ("maxsize", Some(size)) => {
+                                options.max_size = Some(
+                                    parse_memory(size)
+                                        .map_err(|err| format!("invalid maxsize: {err}"))?,
+                                );
+                            }
+                            ("rotate", Some(n)) => {
+                                rotate = Some(
+                                    n.parse()
+                                        .map_err(|err| format!("invalid rotate count: {err}"))?,
+                                );
+                            }
+                            ("timestamp", None) => options.timestamp = true,
+                            _ => Err(format!(
+                                "invalid serial configuration: unknown file option '{key}'"
+                            ))?,
+                        }
+                    }
+                    if options.max_size.is_some() {
+                        // Keep one previous log by default.
+                        options.rotate = rotate.unwrap_or(1);
+                    } else if rotate.is_some() {
+                        return Err("invalid serial configuration: rotate requires maxsize".into());
+                    }
+                    SerialConfigCli::File(path.into(), options)
+                }
                 None => Err("invalid serial configuration: file requires a value")?,
             },
             "term" => match first_value {
@@ -1078,14 +2121,35 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
                             .parse()
                             .map_err(|err| format!("invalid tcp address: {err}"))?;
                         SerialConfigCli::Tcp(addr)
+                    } else if let Some(ws) = path.strip_prefix("ws:") {
+                        let addr = ws
+                            .parse()
+                            .map_err(|err| format!("invalid websocket address: {err}"))?;
+                        SerialConfigCli::WebSocket(addr)
                     } else {
                         SerialConfigCli::Pipe(path.into())
                     }
                 }
                 None => Err(
-                    "invalid serial configuration: listen requires a value of tcp:addr or pipe",
+                    "invalid serial configuration: listen requires a value of tcp:addr, ws:addr, or pipe",
                 )?,
             },
+            "connect" => {
+                let addr = first_value
+                    .and_then(|v| v.strip_prefix("tcp:"))
+                    .ok_or("invalid serial configuration: connect requires a value of tcp:addr")?
+                    .parse()
+                    .map_err(|err| format!("invalid tcp address: {err}"))?;
+                let retry = match keyvalues.iter().find(|(key, _)| key == "retry") {
+                    Some((_, Some(secs))) => Duration::from_secs(
+                        secs.parse()
+                            .map_err(|err| format!("invalid retry interval: {err}"))?,
+                    ),
+                    Some((_, None)) => Err("invalid serial configuration: retry requires a value")?,
+                    None => Duration::from_secs(1),
+                };
+                SerialConfigCli::TcpConnect { addr, retry }
+            }
             _ => {
                 return Err(format!(
                     "invalid serial configuration: '{}' is not a known option",
@@ -1126,26 +2190,43 @@ fn parse_keyvalues(s: &str) -> Result<Vec<(String, Option<String>)>, String> {
 #[derive(Clone, Debug, PartialEq)]
 pub enum EndpointConfigCli {
     None,
-    Consomme { cidr: Option<String> },
-    Dio { id: Option<String> },
-    Tap { name: String },
+    Consomme {
+        cidr: Option<String>,
+        dhcp: ConsommeDhcpOptions,
+    },
+    Dio {
+        id: Option<String>,
+    },
+    Tap {
+        name: String,
+    },
+    Macvtap {
+        name: String,
+    },
 }
 
 impl FromStr for EndpointConfigCli {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Consomme options may contain colons (in MAC addresses), so split
+        // them off first.
+        match s.split_once(':') {
+            Some(("consomme", options)) => return parse_consomme_options(options),
+            None if s == "consomme" => return parse_consomme_options(""),
+            _ => {}
+        }
         let ret = match s.split(':').collect::<Vec<_>>().as_slice() {
             ["none"] => EndpointConfigCli::None,
-            ["consomme", s @ ..] => EndpointConfigCli::Consomme {
-                cidr: s.first().map(|&s| s.to_owned()),
-            },
             ["dio", s @ ..] => EndpointConfigCli::Dio {
                 id: s.first().map(|s| (*s).to_owned()),
             },
             ["tap", name] => EndpointConfigCli::Tap {
                 name: (*name).to_owned(),
             },
+            ["macvtap", name] => EndpointConfigCli::Macvtap {
+                name: (*name).to_owned(),
+            },
             _ => return Err("invalid network backend".into()),
         };
 
@@ -1153,12 +2234,63 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
+/// Parses `[<cidr>][,dns=<ip>][,search=<domain>][,lease=<mac>@<ip>][,tftp=<server>][,bootfile=<file>][,pxe=<dir>]`.
+fn parse_consomme_options(s: &str) -> Result<EndpointConfigCli, String> {
+    let mut cidr = None;
+    let mut dhcp = ConsommeDhcpOptions::default();
+    for (i, option) in s.split(',').enumerate() {
+        match option.split_once('=') {
+            None if i == 0 => {
+                if !option.is_empty() {
+                    cidr = Some(option.to_owned());
+                }
+            }
+            Some(("dns", ip)) => dhcp.dns_servers.push(
+                ip.parse()
+                    .map_err(|_| format!("invalid dns server: {ip}"))?,
+            ),
+            Some(("search", domain)) => {
+                let name = domain.strip_suffix('.').unwrap_or(domain);
+                if name.is_empty()
+                    || name.len() > 253
+                    || name
+                        .split('.')
+                        .any(|label| label.is_empty() || label.len() > 63)
+                {
+                    return Err(format!("invalid search domain: {domain}"));
+                }
+                dhcp.search_domains.push(domain.to_owned());
+            }
+            Some(("lease", lease)) => {
+                let (mac, ip) = lease.split_once('@').ok_or("expected lease=<mac>@<ip>")?;
+                dhcp.static_leases.push(ConsommeStaticLease {
+                    mac: mac
+                        .parse()
+                        .map_err(|_| format!("invalid lease mac address: {mac}"))?,
+                    ip: ip
+                        .parse()
+                        .map_err(|_| format!("invalid lease address: {ip}"))?,
+                });
+            }
+            Some(("tftp", server)) => dhcp.tftp_server = Some(server.to_owned()),
+            Some(("bootfile", file)) => dhcp.boot_file = Some(file.to_owned()),
+            Some(("pxe", dir)) => dhcp.boot_dir = Some(dir.to_owned()),
+            _ => return Err(format!("invalid consomme option: {option}")),
+        }
+    }
+    Ok(EndpointConfigCli::Consomme { cidr, dhcp })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct NicConfigCli {
     pub vtl: DeviceVtl,
     pub endpoint: EndpointConfigCli,
     pub max_queues: Option<u16>,
     pub underhill: bool,
+    pub mac_address: Option<MacAddress>,
+    pub rate_mbps: Option<u64>,
+    pub latency_ms: Option<u64>,
+    pub loss_percent: Option<f64>,
 }
 
 impl FromStr for NicConfigCli {
@@ -1168,12 +2300,51 @@ fn from_str(mut s: &str) -> Result<Self, Self::Err> {
         let mut vtl = DeviceVtl::Vtl0;
         let mut max_queues = None;
         let mut underhill = false;
-        while let Some((opt, rest)) = s.split_once(':') {
+        let mut mac_address = None;
+        let mut rate_mbps = None;
+        let mut latency_ms = None;
+        let mut loss_percent = None;
+        loop {
+            // The MAC address itself may contain colons, so split it off by
+            // length.
+            if let Some(val) = s.strip_prefix("mac=") {
+                let (mac, rest) = val
+                    .split_at_checked(17)
+                    .and_then(|(mac, rest)| Some((mac, rest.strip_prefix(':')?)))
+                    .ok_or("expected mac=<aa:bb:cc:dd:ee:ff>:<endpoint>")?;
+                let mac: MacAddress = mac.parse().map_err(|_| "failed to parse mac address")?;
+                if mac.to_bytes()[0] & 1 != 0 {
+                    return Err("mac address must not be a multicast address".into());
+                }
+                mac_address = Some(mac);
+                s = rest;
+                continue;
+            }
+            let Some((opt, rest)) = s.split_once(':') else {
+                break;
+            };
             if let Some((opt, val)) = opt.split_once('=') {
                 match opt {
                     "queues" => {
                         max_queues = Some(val.parse().map_err(|_| "failed to parse queue count")?);
                     }
+                    "rate" => {
+                        let rate = val.parse().map_err(|_| "failed to parse rate")?;
+                        if rate == 0 {
+                            return Err("rate must be nonzero".into());
+                        }
+                        rate_mbps = Some(rate);
+                    }
+                    "latency" => {
+                        latency_ms = Some(val.parse().map_err(|_| "failed to parse latency")?);
+                    }
+                    "loss" => {
+                        let loss: f64 = val.parse().map_err(|_| "failed to parse loss")?;
+                        if !(0.0..=100.0).contains(&loss) {
+                            return Err("loss must be a percentage between 0 and 100".into());
+                        }
+                        loss_percent = Some(loss);
+                    }
                     _ => break,
                 }
             } else {
@@ -1198,6 +2369,10 @@ fn from_str(mut s: &str) -> Result<Self, Self::Err> {
             endpoint,
             max_queues,
             underhill,
+            mac_address,
+            rate_mbps,
+            latency_ms,
+            loss_percent,
         })
     }
 }
@@ -1291,6 +2466,369 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryBackingCli {
+    /// Huge pages of the given size from hugetlbfs.
+    Hugetlb(u64),
+    /// A file, which is mapped directly if `shared`.
+    File { path: PathBuf, shared: bool },
+}
+
+impl FromStr for MemoryBackingCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+        match kind {
+            "hugetlb" => {
+                let page_size = if s == kind {
+                    2 * 1024 * 1024
+                } else {
+                    parse_memory(arg)?
+                };
+                if page_size != 2 * 1024 * 1024 && page_size != 1024 * 1024 * 1024 {
+                    anyhow::bail!("huge page size must be 2M or 1G");
+                }
+                Ok(Self::Hugetlb(page_size))
+            }
+            "file" => {
+                let (path, shared) = match arg.strip_suffix(",shared") {
+                    Some(path) => (path, true),
+                    None => (arg, false),
+                };
+                if path.is_empty() {
+                    anyhow::bail!("missing memory backing file path");
+                }
+                Ok(Self::File {
+                    path: path.into(),
+                    shared,
+                })
+            }
+            _ => anyhow::bail!("expected hugetlb[:2M|1G] or file:<path>[,shared]"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioCli {
+    /// Discard the audio.
+    Null,
+    /// Write the audio to a WAV file.
+    Wav(PathBuf),
+    /// Play the audio on the host through PulseAudio, on the given sink or
+    /// the default one.
+    Pulse(Option<String>),
+}
+
+impl FromStr for AudioCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once(':') {
+            None if s == "null" => Ok(Self::Null),
+            None if s == "pulse" => Ok(Self::Pulse(None)),
+            Some(("wav", path)) if !path.is_empty() => Ok(Self::Wav(path.into())),
+            Some(("pulse", sink)) if !sink.is_empty() => Ok(Self::Pulse(Some(sink.into()))),
+            _ => anyhow::bail!("expected null, wav:<path>, or pulse[:<sink>]"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IvshmemCli {
+    pub path: PathBuf,
+    pub size: u64,
+    pub doorbell: Option<PathBuf>,
+}
+
+impl FromStr for IvshmemCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut opts = s.split(',');
+        let path = opts.next().unwrap();
+        if path.is_empty() {
+            anyhow::bail!("missing path");
+        }
+        let size = parse_memory(opts.next().context("missing size")?)?;
+        if !size.is_power_of_two() || size < 0x1000 {
+            anyhow::bail!("size must be a power of two of at least 4K");
+        }
+        let mut doorbell = None;
+        for opt in opts {
+            match opt.split_once('=') {
+                Some(("doorbell", socket)) if !socket.is_empty() => {
+                    doorbell = Some(socket.into());
+                }
+                _ => anyhow::bail!("unknown option: '{opt}'"),
+            }
+        }
+        Ok(Self {
+            path: path.into(),
+            size,
+            doorbell,
+        })
+    }
+}
+
+// <device>=[<bus>:]<device number>.<function>
+#[derive(Debug, Clone, PartialEq)]
+pub struct PciSlotCli {
+    pub device: String,
+    pub bus: u8,
+    pub device_number: u8,
+    pub function: u8,
+}
+
+impl FromStr for PciSlotCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (device, bdf) = s.split_once('=').context("expected <device>=<bdf>")?;
+        if device.is_empty() {
+            anyhow::bail!("missing device name");
+        }
+        let (bus, slot) = bdf.split_once(':').unwrap_or(("0", bdf));
+        let bus = u8::from_str_radix(bus, 16).with_context(|| format!("invalid bus '{bus}'"))?;
+        let (device_number, function) = slot
+            .split_once('.')
+            .with_context(|| format!("expected <device number>.<function>, got '{slot}'"))?;
+        let device_number = u8::from_str_radix(device_number, 16)
+            .ok()
+            .filter(|&n| n < 32)
+            .with_context(|| format!("invalid device number '{device_number}'"))?;
+        let function = function
+            .parse()
+            .ok()
+            .filter(|&f| f < 8)
+            .with_context(|| format!("invalid function '{function}'"))?;
+        Ok(Self {
+            device: device.to_owned(),
+            bus,
+            device_number,
+            function,
+        })
+    }
+}
+
+// <address>[,vtl2][,uh-nvme[=<nsid>]][,uh-mana]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VfioCli {
+    pub path: PathBuf,
+    pub vtl: DeviceVtl,
+    /// The NVMe namespaces for OpenHCL to relay to VTL0.
+    pub underhill_nvme_namespaces: Vec<u32>,
+    /// Whether OpenHCL should use the device as a MANA NIC for VTL0.
+    pub underhill_mana: bool,
+}
+
+impl FromStr for VfioCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut opts = s.split(',');
+        let path = opts.next().unwrap();
+        if path.is_empty() {
+            anyhow::bail!("missing device address");
+        }
+        let mut vtl = DeviceVtl::Vtl0;
+        let mut underhill_nvme_namespaces = Vec::new();
+        let mut underhill_mana = false;
+        for opt in opts {
+            match opt.split_once('=') {
+                None if opt == "vtl2" => vtl = DeviceVtl::Vtl2,
+                None if opt == "uh-nvme" => underhill_nvme_namespaces.push(1),
+                None if opt == "uh-mana" => underhill_mana = true,
+                Some(("uh-nvme", nsid)) => {
+                    let nsid = nsid
+                        .parse()
+                        .ok()
+                        .filter(|&nsid| nsid != 0)
+                        .with_context(|| format!("invalid namespace id '{nsid}'"))?;
+                    underhill_nvme_namespaces.push(nsid);
+                }
+                _ => anyhow::bail!("unknown option: '{opt}'"),
+            }
+        }
+        if !underhill_nvme_namespaces.is_empty() && underhill_mana {
+            anyhow::bail!("`uh-nvme` is incompatible with `uh-mana`");
+        }
+        if !underhill_nvme_namespaces.is_empty() || underhill_mana {
+            vtl = DeviceVtl::Vtl2;
+        }
+        Ok(Self {
+            path: path.into(),
+            vtl,
+            underhill_nvme_namespaces,
+            underhill_mana,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsbHostCli {
+    /// A device with the given vendor and product IDs.
+    Id { vendor_id: u16, product_id: u16 },
+    /// The device at the given bus path, e.g. `1-2.3`.
+    Path(String),
+}
+
+impl FromStr for UsbHostCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some((vendor_id, product_id)) = s.split_once(':') {
+            let parse = |id: &str| {
+                u16::from_str_radix(id, 16).with_context(|| format!("invalid id '{id}'"))
+            };
+            return Ok(Self::Id {
+                vendor_id: parse(vendor_id)?,
+                product_id: parse(product_id)?,
+            });
+        }
+        let valid = s.split_once('-').is_some_and(|(bus, ports)| {
+            bus.parse::<u8>().is_ok() && ports.split('.').all(|port| port.parse::<u8>().is_ok())
+        });
+        if !valid {
+            anyhow::bail!("expected <vid>:<pid> or <bus>-<port>[.<port>...]");
+        }
+        Ok(Self::Path(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationAddressCli {
+    /// A TCP host and port.
+    Tcp(String),
+    /// A file containing a saved VM.
+    File(PathBuf),
+}
+
+impl FromStr for MigrationAddressCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once(':') {
+            Some(("tcp", addr)) if !addr.is_empty() => Ok(Self::Tcp(addr.to_owned())),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(path.into())),
+            _ => anyhow::bail!("expected tcp:<host>:<port> or file:<path>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemPolicyCli {
+    /// Allocate guest RAM on first access.
+    Lazy,
+    /// Allocate all of guest RAM up front.
+    Prefault,
+    /// Interleave guest RAM across the given host NUMA nodes, or all online
+    /// nodes if empty.
+    Interleave(Vec<u32>),
+    /// Allocate guest RAM only from the given host NUMA nodes.
+    Bind(Vec<u32>),
+}
+
+impl FromStr for MemPolicyCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let r = match s.split_once(':') {
+            None if s == "lazy" => Self::Lazy,
+            None if s == "prefault" => Self::Prefault,
+            None if s == "interleave" => Self::Interleave(Vec::new()),
+            Some(("interleave", nodes)) => Self::Interleave(parse_index_list(nodes, "node")?),
+            Some(("bind", nodes)) => Self::Bind(parse_index_list(nodes, "node")?),
+            _ => anyhow::bail!("expected lazy, prefault, interleave[:NODES], or bind:NODES"),
+        };
+        Ok(r)
+    }
+}
+
+/// Parses a list of host CPUs, as comma-separated CPU numbers and ranges, e.g.
+/// `0-3,5`.
+pub fn parse_cpu_list(s: &str) -> anyhow::Result<Vec<u32>> {
+    parse_index_list(s, "cpu")
+}
+
+fn parse_index_list(s: &str, what: &str) -> anyhow::Result<Vec<u32>> {
+    let mut indexes = Vec::new();
+    for range in s.split(',') {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start: u32 = start
+            .parse()
+            .with_context(|| format!("invalid {what} '{start}'"))?;
+        let end: u32 = end
+            .parse()
+            .with_context(|| format!("invalid {what} '{end}'"))?;
+        if start > end {
+            anyhow::bail!("invalid {what} range '{range}'");
+        }
+        indexes.extend(start..=end);
+    }
+    Ok(indexes)
+}
+
+fn parse_vcpu_pin(s: &str) -> anyhow::Result<(u32, Vec<u32>)> {
+    let (vp, cpus) = s.split_once(':').context("expected VP:CPUS")?;
+    Ok((
+        vp.parse().context("invalid vp index")?,
+        parse_cpu_list(cpus)?,
+    ))
+}
+
+fn parse_vsock_listen(s: &str) -> anyhow::Result<(u32, u32)> {
+    let (host_port, guest_port) = s.split_once(':').unwrap_or((s, s));
+    Ok((
+        host_port.parse().context("invalid host port")?,
+        guest_port.parse().context("invalid guest port")?,
+    ))
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum CpuModelCli {
+    Nehalem,
+    Sandybridge,
+    Haswell,
+    Skylake,
+    SkylakeServer,
+    IcelakeServer,
+}
+
+#[cfg_attr(not(guest_arch = "x86_64"), expect(dead_code))]
+fn parse_cpuid_set(s: &str) -> anyhow::Result<CpuidOverride> {
+    let parse_u32 = |s: &str| parse_number(s).ok().and_then(|v| u32::try_from(v).ok());
+    let (leaf, value) = s
+        .split_once('=')
+        .context("expected LEAF[:SUBLEAF]:REG=VALUE")?;
+    let mut parts = leaf.split(':').collect::<Vec<_>>();
+    let reg = parts.pop().context("missing register")?;
+    let (function, index) = match parts.as_slice() {
+        [function] => (*function, None),
+        [function, index] => (*function, Some(*index)),
+        _ => anyhow::bail!("expected LEAF[:SUBLEAF]:REG=VALUE"),
+    };
+    let reg = match reg {
+        "eax" => 0,
+        "ebx" => 1,
+        "ecx" => 2,
+        "edx" => 3,
+        _ => anyhow::bail!("invalid register '{reg}'"),
+    };
+    let mut result = [0; 4];
+    let mut mask = [0; 4];
+    result[reg] = parse_u32(value).context("invalid value")?;
+    mask[reg] = !0;
+    Ok(CpuidOverride {
+        function: parse_u32(function).context("invalid leaf")?,
+        index: index
+            .map(|index| parse_u32(index).context("invalid subleaf"))
+            .transpose()?,
+        result,
+        mask,
+    })
+}
+
 #[cfg_attr(not(guest_arch = "x86_64"), expect(dead_code))]
 fn parse_x2apic(s: &str) -> Result<X2ApicConfig, &'static str> {
     let r = match s {
@@ -1350,65 +2888,276 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
-pub enum UefiConsoleModeCli {
-    Default,
-    Com1,
-    Com2,
-    None,
+#[derive(Debug, Clone, PartialEq)]
+pub enum UefiBootDevice {
+    Disk,
+    Dvd,
+    Network,
+    File(String),
 }
 
-/// Read a environment variable that may / may-not have a target-specific
-/// prefix. e.g: `default_value_from_arch_env("FOO")` would first try and read
-/// from `FOO`, and if that's not found, it will try `X86_64_FOO`.
-///
-/// Must return an `OsString`, in order to be compatible with `clap`'s
-/// default_value code. As such - to encode the absence of the env-var, an empty
-/// OsString is returned.
-fn default_value_from_arch_env(name: &str) -> OsString {
-    let prefix = if cfg!(guest_arch = "x86_64") {
-        "X86_64"
-    } else if cfg!(guest_arch = "aarch64") {
-        "AARCH64"
-    } else {
-        return Default::default();
-    };
-    let prefixed = format!("{}_{}", prefix, name);
-    std::env::var_os(name)
-        .or_else(|| std::env::var_os(prefixed))
-        .unwrap_or_default()
-}
+#[derive(Debug, Clone, PartialEq)]
+pub struct UefiBootOrderCli(pub Vec<UefiBootDevice>);
 
-/// Workaround to use `Option<PathBuf>` alongside [`default_value_from_arch_env`]
-#[derive(Clone)]
-pub struct OptionalPathBuf(pub Option<PathBuf>);
+impl FromStr for UefiBootOrderCli {
+    type Err = &'static str;
 
-impl From<&std::ffi::OsStr> for OptionalPathBuf {
-    fn from(s: &std::ffi::OsStr) -> Self {
-        OptionalPathBuf(if s.is_empty() { None } else { Some(s.into()) })
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut order = Vec::new();
+        for item in s.split(',') {
+            let device = match item {
+                "disk" => UefiBootDevice::Disk,
+                "dvd" => UefiBootDevice::Dvd,
+                "net" => UefiBootDevice::Network,
+                _ => match item.strip_prefix("file:") {
+                    Some("") => return Err("missing file path"),
+                    Some(path) => UefiBootDevice::File(path.to_owned()),
+                    None => return Err("unknown boot device type"),
+                },
+            };
+            if order.contains(&device) {
+                return Err("cannot pass duplicate boot devices");
+            }
+            order.push(device);
+        }
+        Ok(Self(order))
     }
 }
 
-#[cfg(test)]
-// UNSAFETY: Needed to set and remove environment variables in tests
-#[expect(unsafe_code)]
-mod tests {
-    use super::*;
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum SleepStateCli {
+    S3,
+    S4,
+}
 
-    fn with_env_var<F, R>(name: &str, value: &str, f: F) -> R
-    where
-        F: FnOnce() -> R,
-    {
-        // SAFETY:
-        // Safe in a testing context because it won't be changed concurrently
-        unsafe {
-            std::env::set_var(name, value);
-        }
-        let result = f();
-        // SAFETY:
-        // Safe in a testing context because it won't be changed concurrently
-        unsafe {
-            std::env::remove_var(name);
+impl Options {
+    /// Returns whether the guest may enter `state`, from
+    /// `--allow-sleep-states` or the deprecated `--s3` and `--s4`.
+    pub fn sleep_state_allowed(&self, state: SleepStateCli) -> bool {
+        match &self.allow_sleep_states {
+            Some(states) => states.contains(&state),
+            None => match state {
+                SleepStateCli::S3 => self.s3,
+                SleepStateCli::S4 => self.s4,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum PcrBankCli {
+    Sha1,
+    Sha256,
+    Sha384,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TpmPcrSeedCli {
+    pub pcr: u32,
+    pub event_data: Vec<u8>,
+}
+
+impl FromStr for TpmPcrSeedCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pcr, data) = s.split_once('=').context("expected INDEX=DATA")?;
+        let pcr = pcr.parse().context("invalid PCR index")?;
+        if pcr > 23 {
+            anyhow::bail!("PCR index must be between 0 and 23");
+        }
+        let event_data = hex::decode(data).context("invalid hex event data")?;
+        if event_data.is_empty() || event_data.len() > 1024 {
+            anyhow::bail!("event data must be between 1 and 1024 bytes");
+        }
+        Ok(Self { pcr, event_data })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecureBootDbxCli {
+    Cert(PathBuf),
+    Sha256([u8; 32]),
+}
+
+impl FromStr for SecureBootDbxCli {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(digest) = s.strip_prefix("sha256:") {
+            let digest = hex::decode(digest)
+                .ok()
+                .and_then(|d| d.try_into().ok())
+                .ok_or("invalid sha256 digest (expected 64 hex digits)")?;
+            Ok(Self::Sha256(digest))
+        } else {
+            Ok(Self::Cert(s.into()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmbiosCli {
+    Uuid(guid::Guid),
+    Manufacturer(String),
+    Product(String),
+    Version(String),
+    Sku(String),
+    Family(String),
+    Serial(String),
+    BaseBoardSerial(String),
+    ChassisSerial(String),
+    ChassisAssetTag(String),
+    Oem(String),
+}
+
+impl FromStr for SmbiosCli {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or("invalid format (expected KEY=VALUE)")?;
+        let value = value.to_owned();
+        Ok(match key {
+            "uuid" => Self::Uuid(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid uuid: {value}"))?,
+            ),
+            "manufacturer" => Self::Manufacturer(value),
+            "product" => Self::Product(value),
+            "version" => Self::Version(value),
+            "sku" => Self::Sku(value),
+            "family" => Self::Family(value),
+            "serial" => Self::Serial(value),
+            "baseboard-serial" => Self::BaseBoardSerial(value),
+            "chassis-serial" => Self::ChassisSerial(value),
+            "chassis-asset-tag" => Self::ChassisAssetTag(value),
+            "oem" => Self::Oem(value),
+            key => return Err(format!("unknown smbios key: {key}")),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RtcBaseCli {
+    /// A fixed point in time.
+    Time(jiff::Timestamp),
+    /// An offset from the current time.
+    Now(jiff::Span),
+}
+
+impl FromStr for RtcBaseCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let Some(offset) = s.strip_prefix("now") else {
+            return Ok(Self::Time(s.parse().context("invalid timestamp")?));
+        };
+        let span = if let Some(span) = offset.strip_prefix('+') {
+            span.parse().context("invalid span")?
+        } else if let Some(span) = offset.strip_prefix('-') {
+            span.parse::<jiff::Span>().context("invalid span")?.negate()
+        } else if offset.is_empty() {
+            jiff::Span::new()
+        } else {
+            anyhow::bail!("expected now, now+SPAN, or now-SPAN");
+        };
+        Ok(Self::Now(span))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum ClockPolicyCli {
+    /// Guest time stops while the VM is paused, so the guest's clock falls
+    /// behind by the length of the pause.
+    Freeze,
+    /// Guest time stops while the VM is paused, but on resume the guest's
+    /// clock is stepped to the host's via the timesync IC (requires --hv).
+    Resync,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum GuestCrashActionCli {
+    /// Log the crash and leave the VM running.
+    None,
+    /// Pause the VM.
+    Pause,
+    /// Pause the VM and write its memory and registers to a new file in
+    /// --guest-crash-dump-dir.
+    Dump,
+    /// Reset the VM.
+    Restart,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum GuestCrashDumpFormatCli {
+    /// An ELF core file, as written by QEMU's dump-guest-memory.
+    Elf,
+    /// A Windows complete memory dump (x86_64 only).
+    Windows,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum UefiConsoleModeCli {
+    Default,
+    Com1,
+    Com2,
+    None,
+}
+
+/// Read a environment variable that may / may-not have a target-specific
+/// prefix. e.g: `default_value_from_arch_env("FOO")` would first try and read
+/// from `FOO`, and if that's not found, it will try `X86_64_FOO`.
+///
+/// Must return an `OsString`, in order to be compatible with `clap`'s
+/// default_value code. As such - to encode the absence of the env-var, an empty
+/// OsString is returned.
+fn default_value_from_arch_env(name: &str) -> OsString {
+    let prefix = if cfg!(guest_arch = "x86_64") {
+        "X86_64"
+    } else if cfg!(guest_arch = "aarch64") {
+        "AARCH64"
+    } else {
+        return Default::default();
+    };
+    let prefixed = format!("{}_{}", prefix, name);
+    std::env::var_os(name)
+        .or_else(|| std::env::var_os(prefixed))
+        .unwrap_or_default()
+}
+
+/// Workaround to use `Option<PathBuf>` alongside [`default_value_from_arch_env`]
+#[derive(Clone)]
+pub struct OptionalPathBuf(pub Option<PathBuf>);
+
+impl From<&std::ffi::OsStr> for OptionalPathBuf {
+    fn from(s: &std::ffi::OsStr) -> Self {
+        OptionalPathBuf(if s.is_empty() { None } else { Some(s.into()) })
+    }
+}
+
+#[cfg(test)]
+// UNSAFETY: Needed to set and remove environment variables in tests
+#[expect(unsafe_code)]
+mod tests {
+    use super::*;
+
+    fn with_env_var<F, R>(name: &str, value: &str, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // SAFETY:
+        // Safe in a testing context because it won't be changed concurrently
+        unsafe {
+            std::env::set_var(name, value);
+        }
+        let result = f();
+        // SAFETY:
+        // Safe in a testing context because it won't be changed concurrently
+        unsafe {
+            std::env::remove_var(name);
         }
         result
     }
@@ -1430,6 +3179,64 @@ fn test_parse_file_disk_with_create() {
         }
     }
 
+    #[test]
+    fn test_parse_vhdx_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("vhdx:test.vhdx").unwrap(),
+            DiskCliKind::Vhdx {
+                path: PathBuf::from("test.vhdx"),
+                create_with_len: None,
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("vhdx:test.vhdx;create=64G").unwrap(),
+            DiskCliKind::Vhdx {
+                path: PathBuf::from("test.vhdx"),
+                create_with_len: Some(64 * 1024 * 1024 * 1024),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_vhd_set_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("vhds:disk.vhds").unwrap(),
+            DiskCliKind::VhdSet {
+                path: PathBuf::from("disk.vhds"),
+                checkpoint: None,
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("vhds:disk.vhds;checkpoint=1f2e3d4c-5b6a-7980-91a2-b3c4d5e6f708")
+                .unwrap(),
+            DiskCliKind::VhdSet {
+                path: PathBuf::from("disk.vhds"),
+                checkpoint: Some(guid::guid!("1f2e3d4c-5b6a-7980-91a2-b3c4d5e6f708")),
+            }
+        );
+
+        assert!(DiskCliKind::from_str("vhds:disk.vhds;checkpoint=bogus").is_err());
+        assert!(DiskCliKind::from_str("vhds:disk.vhds;create=1G").is_err());
+    }
+
+    #[test]
+    fn test_parse_blob_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("blob:vhd1:https://example.com/disk.vhd").unwrap(),
+            DiskCliKind::Blob {
+                kind: BlobKind::Vhd1,
+                url: "https://example.com/disk.vhd".to_string(),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("blob:page-flat:https://example.com/disk.img").unwrap(),
+            DiskCliKind::Blob {
+                kind: BlobKind::PageFlat,
+                url: "https://example.com/disk.img".to_string(),
+            }
+        );
+    }
+
     #[test]
     fn test_parse_direct_file_with_create() {
         let s = "test.vhd;create=1G";
@@ -1553,6 +3360,122 @@ fn test_parse_sqlite_diff_disk() {
         }
     }
 
+    #[test]
+    fn test_parse_crypt_disk() {
+        let disk = DiskCliKind::from_str("crypt:xts-aes-256:key.bin:file:disk.img").unwrap();
+        assert!(matches!(
+            disk,
+            DiskCliKind::Crypt {
+                cipher: DiskCipher::XtsAes256,
+                key: DiskKey::File(path),
+                disk: _,
+            } if path == PathBuf::from("key.bin")
+        ));
+
+        let disk = DiskCliKind::from_str("crypt:luks2:prompt:file:disk.img").unwrap();
+        assert!(matches!(
+            disk,
+            DiskCliKind::Crypt {
+                cipher: DiskCipher::Luks2,
+                key: DiskKey::Prompt,
+                disk: _,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_zstd_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("zstd:disk.img.zst").unwrap(),
+            DiskCliKind::Zstd(PathBuf::from("disk.img.zst"))
+        );
+    }
+
+    #[test]
+    fn test_parse_verity_disk() {
+        let disk = DiskCliKind::from_str("verity:disk.hashtree:00ff:file:disk.img").unwrap();
+        assert!(matches!(
+            disk,
+            DiskCliKind::Verity {
+                hash_tree,
+                root_hash,
+                disk: _,
+            } if hash_tree == PathBuf::from("disk.hashtree") && root_hash == [0x00, 0xff]
+        ));
+
+        assert!(DiskCliKind::from_str("verity:disk.hashtree:xyz:file:disk.img").is_err());
+        assert!(DiskCliKind::from_str("verity:disk.hashtree:00ff").is_err());
+    }
+
+    #[test]
+    fn test_parse_shared_pr_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("prshared:pr.json:file:disk.img").unwrap(),
+            DiskCliKind::SharedPersistentReservations {
+                path: PathBuf::from("pr.json"),
+                host_id: None,
+                disk: Box::new(DiskCliKind::File {
+                    path: PathBuf::from("disk.img"),
+                    create_with_len: None,
+                }),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("prshared:pr.json;host=0x1234:mem:1G").unwrap(),
+            DiskCliKind::SharedPersistentReservations {
+                path: PathBuf::from("pr.json"),
+                host_id: Some(0x1234),
+                disk: Box::new(DiskCliKind::Memory(1024 * 1024 * 1024)),
+            }
+        );
+
+        assert!(DiskCliKind::from_str("prshared:pr.json;id=1:mem:1G").is_err());
+        assert!(DiskCliKind::from_str("prshared:pr.json").is_err());
+    }
+
+    #[test]
+    fn test_parse_writeback_cache_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("cache:64M:file:disk.img").unwrap(),
+            DiskCliKind::WriteBackCache {
+                cache_size: 64 * 1024 * 1024,
+                flush_interval_ms: None,
+                disk: Box::new(DiskCliKind::File {
+                    path: PathBuf::from("disk.img"),
+                    create_with_len: None,
+                }),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("cache:1G;flush=500:mem:1G").unwrap(),
+            DiskCliKind::WriteBackCache {
+                cache_size: 1024 * 1024 * 1024,
+                flush_interval_ms: Some(500),
+                disk: Box::new(DiskCliKind::Memory(1024 * 1024 * 1024)),
+            }
+        );
+
+        assert!(DiskCliKind::from_str("cache:64M;sync:file:disk.img").is_err());
+        assert!(DiskCliKind::from_str("cache:64M").is_err());
+    }
+
+    #[test]
+    fn test_parse_multipath_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("multipath:file:a.img|mem:1G").unwrap(),
+            DiskCliKind::Multipath(vec![
+                DiskCliKind::File {
+                    path: PathBuf::from("a.img"),
+                    create_with_len: None,
+                },
+                DiskCliKind::Memory(1024 * 1024 * 1024),
+            ])
+        );
+
+        assert!(DiskCliKind::from_str("multipath:file:a.img").is_err());
+        assert!(DiskCliKind::from_str("multipath:file:a.img|bogus:x").is_err());
+    }
+
     #[test]
     fn test_parse_autocache_sqlite_disk() {
         // Test with environment variable set
@@ -1606,6 +3529,9 @@ fn test_parse_errors() {
         // Invalid format for crypt (missing parts)
         assert!(DiskCliKind::from_str("crypt:xts-aes-256:key.bin").is_err());
 
+        // Passphrase prompt with a raw cipher
+        assert!(DiskCliKind::from_str("crypt:xts-aes-256:prompt:file:disk.vhd").is_err());
+
         // Invalid disk kind
         assert!(DiskCliKind::from_str("invalid:path").is_err());
 
@@ -1655,15 +3581,47 @@ fn test_serial_config_from_str() {
             SerialConfigCli::from_str("stderr").unwrap(),
             SerialConfigCli::Stderr
         );
+        assert_eq!(
+            SerialConfigCli::from_str("pty").unwrap(),
+            SerialConfigCli::Pty
+        );
 
         // Test file config
         let file_config = SerialConfigCli::from_str("file=/path/to/file").unwrap();
-        if let SerialConfigCli::File(path) = file_config {
+        if let SerialConfigCli::File(path, options) = file_config {
             assert_eq!(path.to_str().unwrap(), "/path/to/file");
+            assert_eq!(options, SerialFileOptions::default());
         } else {
             panic!("Expected File variant");
         }
 
+        // Test file config with rotation and timestamps
+        assert_eq!(
+            SerialConfigCli::from_str("file=/path/to/file,maxsize=10M,rotate=3,timestamp").unwrap(),
+            SerialConfigCli::File(
+                "/path/to/file".into(),
+                SerialFileOptions {
+                    max_size: Some(10 * 1024 * 1024),
+                    rotate: 3,
+                    timestamp: true,
+                }
+            )
+        );
+        assert_eq!(
+            SerialConfigCli::from_str("file=/path/to/file,maxsize=4096").unwrap(),
+            SerialConfigCli::File(
+                "/path/to/file".into(),
+                SerialFileOptions {
+                    max_size: Some(4096),
+                    rotate: 1,
+                    timestamp: false,
+                }
+            )
+        );
+        assert!(SerialConfigCli::from_str("file=/path/to/file,rotate=2").is_err());
+        assert!(SerialConfigCli::from_str("file=/path/to/file,maxsize=big").is_err());
+        assert!(SerialConfigCli::from_str("file=/path/to/file,bogus").is_err());
+
         // Test term config with name
         match SerialConfigCli::from_str("term=/dev/pts/0,name=MyTerm").unwrap() {
             SerialConfigCli::NewConsole(Some(path), Some(name)) => {
@@ -1689,6 +3647,33 @@ fn test_serial_config_from_str() {
             _ => panic!("Expected Tcp variant"),
         }
 
+        // Test websocket config
+        match SerialConfigCli::from_str("listen=ws:127.0.0.1:8080").unwrap() {
+            SerialConfigCli::WebSocket(addr) => {
+                assert_eq!(addr.to_string(), "127.0.0.1:8080");
+            }
+            _ => panic!("Expected WebSocket variant"),
+        }
+        assert!(SerialConfigCli::from_str("listen=ws:localhost").is_err());
+
+        // Test TCP connect config
+        assert_eq!(
+            SerialConfigCli::from_str("connect=tcp:127.0.0.1:1234").unwrap(),
+            SerialConfigCli::TcpConnect {
+                addr: "127.0.0.1:1234".parse().unwrap(),
+                retry: Duration::from_secs(1),
+            }
+        );
+        assert_eq!(
+            SerialConfigCli::from_str("connect=tcp:127.0.0.1:1234,retry=5").unwrap(),
+            SerialConfigCli::TcpConnect {
+                addr: "127.0.0.1:1234".parse().unwrap(),
+                retry: Duration::from_secs(5),
+            }
+        );
+        assert!(SerialConfigCli::from_str("connect=/path/to/pipe").is_err());
+        assert!(SerialConfigCli::from_str("connect=tcp:127.0.0.1:1234,retry=x").is_err());
+
         // Test pipe config
         match SerialConfigCli::from_str("listen=/path/to/pipe").unwrap() {
             SerialConfigCli::Pipe(path) => {
@@ -1704,6 +3689,60 @@ fn test_serial_config_from_str() {
         assert!(SerialConfigCli::from_str("listen").is_err());
     }
 
+    #[test]
+    fn test_extra_serial_config_from_str() {
+        assert_eq!(
+            ExtraSerialConfigCli::from_str("5:console").unwrap(),
+            ExtraSerialConfigCli {
+                index: 5,
+                isa: None,
+                serial: SerialConfigCli::Console,
+            }
+        );
+        assert_eq!(
+            ExtraSerialConfigCli::from_str("6,io=0x2e0,irq=7:listen=tcp:127.0.0.1:1234").unwrap(),
+            ExtraSerialConfigCli {
+                index: 6,
+                isa: Some((0x2e0, 7)),
+                serial: SerialConfigCli::Tcp("127.0.0.1:1234".parse().unwrap()),
+            }
+        );
+
+        // Test error cases
+        assert!(ExtraSerialConfigCli::from_str("console").is_err());
+        assert!(ExtraSerialConfigCli::from_str("4:console").is_err());
+        assert!(ExtraSerialConfigCli::from_str("5,io=0x2e0:console").is_err());
+        assert!(ExtraSerialConfigCli::from_str("5,io=0x12345,irq=7:console").is_err());
+        assert!(ExtraSerialConfigCli::from_str("5,bogus=1:console").is_err());
+        assert!(ExtraSerialConfigCli::from_str("5:bogus").is_err());
+    }
+
+    #[test]
+    fn test_debugcon_config_from_str() {
+        let cfg = DebugconSerialConfigCli::from_str("0xe9,stderr").unwrap();
+        assert_eq!(cfg.port, 0xe9);
+        assert_eq!(cfg.serial, SerialConfigCli::Stderr);
+        assert!(cfg.trace.is_none());
+
+        let cfg = DebugconSerialConfigCli::from_str("0x402,trace").unwrap();
+        assert_eq!(cfg.port, 0x402);
+        assert_eq!(cfg.serial, SerialConfigCli::None);
+        let trace = cfg.trace.unwrap();
+        assert_eq!(trace.level, DebugconTraceLevel::Info);
+        assert_eq!(trace.target, "debugcon");
+
+        let cfg = DebugconSerialConfigCli::from_str("0xe9,trace=debug,target=ovmf").unwrap();
+        let trace = cfg.trace.unwrap();
+        assert_eq!(trace.level, DebugconTraceLevel::Debug);
+        assert_eq!(trace.target, "ovmf");
+
+        // Test error cases
+        assert!(DebugconSerialConfigCli::from_str("0xe9").is_err());
+        assert!(DebugconSerialConfigCli::from_str("0xe9,trace=loud").is_err());
+        assert!(DebugconSerialConfigCli::from_str("0xe9,trace,bogus=1").is_err());
+        assert!(DebugconSerialConfigCli::from_str("0xe9,tracing").is_err());
+    }
+
     #[test]
     fn test_endpoint_config_from_str() {
         // Test none
@@ -1714,18 +3753,74 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme without cidr
         match EndpointConfigCli::from_str("consomme").unwrap() {
-            EndpointConfigCli::Consomme { cidr: None } => (),
+            EndpointConfigCli::Consomme { cidr: None, dhcp } => {
+                assert_eq!(dhcp, ConsommeDhcpOptions::default());
+            }
             _ => panic!("Expected Consomme variant without cidr"),
         }
 
         // Test consomme with cidr
         match EndpointConfigCli::from_str("consomme:192.168.0.0/24").unwrap() {
-            EndpointConfigCli::Consomme { cidr: Some(cidr) } => {
+            EndpointConfigCli::Consomme {
+                cidr: Some(cidr), ..
+            } => {
                 assert_eq!(cidr, "192.168.0.0/24");
             }
             _ => panic!("Expected Consomme variant with cidr"),
         }
 
+        // Test consomme with dhcp options
+        match EndpointConfigCli::from_str(
+            "consomme:192.168.0.0/24,dns=1.1.1.1,dns=8.8.8.8,search=corp.example.com,\
+             lease=00:15:5d:12:34:56@192.168.0.50,tftp=192.168.0.1,bootfile=pxelinux.0",
+        )
+        .unwrap()
+        {
+            EndpointConfigCli::Consomme { cidr, dhcp } => {
+                assert_eq!(cidr.as_deref(), Some("192.168.0.0/24"));
+                assert_eq!(
+                    dhcp.dns_servers,
+                    ["1.1.1.1".parse().unwrap(), "8.8.8.8".parse().unwrap()]
+                );
+                assert_eq!(dhcp.search_domains, ["corp.example.com"]);
+                assert_eq!(
+                    dhcp.static_leases,
+                    [ConsommeStaticLease {
+                        mac: "00:15:5d:12:34:56".parse().unwrap(),
+                        ip: "192.168.0.50".parse().unwrap(),
+                    }]
+                );
+                assert_eq!(dhcp.tftp_server.as_deref(), Some("192.168.0.1"));
+                assert_eq!(dhcp.boot_file.as_deref(), Some("pxelinux.0"));
+            }
+            _ => panic!("Expected Consomme variant with dhcp options"),
+        }
+
+        // Test consomme with dhcp options but no cidr
+        match EndpointConfigCli::from_str("consomme:dns=10.0.0.53").unwrap() {
+            EndpointConfigCli::Consomme { cidr: None, dhcp } => {
+                assert_eq!(
+                    dhcp.dns_servers,
+                    ["10.0.0.53".parse::<std::net::Ipv4Addr>().unwrap()]
+                );
+            }
+            _ => panic!("Expected Consomme variant without cidr"),
+        }
+
+        // Test consomme with a network boot directory
+        match EndpointConfigCli::from_str("consomme:pxe=/srv/tftp,bootfile=bootx64.efi").unwrap() {
+            EndpointConfigCli::Consomme { cidr: None, dhcp } => {
+                assert_eq!(dhcp.boot_dir.as_deref(), Some("/srv/tftp"));
+                assert_eq!(dhcp.boot_file.as_deref(), Some("bootx64.efi"));
+            }
+            _ => panic!("Expected Consomme variant with boot directory"),
+        }
+
+        assert!(EndpointConfigCli::from_str("consomme:dns=bogus").is_err());
+        assert!(EndpointConfigCli::from_str("consomme:lease=00:15:5d:12:34:56").is_err());
+        assert!(EndpointConfigCli::from_str("consomme:search=a..b").is_err());
+        assert!(EndpointConfigCli::from_str("consomme:bogus=1").is_err());
+
         // Test dio without id
         match EndpointConfigCli::from_str("dio").unwrap() {
             EndpointConfigCli::Dio { id: None } => (),
@@ -1748,6 +3843,14 @@ fn test_endpoint_config_from_str() {
             _ => panic!("Expected Tap variant"),
         }
 
+        // Test macvtap
+        match EndpointConfigCli::from_str("macvtap:macvtap0").unwrap() {
+            EndpointConfigCli::Macvtap { name } => {
+                assert_eq!(name, "macvtap0");
+            }
+            _ => panic!("Expected Macvtap variant"),
+        }
+
         // Test error case
         assert!(EndpointConfigCli::from_str("invalid").is_err());
     }
@@ -1781,6 +3884,34 @@ fn test_nic_config_from_str() {
         // Test error cases
         assert!(NicConfigCli::from_str("queues=invalid:none").is_err());
         assert!(NicConfigCli::from_str("uh:vtl2:none").is_err()); // uh incompatible with vtl2
+
+        // Test with a fixed MAC address
+        let config = NicConfigCli::from_str("mac=00:15:5d:01:02:03:uh:none").unwrap();
+        assert_eq!(
+            config.mac_address,
+            Some(MacAddress::new([0x00, 0x15, 0x5d, 0x01, 0x02, 0x03]))
+        );
+        assert!(config.underhill);
+        let config = NicConfigCli::from_str("vtl2:mac=00-15-5d-01-02-03:none").unwrap();
+        assert_eq!(
+            config.mac_address,
+            Some(MacAddress::new([0x00, 0x15, 0x5d, 0x01, 0x02, 0x03]))
+        );
+        assert!(NicConfigCli::from_str("mac=00:15:5d:01:02:none").is_err());
+        assert!(NicConfigCli::from_str("mac=01:00:5e:00:00:01:none").is_err()); // multicast
+
+        // Test with traffic shaping
+        let config = NicConfigCli::from_str("rate=10:latency=50:loss=2.5:consomme").unwrap();
+        assert_eq!(config.rate_mbps, Some(10));
+        assert_eq!(config.latency_ms, Some(50));
+        assert_eq!(config.loss_percent, Some(2.5));
+        assert!(matches!(
+            config.endpoint,
+            EndpointConfigCli::Consomme { .. }
+        ));
+        assert!(NicConfigCli::from_str("rate=0:none").is_err());
+        assert!(NicConfigCli::from_str("loss=101:none").is_err());
+        assert!(NicConfigCli::from_str("latency=-1:none").is_err());
     }
 
     #[test]
@@ -1797,6 +3928,293 @@ fn test_smt_config_from_str() {
         assert!(SmtConfigCli::from_str("").is_err());
     }
 
+    #[test]
+    fn test_rtc_base_from_str() {
+        let RtcBaseCli::Time(time) = RtcBaseCli::from_str("2030-01-01T00:00:00Z").unwrap() else {
+            panic!("expected time")
+        };
+        assert_eq!(time.as_second(), 1893456000);
+        let RtcBaseCli::Now(span) = RtcBaseCli::from_str("now").unwrap() else {
+            panic!("expected now")
+        };
+        assert!(span.is_zero());
+        let RtcBaseCli::Now(span) = RtcBaseCli::from_str("now-30d").unwrap() else {
+            panic!("expected now")
+        };
+        assert_eq!(span.get_days(), -30);
+        let RtcBaseCli::Now(span) = RtcBaseCli::from_str("now+1y6mo").unwrap() else {
+            panic!("expected now")
+        };
+        assert_eq!((span.get_years(), span.get_months()), (1, 6));
+
+        assert!(RtcBaseCli::from_str("2030-01-01").is_err()); // no offset
+        assert!(RtcBaseCli::from_str("now*2").is_err());
+        assert!(RtcBaseCli::from_str("now+forever").is_err());
+    }
+
+    #[cfg(guest_arch = "x86_64")]
+    #[test]
+    fn test_parse_cpuid_set() {
+        let leaf = parse_cpuid_set("0x7:0:ebx=0x20").unwrap();
+        assert_eq!(
+            (leaf.function, leaf.index, leaf.result, leaf.mask),
+            (7, Some(0), [0, 0x20, 0, 0], [0, !0, 0, 0])
+        );
+        let leaf = parse_cpuid_set("0x80000001:edx=0").unwrap();
+        assert_eq!(
+            (leaf.function, leaf.index, leaf.mask),
+            (0x80000001, None, [0, 0, 0, !0])
+        );
+
+        assert!(parse_cpuid_set("0x7:0:ebx").is_err());
+        assert!(parse_cpuid_set("0x7:0:esi=1").is_err());
+        assert!(parse_cpuid_set("0x7:0:0:ebx=1").is_err());
+        assert!(parse_cpuid_set("ebx=1").is_err());
+        assert!(parse_cpuid_set("1:eax=0x100000000").is_err());
+    }
+
+    #[test]
+    fn test_parse_vcpu_pin() {
+        assert_eq!(parse_vcpu_pin("0:2").unwrap(), (0, vec![2]));
+        assert_eq!(parse_vcpu_pin("3:0-2,8").unwrap(), (3, vec![0, 1, 2, 8]));
+
+        assert!(parse_vcpu_pin("0").is_err());
+        assert!(parse_vcpu_pin("0:").is_err());
+        assert!(parse_vcpu_pin("0:3-1").is_err());
+        assert!(parse_vcpu_pin("x:1").is_err());
+    }
+
+    #[test]
+    fn test_memory_backing_from_str() {
+        assert_eq!(
+            MemoryBackingCli::from_str("hugetlb").unwrap(),
+            MemoryBackingCli::Hugetlb(0x200000)
+        );
+        assert_eq!(
+            MemoryBackingCli::from_str("hugetlb:2M").unwrap(),
+            MemoryBackingCli::Hugetlb(0x200000)
+        );
+        assert_eq!(
+            MemoryBackingCli::from_str("hugetlb:1GB").unwrap(),
+            MemoryBackingCli::Hugetlb(0x40000000)
+        );
+
+        assert_eq!(
+            MemoryBackingCli::from_str("file:/dev/shm/vm,shared").unwrap(),
+            MemoryBackingCli::File {
+                path: "/dev/shm/vm".into(),
+                shared: true
+            }
+        );
+        assert_eq!(
+            MemoryBackingCli::from_str("file:template.bin").unwrap(),
+            MemoryBackingCli::File {
+                path: "template.bin".into(),
+                shared: false
+            }
+        );
+
+        assert!(MemoryBackingCli::from_str("hugetlb:4K").is_err());
+        assert!(MemoryBackingCli::from_str("hugetlb:").is_err());
+        assert!(MemoryBackingCli::from_str("file:").is_err());
+        assert!(MemoryBackingCli::from_str("thp").is_err());
+    }
+
+    #[test]
+    fn test_migration_address_from_str() {
+        assert_eq!(
+            MigrationAddressCli::from_str("tcp:10.0.0.2:4444").unwrap(),
+            MigrationAddressCli::Tcp("10.0.0.2:4444".into())
+        );
+        assert_eq!(
+            MigrationAddressCli::from_str("tcp:[::1]:4444").unwrap(),
+            MigrationAddressCli::Tcp("[::1]:4444".into())
+        );
+        assert_eq!(
+            MigrationAddressCli::from_str("file:/tmp/template.vm").unwrap(),
+            MigrationAddressCli::File("/tmp/template.vm".into())
+        );
+        assert!(MigrationAddressCli::from_str("tcp:").is_err());
+        assert!(MigrationAddressCli::from_str("file:").is_err());
+        assert!(MigrationAddressCli::from_str("unix:/tmp/migrate").is_err());
+    }
+
+    #[test]
+    fn test_audio_from_str() {
+        assert_eq!(AudioCli::from_str("null").unwrap(), AudioCli::Null);
+        assert_eq!(
+            AudioCli::from_str("wav:/tmp/out.wav").unwrap(),
+            AudioCli::Wav("/tmp/out.wav".into())
+        );
+        assert_eq!(AudioCli::from_str("pulse").unwrap(), AudioCli::Pulse(None));
+        assert_eq!(
+            AudioCli::from_str("pulse:speakers").unwrap(),
+            AudioCli::Pulse(Some("speakers".into()))
+        );
+        assert!(AudioCli::from_str("wav:").is_err());
+        assert!(AudioCli::from_str("pulse:").is_err());
+        assert!(AudioCli::from_str("host").is_err());
+    }
+
+    #[test]
+    fn test_ivshmem_from_str() {
+        assert_eq!(
+            IvshmemCli::from_str("/dev/shm/x,16M").unwrap(),
+            IvshmemCli {
+                path: "/dev/shm/x".into(),
+                size: 16 << 20,
+                doorbell: None,
+            }
+        );
+        assert_eq!(
+            IvshmemCli::from_str("shm.bin,4K,doorbell=/tmp/ivshmem.sock").unwrap(),
+            IvshmemCli {
+                path: "shm.bin".into(),
+                size: 0x1000,
+                doorbell: Some("/tmp/ivshmem.sock".into()),
+            }
+        );
+        assert!(IvshmemCli::from_str("shm.bin").is_err());
+        assert!(IvshmemCli::from_str("shm.bin,3M").is_err());
+        assert!(IvshmemCli::from_str("shm.bin,2K").is_err());
+        assert!(IvshmemCli::from_str("shm.bin,4K,foo").is_err());
+        assert!(IvshmemCli::from_str(",4K").is_err());
+    }
+
+    #[test]
+    fn test_pci_slot_from_str() {
+        assert_eq!(
+            PciSlotCli::from_str("e1000-0=00:1f.7").unwrap(),
+            PciSlotCli {
+                device: "e1000-0".into(),
+                bus: 0,
+                device_number: 0x1f,
+                function: 7,
+            }
+        );
+        assert_eq!(
+            PciSlotCli::from_str("vfio-0000:01:00.0=05.1").unwrap(),
+            PciSlotCli {
+                device: "vfio-0000:01:00.0".into(),
+                bus: 0,
+                device_number: 5,
+                function: 1,
+            }
+        );
+        assert!(PciSlotCli::from_str("xhci").is_err());
+        assert!(PciSlotCli::from_str("=05.0").is_err());
+        assert_eq!(
+            PciSlotCli::from_str("xhci=01:05.0").unwrap(),
+            PciSlotCli {
+                device: "xhci".into(),
+                bus: 1,
+                device_number: 5,
+                function: 0,
+            }
+        );
+        assert!(PciSlotCli::from_str("xhci=100:05.0").is_err());
+        assert!(PciSlotCli::from_str("xhci=20.0").is_err());
+        assert!(PciSlotCli::from_str("xhci=05.8").is_err());
+        assert!(PciSlotCli::from_str("xhci=05").is_err());
+    }
+
+    #[test]
+    fn test_vfio_from_str() {
+        assert_eq!(
+            VfioCli::from_str("0000:01:00.0").unwrap(),
+            VfioCli {
+                path: "0000:01:00.0".into(),
+                vtl: DeviceVtl::Vtl0,
+                underhill_nvme_namespaces: Vec::new(),
+                underhill_mana: false,
+            }
+        );
+        assert_eq!(
+            VfioCli::from_str("01:00.0,uh-nvme,uh-nvme=3").unwrap(),
+            VfioCli {
+                path: "01:00.0".into(),
+                vtl: DeviceVtl::Vtl2,
+                underhill_nvme_namespaces: vec![1, 3],
+                underhill_mana: false,
+            }
+        );
+        assert_eq!(
+            VfioCli::from_str("01:00.0,uh-mana").unwrap().vtl,
+            DeviceVtl::Vtl2
+        );
+        assert!(VfioCli::from_str("01:00.0,uh-nvme=0").is_err());
+        assert!(VfioCli::from_str("01:00.0,uh-nvme,uh-mana").is_err());
+        assert!(VfioCli::from_str("01:00.0,vtl1").is_err());
+        assert!(VfioCli::from_str(",vtl2").is_err());
+    }
+
+    #[test]
+    fn test_usb_host_from_str() {
+        assert_eq!(
+            UsbHostCli::from_str("046d:c52b").unwrap(),
+            UsbHostCli::Id {
+                vendor_id: 0x046d,
+                product_id: 0xc52b
+            }
+        );
+        assert_eq!(
+            UsbHostCli::from_str("1-2.3").unwrap(),
+            UsbHostCli::Path("1-2.3".into())
+        );
+        assert_eq!(
+            UsbHostCli::from_str("3-1").unwrap(),
+            UsbHostCli::Path("3-1".into())
+        );
+
+        assert!(UsbHostCli::from_str("046d:").is_err());
+        assert!(UsbHostCli::from_str("10000:1").is_err());
+        assert!(UsbHostCli::from_str("1-").is_err());
+        assert!(UsbHostCli::from_str("usb1").is_err());
+    }
+
+    #[test]
+    fn test_mem_policy_from_str() {
+        assert_eq!(
+            MemPolicyCli::from_str("prefault").unwrap(),
+            MemPolicyCli::Prefault
+        );
+        assert_eq!(MemPolicyCli::from_str("lazy").unwrap(), MemPolicyCli::Lazy);
+        assert_eq!(
+            MemPolicyCli::from_str("interleave").unwrap(),
+            MemPolicyCli::Interleave(Vec::new())
+        );
+        assert_eq!(
+            MemPolicyCli::from_str("interleave:0,2").unwrap(),
+            MemPolicyCli::Interleave(vec![0, 2])
+        );
+        assert_eq!(
+            MemPolicyCli::from_str("bind:1-3").unwrap(),
+            MemPolicyCli::Bind(vec![1, 2, 3])
+        );
+
+        assert!(MemPolicyCli::from_str("bind").is_err());
+        assert!(MemPolicyCli::from_str("bind:").is_err());
+        assert!(MemPolicyCli::from_str("interleave:x").is_err());
+        assert!(MemPolicyCli::from_str("prefault:0").is_err());
+    }
+
+    #[test]
+    fn test_uefi_boot_order_from_str() {
+        let order = UefiBootOrderCli::from_str("dvd,file:/EFI/test.efi,disk").unwrap();
+        assert_eq!(
+            order.0,
+            [
+                UefiBootDevice::Dvd,
+                UefiBootDevice::File("/EFI/test.efi".into()),
+                UefiBootDevice::Disk,
+            ]
+        );
+
+        assert!(UefiBootOrderCli::from_str("invalid").is_err());
+        assert!(UefiBootOrderCli::from_str("file:").is_err());
+        assert!(UefiBootOrderCli::from_str("net,net").is_err()); // duplicate device
+    }
+
     #[test]
     fn test_pcat_boot_order_from_str() {
         // Test single device
@@ -1813,6 +4231,124 @@ fn test_pcat_boot_order_from_str() {
         assert!(PcatBootOrderCli::from_str("optical,optical").is_err()); // duplicate device
     }
 
+    #[test]
+    fn test_secure_boot_dbx_from_str() {
+        assert_eq!(
+            SecureBootDbxCli::from_str("revoked.pem").unwrap(),
+            SecureBootDbxCli::Cert("revoked.pem".into())
+        );
+        assert_eq!(
+            SecureBootDbxCli::from_str(&format!("sha256:{}", "ab".repeat(32))).unwrap(),
+            SecureBootDbxCli::Sha256([0xab; 32])
+        );
+        assert!(SecureBootDbxCli::from_str("sha256:abcd").is_err());
+        assert!(SecureBootDbxCli::from_str(&format!("sha256:{}", "zz".repeat(32))).is_err());
+    }
+
+    #[test]
+    fn test_tpm_pcr_seed_from_str() {
+        assert_eq!(
+            TpmPcrSeedCli::from_str("8=deadbeef").unwrap(),
+            TpmPcrSeedCli {
+                pcr: 8,
+                event_data: vec![0xde, 0xad, 0xbe, 0xef],
+            }
+        );
+        assert!(TpmPcrSeedCli::from_str("8").is_err());
+        assert!(TpmPcrSeedCli::from_str("24=00").is_err());
+        assert!(TpmPcrSeedCli::from_str("8=").is_err());
+        assert!(TpmPcrSeedCli::from_str("8=xyz").is_err());
+    }
+
+    #[test]
+    fn test_smbios_from_str() {
+        assert_eq!(
+            SmbiosCli::from_str("manufacturer=Contoso, Ltd.").unwrap(),
+            SmbiosCli::Manufacturer("Contoso, Ltd.".into())
+        );
+        assert_eq!(
+            SmbiosCli::from_str("serial=").unwrap(),
+            SmbiosCli::Serial(String::new())
+        );
+        assert_eq!(
+            SmbiosCli::from_str("uuid=1f2e3d4c-5b6a-7980-91a2-b3c4d5e6f708").unwrap(),
+            SmbiosCli::Uuid(guid::guid!("1f2e3d4c-5b6a-7980-91a2-b3c4d5e6f708"))
+        );
+
+        assert_eq!(
+            SmbiosCli::from_str("oem=key=value").unwrap(),
+            SmbiosCli::Oem("key=value".into())
+        );
+
+        assert!(SmbiosCli::from_str("uuid=nope").is_err());
+        assert!(SmbiosCli::from_str("asset=1").is_err());
+        assert!(SmbiosCli::from_str("product").is_err());
+    }
+
+    #[test]
+    fn test_disk_cli_throttle() {
+        let disk = DiskCli::from_str("file:disk.img,iops=500,bw=20,burst=1000").unwrap();
+        assert_eq!(
+            disk.kind,
+            DiskCliKind::Throttle {
+                iops: Some(500),
+                bytes_per_second: Some(20 * 1024 * 1024),
+                burst: Some(1000),
+                disk: Box::new(DiskCliKind::File {
+                    path: PathBuf::from("disk.img"),
+                    create_with_len: None,
+                }),
+            }
+        );
+
+        let disk = DiskCli::from_str("file:disk.img,ro").unwrap();
+        assert!(matches!(disk.kind, DiskCliKind::File { .. }));
+
+        assert!(DiskCli::from_str("file:disk.img,burst=10").is_err());
+        assert!(DiskCli::from_str("file:disk.img,iops").is_err());
+        assert!(DiskCli::from_str("file:disk.img,bw=fast").is_err());
+    }
+
+    #[test]
+    fn test_disk_cli_sector_size() {
+        let disk = DiskCli::from_str("file:disk.img,sector=4096").unwrap();
+        assert_eq!(
+            disk.kind,
+            DiskCliKind::SectorSize {
+                sector_size: Some(4096),
+                physical_sector_size: None,
+                disk: Box::new(DiskCliKind::File {
+                    path: PathBuf::from("disk.img"),
+                    create_with_len: None,
+                }),
+            }
+        );
+
+        let disk = IdeDiskCli::from_str("file:disk.img,physical-sector=4096").unwrap();
+        assert!(matches!(
+            disk.kind,
+            DiskCliKind::SectorSize {
+                sector_size: None,
+                physical_sector_size: Some(4096),
+                ..
+            }
+        ));
+
+        assert!(DiskCli::from_str("file:disk.img,sector=1024").is_err());
+        assert!(DiskCli::from_str("file:disk.img,sector=4096,physical-sector=512").is_err());
+        assert!(DiskCli::from_str("file:disk.img,physical-sector=3000").is_err());
+        assert!(IdeDiskCli::from_str("file:disk.img,sector=4096").is_err());
+    }
+
+    #[test]
+    fn test_parse_vsock_listen() {
+        assert_eq!(parse_vsock_listen("1234").unwrap(), (1234, 1234));
+        assert_eq!(parse_vsock_listen("1234:5678").unwrap(), (1234, 5678));
+        assert!(parse_vsock_listen("").is_err());
+        assert!(parse_vsock_listen("1234:").is_err());
+        assert!(parse_vsock_listen("abc:5678").is_err());
+    }
+
     #[test]
     fn test_floppy_disk_from_str() {
         // Test basic disk
@@ -1837,4 +4373,72 @@ fn test_floppy_disk_from_str() {
         assert!(FloppyDiskCli::from_str("").is_err());
         assert!(FloppyDiskCli::from_str("file:/path/to/floppy.img,invalid").is_err());
     }
+
+    #[test]
+    fn test_save_command() {
+        let opt =
+            Options::try_parse_from(["openvmm", "save", "--ttrpc", "vm.sock", "--file", "vm.snap"])
+                .unwrap();
+        let Some(Command::Save { ttrpc, file }) = opt.command else {
+            panic!("expected save command");
+        };
+        assert_eq!(ttrpc, PathBuf::from("vm.sock"));
+        assert_eq!(file, PathBuf::from("vm.snap"));
+
+        let opt = Options::try_parse_from(["openvmm", "--resume", "vm.snap"]).unwrap();
+        assert!(opt.command.is_none());
+        assert_eq!(opt.resume, Some(PathBuf::from("vm.snap")));
+
+        assert!(Options::try_parse_from(["openvmm", "save", "--file", "vm.snap"]).is_err());
+    }
+
+    #[test]
+    fn test_allow_sleep_states() {
+        let allowed = |args: &[&str]| {
+            let opt = Options::try_parse_from([&["openvmm"][..], args].concat()).unwrap();
+            [SleepStateCli::S3, SleepStateCli::S4].map(|state| opt.sleep_state_allowed(state))
+        };
+        assert_eq!(allowed(&[]), [false, false]);
+        assert_eq!(allowed(&["--allow-sleep-states"]), [true, true]);
+        assert_eq!(allowed(&["--allow-sleep-states=s3"]), [true, false]);
+        assert_eq!(allowed(&["--allow-sleep-states=s4,s3"]), [true, true]);
+        assert_eq!(allowed(&["--s4"]), [false, true]);
+        assert_eq!(allowed(&["--s3", "--s4"]), [true, true]);
+
+        // The deprecated flags can't be combined with the new one.
+        assert!(Options::try_parse_from(["openvmm", "--s3", "--allow-sleep-states=s4"]).is_err());
+        assert!(Options::try_parse_from(["openvmm", "--allow-sleep-states=s5"]).is_err());
+    }
+
+    #[test]
+    fn test_profile_from_str() {
+        assert_eq!(
+            ProfileCli::from_str("cpu:99").unwrap(),
+            ProfileCli::Cpu { hz: 99 }
+        );
+        assert_eq!(ProfileCli::from_str("heap").unwrap(), ProfileCli::Heap);
+        assert_eq!(
+            ProfileCli::from_str("latency").unwrap(),
+            ProfileCli::Latency
+        );
+        assert_eq!(ProfileCli::Cpu { hz: 99 }.to_string(), "cpu:99");
+
+        assert!(ProfileCli::from_str("cpu").is_err());
+        assert!(ProfileCli::from_str("cpu:0").is_err());
+        assert!(ProfileCli::from_str("heap:1").is_err());
+    }
+
+    #[test]
+    fn test_periodic_state_dump_from_str() {
+        assert_eq!(
+            PeriodicStateDumpCli::from_str("60:/tmp/dumps").unwrap(),
+            PeriodicStateDumpCli {
+                interval: Duration::from_secs(60),
+                dir: "/tmp/dumps".into(),
+            }
+        );
+        assert!(PeriodicStateDumpCli::from_str("60").is_err());
+        assert!(PeriodicStateDumpCli::from_str("0:/tmp").is_err());
+        assert!(PeriodicStateDumpCli::from_str("60:").is_err());
+    }
 }
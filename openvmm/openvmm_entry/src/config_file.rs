@@ -0,0 +1,437 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for loading the VM definition from a config file.
+//!
+//! A config file is a TOML (or, with a `.json` extension, JSON) table whose
+//! keys are the long names of [`Options`] arguments, written either
+//! `kebab-case` or `snake_case`. Flags take a boolean, arguments that can be
+//! repeated take an array, and all other arguments take a string or number:
+//!
+//! ```toml
+//! processors = 4
+//! memory = "4GB"
+//! uefi = true
+//! hv = true
+//! disk = ["file:os.vhdx", "file:data.vhdx,ro"]
+//! com1 = "console"
+//! ```
+//!
+//! The file's values are converted to arguments that are placed before the
+//! ones on the command line. Values on the command line add to repeated
+//! settings from the file. Any other setting from the file is dropped if the
+//! command line sets it or an argument that conflicts with it, and a flag from
+//! the file can be turned off with `--no-<flag>`.
+
+use crate::cli_args::Options;
+use anyhow::Context;
+use clap::ArgAction;
+use clap::ArgMatches;
+use clap::CommandFactory;
+use clap::Parser;
+use clap::parser::ValueSource;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The name of the argument specifying the config file.
+const CONFIG_ARG: &str = "config";
+
+/// Parses [`Options`] from the command line, merging in the contents of the
+/// config file specified with `--config`, if any.
+pub fn parse_options() -> anyhow::Result<Options> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let Some(path) = find_config_path(&args) else {
+        return Ok(Options::parse_from(args));
+    };
+
+    let args = merge_args(&path, args)
+        .with_context(|| format!("failed to load config file {}", path.display()))?;
+    Ok(Options::parse_from(args))
+}
+
+/// Merges the arguments from the config file at `path` into the command line
+/// `args`.
+fn merge_args(path: &Path, mut args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let negated = take_negated_flags(&mut args);
+    let file_args = args_from_file(path, &args, &negated)?;
+    args.splice(1..1, file_args);
+    Ok(args)
+}
+
+/// Removes the `--no-<flag>` arguments from the command line, returning the
+/// names of the flags.
+fn take_negated_flags(args: &mut Vec<OsString>) -> Vec<String> {
+    let command = Options::command();
+    let mut negated = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        let Some(arg) = args[i].to_str() else {
+            i += 1;
+            continue;
+        };
+        if arg == "--" {
+            break;
+        }
+        let flag = arg.strip_prefix("--no-").and_then(|name| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name))
+                .filter(|arg| !arg.get_action().takes_values())
+        });
+        if let Some(flag) = flag {
+            negated.push(flag.get_id().to_string());
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    negated
+}
+
+/// Finds the value of `--config` in the raw command line.
+///
+/// This has to happen before clap parses the command line, since arguments
+/// from the file may satisfy requirements of arguments on the command line.
+fn find_config_path(args: &[OsString]) -> Option<PathBuf> {
+    let long = format!("--{CONFIG_ARG}");
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.to_str() else {
+            continue;
+        };
+        if arg == "--" {
+            break;
+        } else if arg == long {
+            return args.next().map(PathBuf::from);
+        } else if let Some(value) = arg.strip_prefix(&long).and_then(|s| s.strip_prefix('=')) {
+            return Some(value.into());
+        }
+    }
+    None
+}
+
+/// A value from a config file, independent of the file format.
+enum Value {
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn from_toml(value: &toml_edit::Value) -> anyhow::Result<Self> {
+        Ok(match value {
+            toml_edit::Value::String(v) => Self::String(v.value().clone()),
+            toml_edit::Value::Integer(v) => Self::String(v.value().to_string()),
+            toml_edit::Value::Float(v) => Self::String(v.value().to_string()),
+            toml_edit::Value::Boolean(v) => Self::Bool(*v.value()),
+            toml_edit::Value::Array(v) => {
+                Self::Array(v.iter().map(Self::from_toml).collect::<Result<_, _>>()?)
+            }
+            toml_edit::Value::Datetime(_) | toml_edit::Value::InlineTable(_) => {
+                anyhow::bail!("unsupported value type")
+            }
+        })
+    }
+
+    fn from_json(value: &serde_json::Value) -> anyhow::Result<Self> {
+        Ok(match value {
+            serde_json::Value::String(v) => Self::String(v.clone()),
+            serde_json::Value::Number(v) => Self::String(v.to_string()),
+            serde_json::Value::Bool(v) => Self::Bool(*v),
+            serde_json::Value::Array(v) => {
+                Self::Array(v.iter().map(Self::from_json).collect::<Result<_, _>>()?)
+            }
+            serde_json::Value::Null | serde_json::Value::Object(_) => {
+                anyhow::bail!("unsupported value type")
+            }
+        })
+    }
+}
+
+/// Reads the config file at `path` and converts it to command line arguments,
+/// dropping the settings overridden by the command line `cli_args` and the
+/// `negated` flags.
+fn args_from_file(
+    path: &Path,
+    cli_args: &[OsString],
+    negated: &[String],
+) -> anyhow::Result<Vec<OsString>> {
+    let contents = fs_err::read_to_string(path)?;
+    let entries = if path.extension().is_some_and(|ext| ext == "json") {
+        let serde_json::Value::Object(map) =
+            serde_json::from_str(&contents).context("failed to parse json")?
+        else {
+            anyhow::bail!("expected a json object");
+        };
+        map.iter()
+            .map(|(key, value)| {
+                Ok((
+                    key.clone(),
+                    Value::from_json(value).with_context(|| format!("invalid value for {key}"))?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    } else {
+        let doc: toml_edit::DocumentMut = contents.parse().context("failed to parse toml")?;
+        doc.iter()
+            .map(|(key, item)| {
+                let value = item
+                    .as_value()
+                    .with_context(|| format!("{key} must be a value, not a table"))?;
+                Ok((
+                    key.to_owned(),
+                    Value::from_toml(value).with_context(|| format!("invalid value for {key}"))?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+
+    let command = Options::command();
+    let Ok(cli) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(cli_args)
+    else {
+        // The command line asks for help or is invalid, which clap reports
+        // when it is parsed.
+        return Ok(Vec::new());
+    };
+
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        let name = key.replace('_', "-");
+        if name == CONFIG_ARG {
+            anyhow::bail!("config files cannot include other config files");
+        }
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+            .with_context(|| format!("unknown option {key}"))?;
+        if negated.iter().any(|id| id == arg.get_id()) || overridden(&command, &cli, arg) {
+            continue;
+        }
+        let takes_value = arg.get_action().takes_values();
+        // An option whose value is optional is enabled with `true`.
+        let optional_value = arg.get_num_args().is_some_and(|n| n.min_values() == 0);
+        push_args(&mut args, &name, takes_value, optional_value, value)
+            .with_context(|| format!("invalid value for {key}"))?;
+    }
+    Ok(args)
+}
+
+/// Returns whether the command line sets `arg` or an argument that conflicts
+/// with it.
+fn overridden(command: &clap::Command, cli: &ArgMatches, arg: &clap::Arg) -> bool {
+    let set =
+        |arg: &clap::Arg| cli.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine);
+    if set(arg) {
+        // Repeated arguments add to the ones from the file.
+        return !matches!(arg.get_action(), ArgAction::Append);
+    }
+    command.get_arg_conflicts_with(arg).into_iter().any(set)
+        || command
+            .get_arguments()
+            .filter(|other| set(other))
+            .any(|other| {
+                command
+                    .get_arg_conflicts_with(other)
+                    .iter()
+                    .any(|c| c.get_id() == arg.get_id())
+            })
+}
+
+fn push_args(
+    args: &mut Vec<OsString>,
+    name: &str,
+    takes_value: bool,
+    optional_value: bool,
+    value: Value,
+) -> anyhow::Result<()> {
+    match value {
+        Value::Bool(enabled) if !takes_value || optional_value => {
+            if enabled {
+                args.push(format!("--{name}").into());
+            }
+        }
+        _ if !takes_value => anyhow::bail!("expected a boolean"),
+        Value::Bool(v) => args.push(format!("--{name}={v}").into()),
+        Value::String(v) => args.push(format!("--{name}={v}").into()),
+        Value::Array(values) => {
+            for value in values {
+                if matches!(value, Value::Array(_)) {
+                    anyhow::bail!("nested arrays are not supported");
+                }
+                push_args(args, name, takes_value, optional_value, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_config() {
+        assert_eq!(
+            find_config_path(&args(&["openvmm", "--hv", "--config", "vm.toml"])),
+            Some("vm.toml".into())
+        );
+        assert_eq!(
+            find_config_path(&args(&["openvmm", "--config=vm.json"])),
+            Some("vm.json".into())
+        );
+        assert_eq!(
+            find_config_path(&args(&["openvmm", "--", "--config", "vm.toml"])),
+            None
+        );
+    }
+
+    fn args(s: &[&str]) -> Vec<OsString> {
+        s.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn toml_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.toml");
+        std::fs::write(
+            &path,
+            r#"
+processors = 4
+memory = "4GB"
+uefi = true
+hv = false
+halt_on_reset = true
+disk = ["file:a.img", "file:b.img"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            args_from_file(&path, &args(&["openvmm"]), &[]).unwrap(),
+            [
+                "--processors=4",
+                "--memory=4GB",
+                "--uefi",
+                "--halt-on-reset",
+                "--disk=file:a.img",
+                "--disk=file:b.img",
+            ]
+            .map(OsString::from)
+        );
+    }
+
+    #[test]
+    fn json_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.json");
+        std::fs::write(&path, r#"{ "processors": 2, "pcat": true }"#).unwrap();
+        let mut args = args_from_file(&path, &args(&["openvmm"]), &[]).unwrap();
+        args.sort();
+        assert_eq!(args, ["--pcat", "--processors=2"].map(OsString::from));
+    }
+
+    #[test]
+    fn invalid_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.toml");
+        for contents in ["not_an_option = 1", "uefi = \"yes\"", "config = \"a.toml\""] {
+            std::fs::write(&path, contents).unwrap();
+            args_from_file(&path, &args(&["openvmm"]), &[]).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn cli_overrides_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.toml");
+        std::fs::write(
+            &path,
+            r#"
+processors = 4
+pcat = true
+hv = true
+halt_on_reset = true
+disk = ["file:a.img"]
+"#,
+        )
+        .unwrap();
+
+        let merged = merge_args(
+            &path,
+            args(&[
+                "openvmm",
+                "--processors",
+                "2",
+                "--uefi",
+                "--no-hv",
+                "--disk",
+                "file:b.img",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            args(&[
+                "openvmm",
+                "--halt-on-reset",
+                "--disk=file:a.img",
+                "--processors",
+                "2",
+                "--uefi",
+                "--disk",
+                "file:b.img",
+            ])
+        );
+
+        // The merged command line parses without conflicts.
+        let options = Options::try_parse_from(merged).unwrap();
+        assert_eq!(options.processors, 2);
+        assert!(options.uefi);
+        assert!(!options.pcat);
+        assert!(!options.hv);
+        assert!(options.halt_on_reset);
+        assert_eq!(options.disk.len(), 2);
+    }
+
+    #[test]
+    fn optional_value_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.toml");
+        for (contents, expected) in [
+            ("allow_sleep_states = true", &["--allow-sleep-states"][..]),
+            ("allow_sleep_states = false", &[][..]),
+            (
+                "allow_sleep_states = \"s3\"",
+                &["--allow-sleep-states=s3"][..],
+            ),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            assert_eq!(
+                args_from_file(&path, &args(&["openvmm"]), &[]).unwrap(),
+                args(expected)
+            );
+        }
+
+        // The deprecated sleep state flags conflict with the file's setting.
+        std::fs::write(&path, "allow_sleep_states = true").unwrap();
+        assert!(
+            args_from_file(&path, &args(&["openvmm", "--s3"]), &[])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn negated_flags() {
+        let mut cli = args(&["openvmm", "--no-hv", "--uefi", "--", "--no-pcat"]);
+        assert_eq!(take_negated_flags(&mut cli), ["hv"]);
+        assert_eq!(cli, args(&["openvmm", "--uefi", "--", "--no-pcat"]));
+
+        // Options that take values cannot be negated.
+        let mut cli = args(&["openvmm", "--no-memory"]);
+        assert!(take_negated_flags(&mut cli).is_empty());
+    }
+}
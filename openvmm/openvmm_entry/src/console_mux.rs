@@ -0,0 +1,280 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Multiplexes several serial consoles onto the controlling terminal.
+//!
+//! Only one console is connected to the terminal at a time. Output from the
+//! other consoles is buffered (up to a limit) and replayed when switching to
+//! them. Ctrl-A acts as an escape key for switching consoles and for leaving
+//! the consoles for the OpenVMM monitor prompt, similar to QEMU's multiplexed
+//! character devices.
+
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use futures::executor::block_on;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+
+/// The escape key, Ctrl-A.
+const ESCAPE: u8 = 0x01;
+
+/// Ctrl-Q, which returns to the monitor without needing the escape key.
+const CTRL_Q: u8 = 0x11;
+
+/// The maximum amount of output to buffer for each inactive console.
+const BACKLOG_LIMIT: usize = 64 * 1024;
+
+const HELP: &str = "\
+Ctrl-A n      switch to the next console\r
+Ctrl-A 1-9    switch to console N\r
+Ctrl-A c      enter the monitor (also Ctrl-Q)\r
+Ctrl-A x      quit\r
+Ctrl-A h      print this help\r
+Ctrl-A Ctrl-A send Ctrl-A to the console\r
+";
+
+/// What the caller should do after [`ConsoleMux::run`] returns.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MuxAction {
+    /// Enter the monitor prompt.
+    Monitor,
+    /// Quit OpenVMM.
+    Quit,
+}
+
+struct Console {
+    name: String,
+    backlog: VecDeque<u8>,
+}
+
+struct OutputState {
+    stdout: Box<dyn Write + Send>,
+    active: usize,
+    consoles: Vec<Console>,
+}
+
+impl OutputState {
+    /// Writes a status message to the terminal, on its own line.
+    fn message(&mut self, msg: &str) -> io::Result<()> {
+        write!(self.stdout, "\r\n[openvmm: {msg}]\r\n")
+    }
+}
+
+/// A set of consoles sharing the terminal.
+pub struct ConsoleMux {
+    inputs: Vec<Box<dyn AsyncWrite + Send + Unpin>>,
+    output: Arc<Mutex<OutputState>>,
+    escape_pending: bool,
+}
+
+impl ConsoleMux {
+    /// Returns a new multiplexer writing output to `stdout`.
+    pub fn new(stdout: impl Write + Send + 'static) -> Self {
+        Self {
+            inputs: Vec::new(),
+            output: Arc::new(Mutex::new(OutputState {
+                stdout: Box::new(stdout),
+                active: 0,
+                consoles: Vec::new(),
+            })),
+            escape_pending: false,
+        }
+    }
+
+    /// Adds a console named `name`, which receives terminal input via
+    /// `input`.
+    ///
+    /// Returns the writer for the console's output. The first console added
+    /// is initially active.
+    pub fn add(&mut self, name: &str, input: Box<dyn AsyncWrite + Send + Unpin>) -> ConsoleOutput {
+        let mut output = self.output.lock();
+        let index = output.consoles.len();
+        output.consoles.push(Console {
+            name: name.to_owned(),
+            backlog: VecDeque::new(),
+        });
+        self.inputs.push(input);
+        ConsoleOutput {
+            output: self.output.clone(),
+            index,
+        }
+    }
+
+    /// Returns true if no consoles have been added.
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Returns the number of consoles.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Writes `data` to the active console.
+    pub fn write_input(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let active = self.output.lock().active;
+        block_on(self.inputs[active].write_all(data))
+    }
+
+    /// Makes console `index` active, replaying any output it produced while
+    /// inactive.
+    fn switch(&mut self, index: usize) -> io::Result<()> {
+        let mut output = self.output.lock();
+        let output = &mut *output;
+        let Some(console) = output.consoles.get_mut(index) else {
+            return output.message(&format!("no console {}", index + 1));
+        };
+        let backlog = std::mem::take(&mut console.backlog);
+        let msg = format!("switched to {}", console.name);
+        output.active = index;
+        output.message(&msg)?;
+        let (a, b) = backlog.as_slices();
+        output.stdout.write_all(a)?;
+        output.stdout.write_all(b)
+    }
+
+    /// Handles the key following the escape key.
+    fn escape(&mut self, key: u8) -> io::Result<Option<MuxAction>> {
+        match key {
+            ESCAPE => self.write_input(&[ESCAPE])?,
+            b'c' => return Ok(Some(MuxAction::Monitor)),
+            b'x' => return Ok(Some(MuxAction::Quit)),
+            b'n' => {
+                let next = (self.output.lock().active + 1) % self.inputs.len();
+                self.switch(next)?;
+            }
+            b'1'..=b'9' => self.switch((key - b'1') as usize)?,
+            b'h' | b'?' => {
+                let mut output = self.output.lock();
+                output.stdout.write_all(b"\r\n")?;
+                output.stdout.write_all(HELP.as_bytes())?;
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Relays terminal input from `stdin` to the active console until the
+    /// user asks for the monitor or to quit.
+    ///
+    /// The terminal should be in raw mode.
+    pub fn run(&mut self, stdin: &mut impl Read) -> io::Result<MuxAction> {
+        assert!(!self.is_empty());
+        let mut buf = [0; 32];
+        loop {
+            let n = stdin.read(&mut buf)?;
+            if n == 0 {
+                return Ok(MuxAction::Monitor);
+            }
+            let mut data = Vec::with_capacity(n);
+            for &b in &buf[..n] {
+                if std::mem::take(&mut self.escape_pending) {
+                    self.write_input(&data)?;
+                    data.clear();
+                    if let Some(action) = self.escape(b)? {
+                        return Ok(action);
+                    }
+                } else if b == ESCAPE {
+                    self.escape_pending = true;
+                } else if b == CTRL_Q {
+                    self.write_input(&data)?;
+                    return Ok(MuxAction::Monitor);
+                } else {
+                    data.push(b);
+                }
+            }
+            self.write_input(&data)?;
+        }
+    }
+}
+
+/// The output side of a console in a [`ConsoleMux`].
+///
+/// Output is written to the terminal while the console is active, and
+/// buffered otherwise.
+pub struct ConsoleOutput {
+    output: Arc<Mutex<OutputState>>,
+    index: usize,
+}
+
+impl Write for ConsoleOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut output = self.output.lock();
+        if output.active == self.index {
+            output.stdout.write_all(buf)?;
+        } else {
+            let backlog = &mut output.consoles[self.index].backlog;
+            backlog.extend(buf);
+            let excess = backlog.len().saturating_sub(BACKLOG_LIMIT);
+            backlog.drain(..excess);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.lock().stdout.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AllowStdIo;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn take(&self) -> Vec<u8> {
+            std::mem::take(&mut *self.0.lock())
+        }
+    }
+
+    #[test]
+    fn test_mux() {
+        let stdout = SharedBuf::default();
+        let com1 = SharedBuf::default();
+        let com2 = SharedBuf::default();
+        let mut mux = ConsoleMux::new(stdout.clone());
+        let mut out1 = mux.add("com1", Box::new(AllowStdIo::new(com1.clone())));
+        let mut out2 = mux.add("com2", Box::new(AllowStdIo::new(com2.clone())));
+
+        // Input goes to the active console; Ctrl-A Ctrl-A sends Ctrl-A.
+        let action = mux.run(&mut &b"ab\x01\x01c\x01c"[..]).unwrap();
+        assert_eq!(action, MuxAction::Monitor);
+        assert_eq!(com1.take(), b"ab\x01c");
+
+        // Inactive output is buffered until the console is selected.
+        out1.write_all(b"one").unwrap();
+        out2.write_all(b"two").unwrap();
+        assert_eq!(stdout.take(), b"one");
+        let action = mux.run(&mut &b"\x012xy\x11z"[..]).unwrap();
+        assert_eq!(action, MuxAction::Monitor);
+        assert_eq!(stdout.take(), b"\r\n[openvmm: switched to com2]\r\ntwo");
+        assert_eq!(com2.take(), b"xy");
+        assert!(com1.take().is_empty());
+
+        // Switch back around and quit.
+        let action = mux.run(&mut &b"\x01n\x01x"[..]).unwrap();
+        assert_eq!(action, MuxAction::Quit);
+        assert_eq!(stdout.take(), b"\r\n[openvmm: switched to com1]\r\n");
+    }
+}
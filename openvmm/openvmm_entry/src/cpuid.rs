@@ -0,0 +1,323 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Builds the CPUID overrides for `--cpu-model`, `--cpuid-disable`, and
+//! `--cpuid-set`.
+
+use crate::cli_args::CpuModelCli;
+use hvlite_defs::config::CpuidOverride;
+
+const EAX: usize = 0;
+const EBX: usize = 1;
+const ECX: usize = 2;
+const EDX: usize = 3;
+
+/// A CPUID feature bit that can be disabled by name.
+struct Feature {
+    name: &'static str,
+    function: u32,
+    index: Option<u32>,
+    reg: usize,
+    bit: u32,
+}
+
+const fn feature(
+    name: &'static str,
+    function: u32,
+    index: Option<u32>,
+    reg: usize,
+    bit: u32,
+) -> Feature {
+    Feature {
+        name,
+        function,
+        index,
+        reg,
+        bit,
+    }
+}
+
+const FEATURES: &[Feature] = &[
+    // Leaf 1, version and features.
+    feature("pclmulqdq", 1, None, ECX, 1),
+    feature("ssse3", 1, None, ECX, 9),
+    feature("fma", 1, None, ECX, 12),
+    feature("cx16", 1, None, ECX, 13),
+    feature("pcid", 1, None, ECX, 17),
+    feature("sse4.1", 1, None, ECX, 19),
+    feature("sse4.2", 1, None, ECX, 20),
+    feature("x2apic", 1, None, ECX, 21),
+    feature("movbe", 1, None, ECX, 22),
+    feature("popcnt", 1, None, ECX, 23),
+    feature("tsc-deadline", 1, None, ECX, 24),
+    feature("aes", 1, None, ECX, 25),
+    feature("xsave", 1, None, ECX, 26),
+    feature("avx", 1, None, ECX, 28),
+    feature("f16c", 1, None, ECX, 29),
+    feature("rdrand", 1, None, ECX, 30),
+    // Leaf 7, extended features.
+    feature("fsgsbase", 7, Some(0), EBX, 0),
+    feature("bmi1", 7, Some(0), EBX, 3),
+    feature("hle", 7, Some(0), EBX, 4),
+    feature("avx2", 7, Some(0), EBX, 5),
+    feature("smep", 7, Some(0), EBX, 7),
+    feature("bmi2", 7, Some(0), EBX, 8),
+    feature("erms", 7, Some(0), EBX, 9),
+    feature("invpcid", 7, Some(0), EBX, 10),
+    feature("rtm", 7, Some(0), EBX, 11),
+    feature("avx512f", 7, Some(0), EBX, 16),
+    feature("avx512dq", 7, Some(0), EBX, 17),
+    feature("rdseed", 7, Some(0), EBX, 18),
+    feature("adx", 7, Some(0), EBX, 19),
+    feature("smap", 7, Some(0), EBX, 20),
+    feature("avx512ifma", 7, Some(0), EBX, 21),
+    feature("clflushopt", 7, Some(0), EBX, 23),
+    feature("clwb", 7, Some(0), EBX, 24),
+    feature("avx512cd", 7, Some(0), EBX, 28),
+    feature("sha", 7, Some(0), EBX, 29),
+    feature("avx512bw", 7, Some(0), EBX, 30),
+    feature("avx512vl", 7, Some(0), EBX, 31),
+    feature("avx512vbmi", 7, Some(0), ECX, 1),
+    feature("umip", 7, Some(0), ECX, 2),
+    feature("pku", 7, Some(0), ECX, 3),
+    feature("waitpkg", 7, Some(0), ECX, 5),
+    feature("gfni", 7, Some(0), ECX, 8),
+    feature("vaes", 7, Some(0), ECX, 9),
+    feature("vpclmulqdq", 7, Some(0), ECX, 10),
+    feature("la57", 7, Some(0), ECX, 16),
+    feature("rdpid", 7, Some(0), ECX, 22),
+    // Extended leaves.
+    feature("lzcnt", 0x80000001, None, ECX, 5),
+    feature("sse4a", 0x80000001, None, ECX, 6),
+    feature("prefetchw", 0x80000001, None, ECX, 8),
+    feature("pdpe1gb", 0x80000001, None, EDX, 26),
+    feature("rdtscp", 0x80000001, None, EDX, 27),
+    feature("invtsc", 0x80000007, None, EDX, 8),
+];
+
+/// A CPU model, which reports an Intel signature and brand string and hides
+/// the features introduced after it.
+struct CpuModel {
+    /// Leaf 1 EAX: the family, model, and stepping.
+    signature: u32,
+    brand: &'static str,
+    disabled: Vec<&'static str>,
+}
+
+const AFTER_ICELAKE_SERVER: &[&str] = &["waitpkg"];
+const AFTER_SKYLAKE_SERVER: &[&str] = &[
+    "avx512ifma",
+    "sha",
+    "avx512vbmi",
+    "umip",
+    "gfni",
+    "vaes",
+    "vpclmulqdq",
+    "la57",
+    "rdpid",
+];
+const AFTER_SKYLAKE: &[&str] = &[
+    "avx512f", "avx512dq", "avx512cd", "avx512bw", "avx512vl", "clwb", "pku",
+];
+const AFTER_HASWELL: &[&str] = &["rdseed", "adx", "smap", "clflushopt"];
+const AFTER_SANDYBRIDGE: &[&str] = &[
+    "fma",
+    "movbe",
+    "f16c",
+    "rdrand",
+    "fsgsbase",
+    "bmi1",
+    "hle",
+    "avx2",
+    "smep",
+    "bmi2",
+    "erms",
+    "invpcid",
+    "rtm",
+    "lzcnt",
+    "prefetchw",
+];
+const AFTER_NEHALEM: &[&str] = &[
+    "pclmulqdq",
+    "pcid",
+    "x2apic",
+    "tsc-deadline",
+    "aes",
+    "xsave",
+    "avx",
+    "pdpe1gb",
+];
+
+fn cpu_model(model: CpuModelCli) -> CpuModel {
+    let (signature, brand, disabled): (_, _, &[&[&str]]) = match model {
+        CpuModelCli::Nehalem => (
+            0x106a5,
+            "Intel Core i7 9xx (Nehalem Class Core i7)",
+            &[
+                AFTER_NEHALEM,
+                AFTER_SANDYBRIDGE,
+                AFTER_HASWELL,
+                AFTER_SKYLAKE,
+                AFTER_SKYLAKE_SERVER,
+                AFTER_ICELAKE_SERVER,
+            ],
+        ),
+        CpuModelCli::Sandybridge => (
+            0x206a7,
+            "Intel Xeon E312xx (Sandy Bridge)",
+            &[
+                AFTER_SANDYBRIDGE,
+                AFTER_HASWELL,
+                AFTER_SKYLAKE,
+                AFTER_SKYLAKE_SERVER,
+                AFTER_ICELAKE_SERVER,
+            ],
+        ),
+        CpuModelCli::Haswell => (
+            0x306c3,
+            "Intel Core Processor (Haswell)",
+            &[
+                AFTER_HASWELL,
+                AFTER_SKYLAKE,
+                AFTER_SKYLAKE_SERVER,
+                AFTER_ICELAKE_SERVER,
+            ],
+        ),
+        CpuModelCli::Skylake => (
+            0x506e3,
+            "Intel Core Processor (Skylake)",
+            &[AFTER_SKYLAKE, AFTER_SKYLAKE_SERVER, AFTER_ICELAKE_SERVER],
+        ),
+        CpuModelCli::SkylakeServer => (
+            0x50654,
+            "Intel Xeon Processor (Skylake)",
+            &[AFTER_SKYLAKE_SERVER, AFTER_ICELAKE_SERVER],
+        ),
+        CpuModelCli::IcelakeServer => (
+            0x606a6,
+            "Intel Xeon Processor (Icelake)",
+            &[AFTER_ICELAKE_SERVER],
+        ),
+    };
+    CpuModel {
+        signature,
+        brand,
+        disabled: disabled.concat(),
+    }
+}
+
+/// Returns the overrides that clear the named features' bits.
+fn disable_features<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<Vec<CpuidOverride>> {
+    names
+        .into_iter()
+        .map(|name| {
+            let feature = FEATURES
+                .iter()
+                .find(|f| f.name == name)
+                .ok_or_else(|| anyhow::anyhow!("unknown cpuid feature '{name}'"))?;
+            let mut mask = [0; 4];
+            mask[feature.reg] = 1 << feature.bit;
+            Ok(CpuidOverride {
+                function: feature.function,
+                index: feature.index,
+                result: [0; 4],
+                mask,
+            })
+        })
+        .collect()
+}
+
+/// Returns the overrides that report `brand` as the processor brand string.
+fn brand_string(brand: &str) -> Vec<CpuidOverride> {
+    let mut bytes = [0; 48];
+    bytes[..brand.len()].copy_from_slice(brand.as_bytes());
+    bytes
+        .chunks_exact(16)
+        .zip(0x80000002..)
+        .map(|(chunk, function)| {
+            let mut result = [0; 4];
+            for (reg, value) in result.iter_mut().zip(chunk.chunks_exact(4)) {
+                *reg = u32::from_le_bytes(value.try_into().unwrap());
+            }
+            CpuidOverride {
+                function,
+                index: None,
+                result,
+                mask: [!0; 4],
+            }
+        })
+        .collect()
+}
+
+/// Builds the CPUID overrides for the given CPU model, disabled features, and
+/// explicitly set registers, with each taking precedence over the previous.
+pub fn cpuid_overrides(
+    model: Option<CpuModelCli>,
+    disabled: &[String],
+    set: &[CpuidOverride],
+) -> anyhow::Result<Vec<CpuidOverride>> {
+    let mut overrides = Vec::new();
+    if let Some(model) = model {
+        let model = cpu_model(model);
+        let mut mask = [0; 4];
+        mask[EAX] = !0;
+        overrides.push(CpuidOverride {
+            function: 1,
+            index: None,
+            result: [model.signature, 0, 0, 0],
+            mask,
+        });
+        overrides.extend(brand_string(model.brand));
+        overrides.extend(disable_features(model.disabled)?);
+    }
+    overrides.extend(disable_features(disabled.iter().map(|s| s.as_str()))?);
+    overrides.extend(set.iter().cloned());
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disable_features() {
+        let overrides = disable_features(["avx2", "x2apic"]).unwrap();
+        assert_eq!(
+            (overrides[0].function, overrides[0].index, overrides[0].mask),
+            (7, Some(0), [0, 1 << 5, 0, 0])
+        );
+        assert_eq!(
+            (overrides[1].function, overrides[1].index, overrides[1].mask),
+            (1, None, [0, 0, 1 << 21, 0])
+        );
+        assert!(disable_features(["bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_cpu_models() {
+        // Every feature a model hides must be known.
+        for model in [
+            CpuModelCli::Nehalem,
+            CpuModelCli::Sandybridge,
+            CpuModelCli::Haswell,
+            CpuModelCli::Skylake,
+            CpuModelCli::SkylakeServer,
+            CpuModelCli::IcelakeServer,
+        ] {
+            let model = cpu_model(model);
+            assert!(model.brand.len() < 48);
+            disable_features(model.disabled).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_brand_string() {
+        let overrides = brand_string("Intel Core Processor (Haswell)");
+        assert_eq!(overrides.len(), 3);
+        assert_eq!(overrides[0].function, 0x80000002);
+        assert_eq!(overrides[0].result[0].to_le_bytes(), *b"Inte");
+        assert_eq!(overrides[2].result, [0; 4]);
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Code to copy files into the guest with the file copy IC.
+
+use anyhow::Context;
+use hyperv_ic_resources::fcopy::CopyFileParams;
+use hyperv_ic_resources::fcopy::FcopyRpc;
+use mesh::CancelContext;
+use mesh::rpc::RpcSend as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub(crate) struct CopyToGuestCommand {
+    /// The timeout in seconds, including the time to wait for the guest to
+    /// connect to the IC.
+    #[clap(long, default_value = "60")]
+    timeout: u64,
+
+    /// Overwrite the guest file if it exists.
+    #[clap(long, short = 'f')]
+    force: bool,
+
+    /// Create the guest directory if it does not exist.
+    #[clap(long, short = 'p')]
+    create_dir: bool,
+
+    /// The host file to copy.
+    src: PathBuf,
+
+    /// The absolute guest path to copy to. If it ends in a path separator,
+    /// the file is copied into that directory with the source's file name.
+    dest: String,
+}
+
+pub(crate) async fn handle_copy_to_guest(
+    fcopy: &mesh::Sender<FcopyRpc>,
+    command: CopyToGuestCommand,
+) -> anyhow::Result<()> {
+    let CopyToGuestCommand {
+        timeout,
+        force,
+        create_dir,
+        src,
+        dest,
+    } = command;
+
+    let (guest_dir, guest_file_name) = split_guest_path(&dest, &src)?;
+    let file = fs_err::File::open(&src)?;
+    CancelContext::new()
+        .with_timeout(Duration::from_secs(timeout))
+        .until_cancelled(fcopy.call_failable(
+            FcopyRpc::CopyFile,
+            CopyFileParams {
+                file: file.into(),
+                guest_dir,
+                guest_file_name,
+                overwrite: force,
+                create_dir,
+            },
+        ))
+        .await??;
+    Ok(())
+}
+
+/// Splits `dest` into the guest directory and file name, using the file name
+/// of `src` if `dest` names a directory.
+///
+/// Either separator is accepted, since the guest may be Windows or Linux.
+fn split_guest_path(dest: &str, src: &Path) -> anyhow::Result<(String, String)> {
+    let (dir, name) = dest
+        .rsplit_once(['/', '\\'])
+        .context("destination must be an absolute guest path")?;
+    let name = if name.is_empty() {
+        src.file_name()
+            .context("missing source file name")?
+            .to_str()
+            .context("source file name is not valid UTF-8")?
+    } else {
+        name
+    };
+    // Keep the separator for root directories, such as `/` or `C:\`.
+    let dir = if dir.is_empty() || dir.ends_with(':') {
+        &dest[..dir.len() + 1]
+    } else {
+        dir
+    };
+    Ok((dir.to_owned(), name.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_guest_path;
+    use std::path::Path;
+
+    #[test]
+    fn test_split_guest_path() {
+        let src = Path::new("/tmp/file.txt");
+        let split = |dest| split_guest_path(dest, src).unwrap();
+        assert_eq!(split("/root/x.txt"), ("/root".into(), "x.txt".into()));
+        assert_eq!(split("/root/"), ("/root".into(), "file.txt".into()));
+        assert_eq!(split("/x.txt"), ("/".into(), "x.txt".into()));
+        assert_eq!(
+            split(r"C:\Users\a\x.txt"),
+            (r"C:\Users\a".into(), "x.txt".into())
+        );
+        assert_eq!(split(r"C:\"), (r"C:\".into(), "file.txt".into()));
+        assert!(split_guest_path("x.txt", src).is_err());
+    }
+}
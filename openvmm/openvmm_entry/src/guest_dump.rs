@@ -0,0 +1,447 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Saving guest memory and VP register state to a dump file.
+//!
+//! Two formats are supported:
+//!
+//! * An ELF64 core file with a `PT_LOAD` segment for each guest RAM range,
+//!   whose physical address is the range's GPA, and an `NT_PRSTATUS` note
+//!   with each VP's registers. This matches the layout of QEMU's
+//!   `dump-guest-memory` without paging, which tools such as `crash` can open.
+//! * A Windows complete memory dump (x86_64 only), with a `DUMP_HEADER64`
+//!   describing the RAM ranges as physical memory runs and the crashing VP's
+//!   registers as the context record. The kernel debugger data block is not
+//!   located, so the debugger must find the kernel itself.
+
+use anyhow::Context as _;
+use hvlite_defs::rpc::VmRpc;
+use mesh::rpc::RpcSend;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use virt::vp::Registers;
+
+/// How much guest memory to read at a time.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The format of a guest memory dump.
+#[derive(Copy, Clone, Debug)]
+pub enum DumpFormat {
+    Elf,
+    Windows,
+}
+
+/// Information about the crash that triggered a dump.
+#[derive(Debug, Default)]
+pub struct CrashInfo {
+    /// The VP that crashed, if known.
+    pub vp: Option<u32>,
+    /// The bugcheck code and parameters, if the guest reported them through
+    /// the Hyper-V guest crash registers.
+    pub bugcheck: Option<[u64; 5]>,
+}
+
+/// Writes the paused VM's RAM and the registers of its `vp_count` VPs to
+/// `path`.
+pub async fn write_guest_dump(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    path: &Path,
+    format: DumpFormat,
+    vp_count: u32,
+    crash: &CrashInfo,
+) -> anyhow::Result<()> {
+    let ram_ranges = vm_rpc.call(VmRpc::RamRanges, ()).await?;
+    let mut registers = Vec::new();
+    for vp in 0..vp_count {
+        registers.push(
+            vm_rpc
+                .call_failable(VmRpc::VpRegisters, vp)
+                .await
+                .with_context(|| format!("failed to get registers for VP {vp}"))?,
+        );
+    }
+
+    let headers = match format {
+        DumpFormat::Elf => elf::encode_headers(&ram_ranges, &registers),
+        DumpFormat::Windows => windows::encode_header(&ram_ranges, &registers, crash)?,
+    };
+
+    let mut file = BufWriter::new(fs_err::File::create(path)?);
+    file.write_all(&headers)?;
+    for range in &ram_ranges {
+        let mut gpa = range.start();
+        while gpa < range.end() {
+            let len = (range.end() - gpa).min(CHUNK_SIZE) as usize;
+            let data = vm_rpc
+                .call_failable(VmRpc::ReadMemory, (gpa, len))
+                .await
+                .with_context(|| format!("failed to read guest memory at {gpa:#x}"))?;
+            file.write_all(&data)?;
+            gpa += len as u64;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+mod elf {
+    use super::Registers;
+    use memory_range::MemoryRange;
+
+    const ELF_HEADER_SIZE: u16 = 64;
+    const PROGRAM_HEADER_SIZE: u16 = 56;
+    const ET_CORE: u16 = 4;
+    const PT_LOAD: u32 = 1;
+    const PT_NOTE: u32 = 4;
+    const PF_RWX: u32 = 7;
+    const NT_PRSTATUS: u32 = 1;
+
+    /// Offset of `pr_pid` in `struct elf_prstatus`.
+    const PRSTATUS_PID_OFFSET: usize = 32;
+    /// Offset of `pr_reg` in `struct elf_prstatus`.
+    const PRSTATUS_REGS_OFFSET: usize = 112;
+
+    #[cfg(guest_arch = "x86_64")]
+    const MACHINE: u16 = 62; // EM_X86_64
+    #[cfg(guest_arch = "aarch64")]
+    const MACHINE: u16 = 183; // EM_AARCH64
+
+    /// Encodes the ELF header, the program headers, and the register notes,
+    /// with the `PT_LOAD` segments' data laid out back to back after them.
+    pub fn encode_headers(ram_ranges: &[MemoryRange], registers: &[Registers]) -> Vec<u8> {
+        let mut notes = Vec::new();
+        for (vp, regs) in registers.iter().enumerate() {
+            encode_note(&mut notes, b"CORE", NT_PRSTATUS, &encode_prstatus(vp, regs));
+        }
+
+        let phnum = u16::try_from(ram_ranges.len() + 1).expect("too many ram ranges");
+        let mut headers = Vec::new();
+
+        // e_ident: ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE
+        headers.extend(b"\x7fELF\x02\x01\x01\x00");
+        headers.extend([0; 8]);
+        headers.extend(ET_CORE.to_le_bytes());
+        headers.extend(MACHINE.to_le_bytes());
+        headers.extend(1u32.to_le_bytes()); // e_version
+        headers.extend(0u64.to_le_bytes()); // e_entry
+        headers.extend(u64::from(ELF_HEADER_SIZE).to_le_bytes()); // e_phoff
+        headers.extend(0u64.to_le_bytes()); // e_shoff
+        headers.extend(0u32.to_le_bytes()); // e_flags
+        headers.extend(ELF_HEADER_SIZE.to_le_bytes());
+        headers.extend(PROGRAM_HEADER_SIZE.to_le_bytes());
+        headers.extend(phnum.to_le_bytes());
+        headers.extend([0; 6]); // e_shentsize, e_shnum, e_shstrndx
+
+        let notes_offset =
+            u64::from(ELF_HEADER_SIZE) + u64::from(PROGRAM_HEADER_SIZE) * u64::from(phnum);
+        let mut offset = notes_offset + notes.len() as u64;
+        encode_program_header(&mut headers, PT_NOTE, notes_offset, 0, notes.len() as u64);
+        for range in ram_ranges {
+            encode_program_header(&mut headers, PT_LOAD, offset, range.start(), range.len());
+            offset += range.len();
+        }
+        headers.extend(notes);
+        headers
+    }
+
+    fn encode_program_header(headers: &mut Vec<u8>, ty: u32, offset: u64, paddr: u64, len: u64) {
+        headers.extend(ty.to_le_bytes());
+        headers.extend(if ty == PT_LOAD { PF_RWX } else { 0 }.to_le_bytes());
+        headers.extend(offset.to_le_bytes());
+        headers.extend(0u64.to_le_bytes()); // p_vaddr
+        headers.extend(paddr.to_le_bytes());
+        headers.extend(len.to_le_bytes()); // p_filesz
+        headers.extend(len.to_le_bytes()); // p_memsz
+        headers.extend(0u64.to_le_bytes()); // p_align
+    }
+
+    fn encode_note(notes: &mut Vec<u8>, name: &[u8], ty: u32, desc: &[u8]) {
+        notes.extend((name.len() as u32 + 1).to_le_bytes());
+        notes.extend((desc.len() as u32).to_le_bytes());
+        notes.extend(ty.to_le_bytes());
+        notes.extend(name);
+        notes.push(0);
+        notes.resize(notes.len().next_multiple_of(4), 0);
+        notes.extend(desc);
+        notes.resize(notes.len().next_multiple_of(4), 0);
+    }
+
+    /// Encodes a `struct elf_prstatus`, using the VP number (plus one, since
+    /// zero is not a valid PID) as the PID.
+    fn encode_prstatus(vp: usize, regs: &Registers) -> Vec<u8> {
+        let mut prstatus = vec![0; PRSTATUS_REGS_OFFSET];
+        prstatus[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4]
+            .copy_from_slice(&(vp as u32 + 1).to_le_bytes());
+        for reg in prstatus_regs(regs) {
+            prstatus.extend(reg.to_le_bytes());
+        }
+        prstatus.extend([0; 8]); // pr_fpvalid and padding
+        prstatus
+    }
+
+    /// Returns the registers in the order of the kernel's
+    /// `struct user_regs_struct`.
+    #[cfg(guest_arch = "x86_64")]
+    fn prstatus_regs(r: &Registers) -> [u64; 27] {
+        [
+            r.r15,
+            r.r14,
+            r.r13,
+            r.r12,
+            r.rbp,
+            r.rbx,
+            r.r11,
+            r.r10,
+            r.r9,
+            r.r8,
+            r.rax,
+            r.rcx,
+            r.rdx,
+            r.rsi,
+            r.rdi,
+            0, // orig_rax
+            r.rip,
+            r.cs.selector.into(),
+            r.rflags,
+            r.rsp,
+            r.ss.selector.into(),
+            r.fs.base,
+            r.gs.base,
+            r.ds.selector.into(),
+            r.es.selector.into(),
+            r.fs.selector.into(),
+            r.gs.selector.into(),
+        ]
+    }
+
+    /// Returns the registers in the order of the kernel's
+    /// `struct user_pt_regs`.
+    #[cfg(guest_arch = "aarch64")]
+    fn prstatus_regs(r: &Registers) -> [u64; 34] {
+        // Use the stack pointer selected by PSTATE.SP for EL1.
+        let sp = if r.cpsr & 0xd == 0x5 {
+            r.sp_el1
+        } else {
+            r.sp_el0
+        };
+        [
+            r.x0, r.x1, r.x2, r.x3, r.x4, r.x5, r.x6, r.x7, r.x8, r.x9, r.x10, r.x11, r.x12, r.x13,
+            r.x14, r.x15, r.x16, r.x17, r.x18, r.x19, r.x20, r.x21, r.x22, r.x23, r.x24, r.x25,
+            r.x26, r.x27, r.x28, r.fp, r.lr, sp, r.pc, r.cpsr,
+        ]
+    }
+}
+
+#[cfg(guest_arch = "aarch64")]
+mod windows {
+    use super::CrashInfo;
+    use super::Registers;
+    use memory_range::MemoryRange;
+
+    pub fn encode_header(
+        _ram_ranges: &[MemoryRange],
+        _registers: &[Registers],
+        _crash: &CrashInfo,
+    ) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("windows dumps are not supported on aarch64")
+    }
+}
+
+#[cfg(guest_arch = "x86_64")]
+mod windows {
+    use super::CrashInfo;
+    use super::PAGE_SIZE;
+    use super::Registers;
+    use anyhow::Context as _;
+    use memory_range::MemoryRange;
+
+    const HEADER_SIZE: usize = 0x2000;
+
+    // Offsets of the `DUMP_HEADER64` fields that are filled in. The rest keep
+    // the `PAGE` fill pattern, which marks them as not present.
+    const SIGNATURE: usize = 0x0;
+    const VALID_DUMP: usize = 0x4;
+    const MAJOR_VERSION: usize = 0x8;
+    const MINOR_VERSION: usize = 0xc;
+    const DIRECTORY_TABLE_BASE: usize = 0x10;
+    const MACHINE_IMAGE_TYPE: usize = 0x30;
+    const NUMBER_PROCESSORS: usize = 0x34;
+    const BUGCHECK_CODE: usize = 0x38;
+    const BUGCHECK_PARAMETERS: usize = 0x40;
+    const PHYSICAL_MEMORY_BLOCK: usize = 0x88;
+    const PHYSICAL_MEMORY_BLOCK_SIZE: usize = 0x2c0;
+    const CONTEXT_RECORD: usize = 0x348;
+    const DUMP_TYPE: usize = 0xf98;
+    const REQUIRED_DUMP_SPACE: usize = 0xfa0;
+    const SYSTEM_TIME: usize = 0xfa8;
+    const COMMENT: usize = 0xfb0;
+
+    /// The number of runs that fit in the physical memory block, after its
+    /// run and page counts.
+    const MAX_RUNS: usize = (PHYSICAL_MEMORY_BLOCK_SIZE - 16) / 16;
+
+    /// A free build, as reported for retail kernels.
+    const MAJOR_VERSION_FREE: u32 = 0xf;
+    const IMAGE_FILE_MACHINE_AMD64: u32 = 0x8664;
+    const DUMP_TYPE_FULL: u32 = 1;
+
+    const CONTEXT_SIZE: usize = 0x4d0;
+    /// `CONTEXT_AMD64 | CONTEXT_CONTROL | CONTEXT_INTEGER | CONTEXT_SEGMENTS`
+    const CONTEXT_FLAGS: u32 = 0x100007;
+
+    /// The offset between the Unix epoch and the FILETIME epoch (1601), in
+    /// seconds.
+    const FILETIME_UNIX_EPOCH: u64 = 11644473600;
+
+    /// Encodes a `DUMP_HEADER64` for a complete memory dump, with the RAM
+    /// ranges' pages laid out back to back after it.
+    pub fn encode_header(
+        ram_ranges: &[MemoryRange],
+        registers: &[Registers],
+        crash: &CrashInfo,
+    ) -> anyhow::Result<Vec<u8>> {
+        if ram_ranges.len() > MAX_RUNS {
+            anyhow::bail!("too many ram ranges for a windows dump");
+        }
+        let vp = crash.vp.unwrap_or(0) as usize;
+        let regs = registers.get(vp).context("no registers for crashing VP")?;
+
+        let mut header = b"PAGE".repeat(HEADER_SIZE / 4);
+        let mut put = |offset: usize, bytes: &[u8]| {
+            header[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(SIGNATURE, b"PAGE");
+        put(VALID_DUMP, b"DU64");
+        put(MAJOR_VERSION, &MAJOR_VERSION_FREE.to_le_bytes());
+        put(MINOR_VERSION, &0u32.to_le_bytes());
+        put(DIRECTORY_TABLE_BASE, &regs.cr3.to_le_bytes());
+        put(MACHINE_IMAGE_TYPE, &IMAGE_FILE_MACHINE_AMD64.to_le_bytes());
+        put(NUMBER_PROCESSORS, &(registers.len() as u32).to_le_bytes());
+        let bugcheck = crash.bugcheck.unwrap_or_default();
+        put(BUGCHECK_CODE, &(bugcheck[0] as u32).to_le_bytes());
+        for (i, param) in bugcheck[1..].iter().enumerate() {
+            put(BUGCHECK_PARAMETERS + i * 8, &param.to_le_bytes());
+        }
+
+        let pages: u64 = ram_ranges.iter().map(|r| r.len() / PAGE_SIZE).sum();
+        let mut block = Vec::new();
+        block.extend((ram_ranges.len() as u32).to_le_bytes());
+        block.extend([0; 4]);
+        block.extend(pages.to_le_bytes());
+        for range in ram_ranges {
+            block.extend((range.start() / PAGE_SIZE).to_le_bytes());
+            block.extend((range.len() / PAGE_SIZE).to_le_bytes());
+        }
+        put(PHYSICAL_MEMORY_BLOCK, &block);
+
+        put(CONTEXT_RECORD, &encode_context(regs));
+        put(DUMP_TYPE, &DUMP_TYPE_FULL.to_le_bytes());
+        put(
+            REQUIRED_DUMP_SPACE,
+            &(HEADER_SIZE as u64 + pages * PAGE_SIZE).to_le_bytes(),
+        );
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let filetime = (now.as_secs() + FILETIME_UNIX_EPOCH) * 10_000_000
+            + u64::from(now.subsec_nanos() / 100);
+        put(SYSTEM_TIME, &filetime.to_le_bytes());
+        put(COMMENT, b"OpenVMM guest crash dump\0");
+
+        Ok(header)
+    }
+
+    /// Encodes an x64 `CONTEXT` record.
+    fn encode_context(r: &Registers) -> [u8; CONTEXT_SIZE] {
+        let mut context = [0; CONTEXT_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            context[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0x30, &CONTEXT_FLAGS.to_le_bytes());
+        for (i, seg) in [r.cs, r.ds, r.es, r.fs, r.gs, r.ss].iter().enumerate() {
+            put(0x38 + i * 2, &seg.selector.to_le_bytes());
+        }
+        put(0x44, &(r.rflags as u32).to_le_bytes());
+        let gps = [
+            r.rax, r.rcx, r.rdx, r.rbx, r.rsp, r.rbp, r.rsi, r.rdi, r.r8, r.r9, r.r10, r.r11,
+            r.r12, r.r13, r.r14, r.r15, r.rip,
+        ];
+        for (i, reg) in gps.iter().enumerate() {
+            put(0x78 + i * 8, &reg.to_le_bytes());
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_range::MemoryRange;
+
+    fn test_ranges() -> [MemoryRange; 2] {
+        [
+            MemoryRange::new(0..0x1000),
+            MemoryRange::new(0x100000..0x300000),
+        ]
+    }
+
+    #[test]
+    fn test_elf_headers() {
+        let registers = [Registers::default(), Registers::default()];
+        let headers = elf::encode_headers(&test_ranges(), &registers);
+        assert_eq!(&headers[..4], b"\x7fELF");
+        assert_eq!(u16::from_le_bytes([headers[56], headers[57]]), 3);
+
+        let phdr = |i: usize| &headers[64 + i * 56..64 + (i + 1) * 56];
+        let field = |i: usize, offset: usize| {
+            u64::from_le_bytes(phdr(i)[offset..offset + 8].try_into().unwrap())
+        };
+
+        // The notes follow the program headers, and the RAM follows the
+        // notes, which end the headers.
+        assert_eq!(u32::from_le_bytes(phdr(0)[..4].try_into().unwrap()), 4);
+        let notes_offset = field(0, 8);
+        assert_eq!(notes_offset, 64 + 3 * 56);
+        assert_eq!(notes_offset + field(0, 32), headers.len() as u64);
+        assert_eq!(&headers[notes_offset as usize + 12..][..5], b"CORE\0");
+
+        assert_eq!(field(1, 8), headers.len() as u64);
+        assert_eq!(field(2, 8), headers.len() as u64 + 0x1000);
+        assert_eq!(field(2, 24), 0x100000);
+        assert_eq!(field(2, 32), 0x200000);
+    }
+
+    #[cfg(guest_arch = "x86_64")]
+    #[test]
+    fn test_windows_header() {
+        let registers = [Registers {
+            cr3: 0x1ad000,
+            rip: 0xfffff80000001234,
+            ..Default::default()
+        }];
+        let crash = CrashInfo {
+            vp: Some(0),
+            bugcheck: Some([0xd1, 1, 2, 3, 4]),
+        };
+        let header = windows::encode_header(&test_ranges(), &registers, &crash).unwrap();
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+
+        assert_eq!(header.len(), 0x2000);
+        assert_eq!(&header[..8], b"PAGEDU64");
+        assert_eq!(u64_at(0x10), 0x1ad000);
+        assert_eq!(&header[0x38..0x3c], &0xd1u32.to_le_bytes());
+        assert_eq!(u64_at(0x58), 4);
+        // Two runs, of 1 and 0x200 pages.
+        assert_eq!(&header[0x88..0x8c], &2u32.to_le_bytes());
+        assert_eq!(u64_at(0x90), 0x201);
+        assert_eq!(u64_at(0xa8), 0x100);
+        assert_eq!(u64_at(0xb0), 0x200);
+        // The context record's RIP.
+        assert_eq!(u64_at(0x348 + 0xf8), 0xfffff80000001234);
+    }
+}
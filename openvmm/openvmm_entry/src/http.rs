@@ -0,0 +1,196 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Minimal HTTP/1.1 support for the diagnostics endpoints.
+//!
+//! Each connection carries a single `GET` request, and is closed after the
+//! response.
+
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use pal_async::DefaultDriver;
+use pal_async::socket::PolledSocket;
+use std::future::Future;
+use std::net::TcpListener;
+use std::net::TcpStream;
+
+/// The longest HTTP request header accepted.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// An HTTP response.
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn error(status: &'static str, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: format!("{message}\n"),
+        }
+    }
+}
+
+/// A parsed request target.
+pub struct Request {
+    /// The decoded path, e.g. `/inspect`.
+    pub path: String,
+    /// The decoded query parameters, in order.
+    pub query: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Returns the value of the first query parameter named `name`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Serves requests on `listener` with `handler`, one connection at a time.
+pub async fn serve<F, Fut>(
+    driver: &DefaultDriver,
+    listener: TcpListener,
+    mut handler: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let mut listener = PolledSocket::new(driver, listener)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let mut stream = PolledSocket::new(driver, stream)?;
+        let response = match read_request(&mut stream).await {
+            Ok(Some(request)) => handler(request).await,
+            Ok(None) => Response::error("405 Method Not Allowed", "only GET is supported"),
+            Err(err) => {
+                tracing::debug!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "failed to read http request"
+                );
+                continue;
+            }
+        };
+        if let Err(err) = write_response(&mut stream, &response).await {
+            tracing::debug!(
+                error = &err as &dyn std::error::Error,
+                "failed to write http response"
+            );
+        }
+    }
+}
+
+/// Reads a request header, returning `None` if it is not a `GET` request.
+async fn read_request(stream: &mut PolledSocket<TcpStream>) -> anyhow::Result<Option<Request>> {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before end of request");
+        }
+        header.extend_from_slice(&buf[..n]);
+        if header.len() > MAX_REQUEST_SIZE {
+            anyhow::bail!("request too large");
+        }
+    }
+    let header = String::from_utf8_lossy(&header);
+    let mut request_line = header.lines().next().unwrap_or("").split(' ');
+    if request_line.next() != Some("GET") {
+        return Ok(None);
+    }
+    Ok(Some(parse_target(request_line.next().unwrap_or("/"))))
+}
+
+async fn write_response(
+    stream: &mut PolledSocket<TcpStream>,
+    response: &Response,
+) -> std::io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.flush().await
+}
+
+fn parse_target(target: &str) -> Request {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Request {
+        path: decode(path),
+        query: query
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let (name, value) = s.split_once('=').unwrap_or((s, ""));
+                (decode(name), decode(value))
+            })
+            .collect(),
+    }
+}
+
+/// Decodes percent-encoded characters, and `+` as a space.
+fn decode(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = bytes.clone().take(2).collect::<Vec<_>>();
+                match std::str::from_utf8(&hex)
+                    .ok()
+                    .filter(|h| h.len() == 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                {
+                    Some(v) => {
+                        out.push(v);
+                        bytes.nth(1);
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        let request = parse_target("/inspect?path=vm%2Fpartition&depth=2&flag");
+        assert_eq!(request.path, "/inspect");
+        assert_eq!(request.param("path"), Some("vm/partition"));
+        assert_eq!(request.param("depth"), Some("2"));
+        assert_eq!(request.param("flag"), Some(""));
+        assert_eq!(request.param("missing"), None);
+
+        let request = parse_target("/a%20b+c%zz%4");
+        assert_eq!(request.path, "/a b c%zz%4");
+        assert!(request.query.is_empty());
+    }
+}
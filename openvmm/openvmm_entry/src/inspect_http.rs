@@ -0,0 +1,91 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An HTTP endpoint serving the inspect tree as JSON.
+//!
+//! `GET /inspect?path=<PATH>&depth=<DEPTH>` returns the node at `PATH` (the
+//! root by default), to `DEPTH` levels (unlimited by default). The path can
+//! also be given in the URL, as in `/inspect/vm/partition`.
+
+use crate::HttpInspectRequest;
+use crate::http;
+use pal_async::DefaultDriver;
+use std::net::TcpListener;
+
+/// Serves inspect requests over HTTP on `listener`, resolving them via
+/// `inspect_send`.
+pub async fn serve(
+    driver: &DefaultDriver,
+    listener: TcpListener,
+    inspect_send: mesh::Sender<HttpInspectRequest>,
+) -> anyhow::Result<()> {
+    http::serve(driver, listener, |request| {
+        let recv = parse_request(&request).map(|(path, depth)| {
+            let (send, recv) = mesh::oneshot();
+            inspect_send.send(HttpInspectRequest {
+                path,
+                depth,
+                result: send,
+            });
+            recv
+        });
+        async move {
+            match recv {
+                Ok(recv) => match recv.await {
+                    Ok(node) => http::Response::ok("application/json", node.json().to_string()),
+                    Err(err) => http::Response::error("503 Service Unavailable", err),
+                },
+                Err(response) => response,
+            }
+        }
+    })
+    .await
+}
+
+/// Returns the inspect path and depth requested.
+fn parse_request(request: &http::Request) -> Result<(String, Option<usize>), http::Response> {
+    let path = if request.path == "/inspect" {
+        request.param("path").unwrap_or("")
+    } else if let Some(path) = request.path.strip_prefix("/inspect/") {
+        path
+    } else {
+        return Err(http::Response::error(
+            "404 Not Found",
+            "expected /inspect?path=<PATH>&depth=<DEPTH>",
+        ));
+    };
+    let depth = request
+        .param("depth")
+        .map(|depth| depth.parse())
+        .transpose()
+        .map_err(|err| http::Response::error("400 Bad Request", format!("invalid depth: {err}")))?;
+    Ok((path.trim_matches('/').to_owned(), depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &str, query: &[(&str, &str)]) -> Result<(String, Option<usize>), &'static str> {
+        let request = http::Request {
+            path: path.into(),
+            query: query.iter().map(|&(n, v)| (n.into(), v.into())).collect(),
+        };
+        parse_request(&request).map_err(|r| r.status)
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(parse("/inspect", &[]), Ok((String::new(), None)));
+        assert_eq!(
+            parse("/inspect", &[("path", "vm/partition"), ("depth", "2")]),
+            Ok(("vm/partition".into(), Some(2)))
+        );
+        assert_eq!(
+            parse("/inspect/vm/partition/", &[]),
+            Ok(("vm/partition".into(), None))
+        );
+        assert_eq!(parse("/inspect", &[("depth", "x")]), Err("400 Bad Request"));
+        assert_eq!(parse("/other", &[]), Err("404 Not Found"));
+    }
+}
@@ -3,7 +3,10 @@
 
 //! Code to handle KVP (Key-Value Pair) operations.
 
+use hyperv_ic_resources::kvp::EnumerateParams;
+use hyperv_ic_resources::kvp::KeyValue;
 use hyperv_ic_resources::kvp::KvpConnectRpc;
+use hyperv_ic_resources::kvp::KvpRpc;
 use mesh::CancelContext;
 use mesh::rpc::RpcSend as _;
 use std::net::Ipv4Addr;
@@ -59,7 +62,7 @@ enum KvpSubcommand {
 }
 
 #[derive(clap::ValueEnum, Clone)]
-enum KvpPool {
+pub(crate) enum KvpPool {
     Guest,
     External,
     Auto,
@@ -88,7 +91,7 @@ async fn handle_subcommand(
     kvp: &mesh::Sender<KvpConnectRpc>,
     command: KvpSubcommand,
 ) -> anyhow::Result<()> {
-    let (kvp, _) = kvp.call_failable(KvpConnectRpc::WaitForGuest, ()).await?;
+    let kvp = connect(kvp).await?;
     match command {
         KvpSubcommand::Set {
             pool,
@@ -109,45 +112,14 @@ async fn handle_subcommand(
             .await?;
         }
         KvpSubcommand::Get { pool, key } => {
-            // Can you believe it? They never implemented the get
-            // operation in the guest. Enumerate instead.
-            let pool = pool_cvt(pool);
-            for i in 0.. {
-                match kvp
-                    .call_failable(
-                        hyperv_ic_resources::kvp::KvpRpc::Enumerate,
-                        hyperv_ic_resources::kvp::EnumerateParams { pool, index: i },
-                    )
-                    .await?
-                {
-                    Some(v) if v.key == key => {
-                        println!("{}", DisplayValue(&v.value));
-                        break;
-                    }
-                    Some(_) => {
-                        // Do nothing, continue searching
-                    }
-                    None => {
-                        anyhow::bail!("not found");
-                    }
-                }
-            }
+            let value = get(&kvp, pool_cvt(pool), &key)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("not found"))?;
+            println!("{}", DisplayValue(&value));
         }
         KvpSubcommand::Enum { pool } => {
-            let pool = pool_cvt(pool);
-            for i in 0.. {
-                match kvp
-                    .call_failable(
-                        hyperv_ic_resources::kvp::KvpRpc::Enumerate,
-                        hyperv_ic_resources::kvp::EnumerateParams { pool, index: i },
-                    )
-                    .await?
-                {
-                    Some(v) => {
-                        println!("{}: {}", v.key, DisplayValue(&v.value));
-                    }
-                    None => break,
-                }
+            for v in enumerate(&kvp, pool_cvt(pool)).await? {
+                println!("{}: {}", v.key, DisplayValue(&v.value));
             }
         }
         KvpSubcommand::IpInfo { adapter_id } => {
@@ -219,7 +191,54 @@ async fn handle_subcommand(
     Ok(())
 }
 
-fn pool_cvt(pool: KvpPool) -> hyperv_ic_resources::kvp::KvpPool {
+/// Waits for the guest to connect to the KVP IC.
+pub(crate) async fn connect(
+    kvp: &mesh::Sender<KvpConnectRpc>,
+) -> anyhow::Result<mesh::Sender<KvpRpc>> {
+    let (kvp, _) = kvp.call_failable(KvpConnectRpc::WaitForGuest, ()).await?;
+    Ok(kvp)
+}
+
+/// Returns all the key/value pairs in `pool`.
+pub(crate) async fn enumerate(
+    kvp: &mesh::Sender<KvpRpc>,
+    pool: hyperv_ic_resources::kvp::KvpPool,
+) -> anyhow::Result<Vec<KeyValue>> {
+    let mut values = Vec::new();
+    for index in 0.. {
+        match kvp
+            .call_failable(KvpRpc::Enumerate, EnumerateParams { pool, index })
+            .await?
+        {
+            Some(v) => values.push(v),
+            None => break,
+        }
+    }
+    Ok(values)
+}
+
+/// Gets the value of `key` in `pool`.
+pub(crate) async fn get(
+    kvp: &mesh::Sender<KvpRpc>,
+    pool: hyperv_ic_resources::kvp::KvpPool,
+    key: &str,
+) -> anyhow::Result<Option<hyperv_ic_resources::kvp::Value>> {
+    // Can you believe it? They never implemented the get operation in the
+    // guest. Enumerate instead.
+    for index in 0.. {
+        match kvp
+            .call_failable(KvpRpc::Enumerate, EnumerateParams { pool, index })
+            .await?
+        {
+            Some(v) if v.key == key => return Ok(Some(v.value)),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+pub(crate) fn pool_cvt(pool: KvpPool) -> hyperv_ic_resources::kvp::KvpPool {
     match pool {
         KvpPool::Guest => hyperv_ic_resources::kvp::KvpPool::Guest,
         KvpPool::External => hyperv_ic_resources::kvp::KvpPool::External,
@@ -7,38 +7,73 @@
 #![expect(missing_docs)]
 #![cfg_attr(not(test), forbid(unsafe_code))]
 
+mod battery;
 mod cli_args;
+mod config_file;
+mod console_mux;
+#[cfg(guest_arch = "x86_64")]
+mod cpuid;
 mod crash_dump;
+mod fcopy;
+mod guest_dump;
+mod http;
+mod inspect_http;
 mod kvp;
 mod meshworker;
+mod metrics;
+mod migrate;
+mod otlp;
+mod profile;
+mod qmp;
+mod screendump;
 mod serial_io;
+mod serial_log;
+mod serial_ws;
+mod snapshot;
+mod state_dump;
 mod storage_builder;
 mod tracing_init;
 mod ttrpc;
+mod uefi_boot_order;
+mod uefi_boot_volume;
+#[cfg(target_os = "linux")]
+mod vfio;
+mod vss;
 
 // `pub` so that the missing_docs warning fires for options without
 // documentation.
 pub use cli_args::Options;
 use console_relay::ConsoleLaunchOptions;
 
+use crate::cli_args::ClockPolicyCli;
+use crate::cli_args::GuestCrashActionCli;
+use crate::cli_args::GuestCrashDumpFormatCli;
 use crate::cli_args::SecureBootTemplateCli;
 use anyhow::Context;
 use anyhow::bail;
-use chipset_resources::battery::HostBatteryUpdate;
+use chipset_resources::pci_hotplug::PciHotPlugRequest;
+use chipset_resources::pvpanic::PvPanicEvent;
 use clap::CommandFactory;
 use clap::FromArgMatches;
 use clap::Parser;
+use cli_args::AudioCli;
 use cli_args::DiskCliKind;
 use cli_args::EndpointConfigCli;
+use cli_args::MemPolicyCli;
+use cli_args::MemoryBackingCli;
+use cli_args::MigrationAddressCli;
 use cli_args::NicConfigCli;
 use cli_args::ProvisionVmgs;
 use cli_args::SerialConfigCli;
+use cli_args::SleepStateCli;
+use cli_args::SmbiosCli;
 use cli_args::UefiConsoleModeCli;
 use cli_args::VirtioBusCli;
 use cli_args::VmgsCli;
 use crash_dump::spawn_dump_handler;
 use disk_backend_resources::DelayDiskHandle;
 use disk_backend_resources::DiskLayerDescription;
+use disk_backend_resources::SnapshotDiskRequest;
 use disk_backend_resources::layer::DiskLayerHandle;
 use disk_backend_resources::layer::RamDiskLayerHandle;
 use disk_backend_resources::layer::SqliteAutoCacheDiskLayerHandle;
@@ -47,8 +82,6 @@
 use framebuffer::FRAMEBUFFER_SIZE;
 use framebuffer::FramebufferAccess;
 use futures::AsyncReadExt;
-use futures::AsyncWrite;
-use futures::AsyncWriteExt;
 use futures::FutureExt;
 use futures::StreamExt;
 use futures::executor::block_on;
@@ -65,12 +98,22 @@
 use hvlite_defs::config::DEFAULT_MMIO_GAPS_X86_WITH_VTL2;
 use hvlite_defs::config::DEFAULT_PCAT_BOOT_ORDER;
 use hvlite_defs::config::DeviceVtl;
+use hvlite_defs::config::E1000NicConfig;
+use hvlite_defs::config::GuestAccessTraceConfig;
 use hvlite_defs::config::HypervisorConfig;
+use hvlite_defs::config::IoTraceConfig;
+use hvlite_defs::config::IvshmemConfig;
 use hvlite_defs::config::LateMapVtl0MemoryPolicy;
 use hvlite_defs::config::LoadMode;
+use hvlite_defs::config::MemoryBackingFile;
 use hvlite_defs::config::MemoryConfig;
+use hvlite_defs::config::MemoryNumaPolicy;
+use hvlite_defs::config::NvmeSriovConfig;
+use hvlite_defs::config::PciSerialCardConfig;
+use hvlite_defs::config::PciSlotConfig;
 use hvlite_defs::config::ProcessorTopologyConfig;
 use hvlite_defs::config::SerialInformation;
+use hvlite_defs::config::SmbiosConfig;
 use hvlite_defs::config::VirtioBus;
 use hvlite_defs::config::VmbusConfig;
 use hvlite_defs::config::VpciDeviceConfig;
@@ -81,11 +124,15 @@
 use hvlite_defs::worker::VM_WORKER;
 use hvlite_defs::worker::VmWorkerParameters;
 use hvlite_helpers::disk::create_disk_type;
+use hvlite_helpers::disk::create_vhdx;
 use hvlite_helpers::disk::open_disk_type;
+use hvlite_helpers::disk::open_vhd_set;
+use hvlite_helpers::disk::open_vhdx;
 use input_core::MultiplexedInputHandle;
 use inspect::InspectMut;
 use inspect::InspectionBuilder;
 use io::Read;
+use memory_range::MemoryRange;
 use mesh::CancelContext;
 use mesh::CellUpdater;
 use mesh::error::RemoteError;
@@ -97,6 +144,8 @@
 use mesh_worker::launch_local_worker;
 use meshworker::VmmMesh;
 use net_backend_resources::mac_address::MacAddress;
+use nvme_resources::NamespaceDefinition;
+use nvme_resources::NvmeControllerRequest;
 use pal_async::DefaultDriver;
 use pal_async::DefaultPool;
 use pal_async::pipe::PolledPipe;
@@ -104,13 +153,19 @@
 use pal_async::task::Spawn;
 use pal_async::task::Task;
 use pal_async::timer::PolledTimer;
+use profile::ProfileOptions;
+use profile::Profiler;
 use scsidisk_resources::SimpleScsiDiskHandle;
 use scsidisk_resources::SimpleScsiDvdHandle;
 use serial_16550_resources::ComPort;
+use serial_16550_resources::MmioOrIoPort;
+use serial_16550_resources::Serial16550DeviceHandle;
 use serial_core::resources::DisconnectedSerialBackendHandle;
 use serial_io::SerialIo;
 use sparse_mmap::alloc_shared_memory;
+use std::cell::Cell;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Write as _;
 use std::future::pending;
 use std::io;
@@ -187,19 +242,47 @@ pub fn hvlite_main() {
 
 #[derive(Default)]
 struct VmResources {
-    console_in: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    console_mux: Option<console_mux::ConsoleMux>,
     framebuffer_access: Option<FramebufferAccess>,
+    video_resolution: Option<mesh::Sender<(u16, u16)>>,
     shutdown_ic: Option<mesh::Sender<hyperv_ic_resources::shutdown::ShutdownRpc>>,
     kvp_ic: Option<mesh::Sender<hyperv_ic_resources::kvp::KvpConnectRpc>>,
+    fcopy_ic: Option<mesh::Sender<hyperv_ic_resources::fcopy::FcopyRpc>>,
+    vss_ic: Option<mesh::Sender<hyperv_ic_resources::vss::VssRpc>>,
+    battery: Option<battery::BatteryControl>,
+    generation_id: Option<mesh::Sender<[u8; 16]>>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
+    /// Snapshot request channels for writable VTL0 SCSI disks, by LUN, if
+    /// disk snapshots are enabled.
+    scsi_snapshots: Option<HashMap<u8, mesh::Sender<SnapshotDiskRequest>>>,
+    nvme_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
+    nvme_vtl2_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
     ged_rpc: Option<mesh::Sender<get_resources::ged::GuestEmulationRequest>>,
+    pci_hotplug: Option<mesh::Sender<PciHotPlugRequest>>,
+    /// The VPCI instance IDs of the MANA virtual functions.
+    mana_vfs: Vec<Guid>,
+    guest_crash: Option<mesh::Receiver<vmm_core_defs::GuestCrash>>,
+    pvpanic: Option<mesh::Receiver<PvPanicEvent>>,
     #[cfg(windows)]
     switch_ports: Vec<vmswitch::kernel::SwitchPort>,
 }
 
-struct ConsoleState<'a> {
-    device: &'a str,
-    input: Box<dyn AsyncWrite + Unpin + Send>,
+impl VmResources {
+    /// Gives the guest a new random VM generation ID, telling it that it may
+    /// have been reverted to a snapshot or cloned.
+    fn update_generation_id(&self) {
+        let mut generation_id = [0; 16];
+        getrandom::fill(&mut generation_id).expect("rng failure");
+        tracing::info!("updating vm generation id");
+        if let Some(send) = &self.generation_id {
+            send.send(generation_id);
+        }
+        // With VTL2, OpenHCL's firmware reports the generation ID.
+        if let Some(ged_rpc) = &self.ged_rpc {
+            ged_rpc
+                .send(get_resources::ged::GuestEmulationRequest::UpdateGenerationId(generation_id));
+        }
+    }
 }
 
 fn vm_config_from_command_line(
@@ -216,26 +299,35 @@ fn vm_config_from_command_line(
         DeviceVtl::Vtl0
     };
 
-    let console_state: RefCell<Option<ConsoleState<'_>>> = RefCell::new(None);
+    // Additional serial ports, in port number order.
+    let mut extra_serial = opt.extra_serial.clone();
+    extra_serial.sort_by_key(|cfg| cfg.index);
+    if let Some(w) = extra_serial.windows(2).find(|w| w[0].index == w[1].index) {
+        bail!("serial port {} specified more than once", w[0].index);
+    }
+    let extra_serial_names: Vec<_> = extra_serial
+        .iter()
+        .map(|cfg| format!("com{}", cfg.index))
+        .collect();
+
+    // Every port connected to the console shares the terminal through the
+    // multiplexer. The first one is the guest kernel's console.
+    let console_mux = RefCell::new(console_mux::ConsoleMux::new(term::raw_stdout()));
+    let console_device = Cell::new(None);
     let setup_serial = |name: &str, cli_cfg, device| -> anyhow::Result<_> {
         Ok(match cli_cfg {
             SerialConfigCli::Console => {
-                if let Some(console_state) = console_state.borrow().as_ref() {
-                    bail!("console already set by {}", console_state.device);
-                }
                 let (config, serial) = serial_io::anonymous_serial_pair(&serial_driver)?;
                 let (serial_read, serial_write) = AsyncReadExt::split(serial);
-                *console_state.borrow_mut() = Some(ConsoleState {
-                    device,
-                    input: Box::new(serial_write),
-                });
+                let output = console_mux.borrow_mut().add(name, Box::new(serial_write));
+                if console_device.get().is_none() {
+                    console_device.set(Some(device));
+                }
                 thread::Builder::new()
                     .name(name.to_owned())
                     .spawn(move || {
-                        let _ = block_on(futures::io::copy(
-                            serial_read,
-                            &mut AllowStdIo::new(term::raw_stdout()),
-                        ));
+                        let _ =
+                            block_on(futures::io::copy(serial_read, &mut AllowStdIo::new(output)));
                     })
                     .unwrap();
                 Some(config)
@@ -253,9 +345,10 @@ fn vm_config_from_command_line(
                     .unwrap();
                 Some(config)
             }
-            SerialConfigCli::File(path) => {
+            SerialConfigCli::File(path, options) => {
                 let (config, serial) = serial_io::anonymous_serial_pair(&serial_driver)?;
-                let file = fs_err::File::create(path).context("failed to create file")?;
+                let file = serial_log::SerialLogFile::create(path, options)
+                    .context("failed to create file")?;
 
                 thread::Builder::new()
                     .name(name.to_owned())
@@ -272,6 +365,14 @@ fn vm_config_from_command_line(
             SerialConfigCli::Tcp(addr) => {
                 Some(serial_io::bind_tcp_serial(&addr).context("failed to bind serial")?)
             }
+            SerialConfigCli::TcpConnect { addr, retry } => {
+                Some(serial_io::connect_tcp_serial(&addr, retry))
+            }
+            SerialConfigCli::WebSocket(addr) => Some(
+                serial_ws::bind_ws_serial(&serial_driver, name, &addr)
+                    .context("failed to bind websocket serial")?,
+            ),
+            SerialConfigCli::Pty => Some(serial_io::open_pty_serial(name)?),
             SerialConfigCli::NewConsole(app, window_title) => {
                 let path = console_relay::random_console_path();
                 let config =
@@ -297,15 +398,15 @@ fn vm_config_from_command_line(
     let setup_serial_virtio = |name, cli_cfg, device| -> anyhow::Result<_> {
         Ok(match cli_cfg {
             SerialConfigCli::Console => {
-                if console_state.borrow().is_some() {
-                    bail!("console already set");
-                }
                 let mut io = SerialIo::new().context("creating serial IO")?;
-                io.spawn_copy_out(name, term::raw_stdout());
-                *console_state.borrow_mut() = Some(ConsoleState {
-                    device,
-                    input: Box::new(PolledPipe::new(&serial_driver, io.input.unwrap())?),
-                });
+                let output = console_mux.borrow_mut().add(
+                    name,
+                    Box::new(PolledPipe::new(&serial_driver, io.input.take().unwrap())?),
+                );
+                io.spawn_copy_out(name, output);
+                if console_device.get().is_none() {
+                    console_device.set(Some(device));
+                }
                 Some(io.config)
             }
             SerialConfigCli::Stderr => {
@@ -316,9 +417,10 @@ fn vm_config_from_command_line(
                 io.config.input = None;
                 Some(io.config)
             }
-            SerialConfigCli::File(path) => {
+            SerialConfigCli::File(path, options) => {
                 let mut io = SerialIo::new().context("creating serial IO")?;
-                let file = fs_err::File::create(path).context("failed to create file")?;
+                let file = serial_log::SerialLogFile::create(path, options)
+                    .context("failed to create file")?;
                 io.spawn_copy_out(name, file);
                 // Ensure there is no input so that the serial devices don't see
                 // EOF and think the port is disconnected.
@@ -334,6 +436,13 @@ fn vm_config_from_command_line(
                 Some(io.config)
             }
             SerialConfigCli::Tcp(_addr) => anyhow::bail!("TCP virtio serial not supported"),
+            SerialConfigCli::TcpConnect { .. } => {
+                anyhow::bail!("TCP virtio serial not supported")
+            }
+            SerialConfigCli::WebSocket(_addr) => {
+                anyhow::bail!("WebSocket virtio serial not supported")
+            }
+            SerialConfigCli::Pty => anyhow::bail!("pty virtio serial not supported"),
             SerialConfigCli::NewConsole(app, window_title) => {
                 let path = console_relay::random_console_path();
 
@@ -403,6 +512,29 @@ fn vm_config_from_command_line(
             "ttyAMA3"
         },
     )?;
+    let mut extra_isa_serial = Vec::new();
+    let mut pci_serial_ports = Vec::new();
+    for (cfg, name) in extra_serial.into_iter().zip(&extra_serial_names) {
+        let backend = setup_serial(name, cfg.serial, name)?
+            .unwrap_or_else(|| DisconnectedSerialBackendHandle.into_resource());
+        match cfg.isa {
+            Some((io_port, irq)) => extra_isa_serial.push((name, io_port, irq, backend)),
+            None => pci_serial_ports.push(backend),
+        }
+    }
+    let mut pci_serial_cards = Vec::new();
+    let mut pci_serial_ports = pci_serial_ports.into_iter().peekable();
+    while pci_serial_ports.peek().is_some() {
+        // Cards come in one, two, and four port variants.
+        let mut ports: Vec<_> = pci_serial_ports.by_ref().take(4).collect();
+        ports.resize_with(ports.len().next_power_of_two(), || {
+            DisconnectedSerialBackendHandle.into_resource()
+        });
+        pci_serial_cards.push(PciSerialCardConfig {
+            ports,
+            wait_for_rts: false,
+        });
+    }
     let virtio_serial_cfg = setup_serial_virtio(
         "virtio_serial",
         opt.virtio_serial.clone().unwrap_or({
@@ -466,10 +598,13 @@ fn vm_config_from_command_line(
     )?;
 
     let mut resources = VmResources::default();
-    let mut console_str = "";
-    if let Some(ConsoleState { device, input }) = console_state.into_inner() {
-        resources.console_in = Some(input);
-        console_str = device;
+    let console_str = console_device.get().unwrap_or("");
+    let console_mux = console_mux.into_inner();
+    if !console_mux.is_empty() {
+        if console_mux.len() > 1 {
+            tracing::info!("multiple consoles attached, press Ctrl-A h for help");
+        }
+        resources.console_mux = Some(console_mux);
     }
 
     if opt.shared_memory {
@@ -495,6 +630,23 @@ fn vm_config_from_command_line(
     let with_get = opt.get || (opt.vtl2 && !opt.no_get);
 
     let mut storage = storage_builder::StorageBuilder::new(with_get.then_some(openhcl_vtl));
+    // Disk snapshots are only available through QMP.
+    if opt.qmp.is_some() {
+        storage.enable_snapshots();
+    }
+    if let Some(path) = &opt.uefi_boot_file {
+        // Attach the boot volume first so that it is the first device the
+        // firmware tries during a default boot.
+        let file = uefi_boot_volume::build(path)
+            .with_context(|| format!("failed to build boot volume for {}", path.display()))?;
+        storage.add_disk(
+            DeviceVtl::Vtl0,
+            storage_builder::DiskLocation::Scsi(None),
+            Resource::new(disk_backend_resources::FileDiskHandle(file)),
+            false,
+            true,
+        )?;
+    }
     for &cli_args::DiskCli {
         vtl,
         ref kind,
@@ -549,6 +701,45 @@ fn vm_config_from_command_line(
         )?;
     }
 
+    for &cli_args::DiskCli {
+        vtl,
+        ref kind,
+        read_only,
+        is_dvd,
+        underhill,
+    } in &opt.usb_storage
+    {
+        if underhill.is_some() {
+            anyhow::bail!("usb storage cannot be relayed through Underhill");
+        }
+        storage.add(
+            vtl,
+            None,
+            storage_builder::DiskLocation::Usb,
+            kind,
+            is_dvd,
+            read_only,
+        )?;
+    }
+
+    let usb_devices = opt
+        .usb_host
+        .iter()
+        .map(|device| -> anyhow::Result<_> {
+            #[cfg(target_os = "linux")]
+            {
+                let file = open_usb_host(device)?;
+                Ok(usb_resources::UsbHostDeviceHandle { file }.into_resource())
+            }
+
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = device;
+                bail!("cannot pass through host usb devices on non-linux platforms")
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
     let floppy_disks: Vec<_> = opt
         .floppy
         .iter()
@@ -564,11 +755,20 @@ fn vm_config_from_command_line(
         })
         .collect::<Result<Vec<_>, _>>()?;
 
+    // Arbitrary but constant instance IDs for the MANA NIC of each VTL, so that
+    // the guest sees them at the same location across boots.
+    const MANA_INSTANCE_IDS: [Guid; 3] = [
+        guid::guid!("2c5b3f0e-6a1d-4b8e-9f27-5d4c8e1a7b30"),
+        guid::guid!("9a3e7d21-4c6b-4f0a-8d15-b2e6f9c4a871"),
+        guid::guid!("d7f1a4c8-3b2e-4e9d-a6c0-81f5e2b9d34c"),
+    ];
     let mut mana_nics = [(); 3].map(|()| None);
     let mut underhill_nics = Vec::new();
     let mut vpci_devices = Vec::new();
 
     let mut nic_index = 0;
+    // The VTL0 VMBus NICs, for UEFI network boot entries.
+    let mut vtl0_vmbus_nics = Vec::new();
     for cli_cfg in &opt.net {
         let vport = parse_endpoint(cli_cfg, &mut nic_index, &mut resources)?;
         if cli_cfg.underhill {
@@ -576,7 +776,7 @@ fn vm_config_from_command_line(
                 anyhow::bail!("must specify --no-alias-map to offer NICs to VTL2");
             }
             let mana = mana_nics[openhcl_vtl as usize].get_or_insert_with(|| {
-                let vpci_instance_id = Guid::new_random();
+                let vpci_instance_id = MANA_INSTANCE_IDS[openhcl_vtl as usize];
                 underhill_nics.push(vtl2_settings_proto::NicDeviceLegacy {
                     instance_id: vpci_instance_id.to_string(),
                     subordinate_instance_id: None,
@@ -589,6 +789,9 @@ fn vm_config_from_command_line(
                 endpoint: vport.endpoint,
             });
         } else {
+            if vport.vtl == DeviceVtl::Vtl0 {
+                vtl0_vmbus_nics.push((vport.instance_id, vport.mac_address));
+            }
             vmbus_devices.push(vport.into_netvsp_handle());
         }
     }
@@ -597,13 +800,21 @@ fn vm_config_from_command_line(
         let nic_config = parse_endpoint(
             &NicConfigCli {
                 vtl: DeviceVtl::Vtl0,
-                endpoint: EndpointConfigCli::Consomme { cidr: None },
+                endpoint: EndpointConfigCli::Consomme {
+                    cidr: None,
+                    dhcp: Default::default(),
+                },
                 max_queues: None,
                 underhill: false,
+                mac_address: None,
+                rate_mbps: None,
+                latency_ms: None,
+                loss_percent: None,
             },
             &mut nic_index,
             &mut resources,
         )?;
+        vtl0_vmbus_nics.push((nic_config.instance_id, nic_config.mac_address));
         vmbus_devices.push(nic_config.into_netvsp_handle());
     }
 
@@ -656,7 +867,12 @@ fn vm_config_from_command_line(
     for vport in &opt.mana {
         let vport = parse_endpoint(vport, &mut nic_index, &mut resources)?;
         mana_nics[vport.vtl as usize]
-            .get_or_insert_with(|| (Guid::new_random(), GdmaDeviceHandle { vports: Vec::new() }))
+            .get_or_insert_with(|| {
+                (
+                    MANA_INSTANCE_IDS[vport.vtl as usize],
+                    GdmaDeviceHandle { vports: Vec::new() },
+                )
+            })
             .1
             .vports
             .push(VportDefinition {
@@ -665,6 +881,54 @@ fn vm_config_from_command_line(
             });
     }
 
+    // Arbitrary but constant, varying the part of the ID that determines the
+    // device ID.
+    const MANA_VF_BASE_INSTANCE_ID: Guid = guid::guid!("6e0a9c52-0000-4f1b-8d37-a41c5e9b2f60");
+    for (index, cli_cfg) in opt.mana_vf.iter().enumerate() {
+        let vport = parse_endpoint(cli_cfg, &mut nic_index, &mut resources)?;
+        let instance_id = Guid {
+            data2: index as u16,
+            ..MANA_VF_BASE_INSTANCE_ID
+        };
+        let vtl = if cli_cfg.underhill {
+            if !opt.no_alias_map {
+                anyhow::bail!("must specify --no-alias-map to offer NICs to VTL2");
+            }
+            underhill_nics.push(vtl2_settings_proto::NicDeviceLegacy {
+                instance_id: instance_id.to_string(),
+                subordinate_instance_id: None,
+                max_sub_channels: None,
+            });
+            openhcl_vtl
+        } else {
+            vport.vtl
+        };
+        resources.mana_vfs.push(instance_id);
+        vpci_devices.push(VpciDeviceConfig {
+            vtl,
+            instance_id,
+            resource: GdmaDeviceHandle {
+                vports: vec![VportDefinition {
+                    mac_address: vport.mac_address,
+                    endpoint: vport.endpoint,
+                }],
+            }
+            .into_resource(),
+        });
+    }
+
+    let mut e1000_nics = Vec::new();
+    for cli_cfg in &opt.e1000 {
+        if cli_cfg.underhill || cli_cfg.vtl != DeviceVtl::Vtl0 {
+            anyhow::bail!("e1000 NICs can only be assigned to VTL0");
+        }
+        let nic = parse_endpoint(cli_cfg, &mut nic_index, &mut resources)?;
+        e1000_nics.push(E1000NicConfig {
+            mac_address: nic.mac_address,
+            endpoint: nic.endpoint,
+        });
+    }
+
     vpci_devices.extend(mana_nics.into_iter().enumerate().filter_map(|(vtl, nic)| {
         nic.map(|(instance_id, handle)| VpciDeviceConfig {
             vtl: match vtl {
@@ -678,6 +942,45 @@ fn vm_config_from_command_line(
         })
     }));
 
+    #[cfg(target_os = "linux")]
+    let vfio_devices = opt
+        .vfio
+        .iter()
+        .enumerate()
+        .map(|(index, cli)| {
+            let mut device = vfio::open_device(&cli.path)
+                .with_context(|| format!("failed to open vfio device {}", cli.path.display()))?;
+            if cli.vtl == DeviceVtl::Vtl2 {
+                if !opt.vtl2 || !opt.no_alias_map {
+                    anyhow::bail!(
+                        "must specify --vtl2 and --no-alias-map to assign devices to VTL2"
+                    );
+                }
+                // Arbitrary but constant, varying the part of the ID that
+                // determines the VPCI device ID.
+                const BASE_INSTANCE_ID: Guid = guid::guid!("5f2d8c91-0000-4a7e-b3c6-0e9f4d1a2b58");
+                let instance_id = Guid {
+                    data2: index as u16,
+                    ..BASE_INSTANCE_ID
+                };
+                for &nsid in &cli.underhill_nvme_namespaces {
+                    storage.add_underhill_assigned_nvme(instance_id, nsid)?;
+                }
+                if cli.underhill_mana {
+                    underhill_nics.push(vtl2_settings_proto::NicDeviceLegacy {
+                        instance_id: instance_id.to_string(),
+                        subordinate_instance_id: None,
+                        max_sub_channels: None,
+                    });
+                }
+                device.vtl2_instance_id = Some(instance_id);
+            }
+            Ok(device)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    #[cfg(not(target_os = "linux"))]
+    let vfio_devices = Vec::new();
+
     #[cfg(windows)]
     let vpci_resources: Vec<_> = opt
         .device
@@ -755,14 +1058,38 @@ fn vm_config_from_command_line(
     }
     if opt.battery {
         let (tx, rx) = mesh::channel();
-        tx.send(HostBatteryUpdate::default_present());
+        resources.battery = Some(battery::BatteryControl::new(tx));
         chipset = chipset.with_battery(rx);
     }
+    if !opt.pci_hotplug_slots.is_empty() {
+        let (request_send, request_recv) = mesh::channel();
+        let (eject_send, mut eject_recv) = mesh::channel::<u8>();
+        spawner
+            .spawn("pci-hotplug-eject", async move {
+                while let Some(device) = eject_recv.next().await {
+                    tracing::info!(device, "guest ejected pci slot");
+                }
+            })
+            .detach();
+        resources.pci_hotplug = Some(request_send);
+        chipset = chipset.with_pci_hotplug(request_recv, eject_send);
+    }
+    if opt.pvpanic {
+        let (event_send, event_recv) = mesh::channel();
+        resources.pvpanic = Some(event_recv);
+        chipset = chipset.with_pvpanic(event_send);
+    }
+    if opt.on_guest_crash == GuestCrashActionCli::Dump && opt.guest_crash_dump_dir.is_none() {
+        anyhow::bail!("--on-guest-crash dump requires --guest-crash-dump-dir");
+    }
     if let Some(cfg) = &opt.debugcon {
         chipset = chipset.with_debugcon(
             debugcon_cfg.unwrap_or_else(|| DisconnectedSerialBackendHandle.into_resource()),
             cfg.port,
         );
+        if let Some(trace) = &cfg.trace {
+            chipset = chipset.with_debugcon_trace(trace.clone());
+        }
     }
 
     let VmChipsetResult {
@@ -772,6 +1099,52 @@ fn vm_config_from_command_line(
         .build()
         .context("failed to build chipset configuration")?;
 
+    if !opt.pci_hotplug_slots.is_empty() && !chipset.with_generic_pci_bus {
+        anyhow::bail!("--pci-hotplug-slots requires a PCI bus");
+    }
+
+    if opt.pci_bridges != 0 && !chipset.with_generic_pci_bus {
+        anyhow::bail!("--pci-bridges requires the generic PCI bus");
+    }
+
+    if !pci_serial_cards.is_empty() && !chipset.with_generic_pci_bus && !chipset.with_piix4_pci_bus
+    {
+        anyhow::bail!("PCI serial ports require a PCI bus");
+    }
+
+    if !opt.pci_slot.is_empty() && !chipset.with_generic_pci_bus && !chipset.with_piix4_pci_bus {
+        anyhow::bail!("--pci-slot requires a PCI bus");
+    }
+
+    if !opt.ivshmem.is_empty() && !chipset.with_generic_pci_bus && !chipset.with_piix4_pci_bus {
+        anyhow::bail!("--ivshmem requires a PCI bus");
+    }
+
+    #[cfg(target_os = "linux")]
+    if opt.vfio.iter().any(|cli| cli.vtl == DeviceVtl::Vtl0)
+        && !chipset.with_generic_pci_bus
+        && !chipset.with_piix4_pci_bus
+    {
+        anyhow::bail!("--vfio requires a PCI bus");
+    }
+
+    if !extra_isa_serial.is_empty() && !is_x86 {
+        anyhow::bail!("serial ports with an io port are only supported on x86");
+    }
+    for (name, io_port, irq, backend) in extra_isa_serial {
+        chipset_devices.push(ChipsetDeviceHandle {
+            name: format!("serial-{name}"),
+            resource: Serial16550DeviceHandle {
+                base: MmioOrIoPort::IoPort(io_port),
+                register_width: 1,
+                irq,
+                io: backend,
+                wait_for_rts: false,
+            }
+            .into_resource(),
+        });
+    }
+
     if let Some(path) = &opt.igvm {
         let file = fs_err::File::open(path)
             .context("failed to open igvm file")?
@@ -815,6 +1188,20 @@ fn vm_config_from_command_line(
         )
         .context("failed to open uefi firmware")?;
 
+        let nvram = opt
+            .uefi_nvram
+            .as_ref()
+            .map(|path| {
+                fs_err::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .context("failed to open uefi nvram file")
+            })
+            .transpose()?;
+
         // TODO: It would be better to default memory protections to on, but currently Linux does not boot via UEFI due to what
         //       appears to be a GRUB memory protection fault. Memory protections are therefore only enabled if configured.
         load_mode = LoadMode::Uefi {
@@ -832,7 +1219,9 @@ fn vm_config_from_command_line(
                 UefiConsoleModeCli::Com2 => UefiConsoleMode::Com2,
                 UefiConsoleModeCli::None => UefiConsoleMode::None,
             }),
-            default_boot_always_attempt: opt.default_boot_always_attempt,
+            default_boot_always_attempt: opt.default_boot_always_attempt
+                || opt.uefi_boot_file.is_some(),
+            nvram: nvram.map(Into::into),
         };
     } else {
         // Linux Direct
@@ -886,6 +1275,15 @@ fn vm_config_from_command_line(
         };
     }
 
+    if opt
+        .smbios
+        .iter()
+        .any(|field| matches!(field, SmbiosCli::Oem(_)))
+        && !(is_x86 && matches!(load_mode, LoadMode::Linux { .. }))
+    {
+        anyhow::bail!("SMBIOS OEM strings are only supported for Linux direct boot on x86");
+    }
+
     let mut vmgs = Some(if let Some(VmgsCli { kind, provision }) = &opt.vmgs {
         let disk = disk_open(kind, false).context("failed to open vmgs disk")?;
         match provision {
@@ -909,6 +1307,9 @@ fn vm_config_from_command_line(
         };
 
         let (send, guest_request_recv) = mesh::channel();
+        if let Some(battery) = &mut resources.battery {
+            battery.set_ged(send.clone());
+        }
         resources.ged_rpc = Some(send);
 
         let vmgs = vmgs.take().unwrap();
@@ -954,7 +1355,8 @@ fn vm_config_from_command_line(
                                 UefiConsoleModeCli::Com2 => UefiConsoleMode::COM2,
                                 UefiConsoleModeCli::None => UefiConsoleMode::None,
                             },
-                            default_boot_always_attempt: opt.default_boot_always_attempt,
+                            default_boot_always_attempt: opt.default_boot_always_attempt
+                                || opt.uefi_boot_file.is_some(),
                         }
                     },
                     com1: with_vmbus_com1_serial,
@@ -1018,6 +1420,24 @@ fn vm_config_from_command_line(
                 register_layout,
                 guest_secret_key: None,
                 logger: None,
+                pcr_banks: opt.tpm_pcr_banks.as_ref().map(|banks| {
+                    banks
+                        .iter()
+                        .map(|bank| match bank {
+                            cli_args::PcrBankCli::Sha1 => 1 << 0,
+                            cli_args::PcrBankCli::Sha256 => 1 << 1,
+                            cli_args::PcrBankCli::Sha384 => 1 << 2,
+                        })
+                        .fold(0, |a, b| a | b)
+                }),
+                pcr_seeds: opt
+                    .tpm_pcr_seed
+                    .iter()
+                    .map(|seed| tpm_resources::TpmPcrSeed {
+                        pcr: seed.pcr,
+                        event_data: seed.event_data.clone(),
+                    })
+                    .collect(),
             }
             .into_resource(),
         });
@@ -1063,13 +1483,34 @@ fn vm_config_from_command_line(
         };
 
         // obtain the final custom uefi vars by applying the delta onto the base vars
-        match custom_uefi_json_data {
+        let vars = match custom_uefi_json_data {
             Some(data) => {
                 let delta = hyperv_uefi_custom_vars_json::load_delta_from_json(&data)?;
                 base_vars.apply_delta(delta)?
             }
             None => base_vars,
+        };
+
+        // then enroll any keys specified on the command line
+        let keys = secure_boot_keys(opt)?;
+        let mut vars = if keys.is_empty() {
+            vars
+        } else {
+            let has_base = vars.signatures.is_some();
+            vars.apply_delta(keys.into_delta(has_base)?)?
+        };
+
+        // and finally the boot order, replacing any existing boot entries
+        if let Some(order) = &opt.uefi_boot_order {
+            let entries = uefi_boot_entries(order, &storage, &vtl0_vmbus_nics);
+            for (name, var) in uefi_boot_order::boot_vars(&entries) {
+                match vars.custom_vars.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, old)) => *old = var,
+                    None => vars.custom_vars.push((name, var)),
+                }
+            }
         }
+        vars
     };
 
     let vga_firmware = if opt.pcat {
@@ -1081,11 +1522,14 @@ fn vm_config_from_command_line(
     };
 
     if opt.gfx {
+        let (resolution_send, resolution_recv) = mesh::channel();
+        resources.video_resolution = Some(resolution_send);
         vmbus_devices.extend([
             (
                 DeviceVtl::Vtl0,
                 SynthVideoHandle {
                     framebuffer: SharedFramebufferHandle.into_resource(),
+                    resolution_recv: Some(resolution_recv),
                 }
                 .into_resource(),
             ),
@@ -1130,21 +1574,15 @@ fn vm_config_from_command_line(
 
     // If VTL2 is enabled, and we are not in VTL2 self allocate mode, provide an
     // mmio gap for VTL2.
-    let mmio_gaps = if opt.vtl2
-        && !matches!(
-            opt.igvm_vtl2_relocation_type,
-            Vtl2BaseAddressType::Vtl2Allocate { .. },
-        ) {
-        if is_x86 {
-            DEFAULT_MMIO_GAPS_X86_WITH_VTL2.into()
-        } else {
-            DEFAULT_MMIO_GAPS_AARCH64_WITH_VTL2.into()
-        }
-    } else if is_x86 {
-        DEFAULT_MMIO_GAPS_X86.into()
-    } else {
-        DEFAULT_MMIO_GAPS_AARCH64.into()
-    };
+    let mmio_gaps = mmio_gaps(
+        opt,
+        is_x86,
+        opt.vtl2
+            && !matches!(
+                opt.igvm_vtl2_relocation_type,
+                Vtl2BaseAddressType::Vtl2Allocate { .. },
+            ),
+    )?;
 
     if let Some(path) = &opt.openhcl_dump_path {
         let (resource, task) = spawn_dump_handler(&spawner, path.clone(), None);
@@ -1189,6 +1627,10 @@ fn vm_config_from_command_line(
         resources.shutdown_ic = Some(shutdown_send);
         let (kvp_send, kvp_recv) = mesh::channel();
         resources.kvp_ic = Some(kvp_send);
+        let (fcopy_send, fcopy_recv) = mesh::channel();
+        resources.fcopy_ic = Some(fcopy_send);
+        let (vss_send, vss_recv) = mesh::channel();
+        resources.vss_ic = Some(vss_send);
         vmbus_devices.extend(
             [
                 hyperv_ic_resources::shutdown::ShutdownIcHandle {
@@ -1196,10 +1638,27 @@ fn vm_config_from_command_line(
                 }
                 .into_resource(),
                 hyperv_ic_resources::kvp::KvpIcHandle { recv: kvp_recv }.into_resource(),
-                hyperv_ic_resources::timesync::TimesyncIcHandle.into_resource(),
+                hyperv_ic_resources::fcopy::FcopyIcHandle { recv: fcopy_recv }.into_resource(),
+                hyperv_ic_resources::vss::VssIcHandle { recv: vss_recv }.into_resource(),
             ]
             .map(|r| (DeviceVtl::Vtl0, r)),
         );
+        if !opt.no_timesync {
+            vmbus_devices.push((
+                DeviceVtl::Vtl0,
+                hyperv_ic_resources::timesync::TimesyncIcHandle {
+                    resync_on_resume: opt.clock_policy == ClockPolicyCli::Resync,
+                    skew_ms: opt.timesync_skew.unwrap_or(0),
+                }
+                .into_resource(),
+            ));
+        } else if opt.clock_policy == ClockPolicyCli::Resync {
+            anyhow::bail!("--clock-policy resync requires the timesync IC");
+        }
+    } else if opt.clock_policy == ClockPolicyCli::Resync {
+        anyhow::bail!("--clock-policy resync requires --hv");
+    } else if opt.timesync_skew.is_some() {
+        anyhow::bail!("--timesync-skew requires --hv");
     }
 
     if let Some(hive_path) = &opt.imc {
@@ -1299,6 +1758,139 @@ fn vm_config_from_command_line(
         );
     }
 
+    if let Some(audio) = &opt.audio {
+        let backend = match audio {
+            AudioCli::Null => virtio_resources::snd::VirtioSoundBackend::Null,
+            AudioCli::Wav(path) => {
+                let file = fs_err::File::create(path).context("failed to create wav file")?;
+                virtio_resources::snd::VirtioSoundBackend::Wav { file: file.into() }
+            }
+            AudioCli::Pulse(sink) => virtio_resources::snd::VirtioSoundBackend::Pipe {
+                pipe: spawn_pacat(sink.as_deref())?,
+            },
+        };
+        add_virtio_device(
+            VirtioBusCli::Auto,
+            virtio_resources::snd::VirtioSoundHandle { backend }.into_resource(),
+        );
+    }
+
+    let mut ivshmem_devices = Vec::new();
+    for cfg in &opt.ivshmem {
+        let file = fs_err::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&cfg.path)
+            .context("failed to open ivshmem file")?;
+        if file.metadata()?.len() < cfg.size {
+            file.set_len(cfg.size)
+                .context("failed to resize ivshmem file")?;
+        }
+        let doorbell = cfg
+            .doorbell
+            .as_ref()
+            .map(|path| {
+                unix_socket::UnixStream::connect(path).with_context(|| {
+                    format!("failed to connect to doorbell socket {}", path.display())
+                })
+            })
+            .transpose()?;
+        ivshmem_devices.push(IvshmemConfig {
+            file: file.into(),
+            size: cfg.size,
+            doorbell,
+        });
+    }
+
+    let nvme_sriov = if opt.nvme_vf.is_empty() {
+        None
+    } else {
+        if !chipset.with_generic_pci_bus {
+            anyhow::bail!("--nvme-vf requires the generic PCI bus");
+        }
+        if opt.nvme_vf.len() > 7 {
+            anyhow::bail!("at most 7 NVMe virtual functions are supported");
+        }
+        let virtual_functions = opt
+            .nvme_vf
+            .iter()
+            .map(|cli| {
+                if cli.vtl != DeviceVtl::Vtl0 || cli.underhill.is_some() || cli.is_dvd {
+                    anyhow::bail!("--nvme-vf disks must be VTL0 disks");
+                }
+                Ok(vec![NamespaceDefinition {
+                    nsid: 1,
+                    read_only: cli.read_only,
+                    disk: disk_open(&cli.kind, cli.read_only)?,
+                }])
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Some(NvmeSriovConfig { virtual_functions })
+    };
+
+    if opt.pci_ecam.is_some() && !chipset.with_generic_pci_bus {
+        anyhow::bail!("--pci-ecam requires the generic PCI bus");
+    }
+    // The SR-IOV capability is in extended config space, which the guest can
+    // only reach through an ECAM window covering bus 0 through the bridges'
+    // secondary buses.
+    let pci_ecam = pci_ecam(
+        opt.pci_ecam,
+        nvme_sriov.is_some().then_some(opt.pci_bridges as u16 + 1),
+        mmio_gaps[0],
+    )?;
+
+    let (hugetlb_page_size, backing_file) = match &opt.memory_backing {
+        None => (None, None),
+        Some(MemoryBackingCli::Hugetlb(page_size)) => (Some(*page_size), None),
+        Some(MemoryBackingCli::File { path, shared }) => {
+            let file = if *shared {
+                fs_err::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+            } else {
+                fs_err::File::open(path)
+            }
+            .context("failed to open memory backing file")?;
+            (
+                None,
+                Some(MemoryBackingFile {
+                    file: file.into(),
+                    shared: *shared,
+                }),
+            )
+        }
+    };
+
+    let mut prefetch_memory = opt.prefetch;
+    let mut lazy_memory = false;
+    let mut numa_policy = None;
+    for policy in &opt.mem_policy {
+        let policy = match policy {
+            MemPolicyCli::Lazy => {
+                lazy_memory = true;
+                continue;
+            }
+            MemPolicyCli::Prefault => {
+                prefetch_memory = true;
+                continue;
+            }
+            MemPolicyCli::Interleave(nodes) => MemoryNumaPolicy::Interleave(nodes.clone()),
+            MemPolicyCli::Bind(nodes) => MemoryNumaPolicy::Bind(nodes.clone()),
+        };
+        if numa_policy.replace(policy).is_some() {
+            anyhow::bail!("only one of the interleave and bind memory policies can be used");
+        }
+    }
+    if lazy_memory && prefetch_memory {
+        anyhow::bail!("lazy memory policy conflicts with prefault and --prefetch");
+    }
+
     let mut cfg = Config {
         chipset,
         load_mode,
@@ -1308,7 +1900,13 @@ fn vm_config_from_command_line(
         memory: MemoryConfig {
             mem_size: opt.memory,
             mmio_gaps,
-            prefetch_memory: opt.prefetch,
+            pci_ecam,
+            prefetch_memory,
+            hotplug_size: opt.memory_hotplug.unwrap_or(0),
+            balloon: opt.balloon,
+            hugetlb_page_size,
+            backing_file,
+            numa_policy,
         },
         processor_topology: ProcessorTopologyConfig {
             proc_count: opt.processors,
@@ -1346,9 +1944,17 @@ fn vm_config_from_command_line(
         virtio_console_pci: opt.virtio_console_pci,
         virtio_serial: virtio_serial_cfg,
         virtio_devices,
+        usb_devices,
+        e1000_nics,
+        pci_serial_cards,
+        ivshmem_devices,
+        nvme_sriov,
+        vfio_devices,
         vmbus: with_hv.then_some(VmbusConfig {
             vsock_listener: vtl0_vsock_listener,
             vsock_path: opt.vsock_path.clone(),
+            vsock_host_connect_ports: opt.vsock_host_connect.clone(),
+            vsock_host_listen_ports: opt.vsock_host_listen.clone(),
             vtl2_redirect: opt.vmbus_redirect,
             vmbus_max_version: opt.vmbus_max_version,
             #[cfg(windows)]
@@ -1367,16 +1973,169 @@ fn vm_config_from_command_line(
         secure_boot_enabled: opt.secure_boot,
         custom_uefi_vars,
         firmware_event_send: None,
+        // Only ask for Hyper-V crash register reports when acting on them,
+        // since not every backend can deliver them.
+        guest_crash_send: (opt.on_guest_crash != GuestCrashActionCli::None).then(|| {
+            let (send, recv) = mesh::channel();
+            resources.guest_crash = Some(recv);
+            send
+        }),
         debugger_rpc: None,
-        generation_id_recv: None,
-        rtc_delta_milliseconds: 0,
+        vtl2_debugger_rpc: None,
+        generation_id_recv: {
+            let (send, recv) = mesh::channel();
+            resources.generation_id = Some(send);
+            Some(recv)
+        },
+        rtc_delta_milliseconds: rtc_delta_milliseconds(opt)?,
         automatic_guest_reset: !opt.halt_on_reset,
+        enable_s3: opt.sleep_state_allowed(SleepStateCli::S3),
+        enable_s4: opt.sleep_state_allowed(SleepStateCli::S4),
+        resume_from_hibernate: opt.resume_from_hibernate,
+        pci_hotplug_slots: opt.pci_hotplug_slots.clone(),
+        pci_slots: opt
+            .pci_slot
+            .iter()
+            .map(|slot| PciSlotConfig {
+                device: slot.device.clone(),
+                bus: slot.bus,
+                device_number: slot.device_number,
+                function: slot.function,
+            })
+            .collect(),
+        pci_bridges: opt.pci_bridges,
+        pvpanic: opt.pvpanic,
+        smbios: smbios_config(&opt.smbios),
+        vp_affinity: opt.vcpu_pin.clone(),
+        #[cfg(guest_arch = "x86_64")]
+        cpuid: cpuid::cpuid_overrides(opt.cpu_model, &opt.cpuid_disable, &opt.cpuid_set)?,
+        #[cfg(not(guest_arch = "x86_64"))]
+        cpuid: Vec::new(),
+        io_trace: if let Some(path) = &opt.io_trace_record {
+            let file = fs_err::File::create(path).context("failed to create i/o trace")?;
+            Some(IoTraceConfig::Record(file.into()))
+        } else if let Some(path) = &opt.io_trace_compare {
+            let file = fs_err::File::open(path).context("failed to open i/o trace")?;
+            Some(IoTraceConfig::Compare(file.into()))
+        } else {
+            None
+        },
+        access_trace: if let Some(filter) = &opt.trace_guest_access {
+            let file = if let Some(path) = &opt.trace_guest_access_file {
+                let file =
+                    fs_err::File::create(path).context("failed to create guest access trace")?;
+                Some(file.into())
+            } else {
+                None
+            };
+            Some(GuestAccessTraceConfig {
+                filter: filter.clone(),
+                file,
+            })
+        } else {
+            None
+        },
     };
 
     storage.build_config(&mut cfg, &mut resources, opt.scsi_sub_channels)?;
     Ok((cfg, resources))
 }
 
+/// Returns the MMIO gaps, applying the sizes from `--mmio-gap-low` and
+/// `--mmio-gap-high` to the defaults.
+///
+/// The low gap ends at 4GB and the high gap starts just below 64GB. The VTL2
+/// gap, if requested, follows the high gap at the next 1GB boundary.
+fn mmio_gaps(opt: &Options, is_x86: bool, vtl2_gap: bool) -> anyhow::Result<Vec<MemoryRange>> {
+    const FOUR_GB: u64 = 0x1_0000_0000;
+    const ONE_GB: u64 = 0x4000_0000;
+    const ALIGNMENT: u64 = 0x10_0000;
+
+    let defaults: &[MemoryRange] = match (is_x86, vtl2_gap) {
+        (true, false) => &DEFAULT_MMIO_GAPS_X86,
+        (true, true) => &DEFAULT_MMIO_GAPS_X86_WITH_VTL2,
+        (false, false) => &DEFAULT_MMIO_GAPS_AARCH64,
+        (false, true) => &DEFAULT_MMIO_GAPS_AARCH64_WITH_VTL2,
+    };
+    if opt.mmio_gap_low.is_none() && opt.mmio_gap_high.is_none() {
+        return Ok(defaults.to_vec());
+    }
+
+    let low = defaults[0];
+    let high = defaults[1];
+    let low_size = opt.mmio_gap_low.unwrap_or(low.len());
+    let high_size = opt.mmio_gap_high.unwrap_or(high.len());
+
+    // The low gap holds fixed platform devices, such as the APICs and
+    // firmware, so it can only grow, and at least 1GB below 4GB is left for
+    // RAM.
+    if low_size % ALIGNMENT != 0 || low_size < low.len() || low_size > FOUR_GB - ONE_GB {
+        anyhow::bail!(
+            "low mmio gap must be a multiple of 1MB between {:#x} and {:#x}",
+            low.len(),
+            FOUR_GB - ONE_GB
+        );
+    }
+    if high_size % ALIGNMENT != 0 || high_size < high.len() {
+        anyhow::bail!(
+            "high mmio gap must be a multiple of 1MB and at least {:#x}",
+            high.len()
+        );
+    }
+    let high_end = high
+        .start()
+        .checked_add(high_size)
+        .context("high mmio gap is too large")?;
+
+    let mut gaps = vec![
+        MemoryRange::new(FOUR_GB - low_size..FOUR_GB),
+        MemoryRange::new(high.start()..high_end),
+    ];
+    if vtl2_gap {
+        let (start, end) = high_end
+            .checked_next_multiple_of(ONE_GB)
+            .and_then(|start| Some((start, start.checked_add(ONE_GB)?)))
+            .context("high mmio gap is too large to fit the vtl2 mmio gap")?;
+        gaps.push(MemoryRange::new(start..end));
+    }
+
+    // Make sure RAM fits around the gaps.
+    vm_topology::memory::MemoryLayout::new(opt.memory, &gaps, None)
+        .context("invalid mmio gaps for the memory size")?;
+
+    Ok(gaps)
+}
+
+/// Returns the ECAM window for the first `buses` buses of the emulated PCI bus,
+/// at the start of the low MMIO gap. If `required_buses` is set, then the
+/// window defaults to, and must cover, that many buses.
+fn pci_ecam(
+    buses: Option<u16>,
+    required_buses: Option<u16>,
+    low_mmio_gap: MemoryRange,
+) -> anyhow::Result<Option<MemoryRange>> {
+    // Platform devices, such as the IOAPIC and firmware, are in the top 32MB
+    // below 4GB.
+    const PLATFORM_DEVICES_START: u64 = 0xfe00_0000;
+
+    let Some(buses) = buses.or(required_buses) else {
+        return Ok(None);
+    };
+    if let Some(required_buses) = required_buses {
+        if buses < required_buses {
+            anyhow::bail!("the ECAM window must cover at least {required_buses} PCI buses");
+        }
+    }
+    let ecam =
+        MemoryRange::new(low_mmio_gap.start()..low_mmio_gap.start() + u64::from(buses) * 0x10_0000);
+    if ecam.end() > low_mmio_gap.end().min(PLATFORM_DEVICES_START) {
+        anyhow::bail!(
+            "an ECAM window for {buses} PCI buses does not fit in the low mmio gap {low_mmio_gap}"
+        );
+    }
+    Ok(Some(ecam))
+}
+
 /// Gets the terminal to use for externally launched console windows.
 fn openvmm_terminal_app() -> Option<PathBuf> {
     std::env::var_os("OPENVMM_TERM")
@@ -1430,9 +2189,14 @@ fn parse_endpoint(
     resources: &mut VmResources,
 ) -> anyhow::Result<NicConfig> {
     let _ = resources;
-    let endpoint = match &cli_cfg.endpoint {
-        EndpointConfigCli::Consomme { cidr } => {
-            net_backend_resources::consomme::ConsommeHandle { cidr: cidr.clone() }.into_resource()
+    let mut default_mac_address: Option<MacAddress> = None;
+    let mut endpoint = match &cli_cfg.endpoint {
+        EndpointConfigCli::Consomme { cidr, dhcp } => {
+            net_backend_resources::consomme::ConsommeHandle {
+                cidr: cidr.clone(),
+                dhcp: dhcp.clone(),
+            }
+            .into_resource()
         }
         EndpointConfigCli::None => net_backend_resources::null::NullHandle.into_resource(),
         EndpointConfigCli::Dio { id } => {
@@ -1458,11 +2222,42 @@ fn parse_endpoint(
         EndpointConfigCli::Tap { name } => {
             net_backend_resources::tap::TapHandle { name: name.clone() }.into_resource()
         }
+        EndpointConfigCli::Macvtap { name } => {
+            #[cfg(target_os = "linux")]
+            {
+                let (file, mac_address) = open_macvtap(name)?;
+                default_mac_address = Some(mac_address);
+                net_backend_resources::macvtap::MacvtapHandle { file }.into_resource()
+            }
+
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = (name, &mut default_mac_address);
+                bail!("cannot use macvtap on non-linux platforms")
+            }
+        }
     };
 
-    // Pick a random MAC address.
-    let mut mac_address = [0x00, 0x15, 0x5D, 0, 0, 0];
-    getrandom::fill(&mut mac_address[3..]).expect("rng failure");
+    if cli_cfg.rate_mbps.is_some() || cli_cfg.latency_ms.is_some() || cli_cfg.loss_percent.is_some()
+    {
+        endpoint = net_backend_resources::shaped::ShapedHandle {
+            endpoint,
+            rate_bps: cli_cfg.rate_mbps.map(|rate| rate * 1_000_000),
+            latency_ms: cli_cfg.latency_ms.unwrap_or(0),
+            loss_percent: cli_cfg.loss_percent.unwrap_or(0.0),
+        }
+        .into_resource();
+    }
+
+    // Use the configured MAC address, or the backend's, or pick a random one.
+    let mac_address = cli_cfg
+        .mac_address
+        .or(default_mac_address)
+        .unwrap_or_else(|| {
+            let mut mac_address = [0x00, 0x15, 0x5D, 0, 0, 0];
+            getrandom::fill(&mut mac_address[3..]).expect("rng failure");
+            mac_address.into()
+        });
 
     // Pick a fixed instance ID based on the index.
     const BASE_INSTANCE_ID: Guid = guid::guid!("00000000-da43-11ed-936a-00155d6db52f");
@@ -1476,11 +2271,84 @@ fn parse_endpoint(
         vtl: cli_cfg.vtl,
         instance_id,
         endpoint,
-        mac_address: mac_address.into(),
+        mac_address,
         max_queues: cli_cfg.max_queues,
     })
 }
 
+/// Opens the device node for the macvtap interface `name`, returning it along
+/// with the interface's MAC address. The guest must use this MAC address to
+/// receive traffic in most macvtap modes.
+#[cfg(target_os = "linux")]
+fn open_macvtap(name: &str) -> anyhow::Result<(std::fs::File, MacAddress)> {
+    let sysfs = Path::new("/sys/class/net").join(name);
+    let ifindex: u32 = fs_err::read_to_string(sysfs.join("ifindex"))
+        .with_context(|| format!("failed to find network interface {name}"))?
+        .trim()
+        .parse()
+        .context("failed to parse interface index")?;
+    let mac_address = fs_err::read_to_string(sysfs.join("address"))?
+        .trim()
+        .parse()
+        .context("failed to parse interface MAC address")?;
+    let file = fs_err::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("/dev/tap{ifindex}"))
+        .with_context(|| format!("failed to open device node for {name}, is it a macvtap?"))?;
+    Ok((file.into(), mac_address))
+}
+
+/// Finds the host USB device `device` in sysfs and opens its usbfs device
+/// node.
+#[cfg(target_os = "linux")]
+fn open_usb_host(device: &cli_args::UsbHostCli) -> anyhow::Result<std::fs::File> {
+    let sysfs = Path::new("/sys/bus/usb/devices");
+    let read_attr = |dir: &Path, name: &str| -> std::io::Result<String> {
+        Ok(fs_err::read_to_string(dir.join(name))?.trim().to_owned())
+    };
+    let dir = match device {
+        cli_args::UsbHostCli::Path(path) => sysfs.join(path),
+        &cli_args::UsbHostCli::Id {
+            vendor_id,
+            product_id,
+        } => {
+            let mut found = None;
+            for entry in fs_err::read_dir(sysfs)? {
+                let dir = entry?.path();
+                // Interfaces and missing attributes are skipped.
+                let (Ok(vendor), Ok(product)) =
+                    (read_attr(&dir, "idVendor"), read_attr(&dir, "idProduct"))
+                else {
+                    continue;
+                };
+                if u16::from_str_radix(&vendor, 16).ok() == Some(vendor_id)
+                    && u16::from_str_radix(&product, 16).ok() == Some(product_id)
+                {
+                    found = Some(dir);
+                    break;
+                }
+            }
+            found.with_context(|| {
+                format!("no host usb device with id {vendor_id:04x}:{product_id:04x}")
+            })?
+        }
+    };
+    let busnum: u32 = read_attr(&dir, "busnum")
+        .with_context(|| format!("failed to find host usb device {}", dir.display()))?
+        .parse()
+        .context("failed to parse usb bus number")?;
+    let devnum: u32 = read_attr(&dir, "devnum")?
+        .parse()
+        .context("failed to parse usb device number")?;
+    let file = fs_err::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("/dev/bus/usb/{busnum:03}/{devnum:03}"))
+        .context("failed to open host usb device node")?;
+    Ok(file.into())
+}
+
 #[derive(Debug)]
 struct NicConfig {
     vtl: DeviceVtl,
@@ -1505,17 +2373,155 @@ fn into_netvsp_handle(self) -> (DeviceVtl, Resource<VmbusDeviceHandleKind>) {
     }
 }
 
-enum LayerOrDisk {
-    Layer(DiskLayerDescription),
-    Disk(Resource<DiskHandleKind>),
+/// Returns the offset of the guest's RTC from the host's UTC time.
+fn rtc_delta_milliseconds(opt: &Options) -> anyhow::Result<i64> {
+    let now = jiff::Zoned::now();
+    let base = match &opt.rtc_base {
+        None => now.timestamp(),
+        Some(cli_args::RtcBaseCli::Time(time)) => *time,
+        Some(cli_args::RtcBaseCli::Now(span)) => now
+            .checked_add(*span)
+            .context("rtc base time out of range")?
+            .timestamp(),
+    };
+    let mut delta = base.as_millisecond() - now.timestamp().as_millisecond();
+    if opt.rtc_localtime && !opt.rtc_utc {
+        // The RTC reports the local wall-clock time at the base time.
+        let offset = base.to_zoned(now.time_zone().clone()).offset();
+        delta += i64::from(offset.seconds()) * 1000;
+    }
+    Ok(delta)
 }
 
-fn disk_open(disk_cli: &DiskCliKind, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
-    let mut layers = Vec::new();
-    disk_open_inner(disk_cli, read_only, &mut layers)?;
-    if layers.len() == 1 && matches!(layers[0], LayerOrDisk::Disk(_)) {
-        let LayerOrDisk::Disk(disk) = layers.pop().unwrap() else {
-            unreachable!()
+fn uefi_boot_entries(
+    order: &cli_args::UefiBootOrderCli,
+    storage: &storage_builder::StorageBuilder,
+    vtl0_vmbus_nics: &[(Guid, MacAddress)],
+) -> Vec<uefi_boot_order::BootEntry> {
+    use cli_args::UefiBootDevice;
+    use uefi_boot_order::BootEntry;
+
+    let mut entries = Vec::new();
+    for device in &order.0 {
+        match device {
+            UefiBootDevice::Disk | UefiBootDevice::Dvd => {
+                let dvd = matches!(device, UefiBootDevice::Dvd);
+                entries.extend(
+                    storage
+                        .vtl0_scsi_luns()
+                        .iter()
+                        .filter(|&&(_, is_dvd)| is_dvd == dvd)
+                        .map(|&(lun, is_dvd)| BootEntry::scsi(lun, is_dvd)),
+                );
+            }
+            UefiBootDevice::Network => {
+                entries.extend(vtl0_vmbus_nics.iter().map(|&(instance_id, mac_address)| {
+                    BootEntry::network(instance_id, mac_address)
+                }))
+            }
+            UefiBootDevice::File(path) => entries.push(BootEntry::file(path)),
+        }
+    }
+    entries
+}
+
+fn secure_boot_keys(
+    opt: &Options,
+) -> anyhow::Result<hyperv_uefi_custom_vars_json::keys::SecureBootKeys> {
+    use firmware_uefi_custom_vars::Sha256Digest;
+    use hyperv_uefi_custom_vars_json::keys::SecureBootKeys;
+    use hyperv_uefi_custom_vars_json::keys::parse_x509_cert;
+
+    let cert = |path: &Path| {
+        parse_x509_cert(&fs_err::read(path)?)
+            .with_context(|| format!("failed to parse certificate {}", path.display()))
+    };
+
+    let mut keys = SecureBootKeys {
+        pk: opt.secure_boot_pk.as_deref().map(cert).transpose()?,
+        kek: opt
+            .secure_boot_kek
+            .iter()
+            .map(|p| cert(p))
+            .collect::<anyhow::Result<_>>()?,
+        db: opt
+            .secure_boot_db
+            .iter()
+            .map(|p| cert(p))
+            .collect::<anyhow::Result<_>>()?,
+        ..Default::default()
+    };
+    for dbx in &opt.secure_boot_dbx {
+        match dbx {
+            cli_args::SecureBootDbxCli::Cert(path) => keys.dbx.push(cert(path)?),
+            cli_args::SecureBootDbxCli::Sha256(digest) => {
+                keys.dbx_hashes.push(Sha256Digest(*digest))
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Starts `pacat` to play raw audio from the virtio-snd device, returning the
+/// pipe to its standard input. `pacat` exits when the pipe is closed.
+fn spawn_pacat(sink: Option<&str>) -> anyhow::Result<std::fs::File> {
+    let mut command = std::process::Command::new("pacat");
+    command.args([
+        "--playback",
+        "--raw",
+        "--format=s16le",
+        "--rate=48000",
+        "--channels=2",
+    ]);
+    if let Some(sink) = sink {
+        command.arg(format!("--device={sink}"));
+    }
+    let mut child = command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .context("failed to start pacat, which is needed for PulseAudio playback")?;
+    let stdin = child.stdin.take().unwrap();
+    #[cfg(unix)]
+    let pipe = std::fs::File::from(std::os::fd::OwnedFd::from(stdin));
+    #[cfg(windows)]
+    let pipe = std::fs::File::from(std::os::windows::io::OwnedHandle::from(stdin));
+    Ok(pipe)
+}
+
+fn smbios_config(fields: &[SmbiosCli]) -> SmbiosConfig {
+    let mut config = SmbiosConfig::default();
+    for field in fields {
+        // Later values override earlier ones, so that the command line can
+        // override values from a config file.
+        match field.clone() {
+            SmbiosCli::Uuid(uuid) => config.uuid = Some(uuid),
+            SmbiosCli::Manufacturer(v) => config.system_manufacturer = Some(v),
+            SmbiosCli::Product(v) => config.system_product_name = Some(v),
+            SmbiosCli::Version(v) => config.system_version = Some(v),
+            SmbiosCli::Sku(v) => config.system_sku_number = Some(v),
+            SmbiosCli::Family(v) => config.system_family = Some(v),
+            SmbiosCli::Serial(v) => config.system_serial_number = Some(v),
+            SmbiosCli::BaseBoardSerial(v) => config.base_board_serial_number = Some(v),
+            SmbiosCli::ChassisSerial(v) => config.chassis_serial_number = Some(v),
+            SmbiosCli::ChassisAssetTag(v) => config.chassis_asset_tag = Some(v),
+            SmbiosCli::Oem(v) => config.oem_strings.push(v),
+        }
+    }
+    config
+}
+
+enum LayerOrDisk {
+    Layer(DiskLayerDescription),
+    Disk(Resource<DiskHandleKind>),
+}
+
+fn disk_open(disk_cli: &DiskCliKind, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
+    let mut layers = Vec::new();
+    disk_open_inner(disk_cli, read_only, &mut layers)?;
+    if layers.len() == 1 && matches!(layers[0], LayerOrDisk::Disk(_)) {
+        let LayerOrDisk::Disk(disk) = layers.pop().unwrap() else {
+            unreachable!()
         };
         Ok(disk)
     } else {
@@ -1560,13 +2566,56 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
             open_disk_type(path, read_only)
                 .with_context(|| format!("failed to open {}", path.display()))?
         })),
+        DiskCliKind::Zstd(path) => {
+            if !read_only {
+                anyhow::bail!("zstd disks are read-only; use `ro` or `memdiff:zstd:<path>`");
+            }
+            layers.push(disk(disk_backend_resources::ZstdDiskHandle(
+                fs_err::File::open(path)?.into(),
+            )))
+        }
+        DiskCliKind::Vhdx {
+            path,
+            create_with_len,
+        } => layers.push(LayerOrDisk::Disk(if let Some(size) = create_with_len {
+            create_vhdx(path, *size)
+                .with_context(|| format!("failed to create {}", path.display()))?
+        } else {
+            open_vhdx(path, read_only)
+                .with_context(|| format!("failed to open {}", path.display()))?
+        })),
+        DiskCliKind::VhdSet { path, checkpoint } => {
+            if checkpoint.is_some() && !read_only {
+                anyhow::bail!(
+                    "VHD Set checkpoints are read-only; use `ro` or `memdiff:vhds:<path>;checkpoint=<guid>`"
+                );
+            }
+            layers.push(LayerOrDisk::Disk(
+                open_vhd_set(path, *checkpoint, read_only)
+                    .with_context(|| format!("failed to open {}", path.display()))?,
+            ))
+        }
         DiskCliKind::Blob { kind, url } => {
+            let page_blob = match kind {
+                cli_args::BlobKind::Flat | cli_args::BlobKind::Vhd1 => None,
+                cli_args::BlobKind::PageFlat | cli_args::BlobKind::PageVhd1 => {
+                    Some(disk_backend_resources::PageBlobOptions {
+                        bearer_token: std::env::var("OPENVMM_BLOB_BEARER_TOKEN").ok(),
+                        max_dirty_bytes: 64 * 1024 * 1024,
+                    })
+                }
+            };
             layers.push(disk(disk_backend_resources::BlobDiskHandle {
                 url: url.to_owned(),
                 format: match kind {
-                    cli_args::BlobKind::Flat => disk_backend_resources::BlobDiskFormat::Flat,
-                    cli_args::BlobKind::Vhd1 => disk_backend_resources::BlobDiskFormat::FixedVhd1,
+                    cli_args::BlobKind::Flat | cli_args::BlobKind::PageFlat => {
+                        disk_backend_resources::BlobDiskFormat::Flat
+                    }
+                    cli_args::BlobKind::Vhd1 | cli_args::BlobKind::PageVhd1 => {
+                        disk_backend_resources::BlobDiskFormat::FixedVhd1
+                    }
                 },
+                page_blob,
             }))
         }
         DiskCliKind::MemoryDiff(inner) => {
@@ -1576,6 +2625,45 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
         DiskCliKind::PersistentReservationsWrapper(inner) => layers.push(disk(
             disk_backend_resources::DiskWithReservationsHandle(disk_open(inner, read_only)?),
         )),
+        DiskCliKind::SharedPersistentReservations {
+            path,
+            host_id,
+            disk: inner,
+        } => {
+            let store = fs_err::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            let host_id = host_id.unwrap_or_else(|| {
+                let mut host_id = [0; 8];
+                getrandom::fill(&mut host_id).expect("rng failure");
+                u64::from_ne_bytes(host_id)
+            });
+            layers.push(disk(
+                disk_backend_resources::DiskWithSharedReservationsHandle {
+                    disk: disk_open(inner, read_only)?,
+                    store: store.into(),
+                    host_id,
+                },
+            ))
+        }
+        DiskCliKind::Verity {
+            hash_tree,
+            root_hash,
+            disk: inner,
+        } => layers.push(disk(disk_backend_resources::VerityDiskHandle {
+            disk: disk_open(inner, true)?,
+            hash_tree: disk_open(
+                &DiskCliKind::File {
+                    path: hash_tree.clone(),
+                    create_with_len: None,
+                },
+                true,
+            )?,
+            root_hash: root_hash.clone(),
+        })),
         DiskCliKind::DelayDiskWrapper {
             delay_ms,
             disk: inner,
@@ -1583,16 +2671,60 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
             delay: CellUpdater::new(Duration::from_millis(*delay_ms)).cell(),
             disk: disk_open(inner, read_only)?,
         })),
+        DiskCliKind::WriteBackCache {
+            cache_size,
+            flush_interval_ms,
+            disk: inner,
+        } => layers.push(disk(disk_backend_resources::WriteBackCacheDiskHandle {
+            disk: disk_open(inner, read_only)?,
+            cache_size: *cache_size,
+            flush_interval: flush_interval_ms.map(Duration::from_millis),
+        })),
+        DiskCliKind::Multipath(paths) => {
+            layers.push(disk(disk_backend_resources::MultipathDiskHandle {
+                paths: paths
+                    .iter()
+                    .map(|path| disk_open(path, read_only))
+                    .collect::<anyhow::Result<_>>()?,
+            }))
+        }
+        DiskCliKind::Throttle {
+            iops,
+            bytes_per_second,
+            burst,
+            disk: inner,
+        } => layers.push(disk(disk_backend_resources::ThrottleDiskHandle {
+            disk: disk_open(inner, read_only)?,
+            iops: *iops,
+            bytes_per_second: *bytes_per_second,
+            burst: *burst,
+        })),
+        DiskCliKind::SectorSize {
+            sector_size,
+            physical_sector_size,
+            disk: inner,
+        } => layers.push(disk(disk_backend_resources::SectorSizeDiskHandle {
+            disk: disk_open(inner, read_only)?,
+            sector_size: *sector_size,
+            physical_sector_size: *physical_sector_size,
+        })),
         DiskCliKind::Crypt {
             disk: inner,
             cipher,
-            key_file,
+            key,
         } => layers.push(disk(disk_crypt_resources::DiskCryptHandle {
             disk: disk_open(inner, read_only)?,
             cipher: match cipher {
                 cli_args::DiskCipher::XtsAes256 => disk_crypt_resources::Cipher::XtsAes256,
+                cli_args::DiskCipher::Luks2 => disk_crypt_resources::Cipher::Luks2,
+            },
+            key: match key {
+                cli_args::DiskKey::File(path) => {
+                    fs_err::read(path).context("failed to read key file")?
+                }
+                cli_args::DiskKey::Prompt => read_passphrase("Enter disk passphrase: ")
+                    .context("failed to read passphrase")?,
             },
-            key: fs_err::read(key_file).context("failed to read key file")?,
         })),
         DiskCliKind::Sqlite {
             path,
@@ -1672,18 +2804,60 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
     Ok(())
 }
 
+/// Prompts for a passphrase on the terminal without echoing it.
+fn read_passphrase(prompt: &str) -> anyhow::Result<Vec<u8>> {
+    use std::io::IsTerminal as _;
+
+    let mut stdin = io::stdin();
+    if !stdin.is_terminal() {
+        // Read a line from the pipe instead.
+        let mut line = String::new();
+        stdin.read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).as_bytes().to_vec());
+    }
+
+    eprint!("{prompt}");
+    term::set_raw_console(true).context("failed to set raw console mode")?;
+    let mut passphrase = Vec::new();
+    let result = (|| -> anyhow::Result<()> {
+        let mut b = [0];
+        loop {
+            if stdin.read(&mut b)? == 0 {
+                break;
+            }
+            match b[0] {
+                b'\r' | b'\n' => break,
+                // Ctrl-C
+                0x03 => anyhow::bail!("interrupted"),
+                // Backspace
+                0x08 | 0x7f => {
+                    passphrase.pop();
+                }
+                c => passphrase.push(c),
+            }
+        }
+        Ok(())
+    })();
+    term::set_raw_console(false).context("failed to set raw console mode")?;
+    eprintln!();
+    result.map(|()| passphrase)
+}
+
 fn do_main() -> anyhow::Result<()> {
     #[cfg(windows)]
     pal::windows::disable_hard_error_dialog();
 
-    tracing_init::enable_tracing()?;
+    let log = tracing_init::enable_tracing()?;
 
     // Try to run as a worker host.
     // On success the worker runs to completion and then exits the process (does
     // not return). Any worker host setup errors are return and bubbled up.
-    meshworker::run_vmm_mesh_host()?;
+    meshworker::run_vmm_mesh_host(log.clone())?;
 
-    let opt = Options::parse();
+    let opt = config_file::parse_options()?;
+    if let Some(format) = opt.log_format {
+        log.set_format(format);
+    }
     if let Some(path) = &opt.write_saved_state_proto {
         mesh::payload::protofile::DescriptorWriter::new(vmcore::save_restore::saved_state_roots())
             .write_to_path(path)
@@ -1691,6 +2865,10 @@ fn do_main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if let Some(cli_args::Command::Save { ttrpc, file }) = &opt.command {
+        return DefaultPool::run_with(async |driver| save_remote_vm(&driver, ttrpc, file).await);
+    }
+
     if let Some(path) = opt.relay_console_path {
         let console_title = opt.relay_console_title.unwrap_or_default();
         return console_relay::relay_console(&path, console_title.as_str());
@@ -1712,6 +2890,7 @@ fn do_main() -> anyhow::Result<()> {
             let mut handle = launch_local_worker::<TtrpcWorker>(ttrpc::Parameters {
                 listener,
                 transport,
+                allow_guest_memory_access: opt.allow_guest_memory_access,
             })
             .await?;
 
@@ -1725,15 +2904,47 @@ fn do_main() -> anyhow::Result<()> {
             Ok(())
         })
     } else {
-        DefaultPool::run_with(async |driver| {
-            let mesh = VmmMesh::new(&driver, opt.single_process)?;
+        let profile = ProfileOptions::new(opt.profile.clone(), opt.profile_dir.clone());
+        let profiler = Profiler::start(&profile, "openvmm")?;
+        let result = DefaultPool::run_with(async |driver| {
+            let mesh = VmmMesh::new(&driver, opt.single_process, log, profile)?;
             let result = run_control(&driver, &mesh, opt).await;
             mesh.shutdown().await;
             result
-        })
+        });
+        if let Some(profiler) = profiler {
+            profiler.finish();
+        }
+        result
     }
 }
 
+/// Saves a snapshot of the VM run by the ttrpc server at `socket_path`.
+async fn save_remote_vm(
+    driver: &DefaultDriver,
+    socket_path: &Path,
+    file: &Path,
+) -> anyhow::Result<()> {
+    // The server may have a different working directory.
+    let file = std::path::absolute(file).context("failed to resolve snapshot path")?;
+    let client = mesh_rpc::Client::new(
+        driver,
+        mesh_rpc::client::UnixDialier::new(driver.clone(), socket_path),
+    );
+    client
+        .call()
+        .start(
+            hvlite_ttrpc_vmservice::Vm::SaveVm,
+            hvlite_ttrpc_vmservice::SaveVmRequest {
+                path: file.to_string_lossy().into_owned(),
+            },
+        )
+        .await
+        .map_err(|status| anyhow::anyhow!("failed to save vm: {}", status.message))?;
+    client.shutdown().await;
+    Ok(())
+}
+
 fn maybe_with_radix_u64(s: &str) -> Result<u64, String> {
     let (radix, prefix_len) = if s.starts_with("0x") || s.starts_with("0X") {
         (16, 2)
@@ -1748,6 +2959,33 @@ fn maybe_with_radix_u64(s: &str) -> Result<u64, String> {
     u64::from_str_radix(&s[prefix_len..], radix).map_err(|e| format!("{e}"))
 }
 
+/// The arguments to hot-add a disk, from the interactive console or QMP.
+#[derive(clap::Args)]
+struct AddDiskCommand {
+    #[clap(long = "ro")]
+    read_only: bool,
+    #[clap(long = "dvd")]
+    is_dvd: bool,
+    #[clap(long, default_value_t)]
+    target: u8,
+    #[clap(long, default_value_t)]
+    path: u8,
+    #[clap(long, default_value_t)]
+    lun: u8,
+    /// Add the disk as the NVMe namespace with this ID instead of as a
+    /// SCSI disk.
+    #[clap(long, conflicts_with_all = ["is_dvd", "target", "path", "lun"])]
+    nvme: Option<u32>,
+    /// Add the NVMe namespace to the VTL2 controller, to be relayed to the
+    /// guest by OpenHCL.
+    #[clap(long, requires = "nvme")]
+    vtl2: bool,
+    #[clap(long)]
+    ram: Option<u64>,
+    /// The disk to add, using the same syntax as `--disk`.
+    disk: Option<DiskCliKind>,
+}
+
 #[derive(Parser)]
 #[clap(
     name = "openvmm",
@@ -1764,12 +3002,36 @@ enum InteractiveCommand {
     Restart,
 
     /// Inject an NMI.
-    #[clap(visible_alias = "n")]
-    Nmi,
+    #[clap(visible_aliases = ["n", "inject-nmi"])]
+    Nmi {
+        /// The VP to inject the NMI into.
+        #[clap(default_value_t)]
+        vp: u32,
+    },
+
+    /// Assert the ACPI SCI by raising a general-purpose event.
+    #[clap(visible_alias = "inject-sci")]
+    Sci {
+        /// The GPE0 event number to raise.
+        #[clap(default_value_t)]
+        gpe: u32,
+    },
+
+    /// Inject a machine check exception (x86-64 only).
+    #[clap(visible_alias = "inject-mce")]
+    Mce {
+        /// The VP to inject the machine check into.
+        #[clap(default_value_t)]
+        vp: u32,
+    },
 
     /// Pause the VM.
     #[clap(visible_alias = "p")]
-    Pause,
+    Pause {
+        /// Resume the VM automatically after this many seconds.
+        #[clap(long, value_name = "SECONDS")]
+        resume_after: Option<u64>,
+    },
 
     /// Resume the VM.
     #[clap(visible_alias = "r")]
@@ -1788,32 +3050,24 @@ enum InteractiveCommand {
     },
 
     /// Hot add a disk.
-    #[clap(visible_alias = "d")]
-    AddDisk {
-        #[clap(long = "ro")]
-        read_only: bool,
-        #[clap(long = "dvd")]
-        is_dvd: bool,
-        #[clap(long, default_value_t)]
-        target: u8,
-        #[clap(long, default_value_t)]
-        path: u8,
-        #[clap(long, default_value_t)]
-        lun: u8,
-        #[clap(long)]
-        ram: Option<u64>,
-        file_path: Option<PathBuf>,
-    },
+    #[clap(visible_aliases = ["d", "hot-add-disk"])]
+    AddDisk(AddDiskCommand),
 
     /// Hot remove a disk.
-    #[clap(visible_alias = "D")]
+    #[clap(visible_aliases = ["D", "hot-remove-disk"])]
     RmDisk {
-        #[clap(long)]
+        #[clap(long, default_value_t)]
         target: u8,
-        #[clap(long)]
+        #[clap(long, default_value_t)]
         path: u8,
-        #[clap(long)]
+        #[clap(long, default_value_t)]
         lun: u8,
+        /// Remove the NVMe namespace with this ID instead of a SCSI disk.
+        #[clap(long, conflicts_with_all = ["target", "path", "lun"])]
+        nvme: Option<u32>,
+        /// Remove the NVMe namespace from the VTL2 controller.
+        #[clap(long, requires = "nvme")]
+        vtl2: bool,
     },
 
     /// Inspect program state.
@@ -1835,6 +3089,12 @@ enum InteractiveCommand {
         update: Option<String>,
     },
 
+    /// Save the screen contents to a PPM image file.
+    Screendump {
+        /// The file to write.
+        file: PathBuf,
+    },
+
     /// Restart the VNC worker.
     #[clap(visible_alias = "V")]
     RestartVnc,
@@ -1862,7 +3122,8 @@ enum InteractiveCommand {
 
     /// Switch to input mode.
     ///
-    /// Once in input mode, Ctrl-Q returns to command mode.
+    /// Once in input mode, Ctrl-Q or Ctrl-A c returns to command mode, and
+    /// Ctrl-A h lists the keys for switching between consoles.
     #[clap(visible_alias = "I")]
     InputMode,
 
@@ -1887,6 +3148,87 @@ enum InteractiveCommand {
     #[clap(visible_alias = "ch")]
     ClearHalt,
 
+    /// Wake the VM from S3.
+    Wake {
+        /// The wake source to report to the guest.
+        #[clap(default_value = "power-button")]
+        source: WakeSourceCli,
+    },
+
+    /// Wake the VM as if the RTC alarm fired after a delay, replacing any
+    /// earlier scheduled wake.
+    ///
+    /// This wakes the VM from S3, or powers it on if the guest has powered it
+    /// off.
+    ScheduleWake {
+        /// The delay in seconds. Cancels the scheduled wake if omitted.
+        seconds: Option<u64>,
+    },
+
+    /// Give the guest a new VM generation ID, as if the VM had been reverted
+    /// to a snapshot.
+    #[clap(visible_alias = "genid")]
+    UpdateGenerationId,
+
+    /// Set the total guest memory size, hot-adding or removing memory.
+    ///
+    /// Requires `--memory-hotplug`. The guest adds or removes the memory
+    /// asynchronously.
+    Memory {
+        /// The new memory size.
+        #[clap(value_parser = cli_args::parse_memory)]
+        size: u64,
+    },
+
+    /// Set the size of the balloon, the memory the guest should return to the
+    /// host.
+    ///
+    /// Requires `--balloon`.
+    Balloon {
+        /// The new balloon size.
+        #[clap(value_parser = cli_args::parse_memory)]
+        size: u64,
+    },
+
+    /// Migrate the VM to another OpenVMM instance started with `--incoming`,
+    /// or save it to a file to start VMs from, leaving this one paused.
+    Migrate {
+        /// The destination, as `tcp:<host>:<port>` or `file:<path>`.
+        address: MigrationAddressCli,
+    },
+
+    /// Save a snapshot of the VM to a file, to resume it from later with
+    /// `--resume`. The VM keeps running afterwards.
+    Save {
+        /// The file to write the snapshot to.
+        #[clap(long)]
+        file: PathBuf,
+    },
+
+    /// Pin a VP's backing thread to a list of host CPUs.
+    VpPin {
+        /// The VP index.
+        vp: u32,
+        /// The host CPUs, e.g. `2-3,6`, or omit to unpin the VP.
+        cpus: Option<String>,
+    },
+
+    /// Revoke a MANA virtual function from the guest, or offer it again.
+    Vf {
+        /// The action to take.
+        action: VfActionCli,
+        /// The index of the virtual function, in `--mana-vf` order.
+        index: usize,
+    },
+
+    /// Signal a hot-plug event for an ACPI PCI hot-plug slot to the guest.
+    PciSlot {
+        /// The event to signal.
+        action: PciSlotActionCli,
+        /// The PCI device number of the slot.
+        device: u8,
+    },
+
     /// Update the image in VTL2.
     ServiceVtl2 {
         /// Just restart the user-mode paravisor process, not the full
@@ -1931,6 +3273,48 @@ enum InteractiveCommand {
 
     /// Use KVP to interact with the guest.
     Kvp(kvp::KvpCommand),
+
+    /// Copy a host file into the guest with the file copy IC.
+    ///
+    /// Requires `--hv` and a guest running the file copy service (the Hyper-V
+    /// guest service interface on Windows, or `hv_fcopy_uio_daemon` on
+    /// Linux).
+    CopyToGuest(fcopy::CopyToGuestCommand),
+
+    /// Freeze or thaw the guest's file systems with the VSS IC, so that disk
+    /// snapshots taken in between are consistent.
+    ///
+    /// Requires `--hv` and a guest running `hv_vss_daemon` (Linux).
+    Vss(vss::VssCommand),
+
+    /// Show or change the emulated battery and AC power state. The guest is
+    /// notified of each change.
+    ///
+    /// Requires `--battery`.
+    Battery(battery::BatteryCommand),
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum WakeSourceCli {
+    PowerButton,
+    RtcAlarm,
+    Keyboard,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum VfActionCli {
+    /// Remove the virtual function from the guest.
+    Revoke,
+    /// Offer a revoked virtual function to the guest again.
+    Offer,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum PciSlotActionCli {
+    /// A device was inserted into the slot.
+    Insert,
+    /// Ask the guest to release and eject the device in the slot.
+    Remove,
 }
 
 struct CommandParser {
@@ -1966,16 +3350,186 @@ fn new_hvsock_service_id(port: u32) -> Guid {
     }
 }
 
+/// Returns the SCSI path with target and path 0 for `lun`. Disk snapshots are
+/// tracked by LUN, so only disks at these paths can be snapshotted.
+fn scsi_lun_path(lun: u8) -> ScsiPath {
+    ScsiPath {
+        path: 0,
+        target: 0,
+        lun,
+    }
+}
+
+async fn add_disk(resources: &mut VmResources, command: AddDiskCommand) -> anyhow::Result<()> {
+    let AddDiskCommand {
+        read_only,
+        is_dvd,
+        target,
+        path,
+        lun,
+        nvme,
+        vtl2,
+        ram,
+        disk,
+    } = command;
+
+    let mut disk_type = match ram {
+        None => {
+            let disk = disk.context("no disk passed")?;
+            disk_open(&disk, read_only || is_dvd)?
+        }
+        Some(size) => Resource::new(disk_backend_resources::LayeredDiskHandle::single_layer(
+            RamDiskLayerHandle { len: Some(size) },
+        )),
+    };
+
+    if let Some(nsid) = nvme {
+        let nvme = nvme_controller(resources, vtl2)?;
+        nvme.call_failable(
+            NvmeControllerRequest::AddNamespace,
+            NamespaceDefinition {
+                nsid,
+                read_only,
+                disk: disk_type,
+            },
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let scsi_path = ScsiPath { path, target, lun };
+    let mut snapshot_send = None;
+    if resources.scsi_snapshots.is_some()
+        && !is_dvd
+        && !read_only
+        && scsi_path == scsi_lun_path(lun)
+    {
+        let send;
+        (disk_type, send) = storage_builder::snapshot_disk_handle(disk_type);
+        snapshot_send = Some(send);
+    }
+
+    let scsi = resources.scsi_rpc.as_ref().context("no scsi controller")?;
+    let device = if is_dvd {
+        SimpleScsiDvdHandle {
+            media: Some(disk_type),
+            requests: None,
+        }
+        .into_resource()
+    } else {
+        SimpleScsiDiskHandle {
+            disk: disk_type,
+            read_only,
+            parameters: Default::default(),
+        }
+        .into_resource()
+    };
+
+    // The controller fails the add if the path is in use.
+    scsi.call_failable(
+        ScsiControllerRequest::AddDevice,
+        ScsiDeviceAndPath {
+            path: scsi_path,
+            device,
+        },
+    )
+    .await?;
+
+    if let (Some(snapshots), Some(send)) = (&mut resources.scsi_snapshots, snapshot_send) {
+        snapshots.insert(lun, send);
+    }
+    Ok(())
+}
+
+/// Redirects the writes of the VTL0 SCSI disk at `lun` to a new diff layer,
+/// returning the number of snapshots taken of the disk.
+async fn snapshot_disk(
+    resources: &VmResources,
+    lun: u8,
+    diff_path: Option<&Path>,
+) -> anyhow::Result<u64> {
+    let send = resources
+        .scsi_snapshots
+        .as_ref()
+        .context("disk snapshots are not enabled")?
+        .get(&lun)
+        .with_context(|| format!("no writable disk at lun {lun}"))?;
+    let layer = storage_builder::snapshot_diff_layer(diff_path)?;
+    let snapshot_count = send
+        .call_failable(SnapshotDiskRequest::Snapshot, layer)
+        .await
+        .with_context(|| format!("failed to snapshot lun {lun}"))?;
+    Ok(snapshot_count)
+}
+
+fn nvme_controller(
+    resources: &VmResources,
+    vtl2: bool,
+) -> anyhow::Result<&mesh::Sender<NvmeControllerRequest>> {
+    if vtl2 {
+        resources
+            .nvme_vtl2_rpc
+            .as_ref()
+            .context("no vtl2 nvme controller")
+    } else {
+        resources.nvme_rpc.as_ref().context("no nvme controller")
+    }
+}
+
+async fn launch_gdb_worker(
+    mesh: &VmmMesh,
+    name: &str,
+    port: u16,
+    req_chan: mesh::Sender<vmm_core_defs::debug_rpc::DebugRequest>,
+    vp_count: u32,
+    vtl: u8,
+) -> anyhow::Result<WorkerHandle> {
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
+        .with_context(|| format!("binding to gdb port {}", port))?;
+
+    let gdb_host = mesh
+        .make_host(name, None)
+        .await
+        .context("spawning gdbstub process failed")?;
+
+    gdb_host
+        .launch_worker(
+            debug_worker_defs::DEBUGGER_WORKER,
+            debug_worker_defs::DebuggerParameters {
+                listener,
+                req_chan,
+                vp_count,
+                target_arch: if cfg!(guest_arch = "x86_64") {
+                    debug_worker_defs::TargetArch::X86_64
+                } else {
+                    debug_worker_defs::TargetArch::Aarch64
+                },
+                vtl,
+            },
+        )
+        .await
+        .context("failed to launch gdbstub worker")
+}
+
 async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> anyhow::Result<()> {
     let (mut vm_config, mut resources) = vm_config_from_command_line(driver, &opt)?;
 
+    let mut framebuffer_access = resources.framebuffer_access.take();
     let mut vnc_worker = None;
     if opt.gfx || opt.vnc {
         let listener = TcpListener::bind(format!("127.0.0.1:{}", opt.vnc_port))
             .with_context(|| format!("binding to VNC port {}", opt.vnc_port))?;
 
         let input_send = vm_config.input.sender();
-        let framebuffer = resources.framebuffer_access.expect("synth video enabled");
+
+        // Keep a framebuffer accessor for the `screendump` command.
+        let (framebuffer, screendump_framebuffer, relay) = framebuffer_access
+            .take()
+            .expect("synth video enabled")
+            .split()
+            .context("failed to split framebuffer access")?;
+        driver.spawn("framebuffer-format-relay", relay).detach();
+        framebuffer_access = Some(screendump_framebuffer);
 
         let vnc_host = mesh
             .make_host("vnc", None)
@@ -1990,46 +3544,60 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
                         listener,
                         framebuffer,
                         input_send,
+                        resolution_send: resources.video_resolution.take(),
                     },
                 )
                 .await?,
         )
     }
 
-    // spin up the debug worker
-    let gdb_worker = if let Some(port) = opt.gdb {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
-            .with_context(|| format!("binding to gdb port {}", port))?;
+    let mut framebuffer_view = framebuffer_access
+        .map(|access| access.view())
+        .transpose()
+        .context("failed to map framebuffer")?;
 
+    // spin up the debug workers
+    let gdb_worker = if let Some(port) = opt.gdb {
         let (req_tx, req_rx) = mesh::channel();
         vm_config.debugger_rpc = Some(req_rx);
-
-        let gdb_host = mesh
-            .make_host("gdb", None)
-            .await
-            .context("spawning gdbstub process failed")?;
-
-        Some(
-            gdb_host
-                .launch_worker(
-                    debug_worker_defs::DEBUGGER_WORKER,
-                    debug_worker_defs::DebuggerParameters {
-                        listener,
-                        req_chan: req_tx,
-                        vp_count: vm_config.processor_topology.proc_count,
-                        target_arch: if cfg!(guest_arch = "x86_64") {
-                            debug_worker_defs::TargetArch::X86_64
-                        } else {
-                            debug_worker_defs::TargetArch::Aarch64
-                        },
-                    },
-                )
-                .await
-                .context("failed to launch gdbstub worker")?,
-        )
+        let vp_count = vm_config.processor_topology.proc_count;
+        Some(launch_gdb_worker(mesh, "gdb", port, req_tx, vp_count, 0).await?)
     } else {
         None
     };
+    let gdb_vtl2_worker = if let Some(port) = opt.gdb_vtl2 {
+        let (req_tx, req_rx) = mesh::channel();
+        vm_config.vtl2_debugger_rpc = Some(req_rx);
+        let vp_count = vm_config.processor_topology.proc_count;
+        Some(launch_gdb_worker(mesh, "gdb-vtl2", port, req_tx, vp_count, 2).await?)
+    } else {
+        None
+    };
+
+    // Load the snapshot or receive the VM from the migration source before
+    // starting the VM, so that its RAM can back the new VM directly.
+    let mut saved_state = None;
+    let mut migration_completion = None;
+    if let Some(address) = &opt.incoming {
+        let incoming = migrate::receive(driver, address)
+            .await
+            .context("incoming migration failed")?;
+        vm_config.memory.backing_file = Some(MemoryBackingFile {
+            file: incoming.ram,
+            shared: !incoming.copy_on_write,
+        });
+        saved_state = Some(incoming.saved_state);
+        migration_completion = Some(incoming.completion);
+    } else if let Some(path) = &opt.resume {
+        let saved = snapshot::load(path)
+            .await
+            .with_context(|| format!("failed to load snapshot {}", path.display()))?;
+        vm_config.memory.backing_file = Some(MemoryBackingFile {
+            file: saved.ram,
+            shared: false,
+        });
+        saved_state = Some(saved.saved_state);
+    }
 
     // spin up the VM
     let (vm_rpc, rpc_recv) = mesh::channel();
@@ -2040,7 +3608,7 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
         let params = VmWorkerParameters {
             hypervisor: opt.hypervisor,
             cfg: vm_config,
-            saved_state: None,
+            saved_state,
             rpc: rpc_recv,
             notify: notify_send,
         };
@@ -2050,6 +3618,18 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
             .context("failed to launch vm worker")?
     };
 
+    if let Some(completion) = migration_completion {
+        completion.complete().await?;
+        tracing::info!("incoming migration complete");
+    }
+
+    // A VM started from a file is a clone of the saved VM, or a revert to it,
+    // so the guest must not reuse the generation ID it saw when it was saved.
+    // A migrated VM continues the same VM, so it keeps its ID.
+    if opt.resume.is_some() || matches!(&opt.incoming, Some(MigrationAddressCli::File(_))) {
+        resources.update_generation_id();
+    }
+
     if !opt.paused {
         vm_rpc.call(VmRpc::Resume, ()).await?;
     }
@@ -2072,7 +3652,69 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
     let (console_command_send, console_command_recv) = mesh::channel();
     let (inspect_completion_engine_send, inspect_completion_engine_recv) = mesh::channel();
 
-    let mut console_in = resources.console_in;
+    let (qmp_disk_send, qmp_disk_recv) = mesh::channel();
+    if let Some(path) = &opt.qmp {
+        cleanup_socket(path);
+        let listener = unix_socket::UnixListener::bind(path)
+            .with_context(|| format!("failed to bind to qmp socket: {}", path.display()))?;
+        let server = qmp::QmpServer {
+            vm_rpc: vm_rpc.clone(),
+            shutdown_ic: resources.shutdown_ic.clone(),
+            kvp_ic: resources.kvp_ic.clone(),
+            vss_ic: resources.vss_ic.clone(),
+            battery: resources.battery.clone(),
+            commands: console_command_send.clone(),
+            disks: qmp_disk_send,
+        };
+        let qmp_driver = driver.clone();
+        driver
+            .spawn("qmp", async move {
+                if let Err(err) = server.run(&qmp_driver, listener).await {
+                    tracing::error!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "qmp server failed"
+                    );
+                }
+            })
+            .detach();
+    }
+
+    let (http_inspect_send, http_inspect_recv) = mesh::channel();
+    if let Some(address) = opt.metrics {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("binding to metrics address {address}"))?;
+        let metrics_driver = driver.clone();
+        let inspect_send = http_inspect_send.clone();
+        driver
+            .spawn("metrics", async move {
+                if let Err(err) = metrics::serve(&metrics_driver, listener, inspect_send).await {
+                    tracing::error!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "metrics server failed"
+                    );
+                }
+            })
+            .detach();
+    }
+    if let Some(address) = opt.inspect_http {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("binding to inspect address {address}"))?;
+        let inspect_driver = driver.clone();
+        let inspect_send = http_inspect_send.clone();
+        driver
+            .spawn("inspect-http", async move {
+                if let Err(err) = inspect_http::serve(&inspect_driver, listener, inspect_send).await
+                {
+                    tracing::error!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "inspect http server failed"
+                    );
+                }
+            })
+            .detach();
+    }
+
+    let mut console_mux = resources.console_mux;
     thread::Builder::new()
         .name("stdio-thread".to_string())
         .spawn(move || {
@@ -2136,31 +3778,28 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
 
             let mut parser = CommandParser::new();
 
+            let send_command = |cmd| {
+                // Send the command to the main thread for processing.
+                let (processing_done_send, processing_done_recv) = mesh::oneshot::<()>();
+                console_command_send.send((cmd, processing_done_send));
+                let _ = block_on(processing_done_recv);
+            };
+
             let mut stdin = io::stdin();
             loop {
-                // Raw console text until Ctrl-Q.
+                // Raw console text until the user asks for the monitor.
                 term::set_raw_console(true).expect("failed to set raw console mode");
 
-                if let Some(input) = console_in.as_mut() {
-                    let mut buf = [0; 32];
-                    loop {
-                        let n = stdin.read(&mut buf).unwrap();
-                        let mut b = &buf[..n];
-                        let stop = if let Some(ctrlq) = b.iter().position(|x| *x == 0x11) {
-                            b = &b[..ctrlq];
-                            true
-                        } else {
-                            false
-                        };
-                        block_on(input.as_mut().write_all(b)).expect("BUGBUG");
-                        if stop {
-                            break;
-                        }
-                    }
-                }
+                let action = console_mux
+                    .as_mut()
+                    .map(|mux| mux.run(&mut stdin).expect("BUGBUG"));
 
                 term::set_raw_console(false).expect("failed to set raw console mode");
 
+                if action == Some(console_mux::MuxAction::Quit) {
+                    send_command(InteractiveCommand::Quit);
+                }
+
                 loop {
                     let line = rl.readline("openvmm> ");
                     if line.is_err() {
@@ -2183,18 +3822,12 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
                             InteractiveCommand::Input { data } => {
                                 let mut data = data.join(" ");
                                 data.push('\n');
-                                if let Some(input) = console_in.as_mut() {
-                                    block_on(input.write_all(data.as_bytes())).expect("BUGBUG");
+                                if let Some(mux) = console_mux.as_mut() {
+                                    mux.write_input(data.as_bytes()).expect("BUGBUG");
                                 }
                             }
                             InteractiveCommand::InputMode => break,
-                            cmd => {
-                                // Send the command to the main thread for processing.
-                                let (processing_done_send, processing_done_recv) =
-                                    mesh::oneshot::<()>();
-                                console_command_send.send((cmd, processing_done_send));
-                                let _ = block_on(processing_done_recv);
-                            }
+                            cmd => send_command(cmd),
                         },
                         Err(err) => {
                             err.print().unwrap();
@@ -2211,6 +3844,11 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
 
     let mut state_change_task = None::<Task<Result<StateChange, RpcError>>>;
     let mut pulse_save_restore_interval: Option<Duration> = None;
+    let mut scheduled_resume: Option<pal_async::timer::Instant> = None;
+    let mut next_state_dump = opt
+        .periodic_state_dump
+        .as_ref()
+        .map(|dump| pal_async::timer::Instant::now() + dump.interval);
     let mut pending_shutdown = None;
 
     enum StateChange {
@@ -2219,6 +3857,17 @@ enum StateChange {
         Reset(Result<(), RemoteError>),
         PulseSaveRestore(Result<(), PulseSaveRestoreError>),
         ServiceVtl2(anyhow::Result<Duration>),
+        Migrate(anyhow::Result<()>),
+        Save(anyhow::Result<()>),
+        GuestCrashDump(anyhow::Result<()>),
+        StateDump(anyhow::Result<PathBuf>),
+    }
+
+    #[derive(Debug)]
+    enum GuestCrash {
+        HyperV(vmm_core_defs::GuestCrash),
+        PvPanic(PvPanicEvent),
+        TripleFault { vp: u32 },
     }
 
     enum Event {
@@ -2226,9 +3875,14 @@ enum Event {
         InspectRequestFromCompletionEngine(
             (InspectTarget, String, mesh::OneshotSender<inspect::Node>),
         ),
+        InspectRequestFromHttp(HttpInspectRequest),
+        QmpDisk(qmp::DiskRequest),
         Quit,
         Halt(vmm_core_defs::HaltReason),
         PulseSaveRestore,
+        ScheduledResume,
+        PeriodicStateDump,
+        GuestCrash(GuestCrash),
         Worker(WorkerEvent),
         VncWorker(WorkerEvent),
         StateChange(Result<StateChange, RpcError>),
@@ -2239,11 +3893,33 @@ enum Event {
         .map(Event::Command)
         .chain(futures::stream::repeat_with(|| Event::Quit));
 
-    let mut notify_recv = notify_recv.map(Event::Halt);
+    let mut notify_recv = notify_recv.map(|reason| match reason {
+        vmm_core_defs::HaltReason::TripleFault { vp, .. } => {
+            Event::GuestCrash(GuestCrash::TripleFault { vp })
+        }
+        reason => Event::Halt(reason),
+    });
 
     let mut inspect_completion_engine_recv =
         inspect_completion_engine_recv.map(Event::InspectRequestFromCompletionEngine);
 
+    let mut server_recv = (
+        http_inspect_recv.map(Event::InspectRequestFromHttp),
+        qmp_disk_recv.map(Event::QmpDisk),
+    )
+        .merge();
+
+    let mut guest_crash_recv = (
+        futures::stream::iter(resources.guest_crash.take())
+            .flatten()
+            .map(GuestCrash::HyperV),
+        futures::stream::iter(resources.pvpanic.take())
+            .flatten()
+            .map(GuestCrash::PvPanic),
+    )
+        .merge()
+        .map(Event::GuestCrash);
+
     let mut quit = false;
     loop {
         let event = {
@@ -2257,6 +3933,26 @@ enum Event {
                 }
             });
 
+            let resume = pin!(async {
+                match scheduled_resume {
+                    Some(deadline) => {
+                        PolledTimer::new(driver).sleep_until(deadline).await;
+                        Event::ScheduledResume
+                    }
+                    None => pending().await,
+                }
+            });
+
+            let state_dump = pin!(async {
+                match next_state_dump {
+                    Some(deadline) => {
+                        PolledTimer::new(driver).sleep_until(deadline).await;
+                        Event::PeriodicStateDump
+                    }
+                    None => pending().await,
+                }
+            });
+
             let vm = (&mut vm_worker).map(Event::Worker);
             let vnc = futures::stream::iter(vnc_worker.as_mut())
                 .flatten()
@@ -2275,8 +3971,12 @@ enum Event {
             (
                 &mut console_command_recv,
                 &mut inspect_completion_engine_recv,
+                &mut server_recv,
                 &mut notify_recv,
+                &mut guest_crash_recv,
                 pulse_save_restore.into_stream(),
+                resume.into_stream(),
+                state_dump.into_stream(),
                 vm,
                 vnc,
                 change,
@@ -2300,6 +4000,7 @@ enum Event {
                             &vm_worker,
                             vnc_worker.as_ref(),
                             gdb_worker.as_ref(),
+                            gdb_vtl2_worker.as_ref(),
                             &mut diag_inspector,
                         ));
                 let _ = CancelContext::new()
@@ -2311,6 +4012,41 @@ enum Event {
                 res.send(node);
                 continue;
             }
+            Event::InspectRequestFromHttp(request) => {
+                let mut inspection = InspectionBuilder::new(&request.path)
+                    .depth(request.depth)
+                    .inspect(inspect_obj(
+                        InspectTarget::Host,
+                        mesh,
+                        &vm_worker,
+                        vnc_worker.as_ref(),
+                        gdb_worker.as_ref(),
+                        gdb_vtl2_worker.as_ref(),
+                        &mut diag_inspector,
+                    ));
+                let _ = CancelContext::new()
+                    .with_timeout(Duration::from_secs(1))
+                    .until_cancelled(inspection.resolve())
+                    .await;
+
+                request.result.send(inspection.results());
+                continue;
+            }
+            Event::QmpDisk(request) => {
+                match request {
+                    qmp::DiskRequest::Add(rpc) => {
+                        rpc.handle_failable(async |command| add_disk(&mut resources, command).await)
+                            .await
+                    }
+                    qmp::DiskRequest::Snapshot(rpc) => {
+                        rpc.handle_failable(async |(lun, diff_path)| {
+                            snapshot_disk(&resources, lun, diff_path.as_deref()).await
+                        })
+                        .await
+                    }
+                }
+                continue;
+            }
             Event::Quit => break,
             Event::Halt(reason) => {
                 tracing::info!(?reason, "guest halted");
@@ -2320,6 +4056,100 @@ enum Event {
                 vm_rpc.call(VmRpc::PulseSaveRestore, ()).await??;
                 continue;
             }
+            Event::GuestCrash(crash) => {
+                tracing::error!(?crash, "guest crashed");
+                match opt.on_guest_crash {
+                    GuestCrashActionCli::None => {}
+                    GuestCrashActionCli::Pause => {
+                        scheduled_resume = None;
+                        state_change(
+                            driver,
+                            &vm_rpc,
+                            &mut state_change_task,
+                            VmRpc::Pause,
+                            StateChange::Pause,
+                        );
+                    }
+                    GuestCrashActionCli::Dump => {
+                        scheduled_resume = None;
+                        let vm_rpc = vm_rpc.clone();
+                        let dir = opt.guest_crash_dump_dir.clone().unwrap();
+                        let (format, extension) = match opt.guest_crash_dump_format {
+                            GuestCrashDumpFormatCli::Elf => (guest_dump::DumpFormat::Elf, "core"),
+                            GuestCrashDumpFormatCli::Windows => {
+                                (guest_dump::DumpFormat::Windows, "dmp")
+                            }
+                        };
+                        let info = match crash {
+                            GuestCrash::HyperV(crash) => guest_dump::CrashInfo {
+                                vp: Some(crash.vp),
+                                bugcheck: crash.parameters.try_into().ok(),
+                            },
+                            GuestCrash::PvPanic(_) => guest_dump::CrashInfo::default(),
+                            GuestCrash::TripleFault { vp } => guest_dump::CrashInfo {
+                                vp: Some(vp),
+                                bugcheck: None,
+                            },
+                        };
+                        let vp_count = opt.processors;
+                        let r = async move {
+                            vm_rpc.call(VmRpc::Pause, ()).await?;
+                            let timestamp = std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_secs();
+                            fs_err::create_dir_all(&dir)?;
+                            let path = dir.join(format!("guest-crash-{timestamp}.{extension}"));
+                            tracing::info!(path = %path.display(), "writing guest crash dump");
+                            guest_dump::write_guest_dump(&vm_rpc, &path, format, vp_count, &info)
+                                .await
+                        }
+                        .map(|r| Ok(StateChange::GuestCrashDump(r)));
+                        if state_change_task.is_some() {
+                            tracing::error!("state change already in progress");
+                        } else {
+                            state_change_task = Some(driver.spawn("state-change", r));
+                        }
+                    }
+                    GuestCrashActionCli::Restart => {
+                        state_change(
+                            driver,
+                            &vm_rpc,
+                            &mut state_change_task,
+                            VmRpc::Reset,
+                            StateChange::Reset,
+                        );
+                    }
+                }
+                continue;
+            }
+            Event::PeriodicStateDump => {
+                let dump = opt.periodic_state_dump.as_ref().unwrap();
+                next_state_dump = Some(pal_async::timer::Instant::now() + dump.interval);
+                // Skip this dump rather than racing with another state change,
+                // such as a pause requested by the user.
+                if state_change_task.is_some() {
+                    tracing::warn!("state change in progress, skipping state dump");
+                } else {
+                    let vm_rpc = vm_rpc.clone();
+                    let dir = dump.dir.clone();
+                    let r = async move { state_dump::write_state_dump(&vm_rpc, &dir).await }
+                        .map(|r| Ok(StateChange::StateDump(r)));
+                    state_change_task = Some(driver.spawn("state-dump", r));
+                }
+                continue;
+            }
+            Event::ScheduledResume => {
+                scheduled_resume = None;
+                state_change(
+                    driver,
+                    &vm_rpc,
+                    &mut state_change_task,
+                    VmRpc::Resume,
+                    StateChange::Resume,
+                );
+                continue;
+            }
             Event::Worker(event) => {
                 match event {
                     WorkerEvent::Stopped => {
@@ -2410,6 +4240,36 @@ enum Event {
                                 "vtl2 servicing failed"
                             ),
                         },
+                        StateChange::Migrate(r) => match r {
+                            Ok(()) => tracing::info!("migration complete"),
+                            Err(err) => tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "migration failed"
+                            ),
+                        },
+                        StateChange::Save(r) => match r {
+                            Ok(()) => tracing::info!("save complete"),
+                            Err(err) => tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "save failed"
+                            ),
+                        },
+                        StateChange::GuestCrashDump(r) => match r {
+                            Ok(()) => tracing::info!("guest crash dump complete"),
+                            Err(err) => tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "guest crash dump failed"
+                            ),
+                        },
+                        StateChange::StateDump(r) => match r {
+                            Ok(path) => {
+                                tracing::info!(path = %path.display(), "wrote state dump")
+                            }
+                            Err(err) => tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "state dump failed"
+                            ),
+                        },
                     },
                     Err(err) => {
                         tracing::error!(
@@ -2455,15 +4315,22 @@ fn inspect_obj<'a>(
             vm_worker: &'a WorkerHandle,
             vnc_worker: Option<&'a WorkerHandle>,
             gdb_worker: Option<&'a WorkerHandle>,
+            gdb_vtl2_worker: Option<&'a WorkerHandle>,
             diag_inspector: &'a mut DiagInspector,
         ) -> impl 'a + InspectMut {
             inspect::adhoc_mut(move |req| match target {
                 InspectTarget::Host => {
                     let mut resp = req.respond();
                     resp.field("mesh", mesh)
+                        .field("log_filter", mesh.log_filter())
+                        .field(
+                            "heap",
+                            alloc_tracker::is_enabled().then(alloc_tracker::stats),
+                        )
                         .field("vm", vm_worker)
                         .field("vnc", vnc_worker)
-                        .field("gdb", gdb_worker);
+                        .field("gdb", gdb_worker)
+                        .field("gdb_vtl2", gdb_vtl2_worker);
                 }
                 InspectTarget::Paravisor => {
                     diag_inspector.inspect_mut(req);
@@ -2497,7 +4364,9 @@ fn state_change<U: 'static + Send>(
 
                 vm_worker.restart(&vm_host);
             }
-            InteractiveCommand::Pause => {
+            InteractiveCommand::Pause { resume_after } => {
+                scheduled_resume = resume_after
+                    .map(|seconds| pal_async::timer::Instant::now() + Duration::from_secs(seconds));
                 state_change(
                     driver,
                     &vm_rpc,
@@ -2507,6 +4376,7 @@ fn state_change<U: 'static + Send>(
                 );
             }
             InteractiveCommand::Resume => {
+                scheduled_resume = None;
                 state_change(
                     driver,
                     &vm_rpc,
@@ -2566,74 +4436,126 @@ fn state_change<U: 'static + Send>(
                     println!("no shutdown ic configured");
                 }
             }
-            InteractiveCommand::Nmi => {
-                let _ = vm_rpc.call(VmRpc::Nmi, 0).await;
+            InteractiveCommand::Nmi { vp } => {
+                let _ = vm_rpc.call(VmRpc::Nmi, vp).await;
+            }
+            InteractiveCommand::Sci { gpe } => {
+                if let Err(error) = vm_rpc.call_failable(VmRpc::Sci, gpe).await {
+                    eprintln!("error: {}", error);
+                }
+            }
+            InteractiveCommand::Mce { vp } => {
+                if let Err(error) = vm_rpc.call_failable(VmRpc::MachineCheck, vp).await {
+                    eprintln!("error: {}", error);
+                }
+            }
+            InteractiveCommand::Screendump { file } => {
+                if let Some(view) = &mut framebuffer_view {
+                    if let Err(error) = screendump::write_screendump(view, &file) {
+                        eprintln!("error: {:#}", error);
+                    }
+                } else {
+                    eprintln!("error: no framebuffer; use --gfx, --vnc, or --pcat");
+                }
             }
             InteractiveCommand::ClearHalt => {
                 vm_rpc.call(VmRpc::ClearHalt, ()).await.ok();
             }
-            InteractiveCommand::AddDisk {
-                read_only,
+            InteractiveCommand::Wake { source } => {
+                let event = match source {
+                    WakeSourceCli::PowerButton => chipset_resources::pm::WakeEvent::PowerButton,
+                    WakeSourceCli::RtcAlarm => chipset_resources::pm::WakeEvent::RtcAlarm,
+                    WakeSourceCli::Keyboard => chipset_resources::pm::WakeEvent::Keyboard,
+                };
+                if let Err(error) = vm_rpc.call_failable(VmRpc::Wake, event).await {
+                    eprintln!("error: {}", error);
+                }
+            }
+            InteractiveCommand::ScheduleWake { seconds } => {
+                vm_rpc
+                    .call(VmRpc::ScheduleWake, seconds.map(Duration::from_secs))
+                    .await
+                    .ok();
+            }
+            InteractiveCommand::UpdateGenerationId => resources.update_generation_id(),
+            InteractiveCommand::Memory { size } => {
+                if let Err(error) = vm_rpc.call_failable(VmRpc::SetMemorySize, size).await {
+                    eprintln!("error: {}", error);
+                }
+            }
+            InteractiveCommand::Balloon { size } => {
+                if let Err(error) = vm_rpc.call_failable(VmRpc::SetBalloonSize, size).await {
+                    eprintln!("error: {}", error);
+                }
+            }
+            InteractiveCommand::VpPin { vp, cpus } => {
+                let r = async {
+                    let cpus = cpus
+                        .as_deref()
+                        .map(cli_args::parse_cpu_list)
+                        .transpose()?
+                        .unwrap_or_default();
+                    vm_rpc
+                        .call_failable(VmRpc::SetVpAffinity, (vp, cpus))
+                        .await?;
+                    anyhow::Ok(())
+                }
+                .await;
+                if let Err(error) = r {
+                    eprintln!("error: {:#}", error);
+                }
+            }
+            InteractiveCommand::Vf { action, index } => {
+                if let Some(&instance_id) = resources.mana_vfs.get(index) {
+                    let rpc = match action {
+                        VfActionCli::Revoke => VmRpc::RevokeVpciDevice,
+                        VfActionCli::Offer => VmRpc::OfferVpciDevice,
+                    };
+                    if let Err(error) = vm_rpc.call_failable(rpc, instance_id).await {
+                        eprintln!("error: {}", error);
+                    }
+                } else {
+                    eprintln!("error: no mana vf {index}; use --mana-vf");
+                }
+            }
+            InteractiveCommand::PciSlot { action, device } => {
+                if let Some(pci_hotplug) = &resources.pci_hotplug {
+                    pci_hotplug.send(match action {
+                        PciSlotActionCli::Insert => PciHotPlugRequest::Insert(device),
+                        PciSlotActionCli::Remove => PciHotPlugRequest::Remove(device),
+                    });
+                } else {
+                    println!("no pci hot-plug slots configured");
+                }
+            }
+            InteractiveCommand::AddDisk(command) => {
+                if let Err(error) = add_disk(&mut resources, command).await {
+                    tracing::error!(error = error.as_error(), "error adding disk")
+                }
+            }
+            InteractiveCommand::RmDisk {
                 target,
                 path,
                 lun,
-                ram,
-                file_path,
-                is_dvd,
+                nvme,
+                vtl2,
             } => {
                 let action = async {
+                    if let Some(nsid) = nvme {
+                        let nvme = nvme_controller(&resources, vtl2)?;
+                        nvme.call_failable(NvmeControllerRequest::RemoveNamespace, nsid)
+                            .await?;
+                        return anyhow::Ok(());
+                    }
                     let scsi = resources.scsi_rpc.as_ref().context("no scsi controller")?;
-                    let disk_type = match ram {
-                        None => {
-                            let path = file_path.context("no filename passed")?;
-                            open_disk_type(path.as_ref(), read_only)
-                                .with_context(|| format!("failed to open {}", path.display()))?
-                        }
-                        Some(size) => {
-                            Resource::new(disk_backend_resources::LayeredDiskHandle::single_layer(
-                                RamDiskLayerHandle { len: Some(size) },
-                            ))
-                        }
-                    };
-
-                    let device = if is_dvd {
-                        SimpleScsiDvdHandle {
-                            media: Some(disk_type),
-                            requests: None,
-                        }
-                        .into_resource()
-                    } else {
-                        SimpleScsiDiskHandle {
-                            disk: disk_type,
-                            read_only,
-                            parameters: Default::default(),
-                        }
-                        .into_resource()
-                    };
-
-                    let cfg = ScsiDeviceAndPath {
-                        path: ScsiPath { path, target, lun },
-                        device,
-                    };
-
-                    scsi.call_failable(ScsiControllerRequest::AddDevice, cfg)
+                    let scsi_path = ScsiPath { target, path, lun };
+                    scsi.call_failable(ScsiControllerRequest::RemoveDevice, scsi_path)
                         .await?;
-
-                    anyhow::Result::<_>::Ok(())
-                };
-
-                if let Err(error) = action.await {
-                    tracing::error!(error = error.as_error(), "error adding disk")
-                }
-            }
-            InteractiveCommand::RmDisk { target, path, lun } => {
-                let action = async {
-                    let scsi = resources.scsi_rpc.as_ref().context("no scsi controller")?;
-                    scsi.call_failable(
-                        ScsiControllerRequest::RemoveDevice,
-                        ScsiPath { target, path, lun },
-                    )
-                    .await?;
+                    if let Some(snapshots) = &mut resources.scsi_snapshots {
+                        if scsi_path == scsi_lun_path(lun) {
+                            snapshots.remove(&lun);
+                        }
+                    }
                     anyhow::Ok(())
                 };
 
@@ -2658,6 +4580,7 @@ fn state_change<U: 'static + Send>(
                     &vm_worker,
                     vnc_worker.as_ref(),
                     gdb_worker.as_ref(),
+                    gdb_vtl2_worker.as_ref(),
                     &mut diag_inspector,
                 );
 
@@ -2782,11 +4705,35 @@ fn state_change<U: 'static + Send>(
                     state_change_task = Some(driver.spawn("state-change", r));
                 }
             }
+            InteractiveCommand::Migrate { address } => {
+                let migrate_driver = driver.clone();
+                let vm_rpc = vm_rpc.clone();
+                let r = async move { migrate::send(&migrate_driver, &vm_rpc, &address).await }
+                    .map(|r| Ok(StateChange::Migrate(r)));
+                if state_change_task.is_some() {
+                    tracing::error!("state change already in progress");
+                } else {
+                    state_change_task = Some(driver.spawn("state-change", r));
+                }
+            }
+            InteractiveCommand::Save { file } => {
+                let vm_rpc = vm_rpc.clone();
+                let r = async move { snapshot::save(&vm_rpc, &file).await }
+                    .map(|r| Ok(StateChange::Save(r)));
+                if state_change_task.is_some() {
+                    tracing::error!("state change already in progress");
+                } else {
+                    state_change_task = Some(driver.spawn("state-change", r));
+                }
+            }
             InteractiveCommand::Quit => {
                 tracing::info!("quitting");
                 // Work around the detached SCSI task holding up worker stop.
                 // TODO: Fix the underlying bug
                 resources.scsi_rpc = None;
+                resources.scsi_snapshots = None;
+                resources.nvme_rpc = None;
+                resources.nvme_vtl2_rpc = None;
 
                 vm_worker.stop();
                 quit = true;
@@ -2902,6 +4849,31 @@ fn state_change<U: 'static + Send>(
                     eprintln!("error: {err:#}");
                 }
             }
+            InteractiveCommand::CopyToGuest(command) => {
+                let Some(fcopy) = &resources.fcopy_ic else {
+                    eprintln!("error: no file copy ic configured");
+                    continue;
+                };
+                if let Err(err) = fcopy::handle_copy_to_guest(fcopy, command).await {
+                    eprintln!("error: {err:#}");
+                }
+            }
+            InteractiveCommand::Vss(command) => {
+                let Some(vss) = &resources.vss_ic else {
+                    eprintln!("error: no vss ic configured");
+                    continue;
+                };
+                if let Err(err) = vss::handle_vss(vss, command).await {
+                    eprintln!("error: {err:#}");
+                }
+            }
+            InteractiveCommand::Battery(command) => {
+                let Some(battery) = &resources.battery else {
+                    eprintln!("error: no battery configured");
+                    continue;
+                };
+                battery::handle_battery(battery, command);
+            }
             InteractiveCommand::Input { .. } | InteractiveCommand::InputMode => unreachable!(),
         }
     }
@@ -3025,6 +4997,13 @@ enum InspectTarget {
     Paravisor,
 }
 
+/// An inspect request from one of the HTTP diagnostics endpoints.
+struct HttpInspectRequest {
+    path: String,
+    depth: Option<usize>,
+    result: mesh::OneshotSender<inspect::Node>,
+}
+
 mod interactive_console {
     use super::InteractiveCommand;
     use rustyline::Helper;
@@ -3183,3 +5162,119 @@ async fn complete(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Options {
+        Options::try_parse_from([&["openvmm"][..], args].concat()).unwrap()
+    }
+
+    #[test]
+    fn test_mmio_gaps_defaults() {
+        let opt = parse(&[]);
+        assert_eq!(mmio_gaps(&opt, true, false).unwrap(), DEFAULT_MMIO_GAPS_X86);
+        assert_eq!(
+            mmio_gaps(&opt, true, true).unwrap(),
+            DEFAULT_MMIO_GAPS_X86_WITH_VTL2
+        );
+        assert_eq!(
+            mmio_gaps(&opt, false, false).unwrap(),
+            DEFAULT_MMIO_GAPS_AARCH64
+        );
+        assert_eq!(
+            mmio_gaps(&opt, false, true).unwrap(),
+            DEFAULT_MMIO_GAPS_AARCH64_WITH_VTL2
+        );
+    }
+
+    #[test]
+    fn test_mmio_gaps_custom() {
+        let opt = parse(&["--mmio-gap-low", "256M", "--mmio-gap-high", "1G"]);
+        assert_eq!(
+            mmio_gaps(&opt, true, false).unwrap(),
+            [
+                MemoryRange::new(0xf000_0000..0x1_0000_0000),
+                MemoryRange::new(0xf_e000_0000..0x10_2000_0000),
+            ]
+        );
+
+        // The VTL2 gap follows the high gap, aligned to 1GB.
+        let opt = parse(&["--mmio-gap-high", "66G"]);
+        assert_eq!(
+            mmio_gaps(&opt, true, true).unwrap(),
+            [
+                DEFAULT_MMIO_GAPS_X86_WITH_VTL2[0],
+                MemoryRange::new(0xf_e000_0000..0x20_6000_0000),
+                MemoryRange::new(0x20_8000_0000..0x20_c000_0000),
+            ]
+        );
+
+        let opt = parse(&["--mmio-gap-low", "1G"]);
+        assert_eq!(
+            mmio_gaps(&opt, false, true).unwrap(),
+            [
+                MemoryRange::new(0xc000_0000..0x1_0000_0000),
+                DEFAULT_MMIO_GAPS_AARCH64_WITH_VTL2[1],
+                DEFAULT_MMIO_GAPS_AARCH64_WITH_VTL2[2],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mmio_gaps_invalid() {
+        for args in [
+            // Not a multiple of 1MB.
+            &["--mmio-gap-low", "131073K"][..],
+            &["--mmio-gap-high", "1048577K"],
+            // Smaller than the default.
+            &["--mmio-gap-low", "64M"],
+            &["--mmio-gap-high", "256M"],
+            // Overlapping the RAM below 4GB.
+            &["--mmio-gap-low", "3073M"],
+            // Past the end of the address space.
+            &["--mmio-gap-high", "18446744073708503040"],
+        ] {
+            let opt = parse(args);
+            assert!(mmio_gaps(&opt, true, false).is_err(), "{args:?}");
+        }
+
+        // The high gap fits, but the VTL2 gap after it does not.
+        let opt = parse(&["--mmio-gap-high", "18446744004453203968"]);
+        assert!(mmio_gaps(&opt, true, false).is_ok());
+        assert!(mmio_gaps(&opt, true, true).is_err());
+    }
+
+    #[test]
+    fn test_pci_ecam() {
+        let low = DEFAULT_MMIO_GAPS_X86[0];
+        assert_eq!(pci_ecam(None, None, low).unwrap(), None);
+        assert_eq!(
+            pci_ecam(Some(4), None, low).unwrap(),
+            Some(MemoryRange::new(0xf800_0000..0xf840_0000))
+        );
+        assert_eq!(
+            pci_ecam(None, Some(2), low).unwrap(),
+            Some(MemoryRange::new(0xf800_0000..0xf820_0000))
+        );
+        assert!(pci_ecam(Some(1), Some(2), low).is_err());
+        // The window must stay below the platform devices.
+        assert!(pci_ecam(Some(96), None, low).is_ok());
+        assert!(pci_ecam(Some(97), None, low).is_err());
+        assert_eq!(
+            pci_ecam(
+                Some(256),
+                None,
+                MemoryRange::new(0xe000_0000..0x1_0000_0000)
+            )
+            .unwrap(),
+            Some(MemoryRange::new(0xe000_0000..0xf000_0000))
+        );
+
+        assert_eq!(parse(&["--pci-ecam", "256"]).pci_ecam, Some(256));
+        assert!(Options::try_parse_from(["openvmm", "--pci-ecam", "0"]).is_err());
+        assert!(Options::try_parse_from(["openvmm", "--pci-ecam", "257"]).is_err());
+    }
+}
@@ -4,7 +4,12 @@
 //! Functions and types for running a mesh for hvlite and launching workers
 //! within it.
 
+use crate::profile::ProfileOptions;
+use crate::profile::Profiler;
+use crate::tracing_init::LogControl;
+use crate::tracing_init::LogFilter;
 use anyhow::Context;
+use futures_concurrency::future::Race;
 use hvlite_defs::entrypoint::MeshHostParams;
 use inspect::Inspect;
 use mesh_process::Mesh;
@@ -16,9 +21,18 @@
 use pal_async::task::Task;
 use std::path::PathBuf;
 
-pub(crate) fn run_vmm_mesh_host() -> anyhow::Result<()> {
+pub(crate) fn run_vmm_mesh_host(log: LogControl) -> anyhow::Result<()> {
     try_run_mesh_host("openvmm", async |params: MeshHostParams| {
-        params.runner.run(RegisteredWorkers).await;
+        let profiler = Profiler::start_from_env()?;
+        let run = params.runner.run(RegisteredWorkers);
+        if let Some(filter) = params.log_filter {
+            (run, log.follow_filter(filter)).race().await;
+        } else {
+            run.await;
+        }
+        if let Some(profiler) = profiler {
+            profiler.finish();
+        }
         Ok(())
     })
 }
@@ -28,13 +42,22 @@ pub(crate) struct VmmMesh {
     #[inspect(flatten)]
     mesh: Option<Mesh>,
     #[inspect(skip)]
+    log_filter: LogFilter,
+    #[inspect(skip)]
+    profile: ProfileOptions,
+    #[inspect(skip)]
     local_host: WorkerHost,
     #[inspect(skip)]
     _task: Task<()>,
 }
 
 impl VmmMesh {
-    pub fn new(spawn: &impl Spawn, single_process: bool) -> anyhow::Result<Self> {
+    pub fn new(
+        spawn: &impl Spawn,
+        single_process: bool,
+        log: LogControl,
+        profile: ProfileOptions,
+    ) -> anyhow::Result<Self> {
         let mesh = if single_process {
             None
         } else {
@@ -44,11 +67,18 @@ pub fn new(spawn: &impl Spawn, single_process: bool) -> anyhow::Result<Self> {
         let task = spawn.spawn("worker-host", runner.run(RegisteredWorkers));
         Ok(Self {
             mesh,
+            log_filter: LogFilter::new(log),
+            profile,
             local_host,
             _task: task,
         })
     }
 
+    /// Returns the log filter of every process in the mesh.
+    pub fn log_filter(&self) -> &LogFilter {
+        &self.log_filter
+    }
+
     pub async fn make_host(
         &self,
         name: impl Into<String>,
@@ -63,11 +93,23 @@ pub async fn make_host(
             None
         };
 
+        let name = name.into();
         let host = if let Some(mesh) = &self.mesh {
             let (host, runner) = mesh_worker::worker_host();
+            let mut config = ProcessConfig::new(name.clone()).stderr(log_file);
+            for (key, value) in crate::otlp::child_env(&name)
+                .into_iter()
+                .chain(self.log_filter.child_env())
+                .chain(self.profile.child_env(&name))
+            {
+                config = config.env(key, value);
+            }
             mesh.launch_host(
-                ProcessConfig::new(name).stderr(log_file),
-                MeshHostParams { runner },
+                config,
+                MeshHostParams {
+                    runner,
+                    log_filter: Some(self.log_filter.cell()),
+                },
             )
             .await?;
             host
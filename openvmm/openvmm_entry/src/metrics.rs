@@ -0,0 +1,172 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A Prometheus metrics endpoint, serving the VM worker's inspect tree.
+//!
+//! Every numeric or Boolean value in the tree becomes a sample. The metric name
+//! is built from the path to the value, except that path components that are
+//! indexes (such as VP numbers) or GUIDs (such as VMBus instance IDs) become
+//! labels named after the preceding component. For example,
+//! `partition/vp/3/stats/exits` becomes
+//! `openvmm_partition_vp_stats_exits{vp="3"}`. Values marked as counters in
+//! the inspect tree are reported as Prometheus counters, so that rates such as
+//! IOPS can be computed with `rate()`; all other values are gauges.
+
+use crate::HttpInspectRequest;
+use crate::http;
+use guid::Guid;
+use inspect::Node;
+use inspect::ValueKind;
+use pal_async::DefaultDriver;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::TcpListener;
+
+/// Serves metrics over HTTP on `listener`, getting the inspect tree for each
+/// request via `inspect_send`.
+pub async fn serve(
+    driver: &DefaultDriver,
+    listener: TcpListener,
+    inspect_send: mesh::Sender<HttpInspectRequest>,
+) -> anyhow::Result<()> {
+    http::serve(driver, listener, |_request| {
+        let (send, recv) = mesh::oneshot();
+        inspect_send.send(HttpInspectRequest {
+            path: "vm".into(),
+            depth: None,
+            result: send,
+        });
+        async move {
+            match recv.await {
+                Ok(node) => http::Response::ok("text/plain; version=0.0.4", encode(&node)),
+                Err(err) => http::Response::error("503 Service Unavailable", err),
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Default)]
+struct Family {
+    counter: bool,
+    samples: Vec<(String, String)>,
+}
+
+/// Encodes the values in `node` in the Prometheus text format.
+fn encode(node: &Node) -> String {
+    let mut families = BTreeMap::new();
+    collect(node, &mut Vec::new(), &mut Vec::new(), &mut families);
+
+    let mut out = String::new();
+    for (name, family) in families {
+        let kind = if family.counter { "counter" } else { "gauge" };
+        writeln!(out, "# TYPE {name} {kind}").unwrap();
+        for (labels, value) in family.samples {
+            writeln!(out, "{name}{labels} {value}").unwrap();
+        }
+    }
+    out
+}
+
+fn collect(
+    node: &Node,
+    name: &mut Vec<String>,
+    labels: &mut Vec<(String, String)>,
+    families: &mut BTreeMap<String, Family>,
+) {
+    match node {
+        Node::Dir(entries) => {
+            for entry in entries {
+                if entry.name.parse::<u64>().is_ok() || entry.name.parse::<Guid>().is_ok() {
+                    let label = name.last().map_or("index", |s| s.as_str()).to_owned();
+                    labels.push((label, entry.name.clone()));
+                    collect(&entry.node, name, labels, families);
+                    labels.pop();
+                } else {
+                    name.push(sanitize(&entry.name));
+                    collect(&entry.node, name, labels, families);
+                    name.pop();
+                }
+            }
+        }
+        Node::Value(value) => {
+            let sample = match value.kind {
+                ValueKind::Signed(v) => v.to_string(),
+                ValueKind::Unsigned(v) => v.to_string(),
+                ValueKind::Float(v) => v.to_string(),
+                ValueKind::Double(v) => v.to_string(),
+                ValueKind::Bool(v) => u8::from(v).to_string(),
+                _ => return,
+            };
+            let family = families
+                .entry(format!("openvmm_{}", name.join("_")))
+                .or_insert_with(Family::default);
+            family.counter |= value.flags.count();
+            let labels = if labels.is_empty() {
+                String::new()
+            } else {
+                let labels = labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}=\"{value}\""))
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", labels.join(","))
+            };
+            family.samples.push((labels, sample));
+        }
+        Node::Unevaluated | Node::Failed(_) => {}
+    }
+}
+
+/// Replaces characters that are not valid in metric and label names.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inspect::Entry;
+    use inspect::SensitivityLevel;
+    use inspect::Value;
+
+    fn dir(entries: impl IntoIterator<Item = (&'static str, Node)>) -> Node {
+        Node::Dir(
+            entries
+                .into_iter()
+                .map(|(name, node)| Entry {
+                    name: name.into(),
+                    node,
+                    sensitivity: SensitivityLevel::Unspecified,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_encode() {
+        let vp = |exits| {
+            dir([
+                ("exits", Node::Value(Value::counter(exits))),
+                ("running", Node::Value(Value::new(true))),
+            ])
+        };
+        let node = dir([
+            ("vp", dir([("0", vp(5u64)), ("1", vp(7u64))])),
+            ("memory-size", Node::Value(Value::new(4096u64))),
+            ("name", Node::Value(Value::new("vm"))),
+        ]);
+        assert_eq!(
+            encode(&node),
+            "# TYPE openvmm_memory_size gauge\n\
+             openvmm_memory_size 4096\n\
+             # TYPE openvmm_vp_exits counter\n\
+             openvmm_vp_exits{vp=\"0\"} 5\n\
+             openvmm_vp_exits{vp=\"1\"} 7\n\
+             # TYPE openvmm_vp_running gauge\n\
+             openvmm_vp_running{vp=\"0\"} 1\n\
+             openvmm_vp_running{vp=\"1\"} 1\n"
+        );
+    }
+}
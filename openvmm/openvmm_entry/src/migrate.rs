@@ -0,0 +1,543 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Migration of a running VM to another OpenVMM instance.
+//!
+//! The source sends the VM to the destination in the
+//! [snapshot](crate::snapshot) format, and waits for the destination to
+//! acknowledge that it has started the VM by replying with a single `ACK`
+//! byte. The destination must be launched with the same configuration as the
+//! source, plus `--incoming`.
+//!
+//! If the hypervisor can track the pages the guest writes, the source copies
+//! RAM while the VM keeps running, and then repeatedly copies the pages
+//! written since the previous round, until few enough remain (or too many
+//! rounds have passed). Only then is the VM paused, to copy the remaining
+//! pages and the device state. Otherwise, the VM is paused for the whole
+//! transfer.
+//!
+//! The VM can also be written to a file, which then serves as a template that
+//! any number of VMs can be started from.
+
+use crate::cli_args::MigrationAddressCli;
+use crate::snapshot;
+use anyhow::Context as _;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use hvlite_defs::rpc::VmRpc;
+use memory_range::MemoryRange;
+use mesh::payload::message::ProtobufMessage;
+use mesh::rpc::RpcSend;
+use pal_async::driver::Driver;
+use pal_async::socket::PolledSocket;
+use std::fs::File;
+use std::net::TcpListener;
+use std::net::TcpStream;
+
+const ACK: u8 = 1;
+/// The granularity of dirty page tracking.
+const PAGE_SIZE: u64 = 4096;
+
+/// Pre-copy pauses the VM once a round finds at most this many dirty pages.
+const CONVERGED_DIRTY_PAGES: u64 = 256;
+/// The most rounds of copying RAM while the VM runs, for guests that write
+/// memory faster than it can be sent.
+const MAX_PRECOPY_ROUNDS: u32 = 8;
+
+/// Migrates the VM to the destination at `address`.
+///
+/// On success, the VM is left paused, since it is now running on the
+/// destination (or, for a file, saved to it). On failure, the VM is resumed if
+/// it was running.
+pub async fn send(
+    driver: &impl Driver,
+    vm_rpc: &mesh::Sender<VmRpc>,
+    address: &MigrationAddressCli,
+) -> anyhow::Result<()> {
+    let running = vm_rpc.call(VmRpc::IsRunning, ()).await?;
+    let r = send_vm(driver, vm_rpc, address, running).await;
+    if r.is_err() && running {
+        if let Err(err) = vm_rpc.call(VmRpc::Resume, ()).await {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "failed to resume the vm after a failed migration"
+            );
+        }
+    }
+    r
+}
+
+async fn send_vm(
+    driver: &impl Driver,
+    vm_rpc: &mesh::Sender<VmRpc>,
+    address: &MigrationAddressCli,
+    running: bool,
+) -> anyhow::Result<()> {
+    match address {
+        MigrationAddressCli::Tcp(address) => {
+            let stream = TcpStream::connect(address)
+                .with_context(|| format!("failed to connect to migration destination {address}"))?;
+            let mut socket = PolledSocket::new(driver, stream)?;
+            write_vm(vm_rpc, &mut socket, running).await?;
+
+            tracing::info!("waiting for migration destination to start the vm");
+            let mut ack = [0];
+            socket
+                .read_exact(&mut ack)
+                .await
+                .context("migration destination failed to start the vm")?;
+            if ack[0] != ACK {
+                anyhow::bail!("invalid migration acknowledgement {:#x}", ack[0]);
+            }
+        }
+        MigrationAddressCli::File(path) => {
+            vm_rpc.call(VmRpc::Pause, ()).await?;
+            snapshot::save_paused(vm_rpc, path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the VM to `stream`, pausing it. If the VM is `running` and dirty
+/// page tracking is available, RAM is pre-copied while it runs.
+async fn write_vm(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    stream: &mut (impl AsyncWrite + Unpin),
+    running: bool,
+) -> anyhow::Result<()> {
+    if running {
+        match vm_rpc.call_failable(VmRpc::SetDirtyTracking, true).await {
+            Ok(()) => {
+                let r = precopy(vm_rpc, stream).await;
+                if let Err(err) = vm_rpc.call_failable(VmRpc::SetDirtyTracking, false).await {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to disable dirty page tracking"
+                    );
+                }
+                return r;
+            }
+            Err(err) => {
+                tracing::info!(
+                    error = &err as &dyn std::error::Error,
+                    "dirty page tracking unavailable, pausing the vm for the whole migration"
+                );
+            }
+        }
+    }
+    vm_rpc.call(VmRpc::Pause, ()).await?;
+    snapshot::write_vm(vm_rpc, stream).await
+}
+
+/// Copies the RAM of the running VM, which must have dirty page tracking
+/// enabled, until few enough pages are dirty, then pauses the VM to copy the
+/// rest of its RAM and its device state.
+///
+/// TODO: the hypervisor only tracks the guest's own writes, not those made by
+/// emulated devices while the VM runs.
+async fn precopy(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    stream: &mut (impl AsyncWrite + Unpin),
+) -> anyhow::Result<()> {
+    let ram_ranges = vm_rpc.call(VmRpc::RamRanges, ()).await?;
+    snapshot::write_header(stream, &ram_ranges).await?;
+    // Tracking started before this copy, so pages the guest writes during it
+    // are copied again by the next round.
+    let mut written = 0;
+    for range in &ram_ranges {
+        written += snapshot::write_range(vm_rpc, stream, *range).await?;
+    }
+
+    let mut round = 1;
+    loop {
+        let mut dirty = query_dirty_pages(vm_rpc, &ram_ranges).await?;
+        let dirty_pages = dirty
+            .iter()
+            .flatten()
+            .map(|bits| u64::from(bits.count_ones()))
+            .sum::<u64>();
+        tracing::debug!(round, written, dirty_pages, "pre-copy round complete");
+        round += 1;
+        if dirty_pages <= CONVERGED_DIRTY_PAGES || round > MAX_PRECOPY_ROUNDS {
+            vm_rpc.call(VmRpc::Pause, ()).await?;
+            // Include the pages written since the query.
+            let last = query_dirty_pages(vm_rpc, &ram_ranges).await?;
+            for (bitmap, last) in dirty.iter_mut().zip(last) {
+                for (bits, new_bits) in bitmap.iter_mut().zip(last) {
+                    *bits |= new_bits;
+                }
+            }
+            written = write_dirty_pages(vm_rpc, stream, &ram_ranges, &dirty).await?;
+            break;
+        }
+        written = write_dirty_pages(vm_rpc, stream, &ram_ranges, &dirty).await?;
+    }
+
+    let state_size = snapshot::write_state(vm_rpc, stream).await?;
+    tracing::info!(
+        rounds = round,
+        paused_ram_written = written,
+        state_size,
+        "vm written"
+    );
+    Ok(())
+}
+
+/// Gets and clears the dirty page bitmap of each of `ram_ranges`.
+async fn query_dirty_pages(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    ram_ranges: &[MemoryRange],
+) -> anyhow::Result<Vec<Vec<u64>>> {
+    let mut bitmaps = Vec::new();
+    for range in ram_ranges {
+        let bitmap = vm_rpc
+            .call_failable(VmRpc::QueryDirtyPages, *range)
+            .await
+            .with_context(|| format!("failed to query dirty pages in {range}"))?;
+        bitmaps.push(bitmap);
+    }
+    Ok(bitmaps)
+}
+
+/// Writes the pages set in `bitmaps`, one per RAM range, returning the number
+/// of bytes written. Unlike the initial copy, zero pages are written too,
+/// since they may replace non-zero pages.
+async fn write_dirty_pages(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    stream: &mut (impl AsyncWrite + Unpin),
+    ram_ranges: &[MemoryRange],
+    bitmaps: &[Vec<u64>],
+) -> anyhow::Result<u64> {
+    let mut written = 0;
+    for (range, bitmap) in ram_ranges.iter().zip(bitmaps) {
+        for (page, count) in dirty_runs(bitmap, range.len() / PAGE_SIZE) {
+            let gpa = range.start() + page * PAGE_SIZE;
+            let len = (count * PAGE_SIZE) as usize;
+            let data = snapshot::read_memory(vm_rpc, gpa, len).await?;
+            snapshot::write_ram_record(stream, gpa, &data).await?;
+            written += len as u64;
+        }
+    }
+    Ok(written)
+}
+
+/// Returns the runs of consecutive pages set in `bitmap`, as (first page,
+/// page count), split so that each is at most a snapshot chunk.
+fn dirty_runs(bitmap: &[u64], page_count: u64) -> Vec<(u64, u64)> {
+    let max_run = snapshot::CHUNK_SIZE / PAGE_SIZE;
+    let is_dirty = |page: u64| bitmap[(page / 64) as usize] & (1 << (page % 64)) != 0;
+    let mut runs = Vec::new();
+    let mut page = 0;
+    while page < page_count {
+        if !is_dirty(page) {
+            page += 1;
+            continue;
+        }
+        let start = page;
+        while page < page_count && page - start < max_run && is_dirty(page) {
+            page += 1;
+        }
+        runs.push((start, page - start));
+    }
+    runs
+}
+
+/// A VM received from a migration source.
+pub struct IncomingVm {
+    /// The guest RAM, with the RAM ranges laid out consecutively.
+    pub ram: File,
+    /// Whether `ram` is a snapshot file, which must be mapped copy-on-write
+    /// rather than shared.
+    pub copy_on_write: bool,
+    /// The device saved state.
+    pub saved_state: ProtobufMessage,
+    /// The connection to the source, to acknowledge the migration once the VM
+    /// has started.
+    pub completion: MigrationCompletion,
+}
+
+/// Acknowledges a migration to the source.
+pub struct MigrationCompletion(Option<PolledSocket<TcpStream>>);
+
+impl MigrationCompletion {
+    /// Tells the source that the VM has started, so that it stops waiting.
+    ///
+    /// If this is not called, the source resumes the VM when the connection is
+    /// dropped.
+    pub async fn complete(self) -> anyhow::Result<()> {
+        if let Some(mut socket) = self.0 {
+            socket.write_all(&[ACK]).await?;
+            socket.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives a VM from the migration source at `address`: either by waiting for
+/// a source to connect to it, or by reading a file.
+pub async fn receive(
+    driver: &impl Driver,
+    address: &MigrationAddressCli,
+) -> anyhow::Result<IncomingVm> {
+    match address {
+        MigrationAddressCli::Tcp(address) => {
+            let listener = TcpListener::bind(address)
+                .with_context(|| format!("failed to listen for migration on {address}"))?;
+            let mut listener = PolledSocket::new(driver, listener)?;
+            tracing::info!(%address, "waiting for incoming migration");
+            let (stream, source) = listener.accept().await?;
+            tracing::info!(%source, "receiving incoming migration");
+            let mut socket = PolledSocket::new(driver, stream)?;
+            let snapshot::SavedVm { ram, saved_state } = snapshot::read_vm(&mut socket).await?;
+            Ok(IncomingVm {
+                ram,
+                copy_on_write: false,
+                saved_state,
+                completion: MigrationCompletion(Some(socket)),
+            })
+        }
+        MigrationAddressCli::File(path) => {
+            let snapshot::SavedVm { ram, saved_state } = snapshot::load(path).await?;
+            Ok(IncomingVm {
+                ram,
+                copy_on_write: true,
+                saved_state,
+                completion: MigrationCompletion(None),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use pal_async::task::Task;
+    use std::io::Read;
+    use std::io::Seek;
+
+    /// A VM whose guest writes `writes` pages each time the dirty pages are
+    /// queried while it runs, halving that number each time if `converging`.
+    struct FakeVm {
+        ranges: Vec<MemoryRange>,
+        memory: Vec<u8>,
+        dirty: Vec<bool>,
+        running: bool,
+        tracking_supported: bool,
+        tracking: bool,
+        writes: u64,
+        converging: bool,
+        generation: u8,
+        /// The number of rounds of dirty page queries before the VM was
+        /// paused.
+        rounds_before_pause: Option<u32>,
+        rounds: u32,
+    }
+
+    impl FakeVm {
+        fn new(writes: u64, converging: bool) -> Self {
+            let ranges = vec![
+                MemoryRange::new(0..0x200000),
+                MemoryRange::new(0x400000..0x500000),
+            ];
+            let size = ranges.iter().map(|r| r.len()).sum::<u64>() as usize;
+            let mut memory = vec![0; size];
+            for offset in (0..size).step_by(3 * PAGE_SIZE as usize) {
+                memory[offset] = 0xaa;
+            }
+            Self {
+                dirty: vec![false; size / PAGE_SIZE as usize],
+                ranges,
+                memory,
+                running: true,
+                tracking_supported: true,
+                tracking: false,
+                writes,
+                converging,
+                generation: 0,
+                rounds_before_pause: None,
+                rounds: 0,
+            }
+        }
+
+        fn offset(&self, gpa: u64) -> usize {
+            let mut offset = 0;
+            for range in &self.ranges {
+                if range.contains_addr(gpa) {
+                    return (offset + gpa - range.start()) as usize;
+                }
+                offset += range.len();
+            }
+            panic!("gpa {gpa:#x} is not ram");
+        }
+
+        /// Runs the guest between two rounds, writing pages spread across
+        /// RAM, sometimes zeroing them.
+        fn run_guest(&mut self) {
+            self.generation = self.generation.wrapping_add(1);
+            let pages = self.dirty.len() as u64;
+            for i in 0..self.writes {
+                let page = (i * 7 + u64::from(self.generation)) % pages;
+                self.memory[(page * PAGE_SIZE) as usize] = self.generation % 3;
+                self.dirty[page as usize] = true;
+            }
+            if self.converging {
+                self.writes /= 2;
+            }
+        }
+
+        fn query_dirty_pages(&mut self, range: MemoryRange) -> anyhow::Result<Vec<u64>> {
+            anyhow::ensure!(self.tracking, "dirty page tracking is not enabled");
+            let index = self.ranges.iter().position(|r| *r == range).unwrap();
+            if index == 0 {
+                self.rounds += 1;
+                if self.running {
+                    self.run_guest();
+                }
+            }
+            let first_page = self.offset(range.start()) / PAGE_SIZE as usize;
+            let pages = (range.len() / PAGE_SIZE) as usize;
+            let mut bitmap = vec![0; pages.div_ceil(64)];
+            for (i, dirty) in self.dirty[first_page..first_page + pages]
+                .iter_mut()
+                .enumerate()
+            {
+                if std::mem::take(dirty) {
+                    bitmap[i / 64] |= 1 << (i % 64);
+                }
+            }
+            Ok(bitmap)
+        }
+
+        fn spawn(mut self, driver: &DefaultDriver) -> (mesh::Sender<VmRpc>, Task<FakeVm>) {
+            let (send, mut recv) = mesh::channel();
+            let task = driver.spawn("fake-vm", async move {
+                while let Some(rpc) = recv.next().await {
+                    match rpc {
+                        VmRpc::IsRunning(rpc) => rpc.handle_sync(|()| self.running),
+                        VmRpc::Pause(rpc) => rpc.handle_sync(|()| {
+                            self.rounds_before_pause = Some(self.rounds);
+                            std::mem::replace(&mut self.running, false)
+                        }),
+                        VmRpc::RamRanges(rpc) => rpc.handle_sync(|()| self.ranges.clone()),
+                        VmRpc::SetDirtyTracking(rpc) => rpc.handle_failable_sync(|enable| {
+                            anyhow::ensure!(
+                                self.tracking_supported,
+                                "dirty page tracking is not supported"
+                            );
+                            self.tracking = enable;
+                            self.dirty.fill(false);
+                            Ok(())
+                        }),
+                        VmRpc::QueryDirtyPages(rpc) => {
+                            rpc.handle_failable_sync(|range| self.query_dirty_pages(range))
+                        }
+                        VmRpc::ReadMemory(rpc) => rpc.handle_failable_sync(|(gpa, len)| {
+                            // Reading a running VM's memory is only safe while
+                            // its writes are tracked.
+                            assert!(!self.running || self.tracking);
+                            let offset = self.offset(gpa);
+                            anyhow::Ok(self.memory[offset..offset + len].to_vec())
+                        }),
+                        VmRpc::Save(rpc) => {
+                            rpc.handle_failable_sync(|()| anyhow::Ok(ProtobufMessage::new((5u32,))))
+                        }
+                        rpc => panic!("unexpected rpc {rpc:?}"),
+                    }
+                }
+                self
+            });
+            (send, task)
+        }
+    }
+
+    /// Migrates `vm` to a buffer, returning the VM and the RAM that the
+    /// destination loaded.
+    async fn migrate(driver: &DefaultDriver, vm: FakeVm) -> (FakeVm, Vec<u8>) {
+        let (vm_rpc, task) = vm.spawn(driver);
+        let mut stream = futures::io::Cursor::new(Vec::new());
+        let running = vm_rpc.call(VmRpc::IsRunning, ()).await.unwrap();
+        write_vm(&vm_rpc, &mut stream, running).await.unwrap();
+        drop(vm_rpc);
+        let vm = task.await;
+
+        let mut stream = futures::io::Cursor::new(stream.into_inner());
+        let saved = snapshot::read_vm(&mut stream).await.unwrap();
+        assert_eq!(saved.saved_state.parse::<(u32,)>().unwrap(), (5,));
+        let mut ram = saved.ram;
+        let mut data = Vec::new();
+        ram.rewind().unwrap();
+        ram.read_to_end(&mut data).unwrap();
+        (vm, data)
+    }
+
+    #[async_test]
+    async fn test_precopy_converges(driver: DefaultDriver) {
+        // The guest writes 512 pages, then 256, which is few enough to pause.
+        let (vm, ram) = migrate(&driver, FakeVm::new(512, true)).await;
+        assert_eq!(vm.rounds_before_pause, Some(2));
+        assert!(!vm.running);
+        assert!(!vm.tracking);
+        assert!(ram == vm.memory);
+    }
+
+    #[async_test]
+    async fn test_precopy_idle_guest(driver: DefaultDriver) {
+        let (vm, ram) = migrate(&driver, FakeVm::new(0, true)).await;
+        assert_eq!(vm.rounds_before_pause, Some(1));
+        assert!(ram == vm.memory);
+    }
+
+    #[async_test]
+    async fn test_precopy_gives_up(driver: DefaultDriver) {
+        // The guest keeps writing more pages than the threshold, so pre-copy
+        // stops after the maximum number of rounds.
+        let (vm, ram) = migrate(&driver, FakeVm::new(300, false)).await;
+        assert_eq!(vm.rounds_before_pause, Some(MAX_PRECOPY_ROUNDS));
+        assert!(!vm.running);
+        assert!(!vm.tracking);
+        assert!(ram == vm.memory);
+    }
+
+    #[async_test]
+    async fn test_no_dirty_tracking(driver: DefaultDriver) {
+        let mut vm = FakeVm::new(512, true);
+        vm.tracking_supported = false;
+        let (vm, ram) = migrate(&driver, vm).await;
+        assert_eq!(vm.rounds, 0);
+        assert_eq!(vm.rounds_before_pause, Some(0));
+        assert!(ram == vm.memory);
+    }
+
+    #[async_test]
+    async fn test_paused_vm_not_precopied(driver: DefaultDriver) {
+        let mut vm = FakeVm::new(512, true);
+        vm.running = false;
+        let (vm, ram) = migrate(&driver, vm).await;
+        assert_eq!(vm.rounds, 0);
+        assert!(!vm.tracking);
+        assert!(ram == vm.memory);
+    }
+
+    #[test]
+    fn test_dirty_runs() {
+        assert!(dirty_runs(&[0, 0], 128).is_empty());
+        assert_eq!(
+            dirty_runs(&[0b1101 | 1 << 63, 1], 128),
+            [(0, 1), (2, 2), (63, 2)]
+        );
+        // Pages past the end of the range are ignored.
+        assert_eq!(dirty_runs(&[1 << 10 | 1 << 20], 16), [(10, 1)]);
+        // Runs are split at the snapshot chunk size.
+        let max_run = snapshot::CHUNK_SIZE / PAGE_SIZE;
+        let bitmap = vec![!0; (max_run * 2 / 64) as usize];
+        assert_eq!(
+            dirty_runs(&bitmap, max_run + 3),
+            [(0, max_run), (max_run, 3)]
+        );
+    }
+}
@@ -0,0 +1,438 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Export of `tracing` spans and events to an OpenTelemetry collector, using
+//! OTLP over HTTP with JSON encoding.
+//!
+//! Every process in the mesh exports its own spans, in batches, from a
+//! background thread. The processes share a trace ID, which worker processes
+//! inherit from the main process through the environment, so that a VM's
+//! spans from all of its processes can be found together. Events inside a span
+//! are exported as span events, and other events as log records.
+//!
+//! Spans and events are dropped if the collector cannot keep up.
+
+use anyhow::Context as _;
+use serde_json::Value;
+use serde_json::json;
+use std::fmt::Write as _;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use tracing::Subscriber;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::span;
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The environment variable holding the collector's base URL.
+pub const ENDPOINT_ENV: &str = "OPENVMM_OTLP_ENDPOINT";
+/// The environment variable passing the trace ID to worker processes.
+const TRACE_ID_ENV: &str = "OPENVMM_OTLP_TRACE_ID";
+/// The environment variable passing the mesh process name to worker
+/// processes.
+const PROCESS_ENV: &str = "OPENVMM_OTLP_PROCESS";
+
+/// How often to export batches.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// The most spans and log records to export at once.
+const MAX_BATCH: usize = 512;
+/// The most spans and log records waiting to be exported.
+const MAX_QUEUED: usize = 8192;
+/// The most events recorded per span.
+const MAX_SPAN_EVENTS: usize = 128;
+
+/// The trace ID of this process's spans, if exporting is enabled.
+static TRACE_ID: OnceLock<String> = OnceLock::new();
+
+/// Returns the environment variables to pass to the mesh process `name` so
+/// that its spans are exported to the same trace.
+pub fn child_env(name: &str) -> Vec<(&'static str, String)> {
+    match TRACE_ID.get() {
+        Some(trace_id) => vec![
+            (TRACE_ID_ENV, trace_id.clone()),
+            (PROCESS_ENV, name.to_owned()),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// A layer that exports spans and events to the collector at `endpoint`.
+pub struct OtlpLayer {
+    trace_id: String,
+    next_span_id: AtomicU64,
+    send: mpsc::SyncSender<Item>,
+}
+
+enum Item {
+    Span(Value),
+    Log(Value),
+}
+
+impl OtlpLayer {
+    /// Returns a new layer exporting to `endpoint`, such as
+    /// `http://localhost:4318`, and starts the thread that exports to it.
+    pub fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let endpoint = Endpoint::parse(endpoint)
+            .with_context(|| format!("invalid {ENDPOINT_ENV}: {endpoint}"))?;
+
+        let trace_id = match std::env::var(TRACE_ID_ENV) {
+            Ok(trace_id) => trace_id,
+            Err(_) => {
+                let mut trace_id = [0; 16];
+                getrandom::fill(&mut trace_id).expect("rng failure");
+                hex::encode(trace_id)
+            }
+        };
+        TRACE_ID.set(trace_id.clone()).ok();
+
+        let mut span_id_base = [0; 8];
+        getrandom::fill(&mut span_id_base).expect("rng failure");
+
+        let process = std::env::var(PROCESS_ENV).unwrap_or_else(|_| "openvmm".into());
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", string_value("openvmm")),
+                attribute("process.pid", json!({ "intValue": std::process::id().to_string() })),
+                attribute("openvmm.process", string_value(&process)),
+            ],
+        });
+
+        let (send, recv) = mpsc::sync_channel(MAX_QUEUED);
+        std::thread::Builder::new()
+            .name("otlp-export".into())
+            .spawn(move || export_thread(endpoint, resource, recv))
+            .context("failed to spawn otlp export thread")?;
+
+        Ok(Self {
+            trace_id,
+            next_span_id: AtomicU64::new(u64::from_ne_bytes(span_id_base)),
+            send,
+        })
+    }
+}
+
+/// The state of a span that has not closed yet.
+struct SpanData {
+    span_id: String,
+    parent_span_id: Option<String>,
+    start: u64,
+    attributes: Vec<Value>,
+    events: Vec<Value>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let parent_span_id = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|d| d.span_id.clone())
+        });
+        let mut visitor = Visitor::default();
+        attrs.record(&mut visitor);
+        let span_id = self.next_span_id.fetch_add(1, Ordering::Relaxed);
+        span.extensions_mut().insert(SpanData {
+            span_id: format!("{span_id:016x}"),
+            parent_span_id,
+            start: now(),
+            attributes: visitor.attributes,
+            events: Vec::new(),
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            let mut visitor = Visitor::default();
+            values.record(&mut visitor);
+            data.attributes.extend(visitor.attributes);
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = Visitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        let mut attributes = visitor.attributes;
+        attributes.push(attribute("code.namespace", string_value(metadata.target())));
+        let message = visitor.message.unwrap_or_default();
+
+        if let Some(span) = ctx.event_span(event) {
+            let mut extensions = span.extensions_mut();
+            if let Some(data) = extensions.get_mut::<SpanData>() {
+                if data.events.len() < MAX_SPAN_EVENTS {
+                    attributes.push(attribute("level", string_value(metadata.level().as_str())));
+                    data.events.push(json!({
+                        "timeUnixNano": now().to_string(),
+                        "name": message,
+                        "attributes": attributes,
+                    }));
+                }
+                return;
+            }
+        }
+
+        let (severity_number, severity_text) = match *metadata.level() {
+            tracing::Level::TRACE => (1, "TRACE"),
+            tracing::Level::DEBUG => (5, "DEBUG"),
+            tracing::Level::INFO => (9, "INFO"),
+            tracing::Level::WARN => (13, "WARN"),
+            _ => (17, "ERROR"),
+        };
+        let _ = self.send.try_send(Item::Log(json!({
+            "timeUnixNano": now().to_string(),
+            "severityNumber": severity_number,
+            "severityText": severity_text,
+            "body": string_value(&message),
+            "attributes": attributes,
+            "traceId": self.trace_id,
+        })));
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        let metadata = span.metadata();
+        let mut attributes = data.attributes;
+        attributes.push(attribute("code.namespace", string_value(metadata.target())));
+        let mut otlp_span = json!({
+            "traceId": self.trace_id,
+            "spanId": data.span_id,
+            "name": metadata.name(),
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": data.start.to_string(),
+            "endTimeUnixNano": now().to_string(),
+            "attributes": attributes,
+            "events": data.events,
+        });
+        if let Some(parent_span_id) = data.parent_span_id {
+            otlp_span["parentSpanId"] = parent_span_id.into();
+        }
+        let _ = self.send.try_send(Item::Span(otlp_span));
+    }
+}
+
+/// Converts fields to OTLP attributes.
+#[derive(Default)]
+struct Visitor {
+    attributes: Vec<Value>,
+    message: Option<String>,
+}
+
+impl Visitor {
+    fn record(&mut self, field: &Field, value: Value) {
+        self.attributes.push(attribute(field.name(), value));
+    }
+}
+
+impl Visit for Visitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, json!({ "doubleValue": value }));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, json!({ "boolValue": value }));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_owned());
+        } else {
+            self.record(field, string_value(value));
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        let mut message = value.to_string();
+        let mut source = value.source();
+        while let Some(err) = source {
+            write!(message, ": {err}").unwrap();
+            source = err.source();
+        }
+        self.record(field, string_value(&message));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{value:?}"));
+        } else {
+            self.record(field, string_value(&format!("{value:?}")));
+        }
+    }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn string_value(value: &str) -> Value {
+    json!({ "stringValue": value })
+}
+
+/// Returns the time in nanoseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// An `http://` collector endpoint.
+///
+/// Exports are posted with a minimal blocking HTTP/1.1 client over a plain
+/// `TcpStream`, so that the exporter thread does not need an async runtime.
+/// This means TLS (`https://`), proxies, and authentication are not supported;
+/// users that need them should point this at a local collector that forwards
+/// to the remote one.
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .context("only http:// endpoints are supported")?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("invalid port")?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            anyhow::bail!("missing host");
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// Posts a JSON `body` to `signal` (`traces` or `logs`).
+    fn post(&self, signal: &str, body: &[u8]) -> anyhow::Result<()> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .context("failed to connect to collector")?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+        let path = if self.path.is_empty() {
+            format!("/v1/{signal}")
+        } else {
+            format!("/{}/v1/{signal}", self.path)
+        };
+        write!(
+            stream,
+            "POST {path} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.host,
+            self.port,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        let code = status.split_ascii_whitespace().nth(1).unwrap_or_default();
+        if !code.starts_with('2') {
+            anyhow::bail!("collector returned {}", status.trim_end());
+        }
+        Ok(())
+    }
+}
+
+fn export_thread(endpoint: Endpoint, resource: Value, recv: mpsc::Receiver<Item>) {
+    let scope = json!({ "name": "openvmm" });
+    let mut failing = false;
+    while let Ok(item) = recv.recv() {
+        let mut spans = Vec::new();
+        let mut logs = Vec::new();
+        let deadline = Instant::now() + EXPORT_INTERVAL;
+        let mut next = Some(item);
+        while let Some(item) = next.take() {
+            match item {
+                Item::Span(span) => spans.push(span),
+                Item::Log(log) => logs.push(log),
+            }
+            if spans.len() + logs.len() < MAX_BATCH {
+                next = recv
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .ok();
+            }
+        }
+
+        let mut result = Ok(());
+        if !spans.is_empty() {
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": resource,
+                    "scopeSpans": [{ "scope": scope, "spans": spans }],
+                }],
+            });
+            result = endpoint.post("traces", body.to_string().as_bytes());
+        }
+        if !logs.is_empty() && result.is_ok() {
+            let body = json!({
+                "resourceLogs": [{
+                    "resource": resource,
+                    "scopeLogs": [{ "scope": scope, "logRecords": logs }],
+                }],
+            });
+            result = endpoint.post("logs", body.to_string().as_bytes());
+        }
+
+        // Report failures without `tracing`, which would feed back into the
+        // export, and only once until the collector is reachable again.
+        match result {
+            Ok(()) => failing = false,
+            Err(err) if !failing => {
+                eprintln!("otlp export failed, dropping spans: {err:#}");
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Endpoint;
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = Endpoint::parse("http://localhost:4318").unwrap();
+        assert_eq!(endpoint.host, "localhost");
+        assert_eq!(endpoint.port, 4318);
+        assert_eq!(endpoint.path, "");
+        let endpoint = Endpoint::parse("http://collector/otlp/").unwrap();
+        assert_eq!(endpoint.host, "collector");
+        assert_eq!(endpoint.port, 80);
+        assert_eq!(endpoint.path, "otlp");
+        assert!(Endpoint::parse("https://localhost:4318").is_err());
+        assert!(Endpoint::parse("http://:4318").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Opt-in profiling of OpenVMM's own processes, for `--profile`.
+//!
+//! Each process in the mesh profiles itself, and writes its results to the
+//! profile directory when it exits, in files named after the process and its
+//! ID:
+//!
+//! ```text
+//! <process>-<pid>.pb        pprof CPU profile (`--profile cpu:<hz>`)
+//! <process>-<pid>.svg       CPU flamegraph (`--profile cpu:<hz>`)
+//! <process>-<pid>.heap.txt  heap allocation counts (`--profile heap`)
+//! ```
+//!
+//! `--profile latency` instead records device I/O latency histograms, which
+//! are reported in the inspect tree rather than written out.
+//!
+//! Worker processes get the options from the main process through the
+//! environment.
+
+use crate::cli_args::ProfileCli;
+use anyhow::Context as _;
+use std::path::PathBuf;
+
+/// The environment variable passing the profile kinds to worker processes.
+const PROFILE_ENV: &str = "OPENVMM_PROFILE";
+/// The environment variable passing the profile directory to worker processes.
+const DIR_ENV: &str = "OPENVMM_PROFILE_DIR";
+/// The environment variable passing the mesh process name to worker processes.
+const PROCESS_ENV: &str = "OPENVMM_PROFILE_PROCESS";
+
+/// What to profile, and where to write the results.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileOptions {
+    kinds: Vec<ProfileCli>,
+    dir: PathBuf,
+}
+
+impl ProfileOptions {
+    pub fn new(kinds: Vec<ProfileCli>, dir: Option<PathBuf>) -> Self {
+        Self {
+            kinds,
+            dir: dir.unwrap_or_else(|| ".".into()),
+        }
+    }
+
+    /// Returns the environment variables that pass the options to the worker
+    /// process `name`.
+    pub fn child_env(&self, name: &str) -> Vec<(&'static str, String)> {
+        if self.kinds.is_empty() {
+            return Vec::new();
+        }
+        let kinds = self
+            .kinds
+            .iter()
+            .map(|kind| kind.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        vec![
+            (PROFILE_ENV, kinds),
+            (DIR_ENV, self.dir.to_string_lossy().into_owned()),
+            (PROCESS_ENV, name.into()),
+        ]
+    }
+}
+
+/// Profiles the current process until [`finish`](Self::finish) is called.
+pub struct Profiler {
+    name: String,
+    dir: PathBuf,
+    heap: bool,
+    #[cfg(unix)]
+    cpu: Option<pprof::ProfilerGuard<'static>>,
+}
+
+impl Profiler {
+    /// Starts profiling this process, which is named `name` in the results,
+    /// or returns `None` if no profiling was requested.
+    pub fn start(options: &ProfileOptions, name: &str) -> anyhow::Result<Option<Self>> {
+        if options.kinds.is_empty() {
+            return Ok(None);
+        }
+        let mut profiler = Self {
+            name: name.into(),
+            dir: options.dir.clone(),
+            heap: false,
+            #[cfg(unix)]
+            cpu: None,
+        };
+        for kind in &options.kinds {
+            match *kind {
+                ProfileCli::Cpu { hz } => profiler.start_cpu(hz)?,
+                ProfileCli::Heap => {
+                    alloc_tracker::enable();
+                    profiler.heap = true;
+                }
+                ProfileCli::Latency => inspect_counters::set_latency_histograms_enabled(true),
+            }
+        }
+        Ok(Some(profiler))
+    }
+
+    /// Starts profiling a worker process with the options passed by the main
+    /// process, if any.
+    pub fn start_from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(kinds) = std::env::var(PROFILE_ENV) else {
+            return Ok(None);
+        };
+        let kinds = kinds
+            .split(',')
+            .map(|kind| kind.parse())
+            .collect::<anyhow::Result<_>>()
+            .with_context(|| format!("invalid {PROFILE_ENV}"))?;
+        let dir = std::env::var_os(DIR_ENV).map(PathBuf::from);
+        let name = std::env::var(PROCESS_ENV).unwrap_or_else(|_| "worker".into());
+        Self::start(&ProfileOptions::new(kinds, dir), &name)
+    }
+
+    #[cfg(unix)]
+    fn start_cpu(&mut self, hz: u32) -> anyhow::Result<()> {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(hz.try_into().context("sampling frequency too high")?)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .context("failed to start cpu profiler")?;
+        self.cpu = Some(guard);
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn start_cpu(&mut self, hz: u32) -> anyhow::Result<()> {
+        let _ = hz;
+        anyhow::bail!("cpu profiling is only supported on Linux and macOS")
+    }
+
+    /// Stops profiling and writes the results.
+    pub fn finish(self) {
+        if let Err(err) = self.write() {
+            tracing::error!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to write profile"
+            );
+        }
+    }
+
+    fn write(self) -> anyhow::Result<()> {
+        let base = self
+            .dir
+            .join(format!("{}-{}", self.name, std::process::id()));
+        #[cfg(unix)]
+        if let Some(guard) = &self.cpu {
+            write_cpu_profile(guard, &base)?;
+        }
+        if self.heap {
+            let path = base.with_extension("heap.txt");
+            let stats = alloc_tracker::stats();
+            let alloc_tracker::Stats {
+                allocations,
+                frees,
+                allocated_bytes,
+                net_bytes,
+                peak_net_bytes,
+            } = stats;
+            fs_err::write(
+                &path,
+                format!(
+                    "allocations {allocations}\n\
+                    frees {frees}\n\
+                    allocated_bytes {allocated_bytes}\n\
+                    net_bytes {net_bytes}\n\
+                    peak_net_bytes {peak_net_bytes}\n"
+                ),
+            )?;
+            tracing::info!(?stats, path = %path.display(), "wrote heap profile");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_cpu_profile(
+    guard: &pprof::ProfilerGuard<'static>,
+    base: &std::path::Path,
+) -> anyhow::Result<()> {
+    use pprof::protos::Message;
+
+    let report = guard
+        .report()
+        .build()
+        .context("failed to build cpu profile")?;
+
+    let mut content = Vec::new();
+    report
+        .pprof()
+        .context("failed to build pprof profile")?
+        .encode(&mut content)
+        .context("failed to encode pprof profile")?;
+    let path = base.with_extension("pb");
+    fs_err::write(&path, content)?;
+
+    let flamegraph = base.with_extension("svg");
+    report
+        .flamegraph(fs_err::File::create(&flamegraph)?)
+        .context("failed to write flamegraph")?;
+
+    tracing::info!(
+        path = %path.display(),
+        flamegraph = %flamegraph.display(),
+        "wrote cpu profile"
+    );
+    Ok(())
+}
@@ -0,0 +1,761 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A server for a subset of QMP, the QEMU Machine Protocol, so that tooling
+//! written for QEMU (such as `qmp-shell`) can drive OpenVMM.
+//!
+//! Supported commands are mapped onto the equivalent OpenVMM operations. There
+//! are also OpenVMM-specific commands for the guest's KVP (key/value pair)
+//! store, so that tooling can fetch the guest's network configuration without
+//! a guest agent, and the QEMU guest agent's file system freeze commands are
+//! served by the VSS IC. The emulated battery and AC power state can be
+//! queried and changed with `query-battery` and `set-battery`.
+//!
+//! `device_add` and `blockdev-snapshot-sync` hot-add and snapshot disks with
+//! the same code as the interactive console, but take OpenVMM `--disk` syntax
+//! and LUNs rather than QEMU block nodes. `blockdev-snapshot` is not
+//! supported, since its overlay must be a node created with `blockdev-add`.
+//! Commands must be newline terminated, only one client is served at a time,
+//! and no asynchronous events are sent.
+
+use crate::AddDiskCommand;
+use crate::InteractiveCommand;
+use crate::battery::BatteryControl;
+use crate::cli_args::DiskCliKind;
+use crate::kvp;
+use chipset_resources::battery::HostBatteryUpdate;
+use clap::ValueEnum as _;
+use futures::AsyncBufReadExt;
+use futures::AsyncWriteExt;
+use futures::StreamExt;
+use futures::io::BufReader;
+use hvlite_defs::rpc::VmRpc;
+use hyperv_ic_resources::kvp::DeleteParams;
+use hyperv_ic_resources::kvp::GetIpInfoParams;
+use hyperv_ic_resources::kvp::KvpConnectRpc;
+use hyperv_ic_resources::kvp::KvpPool;
+use hyperv_ic_resources::kvp::KvpRpc;
+use hyperv_ic_resources::kvp::SetParams;
+use hyperv_ic_resources::shutdown::ShutdownParams;
+use hyperv_ic_resources::shutdown::ShutdownResult;
+use hyperv_ic_resources::shutdown::ShutdownRpc;
+use hyperv_ic_resources::shutdown::ShutdownType;
+use hyperv_ic_resources::vss::VssRpc;
+use mesh::CancelContext;
+use mesh::rpc::FailableRpc;
+use mesh::rpc::RpcSend;
+use pal_async::DefaultDriver;
+use pal_async::socket::PolledSocket;
+use serde_json::Value;
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Duration;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+
+/// The commands advertised by `query-commands`.
+const COMMANDS: &[&str] = &[
+    "qmp_capabilities",
+    "query-commands",
+    "query-version",
+    "query-status",
+    "stop",
+    "cont",
+    "system_reset",
+    "system_powerdown",
+    "schedule-wake",
+    "update-generation-id",
+    "inject-nmi",
+    "quit",
+    "kvp-set",
+    "kvp-get",
+    "kvp-delete",
+    "query-kvp",
+    "query-kvp-ip-info",
+    "guest-fsfreeze-freeze",
+    "guest-fsfreeze-thaw",
+    "query-battery",
+    "set-battery",
+    "device_add",
+    "blockdev-snapshot-sync",
+];
+
+/// How long file system freeze and thaw commands wait, including the time for
+/// the guest to connect to the VSS IC.
+const VSS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long KVP commands wait, including the time for the guest to connect to
+/// the KVP IC.
+const KVP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The targets of QMP commands.
+pub struct QmpServer {
+    pub vm_rpc: mesh::Sender<VmRpc>,
+    pub shutdown_ic: Option<mesh::Sender<ShutdownRpc>>,
+    pub kvp_ic: Option<mesh::Sender<KvpConnectRpc>>,
+    pub vss_ic: Option<mesh::Sender<VssRpc>>,
+    pub battery: Option<BatteryControl>,
+    /// Used to quit OpenVMM, just as the interactive console does.
+    pub commands: mesh::Sender<(InteractiveCommand, mesh::OneshotSender<()>)>,
+    pub disks: mesh::Sender<DiskRequest>,
+}
+
+/// A disk request, handled by the control loop with the same code as the
+/// interactive console's disk commands.
+pub enum DiskRequest {
+    /// Hot-adds a disk.
+    Add(FailableRpc<AddDiskCommand, ()>),
+    /// Redirects the writes of the VTL0 SCSI disk at a LUN to a new diff
+    /// layer, in a new sqlite file at the path or in memory. Returns the
+    /// number of snapshots taken of the disk.
+    Snapshot(FailableRpc<(u8, Option<PathBuf>), u64>),
+}
+
+/// An error response, with a QMP error class and description.
+#[derive(Debug)]
+struct QmpError(&'static str, String);
+
+impl QmpError {
+    fn generic(desc: impl Into<String>) -> Self {
+        Self("GenericError", desc.into())
+    }
+}
+
+impl QmpServer {
+    /// Serves clients on `listener` until an I/O error occurs.
+    pub async fn run(&self, driver: &DefaultDriver, listener: UnixListener) -> anyhow::Result<()> {
+        let mut listener = PolledSocket::new(driver, listener)?;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let stream = PolledSocket::new(driver, stream)?;
+            if let Err(err) = self.serve(stream).await {
+                tracing::warn!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "qmp connection failed"
+                );
+            }
+        }
+    }
+
+    async fn serve(&self, stream: PolledSocket<UnixStream>) -> anyhow::Result<()> {
+        let (read, mut write) = stream.split();
+        let mut lines = BufReader::new(read).lines();
+
+        let greeting = json!({
+            "QMP": {
+                "version": {
+                    "qemu": { "major": 0, "minor": 0, "micro": 0 },
+                    "package": "openvmm",
+                },
+                "capabilities": [],
+            }
+        });
+        send(&mut write, &greeting).await?;
+
+        let mut negotiated = false;
+        while let Some(line) = lines.next().await {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (id, result) = match serde_json::from_str::<Value>(&line) {
+                Ok(request) => {
+                    let id = request.get("id").cloned();
+                    (id, self.handle(&request, &mut negotiated).await)
+                }
+                Err(err) => (None, Err(QmpError::generic(format!("invalid JSON: {err}")))),
+            };
+            let mut response = match result {
+                Ok(value) => json!({ "return": value }),
+                Err(QmpError(class, desc)) => {
+                    json!({ "error": { "class": class, "desc": desc } })
+                }
+            };
+            if let Some(id) = id {
+                response["id"] = id;
+            }
+            send(&mut write, &response).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, request: &Value, negotiated: &mut bool) -> Result<Value, QmpError> {
+        let command = request
+            .get("execute")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| QmpError::generic("expected an 'execute' member"))?;
+
+        if command == "qmp_capabilities" {
+            *negotiated = true;
+            return Ok(json!({}));
+        }
+        if !*negotiated {
+            return Err(QmpError(
+                "CommandNotFound",
+                "expecting capabilities negotiation with 'qmp_capabilities'".into(),
+            ));
+        }
+
+        match command {
+            "query-commands" => Ok(COMMANDS
+                .iter()
+                .map(|name| json!({ "name": name }))
+                .collect()),
+            "query-version" => Ok(json!({
+                "qemu": { "major": 0, "minor": 0, "micro": 0 },
+                "package": "openvmm",
+            })),
+            "query-status" => {
+                let running = self
+                    .vm_rpc
+                    .call(VmRpc::IsRunning, ())
+                    .await
+                    .map_err(|err| QmpError::generic(err.to_string()))?;
+                Ok(json!({
+                    "running": running,
+                    "singlestep": false,
+                    "status": if running { "running" } else { "paused" },
+                }))
+            }
+            "stop" => {
+                self.vm_rpc
+                    .call(VmRpc::Pause, ())
+                    .await
+                    .map_err(|err| QmpError::generic(err.to_string()))?;
+                Ok(json!({}))
+            }
+            "cont" => {
+                self.vm_rpc
+                    .call(VmRpc::Resume, ())
+                    .await
+                    .map_err(|err| QmpError::generic(err.to_string()))?;
+                Ok(json!({}))
+            }
+            "system_reset" => {
+                self.vm_rpc
+                    .call_failable(VmRpc::Reset, ())
+                    .await
+                    .map_err(|err| QmpError::generic(format!("{err:#}")))?;
+                Ok(json!({}))
+            }
+            "system_powerdown" => {
+                let ic = self
+                    .shutdown_ic
+                    .as_ref()
+                    .ok_or_else(|| QmpError::generic("no shutdown ic configured"))?;
+                let params = ShutdownParams {
+                    shutdown_type: ShutdownType::PowerOff,
+                    force: false,
+                };
+                let result = ic
+                    .call(ShutdownRpc::Shutdown, params)
+                    .await
+                    .map_err(|err| QmpError::generic(err.to_string()))?;
+                match result {
+                    ShutdownResult::Ok => Ok(json!({})),
+                    result => Err(QmpError::generic(format!("shutdown failed: {result:?}"))),
+                }
+            }
+            "schedule-wake" => {
+                let args = request.get("arguments").unwrap_or(&Value::Null);
+                let delay = u64_arg(args, "seconds")?.map(Duration::from_secs);
+                self.vm_rpc
+                    .call(VmRpc::ScheduleWake, delay)
+                    .await
+                    .map_err(|err| QmpError::generic(err.to_string()))?;
+                Ok(json!({}))
+            }
+            "inject-nmi" => {
+                self.vm_rpc
+                    .call(VmRpc::Nmi, 0)
+                    .await
+                    .map_err(|err| QmpError::generic(err.to_string()))?;
+                Ok(json!({}))
+            }
+            "quit" => {
+                let (send, recv) = mesh::oneshot();
+                self.commands.send((InteractiveCommand::Quit, send));
+                let _ = recv.await;
+                Ok(json!({}))
+            }
+            "update-generation-id" => {
+                let (send, recv) = mesh::oneshot();
+                self.commands
+                    .send((InteractiveCommand::UpdateGenerationId, send));
+                let _ = recv.await;
+                Ok(json!({}))
+            }
+            "kvp-set" | "kvp-get" | "kvp-delete" | "query-kvp" | "query-kvp-ip-info" => {
+                let kvp_ic = self
+                    .kvp_ic
+                    .as_ref()
+                    .ok_or_else(|| QmpError::generic("no kvp ic configured"))?;
+                // Validate the arguments before waiting for the guest.
+                let request =
+                    KvpRequest::parse(command, request.get("arguments").unwrap_or(&Value::Null))?;
+                CancelContext::new()
+                    .with_timeout(KVP_TIMEOUT)
+                    .until_cancelled(request.run(kvp_ic))
+                    .await
+                    .map_err(|_| QmpError::generic("timed out waiting for the guest kvp service"))?
+                    .map_err(|err| QmpError::generic(format!("{err:#}")))
+            }
+            "guest-fsfreeze-freeze" | "guest-fsfreeze-thaw" => {
+                let vss_ic = self
+                    .vss_ic
+                    .as_ref()
+                    .ok_or_else(|| QmpError::generic("no vss ic configured"))?;
+                let rpc = if command == "guest-fsfreeze-freeze" {
+                    VssRpc::Freeze
+                } else {
+                    VssRpc::Thaw
+                };
+                CancelContext::new()
+                    .with_timeout(VSS_TIMEOUT)
+                    .until_cancelled(vss_ic.call_failable(rpc, ()))
+                    .await
+                    .map_err(|_| QmpError::generic("timed out waiting for the guest vss service"))?
+                    .map_err(|err| QmpError::generic(format!("{err:#}")))?;
+                Ok(json!({}))
+            }
+            "query-battery" | "set-battery" => {
+                let battery = self
+                    .battery
+                    .as_ref()
+                    .ok_or_else(|| QmpError::generic("no battery configured"))?;
+                let state = if command == "set-battery" {
+                    let change =
+                        BatteryChange::parse(request.get("arguments").unwrap_or(&Value::Null))?;
+                    battery.update(|state| change.apply(state))
+                } else {
+                    battery.state()
+                };
+                Ok(json_battery(&state))
+            }
+            "device_add" => {
+                let command = parse_device_add(request.get("arguments").unwrap_or(&Value::Null))?;
+                self.disks
+                    .call_failable(DiskRequest::Add, command)
+                    .await
+                    .map_err(|err| QmpError::generic(format!("{err:#}")))?;
+                Ok(json!({}))
+            }
+            "blockdev-snapshot-sync" => {
+                let args = request.get("arguments").unwrap_or(&Value::Null);
+                let lun = u64_arg(args, "lun")?
+                    .ok_or_else(|| QmpError::generic("Parameter 'lun' is missing"))?
+                    .try_into()
+                    .map_err(|_| QmpError::generic("'lun' must be less than 256"))?;
+                let diff_path = args
+                    .get("snapshot-file")
+                    .map(|_| str_arg(args, "snapshot-file").map(PathBuf::from))
+                    .transpose()?;
+                self.disks
+                    .call_failable(DiskRequest::Snapshot, (lun, diff_path))
+                    .await
+                    .map_err(|err| QmpError::generic(format!("{err:#}")))?;
+                Ok(json!({}))
+            }
+            _ => Err(QmpError(
+                "CommandNotFound",
+                format!("The command {command} has not been found"),
+            )),
+        }
+    }
+}
+
+/// A parsed KVP command.
+#[derive(Debug)]
+enum KvpRequest {
+    Set(SetParams),
+    Get { pool: KvpPool, key: String },
+    Delete(DeleteParams),
+    Enumerate(KvpPool),
+    IpInfo(GetIpInfoParams),
+}
+
+impl KvpRequest {
+    fn parse(command: &str, args: &Value) -> Result<Self, QmpError> {
+        let request = match command {
+            "kvp-set" => Self::Set(SetParams {
+                pool: pool_arg(args)?,
+                key: str_arg(args, "key")?.to_owned(),
+                value: value_arg(args)?,
+            }),
+            "kvp-get" => Self::Get {
+                pool: pool_arg(args)?,
+                key: str_arg(args, "key")?.to_owned(),
+            },
+            "kvp-delete" => Self::Delete(DeleteParams {
+                pool: pool_arg(args)?,
+                key: str_arg(args, "key")?.to_owned(),
+            }),
+            "query-kvp" => Self::Enumerate(pool_arg(args)?),
+            "query-kvp-ip-info" => Self::IpInfo(GetIpInfoParams {
+                adapter_id: str_arg(args, "adapter-id")?.to_owned(),
+            }),
+            _ => unreachable!(),
+        };
+        Ok(request)
+    }
+
+    async fn run(self, kvp_ic: &mesh::Sender<KvpConnectRpc>) -> anyhow::Result<Value> {
+        let kvp = kvp::connect(kvp_ic).await?;
+        let value = match self {
+            KvpRequest::Set(params) => {
+                kvp.call_failable(KvpRpc::Set, params).await?;
+                json!({})
+            }
+            KvpRequest::Get { pool, key } => {
+                let value = kvp::get(&kvp, pool, &key)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("key '{key}' not found"))?;
+                json_value(&value)
+            }
+            KvpRequest::Delete(params) => {
+                kvp.call_failable(KvpRpc::Delete, params).await?;
+                json!({})
+            }
+            KvpRequest::Enumerate(pool) => kvp::enumerate(&kvp, pool)
+                .await?
+                .iter()
+                .map(|v| json!({ "key": v.key, "value": json_value(&v.value) }))
+                .collect(),
+            KvpRequest::IpInfo(params) => {
+                let info = kvp.call_failable(KvpRpc::GetIpInfo, params).await?;
+                json_ip_info(&info)
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Parses the arguments to `device_add`: a `driver` of `scsi-hd`, `scsi-cd`,
+/// or `nvme-ns`, a `disk` in `--disk` syntax, `lun` or `nsid`, and
+/// `read-only`.
+fn parse_device_add(args: &Value) -> Result<AddDiskCommand, QmpError> {
+    let driver = str_arg(args, "driver")?;
+    let disk = str_arg(args, "disk")?
+        .parse::<DiskCliKind>()
+        .map_err(|err| QmpError::generic(format!("invalid disk: {err:#}")))?;
+    let (is_dvd, nvme) = match driver {
+        "scsi-hd" => (false, None),
+        "scsi-cd" => (true, None),
+        "nvme-ns" => (
+            false,
+            Some(
+                u64_arg(args, "nsid")?
+                    .and_then(|nsid| u32::try_from(nsid).ok())
+                    .filter(|&nsid| nsid != 0)
+                    .ok_or_else(|| QmpError::generic("'nsid' must be a nonzero u32"))?,
+            ),
+        ),
+        _ => {
+            return Err(QmpError::generic(format!(
+                "driver '{driver}' is not supported; use scsi-hd, scsi-cd, or nvme-ns"
+            )));
+        }
+    };
+    let lun = match u64_arg(args, "lun")? {
+        Some(_) if nvme.is_some() => {
+            return Err(QmpError::generic("'lun' is not used by nvme-ns"));
+        }
+        Some(lun) => lun
+            .try_into()
+            .map_err(|_| QmpError::generic("'lun' must be less than 256"))?,
+        None => 0,
+    };
+    Ok(AddDiskCommand {
+        read_only: bool_arg(args, "read-only")?.unwrap_or(false),
+        is_dvd,
+        target: 0,
+        path: 0,
+        lun,
+        nvme,
+        vtl2: false,
+        ram: None,
+        disk: Some(disk),
+    })
+}
+
+/// The arguments to `set-battery`, each of which is optional.
+#[derive(Debug, PartialEq)]
+struct BatteryChange {
+    present: Option<bool>,
+    ac_online: Option<bool>,
+    charge: Option<u8>,
+}
+
+impl BatteryChange {
+    fn parse(args: &Value) -> Result<Self, QmpError> {
+        let charge = match args.get("charge") {
+            None => None,
+            Some(v) => Some(
+                v.as_u64()
+                    .filter(|&v| v <= 100)
+                    .ok_or_else(|| QmpError::generic("'charge' must be a percentage"))?
+                    as u8,
+            ),
+        };
+        Ok(Self {
+            present: bool_arg(args, "present")?,
+            ac_online: bool_arg(args, "ac-online")?,
+            charge,
+        })
+    }
+
+    fn apply(&self, state: &mut HostBatteryUpdate) {
+        if let Some(present) = self.present {
+            state.set_battery_present(present);
+        }
+        if let Some(charge) = self.charge {
+            state.set_charge_percent(charge);
+        }
+        if let Some(ac_online) = self.ac_online {
+            state.set_ac_online(ac_online);
+        }
+    }
+}
+
+fn json_battery(state: &HostBatteryUpdate) -> Value {
+    json!({
+        "present": state.battery_present,
+        "ac-online": state.ac_online,
+        "charge": state.charge_percent(),
+        "charging": state.charging,
+        "discharging": state.discharging,
+    })
+}
+
+fn bool_arg(args: &Value, name: &str) -> Result<Option<bool>, QmpError> {
+    args.get(name)
+        .map(|v| {
+            v.as_bool()
+                .ok_or_else(|| QmpError::generic(format!("Parameter '{name}' must be a boolean")))
+        })
+        .transpose()
+}
+
+fn u64_arg(args: &Value, name: &str) -> Result<Option<u64>, QmpError> {
+    args.get(name)
+        .map(|v| {
+            v.as_u64().ok_or_else(|| {
+                QmpError::generic(format!("Parameter '{name}' must be an unsigned integer"))
+            })
+        })
+        .transpose()
+}
+
+fn str_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, QmpError> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| QmpError::generic(format!("Parameter '{name}' is missing")))
+}
+
+fn pool_arg(args: &Value) -> Result<KvpPool, QmpError> {
+    let pool = str_arg(args, "pool")?;
+    let pool = kvp::KvpPool::from_str(pool, true)
+        .map_err(|_| QmpError::generic("'pool' must be guest, external, auto, or auto-external"))?;
+    Ok(kvp::pool_cvt(pool))
+}
+
+fn value_arg(args: &Value) -> Result<hyperv_ic_resources::kvp::Value, QmpError> {
+    use hyperv_ic_resources::kvp::Value as KvpValue;
+    let value = match (args.get("value"), args.get("type").and_then(|v| v.as_str())) {
+        (Some(Value::String(s)), None | Some("string")) => Some(KvpValue::String(s.clone())),
+        (Some(Value::Number(n)), None | Some("qword")) => n.as_u64().map(KvpValue::U64),
+        (Some(Value::Number(n)), Some("dword")) => n
+            .as_u64()
+            .and_then(|n| n.try_into().ok())
+            .map(KvpValue::U32),
+        _ => None,
+    };
+    value.ok_or_else(|| {
+        QmpError::generic(
+            "'value' must be a string, or an unsigned integer with 'type' dword or qword",
+        )
+    })
+}
+
+fn json_value(value: &hyperv_ic_resources::kvp::Value) -> Value {
+    match value {
+        hyperv_ic_resources::kvp::Value::String(s) => json!(s),
+        hyperv_ic_resources::kvp::Value::U32(v) => json!(v),
+        hyperv_ic_resources::kvp::Value::U64(v) => json!(v),
+    }
+}
+
+fn json_ip_info(info: &hyperv_ic_resources::kvp::IpInfo) -> Value {
+    let origin = |origin: &hyperv_ic_resources::kvp::AddressOrigin| match origin {
+        hyperv_ic_resources::kvp::AddressOrigin::Unknown => "unknown",
+        hyperv_ic_resources::kvp::AddressOrigin::Static => "static",
+        hyperv_ic_resources::kvp::AddressOrigin::Other => "other",
+    };
+    let ipv4_addresses = info
+        .ipv4_addresses
+        .iter()
+        .map(|a| json!({ "address": a.address, "netmask": a.subnet, "origin": origin(&a.origin) }))
+        .collect::<Vec<_>>();
+    let ipv6_addresses = info
+        .ipv6_addresses
+        .iter()
+        .map(|a| json!({ "address": a.address, "prefix": a.subnet, "origin": origin(&a.origin) }))
+        .collect::<Vec<_>>();
+    // IP addresses serialize as strings.
+    json!({
+        "dhcp-enabled": info.dhcp_enabled,
+        "ipv4-enabled": info.ipv4,
+        "ipv6-enabled": info.ipv6,
+        "ipv4-addresses": ipv4_addresses,
+        "ipv6-addresses": ipv6_addresses,
+        "ipv4-gateways": info.ipv4_gateways,
+        "ipv6-gateways": info.ipv6_gateways,
+        "ipv4-dns-servers": info.ipv4_dns_servers,
+        "ipv6-dns-servers": info.ipv6_dns_servers,
+    })
+}
+
+async fn send(
+    write: &mut (impl futures::AsyncWrite + Unpin),
+    value: &Value,
+) -> std::io::Result<()> {
+    let mut data = value.to_string();
+    data.push_str("\r\n");
+    write.write_all(data.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn error_class<T>(result: Result<T, QmpError>) -> &'static str {
+        result.err().unwrap().0
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let server = QmpServer {
+            vm_rpc: mesh::channel().0,
+            shutdown_ic: None,
+            kvp_ic: None,
+            vss_ic: None,
+            battery: None,
+            commands: mesh::channel().0,
+            disks: mesh::channel().0,
+        };
+        let mut negotiated = false;
+        let execute = |command| json!({ "execute": command });
+
+        let r = block_on(server.handle(&execute("query-version"), &mut negotiated));
+        assert_eq!(error_class(r), "CommandNotFound");
+        block_on(server.handle(&execute("qmp_capabilities"), &mut negotiated)).unwrap();
+        assert!(negotiated);
+        block_on(server.handle(&execute("query-version"), &mut negotiated)).unwrap();
+
+        let r = block_on(server.handle(&execute("device_add"), &mut negotiated));
+        assert_eq!(error_class(r), "GenericError");
+        let r = block_on(server.handle(&execute("migrate-incoming"), &mut negotiated));
+        assert_eq!(error_class(r), "CommandNotFound");
+        let r = block_on(server.handle(
+            &json!({ "execute": "schedule-wake", "arguments": { "seconds": -1 } }),
+            &mut negotiated,
+        ));
+        assert_eq!(error_class(r), "GenericError");
+        let r = block_on(server.handle(&json!({}), &mut negotiated));
+        assert_eq!(error_class(r), "GenericError");
+    }
+
+    #[test]
+    fn test_kvp_args() {
+        let parse = |command, args| KvpRequest::parse(command, &args);
+
+        let r = parse(
+            "kvp-set",
+            json!({ "pool": "external", "key": "a", "value": "b" }),
+        )
+        .unwrap();
+        let KvpRequest::Set(params) = r else { panic!() };
+        assert_eq!(
+            params.value,
+            hyperv_ic_resources::kvp::Value::String("b".into())
+        );
+
+        let r = parse(
+            "kvp-set",
+            json!({ "pool": "auto-external", "key": "a", "value": 5, "type": "dword" }),
+        )
+        .unwrap();
+        let KvpRequest::Set(params) = r else { panic!() };
+        assert_eq!(params.value, hyperv_ic_resources::kvp::Value::U32(5));
+
+        let r = parse(
+            "kvp-set",
+            json!({ "pool": "guest", "key": "a", "value": 1u64 << 32, "type": "dword" }),
+        );
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse("kvp-get", json!({ "pool": "bogus", "key": "a" }));
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse("query-kvp-ip-info", json!({}));
+        assert_eq!(error_class(r), "GenericError");
+        parse("query-kvp", json!({ "pool": "Guest" })).unwrap();
+    }
+
+    #[test]
+    fn test_device_add_args() {
+        let command = parse_device_add(&json!({
+            "driver": "scsi-hd",
+            "disk": "mem:1G",
+            "lun": 3,
+            "read-only": true,
+        }))
+        .unwrap();
+        assert!(command.read_only && !command.is_dvd);
+        assert_eq!(command.lun, 3);
+        assert_eq!(command.nvme, None);
+
+        let command =
+            parse_device_add(&json!({ "driver": "nvme-ns", "disk": "mem:1G", "nsid": 2 })).unwrap();
+        assert_eq!(command.nvme, Some(2));
+        let command = parse_device_add(&json!({ "driver": "scsi-cd", "disk": "mem:1G" })).unwrap();
+        assert!(command.is_dvd);
+
+        let r = parse_device_add(&json!({ "driver": "virtio-blk-pci", "disk": "mem:1G" }));
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse_device_add(&json!({ "driver": "nvme-ns", "disk": "mem:1G" }));
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse_device_add(
+            &json!({ "driver": "nvme-ns", "disk": "mem:1G", "nsid": 1, "lun": 1 }),
+        );
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse_device_add(&json!({ "driver": "scsi-hd", "disk": "mem:1G", "lun": 256 }));
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse_device_add(&json!({ "driver": "scsi-hd" }));
+        assert_eq!(error_class(r), "GenericError");
+    }
+
+    #[test]
+    fn test_battery_args() {
+        let parse = |args| BatteryChange::parse(&args);
+
+        let change = parse(json!({ "ac-online": false, "charge": 20 })).unwrap();
+        assert_eq!(
+            change,
+            BatteryChange {
+                present: None,
+                ac_online: Some(false),
+                charge: Some(20),
+            }
+        );
+        let mut state = HostBatteryUpdate::default_present();
+        change.apply(&mut state);
+        assert_eq!(state.charge_percent(), Some(20));
+        assert!(state.discharging && !state.charging);
+
+        let r = parse(json!({ "charge": 101 }));
+        assert_eq!(error_class(r), "GenericError");
+        let r = parse(json!({ "present": "yes" }));
+        assert_eq!(error_class(r), "GenericError");
+        parse(Value::Null).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Saving the framebuffer contents to an image file.
+
+use framebuffer::View;
+use std::io::Write;
+use std::path::Path;
+
+/// The framebuffer has 4 bytes per pixel, in BGRX order.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Writes the current framebuffer contents to `path` as a binary PPM image.
+pub fn write_screendump(view: &mut View, path: &Path) -> anyhow::Result<()> {
+    let (width, height) = view.resolution();
+    let stride = width as usize * BYTES_PER_PIXEL;
+    let mut pixels = vec![0; stride * height as usize];
+    for (i, line) in (0..height).zip(pixels.chunks_exact_mut(stride)) {
+        view.read_line(i, line);
+    }
+    let mut file = std::io::BufWriter::new(fs_err::File::create(path)?);
+    file.write_all(&encode_ppm(width.into(), height.into(), &pixels))?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Encodes BGRX pixels as a binary PPM image.
+fn encode_ppm(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+    ppm.reserve(width * height * 3);
+    for pixel in pixels.chunks_exact(BYTES_PER_PIXEL) {
+        ppm.extend([pixel[2], pixel[1], pixel[0]]);
+    }
+    ppm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ppm() {
+        let pixels = [0x01, 0x02, 0x03, 0xff, 0x04, 0x05, 0x06, 0xff];
+        assert_eq!(
+            encode_ppm(2, 1, &pixels),
+            b"P6\n2 1\n255\n\x03\x02\x01\x06\x05\x04"
+        );
+    }
+}
@@ -13,6 +13,7 @@
 use pal_async::driver::SpawnDriver;
 use pal_async::pipe::PolledPipe;
 use pal_async::task::Task;
+use serial_socket::client::TcpClientSerialConfig;
 use serial_socket::net::OpenSocketSerialConfig;
 use std::fs::File;
 use std::io;
@@ -20,6 +21,7 @@
 use std::net::SocketAddr;
 use std::path::Path;
 use std::thread;
+use std::time::Duration;
 use unix_socket::UnixListener;
 use vm_resource::IntoResource;
 use vm_resource::Resource;
@@ -256,3 +258,33 @@ pub fn bind_tcp_serial(addr: &SocketAddr) -> anyhow::Result<Resource<SerialBacke
         .with_context(|| format!("failed to bind tcp address {addr}"))?;
     Ok(OpenSocketSerialConfig::from(listener).into_resource())
 }
+
+/// Returns a serial backend that connects to the TCP listener at `addr`,
+/// retrying every `retry` until the connection succeeds or after it is lost.
+pub fn connect_tcp_serial(addr: &SocketAddr, retry: Duration) -> Resource<SerialBackendHandle> {
+    TcpClientSerialConfig {
+        addr: addr.to_string(),
+        retry,
+    }
+    .into_resource()
+}
+
+/// Opens a pseudo-terminal for the serial port `name`, reporting the path
+/// for terminal programs to open.
+pub fn open_pty_serial(name: &str) -> anyhow::Result<Resource<SerialBackendHandle>> {
+    #[cfg(unix)]
+    {
+        let (primary, secondary) = pal::unix::pty::open().context("failed to open pty")?;
+        let path = pal::unix::pty::path(&secondary).context("failed to get pty path")?;
+        tracing::info!(name, path = %path.display(), "serial port attached to pty");
+        // Close the secondary side so that the backend sees a hangup until a
+        // terminal program opens it.
+        drop(secondary);
+        Ok(serial_socket::pty::OpenPtySerialConfig::from(primary).into_resource())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = name;
+        anyhow::bail!("pty serial ports are only supported on unix")
+    }
+}
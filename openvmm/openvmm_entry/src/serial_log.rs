@@ -0,0 +1,146 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Serial output log files, with optional size-based rotation and per-line
+//! host timestamps.
+
+use crate::cli_args::SerialFileOptions;
+use std::ffi::OsString;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A [`Write`] implementation that writes serial output to a log file.
+///
+/// When the file reaches the configured maximum size, it is renamed to
+/// `<path>.1` (shifting any older logs to `<path>.2` and so on, up to the
+/// configured number of rotated logs) and a new file is started.
+pub struct SerialLogFile {
+    path: PathBuf,
+    options: SerialFileOptions,
+    file: fs_err::File,
+    size: u64,
+    at_line_start: bool,
+}
+
+impl SerialLogFile {
+    /// Creates (or truncates) the log file at `path`.
+    pub fn create(path: impl Into<PathBuf>, options: SerialFileOptions) -> io::Result<Self> {
+        let path = path.into();
+        let file = fs_err::File::create(&path)?;
+        Ok(Self {
+            path,
+            options,
+            file,
+            size: 0,
+            at_line_start: true,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.options.rotate > 0 {
+            for index in (1..self.options.rotate).rev() {
+                rename_if_exists(&self.rotated_path(index), &self.rotated_path(index + 1))?;
+            }
+            fs_err::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = fs_err::File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if self.options.max_size.is_some_and(|max| self.size >= max) {
+            self.rotate()?;
+        }
+        if self.at_line_start && self.options.timestamp {
+            let prefix = format!("[{:.6}] ", jiff::Timestamp::now());
+            self.file.write_all(prefix.as_bytes())?;
+            self.size += prefix.len() as u64;
+        }
+        self.file.write_all(chunk)?;
+        self.size += chunk.len() as u64;
+        self.at_line_start = chunk.ends_with(b"\n");
+        Ok(())
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs_err::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+impl Write for SerialLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Split the output into lines so that each one can be timestamped and
+        // so that rotation happens on line boundaries where possible.
+        for chunk in buf.split_inclusive(|&b| b == b'\n') {
+            self.write_chunk(chunk)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_size: Option<u64>, rotate: u32, timestamp: bool) -> SerialFileOptions {
+        SerialFileOptions {
+            max_size,
+            rotate,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("com1.log");
+        let mut log = SerialLogFile::create(&path, options(Some(8), 2, false)).unwrap();
+        log.write_all(b"one\ntwo\n").unwrap();
+        log.write_all(b"three\n").unwrap();
+        log.write_all(b"four\nfive\n").unwrap();
+        log.write_all(b"six\n").unwrap();
+        log.flush().unwrap();
+
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "five\nsix\n");
+        assert_eq!(read(&log.rotated_path(1)), "three\nfour\n");
+        assert_eq!(read(&log.rotated_path(2)), "one\ntwo\n");
+        assert!(!log.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("com1.log");
+        let mut log = SerialLogFile::create(&path, options(None, 0, true)).unwrap();
+        log.write_all(b"hello ").unwrap();
+        log.write_all(b"world\nbye\n").unwrap();
+        log.flush().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, text) in lines.iter().zip(["hello world", "bye"]) {
+            let (stamp, rest) = line.split_once("] ").unwrap();
+            assert!(stamp.starts_with('['));
+            assert!(stamp[1..].parse::<jiff::Timestamp>().is_ok());
+            assert_eq!(rest, text);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Relays a serial port to WebSocket clients, for browser-based consoles.
+//!
+//! Serial output is broadcast to every connected client. The first client to
+//! connect can also send input; later clients are read-only viewers until the
+//! read-write client disconnects, at which point the next client to connect
+//! takes its place.
+
+use crate::serial_io::anonymous_serial_pair;
+use anyhow::Context;
+use base64::Engine;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use futures::StreamExt;
+use futures::lock::Mutex as AsyncMutex;
+use futures_concurrency::prelude::*;
+use pal_async::driver::SpawnDriver;
+use pal_async::socket::PolledSocket;
+use pal_async::task::Spawn;
+use parking_lot::Mutex;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Arc;
+use vm_resource::Resource;
+use vm_resource::kind::SerialBackendHandle;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_REQUEST_LEN: usize = 8192;
+const MAX_FRAME_LEN: u64 = 0x10000;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Listens for WebSocket connections on `addr` and relays them to a new serial
+/// port backend.
+pub fn bind_ws_serial(
+    driver: &(impl SpawnDriver + Clone),
+    name: &str,
+    addr: &SocketAddr,
+) -> anyhow::Result<Resource<SerialBackendHandle>> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to bind tcp address {addr}"))?;
+    let mut listener = PolledSocket::new(driver, listener)?;
+    let (config, serial) = anonymous_serial_pair(driver)?;
+    let (serial_read, serial_write) = AsyncReadExt::split(serial);
+
+    let hub = Arc::new(Hub {
+        clients: Mutex::new(Clients::default()),
+        serial_input: AsyncMutex::new(Box::new(serial_write)),
+    });
+
+    driver
+        .spawn(format!("{name} websocket output"), {
+            let hub = hub.clone();
+            async move {
+                if let Err(err) = hub.broadcast_output(serial_read).await {
+                    tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to read serial output"
+                    );
+                }
+            }
+        })
+        .detach();
+
+    driver
+        .spawn(format!("{name} websocket listener"), {
+            let driver = driver.clone();
+            let name = name.to_owned();
+            async move {
+                loop {
+                    let (conn, peer) = match listener.accept().await {
+                        Ok(r) => r,
+                        Err(err) => {
+                            tracing::error!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to accept websocket connection"
+                            );
+                            break;
+                        }
+                    };
+                    let socket = match PolledSocket::new(&driver, conn) {
+                        Ok(socket) => socket,
+                        Err(err) => {
+                            tracing::error!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to create polled socket"
+                            );
+                            continue;
+                        }
+                    };
+                    let hub = hub.clone();
+                    driver
+                        .spawn(format!("{name} websocket client {peer}"), async move {
+                            let (read, write) = socket.split();
+                            match hub.run_client(read, write).await {
+                                Ok(()) => tracing::debug!(%peer, "websocket client done"),
+                                Err(err) => tracing::debug!(
+                                    %peer,
+                                    error = err.as_ref() as &dyn std::error::Error,
+                                    "websocket client failed"
+                                ),
+                            }
+                        })
+                        .detach();
+                }
+            }
+        })
+        .detach();
+
+    Ok(config)
+}
+
+struct Hub {
+    clients: Mutex<Clients>,
+    serial_input: AsyncMutex<Box<dyn AsyncWrite + Send + Unpin>>,
+}
+
+#[derive(Default)]
+struct Clients {
+    next_id: u64,
+    read_write: Option<u64>,
+    outputs: Vec<(u64, mesh::Sender<Vec<u8>>)>,
+}
+
+impl Hub {
+    async fn broadcast_output(&self, mut serial: impl AsyncRead + Unpin) -> std::io::Result<()> {
+        let mut buf = vec![0; 4096];
+        loop {
+            let n = serial.read(&mut buf).await?;
+            if n == 0 {
+                break Ok(());
+            }
+            for (_, output) in &self.clients.lock().outputs {
+                output.send(buf[..n].to_vec());
+            }
+        }
+    }
+
+    async fn run_client(
+        &self,
+        mut read: impl AsyncRead + Unpin,
+        mut write: impl AsyncWrite + Unpin,
+    ) -> anyhow::Result<()> {
+        let key = read_handshake(&mut read).await?;
+        write
+            .write_all(
+                format!(
+                    "HTTP/1.1 101 Switching Protocols\r\n\
+                    Upgrade: websocket\r\n\
+                    Connection: Upgrade\r\n\
+                    Sec-WebSocket-Accept: {}\r\n\r\n",
+                    accept_key(&key)
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        let (output_send, output_recv) = mesh::channel();
+        let (id, read_write) = {
+            let mut clients = self.clients.lock();
+            let id = clients.next_id;
+            clients.next_id += 1;
+            let read_write = clients.read_write.is_none();
+            if read_write {
+                clients.read_write = Some(id);
+            }
+            clients.outputs.push((id, output_send));
+            (id, read_write)
+        };
+        tracing::debug!(id, read_write, "websocket client connected");
+
+        let (control_send, control_recv) = mesh::channel();
+        let input = async {
+            let r = self
+                .relay_client_input(&mut read, read_write, control_send)
+                .await;
+            // Stop sending output so that the output relay completes.
+            let mut clients = self.clients.lock();
+            clients.outputs.retain(|(i, _)| *i != id);
+            if clients.read_write == Some(id) {
+                clients.read_write = None;
+            }
+            r
+        };
+        let output = async {
+            let mut frames = (output_recv.map(|data| (OPCODE_BINARY, data)), control_recv).merge();
+            while let Some((opcode, data)) = frames.next().await {
+                write_frame(&mut write, opcode, &data).await?;
+                if opcode == OPCODE_CLOSE {
+                    break;
+                }
+            }
+            anyhow::Ok(())
+        };
+        let (input, output) = (input, output).join().await;
+        input.and(output)
+    }
+
+    async fn relay_client_input(
+        &self,
+        read: &mut (impl AsyncRead + Unpin),
+        read_write: bool,
+        control: mesh::Sender<(u8, Vec<u8>)>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let (opcode, data) = read_frame(read).await?;
+            match opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                    if read_write {
+                        self.serial_input.lock().await.write_all(&data).await?;
+                    }
+                }
+                OPCODE_PING => control.send((OPCODE_PONG, data)),
+                OPCODE_PONG => {}
+                OPCODE_CLOSE => {
+                    control.send((OPCODE_CLOSE, data));
+                    break Ok(());
+                }
+                _ => anyhow::bail!("unknown websocket opcode {opcode:#x}"),
+            }
+        }
+    }
+}
+
+/// Reads the client's opening handshake, returning its `Sec-WebSocket-Key`.
+async fn read_handshake(read: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<String> {
+    let mut request = Vec::new();
+    let mut byte = [0];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_LEN {
+            anyhow::bail!("websocket handshake too long");
+        }
+        read.read_exact(&mut byte).await?;
+        request.push(byte[0]);
+    }
+    let request = std::str::from_utf8(&request).context("invalid websocket handshake")?;
+    request
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, value)| value.trim().to_owned())
+        .context("missing websocket key")
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for a client's key.
+fn accept_key(key: &str) -> String {
+    base64::engine::general_purpose::STANDARD
+        .encode(sha1(format!("{key}{WEBSOCKET_GUID}").as_bytes()))
+}
+
+/// Reads a (masked) frame from a client.
+async fn read_frame(read: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    read.read_exact(&mut header).await?;
+    let opcode = header[0] & 0xf;
+    let masked = header[1] & 0x80 != 0;
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            read.read_exact(&mut len).await?;
+            u16::from_be_bytes(len).into()
+        }
+        127 => {
+            let mut len = [0; 8];
+            read.read_exact(&mut len).await?;
+            u64::from_be_bytes(len)
+        }
+        len => len.into(),
+    };
+    if !masked {
+        anyhow::bail!("unmasked websocket frame from client");
+    }
+    if len > MAX_FRAME_LEN {
+        anyhow::bail!("websocket frame too large: {len}");
+    }
+    let mut mask = [0; 4];
+    read.read_exact(&mut mask).await?;
+    let mut data = vec![0; len as usize];
+    read.read_exact(&mut data).await?;
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok((opcode, data))
+}
+
+/// Writes an unfragmented, unmasked frame.
+async fn write_frame(
+    write: &mut (impl AsyncWrite + Unpin),
+    opcode: u8,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(data.len() + 10);
+    frame.push(0x80 | opcode);
+    match data.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(data);
+    write.write_all(&frame).await
+}
+
+/// Computes the SHA-1 digest of `data`, as needed for the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for chunk in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (out, h) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::async_test;
+
+    #[test]
+    fn test_accept_key() {
+        // From RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kzYfxZPY3Hq+Oo="
+        );
+    }
+
+    #[async_test]
+    async fn test_read_frame() {
+        // A masked "Hello" text frame, from RFC 6455, section 5.7.
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (opcode, data) = read_frame(&mut &frame[..]).await.unwrap();
+        assert_eq!(opcode, OPCODE_TEXT);
+        assert_eq!(data, b"Hello");
+
+        // Unmasked frames from clients are rejected.
+        let frame = [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+        assert!(read_frame(&mut &frame[..]).await.is_err());
+    }
+}
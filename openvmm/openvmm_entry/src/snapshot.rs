@@ -0,0 +1,671 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Snapshots of a VM's RAM and device state, which the VM can later be resumed
+//! from, even after the host reboots.
+//!
+//! A snapshot is a stream that starts with a header containing the VM's RAM
+//! ranges, followed by records, each starting with a tag byte:
+//!
+//! * `RECORD_RAM`: a GPA and a length (little endian `u64` and `u32`) followed
+//!   by that many bytes of RAM. All-zero chunks are not written. A later
+//!   record for the same RAM replaces the earlier one, which is how migration
+//!   resends pages the guest wrote after they were first sent.
+//! * `RECORD_STATE`: a length (`u64`) followed by the encoded saved state.
+//!   This is always the last record.
+//!
+//! A snapshot saved to a file instead starts with the VM's RAM, with the RAM
+//! ranges laid out consecutively and all-zero chunks left as holes, so that
+//! VMs resumed from it can map their RAM from the file copy-on-write. The RAM
+//! is followed by the encoded saved state, the RAM ranges (start and end, as
+//! little endian `u64`s), and a footer with the number of ranges (`u32`), the
+//! saved state's length (`u64`), and `FILE_MAGIC`.
+
+use anyhow::Context as _;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use hvlite_defs::rpc::VmRpc;
+use memory_range::MemoryRange;
+use mesh::payload::message::ProtobufMessage;
+use mesh::rpc::RpcSend;
+use std::fs::File;
+use std::path::Path;
+
+const MAGIC: [u8; 8] = *b"OVMMSNP1";
+const FILE_MAGIC: [u8; 8] = *b"OVMMIMG1";
+/// The length of a snapshot file's footer.
+const FOOTER_LEN: u64 = 4 + 8 + 8;
+const RECORD_RAM: u8 = 1;
+const RECORD_STATE: u8 = 2;
+
+/// The amount of RAM read and written at a time.
+pub const CHUNK_SIZE: u64 = 0x100000;
+/// The largest saved state accepted when loading a snapshot.
+const MAX_STATE_SIZE: u64 = 0x10000000;
+
+/// A VM loaded from a snapshot.
+pub struct SavedVm {
+    /// The guest RAM, with the RAM ranges laid out consecutively from the
+    /// start of the file.
+    pub ram: File,
+    /// The device saved state.
+    pub saved_state: ProtobufMessage,
+}
+
+/// Saves a snapshot of the VM to the file at `path`.
+///
+/// The VM is paused while it is saved, and then resumed if it was running.
+pub async fn save(vm_rpc: &mesh::Sender<VmRpc>, path: &Path) -> anyhow::Result<()> {
+    let running = vm_rpc.call(VmRpc::Pause, ()).await?;
+    let r = save_paused(vm_rpc, path).await;
+    if running {
+        if let Err(err) = vm_rpc.call(VmRpc::Resume, ()).await {
+            // Report the save's result over the resume's, since that is what
+            // the caller asked for.
+            match &r {
+                Ok(()) => {
+                    return Err(anyhow::Error::new(err)
+                        .context("saved the snapshot, but failed to resume the vm"));
+                }
+                Err(_) => tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to resume the vm after a failed save"
+                ),
+            }
+        }
+    }
+    r
+}
+
+/// Saves a snapshot of the paused VM to the file at `path`.
+///
+/// The snapshot is written to a new file that then replaces `path`, since VMs
+/// resumed from an existing snapshot at `path` still map their RAM from it.
+pub async fn save_paused(vm_rpc: &mesh::Sender<VmRpc>, path: &Path) -> anyhow::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed to create a file in {}", dir.display()))?;
+    write_vm_file(vm_rpc, file.as_file()).await?;
+    // Make sure the snapshot survives a host crash or reboot.
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Writes the paused VM's RAM and saved state to `file` in the snapshot file
+/// format.
+async fn write_vm_file(vm_rpc: &mesh::Sender<VmRpc>, file: &File) -> anyhow::Result<()> {
+    let ram_ranges = vm_rpc.call(VmRpc::RamRanges, ()).await?;
+    let ram_size = ram_ranges.iter().map(|r| r.len()).sum();
+    file.set_len(ram_size)?;
+    let mut written = 0;
+    let mut offset = 0;
+    for range in &ram_ranges {
+        let mut gpa = range.start();
+        while gpa < range.end() {
+            let len = (range.end() - gpa).min(CHUNK_SIZE) as usize;
+            let data = read_memory(vm_rpc, gpa, len).await?;
+            if data.iter().any(|&b| b != 0) {
+                write_ram(file, &data, offset)?;
+                written += len as u64;
+            }
+            gpa += len as u64;
+            offset += len as u64;
+        }
+    }
+
+    let state = save_state(vm_rpc).await?;
+    let mut trailer = state.clone();
+    for range in &ram_ranges {
+        trailer.extend(range.start().to_le_bytes());
+        trailer.extend(range.end().to_le_bytes());
+    }
+    trailer.extend((ram_ranges.len() as u32).to_le_bytes());
+    trailer.extend((state.len() as u64).to_le_bytes());
+    trailer.extend(FILE_MAGIC);
+    write_ram(file, &trailer, ram_size)?;
+
+    tracing::info!(
+        ram_written = written,
+        state_size = state.len(),
+        "vm written"
+    );
+    Ok(())
+}
+
+/// Loads a VM from the snapshot file at `path`.
+///
+/// The returned RAM is the snapshot file itself, which must not be modified
+/// while VMs resumed from it are running.
+pub async fn load(path: &Path) -> anyhow::Result<SavedVm> {
+    let file: File = fs_err::File::open(path)?.into();
+    let len = file.metadata()?.len();
+    let footer_offset = len
+        .checked_sub(FOOTER_LEN)
+        .context("snapshot file too small")?;
+    let mut footer = [0; FOOTER_LEN as usize];
+    read_file(&file, &mut footer, footer_offset)?;
+    let (count, rest) = footer.split_at(4);
+    let (state_len, magic) = rest.split_at(8);
+    if magic != FILE_MAGIC {
+        anyhow::bail!("not a vm snapshot file, or an unsupported version");
+    }
+    let count = u32::from_le_bytes(count.try_into().unwrap());
+    let state_len = u64::from_le_bytes(state_len.try_into().unwrap());
+    if state_len > MAX_STATE_SIZE {
+        anyhow::bail!("saved state too large: {state_len:#x}");
+    }
+
+    let ranges_offset = footer_offset
+        .checked_sub(u64::from(count) * 16)
+        .context("snapshot file too small")?;
+    let mut ranges = vec![0; count as usize * 16];
+    read_file(&file, &mut ranges, ranges_offset)?;
+    let mut ram_ranges = Vec::new();
+    for range in ranges.chunks_exact(16) {
+        let start = u64::from_le_bytes(range[..8].try_into().unwrap());
+        let end = u64::from_le_bytes(range[8..].try_into().unwrap());
+        let range = MemoryRange::try_new(start..end)
+            .map_err(|_| anyhow::anyhow!("invalid ram range {start:#x}-{end:#x}"))?;
+        ram_ranges.push(range);
+    }
+    let ram_size: u64 = ram_ranges.iter().map(|r| r.len()).sum();
+    if ram_size.checked_add(state_len) != Some(ranges_offset) {
+        anyhow::bail!("snapshot file size does not match its ram ranges");
+    }
+
+    let mut state = vec![0; state_len as usize];
+    read_file(&file, &mut state, ram_size)?;
+    let saved_state =
+        mesh::payload::decode::<ProtobufMessage>(&state).context("failed to decode saved state")?;
+    Ok(SavedVm {
+        ram: file,
+        saved_state,
+    })
+}
+
+/// Writes the paused VM's RAM and saved state to `stream`.
+pub async fn write_vm(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    stream: &mut (impl AsyncWrite + Unpin),
+) -> anyhow::Result<()> {
+    let ram_ranges = vm_rpc.call(VmRpc::RamRanges, ()).await?;
+    write_header(stream, &ram_ranges).await?;
+    let mut written = 0;
+    for range in &ram_ranges {
+        written += write_range(vm_rpc, stream, *range).await?;
+    }
+    let state_size = write_state(vm_rpc, stream).await?;
+
+    tracing::info!(ram_written = written, state_size, "vm written");
+    Ok(())
+}
+
+/// Writes the snapshot header for a VM with the given RAM ranges.
+pub async fn write_header(
+    stream: &mut (impl AsyncWrite + Unpin),
+    ram_ranges: &[MemoryRange],
+) -> anyhow::Result<()> {
+    let mut header = MAGIC.to_vec();
+    header.extend((ram_ranges.len() as u32).to_le_bytes());
+    for range in ram_ranges {
+        header.extend(range.start().to_le_bytes());
+        header.extend(range.end().to_le_bytes());
+    }
+    stream.write_all(&header).await?;
+    Ok(())
+}
+
+/// Writes the non-zero chunks of the RAM in `range`, returning the number of
+/// bytes written.
+pub async fn write_range(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    stream: &mut (impl AsyncWrite + Unpin),
+    range: MemoryRange,
+) -> anyhow::Result<u64> {
+    let mut written = 0;
+    let mut gpa = range.start();
+    while gpa < range.end() {
+        let len = (range.end() - gpa).min(CHUNK_SIZE) as usize;
+        let data = read_memory(vm_rpc, gpa, len).await?;
+        if data.iter().any(|&b| b != 0) {
+            write_ram_record(stream, gpa, &data).await?;
+            written += len as u64;
+        }
+        gpa += len as u64;
+    }
+    Ok(written)
+}
+
+/// Reads `len` bytes of guest memory at `gpa`.
+pub async fn read_memory(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    gpa: u64,
+    len: usize,
+) -> anyhow::Result<Vec<u8>> {
+    vm_rpc
+        .call_failable(VmRpc::ReadMemory, (gpa, len))
+        .await
+        .with_context(|| format!("failed to read guest memory at {gpa:#x}"))
+}
+
+/// Writes a record containing `data`, the RAM at `gpa`.
+pub async fn write_ram_record(
+    stream: &mut (impl AsyncWrite + Unpin),
+    gpa: u64,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let mut record = vec![RECORD_RAM];
+    record.extend(gpa.to_le_bytes());
+    record.extend((data.len() as u32).to_le_bytes());
+    stream.write_all(&record).await?;
+    stream.write_all(data).await?;
+    Ok(())
+}
+
+/// Saves the paused VM's device state and writes it as the last record,
+/// returning its size.
+pub async fn write_state(
+    vm_rpc: &mesh::Sender<VmRpc>,
+    stream: &mut (impl AsyncWrite + Unpin),
+) -> anyhow::Result<usize> {
+    let state = save_state(vm_rpc).await?;
+    let mut record = vec![RECORD_STATE];
+    record.extend((state.len() as u64).to_le_bytes());
+    stream.write_all(&record).await?;
+    stream.write_all(&state).await?;
+    stream.flush().await?;
+    Ok(state.len())
+}
+
+/// Saves the paused VM's device state, returning it encoded.
+async fn save_state(vm_rpc: &mesh::Sender<VmRpc>) -> anyhow::Result<Vec<u8>> {
+    let state = vm_rpc
+        .call_failable(VmRpc::Save, ())
+        .await
+        .context("failed to save vm state")?;
+    Ok(mesh::payload::encode(state))
+}
+
+/// Reads a VM's RAM and saved state from `stream`.
+pub async fn read_vm(stream: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<SavedVm> {
+    let mut magic = [0; 8];
+    stream.read_exact(&mut magic).await?;
+    if magic != MAGIC {
+        anyhow::bail!("not a vm snapshot, or an unsupported version");
+    }
+    let count = read_u32(stream).await?;
+    let mut ram_ranges = Vec::new();
+    for _ in 0..count {
+        let start = read_u64(stream).await?;
+        let end = read_u64(stream).await?;
+        let range = MemoryRange::try_new(start..end)
+            .map_err(|_| anyhow::anyhow!("invalid ram range {start:#x}-{end:#x}"))?;
+        ram_ranges.push(range);
+    }
+    let ram_size = ram_ranges.iter().map(|r| r.len()).sum();
+    let ram = alloc_ram(ram_size).context("failed to allocate guest ram")?;
+
+    loop {
+        let mut tag = [0];
+        stream.read_exact(&mut tag).await?;
+        match tag[0] {
+            RECORD_RAM => {
+                let gpa = read_u64(stream).await?;
+                let len = read_u32(stream).await?;
+                let offset = ram_offset(&ram_ranges, gpa, len.into())
+                    .with_context(|| format!("invalid ram chunk {gpa:#x}+{len:#x}"))?;
+                let mut data = vec![0; len as usize];
+                stream.read_exact(&mut data).await?;
+                write_ram(&ram, &data, offset).context("failed to write guest ram")?;
+            }
+            RECORD_STATE => {
+                let len = read_u64(stream).await?;
+                if len > MAX_STATE_SIZE {
+                    anyhow::bail!("saved state too large: {len:#x}");
+                }
+                let mut state = vec![0; len as usize];
+                stream.read_exact(&mut state).await?;
+                let saved_state = mesh::payload::decode::<ProtobufMessage>(&state)
+                    .context("failed to decode saved state")?;
+                break Ok(SavedVm { ram, saved_state });
+            }
+            tag => anyhow::bail!("invalid snapshot record {tag:#x}"),
+        }
+    }
+}
+
+/// Returns the offset of `gpa..gpa + len` in the consecutively laid out RAM
+/// ranges, if it lies within a single range.
+fn ram_offset(ram_ranges: &[MemoryRange], gpa: u64, len: u64) -> Option<u64> {
+    let mut offset = 0;
+    for range in ram_ranges {
+        if range.contains_addr(gpa) {
+            return (gpa.checked_add(len)? <= range.end()).then(|| offset + (gpa - range.start()));
+        }
+        offset += range.len();
+    }
+    None
+}
+
+#[cfg(unix)]
+fn alloc_ram(size: u64) -> std::io::Result<File> {
+    let size = size
+        .try_into()
+        .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    Ok(sparse_mmap::alloc_shared_memory(size)?.into())
+}
+
+#[cfg(windows)]
+fn alloc_ram(size: u64) -> std::io::Result<File> {
+    let file = tempfile::tempfile()?;
+    file.set_len(size)?;
+    Ok(file)
+}
+
+#[cfg(unix)]
+fn write_ram(ram: &File, data: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(ram, data, offset)
+}
+
+#[cfg(windows)]
+fn write_ram(ram: &File, mut data: &[u8], mut offset: u64) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let n = std::os::windows::fs::FileExt::seek_write(ram, data, offset)?;
+        data = &data[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_file(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_file(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        let n = std::os::windows::fs::FileExt::seek_read(file, buf, offset)?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf = &mut buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+async fn read_u32(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+async fn read_u64(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<u64> {
+    let mut buf = [0; 8];
+    stream.read_exact(&mut buf).await?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use pal_async::task::Task;
+    use std::io::Read;
+    use std::io::Seek;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_ram_offset() {
+        let ranges = [
+            MemoryRange::new(0..0x80000000),
+            MemoryRange::new(0x100000000..0x180000000),
+        ];
+        assert_eq!(ram_offset(&ranges, 0x1000, 0x1000), Some(0x1000));
+        assert_eq!(ram_offset(&ranges, 0x100000000, 0x100000), Some(0x80000000));
+        assert_eq!(ram_offset(&ranges, 0x7ffff000, 0x2000), None);
+        assert_eq!(ram_offset(&ranges, 0x80000000, 0x1000), None);
+        assert_eq!(ram_offset(&ranges, 0x180000000, 0x1000), None);
+    }
+
+    struct FakeVm {
+        ranges: Vec<MemoryRange>,
+        memory: Vec<u8>,
+        running: Arc<AtomicBool>,
+        fail_reads: bool,
+        fail_resume: bool,
+    }
+
+    impl FakeVm {
+        fn new() -> Self {
+            let mut memory = vec![0u8; 0x300000];
+            memory[0x1000..0x1010].fill(0xaa);
+            memory[0x2ff000] = 0x55;
+            Self {
+                ranges: vec![
+                    MemoryRange::new(0..0x200000),
+                    MemoryRange::new(0x400000..0x500000),
+                ],
+                memory,
+                running: Arc::new(AtomicBool::new(true)),
+                fail_reads: false,
+                fail_resume: false,
+            }
+        }
+
+        fn spawn(self, driver: &DefaultDriver) -> (mesh::Sender<VmRpc>, Task<()>) {
+            let (send, mut recv) = mesh::channel();
+            let task = driver.spawn("fake-vm", async move {
+                while let Some(rpc) = recv.next().await {
+                    match rpc {
+                        VmRpc::Pause(rpc) => {
+                            rpc.handle_sync(|()| self.running.swap(false, Ordering::SeqCst))
+                        }
+                        VmRpc::Resume(rpc) => {
+                            if !self.fail_resume {
+                                rpc.handle_sync(|()| !self.running.swap(true, Ordering::SeqCst))
+                            }
+                        }
+                        VmRpc::RamRanges(rpc) => rpc.handle_sync(|()| self.ranges.clone()),
+                        VmRpc::ReadMemory(rpc) => rpc.handle_failable_sync(|(gpa, len)| {
+                            assert!(!self.running.load(Ordering::SeqCst));
+                            if self.fail_reads {
+                                anyhow::bail!("read failed");
+                            }
+                            let offset = ram_offset(&self.ranges, gpa, len as u64)
+                                .ok_or_else(|| anyhow::anyhow!("bad read"))?
+                                as usize;
+                            Ok(self.memory[offset..offset + len].to_vec())
+                        }),
+                        VmRpc::Save(rpc) => {
+                            rpc.handle_failable_sync(|()| anyhow::Ok(ProtobufMessage::new((5u32,))))
+                        }
+                        rpc => panic!("unexpected rpc {rpc:?}"),
+                    }
+                }
+            });
+            (send, task)
+        }
+    }
+
+    fn read_ram(mut ram: File, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        ram.rewind().unwrap();
+        ram.read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[async_test]
+    async fn test_stream_round_trip(driver: DefaultDriver) {
+        let vm = FakeVm::new();
+        let expected = vm.memory.clone();
+        let (vm_rpc, _task) = vm.spawn(&driver);
+
+        vm_rpc.call(VmRpc::Pause, ()).await.unwrap();
+        let mut stream = futures::io::Cursor::new(Vec::new());
+        write_vm(&vm_rpc, &mut stream).await.unwrap();
+        let mut stream = futures::io::Cursor::new(stream.into_inner());
+        let saved = read_vm(&mut stream).await.unwrap();
+
+        assert_eq!(saved.saved_state.parse::<(u32,)>().unwrap(), (5,));
+        assert_eq!(read_ram(saved.ram, expected.len()), expected);
+    }
+
+    /// Saves a running VM to a file and loads it back, as `--resume` does.
+    #[async_test]
+    async fn test_save_and_resume(driver: DefaultDriver) {
+        let vm = FakeVm::new();
+        let expected = vm.memory.clone();
+        let running = vm.running.clone();
+        let (vm_rpc, _task) = vm.spawn(&driver);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.snapshot");
+        save(&vm_rpc, &path).await.unwrap();
+        assert!(running.load(Ordering::SeqCst));
+
+        let saved = load(&path).await.unwrap();
+        assert_eq!(saved.saved_state.parse::<(u32,)>().unwrap(), (5,));
+        assert_eq!(read_ram(saved.ram, expected.len()), expected);
+    }
+
+    /// Saving over a snapshot leaves the RAM of VMs resumed from it intact.
+    #[async_test]
+    async fn test_save_replaces_snapshot(driver: DefaultDriver) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.snapshot");
+
+        let vm = FakeVm::new();
+        let expected = vm.memory.clone();
+        let (vm_rpc, _task) = vm.spawn(&driver);
+        save(&vm_rpc, &path).await.unwrap();
+        let saved = load(&path).await.unwrap();
+
+        let mut vm = FakeVm::new();
+        vm.memory.fill(0x33);
+        let (vm_rpc, _task) = vm.spawn(&driver);
+        save(&vm_rpc, &path).await.unwrap();
+
+        assert_eq!(read_ram(saved.ram, expected.len()), expected);
+        let saved = load(&path).await.unwrap();
+        assert_eq!(
+            read_ram(saved.ram, expected.len()),
+            vec![0x33; expected.len()]
+        );
+    }
+
+    #[async_test]
+    async fn test_save_keeps_paused_vm_paused(driver: DefaultDriver) {
+        let vm = FakeVm::new();
+        let running = vm.running.clone();
+        running.store(false, Ordering::SeqCst);
+        let (vm_rpc, _task) = vm.spawn(&driver);
+
+        let dir = tempfile::tempdir().unwrap();
+        save(&vm_rpc, &dir.path().join("vm.snapshot"))
+            .await
+            .unwrap();
+        assert!(!running.load(Ordering::SeqCst));
+    }
+
+    #[async_test]
+    async fn test_save_failure_resumes_vm(driver: DefaultDriver) {
+        let mut vm = FakeVm::new();
+        vm.fail_reads = true;
+        let running = vm.running.clone();
+        let (vm_rpc, _task) = vm.spawn(&driver);
+
+        let dir = tempfile::tempdir().unwrap();
+        let err = save(&vm_rpc, &dir.path().join("vm.snapshot"))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("read failed"), "{err:#}");
+        assert!(running.load(Ordering::SeqCst));
+    }
+
+    /// When both the save and the resume fail, the save's error is the one
+    /// reported.
+    #[async_test]
+    async fn test_save_failure_not_hidden_by_resume_failure(driver: DefaultDriver) {
+        let mut vm = FakeVm::new();
+        vm.fail_reads = true;
+        vm.fail_resume = true;
+        let (vm_rpc, _task) = vm.spawn(&driver);
+
+        let dir = tempfile::tempdir().unwrap();
+        let err = save(&vm_rpc, &dir.path().join("vm.snapshot"))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("read failed"), "{err:#}");
+    }
+
+    #[async_test]
+    async fn test_resume_failure_after_save(driver: DefaultDriver) {
+        let mut vm = FakeVm::new();
+        vm.fail_resume = true;
+        let (vm_rpc, _task) = vm.spawn(&driver);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.snapshot");
+        let err = save(&vm_rpc, &path).await.unwrap_err();
+        assert!(format!("{err:#}").contains("failed to resume"), "{err:#}");
+        // The snapshot itself is still usable.
+        load(&path).await.unwrap();
+    }
+
+    #[async_test]
+    async fn test_bad_snapshot() {
+        let mut stream = futures::io::Cursor::new(b"OVMMMIG1".to_vec());
+        assert!(read_vm(&mut stream).await.is_err());
+
+        // A snapshot without a saved state is incomplete.
+        let mut data = MAGIC.to_vec();
+        data.extend(0u32.to_le_bytes());
+        let mut stream = futures::io::Cursor::new(data);
+        assert!(read_vm(&mut stream).await.is_err());
+
+        // RAM outside of the snapshot's ranges is rejected.
+        let mut data = MAGIC.to_vec();
+        data.extend(1u32.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.extend(0x1000u64.to_le_bytes());
+        data.push(RECORD_RAM);
+        data.extend(0x1000u64.to_le_bytes());
+        data.extend(0x1000u32.to_le_bytes());
+        data.extend([0; 0x1000]);
+        let mut stream = futures::io::Cursor::new(data);
+        assert!(read_vm(&mut stream).await.is_err());
+    }
+
+    #[async_test]
+    async fn test_bad_snapshot_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vm.snapshot");
+        std::fs::write(&path, FILE_MAGIC).unwrap();
+        assert!(load(&path).await.is_err());
+
+        // The footer's ranges and saved state must account for the rest of
+        // the file.
+        let mut data = vec![0; 0x1000];
+        data.extend(0u64.to_le_bytes());
+        data.extend(0x2000u64.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.extend(FILE_MAGIC);
+        std::fs::write(&path, data).unwrap();
+        assert!(load(&path).await.is_err());
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Periodic dumps of the VM's device saved state, for `--periodic-state-dump`.
+//!
+//! Each dump briefly pauses the VM, saves the state of every device, and
+//! resumes the VM before writing the state to disk, so the VM is only stopped
+//! for as long as the devices take to save. Guest RAM is not included.
+//!
+//! Dumps are written to `state-<unix time>.bin` in the dump directory, as an
+//! encoded `openvmm.SavedState` message, which can be decoded with `protoc`
+//! and the `.proto` files written by `--write-saved-state-proto`. Each file is
+//! written under a temporary name and then renamed, so a crash mid-write never
+//! leaves a truncated dump, and only the most recent [`MAX_DUMPS`] are kept.
+
+use anyhow::Context as _;
+use hvlite_defs::rpc::VmRpc;
+use mesh::rpc::RpcSend;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The number of dumps to keep in the dump directory.
+const MAX_DUMPS: usize = 3;
+
+const PREFIX: &str = "state-";
+const EXTENSION: &str = "bin";
+
+/// Saves the VM's device state to a new dump in `dir`, returning its path.
+///
+/// The VM is paused while saving, and resumed afterward if it was running.
+pub async fn write_state_dump(vm_rpc: &mesh::Sender<VmRpc>, dir: &Path) -> anyhow::Result<PathBuf> {
+    let running = vm_rpc.call(VmRpc::Pause, ()).await?;
+    let state = vm_rpc.call_failable(VmRpc::Save, ()).await;
+    if running {
+        vm_rpc.call(VmRpc::Resume, ()).await?;
+    }
+    let state = mesh::payload::encode(state.context("failed to save vm state")?);
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    fs_err::create_dir_all(dir)?;
+    let path = dir.join(format!("{PREFIX}{timestamp}.{EXTENSION}"));
+    let temp_path = path.with_extension("tmp");
+    fs_err::write(&temp_path, &state)?;
+    fs_err::rename(&temp_path, &path)?;
+
+    prune_dumps(dir)?;
+    Ok(path)
+}
+
+/// Removes all but the most recent [`MAX_DUMPS`] dumps from `dir`.
+fn prune_dumps(dir: &Path) -> anyhow::Result<()> {
+    let mut dumps = Vec::new();
+    for entry in fs_err::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(timestamp) = dump_timestamp(&path) {
+            dumps.push((timestamp, path));
+        }
+    }
+    dumps.sort();
+    let excess = dumps.len().saturating_sub(MAX_DUMPS);
+    for (_, path) in dumps.drain(..excess) {
+        fs_err::remove_file(&path).context("failed to remove old state dump")?;
+    }
+    Ok(())
+}
+
+/// Returns the timestamp in the name of the dump at `path`, or `None` if it is
+/// not a dump.
+fn dump_timestamp(path: &Path) -> Option<u64> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix(PREFIX)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dump_timestamp;
+    use std::path::Path;
+
+    #[test]
+    fn test_dump_timestamp() {
+        assert_eq!(dump_timestamp(Path::new("d/state-1234.bin")), Some(1234));
+        assert_eq!(dump_timestamp(Path::new("d/state-1234.tmp")), None);
+        assert_eq!(dump_timestamp(Path::new("d/state-x.bin")), None);
+        assert_eq!(dump_timestamp(Path::new("d/other-1234.bin")), None);
+    }
+}
@@ -8,6 +8,11 @@
 use crate::cli_args::UnderhillDiskSource;
 use crate::disk_open;
 use anyhow::Context;
+use disk_backend_resources::SnapshotDiskHandle;
+use disk_backend_resources::SnapshotDiskRequest;
+use disk_backend_resources::layer::RamDiskLayerHandle;
+use disk_backend_resources::layer::SqliteDiskLayerFormatParams;
+use disk_backend_resources::layer::SqliteDiskLayerHandle;
 use guid::Guid;
 use hvlite_defs::config::Config;
 use hvlite_defs::config::DeviceVtl;
@@ -20,10 +25,16 @@
 use nvme_resources::NvmeControllerHandle;
 use scsidisk_resources::SimpleScsiDiskHandle;
 use scsidisk_resources::SimpleScsiDvdHandle;
+use std::collections::HashMap;
+use std::path::Path;
 use storvsp_resources::ScsiControllerHandle;
 use storvsp_resources::ScsiDeviceAndPath;
 use storvsp_resources::ScsiPath;
+use usb_resources::UsbStorageHandle;
 use vm_resource::IntoResource;
+use vm_resource::Resource;
+use vm_resource::kind::DiskHandleKind;
+use vm_resource::kind::DiskLayerHandleKind;
 use vtl2_settings_proto::Lun;
 use vtl2_settings_proto::StorageController;
 use vtl2_settings_proto::storage_controller;
@@ -31,12 +42,18 @@
 pub(super) struct StorageBuilder {
     vtl0_ide_disks: Vec<IdeDeviceConfig>,
     vtl0_scsi_devices: Vec<ScsiDeviceAndPath>,
+    /// The LUN of each VTL0 SCSI device, and whether it is a DVD.
+    vtl0_scsi_luns: Vec<(u8, bool)>,
     vtl2_scsi_devices: Vec<ScsiDeviceAndPath>,
     vtl0_nvme_namespaces: Vec<NamespaceDefinition>,
     vtl2_nvme_namespaces: Vec<NamespaceDefinition>,
+    vtl0_usb_devices: Vec<UsbStorageHandle>,
     underhill_scsi_luns: Vec<Lun>,
     underhill_nvme_luns: Vec<Lun>,
     openhcl_vtl: Option<DeviceVtl>,
+    /// Snapshot request channels for writable VTL0 SCSI disks, by LUN, if
+    /// snapshots are enabled.
+    vtl0_scsi_snapshots: Option<HashMap<u8, mesh::Sender<SnapshotDiskRequest>>>,
 }
 
 #[derive(Copy, Clone)]
@@ -44,6 +61,7 @@ pub enum DiskLocation {
     Ide(Option<u8>, Option<u8>),
     Scsi(Option<u8>),
     Nvme(Option<u32>),
+    Usb,
 }
 
 impl From<UnderhillDiskSource> for DiskLocation {
@@ -69,19 +87,33 @@ pub fn new(openhcl_vtl: Option<DeviceVtl>) -> Self {
         Self {
             vtl0_ide_disks: Vec::new(),
             vtl0_scsi_devices: Vec::new(),
+            vtl0_scsi_luns: Vec::new(),
             vtl2_scsi_devices: Vec::new(),
             vtl0_nvme_namespaces: Vec::new(),
             vtl2_nvme_namespaces: Vec::new(),
+            vtl0_usb_devices: Vec::new(),
             underhill_scsi_luns: Vec::new(),
             underhill_nvme_luns: Vec::new(),
             openhcl_vtl,
+            vtl0_scsi_snapshots: None,
         }
     }
 
+    /// Makes writable VTL0 SCSI disks support snapshots while the VM runs.
+    pub fn enable_snapshots(&mut self) {
+        self.vtl0_scsi_snapshots = Some(HashMap::new());
+    }
+
     pub fn has_vtl0_nvme(&self) -> bool {
         !self.vtl0_nvme_namespaces.is_empty() || !self.underhill_nvme_luns.is_empty()
     }
 
+    /// Returns the LUNs of the VTL0 SCSI devices, in the order they were
+    /// added, along with whether each is a DVD.
+    pub fn vtl0_scsi_luns(&self) -> &[(u8, bool)] {
+        &self.vtl0_scsi_luns
+    }
+
     pub fn add(
         &mut self,
         vtl: DeviceVtl,
@@ -113,6 +145,18 @@ fn add_inner(
         read_only: bool,
     ) -> anyhow::Result<Option<u32>> {
         let disk = disk_open(kind, read_only || is_dvd)?;
+        self.add_disk(vtl, target, disk, is_dvd, read_only)
+    }
+
+    /// Adds an already-opened disk at `target`.
+    pub fn add_disk(
+        &mut self,
+        vtl: DeviceVtl,
+        target: DiskLocation,
+        mut disk: Resource<DiskHandleKind>,
+        is_dvd: bool,
+        read_only: bool,
+    ) -> anyhow::Result<Option<u32>> {
         let location = match target {
             DiskLocation::Ide(channel, device) => {
                 let guest_media = if is_dvd {
@@ -158,6 +202,19 @@ fn add_inner(
                 None
             }
             DiskLocation::Scsi(lun) => {
+                let devices = match vtl {
+                    DeviceVtl::Vtl0 => &mut self.vtl0_scsi_devices,
+                    DeviceVtl::Vtl1 => anyhow::bail!("vtl1 unsupported"),
+                    DeviceVtl::Vtl2 => &mut self.vtl2_scsi_devices,
+                };
+                let lun = lun.unwrap_or(devices.len() as u8);
+                if let Some(snapshots) = &mut self.vtl0_scsi_snapshots {
+                    if vtl == DeviceVtl::Vtl0 && !is_dvd && !read_only {
+                        let send;
+                        (disk, send) = snapshot_disk_handle(disk);
+                        snapshots.insert(lun, send);
+                    }
+                }
                 let device = if is_dvd {
                     SimpleScsiDvdHandle {
                         media: Some(disk),
@@ -172,12 +229,9 @@ fn add_inner(
                     }
                     .into_resource()
                 };
-                let devices = match vtl {
-                    DeviceVtl::Vtl0 => &mut self.vtl0_scsi_devices,
-                    DeviceVtl::Vtl1 => anyhow::bail!("vtl1 unsupported"),
-                    DeviceVtl::Vtl2 => &mut self.vtl2_scsi_devices,
-                };
-                let lun = lun.unwrap_or(devices.len() as u8);
+                if vtl == DeviceVtl::Vtl0 {
+                    self.vtl0_scsi_luns.push((lun, is_dvd));
+                }
                 devices.push(ScsiDeviceAndPath {
                     path: ScsiPath {
                         path: 0,
@@ -205,6 +259,27 @@ fn add_inner(
                 });
                 Some(nsid)
             }
+            DiskLocation::Usb => {
+                if vtl != DeviceVtl::Vtl0 {
+                    anyhow::bail!("usb only supported for VTL0");
+                }
+                let device = if is_dvd {
+                    SimpleScsiDvdHandle {
+                        media: Some(disk),
+                        requests: None,
+                    }
+                    .into_resource()
+                } else {
+                    SimpleScsiDiskHandle {
+                        disk,
+                        read_only,
+                        parameters: Default::default(),
+                    }
+                    .into_resource()
+                };
+                self.vtl0_usb_devices.push(UsbStorageHandle { device });
+                None
+            }
         };
         Ok(location)
     }
@@ -224,6 +299,7 @@ fn add_underhill(
 
         let (device_type, device_path) = match source {
             DiskLocation::Ide(_, _) => anyhow::bail!("ide source not supported for Underhill"),
+            DiskLocation::Usb => anyhow::bail!("usb source not supported for Underhill"),
             DiskLocation::Scsi(_) => (
                 vtl2_settings_proto::physical_device::DeviceType::Vscsi,
                 if vtl == DeviceVtl::Vtl2 {
@@ -247,6 +323,7 @@ fn add_underhill(
             DiskLocation::Ide(_, _) => {
                 anyhow::bail!("ide target currently not supported for Underhill (no PCAT support)")
             }
+            DiskLocation::Usb => anyhow::bail!("usb target not supported for Underhill"),
             DiskLocation::Scsi(lun) => {
                 let lun = lun.unwrap_or(self.underhill_scsi_luns.len() as u8);
                 (&mut self.underhill_scsi_luns, lun.into())
@@ -257,30 +334,37 @@ fn add_underhill(
             }
         };
 
-        luns.push(Lun {
+        luns.push(underhill_lun(
             location,
-            device_id: Guid::new_random().to_string(),
-            vendor_id: "OpenVMM".to_string(),
-            product_id: "Disk".to_string(),
-            product_revision_level: "1.0".to_string(),
-            serial_number: "0".to_string(),
-            model_number: "1".to_string(),
-            physical_devices: Some(vtl2_settings_proto::PhysicalDevices {
-                r#type: vtl2_settings_proto::physical_devices::BackingType::Single.into(),
-                device: Some(vtl2_settings_proto::PhysicalDevice {
-                    device_type: device_type.into(),
-                    device_path: device_path.to_string(),
-                    sub_device_path,
-                }),
-                devices: Vec::new(),
-            }),
+            device_type,
+            device_path,
+            sub_device_path,
             is_dvd,
-            ..Default::default()
-        });
+        ));
 
         Ok(())
     }
 
+    /// Has OpenHCL relay namespace `nsid` of the NVMe controller assigned to
+    /// VTL2 with VPCI instance ID `instance_id` to VTL0 as a SCSI disk.
+    #[cfg(target_os = "linux")]
+    pub fn add_underhill_assigned_nvme(
+        &mut self,
+        instance_id: Guid,
+        nsid: u32,
+    ) -> anyhow::Result<()> {
+        self.openhcl_vtl.context("openhcl not configured")?;
+        let lun = self.underhill_scsi_luns.len() as u32;
+        self.underhill_scsi_luns.push(underhill_lun(
+            lun,
+            vtl2_settings_proto::physical_device::DeviceType::Nvme,
+            instance_id,
+            nsid,
+            false,
+        ));
+        Ok(())
+    }
+
     pub fn build_config(
         &mut self,
         config: &mut Config,
@@ -288,6 +372,11 @@ pub fn build_config(
         scsi_sub_channels: u16,
     ) -> anyhow::Result<()> {
         config.ide_disks.append(&mut self.vtl0_ide_disks);
+        config.usb_devices.extend(
+            self.vtl0_usb_devices
+                .drain(..)
+                .map(|device| device.into_resource()),
+        );
 
         // Add an empty VTL0 SCSI controller even if there are no configured disks.
         if !self.vtl0_scsi_devices.is_empty() || config.vmbus.is_some() {
@@ -305,6 +394,7 @@ pub fn build_config(
             ));
             resources.scsi_rpc = Some(send);
         }
+        resources.scsi_snapshots = self.vtl0_scsi_snapshots.take();
 
         if !self.vtl2_scsi_devices.is_empty() {
             if config
@@ -329,6 +419,7 @@ pub fn build_config(
         }
 
         if !self.vtl0_nvme_namespaces.is_empty() {
+            let (send, recv) = mesh::channel();
             config.vpci_devices.push(VpciDeviceConfig {
                 vtl: DeviceVtl::Vtl0,
                 instance_id: NVME_VTL0_INSTANCE_ID,
@@ -337,9 +428,11 @@ pub fn build_config(
                     namespaces: std::mem::take(&mut self.vtl0_nvme_namespaces),
                     max_io_queues: 64,
                     msix_count: 64,
+                    requests: Some(recv),
                 }
                 .into_resource(),
             });
+            resources.nvme_rpc = Some(send);
 
             // Tell UEFI to try to enumerate VPCI devices since there might be
             // an NVMe namespace to boot from.
@@ -361,6 +454,7 @@ pub fn build_config(
             {
                 anyhow::bail!("must specify --vtl2 and --no-alias-map to offer disks to VTL2");
             }
+            let (send, recv) = mesh::channel();
             config.vpci_devices.push(VpciDeviceConfig {
                 vtl: DeviceVtl::Vtl2,
                 instance_id: NVME_VTL2_INSTANCE_ID,
@@ -369,9 +463,11 @@ pub fn build_config(
                     namespaces: std::mem::take(&mut self.vtl2_nvme_namespaces),
                     max_io_queues: 64,
                     msix_count: 64,
+                    requests: Some(recv),
                 }
                 .into_resource(),
             });
+            resources.nvme_vtl2_rpc = Some(send);
         }
 
         Ok(())
@@ -402,3 +498,70 @@ pub fn build_underhill(&self) -> Vec<StorageController> {
         storage_controllers
     }
 }
+
+/// Wraps `disk` so that snapshots can be taken of it while the VM runs,
+/// returning the channel for snapshot requests.
+pub(crate) fn snapshot_disk_handle(
+    disk: Resource<DiskHandleKind>,
+) -> (Resource<DiskHandleKind>, mesh::Sender<SnapshotDiskRequest>) {
+    let (send, recv) = mesh::channel();
+    let disk = SnapshotDiskHandle {
+        disk,
+        requests: recv,
+    }
+    .into_resource();
+    (disk, send)
+}
+
+/// Returns the diff layer to receive a disk's writes after a snapshot: a new
+/// sqlite file at `diff_path`, or memory if there is no path.
+pub(crate) fn snapshot_diff_layer(
+    diff_path: Option<&Path>,
+) -> anyhow::Result<Resource<DiskLayerHandleKind>> {
+    let Some(diff_path) = diff_path else {
+        return Ok(RamDiskLayerHandle { len: None }.into_resource());
+    };
+    if diff_path.exists() {
+        anyhow::bail!(
+            "cannot create diff layer at {} - file already exists",
+            diff_path.display()
+        );
+    }
+    Ok(SqliteDiskLayerHandle {
+        dbhd_path: diff_path.to_string_lossy().into_owned(),
+        format_dbhd: Some(SqliteDiskLayerFormatParams {
+            logically_read_only: false,
+            len: None,
+        }),
+    }
+    .into_resource())
+}
+
+fn underhill_lun(
+    location: u32,
+    device_type: vtl2_settings_proto::physical_device::DeviceType,
+    device_path: Guid,
+    sub_device_path: u32,
+    is_dvd: bool,
+) -> Lun {
+    Lun {
+        location,
+        device_id: Guid::new_random().to_string(),
+        vendor_id: "OpenVMM".to_string(),
+        product_id: "Disk".to_string(),
+        product_revision_level: "1.0".to_string(),
+        serial_number: "0".to_string(),
+        model_number: "1".to_string(),
+        physical_devices: Some(vtl2_settings_proto::PhysicalDevices {
+            r#type: vtl2_settings_proto::physical_devices::BackingType::Single.into(),
+            device: Some(vtl2_settings_proto::PhysicalDevice {
+                device_type: device_type.into(),
+                device_path: device_path.to_string(),
+                sub_device_path,
+            }),
+            devices: Vec::new(),
+        }),
+        is_dvd,
+        ..Default::default()
+    }
+}
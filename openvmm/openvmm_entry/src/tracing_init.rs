@@ -1,12 +1,46 @@
-// Copyright (c) Microsoft Corporation.
-// Licensed under the MIT License.
-
+use crate::cli_args::LogFormat;
+use crate::otlp;
 use anyhow::Context as _;
 use anyhow::anyhow;
+use clap::ValueEnum;
+use futures::FutureExt;
+use inspect::Inspect;
+use mesh::CellUpdater;
+use parking_lot::Mutex;
+use serde_json::Value;
+use serde_json::json;
 use std::io::IsTerminal;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use tracing::Event;
+use tracing::Subscriber;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormatEvent;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::FormattedFields;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::fmt::format::Format;
+use tracing_subscriber::fmt::format::Full;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::time::SystemTime;
+use tracing_subscriber::fmt::time::Uptime;
 use tracing_subscriber::fmt::time::uptime;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::reload;
+
+/// The environment variable selecting the log format, which is also used to
+/// pass the format to worker processes.
+const LOG_FORMAT_ENV: &str = "OPENVMM_LOG_FORMAT";
+
+/// Enable tracing for paravisor_log by default since this is passed through
+/// from the guest (but still allow it to be disabled via OPENVMM_LOG).
+const BASE_FILTER: &str = "paravisor_log=trace";
 
 /// Reads an environment variable, falling back to a legacy variable (replacing
 /// "OPENVMM_" with "HVLITE_") if the original is not set.
@@ -19,22 +53,24 @@ fn legacy_openvmm_env(name: &str) -> Result<String, std::env::VarError> {
     })
 }
 
-/// Enables tracing output to stderr.
-pub fn enable_tracing() -> anyhow::Result<()> {
+fn parse_filter(filter: &str) -> anyhow::Result<EnvFilter> {
+    Ok(EnvFilter::try_new(format!("{BASE_FILTER},{filter}"))?)
+}
+
+/// Enables tracing output to stderr, and export to an OpenTelemetry collector
+/// if `OPENVMM_OTLP_ENDPOINT` is set.
+pub fn enable_tracing() -> anyhow::Result<LogControl> {
     use tracing_subscriber::fmt::writer::BoxMakeWriter;
     use tracing_subscriber::layer::SubscriberExt;
     use tracing_subscriber::util::SubscriberInitExt;
 
-    // Enable tracing for paravisor_log by default since this is passed through
-    // from the guest (but still allow it to be disabled via OPENVMM_LOG).
-    let base = "paravisor_log=trace";
-    let filter = if let Ok(filter) = legacy_openvmm_env("OPENVMM_LOG") {
-        tracing_subscriber::EnvFilter::try_new(format!("{base},{filter}"))
-            .context("invalid OPENVMM_LOG")?
-    } else {
-        tracing_subscriber::EnvFilter::default()
-            .add_directive(tracing::metadata::LevelFilter::INFO.into())
-            .add_directive(base.parse().unwrap())
+    let filter_string = legacy_openvmm_env("OPENVMM_LOG").unwrap_or_else(|_| "info".into());
+    let filter = parse_filter(&filter_string).context("invalid OPENVMM_LOG")?;
+
+    let format = match legacy_openvmm_env(LOG_FORMAT_ENV) {
+        Ok(format) => LogFormat::from_str(&format, true)
+            .map_err(|err| anyhow!(err).context("invalid OPENVMM_LOG_FORMAT"))?,
+        Err(_) => LogFormat::Text,
     };
 
     if legacy_openvmm_env("OPENVMM_DISABLE_TRACING_RATELIMITS").is_ok_and(|v| !v.is_empty()) {
@@ -49,9 +85,13 @@ pub fn enable_tracing() -> anyhow::Result<()> {
         BoxMakeWriter::new(std::io::stderr)
     };
 
-    let format = Format::default()
-        .with_timer(uptime())
-        .with_ansi(is_terminal);
+    let json = Arc::new(AtomicBool::new(format == LogFormat::Json));
+    let format = EventFormat {
+        text: Format::default()
+            .with_timer(uptime())
+            .with_ansi(is_terminal),
+        json: json.clone(),
+    };
     let fmt_layer = tracing_subscriber::fmt::layer()
         .event_format(format)
         .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
@@ -59,14 +99,10 @@ pub fn enable_tracing() -> anyhow::Result<()> {
         .log_internal_errors(true)
         .with_writer(writer);
 
-    let sub = tracing_subscriber::Registry::default()
-        .with(fmt_layer)
-        .with(filter);
-
     // Enable an ETW layer on Windows.
     // TODO: include the process name and maybe a VM ID?
     #[cfg(windows)]
-    let sub = sub.with(
+    let fmt_layer = fmt_layer.and_then(
         win_etw_tracing::TracelogSubscriber::new(
             winapi::shared::guiddef::GUID::from(
                 "22bc55fe-2116-5adc-12fb-3fadfd7e360c"
@@ -78,8 +114,248 @@ pub fn enable_tracing() -> anyhow::Result<()> {
         .map_err(|e| anyhow!("failed to start ETW provider: {:?}", e))?,
     );
 
+    // The exporter has its own filter, so that verbose spans (such as for each
+    // VM exit) can be exported without also being logged.
+    let otlp_layer = if let Ok(endpoint) = legacy_openvmm_env(otlp::ENDPOINT_ENV) {
+        let filter = if let Ok(filter) = legacy_openvmm_env("OPENVMM_OTLP_LOG") {
+            EnvFilter::try_new(filter).context("invalid OPENVMM_OTLP_LOG")?
+        } else {
+            EnvFilter::default().add_directive(tracing::metadata::LevelFilter::INFO.into())
+        };
+        Some(otlp::OtlpLayer::new(&endpoint)?.with_filter(filter))
+    } else {
+        None
+    };
+
+    let (fmt_layer, reload_handle) = reload::Layer::new(fmt_layer.with_filter(filter));
+
+    let sub = tracing_subscriber::Registry::default()
+        .with(fmt_layer)
+        .with(otlp_layer);
+
     sub.try_init()
         .map_err(|e| anyhow!(e).context("failed to enable tracing"))?;
 
-    Ok(())
+    Ok(LogControl {
+        json,
+        filter: Arc::new(Mutex::new(filter_string)),
+        reload_filter: Arc::new(move |filter| {
+            reload_handle.modify(|layer| *layer.filter_mut() = filter)
+        }),
+    })
+}
+
+/// Controls the log output of this process after tracing is enabled.
+#[derive(Clone)]
+pub struct LogControl {
+    json: Arc<AtomicBool>,
+    filter: Arc<Mutex<String>>,
+    reload_filter: Arc<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>,
+}
+
+impl LogControl {
+    /// Returns the log format.
+    pub fn format(&self) -> LogFormat {
+        if self.json.load(Ordering::Relaxed) {
+            LogFormat::Json
+        } else {
+            LogFormat::Text
+        }
+    }
+
+    /// Sets the log format.
+    pub fn set_format(&self, format: LogFormat) {
+        self.json
+            .store(format == LogFormat::Json, Ordering::Relaxed);
+    }
+
+    /// Returns the log filter, in `OPENVMM_LOG` syntax.
+    pub fn filter(&self) -> String {
+        self.filter.lock().clone()
+    }
+
+    /// Sets the log filter, in `OPENVMM_LOG` syntax.
+    pub fn set_filter(&self, filter: &str) -> anyhow::Result<()> {
+        let new_filter = parse_filter(filter).context("invalid filter")?;
+        (self.reload_filter)(new_filter).context("failed to update filter")?;
+        *self.filter.lock() = filter.to_owned();
+        Ok(())
+    }
+
+    /// Returns the environment variables that pass the log format to a worker
+    /// process.
+    pub fn child_env(&self) -> Vec<(&'static str, String)> {
+        let format = match self.format() {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        };
+        vec![(LOG_FORMAT_ENV, format.into())]
+    }
+
+    /// Follows the log filter of the process that launched this one, until
+    /// the process exits.
+    pub async fn follow_filter(self, mut filter: mesh::Cell<String>) {
+        loop {
+            filter.with(|filter| {
+                if *filter == self.filter() {
+                    return;
+                }
+                if let Err(err) = self.set_filter(filter) {
+                    tracing::error!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "failed to update log filter"
+                    );
+                } else {
+                    tracing::info!(filter = filter.as_str(), "updated log filter");
+                }
+            });
+            filter.wait_next().await;
+        }
+    }
+}
+
+/// The log filter of every process in the mesh, which can be read and updated
+/// through inspect.
+pub struct LogFilter {
+    control: LogControl,
+    updater: Mutex<CellUpdater<String>>,
+}
+
+impl LogFilter {
+    pub fn new(control: LogControl) -> Self {
+        let filter = control.filter();
+        Self {
+            control,
+            updater: Mutex::new(CellUpdater::new(filter)),
+        }
+    }
+
+    /// Returns the log format.
+    pub fn format(&self) -> LogFormat {
+        self.control.format()
+    }
+
+    /// Returns the environment variables that pass the log format to a worker
+    /// process.
+    pub fn child_env(&self) -> Vec<(&'static str, String)> {
+        self.control.child_env()
+    }
+
+    /// Returns a cell for a worker process to follow the filter with
+    /// [`LogControl::follow_filter`].
+    pub fn cell(&self) -> mesh::Cell<String> {
+        self.updater.lock().cell()
+    }
+}
+
+impl Inspect for LogFilter {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        match req.update() {
+            Ok(req) => {
+                let filter = req.new_value().to_owned();
+                match self.control.set_filter(&filter) {
+                    Ok(()) => {
+                        self.updater.lock().set(filter.clone()).now_or_never();
+                        tracing::info!(filter = filter.as_str(), "updated log filter");
+                        req.succeed(filter);
+                    }
+                    Err(err) => req.fail(err),
+                }
+            }
+            Err(req) => req.value(self.control.filter()),
+        }
+    }
+}
+
+/// Formats events as text, or as one JSON object per line.
+struct EventFormat {
+    text: Format<Full, Uptime>,
+    json: Arc<AtomicBool>,
+}
+
+impl<S, N> FormatEvent<S, N> for EventFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        if !self.json.load(Ordering::Relaxed) {
+            return self.text.format_event(ctx, writer, event);
+        }
+
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut visitor = JsonVisitor(serde_json::Map::new());
+        event.record(&mut visitor);
+        // Span fields have already been formatted as text by the field
+        // formatter.
+        let spans = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .map_or("", |fields| fields.fields.as_str());
+                json!({ "name": span.name(), "fields": fields })
+            })
+            .collect::<Vec<_>>();
+        let metadata = event.metadata();
+        let line = json!({
+            "timestamp": timestamp,
+            "pid": std::process::id(),
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "spans": spans,
+            "fields": visitor.0,
+        });
+        writeln!(writer, "{line}")
+    }
+}
+
+struct JsonVisitor(serde_json::Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        // Include the full error source chain.
+        let mut message = value.to_string();
+        let mut source = value.source();
+        while let Some(err) = source {
+            message += ": ";
+            message += &err.to_string();
+            source = err.source();
+        }
+        self.0.insert(field.name().into(), message.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
 }
@@ -0,0 +1,173 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VM lifecycle event log returned by `WatchVM`.
+
+use hvlite_ttrpc_vmservice as vmservice;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+use vmm_core_defs::HaltReason;
+
+/// The number of events kept for clients that have not received them yet.
+const MAX_EVENTS: usize = 1024;
+
+pub struct VmEvents {
+    state: Mutex<State>,
+}
+
+struct State {
+    events: VecDeque<vmservice::VmEvent>,
+    next_sequence: u64,
+    waiters: Vec<mesh::OneshotSender<()>>,
+    closed: bool,
+}
+
+impl VmEvents {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                events: VecDeque::new(),
+                next_sequence: 1,
+                waiters: Vec::new(),
+                closed: false,
+            }),
+        }
+    }
+
+    /// Records an event, waking any waiters.
+    pub fn push(&self, event_type: vmservice::VmEventType) {
+        self.push_event(vmservice::VmEvent {
+            r#type: event_type.into(),
+            ..Default::default()
+        });
+    }
+
+    /// Records that the guest halted.
+    pub fn push_halt(&self, reason: &HaltReason) {
+        let (halt_reason, vp) = match *reason {
+            HaltReason::PowerOff => (vmservice::HaltReason::PowerOff, None),
+            HaltReason::Reset => (vmservice::HaltReason::Reset, None),
+            HaltReason::Hibernate => (vmservice::HaltReason::Hibernate, None),
+            HaltReason::Suspend => (vmservice::HaltReason::Suspend, None),
+            HaltReason::DebugBreak { vp } => (vmservice::HaltReason::DebugBreak, vp),
+            HaltReason::TripleFault { vp, .. } => (vmservice::HaltReason::TripleFault, Some(vp)),
+            HaltReason::InvalidVmState { vp } => (vmservice::HaltReason::InvalidVmState, Some(vp)),
+            HaltReason::VpError { vp } => (vmservice::HaltReason::VpError, Some(vp)),
+            HaltReason::SingleStep { vp } => (vmservice::HaltReason::SingleStep, Some(vp)),
+            HaltReason::HwBreakpoint { vp, .. } => (vmservice::HaltReason::HwBreakpoint, Some(vp)),
+        };
+        self.push_event(vmservice::VmEvent {
+            r#type: vmservice::VmEventType::Halted.into(),
+            halt_reason: halt_reason.into(),
+            has_vp: vp.is_some(),
+            vp: vp.unwrap_or(0),
+            ..Default::default()
+        });
+    }
+
+    fn push_event(&self, mut event: vmservice::VmEvent) {
+        let mut state = self.state.lock();
+        event.sequence = state.next_sequence;
+        event.timestamp_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        state.next_sequence += 1;
+        if state.events.len() == MAX_EVENTS {
+            state.events.pop_front();
+        }
+        state.events.push_back(event);
+        for waiter in state.waiters.drain(..) {
+            waiter.send(());
+        }
+    }
+
+    /// Wakes all waiters and makes future waits return immediately, so that
+    /// outstanding `WatchVM` calls complete when the service shuts down.
+    pub fn close(&self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+        for waiter in state.waiters.drain(..) {
+            waiter.send(());
+        }
+    }
+
+    /// Returns the sequence number of the most recent event, or 0 if there
+    /// have been none.
+    pub fn last_sequence(&self) -> u64 {
+        self.state.lock().next_sequence - 1
+    }
+
+    /// Waits for events after `after_sequence` and returns them.
+    ///
+    /// Returns no events if the log has been closed.
+    pub async fn wait(&self, after_sequence: u64) -> vmservice::WatchVmResponse {
+        loop {
+            let recv = {
+                let mut state = self.state.lock();
+                let events = state
+                    .events
+                    .iter()
+                    .filter(|e| e.sequence > after_sequence)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if !events.is_empty() || state.closed {
+                    let events_lost = state
+                        .events
+                        .front()
+                        .is_some_and(|e| e.sequence > after_sequence.saturating_add(1));
+                    return vmservice::WatchVmResponse {
+                        events,
+                        events_lost,
+                    };
+                }
+                let (send, recv) = mesh::oneshot();
+                state.waiters.push(send);
+                recv
+            };
+            let _ = recv.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_vm_events() {
+        let events = VmEvents::new();
+        assert!(events.wait(0).now_or_never().is_none());
+
+        events.push(vmservice::VmEventType::Created);
+        events.push_halt(&HaltReason::VpError { vp: 3 });
+        let response = block_on(events.wait(0));
+        assert!(!response.events_lost);
+        assert_eq!(
+            response
+                .events
+                .iter()
+                .map(|e| e.sequence)
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+        let halt = &response.events[1];
+        assert_eq!(halt.halt_reason, vmservice::HaltReason::VpError as i32);
+        assert!(halt.has_vp);
+        assert_eq!(halt.vp, 3);
+
+        assert!(events.wait(2).now_or_never().is_none());
+
+        for _ in 0..MAX_EVENTS {
+            events.push(vmservice::VmEventType::Running);
+        }
+        let response = block_on(events.wait(1));
+        assert!(response.events_lost);
+        assert_eq!(response.events.len(), MAX_EVENTS);
+
+        events.close();
+        assert!(block_on(events.wait(u64::MAX)).events.is_empty());
+    }
+}
@@ -3,20 +3,31 @@
 
 //! Worker for the prototype gRPC/ttrpc management endpoint.
 
+mod events;
+
+use self::events::VmEvents;
 use self::vmservice::nic_config::Backend;
+use crate::cli_args::DiskCliKind;
+use crate::disk_open;
 use crate::serial_io::bind_serial;
+use crate::snapshot;
+use crate::storage_builder::snapshot_diff_layer;
+use crate::storage_builder::snapshot_disk_handle;
 use anyhow::Context;
 use anyhow::anyhow;
 use anyhow::bail;
 use awaitgroup::WaitGroup;
+use disk_backend_resources::SnapshotDiskRequest;
 use futures::FutureExt;
 use futures::StreamExt;
+use futures::lock::Mutex as AsyncMutex;
 use guid::Guid;
 use hvlite_defs::config::Config;
 use hvlite_defs::config::DEFAULT_MMIO_GAPS_X86;
 use hvlite_defs::config::DeviceVtl;
 use hvlite_defs::config::HypervisorConfig;
 use hvlite_defs::config::LoadMode;
+use hvlite_defs::config::MemoryBackingFile;
 use hvlite_defs::config::MemoryConfig;
 use hvlite_defs::config::ProcessorTopologyConfig;
 use hvlite_defs::config::VirtioBus;
@@ -27,6 +38,10 @@
 use hvlite_defs::worker::VmWorkerParameters;
 use hvlite_helpers::disk::open_disk_type;
 use hvlite_ttrpc_vmservice as vmservice;
+use hyperv_ic_resources::shutdown::ShutdownParams;
+use hyperv_ic_resources::shutdown::ShutdownResult;
+use hyperv_ic_resources::shutdown::ShutdownRpc;
+use hyperv_ic_resources::vss::VssRpc;
 use inspect::Inspect;
 use inspect::InspectionBuilder;
 use inspect_proto::InspectResponse2;
@@ -43,13 +58,20 @@
 use mesh_worker::WorkerId;
 use mesh_worker::WorkerRpc;
 use netvsp_resources::NetvspHandle;
+use nvme_resources::NamespaceDefinition;
+use nvme_resources::NvmeControllerHandle;
+use nvme_resources::NvmeControllerRequest;
 use pal_async::DefaultDriver;
 use pal_async::DefaultPool;
 use pal_async::task::Spawn;
+use pal_async::timer::PolledTimer;
 use parking_lot::Mutex;
 use scsidisk_resources::SimpleScsiDiskHandle;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs::File;
 use std::future::Future;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use storvsp_resources::ScsiControllerHandle;
@@ -60,13 +82,31 @@
 use vm_manifest_builder::VmManifestBuilder;
 use vm_resource::IntoResource;
 use vm_resource::Resource;
+use vm_resource::kind::DiskHandleKind;
 use vm_resource::kind::VmbusDeviceHandleKind;
 use vmm_core_defs::HaltReason;
 
+const SCSI_INSTANCE_ID: Guid = guid::guid!("ba6163d9-04a1-4d29-b605-72e2ffb1dc7f");
+const NVME_INSTANCE_ID: Guid = guid::guid!("3ca4d4a8-8c2f-4f4e-b1c4-2f0d7f6f4b1e");
+const NVME_VTL2_INSTANCE_ID: Guid = guid::guid!("6e1d3f0c-5a0b-4c55-9f0e-8d6a6b1c2e47");
+
+/// The most guest memory that `ReadGuestMemory` reads at once.
+const MAX_GUEST_MEMORY_READ: u64 = 16 << 20;
+/// How much guest memory `SearchGuestMemory` reads at a time.
+const SEARCH_CHUNK_SIZE: u64 = 1 << 20;
+/// The most matches `SearchGuestMemory` returns if the request does not say.
+const DEFAULT_SEARCH_RESULTS: u32 = 1024;
+/// The number of SCSI controllers a VM can have, as in Hyper-V.
+const MAX_SCSI_CONTROLLERS: u32 = 4;
+/// How long `SnapshotDisks` waits for the guest to freeze or thaw its file
+/// systems if the request does not say.
+const DEFAULT_QUIESCE_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(mesh::MeshPayload)]
 pub struct Parameters {
     pub listener: UnixListener,
     pub transport: RpcTransport,
+    pub allow_guest_memory_access: bool,
 }
 
 #[derive(Copy, Clone, mesh::MeshPayload)]
@@ -95,6 +135,7 @@ enum ResolvedTransport {
 pub struct TtrpcWorker {
     listener: UnixListener,
     transport: ResolvedTransport,
+    allow_guest_memory_access: bool,
 }
 
 pub const TTRPC_WORKER: WorkerId<Parameters> = WorkerId::new("TtrpcWorker");
@@ -115,6 +156,7 @@ fn new(parameters: Self::Parameters) -> anyhow::Result<Self> {
                 #[allow(unreachable_patterns)]
                 transport => bail!("unsupported transport {transport}"),
             },
+            allow_guest_memory_access: parameters.allow_guest_memory_access,
         })
     }
 
@@ -130,6 +172,8 @@ fn run(self, recv: mesh::Receiver<WorkerRpc<Self::State>>) -> anyhow::Result<()>
                 worker_handle: None,
                 rpc_wait_group: WaitGroup::new(),
                 transport: self.transport,
+                events: Arc::new(VmEvents::new()),
+                allow_guest_memory_access: self.allow_guest_memory_access,
             };
             service.run(self.listener, recv).await?;
             Ok(())
@@ -222,6 +266,7 @@ async fn run(
         }
 
         // Drain any remaining RPCs.
+        self.events.close();
         self.rpc_wait_group.wait().await;
         if let Some(vm) = self.vm.take() {
             let _ = Arc::try_unwrap(vm).ok().expect("no more VM references");
@@ -258,8 +303,20 @@ fn start_rpc<F, R>(
 
 struct Vm {
     worker_rpc: mesh::Sender<VmRpc>,
-    scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
+    /// The SCSI controllers, by controller number. Held across changes to a
+    /// controller's disks, so that its snapshot channels match its disks.
+    scsi: AsyncMutex<HashMap<u32, ScsiController>>,
+    nvme_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
+    nvme_vtl2_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
     notify_recv: Mutex<Option<mesh::Receiver<HaltReason>>>,
+    shutdown_ic: mesh::Sender<ShutdownRpc>,
+    vss_ic: mesh::Sender<VssRpc>,
+}
+
+struct ScsiController {
+    rpc: mesh::Sender<ScsiControllerRequest>,
+    /// Snapshot request channels for writable disks, by LUN.
+    disk_snapshots: HashMap<u8, mesh::Sender<SnapshotDiskRequest>>,
 }
 
 struct VmService {
@@ -268,6 +325,8 @@ struct VmService {
     worker_handle: Option<mesh_worker::WorkerHandle>,
     rpc_wait_group: WaitGroup,
     transport: ResolvedTransport,
+    events: Arc<VmEvents>,
+    allow_guest_memory_access: bool,
 }
 
 fn grpc_error(err: anyhow::Error) -> Status {
@@ -309,6 +368,10 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                 response.send(map_grpc(self.teardown_vm().await))
             }
             vmservice::Vm::Quit((), response) => return HandleAction::Quit(response),
+            vmservice::Vm::WatchVm(request, response) => {
+                let r = Ok(self.watch_vm(ctx, request));
+                self.start_rpc(response, r);
+            }
             request => {
                 let vm = match &self.vm {
                     Some(vm) => vm.clone(),
@@ -334,6 +397,34 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                         let r = self.modify_resource(&vm, request);
                         self.start_rpc(response, r);
                     }
+                    vmservice::Vm::SnapshotDisks(request, response) => {
+                        let r = self.snapshot_disks(&vm, request);
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::SaveVm(request, response) => {
+                        let r = Ok(self.save_vm(&vm, request));
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::ShutdownVm(request, response) => {
+                        let r = Ok(self.shutdown_vm(ctx, vm, request));
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::ReadGuestMemory(request, response) => {
+                        let r = self.read_guest_memory(&vm, request);
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::WriteGuestMemory(request, response) => {
+                        let r = self.write_guest_memory(&vm, request);
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::SearchGuestMemory(request, response) => {
+                        let r = self.search_guest_memory(&vm, request);
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::TranslateGuestVirtualAddress(request, response) => {
+                        let r = self.translate_guest_virtual_address(&vm, request);
+                        self.start_rpc(response, r);
+                    }
 
                     r @ vmservice::Vm::CapabilitiesVm(_, _)
                     | r @ vmservice::Vm::PropertiesVm(_, _) => {
@@ -342,7 +433,8 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
 
                     vmservice::Vm::CreateVm(_, _)
                     | vmservice::Vm::TeardownVm(_, _)
-                    | vmservice::Vm::Quit(_, _) => unreachable!(),
+                    | vmservice::Vm::Quit(_, _)
+                    | vmservice::Vm::WatchVm(_, _) => unreachable!(),
                 };
             }
         }
@@ -452,6 +544,11 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
         .build()
         .context("failed to build vm configuration")?;
 
+        let memory_config = req_config
+            .memory_config
+            .as_ref()
+            .context("missing memory configuration")?;
+
         let mut config = Config {
             // TODO: devices, other stuff
             load_mode,
@@ -459,15 +556,21 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             floppy_disks: vec![],
             vpci_devices: vec![],
             memory: MemoryConfig {
-                mem_size: req_config
-                    .memory_config
-                    .as_ref()
-                    .context("missing memory configuration")?
+                mem_size: memory_config
                     .memory_mb
                     .checked_mul(0x100000)
                     .context("invalid memory configuration")?,
                 mmio_gaps: DEFAULT_MMIO_GAPS_X86.into(),
+                pci_ecam: None,
                 prefetch_memory: false,
+                hotplug_size: memory_config
+                    .hotplug_mb
+                    .checked_mul(0x100000)
+                    .context("invalid memory configuration")?,
+                balloon: memory_config.balloon,
+                hugetlb_page_size: None,
+                backing_file: None,
+                numa_policy: None,
             },
             chipset: chipset.chipset,
             processor_topology: ProcessorTopologyConfig {
@@ -493,6 +596,12 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             virtio_console_pci: false,
             virtio_serial: None,
             virtio_devices: vec![],
+            usb_devices: vec![],
+            e1000_nics: vec![],
+            pci_serial_cards: vec![],
+            ivshmem_devices: vec![],
+            nvme_sriov: None,
+            vfio_devices: vec![],
             vmbus: Some(VmbusConfig::default()),
             vtl2_vmbus: None,
             vmbus_devices: vec![],
@@ -502,25 +611,65 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             secure_boot_enabled: false,
             custom_uefi_vars: Default::default(),
             firmware_event_send: None,
+            guest_crash_send: None,
             debugger_rpc: None,
+            vtl2_debugger_rpc: None,
             chipset_devices: chipset.chipset_devices,
             generation_id_recv: None,
             rtc_delta_milliseconds: 0,
             automatic_guest_reset: true,
+            enable_s3: false,
+            enable_s4: false,
+            resume_from_hibernate: false,
+            pci_hotplug_slots: Vec::new(),
+            pci_slots: Vec::new(),
+            pci_bridges: 0,
+            pvpanic: false,
+            smbios: Default::default(),
+            vp_affinity: Vec::new(),
+            cpuid: Vec::new(),
+            io_trace: None,
+            access_trace: None,
         };
 
-        let mut scsi_rpc = None;
+        let (shutdown_ic, shutdown_recv) = mesh::channel();
+        config.vmbus_devices.push((
+            DeviceVtl::Vtl0,
+            hyperv_ic_resources::shutdown::ShutdownIcHandle {
+                recv: shutdown_recv,
+            }
+            .into_resource(),
+        ));
+
+        let (vss_ic, vss_recv) = mesh::channel();
+        config.vmbus_devices.push((
+            DeviceVtl::Vtl0,
+            hyperv_ic_resources::vss::VssIcHandle { recv: vss_recv }.into_resource(),
+        ));
+
+        let mut scsi = HashMap::new();
+        let mut nvme_rpc = None;
+        let mut nvme_vtl2_rpc = None;
         if let Some(devices_config) = req_config.devices_config {
-            if !devices_config.scsi_disks.is_empty() {
-                let mut devices = Vec::new();
-                for disk in devices_config.scsi_disks {
-                    devices.push(make_disk_config(disk)?);
+            let mut controllers = BTreeMap::<u32, (Vec<_>, HashMap<_, _>)>::new();
+            for disk in devices_config.scsi_disks {
+                let controller = disk.controller;
+                if controller >= MAX_SCSI_CONTROLLERS {
+                    bail!("controller must be less than {MAX_SCSI_CONTROLLERS}");
                 }
+                let (device, snapshot_send) = make_disk_config(disk)?;
+                let (devices, disk_snapshots) = controllers.entry(controller).or_default();
+                if let Some(send) = snapshot_send {
+                    disk_snapshots.insert(device.path.lun, send);
+                }
+                devices.push(device);
+            }
+            for (controller, (devices, disk_snapshots)) in controllers {
                 let (send, recv) = mesh::channel();
                 config.vmbus_devices.push((
                     DeviceVtl::Vtl0,
                     ScsiControllerHandle {
-                        instance_id: guid::guid!("ba6163d9-04a1-4d29-b605-72e2ffb1dc7f"),
+                        instance_id: scsi_instance_id(controller),
                         max_sub_channel_count: 0,
                         devices,
                         io_queue_depth: None,
@@ -528,7 +677,49 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                     }
                     .into_resource(),
                 ));
-                scsi_rpc = Some(send);
+                scsi.insert(
+                    controller,
+                    ScsiController {
+                        rpc: send,
+                        disk_snapshots,
+                    },
+                );
+            }
+
+            let (vtl2_nvme_disks, nvme_disks) = devices_config
+                .nvme_disks
+                .into_iter()
+                .partition::<Vec<_>, _>(|disk| disk.vtl2);
+            for (vtl, instance_id, disks, rpc) in [
+                (DeviceVtl::Vtl0, NVME_INSTANCE_ID, nvme_disks, &mut nvme_rpc),
+                (
+                    DeviceVtl::Vtl2,
+                    NVME_VTL2_INSTANCE_ID,
+                    vtl2_nvme_disks,
+                    &mut nvme_vtl2_rpc,
+                ),
+            ] {
+                if disks.is_empty() {
+                    continue;
+                }
+                let namespaces = disks
+                    .into_iter()
+                    .map(make_nvme_namespace)
+                    .collect::<anyhow::Result<_>>()?;
+                let (send, recv) = mesh::channel();
+                config.vpci_devices.push(VpciDeviceConfig {
+                    vtl,
+                    instance_id,
+                    resource: NvmeControllerHandle {
+                        subsystem_id: instance_id,
+                        namespaces,
+                        max_io_queues: 64,
+                        msix_count: 64,
+                        requests: Some(recv),
+                    }
+                    .into_resource(),
+                });
+                *rpc = Some(send);
             }
 
             for nic in devices_config.nic_config {
@@ -566,8 +757,34 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             config.vmbus.as_mut().unwrap().vsock_path = Some(hvsocket_config.path);
         }
 
+        let mut saved_state = None;
+        if !request.resume_path.is_empty() {
+            let saved = snapshot::load(Path::new(&request.resume_path))
+                .await
+                .context("failed to load vm snapshot")?;
+            config.memory.backing_file = Some(MemoryBackingFile {
+                file: saved.ram,
+                shared: false,
+            });
+            saved_state = Some(saved.saved_state);
+        }
+
         let (send, recv) = mesh::channel();
-        let (notify_send, notify_recv) = mesh::channel();
+        let (notify_send, mut halt_recv) = mesh::channel();
+
+        // Record halts as events before passing them on to `WaitVM`.
+        let (wait_send, notify_recv) = mesh::channel();
+        self.driver
+            .spawn("vm-halt-events", {
+                let events = self.events.clone();
+                async move {
+                    while let Some(reason) = halt_recv.next().await {
+                        events.push_halt(&reason);
+                        wait_send.send(reason);
+                    }
+                }
+            })
+            .detach();
 
         let (host, runner) = mesh_worker::worker_host();
         self.driver
@@ -580,7 +797,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                 VmWorkerParameters {
                     hypervisor: None,
                     cfg: config,
-                    saved_state: None,
+                    saved_state,
                     rpc: recv,
                     notify: notify_send,
                 },
@@ -589,10 +806,15 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
 
         self.worker_handle = Some(worker);
         self.vm = Some(Arc::new(Vm {
-            scsi_rpc,
+            scsi: AsyncMutex::new(scsi),
+            nvme_rpc,
+            nvme_vtl2_rpc,
             notify_recv: Mutex::new(Some(notify_recv)),
+            shutdown_ic,
+            vss_ic,
             worker_rpc: send,
         }));
+        self.events.push(vmservice::VmEventType::Created);
         Ok(())
     }
 
@@ -601,17 +823,159 @@ async fn teardown_vm(&mut self) -> anyhow::Result<()> {
         worker_handle.stop();
         worker_handle.join().await?;
         let _ = self.vm.take();
+        self.events.push(vmservice::VmEventType::TornDown);
         Ok(())
     }
 
     fn pause_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + use<> {
         let recv = vm.worker_rpc.call(VmRpc::Pause, ());
-        async move { recv.await.map(drop).context("pause failed") }
+        let events = self.events.clone();
+        async move {
+            if recv.await.context("pause failed")? {
+                events.push(vmservice::VmEventType::Paused);
+            }
+            Ok(())
+        }
     }
 
     fn resume_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + use<> {
         let recv = vm.worker_rpc.call(VmRpc::Resume, ());
-        async move { recv.await.map(drop).context("resume failed") }
+        let events = self.events.clone();
+        async move {
+            if recv.await.context("resume failed")? {
+                events.push(vmservice::VmEventType::Running);
+            }
+            Ok(())
+        }
+    }
+
+    fn shutdown_vm(
+        &mut self,
+        ctx: mesh::CancelContext,
+        vm: Arc<Vm>,
+        request: vmservice::ShutdownVmRequest,
+    ) -> impl Future<Output = anyhow::Result<vmservice::ShutdownVmResponse>> + use<> {
+        let driver = self.driver.clone();
+        let events = self.events.clone();
+        let reboot = request.r#type == vmservice::ShutdownType::Reboot as i32;
+        let mut timeout_ctx = if request.timeout_seconds == 0 {
+            ctx
+        } else {
+            ctx.with_timeout(Duration::from_secs(request.timeout_seconds.into()))
+        };
+        async move {
+            // Only halts after the request count towards completion.
+            let after_sequence = events.last_sequence();
+
+            // Retry until the guest's IC is ready to accept the request.
+            let acknowledged = timeout_ctx
+                .until_cancelled(async {
+                    loop {
+                        let params = ShutdownParams {
+                            shutdown_type: if reboot {
+                                hyperv_ic_resources::shutdown::ShutdownType::Reboot
+                            } else {
+                                hyperv_ic_resources::shutdown::ShutdownType::PowerOff
+                            },
+                            force: request.force,
+                        };
+                        match vm.shutdown_ic.call(ShutdownRpc::Shutdown, params).await? {
+                            ShutdownResult::Ok | ShutdownResult::AlreadyInProgress => {
+                                break anyhow::Ok(true);
+                            }
+                            ShutdownResult::NotReady => {
+                                PolledTimer::new(&driver)
+                                    .sleep(Duration::from_secs(1))
+                                    .await
+                            }
+                            ShutdownResult::Failed(status) => {
+                                tracing::info!(status, "guest rejected shutdown request");
+                                break Ok(false);
+                            }
+                        }
+                    }
+                })
+                .await;
+            let acknowledged = match acknowledged {
+                Ok(r) => r?,
+                Err(CancelReason::DeadlineExceeded) => false,
+                Err(reason) => return Err(reason.into()),
+            };
+
+            let completed = if reboot || !acknowledged {
+                acknowledged
+            } else {
+                let halted = timeout_ctx
+                    .until_cancelled(async {
+                        let mut after_sequence = after_sequence;
+                        loop {
+                            let response = events.wait(after_sequence).await;
+                            let Some(last) = response.events.last() else {
+                                // The service is shutting down.
+                                break false;
+                            };
+                            if response.events.iter().any(|e| {
+                                e.r#type == vmservice::VmEventType::Halted as i32
+                                    && e.halt_reason == vmservice::HaltReason::PowerOff as i32
+                            }) {
+                                break true;
+                            }
+                            after_sequence = last.sequence;
+                        }
+                    })
+                    .await;
+                match halted {
+                    Ok(halted) => halted,
+                    Err(CancelReason::DeadlineExceeded) => false,
+                    Err(reason) => return Err(reason.into()),
+                }
+            };
+
+            let forced = !completed && request.hard_fallback;
+            if forced {
+                if reboot {
+                    tracing::info!("guest did not reboot in time, resetting");
+                    vm.worker_rpc
+                        .call_failable(VmRpc::Reset, ())
+                        .await
+                        .context("reset failed")?;
+                } else {
+                    tracing::info!("guest did not power off in time, powering off");
+                    vm.worker_rpc
+                        .call(VmRpc::PowerOff, ())
+                        .await
+                        .context("power off failed")?;
+                }
+            }
+
+            Ok(vmservice::ShutdownVmResponse {
+                acknowledged,
+                completed,
+                forced,
+            })
+        }
+    }
+
+    fn watch_vm(
+        &mut self,
+        mut ctx: mesh::CancelContext,
+        request: vmservice::WatchVmRequest,
+    ) -> impl Future<Output = anyhow::Result<vmservice::WatchVmResponse>> + use<> {
+        let events = self.events.clone();
+        async move {
+            Ok(ctx
+                .until_cancelled(events.wait(request.after_sequence))
+                .await?)
+        }
+    }
+
+    fn save_vm(
+        &mut self,
+        vm: &Vm,
+        request: vmservice::SaveVmRequest,
+    ) -> impl Future<Output = anyhow::Result<()>> + use<> {
+        let vm_rpc = vm.worker_rpc.clone();
+        async move { snapshot::save(&vm_rpc, Path::new(&request.path)).await }
     }
 
     fn wait_vm(
@@ -641,55 +1005,331 @@ fn wait_vm(
 
     fn modify_resource(
         &mut self,
-        vm: &Vm,
+        vm: &Arc<Vm>,
         request: vmservice::ModifyResourceRequest,
     ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
         use vmservice::modify_resource_request::Resource;
         match request.resource.context("missing resource")? {
             Resource::ScsiDisk(disk) => {
-                let scsi_path = storvsp_resources::ScsiPath {
-                    path: 0,
-                    target: 0,
-                    lun: disk.lun.try_into().ok().context("lun value out of range")?,
-                };
-
+                let controller = disk.controller;
+                let lun: u8 = disk.lun.try_into().ok().context("lun value out of range")?;
+                let vm = vm.clone();
                 if request.r#type == vmservice::ModifyType::Add as i32 {
-                    if disk.controller != 0 {
-                        anyhow::bail!("controller must be 0");
+                    let (config, snapshot_send) = make_disk_config(disk)?;
+                    Ok(async move {
+                        let mut scsi = vm.scsi.lock().await;
+                        let scsi = scsi
+                            .get_mut(&controller)
+                            .with_context(|| format!("no scsi controller {controller}"))?;
+                        // The controller fails the add if the LUN is in use.
+                        scsi.rpc
+                            .call_failable(ScsiControllerRequest::AddDevice, config)
+                            .await?;
+                        if let Some(send) = snapshot_send {
+                            scsi.disk_snapshots.insert(lun, send);
+                        }
+                        anyhow::Ok(())
                     }
-                    let config = make_disk_config(disk)?;
-                    let recv = vm
-                        .scsi_rpc
+                    .boxed())
+                } else if request.r#type == vmservice::ModifyType::Remove as i32 {
+                    let scsi_path = storvsp_resources::ScsiPath {
+                        path: 0,
+                        target: 0,
+                        lun,
+                    };
+                    Ok(async move {
+                        let mut scsi = vm.scsi.lock().await;
+                        let scsi = scsi
+                            .get_mut(&controller)
+                            .with_context(|| format!("no scsi controller {controller}"))?;
+                        scsi.rpc
+                            .call_failable(ScsiControllerRequest::RemoveDevice, scsi_path)
+                            .await?;
+                        scsi.disk_snapshots.remove(&lun);
+                        anyhow::Ok(())
+                    }
+                    .boxed())
+                } else {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                }
+            }
+            Resource::NvmeDisk(disk) => {
+                let nvme = if disk.vtl2 {
+                    vm.nvme_vtl2_rpc
                         .as_ref()
-                        .context("no scsi controller")?
-                        .call_failable(ScsiControllerRequest::AddDevice, config);
+                        .context("no vtl2 nvme controller")?
+                } else {
+                    vm.nvme_rpc.as_ref().context("no nvme controller")?
+                };
+                if request.r#type == vmservice::ModifyType::Add as i32 {
+                    let namespace = make_nvme_namespace(disk)?;
+                    let recv = nvme.call_failable(NvmeControllerRequest::AddNamespace, namespace);
                     Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
                 } else if request.r#type == vmservice::ModifyType::Remove as i32 {
-                    let recv = vm
-                        .scsi_rpc
-                        .as_ref()
-                        .context("no scsi controller")?
-                        .call_failable(ScsiControllerRequest::RemoveDevice, scsi_path);
+                    let recv =
+                        nvme.call_failable(NvmeControllerRequest::RemoveNamespace, disk.nsid);
                     Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
                 } else {
                     anyhow::bail!("unsupported request type {}", request.r#type);
                 }
             }
             Resource::NicConfig(nic) => {
-                if request.r#type != vmservice::ModifyType::Add as i32 {
-                    anyhow::bail!("not supported yet");
+                if nic.nic_type != vmservice::NicType::Netvsc as i32 {
+                    anyhow::bail!(
+                        "runtime changes to nic type {} are not supported",
+                        nic.nic_type
+                    );
+                }
+                if request.r#type == vmservice::ModifyType::Add as i32 {
+                    let config = parse_nic_config(nic)?;
+                    let recv = vm.worker_rpc.call_failable(VmRpc::AddVmbusDevice, config);
+                    Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+                } else if request.r#type == vmservice::ModifyType::Remove as i32 {
+                    let instance_id = nic.nic_id.parse().context("invalid instance ID")?;
+                    let recv = vm
+                        .worker_rpc
+                        .call_failable(VmRpc::RemoveVmbusDevice, instance_id);
+                    Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+                } else {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
                 }
-                let config = parse_nic_config(nic)?;
-                let recv = vm.worker_rpc.call_failable(VmRpc::AddVmbusDevice, config);
-                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
             }
             Resource::VpmemDisk(_) => anyhow::bail!("vpmem not supported"),
             Resource::WindowsDevice(_) => anyhow::bail!("device assignment not supported"),
-            Resource::Processor(_) | Resource::ProcessorConfig(_) | Resource::Memory(_) => {
-                anyhow::bail!("processor and memory resources not supported")
+            Resource::Memory(memory) => {
+                if request.r#type != vmservice::ModifyType::Update as i32 {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                }
+                let size = memory
+                    .memory_mb
+                    .checked_mul(0x100000)
+                    .context("invalid memory size")?;
+                let recv = vm.worker_rpc.call_failable(VmRpc::SetMemorySize, size);
+                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+            }
+            Resource::Balloon(balloon) => {
+                if request.r#type != vmservice::ModifyType::Update as i32 {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                }
+                let size = balloon
+                    .balloon_mb
+                    .checked_mul(0x100000)
+                    .context("invalid balloon size")?;
+                let recv = vm.worker_rpc.call_failable(VmRpc::SetBalloonSize, size);
+                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+            }
+            Resource::ProcessorAffinity(affinity) => {
+                if request.r#type != vmservice::ModifyType::Update as i32 {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                }
+                let recv = vm.worker_rpc.call_failable(
+                    VmRpc::SetVpAffinity,
+                    (affinity.processor_index, affinity.host_processors),
+                );
+                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+            }
+            Resource::Processor(_) | Resource::ProcessorConfig(_) => {
+                anyhow::bail!("processor resources not supported")
             }
         }
     }
+
+    fn snapshot_disks(
+        &mut self,
+        vm: &Arc<Vm>,
+        request: vmservice::SnapshotDisksRequest,
+    ) -> anyhow::Result<
+        impl Future<Output = anyhow::Result<vmservice::SnapshotDisksResponse>> + use<>,
+    > {
+        let disks = request
+            .disks
+            .into_iter()
+            .map(|disk| {
+                let lun: u8 = disk.lun.try_into().ok().context("lun value out of range")?;
+                let diff_path = (!disk.diff_path.is_empty()).then(|| Path::new(&disk.diff_path));
+                let layer = snapshot_diff_layer(diff_path)?;
+                Ok((disk, lun, layer))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let vm = vm.clone();
+        let quiesce_timeout = if request.quiesce_timeout_seconds == 0 {
+            DEFAULT_QUIESCE_TIMEOUT
+        } else {
+            Duration::from_secs(request.quiesce_timeout_seconds.into())
+        };
+        let quiesce = !request.crash_consistent;
+        Ok(async move {
+            let disks = {
+                let scsi = vm.scsi.lock().await;
+                disks
+                    .into_iter()
+                    .map(|(disk, lun, layer)| {
+                        let send = scsi
+                            .get(&disk.controller)
+                            .and_then(|scsi| scsi.disk_snapshots.get(&lun))
+                            .with_context(|| {
+                                format!(
+                                    "no writable disk at lun {lun} on controller {}",
+                                    disk.controller
+                                )
+                            })?
+                            .clone();
+                        Ok((disk, lun, layer, send))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            };
+
+            // Don't send the snapshot requests until the guest is frozen.
+            let snapshot = futures::future::try_join_all(disks.into_iter().map(
+                |(disk, lun, layer, send)| async move {
+                    let snapshot_count = send
+                        .call_failable(SnapshotDiskRequest::Snapshot, layer)
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "failed to snapshot lun {lun} on controller {}",
+                                disk.controller
+                            )
+                        })?;
+                    anyhow::Ok(vmservice::DiskSnapshotResult {
+                        controller: disk.controller,
+                        lun: disk.lun,
+                        diff_path: disk.diff_path,
+                        snapshot_count,
+                    })
+                },
+            ));
+            let snapshots = if quiesce {
+                crate::vss::with_frozen_guest(&vm.vss_ic, quiesce_timeout, snapshot).await?
+            } else {
+                snapshot.await?
+            };
+            Ok(vmservice::SnapshotDisksResponse {
+                snapshots,
+                quiesced: quiesce,
+            })
+        })
+    }
+
+    fn check_guest_memory_access(&self) -> anyhow::Result<()> {
+        if !self.allow_guest_memory_access {
+            return Err(anyhow::Error::new(Code::PermissionDenied)
+                .context("guest memory access requires --allow-guest-memory-access"));
+        }
+        Ok(())
+    }
+
+    fn read_guest_memory(
+        &mut self,
+        vm: &Vm,
+        request: vmservice::ReadGuestMemoryRequest,
+    ) -> anyhow::Result<
+        impl Future<Output = anyhow::Result<vmservice::ReadGuestMemoryResponse>> + use<>,
+    > {
+        self.check_guest_memory_access()?;
+        if request.length > MAX_GUEST_MEMORY_READ {
+            return Err(anyhow::Error::new(Code::InvalidArgument).context(format!(
+                "cannot read more than {MAX_GUEST_MEMORY_READ:#x} bytes at once"
+            )));
+        }
+        let recv = vm
+            .worker_rpc
+            .call_failable(VmRpc::ReadMemory, (request.gpa, request.length as usize));
+        Ok(async move {
+            let data = recv.await.context("failed to read guest memory")?;
+            Ok(vmservice::ReadGuestMemoryResponse { data })
+        })
+    }
+
+    fn write_guest_memory(
+        &mut self,
+        vm: &Vm,
+        request: vmservice::WriteGuestMemoryRequest,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        self.check_guest_memory_access()?;
+        let recv = vm
+            .worker_rpc
+            .call_failable(VmRpc::WriteMemory, (request.gpa, request.data));
+        Ok(async move {
+            recv.await.context("failed to write guest memory")?;
+            Ok(())
+        })
+    }
+
+    fn search_guest_memory(
+        &mut self,
+        vm: &Vm,
+        request: vmservice::SearchGuestMemoryRequest,
+    ) -> anyhow::Result<
+        impl Future<Output = anyhow::Result<vmservice::SearchGuestMemoryResponse>> + use<>,
+    > {
+        self.check_guest_memory_access()?;
+        let vmservice::SearchGuestMemoryRequest {
+            gpa,
+            length,
+            pattern,
+            max_results,
+        } = request;
+        if pattern.is_empty() || pattern.len() as u64 > SEARCH_CHUNK_SIZE {
+            return Err(anyhow::Error::new(Code::InvalidArgument).context(format!(
+                "pattern must be between 1 and {SEARCH_CHUNK_SIZE:#x} bytes"
+            )));
+        }
+        let end = gpa
+            .checked_add(length)
+            .ok_or_else(|| anyhow::Error::new(Code::InvalidArgument).context("invalid range"))?;
+        let max_results = if max_results == 0 {
+            DEFAULT_SEARCH_RESULTS
+        } else {
+            max_results
+        } as usize;
+        let worker_rpc = vm.worker_rpc.clone();
+        Ok(async move {
+            let mut gpas = Vec::new();
+            let mut gpa = gpa;
+            // The last bytes read, starting at `base`, which is kept so that
+            // matches that straddle two chunks are found.
+            let mut data = Vec::new();
+            while gpa < end && gpas.len() < max_results {
+                let len = (end - gpa).min(SEARCH_CHUNK_SIZE);
+                let base = gpa - data.len() as u64;
+                data.extend(
+                    worker_rpc
+                        .call_failable(VmRpc::ReadMemory, (gpa, len as usize))
+                        .await
+                        .with_context(|| format!("failed to read guest memory at {gpa:#x}"))?,
+                );
+                gpas.extend(
+                    data.windows(pattern.len())
+                        .enumerate()
+                        .filter(|(_, window)| *window == pattern)
+                        .map(|(i, _)| base + i as u64)
+                        .take(max_results - gpas.len()),
+                );
+                data.drain(..data.len() - (pattern.len() - 1).min(data.len()));
+                gpa += len;
+            }
+            Ok(vmservice::SearchGuestMemoryResponse { gpas })
+        })
+    }
+
+    fn translate_guest_virtual_address(
+        &mut self,
+        vm: &Vm,
+        request: vmservice::TranslateGuestVirtualAddressRequest,
+    ) -> anyhow::Result<
+        impl Future<Output = anyhow::Result<vmservice::TranslateGuestVirtualAddressResponse>> + use<>,
+    > {
+        self.check_guest_memory_access()?;
+        let recv = vm
+            .worker_rpc
+            .call_failable(VmRpc::TranslateGva, (request.vp, request.gva));
+        Ok(async move {
+            let gpa = recv.await?;
+            Ok(vmservice::TranslateGuestVirtualAddressResponse { gpa })
+        })
+    }
 }
 
 fn parse_nic_config(
@@ -732,19 +1372,66 @@ fn parse_nic_config(
     Ok((DeviceVtl::Vtl0, cfg.into_resource()))
 }
 
-fn make_disk_config(disk: vmservice::ScsiDisk) -> anyhow::Result<ScsiDeviceAndPath> {
-    Ok(ScsiDeviceAndPath {
+/// Returns the SCSI device for `disk`, along with a channel for snapshotting
+/// the disk if it is writable.
+/// Returns the VMBus instance ID of SCSI controller `controller`.
+fn scsi_instance_id(controller: u32) -> Guid {
+    let mut instance_id = SCSI_INSTANCE_ID;
+    instance_id.data1 = instance_id.data1.wrapping_add(controller);
+    instance_id
+}
+
+fn make_disk_config(
+    disk: vmservice::ScsiDisk,
+) -> anyhow::Result<(ScsiDeviceAndPath, Option<mesh::Sender<SnapshotDiskRequest>>)> {
+    let mut backing = open_disk(&disk.disk, &disk.host_path, disk.read_only)?;
+    let mut snapshot_send = None;
+    if !disk.read_only {
+        let send;
+        (backing, send) = snapshot_disk_handle(backing);
+        snapshot_send = Some(send);
+    }
+    let device = ScsiDeviceAndPath {
         path: storvsp_resources::ScsiPath {
             path: 0,
             target: 0,
             lun: disk.lun.try_into().ok().context("lun value out of range")?,
         },
         device: SimpleScsiDiskHandle {
-            disk: open_disk_type(disk.host_path.as_ref(), disk.read_only)
-                .with_context(|| format!("failed to open {}", disk.host_path))?,
+            disk: backing,
             read_only: disk.read_only,
             parameters: Default::default(),
         }
         .into_resource(),
+    };
+    Ok((device, snapshot_send))
+}
+
+fn make_nvme_namespace(disk: vmservice::NvmeDisk) -> anyhow::Result<NamespaceDefinition> {
+    if disk.nsid == 0 {
+        anyhow::bail!("invalid nsid 0");
+    }
+    Ok(NamespaceDefinition {
+        nsid: disk.nsid,
+        read_only: disk.read_only,
+        disk: open_disk(&disk.disk, &disk.host_path, disk.read_only)?,
     })
 }
+
+/// Opens a disk from either a `--disk`-style description or, if that is
+/// empty, a host path.
+fn open_disk(
+    disk: &str,
+    host_path: &str,
+    read_only: bool,
+) -> anyhow::Result<Resource<DiskHandleKind>> {
+    if !disk.is_empty() {
+        let kind: DiskCliKind = disk
+            .parse()
+            .with_context(|| format!("invalid disk description {disk}"))?;
+        disk_open(&kind, read_only).with_context(|| format!("failed to open {disk}"))
+    } else {
+        open_disk_type(host_path.as_ref(), read_only)
+            .with_context(|| format!("failed to open {host_path}"))
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Builds the `Boot####` and `BootOrder` UEFI variables for
+//! `--uefi-boot-order`.
+
+use firmware_uefi_custom_vars::CustomVar;
+use guid::Guid;
+use net_backend_resources::mac_address::MacAddress;
+use uefi_specs::hyperv::VM_DISK_VMBUS_CHILD_GUID;
+use uefi_specs::hyperv::VM_HW_VENDOR_VMBUS_GUID;
+use uefi_specs::hyperv::VM_NET_VMBUS_CHILD_GUID;
+use uefi_specs::uefi::boot::EfiDeviceType;
+use uefi_specs::uefi::boot::EfiEndDeviceSubType;
+use uefi_specs::uefi::boot::EfiHardwareDeviceSubType;
+use uefi_specs::uefi::boot::EfiMediaDeviceSubType;
+use uefi_specs::uefi::boot::EfiMessagingDeviceSubType;
+use uefi_specs::uefi::nvram::EfiVariableAttributes;
+use uefi_specs::uefi::nvram::vars::EFI_GLOBAL_VARIABLE;
+use zerocopy::IntoBytes;
+
+/// UEFI spec 3.1.3: the load option is active.
+const LOAD_OPTION_ACTIVE: u32 = 0x1;
+
+/// A single boot entry.
+pub struct BootEntry {
+    description: String,
+    device_path: DevicePath,
+}
+
+impl BootEntry {
+    /// A boot entry for the VTL0 SCSI disk or DVD at `lun`.
+    pub fn scsi(lun: u8, is_dvd: bool) -> Self {
+        // OpenVMM uses the storage class GUID as the instance ID of the VTL0
+        // SCSI controller.
+        let mut path = DevicePath::vmbus(VM_DISK_VMBUS_CHILD_GUID, VM_DISK_VMBUS_CHILD_GUID);
+        path.node(
+            EfiDeviceType::MESSAGING,
+            EfiMessagingDeviceSubType::SCSI.0,
+            &[0u16.to_le_bytes(), u16::from(lun).to_le_bytes()].concat(),
+        );
+        Self {
+            description: format!("SCSI {} {lun}", if is_dvd { "DVD" } else { "Disk" }),
+            device_path: path,
+        }
+    }
+
+    /// A network boot entry for the VMBus NIC `instance_id`.
+    pub fn network(instance_id: Guid, mac_address: MacAddress) -> Self {
+        let mut path = DevicePath::vmbus(VM_NET_VMBUS_CHILD_GUID, instance_id);
+        // The MAC address is padded to 32 bytes, followed by the interface
+        // type (1 for Ethernet).
+        let mut mac = [0; 33];
+        mac[..6].copy_from_slice(&<[u8; 6]>::from(mac_address));
+        mac[32] = 1;
+        path.node(
+            EfiDeviceType::MESSAGING,
+            EfiMessagingDeviceSubType::MAC_ADDRESS.0,
+            &mac,
+        );
+        Self {
+            description: format!("Network Adapter {mac_address}"),
+            device_path: path,
+        }
+    }
+
+    /// A boot entry for the file `path`, which the firmware searches for on
+    /// all attached file systems.
+    pub fn file(path: &str) -> Self {
+        let mut device_path = DevicePath::default();
+        device_path.node(
+            EfiDeviceType::MEDIA,
+            EfiMediaDeviceSubType::FILE.0,
+            &ucs2(&path.replace('/', "\\")),
+        );
+        Self {
+            description: path.to_owned(),
+            device_path,
+        }
+    }
+
+    /// Returns the `EFI_LOAD_OPTION` for this entry.
+    fn load_option(&self) -> Vec<u8> {
+        let path = self.device_path.finish();
+        let mut data = Vec::new();
+        data.extend_from_slice(&LOAD_OPTION_ACTIVE.to_le_bytes());
+        data.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        data.extend_from_slice(&ucs2(&self.description));
+        data.extend_from_slice(&path);
+        data
+    }
+}
+
+/// A device path under construction.
+#[derive(Default)]
+struct DevicePath(Vec<u8>);
+
+impl DevicePath {
+    /// A path to the VMBus channel with the given class and instance IDs.
+    fn vmbus(class_id: Guid, instance_id: Guid) -> Self {
+        let mut path = Self::default();
+        path.node(
+            EfiDeviceType::HARDWARE,
+            EfiHardwareDeviceSubType::VENDOR.0,
+            &[
+                VM_HW_VENDOR_VMBUS_GUID.as_bytes(),
+                class_id.as_bytes(),
+                instance_id.as_bytes(),
+            ]
+            .concat(),
+        );
+        path
+    }
+
+    fn node(&mut self, device_type: EfiDeviceType, sub_type: u8, data: &[u8]) {
+        self.0.push(device_type.0);
+        self.0.push(sub_type);
+        self.0
+            .extend_from_slice(&(4 + data.len() as u16).to_le_bytes());
+        self.0.extend_from_slice(data);
+    }
+
+    /// Returns the path with the end node appended.
+    fn finish(&self) -> Vec<u8> {
+        let mut path = DevicePath(self.0.clone());
+        path.node(EfiDeviceType::END, EfiEndDeviceSubType::ENTIRE.0, &[]);
+        path.0
+    }
+}
+
+/// Encodes `s` as a null-terminated UCS-2 string.
+fn ucs2(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain([0])
+        .flat_map(|c| c.to_le_bytes())
+        .collect()
+}
+
+/// Returns the `Boot####` and `BootOrder` variables that boot `entries` in
+/// order.
+pub fn boot_vars(entries: &[BootEntry]) -> Vec<(String, CustomVar)> {
+    let var = |value| CustomVar {
+        guid: EFI_GLOBAL_VARIABLE,
+        attr: EfiVariableAttributes::DEFAULT_ATTRIBUTES.into(),
+        value,
+    };
+
+    let mut vars = Vec::new();
+    let mut order = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let i = i as u16;
+        order.extend_from_slice(&i.to_le_bytes());
+        vars.push((format!("Boot{i:04X}"), var(entry.load_option())));
+    }
+    vars.push(("BootOrder".to_owned(), var(order)));
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uefi_nvram_specvars::boot_order::EfiDevicePathProtocol;
+    use uefi_nvram_specvars::boot_order::EfiLoadOption;
+    use uefi_nvram_specvars::boot_order::MediaDevice;
+    use uefi_nvram_specvars::boot_order::MessagingDevice;
+    use uefi_nvram_specvars::boot_order::parse_boot_order;
+
+    #[test]
+    fn test_boot_vars() {
+        let vars = boot_vars(&[
+            BootEntry::file("/EFI/test/boot.efi"),
+            BootEntry::scsi(3, true),
+            BootEntry::network(Guid::new_random(), [0, 0x15, 0x5d, 1, 2, 3].into()),
+        ]);
+        assert_eq!(vars.len(), 4);
+
+        let (name, order) = &vars[3];
+        assert_eq!(name, "BootOrder");
+        assert_eq!(
+            parse_boot_order(&order.value).unwrap().collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+
+        let (name, file) = &vars[0];
+        assert_eq!(name, "Boot0000");
+        let option = EfiLoadOption::parse(&file.value).unwrap();
+        assert_eq!(option.attributes, LOAD_OPTION_ACTIVE);
+        let [EfiDevicePathProtocol::Media(MediaDevice::File(path))] = &option.device_paths[..]
+        else {
+            panic!("unexpected device path {:?}", option.device_paths);
+        };
+        assert_eq!(path.to_string(), "\\EFI\\test\\boot.efi");
+
+        let option = EfiLoadOption::parse(&vars[1].1.value).unwrap();
+        let [
+            EfiDevicePathProtocol::Hardware(_),
+            EfiDevicePathProtocol::Messaging(MessagingDevice::Scsi(scsi)),
+        ] = &option.device_paths[..]
+        else {
+            panic!("unexpected device path {:?}", option.device_paths);
+        };
+        assert_eq!({ scsi.logical_unit_num }, 3);
+
+        let option = EfiLoadOption::parse(&vars[2].1.value).unwrap();
+        assert_eq!(option.device_paths.len(), 2);
+    }
+}
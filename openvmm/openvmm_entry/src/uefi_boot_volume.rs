@@ -0,0 +1,145 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Builds a throwaway EFI system partition containing a single EFI
+//! application, for `--uefi-boot-file`.
+
+use anyhow::Context;
+use fatfs::FormatVolumeOptions;
+use fatfs::FsOptions;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+
+const SECTOR_SIZE: u64 = 512;
+
+/// The minimum volume size. FAT32 requires at least 65525 clusters, so leave
+/// plenty of room above that.
+const MIN_VOLUME_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The removable media boot path for the guest architecture, which the
+/// firmware tries when attempting a default boot from the volume.
+#[cfg(guest_arch = "x86_64")]
+const BOOT_FILE_PATH: &str = "EFI/BOOT/BOOTX64.EFI";
+#[cfg(guest_arch = "aarch64")]
+const BOOT_FILE_PATH: &str = "EFI/BOOT/BOOTAA64.EFI";
+
+/// Builds a GPT disk image with a single FAT32 EFI system partition that
+/// contains the EFI application at `path` at the default removable media boot
+/// path.
+///
+/// The image is written to an anonymous temporary file, which is returned.
+pub fn build(path: &Path) -> anyhow::Result<File> {
+    let app = fs_err::read(path)?;
+    let size = (app.len() as u64 * 2 + 16 * 1024 * 1024)
+        .max(MIN_VOLUME_SIZE)
+        .next_multiple_of(1024 * 1024);
+
+    let mut file = tempfile::tempfile().context("failed to create boot volume file")?;
+    file.set_len(size).context("failed to set file size")?;
+
+    let partition_range = build_gpt(&mut file).context("failed to construct partition table")?;
+    build_fat32(
+        &mut fscommon::StreamSlice::new(&mut file, partition_range.start, partition_range.end)?,
+        &app,
+    )
+    .context("failed to format volume")?;
+    file.rewind()?;
+    Ok(file)
+}
+
+fn build_gpt(file: &mut (impl Read + Write + Seek)) -> anyhow::Result<Range<u64>> {
+    // C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+    const ESP_GUID: [u8; 16] = [
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9,
+        0x3B,
+    ];
+    const PARTITION_GUID: [u8; 16] = [
+        0x7B, 0x1C, 0x4E, 0x35, 0x90, 0x2D, 0x6A, 0x4F, 0x9E, 0x51, 0x0C, 0x43, 0xA2, 0x8D, 0x3F,
+        0x61,
+    ];
+
+    let mut mbr = mbrman::MBR::new_from(file, SECTOR_SIZE as u32, [0xff; 4])?;
+    let mut gpt = gptman::GPT::new_from(file, SECTOR_SIZE, [0xff; 16])?;
+
+    // Set up the "Protective" Master Boot Record
+    mbr[1] = mbrman::MBRPartitionEntry {
+        boot: mbrman::BOOT_INACTIVE,
+        first_chs: mbrman::CHS::new(0, 0, 2),
+        sys: 0xEE, // GPT protective
+        last_chs: mbrman::CHS::empty(),
+        starting_lba: 1,
+        sectors: gpt.header.last_usable_lba.try_into().unwrap_or(0xFFFFFFFF),
+    };
+    mbr.write_into(file)?;
+
+    file.rewind()?;
+
+    gpt[1] = gptman::GPTPartitionEntry {
+        partition_type_guid: ESP_GUID,
+        unique_partition_guid: PARTITION_GUID,
+        starting_lba: gpt.header.first_usable_lba,
+        ending_lba: gpt.header.last_usable_lba,
+        attribute_bits: 0,
+        partition_name: "EFI system partition".into(),
+    };
+    gpt.write_into(file)?;
+
+    let partition_start_byte = gpt[1].starting_lba * SECTOR_SIZE;
+    let partition_num_bytes = (gpt[1].ending_lba - gpt[1].starting_lba) * SECTOR_SIZE;
+    Ok(partition_start_byte..partition_start_byte + partition_num_bytes)
+}
+
+fn build_fat32(file: &mut (impl Read + Write + Seek), app: &[u8]) -> anyhow::Result<()> {
+    fatfs::format_volume(
+        &mut *file,
+        FormatVolumeOptions::new()
+            .volume_label(*b"EFI        ")
+            .fat_type(fatfs::FatType::Fat32),
+    )
+    .context("failed to format volume")?;
+    let fs = fatfs::FileSystem::new(file, FsOptions::new()).context("failed to open fs")?;
+    let (dir, name) = BOOT_FILE_PATH.rsplit_once('/').unwrap();
+    let mut parent = fs.root_dir();
+    for component in dir.split('/') {
+        parent = parent
+            .create_dir(component)
+            .context("failed to create directory")?;
+    }
+    let mut dest = parent.create_file(name).context("failed to create file")?;
+    dest.write_all(app).context("failed to write file")?;
+    dest.flush().context("failed to flush file")?;
+    drop(dest);
+    fs.unmount().context("failed to unmount fs")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_volume() {
+        let mut app = tempfile::NamedTempFile::new().unwrap();
+        app.write_all(b"MZ not really an EFI application").unwrap();
+        let mut file = build(app.path()).unwrap();
+
+        let gpt = gptman::GPT::find_from(&mut file).unwrap();
+        let range = gpt[1].starting_lba * SECTOR_SIZE..(gpt[1].ending_lba) * SECTOR_SIZE;
+        let fs = fatfs::FileSystem::new(
+            fscommon::StreamSlice::new(&mut file, range.start, range.end).unwrap(),
+            FsOptions::new(),
+        )
+        .unwrap();
+        let mut data = Vec::new();
+        fs.root_dir()
+            .open_file(BOOT_FILE_PATH)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"MZ not really an EFI application");
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Code to open host PCI devices for assignment to the guest with VFIO.
+
+use anyhow::Context;
+use hvlite_defs::config::VfioDeviceConfig;
+use std::path::Path;
+use std::path::PathBuf;
+use vfio_sys::IommuType;
+
+/// Opens the device at `path`, which is either its sysfs directory or its PCI
+/// address, such as `0000:01:00.0` or `01:00.0`.
+pub(crate) fn open_device(path: &Path) -> anyhow::Result<VfioDeviceConfig> {
+    let path = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        let address = path.to_str().context("invalid PCI address")?;
+        // Default to PCI domain 0.
+        let address = if address.matches(':').count() == 1 {
+            format!("0000:{address}")
+        } else {
+            address.to_owned()
+        };
+        PathBuf::from("/sys/bus/pci/devices").join(address)
+    };
+    let path = fs_err::canonicalize(path)?;
+    let pci_id = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("invalid device path")?
+        .to_owned();
+
+    let container = vfio_sys::Container::new()?;
+    let group_id = vfio_sys::Group::find_group_for_device(&path)?;
+    let group = vfio_sys::Group::open(group_id)
+        .with_context(|| format!("is {pci_id} bound to the vfio-pci driver?"))?;
+    if !group.status()?.viable() {
+        anyhow::bail!(
+            "iommu group {group_id} is not viable, all of its devices must be bound to vfio-pci"
+        );
+    }
+    group.set_container(&container)?;
+    container.set_iommu(IommuType::Type1v2)?;
+    let device = group.open_device(&pci_id)?;
+
+    Ok(VfioDeviceConfig {
+        pci_id,
+        container: container.into(),
+        group: group.into(),
+        device: device.into(),
+        vtl2_instance_id: None,
+    })
+}
@@ -0,0 +1,189 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Code to quiesce guest file systems with the VSS IC.
+
+use anyhow::Context as _;
+use hyperv_ic_resources::vss::VssRpc;
+use mesh::CancelContext;
+use mesh::rpc::Rpc;
+use mesh::rpc::RpcSend as _;
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub(crate) struct VssCommand {
+    /// The timeout in seconds, including the time to wait for the guest to
+    /// connect to the IC.
+    #[clap(long, default_value = "60")]
+    timeout: u64,
+    #[clap(subcommand)]
+    command: VssSubcommand,
+}
+
+#[derive(clap::Subcommand)]
+enum VssSubcommand {
+    /// Flush and freeze the guest's file systems before taking disk snapshots.
+    Freeze,
+    /// Thaw the guest's file systems after taking disk snapshots.
+    Thaw,
+}
+
+pub(crate) async fn handle_vss(
+    vss: &mesh::Sender<VssRpc>,
+    command: VssCommand,
+) -> anyhow::Result<()> {
+    let VssCommand { timeout, command } = command;
+    let rpc = match command {
+        VssSubcommand::Freeze => VssRpc::Freeze,
+        VssSubcommand::Thaw => VssRpc::Thaw,
+    };
+    CancelContext::new()
+        .with_timeout(Duration::from_secs(timeout))
+        .until_cancelled(vss.call_failable(rpc, ()))
+        .await??;
+    Ok(())
+}
+
+/// Runs `f` with the guest's file systems frozen, thawing them afterwards
+/// whether or not `f` succeeds.
+///
+/// Fails without running `f` if the guest does not freeze within `timeout`,
+/// which is the case when it is not running a VSS daemon.
+pub(crate) async fn with_frozen_guest<T>(
+    vss: &mesh::Sender<VssRpc>,
+    timeout: Duration,
+    f: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let frozen = CancelContext::new()
+        .with_timeout(timeout)
+        .until_cancelled(vss.call_failable(VssRpc::Freeze, ()))
+        .await;
+    match frozen {
+        Ok(r) => r.context("failed to freeze guest file systems")?,
+        Err(_) => {
+            // The IC queues requests until the guest connects, so queue a
+            // thaw behind the freeze in case it is eventually delivered.
+            vss.send(VssRpc::Thaw(Rpc::detached(())));
+            anyhow::bail!(
+                "guest did not freeze its file systems within {}s; is the guest running hv_vss_daemon?",
+                timeout.as_secs()
+            );
+        }
+    }
+    let r = f.await;
+    let thawed = CancelContext::new()
+        .with_timeout(timeout)
+        .until_cancelled(vss.call_failable(VssRpc::Thaw, ()))
+        .await
+        .context("timed out waiting for the guest to thaw")
+        .and_then(|r| r.context("failed to thaw guest file systems"));
+    match (r, thawed) {
+        (Ok(v), Ok(())) => Ok(v),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), thawed) => {
+            if let Err(thaw_err) = thawed {
+                tracing::error!(
+                    error = thaw_err.as_ref() as &dyn std::error::Error,
+                    "failed to thaw guest after failed snapshot"
+                );
+            }
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// Answers VSS requests like a guest would, logging each one.
+    fn fake_guest(
+        driver: &DefaultDriver,
+        log: Arc<Mutex<Vec<&'static str>>>,
+        freeze_result: Result<(), &'static str>,
+    ) -> mesh::Sender<VssRpc> {
+        let (send, mut recv) = mesh::channel();
+        driver
+            .spawn("fake-vss", async move {
+                while let Some(rpc) = recv.next().await {
+                    match rpc {
+                        VssRpc::Freeze(rpc) => {
+                            log.lock().push("freeze");
+                            match freeze_result {
+                                Ok(()) => rpc.complete(Ok(())),
+                                Err(err) => rpc.fail(anyhow::anyhow!(err)),
+                            }
+                        }
+                        VssRpc::Thaw(rpc) => {
+                            log.lock().push("thaw");
+                            rpc.complete(Ok(()));
+                        }
+                    }
+                }
+            })
+            .detach();
+        send
+    }
+
+    #[async_test]
+    async fn test_freeze_around_snapshot(driver: DefaultDriver) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let vss = fake_guest(&driver, log.clone(), Ok(()));
+        let v = with_frozen_guest(&vss, Duration::from_secs(10), async {
+            log.lock().push("snapshot");
+            Ok(5)
+        })
+        .await
+        .unwrap();
+        assert_eq!(v, 5);
+        assert_eq!(*log.lock(), ["freeze", "snapshot", "thaw"]);
+    }
+
+    #[async_test]
+    async fn test_thaw_after_failed_snapshot(driver: DefaultDriver) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let vss = fake_guest(&driver, log.clone(), Ok(()));
+        with_frozen_guest(&vss, Duration::from_secs(10), async {
+            log.lock().push("snapshot");
+            anyhow::Result::<()>::Err(anyhow::anyhow!("snapshot failed"))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(*log.lock(), ["freeze", "snapshot", "thaw"]);
+    }
+
+    #[async_test]
+    async fn test_no_snapshot_if_freeze_fails(driver: DefaultDriver) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let vss = fake_guest(&driver, log.clone(), Err("busy"));
+        with_frozen_guest(&vss, Duration::from_secs(10), async {
+            log.lock().push("snapshot");
+            Ok(())
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(*log.lock(), ["freeze"]);
+    }
+
+    #[async_test]
+    async fn test_guest_without_vss(_driver: DefaultDriver) {
+        // Nothing answers requests, as when the guest has no VSS daemon.
+        let (vss, _recv) = mesh::channel();
+        let snapshotted = Mutex::new(false);
+        let err = with_frozen_guest(&vss, Duration::from_millis(10), async {
+            *snapshotted.lock() = true;
+            Ok(())
+        })
+        .await
+        .unwrap_err();
+        assert!(!*snapshotted.lock());
+        assert!(format!("{err:#}").contains("hv_vss_daemon"));
+    }
+}
@@ -21,6 +21,8 @@
     #[cfg(guest_arch = "aarch64")]
     serial_pl011::resolver::SerialPl011Resolver,
     chipset::battery::resolver::BatteryResolver,
+    chipset::pci_hotplug::resolver::PciHotPlugResolver,
+    chipset::pvpanic::resolver::PvPanicResolver,
 
     // Non-volatile stores
     vmcore::non_volatile_store::resources::EphemeralNonVolatileStoreResolver,
@@ -30,13 +32,20 @@
     #[cfg(windows)]
     serial_socket::windows::WindowsPipeSerialResolver,
     serial_socket::net::SocketSerialResolver,
+    serial_socket::client::TcpClientSerialResolver,
+    #[cfg(unix)]
+    serial_socket::pty::PtySerialResolver,
 
     // Network backends
     net_backend::null::NullResolver,
+    net_hub::resolver::HubResolver,
+    net_shaper::resolver::ShapedResolver,
     #[cfg(feature = "net_consomme")]
     net_consomme::resolver::ConsommeResolver,
     #[cfg(all(feature = "net_tap", target_os = "linux"))]
     net_tap::resolver::TapResolver,
+    #[cfg(all(feature = "net_tap", target_os = "linux"))]
+    net_tap::resolver::MacvtapResolver,
     #[cfg(windows)]
     net_dio::resolver::DioResolver,
 
@@ -45,13 +54,23 @@
     #[cfg(feature = "disk_crypt")]
     disk_crypt::resolver::DiskCryptResolver,
     disk_file::FileDiskResolver,
+    disk_multipath::resolver::MultipathDiskResolver,
     disk_prwrap::DiskWithReservationsResolver,
+    disk_prwrap::DiskWithSharedReservationsResolver,
     disk_delay::resolver::DelayDiskResolver,
+    disk_sector_size::resolver::SectorSizeDiskResolver,
+    disk_snapshot::resolver::SnapshotDiskResolver,
+    disk_throttle::resolver::ThrottleDiskResolver,
+    disk_verity::resolver::VerityDiskResolver,
     disk_vhd1::Vhd1Resolver,
+    disk_vhdx::VhdxResolver,
+    disk_writeback::resolver::WriteBackCacheDiskResolver,
     #[cfg(windows)]
     disk_vhdmp::VhdmpDiskResolver,
     #[cfg(feature = "disk_blob")]
     disk_blob::resolver::BlobDiskResolver,
+    #[cfg(feature = "disk_zstd")]
+    disk_zstd::resolver::ZstdDiskResolver,
 
     // Disk Layers
     disklayer_ram::resolver::RamDiskLayerResolver,
@@ -66,6 +85,11 @@
     // SCSI
     scsidisk::resolver::SimpleScsiResolver,
 
+    // USB devices
+    #[cfg(target_os = "linux")]
+    usb_host::resolver::UsbHostResolver,
+    usb_storage::resolver::UsbStorageResolver,
+
     // Virtio devices
     #[cfg(any(windows, target_os = "linux"))]
     virtiofs::resolver::VirtioFsResolver,
@@ -73,14 +97,17 @@
     virtio_p9::resolver::VirtioPlan9Resolver,
     virtio_net::resolver::VirtioNetResolver,
     virtio_pmem::resolver::VirtioPmemResolver,
+    virtio_snd::resolver::VirtioSoundResolver,
 
     // Vmbus devices
     guest_crash_device::resolver::GuestCrashDeviceResolver,
     guest_emulation_device::resolver::GuestEmulationDeviceResolver,
     guest_emulation_log::resolver::GuestEmulationLogResolver,
+    hyperv_ic::resolver::FcopyIcResolver,
     hyperv_ic::resolver::KvpIcResolver,
     hyperv_ic::resolver::ShutdownIcResolver,
     hyperv_ic::resolver::TimesyncIcResolver,
+    hyperv_ic::resolver::VssIcResolver,
     netvsp::resolver::NetvspResolver,
     storvsp::resolver::StorvspResolver,
     uidevices::resolver::VmbusUiResolver,
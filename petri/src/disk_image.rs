@@ -18,6 +18,7 @@
 use std::path::Path;
 
 /// The description and artifacts needed to build a pipette disk image for a VM.
+#[derive(Clone)]
 pub struct AgentImage {
     os_flavor: OsFlavor,
     pipette: Option<ResolvedArtifact>,
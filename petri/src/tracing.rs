@@ -12,6 +12,7 @@
 use jiff::Timestamp;
 use kmsg::KmsgParsedEntry;
 use parking_lot::Mutex;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::Write as _;
 use std::path::Path;
@@ -27,7 +28,11 @@
 
 /// A source of [`PetriLogFile`] log files for test output.
 #[derive(Clone)]
-pub struct PetriLogSource(Arc<LogSourceInner>);
+pub struct PetriLogSource {
+    inner: Arc<LogSourceInner>,
+    /// The prefix for the names of log files and attachments.
+    prefix: Option<Arc<str>>,
+}
 
 struct LogSourceInner {
     root_path: PathBuf,
@@ -37,6 +42,26 @@ struct LogSourceInner {
 }
 
 impl PetriLogSource {
+    /// Returns a log source whose log files and attachments are named with
+    /// the given prefix, to keep them apart from those of other sources, such
+    /// as the other VMs in a test.
+    pub fn with_prefix(&self, prefix: &str) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            prefix: Some(match &self.prefix {
+                Some(outer) => format!("{outer}-{prefix}").into(),
+                None => prefix.into(),
+            }),
+        }
+    }
+
+    fn prefixed<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match &self.prefix {
+            Some(prefix) => format!("{prefix}-{name}").into(),
+            None => name.into(),
+        }
+    }
+
     /// Returns a log file for the given name.
     ///
     /// The name should not have an extension; `.log` will be appended
@@ -44,11 +69,12 @@ impl PetriLogSource {
     pub fn log_file(&self, name: &str) -> anyhow::Result<PetriLogFile> {
         use std::collections::hash_map::Entry;
 
-        let mut log_files = self.0.log_files.lock();
-        let log_file = match log_files.entry(name.to_owned()) {
+        let name = self.prefixed(name);
+        let mut log_files = self.inner.log_files.lock();
+        let log_file = match log_files.entry(name.to_string()) {
             Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
             Entry::Vacant(vacant_entry) => {
-                let mut path = self.0.root_path.join(name);
+                let mut path = self.inner.root_path.join(&*name);
                 // Note that .log is preferred to .txt at least partially
                 // because WSL2 and Defender reportedly conspire to make
                 // cross-OS .txt file accesses extremely slow.
@@ -60,8 +86,8 @@ pub fn log_file(&self, name: &str) -> anyhow::Result<PetriLogFile> {
                 vacant_entry
                     .insert(PetriLogFile(Arc::new(LogFileInner {
                         file,
-                        json_log: self.0.json_log.clone(),
-                        source: name.to_owned(),
+                        json_log: self.inner.json_log.clone(),
+                        source: name.into_owned(),
                     })))
                     .clone()
             }
@@ -70,9 +96,10 @@ pub fn log_file(&self, name: &str) -> anyhow::Result<PetriLogFile> {
     }
 
     fn attachment_path(&self, name: &str) -> PathBuf {
-        let mut attachments = self.0.attachments.lock();
-        let next = attachments.entry(name.to_owned()).or_default();
-        let name = Path::new(name);
+        let name = self.prefixed(name);
+        let mut attachments = self.inner.attachments.lock();
+        let next = attachments.entry(name.to_string()).or_default();
+        let name = Path::new(&*name);
         let name = if *next == 0 {
             name
         } else {
@@ -81,7 +108,7 @@ fn attachment_path(&self, name: &str) -> PathBuf {
             &Path::new(&format!("{}_{}", base, *next)).with_extension(extension)
         };
         *next += 1;
-        self.0.root_path.join(name)
+        self.inner.root_path.join(name)
     }
 
     /// Creates a file with the given name and returns a handle to it.
@@ -112,7 +139,7 @@ pub fn write_attachment(
 
     fn trace_attachment(&self, path: &Path) {
         // Just write the relative path to the JSON log.
-        self.0
+        self.inner
             .json_log
             .write_attachment(path.file_name().unwrap().as_ref());
         println!("[[ATTACHMENT|{}]]", path.display());
@@ -259,12 +286,15 @@ pub fn try_init_tracing(root_path: &Path) -> anyhow::Result<PetriLogSource> {
     // Canonicalize so that printed attachment paths are most likely to work.
     let root_path = root_path.fs_err_canonicalize()?;
     let jsonl = File::create(root_path.join("petri.jsonl"))?;
-    let logger = PetriLogSource(Arc::new(LogSourceInner {
-        json_log: JsonLog(Arc::new(jsonl)),
-        root_path,
-        log_files: Default::default(),
-        attachments: Default::default(),
-    }));
+    let logger = PetriLogSource {
+        inner: Arc::new(LogSourceInner {
+            json_log: JsonLog(Arc::new(jsonl)),
+            root_path,
+            log_files: Default::default(),
+            attachments: Default::default(),
+        }),
+        prefix: None,
+    };
 
     let petri_log = logger.log_file("petri")?;
 
@@ -0,0 +1,113 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Running multiple VMs in a single test.
+
+use super::PetriVm;
+use super::PetriVmBuilder;
+use super::PetriVmmBackend;
+use anyhow::Context as _;
+use pipette_client::PipetteClient;
+
+/// A group of VMs that run together in a single test, such as to test
+/// guest-to-guest networking.
+///
+/// Each VM is built from the same artifacts as the test's VM, and the VMs are
+/// started one at a time, in order, and torn down in the reverse order. VMs
+/// can be connected to each other through backend-specific configuration,
+/// such as by giving each OpenVMM VM a NIC on a shared hub with
+/// [`PetriVmConfigOpenVmm::with_hub_nic`].
+///
+/// [`PetriVmConfigOpenVmm::with_hub_nic`]: super::openvmm::PetriVmConfigOpenVmm::with_hub_nic
+pub struct PetriVmGroup<T: PetriVmmBackend> {
+    template: PetriVmBuilder<T>,
+    vms: Vec<(String, PetriVm<T>)>,
+}
+
+impl<T: PetriVmmBackend + Clone> PetriVmGroup<T> {
+    /// Returns a new, empty group whose VMs are built from `template`.
+    ///
+    /// The template itself is never run.
+    pub fn new(template: PetriVmBuilder<T>) -> Self {
+        Self {
+            template,
+            vms: Vec::new(),
+        }
+    }
+
+    /// Builds a VM named `name`, configured by `f`, and runs it after the VMs
+    /// already in the group, returning a client to its pipette agent.
+    pub async fn run(
+        &mut self,
+        name: &str,
+        f: impl FnOnce(PetriVmBuilder<T>) -> PetriVmBuilder<T>,
+    ) -> anyhow::Result<PipetteClient> {
+        let builder = self.builder(name, f)?;
+        let vm = builder
+            .run_with_lazy_pipette()
+            .await
+            .with_context(|| format!("failed to start vm {name}"))?;
+        self.vms.push((name.to_owned(), vm));
+        let (_, vm) = self.vms.last_mut().unwrap();
+        vm.wait_for_agent()
+            .await
+            .with_context(|| format!("failed to connect to pipette in vm {name}"))
+    }
+
+    /// Builds a VM named `name`, configured by `f`, and runs it after the VMs
+    /// already in the group, without configuring and starting pipette.
+    pub async fn run_without_agent(
+        &mut self,
+        name: &str,
+        f: impl FnOnce(PetriVmBuilder<T>) -> PetriVmBuilder<T>,
+    ) -> anyhow::Result<()> {
+        let builder = self.builder(name, f)?;
+        let vm = builder
+            .run_without_agent()
+            .await
+            .with_context(|| format!("failed to start vm {name}"))?;
+        self.vms.push((name.to_owned(), vm));
+        Ok(())
+    }
+
+    fn builder(
+        &self,
+        name: &str,
+        f: impl FnOnce(PetriVmBuilder<T>) -> PetriVmBuilder<T>,
+    ) -> anyhow::Result<PetriVmBuilder<T>> {
+        if self.vms.iter().any(|(n, _)| n == name) {
+            anyhow::bail!("vm {name} is already in the group");
+        }
+        tracing::info!(name, "starting vm");
+        Ok(f(self.template.sibling(name)))
+    }
+}
+
+impl<T: PetriVmmBackend> PetriVmGroup<T> {
+    /// Returns the VM named `name`.
+    ///
+    /// Panics if there is no such VM in the group.
+    #[track_caller]
+    pub fn vm(&mut self, name: &str) -> &mut PetriVm<T> {
+        self.vms
+            .iter_mut()
+            .find_map(|(n, vm)| (n == name).then_some(vm))
+            .unwrap_or_else(|| panic!("no vm {name} in the group"))
+    }
+
+    /// Tears down the VMs in the reverse of the order they were started,
+    /// waiting for each to be torn down before moving on to the next.
+    ///
+    /// The VMs are torn down immediately, without waiting for them to halt.
+    /// To shut the guests down cleanly, power them off first through their
+    /// agents.
+    pub async fn teardown(mut self) -> anyhow::Result<()> {
+        while let Some((name, vm)) = self.vms.pop() {
+            tracing::info!(%name, "tearing down vm");
+            vm.teardown()
+                .await
+                .with_context(|| format!("failed to tear down vm {name}"))?;
+        }
+        Ok(())
+    }
+}
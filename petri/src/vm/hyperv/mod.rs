@@ -46,6 +46,7 @@
 use vmm_core_defs::HaltReason;
 
 /// The Hyper-V Petri backend
+#[derive(Clone)]
 pub struct HyperVPetriBackend {}
 
 /// Resources needed at runtime for a Hyper-V Petri VM
@@ -7,6 +7,10 @@
 /// OpenVMM VM management
 pub mod openvmm;
 
+mod group;
+
+pub use group::PetriVmGroup;
+
 use crate::PetriLogSource;
 use crate::PetriTestParams;
 use crate::ShutdownKind;
@@ -83,6 +87,7 @@ pub struct PetriVmBuilder<T: PetriVmmBackend> {
 }
 
 /// Petri VM configuration
+#[derive(Clone)]
 pub struct PetriVmConfig {
     /// The name of the VM
     pub name: String,
@@ -103,6 +108,7 @@ pub struct PetriVmConfig {
 }
 
 /// Resources used by a Petri VM during contruction and runtime
+#[derive(Clone)]
 pub struct PetriVmResources {
     driver: DefaultDriver,
     output_dir: PathBuf,
@@ -194,6 +200,29 @@ pub async fn run(self) -> anyhow::Result<(PetriVm<T>, PipetteClient)> {
         Ok((vm, client))
     }
 
+    /// Returns a builder for another VM in the same test, named `name`, with
+    /// the same artifacts and configuration as this one.
+    ///
+    /// The new VM's log files are named with `name` as a prefix. Changes made
+    /// with [`Self::modify_backend`] are not copied.
+    pub fn sibling(&self, name: &str) -> Self
+    where
+        T: Clone,
+    {
+        Self {
+            backend: self.backend.clone(),
+            config: PetriVmConfig {
+                name: format!("{}-{name}", self.config.name),
+                ..self.config.clone()
+            },
+            modify_vmm_config: None,
+            resources: PetriVmResources {
+                log_source: self.resources.log_source.with_prefix(name),
+                ..self.resources.clone()
+            },
+        }
+    }
+
     async fn run_core(self) -> anyhow::Result<PetriVm<T>> {
         let arch = self.config.arch;
         let quirks = self.config.firmware.quirks();
@@ -421,6 +450,12 @@ pub async fn wait_for_teardown(mut self) -> anyhow::Result<HaltReason> {
         self.runtime.teardown().await?;
         Ok(halt_reason)
     }
+
+    /// Cleanly tear down the VM immediately, without waiting for it to halt.
+    pub async fn teardown(self) -> anyhow::Result<()> {
+        self.runtime.teardown().await
+    }
+
     /// Test that we are able to inspect OpenHCL.
     pub async fn test_inspect_openhcl(&mut self) -> anyhow::Result<()> {
         self.openhcl_diag()?.test_inspect().await
@@ -570,6 +605,7 @@ async fn restart_openhcl(
 }
 
 /// Common processor topology information for the VM.
+#[derive(Clone)]
 pub struct ProcessorTopology {
     /// The number of virtual processors.
     pub vp_count: u32,
@@ -604,6 +640,7 @@ pub enum ApicMode {
 }
 
 /// Common memory configuration information for the VM.
+#[derive(Clone)]
 pub struct MemoryConfig {
     /// Specifies the amount of memory, in bytes, to assign to the
     /// virtual machine.
@@ -624,7 +661,7 @@ fn default() -> Self {
 }
 
 /// UEFI firmware configuration
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UefiConfig {
     /// Enable secure boot
     pub secure_boot_enabled: bool,
@@ -657,7 +694,7 @@ pub struct OpenHclConfig {
 }
 
 /// Firmware to load into the test VM.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Firmware {
     /// Boot Linux directly, without any firmware.
     LinuxDirect {
@@ -963,7 +1000,7 @@ fn uefi_config_mut(&mut self) -> Option<&mut UefiConfig> {
 
 /// The guest the VM will boot into. A boot drive with the chosen setup
 /// will be automatically configured.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PcatGuest {
     /// Mount a VHD as the boot drive.
     Vhd(BootImageConfig<boot_image_type::Vhd>),
@@ -982,7 +1019,7 @@ fn artifact(&self) -> &ResolvedArtifact {
 
 /// The guest the VM will boot into. A boot drive with the chosen setup
 /// will be automatically configured.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum UefiGuest {
     /// Mount a VHD as the boot drive.
     Vhd(BootImageConfig<boot_image_type::Vhd>),
@@ -1025,11 +1062,11 @@ impl Sealed for super::Iso {}
     pub trait BootImageType: private::Sealed {}
 
     /// BootImageConfig for a VHD file
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub enum Vhd {}
 
     /// BootImageConfig for an ISO file
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub enum Iso {}
 
     impl BootImageType for Vhd {}
@@ -1037,7 +1074,7 @@ impl BootImageType for Iso {}
 }
 
 /// Configuration information for the boot drive of the VM.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BootImageConfig<T: boot_image_type::BootImageType> {
     /// Artifact handle corresponding to the boot media.
     artifact: ResolvedArtifact,
@@ -161,6 +161,8 @@ pub fn new(
         let mut devices = Vec::new();
 
         let (firmware_event_send, firmware_event_recv) = mesh::mpsc_channel();
+        let (guest_crash_send, guest_crash_recv) = mesh::channel();
+        let (generation_id_send, generation_id_recv) = mesh::channel();
 
         let make_vsock_listener = || -> anyhow::Result<(UnixListener, TempPath)> {
             Ok(tempfile::Builder::new()
@@ -192,6 +194,8 @@ pub fn new(
                 Some(VmbusConfig {
                     vsock_listener: Some(vtl2_vsock_listener),
                     vsock_path: Some(vtl2_vsock_path.to_string_lossy().into_owned()),
+                    vsock_host_connect_ports: Vec::new(),
+                    vsock_host_listen_ports: Vec::new(),
                     vmbus_max_version: None,
                     vtl2_redirect: false,
                     #[cfg(windows)]
@@ -271,7 +275,11 @@ pub fn new(
         // Add the Hyper-V timesync IC
         vmbus_devices.push((
             DeviceVtl::Vtl0,
-            hyperv_ic_resources::timesync::TimesyncIcHandle.into_resource(),
+            hyperv_ic_resources::timesync::TimesyncIcHandle {
+                resync_on_resume: false,
+                skew_ms: 0,
+            }
+            .into_resource(),
         ));
 
         // Make a vmbus vsock path for pipette connections
@@ -304,7 +312,13 @@ pub fn new(
                         MachineArch::Aarch64 => DEFAULT_MMIO_GAPS_AARCH64.into(),
                     }
                 },
+                pci_ecam: None,
                 prefetch_memory: false,
+                hotplug_size: 0,
+                balloon: false,
+                hugetlb_page_size: None,
+                backing_file: None,
+                numa_policy: None,
             }
         };
 
@@ -383,6 +397,7 @@ pub fn new(
             // Firmware
             load_mode,
             firmware_event_send: Some(firmware_event_send),
+            guest_crash_send: Some(guest_crash_send),
 
             // CPU and RAM
             memory,
@@ -407,6 +422,8 @@ pub fn new(
             vmbus: Some(VmbusConfig {
                 vsock_listener: Some(vmbus_vsock_listener),
                 vsock_path: Some(vmbus_vsock_path.to_string_lossy().into_owned()),
+                vsock_host_connect_ports: Vec::new(),
+                vsock_host_listen_ports: Vec::new(),
                 vmbus_max_version: None,
                 vtl2_redirect: firmware.openhcl_config().is_some_and(|c| c.vmbus_redirect),
                 #[cfg(windows)]
@@ -430,6 +447,18 @@ pub fn new(
 
             // Don't automatically reset the guest by default
             automatic_guest_reset: false,
+            enable_s3: false,
+            enable_s4: false,
+            resume_from_hibernate: false,
+            pci_hotplug_slots: Vec::new(),
+            pci_slots: Vec::new(),
+            pci_bridges: 0,
+            pvpanic: false,
+            smbios: Default::default(),
+            vp_affinity: Vec::new(),
+            cpuid: Vec::new(),
+            io_trace: None,
+            access_trace: None,
 
             // Disabled for VMM tests by default
             #[cfg(windows)]
@@ -439,10 +468,17 @@ pub fn new(
             virtio_console_pci: false,
             virtio_serial: None,
             virtio_devices: vec![],
+            usb_devices: vec![],
+            e1000_nics: vec![],
+            pci_serial_cards: vec![],
+            ivshmem_devices: vec![],
+            nvme_sriov: None,
+            vfio_devices: vec![],
             #[cfg(windows)]
             vpci_resources: vec![],
             debugger_rpc: None,
-            generation_id_recv: None,
+            vtl2_debugger_rpc: None,
+            generation_id_recv: Some(generation_id_recv),
             rtc_delta_milliseconds: 0,
         };
 
@@ -474,10 +510,14 @@ pub fn new(
             resources: PetriVmResourcesOpenVmm {
                 log_stream_tasks,
                 firmware_event_recv,
+                guest_crash_recv,
                 shutdown_ic_send,
                 kvp_ic_send,
                 expected_boot_event,
                 ged_send,
+                battery_state: None,
+                battery_send: None,
+                generation_id_send,
                 pipette_listener,
                 vtl2_pipette_listener,
                 openhcl_diag_handler,
@@ -678,6 +718,7 @@ fn load_firmware(&self) -> anyhow::Result<LoadMode> {
                     enable_vpci_boot: false,
                     uefi_console_mode: Some(hvlite_defs::config::UefiConsoleMode::Com1),
                     default_boot_always_attempt: false,
+                    nvram: None,
                 }
             }
             (
@@ -852,6 +893,7 @@ fn load_boot_disk(
                             )?,
                             read_only: false,
                         }],
+                        requests: None,
                     }
                     .into_resource(),
                 })]);
@@ -1008,6 +1050,7 @@ fn config_video(
                 DeviceVtl::Vtl0,
                 SynthVideoHandle {
                     framebuffer: SharedFramebufferHandle.into_resource(),
+                    resolution_recv: None,
                 }
                 .into_resource(),
             )),
@@ -27,6 +27,7 @@
 use crate::openhcl_diag::OpenHclDiagHandler;
 use anyhow::Context;
 use async_trait::async_trait;
+use chipset_resources::battery::HostBatteryUpdate;
 use disk_backend_resources::LayeredDiskHandle;
 use disk_backend_resources::layer::DiskLayerHandle;
 use disk_backend_resources::layer::RamDiskLayerHandle;
@@ -74,6 +75,7 @@
 pub const NIC_MAC_ADDRESS: MacAddress = MacAddress::new([0x00, 0x15, 0x5D, 0x12, 0x12, 0x12]);
 
 /// OpenVMM Petri Backend
+#[derive(Clone)]
 pub struct OpenVmmPetriBackend {
     openvmm_path: ResolvedArtifact,
 }
@@ -134,10 +136,19 @@ pub struct PetriVmConfigOpenVmm {
 struct PetriVmResourcesOpenVmm {
     log_stream_tasks: Vec<Task<anyhow::Result<()>>>,
     firmware_event_recv: Receiver<FirmwareEvent>,
+    guest_crash_recv: Receiver<vmm_core_defs::GuestCrash>,
     shutdown_ic_send: Sender<ShutdownRpc>,
     kvp_ic_send: Sender<hyperv_ic_resources::kvp::KvpConnectRpc>,
     expected_boot_event: Option<FirmwareEvent>,
     ged_send: Option<Sender<get_resources::ged::GuestEmulationRequest>>,
+    /// The battery state, if the battery is enabled.
+    battery_state: Option<HostBatteryUpdate>,
+    /// The battery device's update channel. With OpenHCL, updates are sent
+    /// through the GED instead.
+    battery_send: Option<Sender<HostBatteryUpdate>>,
+    /// Sends new VM generation IDs to the firmware's generation ID device.
+    /// With OpenHCL, they are sent through the GED instead.
+    generation_id_send: Sender<[u8; 16]>,
     pipette_listener: PolledSocket<UnixListener>,
     vtl2_pipette_listener: Option<PolledSocket<UnixListener>>,
     openhcl_diag_handler: Option<OpenHclDiagHandler>,
@@ -20,9 +20,14 @@
 use hvlite_defs::config::LoadMode;
 use hvlite_defs::config::VpciDeviceConfig;
 use hvlite_defs::config::Vtl2BaseAddressType;
+use net_backend_resources::hub::HubPortHandle;
+use net_backend_resources::mac_address::MacAddress;
+use std::path::Path;
 use tpm_resources::TpmDeviceHandle;
 use tpm_resources::TpmRegisterLayout;
 use vm_resource::IntoResource;
+use vm_resource::Resource;
+use vm_resource::kind::NetEndpointHandleKind;
 use vmcore::non_volatile_store::resources::EphemeralNonVolatileStoreHandle;
 use vmotherboard::ChipsetDeviceHandle;
 use vtl2_settings_proto::Vtl2Settings;
@@ -55,6 +60,8 @@ pub fn with_tpm(mut self) -> Self {
                     register_layout: TpmRegisterLayout::IoPort,
                     guest_secret_key: None,
                     logger: None,
+                    pcr_banks: None,
+                    pcr_seeds: Vec::new(),
                 }
                 .into_resource(),
             });
@@ -67,18 +74,26 @@ pub fn with_tpm(mut self) -> Self {
     }
 
     /// Enable the battery for the VM.
+    ///
+    /// The battery starts out present and charging, and can be changed at
+    /// runtime with [`PetriVmOpenVmm::set_battery_charge`] and
+    /// [`PetriVmOpenVmm::set_ac_online`].
+    ///
+    /// [`PetriVmOpenVmm::set_battery_charge`]: super::PetriVmOpenVmm::set_battery_charge
+    /// [`PetriVmOpenVmm::set_ac_online`]: super::PetriVmOpenVmm::set_ac_online
     pub fn with_battery(mut self) -> Self {
+        let state = HostBatteryUpdate::default_present();
+        self.resources.battery_state = Some(state);
         if self.firmware.is_openhcl() {
             self.ged.as_mut().unwrap().enable_battery = true;
         } else {
+            let (tx, rx) = mesh::channel();
+            tx.send(state);
+            self.resources.battery_send = Some(tx);
             self.config.chipset_devices.push(ChipsetDeviceHandle {
                 name: "battery".to_string(),
                 resource: BatteryDeviceHandleX64 {
-                    battery_status_recv: {
-                        let (tx, rx) = mesh::channel();
-                        tx.send(HostBatteryUpdate::default_present());
-                        rx
-                    },
+                    battery_status_recv: rx,
                 }
                 .into_resource(),
             });
@@ -120,16 +135,77 @@ pub fn with_igvm_attest_test_config(mut self, config: IgvmAttestTestConfig) -> S
     /// Enable a synthnic for the VM.
     ///
     /// Uses a mana emulator and the paravisor if a paravisor is present.
-    pub fn with_nic(mut self) -> Self {
-        let endpoint =
-            net_backend_resources::consomme::ConsommeHandle { cidr: None }.into_resource();
+    pub fn with_nic(self) -> Self {
+        let endpoint = net_backend_resources::consomme::ConsommeHandle {
+            cidr: None,
+            dhcp: Default::default(),
+        }
+        .into_resource();
+        self.with_nic_endpoint(endpoint, NIC_MAC_ADDRESS)
+    }
+
+    /// Enable a synthnic for the VM, like [`Self::with_nic`], with its traffic
+    /// shaped to emulate a constrained or lossy link.
+    ///
+    /// `rate_mbps` limits the link's bandwidth, `latency_ms` is added to each
+    /// packet, and `loss_percent` of packets are dropped, in each direction.
+    pub fn with_shaped_nic(
+        self,
+        rate_mbps: Option<u64>,
+        latency_ms: u64,
+        loss_percent: f64,
+    ) -> Self {
+        let endpoint = net_backend_resources::shaped::ShapedHandle {
+            endpoint: net_backend_resources::consomme::ConsommeHandle {
+                cidr: None,
+                dhcp: Default::default(),
+            }
+            .into_resource(),
+            rate_bps: rate_mbps.map(|rate| rate * 1_000_000),
+            latency_ms,
+            loss_percent,
+        }
+        .into_resource();
+        self.with_nic_endpoint(endpoint, NIC_MAC_ADDRESS)
+    }
+
+    /// Enable a synthnic for the VM, like [`Self::with_nic`], whose network
+    /// serves the files in `boot_dir` over TFTP and HTTP and directs network
+    /// boot clients to `boot_file`.
+    pub fn with_network_boot_nic(self, boot_dir: &Path, boot_file: &str) -> Self {
+        let endpoint = net_backend_resources::consomme::ConsommeHandle {
+            cidr: None,
+            dhcp: net_backend_resources::consomme::ConsommeDhcpOptions {
+                boot_file: Some(boot_file.to_owned()),
+                boot_dir: Some(boot_dir.to_string_lossy().into_owned()),
+                ..Default::default()
+            },
+        }
+        .into_resource();
+        self.with_nic_endpoint(endpoint, NIC_MAC_ADDRESS)
+    }
+
+    /// Enable a synthnic for the VM, like [`Self::with_nic`], connected to a
+    /// port on a hub shared with other VMs instead of to the host's network.
+    ///
+    /// Each VM on the hub needs its own `mac_address`. There is no DHCP
+    /// server on the hub, so the guest must be assigned a static address.
+    pub fn with_hub_nic(self, port: HubPortHandle, mac_address: MacAddress) -> Self {
+        self.with_nic_endpoint(port.into_resource(), mac_address)
+    }
+
+    fn with_nic_endpoint(
+        mut self,
+        endpoint: Resource<NetEndpointHandleKind>,
+        mac_address: MacAddress,
+    ) -> Self {
         if self.resources.vtl2_settings.is_some() {
             self.config.vpci_devices.push(VpciDeviceConfig {
                 vtl: DeviceVtl::Vtl2,
                 instance_id: MANA_INSTANCE,
                 resource: GdmaDeviceHandle {
                     vports: vec![VportDefinition {
-                        mac_address: NIC_MAC_ADDRESS,
+                        mac_address,
                         endpoint,
                     }],
                 }
@@ -155,7 +231,7 @@ pub fn with_nic(mut self) -> Self {
                 DeviceVtl::Vtl0,
                 netvsp_resources::NetvspHandle {
                     instance_id: NETVSP_INSTANCE,
-                    mac_address: NIC_MAC_ADDRESS,
+                    mac_address,
                     endpoint,
                     max_queues: None,
                 }
@@ -11,6 +11,7 @@
 use crate::worker::Worker;
 use anyhow::Context;
 use async_trait::async_trait;
+use chipset_resources::battery::HostBatteryUpdate;
 use diag_client::kmsg_stream::KmsgStream;
 use futures::FutureExt;
 use futures_concurrency::future::Race;
@@ -195,6 +196,11 @@ pub async fn wait_for_successful_boot_event(&mut self) -> anyhow::Result<()>
         /// returns that status.
         pub async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent>
     );
+    petri_vm_fn!(
+        /// Waits for the guest to report a crash through the Hyper-V guest
+        /// crash registers, and returns the report.
+        pub async fn wait_for_guest_crash(&mut self) -> anyhow::Result<vmm_core_defs::GuestCrash>
+    );
     petri_vm_fn!(
         /// Waits for the Hyper-V shutdown IC to be ready, returning a receiver
         /// that will be closed when it is no longer ready.
@@ -209,6 +215,23 @@ pub async fn send_enlightened_shutdown(&mut self, kind: ShutdownKind) -> anyhow:
         /// to send requests to it.
         pub async fn wait_for_kvp(&mut self) -> anyhow::Result<mesh::Sender<hyperv_ic_resources::kvp::KvpRpc>>
     );
+    petri_vm_fn!(
+        /// Sets the emulated battery's remaining charge, as a percentage of
+        /// its capacity, notifying the guest. Requires
+        /// [`PetriVmConfigOpenVmm::with_battery`](super::PetriVmConfigOpenVmm::with_battery).
+        pub async fn set_battery_charge(&mut self, percent: u8) -> anyhow::Result<()>
+    );
+    petri_vm_fn!(
+        /// Connects or disconnects AC power, notifying the guest. The battery
+        /// discharges while AC power is disconnected. Requires
+        /// [`PetriVmConfigOpenVmm::with_battery`](super::PetriVmConfigOpenVmm::with_battery).
+        pub async fn set_ac_online(&mut self, online: bool) -> anyhow::Result<()>
+    );
+    petri_vm_fn!(
+        /// Gives the guest a new random VM generation ID, as if the VM had
+        /// been reverted to a snapshot.
+        pub async fn update_generation_id(&mut self) -> anyhow::Result<()>
+    );
     petri_vm_fn!(
         /// Restarts OpenHCL.
         pub async fn restart_openhcl(
@@ -221,6 +244,11 @@ pub async fn restart_openhcl(
         /// Resets the hardware state of the VM, simulating a power cycle.
         pub async fn reset(&mut self) -> anyhow::Result<()>
     );
+    petri_vm_fn!(
+        /// Wakes the VM after `delay` as if the RTC alarm fired, powering it on
+        /// if the guest has powered it off. `None` cancels the scheduled wake.
+        pub async fn schedule_wake(&mut self, delay: Option<Duration>) -> anyhow::Result<()>
+    );
     petri_vm_fn!(
         /// Test that we are able to inspect OpenHCL.
         pub async fn test_inspect_openhcl(&mut self) -> anyhow::Result<()>
@@ -353,6 +381,14 @@ async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent> {
             .context("Failed to get firmware boot event")
     }
 
+    async fn wait_for_guest_crash(&mut self) -> anyhow::Result<vmm_core_defs::GuestCrash> {
+        self.resources
+            .guest_crash_recv
+            .recv()
+            .await
+            .context("Failed to get guest crash report")
+    }
+
     async fn wait_for_enlightened_shutdown_ready(
         &mut self,
     ) -> anyhow::Result<mesh::OneshotReceiver<()>> {
@@ -408,6 +444,49 @@ async fn wait_for_kvp(
         Ok(send)
     }
 
+    async fn set_battery_charge(&mut self, percent: u8) -> anyhow::Result<()> {
+        anyhow::ensure!(percent <= 100, "invalid battery charge {percent}%");
+        self.update_battery(|state| state.set_charge_percent(percent))
+    }
+
+    async fn set_ac_online(&mut self, online: bool) -> anyhow::Result<()> {
+        self.update_battery(|state| state.set_ac_online(online))
+    }
+
+    async fn update_generation_id(&mut self) -> anyhow::Result<()> {
+        let generation_id: [u8; 16] = guid::Guid::new_random().into();
+        tracing::info!(?generation_id, "Updating generation ID");
+        if let Some(ged_send) = &self.resources.ged_send {
+            ged_send
+                .send(get_resources::ged::GuestEmulationRequest::UpdateGenerationId(generation_id));
+        } else {
+            self.resources.generation_id_send.send(generation_id);
+        }
+        Ok(())
+    }
+
+    fn update_battery(&mut self, f: impl FnOnce(&mut HostBatteryUpdate)) -> anyhow::Result<()> {
+        let state = self
+            .resources
+            .battery_state
+            .as_mut()
+            .context("battery not configured")?;
+        f(state);
+        tracing::info!(?state, "Updating battery state");
+        if let Some(battery_send) = &self.resources.battery_send {
+            battery_send.send(*state);
+        } else {
+            self.resources
+                .ged_send
+                .as_ref()
+                .context("openhcl not configured")?
+                .send(get_resources::ged::GuestEmulationRequest::SetBatteryStatus(
+                    *state,
+                ));
+        }
+        Ok(())
+    }
+
     async fn restart_openhcl(
         &self,
         new_openhcl: &ResolvedArtifact,
@@ -447,6 +526,12 @@ async fn modify_vtl2_settings(
         Ok(())
     }
 
+    async fn schedule_wake(&mut self, delay: Option<Duration>) -> anyhow::Result<()> {
+        tracing::info!(?delay, "Scheduling wake");
+        self.worker.schedule_wake(delay).await?;
+        Ok(())
+    }
+
     async fn reset(&mut self) -> anyhow::Result<()> {
         tracing::info!("Resetting VM");
         self.worker.reset().await?;
@@ -375,7 +375,10 @@ async fn openvmm_host(
             ProcessConfig::new("vmm")
                 .process_name(&resources.openvmm_path)
                 .stderr(Some(stderr_write)),
-            hvlite_defs::entrypoint::MeshHostParams { runner },
+            hvlite_defs::entrypoint::MeshHostParams {
+                runner,
+                log_filter: None,
+            },
         )
         .await?;
         Ok(host)
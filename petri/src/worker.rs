@@ -12,6 +12,7 @@
 use mesh::rpc::RpcSend;
 use mesh_worker::WorkerHandle;
 use mesh_worker::WorkerHost;
+use std::time::Duration;
 use vmm_core_defs::HaltReason;
 
 pub(crate) struct Worker {
@@ -54,6 +55,10 @@ pub(crate) async fn reset(&self) -> anyhow::Result<()> {
         Ok(())
     }
 
+    pub(crate) async fn schedule_wake(&self, delay: Option<Duration>) -> Result<(), RpcError> {
+        self.rpc.call(VmRpc::ScheduleWake, delay).await
+    }
+
     pub(crate) async fn pulse_save_restore(&self) -> Result<(), RpcError<PulseSaveRestoreError>> {
         self.rpc.call_failable(VmRpc::PulseSaveRestore, ()).await
     }
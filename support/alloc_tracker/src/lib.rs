@@ -0,0 +1,129 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A global allocator wrapper that counts a process's heap allocations.
+//!
+//! Counting is off until [`enable`] is called, so that processes that do not
+//! need it only pay for a relaxed load per allocation. Since allocations made
+//! before counting was enabled may be freed afterwards, the byte counts are
+//! relative to when it was enabled.
+
+// UNSAFETY: Implementing GlobalAlloc, whose methods are unsafe.
+#![expect(unsafe_code)]
+
+use inspect::Inspect;
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static FREES: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static NET_BYTES: AtomicI64 = AtomicI64::new(0);
+static PEAK_NET_BYTES: AtomicI64 = AtomicI64::new(0);
+
+/// A global allocator that wraps `A`, counting allocations once [`enable`]
+/// has been called.
+pub struct TrackingAllocator<A>(A);
+
+impl<A> TrackingAllocator<A> {
+    /// Returns an allocator that wraps `inner`.
+    pub const fn new(inner: A) -> Self {
+        Self(inner)
+    }
+}
+
+fn record_alloc(size: usize) {
+    if ENABLED.load(Ordering::Relaxed) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+        let net = NET_BYTES.fetch_add(size as i64, Ordering::Relaxed) + size as i64;
+        PEAK_NET_BYTES.fetch_max(net, Ordering::Relaxed);
+    }
+}
+
+fn record_free(size: usize) {
+    if ENABLED.load(Ordering::Relaxed) {
+        FREES.fetch_add(1, Ordering::Relaxed);
+        NET_BYTES.fetch_sub(size as i64, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: the allocations are made by the wrapped allocator.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: guaranteed by caller.
+        let ptr = unsafe { self.0.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: guaranteed by caller.
+        let ptr = unsafe { self.0.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: guaranteed by caller.
+        unsafe { self.0.dealloc(ptr, layout) };
+        record_free(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // SAFETY: guaranteed by caller.
+        let new_ptr = unsafe { self.0.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            record_free(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Starts counting allocations.
+///
+/// This has no effect unless a [`TrackingAllocator`] is the global allocator.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether allocations are being counted.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Allocation counts since counting was enabled.
+#[derive(Debug, Copy, Clone, Inspect)]
+pub struct Stats {
+    /// The number of allocations, including reallocations.
+    pub allocations: u64,
+    /// The number of frees, including reallocations.
+    pub frees: u64,
+    /// The total number of bytes allocated.
+    pub allocated_bytes: u64,
+    /// The bytes allocated less the bytes freed.
+    pub net_bytes: i64,
+    /// The highest value of `net_bytes`.
+    pub peak_net_bytes: i64,
+}
+
+/// Returns the allocation counts.
+pub fn stats() -> Stats {
+    Stats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        frees: FREES.load(Ordering::Relaxed),
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        net_bytes: NET_BYTES.load(Ordering::Relaxed),
+        peak_net_bytes: PEAK_NET_BYTES.load(Ordering::Relaxed),
+    }
+}
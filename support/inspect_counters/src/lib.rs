@@ -6,8 +6,11 @@
 #![forbid(unsafe_code)]
 
 use inspect::Inspect;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
 /// A simple 64-bit counter.
 #[derive(Debug, Default, Clone)]
@@ -131,3 +134,135 @@ fn inspect(&self, req: inspect::Request<'_>) {
         resp.counter(&BUCKETS[N - 1][..WIDTH[N - 1] + 1], self.0[N - 1]);
     }
 }
+
+/// Whether [`LatencyHistogram`]s record samples in this process.
+static LATENCY_HISTOGRAMS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables recording into [`LatencyHistogram`]s in this process.
+pub fn set_latency_histograms_enabled(enabled: bool) {
+    LATENCY_HISTOGRAMS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether [`LatencyHistogram`]s record samples in this process.
+pub fn latency_histograms_enabled() -> bool {
+    LATENCY_HISTOGRAMS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// The number of buckets in a [`LatencyHistogram`]. The last bucket holds
+/// samples of 2^22 microseconds (about four seconds) or more.
+const LATENCY_BUCKETS: usize = 24;
+
+/// A histogram of operation latencies, with power-of-two microsecond buckets.
+///
+/// The histogram is updated atomically, so it can be shared between
+/// concurrent operations (such as the IOs of a disk) without a lock.
+///
+/// [`start`](Self::start) only reads the clock when latency histograms are
+/// enabled (see [`set_latency_histograms_enabled`]), so that hot paths do not
+/// pay for it unless asked to. Callers that time operations themselves can
+/// record samples unconditionally with [`add_sample`](Self::add_sample).
+///
+/// Each bucket is named by its lower bound in microseconds, and counts the
+/// samples below the next bucket's bound. For example, bucket `8` counts
+/// latencies of 8 to 15 microseconds.
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    count: AtomicU64,
+    total_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// Returns an empty histogram.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the start time of an operation, to pass to
+    /// [`record`](Self::record) when the operation completes, or `None` if
+    /// latency histograms are disabled.
+    pub fn start() -> Option<Instant> {
+        latency_histograms_enabled().then(Instant::now)
+    }
+
+    /// Records the latency of an operation that started at `start`, as
+    /// returned by [`start`](Self::start).
+    pub fn record(&self, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.add_sample(start.elapsed());
+        }
+    }
+
+    /// Records an operation that took `latency`.
+    pub fn add_sample(&self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (64 - us.leading_zeros() as usize).min(LATENCY_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    /// Returns the number of recorded samples.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the sum of the recorded latencies in microseconds, wrapping on
+    /// overflow.
+    pub fn total_us(&self) -> u64 {
+        self.total_us.load(Ordering::Relaxed)
+    }
+}
+
+impl Inspect for LatencyHistogram {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        req.respond()
+            .counter("count", self.count())
+            .counter("total_us", self.total_us())
+            .child("us", |req| {
+                let mut resp = req.respond();
+                for (i, n) in self.buckets.iter().enumerate() {
+                    let lower = if i == 0 { 0 } else { 1u64 << (i - 1) };
+                    resp.counter(&lower.to_string(), n.load(Ordering::Relaxed));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LatencyHistogram;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn test_latency_histogram() {
+        let h = LatencyHistogram::new();
+        h.add_sample(Duration::from_nanos(500));
+        h.add_sample(Duration::from_micros(1));
+        h.add_sample(Duration::from_micros(3));
+        h.add_sample(Duration::from_micros(12));
+        h.add_sample(Duration::from_micros(1024));
+        h.add_sample(Duration::from_secs(60));
+        h.add_sample(Duration::MAX);
+        let buckets = h
+            .buckets
+            .iter()
+            .map(|n| n.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let mut expected = [0; super::LATENCY_BUCKETS];
+        expected[0] = 1;
+        expected[1] = 1;
+        expected[2] = 1;
+        expected[4] = 1;
+        expected[11] = 1;
+        expected[super::LATENCY_BUCKETS - 1] = 2;
+        assert_eq!(buckets, expected);
+        assert_eq!(h.count(), 7);
+        assert_eq!(h.total_us(), 60_001_040u64.wrapping_add(u64::MAX));
+
+        // Disabled by default, so nothing is recorded.
+        h.record(LatencyHistogram::start());
+        assert_eq!(h.count(), 7);
+    }
+}
@@ -267,6 +267,7 @@ pub struct ProcessConfig {
     name: String,
     process_name: Option<PathBuf>,
     process_args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
     stderr: Option<File>,
     skip_worker_arg: bool,
     sandbox_profile: Option<Box<dyn SandboxProfile + Sync>>,
@@ -280,6 +281,7 @@ pub fn new(name: impl Into<String>) -> Self {
             name: name.into(),
             process_name: None,
             process_args: Vec::new(),
+            env: Vec::new(),
             stderr: None,
             skip_worker_arg: false,
             sandbox_profile: None,
@@ -296,6 +298,7 @@ pub fn new_with_sandbox(
             name: name.into(),
             process_name: None,
             process_args: Vec::new(),
+            env: Vec::new(),
             stderr: None,
             skip_worker_arg: false,
             sandbox_profile: Some(sandbox_profile),
@@ -329,6 +332,12 @@ pub fn args<I>(mut self, args: I) -> Self
         self
     }
 
+    /// Sets an environment variable for the process.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
     /// Sets the process's stderr to `file`.
     pub fn stderr(mut self, file: Option<File>) -> Self {
         self.stderr = file;
@@ -653,6 +662,10 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
                 .env(INVITATION_ENV_NAME, invitation_env)
                 .job(self.job.as_handle());
 
+            for (key, value) in &config.env {
+                builder.env(key, value);
+            }
+
             if let Some(log_file) = config.stderr.as_ref() {
                 builder.stderr(process::Stdio::Handle(log_file.as_handle()));
             }
@@ -707,6 +720,10 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
                 .dup_fd(invitation.fd.as_fd(), IPC_FD)
                 .env(INVITATION_ENV_NAME, invitation_env);
 
+            for (key, value) in config.env {
+                command.env(key, value);
+            }
+
             if !config.skip_worker_arg {
                 command.arg(&name);
             }
@@ -9,6 +9,7 @@
 pub mod pipe;
 pub mod process;
 pub mod pthread;
+pub mod pty;
 
 use std::fs::File;
 use std::io;
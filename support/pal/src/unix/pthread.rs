@@ -32,4 +32,16 @@ pub fn signal(&self, signal: i32) -> io::Result<()> {
         }
         Ok(())
     }
+
+    /// Sets the affinity of Pthread's thread.
+    #[cfg(target_os = "linux")]
+    pub fn set_affinity(&self, cpu_set: &super::affinity::CpuSet) -> io::Result<()> {
+        // SAFETY: calling as documented, with an appropriately-sized buffer.
+        let r =
+            unsafe { libc::pthread_setaffinity_np(self.0, cpu_set.buffer_len(), cpu_set.as_ptr()) };
+        if r != 0 {
+            return Err(io::Error::from_raw_os_error(r));
+        }
+        Ok(())
+    }
 }
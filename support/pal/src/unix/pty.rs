@@ -0,0 +1,75 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pseudo-terminal support.
+
+use super::SyscallResult;
+use std::ffi::CStr;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Result;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+use std::ptr::null_mut;
+
+/// Opens a new pseudo-terminal in raw mode, returning (primary, secondary).
+pub fn open() -> Result<(File, File)> {
+    // SAFETY: calling C APIs as documented, with no special requirements.
+    unsafe {
+        let mut primary = 0;
+        let mut secondary = 0;
+        libc::openpty(
+            &mut primary,
+            &mut secondary,
+            null_mut(),
+            null_mut(),
+            null_mut(),
+        )
+        .syscall_result()?;
+        let primary = File::from_raw_fd(primary);
+        let secondary = File::from_raw_fd(secondary);
+        for file in [&primary, &secondary] {
+            libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC).syscall_result()?;
+        }
+
+        // Disable echo and line processing so that data passes through
+        // unmodified until the user's terminal program configures the
+        // terminal.
+        let mut termios = std::mem::zeroed();
+        libc::tcgetattr(secondary.as_raw_fd(), &mut termios).syscall_result()?;
+        libc::cfmakeraw(&mut termios);
+        libc::tcsetattr(secondary.as_raw_fd(), libc::TCSANOW, &termios).syscall_result()?;
+        Ok((primary, secondary))
+    }
+}
+
+/// Returns the path of the terminal device open as `file`.
+pub fn path(file: &File) -> Result<PathBuf> {
+    let mut buf = [0u8; 256];
+    // SAFETY: calling with a valid buffer and length.
+    let r = unsafe { libc::ttyname_r(file.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+    if r != 0 {
+        return Err(std::io::Error::from_raw_os_error(r));
+    }
+    let name = CStr::from_bytes_until_nul(&buf).unwrap();
+    Ok(OsStr::from_bytes(name.to_bytes()).into())
+}
+
+/// Returns whether the primary side of a pseudo-terminal is hung up, meaning
+/// that no process has the secondary side open.
+pub fn is_hung_up(primary: &File) -> Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: primary.as_raw_fd(),
+        events: 0,
+        revents: 0,
+    };
+    // SAFETY: calling with a valid pollfd and a zero timeout.
+    unsafe { libc::poll(&mut pollfd, 1, 0).syscall_result()? };
+    Ok(pollfd.revents & libc::POLLHUP != 0)
+}
+
+/// Returns whether `err`, returned from an I/O to the primary side of a
+/// pseudo-terminal, indicates that the secondary side has been closed.
+pub fn is_hangup_error(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EIO)
+}
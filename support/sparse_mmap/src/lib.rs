@@ -18,8 +18,11 @@
 pub use sys::Mappable;
 pub use sys::MappableRef;
 pub use sys::SparseMapping;
+pub use sys::alloc_huge_shared_memory;
 pub use sys::alloc_shared_memory;
+pub use sys::discard_shared_memory;
 pub use sys::new_mappable_from_file;
+pub use sys::set_shared_memory_numa_policy;
 
 use std::mem::MaybeUninit;
 use std::sync::atomic::AtomicU8;
@@ -33,6 +36,16 @@
 use zerocopy::IntoBytes;
 use zerocopy::KnownLayout;
 
+/// A host NUMA policy for shared memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Interleave pages across the given host nodes, or across all online
+    /// nodes if empty.
+    Interleave(Vec<u32>),
+    /// Allocate pages only from the given host nodes.
+    Bind(Vec<u32>),
+}
+
 /// Must be called before using try_copy on Unix platforms.
 pub fn initialize_try_copy() {
     #[cfg(unix)]
@@ -237,6 +237,30 @@ pub fn map_file(
         }
     }
 
+    /// Maps a portion of a file mapping at `offset`, copy-on-write.
+    ///
+    /// The mapping starts out sharing the file's pages, but writes go to
+    /// private copies of them and never reach the file.
+    pub fn map_file_copy_on_write(
+        &self,
+        offset: usize,
+        len: usize,
+        file_mapping: impl AsFd,
+        file_offset: u64,
+    ) -> Result<(), Error> {
+        // SAFETY: The flags passed in are guaranteed to be valid.
+        unsafe {
+            self.mmap(
+                offset,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE,
+                file_mapping.as_fd(),
+                file_offset as i64,
+            )
+        }
+    }
+
     /// Maps memory into the mapping, passing parameters through to the mmap
     /// syscall.
     ///
@@ -377,3 +401,159 @@ pub fn alloc_shared_memory(size: usize) -> io::Result<OwnedFd> {
     fd.set_len(size as u64)?;
     Ok(fd.into())
 }
+
+/// Allocates a mappable shared memory object of `size` bytes backed by huge
+/// pages of `page_size` bytes from hugetlbfs.
+///
+/// `page_size` must be 2MB or 1GB, and `size` must be a multiple of it. The
+/// pages are allocated up front, so that this fails if the host does not have
+/// enough huge pages available rather than failing at fault time. Mappings of
+/// the object must be aligned to `page_size`.
+#[cfg(target_os = "linux")]
+pub fn alloc_huge_shared_memory(size: usize, page_size: usize) -> io::Result<OwnedFd> {
+    let huge_flag = match page_size {
+        0x200000 => libc::MFD_HUGE_2MB,
+        0x40000000 => libc::MFD_HUGE_1GB,
+        _ => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+    if size % page_size != 0 {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    // SAFETY: creating a new file descriptor according to the documented
+    // contract.
+    let fd = unsafe {
+        let fd = libc::memfd_create(
+            c"mem".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_HUGETLB | huge_flag,
+        )
+        .syscall_result()?;
+        File::from_raw_fd(fd)
+    };
+    fd.set_len(size as u64)?;
+    if size != 0 {
+        // SAFETY: calling fallocate on a valid file descriptor.
+        unsafe {
+            libc::fallocate(fd.as_raw_fd(), 0, 0, size as i64).syscall_result()?;
+        }
+    }
+    Ok(fd.into())
+}
+
+/// Allocates a mappable shared memory object of `size` bytes backed by huge
+/// pages.
+///
+/// Not supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn alloc_huge_shared_memory(_size: usize, _page_size: usize) -> io::Result<OwnedFd> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Sets the host NUMA policy for the first `len` bytes of a shared memory
+/// object allocated with [`alloc_shared_memory`].
+///
+/// The policy is stored with the object, so it applies to all its mappings.
+/// Pages that have already been allocated are not moved.
+#[cfg(target_os = "linux")]
+pub fn set_shared_memory_numa_policy(
+    mappable: MappableRef<'_>,
+    len: usize,
+    policy: &crate::NumaPolicy,
+) -> io::Result<()> {
+    let (mode, nodes) = match policy {
+        crate::NumaPolicy::Interleave(nodes) => (libc::MPOL_INTERLEAVE, nodes),
+        crate::NumaPolicy::Bind(nodes) => (libc::MPOL_BIND, nodes),
+    };
+    let nodes = if nodes.is_empty() {
+        online_numa_nodes()?
+    } else {
+        nodes.clone()
+    };
+    let max_node = nodes
+        .iter()
+        .max()
+        .copied()
+        .ok_or(io::ErrorKind::InvalidInput)?;
+    let mut mask = vec![0u64; max_node as usize / 64 + 1];
+    for node in nodes {
+        mask[node as usize / 64] |= 1 << (node % 64);
+    }
+
+    let mapping = SparseMapping::new(len)?;
+    mapping.map_file(0, len, mappable, 0, true)?;
+    // SAFETY: calling mbind on a valid mapping, with a node mask of the
+    // specified size. The kernel ignores the last bit of the mask size.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            mapping.as_ptr(),
+            len,
+            mode,
+            mask.as_ptr(),
+            mask.len() * 64 + 1,
+            0,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sets the host NUMA policy for a shared memory object.
+///
+/// Not supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn set_shared_memory_numa_policy(
+    _mappable: MappableRef<'_>,
+    _len: usize,
+    _policy: &crate::NumaPolicy,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Returns the host's online NUMA nodes.
+#[cfg(target_os = "linux")]
+fn online_numa_nodes() -> io::Result<Vec<u32>> {
+    let online = std::fs::read_to_string("/sys/devices/system/node/online")?;
+    let mut nodes = Vec::new();
+    for range in online.trim_end().split(',') {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let parse = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid node list"))
+        };
+        nodes.extend(parse(start)?..=parse(end)?);
+    }
+    Ok(nodes)
+}
+
+/// Discards `len` bytes at `offset` in a shared memory object allocated with
+/// [`alloc_shared_memory`], releasing the backing memory to the host. The
+/// range reads as zero afterward.
+#[cfg(target_os = "linux")]
+pub fn discard_shared_memory(mappable: MappableRef<'_>, offset: u64, len: u64) -> io::Result<()> {
+    // SAFETY: calling fallocate on a valid file descriptor.
+    unsafe {
+        libc::fallocate(
+            mappable.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as i64,
+            len as i64,
+        )
+        .syscall_result()?;
+    }
+    Ok(())
+}
+
+/// Discards `len` bytes at `offset` in a shared memory object allocated with
+/// [`alloc_shared_memory`].
+///
+/// Not supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn discard_shared_memory(
+    _mappable: MappableRef<'_>,
+    _offset: u64,
+    _len: u64,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -464,6 +464,26 @@ pub fn map_file(
         self.map_view_of_file(offset, len, file_mapping.as_handle(), file_offset, protect)
     }
 
+    /// Maps a portion of a file mapping at `offset`, copy-on-write.
+    ///
+    /// The mapping starts out sharing the file's pages, but writes go to
+    /// private copies of them and never reach the file.
+    pub fn map_file_copy_on_write(
+        &self,
+        offset: usize,
+        len: usize,
+        file_mapping: impl AsHandle,
+        file_offset: u64,
+    ) -> Result<(), Error> {
+        self.map_view_of_file(
+            offset,
+            len,
+            file_mapping.as_handle(),
+            file_offset,
+            PAGE_WRITECOPY,
+        )
+    }
+
     /// Maps a portion of a file mapping at `offset` with protection `protect`.
     pub fn map_view_of_file(
         &self,
@@ -685,6 +705,37 @@ pub fn alloc_shared_memory(size: usize) -> io::Result<OwnedHandle> {
     }
 }
 
+/// Allocates a mappable shared memory object of `size` bytes backed by huge
+/// pages.
+///
+/// Not yet supported on Windows.
+pub fn alloc_huge_shared_memory(_size: usize, _page_size: usize) -> io::Result<OwnedHandle> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Sets the host NUMA policy for a shared memory object.
+///
+/// Not yet supported on Windows.
+pub fn set_shared_memory_numa_policy(
+    _mappable: MappableRef<'_>,
+    _len: usize,
+    _policy: &crate::NumaPolicy,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Discards `len` bytes at `offset` in a shared memory object allocated with
+/// [`alloc_shared_memory`].
+///
+/// Not yet supported on Windows.
+pub fn discard_shared_memory(
+    _mappable: MappableRef<'_>,
+    _offset: u64,
+    _len: u64,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::SparseMapping;
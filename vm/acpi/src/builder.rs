@@ -133,6 +133,19 @@ pub fn append_raw(&mut self, data: &[u8]) -> u64 {
         self.base_addr + offset
     }
 
+    /// Appends the FACS, which is not a standard table and so is neither
+    /// checksummed nor referenced from the XSDT.
+    pub fn append_facs(&mut self, facs: &acpi_spec::facs::Facs) -> u64 {
+        let align = acpi_spec::facs::FACS_ALIGNMENT;
+        if self.v.len() % align != 0 {
+            self.v.resize(self.v.len().next_multiple_of(align), 0);
+        }
+        let addr = self.base_addr + self.v.len() as u64;
+        assert_eq!(addr % align as u64, 0, "base address must be FACS aligned");
+        self.v.extend_from_slice(facs.as_bytes());
+        addr
+    }
+
     fn rsdp(&self, xsdt: u64) -> acpi_spec::Rsdp {
         let mut r = acpi_spec::Rsdp {
             signature: *b"RSD PTR ",                     // [u8; 8], // "RSD PTR "
@@ -31,6 +31,40 @@ pub struct DescriptionHeader {
     pub creator_rev: u32,
 }
 
+impl DescriptionHeader {
+    pub(crate) fn new(signature: [u8; 4], oem_table_id: u64) -> Self {
+        Self {
+            signature: u32::from_le_bytes(signature),
+            _length: 0,
+            revision: 2,
+            _checksum: 0,
+            oem_id: *b"MSFTVM",
+            oem_table_id,
+            oem_revision: 1,
+            creator_id: u32::from_le_bytes(*b"MSFT"),
+            creator_rev: 0x5000000,
+        }
+    }
+
+    /// Serializes a definition block with this header, filling in the length
+    /// and checksum.
+    pub(crate) fn to_bytes(&self, objects: &[u8]) -> Vec<u8> {
+        let mut byte_stream = Vec::new();
+        byte_stream.extend_from_slice(self.as_bytes());
+        byte_stream.extend_from_slice(objects);
+
+        let length = byte_stream.len();
+        byte_stream[4..8].copy_from_slice(&u32::try_from(length).unwrap().to_le_bytes());
+        let mut checksum: u8 = 0;
+        for byte in &byte_stream {
+            checksum = checksum.wrapping_add(*byte);
+        }
+
+        byte_stream[9] = (!checksum).wrapping_add(1);
+        byte_stream
+    }
+}
+
 pub struct Method {
     pub name: [u8; 4],
     pub sync_level: u8,
@@ -182,35 +216,13 @@ pub struct Dsdt {
 impl Dsdt {
     pub fn new() -> Self {
         Self {
-            description_header: DescriptionHeader {
-                signature: u32::from_le_bytes(*b"DSDT"),
-                _length: 0,
-                revision: 2,
-                _checksum: 0,
-                oem_id: *b"MSFTVM",
-                oem_table_id: 0x313054445344, // b'DSDT01'
-                oem_revision: 1,
-                creator_id: u32::from_le_bytes(*b"MSFT"),
-                creator_rev: 0x5000000,
-            },
+            description_header: DescriptionHeader::new(*b"DSDT", 0x313054445344), // b'DSDT01'
             objects: vec![],
         }
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut byte_stream = Vec::new();
-        byte_stream.extend_from_slice(self.description_header.as_bytes());
-        byte_stream.extend_from_slice(&self.objects);
-
-        let length = byte_stream.len();
-        byte_stream[4..8].copy_from_slice(&u32::try_from(length).unwrap().to_le_bytes());
-        let mut checksum: u8 = 0;
-        for byte in &byte_stream {
-            checksum = checksum.wrapping_add(*byte);
-        }
-
-        byte_stream[9] = (!checksum).wrapping_add(1);
-        byte_stream
+        self.description_header.to_bytes(&self.objects)
     }
 
     pub fn add_object(&mut self, obj: &impl DsdtObject) {
@@ -328,8 +340,8 @@ pub fn add_pci(
         &mut self,
         low: MemoryRange,
         high: MemoryRange,
-        // array of ((device, function), line)
-        legacy_interrupts: &[((u8, Option<u8>), u32)],
+        // array of ((device, function, pin), line), with pin 0 for INTA
+        legacy_interrupts: &[((u8, Option<u8>, u8), u32)],
     ) {
         let mut pci0 = Device::new(b"\\_SB.PCI0");
         pci0.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0A03")));
@@ -345,17 +357,18 @@ pub fn add_pci(
         });
         pci0.add_object(&empty_os_method);
         let mut prt = PciRoutingTable::new();
-        for &((device, function), line) in legacy_interrupts {
+        for &((device, function, pin), line) in legacy_interrupts {
             prt.add_entry(PciRoutingTableEntry {
                 address: ((device as u32) << 16) | function.map(|x| x as u32).unwrap_or(0xffff),
-                pin: 0,
+                pin,
                 source: None,
                 source_index: line, // Interrupt line
             });
         }
         pci0.add_object(&prt);
         let mut crs = CurrentResourceSettings::new();
-        crs.add_resource(&BusNumber::new(0, 1));
+        // All bus numbers, so that buses behind bridges can be numbered.
+        crs.add_resource(&BusNumber::new(0, 256));
         crs.add_resource(&IoPort::new(0xcf8, 0xcf8, 8));
         crs.add_resource(&QwordMemory::new(low.start(), low.end() - low.start()));
         crs.add_resource(&QwordMemory::new(high.start(), high.end() - high.start()));
@@ -363,6 +376,30 @@ pub fn add_pci(
         self.add_object(&pci0);
     }
 
+    /// Reserves the ECAM window of the PCI bus, described to the guest by the
+    /// MCFG table, as a motherboard resource.
+    ///
+    /// ```text
+    /// Device(\_SB.ECAM)
+    /// {
+    ///     Name(_HID, PNP0C02)
+    ///     Name(_UID, 0)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         QWordMemory() // ECAM window
+    ///     })
+    /// }
+    /// ```
+    pub fn add_pci_ecam(&mut self, ecam: MemoryRange) {
+        let mut device = Device::new(b"\\_SB.ECAM");
+        device.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0C02")));
+        device.add_object(&NamedInteger::new(b"_UID", 0));
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&QwordMemory::new(ecam.start(), ecam.len()));
+        device.add_object(&crs);
+        self.add_object(&device);
+    }
+
     /// Add a VMBUS device to the DSDT.
     ///
     /// If `in_pci`, then enumerate the device under PCI0. Otherwise, enumerate
@@ -140,6 +140,94 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+#[derive(Copy, Clone)]
+pub enum OperationRegionSpace {
+    SystemMemory = 0,
+    SystemIo = 1,
+}
+
+pub struct OperationRegion {
+    name: Vec<u8>,
+    space: OperationRegionSpace,
+    offset: u64,
+    len: u64,
+}
+
+impl OperationRegion {
+    pub fn new(name: &[u8], space: OperationRegionSpace, offset: u64, len: u64) -> Self {
+        Self {
+            name: encode_name(name),
+            space,
+            offset,
+            len,
+        }
+    }
+}
+
+impl DsdtObject for OperationRegion {
+    // An operation region consists of the extended identifier (0x5b 0x80)
+    // followed by the name, the address space, the offset and the length.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x5b);
+        byte_stream.push(0x80);
+        byte_stream.extend_from_slice(&self.name);
+        byte_stream.push(self.space as u8);
+        byte_stream.extend_from_slice(&encode_integer(self.offset));
+        byte_stream.extend_from_slice(&encode_integer(self.len));
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum FieldAccessType {
+    Any = 0,
+    Byte = 1,
+    Word = 2,
+    DWord = 3,
+    QWord = 4,
+}
+
+pub struct Field {
+    region: Vec<u8>,
+    access_type: FieldAccessType,
+    fields: Vec<u8>,
+}
+
+impl Field {
+    /// Creates a field list for the operation region `region`. Accesses to the
+    /// fields are not locked and preserve the unused bits.
+    pub fn new(region: &[u8], access_type: FieldAccessType) -> Self {
+        Self {
+            region: encode_name(region),
+            access_type,
+            fields: vec![],
+        }
+    }
+
+    /// Adds the next field unit, `bits` wide.
+    pub fn add_field(&mut self, name: &[u8; 4], bits: usize) {
+        // The width is encoded like a package length, but without counting
+        // itself; only single-byte encodings are supported.
+        assert!(bits > 0 && bits < 64);
+        self.fields.extend_from_slice(name);
+        self.fields.extend_from_slice(&encode_package_len(bits - 1));
+    }
+}
+
+impl DsdtObject for Field {
+    // A field consists of the extended identifier (0x5b 0x81) followed by the
+    // length, the region name, the field flags and then the field units.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x5b);
+        byte_stream.push(0x81);
+        byte_stream.extend_from_slice(&encode_package_len(
+            self.region.len() + 1 + self.fields.len(),
+        ));
+        byte_stream.extend_from_slice(&self.region);
+        byte_stream.push(self.access_type as u8);
+        byte_stream.extend_from_slice(&self.fields);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,6 +292,38 @@ fn verify_named_integers() {
         );
     }
 
+    #[test]
+    fn verify_operation_region() {
+        let region = OperationRegion::new(
+            b"PHPR",
+            OperationRegionSpace::SystemMemory,
+            0xfed3e000,
+            0x14,
+        );
+        let bytes = region.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x5b, 0x80, b'P', b'H', b'P', b'R', 0, 0xc, 0x00, 0xe0, 0xd3, 0xfe, 0xa, 0x14,
+            ],
+        );
+    }
+
+    #[test]
+    fn verify_field() {
+        let mut field = Field::new(b"PHPR", FieldAccessType::DWord);
+        field.add_field(b"PCIU", 32);
+        field.add_field(b"PCID", 32);
+        let bytes = field.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x5b, 0x81, 16, b'P', b'H', b'P', b'R', 3, b'P', b'C', b'I', b'U', 32, b'P', b'C',
+                b'I', b'D', 32,
+            ],
+        );
+    }
+
     #[test]
     fn verify_named_string() {
         let nobj = NamedString::new(b"FOO", b"hello");
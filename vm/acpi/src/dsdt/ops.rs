@@ -1,6 +1,8 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use super::helpers::*;
+
 pub trait OperationObject {
     fn append_to_vec(&self, byte_stream: &mut Vec<u8>);
 
@@ -52,6 +54,63 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+pub struct StoreOp {
+    pub operand: Vec<u8>,
+    pub target_name: Vec<u8>,
+}
+
+impl OperationObject for StoreOp {
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x70);
+        byte_stream.extend_from_slice(&self.operand);
+        byte_stream.extend_from_slice(&self.target_name);
+    }
+}
+
+pub struct NotifyOp {
+    pub object: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl OperationObject for NotifyOp {
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x86);
+        byte_stream.extend_from_slice(&self.object);
+        byte_stream.extend_from_slice(&self.value);
+    }
+}
+
+pub struct IfOp {
+    pub predicate: Vec<u8>,
+    operations: Vec<u8>,
+}
+
+impl IfOp {
+    pub fn new(predicate: Vec<u8>) -> Self {
+        Self {
+            predicate,
+            operations: vec![],
+        }
+    }
+
+    pub fn add_operation(&mut self, op: &impl OperationObject) {
+        op.append_to_vec(&mut self.operations);
+    }
+}
+
+impl OperationObject for IfOp {
+    // An if operation consists of the identifier (0xa0), followed by the
+    // length, the predicate and then the operations to run when it is true.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0xa0);
+        byte_stream.extend_from_slice(&encode_package_len(
+            self.predicate.len() + self.operations.len(),
+        ));
+        byte_stream.extend_from_slice(&self.predicate);
+        byte_stream.extend_from_slice(&self.operations);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +157,34 @@ fn verify_return_operation() {
         let bytes = op.to_bytes();
         verify_expected_bytes(&bytes, &[0xa4, b'S', b'T', b'A', b'_']);
     }
+
+    #[test]
+    fn verify_store_operation() {
+        let op = StoreOp {
+            operand: encode_integer(2),
+            target_name: b"PCEJ".to_vec(),
+        };
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0x70, 0x0a, 0x02, b'P', b'C', b'E', b'J']);
+    }
+
+    #[test]
+    fn verify_notify_operation() {
+        let op = NotifyOp {
+            object: b"S01_".to_vec(),
+            value: encode_integer(3),
+        };
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0x86, b'S', b'0', b'1', b'_', 0x0a, 0x03]);
+    }
+
+    #[test]
+    fn verify_if_operation() {
+        let mut op = IfOp::new(b"STA_".to_vec());
+        op.add_operation(&ReturnOp {
+            result: encode_integer(0xf),
+        });
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0xa0, 8, b'S', b'T', b'A', b'_', 0xa4, 0x0a, 0x0f]);
+    }
 }
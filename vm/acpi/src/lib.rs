@@ -8,3 +8,4 @@
 
 pub mod builder;
 pub mod dsdt;
+pub mod ssdt;
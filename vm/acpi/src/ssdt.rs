@@ -0,0 +1,321 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Secondary system description table, used to extend the firmware-provided
+//! DSDT with VMM-generated definitions.
+
+use crate::dsdt::AndOp;
+use crate::dsdt::CurrentResourceSettings;
+use crate::dsdt::DescriptionHeader;
+use crate::dsdt::Device;
+use crate::dsdt::DsdtObject;
+use crate::dsdt::Field;
+use crate::dsdt::FieldAccessType;
+use crate::dsdt::IfOp;
+use crate::dsdt::Interrupt;
+use crate::dsdt::IoPort;
+use crate::dsdt::Method;
+use crate::dsdt::NamedInteger;
+use crate::dsdt::NamedObject;
+use crate::dsdt::NamedString;
+use crate::dsdt::NotifyOp;
+use crate::dsdt::OperationObject;
+use crate::dsdt::OperationRegion;
+use crate::dsdt::OperationRegionSpace;
+use crate::dsdt::OrOp;
+use crate::dsdt::ReturnOp;
+use crate::dsdt::StoreOp;
+use crate::dsdt::StructuredPackage;
+use crate::dsdt::encode_integer;
+use crate::dsdt::encode_name;
+
+const LOCAL0: u8 = 0x60;
+const LOCAL1: u8 = 0x61;
+
+/// ACPI notification values sent to hot-plug slots.
+const NOTIFY_DEVICE_CHECK: u64 = 1;
+const NOTIFY_EJECT_REQUEST: u64 = 3;
+
+/// Size of the PCI hot-plug controller's register block.
+pub const PCI_HOTPLUG_REGISTERS_SIZE: u64 = 0x14;
+
+/// Describes the ACPI PCI hot-plug objects to add to an SSDT.
+///
+/// The hot-plug controller exposes the following 32-bit registers, each a
+/// bitmap indexed by PCI device number:
+///
+/// | Offset | Name | Access | Description                              |
+/// |--------|------|--------|------------------------------------------|
+/// | 0x00   | PCIU | R      | slots with a pending insertion           |
+/// | 0x04   | PCID | R      | slots with a pending removal request     |
+/// | 0x08   | PCCL | W      | write 1 to acknowledge pending events    |
+/// | 0x0c   | PCEJ | W      | write 1 to eject the device in a slot    |
+/// | 0x10   | PCPR | R      | slots that currently contain a device    |
+pub struct PciHotPlug<'a> {
+    /// Path of the PCI bus containing the slots, e.g. `\_SB.PCI0`.
+    pub bus: &'a [u8],
+    /// PCI device numbers of the hot-pluggable slots.
+    pub slots: &'a [u8],
+    /// Base address of the hot-plug controller's MMIO registers.
+    pub mmio_base: u64,
+    /// The interrupt the controller asserts while events are pending.
+    pub irq: u32,
+}
+
+pub struct Ssdt {
+    description_header: DescriptionHeader,
+    objects: Vec<u8>,
+}
+
+impl Ssdt {
+    pub fn new() -> Self {
+        Self {
+            description_header: DescriptionHeader::new(*b"SSDT", 0x313054445353), // b'SSDT01'
+            objects: vec![],
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.description_header.to_bytes(&self.objects)
+    }
+
+    pub fn add_object(&mut self, obj: &impl DsdtObject) {
+        obj.append_to_vec(&mut self.objects);
+    }
+
+    /// Advertises a sleep state that the DSDT does not, with the following ASL
+    /// code:
+    /// ```text
+    /// Name(<name>, Package(2){<slp_typ>, <slp_typ>})
+    /// ```
+    pub fn add_sleep_state(&mut self, name: &[u8], slp_typ: u8) {
+        let slp_typ = encode_integer(slp_typ.into());
+        self.add_object(&NamedObject::new(
+            name,
+            &StructuredPackage {
+                elem_count: 2,
+                elem_data: [slp_typ.as_slice(), slp_typ.as_slice()].concat(),
+            },
+        ));
+    }
+
+    /// Adds a generic event device and slot objects for ACPI PCI hot-plug,
+    /// with the following ASL code:
+    /// ```text
+    /// Device(\_SB.GED0)
+    /// {
+    ///     Name(_HID, "ACPI0013") // Generic Event Device
+    ///     Name(_UID, 0)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         Interrupt(ResourceConsumer, Level, ActiveHigh, Exclusive) {<irq>}
+    ///     })
+    ///     OperationRegion(PHPR, SystemMemory, <mmio_base>, 0x14)
+    ///     Field(PHPR, DWordAcc, NoLock, Preserve)
+    ///     {
+    ///         PCIU, 32,
+    ///         PCID, 32,
+    ///         PCCL, 32,
+    ///         PCEJ, 32,
+    ///         PCPR, 32,
+    ///     }
+    ///     Method(_EVT, 1)
+    ///     {
+    ///         Store(PCIU, Local0)
+    ///         Store(PCID, Local1)
+    ///         Or(Local0, Local1, PCCL)
+    ///         If (And(Local0, <1 << slot>)) { Notify(<bus>.S<slot>, 1) }
+    ///         If (And(Local1, <1 << slot>)) { Notify(<bus>.S<slot>, 3) }
+    ///         ...
+    ///     }
+    /// }
+    ///
+    /// Device(<bus>.S<slot>)
+    /// {
+    ///     Name(_ADR, <slot << 16>)
+    ///     Name(_SUN, <slot>)
+    ///     Method(_STA, 0)
+    ///     {
+    ///         If (And(\_SB.GED0.PCPR, <1 << slot>)) { Return(0xF) }
+    ///         Return(0)
+    ///     }
+    ///     Method(_EJ0, 1) { Store(<1 << slot>, \_SB.GED0.PCEJ) }
+    /// }
+    /// ```
+    pub fn add_pci_hotplug(&mut self, hotplug: &PciHotPlug<'_>) {
+        let slot_path = |slot: u8| {
+            assert!(slot < 32, "invalid PCI device number {slot}");
+            let mut path = hotplug.bus.to_vec();
+            path.extend_from_slice(format!(".S{slot:02X}").as_bytes());
+            path
+        };
+
+        let mut ged = Device::new(b"\\_SB.GED0");
+        ged.add_object(&NamedString::new(b"_HID", b"ACPI0013"));
+        ged.add_object(&NamedInteger::new(b"_UID", 0));
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&Interrupt::new(hotplug.irq));
+        ged.add_object(&crs);
+        ged.add_object(&OperationRegion::new(
+            b"PHPR",
+            OperationRegionSpace::SystemMemory,
+            hotplug.mmio_base,
+            PCI_HOTPLUG_REGISTERS_SIZE,
+        ));
+        let mut field = Field::new(b"PHPR", FieldAccessType::DWord);
+        for name in [b"PCIU", b"PCID", b"PCCL", b"PCEJ", b"PCPR"] {
+            field.add_field(name, 32);
+        }
+        ged.add_object(&field);
+
+        let mut evt = Method::new(b"_EVT");
+        evt.set_arg_count(1);
+        evt.add_operation(&StoreOp {
+            operand: b"PCIU".to_vec(),
+            target_name: vec![LOCAL0],
+        });
+        evt.add_operation(&StoreOp {
+            operand: b"PCID".to_vec(),
+            target_name: vec![LOCAL1],
+        });
+        evt.add_operation(&OrOp {
+            operand1: vec![LOCAL0],
+            operand2: vec![LOCAL1],
+            target_name: b"PCCL".to_vec(),
+        });
+        for &slot in hotplug.slots {
+            for (local, value) in [
+                (LOCAL0, NOTIFY_DEVICE_CHECK),
+                (LOCAL1, NOTIFY_EJECT_REQUEST),
+            ] {
+                let mut op = IfOp::new(
+                    AndOp {
+                        operand1: vec![local],
+                        operand2: encode_integer(1 << slot),
+                        target_name: vec![0],
+                    }
+                    .to_bytes(),
+                );
+                op.add_operation(&NotifyOp {
+                    object: encode_name(&slot_path(slot)),
+                    value: encode_integer(value),
+                });
+                evt.add_operation(&op);
+            }
+        }
+        ged.add_object(&evt);
+        self.add_object(&ged);
+
+        for &slot in hotplug.slots {
+            let mut dev = Device::new(&slot_path(slot));
+            dev.add_object(&NamedInteger::new(b"_ADR", (slot as u64) << 16));
+            dev.add_object(&NamedInteger::new(b"_SUN", slot.into()));
+
+            let mut present = IfOp::new(
+                AndOp {
+                    operand1: encode_name(b"\\_SB.GED0.PCPR"),
+                    operand2: encode_integer(1 << slot),
+                    target_name: vec![0],
+                }
+                .to_bytes(),
+            );
+            present.add_operation(&ReturnOp {
+                result: encode_integer(0xf),
+            });
+            let mut sta = Method::new(b"_STA");
+            sta.add_operation(&present);
+            sta.add_operation(&ReturnOp {
+                result: encode_integer(0),
+            });
+            dev.add_object(&sta);
+
+            let mut ej0 = Method::new(b"_EJ0");
+            ej0.set_arg_count(1);
+            ej0.add_operation(&StoreOp {
+                operand: encode_integer(1 << slot),
+                target_name: encode_name(b"\\_SB.GED0.PCEJ"),
+            });
+            dev.add_object(&ej0);
+            self.add_object(&dev);
+        }
+    }
+
+    /// Adds a QEMU pvpanic device using the given I/O port, with the
+    /// following ASL code:
+    /// ```text
+    /// Device(\_SB.PEVT)
+    /// {
+    ///     Name(_HID, "QEMU0001")
+    ///     Name(_UID, 0)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         IO(Decode16, <port>, <port>, 1, 1)
+    ///     })
+    /// }
+    /// ```
+    pub fn add_pvpanic(&mut self, port: u16) {
+        let mut pevt = Device::new(b"\\_SB.PEVT");
+        pevt.add_object(&NamedString::new(b"_HID", b"QEMU0001"));
+        pevt.add_object(&NamedInteger::new(b"_UID", 0));
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&IoPort::new(port, port, 1));
+        pevt.add_object(&crs);
+        self.add_object(&pevt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_pci_hotplug() {
+        let mut ssdt = Ssdt::new();
+        ssdt.add_pci_hotplug(&PciHotPlug {
+            bus: b"\\_SB.PCI0",
+            slots: &[1, 2],
+            mmio_base: 0xfed3e000,
+            irq: 18,
+        });
+        let bytes = ssdt.to_bytes();
+
+        assert_eq!(&bytes[0..4], b"SSDT");
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+            bytes.len()
+        );
+        assert_eq!(bytes.iter().fold(0u8, |x, y| x.wrapping_add(*y)), 0);
+
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"ACPI0013"));
+        // Device(\_SB.PCI0.S01) and Device(\_SB.PCI0.S02)
+        assert!(contains(b"\\\x2f\x03_SB_PCI0S01_"));
+        assert!(contains(b"\\\x2f\x03_SB_PCI0S02_"));
+        // Store(0x4, \_SB.GED0.PCEJ)
+        assert!(contains(b"\x70\x0a\x04\\\x2f\x03_SB_GED0PCEJ"));
+    }
+
+    #[test]
+    fn verify_sleep_state() {
+        let mut ssdt = Ssdt::new();
+        ssdt.add_sleep_state(b"\\_S3", 2);
+        let bytes = ssdt.to_bytes();
+        // Name(\_S3, Package(2){2, 2})
+        assert!(
+            bytes
+                .windows(10)
+                .any(|w| w == b"\x08\\_S3_\x12\x06\x02\x0a\x02\x0a")
+        );
+    }
+
+    #[test]
+    fn verify_pvpanic() {
+        let mut ssdt = Ssdt::new();
+        ssdt.add_pvpanic(0x505);
+        let bytes = ssdt.to_bytes();
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"QEMU0001"));
+        // IO(Decode16, 0x505, 0x505, 1, 1)
+        assert!(contains(b"\x47\x01\x05\x05\x05\x05\x01\x01"));
+    }
+}
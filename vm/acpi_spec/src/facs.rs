@@ -0,0 +1,65 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::Unaligned;
+
+/// Firmware ACPI Control Structure.
+///
+/// Unlike the other ACPI tables, the FACS has no standard header and is not
+/// referenced from the XSDT. It is located via the FADT, and must be 64-byte
+/// aligned.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct Facs {
+    pub signature: [u8; 4], // "FACS"
+    pub length: u32,
+    pub hardware_signature: u32,
+    pub firmware_waking_vector: u32,
+    pub global_lock: u32,
+    pub flags: u32,
+    pub x_firmware_waking_vector: u64,
+    pub version: u8,
+    pub rsvd: [u8; 3],
+    pub ospm_flags: u32,
+    pub rsvd2: [u8; 24],
+}
+
+const_assert_eq!(size_of::<Facs>(), 64);
+
+/// Required alignment of the FACS in guest memory.
+pub const FACS_ALIGNMENT: usize = 64;
+
+pub const FACS_S4BIOS_F: u32 = 1 << 0;
+pub const FACS_64BIT_WAKE_SUPPORTED_F: u32 = 1 << 1;
+
+pub const FACS_OSPM_64BIT_WAKE_F: u32 = 1 << 0;
+
+impl Facs {
+    pub fn new() -> Self {
+        Self {
+            signature: *b"FACS",
+            length: size_of::<Self>() as u32,
+            hardware_signature: 0,
+            firmware_waking_vector: 0,
+            global_lock: 0,
+            flags: 0,
+            x_firmware_waking_vector: 0,
+            version: 2,
+            rsvd: [0; 3],
+            ospm_flags: 0,
+            rsvd2: [0; 24],
+        }
+    }
+}
+
+impl Default for Facs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -11,8 +11,10 @@
 extern crate alloc;
 
 pub mod aspt;
+pub mod facs;
 pub mod fadt;
 pub mod madt;
+pub mod mcfg;
 pub mod pptt;
 pub mod srat;
 
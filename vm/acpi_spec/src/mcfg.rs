@@ -0,0 +1,61 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// ACPI definitions for the PCI Express memory mapped configuration space base
+// address description table (MCFG).
+//
+// Describes the ECAM windows through which the extended PCI config space can
+// be accessed.
+
+use super::Table;
+use crate::packed_nums::*;
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::Unaligned;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct McfgHeader {
+    pub rsvd: u64_ne,
+}
+
+impl McfgHeader {
+    pub fn new() -> McfgHeader {
+        McfgHeader { rsvd: 0.into() }
+    }
+}
+
+impl Table for McfgHeader {
+    const SIGNATURE: [u8; 4] = *b"MCFG";
+}
+
+pub const MCFG_REVISION: u8 = 1;
+
+/// An ECAM window for buses `start_bus..=end_bus` of a PCI segment.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct McfgSegmentBusRange {
+    pub ecam_base: u64_ne,
+    pub segment: u16_ne,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    pub rsvd: u32_ne,
+}
+
+const_assert_eq!(size_of::<McfgSegmentBusRange>(), 16);
+
+impl McfgSegmentBusRange {
+    pub fn new(ecam_base: u64, segment: u16, start_bus: u8, end_bus: u8) -> Self {
+        Self {
+            ecam_base: ecam_base.into(),
+            segment: segment.into(),
+            start_bus,
+            end_bus,
+            rsvd: 0.into(),
+        }
+    }
+}
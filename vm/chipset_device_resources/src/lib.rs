@@ -36,6 +36,8 @@ pub fn name(&self) -> &str {
 pub const GPE0_LINE_SET: LineSetId = LineSetId("gpe0");
 /// Line set for the BSP's local interrupts (LINT0/1) on x86.
 pub const BSP_LINT_LINE_SET: LineSetId = LineSetId("bsp_lint");
+/// Line set for the RTC alarm, which can wake the system from a sleep state.
+pub const RTC_ALARM_LINE_SET: LineSetId = LineSetId("rtc_alarm");
 
 impl CanResolveTo<ResolvedChipsetDevice> for ChipsetDeviceHandleKind {
     type Input<'a> = ResolveChipsetDeviceHandleParams<'a>;
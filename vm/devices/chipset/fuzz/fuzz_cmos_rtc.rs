@@ -33,6 +33,7 @@ fn do_fuzz(u: &mut Unstructured<'_>) -> arbitrary::Result<()> {
                 Rtc::new(
                     Box::new(time),
                     LineInterrupt::detached(),
+                    LineInterrupt::detached(),
                     &vm_time_source,
                     0x32,
                     initial_cmos,
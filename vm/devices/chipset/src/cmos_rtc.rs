@@ -178,6 +178,7 @@ pub struct Rtc {
     // Runtime deps
     real_time_source: Box<dyn InspectableLocalClock>,
     interrupt: LineInterrupt,
+    alarm_wake: LineInterrupt,
     vmtime_alarm: VmTimeAccess,
     vmtimer_periodic: VmTimerPeriodic,
     vmtimer_update: VmTimerPeriodic,
@@ -299,9 +300,13 @@ impl Rtc {
     ///
     /// [Windows ACPI Emulated Devices Table]:
     ///     <https://download.microsoft.com/download/7/E/7/7E7662CF-CBEA-470B-A97E-CE7CE0D98DC2/WAET.docx>
+    ///
+    /// `alarm_wake` is pulsed each time the alarm fires, so that the power
+    /// management device can wake the system from a sleep state.
     pub fn new(
         real_time_source: Box<dyn InspectableLocalClock>,
         interrupt: LineInterrupt,
+        alarm_wake: LineInterrupt,
         vmtime_source: &VmTimeSource,
         century_reg_idx: u8,
         initial_cmos: Option<[u8; 256]>,
@@ -314,6 +319,7 @@ pub fn new(
 
             real_time_source,
             interrupt,
+            alarm_wake,
             vmtime_alarm: vmtime_source.access("rtc-alarm"),
             vmtimer_periodic: VmTimerPeriodic::new(vmtime_source.access("rtc-periodic")),
             vmtimer_update: VmTimerPeriodic::new(vmtime_source.access("rtc-update")),
@@ -461,6 +467,8 @@ fn on_alarm_timer(&mut self, now: VmTime) {
             status_c.with_irq_alarm(true).with_irq_combined(true).into();
 
         self.update_interrupt_line_level();
+        self.alarm_wake.set_level(true);
+        self.alarm_wake.set_level(false);
 
         // re-arm the alarm timer
         self.set_alarm_timer(now)
@@ -987,6 +995,7 @@ fn new_test_rtc() -> (
         let rtc = Rtc::new(
             Box::new(time),
             LineInterrupt::detached(),
+            LineInterrupt::detached(),
             &vm_time_source,
             0x32,
             None,
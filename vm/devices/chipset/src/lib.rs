@@ -21,7 +21,9 @@
 pub mod dma;
 pub mod i8042;
 pub mod ioapic;
+pub mod pci_hotplug;
 pub mod pic;
 pub mod pit;
 pub mod pm;
 pub mod psp;
+pub mod pvpanic;
@@ -0,0 +1,394 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! ACPI PCI hot-plug controller.
+//!
+//! This device backs the generic event device (GED) and slot objects that the
+//! VMM adds to the guest's ACPI tables (see `acpi::ssdt::Ssdt::add_pci_hotplug`).
+//! It tracks which slots contain a device and which slots have pending
+//! insertion or removal events, and asserts its interrupt while any event is
+//! pending. The GED's `_EVT` method reads and acknowledges the pending events
+//! and notifies the corresponding slots; a slot's `_EJ0` method writes the
+//! eject register once the guest has released the device.
+//!
+//! Every register is a bitmap indexed by PCI device number.
+//!
+//! This device only provides the guest-visible half of hot-plug. Host
+//! requests arrive over a mesh channel, and guest ejections are reported back
+//! over another, so that the caller can attach or detach the device backing
+//! the slot.
+
+pub mod resolver;
+
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::poll_device::PollDevice;
+use chipset_resources::pci_hotplug::PciHotPlugRequest;
+use futures::StreamExt;
+use inspect::Inspect;
+use inspect::InspectMut;
+use open_enum::open_enum;
+use std::ops::RangeInclusive;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+
+// Hot-plug controller MMIO constants
+pub const PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_X64: u64 = 0xfed3e000;
+pub const PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_ARM: u64 = 0xEFFE9000;
+pub const PCI_HOTPLUG_MMIO_REGION_SIZE: u64 = 0x20;
+pub const PCI_HOTPLUG_MMIO_REGION_MASK: u64 = PCI_HOTPLUG_MMIO_REGION_SIZE - 1;
+
+// Hot-plug interrupt lines. For x64, use IOAPIC line 18.
+// For ARM64, use IRQ 5 [derived from 5 + 32 (SPI range start) = 37].
+pub const PCI_HOTPLUG_IOAPIC_IRQ: u32 = 18;
+pub const PCI_HOTPLUG_IRQ_NO: u32 = 5;
+
+// Hot-plug register offsets. These must match the field list of the GED's
+// operation region.
+open_enum! {
+    #[derive(Inspect)]
+    #[inspect(debug)]
+    pub enum RegisterOffset: u64 {
+        INSERT_PENDING = 0x0,
+        REMOVE_PENDING = 0x4,
+        EVENT_CLEAR = 0x8,
+        EJECT = 0xc,
+        PRESENT = 0x10,
+    }
+}
+
+/// Various runtime objects used by the PciHotPlugDevice
+pub struct PciHotPlugRuntimeDeps {
+    pub request_recv: mesh::Receiver<PciHotPlugRequest>,
+    pub eject_send: mesh::Sender<u8>,
+    pub interrupt: LineInterrupt,
+}
+
+/// ACPI PCI hot-plug controller.
+#[derive(InspectMut)]
+pub struct PciHotPlugDevice {
+    // Runtime glue
+    #[inspect(skip)]
+    rt: PciHotPlugRuntimeDeps,
+
+    // Static configuration
+    #[inspect(skip)]
+    mmio_region: (&'static str, RangeInclusive<u64>),
+    base_addr: u64,
+
+    // Volatile state
+    #[inspect(hex)]
+    present: u32,
+    #[inspect(hex)]
+    insert_pending: u32,
+    #[inspect(hex)]
+    remove_pending: u32,
+}
+
+impl PciHotPlugDevice {
+    /// Create a new PCI hot-plug controller
+    pub fn new(platform: PciHotPlugRuntimeDeps, base_addr: u64) -> Self {
+        PciHotPlugDevice {
+            rt: platform,
+            mmio_region: (
+                "pci_hotplug",
+                base_addr..=base_addr + (PCI_HOTPLUG_MMIO_REGION_SIZE - 1),
+            ),
+            base_addr,
+            present: 0,
+            insert_pending: 0,
+            remove_pending: 0,
+        }
+    }
+
+    fn handle_request(&mut self, request: PciHotPlugRequest) {
+        let (PciHotPlugRequest::Insert(device) | PciHotPlugRequest::Remove(device)) = request;
+        if device >= 32 {
+            tracelimit::warn_ratelimited!(device, "invalid hot-plug PCI device number");
+            return;
+        }
+        let bit = 1 << device;
+        match request {
+            PciHotPlugRequest::Insert(_) => {
+                self.present |= bit;
+                self.insert_pending |= bit;
+                self.remove_pending &= !bit;
+            }
+            PciHotPlugRequest::Remove(_) => {
+                if self.present & bit == 0 {
+                    tracelimit::warn_ratelimited!(device, "hot-remove of empty slot");
+                    return;
+                }
+                self.remove_pending |= bit;
+                self.insert_pending &= !bit;
+            }
+        }
+        self.check_interrupt_assertion();
+    }
+
+    fn read_register(&self, offset: RegisterOffset) -> u32 {
+        match offset {
+            RegisterOffset::INSERT_PENDING => self.insert_pending,
+            RegisterOffset::REMOVE_PENDING => self.remove_pending,
+            RegisterOffset::PRESENT => self.present,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: RegisterOffset, value: u32) {
+        match offset {
+            RegisterOffset::EVENT_CLEAR => {
+                self.insert_pending &= !value;
+                self.remove_pending &= !value;
+                self.check_interrupt_assertion();
+            }
+            RegisterOffset::EJECT => {
+                let ejected = value & self.present;
+                self.present &= !ejected;
+                for device in 0..32 {
+                    if ejected & (1 << device) != 0 {
+                        tracing::debug!(device, "guest ejected PCI slot");
+                        self.rt.eject_send.send(device);
+                    }
+                }
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(
+                    "Invalid write to PCI hot-plug device at offset {:?}",
+                    offset
+                );
+            }
+        }
+    }
+
+    /// evaluates whether the controller's interrupt should be
+    /// asserted or de-asserted
+    fn check_interrupt_assertion(&self) {
+        self.rt
+            .interrupt
+            .set_level(self.insert_pending | self.remove_pending != 0)
+    }
+}
+
+impl ChangeDeviceState for PciHotPlugDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        // Slot occupancy reflects the host's device configuration, so it
+        // survives a reset. Pending events are dropped, since the guest will
+        // enumerate the present devices at boot.
+        let Self {
+            rt,
+            mmio_region: _,
+            base_addr: _,
+            present: _,
+            insert_pending,
+            remove_pending,
+        } = self;
+        *insert_pending = 0;
+        *remove_pending = 0;
+        rt.interrupt.set_level(false);
+    }
+}
+
+impl ChipsetDevice for PciHotPlugDevice {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl MmioIntercept for PciHotPlugDevice {
+    fn mmio_read(&mut self, address: u64, data: &mut [u8]) -> IoResult {
+        assert_eq!(address & !PCI_HOTPLUG_MMIO_REGION_MASK, self.base_addr);
+        if data.len() == size_of::<u32>() {
+            let value = self.read_register(RegisterOffset(address & PCI_HOTPLUG_MMIO_REGION_MASK));
+            data.copy_from_slice(&value.to_ne_bytes());
+            IoResult::Ok
+        } else {
+            IoResult::Err(IoError::InvalidAccessSize)
+        }
+    }
+
+    fn mmio_write(&mut self, address: u64, data: &[u8]) -> IoResult {
+        assert_eq!(address & !PCI_HOTPLUG_MMIO_REGION_MASK, self.base_addr);
+        if let Ok(x) = data.try_into().map(u32::from_ne_bytes) {
+            self.write_register(RegisterOffset(address & PCI_HOTPLUG_MMIO_REGION_MASK), x);
+            IoResult::Ok
+        } else {
+            IoResult::Err(IoError::InvalidAccessSize)
+        }
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u64>)] {
+        std::slice::from_ref(&self.mmio_region)
+    }
+}
+
+impl PollDevice for PciHotPlugDevice {
+    fn poll_device(&mut self, cx: &mut std::task::Context<'_>) {
+        while let std::task::Poll::Ready(Some(request)) = self.rt.request_recv.poll_next_unpin(cx) {
+            self.handle_request(request);
+        }
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "chipset.pci_hotplug")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub present: u32,
+            #[mesh(2)]
+            pub insert_pending: u32,
+            #[mesh(3)]
+            pub remove_pending: u32,
+        }
+    }
+
+    impl SaveRestore for PciHotPlugDevice {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let Self {
+                rt: _,
+                mmio_region: _,
+                base_addr: _,
+                present,
+                insert_pending,
+                remove_pending,
+            } = *self;
+
+            Ok(state::SavedState {
+                present,
+                insert_pending,
+                remove_pending,
+            })
+        }
+
+        fn restore(&mut self, saved_state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                present,
+                insert_pending,
+                remove_pending,
+            } = saved_state;
+
+            self.present = present;
+            self.insert_pending = insert_pending;
+            self.remove_pending = remove_pending;
+
+            self.check_interrupt_assertion();
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::Context;
+    use vmcore::line_interrupt::LineInterrupt;
+
+    struct TestPlatform {
+        device: PciHotPlugDevice,
+        request_send: mesh::Sender<PciHotPlugRequest>,
+        eject_recv: mesh::Receiver<u8>,
+    }
+
+    fn create_test_platform() -> TestPlatform {
+        let (request_send, request_recv) = mesh::channel();
+        let (eject_send, eject_recv) = mesh::channel();
+        let device = PciHotPlugDevice::new(
+            PciHotPlugRuntimeDeps {
+                request_recv,
+                eject_send,
+                interrupt: LineInterrupt::detached(),
+            },
+            PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_X64,
+        );
+        TestPlatform {
+            device,
+            request_send,
+            eject_recv,
+        }
+    }
+
+    fn send_request(platform: &mut TestPlatform, request: PciHotPlugRequest) {
+        platform.request_send.send(request);
+        platform
+            .device
+            .poll_device(&mut Context::from_waker(std::task::Waker::noop()));
+    }
+
+    fn read(device: &mut PciHotPlugDevice, offset: RegisterOffset) -> u32 {
+        let mut bytes = [0; 4];
+        device
+            .mmio_read(device.base_addr + offset.0, &mut bytes)
+            .unwrap();
+        u32::from_ne_bytes(bytes)
+    }
+
+    fn write(device: &mut PciHotPlugDevice, offset: RegisterOffset, value: u32) {
+        device
+            .mmio_write(device.base_addr + offset.0, &value.to_ne_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_insert_and_eject() {
+        let mut platform = create_test_platform();
+        assert_eq!(read(&mut platform.device, RegisterOffset::PRESENT), 0);
+
+        send_request(&mut platform, PciHotPlugRequest::Insert(3));
+        let device = &mut platform.device;
+        assert_eq!(read(device, RegisterOffset::PRESENT), 1 << 3);
+        assert_eq!(read(device, RegisterOffset::INSERT_PENDING), 1 << 3);
+        assert_eq!(read(device, RegisterOffset::REMOVE_PENDING), 0);
+
+        write(device, RegisterOffset::EVENT_CLEAR, 1 << 3);
+        assert_eq!(read(device, RegisterOffset::INSERT_PENDING), 0);
+        assert_eq!(read(device, RegisterOffset::PRESENT), 1 << 3);
+
+        send_request(&mut platform, PciHotPlugRequest::Remove(3));
+        let device = &mut platform.device;
+        assert_eq!(read(device, RegisterOffset::REMOVE_PENDING), 1 << 3);
+        write(device, RegisterOffset::EVENT_CLEAR, 1 << 3);
+        assert_eq!(read(device, RegisterOffset::REMOVE_PENDING), 0);
+        assert_eq!(read(device, RegisterOffset::PRESENT), 1 << 3);
+
+        write(device, RegisterOffset::EJECT, 1 << 3);
+        assert_eq!(read(device, RegisterOffset::PRESENT), 0);
+        assert_eq!(platform.eject_recv.try_recv().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_remove_empty_slot() {
+        let mut platform = create_test_platform();
+        send_request(&mut platform, PciHotPlugRequest::Remove(4));
+        assert_eq!(
+            read(&mut platform.device, RegisterOffset::REMOVE_PENDING),
+            0
+        );
+
+        // Ejecting an empty slot is not reported to the host.
+        write(&mut platform.device, RegisterOffset::EJECT, 1 << 4);
+        assert!(platform.eject_recv.try_recv().is_err());
+    }
+}
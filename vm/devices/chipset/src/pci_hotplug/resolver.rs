@@ -0,0 +1,81 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for PCI hot-plug controllers.
+
+use super::PCI_HOTPLUG_IOAPIC_IRQ;
+use super::PCI_HOTPLUG_IRQ_NO;
+use super::PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_ARM;
+use super::PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_X64;
+use super::PciHotPlugDevice;
+use super::PciHotPlugRuntimeDeps;
+use chipset_device_resources::IRQ_LINE_SET;
+use chipset_device_resources::ResolveChipsetDeviceHandleParams;
+use chipset_device_resources::ResolvedChipsetDevice;
+use chipset_resources::pci_hotplug::PciHotPlugDeviceHandleAArch64;
+use chipset_resources::pci_hotplug::PciHotPlugDeviceHandleX64;
+use std::convert::Infallible;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+
+/// A resolver for PCI hot-plug controllers.
+pub struct PciHotPlugResolver;
+
+declare_static_resolver! {
+    PciHotPlugResolver,
+    (ChipsetDeviceHandleKind, PciHotPlugDeviceHandleX64),
+    (ChipsetDeviceHandleKind, PciHotPlugDeviceHandleAArch64),
+}
+
+impl ResolveResource<ChipsetDeviceHandleKind, PciHotPlugDeviceHandleX64> for PciHotPlugResolver {
+    type Output = ResolvedChipsetDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: PciHotPlugDeviceHandleX64,
+        input: ResolveChipsetDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(PciHotPlugDevice::new(
+            PciHotPlugRuntimeDeps {
+                request_recv: resource.request_recv,
+                eject_send: resource.eject_send,
+                interrupt: input.configure.new_line(
+                    IRQ_LINE_SET,
+                    "pci_hotplug",
+                    PCI_HOTPLUG_IOAPIC_IRQ,
+                ),
+            },
+            PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_X64,
+        )
+        .into())
+    }
+}
+
+impl ResolveResource<ChipsetDeviceHandleKind, PciHotPlugDeviceHandleAArch64>
+    for PciHotPlugResolver
+{
+    type Output = ResolvedChipsetDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: PciHotPlugDeviceHandleAArch64,
+        input: ResolveChipsetDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(PciHotPlugDevice::new(
+            PciHotPlugRuntimeDeps {
+                request_recv: resource.request_recv,
+                eject_send: resource.eject_send,
+                interrupt: input.configure.new_line(
+                    IRQ_LINE_SET,
+                    "pci_hotplug",
+                    PCI_HOTPLUG_IRQ_NO,
+                ),
+            },
+            PCI_HOTPLUG_MMIO_REGION_BASE_ADDRESS_ARM,
+        )
+        .into())
+    }
+}
@@ -35,6 +35,9 @@
 use chipset_device::pio::ControlPortIoIntercept;
 use chipset_device::pio::PortIoIntercept;
 use chipset_device::pio::RegisterPortIoIntercept;
+use chipset_device::poll_device::PollDevice;
+use chipset_resources::pm::WakeEvent;
+use futures::StreamExt;
 use inspect::Inspect;
 use inspect::InspectMut;
 use open_enum::open_enum;
@@ -75,15 +78,31 @@ pub enum DynReg: u8 {
 const CONTROL_SUSPEND_ENABLE_MASK: u16 = 0x2000; // Enable the specified suspend type
 const CONTROL_SUSPEND_TYPE_MASK: u16 = 0x1C00; // Suspend type field
 const ENABLE_TIMER_OVERFLOW_MASK: u16 = 0x0001; // Timer overflow should interrupt
+const ENABLE_RTC_MASK: u16 = 0x0400; // The RTC alarm should wake the system
 const GLOBAL_CONTROL_BIOS_RLS_MASK: u32 = 0x00000002; // Generate SCI?
 const STATUS_DEVICE_MASK: u16 = 0x0010; // One device event flags is set
 const STATUS_GP_MASK: u16 = 0x0080; // One of the GP event flags is set
 const STATUS_PM_MASK: u16 = 0x0040; // One of the PM event flags is set
 const TIMER_OVERFLOW_MASK: u16 = 0x0001; // The PM timer overflowed
+const STATUS_POWER_BUTTON_MASK: u16 = 0x0100; // The power button was pressed
+const STATUS_RTC_MASK: u16 = 0x0400; // The RTC alarm fired
+const STATUS_WAKE_MASK: u16 = 0x8000; // The system woke from a sleep state
+
+/// `SLP_TYP` value that requests the S5 (soft off) state.
+pub const SLEEP_TYPE_S5: u8 = 0;
+/// `SLP_TYP` value that requests the S4 (hibernate) state.
+pub const SLEEP_TYPE_S4: u8 = 1;
+/// `SLP_TYP` value that requests the S3 (suspend-to-RAM) state.
+pub const SLEEP_TYPE_S3: u8 = 2;
 
 /// Value that initiates a system reset when written to [`DynReg::RESET`].
 pub const RESET_VALUE: u8 = 0x01; // Reset the VM
 
+/// The lines corresponding to bits in General Purpose Event Block 0.
+pub const GPE0_LINES: std::ops::RangeInclusive<u32> = 0..=15;
+/// The line driven by the RTC alarm.
+pub const RTC_ALARM_LINE: u32 = 16;
+
 #[derive(Clone, Debug, Inspect)]
 struct PmState {
     #[inspect(hex)]
@@ -226,15 +245,17 @@ fn write_dynamic(&mut self, action: &mut PowerActionFn, offset: u8, value: u32,
                 let value = value as u16;
                 if (value & CONTROL_SUSPEND_ENABLE_MASK) != 0 {
                     // Get the suspend type, which is Bits[12:10] of the control register.
-                    // Our platform defines a suspend type of 0 as power off(S5) and a suspend
-                    // type of 1 as hibernate(S4); no other types are supported.The BIOS/firmware
-                    // ACPI tables must reflect these values to the guest.
+                    // Our platform defines a suspend type of 0 as power off(S5), a suspend
+                    // type of 1 as hibernate(S4), and a suspend type of 2 as suspend-to-RAM
+                    // (S3); no other types are supported. The BIOS/firmware ACPI tables must
+                    // reflect these values to the guest.
                     //
                     // Any other values will be ignored.
-                    let suspend_type = (value & CONTROL_SUSPEND_TYPE_MASK) >> 10;
+                    let suspend_type = ((value & CONTROL_SUSPEND_TYPE_MASK) >> 10) as u8;
                     match suspend_type {
-                        0 => (action)(PowerAction::PowerOff),
-                        1 => (action)(PowerAction::Hibernate),
+                        SLEEP_TYPE_S5 => (action)(PowerAction::PowerOff),
+                        SLEEP_TYPE_S4 => (action)(PowerAction::Hibernate),
+                        SLEEP_TYPE_S3 => (action)(PowerAction::Suspend),
                         _ => {}
                     }
                 }
@@ -329,6 +350,7 @@ fn write_dynamic(&mut self, action: &mut PowerActionFn, offset: u8, value: u32,
 pub enum PowerAction {
     PowerOff,
     Hibernate,
+    Suspend,
     Reboot,
 }
 
@@ -349,6 +371,15 @@ struct PowerManagementDeviceRt {
     /// Enable / Disable hypervisor PM timer assist (when available)
     #[inspect(skip)]
     pm_timer_assist: Option<Box<dyn PmTimerAssist>>,
+    /// Host-initiated wake events
+    #[inspect(skip)]
+    wake_recv: Option<mesh::Receiver<WakeEvent>>,
+    /// Host-injected general-purpose events
+    #[inspect(skip)]
+    gpe_recv: Option<mesh::Receiver<u32>>,
+    /// Requests to the VMM to wake the system from a sleep state
+    #[inspect(skip)]
+    wake_request_send: Option<mesh::Sender<WakeEvent>>,
 }
 
 /// This is used when running the UEFI BIOS. When passed via
@@ -399,6 +430,13 @@ impl PowerManagementDevice {
     /// - `pio_control` and `pio_status`: define where in the port IO space the
     ///   control/status registers get mapped to.
     /// - `enable_acpi_mode`: see the docs for [`EnableAcpiMode`]
+    /// - `wake_recv`: host-initiated wake events, delivered after the VMM has
+    ///   resumed the guest from a sleep state
+    /// - `gpe_recv`: host-injected general-purpose events, by GPE0 bit
+    ///   number, used to exercise the guest's SCI handling
+    /// - `wake_request_send`: requests that the VMM wake the system from a
+    ///   sleep state (including soft off), sent when the RTC alarm fires and
+    ///   the guest enabled RTC wake
     pub fn new(
         action: PowerActionFn,
         acpi_interrupt: LineInterrupt,
@@ -406,6 +444,9 @@ pub fn new(
         vmtime: VmTimeAccess,
         enable_acpi_mode: Option<EnableAcpiMode>,
         pm_timer_assist: Option<Box<dyn PmTimerAssist>>,
+        wake_recv: Option<mesh::Receiver<WakeEvent>>,
+        gpe_recv: Option<mesh::Receiver<u32>>,
+        wake_request_send: Option<mesh::Sender<WakeEvent>>,
     ) -> Self {
         let pio_dynamic = register_pio.new_io_region("dynamic", 0x37);
 
@@ -417,6 +458,9 @@ pub fn new(
                 acpi_interrupt,
                 vmtime,
                 pm_timer_assist,
+                wake_recv,
+                gpe_recv,
+                wake_request_send,
             },
             state: PmState::new(),
         };
@@ -500,6 +544,39 @@ pub fn pcat_facp_acpi_enable(&mut self, enable: bool) {
     pub fn power_action(&mut self) -> &mut PowerActionFn {
         &mut self.rt.action
     }
+
+    /// Latch the fixed event status bits corresponding to a wake from a sleep
+    /// state.
+    ///
+    /// The VMM is responsible for actually resuming the VPs at the guest's
+    /// waking vector; this just lets the guest's OSPM discover why it woke up.
+    pub fn wake(&mut self, event: WakeEvent) {
+        tracing::debug!(?event, "waking from sleep state");
+        let source = match event {
+            WakeEvent::PowerButton => STATUS_POWER_BUTTON_MASK,
+            WakeEvent::RtcAlarm => STATUS_RTC_MASK,
+            // Keyboard wake is signaled via WAK_STS alone.
+            WakeEvent::Keyboard => 0,
+        };
+        self.state.status |= STATUS_WAKE_MASK | source;
+        self.check_interrupt_assertion();
+    }
+
+    /// Handles the RTC alarm firing.
+    ///
+    /// If the system is sleeping (the guest set `SLP_EN`, and the device has
+    /// not been reset since) and the guest enabled RTC wake, ask the VMM to
+    /// wake it. The VMM delivers the wake event after resetting the device.
+    fn rtc_alarm(&mut self) {
+        let sleeping = self.state.control & CONTROL_SUSPEND_ENABLE_MASK != 0;
+        if !sleeping || self.state.resume_enable & ENABLE_RTC_MASK == 0 {
+            return;
+        }
+        if let Some(send) = &self.rt.wake_request_send {
+            tracing::info!("rtc alarm requesting wake");
+            send.send(WakeEvent::RtcAlarm);
+        }
+    }
 }
 
 impl ChangeDeviceState for PowerManagementDevice {
@@ -525,6 +602,36 @@ fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
     fn supports_line_interrupt_target(&mut self) -> Option<&mut dyn LineInterruptTarget> {
         Some(self)
     }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for PowerManagementDevice {
+    fn poll_device(&mut self, cx: &mut std::task::Context<'_>) {
+        while let Some(std::task::Poll::Ready(Some(event))) = self
+            .rt
+            .wake_recv
+            .as_mut()
+            .map(|recv| recv.poll_next_unpin(cx))
+        {
+            self.wake(event);
+        }
+        while let Some(std::task::Poll::Ready(Some(gpe))) = self
+            .rt
+            .gpe_recv
+            .as_mut()
+            .map(|recv| recv.poll_next_unpin(cx))
+        {
+            if gpe < u16::BITS {
+                tracing::debug!(gpe, "injecting general-purpose event");
+                self.set_irq(gpe, true);
+            } else {
+                tracelimit::warn_ratelimited!(gpe, "invalid general-purpose event");
+            }
+        }
+    }
 }
 
 fn aligned_offset(offset: u8) -> Option<u8> {
@@ -606,19 +713,26 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
     }
 }
 
-/// Target for lines corresponding to bits in General Purpose Event Block 0.
+/// Target for lines corresponding to bits in General Purpose Event Block 0,
+/// plus [`RTC_ALARM_LINE`].
 ///
 /// For a full general description of this register, see the ACPI Spec. See
 /// section 4.7.1 in the ACPI 2.0 spec.
 impl LineInterruptTarget for PowerManagementDevice {
     fn set_irq(&mut self, vector: u32, high: bool) {
+        if vector == RTC_ALARM_LINE {
+            if high {
+                self.rtc_alarm();
+            }
+            return;
+        }
         // Latch the bit; it can only be cleared by the guest.
         self.state.general_purpose_status |= (high as u16) << vector;
         self.check_interrupt_assertion();
     }
 
     fn valid_lines(&self) -> &[std::ops::RangeInclusive<u32>] {
-        &[0..=15]
+        &[GPE0_LINES, RTC_ALARM_LINE..=RTC_ALARM_LINE]
     }
 }
 
@@ -0,0 +1,165 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! QEMU-compatible pvpanic device.
+//!
+//! The device is a single byte-wide I/O port, described to the guest by an
+//! ACPI device with `_HID` `QEMU0001` (see `acpi::ssdt::Ssdt::add_pvpanic`).
+//! Reading the port returns the set of events the device supports, and the
+//! guest writes one of those events when its kernel panics. Each event is
+//! forwarded to the VMM over a mesh channel.
+
+pub mod resolver;
+
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::pio::PortIoIntercept;
+use chipset_resources::pvpanic::PvPanicEvent;
+use inspect::InspectMut;
+use std::ops::RangeInclusive;
+use vmcore::device_state::ChangeDeviceState;
+
+/// The I/O port QEMU uses for the pvpanic device.
+pub const PVPANIC_IO_PORT: u16 = 0x505;
+
+/// The guest kernel panicked.
+const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest kernel panicked and is loading a crash kernel.
+const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+const PVPANIC_SUPPORTED: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// QEMU-compatible pvpanic device.
+#[derive(InspectMut)]
+pub struct PvPanicDevice {
+    // Runtime glue
+    #[inspect(skip)]
+    event_send: mesh::Sender<PvPanicEvent>,
+
+    // Static configuration
+    #[inspect(skip)]
+    io_region: (&'static str, RangeInclusive<u16>),
+    #[inspect(hex)]
+    io_port: u16,
+
+    // Volatile state
+    events: u64,
+}
+
+impl PvPanicDevice {
+    /// Create a new pvpanic device listening on `io_port`.
+    pub fn new(event_send: mesh::Sender<PvPanicEvent>, io_port: u16) -> Self {
+        PvPanicDevice {
+            event_send,
+            io_region: ("pvpanic", io_port..=io_port),
+            io_port,
+            events: 0,
+        }
+    }
+}
+
+impl ChangeDeviceState for PvPanicDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {}
+}
+
+impl ChipsetDevice for PvPanicDevice {
+    fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
+        Some(self)
+    }
+}
+
+impl PortIoIntercept for PvPanicDevice {
+    fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
+        if io_port != self.io_port {
+            return IoResult::Err(IoError::InvalidRegister);
+        }
+        if data.len() != 1 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+        data[0] = PVPANIC_SUPPORTED;
+        IoResult::Ok
+    }
+
+    fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
+        if io_port != self.io_port {
+            return IoResult::Err(IoError::InvalidRegister);
+        }
+        let &[value] = data else {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        };
+
+        // CRASH_LOADED implies a panic, so report only the more specific
+        // event if the guest sets both.
+        let event = if value & PVPANIC_CRASH_LOADED != 0 {
+            PvPanicEvent::CrashLoaded
+        } else if value & PVPANIC_PANICKED != 0 {
+            PvPanicEvent::Panicked
+        } else {
+            tracelimit::warn_ratelimited!(value, "unsupported pvpanic event");
+            return IoResult::Ok;
+        };
+
+        tracing::warn!(?event, "guest reported panic via pvpanic");
+        self.events += 1;
+        self.event_send.send(event);
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u16>)] {
+        std::slice::from_ref(&self.io_region)
+    }
+}
+
+mod save_restore {
+    use super::PvPanicDevice;
+    use vmcore::save_restore::NoSavedState;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    impl SaveRestore for PvPanicDevice {
+        type SavedState = NoSavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            Ok(NoSavedState)
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let NoSavedState = state;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic() {
+        let (event_send, mut event_recv) = mesh::channel();
+        let mut device = PvPanicDevice::new(event_send, PVPANIC_IO_PORT);
+
+        let mut data = [0];
+        device.io_read(PVPANIC_IO_PORT, &mut data).unwrap();
+        assert_eq!(data[0], PVPANIC_SUPPORTED);
+
+        device.io_write(PVPANIC_IO_PORT, &[0]).unwrap();
+        assert!(event_recv.try_recv().is_err());
+
+        device
+            .io_write(PVPANIC_IO_PORT, &[PVPANIC_PANICKED])
+            .unwrap();
+        assert_eq!(event_recv.try_recv().unwrap(), PvPanicEvent::Panicked);
+
+        device
+            .io_write(PVPANIC_IO_PORT, &[PVPANIC_PANICKED | PVPANIC_CRASH_LOADED])
+            .unwrap();
+        assert_eq!(event_recv.try_recv().unwrap(), PvPanicEvent::CrashLoaded);
+    }
+}
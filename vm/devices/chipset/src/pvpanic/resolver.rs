@@ -0,0 +1,35 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for pvpanic devices.
+
+use super::PVPANIC_IO_PORT;
+use super::PvPanicDevice;
+use chipset_device_resources::ResolveChipsetDeviceHandleParams;
+use chipset_device_resources::ResolvedChipsetDevice;
+use chipset_resources::pvpanic::PvPanicDeviceHandle;
+use std::convert::Infallible;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+
+/// A resolver for pvpanic devices.
+pub struct PvPanicResolver;
+
+declare_static_resolver! {
+    PvPanicResolver,
+    (ChipsetDeviceHandleKind, PvPanicDeviceHandle),
+}
+
+impl ResolveResource<ChipsetDeviceHandleKind, PvPanicDeviceHandle> for PvPanicResolver {
+    type Output = ResolvedChipsetDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: PvPanicDeviceHandle,
+        _input: ResolveChipsetDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(PvPanicDevice::new(resource.event_send, PVPANIC_IO_PORT).into())
+    }
+}
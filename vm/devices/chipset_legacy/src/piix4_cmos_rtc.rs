@@ -59,6 +59,8 @@ pub fn new(
             inner: chipset::cmos_rtc::Rtc::new(
                 real_time_source,
                 interrupt,
+                // The PIIX4 PM device does not support RTC wake.
+                LineInterrupt::detached(),
                 vmtime_source,
                 0x32,
                 initial_cmos,
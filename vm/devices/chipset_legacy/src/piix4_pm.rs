@@ -153,6 +153,9 @@ pub fn new(
                 vmtime,
                 None, // manually configured
                 pm_timer_assist,
+                None,
+                None,
+                None,
             ),
             cfg_space,
             rt: Piix4PmRt {
@@ -90,5 +90,136 @@ pub fn default_present() -> Self {
                 ac_online: true,
             }
         }
+
+        /// Returns the remaining capacity as a percentage of the max capacity,
+        /// or `None` if no battery is present.
+        pub fn charge_percent(&self) -> Option<u8> {
+            (self.battery_present && self.max_capacity != 0).then(|| {
+                (u64::from(self.remaining_capacity) * 100 / u64::from(self.max_capacity)).min(100)
+                    as u8
+            })
+        }
+
+        /// Sets the remaining capacity to `percent` of the max capacity.
+        pub fn set_charge_percent(&mut self, percent: u8) {
+            let percent = percent.min(100);
+            self.remaining_capacity =
+                (u64::from(self.max_capacity) * u64::from(percent) / 100) as u32;
+            self.update_charging();
+        }
+
+        /// Connects or disconnects AC power.
+        pub fn set_ac_online(&mut self, ac_online: bool) {
+            self.ac_online = ac_online;
+            self.update_charging();
+        }
+
+        /// Inserts or removes the battery.
+        pub fn set_battery_present(&mut self, battery_present: bool) {
+            self.battery_present = battery_present;
+            self.update_charging();
+        }
+
+        /// Updates the charging state to match the AC state and remaining
+        /// capacity: the battery charges on AC power until it is full, and
+        /// discharges otherwise.
+        fn update_charging(&mut self) {
+            self.charging = self.battery_present
+                && self.ac_online
+                && self.remaining_capacity < self.max_capacity;
+            self.discharging = self.battery_present && !self.ac_online;
+        }
+    }
+}
+
+pub mod pm {
+    //! Resource definitions for the ACPI power management device.
+
+    use inspect::Inspect;
+    use mesh::MeshPayload;
+
+    /// A host-initiated event that wakes the guest from a sleep state.
+    #[derive(Debug, Clone, Copy, Inspect, PartialEq, Eq, MeshPayload)]
+    pub enum WakeEvent {
+        /// The power button was pressed.
+        PowerButton,
+        /// The RTC alarm fired.
+        RtcAlarm,
+        /// A key was pressed on the keyboard.
+        Keyboard,
+    }
+}
+
+pub mod pci_hotplug {
+    //! Resource definitions for the ACPI PCI hot-plug controller.
+
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::ChipsetDeviceHandleKind;
+
+    /// A handle to an ACPI PCI hot-plug controller for x64.
+    #[derive(MeshPayload)]
+    pub struct PciHotPlugDeviceHandleX64 {
+        /// Channel to receive hot-plug requests from the host.
+        pub request_recv: mesh::Receiver<PciHotPlugRequest>,
+        /// Channel to report the PCI device numbers of slots ejected by the
+        /// guest.
+        pub eject_send: mesh::Sender<u8>,
+    }
+
+    impl ResourceId<ChipsetDeviceHandleKind> for PciHotPlugDeviceHandleX64 {
+        const ID: &'static str = "pciHotPlugX64";
+    }
+
+    /// A handle to an ACPI PCI hot-plug controller for aarch64.
+    #[derive(MeshPayload)]
+    pub struct PciHotPlugDeviceHandleAArch64 {
+        /// Channel to receive hot-plug requests from the host.
+        pub request_recv: mesh::Receiver<PciHotPlugRequest>,
+        /// Channel to report the PCI device numbers of slots ejected by the
+        /// guest.
+        pub eject_send: mesh::Sender<u8>,
+    }
+
+    impl ResourceId<ChipsetDeviceHandleKind> for PciHotPlugDeviceHandleAArch64 {
+        const ID: &'static str = "pciHotPlugAArch64";
+    }
+
+    /// A hot-plug request for the slot with the given PCI device number.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, MeshPayload)]
+    pub enum PciHotPlugRequest {
+        /// A device has been inserted into the slot.
+        Insert(u8),
+        /// Ask the guest to release the device in the slot. The guest ejects
+        /// the slot once it is done with the device.
+        Remove(u8),
+    }
+}
+
+pub mod pvpanic {
+    //! Resource definitions for the pvpanic device.
+
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::ChipsetDeviceHandleKind;
+
+    /// A handle to a QEMU-compatible pvpanic device on the x86 I/O port bus.
+    #[derive(MeshPayload)]
+    pub struct PvPanicDeviceHandle {
+        /// Channel to report the events written by the guest.
+        pub event_send: mesh::Sender<PvPanicEvent>,
+    }
+
+    impl ResourceId<ChipsetDeviceHandleKind> for PvPanicDeviceHandle {
+        const ID: &'static str = "pvpanic";
+    }
+
+    /// An event written by the guest to the pvpanic device.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, MeshPayload)]
+    pub enum PvPanicEvent {
+        /// The guest kernel panicked.
+        Panicked,
+        /// The guest kernel panicked and is loading a crash kernel.
+        CrashLoaded,
     }
 }
@@ -0,0 +1,288 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for enrolling user-provided secure boot keys.
+//!
+//! [`SecureBootKeys`] collects PK/KEK/db/dbx entries from PEM or DER encoded
+//! X.509 certificates and SHA-256 digests, and converts them into a
+//! [`CustomVarsDelta`] which can be applied on top of a template, or written
+//! out as a UEFI custom nvram variables JSON file with [`delta_to_json`].
+
+use base64::Engine;
+use firmware_uefi_custom_vars::CustomVar;
+use firmware_uefi_custom_vars::Sha256Digest;
+use firmware_uefi_custom_vars::Signature;
+use firmware_uefi_custom_vars::X509Cert;
+use firmware_uefi_custom_vars::delta::CustomVarsDelta;
+use firmware_uefi_custom_vars::delta::SignatureDelta;
+use firmware_uefi_custom_vars::delta::SignatureDeltaVec;
+use firmware_uefi_custom_vars::delta::SignaturesAppend;
+use firmware_uefi_custom_vars::delta::SignaturesDelta;
+use firmware_uefi_custom_vars::delta::SignaturesReplace;
+use serde_json::Value;
+use serde_json::json;
+use thiserror::Error;
+use zerocopy::IntoBytes;
+
+#[derive(Debug, Error)]
+pub enum ParseCertError {
+    #[error("no PEM certificate found")]
+    MissingPemCertificate,
+    #[error("PEM certificate is not terminated")]
+    UnterminatedPem,
+    #[error("invalid base64 in PEM certificate")]
+    Base64(#[source] base64::DecodeError),
+    #[error("certificate is not DER encoded")]
+    NotDer,
+}
+
+#[derive(Debug, Error)]
+pub enum KeysError {
+    #[error("a PK is required when there are no base secure boot keys to append to")]
+    MissingPk,
+}
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// Parses a single X.509 certificate, either DER encoded or PEM encoded.
+pub fn parse_x509_cert(data: &[u8]) -> Result<X509Cert, ParseCertError> {
+    // A DER certificate is an ASN.1 SEQUENCE.
+    if data.first() == Some(&0x30) {
+        return Ok(X509Cert(data.to_vec()));
+    }
+
+    let text = std::str::from_utf8(data).map_err(|_| ParseCertError::NotDer)?;
+    let (_, rest) = text
+        .split_once(PEM_BEGIN)
+        .ok_or(ParseCertError::MissingPemCertificate)?;
+    let (body, _) = rest
+        .split_once(PEM_END)
+        .ok_or(ParseCertError::UnterminatedPem)?;
+    let body: String = body.split_whitespace().collect();
+    let der = base64::engine::general_purpose::STANDARD
+        .decode(body)
+        .map_err(ParseCertError::Base64)?;
+    if der.first() != Some(&0x30) {
+        return Err(ParseCertError::NotDer);
+    }
+    Ok(X509Cert(der))
+}
+
+/// A set of user-provided secure boot keys.
+#[derive(Debug, Default, Clone)]
+pub struct SecureBootKeys {
+    /// The platform key. If set, the keys replace the base secure boot
+    /// variables instead of being appended to them.
+    pub pk: Option<X509Cert>,
+    pub kek: Vec<X509Cert>,
+    pub db: Vec<X509Cert>,
+    pub dbx: Vec<X509Cert>,
+    pub dbx_hashes: Vec<Sha256Digest>,
+}
+
+impl SecureBootKeys {
+    /// Returns true if no keys have been specified.
+    pub fn is_empty(&self) -> bool {
+        self.pk.is_none()
+            && self.kek.is_empty()
+            && self.db.is_empty()
+            && self.dbx.is_empty()
+            && self.dbx_hashes.is_empty()
+    }
+
+    /// Converts the keys into a delta.
+    ///
+    /// If a PK was specified, the delta replaces the secure boot variables.
+    /// When `has_base` is true, any of KEK, db, and dbx that were not
+    /// specified keep the base values; otherwise they are left empty.
+    ///
+    /// Without a PK, the keys are appended to the base variables, which must
+    /// exist.
+    pub fn into_delta(self, has_base: bool) -> Result<CustomVarsDelta, KeysError> {
+        fn certs(certs: Vec<X509Cert>) -> Vec<Signature> {
+            // Each certificate gets its own signature list, since the entries
+            // of a list must all be the same size.
+            certs
+                .into_iter()
+                .map(|cert| Signature::X509(vec![cert]))
+                .collect()
+        }
+
+        let mut dbx = certs(self.dbx);
+        if !self.dbx_hashes.is_empty() {
+            dbx.push(Signature::Sha256(self.dbx_hashes));
+        }
+        let kek = certs(self.kek);
+        let db = certs(self.db);
+
+        let signatures = if let Some(pk) = self.pk {
+            let replace = |sigs: Vec<Signature>| {
+                if sigs.is_empty() && has_base {
+                    SignatureDeltaVec::Default
+                } else {
+                    SignatureDeltaVec::Sigs(sigs)
+                }
+            };
+            SignaturesDelta::Replace(SignaturesReplace {
+                pk: SignatureDelta::Sig(Signature::X509(vec![pk])),
+                kek: replace(kek),
+                db: replace(db),
+                dbx: replace(dbx),
+                moklist: None,
+                moklistx: None,
+            })
+        } else {
+            if !has_base {
+                return Err(KeysError::MissingPk);
+            }
+            let append = |sigs: Vec<Signature>| (!sigs.is_empty()).then_some(sigs);
+            SignaturesDelta::Append(SignaturesAppend {
+                kek: append(kek),
+                db: append(db),
+                dbx: append(dbx),
+                moklist: None,
+                moklistx: None,
+            })
+        };
+
+        Ok(CustomVarsDelta {
+            signatures,
+            custom_vars: Vec::new(),
+        })
+    }
+}
+
+/// Serializes `delta` as a UEFI custom nvram variables JSON file, in the
+/// format accepted by [`load_delta_from_json`](crate::load_delta_from_json).
+pub fn delta_to_json(delta: &CustomVarsDelta) -> String {
+    let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+
+    let signature = |sig: &Signature| match sig {
+        Signature::X509(certs) => json!({
+            "type": "x509",
+            "value": certs.iter().map(|cert| b64(&cert.0)).collect::<Vec<_>>(),
+        }),
+        Signature::Sha256(digests) => json!({
+            "type": "sha256",
+            "value": digests.iter().map(|digest| b64(&digest.0)).collect::<Vec<_>>(),
+        }),
+    };
+    let signatures = |sigs: &[Signature]| Value::Array(sigs.iter().map(signature).collect());
+    let delta_vec = |sigs: &SignatureDeltaVec| match sigs {
+        SignatureDeltaVec::Sigs(sigs) => signatures(sigs),
+        SignatureDeltaVec::Default => json!([{ "type": "Default" }]),
+    };
+
+    let mut settings = serde_json::Map::new();
+    match &delta.signatures {
+        SignaturesDelta::Append(append) => {
+            let mut sigs = serde_json::Map::new();
+            for (name, value) in [
+                ("KEK", &append.kek),
+                ("db", &append.db),
+                ("dbx", &append.dbx),
+                ("MokList", &append.moklist),
+                ("MokListX", &append.moklistx),
+            ] {
+                if let Some(value) = value {
+                    sigs.insert(name.into(), signatures(value));
+                }
+            }
+            settings.insert("signatureMode".into(), "Append".into());
+            settings.insert("signatures".into(), sigs.into());
+        }
+        SignaturesDelta::Replace(replace) => {
+            let mut sigs = serde_json::Map::new();
+            sigs.insert(
+                "PK".into(),
+                match &replace.pk {
+                    SignatureDelta::Sig(sig) => signature(sig),
+                    SignatureDelta::Default => json!({ "type": "Default" }),
+                },
+            );
+            sigs.insert("KEK".into(), delta_vec(&replace.kek));
+            sigs.insert("db".into(), delta_vec(&replace.db));
+            sigs.insert("dbx".into(), delta_vec(&replace.dbx));
+            for (name, value) in [
+                ("MokList", &replace.moklist),
+                ("MokListX", &replace.moklistx),
+            ] {
+                if let Some(value) = value {
+                    sigs.insert(name.into(), delta_vec(value));
+                }
+            }
+            settings.insert("signatureMode".into(), "Replace".into());
+            settings.insert("signatures".into(), sigs.into());
+        }
+    }
+
+    for (name, CustomVar { guid, attr, value }) in &delta.custom_vars {
+        settings.insert(
+            name.clone(),
+            json!({
+                "guid": b64(guid.as_bytes()),
+                "attributes": b64(&attr.to_le_bytes()),
+                "value": b64(value),
+            }),
+        );
+    }
+
+    let root = json!({
+        "type": "Microsoft.Compute/disks",
+        "properties": {
+            "uefiSettings": settings,
+        },
+    });
+    serde_json::to_string_pretty(&root).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A truncated certificate; only the outer SEQUENCE tag matters here.
+    const DER: &[u8] = &[0x30, 0x82, 0x01, 0x0a, 0x02, 0x01, 0x01];
+
+    fn pem(der: &[u8]) -> String {
+        format!(
+            "subject=CN=test\n{PEM_BEGIN}\n{}\n{PEM_END}\n",
+            base64::engine::general_purpose::STANDARD.encode(der)
+        )
+    }
+
+    #[test]
+    fn parse_cert() {
+        assert_eq!(parse_x509_cert(DER).unwrap().0, DER);
+        assert_eq!(parse_x509_cert(pem(DER).as_bytes()).unwrap().0, DER);
+        assert!(parse_x509_cert(b"not a cert").is_err());
+        assert!(parse_x509_cert(pem(b"xyz").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let keys = SecureBootKeys {
+            pk: Some(X509Cert(DER.to_vec())),
+            kek: vec![X509Cert(DER.to_vec())],
+            db: vec![X509Cert(DER.to_vec()), X509Cert(DER.to_vec())],
+            dbx: Vec::new(),
+            dbx_hashes: vec![Sha256Digest([1; 32])],
+        };
+        let json = delta_to_json(&keys.clone().into_delta(false).unwrap());
+        let delta = crate::load_delta_from_json(json.as_bytes()).unwrap();
+        let SignaturesDelta::Replace(replace) = delta.signatures else {
+            panic!("expected replace")
+        };
+        let SignatureDeltaVec::Sigs(db) = replace.db else {
+            panic!("expected db")
+        };
+        assert_eq!(db.len(), 2);
+
+        // Without a PK, the keys can only be appended to a base.
+        let keys = SecureBootKeys { pk: None, ..keys };
+        assert!(keys.clone().into_delta(false).is_err());
+        let json = delta_to_json(&keys.into_delta(true).unwrap());
+        let delta = crate::load_delta_from_json(json.as_bytes()).unwrap();
+        assert!(matches!(delta.signatures, SignaturesDelta::Append(_)));
+    }
+}
@@ -14,6 +14,8 @@
 
 use thiserror::Error;
 
+pub mod keys;
+
 #[derive(Debug, Error)]
 pub enum ParseJsonError {
     #[error("malformed JSON: {0}")]
@@ -23,3 +23,6 @@
 
 /// MsvmPkg: `gSyntheticStorageClassGuid`
 pub const VM_DISK_VMBUS_CHILD_GUID: Guid = guid::guid!("ba6163d9-04a1-4d29-b605-72e2ffb1dc7f");
+
+/// MsvmPkg: `gSyntheticNetworkClassGuid`
+pub const VM_NET_VMBUS_CHILD_GUID: Guid = guid::guid!("f8615163-df3e-46c5-913f-f2d2f965ed0e");
@@ -143,6 +143,36 @@ pub fn view(self) -> io::Result<View> {
             offset: self.offset,
         })
     }
+
+    /// Splits the accessor in two, so that the framebuffer can be read from two
+    /// places, such as a VNC server and a screenshot command.
+    ///
+    /// The returned future forwards format changes to both accessors, and must
+    /// be polled (e.g. by spawning it) for them to see resolution changes.
+    pub fn split(self) -> io::Result<(Self, Self, impl 'static + Send + Future<Output = ()>)> {
+        let (send_a, recv_a) = mesh::channel();
+        let (send_b, recv_b) = mesh::channel();
+        let b = Self {
+            vram: self.vram.try_clone()?,
+            len: self.len,
+            format_recv: recv_b,
+            offset: self.offset,
+        };
+        let mut format_recv = self.format_recv;
+        let a = Self {
+            vram: self.vram,
+            len: self.len,
+            format_recv: recv_a,
+            offset: self.offset,
+        };
+        let relay = async move {
+            while let Ok(format) = format_recv.recv().await {
+                send_a.send(format);
+                send_b.send(format);
+            }
+        };
+        Ok((a, b, relay))
+    }
 }
 
 /// A mapped view of the framebuffer.
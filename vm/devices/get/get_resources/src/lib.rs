@@ -44,6 +44,7 @@ impl ResourceId<VmbusDeviceHandleKind> for GuestCrashDeviceHandle {
 
 /// Guest Emulation Device resources.
 pub mod ged {
+    use chipset_resources::battery::HostBatteryUpdate;
     use mesh::MeshPayload;
     use mesh::error::RemoteError;
     use mesh::payload::Protobuf;
@@ -173,6 +174,10 @@ pub enum GuestEmulationRequest {
         SaveGuestVtl2State(Rpc<GuestServicingFlags, Result<(), SaveRestoreError>>),
         /// Update the VTL2 settings.
         ModifyVtl2Settings(Rpc<Vec<u8>, Result<(), ModifyVtl2SettingsError>>),
+        /// Send an updated battery state to VTL2.
+        SetBatteryStatus(HostBatteryUpdate),
+        /// Send a new VM generation ID to VTL2.
+        UpdateGenerationId([u8; 16]),
     }
 
     /// An error waiting to start VTL0.
@@ -17,6 +17,7 @@
 pub mod test_utilities;
 
 use async_trait::async_trait;
+use chipset_resources::battery::HostBatteryUpdate;
 use core::mem::size_of;
 use disk_backend::Disk;
 use futures::FutureExt;
@@ -232,6 +233,9 @@ pub struct GuestEmulationDevice {
     #[inspect(skip)]
     igvm_attest_test_config: Option<IgvmAttestTestConfig>,
 
+    /// The battery state to report to VTL2.
+    battery_state: HostBatteryUpdate,
+
     /// State machine for `handle_igvm_attest`
     #[inspect(skip)]
     igvm_attest_state: IgvmAttestState,
@@ -271,6 +275,7 @@ pub fn new(
             last_save_restore_buf_len: 0,
             igvm_attest_state: IgvmAttestState::Init,
             igvm_attest_test_config,
+            battery_state: HostBatteryUpdate::default_present(),
         }
     }
 
@@ -463,14 +468,13 @@ async fn process(
                     tracing::info!("version negotiated successfully!");
                     self.state = GedState::Ready;
 
-                    // Send a hardcoded battery status update
+                    // Send the current battery status, which starts out as an
+                    // arbitrary present and charging battery and can be
+                    // changed at runtime with `SetBatteryStatus`.
                     //
                     // TODO: Need to subscribe to WNF to get real battery notifications from the host
                     // and query NT for the host battery status details.
-                    //
-                    // For now, we just hardcode an initial arbitrary battery status update
-                    // to the guest for testing battery presence in our VMM tests.
-                    let _ = self.send_hardcoded_battery_update();
+                    let _ = self.send_battery_update(&state.battery_state);
                 }
                 GedState::Ready => {
                     let mut message_buf = [0; get_protocol::MAX_MESSAGE_SIZE];
@@ -610,6 +614,16 @@ fn handle_guest_request_input(
 
                 self.modify = Some(response);
             }
+            GuestEmulationRequest::SetBatteryStatus(update) => {
+                state.battery_state = update;
+                self.send_battery_update(&update)?;
+            }
+            GuestEmulationRequest::UpdateGenerationId(generation_id) => {
+                let notification = get_protocol::UpdateGenerationId::new(generation_id);
+                self.channel
+                    .try_send(notification.as_bytes())
+                    .map_err(Error::Vmbus)?;
+            }
             GuestEmulationRequest::SaveGuestVtl2State(rpc) => {
                 let r = (|| {
                     if self.save.is_some() {
@@ -1458,15 +1472,19 @@ fn handle_device_platform_settings_v2(
         Ok(())
     }
 
-    fn send_hardcoded_battery_update(&mut self) -> Result<(), Error> {
-        let mut flags = BatteryStatusFlags::new();
-        flags.set_ac_online(true);
-        flags.set_battery_present(true);
-        flags.set_charging(true);
-        flags.set_discharging(false);
-        flags.set_reserved(0);
-
-        let response = BatteryStatusNotification::new(flags, 1000, 950, 1);
+    fn send_battery_update(&mut self, update: &HostBatteryUpdate) -> Result<(), Error> {
+        let flags = BatteryStatusFlags::new()
+            .with_ac_online(update.ac_online)
+            .with_battery_present(update.battery_present)
+            .with_charging(update.charging)
+            .with_discharging(update.discharging);
+
+        let response = BatteryStatusNotification::new(
+            flags,
+            update.max_capacity,
+            update.remaining_capacity,
+            update.rate,
+        );
         self.channel
             .try_send(response.as_bytes())
             .map_err(Error::Vmbus)?;
@@ -0,0 +1,303 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The guest file copy IC, for pushing host files into the guest.
+
+use crate::common::IcPipe;
+use crate::common::NegotiateState;
+use crate::common::Versions;
+use async_trait::async_trait;
+use futures::StreamExt;
+use guestmem::GuestMemory;
+use hyperv_ic_protocol::HeaderFlags;
+use hyperv_ic_protocol::MessageType;
+use hyperv_ic_protocol::Status;
+use hyperv_ic_protocol::fcopy as proto;
+use hyperv_ic_resources::fcopy::CopyFileParams;
+use hyperv_ic_resources::fcopy::FcopyRpc;
+use inspect::Inspect;
+use inspect::InspectMut;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use task_control::Cancelled;
+use task_control::StopTask;
+use thiserror::Error;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::save_restore::NoSavedState;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+const FCOPY_VERSIONS: &[hyperv_ic_protocol::Version] =
+    &[proto::FCOPY_VERSION_1, proto::FCOPY_VERSION_1_1];
+
+/// File copy IC device.
+#[derive(InspectMut)]
+pub struct FcopyIc {
+    #[inspect(skip)]
+    recv: mesh::Receiver<FcopyRpc>,
+}
+
+#[doc(hidden)]
+#[derive(InspectMut)]
+pub struct FcopyChannel {
+    #[inspect(mut)]
+    pipe: IcPipe,
+    state: ChannelState,
+    files_copied: u64,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Negotiate(#[inspect(rename = "state")] NegotiateState),
+    Ready { versions: Versions },
+    Failed,
+}
+
+#[derive(Debug, Error)]
+enum CopyError {
+    #[error("guest failed the request: {0:x?}")]
+    Guest(Status),
+    #[error("failed to read the source file")]
+    Read(#[source] io::Error),
+    #[error("{0} is too long")]
+    NameTooLong(&'static str),
+    #[error("channel failure")]
+    Channel(#[source] anyhow::Error),
+}
+
+impl FcopyIc {
+    /// Create a new file copy IC device.
+    pub fn new(recv: mesh::Receiver<FcopyRpc>) -> Self {
+        Self { recv }
+    }
+
+    async fn next_request(&mut self) -> FcopyRpc {
+        match self.recv.next().await {
+            Some(rpc) => rpc,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for FcopyIc {
+    type SavedState = NoSavedState;
+    type Runner = FcopyChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "fcopy_ic".to_owned(),
+            instance_id: proto::INSTANCE_ID,
+            interface_id: proto::INTERFACE_ID,
+            channel_type: ChannelType::Pipe { message_mode: true },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond().merge(self).merge(runner);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        FcopyChannel::new(channel)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async { runner.process(self).await })
+            .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        None
+    }
+}
+
+impl FcopyChannel {
+    fn new(channel: RawAsyncChannel<GpadlRingMem>) -> Result<Self, ChannelOpenError> {
+        let pipe = IcPipe::new(channel)?;
+        Ok(Self {
+            pipe,
+            state: ChannelState::Negotiate(NegotiateState::default()),
+            files_copied: 0,
+        })
+    }
+
+    async fn process(&mut self, ic: &mut FcopyIc) -> ! {
+        loop {
+            match &mut self.state {
+                ChannelState::Negotiate(state) => {
+                    match self.pipe.negotiate(state, FCOPY_VERSIONS).await {
+                        Ok(Some(versions)) => self.state = ChannelState::Ready { versions },
+                        Ok(None) => {}
+                        Err(err) => {
+                            tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "fcopy ic error"
+                            );
+                            self.state = ChannelState::Failed;
+                        }
+                    }
+                }
+                &mut ChannelState::Ready { versions } => {
+                    let FcopyRpc::CopyFile(rpc) = ic.next_request().await;
+                    let (params, rpc) = rpc.split();
+                    match self.copy_file(&versions, params).await {
+                        Ok(()) => {
+                            self.files_copied += 1;
+                            rpc.complete(Ok(()));
+                        }
+                        Err(CopyError::Channel(err)) => {
+                            tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "fcopy ic error"
+                            );
+                            self.state = ChannelState::Failed;
+                            rpc.fail(anyhow::anyhow!("fcopy channel failed"));
+                        }
+                        Err(err) => rpc.fail(err),
+                    }
+                }
+                ChannelState::Failed => {
+                    let FcopyRpc::CopyFile(rpc) = ic.next_request().await;
+                    rpc.fail(anyhow::anyhow!("fcopy channel failed"));
+                }
+            }
+        }
+    }
+
+    async fn copy_file(
+        &mut self,
+        versions: &Versions,
+        params: CopyFileParams,
+    ) -> Result<(), CopyError> {
+        let CopyFileParams {
+            file,
+            guest_dir,
+            guest_file_name,
+            overwrite,
+            create_dir,
+        } = params;
+
+        let file_size = file.metadata().map_err(CopyError::Read)?.len();
+        let mut message = proto::StartCopyMessage::new_zeroed();
+        message.header.operation = proto::Operation::START_FILE_COPY;
+        write_str(&mut message.file_name, &guest_file_name)
+            .ok_or(CopyError::NameTooLong("file name"))?;
+        write_str(&mut message.path_name, &guest_dir).ok_or(CopyError::NameTooLong("path"))?;
+        if overwrite {
+            message.copy_flags |= proto::COPY_FLAG_OVERWRITE;
+        }
+        if create_dir {
+            message.copy_flags |= proto::COPY_FLAG_CREATE_PATH;
+        }
+        message.file_size = file_size.into();
+        self.request(versions, message.as_bytes()).await?;
+
+        let r = self.write_file(versions, file).await;
+        let operation = if r.is_ok() {
+            proto::Operation::COMPLETE_FCOPY
+        } else {
+            // Have the guest delete the partial file.
+            proto::Operation::CANCEL_FCOPY
+        };
+        let header = proto::FcopyHeader {
+            operation,
+            ..FromZeros::new_zeroed()
+        };
+        match self.request(versions, header.as_bytes()).await {
+            Err(err @ CopyError::Channel(_)) => Err(err),
+            complete => r.and(complete),
+        }
+    }
+
+    async fn write_file(&mut self, versions: &Versions, mut file: File) -> Result<(), CopyError> {
+        let mut message = Box::new(proto::WriteToFileMessage::new_zeroed());
+        message.header.operation = proto::Operation::WRITE_TO_FILE;
+        let mut offset = 0;
+        loop {
+            let len;
+            (file, message, len) = blocking::unblock(move || {
+                let r = read_fragment(&mut file, &mut message.data);
+                (file, message, r)
+            })
+            .await;
+            let len = len.map_err(CopyError::Read)?;
+            if len == 0 {
+                break Ok(());
+            }
+            message.offset = offset.into();
+            message.size = len as u32;
+            self.request(versions, message.as_bytes()).await?;
+            offset += len as u64;
+        }
+    }
+
+    /// Sends a request and waits for the guest's response.
+    async fn request(&mut self, versions: &Versions, message: &[u8]) -> Result<(), CopyError> {
+        self.pipe
+            .write_message(
+                versions,
+                MessageType::GUEST_INTERFACE,
+                HeaderFlags::new().with_request(true).with_transaction(true),
+                message,
+            )
+            .await
+            .map_err(CopyError::Channel)?;
+        let (status, _) = self
+            .pipe
+            .read_response()
+            .await
+            .map_err(CopyError::Channel)?;
+        if status != Status::SUCCESS {
+            return Err(CopyError::Guest(status));
+        }
+        Ok(())
+    }
+}
+
+/// Fills `buf` from `file`, returning the number of bytes read, which is only
+/// less than the buffer size at the end of the file.
+fn read_fragment(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(n)
+}
+
+/// Writes `s` as a null-terminated UTF-16 string, returning `None` if it
+/// does not fit.
+fn write_str(v: &mut [u16], s: &str) -> Option<()> {
+    let mut i = 0;
+    for c in s.encode_utf16() {
+        *v.get_mut(i)? = c;
+        i += 1;
+    }
+    *v.get_mut(i)? = 0;
+    Some(())
+}
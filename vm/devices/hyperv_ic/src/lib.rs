@@ -12,11 +12,15 @@
 //! * timesync IC for synchronizing time
 //! * heartbeat IC for reporting guest health
 //! * KVP IC for exchanging arbitrary key/value data between the host and guest
+//! * file copy IC for copying files from the host into the guest
+//! * VSS IC for quiescing guest file systems around disk snapshots
 
 #![forbid(unsafe_code)]
 
 mod common;
+pub mod fcopy;
 pub mod kvp;
 pub mod resolver;
 pub mod shutdown;
 pub mod timesync;
+pub mod vss;
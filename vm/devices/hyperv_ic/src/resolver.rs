@@ -3,14 +3,18 @@
 
 //! Resource resolvers for the ICs.
 
+use crate::fcopy::FcopyIc;
 use crate::kvp::KvpIc;
 use crate::shutdown::ShutdownIc;
 use crate::timesync::TimesyncIc;
+use crate::vss::VssIc;
 use anyhow::Context as _;
 use async_trait::async_trait;
+use hyperv_ic_resources::fcopy::FcopyIcHandle;
 use hyperv_ic_resources::kvp::KvpIcHandle;
 use hyperv_ic_resources::shutdown::ShutdownIcHandle;
 use hyperv_ic_resources::timesync::TimesyncIcHandle;
+use hyperv_ic_resources::vss::VssIcHandle;
 use std::convert::Infallible;
 use vm_resource::AsyncResolveResource;
 use vm_resource::IntoResource;
@@ -73,6 +77,54 @@ fn resolve(
     }
 }
 
+/// Resource resolver for the file copy IC.
+pub struct FcopyIcResolver;
+
+declare_static_resolver! {
+    FcopyIcResolver,
+    (VmbusDeviceHandleKind, FcopyIcHandle),
+}
+
+impl ResolveResource<VmbusDeviceHandleKind, FcopyIcHandle> for FcopyIcResolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: FcopyIcHandle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(
+            SimpleDeviceWrapper::new(input.driver_source.simple(), FcopyIc::new(resource.recv))
+                .into(),
+        )
+    }
+}
+
+/// Resource resolver for the VSS IC.
+pub struct VssIcResolver;
+
+declare_static_resolver! {
+    VssIcResolver,
+    (VmbusDeviceHandleKind, VssIcHandle),
+}
+
+impl ResolveResource<VmbusDeviceHandleKind, VssIcHandle> for VssIcResolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: VssIcHandle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(
+            SimpleDeviceWrapper::new(input.driver_source.simple(), VssIc::new(resource.recv))
+                .into(),
+        )
+    }
+}
+
 /// Resource resolver for the timesync IC.
 pub struct TimesyncIcResolver;
 
@@ -89,7 +141,10 @@ impl AsyncResolveResource<VmbusDeviceHandleKind, TimesyncIcHandle> for TimesyncI
     async fn resolve(
         &self,
         resolver: &ResourceResolver,
-        TimesyncIcHandle: TimesyncIcHandle,
+        TimesyncIcHandle {
+            resync_on_resume,
+            skew_ms,
+        }: TimesyncIcHandle,
         input: ResolveVmbusDeviceHandleParams<'_>,
     ) -> Result<Self::Output, Self::Error> {
         let ref_time = resolver
@@ -99,7 +154,12 @@ async fn resolve(
 
         Ok(SimpleDeviceWrapper::new(
             input.driver_source.simple(),
-            TimesyncIc::new(&input.driver_source.simple(), ref_time),
+            TimesyncIc::new(
+                &input.driver_source.simple(),
+                ref_time,
+                resync_on_resume,
+                jiff::SignedDuration::from_millis(skew_ms),
+            ),
         )
         .into())
     }
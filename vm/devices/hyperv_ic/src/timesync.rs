@@ -47,6 +47,9 @@ pub struct TimesyncIc {
     timer: PolledTimer,
     #[inspect(skip)]
     ref_time: ReferenceTimeSource,
+    resync_on_resume: bool,
+    #[inspect(display)]
+    skew: jiff::SignedDuration,
 }
 
 #[doc(hidden)]
@@ -55,6 +58,7 @@ pub struct TimesyncChannel {
     #[inspect(mut)]
     pipe: IcPipe,
     state: ChannelState,
+    sync_pending: bool,
 }
 
 #[derive(Inspect)]
@@ -94,10 +98,24 @@ fn inspect_instant(&instant: &Instant) -> inspect::AsDisplay<jiff::Timestamp> {
 
 impl TimesyncIc {
     /// Create a new timesync IC.
-    pub fn new(driver: &(impl Driver + ?Sized), ref_time: ReferenceTimeSource) -> Self {
+    ///
+    /// If `resync_on_resume` is set, a sync message is sent each time the
+    /// device is started, so that the guest steps its clock past the time
+    /// that elapsed while the VM was paused.
+    ///
+    /// `skew` is added to the host time in every message, so that the guest's
+    /// clock is kept deliberately offset from the host's.
+    pub fn new(
+        driver: &(impl Driver + ?Sized),
+        ref_time: ReferenceTimeSource,
+        resync_on_resume: bool,
+        skew: jiff::SignedDuration,
+    ) -> Self {
         Self {
             timer: PolledTimer::new(driver),
             ref_time,
+            resync_on_resume,
+            skew,
         }
     }
 }
@@ -134,6 +152,9 @@ async fn run(
         stop: &mut StopTask<'_>,
         runner: &mut Self::Runner,
     ) -> Result<(), Cancelled> {
+        if self.resync_on_resume {
+            runner.request_sync();
+        }
         stop.until_stopped(async { runner.process(self).await })
             .await
     }
@@ -156,9 +177,26 @@ fn new(
         Ok(Self {
             pipe,
             state: restore_state.unwrap_or(ChannelState::Negotiate(NegotiateState::default())),
+            sync_pending: false,
         })
     }
 
+    /// Sends a sync message rather than a sample as soon as possible.
+    fn request_sync(&mut self) {
+        match &mut self.state {
+            ChannelState::Ready { state, .. } => match state {
+                ReadyState::SleepUntilNextSample { .. } => {
+                    *state = ReadyState::SendMessage { is_sync: true };
+                }
+                ReadyState::SendMessage { is_sync } => *is_sync = true,
+                // Send the sync after the outstanding response arrives.
+                ReadyState::WaitForResponse => self.sync_pending = true,
+            },
+            // A sync is sent once negotiation completes anyway.
+            ChannelState::Negotiate(_) | ChannelState::Failed => {}
+        }
+    }
+
     async fn process(&mut self, ic: &mut TimesyncIc) -> ! {
         loop {
             if let Err(err) = self.process_state_machine(ic).await {
@@ -207,7 +245,7 @@ async fn process_state_machine(&mut self, ic: &mut TimesyncIc) -> anyhow::Result
                     // avoid drift.
                     let r = ic.ref_time.now();
                     let ref_time = r.ref_time;
-                    let time = r.system_time.unwrap_or_else(jiff::Timestamp::now);
+                    let time = r.system_time.unwrap_or_else(jiff::Timestamp::now) + ic.skew;
 
                     let message = proto::TimesyncMessageV4 {
                         parent_time: ((time.duration_since(proto::EPOCH).as_nanos() / 100) as u64)
@@ -244,9 +282,13 @@ async fn process_state_machine(&mut self, ic: &mut TimesyncIc) -> anyhow::Result
                 }
                 ReadyState::WaitForResponse => {
                     self.pipe.read_response().await?;
-                    // Send another sample in a few seconds.
-                    *state = ReadyState::SleepUntilNextSample {
-                        next_sample: Instant::now() + SAMPLE_PERIOD,
+                    *state = if std::mem::take(&mut self.sync_pending) {
+                        ReadyState::SendMessage { is_sync: true }
+                    } else {
+                        // Send another sample in a few seconds.
+                        ReadyState::SleepUntilNextSample {
+                            next_sample: Instant::now() + SAMPLE_PERIOD,
+                        }
                     };
                 }
             },
@@ -0,0 +1,188 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VSS (backup) IC, for quiescing guest file systems around disk
+//! snapshots.
+//!
+//! Only the freeze and thaw operations used by Linux's `hv_vss_daemon` are
+//! implemented, not the Windows shadow copy flow.
+
+use crate::common::IcPipe;
+use crate::common::NegotiateState;
+use crate::common::Versions;
+use async_trait::async_trait;
+use futures::StreamExt;
+use guestmem::GuestMemory;
+use hyperv_ic_protocol::HeaderFlags;
+use hyperv_ic_protocol::MessageType;
+use hyperv_ic_protocol::Status;
+use hyperv_ic_protocol::vss as proto;
+use hyperv_ic_resources::vss::VssRpc;
+use inspect::Inspect;
+use inspect::InspectMut;
+use task_control::Cancelled;
+use task_control::StopTask;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::save_restore::NoSavedState;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// Freeze and thaw require version 5.0, which is also the only version Linux
+/// supports.
+const VSS_VERSIONS: &[hyperv_ic_protocol::Version] = &[proto::VSS_VERSION_WINBLUE];
+
+/// VSS IC device.
+#[derive(InspectMut)]
+pub struct VssIc {
+    #[inspect(skip)]
+    recv: mesh::Receiver<VssRpc>,
+}
+
+#[doc(hidden)]
+#[derive(InspectMut)]
+pub struct VssChannel {
+    #[inspect(mut)]
+    pipe: IcPipe,
+    state: ChannelState,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Negotiate(#[inspect(rename = "state")] NegotiateState),
+    Ready { versions: Versions, frozen: bool },
+    Failed,
+}
+
+impl VssIc {
+    /// Create a new VSS IC device.
+    pub fn new(recv: mesh::Receiver<VssRpc>) -> Self {
+        Self { recv }
+    }
+
+    async fn next_request(&mut self) -> VssRpc {
+        match self.recv.next().await {
+            Some(rpc) => rpc,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for VssIc {
+    type SavedState = NoSavedState;
+    type Runner = VssChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "vss_ic".to_owned(),
+            instance_id: proto::INSTANCE_ID,
+            interface_id: proto::INTERFACE_ID,
+            channel_type: ChannelType::Pipe { message_mode: true },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond().merge(self).merge(runner);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        VssChannel::new(channel)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async { runner.process(self).await })
+            .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        None
+    }
+}
+
+impl VssChannel {
+    fn new(channel: RawAsyncChannel<GpadlRingMem>) -> Result<Self, ChannelOpenError> {
+        let pipe = IcPipe::new(channel)?;
+        Ok(Self {
+            pipe,
+            state: ChannelState::Negotiate(NegotiateState::default()),
+        })
+    }
+
+    async fn process(&mut self, ic: &mut VssIc) -> ! {
+        loop {
+            if let Err(err) = self.process_state_machine(ic).await {
+                tracing::error!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "vss ic error"
+                );
+                self.state = ChannelState::Failed;
+            }
+        }
+    }
+
+    async fn process_state_machine(&mut self, ic: &mut VssIc) -> anyhow::Result<()> {
+        match &mut self.state {
+            ChannelState::Negotiate(state) => {
+                if let Some(versions) = self.pipe.negotiate(state, VSS_VERSIONS).await? {
+                    self.state = ChannelState::Ready {
+                        versions,
+                        frozen: false,
+                    };
+                }
+            }
+            &mut ChannelState::Ready { versions, .. } => {
+                let (operation, rpc, freeze) = match ic.next_request().await {
+                    VssRpc::Freeze(rpc) => (proto::Operation::FREEZE_APPLICATIONS, rpc, true),
+                    VssRpc::Thaw(rpc) => (proto::Operation::THAW_APPLICATIONS, rpc, false),
+                };
+                let mut message = proto::VssFlagsMessage::new_zeroed();
+                message.header.operation = operation;
+                self.pipe
+                    .write_message(
+                        &versions,
+                        MessageType::VSS,
+                        HeaderFlags::new().with_request(true).with_transaction(true),
+                        message.as_bytes(),
+                    )
+                    .await?;
+                let (status, _) = self.pipe.read_response().await?;
+                if status == Status::SUCCESS {
+                    tracing::info!(freeze, "guest file systems quiesced");
+                    self.state = ChannelState::Ready {
+                        versions,
+                        frozen: freeze,
+                    };
+                    rpc.complete(Ok(()));
+                } else {
+                    let op = if freeze { "freeze" } else { "thaw" };
+                    rpc.fail(anyhow::anyhow!("guest failed to {op}: {status:x?}"));
+                }
+            }
+            ChannelState::Failed => {
+                let (VssRpc::Freeze(rpc) | VssRpc::Thaw(rpc)) = ic.next_request().await;
+                rpc.fail(anyhow::anyhow!("vss channel failed"));
+            }
+        }
+        Ok(())
+    }
+}
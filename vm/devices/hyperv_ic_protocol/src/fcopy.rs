@@ -0,0 +1,96 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Protocol definitions for the guest file copy IC (also known as the guest
+//! service interface).
+
+use crate::Version;
+use guid::Guid;
+use open_enum::open_enum;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::little_endian::U64 as U64LE;
+
+/// The interface ID for the file copy IC.
+pub const INTERFACE_ID: Guid = guid::guid!("34d14be3-dee4-41c8-9ae7-6b174977c192");
+/// The instance ID for the file copy IC.
+pub const INSTANCE_ID: Guid = guid::guid!("d01ec8bb-9f9c-4d5b-8c1f-6f8e2a1b7a35");
+
+/// Version 1.0.
+pub const FCOPY_VERSION_1: Version = Version::new(1, 0);
+/// Version 1.1.
+pub const FCOPY_VERSION_1_1: Version = Version::new(1, 1);
+
+/// The maximum length of file and path names, in UTF-16 code units,
+/// including the null terminator.
+pub const MAX_PATH: usize = 260;
+
+/// The maximum number of bytes in a [`WriteToFileMessage`].
+pub const DATA_FRAGMENT: usize = 6 * 1024;
+
+open_enum! {
+    /// The file copy operation.
+    #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+    pub enum Operation: u32 {
+        /// Create the file. Uses [`StartCopyMessage`].
+        START_FILE_COPY = 0,
+        /// Write data to the file. Uses [`WriteToFileMessage`].
+        WRITE_TO_FILE = 1,
+        /// Finish writing the file. Uses [`FcopyHeader`].
+        COMPLETE_FCOPY = 2,
+        /// Abandon the copy, deleting the partially written file. Uses
+        /// [`FcopyHeader`].
+        CANCEL_FCOPY = 3,
+    }
+}
+
+/// The header for all file copy messages.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct FcopyHeader {
+    /// The operation.
+    pub operation: Operation,
+    /// Unused.
+    pub service_id0: Guid,
+    /// Unused.
+    pub service_id1: Guid,
+}
+
+/// Overwrite the file if it already exists.
+pub const COPY_FLAG_OVERWRITE: u32 = 0x1;
+/// Create the destination directory if it does not exist.
+pub const COPY_FLAG_CREATE_PATH: u32 = 0x2;
+
+/// The message to start a file copy.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct StartCopyMessage {
+    /// The header, with [`Operation::START_FILE_COPY`].
+    pub header: FcopyHeader,
+    /// The null-terminated UTF-16 file name.
+    pub file_name: [u16; MAX_PATH],
+    /// The null-terminated UTF-16 path of the destination directory.
+    pub path_name: [u16; MAX_PATH],
+    /// `COPY_FLAG_*` flags.
+    pub copy_flags: u32,
+    /// The total size of the file.
+    pub file_size: U64LE,
+}
+
+/// The message to write a fragment of the file.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct WriteToFileMessage {
+    /// The header, with [`Operation::WRITE_TO_FILE`].
+    pub header: FcopyHeader,
+    /// Padding.
+    pub pad: u32,
+    /// The offset of the data within the file.
+    pub offset: U64LE,
+    /// The number of valid bytes in `data`.
+    pub size: u32,
+    /// The data.
+    pub data: [u8; DATA_FRAGMENT],
+}
@@ -5,6 +5,7 @@
 
 #![forbid(unsafe_code)]
 
+pub mod fcopy;
 pub mod heartbeat;
 pub mod kvp;
 pub mod shutdown;
@@ -70,7 +71,7 @@ pub enum MessageType: u16 {
         VSS = 5,
         /// RDV
         RDV = 6,
-        /// Guest interface.
+        /// Guest interface, used by the file copy IC.
         GUEST_INTERFACE = 7,
         /// VM Session.
         VM_SESSION = 8,
@@ -113,6 +114,12 @@ pub enum Status: u32 {
         NOT_SUPPORTED = 0x80070032,
         /// Not found.
         NOT_FOUND = 0x80041002,
+        /// The path was not found.
+        PATH_NOT_FOUND = 0x80070003,
+        /// The file already exists.
+        ALREADY_EXISTS = 0x80070050,
+        /// There is not enough space on the disk.
+        DISK_FULL = 0x80070070,
     }
 }
 
@@ -13,6 +13,9 @@
 use zerocopy::IntoBytes;
 use zerocopy::KnownLayout;
 
+pub const INTERFACE_ID: Guid = guid::guid!("35fa2e29-ea23-4236-96ae-3a6ebacba440");
+pub const INSTANCE_ID: Guid = guid::guid!("0e0c5f4b-3397-4c62-aa3c-ccb5e5ae7923");
+
 pub const VSS_VERSION_WIN8: Version = Version::new(4, 0);
 pub const VSS_VERSION_WINBLUE: Version = Version::new(5, 0);
 pub const VSS_VERSION_THRESHOLD: Version = Version::new(6, 0);
@@ -53,6 +56,15 @@ pub enum Operation: u8 {
     }
 }
 
+/// A header followed by a flags field, the smallest message that Linux
+/// guests accept.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VssFlagsMessage {
+    pub header: VssHeader,
+    pub flags: u32,
+}
+
 #[repr(C)]
 #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
 pub struct MessageCheckHotBackup {
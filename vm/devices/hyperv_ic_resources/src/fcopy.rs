@@ -0,0 +1,45 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the guest file copy IC.
+
+use mesh::MeshPayload;
+use mesh::rpc::FailableRpc;
+use std::fs::File;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to the file copy IC.
+#[derive(MeshPayload)]
+pub struct FcopyIcHandle {
+    /// The receiver for file copy requests.
+    pub recv: mesh::Receiver<FcopyRpc>,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for FcopyIcHandle {
+    const ID: &'static str = "fcopy_ic";
+}
+
+/// A file copy request.
+///
+/// Requests are queued until the guest connects to the IC.
+#[derive(MeshPayload)]
+pub enum FcopyRpc {
+    /// Copies a host file into the guest.
+    CopyFile(FailableRpc<CopyFileParams, ()>),
+}
+
+/// Parameters for copying a file into the guest.
+#[derive(MeshPayload, Debug)]
+pub struct CopyFileParams {
+    /// The file to copy.
+    pub file: File,
+    /// The guest directory to write the file to.
+    pub guest_dir: String,
+    /// The name of the file in the guest directory.
+    pub guest_file_name: String,
+    /// Overwrite the guest file if it already exists.
+    pub overwrite: bool,
+    /// Create the guest directory if it does not exist.
+    pub create_dir: bool,
+}
@@ -5,6 +5,8 @@
 
 #![forbid(unsafe_code)]
 
+pub mod fcopy;
 pub mod kvp;
 pub mod shutdown;
 pub mod timesync;
+pub mod vss;
@@ -9,7 +9,14 @@
 
 /// A handle to the timesync IC.
 #[derive(MeshPayload)]
-pub struct TimesyncIcHandle;
+pub struct TimesyncIcHandle {
+    /// Whether to step the guest's clock to the host's time when the VM
+    /// resumes, to account for time lost while it was paused.
+    pub resync_on_resume: bool,
+    /// An offset, in milliseconds, to add to the host time sent to the guest.
+    /// This is used to deliberately skew the guest's clock for testing.
+    pub skew_ms: i64,
+}
 
 impl ResourceId<VmbusDeviceHandleKind> for TimesyncIcHandle {
     const ID: &'static str = "timesync_ic";
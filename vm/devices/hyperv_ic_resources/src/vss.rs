@@ -0,0 +1,32 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the VSS (backup) IC.
+
+use mesh::MeshPayload;
+use mesh::rpc::FailableRpc;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to the VSS IC.
+#[derive(MeshPayload)]
+pub struct VssIcHandle {
+    /// The receiver for VSS requests.
+    pub recv: mesh::Receiver<VssRpc>,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for VssIcHandle {
+    const ID: &'static str = "vss_ic";
+}
+
+/// A VSS request.
+///
+/// Requests are queued until the guest connects to the IC.
+#[derive(MeshPayload)]
+pub enum VssRpc {
+    /// Asks the guest to flush and freeze its file systems, so that disk
+    /// snapshots taken until the next [`VssRpc::Thaw`] are consistent.
+    Freeze(FailableRpc<(), ()>),
+    /// Asks the guest to thaw its file systems.
+    Thaw(FailableRpc<(), ()>),
+}
@@ -0,0 +1,1729 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An emulated Intel 82574L (e1000e) gigabit Ethernet controller.
+//!
+//! This is for guests that lack paravirtualized network drivers, such as old
+//! OS installers. It has a single receive and transmit queue and uses legacy
+//! INTx interrupts. Packets are sent and received via a [`net_backend`]
+//! endpoint.
+//!
+//! Checksum offloads requested by the guest are performed in software by the
+//! device. TCP segmentation offload is passed through to the endpoint;
+//! segmentation requests are dropped if the endpoint does not support it.
+
+#![forbid(unsafe_code)]
+
+pub mod spec;
+
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::poll_device::PollDevice;
+use device_emulators::read_as_u32_chunks;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use net_backend::BufferAccess;
+use net_backend::Endpoint;
+use net_backend::L3Protocol;
+use net_backend::Queue;
+use net_backend::QueueConfig;
+use net_backend::RxBufferSegment;
+use net_backend::RxId;
+use net_backend::RxMetadata;
+use net_backend::TxId;
+use net_backend::TxMetadata;
+use net_backend::TxOffloadSupport;
+use net_backend::TxSegment;
+use net_backend::TxSegmentType;
+use net_backend_resources::mac_address::MacAddress;
+use parking_lot::Mutex;
+use pci_core::PciInterruptPin;
+use pci_core::cfg_space_emu::BarMemoryKind;
+use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
+use pci_core::cfg_space_emu::DeviceBars;
+use pci_core::cfg_space_emu::IntxInterrupt;
+use pci_core::spec::hwid::ClassCode;
+use pci_core::spec::hwid::HardwareIds;
+use pci_core::spec::hwid::ProgrammingInterface;
+use pci_core::spec::hwid::Subclass;
+use slab::Slab;
+use spec::Register;
+use spec::StatsRegister;
+use spec::TxCommand;
+use spec::TxContextDescriptor;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
+use vmcore::save_restore::SaveRestore;
+use vmcore::save_restore::SavedStateNotSupported;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+
+/// The size of the register BAR.
+///
+/// The real device's BAR is 128KiB, but all the emulated registers are in
+/// the first 64KiB.
+const BAR0_LEN: u64 = 0x10000;
+
+/// The maximum number of descriptors in a single transmit packet, to bound
+/// the work done for a malicious ring.
+const MAX_TX_DESCRIPTORS_PER_PACKET: usize = 64;
+/// The maximum number of transmit packets outstanding in the endpoint.
+const MAX_TX_IN_FLIGHT: usize = 256;
+/// The number of times to process the queue in one poll before yielding.
+const MAX_POLL_ITERATIONS: usize = 16;
+
+/// An emulated 82574L Ethernet controller.
+#[derive(InspectMut)]
+pub struct E1000 {
+    cfg_space: ConfigSpaceType0Emulator,
+    #[inspect(skip)]
+    interrupt: Arc<IntxInterrupt>,
+    #[inspect(skip)]
+    guest_memory: GuestMemory,
+    #[inspect(skip)]
+    driver: VmTaskDriver,
+    #[inspect(skip)]
+    waker: Option<Waker>,
+
+    mac_address: MacAddress,
+    #[inspect(skip)]
+    nvm: [u16; spec::NVM_WORDS],
+    #[inspect(skip)]
+    tx_offloads: TxOffloadSupport,
+
+    registers: Registers,
+    #[inspect(iter_by_index)]
+    phy: [u16; 32],
+    #[inspect(mut)]
+    backend: Backend,
+    #[inspect(skip)]
+    rx_buffers: Arc<Mutex<Vec<RxBuffer>>>,
+    /// The next receive descriptor to hand to the endpoint.
+    rx_posted: u32,
+    #[inspect(skip)]
+    tx_context: TxContextDescriptor,
+    /// The transmit descriptors to mark done when each outstanding packet
+    /// completes.
+    #[inspect(with = "Slab::len")]
+    tx_in_flight: Slab<Vec<u32>>,
+    hw_stats: HwStats,
+    stats: Stats,
+}
+
+#[derive(Inspect)]
+struct Registers {
+    ctrl: spec::Ctrl,
+    #[inspect(hex)]
+    ctrl_ext: u32,
+    #[inspect(hex)]
+    eerd: u32,
+    #[inspect(hex)]
+    mdic: u32,
+    icr: spec::Interrupts,
+    ims: spec::Interrupts,
+    #[inspect(hex)]
+    iam: u32,
+    rctl: spec::Rctl,
+    tctl: spec::Tctl,
+    #[inspect(hex)]
+    rfctl: u32,
+    #[inspect(hex)]
+    rdba: u64,
+    #[inspect(hex)]
+    rdlen: u32,
+    rdh: u32,
+    rdt: u32,
+    #[inspect(hex)]
+    tdba: u64,
+    #[inspect(hex)]
+    tdlen: u32,
+    tdh: u32,
+    tdt: u32,
+    #[inspect(skip)]
+    mta: [u32; spec::MTA_LEN],
+    #[inspect(skip)]
+    ra: [(u32, u32); spec::RA_LEN],
+    #[inspect(skip)]
+    vfta: [u32; spec::VFTA_LEN],
+    /// Registers that are stored but otherwise ignored.
+    #[inspect(with = "|x| inspect::iter_by_key(x).map_key(|k| format!(\"{k:#x}\"))")]
+    other: BTreeMap<u16, u32>,
+}
+
+impl Registers {
+    fn new(mac_address: MacAddress) -> Self {
+        let mac = mac_address.to_bytes();
+        let mut ra = [(0, 0); spec::RA_LEN];
+        ra[0] = (
+            u32::from_le_bytes(mac[..4].try_into().unwrap()),
+            spec::RahFlags::new()
+                .with_addr_high(u16::from_le_bytes([mac[4], mac[5]]))
+                .with_av(true)
+                .into(),
+        );
+        Self {
+            ctrl: spec::Ctrl::new()
+                .with_fd(true)
+                .with_asde(true)
+                .with_slu(true)
+                .with_speed(spec::SPEED_1000),
+            ctrl_ext: 0,
+            eerd: 0,
+            mdic: spec::Mdic::new().with_ready(true).into(),
+            icr: spec::Interrupts::new(),
+            ims: spec::Interrupts::new(),
+            iam: 0,
+            rctl: spec::Rctl::new(),
+            tctl: spec::Tctl::new(),
+            rfctl: 0,
+            rdba: 0,
+            rdlen: 0,
+            rdh: 0,
+            rdt: 0,
+            tdba: 0,
+            tdlen: 0,
+            tdh: 0,
+            tdt: 0,
+            mta: [0; spec::MTA_LEN],
+            ra,
+            vfta: [0; spec::VFTA_LEN],
+            other: BTreeMap::new(),
+        }
+    }
+
+    fn rx_ring_len(&self) -> u32 {
+        (self.rdlen as u64 / spec::DESCRIPTOR_SIZE) as u32
+    }
+
+    fn tx_ring_len(&self) -> u32 {
+        (self.tdlen as u64 / spec::DESCRIPTOR_SIZE) as u32
+    }
+}
+
+/// The statistics registers exposed to the guest, which are cleared when
+/// read.
+#[derive(Inspect, Default)]
+struct HwStats {
+    mpc: u32,
+    gprc: u32,
+    bprc: u32,
+    mprc: u32,
+    gptc: u32,
+    gorc: u64,
+    gotc: u64,
+    tpr: u32,
+    tpt: u32,
+    tor: u64,
+    tot: u64,
+}
+
+#[derive(Inspect, Default)]
+struct Stats {
+    rx_filtered: Counter,
+    rx_ring_changed: Counter,
+    tx_dropped: Counter,
+    tx_tso_unsupported: Counter,
+    tx_vlan_ignored: Counter,
+}
+
+type BackendFuture =
+    Pin<Box<dyn Send + Future<Output = (Box<dyn Endpoint>, Option<Box<dyn Queue>>)>>>;
+
+/// The state of the endpoint and its queue.
+///
+/// The queue runs while either receive or transmit is enabled. It is stopped
+/// when the device is reset or when the guest disables receive or transmit,
+/// so that the endpoint releases any buffers it holds from the old ring.
+struct Backend {
+    /// The endpoint, or `None` while `pending` owns it.
+    endpoint: Option<Box<dyn Endpoint>>,
+    queue: Option<Box<dyn Queue>>,
+    /// A pending queue start or stop.
+    pending: Option<BackendFuture>,
+    /// Whether `pending` is starting the queue.
+    starting: bool,
+    /// The queue must be stopped before it is used again.
+    restart: bool,
+    /// Starting the queue failed, so don't retry until the device is reset.
+    failed: bool,
+}
+
+impl InspectMut for Backend {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        resp.field("busy", self.pending.is_some())
+            .field("failed", self.failed);
+        if let Some(endpoint) = &mut self.endpoint {
+            resp.field_mut("endpoint", endpoint.as_mut());
+        }
+        if let Some(queue) = &mut self.queue {
+            resp.field_mut("queue", queue.as_mut());
+        }
+    }
+}
+
+/// A receive buffer, indexed by its descriptor index.
+#[derive(Copy, Clone, Default)]
+struct RxBuffer {
+    gpa: u64,
+    capacity: u32,
+    len: u32,
+    done: bool,
+}
+
+/// The receive buffer pool handed to the endpoint.
+struct RxPool {
+    guest_memory: GuestMemory,
+    buffers: Arc<Mutex<Vec<RxBuffer>>>,
+    segment: [RxBufferSegment; 1],
+}
+
+impl BufferAccess for RxPool {
+    fn guest_memory(&self) -> &GuestMemory {
+        &self.guest_memory
+    }
+
+    fn write_data(&mut self, id: RxId, data: &[u8]) {
+        let buffer = self.buffers.lock()[id.0 as usize];
+        let len = data.len().min(buffer.capacity as usize);
+        if let Err(err) = self.guest_memory.write_at(buffer.gpa, &data[..len]) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "rx memory write failure"
+            );
+        }
+    }
+
+    fn guest_addresses(&mut self, id: RxId) -> &[RxBufferSegment] {
+        let buffer = self.buffers.lock()[id.0 as usize];
+        self.segment = [RxBufferSegment {
+            gpa: buffer.gpa,
+            len: buffer.capacity,
+        }];
+        &self.segment
+    }
+
+    fn capacity(&self, id: RxId) -> u32 {
+        self.buffers.lock()[id.0 as usize].capacity
+    }
+
+    fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
+        // The descriptor has no way to report an offset into the buffer.
+        assert_eq!(metadata.offset, 0);
+        let buffer = &mut self.buffers.lock()[id.0 as usize];
+        buffer.len = metadata.len.min(buffer.capacity as usize) as u32;
+    }
+}
+
+/// A transmit packet read from the descriptor ring.
+struct TxPacket {
+    /// The descriptor index following the packet.
+    next: u32,
+    /// The descriptors to mark done when the packet completes.
+    report_status: Vec<u32>,
+    /// The guest buffers holding the packet data.
+    buffers: Vec<(u64, u32)>,
+    offload: TxOffload,
+    vlan: bool,
+}
+
+enum TxOffload {
+    None,
+    /// A legacy descriptor checksum request.
+    Legacy {
+        css: u8,
+        cso: u8,
+    },
+    /// Offloads described by the current context descriptor.
+    Context {
+        ixsm: bool,
+        txsm: bool,
+        tse: bool,
+    },
+}
+
+impl E1000 {
+    /// Creates a new controller that sends and receives packets via
+    /// `endpoint`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        guest_memory: GuestMemory,
+        interrupt: LineInterrupt,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        endpoint: Box<dyn Endpoint>,
+        mac_address: MacAddress,
+    ) -> Self {
+        let bars = DeviceBars::new().bar0(
+            BAR0_LEN,
+            BarMemoryKind::Intercept(register_mmio.new_io_region("bar0", BAR0_LEN)),
+        );
+
+        let mut cfg_space = ConfigSpaceType0Emulator::new(
+            HardwareIds {
+                vendor_id: spec::VENDOR_ID,
+                device_id: spec::DEVICE_ID,
+                revision_id: 0,
+                prog_if: ProgrammingInterface::NONE,
+                sub_class: Subclass::NETWORK_CONTROLLER_ETHERNET,
+                base_class: ClassCode::NETWORK_CONTROLLER,
+                type0_sub_vendor_id: spec::VENDOR_ID,
+                type0_sub_system_id: 0,
+            },
+            Vec::new(),
+            bars,
+        );
+
+        let interrupt = cfg_space.set_interrupt_pin(PciInterruptPin::IntA, interrupt);
+        let tx_offloads = endpoint.tx_offload_support();
+
+        Self {
+            cfg_space,
+            interrupt,
+            guest_memory,
+            driver: driver_source.simple(),
+            waker: None,
+            mac_address,
+            nvm: build_nvm(mac_address),
+            tx_offloads,
+            registers: Registers::new(mac_address),
+            phy: phy_defaults(),
+            backend: Backend {
+                endpoint: Some(endpoint),
+                queue: None,
+                pending: None,
+                starting: false,
+                restart: false,
+                failed: false,
+            },
+            rx_buffers: Default::default(),
+            rx_posted: 0,
+            tx_context: FromZeros::new_zeroed(),
+            tx_in_flight: Slab::new(),
+            hw_stats: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn update_interrupt(&self) {
+        self.interrupt
+            .set_level(self.registers.icr.into_bits() & self.registers.ims.into_bits() != 0);
+    }
+
+    fn raise_interrupt(&mut self, causes: spec::Interrupts) {
+        self.registers.icr = (self.registers.icr.into_bits() | causes.into_bits()).into();
+        self.update_interrupt();
+    }
+
+    /// Resets the MAC and PHY, as for CTRL.RST or a device reset.
+    fn reset_registers(&mut self) {
+        self.registers = Registers::new(self.mac_address);
+        self.phy = phy_defaults();
+        self.rx_posted = 0;
+        self.tx_context = FromZeros::new_zeroed();
+        self.tx_in_flight.clear();
+        self.backend.restart = true;
+        self.backend.failed = false;
+        self.update_interrupt();
+        self.update_backend();
+    }
+
+    /// Starts or stops the queue to match the device state.
+    fn update_backend(&mut self) {
+        if self.backend.pending.is_some() {
+            // This will be called again when the pending operation completes.
+            return;
+        }
+        if self.backend.restart {
+            self.backend.restart = false;
+            if let Some(queue) = self.backend.queue.take() {
+                // Complete any outstanding transmits, since the endpoint will
+                // drop them.
+                for (_, descriptors) in std::mem::take(&mut self.tx_in_flight) {
+                    self.complete_tx_descriptors(&descriptors);
+                }
+                let mut endpoint = self.backend.endpoint.take().unwrap();
+                self.backend.starting = false;
+                self.backend.pending = Some(Box::pin(async move {
+                    drop(queue);
+                    endpoint.stop().await;
+                    (endpoint, None)
+                }));
+                self.wake();
+                return;
+            }
+        }
+
+        let enabled = self.registers.rctl.en() || self.registers.tctl.en();
+        if enabled && self.backend.queue.is_none() && !self.backend.failed {
+            let mut endpoint = self.backend.endpoint.take().unwrap();
+            let buffers = Arc::new(Mutex::new(Vec::new()));
+            let pool = RxPool {
+                guest_memory: self.guest_memory.clone(),
+                buffers: buffers.clone(),
+                segment: [RxBufferSegment { gpa: 0, len: 0 }],
+            };
+            self.rx_buffers = buffers;
+            self.rx_posted = self.registers.rdh;
+            let driver = self.driver.clone();
+            self.backend.starting = true;
+            self.backend.pending = Some(Box::pin(async move {
+                let mut queues = Vec::new();
+                let result = endpoint
+                    .get_queues(
+                        vec![QueueConfig {
+                            pool: Box::new(pool),
+                            initial_rx: &[],
+                            driver: Box::new(driver),
+                        }],
+                        None,
+                        &mut queues,
+                    )
+                    .await;
+                let queue = match result {
+                    Ok(()) => queues.into_iter().next(),
+                    Err(err) => {
+                        tracing::error!(
+                            error = err.as_ref() as &dyn std::error::Error,
+                            "failed to start e1000 queue"
+                        );
+                        None
+                    }
+                };
+                (endpoint, queue)
+            }));
+            self.wake();
+        }
+    }
+
+    fn backend_transition_complete(
+        &mut self,
+        endpoint: Box<dyn Endpoint>,
+        queue: Option<Box<dyn Queue>>,
+    ) {
+        self.backend.pending = None;
+        self.backend.endpoint = Some(endpoint);
+        if self.backend.starting && queue.is_none() {
+            self.backend.failed = true;
+        }
+        self.backend.queue = queue;
+        if self.backend.queue.is_some() {
+            self.post_rx();
+        }
+        self.update_backend();
+    }
+
+    fn set_ctrl(&mut self, value: u32) {
+        let ctrl = spec::Ctrl::from(value);
+        if ctrl.rst() {
+            self.reset_registers();
+            return;
+        }
+        if ctrl.phy_rst() {
+            self.phy = phy_defaults();
+        }
+        self.registers.ctrl = ctrl.with_phy_rst(false);
+    }
+
+    fn set_rctl(&mut self, value: u32) {
+        let old = self.registers.rctl;
+        self.registers.rctl = value.into();
+        if old.en() && !self.registers.rctl.en() {
+            self.backend.restart = true;
+        }
+        self.update_backend();
+    }
+
+    fn set_tctl(&mut self, value: u32) {
+        let old = self.registers.tctl;
+        self.registers.tctl = value.into();
+        if old.en() && !self.registers.tctl.en() {
+            self.backend.restart = true;
+        }
+        self.update_backend();
+        self.wake();
+    }
+
+    fn read_icr(&mut self) -> u32 {
+        let mut icr = self.registers.icr;
+        if icr.into_bits() & self.registers.ims.into_bits() != 0 {
+            icr.set_int_asserted(true);
+            if spec::CtrlExt::from(self.registers.ctrl_ext).iame() {
+                self.registers.ims = (self.registers.ims.into_bits() & !self.registers.iam).into();
+            }
+        }
+        self.registers.icr = spec::Interrupts::new();
+        self.update_interrupt();
+        icr.into()
+    }
+
+    fn read_nvm(&mut self, value: u32) {
+        let eerd = spec::Eerd::from(value);
+        if !eerd.start() {
+            return;
+        }
+        let data = self
+            .nvm
+            .get(eerd.addr() as usize)
+            .copied()
+            .unwrap_or(0xffff);
+        self.registers.eerd = eerd
+            .with_start(false)
+            .with_done(true)
+            .with_data(data)
+            .into();
+    }
+
+    fn access_phy(&mut self, value: u32) {
+        let mut mdic = spec::Mdic::from(value);
+        if mdic.phyadd() != spec::PHY_ADDRESS {
+            mdic.set_error(true);
+        } else {
+            match mdic.op() {
+                spec::MDIC_OP_READ => mdic.set_data(self.read_phy(mdic.regadd())),
+                spec::MDIC_OP_WRITE => self.write_phy(mdic.regadd(), mdic.data()),
+                _ => mdic.set_error(true),
+            }
+        }
+        self.registers.mdic = mdic.with_ready(true).into();
+    }
+
+    fn read_phy(&self, reg: u8) -> u16 {
+        match spec::PhyRegister(reg) {
+            spec::PhyRegister::STATUS => spec::PHY_STATUS_LINK_UP,
+            spec::PhyRegister::ID1 => spec::PHY_ID1,
+            spec::PhyRegister::ID2 => spec::PHY_ID2,
+            spec::PhyRegister::LP_ABILITY => spec::PHY_LP_ABILITY,
+            spec::PhyRegister::GIGABIT_STATUS => spec::PHY_GIGABIT_STATUS,
+            spec::PhyRegister::EXT_STATUS => spec::PHY_EXT_STATUS,
+            spec::PhyRegister::SPEC_STATUS => spec::PHY_SPEC_STATUS_LINK_UP,
+            _ => self.phy[reg as usize],
+        }
+    }
+
+    fn write_phy(&mut self, reg: u8, value: u16) {
+        match spec::PhyRegister(reg) {
+            spec::PhyRegister::CONTROL => {
+                if value & spec::PHY_CONTROL_RESET != 0 {
+                    self.phy = phy_defaults();
+                } else {
+                    // Autonegotiation completes immediately.
+                    self.phy[reg as usize] = value & !spec::PHY_CONTROL_RESTART_AUTONEG;
+                }
+            }
+            spec::PhyRegister::STATUS
+            | spec::PhyRegister::ID1
+            | spec::PhyRegister::ID2
+            | spec::PhyRegister::LP_ABILITY
+            | spec::PhyRegister::GIGABIT_STATUS
+            | spec::PhyRegister::EXT_STATUS
+            | spec::PhyRegister::SPEC_STATUS => {}
+            _ => self.phy[reg as usize] = value,
+        }
+    }
+
+    fn read_stat(&mut self, reg: StatsRegister) -> u32 {
+        let stats = &mut self.hw_stats;
+        let take32 = std::mem::take::<u32>;
+        // The 64-bit counters are cleared when the high half is read.
+        match reg {
+            StatsRegister::MPC => take32(&mut stats.mpc),
+            StatsRegister::GPRC => take32(&mut stats.gprc),
+            StatsRegister::BPRC => take32(&mut stats.bprc),
+            StatsRegister::MPRC => take32(&mut stats.mprc),
+            StatsRegister::GPTC => take32(&mut stats.gptc),
+            StatsRegister::TPR => take32(&mut stats.tpr),
+            StatsRegister::TPT => take32(&mut stats.tpt),
+            StatsRegister::GORCL => stats.gorc as u32,
+            StatsRegister::GORCH => (std::mem::take(&mut stats.gorc) >> 32) as u32,
+            StatsRegister::GOTCL => stats.gotc as u32,
+            StatsRegister::GOTCH => (std::mem::take(&mut stats.gotc) >> 32) as u32,
+            StatsRegister::TORL => stats.tor as u32,
+            StatsRegister::TORH => (std::mem::take(&mut stats.tor) >> 32) as u32,
+            StatsRegister::TOTL => stats.tot as u32,
+            StatsRegister::TOTH => (std::mem::take(&mut stats.tot) >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn read_u32(&mut self, offset: u16) -> u32 {
+        let regs = &self.registers;
+        match Register(offset) {
+            Register::CTRL => regs.ctrl.into(),
+            Register::STATUS => spec::Status::new()
+                .with_fd(true)
+                .with_lu(true)
+                .with_speed(spec::SPEED_1000)
+                .with_gio_master_enable(!regs.ctrl.gio_master_disable())
+                .into(),
+            Register::EECD => spec::Eecd::new().with_pres(true).with_auto_rd(true).into(),
+            Register::EERD => regs.eerd,
+            Register::CTRL_EXT => regs.ctrl_ext,
+            Register::MDIC => regs.mdic,
+            Register::ICR => self.read_icr(),
+            Register::ICS | Register::IMS => regs.ims.into(),
+            Register::IAM => regs.iam,
+            Register::RCTL => regs.rctl.into(),
+            Register::TCTL => regs.tctl.into(),
+            Register::RFCTL => regs.rfctl,
+            Register::EEMNGCTL => {
+                // The configuration has been loaded from the NVM.
+                1 << 18
+            }
+            Register::RDBAL => regs.rdba as u32,
+            Register::RDBAH => (regs.rdba >> 32) as u32,
+            Register::RDLEN => regs.rdlen,
+            Register::RDH => regs.rdh,
+            Register::RDT => regs.rdt,
+            Register::TDBAL => regs.tdba as u32,
+            Register::TDBAH => (regs.tdba >> 32) as u32,
+            Register::TDLEN => regs.tdlen,
+            Register::TDH => regs.tdh,
+            Register::TDT => regs.tdt,
+            _ => match offset {
+                spec::STATS_START..spec::STATS_END => self.read_stat(StatsRegister(offset)),
+                _ => {
+                    if let Some(value) = self.read_table(offset) {
+                        value
+                    } else {
+                        regs.other.get(&offset).copied().unwrap_or(0)
+                    }
+                }
+            },
+        }
+    }
+
+    fn read_table(&self, offset: u16) -> Option<u32> {
+        let regs = &self.registers;
+        let value = if let Some(i) = table_index(offset, spec::MTA_START, spec::MTA_LEN) {
+            regs.mta[i]
+        } else if let Some(i) = table_index(offset, spec::RA_START, spec::RA_LEN * 2) {
+            let (low, high) = regs.ra[i / 2];
+            if i % 2 == 0 { low } else { high }
+        } else if let Some(i) = table_index(offset, spec::VFTA_START, spec::VFTA_LEN) {
+            regs.vfta[i]
+        } else {
+            return None;
+        };
+        Some(value)
+    }
+
+    fn write_table(&mut self, offset: u16, value: u32) -> bool {
+        let regs = &mut self.registers;
+        if let Some(i) = table_index(offset, spec::MTA_START, spec::MTA_LEN) {
+            regs.mta[i] = value;
+        } else if let Some(i) = table_index(offset, spec::RA_START, spec::RA_LEN * 2) {
+            let (low, high) = &mut regs.ra[i / 2];
+            if i % 2 == 0 {
+                *low = value;
+            } else {
+                *high = value;
+            }
+        } else if let Some(i) = table_index(offset, spec::VFTA_START, spec::VFTA_LEN) {
+            regs.vfta[i] = value;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn write_u32(&mut self, offset: u16, value: u32) {
+        let regs = &mut self.registers;
+        match Register(offset) {
+            Register::CTRL => self.set_ctrl(value),
+            Register::STATUS | Register::EECD | Register::EEMNGCTL => {}
+            Register::EERD => self.read_nvm(value),
+            Register::CTRL_EXT => regs.ctrl_ext = value,
+            Register::MDIC => self.access_phy(value),
+            Register::ICR => {
+                regs.icr = (regs.icr.into_bits() & !value).into();
+                self.update_interrupt();
+            }
+            Register::ICS => self.raise_interrupt(value.into()),
+            Register::IMS => {
+                regs.ims = (regs.ims.into_bits() | value).into();
+                self.update_interrupt();
+            }
+            Register::IMC => {
+                regs.ims = (regs.ims.into_bits() & !value).into();
+                self.update_interrupt();
+            }
+            Register::IAM => regs.iam = value,
+            Register::RCTL => self.set_rctl(value),
+            Register::TCTL => self.set_tctl(value),
+            Register::RFCTL => regs.rfctl = value,
+            Register::RDBAL => regs.rdba = (regs.rdba & !0xffff_ffff) | (value & !0xf) as u64,
+            Register::RDBAH => regs.rdba = (regs.rdba & 0xffff_ffff) | ((value as u64) << 32),
+            Register::RDLEN => regs.rdlen = value & 0xf_ff80,
+            Register::RDH => regs.rdh = value & 0xffff,
+            Register::RDT => {
+                regs.rdt = value & 0xffff;
+                self.post_rx();
+            }
+            Register::TDBAL => regs.tdba = (regs.tdba & !0xffff_ffff) | (value & !0xf) as u64,
+            Register::TDBAH => regs.tdba = (regs.tdba & 0xffff_ffff) | ((value as u64) << 32),
+            Register::TDLEN => regs.tdlen = value & 0xf_ff80,
+            Register::TDH => regs.tdh = value & 0xffff,
+            Register::TDT => {
+                regs.tdt = value & 0xffff;
+                self.wake();
+            }
+            _ => match offset {
+                spec::STATS_START..spec::STATS_END => {}
+                _ => {
+                    if !self.write_table(offset, value) {
+                        self.registers.other.insert(offset, value);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Hands the receive descriptors the guest has made available to the
+    /// endpoint.
+    fn post_rx(&mut self) {
+        let Some(queue) = &mut self.backend.queue else {
+            return;
+        };
+        let ring_len = self.registers.rx_ring_len();
+        if ring_len == 0 || self.registers.rdt >= ring_len || self.rx_posted >= ring_len {
+            return;
+        }
+        let capacity = self.registers.rctl.buffer_size();
+        let mut ids = Vec::new();
+        {
+            let mut buffers = self.rx_buffers.lock();
+            if buffers.len() < ring_len as usize {
+                buffers.resize(ring_len as usize, RxBuffer::default());
+            }
+            while self.rx_posted != self.registers.rdt {
+                let index = self.rx_posted;
+                let gpa = match self
+                    .guest_memory
+                    .read_plain::<u64>(self.registers.rdba + index as u64 * spec::DESCRIPTOR_SIZE)
+                {
+                    Ok(gpa) => gpa,
+                    Err(err) => {
+                        tracelimit::warn_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to read rx descriptor"
+                        );
+                        break;
+                    }
+                };
+                buffers[index as usize] = RxBuffer {
+                    gpa,
+                    capacity,
+                    len: 0,
+                    done: false,
+                };
+                ids.push(RxId(index));
+                self.rx_posted = (index + 1) % ring_len;
+            }
+        }
+        if !ids.is_empty() {
+            queue.rx_avail(&ids);
+        }
+    }
+
+    /// Returns whether a received frame passes the receive filters.
+    fn rx_filter(&self, dest: [u8; 6]) -> bool {
+        let rctl = self.registers.rctl;
+        if rctl.upe() {
+            return true;
+        }
+        if dest == [0xff; 6] {
+            return rctl.bam() || rctl.mpe();
+        }
+        if dest[0] & 1 != 0 {
+            if rctl.mpe() {
+                return true;
+            }
+            let hash = match rctl.mo() {
+                0 => (dest[4] as u16 >> 4) | ((dest[5] as u16) << 4),
+                1 => (dest[4] as u16 >> 3) | ((dest[5] as u16) << 5),
+                2 => (dest[4] as u16 >> 2) | ((dest[5] as u16) << 6),
+                _ => dest[4] as u16 | ((dest[5] as u16) << 8),
+            } & 0xfff;
+            return self.registers.mta[hash as usize >> 5] & (1 << (hash & 31)) != 0;
+        }
+        self.registers.ra.iter().any(|&(low, high)| {
+            let high = spec::RahFlags::from(high);
+            high.av()
+                && dest[..4] == low.to_le_bytes()
+                && dest[4..] == high.addr_high().to_le_bytes()
+        })
+    }
+
+    /// Processes receive completions from the endpoint.
+    fn process_rx(&mut self) -> bool {
+        let Some(queue) = &mut self.backend.queue else {
+            return false;
+        };
+        let mut ids = [RxId(0); 32];
+        let n = match queue.rx_poll(&mut ids) {
+            Ok(n) => n,
+            Err(err) => {
+                tracelimit::error_ratelimited!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "rx poll failed"
+                );
+                0
+            }
+        };
+        for &id in &ids[..n] {
+            self.complete_rx(id);
+        }
+        if n > 0 {
+            self.raise_interrupt(spec::Interrupts::new().with_rxt0(true));
+        }
+        n > 0
+    }
+
+    fn complete_rx(&mut self, id: RxId) {
+        let ring_len = self.registers.rx_ring_len();
+        if id.0 >= ring_len || self.registers.rdh >= ring_len {
+            // The guest resized the ring or moved the head out of it while
+            // the buffer was posted. Drop the packet rather than write a
+            // descriptor outside the ring.
+            self.stats.rx_ring_changed.increment();
+            self.rx_buffers.lock()[id.0 as usize].len = 0;
+            return;
+        }
+        let buffer = self.rx_buffers.lock()[id.0 as usize];
+        let mut dest = [0; 6];
+        let accept = self.registers.rctl.en()
+            && buffer.len >= 14
+            && self.guest_memory.read_at(buffer.gpa, &mut dest).is_ok()
+            && self.rx_filter(dest);
+        if !accept {
+            self.stats.rx_filtered.increment();
+            // Reuse the buffer for the next packet.
+            self.rx_buffers.lock()[id.0 as usize].len = 0;
+            self.backend.queue.as_mut().unwrap().rx_avail(&[id]);
+            return;
+        }
+
+        let mut len = buffer.len;
+        if !self.registers.rctl.secrc() && len + 4 <= buffer.capacity {
+            // Include a (zero) CRC, since the guest expects one.
+            let _ = self.guest_memory.write_at(buffer.gpa + len as u64, &[0; 4]);
+            len += 4;
+        }
+
+        let status = spec::RxStatus::new()
+            .with_dd(true)
+            .with_eop(true)
+            .with_ixsm(true);
+        let addr = self.registers.rdba + id.0 as u64 * spec::DESCRIPTOR_SIZE;
+        let result = if spec::Rfctl::from(self.registers.rfctl).exsten() {
+            self.guest_memory.write_plain(
+                addr,
+                &spec::RxExtendedWriteback {
+                    mrq: 0,
+                    rss_hash: 0,
+                    status,
+                    extended_status: 0,
+                    extended_errors: 0,
+                    length: len as u16,
+                    vlan: 0,
+                },
+            )
+        } else {
+            self.guest_memory.write_plain(
+                addr,
+                &spec::RxLegacyDescriptor {
+                    buffer_addr: buffer.gpa,
+                    length: len as u16,
+                    checksum: 0,
+                    status,
+                    errors: 0,
+                    special: 0,
+                },
+            )
+        };
+        if let Err(err) = result {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write rx descriptor"
+            );
+        }
+
+        let stats = &mut self.hw_stats;
+        stats.tpr = stats.tpr.wrapping_add(1);
+        stats.gprc = stats.gprc.wrapping_add(1);
+        stats.tor = stats.tor.wrapping_add(len.into());
+        stats.gorc = stats.gorc.wrapping_add(len.into());
+        if dest == [0xff; 6] {
+            stats.bprc = stats.bprc.wrapping_add(1);
+        } else if dest[0] & 1 != 0 {
+            stats.mprc = stats.mprc.wrapping_add(1);
+        }
+
+        // Advance the head past completed descriptors. Descriptors may
+        // complete out of order if the endpoint completes them out of order.
+        let mut buffers = self.rx_buffers.lock();
+        buffers[id.0 as usize].done = true;
+        while self.registers.rdh != self.rx_posted
+            && buffers
+                .get(self.registers.rdh as usize)
+                .is_some_and(|b| b.done)
+        {
+            buffers[self.registers.rdh as usize].done = false;
+            self.registers.rdh = (self.registers.rdh + 1) % ring_len;
+        }
+    }
+
+    /// Sets the descriptor done bit in each of `descriptors`.
+    fn complete_tx_descriptors(&mut self, descriptors: &[u32]) {
+        let ring_len = self.registers.tx_ring_len();
+        for &index in descriptors {
+            if index >= ring_len {
+                // The guest shrank the ring while the packet was in flight.
+                continue;
+            }
+            let addr =
+                self.registers.tdba + index as u64 * spec::DESCRIPTOR_SIZE + spec::TX_STATUS_OFFSET;
+            let result = self.guest_memory.read_plain::<u8>(addr).and_then(|status| {
+                self.guest_memory
+                    .write_plain(addr, &(status | spec::TX_STATUS_DD))
+            });
+            if let Err(err) = result {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write tx descriptor"
+                );
+            }
+        }
+    }
+
+    /// Processes transmit completions from the endpoint.
+    fn process_tx_completions(&mut self) -> bool {
+        let Some(queue) = &mut self.backend.queue else {
+            return false;
+        };
+        let mut ids = [TxId(0); 32];
+        let n = match queue.tx_poll(&mut ids) {
+            Ok(n) => n,
+            Err(err) => {
+                tracelimit::error_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "tx poll failed"
+                );
+                0
+            }
+        };
+        for id in &ids[..n] {
+            let index = id.0 as usize;
+            if self.tx_in_flight.contains(index) {
+                let descriptors = self.tx_in_flight.remove(index);
+                self.complete_tx_descriptors(&descriptors);
+            }
+        }
+        if n > 0 {
+            self.tx_done();
+        }
+        n > 0
+    }
+
+    fn tx_done(&mut self) {
+        let mut causes = spec::Interrupts::new().with_txdw(true);
+        if self.registers.tdh == self.registers.tdt && self.tx_in_flight.is_empty() {
+            causes.set_txqe(true);
+        }
+        self.raise_interrupt(causes);
+    }
+
+    /// Reads the next complete packet from the transmit ring, returning
+    /// `None` if the guest has not yet made the whole packet available.
+    fn read_tx_packet(&mut self, ring_len: u32) -> Result<Option<TxPacket>, GuestMemoryError> {
+        if ring_len == 0 {
+            return Ok(None);
+        }
+        let mut packet = TxPacket {
+            next: self.registers.tdh,
+            report_status: Vec::new(),
+            buffers: Vec::new(),
+            offload: TxOffload::None,
+            vlan: false,
+        };
+        let mut first = true;
+        for _ in 0..MAX_TX_DESCRIPTORS_PER_PACKET {
+            if packet.next == self.registers.tdt {
+                return Ok(None);
+            }
+            let index = packet.next;
+            packet.next = (index + 1) % ring_len;
+            let descriptor: [u8; 16] = self
+                .guest_memory
+                .read_plain(self.registers.tdba + index as u64 * spec::DESCRIPTOR_SIZE)?;
+            let cmd = TxCommand::from(descriptor[11]);
+            if cmd.rs() {
+                packet.report_status.push(index);
+            }
+            let (addr, len) = if !cmd.dext() {
+                let desc = spec::TxLegacyDescriptor::read_from_bytes(&descriptor).unwrap();
+                if first && desc.cmd.ic_or_tse() {
+                    packet.offload = TxOffload::Legacy {
+                        css: desc.css,
+                        cso: desc.cso,
+                    };
+                }
+                (desc.buffer_addr, desc.length.into())
+            } else {
+                match spec::tx_descriptor_type(&descriptor) {
+                    spec::TX_DTYP_CONTEXT => {
+                        self.tx_context =
+                            TxContextDescriptor::read_from_bytes(&descriptor).unwrap();
+                        continue;
+                    }
+                    spec::TX_DTYP_DATA => {
+                        let desc = spec::TxDataDescriptor::read_from_bytes(&descriptor).unwrap();
+                        if first {
+                            packet.offload = TxOffload::Context {
+                                ixsm: desc.popts.ixsm(),
+                                txsm: desc.popts.txsm(),
+                                tse: desc.dcmd.ic_or_tse(),
+                            };
+                        }
+                        (desc.buffer_addr, desc.length())
+                    }
+                    ty => {
+                        tracelimit::warn_ratelimited!(ty, "unknown tx descriptor type");
+                        continue;
+                    }
+                }
+            };
+            if first {
+                packet.vlan = cmd.vle();
+                first = false;
+            }
+            if len > 0 {
+                packet.buffers.push((addr, len));
+            }
+            if cmd.eop_or_tcp() {
+                return Ok(Some(packet));
+            }
+        }
+        // The packet is too long. Drop it.
+        tracelimit::warn_ratelimited!("too many tx descriptors in packet");
+        packet.buffers.clear();
+        Ok(Some(packet))
+    }
+
+    /// Performs checksum offloads in software.
+    fn insert_checksums(&self, packet: &TxPacket) -> Result<(), GuestMemoryError> {
+        let mut fields: Vec<(usize, usize, usize)> = Vec::new();
+        match packet.offload {
+            TxOffload::None => return Ok(()),
+            TxOffload::Legacy { css, cso } => {
+                fields.push((css.into(), 0, cso.into()));
+            }
+            TxOffload::Context { ixsm, txsm, tse: _ } => {
+                let ctx = &self.tx_context;
+                if ixsm {
+                    fields.push((ctx.ipcss.into(), ctx.ipcse.into(), ctx.ipcso.into()));
+                }
+                if txsm {
+                    fields.push((ctx.tucss.into(), ctx.tucse.into(), ctx.tucso.into()));
+                }
+            }
+        }
+        let mut data = read_buffers(&self.guest_memory, &packet.buffers)?;
+        for (start, end, offset) in fields {
+            // An end of zero means the end of the packet.
+            let end = if end == 0 {
+                data.len()
+            } else {
+                (end + 1).min(data.len())
+            };
+            if start >= end || offset + 2 > data.len() {
+                continue;
+            }
+            let checksum = checksum(&data[start..end]).to_be_bytes();
+            data[offset..offset + 2].copy_from_slice(&checksum);
+            write_buffers(&self.guest_memory, &packet.buffers, offset, &checksum)?;
+        }
+        Ok(())
+    }
+
+    /// Sends available packets from the transmit ring to the endpoint.
+    fn process_tx(&mut self) -> bool {
+        if !self.registers.tctl.en() || self.backend.queue.is_none() {
+            return false;
+        }
+        let ring_len = self.registers.tx_ring_len();
+        if ring_len == 0 || self.registers.tdt >= ring_len || self.registers.tdh >= ring_len {
+            return false;
+        }
+        let mut progress = false;
+        while self.registers.tdh != self.registers.tdt && self.tx_in_flight.len() < MAX_TX_IN_FLIGHT
+        {
+            let packet = match self.read_tx_packet(ring_len) {
+                Ok(Some(packet)) => packet,
+                Ok(None) => break,
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to read tx descriptor"
+                    );
+                    break;
+                }
+            };
+
+            let mut metadata = TxMetadata::default();
+            let mut drop = packet.buffers.is_empty();
+            if packet.vlan {
+                // VLAN tag insertion is not supported. Send the packet
+                // untagged.
+                self.stats.tx_vlan_ignored.increment();
+            }
+            match packet.offload {
+                TxOffload::Context { tse: true, .. } => {
+                    if self.tx_offloads.tso {
+                        let ctx = &self.tx_context;
+                        let ipv4 = ctx.tucmd.ifcs_or_ip();
+                        metadata.offload_tcp_segmentation = true;
+                        metadata.offload_tcp_checksum = true;
+                        metadata.offload_ip_header_checksum = ipv4;
+                        metadata.l3_protocol = if ipv4 {
+                            L3Protocol::Ipv4
+                        } else {
+                            L3Protocol::Ipv6
+                        };
+                        metadata.l2_len = ctx.ipcss;
+                        metadata.l3_len = ctx.tucss.saturating_sub(ctx.ipcss).into();
+                        metadata.l4_len = ctx.hdrlen.saturating_sub(ctx.tucss);
+                        metadata.max_tcp_segment_size = ctx.mss;
+                    } else {
+                        self.stats.tx_tso_unsupported.increment();
+                        drop = true;
+                    }
+                }
+                _ => {
+                    if let Err(err) = self.insert_checksums(&packet) {
+                        tracelimit::warn_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to compute tx checksum"
+                        );
+                        drop = true;
+                    }
+                }
+            }
+
+            let mut sent = None;
+            if !drop {
+                let entry = self.tx_in_flight.vacant_entry();
+                metadata.id = TxId(entry.key() as u32);
+                metadata.segment_count = packet.buffers.len();
+                metadata.len = packet.buffers.iter().map(|&(_, len)| len as usize).sum();
+                let mut ty = Some(TxSegmentType::Head(metadata));
+                let segments = packet
+                    .buffers
+                    .iter()
+                    .map(|&(gpa, len)| TxSegment {
+                        ty: ty.take().unwrap_or(TxSegmentType::Tail),
+                        gpa,
+                        len,
+                    })
+                    .collect::<Vec<_>>();
+                let len = segments.iter().map(|s| s.len as u64).sum::<u64>();
+                match self.backend.queue.as_mut().unwrap().tx_avail(&segments) {
+                    Ok((_, 0)) => {
+                        // The endpoint is full. Try again when it completes
+                        // some packets.
+                        break;
+                    }
+                    Ok((false, _)) => {
+                        entry.insert(packet.report_status.clone());
+                        sent = Some((len, false));
+                    }
+                    Ok((true, _)) => sent = Some((len, true)),
+                    Err(err) => {
+                        tracelimit::warn_ratelimited!(
+                            error = err.as_ref() as &dyn std::error::Error,
+                            "tx failed"
+                        );
+                    }
+                }
+            }
+
+            self.registers.tdh = packet.next;
+            progress = true;
+            match sent {
+                Some((len, complete)) => {
+                    let stats = &mut self.hw_stats;
+                    stats.tpt = stats.tpt.wrapping_add(1);
+                    stats.gptc = stats.gptc.wrapping_add(1);
+                    stats.tot = stats.tot.wrapping_add(len);
+                    stats.gotc = stats.gotc.wrapping_add(len);
+                    if complete {
+                        self.complete_tx_descriptors(&packet.report_status);
+                        self.tx_done();
+                    }
+                }
+                None => {
+                    self.stats.tx_dropped.increment();
+                    self.complete_tx_descriptors(&packet.report_status);
+                    self.tx_done();
+                }
+            }
+        }
+        progress
+    }
+}
+
+fn table_index(offset: u16, start: u16, len: usize) -> Option<usize> {
+    let i = offset.checked_sub(start)? as usize / 4;
+    (i < len).then_some(i)
+}
+
+fn phy_defaults() -> [u16; 32] {
+    let mut phy = [0; 32];
+    // Autonegotiation enabled, full duplex, 1000 Mb/s.
+    phy[spec::PhyRegister::CONTROL.0 as usize] = 0x1140;
+    // Advertise 10/100 full and half duplex.
+    phy[spec::PhyRegister::AUTONEG_ADV.0 as usize] = 0x01e1;
+    // The link partner is autonegotiation capable.
+    phy[spec::PhyRegister::AUTONEG_EXP.0 as usize] = 0x0001;
+    // Advertise 1000 Mb/s full and half duplex.
+    phy[spec::PhyRegister::GIGABIT_CONTROL.0 as usize] = 0x0300;
+    phy
+}
+
+fn build_nvm(mac_address: MacAddress) -> [u16; spec::NVM_WORDS] {
+    let mut nvm = [0; spec::NVM_WORDS];
+    let mac = mac_address.to_bytes();
+    for (word, bytes) in nvm[spec::NvmWord::MAC_ADDRESS_0.0 as usize..]
+        .iter_mut()
+        .zip(mac.chunks_exact(2))
+    {
+        *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+    // Use the default LED configuration.
+    nvm[spec::NvmWord::ID_LED_SETTINGS.0 as usize] = 0xffff;
+    nvm[spec::NvmWord::SUBSYSTEM_ID.0 as usize] = 0;
+    nvm[spec::NvmWord::SUBSYSTEM_VENDOR_ID.0 as usize] = spec::VENDOR_ID;
+    nvm[spec::NvmWord::DEVICE_ID.0 as usize] = spec::DEVICE_ID;
+    nvm[spec::NvmWord::VENDOR_ID.0 as usize] = spec::VENDOR_ID;
+    let sum = nvm[..spec::NvmWord::CHECKSUM.0 as usize]
+        .iter()
+        .fold(0u16, |sum, &word| sum.wrapping_add(word));
+    nvm[spec::NvmWord::CHECKSUM.0 as usize] = spec::NVM_CHECKSUM_SUM.wrapping_sub(sum);
+    nvm
+}
+
+/// Computes the Internet checksum of `data`.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    match !(sum as u16) {
+        // A zero UDP checksum means no checksum, so use the equivalent
+        // ones' complement value instead.
+        0 => 0xffff,
+        checksum => checksum,
+    }
+}
+
+fn read_buffers(
+    guest_memory: &GuestMemory,
+    buffers: &[(u64, u32)],
+) -> Result<Vec<u8>, GuestMemoryError> {
+    let mut data = Vec::new();
+    for &(gpa, len) in buffers {
+        let start = data.len();
+        data.resize(start + len as usize, 0);
+        guest_memory.read_at(gpa, &mut data[start..])?;
+    }
+    Ok(data)
+}
+
+/// Writes `data` at `offset` into the packet stored in `buffers`.
+fn write_buffers(
+    guest_memory: &GuestMemory,
+    buffers: &[(u64, u32)],
+    mut offset: usize,
+    mut data: &[u8],
+) -> Result<(), GuestMemoryError> {
+    for &(gpa, len) in buffers {
+        let len = len as usize;
+        if offset >= len {
+            offset -= len;
+            continue;
+        }
+        let n = data.len().min(len - offset);
+        guest_memory.write_at(gpa + offset as u64, &data[..n])?;
+        data = &data[n..];
+        offset = 0;
+        if data.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl ChangeDeviceState for E1000 {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.cfg_space.reset();
+        self.registers = Registers::new(self.mac_address);
+        self.phy = phy_defaults();
+        self.rx_posted = 0;
+        self.tx_context = FromZeros::new_zeroed();
+        self.tx_in_flight.clear();
+        self.update_interrupt();
+
+        // Stop the queue so that the endpoint releases its buffers.
+        if let Some(pending) = self.backend.pending.take() {
+            let (endpoint, queue) = pending.await;
+            self.backend.endpoint = Some(endpoint);
+            self.backend.queue = queue;
+        }
+        if let Some(queue) = self.backend.queue.take() {
+            drop(queue);
+            self.backend.endpoint.as_mut().unwrap().stop().await;
+        }
+        self.backend.starting = false;
+        self.backend.restart = false;
+        self.backend.failed = false;
+    }
+}
+
+impl ChipsetDevice for E1000 {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl MmioIntercept for E1000 {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        match self.cfg_space.find_bar(addr) {
+            Some((0, offset)) => {
+                read_as_u32_chunks(offset, data, |offset| self.read_u32(offset));
+                IoResult::Ok
+            }
+            _ => IoResult::Err(IoError::InvalidRegister),
+        }
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        match self.cfg_space.find_bar(addr) {
+            Some((0, offset)) => {
+                if data.len() != 4 && data.len() != 8 {
+                    return IoResult::Err(IoError::InvalidAccessSize);
+                }
+                if offset & 3 != 0 {
+                    return IoResult::Err(IoError::UnalignedAccess);
+                }
+                for (i, chunk) in data.chunks_exact(4).enumerate() {
+                    self.write_u32(
+                        offset + i as u16 * 4,
+                        u32::from_ne_bytes(chunk.try_into().unwrap()),
+                    );
+                }
+                IoResult::Ok
+            }
+            _ => IoResult::Err(IoError::InvalidRegister),
+        }
+    }
+}
+
+impl PciConfigSpace for E1000 {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        self.cfg_space.read_u32(offset, value)
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        self.cfg_space.write_u32(offset, value)
+    }
+}
+
+impl PollDevice for E1000 {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        self.waker = Some(cx.waker().clone());
+        while let Some(pending) = &mut self.backend.pending {
+            let Poll::Ready((endpoint, queue)) = pending.as_mut().poll(cx) else {
+                return;
+            };
+            self.backend_transition_complete(endpoint, queue);
+        }
+        for _ in 0..MAX_POLL_ITERATIONS {
+            let Some(queue) = &mut self.backend.queue else {
+                return;
+            };
+            let ready = queue.poll_ready(cx).is_ready();
+            let mut progress = self.process_tx();
+            if ready {
+                progress |= self.process_rx();
+                progress |= self.process_tx_completions();
+            }
+            if !ready && !progress {
+                return;
+            }
+        }
+        // Yield to other devices, but poll again soon.
+        cx.waker().wake_by_ref();
+    }
+}
+
+impl SaveRestore for E1000 {
+    type SavedState = SavedStateNotSupported;
+
+    fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+        Err(SaveError::NotSupported)
+    }
+
+    fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+        match state {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chipset_device::mmio::ExternallyManagedMmioIntercepts;
+    use net_backend::loopback::LoopbackEndpoint;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use vmcore::vm_task::SingleDriverBackend;
+
+    const RX_RING: u64 = 0x1000;
+    const TX_RING: u64 = 0x2000;
+    const RX_BUFFERS: u64 = 0x4000;
+    const TX_BUFFER: u64 = 0xc000;
+    const RING_LEN: u32 = 16;
+    const FRAME_LEN: u16 = 60;
+
+    /// Returns a device attached to a loopback endpoint, with both rings
+    /// configured but not yet enabled.
+    fn new_e1000(driver: &DefaultDriver) -> (E1000, GuestMemory) {
+        let guest_memory = GuestMemory::allocate(0x10000);
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let mut e1000 = E1000::new(
+            &driver_source,
+            guest_memory.clone(),
+            LineInterrupt::detached(),
+            &mut ExternallyManagedMmioIntercepts,
+            Box::new(LoopbackEndpoint::new()),
+            MacAddress::new([0x00, 0x15, 0x5d, 0x12, 0x34, 0x56]),
+        );
+        for i in 0..RING_LEN as u64 {
+            guest_memory
+                .write_plain(
+                    RX_RING + i * spec::DESCRIPTOR_SIZE,
+                    &(RX_BUFFERS + i * 0x800),
+                )
+                .unwrap();
+        }
+        guest_memory
+            .write_at(TX_BUFFER, &[0xff; FRAME_LEN as usize])
+            .unwrap();
+        let ring_bytes = RING_LEN * spec::DESCRIPTOR_SIZE as u32;
+        e1000.write_u32(Register::RDBAL.0, RX_RING as u32);
+        e1000.write_u32(Register::RDLEN.0, ring_bytes);
+        e1000.write_u32(Register::TDBAL.0, TX_RING as u32);
+        e1000.write_u32(Register::TDLEN.0, ring_bytes);
+        (e1000, guest_memory)
+    }
+
+    fn poll(e1000: &mut E1000) {
+        e1000.poll_device(&mut Context::from_waker(Waker::noop()));
+    }
+
+    /// Enables both rings and gives the device `rx` receive buffers.
+    fn start(e1000: &mut E1000, rx: u32) {
+        e1000.write_u32(Register::RDT.0, rx);
+        e1000.write_u32(
+            Register::RCTL.0,
+            spec::Rctl::new().with_en(true).with_upe(true).into(),
+        );
+        e1000.write_u32(Register::TCTL.0, spec::Tctl::new().with_en(true).into());
+        poll(e1000);
+        assert!(e1000.backend.queue.is_some());
+    }
+
+    /// Writes a single-descriptor packet at `index` in the transmit ring.
+    fn write_tx_descriptor(guest_memory: &GuestMemory, index: u32, eop: bool) {
+        guest_memory
+            .write_plain(
+                TX_RING + index as u64 * spec::DESCRIPTOR_SIZE,
+                &spec::TxLegacyDescriptor {
+                    buffer_addr: TX_BUFFER,
+                    length: FRAME_LEN,
+                    cso: 0,
+                    cmd: TxCommand::new().with_eop_or_tcp(eop).with_rs(true),
+                    status: 0,
+                    css: 0,
+                    special: 0,
+                },
+            )
+            .unwrap();
+    }
+
+    fn rx_descriptor(guest_memory: &GuestMemory, index: u32) -> spec::RxLegacyDescriptor {
+        guest_memory
+            .read_plain(RX_RING + index as u64 * spec::DESCRIPTOR_SIZE)
+            .unwrap()
+    }
+
+    fn tx_done(guest_memory: &GuestMemory, index: u32) -> bool {
+        let status: u8 = guest_memory
+            .read_plain(TX_RING + index as u64 * spec::DESCRIPTOR_SIZE + spec::TX_STATUS_OFFSET)
+            .unwrap();
+        status & spec::TX_STATUS_DD != 0
+    }
+
+    #[async_test]
+    async fn test_loopback(driver: DefaultDriver) {
+        let (mut e1000, guest_memory) = new_e1000(&driver);
+        start(&mut e1000, 4);
+        write_tx_descriptor(&guest_memory, 0, true);
+        e1000.write_u32(Register::TDT.0, 1);
+        poll(&mut e1000);
+
+        assert_eq!(e1000.registers.tdh, 1);
+        assert!(tx_done(&guest_memory, 0));
+        assert_eq!(e1000.registers.rdh, 1);
+        let desc = rx_descriptor(&guest_memory, 0);
+        assert!(desc.status.dd());
+        assert_eq!(desc.length, FRAME_LEN + 4);
+    }
+
+    #[async_test]
+    async fn test_rx_ring_disabled_in_flight(driver: DefaultDriver) {
+        let (mut e1000, guest_memory) = new_e1000(&driver);
+        start(&mut e1000, 4);
+        write_tx_descriptor(&guest_memory, 0, true);
+        e1000.write_u32(Register::TDT.0, 1);
+        // The packet is looped back into a buffer that was posted before the
+        // ring was removed.
+        e1000.write_u32(Register::RDLEN.0, 0);
+        poll(&mut e1000);
+
+        assert_eq!(e1000.registers.tdh, 1);
+        assert_eq!(e1000.registers.rdh, 0);
+        assert!(!rx_descriptor(&guest_memory, 0).status.dd());
+        assert_eq!(e1000.stats.rx_ring_changed.get(), 1);
+    }
+
+    #[async_test]
+    async fn test_rx_ring_shrunk_in_flight(driver: DefaultDriver) {
+        let (mut e1000, guest_memory) = new_e1000(&driver);
+        e1000.write_u32(Register::RDH.0, 14);
+        start(&mut e1000, 0);
+        write_tx_descriptor(&guest_memory, 0, true);
+        e1000.write_u32(Register::TDT.0, 1);
+        // The posted buffer at index 14 is now past the end of the ring.
+        e1000.write_u32(Register::RDLEN.0, 8 * spec::DESCRIPTOR_SIZE as u32);
+        poll(&mut e1000);
+
+        assert_eq!(e1000.registers.tdh, 1);
+        assert_eq!(e1000.registers.rdh, 14);
+        assert!(!rx_descriptor(&guest_memory, 14).status.dd());
+        assert_eq!(e1000.stats.rx_ring_changed.get(), 1);
+    }
+
+    #[async_test]
+    async fn test_tx_ring_resized_in_flight(driver: DefaultDriver) {
+        let (mut e1000, guest_memory) = new_e1000(&driver);
+        start(&mut e1000, 4);
+        // Make half a packet available, then remove the ring.
+        write_tx_descriptor(&guest_memory, 0, false);
+        e1000.write_u32(Register::TDT.0, 1);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 0);
+        e1000.write_u32(Register::TDLEN.0, 0);
+        write_tx_descriptor(&guest_memory, 1, true);
+        e1000.write_u32(Register::TDT.0, 2);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 0);
+
+        // Shrink the ring so that the tail is past the end.
+        e1000.write_u32(Register::TDLEN.0, 8 * spec::DESCRIPTOR_SIZE as u32);
+        e1000.write_u32(Register::TDT.0, 10);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 0);
+        assert!(!tx_done(&guest_memory, 1));
+
+        // Once the tail is back in the ring, the packet is sent.
+        e1000.write_u32(Register::TDT.0, 2);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 2);
+        assert!(tx_done(&guest_memory, 0));
+        assert!(tx_done(&guest_memory, 1));
+        assert_eq!(e1000.registers.rdh, 1);
+    }
+
+    #[async_test]
+    async fn test_ring_registers_out_of_range(driver: DefaultDriver) {
+        let (mut e1000, guest_memory) = new_e1000(&driver);
+        // A tail past the end of the ring posts no receive buffers.
+        start(&mut e1000, 100);
+        assert!(e1000.rx_buffers.lock().is_empty());
+
+        // Nor does it send anything.
+        write_tx_descriptor(&guest_memory, 0, true);
+        e1000.write_u32(Register::TDT.0, 100);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 0);
+        assert!(!tx_done(&guest_memory, 0));
+
+        // A head past the end of the ring sends nothing either.
+        e1000.write_u32(Register::TDH.0, 100);
+        e1000.write_u32(Register::TDT.0, 1);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 100);
+        assert!(!tx_done(&guest_memory, 0));
+
+        // With valid registers, receives work, but a packet that completes
+        // after the guest moves the receive head out of the ring is dropped.
+        e1000.write_u32(Register::RDT.0, 4);
+        e1000.write_u32(Register::TDH.0, 0);
+        e1000.write_u32(Register::RDH.0, 100);
+        poll(&mut e1000);
+        assert_eq!(e1000.registers.tdh, 1);
+        assert!(tx_done(&guest_memory, 0));
+        assert_eq!(e1000.registers.rdh, 100);
+        assert!(!rx_descriptor(&guest_memory, 0).status.dd());
+        assert_eq!(e1000.stats.rx_ring_changed.get(), 1);
+    }
+}
@@ -0,0 +1,532 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions from the Intel 82574 GbE controller datasheet.
+
+#![expect(missing_docs)]
+
+use bitfield_struct::bitfield;
+use inspect::Inspect;
+use open_enum::open_enum;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+pub const VENDOR_ID: u16 = 0x8086;
+/// The 82574L device ID.
+pub const DEVICE_ID: u16 = 0x10d3;
+
+open_enum! {
+    /// Register offsets in BAR0.
+    pub enum Register: u16 {
+        CTRL = 0x0000,
+        STATUS = 0x0008,
+        EECD = 0x0010,
+        EERD = 0x0014,
+        CTRL_EXT = 0x0018,
+        MDIC = 0x0020,
+        FCAL = 0x0028,
+        FCAH = 0x002c,
+        FCT = 0x0030,
+        VET = 0x0038,
+        ICR = 0x00c0,
+        ITR = 0x00c4,
+        ICS = 0x00c8,
+        IMS = 0x00d0,
+        IMC = 0x00d8,
+        EIAC = 0x00dc,
+        IAM = 0x00e0,
+        RCTL = 0x0100,
+        FCTTV = 0x0170,
+        TCTL = 0x0400,
+        TIPG = 0x0410,
+        LEDCTL = 0x0e00,
+        EXTCNF_CTRL = 0x0f00,
+        PBA = 0x1000,
+        EEMNGCTL = 0x1010,
+        FCRTL = 0x2160,
+        FCRTH = 0x2168,
+        RDBAL = 0x2800,
+        RDBAH = 0x2804,
+        RDLEN = 0x2808,
+        RDH = 0x2810,
+        RDT = 0x2818,
+        RDTR = 0x2820,
+        RXDCTL = 0x2828,
+        RADV = 0x282c,
+        TDBAL = 0x3800,
+        TDBAH = 0x3804,
+        TDLEN = 0x3808,
+        TDH = 0x3810,
+        TDT = 0x3818,
+        TIDV = 0x3820,
+        TXDCTL = 0x3828,
+        TADV = 0x382c,
+        RXCSUM = 0x5000,
+        RFCTL = 0x5008,
+        MANC = 0x5820,
+        SWSM = 0x5b50,
+        FWSM = 0x5b54,
+    }
+}
+
+/// The statistics registers, which are cleared on read.
+pub const STATS_START: u16 = 0x4000;
+pub const STATS_END: u16 = 0x4100;
+
+open_enum! {
+    /// Statistics register offsets.
+    pub enum StatsRegister: u16 {
+        MPC = 0x4010,
+        TPR = 0x40d0,
+        TPT = 0x40d4,
+        GPRC = 0x4074,
+        BPRC = 0x4078,
+        MPRC = 0x407c,
+        GPTC = 0x4080,
+        GORCL = 0x4088,
+        GORCH = 0x408c,
+        GOTCL = 0x4090,
+        GOTCH = 0x4094,
+        TORL = 0x40c0,
+        TORH = 0x40c4,
+        TOTL = 0x40c8,
+        TOTH = 0x40cc,
+    }
+}
+
+/// The multicast table array.
+pub const MTA_START: u16 = 0x5200;
+pub const MTA_LEN: usize = 128;
+/// The receive address registers, each a RAL/RAH pair.
+pub const RA_START: u16 = 0x5400;
+pub const RA_LEN: usize = 16;
+/// The VLAN filter table array.
+pub const VFTA_START: u16 = 0x5600;
+pub const VFTA_LEN: usize = 128;
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct Ctrl {
+    pub fd: bool,
+    #[bits(1)]
+    _reserved: u32,
+    pub gio_master_disable: bool,
+    #[bits(2)]
+    _reserved2: u32,
+    pub asde: bool,
+    pub slu: bool,
+    #[bits(1)]
+    _reserved3: u32,
+    #[bits(2)]
+    pub speed: u32,
+    #[bits(1)]
+    _reserved4: u32,
+    pub frcspd: bool,
+    pub frcdplx: bool,
+    #[bits(7)]
+    _reserved5: u32,
+    pub adv3wuc: bool,
+    #[bits(5)]
+    _reserved6: u32,
+    pub rst: bool,
+    pub rfce: bool,
+    pub tfce: bool,
+    #[bits(1)]
+    _reserved7: u32,
+    pub vme: bool,
+    pub phy_rst: bool,
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct Status {
+    pub fd: bool,
+    pub lu: bool,
+    #[bits(2)]
+    _reserved: u32,
+    pub txoff: bool,
+    #[bits(1)]
+    _reserved2: u32,
+    #[bits(2)]
+    pub speed: u32,
+    #[bits(2)]
+    pub asdv: u32,
+    pub phyra: bool,
+    #[bits(8)]
+    _reserved3: u32,
+    pub gio_master_enable: bool,
+    #[bits(12)]
+    _reserved4: u32,
+}
+
+/// STATUS.SPEED and CTRL.SPEED value for 1000 Mb/s.
+pub const SPEED_1000: u32 = 2;
+
+#[bitfield(u32)]
+pub struct Eecd {
+    #[bits(8)]
+    _reserved: u32,
+    pub pres: bool,
+    pub auto_rd: bool,
+    #[bits(1)]
+    _reserved2: u32,
+    #[bits(4)]
+    pub size: u32,
+    #[bits(17)]
+    _reserved3: u32,
+}
+
+#[bitfield(u32)]
+pub struct Eerd {
+    pub start: bool,
+    pub done: bool,
+    #[bits(14)]
+    pub addr: u16,
+    pub data: u16,
+}
+
+#[bitfield(u32)]
+pub struct Mdic {
+    pub data: u16,
+    #[bits(5)]
+    pub regadd: u8,
+    #[bits(5)]
+    pub phyadd: u8,
+    #[bits(2)]
+    pub op: u8,
+    pub ready: bool,
+    pub interrupt: bool,
+    pub error: bool,
+    #[bits(1)]
+    _reserved: u32,
+}
+
+pub const MDIC_OP_WRITE: u8 = 1;
+pub const MDIC_OP_READ: u8 = 2;
+
+#[bitfield(u32)]
+pub struct CtrlExt {
+    #[bits(27)]
+    _reserved: u32,
+    pub iame: bool,
+    #[bits(4)]
+    _reserved2: u32,
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct Interrupts {
+    pub txdw: bool,
+    pub txqe: bool,
+    pub lsc: bool,
+    pub rxseq: bool,
+    pub rxdmt0: bool,
+    #[bits(1)]
+    _reserved: u32,
+    pub rxo: bool,
+    pub rxt0: bool,
+    #[bits(23)]
+    _reserved2: u32,
+    pub int_asserted: bool,
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct Rctl {
+    #[bits(1)]
+    _reserved: u32,
+    pub en: bool,
+    pub sbp: bool,
+    pub upe: bool,
+    pub mpe: bool,
+    pub lpe: bool,
+    #[bits(2)]
+    pub lbm: u32,
+    #[bits(2)]
+    pub rdmts: u32,
+    #[bits(2)]
+    pub dtyp: u32,
+    #[bits(2)]
+    pub mo: u32,
+    #[bits(1)]
+    _reserved2: u32,
+    pub bam: bool,
+    #[bits(2)]
+    pub bsize: u32,
+    pub vfe: bool,
+    pub cfien: bool,
+    pub cfi: bool,
+    #[bits(1)]
+    _reserved3: u32,
+    pub dpf: bool,
+    pub pmcf: bool,
+    #[bits(1)]
+    _reserved4: u32,
+    pub bsex: bool,
+    pub secrc: bool,
+    #[bits(5)]
+    _reserved5: u32,
+}
+
+impl Rctl {
+    /// The size of each receive buffer, in bytes.
+    pub fn buffer_size(&self) -> u32 {
+        match (self.bsex(), self.bsize()) {
+            (false, 0) => 2048,
+            (false, 1) => 1024,
+            (false, 2) => 512,
+            (false, _) => 256,
+            // This combination is reserved.
+            (true, 0) => 2048,
+            (true, 1) => 16384,
+            (true, 2) => 8192,
+            (true, _) => 4096,
+        }
+    }
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct Tctl {
+    #[bits(1)]
+    _reserved: u32,
+    pub en: bool,
+    #[bits(1)]
+    _reserved2: u32,
+    pub psp: bool,
+    #[bits(8)]
+    pub ct: u32,
+    #[bits(10)]
+    pub cold: u32,
+    pub swxoff: bool,
+    #[bits(1)]
+    _reserved3: u32,
+    pub rtlc: bool,
+    #[bits(7)]
+    _reserved4: u32,
+}
+
+#[bitfield(u32)]
+pub struct Rfctl {
+    #[bits(15)]
+    _reserved: u32,
+    pub exsten: bool,
+    #[bits(16)]
+    _reserved2: u32,
+}
+
+#[bitfield(u32)]
+pub struct RahFlags {
+    #[bits(16)]
+    pub addr_high: u16,
+    #[bits(2)]
+    pub asel: u32,
+    #[bits(13)]
+    _reserved: u32,
+    pub av: bool,
+}
+
+/// The size of a transmit or receive descriptor.
+pub const DESCRIPTOR_SIZE: u64 = 16;
+
+/// A legacy transmit descriptor.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct TxLegacyDescriptor {
+    pub buffer_addr: u64,
+    pub length: u16,
+    pub cso: u8,
+    pub cmd: TxCommand,
+    pub status: u8,
+    pub css: u8,
+    pub special: u16,
+}
+
+/// A transmit context descriptor, for `TxCommand::dext` with
+/// [`TX_DTYP_CONTEXT`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct TxContextDescriptor {
+    pub ipcss: u8,
+    pub ipcso: u8,
+    pub ipcse: u16,
+    pub tucss: u8,
+    pub tucso: u8,
+    pub tucse: u16,
+    pub paylen_dtyp: [u8; 3],
+    pub tucmd: TxCommand,
+    pub status: u8,
+    pub hdrlen: u8,
+    pub mss: u16,
+}
+
+/// A transmit data descriptor, for `TxCommand::dext` with
+/// [`TX_DTYP_DATA`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct TxDataDescriptor {
+    pub buffer_addr: u64,
+    pub dtalen_dtyp: [u8; 3],
+    pub dcmd: TxCommand,
+    pub status: u8,
+    pub popts: TxPacketOptions,
+    pub special: u16,
+}
+
+impl TxDataDescriptor {
+    pub fn length(&self) -> u32 {
+        u32::from_le_bytes([
+            self.dtalen_dtyp[0],
+            self.dtalen_dtyp[1],
+            self.dtalen_dtyp[2] & 0xf,
+            0,
+        ])
+    }
+}
+
+/// Returns the descriptor type of an extended transmit descriptor.
+pub fn tx_descriptor_type(descriptor: &[u8; 16]) -> u8 {
+    descriptor[10] >> 4
+}
+
+pub const TX_DTYP_CONTEXT: u8 = 0;
+pub const TX_DTYP_DATA: u8 = 1;
+
+/// The offset of the status byte in all transmit descriptor formats.
+pub const TX_STATUS_OFFSET: u64 = 12;
+/// The descriptor done bit in the transmit status byte.
+pub const TX_STATUS_DD: u8 = 1;
+
+/// The command byte of a legacy or data descriptor, or the TUCMD byte of a
+/// context descriptor.
+#[bitfield(u8)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct TxCommand {
+    /// End of packet, or TCP (vs UDP) for a context descriptor.
+    pub eop_or_tcp: bool,
+    /// Insert FCS, or IPv4 (vs IPv6) for a context descriptor.
+    pub ifcs_or_ip: bool,
+    /// Insert checksum (legacy) or TCP segmentation enable (extended).
+    pub ic_or_tse: bool,
+    pub rs: bool,
+    #[bits(1)]
+    _reserved: u8,
+    pub dext: bool,
+    pub vle: bool,
+    pub ide: bool,
+}
+
+#[bitfield(u8)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct TxPacketOptions {
+    pub ixsm: bool,
+    pub txsm: bool,
+    #[bits(6)]
+    _reserved: u8,
+}
+
+/// A legacy receive descriptor.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RxLegacyDescriptor {
+    pub buffer_addr: u64,
+    pub length: u16,
+    pub checksum: u16,
+    pub status: RxStatus,
+    pub errors: u8,
+    pub special: u16,
+}
+
+/// The write-back format of an extended receive descriptor, used when
+/// `RFCTL.EXSTEN` is set. The read format is just the buffer address
+/// followed by a reserved field.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RxExtendedWriteback {
+    pub mrq: u32,
+    pub rss_hash: u32,
+    pub status: RxStatus,
+    pub extended_status: u8,
+    pub extended_errors: u16,
+    pub length: u16,
+    pub vlan: u16,
+}
+
+#[bitfield(u8)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RxStatus {
+    pub dd: bool,
+    pub eop: bool,
+    /// Ignore checksum indication.
+    pub ixsm: bool,
+    pub vp: bool,
+    pub udpcs: bool,
+    pub tcpcs: bool,
+    pub ipcs: bool,
+    pub pif: bool,
+}
+
+open_enum! {
+    /// NVM word offsets.
+    pub enum NvmWord: u16 {
+        MAC_ADDRESS_0 = 0x00,
+        MAC_ADDRESS_1 = 0x01,
+        MAC_ADDRESS_2 = 0x02,
+        ID_LED_SETTINGS = 0x04,
+        SUBSYSTEM_ID = 0x0b,
+        SUBSYSTEM_VENDOR_ID = 0x0c,
+        DEVICE_ID = 0x0d,
+        VENDOR_ID = 0x0e,
+        INIT_CONTROL_2 = 0x0f,
+        CHECKSUM = 0x3f,
+    }
+}
+
+/// The NVM words from 0 through [`NvmWord::CHECKSUM`] must sum to this value.
+pub const NVM_CHECKSUM_SUM: u16 = 0xbaba;
+/// The number of NVM words.
+pub const NVM_WORDS: usize = 64;
+
+/// The MDIO address of the PHY.
+pub const PHY_ADDRESS: u8 = 1;
+
+open_enum! {
+    /// PHY register numbers.
+    pub enum PhyRegister: u8 {
+        CONTROL = 0,
+        STATUS = 1,
+        ID1 = 2,
+        ID2 = 3,
+        AUTONEG_ADV = 4,
+        LP_ABILITY = 5,
+        AUTONEG_EXP = 6,
+        GIGABIT_CONTROL = 9,
+        GIGABIT_STATUS = 10,
+        EXT_STATUS = 15,
+        SPEC_CONTROL = 16,
+        SPEC_STATUS = 17,
+        PAGE_SELECT = 22,
+    }
+}
+
+/// The BME1000 PHY ID, revision 1.
+pub const PHY_ID1: u16 = 0x0141;
+pub const PHY_ID2: u16 = 0x0cb1;
+
+pub const PHY_CONTROL_RESET: u16 = 0x8000;
+pub const PHY_CONTROL_RESTART_AUTONEG: u16 = 0x0200;
+
+/// Extended capabilities, autonegotiation complete, autonegotiation capable,
+/// and link up.
+pub const PHY_STATUS_LINK_UP: u16 = 0x796d;
+/// 1000 Mb/s full duplex link partner ability, with the local and remote
+/// receivers OK.
+pub const PHY_GIGABIT_STATUS: u16 = 0x3800;
+/// 1000BASE-T full duplex capable.
+pub const PHY_EXT_STATUS: u16 = 0x3000;
+/// 1000 Mb/s, full duplex, speed and duplex resolved, link up.
+pub const PHY_SPEC_STATUS_LINK_UP: u16 = 0xac00;
+/// 100BASE-TX and 10BASE-T, full and half duplex, with autonegotiation.
+pub const PHY_LP_ABILITY: u16 = 0xc1e1;
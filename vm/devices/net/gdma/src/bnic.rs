@@ -38,12 +38,14 @@
 use guestmem::Limit;
 use guestmem::MemoryRead;
 use guestmem::MemoryWrite;
+use inspect::Inspect;
 use inspect::InspectMut;
 use net_backend::BufferAccess;
 use net_backend::Endpoint;
 use net_backend::L3Protocol;
 use net_backend::Queue;
 use net_backend::QueueConfig;
+use net_backend::RssConfig;
 use net_backend::RxBufferSegment;
 use net_backend::RxChecksumState;
 use net_backend::RxId;
@@ -153,6 +155,20 @@ fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
     }
 }
 
+/// The maximum number of send and receive queues per vport.
+const MAX_QUEUES_PER_VPORT: u16 = 16;
+
+/// The number of entries in a vport's RSS indirection table.
+const NUM_INDIRECTION_ENTRIES: usize = 128;
+
+/// Returns the work queue object handle for queue `index` of vport `vport`.
+///
+/// The low 32 bits are the vport index, so the handle of the first queue is
+/// just the vport index.
+fn wq_obj_handle(vport: u32, index: usize) -> u64 {
+    ((index as u64) << 32) | vport as u64
+}
+
 pub struct BasicNic {
     vports: Vec<Vport>,
 }
@@ -167,8 +183,11 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
 struct Vport {
     mac_address: MacAddress,
     endpoint: Box<dyn Endpoint>,
-    task: TaskControl<TxRxState, TxRxTask>,
-    queue_cfg: QueueCfg,
+    tasks: Vec<TaskControl<TxRxState, TxRxTask>>,
+    tx_queues: Vec<Option<TxQueue>>,
+    rx_queues: Vec<Option<RxQueue>>,
+    steering: RxSteering,
+    max_queues: u16,
     serial_no: u32,
 }
 
@@ -177,17 +196,178 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
         req.respond()
             .field("mac_address", self.mac_address)
             .field_mut("endpoint", self.endpoint.as_mut())
-            .field("tx_wq", self.queue_cfg.tx.map(|(wq, _cq)| wq))
-            .field("tx_cq", self.queue_cfg.tx.map(|(_wq, cq)| cq))
-            .field("rx_wq", self.queue_cfg.tx.map(|(wq, _cq)| wq))
-            .field("rx_cq", self.queue_cfg.tx.map(|(_wq, cq)| cq))
-            .merge(&mut self.task);
+            .field(
+                "tx_queues",
+                inspect::iter_by_index(self.tx_queues.iter().map(|q| q.as_ref())),
+            )
+            .field(
+                "rx_queues",
+                inspect::iter_by_index(self.rx_queues.iter().map(|q| q.as_ref())),
+            )
+            .field("steering", &self.steering)
+            .field("max_queues", self.max_queues)
+            .field("serial_no", self.serial_no)
+            .fields_mut("queues", self.tasks.iter_mut().enumerate());
+    }
+}
+
+#[derive(Inspect)]
+struct TxQueue {
+    wq_id: u32,
+    cq_id: u32,
+}
+
+#[derive(Inspect)]
+struct RxQueue {
+    wq_id: u32,
+    cq_id: u32,
+    /// The receive buffers posted by the guest, kept across endpoint restarts
+    /// so that they can be handed to the new endpoint queues.
+    #[inspect(with = "|x| x.lock().len()")]
+    packets: Arc<Mutex<Slab<RxPacket>>>,
+}
+
+/// The receive steering configuration of a vport.
+#[derive(Inspect)]
+struct RxSteering {
+    rss_enabled: bool,
+    hash_key: [u8; 40],
+    #[inspect(hex)]
+    default_rxobj: u64,
+    #[inspect(with = "|x| inspect::iter_by_index(x).map_value(inspect::AsHex)")]
+    indirection_table: Vec<u64>,
+}
+
+impl RxSteering {
+    fn new(vport_index: u32) -> Self {
+        Self {
+            rss_enabled: false,
+            hash_key: [0; 40],
+            default_rxobj: wq_obj_handle(vport_index, 0),
+            indirection_table: Vec::new(),
+        }
     }
 }
 
-struct QueueCfg {
-    tx: Option<(u32, u32)>,
-    rx: Option<(u32, u32)>,
+impl Vport {
+    fn is_running(&self) -> bool {
+        !self.tasks.is_empty()
+    }
+
+    /// Returns the queue index for the receive work queue object `wq_obj`.
+    fn rx_queue_index(&self, vport: u32, wq_obj: u64) -> anyhow::Result<usize> {
+        if wq_obj as u32 != vport {
+            anyhow::bail!("wq obj {wq_obj:#x} is for a different vport");
+        }
+        let index = (wq_obj >> 32) as usize;
+        if self.rx_queues.get(index).is_none_or(|q| q.is_none()) {
+            anyhow::bail!("invalid rx wq obj {wq_obj:#x}");
+        }
+        Ok(index)
+    }
+
+    async fn stop(&mut self) {
+        for mut task in self.tasks.drain(..) {
+            task.stop().await;
+            task.remove();
+        }
+        self.endpoint.stop().await;
+    }
+
+    async fn start(&mut self, vport_index: u32, state: &mut HwState) -> anyhow::Result<()> {
+        // Each endpoint queue services a send and receive queue pair with the
+        // same index.
+        let queue_count = self.rx_queues.len().max(self.tx_queues.len());
+        let mut pairs = Vec::with_capacity(queue_count);
+        for index in 0..queue_count {
+            match (
+                self.tx_queues.get(index).and_then(|q| q.as_ref()),
+                self.rx_queues.get(index).and_then(|q| q.as_ref()),
+            ) {
+                (Some(tx), Some(rx)) => pairs.push((index, tx, rx)),
+                (None, None) => {}
+                _ => anyhow::bail!("queue {index} is missing its send or receive queue"),
+            }
+        }
+        if pairs.is_empty() {
+            anyhow::bail!("queues not configured");
+        }
+
+        // Map the steering configuration's queue objects to endpoint queue
+        // numbers. Without RSS, everything goes to the default queue.
+        let queue_number = |wq_obj| -> anyhow::Result<u16> {
+            let index = self.rx_queue_index(vport_index, wq_obj)?;
+            Ok(pairs.iter().position(|&(i, _, _)| i == index).unwrap() as u16)
+        };
+        let default_queue = queue_number(self.steering.default_rxobj)?;
+        let indirection_table = if self.steering.rss_enabled {
+            self.steering
+                .indirection_table
+                .iter()
+                .map(|&wq_obj| queue_number(wq_obj))
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else {
+            vec![default_queue]
+        };
+
+        let initial_rx = pairs
+            .iter()
+            .map(|(_, _, rx)| {
+                rx.packets
+                    .lock()
+                    .iter()
+                    .map(|(id, _)| RxId(id as u32))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut queues = Vec::new();
+        self.endpoint
+            .get_queues(
+                pairs
+                    .iter()
+                    .zip(&initial_rx)
+                    .map(|((_, _, rx), initial_rx)| QueueConfig {
+                        pool: Box::new(GuestBuffers {
+                            gm: state.queues.gm.clone(),
+                            rx_packets: rx.packets.clone(),
+                            buffer_segments: Vec::new(),
+                        }),
+                        initial_rx,
+                        driver: Box::new(state.queues.driver.clone()),
+                    })
+                    .collect(),
+                Some(&RssConfig {
+                    key: &self.steering.hash_key,
+                    indirection_table: &indirection_table,
+                    flags: 0,
+                }),
+                &mut queues,
+            )
+            .await?;
+
+        for (((_, tx, rx), initial_rx), epqueue) in pairs.iter().zip(&initial_rx).zip(queues) {
+            let mut task = TaskControl::new(TxRxState);
+            task.insert(
+                &state.queues.driver,
+                "gdma-bnic",
+                TxRxTask {
+                    queues: state.queues.clone(),
+                    epqueue,
+                    rx_packets: rx.packets.clone(),
+                    sq_id: tx.wq_id,
+                    sq_cq_id: tx.cq_id,
+                    rq_id: rx.wq_id,
+                    rq_cq_id: rx.cq_id,
+                    tx_segment_buffer: Vec::new(),
+                    rx_buf_count: initial_rx.len() as u32,
+                },
+            );
+            task.start();
+            self.tasks.push(task);
+        }
+        Ok(())
+    }
 }
 
 impl BasicNic {
@@ -196,17 +376,28 @@ pub fn new(vports: Vec<VportConfig>) -> Self {
 
         let vports = vports
             .into_iter()
+            .enumerate()
             .map(
-                |VportConfig {
-                     mac_address,
-                     endpoint,
-                 }| {
+                |(
+                    index,
+                    VportConfig {
+                        mac_address,
+                        endpoint,
+                    },
+                )| {
                     assert!(endpoint.is_ordered());
+                    let max_queues = endpoint
+                        .multiqueue_support()
+                        .max_queues
+                        .clamp(1, MAX_QUEUES_PER_VPORT);
                     Vport {
                         mac_address,
                         endpoint,
-                        task: TaskControl::new(TxRxState),
-                        queue_cfg: QueueCfg { tx: None, rx: None },
+                        tasks: Vec::new(),
+                        tx_queues: Vec::new(),
+                        rx_queues: Vec::new(),
+                        steering: RxSteering::new(index as u32),
+                        max_queues,
                         serial_no: 0,
                     }
                 },
@@ -216,6 +407,18 @@ pub fn new(vports: Vec<VportConfig>) -> Self {
         Self { vports }
     }
 
+    /// Stops the vports and forgets their queues and configuration, as on a
+    /// device reset.
+    pub async fn reset(&mut self) {
+        for (index, vport) in self.vports.iter_mut().enumerate() {
+            vport.stop().await;
+            vport.tx_queues.clear();
+            vport.rx_queues.clear();
+            vport.steering = RxSteering::new(index as u32);
+            vport.serial_no = 0;
+        }
+    }
+
     pub async fn handle_req(
         &mut self,
         state: &mut HwState,
@@ -275,10 +478,18 @@ pub async fn handle_req(
                     ty => anyhow::bail!("unsupported queue type: {:?}", ty),
                 };
 
-                if (is_send && vport.queue_cfg.tx.is_some())
-                    || (!is_send && vport.queue_cfg.rx.is_some())
-                {
-                    anyhow::bail!("queue already created");
+                let index = if is_send {
+                    vport.tx_queues.iter().position(|q| q.is_none())
+                } else {
+                    vport.rx_queues.iter().position(|q| q.is_none())
+                }
+                .unwrap_or(if is_send {
+                    vport.tx_queues.len()
+                } else {
+                    vport.rx_queues.len()
+                });
+                if index >= vport.max_queues.into() {
+                    anyhow::bail!("too many queues");
                 }
 
                 let wq_region = state.get_dma_region(req.wq_gdma_region, req.wq_size)?;
@@ -297,14 +508,24 @@ pub async fn handle_req(
                 let resp = ManaCreateWqobjResp {
                     wq_id,
                     cq_id,
-                    wq_obj: req.vport, // use the vport # as the handle
+                    wq_obj: wq_obj_handle(req.vport as u32, index),
                 };
 
-                *if is_send {
-                    &mut vport.queue_cfg.tx
+                if is_send {
+                    if index == vport.tx_queues.len() {
+                        vport.tx_queues.push(None);
+                    }
+                    vport.tx_queues[index] = Some(TxQueue { wq_id, cq_id });
                 } else {
-                    &mut vport.queue_cfg.rx
-                } = Some((wq_id, cq_id));
+                    if index == vport.rx_queues.len() {
+                        vport.rx_queues.push(None);
+                    }
+                    vport.rx_queues[index] = Some(RxQueue {
+                        wq_id,
+                        cq_id,
+                        packets: Default::default(),
+                    });
+                }
 
                 write.write(resp.as_bytes())?;
 
@@ -319,18 +540,34 @@ pub async fn handle_req(
                     .context("failed to read destroy wq obj request")?;
                 let vport = self
                     .vports
-                    .get_mut(req.wq_obj_handle as usize)
+                    .get_mut(req.wq_obj_handle as u32 as usize)
                     .context("invalid obj handle")?;
+                let index = (req.wq_obj_handle >> 32) as usize;
 
-                if vport.task.has_state() {
+                if vport.is_running() {
                     anyhow::bail!("queue still in use");
                 }
-                let (is_send, queues) = match req.wq_type {
-                    GdmaQueueType::GDMA_RQ => (false, &mut vport.queue_cfg.rx),
-                    GdmaQueueType::GDMA_SQ => (true, &mut vport.queue_cfg.tx),
+                let (is_send, (wq_id, cq_id)) = match req.wq_type {
+                    GdmaQueueType::GDMA_RQ => (
+                        false,
+                        vport
+                            .rx_queues
+                            .get_mut(index)
+                            .and_then(Option::take)
+                            .map(|q| (q.wq_id, q.cq_id))
+                            .context("specified queue does not exist")?,
+                    ),
+                    GdmaQueueType::GDMA_SQ => (
+                        true,
+                        vport
+                            .tx_queues
+                            .get_mut(index)
+                            .and_then(Option::take)
+                            .map(|q| (q.wq_id, q.cq_id))
+                            .context("specified queue does not exist")?,
+                    ),
                     ty => anyhow::bail!("unsupported queue type: {:?}", ty),
                 };
-                let (wq_id, cq_id) = queues.take().context("specified queue does not exist")?;
                 state.queues.free_wq(is_send, wq_id).unwrap();
                 state.queues.free_cq(cq_id).unwrap();
                 0
@@ -340,63 +577,75 @@ pub async fn handle_req(
                     .read_plain()
                     .context("reading config vport rx request")?;
                 tracing::debug!(?req, "rx config");
+                let vport_index = req.vport as u32;
                 let vport = self
                     .vports
                     .get_mut(req.vport as usize)
                     .context("invalid vport")?;
 
-                match req.rx_enable {
-                    Tristate::FALSE if vport.task.is_running() => {
-                        vport.task.stop().await;
-                        vport.task.remove();
-                        vport.endpoint.stop().await;
+                let mut steering_changed = false;
+                if req.update_indir_tab != 0 {
+                    let len = req.num_indir_entries as usize;
+                    if len == 0 || len > NUM_INDIRECTION_ENTRIES {
+                        anyhow::bail!("invalid indirection table size {len}");
                     }
-                    Tristate::TRUE if !vport.task.is_running() => {
-                        if let (Some((sq_id, sq_cq_id)), Some((rq_id, rq_cq_id))) =
-                            (vport.queue_cfg.tx, vport.queue_cfg.rx)
-                        {
-                            let rx_packets = Arc::new(Default::default());
-
-                            let mut queues = vec![];
-                            vport
-                                .endpoint
-                                .get_queues(
-                                    vec![QueueConfig {
-                                        pool: Box::new(GuestBuffers {
-                                            gm: state.queues.gm.clone(),
-                                            rx_packets: Arc::clone(&rx_packets),
-                                            buffer_segments: Vec::new(),
-                                        }),
-                                        initial_rx: &[],
-                                        driver: Box::new(state.queues.driver.clone()),
-                                    }],
-                                    None,
-                                    &mut queues,
-                                )
-                                .await?;
-
-                            vport.task.insert(
-                                &state.queues.driver,
-                                "gdma-bnic",
-                                TxRxTask {
-                                    queues: state.queues.clone(),
-                                    epqueue: queues.drain(..).next().unwrap(),
-                                    rx_packets,
-                                    sq_id,
-                                    sq_cq_id,
-                                    rq_id,
-                                    rq_cq_id,
-                                    tx_segment_buffer: Vec::new(),
-                                    rx_buf_count: 0,
-                                },
-                            );
-                            vport.task.start();
-                        } else {
-                            anyhow::bail!("queues not configured");
+                    // The table offset is relative to the start of the
+                    // request, including the header.
+                    let skip = (req.indir_tab_offset as usize)
+                        .checked_sub(size_of::<GdmaReqHdr>() + size_of_val(&req))
+                        .context("invalid indirection table offset")?;
+                    read.skip(skip)?;
+                    let mut table = vec![0u64; len];
+                    read.read(table.as_mut_bytes())
+                        .context("reading indirection table")?;
+                    for &wq_obj in &table {
+                        vport.rx_queue_index(vport_index, wq_obj)?;
+                    }
+                    vport.steering.indirection_table = table;
+                    steering_changed = true;
+                }
+                if req.update_hashkey != 0 {
+                    vport.steering.hash_key = req.hashkey;
+                    steering_changed = true;
+                }
+                if req.update_default_rxobj != 0 {
+                    vport.rx_queue_index(vport_index, req.default_rxobj)?;
+                    vport.steering.default_rxobj = req.default_rxobj;
+                    steering_changed = true;
+                }
+                match req.rss_enable {
+                    Tristate::TRUE => {
+                        if vport.steering.indirection_table.is_empty() {
+                            anyhow::bail!("rss enabled without an indirection table");
                         }
+                        steering_changed |= !vport.steering.rss_enabled;
+                        vport.steering.rss_enabled = true;
+                    }
+                    Tristate::FALSE => {
+                        steering_changed |= vport.steering.rss_enabled;
+                        vport.steering.rss_enabled = false;
                     }
                     _ => {}
                 }
+
+                let enable = match req.rx_enable {
+                    Tristate::TRUE => true,
+                    Tristate::FALSE => false,
+                    _ => vport.is_running(),
+                };
+
+                // The endpoint applies the steering configuration when its
+                // queues are created, so restart it to apply any changes. The
+                // posted receive buffers are handed to the new queues.
+                if vport.is_running() && (!enable || steering_changed) {
+                    vport.stop().await;
+                }
+                if enable && !vport.is_running() {
+                    if let Err(err) = vport.start(vport_index, state).await {
+                        vport.stop().await;
+                        return Err(err);
+                    }
+                }
                 0
             }
             ManaCommandCode::MANA_VTL2_MOVE_FILTER => {
@@ -429,9 +678,9 @@ pub async fn handle_req(
                     .context("invalid vport")?;
 
                 let resp = ManaQueryVportCfgResp {
-                    max_num_sq: 1,
-                    max_num_rq: 1,
-                    num_indirection_ent: 128,
+                    max_num_sq: vport.max_queues.into(),
+                    max_num_rq: vport.max_queues.into(),
+                    num_indirection_ent: NUM_INDIRECTION_ENTRIES as u32,
                     reserved1: 0,
                     mac_addr: vport.mac_address.to_bytes(),
                     reserved2: [0; 2],
@@ -19,6 +19,7 @@
 use device_emulators::read_as_u32_chunks;
 use device_emulators::write_as_u32_chunks;
 use futures::FutureExt;
+use futures::future::BoxFuture;
 use gdma_defs::CqEqDoorbellValue;
 use gdma_defs::DB_CQ;
 use gdma_defs::DB_EQ;
@@ -40,6 +41,8 @@
 use net_backend::Endpoint;
 use net_backend_resources::mac_address::MacAddress;
 use pci_core::capabilities::msix::MsixEmulator;
+use pci_core::capabilities::pci_express::FlrRequest;
+use pci_core::capabilities::pci_express::PciExpressCapability;
 use pci_core::cfg_space_emu::BarMemoryKind;
 use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
 use pci_core::cfg_space_emu::DeviceBars;
@@ -74,15 +77,21 @@ pub struct GdmaDevice {
     shmem: Shmem,
     destroying_hwc: bool,
     queues: Arc<Queues>,
-    hwc: TaskControl<Devices, HwControl>,
+    /// `None` while a reset is in progress.
+    hwc: Option<TaskControl<Devices, HwControl>>,
+    resetting: Option<BoxFuture<'static, TaskControl<Devices, HwControl>>>,
+    flr: FlrRequest,
 }
 
 impl InspectMut for GdmaDevice {
     fn inspect_mut(&mut self, req: inspect::Request<'_>) {
-        req.respond()
-            .field("config", &self.config)
+        let mut resp = req.respond();
+        resp.field("config", &self.config)
             .field("queues", &self.queues)
-            .merge(&mut self.hwc);
+            .field("resetting", self.resetting.is_some());
+        if let Some(hwc) = &mut self.hwc {
+            resp.merge(hwc);
+        }
     }
 }
 
@@ -143,7 +152,13 @@ pub fn new(
             type0_sub_system_id: 0,
         };
 
-        let capabilities = vec![Box::new(msix_capability) as _];
+        // The device is a virtual function, which the guest can reset
+        // independently of the other functions with a function level reset.
+        let flr = FlrRequest::new();
+        let capabilities = vec![
+            Box::new(PciExpressCapability::new(Some(flr.clone()))) as _,
+            Box::new(msix_capability) as _,
+        ];
 
         let bar0_mem = mmio_registration.new_io_region("regs", 8192);
         let bar2_mem = mmio_registration.new_io_region("msix", msix.bar_len());
@@ -180,10 +195,52 @@ pub fn new(
             regmap,
             queues,
             destroying_hwc: false,
-            hwc: TaskControl::new(Devices {
+            hwc: Some(TaskControl::new(Devices {
                 bnic: bnic::BasicNic::new(vports),
-            }),
+            })),
+            resetting: None,
+            flr,
+        }
+    }
+
+    /// Begins resetting the device. The HWC and vports stop asynchronously,
+    /// so the reset completes in [`Self::poll_reset`].
+    fn begin_reset(&mut self) {
+        self.config.reset();
+        self.shmem = Shmem(FromZeros::new_zeroed());
+        self.destroying_hwc = false;
+        if let Some(mut hwc) = self.hwc.take() {
+            self.resetting = Some(Box::pin(async move {
+                hwc.stop().await;
+                if hwc.has_state() {
+                    hwc.remove();
+                }
+                hwc.task_mut().bnic.reset().await;
+                hwc
+            }));
+        }
+    }
+
+    /// Polls the pending reset, returning whether the device is ready.
+    fn poll_reset(&mut self) -> bool {
+        if let Some(resetting) = &mut self.resetting {
+            let Some(hwc) = resetting.now_or_never() else {
+                return false;
+            };
+            self.finish_reset(hwc);
         }
+        true
+    }
+
+    fn finish_reset(&mut self, hwc: TaskControl<Devices, HwControl>) {
+        self.resetting = None;
+        self.hwc = Some(hwc);
+        // Drop all the queues the guest created.
+        self.queues = Arc::new(Queues::new(
+            self.queues.gm.clone(),
+            self.queues.driver.clone(),
+            &self.msix,
+        ));
     }
 
     fn read_regmap(&self, offset: usize, data: &mut [u8]) {
@@ -193,9 +250,10 @@ fn read_regmap(&self, offset: usize, data: &mut [u8]) {
     fn read_shmem(&mut self, offset: usize, data: &mut [u8]) {
         // If there is a pending DESTROY_HWC request, then poll whether the HWC
         // task has stopped.
-        if self.destroying_hwc && self.hwc.stop().now_or_never().is_some() {
-            if self.hwc.has_state() {
-                let _ = self.hwc.remove();
+        let hwc = self.hwc.as_mut().expect("not resetting");
+        if self.destroying_hwc && hwc.stop().now_or_never().is_some() {
+            if hwc.has_state() {
+                let _ = hwc.remove();
             }
             self.destroying_hwc = false;
             self.complete_smc(0);
@@ -231,12 +289,13 @@ fn handle_smc(&mut self) -> Result<bool, SmcError> {
         if hdr.is_response() {
             return Err(SmcError::RequestIsResponse);
         }
+        let hwc = self.hwc.as_mut().expect("not resetting");
         match SmcMessageType(hdr.msg_type()) {
             SmcMessageType::SMC_MSG_TYPE_ESTABLISH_HWC => {
                 if hdr.msg_version() != SMC_MSG_TYPE_ESTABLISH_HWC_VERSION {
                     return Err(SmcError::UnsupportedVersion);
                 }
-                if self.hwc.has_state() {
+                if hwc.has_state() {
                     return Err(SmcError::HwcAlreadyActive);
                 }
                 let packed = self.shmem.0.as_bytes();
@@ -252,7 +311,7 @@ fn handle_smc(&mut self) -> Result<bool, SmcError> {
                     | ((high << 40) & high_mask);
                 let sq_gpn = (u64::from_ne_bytes(packed[18..26].try_into().unwrap()) & low_mask)
                     | ((high << 36) & high_mask);
-                let hwc = HwControl::new(
+                let state = HwControl::new(
                     self.queues.clone(),
                     sq_gpn * PAGE_SIZE64,
                     rq_gpn * PAGE_SIZE64,
@@ -261,8 +320,8 @@ fn handle_smc(&mut self) -> Result<bool, SmcError> {
                     msix,
                 )
                 .map_err(SmcError::QueueAlloc)?;
-                self.hwc.insert(&self.queues.driver, "gdma-hwc", hwc);
-                self.hwc.start();
+                hwc.insert(&self.queues.driver, "gdma-hwc", state);
+                hwc.start();
                 Ok(true)
             }
             SmcMessageType::SMC_MSG_TYPE_DESTROY_HWC => {
@@ -271,7 +330,7 @@ fn handle_smc(&mut self) -> Result<bool, SmcError> {
                 }
                 // Tell HWC to stop. When the guest reads shared memory, we will
                 // poll whether it has stopped yet.
-                self.hwc.stop().now_or_never();
+                hwc.stop().now_or_never();
                 self.destroying_hwc = true;
                 Ok(false)
             }
@@ -328,6 +387,11 @@ fn write_doorbell(&mut self, offset: usize, data: u64) {
     }
 
     fn read_reg(&mut self, offset: usize, data: &mut [u8]) {
+        if !self.poll_reset() {
+            tracing::warn!(offset, "read during reset");
+            data.fill(!0);
+            return;
+        }
         let range = offset..offset + data.len();
         if REGMAP.contains_range(&range) {
             self.read_regmap(offset, data);
@@ -340,6 +404,10 @@ fn read_reg(&mut self, offset: usize, data: &mut [u8]) {
     }
 
     fn write_reg(&mut self, offset: usize, data: &[u8]) {
+        if !self.poll_reset() {
+            tracing::warn!(offset, "write during reset");
+            return;
+        }
         let range = offset..offset + data.len();
         if SHMEM.contains_range(&range) {
             self.write_shmem(offset - SHMEM.start, data);
@@ -360,7 +428,11 @@ fn start(&mut self) {}
     async fn stop(&mut self) {}
 
     async fn reset(&mut self) {
-        todo!()
+        self.begin_reset();
+        if let Some(resetting) = self.resetting.take() {
+            let hwc = resetting.await;
+            self.finish_reset(hwc);
+        }
     }
 }
 
@@ -422,6 +494,11 @@ fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
     }
 
     fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
-        self.config.write_u32(offset, value)
+        let result = self.config.write_u32(offset, value);
+        if self.flr.take() {
+            tracing::info!("function level reset");
+            self.begin_reset();
+        }
+        result
     }
 }
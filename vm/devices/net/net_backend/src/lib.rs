@@ -9,6 +9,7 @@
 
 pub mod loopback;
 pub mod null;
+pub mod offload;
 pub mod resolve;
 pub mod tests;
 
@@ -70,6 +71,13 @@ fn tx_offload_support(&self) -> TxOffloadSupport {
         TxOffloadSupport::default()
     }
 
+    /// Specifies whether the endpoint can coalesce received TCP segments into
+    /// packets larger than the MTU, when the buffer pool allows it via
+    /// [`BufferAccess::lro_enabled`].
+    fn rx_lro_support(&self) -> bool {
+        false
+    }
+
     /// Specifies parameters related to supporting multiple queues.
     fn multiqueue_support(&self) -> MultiQueueSupport {
         MultiQueueSupport {
@@ -196,6 +204,12 @@ fn write_packet(&mut self, id: RxId, metadata: &RxMetadata, data: &[u8]) {
         self.write_data(id, data);
         self.write_header(id, metadata);
     }
+
+    /// Returns true if the guest can receive coalesced TCP packets larger than
+    /// the MTU, described by [`RxMetadata::lro`].
+    fn lro_enabled(&self) -> bool {
+        false
+    }
 }
 
 /// A receive buffer ID.
@@ -225,6 +239,10 @@ pub struct RxMetadata {
     pub l4_checksum: RxChecksumState,
     /// The L4 protocol.
     pub l4_protocol: L4Protocol,
+    /// If set, the packet is a TCP packet coalesced from multiple segments.
+    ///
+    /// Only set when [`BufferAccess::lro_enabled`] returns true.
+    pub lro: Option<RxLro>,
 }
 
 impl Default for RxMetadata {
@@ -235,10 +253,22 @@ fn default() -> Self {
             ip_checksum: RxChecksumState::Unknown,
             l4_checksum: RxChecksumState::Unknown,
             l4_protocol: L4Protocol::Unknown,
+            lro: None,
         }
     }
 }
 
+/// Receive metadata for a coalesced TCP packet.
+#[derive(Debug, Copy, Clone)]
+pub struct RxLro {
+    /// The L3 protocol of the packet.
+    pub l3_protocol: L3Protocol,
+    /// The total length of the Ethernet, IP, and TCP headers.
+    pub header_len: u16,
+    /// The size of the segments the packet was coalesced from.
+    pub segment_size: u16,
+}
+
 /// The "L3" protocol: the IP layer.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum L3Protocol {
@@ -439,6 +469,7 @@ pub async fn disconnect(&mut self) -> anyhow::Result<Option<Box<dyn Endpoint>>>
 pub struct DisconnectableEndpointCachedState {
     is_ordered: bool,
     tx_offload_support: TxOffloadSupport,
+    rx_lro_support: bool,
     multiqueue_support: MultiQueueSupport,
     tx_fast_completions: bool,
     link_speed: u64,
@@ -522,6 +553,13 @@ fn tx_offload_support(&self) -> TxOffloadSupport {
             .tx_offload_support
     }
 
+    fn rx_lro_support(&self) -> bool {
+        self.cached_state
+            .as_ref()
+            .expect("Endpoint needs connected at least once before use")
+            .rx_lro_support
+    }
+
     fn multiqueue_support(&self) -> MultiQueueSupport {
         self.cached_state
             .as_ref()
@@ -575,6 +613,7 @@ enum Message {
                 self.cached_state = Some(DisconnectableEndpointCachedState {
                     is_ordered: self.current().is_ordered(),
                     tx_offload_support: self.current().tx_offload_support(),
+                    rx_lro_support: self.current().rx_lro_support(),
                     multiqueue_support: self.current().multiqueue_support(),
                     tx_fast_completions: self.current().tx_fast_completions(),
                     link_speed: self.current().link_speed(),
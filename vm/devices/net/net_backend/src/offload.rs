@@ -0,0 +1,349 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Software implementations of transmit offloads, for endpoints whose
+//! transport cannot perform checksum calculation or TCP segmentation itself.
+
+use crate::L3Protocol;
+use crate::TxMetadata;
+use thiserror::Error;
+
+const IPV4_TOTAL_LEN_OFFSET: usize = 2;
+const IPV4_ID_OFFSET: usize = 4;
+const IPV4_CHECKSUM_OFFSET: usize = 10;
+const IPV6_PAYLOAD_LEN_OFFSET: usize = 4;
+const IPV6_HEADER_LEN: usize = 40;
+const TCP_SEQ_OFFSET: usize = 4;
+const TCP_FLAGS_OFFSET: usize = 13;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+const TCP_FLAG_FIN: u8 = 0x01;
+const TCP_FLAG_PSH: u8 = 0x08;
+const TCP_FLAG_CWR: u8 = 0x80;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+/// An error performing a software offload.
+#[derive(Debug, Error)]
+pub enum OffloadError {
+    #[error("packet is too short for its headers")]
+    Truncated,
+    #[error("offload requested for an unknown L3 protocol")]
+    UnknownL3Protocol,
+    #[error("invalid TCP segment size {0}")]
+    InvalidSegmentSize(u16),
+}
+
+/// Performs the offloads requested in `metadata` on `packet`, calling `send`
+/// with each resulting packet.
+///
+/// Checksums are computed in place. If TCP segmentation was requested, the
+/// packet's payload is split into segments of at most
+/// `metadata.max_tcp_segment_size` bytes, and `send` is called once for each
+/// segment.
+pub fn software_offload(
+    metadata: &TxMetadata,
+    packet: &mut [u8],
+    mut send: impl FnMut(&[u8]),
+) -> Result<(), OffloadError> {
+    if metadata.offload_tcp_segmentation {
+        segment_tcp(metadata, packet, send)
+    } else {
+        finalize_checksums(metadata, packet)?;
+        send(packet);
+        Ok(())
+    }
+}
+
+/// Computes the IPv4 header, TCP, and UDP checksums requested in `metadata`,
+/// ignoring any existing values in the checksum fields.
+pub fn finalize_checksums(metadata: &TxMetadata, packet: &mut [u8]) -> Result<(), OffloadError> {
+    if !(metadata.offload_ip_header_checksum
+        || metadata.offload_tcp_checksum
+        || metadata.offload_udp_checksum)
+    {
+        return Ok(());
+    }
+    let l3_start = metadata.l2_len as usize;
+    let l4_start = l3_start + metadata.l3_len as usize;
+    if packet.len() < l4_start {
+        return Err(OffloadError::Truncated);
+    }
+    if metadata.offload_ip_header_checksum && metadata.l3_protocol == L3Protocol::Ipv4 {
+        ipv4_header_checksum(&mut packet[l3_start..l4_start])?;
+    }
+    let l4 = if metadata.offload_tcp_checksum {
+        Some((IP_PROTOCOL_TCP, TCP_CHECKSUM_OFFSET))
+    } else if metadata.offload_udp_checksum {
+        Some((IP_PROTOCOL_UDP, UDP_CHECKSUM_OFFSET))
+    } else {
+        None
+    };
+    if let Some((protocol, checksum_offset)) = l4 {
+        let (ip_header, l4) = packet[l3_start..].split_at_mut(l4_start - l3_start);
+        l4_checksum(
+            metadata.l3_protocol,
+            ip_header,
+            l4,
+            protocol,
+            checksum_offset,
+        )?;
+    }
+    Ok(())
+}
+
+/// Splits a TCP packet into segments, computing the checksums of each.
+fn segment_tcp(
+    metadata: &TxMetadata,
+    packet: &[u8],
+    mut send: impl FnMut(&[u8]),
+) -> Result<(), OffloadError> {
+    let mss = metadata.max_tcp_segment_size;
+    if mss == 0 {
+        return Err(OffloadError::InvalidSegmentSize(mss));
+    }
+    let l3_start = metadata.l2_len as usize;
+    let l4_start = l3_start + metadata.l3_len as usize;
+    let header_len = l4_start + metadata.l4_len as usize;
+    if packet.len() < header_len || (metadata.l4_len as usize) < 20 {
+        return Err(OffloadError::Truncated);
+    }
+    match metadata.l3_protocol {
+        L3Protocol::Ipv4 if metadata.l3_len >= 20 => {}
+        L3Protocol::Ipv6 if metadata.l3_len as usize >= IPV6_HEADER_LEN => {}
+        L3Protocol::Ipv4 | L3Protocol::Ipv6 => return Err(OffloadError::Truncated),
+        L3Protocol::Unknown => return Err(OffloadError::UnknownL3Protocol),
+    }
+
+    let (header, payload) = packet.split_at(header_len);
+    let seq = u32::from_be_bytes(
+        header[l4_start + TCP_SEQ_OFFSET..l4_start + TCP_SEQ_OFFSET + 4]
+            .try_into()
+            .unwrap(),
+    );
+    let ip_id = u16::from_be_bytes([
+        header[l3_start + IPV4_ID_OFFSET],
+        header[l3_start + IPV4_ID_OFFSET + 1],
+    ]);
+    let flags = header[l4_start + TCP_FLAGS_OFFSET];
+
+    let mut segment = Vec::with_capacity(header_len + mss as usize);
+    let mut chunks = payload.chunks(mss as usize).peekable();
+    let mut offset = 0;
+    let mut index: u16 = 0;
+    loop {
+        let chunk = chunks.next().unwrap_or(&[]);
+        let last = chunks.peek().is_none();
+        segment.clear();
+        segment.extend_from_slice(header);
+        segment.extend_from_slice(chunk);
+
+        let l3_payload_len = segment.len() - l4_start;
+        match metadata.l3_protocol {
+            L3Protocol::Ipv4 => {
+                let total_len = (segment.len() - l3_start) as u16;
+                segment[l3_start + IPV4_TOTAL_LEN_OFFSET..][..2]
+                    .copy_from_slice(&total_len.to_be_bytes());
+                segment[l3_start + IPV4_ID_OFFSET..][..2]
+                    .copy_from_slice(&ip_id.wrapping_add(index).to_be_bytes());
+                ipv4_header_checksum(&mut segment[l3_start..l4_start])?;
+            }
+            L3Protocol::Ipv6 => {
+                let payload_len =
+                    (metadata.l3_len as usize - IPV6_HEADER_LEN + l3_payload_len) as u16;
+                segment[l3_start + IPV6_PAYLOAD_LEN_OFFSET..][..2]
+                    .copy_from_slice(&payload_len.to_be_bytes());
+            }
+            L3Protocol::Unknown => unreachable!(),
+        }
+
+        segment[l4_start + TCP_SEQ_OFFSET..][..4]
+            .copy_from_slice(&seq.wrapping_add(offset).to_be_bytes());
+        let mut segment_flags = flags;
+        if index != 0 {
+            segment_flags &= !TCP_FLAG_CWR;
+        }
+        if !last {
+            segment_flags &= !(TCP_FLAG_FIN | TCP_FLAG_PSH);
+        }
+        segment[l4_start + TCP_FLAGS_OFFSET] = segment_flags;
+
+        let (ip_header, l4) = segment[l3_start..].split_at_mut(l4_start - l3_start);
+        l4_checksum(
+            metadata.l3_protocol,
+            ip_header,
+            l4,
+            IP_PROTOCOL_TCP,
+            TCP_CHECKSUM_OFFSET,
+        )?;
+        send(&segment);
+
+        if last {
+            break;
+        }
+        offset = offset.wrapping_add(chunk.len() as u32);
+        index = index.wrapping_add(1);
+    }
+    Ok(())
+}
+
+fn ipv4_header_checksum(header: &mut [u8]) -> Result<(), OffloadError> {
+    if header.len() < 20 {
+        return Err(OffloadError::Truncated);
+    }
+    header[IPV4_CHECKSUM_OFFSET..][..2].fill(0);
+    let checksum = fold(sum(header, 0));
+    header[IPV4_CHECKSUM_OFFSET..][..2].copy_from_slice(&checksum.to_be_bytes());
+    Ok(())
+}
+
+fn l4_checksum(
+    l3_protocol: L3Protocol,
+    ip_header: &[u8],
+    l4: &mut [u8],
+    protocol: u8,
+    checksum_offset: usize,
+) -> Result<(), OffloadError> {
+    if l4.len() < checksum_offset + 2 {
+        return Err(OffloadError::Truncated);
+    }
+    let pseudo_header = match l3_protocol {
+        L3Protocol::Ipv4 => {
+            if ip_header.len() < 20 {
+                return Err(OffloadError::Truncated);
+            }
+            let partial = sum(&ip_header[12..20], 0);
+            sum(&(l4.len() as u16).to_be_bytes(), partial) + protocol as u64
+        }
+        L3Protocol::Ipv6 => {
+            if ip_header.len() < IPV6_HEADER_LEN {
+                return Err(OffloadError::Truncated);
+            }
+            let partial = sum(&ip_header[8..40], 0);
+            sum(&(l4.len() as u32).to_be_bytes(), partial) + protocol as u64
+        }
+        L3Protocol::Unknown => return Err(OffloadError::UnknownL3Protocol),
+    };
+    l4[checksum_offset..][..2].fill(0);
+    let mut checksum = fold(sum(l4, pseudo_header));
+    if checksum == 0 && protocol == IP_PROTOCOL_UDP {
+        // Zero means no checksum for UDP, so use the equivalent ones'
+        // complement value.
+        checksum = 0xffff;
+    }
+    l4[checksum_offset..][..2].copy_from_slice(&checksum.to_be_bytes());
+    Ok(())
+}
+
+/// Adds `data` as big-endian 16-bit words to `sum`.
+fn sum(data: &[u8], mut sum: u64) -> u64 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u64;
+    }
+    if let [b] = chunks.remainder() {
+        sum += (*b as u64) << 8;
+    }
+    sum
+}
+
+/// Folds `sum` into a 16-bit ones' complement checksum.
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp4_packet(payload_len: usize) -> Vec<u8> {
+        let mut packet = vec![0; 14 + 20 + 20 + payload_len];
+        packet[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        let ip = &mut packet[14..34];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&((20 + 20 + payload_len) as u16).to_be_bytes());
+        ip[4..6].copy_from_slice(&0x1234u16.to_be_bytes());
+        ip[8] = 64;
+        ip[9] = IP_PROTOCOL_TCP;
+        ip[12..16].copy_from_slice(&[10, 0, 0, 2]);
+        ip[16..20].copy_from_slice(&[10, 0, 0, 1]);
+        let tcp = &mut packet[34..54];
+        tcp[0..2].copy_from_slice(&1234u16.to_be_bytes());
+        tcp[2..4].copy_from_slice(&80u16.to_be_bytes());
+        tcp[4..8].copy_from_slice(&0xffff_fff0u32.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = 0x18 | TCP_FLAG_FIN;
+        for (i, b) in packet[54..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        packet
+    }
+
+    fn tso_metadata(mss: u16) -> TxMetadata {
+        TxMetadata {
+            offload_tcp_segmentation: true,
+            offload_tcp_checksum: true,
+            offload_ip_header_checksum: true,
+            l3_protocol: L3Protocol::Ipv4,
+            l2_len: 14,
+            l3_len: 20,
+            l4_len: 20,
+            max_tcp_segment_size: mss,
+            ..Default::default()
+        }
+    }
+
+    fn verify_checksums(packet: &[u8]) {
+        assert_eq!(fold(sum(&packet[14..34], 0)), 0);
+        let l4 = &packet[34..];
+        let pseudo =
+            sum(&(l4.len() as u16).to_be_bytes(), sum(&packet[26..34], 0)) + IP_PROTOCOL_TCP as u64;
+        assert_eq!(fold(sum(l4, pseudo)), 0);
+    }
+
+    #[test]
+    fn checksums() {
+        let mut packet = tcp4_packet(101);
+        let metadata = TxMetadata {
+            offload_tcp_segmentation: false,
+            ..tso_metadata(0)
+        };
+        let mut sent = Vec::new();
+        software_offload(&metadata, &mut packet, |p| sent.push(p.to_vec())).unwrap();
+        assert_eq!(sent.len(), 1);
+        verify_checksums(&sent[0]);
+    }
+
+    #[test]
+    fn segmentation() {
+        let payload_len = 3000;
+        let mut packet = tcp4_packet(payload_len);
+        let mut sent = Vec::new();
+        software_offload(&tso_metadata(1000), &mut packet, |p| sent.push(p.to_vec())).unwrap();
+        assert_eq!(sent.len(), 3);
+        let mut payload = Vec::new();
+        for (i, segment) in sent.iter().enumerate() {
+            verify_checksums(segment);
+            assert_eq!(
+                u16::from_be_bytes([segment[16], segment[17]]) as usize,
+                segment.len() - 14
+            );
+            assert_eq!(
+                u16::from_be_bytes([segment[18], segment[19]]),
+                0x1234 + i as u16
+            );
+            let seq = u32::from_be_bytes(segment[38..42].try_into().unwrap());
+            assert_eq!(seq, 0xffff_fff0u32.wrapping_add(i as u32 * 1000));
+            let last = i == sent.len() - 1;
+            assert_eq!(segment[47] & TCP_FLAG_FIN != 0, last);
+            assert_eq!(segment[47] & TCP_FLAG_PSH != 0, last);
+            payload.extend_from_slice(&segment[54..]);
+        }
+        assert_eq!(payload, packet[54..]);
+    }
+}
@@ -27,7 +27,9 @@ impl ResourceId<NetEndpointHandleKind> for NullHandle {
 
 /// Consomme backend.
 pub mod consomme {
+    use crate::mac_address::MacAddress;
     use mesh::MeshPayload;
+    use std::net::Ipv4Addr;
     use vm_resource::ResourceId;
     use vm_resource::kind::NetEndpointHandleKind;
 
@@ -36,6 +38,35 @@ pub mod consomme {
     pub struct ConsommeHandle {
         /// The CIDR of the network to use.
         pub cidr: Option<String>,
+        /// Options for the built-in DHCP server.
+        pub dhcp: ConsommeDhcpOptions,
+    }
+
+    /// Options for Consomme's built-in DHCP server.
+    #[derive(MeshPayload, Debug, Clone, Default, PartialEq)]
+    pub struct ConsommeDhcpOptions {
+        /// The DNS servers to provide, instead of the host's.
+        pub dns_servers: Vec<Ipv4Addr>,
+        /// The DNS search domains to provide.
+        pub search_domains: Vec<String>,
+        /// Fixed address assignments.
+        pub static_leases: Vec<ConsommeStaticLease>,
+        /// The TFTP server name or address, for network boot.
+        pub tftp_server: Option<String>,
+        /// The boot file name, for network boot.
+        pub boot_file: Option<String>,
+        /// A host directory to serve over TFTP and HTTP from the gateway
+        /// address, for network boot.
+        pub boot_dir: Option<String>,
+    }
+
+    /// A fixed DHCP address assignment.
+    #[derive(MeshPayload, Debug, Copy, Clone, PartialEq)]
+    pub struct ConsommeStaticLease {
+        /// The client's MAC address.
+        pub mac: MacAddress,
+        /// The address to assign.
+        pub ip: Ipv4Addr,
     }
 
     impl ResourceId<NetEndpointHandleKind> for ConsommeHandle {
@@ -90,3 +121,72 @@ impl ResourceId<NetEndpointHandleKind> for TapHandle {
         const ID: &'static str = "tap";
     }
 }
+
+/// Linux macvtap backend.
+pub mod macvtap {
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// A handle to a macvtap device.
+    #[derive(MeshPayload)]
+    pub struct MacvtapHandle {
+        /// The opened macvtap device node (`/dev/tapN`, where `N` is the
+        /// interface index).
+        ///
+        /// This is opened by the caller so that the backend does not need
+        /// permission to access the device node itself.
+        pub file: std::fs::File,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for MacvtapHandle {
+        const ID: &'static str = "macvtap";
+    }
+}
+
+/// Backend connecting the NICs of multiple VMs to a shared hub.
+pub mod hub {
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// A handle to a port on a hub, which forwards the Ethernet frames sent on
+    /// each of its ports to all the others.
+    #[derive(MeshPayload)]
+    pub struct HubPortHandle {
+        /// Frames sent by the NIC, to the hub.
+        pub send: mesh::Sender<Vec<u8>>,
+        /// Frames received by the NIC, from the hub.
+        pub recv: mesh::Receiver<Vec<u8>>,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for HubPortHandle {
+        const ID: &'static str = "hub";
+    }
+}
+
+/// Traffic shaping wrapper.
+pub mod shaped {
+    use mesh::MeshPayload;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// A handle to an endpoint that shapes the traffic of another endpoint,
+    /// to emulate a constrained or lossy link.
+    #[derive(MeshPayload)]
+    pub struct ShapedHandle {
+        /// The endpoint to shape.
+        pub endpoint: Resource<NetEndpointHandleKind>,
+        /// The link bandwidth in bits per second, or `None` for no limit.
+        pub rate_bps: Option<u64>,
+        /// The one-way latency to add to each packet, in milliseconds.
+        pub latency_ms: u64,
+        /// The percentage of packets to drop in each direction.
+        pub loss_percent: f64,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for ShapedHandle {
+        const ID: &'static str = "shaped";
+    }
+}
@@ -5,15 +5,18 @@
 use super::Client;
 use super::DropReason;
 use crate::ChecksumState;
+use crate::DhcpOptions;
 use crate::MIN_MTU;
 use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::wire::DHCP_MAX_DNS_SERVER_COUNT;
 use smoltcp::wire::DhcpMessageType;
 use smoltcp::wire::DhcpPacket;
 use smoltcp::wire::DhcpRepr;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
 use smoltcp::wire::EthernetFrame;
 use smoltcp::wire::EthernetProtocol;
 use smoltcp::wire::EthernetRepr;
+use smoltcp::wire::IPV4_HEADER_LEN;
 use smoltcp::wire::IpAddress;
 use smoltcp::wire::IpProtocol;
 use smoltcp::wire::Ipv4Address;
@@ -21,27 +24,131 @@
 use smoltcp::wire::Ipv4Repr;
 use smoltcp::wire::UdpPacket;
 use smoltcp::wire::UdpRepr;
+use std::borrow::Cow;
+use std::net::Ipv4Addr;
 
 pub const DHCP_SERVER: u16 = 67;
 pub const DHCP_CLIENT: u16 = 68;
 
+// BOOTP header fields not exposed by smoltcp.
+const BOOTP_SNAME: std::ops::Range<usize> = 44..108;
+const BOOTP_FILE: std::ops::Range<usize> = 108..236;
+/// The offset of the options, after the magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+
+// DHCP option codes not supported by smoltcp.
+const OPTION_PAD: u8 = 0;
+const OPTION_DOMAIN_NAME: u8 = 15;
+const OPTION_VENDOR_CLASS: u8 = 60;
+const OPTION_TFTP_SERVER_NAME: u8 = 66;
+const OPTION_BOOTFILE_NAME: u8 = 67;
+const OPTION_DOMAIN_SEARCH: u8 = 119;
+const OPTION_END: u8 = 255;
+
+/// The vendor class used by UEFI HTTP boot clients.
+const HTTP_CLIENT_CLASS: &[u8] = b"HTTPClient";
+
+/// Finds the first instance of an option that smoltcp does not parse.
+fn find_option(packet: &[u8], code: u8) -> Option<&[u8]> {
+    let mut options = packet.get(OPTIONS_OFFSET..)?;
+    loop {
+        match *options.first()? {
+            OPTION_PAD => options = &options[1..],
+            OPTION_END => return None,
+            c => {
+                let len = (*options.get(1)?).into();
+                let data = options.get(2..2 + len)?;
+                if c == code {
+                    return Some(data);
+                }
+                options = &options[2 + len..];
+            }
+        }
+    }
+}
+
+/// Appends an option, splitting it into multiple instances if it is too long
+/// (RFC 3396).
+fn push_option(buf: &mut Vec<u8>, code: u8, data: &[u8]) {
+    for chunk in data.chunks(255) {
+        buf.push(code);
+        buf.push(chunk.len() as u8);
+        buf.extend_from_slice(chunk);
+    }
+}
+
+/// Appends `name` in DNS wire format (RFC 1035), without compression.
+fn push_domain_name(buf: &mut Vec<u8>, name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return false;
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return false;
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    true
+}
+
+/// Encodes the configured options that smoltcp cannot emit itself.
+fn encode_extra_options(options: &DhcpOptions) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(domain) = options.search_domains.first() {
+        push_option(&mut buf, OPTION_DOMAIN_NAME, domain.as_bytes());
+    }
+    let mut search = Vec::new();
+    for domain in &options.search_domains {
+        if !push_domain_name(&mut search, domain) {
+            tracing::warn!(domain, "ignoring invalid dhcp search domain");
+        }
+    }
+    if !search.is_empty() {
+        push_option(&mut buf, OPTION_DOMAIN_SEARCH, &search);
+    }
+    if let Some(server) = &options.tftp_server {
+        push_option(&mut buf, OPTION_TFTP_SERVER_NAME, server.as_bytes());
+    }
+    if let Some(file) = &options.boot_file {
+        push_option(&mut buf, OPTION_BOOTFILE_NAME, file.as_bytes());
+    }
+    buf
+}
+
 impl<T: Client> Access<'_, T> {
     pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
         let dhcp_packet = DhcpPacket::new_checked(payload)?;
         let dhcp_req = DhcpRepr::parse(&dhcp_packet)?;
+        let static_ip = self
+            .inner
+            .state
+            .dhcp
+            .static_leases
+            .iter()
+            .find(|lease| lease.mac == dhcp_req.client_hardware_address.0)
+            .map(|lease| Ipv4Address::from(lease.ip));
+        let client_ip = static_ip.unwrap_or(self.inner.state.client_ip);
         let your_ip;
         let message_type;
         match dhcp_req.message_type {
             DhcpMessageType::Discover => {
-                your_ip = Some(self.inner.state.client_ip);
+                your_ip = Some(client_ip);
                 message_type = DhcpMessageType::Offer;
             }
             DhcpMessageType::Request => {
                 your_ip = match dhcp_req.requested_ip {
-                    Some(addr) if addr == self.inner.state.client_ip => Some(addr),
-                    None => Some(self.inner.state.client_ip),
+                    Some(addr) if addr == client_ip => Some(addr),
+                    None => Some(client_ip),
                     Some(_) => None,
                 };
+                if let (Some(addr), Some(_)) = (your_ip, static_ip) {
+                    // Route inbound connections to the statically assigned
+                    // address.
+                    self.inner.state.client_ip = addr;
+                }
                 message_type = DhcpMessageType::Ack;
             }
             ty => return Err(DropReason::UnsupportedDhcp(ty)),
@@ -57,14 +164,44 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
             Some(dns_servers)
         };
 
+        let mut options = Cow::Borrowed(&self.inner.state.dhcp);
+        // UEFI HTTP boot clients identify themselves with a vendor class, and
+        // expect the boot file as a URL.
+        let http_boot = options.boot_dir.is_some()
+            && find_option(payload, OPTION_VENDOR_CLASS)
+                .is_some_and(|class| class.starts_with(HTTP_CLIENT_CLASS));
+        if http_boot {
+            let gateway_ip = self.inner.state.gateway_ip;
+            let options = options.to_mut();
+            options.tftp_server = None;
+            options.boot_file = options.boot_file.as_ref().map(|file| {
+                format!(
+                    "http://{gateway_ip}/{}",
+                    file.trim_start_matches(['/', '\\'])
+                )
+            });
+        }
+        // If the TFTP server is specified by address, then also provide it
+        // as the next server for BOOTP clients.
+        let next_server = options
+            .tftp_server
+            .as_deref()
+            .and_then(|server| server.parse::<Ipv4Addr>().ok())
+            .map_or(self.inner.state.gateway_ip, Ipv4Address::from);
+
+        let mut extra_options = Vec::new();
         let resp_dhcp = if let Some(your_ip) = your_ip {
+            extra_options = encode_extra_options(&options);
+            if http_boot {
+                push_option(&mut extra_options, OPTION_VENDOR_CLASS, HTTP_CLIENT_CLASS);
+            }
             DhcpRepr {
                 message_type,
                 transaction_id: dhcp_req.transaction_id,
                 client_hardware_address: dhcp_req.client_hardware_address,
                 client_ip: Ipv4Address::UNSPECIFIED,
                 your_ip,
-                server_ip: self.inner.state.gateway_ip,
+                server_ip: next_server,
                 router: Some(self.inner.state.gateway_ip),
                 subnet_mask: Some(self.inner.state.net_mask),
                 relay_agent_ip: Ipv4Address::UNSPECIFIED,
@@ -103,11 +240,34 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
             src_port: DHCP_SERVER,
             dst_port: DHCP_CLIENT,
         };
+        // The extra options replace smoltcp's end option, and are followed by
+        // a new one.
+        let max_extra_len = MIN_MTU
+            - ETHERNET_HEADER_LEN
+            - IPV4_HEADER_LEN
+            - resp_udp.header_len()
+            - resp_dhcp.buffer_len();
+        if extra_options.len() > max_extra_len {
+            tracing::warn!(
+                len = extra_options.len(),
+                "dhcp options too large, omitting them"
+            );
+            extra_options.clear();
+        }
+        let dhcp_len = resp_dhcp.buffer_len() + extra_options.len();
+        let boot_file = options
+            .boot_file
+            .as_deref()
+            .filter(|file| file.len() < BOOTP_FILE.len());
+        let tftp_server = options
+            .tftp_server
+            .as_deref()
+            .filter(|server| server.len() < BOOTP_SNAME.len());
         let resp_ipv4 = Ipv4Repr {
             src_addr: self.inner.state.gateway_ip,
             dst_addr: Ipv4Address::BROADCAST,
             protocol: IpProtocol::Udp,
-            payload_len: resp_udp.header_len() + resp_dhcp.buffer_len(),
+            payload_len: resp_udp.header_len() + dhcp_len,
             hop_limit: 64,
         };
         let resp_eth = EthernetRepr {
@@ -126,10 +286,23 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
             &mut resp_udp_packet,
             &IpAddress::Ipv4(resp_ipv4.src_addr),
             &IpAddress::Ipv4(resp_ipv4.dst_addr),
-            resp_dhcp.buffer_len(),
+            dhcp_len,
             |udp_payload| {
-                let mut resp_dhcp_packet = DhcpPacket::new_unchecked(udp_payload);
+                let mut resp_dhcp_packet = DhcpPacket::new_unchecked(&mut *udp_payload);
                 resp_dhcp.emit(&mut resp_dhcp_packet).unwrap();
+                if !extra_options.is_empty() {
+                    let end = resp_dhcp.buffer_len() - 1;
+                    udp_payload[end..][..extra_options.len()].copy_from_slice(&extra_options);
+                    udp_payload[end + extra_options.len()] = OPTION_END;
+                }
+                if your_ip.is_some() {
+                    if let Some(server) = tftp_server {
+                        udp_payload[BOOTP_SNAME][..server.len()].copy_from_slice(server.as_bytes());
+                    }
+                    if let Some(file) = boot_file {
+                        udp_payload[BOOTP_FILE][..file.len()].copy_from_slice(file.as_bytes());
+                    }
+                }
             },
             &ChecksumCapabilities::default(),
         );
@@ -138,7 +311,7 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
             &resp_buffer[..resp_eth.buffer_len()
                 + resp_ipv4.buffer_len()
                 + resp_udp.header_len()
-                + resp_dhcp.buffer_len()],
+                + dhcp_len],
             &ChecksumState::IPV4_ONLY,
         );
         Ok(())
@@ -0,0 +1,422 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal DHCPv6 server (RFC 8415), assigning the client a single address
+//! and providing DNS servers.
+
+use super::Access;
+use super::Client;
+use super::DropReason;
+use crate::ChecksumState;
+use crate::IPV6_HEADER_LEN;
+use crate::MIN_MTU;
+use crate::emit_ip_headers;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
+use smoltcp::wire::EthernetRepr;
+use smoltcp::wire::IpAddress;
+use smoltcp::wire::IpProtocol;
+use smoltcp::wire::UDP_HEADER_LEN;
+use smoltcp::wire::UdpPacket;
+use std::net::Ipv6Addr;
+
+pub const DHCPV6_SERVER: u16 = 547;
+
+mod message_type {
+    pub const SOLICIT: u8 = 1;
+    pub const ADVERTISE: u8 = 2;
+    pub const REQUEST: u8 = 3;
+    pub const CONFIRM: u8 = 4;
+    pub const RENEW: u8 = 5;
+    pub const REBIND: u8 = 6;
+    pub const REPLY: u8 = 7;
+    pub const RELEASE: u8 = 8;
+    pub const DECLINE: u8 = 9;
+    pub const INFORMATION_REQUEST: u8 = 11;
+}
+
+mod option {
+    pub const CLIENTID: u16 = 1;
+    pub const SERVERID: u16 = 2;
+    pub const IA_NA: u16 = 3;
+    pub const IAADDR: u16 = 5;
+    pub const STATUS_CODE: u16 = 13;
+    pub const RAPID_COMMIT: u16 = 14;
+    pub const DNS_SERVERS: u16 = 23;
+}
+
+const STATUS_SUCCESS: u16 = 0;
+const DUID_LL: u16 = 3;
+const HARDWARE_TYPE_ETHERNET: u16 = 1;
+
+const VALID_LIFETIME_SECS: u32 = 86400;
+const PREFERRED_LIFETIME_SECS: u32 = 86400;
+const RENEW_TIME_SECS: u32 = VALID_LIFETIME_SECS / 2;
+const REBIND_TIME_SECS: u32 = VALID_LIFETIME_SECS / 8 * 7;
+
+/// Iterates over the options in a DHCPv6 message.
+fn options(mut data: &[u8]) -> impl Iterator<Item = Result<(u16, &[u8]), DropReason>> {
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        if data.len() < 4 {
+            return Some(Err(DropReason::Packet(smoltcp::Error::Truncated)));
+        }
+        let code = u16::from_be_bytes([data[0], data[1]]);
+        let len: usize = u16::from_be_bytes([data[2], data[3]]).into();
+        let Some(value) = data.get(4..4 + len) else {
+            data = &[];
+            return Some(Err(DropReason::Packet(smoltcp::Error::Truncated)));
+        };
+        data = &data[4 + len..];
+        Some(Ok((code, value)))
+    })
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_be_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+impl<T: Client> Access<'_, T> {
+    pub(crate) fn handle_dhcpv6(
+        &mut self,
+        frame: &EthernetRepr,
+        client_addr: Ipv6Addr,
+        client_port: u16,
+        payload: &[u8],
+    ) -> Result<(), DropReason> {
+        if payload.len() < 4 {
+            return Err(DropReason::Packet(smoltcp::Error::Truncated));
+        }
+        let msg_type = payload[0];
+        let transaction_id = &payload[1..4];
+
+        let mut server_duid = Vec::new();
+        server_duid.extend_from_slice(&DUID_LL.to_be_bytes());
+        server_duid.extend_from_slice(&HARDWARE_TYPE_ETHERNET.to_be_bytes());
+        server_duid.extend_from_slice(self.inner.state.gateway_mac.as_bytes());
+
+        let mut client_id = None;
+        let mut iaids = Vec::new();
+        let mut rapid_commit = false;
+        for option in options(&payload[4..]) {
+            let (code, value) = option?;
+            match code {
+                option::CLIENTID => client_id = Some(value),
+                option::SERVERID => {
+                    // The message is intended for a different server.
+                    if value != server_duid {
+                        return Ok(());
+                    }
+                }
+                option::IA_NA => {
+                    let iaid = value
+                        .get(..4)
+                        .ok_or(DropReason::Packet(smoltcp::Error::Truncated))?;
+                    iaids.push(iaid);
+                }
+                option::RAPID_COMMIT => rapid_commit = true,
+                _ => {}
+            }
+        }
+
+        let reply_type = match msg_type {
+            message_type::SOLICIT if rapid_commit => message_type::REPLY,
+            message_type::SOLICIT => message_type::ADVERTISE,
+            message_type::REQUEST
+            | message_type::CONFIRM
+            | message_type::RENEW
+            | message_type::REBIND
+            | message_type::RELEASE
+            | message_type::DECLINE
+            | message_type::INFORMATION_REQUEST => message_type::REPLY,
+            ty => return Err(DropReason::UnsupportedDhcpv6(ty)),
+        };
+        if client_id.is_none() && msg_type != message_type::INFORMATION_REQUEST {
+            return Err(DropReason::Packet(smoltcp::Error::Malformed));
+        }
+
+        let mut reply = vec![reply_type];
+        reply.extend_from_slice(transaction_id);
+        push_option(&mut reply, option::SERVERID, &server_duid);
+        if let Some(client_id) = client_id {
+            push_option(&mut reply, option::CLIENTID, client_id);
+        }
+        match msg_type {
+            message_type::SOLICIT
+            | message_type::REQUEST
+            | message_type::RENEW
+            | message_type::REBIND => {
+                if msg_type == message_type::SOLICIT && rapid_commit {
+                    push_option(&mut reply, option::RAPID_COMMIT, &[]);
+                }
+                for iaid in iaids {
+                    let mut ia_addr = Vec::new();
+                    ia_addr.extend_from_slice(self.inner.state.client_ipv6.as_bytes());
+                    ia_addr.extend_from_slice(&PREFERRED_LIFETIME_SECS.to_be_bytes());
+                    ia_addr.extend_from_slice(&VALID_LIFETIME_SECS.to_be_bytes());
+
+                    let mut ia_na = Vec::new();
+                    ia_na.extend_from_slice(iaid);
+                    ia_na.extend_from_slice(&RENEW_TIME_SECS.to_be_bytes());
+                    ia_na.extend_from_slice(&REBIND_TIME_SECS.to_be_bytes());
+                    push_option(&mut ia_na, option::IAADDR, &ia_addr);
+                    push_option(&mut reply, option::IA_NA, &ia_na);
+                }
+            }
+            message_type::CONFIRM | message_type::RELEASE | message_type::DECLINE => {
+                push_option(
+                    &mut reply,
+                    option::STATUS_CODE,
+                    &STATUS_SUCCESS.to_be_bytes(),
+                );
+            }
+            _ => {}
+        }
+        if !self.inner.state.nameservers_ipv6.is_empty() {
+            let servers = self
+                .inner
+                .state
+                .nameservers_ipv6
+                .iter()
+                .flat_map(|addr| addr.as_bytes())
+                .copied()
+                .collect::<Vec<_>>();
+            push_option(&mut reply, option::DNS_SERVERS, &servers);
+        }
+
+        let udp_len = UDP_HEADER_LEN + reply.len();
+        if ETHERNET_HEADER_LEN + IPV6_HEADER_LEN + udp_len > MIN_MTU {
+            return Err(DropReason::Packet(smoltcp::Error::Exhausted));
+        }
+        let src_addr = Ipv6Addr::from(self.inner.state.gateway_ipv6);
+        let mut buffer = [0; MIN_MTU];
+        let offset = emit_ip_headers(
+            &mut buffer,
+            self.inner.state.gateway_mac,
+            frame.src_addr,
+            src_addr.into(),
+            client_addr.into(),
+            IpProtocol::Udp,
+            udp_len,
+        );
+        let len = offset + udp_len;
+        let mut udp = UdpPacket::new_unchecked(&mut buffer[offset..len]);
+        udp.set_src_port(DHCPV6_SERVER);
+        udp.set_dst_port(client_port);
+        udp.set_len(udp_len as u16);
+        udp.payload_mut().copy_from_slice(&reply);
+        udp.fill_checksum(
+            &IpAddress::Ipv6(src_addr.into()),
+            &IpAddress::Ipv6(client_addr.into()),
+        );
+        self.client.recv(&buffer[..len], &ChecksumState::UDP6);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Consomme;
+    use crate::test_helpers::TestClient;
+    use crate::test_helpers::client_frame;
+    use crate::test_helpers::test_state;
+    use crate::test_helpers::udp6_payload;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use smoltcp::wire::EthernetProtocol;
+
+    const CLIENT_PORT: u16 = 546;
+    const CLIENT_DUID: &[u8] = &[0, 3, 0, 1, 0, 0, 0, 0, 1, 0];
+    const IAID: [u8; 4] = [0, 0, 0, 7];
+
+    fn client_addr() -> Ipv6Addr {
+        Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2)
+    }
+
+    fn message(msg_type: u8, options: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut msg = vec![msg_type, 0x12, 0x34, 0x56];
+        for &(code, value) in options {
+            push_option(&mut msg, code, value);
+        }
+        msg
+    }
+
+    fn ia_na() -> Vec<u8> {
+        let mut ia_na = IAID.to_vec();
+        ia_na.extend_from_slice(&[0; 8]);
+        ia_na
+    }
+
+    /// Sends `msg` to the server, returning the reply, if any.
+    fn exchange(driver: &DefaultDriver, msg: &[u8]) -> Result<Option<Vec<u8>>, DropReason> {
+        let mut consomme = Consomme::new_with_state(test_state());
+        let frame = client_frame(&consomme.state, EthernetProtocol::Ipv6);
+        let mut client = TestClient::new(driver.clone());
+        consomme
+            .access(&mut client)
+            .handle_dhcpv6(&frame, client_addr(), CLIENT_PORT, msg)?;
+        Ok(client.frames.pop().map(|frame| udp6_payload(&frame)))
+    }
+
+    fn reply_options(reply: &[u8]) -> Vec<(u16, Vec<u8>)> {
+        options(&reply[4..])
+            .map(|option| {
+                let (code, value) = option.unwrap();
+                (code, value.to_vec())
+            })
+            .collect()
+    }
+
+    fn find_option(options: &[(u16, Vec<u8>)], code: u16) -> Option<&[u8]> {
+        options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    #[test]
+    fn test_options() {
+        let mut data = Vec::new();
+        push_option(&mut data, option::CLIENTID, CLIENT_DUID);
+        push_option(&mut data, option::RAPID_COMMIT, &[]);
+        let parsed = options(&data).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            parsed,
+            [
+                (option::CLIENTID, CLIENT_DUID),
+                (option::RAPID_COMMIT, &[][..])
+            ]
+        );
+
+        // A truncated option header.
+        let mut parsed = options(&[0, 1, 0]);
+        assert!(matches!(
+            parsed.next(),
+            Some(Err(DropReason::Packet(smoltcp::Error::Truncated)))
+        ));
+
+        // An option length past the end of the message, after which parsing
+        // stops.
+        let mut parsed = options(&[0, 1, 0, 4, 1, 2]);
+        assert!(matches!(
+            parsed.next(),
+            Some(Err(DropReason::Packet(smoltcp::Error::Truncated)))
+        ));
+        assert!(parsed.next().is_none());
+    }
+
+    #[async_test]
+    async fn test_solicit_advertise(driver: DefaultDriver) {
+        let ia_na = ia_na();
+        let msg = message(
+            message_type::SOLICIT,
+            &[(option::CLIENTID, CLIENT_DUID), (option::IA_NA, &ia_na)],
+        );
+        let reply = exchange(&driver, &msg).unwrap().unwrap();
+        assert_eq!(reply[0], message_type::ADVERTISE);
+        assert_eq!(reply[1..4], msg[1..4]);
+
+        let options = reply_options(&reply);
+        assert_eq!(find_option(&options, option::CLIENTID), Some(CLIENT_DUID));
+        assert!(find_option(&options, option::SERVERID).is_some());
+        assert!(find_option(&options, option::RAPID_COMMIT).is_none());
+        assert_eq!(
+            find_option(&options, option::DNS_SERVERS),
+            Some(test_state().nameservers_ipv6[0].as_bytes())
+        );
+
+        let ia_na = find_option(&options, option::IA_NA).unwrap();
+        assert_eq!(ia_na[..4], IAID);
+        let (code, ia_addr) = super::options(&ia_na[12..]).next().unwrap().unwrap();
+        assert_eq!(code, option::IAADDR);
+        assert_eq!(ia_addr[..16], *test_state().client_ipv6.as_bytes());
+    }
+
+    #[async_test]
+    async fn test_solicit_rapid_commit_reply(driver: DefaultDriver) {
+        let ia_na = ia_na();
+        let msg = message(
+            message_type::SOLICIT,
+            &[
+                (option::CLIENTID, CLIENT_DUID),
+                (option::IA_NA, &ia_na),
+                (option::RAPID_COMMIT, &[]),
+            ],
+        );
+        let reply = exchange(&driver, &msg).unwrap().unwrap();
+        assert_eq!(reply[0], message_type::REPLY);
+        let options = reply_options(&reply);
+        assert!(find_option(&options, option::RAPID_COMMIT).is_some());
+        assert!(find_option(&options, option::IA_NA).is_some());
+    }
+
+    #[async_test]
+    async fn test_request_reply(driver: DefaultDriver) {
+        let ia_na = ia_na();
+        let msg = message(
+            message_type::REQUEST,
+            &[(option::CLIENTID, CLIENT_DUID), (option::IA_NA, &ia_na)],
+        );
+        let reply = exchange(&driver, &msg).unwrap().unwrap();
+        assert_eq!(reply[0], message_type::REPLY);
+        assert!(find_option(&reply_options(&reply), option::IA_NA).is_some());
+    }
+
+    #[async_test]
+    async fn test_other_server(driver: DefaultDriver) {
+        let msg = message(
+            message_type::REQUEST,
+            &[
+                (option::CLIENTID, CLIENT_DUID),
+                (option::SERVERID, &[0, 3, 0, 1, 1, 2, 3, 4, 5, 6]),
+            ],
+        );
+        assert!(exchange(&driver, &msg).unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn test_malformed(driver: DefaultDriver) {
+        // Shorter than the message header.
+        assert!(matches!(
+            exchange(&driver, &[message_type::SOLICIT, 0, 0]),
+            Err(DropReason::Packet(smoltcp::Error::Truncated))
+        ));
+
+        // An option running past the end of the message.
+        let mut msg = message(message_type::SOLICIT, &[(option::CLIENTID, CLIENT_DUID)]);
+        msg.extend_from_slice(&[0, 3, 0, 12, 0, 0]);
+        assert!(matches!(
+            exchange(&driver, &msg),
+            Err(DropReason::Packet(smoltcp::Error::Truncated))
+        ));
+
+        // An IA_NA too short to hold its IAID.
+        let msg = message(
+            message_type::SOLICIT,
+            &[(option::CLIENTID, CLIENT_DUID), (option::IA_NA, &[0, 0])],
+        );
+        assert!(matches!(
+            exchange(&driver, &msg),
+            Err(DropReason::Packet(smoltcp::Error::Truncated))
+        ));
+
+        // A solicit without a client ID.
+        let ia_na = ia_na();
+        let msg = message(message_type::SOLICIT, &[(option::IA_NA, &ia_na)]);
+        assert!(matches!(
+            exchange(&driver, &msg),
+            Err(DropReason::Packet(smoltcp::Error::Malformed))
+        ));
+
+        // A message type only sent by servers.
+        let msg = message(message_type::ADVERTISE, &[(option::CLIENTID, CLIENT_DUID)]);
+        assert!(matches!(
+            exchange(&driver, &msg),
+            Err(DropReason::UnsupportedDhcpv6(message_type::ADVERTISE))
+        ));
+    }
+}
@@ -2,7 +2,7 @@
 // Licensed under the MIT License.
 
 use resolv_conf::ScopedIp;
-use smoltcp::wire::Ipv4Address;
+use std::net::IpAddr;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -13,15 +13,17 @@ pub enum Error {
     Parse(#[from] resolv_conf::ParseError),
 }
 
-pub fn nameservers() -> Result<Vec<Ipv4Address>, Error> {
+pub fn nameservers() -> Result<Vec<IpAddr>, Error> {
     let contents = std::fs::read("/etc/resolv.conf")?;
     let config = resolv_conf::Config::parse(contents)?;
     Ok(config
         .nameservers
         .iter()
         .filter_map(|ns| match ns {
-            ScopedIp::V4(addr) => Some(Ipv4Address::from(*addr)),
-            ScopedIp::V6(_, _) => None,
+            ScopedIp::V4(addr) => Some(IpAddr::V4(*addr)),
+            ScopedIp::V6(addr, None) => Some(IpAddr::V6(*addr)),
+            // Scoped addresses are only reachable via a specific interface.
+            ScopedIp::V6(_, Some(_)) => None,
         })
         .collect())
 }
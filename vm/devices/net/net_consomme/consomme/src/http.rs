@@ -0,0 +1,221 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal HTTP server for network boot.
+//!
+//! The server listens on a host loopback socket, and guest connections to port
+//! 80 of the gateway are redirected to it.
+
+use super::Access;
+use super::Client;
+use super::SocketAddress;
+use crate::resolve_boot_path;
+use inspect::Inspect;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const HTTP_PORT: u16 = 80;
+
+const MAX_REQUEST_LEN: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Inspect)]
+pub(crate) struct HttpServer {
+    #[inspect(display)]
+    addr: SocketAddr,
+    #[inspect(with = "|x| x.display().to_string()")]
+    root: PathBuf,
+    #[inspect(skip)]
+    shutdown: Arc<AtomicBool>,
+    #[inspect(skip)]
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Starts a server for the files in `root`.
+    fn new(root: PathBuf) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("consomme-http".into())
+            .spawn({
+                let root = root.clone();
+                let shutdown = shutdown.clone();
+                move || run_listener(listener, &root, &shutdown)
+            })?;
+        Ok(Self {
+            addr,
+            root,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the listener thread.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_listener(listener: TcpListener, root: &Path, shutdown: &AtomicBool) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "http accept failure"
+                );
+                continue;
+            }
+        };
+        let root = root.to_owned();
+        let r = std::thread::Builder::new()
+            .name("consomme-http-conn".into())
+            .spawn(move || {
+                if let Err(err) = serve(&root, stream) {
+                    tracing::debug!(
+                        error = &err as &dyn std::error::Error,
+                        "http request failure"
+                    );
+                }
+            });
+        if let Err(err) = r {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "failed to spawn http connection thread"
+            );
+        }
+    }
+}
+
+/// Decodes `%XX` escapes in a request path.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(c) = bytes.next() {
+        if c == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(c);
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_length: u64) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+        Content-Length: {content_length}\r\n\
+        Content-Type: application/octet-stream\r\n\
+        Connection: close\r\n\r\n"
+    )
+}
+
+/// Serves a single GET or HEAD request.
+fn serve(root: &Path, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_LEN {
+            return respond(&mut stream, "431 Request Header Fields Too Large", 0);
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let line = request.split(|&c| c == b'\r').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(line).unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, "400 Bad Request", 0);
+    };
+    let head = match method {
+        "GET" => false,
+        "HEAD" => true,
+        _ => return respond(&mut stream, "405 Method Not Allowed", 0),
+    };
+    let Some(path) = percent_decode(target.split(['?', '#']).next().unwrap_or_default()) else {
+        return respond(&mut stream, "400 Bad Request", 0);
+    };
+
+    let Some((mut file, len)) = resolve_boot_path(root, &path).and_then(|path| {
+        let file = File::open(path).ok()?;
+        let metadata = file.metadata().ok()?;
+        metadata.is_file().then_some((file, metadata.len()))
+    }) else {
+        tracing::debug!(path, "http file not found");
+        return respond(&mut stream, "404 Not Found", 0);
+    };
+
+    respond(&mut stream, "200 OK", len)?;
+    if !head {
+        io::copy(&mut file, &mut stream)?;
+    }
+    Ok(())
+}
+
+impl<T: Client> Access<'_, T> {
+    /// Returns the address to connect to for a guest TCP connection to `dst`,
+    /// if it is for the HTTP boot server, starting the server if necessary.
+    pub(crate) fn http_boot_addr(&mut self, dst: &SocketAddress) -> Option<SocketAddr> {
+        let root = self.inner.state.dhcp.boot_dir.as_ref()?;
+        let to_gateway = match dst.ip {
+            IpAddr::V4(ip) => ip == Ipv4Addr::from(self.inner.state.gateway_ip),
+            IpAddr::V6(ip) => ip == Ipv6Addr::from(self.inner.state.gateway_ipv6),
+        };
+        if !to_gateway || dst.port != HTTP_PORT {
+            return None;
+        }
+        if self
+            .inner
+            .http
+            .as_ref()
+            .is_none_or(|server| &server.root != root)
+        {
+            // Stop any server for a previous directory first.
+            self.inner.http = None;
+            match HttpServer::new(root.clone()) {
+                Ok(server) => self.inner.http = Some(server),
+                Err(err) => {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to start http boot server"
+                    );
+                    return None;
+                }
+            }
+        }
+        self.inner.http.as_ref().map(|server| server.addr)
+    }
+}
@@ -0,0 +1,464 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! ICMPv6 handling, including the router side of neighbor discovery (NDP).
+//!
+//! The gateway responds to router solicitations with router advertisements
+//! carrying the IPv6 prefix for SLAAC, answers neighbor solicitations for its
+//! own address, and replies to echo requests sent to it.
+
+use super::Access;
+use super::Client;
+use super::DropReason;
+use crate::ChecksumState;
+use crate::IPV6_HEADER_LEN;
+use crate::IpAddresses;
+use crate::MIN_MTU;
+use crate::emit_ip_headers;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
+use smoltcp::wire::EthernetAddress;
+use smoltcp::wire::EthernetRepr;
+use smoltcp::wire::IpProtocol;
+use smoltcp::wire::Ipv6Packet;
+use std::net::IpAddr;
+use std::net::Ipv6Addr;
+use std::task::Context;
+use std::time::Duration;
+
+const ECHO_REQUEST: u8 = 128;
+const ECHO_REPLY: u8 = 129;
+const ROUTER_SOLICIT: u8 = 133;
+const ROUTER_ADVERT: u8 = 134;
+const NEIGHBOR_SOLICIT: u8 = 135;
+const NEIGHBOR_ADVERT: u8 = 136;
+
+const OPT_SOURCE_LINK_ADDR: u8 = 1;
+const OPT_TARGET_LINK_ADDR: u8 = 2;
+const OPT_PREFIX_INFO: u8 = 3;
+const OPT_MTU: u8 = 5;
+const OPT_RDNSS: u8 = 25;
+
+/// RA flag telling the guest to use DHCPv6 for other configuration (DNS).
+const RA_FLAG_OTHER_CONFIG: u8 = 0x40;
+/// Prefix flags: on-link and autonomous address configuration.
+const PREFIX_FLAG_ON_LINK: u8 = 0x80;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+const NA_FLAG_ROUTER: u8 = 0x80;
+const NA_FLAG_SOLICITED: u8 = 0x40;
+const NA_FLAG_OVERRIDE: u8 = 0x20;
+
+/// NDP messages must be sent with the maximum hop limit so that receivers
+/// can verify they originated on the link.
+const NDP_HOP_LIMIT: u8 = 255;
+
+const ROUTER_LIFETIME_SECS: u16 = 1800;
+const PREFIX_VALID_LIFETIME_SECS: u32 = 86400;
+const PREFIX_PREFERRED_LIFETIME_SECS: u32 = 14400;
+const RDNSS_LIFETIME_SECS: u32 = 1800;
+const UNSOLICITED_ADVERT_INTERVAL: Duration = Duration::from_secs(600);
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const ALL_NODES_MAC: EthernetAddress = EthernetAddress([0x33, 0x33, 0, 0, 0, 1]);
+
+/// State for sending periodic unsolicited router advertisements.
+pub(crate) struct Ndp {
+    timer: Option<PolledTimer>,
+    /// The time of the next unsolicited advertisement, or `None` if the guest
+    /// has not used IPv6 yet.
+    next_advert: Option<Instant>,
+}
+
+impl Ndp {
+    pub fn new() -> Self {
+        Self {
+            timer: None,
+            next_advert: None,
+        }
+    }
+}
+
+impl<T: Client> Access<'_, T> {
+    pub(crate) fn poll_ndp(&mut self, cx: &mut Context<'_>) {
+        let Some(next_advert) = self.inner.ndp.next_advert else {
+            return;
+        };
+        let timer = self
+            .inner
+            .ndp
+            .timer
+            .get_or_insert_with(|| PolledTimer::new(self.client.driver()));
+        if timer.poll_until(cx, next_advert).is_ready() {
+            self.send_router_advert(ALL_NODES_MAC, ALL_NODES);
+        }
+    }
+
+    pub(crate) fn refresh_ndp_driver(&mut self) {
+        self.inner.ndp.timer = None;
+    }
+
+    pub(crate) fn handle_icmpv6(
+        &mut self,
+        frame: &EthernetRepr,
+        addresses: &IpAddresses,
+        payload: &[u8],
+    ) -> Result<(), DropReason> {
+        let (IpAddr::V6(src_addr), IpAddr::V6(dst_addr)) = (addresses.src_addr, addresses.dst_addr)
+        else {
+            unreachable!()
+        };
+        if payload.len() < 8 {
+            return Err(DropReason::Packet(smoltcp::Error::Truncated));
+        }
+        if checksum(&src_addr, &dst_addr, payload) != 0 {
+            return Err(DropReason::Packet(smoltcp::Error::Checksum));
+        }
+
+        let gateway_ip = Ipv6Addr::from(self.inner.state.gateway_ipv6);
+        match payload[0] {
+            ROUTER_SOLICIT => {
+                let dst_addr = if src_addr.is_unspecified() {
+                    ALL_NODES
+                } else {
+                    src_addr
+                };
+                self.send_router_advert(frame.src_addr, dst_addr);
+            }
+            NEIGHBOR_SOLICIT => {
+                if payload.len() < 24 {
+                    return Err(DropReason::Packet(smoltcp::Error::Truncated));
+                }
+                let target: [u8; 16] = payload[8..24].try_into().unwrap();
+                let target = Ipv6Addr::from(target);
+                // Ignore solicitations for other addresses, including duplicate
+                // address detection probes for the guest's own addresses.
+                if target != gateway_ip {
+                    return Ok(());
+                }
+                // Solicitations from the unspecified address must be answered
+                // via multicast, without the solicited flag.
+                let (dst_mac, dst_addr, solicited) = if src_addr.is_unspecified() {
+                    (ALL_NODES_MAC, ALL_NODES, 0)
+                } else {
+                    (frame.src_addr, src_addr, NA_FLAG_SOLICITED)
+                };
+                let mut advert = vec![
+                    NEIGHBOR_ADVERT,
+                    0,
+                    0,
+                    0,
+                    NA_FLAG_ROUTER | solicited | NA_FLAG_OVERRIDE,
+                    0,
+                    0,
+                    0,
+                ];
+                advert.extend_from_slice(&target.octets());
+                advert.extend_from_slice(&[OPT_TARGET_LINK_ADDR, 1]);
+                advert.extend_from_slice(self.inner.state.gateway_mac.as_bytes());
+                self.send_icmpv6(dst_mac, dst_addr, NDP_HOP_LIMIT, &mut advert);
+            }
+            ECHO_REQUEST if dst_addr == gateway_ip => {
+                let mut reply = payload.to_vec();
+                reply[0] = ECHO_REPLY;
+                self.send_icmpv6(frame.src_addr, src_addr, 64, &mut reply);
+            }
+            ty => return Err(DropReason::UnsupportedIcmpv6(ty)),
+        }
+        Ok(())
+    }
+
+    fn send_router_advert(&mut self, dst_mac: EthernetAddress, dst_addr: Ipv6Addr) {
+        let state = &self.inner.state;
+        let mut advert = vec![ROUTER_ADVERT, 0, 0, 0, 64, RA_FLAG_OTHER_CONFIG];
+        advert.extend_from_slice(&ROUTER_LIFETIME_SECS.to_be_bytes());
+        // Reachable time and retransmit timer are left unspecified.
+        advert.extend_from_slice(&[0; 8]);
+
+        advert.extend_from_slice(&[OPT_SOURCE_LINK_ADDR, 1]);
+        advert.extend_from_slice(state.gateway_mac.as_bytes());
+
+        advert.extend_from_slice(&[OPT_MTU, 1, 0, 0]);
+        advert.extend_from_slice(&((MIN_MTU - ETHERNET_HEADER_LEN) as u32).to_be_bytes());
+
+        advert.extend_from_slice(&[
+            OPT_PREFIX_INFO,
+            4,
+            64,
+            PREFIX_FLAG_ON_LINK | PREFIX_FLAG_AUTONOMOUS,
+        ]);
+        advert.extend_from_slice(&PREFIX_VALID_LIFETIME_SECS.to_be_bytes());
+        advert.extend_from_slice(&PREFIX_PREFERRED_LIFETIME_SECS.to_be_bytes());
+        advert.extend_from_slice(&[0; 4]);
+        advert.extend_from_slice(state.ipv6_prefix.as_bytes());
+
+        if !state.nameservers_ipv6.is_empty() {
+            let len = 1 + 2 * state.nameservers_ipv6.len();
+            advert.extend_from_slice(&[OPT_RDNSS, len as u8, 0, 0]);
+            advert.extend_from_slice(&RDNSS_LIFETIME_SECS.to_be_bytes());
+            for addr in &state.nameservers_ipv6 {
+                advert.extend_from_slice(addr.as_bytes());
+            }
+        }
+
+        self.send_icmpv6(dst_mac, dst_addr, NDP_HOP_LIMIT, &mut advert);
+        self.inner.ndp.next_advert =
+            Some(Instant::now().saturating_add(UNSOLICITED_ADVERT_INTERVAL));
+    }
+
+    /// Sends an ICMPv6 message from the gateway, filling in its checksum.
+    fn send_icmpv6(
+        &mut self,
+        dst_mac: EthernetAddress,
+        dst_addr: Ipv6Addr,
+        hop_limit: u8,
+        message: &mut [u8],
+    ) {
+        if ETHERNET_HEADER_LEN + IPV6_HEADER_LEN + message.len() > MIN_MTU {
+            tracing::debug!(len = message.len(), "icmpv6 message too large");
+            return;
+        }
+        let src_addr = Ipv6Addr::from(self.inner.state.gateway_ipv6);
+        message[2..4].fill(0);
+        let checksum = checksum(&src_addr, &dst_addr, message);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut buffer = [0; MIN_MTU];
+        let offset = emit_ip_headers(
+            &mut buffer,
+            self.inner.state.gateway_mac,
+            dst_mac,
+            src_addr.into(),
+            dst_addr.into(),
+            IpProtocol::Icmpv6,
+            message.len(),
+        );
+        let len = offset + message.len();
+        Ipv6Packet::new_unchecked(&mut buffer[ETHERNET_HEADER_LEN..]).set_hop_limit(hop_limit);
+        buffer[offset..len].copy_from_slice(message);
+        self.client.recv(&buffer[..len], &ChecksumState::NONE);
+    }
+}
+
+/// Computes the ICMPv6 checksum, including the IPv6 pseudo-header. Returns
+/// zero when computed over a message with a valid checksum.
+fn checksum(src_addr: &Ipv6Addr, dst_addr: &Ipv6Addr, message: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    let mut add = |data: &[u8]| {
+        for chunk in data.chunks(2) {
+            sum += u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]));
+        }
+    };
+    add(&src_addr.octets());
+    add(&dst_addr.octets());
+    add(&(message.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, IpProtocol::Icmpv6.into()]);
+    add(message);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Consomme;
+    use crate::test_helpers::TestClient;
+    use crate::test_helpers::client_frame;
+    use crate::test_helpers::ipv6_packet;
+    use crate::test_helpers::test_state;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use smoltcp::wire::EthernetFrame;
+    use smoltcp::wire::EthernetProtocol;
+
+    const CLIENT_ADDR: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+    const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+    /// Builds an ICMPv6 message with a valid checksum.
+    fn message(src_addr: Ipv6Addr, dst_addr: Ipv6Addr, mut message: Vec<u8>) -> Vec<u8> {
+        let checksum = checksum(&src_addr, &dst_addr, &message);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        message
+    }
+
+    /// Sends `message` to consomme, returning the frames sent in response.
+    fn exchange(
+        driver: &DefaultDriver,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+        message: &[u8],
+    ) -> Result<Vec<Vec<u8>>, DropReason> {
+        let mut consomme = Consomme::new_with_state(test_state());
+        let frame = client_frame(&consomme.state, EthernetProtocol::Ipv6);
+        let mut client = TestClient::new(driver.clone());
+        let addresses = IpAddresses {
+            src_addr: src_addr.into(),
+            dst_addr: dst_addr.into(),
+        };
+        consomme
+            .access(&mut client)
+            .handle_icmpv6(&frame, &addresses, message)?;
+        Ok(client.frames)
+    }
+
+    /// Returns the ICMPv6 message in `frame`, checking that it was sent to
+    /// `dst_addr` with a valid checksum.
+    fn reply(frame: &[u8], dst_addr: Ipv6Addr) -> Vec<u8> {
+        let ipv6 = ipv6_packet(frame);
+        assert_eq!(ipv6.next_header(), IpProtocol::Icmpv6);
+        assert_eq!(Ipv6Addr::from(ipv6.dst_addr()), dst_addr);
+        let src_addr = Ipv6Addr::from(ipv6.src_addr());
+        assert_eq!(src_addr, Ipv6Addr::from(test_state().gateway_ipv6));
+        assert_eq!(checksum(&src_addr, &dst_addr, ipv6.payload()), 0);
+        ipv6.payload().to_vec()
+    }
+
+    /// Returns the NDP options in `data`, by type.
+    fn ndp_options(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut options = Vec::new();
+        while !data.is_empty() {
+            let len = usize::from(data[1]) * 8;
+            assert!(len != 0 && len <= data.len());
+            options.push((data[0], data[2..len].to_vec()));
+            data = &data[len..];
+        }
+        options
+    }
+
+    #[async_test]
+    async fn test_router_solicit(driver: DefaultDriver) {
+        let rs = message(
+            CLIENT_ADDR,
+            ALL_ROUTERS,
+            vec![ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0],
+        );
+        let frames = exchange(&driver, CLIENT_ADDR, ALL_ROUTERS, &rs).unwrap();
+        assert_eq!(frames.len(), 1);
+        let state = test_state();
+        assert_eq!(
+            EthernetFrame::new_checked(&frames[0][..])
+                .unwrap()
+                .dst_addr(),
+            state.client_mac
+        );
+        assert_eq!(ipv6_packet(&frames[0]).hop_limit(), NDP_HOP_LIMIT);
+
+        let ra = reply(&frames[0], CLIENT_ADDR);
+        assert_eq!(ra[0], ROUTER_ADVERT);
+        assert_eq!(ra[5], RA_FLAG_OTHER_CONFIG);
+        assert_eq!(ra[6..8], ROUTER_LIFETIME_SECS.to_be_bytes());
+
+        let options = ndp_options(&ra[16..]);
+        let option = |ty| {
+            options
+                .iter()
+                .find(|(t, _)| *t == ty)
+                .map(|(_, v)| v.as_slice())
+                .unwrap()
+        };
+        assert_eq!(option(OPT_SOURCE_LINK_ADDR), state.gateway_mac.as_bytes());
+        let prefix = option(OPT_PREFIX_INFO);
+        assert_eq!(prefix[0], 64);
+        assert_eq!(prefix[1], PREFIX_FLAG_ON_LINK | PREFIX_FLAG_AUTONOMOUS);
+        assert_eq!(prefix[14..], *state.ipv6_prefix.as_bytes());
+        let rdnss = option(OPT_RDNSS);
+        assert_eq!(rdnss[6..], *state.nameservers_ipv6[0].as_bytes());
+    }
+
+    #[async_test]
+    async fn test_router_solicit_unspecified(driver: DefaultDriver) {
+        // A solicitation from a guest without an address is answered via
+        // multicast.
+        let rs = message(
+            Ipv6Addr::UNSPECIFIED,
+            ALL_ROUTERS,
+            vec![ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0],
+        );
+        let frames = exchange(&driver, Ipv6Addr::UNSPECIFIED, ALL_ROUTERS, &rs).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(reply(&frames[0], ALL_NODES)[0], ROUTER_ADVERT);
+    }
+
+    #[async_test]
+    async fn test_neighbor_solicit(driver: DefaultDriver) {
+        let gateway = Ipv6Addr::from(test_state().gateway_ipv6);
+        let mut ns = vec![NEIGHBOR_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+        ns.extend_from_slice(&gateway.octets());
+        let ns = message(CLIENT_ADDR, gateway, ns);
+        let frames = exchange(&driver, CLIENT_ADDR, gateway, &ns).unwrap();
+        assert_eq!(frames.len(), 1);
+        let na = reply(&frames[0], CLIENT_ADDR);
+        assert_eq!(na[0], NEIGHBOR_ADVERT);
+        assert_eq!(na[4], NA_FLAG_ROUTER | NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE);
+        assert_eq!(na[8..24], gateway.octets());
+
+        // Solicitations for other addresses are ignored.
+        let mut ns = vec![NEIGHBOR_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+        ns.extend_from_slice(&CLIENT_ADDR.octets());
+        let ns = message(Ipv6Addr::UNSPECIFIED, gateway, ns);
+        let frames = exchange(&driver, Ipv6Addr::UNSPECIFIED, gateway, &ns).unwrap();
+        assert!(frames.is_empty());
+    }
+
+    #[async_test]
+    async fn test_echo(driver: DefaultDriver) {
+        let gateway = Ipv6Addr::from(test_state().gateway_ipv6);
+        let echo = message(
+            CLIENT_ADDR,
+            gateway,
+            vec![ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 2, b'h', b'i'],
+        );
+        let frames = exchange(&driver, CLIENT_ADDR, gateway, &echo).unwrap();
+        assert_eq!(frames.len(), 1);
+        let reply = reply(&frames[0], CLIENT_ADDR);
+        assert_eq!(reply[0], ECHO_REPLY);
+        assert_eq!(reply[4..], echo[4..]);
+    }
+
+    #[async_test]
+    async fn test_malformed(driver: DefaultDriver) {
+        let gateway = Ipv6Addr::from(test_state().gateway_ipv6);
+
+        // Shorter than the ICMPv6 header.
+        assert!(matches!(
+            exchange(&driver, CLIENT_ADDR, gateway, &[ROUTER_SOLICIT, 0, 0, 0]),
+            Err(DropReason::Packet(smoltcp::Error::Truncated))
+        ));
+
+        // A bad checksum.
+        let mut rs = message(
+            CLIENT_ADDR,
+            ALL_ROUTERS,
+            vec![ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0],
+        );
+        rs[2] ^= 1;
+        assert!(matches!(
+            exchange(&driver, CLIENT_ADDR, ALL_ROUTERS, &rs),
+            Err(DropReason::Packet(smoltcp::Error::Checksum))
+        ));
+
+        // A neighbor solicitation too short to hold its target.
+        let ns = message(
+            CLIENT_ADDR,
+            gateway,
+            vec![NEIGHBOR_SOLICIT, 0, 0, 0, 0, 0, 0, 0, 0xfe, 0x80],
+        );
+        assert!(matches!(
+            exchange(&driver, CLIENT_ADDR, gateway, &ns),
+            Err(DropReason::Packet(smoltcp::Error::Truncated))
+        ));
+
+        // A message type the gateway does not handle.
+        let ra = message(
+            CLIENT_ADDR,
+            gateway,
+            vec![ROUTER_ADVERT, 0, 0, 0, 0, 0, 0, 0],
+        );
+        assert!(matches!(
+            exchange(&driver, CLIENT_ADDR, gateway, &ra),
+            Err(DropReason::UnsupportedIcmpv6(ROUTER_ADVERT))
+        ));
+    }
+}
@@ -12,14 +12,24 @@
 //! essentially causing this stack to act as a NAT implementation, providing
 //! guest OS networking by leveraging the host's network stack.
 //!
-//! This implementation includes a small DHCP server for address assignment.
+//! This implementation includes a small DHCP server for address assignment,
+//! plus optional TFTP and HTTP servers for network boot.
+//! IPv6 is also supported: the guest configures its address via SLAAC from
+//! router advertisements, and a small DHCPv6 server provides addresses and DNS
+//! servers to guests that request them.
 
 mod arp;
 mod dhcp;
+mod dhcpv6;
 #[cfg_attr(unix, path = "dns_unix.rs")]
 #[cfg_attr(windows, path = "dns_windows.rs")]
 mod dns;
+mod http;
+mod icmpv6;
 mod tcp;
+#[cfg(test)]
+mod test_helpers;
+mod tftp;
 mod udp;
 mod windows;
 
@@ -31,16 +41,25 @@
 use smoltcp::phy::Checksum;
 use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::wire::DhcpMessageType;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
 use smoltcp::wire::EthernetAddress;
 use smoltcp::wire::EthernetFrame;
 use smoltcp::wire::EthernetProtocol;
 use smoltcp::wire::EthernetRepr;
 use smoltcp::wire::IPV4_HEADER_LEN;
+use smoltcp::wire::IpAddress;
 use smoltcp::wire::IpProtocol;
 use smoltcp::wire::Ipv4Address;
 use smoltcp::wire::Ipv4Packet;
+use smoltcp::wire::Ipv4Repr;
+use smoltcp::wire::Ipv6Address;
+use smoltcp::wire::Ipv6Packet;
+use smoltcp::wire::Ipv6Repr;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::net::SocketAddrV4;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
 use std::task::Context;
 use std::task::Poll;
 use thiserror::Error;
@@ -133,13 +152,18 @@ pub struct Consomme {
     recv: Option<mesh::Receiver<ConsommeMessage>>,
     tcp: tcp::Tcp,
     udp: udp::Udp,
+    ndp: icmpv6::Ndp,
+    tftp: tftp::Tftp,
+    http: Option<http::HttpServer>,
 }
 
 impl InspectMut for Consomme {
     fn inspect_mut(&mut self, req: inspect::Request<'_>) {
         req.respond()
             .field("tcp", &self.tcp)
-            .field_mut("udp", &mut self.udp);
+            .field_mut("udp", &mut self.udp)
+            .field("tftp", &self.tftp)
+            .field("http", &self.http);
     }
 }
 
@@ -157,10 +181,49 @@ pub struct ConsommeState {
     pub client_mac: EthernetAddress,
     /// Current list of DNS resolvers.
     pub nameservers: Vec<Ipv4Address>,
+    /// Current IPv6 prefix (a /64) advertised for address autoconfiguration.
+    pub ipv6_prefix: Ipv6Address,
+    /// Current IPv6 link-local gateway address.
+    pub gateway_ipv6: Ipv6Address,
+    /// Current IPv6 address assigned to the endpoint via DHCPv6.
+    pub client_ipv6: Ipv6Address,
+    /// Current list of IPv6 DNS resolvers.
+    pub nameservers_ipv6: Vec<Ipv6Address>,
+    /// Additional options provided by the DHCP server.
+    pub dhcp: DhcpOptions,
     /// Buffer for packet processing
     buffer: Box<[u8]>,
 }
 
+/// Additional configuration for the built-in DHCP server.
+#[derive(Debug, Clone, Default)]
+pub struct DhcpOptions {
+    /// DNS search domains. The first is also provided as the domain name.
+    pub search_domains: Vec<String>,
+    /// Fixed addresses to assign to specific clients, instead of the default
+    /// client IP.
+    pub static_leases: Vec<StaticLease>,
+    /// The TFTP server name or address, for network boot.
+    pub tftp_server: Option<String>,
+    /// The boot file name, for network boot.
+    pub boot_file: Option<String>,
+    /// A directory to serve over TFTP and HTTP from the gateway address, for
+    /// network boot.
+    ///
+    /// Clients requesting HTTP boot are given an HTTP URL for `boot_file`;
+    /// other clients fetch it over TFTP.
+    pub boot_dir: Option<PathBuf>,
+}
+
+/// A fixed DHCP address assignment.
+#[derive(Debug, Copy, Clone)]
+pub struct StaticLease {
+    /// The client's MAC address.
+    pub mac: [u8; 6],
+    /// The address to assign.
+    pub ip: Ipv4Addr,
+}
+
 /// An error indicating that the CIDR is invalid.
 #[derive(Debug, Error)]
 #[error("invalid CIDR")]
@@ -170,9 +233,25 @@ impl ConsommeState {
     /// Create default dynamic network state. The default state is
     ///     IP address: 10.0.0.2 / 24
     ///     gateway: 10.0.0.1 with MAC address 52-55-10-0-0-1
-    ///     no DNS resolvers
+    ///     IPv6 prefix: fd00::/64, with DHCPv6 address fd00::2
+    ///     IPv6 gateway: fe80::1
+    ///     the host's DNS resolvers
     pub fn new() -> Result<Self, Error> {
-        let nameservers = dns::nameservers()?;
+        let mut nameservers = Vec::new();
+        let mut nameservers_ipv6 = Vec::new();
+        for addr in dns::nameservers()? {
+            match addr {
+                IpAddr::V4(addr) => nameservers.push(addr.into()),
+                // Link-local and site-local resolvers are only reachable via a
+                // specific host interface.
+                IpAddr::V6(addr)
+                    if !addr.is_unicast_link_local() && addr.segments()[0] & 0xffc0 != 0xfec0 =>
+                {
+                    nameservers_ipv6.push(addr.into())
+                }
+                IpAddr::V6(_) => {}
+            }
+        }
         Ok(Self {
             gateway_ip: Ipv4Address::new(10, 0, 0, 1),
             gateway_mac: EthernetAddress([0x52, 0x55, 10, 0, 0, 1]),
@@ -180,6 +259,11 @@ pub fn new() -> Result<Self, Error> {
             client_mac: EthernetAddress([0x0, 0x0, 0x0, 0x0, 0x1, 0x0]),
             net_mask: Ipv4Address::new(255, 255, 255, 0),
             nameservers,
+            ipv6_prefix: Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 0),
+            gateway_ipv6: Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            client_ipv6: Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 2),
+            nameservers_ipv6,
+            dhcp: DhcpOptions::default(),
             buffer: Box::new([0; 65535]),
         })
     }
@@ -220,11 +304,12 @@ pub trait Client {
     /// packet contains an IPv4 header, TCP header, and/or UDP header with a
     /// valid checksum.
     ///
-    /// TODO:
+    /// If `checksum.tso` is set, then the packet is a TCP packet coalesced from
+    /// segments of the given size. This only occurs if [`Client::rx_lro`]
+    /// returns true.
     ///
-    /// 1. support >MTU sized packets (RSC/LRO/GRO)
-    /// 2. allow discontiguous data to eliminate the extra copy from the TCP
-    ///    window.
+    /// TODO: allow discontiguous data to eliminate the extra copy from the TCP
+    /// window.
     fn recv(&mut self, data: &[u8], checksum: &ChecksumState);
 
     /// Specifies the maximum size for the next call to `recv`.
@@ -235,6 +320,12 @@ pub trait Client {
     /// Return 0 to indicate that there are no buffers available for receiving
     /// data.
     fn rx_mtu(&mut self) -> usize;
+
+    /// Specifies whether the client can receive TCP packets larger than the
+    /// maximum segment size, up to [`Client::rx_mtu`] bytes.
+    fn rx_lro(&mut self) -> bool {
+        false
+    }
 }
 
 /// Specifies the checksum state for a packet being transmitted.
@@ -252,7 +343,8 @@ pub struct ChecksumState {
     /// The data consists of multiple TCP segments, each with the provided
     /// segment size.
     ///
-    /// The IP header's length field may be invalid and should be ignored.
+    /// On send, the IP header's length field may be invalid and should be
+    /// ignored.
     pub tso: Option<u16>,
 }
 
@@ -281,6 +373,18 @@ impl ChecksumState {
         udp: true,
         tso: None,
     };
+    const TCP6: Self = Self {
+        ipv4: false,
+        tcp: true,
+        udp: false,
+        tso: None,
+    };
+    const UDP6: Self = Self {
+        ipv4: false,
+        tcp: false,
+        udp: true,
+        tso: None,
+    };
 
     fn caps(&self) -> ChecksumCapabilities {
         let mut caps = ChecksumCapabilities::default();
@@ -301,21 +405,100 @@ fn caps(&self) -> ChecksumCapabilities {
 /// frame).
 pub const MIN_MTU: usize = 1514;
 
+const IPV6_HEADER_LEN: usize = 40;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 struct SocketAddress {
-    ip: Ipv4Address,
+    ip: IpAddr,
     port: u16,
 }
 
-impl From<SocketAddress> for SocketAddrV4 {
+impl From<SocketAddress> for SocketAddr {
     fn from(addr: SocketAddress) -> Self {
-        Self::new(addr.ip.into(), addr.port)
+        Self::new(addr.ip, addr.port)
     }
 }
 
 impl From<SocketAddress> for socket2::SockAddr {
     fn from(addr: SocketAddress) -> Self {
-        socket2::SockAddr::from(SocketAddrV4::from(addr))
+        socket2::SockAddr::from(SocketAddr::from(addr))
+    }
+}
+
+fn ip_address(addr: IpAddr) -> IpAddress {
+    match addr {
+        IpAddr::V4(addr) => IpAddress::Ipv4(addr.into()),
+        IpAddr::V6(addr) => IpAddress::Ipv6(addr.into()),
+    }
+}
+
+/// Resolves a path requested by a network boot client to a file within
+/// `root`, rejecting paths that would escape it.
+fn resolve_boot_path(root: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = root.to_owned();
+    // Windows boot loaders use backslashes as path separators.
+    for component in name.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            c if c.contains(':') => return None,
+            c => path.push(c),
+        }
+    }
+    Some(path)
+}
+
+fn ip_header_len(addr: IpAddr) -> usize {
+    match addr {
+        IpAddr::V4(_) => IPV4_HEADER_LEN,
+        IpAddr::V6(_) => IPV6_HEADER_LEN,
+    }
+}
+
+/// Writes the Ethernet and IP headers for a packet from `src` to `dst` with
+/// `payload_len` bytes of `protocol` payload, returning the offset of the
+/// payload within `buffer`.
+fn emit_ip_headers(
+    buffer: &mut [u8],
+    src_mac: EthernetAddress,
+    dst_mac: EthernetAddress,
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: IpProtocol,
+    payload_len: usize,
+) -> usize {
+    let mut eth = EthernetFrame::new_unchecked(buffer);
+    eth.set_src_addr(src_mac);
+    eth.set_dst_addr(dst_mac);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            eth.set_ethertype(EthernetProtocol::Ipv4);
+            let ipv4 = Ipv4Repr {
+                src_addr: src.into(),
+                dst_addr: dst.into(),
+                protocol,
+                payload_len,
+                hop_limit: 64,
+            };
+            ipv4.emit(
+                &mut Ipv4Packet::new_unchecked(eth.payload_mut()),
+                &ChecksumCapabilities::default(),
+            );
+            ETHERNET_HEADER_LEN + ipv4.buffer_len()
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            eth.set_ethertype(EthernetProtocol::Ipv6);
+            let ipv6 = Ipv6Repr {
+                src_addr: src.into(),
+                dst_addr: dst.into(),
+                next_header: protocol,
+                payload_len,
+                hop_limit: 64,
+            };
+            ipv6.emit(&mut Ipv6Packet::new_unchecked(eth.payload_mut()));
+            ETHERNET_HEADER_LEN + ipv6.buffer_len()
+        }
+        _ => unreachable!("mismatched address families"),
     }
 }
 
@@ -343,6 +526,12 @@ pub enum DropReason {
     /// The ARP type is unsupported.
     #[error("unsupported arp type")]
     UnsupportedArp,
+    /// The DHCPv6 message type is unsupported.
+    #[error("unsupported dhcpv6 message type {0}")]
+    UnsupportedDhcpv6(u8),
+    /// The ICMPv6 message type is unsupported.
+    #[error("unsupported icmpv6 message type {0}")]
+    UnsupportedIcmpv6(u8),
     /// The IPv4 checksum was invalid.
     #[error("ipv4 checksum failure")]
     Ipv4Checksum,
@@ -369,9 +558,9 @@ pub enum Error {
 }
 
 #[derive(Debug)]
-struct Ipv4Addresses {
-    src_addr: Ipv4Address,
-    dst_addr: Ipv4Address,
+struct IpAddresses {
+    src_addr: IpAddr,
+    dst_addr: IpAddr,
 }
 
 impl Consomme {
@@ -388,6 +577,9 @@ pub fn new_with_state(state: ConsommeState) -> Self {
             recv: None,
             tcp: tcp::Tcp::new(),
             udp: udp::Udp::new(),
+            ndp: icmpv6::Ndp::new(),
+            tftp: tftp::Tftp::new(),
+            http: None,
         }
     }
 
@@ -399,6 +591,9 @@ pub fn new_dynamic(state: ConsommeState) -> (Self, ConsommeControl) {
             recv: Some(recv),
             tcp: tcp::Tcp::new(),
             udp: udp::Udp::new(),
+            ndp: icmpv6::Ndp::new(),
+            tftp: tftp::Tftp::new(),
+            http: None,
         };
         let control = ConsommeControl { send };
         (this, control)
@@ -458,6 +653,7 @@ fn poll_message(&mut self, cx: &mut Context<'_>) {
     pub fn poll(&mut self, cx: &mut Context<'_>) {
         self.poll_udp(cx);
         self.poll_tcp(cx);
+        self.poll_ndp(cx);
         self.poll_message(cx);
     }
 
@@ -467,6 +663,7 @@ pub fn poll(&mut self, cx: &mut Context<'_>) {
     pub fn refresh_driver(&mut self) {
         self.refresh_tcp_driver();
         self.refresh_udp_driver();
+        self.refresh_ndp_driver();
     }
 
     /// Sends an Ethernet frame to the network.
@@ -503,6 +700,7 @@ pub fn send(&mut self, data: &[u8], checksum: &ChecksumState) -> Result<(), Drop
         let frame = EthernetRepr::parse(&frame_packet)?;
         match frame.ethertype {
             EthernetProtocol::Ipv4 => self.handle_ipv4(&frame, frame_packet.payload(), checksum)?,
+            EthernetProtocol::Ipv6 => self.handle_ipv6(&frame, frame_packet.payload(), checksum)?,
             EthernetProtocol::Arp => self.handle_arp(&frame, frame_packet.payload())?,
             _ => return Err(DropReason::UnsupportedEthertype(frame.ethertype)),
         }
@@ -541,9 +739,9 @@ fn handle_ipv4(
             return Err(DropReason::Ipv4Checksum);
         }
 
-        let addresses = Ipv4Addresses {
-            src_addr: ipv4.src_addr(),
-            dst_addr: ipv4.dst_addr(),
+        let addresses = IpAddresses {
+            src_addr: IpAddr::V4(ipv4.src_addr().into()),
+            dst_addr: IpAddr::V4(ipv4.dst_addr().into()),
         };
 
         let inner = &payload[ipv4.header_len().into()..total_len];
@@ -555,4 +753,83 @@ fn handle_ipv4(
         };
         Ok(())
     }
+
+    fn handle_ipv6(
+        &mut self,
+        frame: &EthernetRepr,
+        payload: &[u8],
+        checksum: &ChecksumState,
+    ) -> Result<(), DropReason> {
+        let ipv6 = Ipv6Packet::new_unchecked(payload);
+        if payload.len() < IPV6_HEADER_LEN || ipv6.version() != 6 {
+            return Err(DropReason::Packet(smoltcp::Error::Malformed));
+        }
+
+        let total_len = if checksum.tso.is_some() {
+            payload.len()
+        } else {
+            IPV6_HEADER_LEN + usize::from(ipv6.payload_len())
+        };
+        if payload.len() < total_len {
+            return Err(DropReason::Packet(smoltcp::Error::Malformed));
+        }
+
+        let addresses = IpAddresses {
+            src_addr: IpAddr::V6(ipv6.src_addr().into()),
+            dst_addr: IpAddr::V6(ipv6.dst_addr().into()),
+        };
+
+        let inner = &payload[IPV6_HEADER_LEN..total_len];
+
+        // Extension headers are not supported, so packets with them (such as
+        // MLD reports, which use a hop-by-hop header) are dropped.
+        match ipv6.next_header() {
+            IpProtocol::Tcp => self.handle_tcp(&addresses, inner, checksum)?,
+            IpProtocol::Udp => self.handle_udp(frame, &addresses, inner, checksum)?,
+            IpProtocol::Icmpv6 => self.handle_icmpv6(frame, &addresses, inner)?,
+            p => return Err(DropReason::UnsupportedIpProtocol(p)),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_boot_path() {
+        let root = Path::new("boot");
+        let resolve = |name| resolve_boot_path(root, name);
+        assert_eq!(resolve("shimx64.efi"), Some(root.join("shimx64.efi")));
+        assert_eq!(
+            resolve("efi/boot.efi"),
+            Some(root.join("efi").join("boot.efi"))
+        );
+        assert_eq!(
+            resolve("efi\\microsoft\\bootmgfw.efi"),
+            Some(root.join("efi").join("microsoft").join("bootmgfw.efi"))
+        );
+        assert_eq!(
+            resolve("./efi//boot.efi"),
+            Some(root.join("efi").join("boot.efi"))
+        );
+
+        // Absolute paths are relative to the root.
+        assert_eq!(
+            resolve("/efi/boot.efi"),
+            Some(root.join("efi").join("boot.efi"))
+        );
+        assert_eq!(resolve("\\boot.efi"), Some(root.join("boot.efi")));
+        assert_eq!(resolve(""), Some(root.to_owned()));
+
+        // Paths that could escape the root are rejected.
+        assert_eq!(resolve(".."), None);
+        assert_eq!(resolve("../etc/passwd"), None);
+        assert_eq!(resolve("efi/../../etc/passwd"), None);
+        assert_eq!(resolve("efi\\..\\boot.efi"), None);
+        assert_eq!(resolve("C:\\Windows\\boot.efi"), None);
+        assert_eq!(resolve("C:boot.efi"), None);
+        assert_eq!(resolve("efi/boot.efi:stream"), None);
+    }
 }
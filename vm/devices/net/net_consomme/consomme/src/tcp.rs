@@ -10,7 +10,10 @@
 use super::FourTuple;
 use super::SocketAddress;
 use crate::ChecksumState;
-use crate::Ipv4Addresses;
+use crate::IpAddresses;
+use crate::emit_ip_headers;
+use crate::ip_address;
+use crate::ip_header_len;
 use futures::AsyncRead;
 use futures::AsyncWrite;
 use inspect::Inspect;
@@ -19,12 +22,7 @@
 use pal_async::socket::PolledSocket;
 use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::wire::ETHERNET_HEADER_LEN;
-use smoltcp::wire::EthernetFrame;
-use smoltcp::wire::EthernetProtocol;
-use smoltcp::wire::IPV4_HEADER_LEN;
 use smoltcp::wire::IpProtocol;
-use smoltcp::wire::Ipv4Packet;
-use smoltcp::wire::Ipv4Repr;
 use smoltcp::wire::TcpControl;
 use smoltcp::wire::TcpPacket;
 use smoltcp::wire::TcpRepr;
@@ -41,9 +39,10 @@
 use std::io::ErrorKind;
 use std::io::IoSlice;
 use std::io::IoSliceMut;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::Shutdown;
-use std::net::SocketAddrV4;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
@@ -222,7 +221,7 @@ pub(crate) fn poll_tcp(&mut self, cx: &mut Context<'_>) {
                         }
 
                         let ft = FourTuple { dst: other_addr, src: SocketAddress {
-                            ip: self.inner.state.client_ip,
+                            ip: IpAddr::V4(self.inner.state.client_ip.into()),
                             port: *port,
                         } };
 
@@ -295,15 +294,15 @@ pub(crate) fn refresh_tcp_driver(&mut self) {
 
     pub(crate) fn handle_tcp(
         &mut self,
-        addresses: &Ipv4Addresses,
+        addresses: &IpAddresses,
         payload: &[u8],
         checksum: &ChecksumState,
     ) -> Result<(), DropReason> {
         let tcp_packet = TcpPacket::new_checked(payload)?;
         let tcp = TcpRepr::parse(
             &tcp_packet,
-            &addresses.src_addr.into(),
-            &addresses.dst_addr.into(),
+            &ip_address(addresses.src_addr),
+            &ip_address(addresses.dst_addr),
             &checksum.caps(),
         )?;
 
@@ -320,6 +319,14 @@ pub(crate) fn handle_tcp(
             },
         };
 
+        // Connections to the HTTP boot server are redirected to a local
+        // socket.
+        let remote = if tcp.control == TcpControl::Syn && tcp.ack_number.is_none() {
+            self.http_boot_addr(&ft.dst)
+        } else {
+            None
+        };
+
         let mut sender = Sender {
             ft: &ft,
             client: self.client,
@@ -340,7 +347,8 @@ pub(crate) fn handle_tcp(
                     // This is for an old connection. Send reset.
                     sender.rst(ack, None);
                 } else if tcp.control == TcpControl::Syn {
-                    let conn = TcpConnection::new(&mut sender, &tcp)?;
+                    let remote = remote.unwrap_or_else(|| ft.dst.into());
+                    let conn = TcpConnection::new(&mut sender, &tcp, remote)?;
                     e.insert(conn);
                 } else {
                     // Ignore the packet.
@@ -401,26 +409,35 @@ struct Sender<'a, T> {
 }
 
 impl<T: Client> Sender<'_, T> {
-    fn send_packet(&mut self, tcp: &TcpRepr<'_>, payload: Option<ring::View<'_>>) {
+    /// Sends a TCP packet to the client.
+    ///
+    /// `segment_size` is set if the payload is coalesced from multiple
+    /// segments, which is only allowed if the client supports LRO.
+    fn send_packet(
+        &mut self,
+        tcp: &TcpRepr<'_>,
+        payload: Option<ring::View<'_>>,
+        segment_size: Option<u16>,
+    ) {
         let buffer = &mut self.state.buffer;
-        let mut eth_packet = EthernetFrame::new_unchecked(&mut buffer[..]);
-        eth_packet.set_ethertype(EthernetProtocol::Ipv4);
-        eth_packet.set_dst_addr(self.state.client_mac);
-        eth_packet.set_src_addr(self.state.gateway_mac);
-        let mut ipv4_packet = Ipv4Packet::new_unchecked(eth_packet.payload_mut());
-        let ipv4 = Ipv4Repr {
-            src_addr: self.ft.dst.ip,
-            dst_addr: self.ft.src.ip,
-            protocol: IpProtocol::Tcp,
-            payload_len: tcp.header_len() + payload.as_ref().map_or(0, |p| p.len()),
-            hop_limit: 64,
-        };
-        ipv4.emit(&mut ipv4_packet, &ChecksumCapabilities::default());
-        let mut tcp_packet = TcpPacket::new_unchecked(ipv4_packet.payload_mut());
+        let payload_len = tcp.header_len() + payload.as_ref().map_or(0, |p| p.len());
+        let offset = emit_ip_headers(
+            &mut buffer[..],
+            self.state.gateway_mac,
+            self.state.client_mac,
+            self.ft.dst.ip,
+            self.ft.src.ip,
+            IpProtocol::Tcp,
+            payload_len,
+        );
+        let n = offset + payload_len;
+        let mut tcp_packet = TcpPacket::new_unchecked(&mut buffer[offset..n]);
+        let src_addr = ip_address(self.ft.dst.ip);
+        let dst_addr = ip_address(self.ft.src.ip);
         tcp.emit(
             &mut tcp_packet,
-            &self.ft.dst.ip.into(),
-            &self.ft.src.ip.into(),
+            &src_addr,
+            &dst_addr,
             &ChecksumCapabilities::default(),
         );
         if let Some(payload) = payload {
@@ -428,9 +445,17 @@ fn send_packet(&mut self, tcp: &TcpRepr<'_>, payload: Option<ring::View<'_>>) {
                 *b = *c;
             }
         }
-        tcp_packet.fill_checksum(&self.ft.dst.ip.into(), &self.ft.src.ip.into());
-        let n = ETHERNET_HEADER_LEN + ipv4_packet.total_len() as usize;
-        self.client.recv(&buffer[..n], &ChecksumState::TCP4);
+        tcp_packet.fill_checksum(&src_addr, &dst_addr);
+        let checksum = if self.ft.src.ip.is_ipv4() {
+            ChecksumState::TCP4
+        } else {
+            ChecksumState::TCP6
+        };
+        let checksum = ChecksumState {
+            tso: segment_size,
+            ..checksum
+        };
+        self.client.recv(&buffer[..n], &checksum);
     }
 
     fn rst(&mut self, seq: TcpSeqNumber, ack: Option<TcpSeqNumber>) {
@@ -450,7 +475,7 @@ fn rst(&mut self, seq: TcpSeqNumber, ack: Option<TcpSeqNumber>) {
 
         tracing::trace!(?tcp, "tcp rst xmit");
 
-        self.send_packet(&tcp, None);
+        self.send_packet(&tcp, None, None);
     }
 }
 
@@ -498,28 +523,33 @@ fn default() -> Self {
 }
 
 impl TcpConnection {
-    fn new(sender: &mut Sender<'_, impl Client>, tcp: &TcpRepr<'_>) -> Result<Self, DropReason> {
+    fn new(
+        sender: &mut Sender<'_, impl Client>,
+        tcp: &TcpRepr<'_>,
+        remote: SocketAddr,
+    ) -> Result<Self, DropReason> {
         let mut this = Self::default();
         this.initialize_from_first_client_packet(tcp)?;
 
-        let socket =
-            Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).map_err(DropReason::Io)?;
+        let socket = Socket::new(
+            Domain::for_address(remote),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )
+        .map_err(DropReason::Io)?;
 
         // On Windows the default behavior for non-existent loopback sockets is
         // to wait and try again. This is different than the Linux behavior of
         // immediately failing. Default to the Linux behavior.
         #[cfg(windows)]
-        if sender.ft.dst.ip.is_loopback() {
+        if remote.ip().is_loopback() {
             if let Err(err) = crate::windows::disable_connection_retries(&socket) {
                 tracing::trace!(err, "Failed to disable loopback retries");
             }
         }
 
         let socket = PolledSocket::new(sender.client.driver(), socket).map_err(DropReason::Io)?;
-        match socket
-            .get()
-            .connect(&SockAddr::from(SocketAddrV4::from(sender.ft.dst)))
-        {
+        match socket.get().connect(&SockAddr::from(remote)) {
             Ok(_) => unreachable!(),
             Err(err) if is_connect_incomplete_error(&err) => (),
             Err(err) => {
@@ -532,7 +562,7 @@ fn new(sender: &mut Sender<'_, impl Client>, tcp: &TcpRepr<'_>) -> Result<Self,
             }
         }
         if let Ok(addr) = socket.get().local_addr() {
-            if let Some(addr) = addr.as_socket_ipv4() {
+            if let Some(addr) = addr.as_socket() {
                 if addr.ip().is_loopback() {
                     this.loopback_port = LoopbackPortInfo::ProxyForGuestPort {
                         sending_port: addr.port(),
@@ -799,7 +829,7 @@ fn send_syn(&mut self, sender: &mut Sender<'_, impl Client>, ack_number: Option<
             payload: &[],
         };
 
-        sender.send_packet(&tcp, None);
+        sender.send_packet(&tcp, None, None);
         self.tx_send += 1;
     }
 
@@ -810,6 +840,10 @@ fn send_data(&mut self, sender: &mut Sender<'_, impl Client>) {
         let tx_window_end = self.tx_acked + ((self.tx_window_len as usize) << self.tx_window_scale);
         let tx_done = seq_min([tx_end, tx_window_end]);
 
+        // If the client can receive coalesced segments, then send as much data
+        // as fits in the receive buffer, rather than a segment at a time.
+        let lro = sender.client.rx_lro();
+
         while self.needs_ack || self.tx_send < tx_done {
             let rx_mtu = sender.client.rx_mtu();
             if rx_mtu == 0 {
@@ -837,17 +871,18 @@ fn send_data(&mut self, sender: &mut Sender<'_, impl Client>) {
             // exceeding:
             // 1. The available buffer length.
             // 2. The current window.
-            // 3. The configured maximum segment size.
+            // 3. The configured maximum segment size, unless coalescing.
             // 4. The client MTU.
             let tx_segment_end = {
-                let header_len = ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + tcp.header_len();
+                let header_len =
+                    ETHERNET_HEADER_LEN + ip_header_len(sender.ft.dst.ip) + tcp.header_len();
                 let mtu = rx_mtu.min(sender.state.buffer.len());
-                seq_min([
-                    tx_payload_end,
-                    tx_window_end,
-                    tx_next + self.tx_mss,
-                    tx_next + (mtu - header_len),
-                ])
+                let end = seq_min([tx_payload_end, tx_window_end, tx_next + (mtu - header_len)]);
+                if lro {
+                    end
+                } else {
+                    seq_min([end, tx_next + self.tx_mss])
+                }
             };
 
             let (payload_start, payload_len) = if tx_next < tx_segment_end {
@@ -877,7 +912,8 @@ fn send_data(&mut self, sender: &mut Sender<'_, impl Client>) {
                 .tx_buffer
                 .view(payload_start..payload_start + payload_len);
 
-            sender.send_packet(&tcp, Some(payload));
+            let segment_size = (payload_len > self.tx_mss).then_some(self.tx_mss as u16);
+            sender.send_packet(&tcp, Some(payload), segment_size);
             self.tx_send = tx_next;
             self.needs_ack = false;
         }
@@ -927,7 +963,7 @@ fn ack(&self, sender: &mut Sender<'_, impl Client>) {
 
         tracing::trace!(?tcp, "tcp ack xmit");
 
-        sender.send_packet(&tcp, None);
+        sender.send_packet(&tcp, None, None);
     }
 
     fn handle_listen_syn(
@@ -0,0 +1,99 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for unit tests.
+
+use crate::ChecksumState;
+use crate::Client;
+use crate::ConsommeState;
+use crate::DhcpOptions;
+use crate::MIN_MTU;
+use pal_async::driver::Driver;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
+use smoltcp::wire::EthernetAddress;
+use smoltcp::wire::EthernetFrame;
+use smoltcp::wire::EthernetProtocol;
+use smoltcp::wire::EthernetRepr;
+use smoltcp::wire::Ipv4Address;
+use smoltcp::wire::Ipv6Address;
+use smoltcp::wire::Ipv6Packet;
+use smoltcp::wire::UdpPacket;
+
+/// Returns the default state, but with fixed DNS servers rather than the
+/// host's.
+pub fn test_state() -> ConsommeState {
+    ConsommeState {
+        gateway_ip: Ipv4Address::new(10, 0, 0, 1),
+        gateway_mac: EthernetAddress([0x52, 0x55, 10, 0, 0, 1]),
+        client_ip: Ipv4Address::new(10, 0, 0, 2),
+        client_mac: EthernetAddress([0x0, 0x0, 0x0, 0x0, 0x1, 0x0]),
+        net_mask: Ipv4Address::new(255, 255, 255, 0),
+        nameservers: vec![Ipv4Address::new(10, 0, 0, 53)],
+        ipv6_prefix: Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 0),
+        gateway_ipv6: Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+        client_ipv6: Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 2),
+        nameservers_ipv6: vec![Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53)],
+        dhcp: DhcpOptions::default(),
+        buffer: Box::new([0; 65535]),
+    }
+}
+
+/// The Ethernet header of a frame sent by the client to the gateway.
+pub fn client_frame(state: &ConsommeState, ethertype: EthernetProtocol) -> EthernetRepr {
+    EthernetRepr {
+        src_addr: state.client_mac,
+        dst_addr: state.gateway_mac,
+        ethertype,
+    }
+}
+
+/// A client that records the frames it receives.
+pub struct TestClient<D> {
+    driver: D,
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl<D: Driver> TestClient<D> {
+    pub fn new(driver: D) -> Self {
+        Self {
+            driver,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Removes and returns the single frame received since the last call.
+    pub fn take_frame(&mut self) -> Vec<u8> {
+        assert_eq!(self.frames.len(), 1, "expected exactly one frame");
+        self.frames.pop().unwrap()
+    }
+}
+
+impl<D: Driver> Client for TestClient<D> {
+    fn driver(&self) -> &dyn Driver {
+        &self.driver
+    }
+
+    fn recv(&mut self, data: &[u8], _checksum: &ChecksumState) {
+        self.frames.push(data.to_vec());
+    }
+
+    fn rx_mtu(&mut self) -> usize {
+        MIN_MTU
+    }
+}
+
+/// Returns the IPv6 packet in an Ethernet frame.
+pub fn ipv6_packet(frame: &[u8]) -> Ipv6Packet<&[u8]> {
+    let eth = EthernetFrame::new_checked(frame).unwrap();
+    assert_eq!(eth.ethertype(), EthernetProtocol::Ipv6);
+    Ipv6Packet::new_checked(&frame[ETHERNET_HEADER_LEN..]).unwrap()
+}
+
+/// Returns the UDP payload of an IPv6 Ethernet frame.
+pub fn udp6_payload(frame: &[u8]) -> Vec<u8> {
+    let ipv6 = ipv6_packet(frame);
+    UdpPacket::new_checked(ipv6.payload())
+        .unwrap()
+        .payload()
+        .to_vec()
+}
@@ -0,0 +1,740 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A read-only TFTP server (RFC 1350) for network boot, with support for the
+//! block size (RFC 2348) and transfer size (RFC 2349) options.
+
+use super::Access;
+use super::Client;
+use super::DropReason;
+use super::SocketAddress;
+use crate::ChecksumState;
+use crate::IpAddresses;
+use crate::MIN_MTU;
+use crate::emit_ip_headers;
+use crate::ip_address;
+use crate::ip_header_len;
+use crate::resolve_boot_path;
+use inspect::Inspect;
+use pal_async::timer::Instant;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
+use smoltcp::wire::EthernetAddress;
+use smoltcp::wire::EthernetRepr;
+use smoltcp::wire::IpProtocol;
+use smoltcp::wire::UDP_HEADER_LEN;
+use smoltcp::wire::UdpPacket;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+pub const TFTP_SERVER: u16 = 69;
+
+const OPCODE_RRQ: u16 = 1;
+const OPCODE_WRQ: u16 = 2;
+const OPCODE_DATA: u16 = 3;
+const OPCODE_ACK: u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+const OPCODE_OACK: u16 = 6;
+
+const ERROR_NOT_DEFINED: u16 = 0;
+const ERROR_FILE_NOT_FOUND: u16 = 1;
+const ERROR_ACCESS_VIOLATION: u16 = 2;
+const ERROR_ILLEGAL_OPERATION: u16 = 4;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const MIN_BLOCK_SIZE: usize = 8;
+const DATA_HEADER_LEN: usize = 4;
+
+/// The first port used as a transfer identifier for the server side of a
+/// transfer.
+const FIRST_TRANSFER_PORT: u16 = 49152;
+
+/// The maximum number of concurrent transfers. Read requests beyond this are
+/// rejected, since each transfer holds an open file.
+const MAX_TRANSFERS: usize = 16;
+
+/// The time after which a transfer with no acknowledgements is abandoned.
+/// Clients give up on a lost transfer without telling the server, so without
+/// this their transfers would never be removed.
+const TRANSFER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub(crate) struct Tftp {
+    transfers: HashMap<SocketAddress, Transfer>,
+    next_port: u16,
+}
+
+impl Tftp {
+    pub fn new() -> Self {
+        Self {
+            transfers: HashMap::new(),
+            next_port: FIRST_TRANSFER_PORT,
+        }
+    }
+
+    /// Removes transfers that have been idle for longer than
+    /// [`TRANSFER_IDLE_TIMEOUT`].
+    fn evict_idle(&mut self, now: Instant) {
+        self.transfers.retain(|addr, transfer| {
+            let active = now < transfer.last_active.saturating_add(TRANSFER_IDLE_TIMEOUT);
+            if !active {
+                tracing::debug!(ip = %addr.ip, port = addr.port, "tftp transfer timed out");
+            }
+            active
+        });
+    }
+
+    fn alloc_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = self.next_port.checked_add(1).unwrap_or(FIRST_TRANSFER_PORT);
+        port
+    }
+}
+
+impl Inspect for Tftp {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        for (addr, transfer) in &self.transfers {
+            resp.field(&format!("{}:{}", addr.ip, addr.port), transfer);
+        }
+    }
+}
+
+#[derive(Inspect)]
+struct Transfer {
+    #[inspect(skip)]
+    file: File,
+    #[inspect(display)]
+    guest_mac: EthernetAddress,
+    server_port: u16,
+    block_size: usize,
+    /// The last block sent, or zero if an option acknowledgement was sent.
+    block: u16,
+    /// The data of the last block sent, for retransmission.
+    #[inspect(with = "|x| x.as_ref().map(|x| x.len())")]
+    data: Option<Vec<u8>>,
+    /// The time of the request or of the last acknowledgement.
+    #[inspect(skip)]
+    last_active: Instant,
+}
+
+impl Transfer {
+    /// Reads the next block from the file.
+    fn read_next(&mut self) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(self.block_size);
+        (&mut self.file)
+            .take(self.block_size as u64)
+            .read_to_end(&mut data)?;
+        self.block = self.block.wrapping_add(1);
+        self.data = Some(data);
+        Ok(())
+    }
+
+    /// Returns true if the last block sent was the final one.
+    fn is_complete(&self) -> bool {
+        self.data
+            .as_ref()
+            .is_some_and(|data| data.len() < self.block_size)
+    }
+}
+
+/// Splits a NUL-terminated string from the front of `buf`.
+fn take_string<'a>(buf: &mut &'a [u8]) -> Option<&'a str> {
+    let n = buf.iter().position(|&c| c == 0)?;
+    let s = std::str::from_utf8(&buf[..n]).ok()?;
+    *buf = &buf[n + 1..];
+    Some(s)
+}
+
+impl<T: Client> Access<'_, T> {
+    /// Handles a TFTP packet sent to the gateway, returning false if it is not
+    /// for the TFTP server.
+    pub(crate) fn handle_tftp(
+        &mut self,
+        frame: &EthernetRepr,
+        addresses: &IpAddresses,
+        udp: &UdpPacket<&[u8]>,
+    ) -> Result<bool, DropReason> {
+        let Some(root) = self.inner.state.dhcp.boot_dir.clone() else {
+            return Ok(false);
+        };
+        let guest_addr = SocketAddress {
+            ip: addresses.src_addr,
+            port: udp.src_port(),
+        };
+        let payload = udp.payload();
+        if payload.len() < 2 {
+            return Err(DropReason::Packet(smoltcp::Error::Truncated));
+        }
+        let opcode = u16::from_be_bytes([payload[0], payload[1]]);
+        let body = &payload[2..];
+
+        if udp.dst_port() == TFTP_SERVER {
+            match opcode {
+                OPCODE_RRQ => self.tftp_read_request(frame, guest_addr, &root, body),
+                OPCODE_WRQ => self.tftp_error(
+                    frame.src_addr,
+                    guest_addr,
+                    TFTP_SERVER,
+                    ERROR_ACCESS_VIOLATION,
+                    "read only",
+                ),
+                _ => self.tftp_error(
+                    frame.src_addr,
+                    guest_addr,
+                    TFTP_SERVER,
+                    ERROR_ILLEGAL_OPERATION,
+                    "illegal operation",
+                ),
+            }
+            return Ok(true);
+        }
+
+        let Some(transfer) = self.inner.tftp.transfers.get_mut(&guest_addr) else {
+            return Ok(false);
+        };
+        if transfer.server_port != udp.dst_port() {
+            return Ok(false);
+        }
+        match opcode {
+            OPCODE_ACK if body.len() >= 2 => {
+                let block = u16::from_be_bytes([body[0], body[1]]);
+                transfer.last_active = Instant::now();
+                if block == transfer.block {
+                    if transfer.is_complete() {
+                        self.inner.tftp.transfers.remove(&guest_addr);
+                        return Ok(true);
+                    }
+                    if let Err(err) = transfer.read_next() {
+                        tracing::warn!(error = &err as &dyn std::error::Error, "tftp read failure");
+                        let port = transfer.server_port;
+                        self.inner.tftp.transfers.remove(&guest_addr);
+                        self.tftp_error(
+                            frame.src_addr,
+                            guest_addr,
+                            port,
+                            ERROR_NOT_DEFINED,
+                            "read failure",
+                        );
+                        return Ok(true);
+                    }
+                }
+                // Send the next block. If this acknowledged the previous
+                // block instead, then the last block was lost, so resend it.
+                self.tftp_send_data(guest_addr);
+            }
+            OPCODE_ERROR => {
+                self.inner.tftp.transfers.remove(&guest_addr);
+            }
+            _ => {
+                let port = transfer.server_port;
+                self.inner.tftp.transfers.remove(&guest_addr);
+                self.tftp_error(
+                    frame.src_addr,
+                    guest_addr,
+                    port,
+                    ERROR_ILLEGAL_OPERATION,
+                    "illegal operation",
+                );
+            }
+        }
+        Ok(true)
+    }
+
+    fn tftp_read_request(
+        &mut self,
+        frame: &EthernetRepr,
+        guest_addr: SocketAddress,
+        root: &Path,
+        mut body: &[u8],
+    ) {
+        let guest_mac = frame.src_addr;
+        let (Some(name), Some(mode)) = (take_string(&mut body), take_string(&mut body)) else {
+            self.tftp_error(
+                guest_mac,
+                guest_addr,
+                TFTP_SERVER,
+                ERROR_ILLEGAL_OPERATION,
+                "malformed request",
+            );
+            return;
+        };
+        if !mode.eq_ignore_ascii_case("octet") {
+            self.tftp_error(
+                guest_mac,
+                guest_addr,
+                TFTP_SERVER,
+                ERROR_ILLEGAL_OPERATION,
+                "only octet mode is supported",
+            );
+            return;
+        }
+
+        let now = Instant::now();
+        let tftp = &mut self.inner.tftp;
+        tftp.evict_idle(now);
+        if tftp.transfers.len() >= MAX_TRANSFERS && !tftp.transfers.contains_key(&guest_addr) {
+            tracing::debug!(name, "too many tftp transfers");
+            self.tftp_error(
+                guest_mac,
+                guest_addr,
+                TFTP_SERVER,
+                ERROR_NOT_DEFINED,
+                "too many transfers",
+            );
+            return;
+        }
+
+        let Some(mut file) = resolve_boot_path(root, name).and_then(|path| {
+            let file = File::open(path).ok()?;
+            file.metadata().ok()?.is_file().then_some(file)
+        }) else {
+            tracing::debug!(name, "tftp file not found");
+            self.tftp_error(
+                guest_mac,
+                guest_addr,
+                TFTP_SERVER,
+                ERROR_FILE_NOT_FOUND,
+                "file not found",
+            );
+            return;
+        };
+
+        // Limit the block size so that each data packet fits in a single
+        // frame.
+        let max_block_size = MIN_MTU
+            - ETHERNET_HEADER_LEN
+            - ip_header_len(guest_addr.ip)
+            - UDP_HEADER_LEN
+            - DATA_HEADER_LEN;
+
+        let mut block_size = DEFAULT_BLOCK_SIZE;
+        let mut oack = Vec::new();
+        while let (Some(option), Some(value)) = (take_string(&mut body), take_string(&mut body)) {
+            let value = if option.eq_ignore_ascii_case("blksize") {
+                let Ok(requested) = value.parse::<usize>() else {
+                    continue;
+                };
+                if requested < MIN_BLOCK_SIZE {
+                    continue;
+                }
+                block_size = requested.min(max_block_size);
+                block_size.to_string()
+            } else if option.eq_ignore_ascii_case("tsize") {
+                let Ok(size) = file.seek(SeekFrom::End(0)) else {
+                    continue;
+                };
+                if file.rewind().is_err() {
+                    continue;
+                }
+                size.to_string()
+            } else {
+                continue;
+            };
+            oack.extend_from_slice(option.as_bytes());
+            oack.push(0);
+            oack.extend_from_slice(value.as_bytes());
+            oack.push(0);
+        }
+
+        let tftp = &mut self.inner.tftp;
+        let server_port = tftp.alloc_port();
+        let mut transfer = Transfer {
+            file,
+            guest_mac,
+            server_port,
+            block_size,
+            block: 0,
+            data: None,
+            last_active: now,
+        };
+
+        if !oack.is_empty() {
+            // The client acknowledges the options with block zero before the
+            // data transfer starts.
+            tftp.transfers.insert(guest_addr, transfer);
+            self.tftp_send(guest_mac, guest_addr, server_port, OPCODE_OACK, &oack);
+            return;
+        }
+
+        if let Err(err) = transfer.read_next() {
+            tracing::warn!(error = &err as &dyn std::error::Error, "tftp read failure");
+            self.tftp_error(
+                guest_mac,
+                guest_addr,
+                server_port,
+                ERROR_NOT_DEFINED,
+                "read failure",
+            );
+            return;
+        }
+        tftp.transfers.insert(guest_addr, transfer);
+        self.tftp_send_data(guest_addr);
+    }
+
+    fn tftp_send_data(&mut self, guest_addr: SocketAddress) {
+        let transfer = &self.inner.tftp.transfers[&guest_addr];
+        let guest_mac = transfer.guest_mac;
+        let server_port = transfer.server_port;
+        let mut body = transfer.block.to_be_bytes().to_vec();
+        if let Some(data) = &transfer.data {
+            body.extend_from_slice(data);
+        }
+        self.tftp_send(guest_mac, guest_addr, server_port, OPCODE_DATA, &body);
+    }
+
+    fn tftp_error(
+        &mut self,
+        guest_mac: EthernetAddress,
+        guest_addr: SocketAddress,
+        server_port: u16,
+        code: u16,
+        message: &str,
+    ) {
+        let mut body = code.to_be_bytes().to_vec();
+        body.extend_from_slice(message.as_bytes());
+        body.push(0);
+        self.tftp_send(guest_mac, guest_addr, server_port, OPCODE_ERROR, &body);
+    }
+
+    fn tftp_send(
+        &mut self,
+        guest_mac: EthernetAddress,
+        guest_addr: SocketAddress,
+        server_port: u16,
+        opcode: u16,
+        body: &[u8],
+    ) {
+        let state = &mut self.inner.state;
+        let src_ip = match guest_addr.ip {
+            IpAddr::V4(_) => IpAddr::V4(state.gateway_ip.into()),
+            IpAddr::V6(_) => IpAddr::V6(state.gateway_ipv6.into()),
+        };
+        let payload_len = UDP_HEADER_LEN + 2 + body.len();
+        let offset = emit_ip_headers(
+            &mut state.buffer[..],
+            state.gateway_mac,
+            guest_mac,
+            src_ip,
+            guest_addr.ip,
+            IpProtocol::Udp,
+            payload_len,
+        );
+        let len = offset + payload_len;
+        let mut udp = UdpPacket::new_unchecked(&mut state.buffer[offset..len]);
+        udp.set_src_port(server_port);
+        udp.set_dst_port(guest_addr.port);
+        udp.set_len(payload_len as u16);
+        let payload = udp.payload_mut();
+        payload[..2].copy_from_slice(&opcode.to_be_bytes());
+        payload[2..].copy_from_slice(body);
+        udp.fill_checksum(&ip_address(src_ip), &ip_address(guest_addr.ip));
+        let checksum = if guest_addr.ip.is_ipv4() {
+            &ChecksumState::UDP4
+        } else {
+            &ChecksumState::UDP6
+        };
+        self.client.recv(&state.buffer[..len], checksum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Consomme;
+    use crate::test_helpers::TestClient;
+    use crate::test_helpers::client_frame;
+    use crate::test_helpers::test_state;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use smoltcp::wire::EthernetProtocol;
+    use smoltcp::wire::IPV4_HEADER_LEN;
+    use smoltcp::wire::Ipv4Packet;
+
+    const FILE_LEN: usize = 1000;
+
+    fn file_data() -> Vec<u8> {
+        (0..FILE_LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn request(opcode: u16, strings: &[&str]) -> Vec<u8> {
+        let mut msg = opcode.to_be_bytes().to_vec();
+        for s in strings {
+            msg.extend_from_slice(s.as_bytes());
+            msg.push(0);
+        }
+        msg
+    }
+
+    fn ack(block: u16) -> Vec<u8> {
+        [OPCODE_ACK.to_be_bytes(), block.to_be_bytes()].concat()
+    }
+
+    /// Returns the error code of an error packet.
+    fn error_code(payload: &[u8]) -> u16 {
+        assert_eq!(u16::from_be_bytes([payload[0], payload[1]]), OPCODE_ERROR);
+        u16::from_be_bytes([payload[2], payload[3]])
+    }
+
+    /// Returns the block number and data of a data packet.
+    fn data(payload: &[u8]) -> (u16, &[u8]) {
+        assert_eq!(u16::from_be_bytes([payload[0], payload[1]]), OPCODE_DATA);
+        (u16::from_be_bytes([payload[2], payload[3]]), &payload[4..])
+    }
+
+    struct TestServer {
+        consomme: Consomme,
+        client: TestClient<DefaultDriver>,
+        _dir: tempfile::TempDir,
+    }
+
+    impl TestServer {
+        /// Returns a server for a boot directory containing `boot.efi`.
+        fn new(driver: &DefaultDriver) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("boot.efi"), file_data()).unwrap();
+            let mut state = test_state();
+            state.dhcp.boot_dir = Some(dir.path().to_owned());
+            Self {
+                consomme: Consomme::new_with_state(state),
+                client: TestClient::new(driver.clone()),
+                _dir: dir,
+            }
+        }
+
+        /// Sends `payload` from the client's `port` to the server's
+        /// `server_port`.
+        fn send(
+            &mut self,
+            port: u16,
+            server_port: u16,
+            payload: &[u8],
+        ) -> Result<bool, DropReason> {
+            let mut buf = vec![0; UDP_HEADER_LEN + payload.len()];
+            {
+                let mut udp = UdpPacket::new_unchecked(&mut buf[..]);
+                udp.set_src_port(port);
+                udp.set_dst_port(server_port);
+                udp.set_len((UDP_HEADER_LEN + payload.len()) as u16);
+                udp.payload_mut().copy_from_slice(payload);
+            }
+            let state = &self.consomme.state;
+            let frame = client_frame(state, EthernetProtocol::Ipv4);
+            let addresses = IpAddresses {
+                src_addr: IpAddr::V4(state.client_ip.into()),
+                dst_addr: IpAddr::V4(state.gateway_ip.into()),
+            };
+            self.consomme.access(&mut self.client).handle_tftp(
+                &frame,
+                &addresses,
+                &UdpPacket::new_unchecked(&buf[..]),
+            )
+        }
+
+        /// Returns the source port and payload of the single packet sent to
+        /// the client.
+        fn reply(&mut self) -> (u16, Vec<u8>) {
+            let frame = self.client.take_frame();
+            let ipv4 = Ipv4Packet::new_checked(&frame[ETHERNET_HEADER_LEN..]).unwrap();
+            let udp = UdpPacket::new_checked(ipv4.payload()).unwrap();
+            (udp.src_port(), udp.payload().to_vec())
+        }
+
+        /// Sends a read request and returns the reply.
+        fn read_request(&mut self, port: u16, strings: &[&str]) -> (u16, Vec<u8>) {
+            assert!(
+                self.send(port, TFTP_SERVER, &request(OPCODE_RRQ, strings))
+                    .unwrap()
+            );
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn test_take_string() {
+        let mut buf: &[u8] = b"boot.efi\0octet\0rest";
+        assert_eq!(take_string(&mut buf), Some("boot.efi"));
+        assert_eq!(take_string(&mut buf), Some("octet"));
+        // Unterminated strings are not returned.
+        assert_eq!(take_string(&mut buf), None);
+        assert_eq!(buf, b"rest");
+
+        let mut buf: &[u8] = b"\xff\xfe\0";
+        assert_eq!(take_string(&mut buf), None);
+    }
+
+    #[async_test]
+    async fn test_read_request(driver: DefaultDriver) {
+        let mut server = TestServer::new(&driver);
+        let file = file_data();
+        let (port, reply) = server.read_request(1000, &["boot.efi", "octet"]);
+        assert!(port >= FIRST_TRANSFER_PORT);
+        assert_eq!(data(&reply), (1, &file[..DEFAULT_BLOCK_SIZE]));
+
+        // A duplicate acknowledgement of the previous block resends the last
+        // block.
+        assert!(server.send(1000, port, &ack(0)).unwrap());
+        assert_eq!(data(&server.reply().1), (1, &file[..DEFAULT_BLOCK_SIZE]));
+
+        assert!(server.send(1000, port, &ack(1)).unwrap());
+        let (_, reply) = server.reply();
+        assert_eq!(data(&reply), (2, &file[DEFAULT_BLOCK_SIZE..]));
+
+        // Acknowledging the short final block ends the transfer.
+        assert!(server.send(1000, port, &ack(2)).unwrap());
+        assert!(server.client.frames.is_empty());
+        assert!(server.consomme.tftp.transfers.is_empty());
+    }
+
+    #[async_test]
+    async fn test_read_request_options(driver: DefaultDriver) {
+        let mut server = TestServer::new(&driver);
+        let (port, reply) = server.read_request(
+            1000,
+            &[
+                "boot.efi", "OCTET", "blksize", "1024", "unknown", "1", "tsize", "0",
+            ],
+        );
+        assert_eq!(
+            reply,
+            request(OPCODE_OACK, &["blksize", "1024", "tsize", "1000"])
+        );
+
+        // The client acknowledges the options with block zero.
+        assert!(server.send(1000, port, &ack(0)).unwrap());
+        let (_, reply) = server.reply();
+        assert_eq!(data(&reply), (1, &file_data()[..]));
+        assert!(server.send(1000, port, &ack(1)).unwrap());
+        assert!(server.consomme.tftp.transfers.is_empty());
+    }
+
+    #[async_test]
+    async fn test_block_size_limits(driver: DefaultDriver) {
+        let mut server = TestServer::new(&driver);
+        let max =
+            MIN_MTU - ETHERNET_HEADER_LEN - IPV4_HEADER_LEN - UDP_HEADER_LEN - DATA_HEADER_LEN;
+        let (_, reply) = server.read_request(1000, &["boot.efi", "octet", "blksize", "65464"]);
+        assert_eq!(reply, request(OPCODE_OACK, &["blksize", &max.to_string()]));
+
+        // Invalid block sizes are ignored.
+        for blksize in ["4", "big"] {
+            let (_, reply) = server.read_request(1001, &["boot.efi", "octet", "blksize", blksize]);
+            assert_eq!(data(&reply).1.len(), DEFAULT_BLOCK_SIZE);
+        }
+    }
+
+    #[async_test]
+    async fn test_bad_requests(driver: DefaultDriver) {
+        let mut server = TestServer::new(&driver);
+        for (strings, code) in [
+            (&["boot.efi"][..], ERROR_ILLEGAL_OPERATION),
+            (&["boot.efi", "netascii"][..], ERROR_ILLEGAL_OPERATION),
+            (&["missing.efi", "octet"][..], ERROR_FILE_NOT_FOUND),
+            (&["../boot.efi", "octet"][..], ERROR_FILE_NOT_FOUND),
+            (&["", "octet"][..], ERROR_FILE_NOT_FOUND),
+        ] {
+            let (port, reply) = server.read_request(1000, strings);
+            assert_eq!(port, TFTP_SERVER);
+            assert_eq!(error_code(&reply), code, "{strings:?}");
+        }
+
+        assert!(
+            server
+                .send(
+                    1000,
+                    TFTP_SERVER,
+                    &request(OPCODE_WRQ, &["boot.efi", "octet"])
+                )
+                .unwrap()
+        );
+        assert_eq!(error_code(&server.reply().1), ERROR_ACCESS_VIOLATION);
+
+        assert!(server.send(1000, TFTP_SERVER, &ack(1)).unwrap());
+        assert_eq!(error_code(&server.reply().1), ERROR_ILLEGAL_OPERATION);
+
+        assert!(matches!(
+            server.send(1000, TFTP_SERVER, &[OPCODE_RRQ as u8]),
+            Err(DropReason::Packet(smoltcp::Error::Truncated))
+        ));
+        assert!(server.client.frames.is_empty());
+        assert!(server.consomme.tftp.transfers.is_empty());
+    }
+
+    #[async_test]
+    async fn test_transfer_ports(driver: DefaultDriver) {
+        let mut server = TestServer::new(&driver);
+        let (port, _) = server.read_request(1000, &["boot.efi", "octet"]);
+
+        // Packets for other ports are not for the TFTP server.
+        assert!(!server.send(1000, port + 1, &ack(1)).unwrap());
+        assert!(!server.send(1001, port, &ack(1)).unwrap());
+        assert!(server.client.frames.is_empty());
+
+        // An unexpected packet ends the transfer.
+        assert!(
+            server
+                .send(1000, port, &request(OPCODE_RRQ, &["boot.efi", "octet"]))
+                .unwrap()
+        );
+        let (error_port, reply) = server.reply();
+        assert_eq!(error_port, port);
+        assert_eq!(error_code(&reply), ERROR_ILLEGAL_OPERATION);
+        assert!(server.consomme.tftp.transfers.is_empty());
+
+        // As does an error from the client.
+        let (port, _) = server.read_request(1000, &["boot.efi", "octet"]);
+        let mut error = OPCODE_ERROR.to_be_bytes().to_vec();
+        error.extend_from_slice(&[0, 0, 0]);
+        assert!(server.send(1000, port, &error).unwrap());
+        assert!(server.client.frames.is_empty());
+        assert!(server.consomme.tftp.transfers.is_empty());
+    }
+
+    #[async_test]
+    async fn test_transfer_limit(driver: DefaultDriver) {
+        let mut server = TestServer::new(&driver);
+        for i in 0..MAX_TRANSFERS as u16 {
+            let (port, _) = server.read_request(2000 + i, &["boot.efi", "octet"]);
+            assert_ne!(port, TFTP_SERVER);
+        }
+
+        // New transfers are rejected at the limit...
+        let (port, reply) = server.read_request(3000, &["boot.efi", "octet"]);
+        assert_eq!(port, TFTP_SERVER);
+        assert_eq!(error_code(&reply), ERROR_NOT_DEFINED);
+        assert_eq!(server.consomme.tftp.transfers.len(), MAX_TRANSFERS);
+
+        // ...but a client can restart its own transfer.
+        let (port, reply) = server.read_request(2000, &["boot.efi", "octet"]);
+        assert_ne!(port, TFTP_SERVER);
+        assert_eq!(data(&reply).0, 1);
+        assert_eq!(server.consomme.tftp.transfers.len(), MAX_TRANSFERS);
+
+        // Idle transfers are evicted, but active ones are kept.
+        let later = Instant::now() + TRANSFER_IDLE_TIMEOUT;
+        let active = SocketAddress {
+            ip: IpAddr::V4(server.consomme.state.client_ip.into()),
+            port: 2001,
+        };
+        server
+            .consomme
+            .tftp
+            .transfers
+            .get_mut(&active)
+            .unwrap()
+            .last_active = later;
+        server.consomme.tftp.evict_idle(later);
+        assert_eq!(
+            server.consomme.tftp.transfers.keys().collect::<Vec<_>>(),
+            [&active]
+        );
+
+        let (port, reply) = server.read_request(3000, &["boot.efi", "octet"]);
+        assert_ne!(port, TFTP_SERVER);
+        assert_eq!(data(&reply).0, 1);
+    }
+}
@@ -7,24 +7,22 @@
 use super::DropReason;
 use super::SocketAddress;
 use super::dhcp::DHCP_SERVER;
+use super::dhcpv6::DHCPV6_SERVER;
 use crate::ChecksumState;
-use crate::Ipv4Addresses;
+use crate::IpAddresses;
+use crate::emit_ip_headers;
+use crate::ip_address;
+use crate::ip_header_len;
 use inspect::Inspect;
 use inspect::InspectMut;
 use inspect_counters::Counter;
 use pal_async::interest::InterestSlot;
 use pal_async::interest::PollEvents;
 use pal_async::socket::PolledSocket;
-use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::wire::ETHERNET_HEADER_LEN;
 use smoltcp::wire::EthernetAddress;
-use smoltcp::wire::EthernetFrame;
-use smoltcp::wire::EthernetProtocol;
 use smoltcp::wire::EthernetRepr;
-use smoltcp::wire::IPV4_HEADER_LEN;
 use smoltcp::wire::IpProtocol;
-use smoltcp::wire::Ipv4Packet;
-use smoltcp::wire::Ipv4Repr;
 use smoltcp::wire::UDP_HEADER_LEN;
 use smoltcp::wire::UdpPacket;
 use smoltcp::wire::UdpRepr;
@@ -33,6 +31,7 @@
 use std::io::ErrorKind;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::UdpSocket;
 use std::task::Context;
 use std::task::Poll;
@@ -89,7 +88,7 @@ fn poll_conn(
             return false;
         }
 
-        let mut eth = EthernetFrame::new_unchecked(&mut state.buffer);
+        let header_len = ETHERNET_HEADER_LEN + ip_header_len(dst_addr.ip) + UDP_HEADER_LEN;
         loop {
             // Receive UDP packets while there are receive buffers available. This
             // means we won't drop UDP packets at this level--instead, we only drop
@@ -103,37 +102,31 @@ fn poll_conn(
                 cx,
                 InterestSlot::Read,
                 PollEvents::IN,
-                |socket| {
-                    socket
-                        .get()
-                        .recv_from(&mut eth.payload_mut()[IPV4_HEADER_LEN + UDP_HEADER_LEN..])
-                },
+                |socket| socket.get().recv_from(&mut state.buffer[header_len..]),
             ) {
                 Poll::Ready(Ok((n, src_addr))) => {
-                    let src_ip = if let IpAddr::V4(ip) = src_addr.ip() {
-                        ip
-                    } else {
-                        unreachable!()
-                    };
-                    eth.set_ethertype(EthernetProtocol::Ipv4);
-                    eth.set_src_addr(state.gateway_mac);
-                    eth.set_dst_addr(self.guest_mac);
-                    let mut ipv4 = Ipv4Packet::new_unchecked(eth.payload_mut());
-                    Ipv4Repr {
-                        src_addr: src_ip.into(),
-                        dst_addr: dst_addr.ip,
-                        protocol: IpProtocol::Udp,
-                        payload_len: UDP_HEADER_LEN + n,
-                        hop_limit: 64,
-                    }
-                    .emit(&mut ipv4, &ChecksumCapabilities::default());
-                    let mut udp = UdpPacket::new_unchecked(ipv4.payload_mut());
+                    let payload_len = UDP_HEADER_LEN + n;
+                    let offset = emit_ip_headers(
+                        &mut state.buffer[..],
+                        state.gateway_mac,
+                        self.guest_mac,
+                        src_addr.ip(),
+                        dst_addr.ip,
+                        IpProtocol::Udp,
+                        payload_len,
+                    );
+                    let len = offset + payload_len;
+                    let mut udp = UdpPacket::new_unchecked(&mut state.buffer[offset..len]);
                     udp.set_src_port(src_addr.port());
                     udp.set_dst_port(dst_addr.port);
-                    udp.set_len((UDP_HEADER_LEN + n) as u16);
-                    udp.fill_checksum(&src_ip.into(), &dst_addr.ip.into());
-                    let len = ETHERNET_HEADER_LEN + ipv4.total_len() as usize;
-                    client.recv(&eth.as_ref()[..len], &ChecksumState::UDP4);
+                    udp.set_len(payload_len as u16);
+                    udp.fill_checksum(&ip_address(src_addr.ip()), &ip_address(dst_addr.ip));
+                    let checksum = if dst_addr.ip.is_ipv4() {
+                        &ChecksumState::UDP4
+                    } else {
+                        &ChecksumState::UDP6
+                    };
+                    client.recv(&state.buffer[..len], checksum);
                     self.stats.rx_packets.increment();
                 }
                 Poll::Ready(Err(err)) => {
@@ -175,20 +168,34 @@ pub(crate) fn refresh_udp_driver(&mut self) {
     pub(crate) fn handle_udp(
         &mut self,
         frame: &EthernetRepr,
-        addresses: &Ipv4Addresses,
+        addresses: &IpAddresses,
         payload: &[u8],
         checksum: &ChecksumState,
     ) -> Result<(), DropReason> {
         let udp_packet = UdpPacket::new_checked(payload)?;
         let udp = UdpRepr::parse(
             &udp_packet,
-            &addresses.src_addr.into(),
-            &addresses.dst_addr.into(),
+            &ip_address(addresses.src_addr),
+            &ip_address(addresses.dst_addr),
             &checksum.caps(),
         )?;
 
-        if addresses.dst_addr == self.inner.state.gateway_ip || addresses.dst_addr.is_broadcast() {
-            if self.handle_gateway_udp(&udp_packet)? {
+        let to_gateway = match addresses.dst_addr {
+            IpAddr::V4(addr) => {
+                addr == Ipv4Addr::from(self.inner.state.gateway_ip) || addr.is_broadcast()
+            }
+            IpAddr::V6(addr) => {
+                addr == Ipv6Addr::from(self.inner.state.gateway_ipv6) || addr.is_multicast()
+            }
+        };
+        if to_gateway && self.handle_gateway_udp(frame, addresses, &udp_packet)? {
+            return Ok(());
+        }
+
+        // Link-local and multicast IPv6 traffic (such as mDNS) has no
+        // meaning on the host's network.
+        if let IpAddr::V6(addr) = addresses.dst_addr {
+            if addr.is_multicast() || addr.is_unicast_link_local() {
                 return Ok(());
             }
         }
@@ -199,10 +206,13 @@ pub(crate) fn handle_udp(
         };
 
         let conn = self.get_or_insert(guest_addr, None, Some(frame.src_addr))?;
-        match conn.socket.as_mut().unwrap().get().send_to(
-            udp_packet.payload(),
-            (Ipv4Addr::from(addresses.dst_addr), udp.dst_port),
-        ) {
+        match conn
+            .socket
+            .as_mut()
+            .unwrap()
+            .get()
+            .send_to(udp_packet.payload(), (addresses.dst_addr, udp.dst_port))
+        {
             Ok(_) => {
                 conn.stats.tx_packets.increment();
                 Ok(())
@@ -228,8 +238,12 @@ fn get_or_insert(
         match entry {
             hash_map::Entry::Occupied(conn) => Ok(conn.into_mut()),
             hash_map::Entry::Vacant(e) => {
-                let socket = UdpSocket::bind((host_addr.unwrap_or(Ipv4Addr::UNSPECIFIED), 0))
-                    .map_err(DropReason::Io)?;
+                let bind_addr = match (host_addr, guest_addr.ip) {
+                    (Some(addr), _) => addr.into(),
+                    (None, IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    (None, IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                };
+                let socket = UdpSocket::bind((bind_addr, 0)).map_err(DropReason::Io)?;
                 let socket =
                     PolledSocket::new(self.client.driver(), socket).map_err(DropReason::Io)?;
                 let conn = UdpConnection {
@@ -243,14 +257,23 @@ fn get_or_insert(
         }
     }
 
-    fn handle_gateway_udp(&mut self, udp: &UdpPacket<&[u8]>) -> Result<bool, DropReason> {
+    fn handle_gateway_udp(
+        &mut self,
+        frame: &EthernetRepr,
+        addresses: &IpAddresses,
+        udp: &UdpPacket<&[u8]>,
+    ) -> Result<bool, DropReason> {
         let payload = udp.payload();
-        match udp.dst_port() {
-            DHCP_SERVER => {
+        match (addresses.src_addr, udp.dst_port()) {
+            (IpAddr::V4(_), DHCP_SERVER) => {
                 self.handle_dhcp(payload)?;
                 Ok(true)
             }
-            _ => Ok(false),
+            (IpAddr::V6(src_addr), DHCPV6_SERVER) => {
+                self.handle_dhcpv6(frame, src_addr, udp.src_port(), payload)?;
+                Ok(true)
+            }
+            _ => self.handle_tftp(frame, addresses, udp),
         }
     }
 
@@ -15,11 +15,13 @@
 use inspect::InspectMut;
 use inspect_counters::Counter;
 use net_backend::BufferAccess;
+use net_backend::L3Protocol;
 use net_backend::L4Protocol;
 use net_backend::QueueConfig;
 use net_backend::RssConfig;
 use net_backend::RxChecksumState;
 use net_backend::RxId;
+use net_backend::RxLro;
 use net_backend::RxMetadata;
 use net_backend::TxError;
 use net_backend::TxId;
@@ -117,6 +119,10 @@ fn tx_offload_support(&self) -> TxOffloadSupport {
             tso: true,
         }
     }
+
+    fn rx_lro_support(&self) -> bool {
+        true
+    }
 }
 
 pub struct ConsommeQueue {
@@ -195,6 +201,8 @@ fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
                     consomme::DropReason::UnsupportedEthertype(_)
                     | consomme::DropReason::UnsupportedIpProtocol(_)
                     | consomme::DropReason::UnsupportedDhcp(_)
+                    | consomme::DropReason::UnsupportedDhcpv6(_)
+                    | consomme::DropReason::UnsupportedIcmpv6(_)
                     | consomme::DropReason::UnsupportedArp => self.stats.tx_unknown.increment(),
                     consomme::DropReason::Packet(_)
                     | consomme::DropReason::Ipv4Checksum
@@ -305,6 +313,9 @@ fn recv(&mut self, data: &[u8], checksum: &ChecksumState) {
                     } else {
                         L4Protocol::Unknown
                     },
+                    lro: checksum
+                        .tso
+                        .and_then(|segment_size| lro_metadata(data, segment_size)),
                 },
                 data,
             );
@@ -322,4 +333,30 @@ fn rx_mtu(&mut self) -> usize {
             0
         }
     }
+
+    fn rx_lro(&mut self) -> bool {
+        self.state.pool.lro_enabled()
+    }
+}
+
+/// Computes the LRO metadata for a coalesced TCP packet from consomme, which
+/// always has an untagged Ethernet header.
+fn lro_metadata(data: &[u8], segment_size: u16) -> Option<RxLro> {
+    const ETHERNET_HEADER_LEN: usize = 14;
+    let (l3_protocol, ip_header_len) = match u16::from_be_bytes(data.get(12..14)?.try_into().ok()?)
+    {
+        0x0800 => (
+            L3Protocol::Ipv4,
+            (*data.get(ETHERNET_HEADER_LEN)? & 0xf) as usize * 4,
+        ),
+        0x86dd => (L3Protocol::Ipv6, 40),
+        _ => return None,
+    };
+    let tcp_start = ETHERNET_HEADER_LEN + ip_header_len;
+    let tcp_header_len = (*data.get(tcp_start + 12)? >> 4) as usize * 4;
+    Some(RxLro {
+        l3_protocol,
+        header_len: (tcp_start + tcp_header_len) as u16,
+        segment_size,
+    })
 }
@@ -3,6 +3,8 @@
 
 use crate::ConsommeEndpoint;
 use consomme::ConsommeState;
+use consomme::DhcpOptions;
+use consomme::StaticLease;
 use net_backend::resolve::ResolveEndpointParams;
 use net_backend::resolve::ResolvedEndpoint;
 use net_backend_resources::consomme::ConsommeHandle;
@@ -42,6 +44,24 @@ fn resolve(
                 .set_cidr(cidr)
                 .map_err(ResolveConsommeError::InvalidCidr)?;
         }
+        let dhcp = resource.dhcp;
+        if !dhcp.dns_servers.is_empty() {
+            state.nameservers = dhcp.dns_servers.iter().map(|&addr| addr.into()).collect();
+        }
+        state.dhcp = DhcpOptions {
+            search_domains: dhcp.search_domains,
+            static_leases: dhcp
+                .static_leases
+                .iter()
+                .map(|lease| StaticLease {
+                    mac: lease.mac.to_bytes(),
+                    ip: lease.ip,
+                })
+                .collect(),
+            tftp_server: dhcp.tftp_server,
+            boot_file: dhcp.boot_file,
+            boot_dir: dhcp.boot_dir.map(Into::into),
+        };
         let endpoint = ConsommeEndpoint::new_with_state(state);
         Ok(endpoint.into())
     }
@@ -0,0 +1,256 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A network endpoint that connects the NICs of multiple VMs to a shared hub.
+//!
+//! Like an Ethernet hub, the hub forwards every frame sent on one of its ports
+//! to all the others, leaving it to the guests to filter by MAC address. There
+//! is no DHCP server or gateway, so guests must be assigned static addresses.
+//! This is meant for testing guest-to-guest networking, not for production
+//! use.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use async_trait::async_trait;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use net_backend::BufferAccess;
+use net_backend::Endpoint;
+use net_backend::Queue;
+use net_backend::QueueConfig;
+use net_backend::RssConfig;
+use net_backend::RxId;
+use net_backend::RxMetadata;
+use net_backend::TxError;
+use net_backend::TxId;
+use net_backend::TxSegment;
+use net_backend::linearize;
+use net_backend_resources::hub::HubPortHandle;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+/// A hub that forwards the frames sent on each of its ports to all the others.
+///
+/// The hub stops forwarding when it is dropped.
+pub struct Hub {
+    new_ports: mesh::Sender<HubPort>,
+    _task: Task<()>,
+}
+
+/// The hub's end of a port.
+struct HubPort {
+    send: mesh::Sender<Vec<u8>>,
+    recv: mesh::Receiver<Vec<u8>>,
+}
+
+impl Hub {
+    /// Returns a new hub, which forwards frames on a task spawned with
+    /// `spawn`.
+    pub fn new(spawn: impl Spawn) -> Self {
+        let (new_ports, recv) = mesh::channel();
+        let task = spawn.spawn("net-hub", run_hub(recv));
+        Self {
+            new_ports,
+            _task: task,
+        }
+    }
+
+    /// Adds a port to the hub, returning the handle for a NIC's endpoint.
+    pub fn new_port(&self) -> HubPortHandle {
+        let (send, hub_recv) = mesh::channel();
+        let (hub_send, recv) = mesh::channel();
+        self.new_ports.send(HubPort {
+            send: hub_send,
+            recv: hub_recv,
+        });
+        HubPortHandle { send, recv }
+    }
+}
+
+enum HubEvent {
+    NewPort(Option<HubPort>),
+    Frame(usize, Option<Vec<u8>>),
+}
+
+async fn run_hub(mut new_ports: mesh::Receiver<HubPort>) {
+    let mut ports = Vec::<HubPort>::new();
+    loop {
+        let event = std::future::poll_fn(|cx| {
+            if let Poll::Ready(port) = new_ports.poll_recv(cx) {
+                return Poll::Ready(HubEvent::NewPort(port.ok()));
+            }
+            for (i, port) in ports.iter_mut().enumerate() {
+                if let Poll::Ready(frame) = port.recv.poll_recv(cx) {
+                    return Poll::Ready(HubEvent::Frame(i, frame.ok()));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+
+        match event {
+            HubEvent::NewPort(Some(port)) => ports.push(port),
+            HubEvent::NewPort(None) => break,
+            HubEvent::Frame(i, Some(frame)) => {
+                for (j, port) in ports.iter().enumerate() {
+                    if j != i {
+                        port.send.send(frame.clone());
+                    }
+                }
+            }
+            // The endpoint went away.
+            HubEvent::Frame(i, None) => {
+                ports.swap_remove(i);
+            }
+        }
+    }
+}
+
+/// An endpoint connected to a port on a [`Hub`].
+pub struct HubEndpoint {
+    send: mesh::Sender<Vec<u8>>,
+    recv: Arc<Mutex<mesh::Receiver<Vec<u8>>>>,
+}
+
+impl HubEndpoint {
+    /// Returns a new endpoint connected to the hub port `handle`.
+    pub fn new(handle: HubPortHandle) -> Self {
+        Self {
+            send: handle.send,
+            recv: Arc::new(Mutex::new(handle.recv)),
+        }
+    }
+}
+
+impl InspectMut for HubEndpoint {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.ignore();
+    }
+}
+
+#[async_trait]
+impl Endpoint for HubEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        "hub"
+    }
+
+    async fn get_queues(
+        &mut self,
+        config: Vec<QueueConfig<'_>>,
+        _rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn Queue>>,
+    ) -> anyhow::Result<()> {
+        // Only a single queue is supported.
+        let [config] = <[_; 1]>::try_from(config)
+            .map_err(|_| anyhow::anyhow!("hub endpoint supports only one queue"))?;
+        queues.push(Box::new(HubQueue {
+            pool: config.pool,
+            send: self.send.clone(),
+            recv: self.recv.clone(),
+            rx_avail: config.initial_rx.iter().copied().collect(),
+            rx_done: VecDeque::new(),
+            stats: Default::default(),
+        }));
+        Ok(())
+    }
+
+    async fn stop(&mut self) {}
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Inspect, Default)]
+struct Stats {
+    rx_packets: Counter,
+    rx_dropped: Counter,
+    tx_packets: Counter,
+}
+
+#[derive(InspectMut)]
+struct HubQueue {
+    #[inspect(skip)]
+    pool: Box<dyn BufferAccess>,
+    #[inspect(skip)]
+    send: mesh::Sender<Vec<u8>>,
+    #[inspect(skip)]
+    recv: Arc<Mutex<mesh::Receiver<Vec<u8>>>>,
+    #[inspect(with = "VecDeque::len")]
+    rx_avail: VecDeque<RxId>,
+    #[inspect(with = "VecDeque::len")]
+    rx_done: VecDeque<RxId>,
+    stats: Stats,
+}
+
+impl Queue for HubQueue {
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut recv = self.recv.lock();
+        while let Some(&id) = self.rx_avail.front() {
+            // A closed channel means the hub is gone, so nothing more will be
+            // received.
+            let Poll::Ready(Ok(frame)) = recv.poll_recv(cx) else {
+                break;
+            };
+            if frame.len() > self.pool.capacity(id) as usize {
+                self.stats.rx_dropped.increment();
+                continue;
+            }
+            self.pool.write_packet(
+                id,
+                &RxMetadata {
+                    offset: 0,
+                    len: frame.len(),
+                    ..Default::default()
+                },
+                &frame,
+            );
+            self.rx_avail.pop_front();
+            self.rx_done.push_back(id);
+            self.stats.rx_packets.increment();
+        }
+        if self.rx_done.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    fn rx_avail(&mut self, done: &[RxId]) {
+        self.rx_avail.extend(done);
+    }
+
+    fn rx_poll(&mut self, packets: &mut [RxId]) -> anyhow::Result<usize> {
+        let n = packets.len().min(self.rx_done.len());
+        for (d, s) in packets.iter_mut().zip(self.rx_done.drain(..n)) {
+            *d = s;
+        }
+        Ok(n)
+    }
+
+    fn tx_avail(&mut self, mut segments: &[TxSegment]) -> anyhow::Result<(bool, usize)> {
+        let n = segments.len();
+        while !segments.is_empty() {
+            let frame = linearize(self.pool.as_ref(), &mut segments)?;
+            self.send.send(frame);
+            self.stats.tx_packets.increment();
+        }
+        Ok((true, n))
+    }
+
+    fn tx_poll(&mut self, _done: &mut [TxId]) -> Result<usize, TxError> {
+        Ok(0)
+    }
+
+    fn buffer_access(&mut self) -> Option<&mut dyn BufferAccess> {
+        Some(self.pool.as_mut())
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::HubEndpoint;
+use net_backend::resolve::ResolveEndpointParams;
+use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::hub::HubPortHandle;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::NetEndpointHandleKind;
+
+/// A resolver for [`HubEndpoint`].
+pub struct HubResolver;
+
+declare_static_resolver! {
+    HubResolver,
+    (NetEndpointHandleKind, HubPortHandle),
+}
+
+impl ResolveResource<NetEndpointHandleKind, HubPortHandle> for HubResolver {
+    type Output = ResolvedEndpoint;
+    type Error = std::convert::Infallible;
+
+    fn resolve(
+        &self,
+        resource: HubPortHandle,
+        _input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(HubEndpoint::new(resource).into())
+    }
+}
@@ -861,6 +861,7 @@ fn rx_poll(&mut self, packets: &mut [RxId]) -> anyhow::Result<usize> {
                                 ip_checksum,
                                 l4_checksum,
                                 l4_protocol,
+                                lro: None,
                             },
                         );
                         if rx.bounced_len_with_padding > 0 {
@@ -1444,6 +1445,7 @@ mod tests {
     use mana_driver::mana::ManaDevice;
     use net_backend::Endpoint;
     use net_backend::QueueConfig;
+    use net_backend::RssConfig;
     use net_backend::RxId;
     use net_backend::TxId;
     use net_backend::TxSegment;
@@ -1591,6 +1593,121 @@ async fn test_endpoint(
         endpoint.stop().await;
     }
 
+    /// Opens the second vport of a two vport emulator with multiple queues
+    /// and RSS enabled, and ensures that each queue can send and receive.
+    #[async_test]
+    async fn test_multi_vport_rss(driver: DefaultDriver) {
+        let pages = 256; // 1MB
+        let mem = DeviceTestMemory::new(pages * 2, true, "test_multi_vport_rss");
+        let payload_mem = mem.payload_mem();
+
+        let mut msi_set = MsiInterruptSet::new();
+        let device = gdma::GdmaDevice::new(
+            &VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone())),
+            mem.guest_memory(),
+            &mut msi_set,
+            vec![
+                VportConfig {
+                    mac_address: [1, 2, 3, 4, 5, 6].into(),
+                    endpoint: Box::new(LoopbackEndpoint::new()),
+                },
+                VportConfig {
+                    mac_address: [1, 2, 3, 4, 5, 7].into(),
+                    endpoint: Box::new(LoopbackEndpoint::new()),
+                },
+            ],
+            &mut ExternallyManagedMmioIntercepts,
+        );
+        let device = EmulatedDevice::new(device, msi_set, mem.dma_client());
+        let dev_config = ManaQueryDeviceCfgResp {
+            pf_cap_flags1: 0.into(),
+            pf_cap_flags2: 0,
+            pf_cap_flags3: 0,
+            pf_cap_flags4: 0,
+            max_num_vports: 2,
+            reserved: 0,
+            max_num_eqs: 64,
+        };
+        let thing = ManaDevice::new(&driver, device, 1, 4).await.unwrap();
+        let vport = thing.new_vport(1, None, &dev_config).await.unwrap();
+        let mut endpoint = ManaEndpoint::new(driver.clone(), vport, GuestDmaMode::DirectDma).await;
+        let multiqueue = endpoint.multiqueue_support();
+        assert!(multiqueue.max_queues >= 2);
+        assert_eq!(multiqueue.indirection_table_size, 128);
+
+        const NUM_QUEUES: u16 = 2;
+        let rx_ids = (0..NUM_QUEUES)
+            .map(|i| {
+                (1 + i as u32 * 32..(i as u32 + 1) * 32)
+                    .map(RxId)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let indirection_table = (0..multiqueue.indirection_table_size)
+            .map(|i| i % NUM_QUEUES)
+            .collect::<Vec<_>>();
+        let mut queues = Vec::new();
+        endpoint
+            .get_queues(
+                rx_ids
+                    .iter()
+                    .map(|initial_rx| QueueConfig {
+                        pool: Box::new(net_backend::tests::Bufs::new(payload_mem.clone())),
+                        initial_rx,
+                        driver: Box::new(driver.clone()),
+                    })
+                    .collect(),
+                Some(&RssConfig {
+                    key: &[0x6d; 40],
+                    indirection_table: &indirection_table,
+                    flags: 0,
+                }),
+                &mut queues,
+            )
+            .await
+            .unwrap();
+        assert_eq!(queues.len(), NUM_QUEUES.into());
+
+        let packet_len = 1138;
+        for (i, queue) in queues.iter_mut().enumerate() {
+            let sent_data = (0..packet_len).map(|v| (i + v) as u8).collect::<Vec<u8>>();
+            payload_mem.write_at(0, &sent_data).unwrap();
+            queue
+                .tx_avail(&[TxSegment {
+                    ty: net_backend::TxSegmentType::Head(net_backend::TxMetadata {
+                        id: TxId(1),
+                        segment_count: 1,
+                        len: packet_len,
+                        ..Default::default()
+                    }),
+                    gpa: 0,
+                    len: packet_len as u32,
+                }])
+                .unwrap();
+
+            let mut packets = [RxId(0); 2];
+            let mut done = [TxId(0); 2];
+            let mut done_n = 0;
+            let mut packets_n = 0;
+            while done_n == 0 || packets_n == 0 {
+                poll_fn(|cx| queue.poll_ready(cx)).await;
+                packets_n += queue.rx_poll(&mut packets[packets_n..]).unwrap();
+                done_n += queue.tx_poll(&mut done[done_n..]).unwrap();
+            }
+            assert_eq!(packets_n, 1);
+            assert!(rx_ids[i].contains(&packets[0]));
+
+            let mut received_data = vec![0; packet_len];
+            payload_mem
+                .read_at(2048 * packets[0].0 as u64, &mut received_data)
+                .unwrap();
+            assert_eq!(received_data, sent_data, "queue {i}");
+        }
+
+        drop(queues);
+        endpoint.stop().await;
+    }
+
     #[async_test]
     async fn test_vport_with_query_filter_state(driver: DefaultDriver) {
         let pages = 512; // 2MB
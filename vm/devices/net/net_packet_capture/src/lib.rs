@@ -271,6 +271,10 @@ fn tx_offload_support(&self) -> TxOffloadSupport {
         self.current().tx_offload_support()
     }
 
+    fn rx_lro_support(&self) -> bool {
+        self.current().rx_lro_support()
+    }
+
     fn multiqueue_support(&self) -> MultiQueueSupport {
         self.current().multiqueue_support()
     }
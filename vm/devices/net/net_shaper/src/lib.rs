@@ -0,0 +1,686 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A network endpoint wrapper that shapes traffic to emulate a constrained or
+//! lossy link.
+//!
+//! Packets in each direction are delayed to model a link with a fixed
+//! bandwidth and latency, and are randomly dropped at a configured rate. This
+//! is meant for testing guest behavior, not for production use.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use net_backend::BufferAccess;
+use net_backend::Endpoint;
+use net_backend::EndpointAction;
+use net_backend::MultiQueueSupport;
+use net_backend::Queue;
+use net_backend::QueueConfig;
+use net_backend::RssConfig;
+use net_backend::RxBufferSegment;
+use net_backend::RxId;
+use net_backend::RxMetadata;
+use net_backend::TxError;
+use net_backend::TxId;
+use net_backend::TxOffloadSupport;
+use net_backend::TxSegment;
+use net_backend::next_packet;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+/// The shaping to apply to an endpoint's traffic. Each setting applies
+/// independently to each direction.
+#[derive(Debug, Copy, Clone, Default, Inspect)]
+pub struct ShapingConfig {
+    /// The link bandwidth in bits per second, or `None` for no limit.
+    pub rate_bps: Option<u64>,
+    /// The one-way latency added to each packet.
+    #[inspect(debug)]
+    pub latency: Duration,
+    /// The fraction of packets to drop, from 0.0 to 1.0.
+    pub loss: f64,
+}
+
+/// An endpoint that shapes the traffic of an inner endpoint.
+pub struct ShapedEndpoint {
+    endpoint: Box<dyn Endpoint>,
+    shaping: Arc<Shaping>,
+}
+
+impl ShapedEndpoint {
+    /// Returns a new endpoint that shapes the traffic of `endpoint`.
+    pub fn new(endpoint: Box<dyn Endpoint>, config: ShapingConfig) -> Self {
+        Self {
+            endpoint,
+            shaping: Arc::new(Shaping::new(config)),
+        }
+    }
+}
+
+impl InspectMut for ShapedEndpoint {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond()
+            .field("shaping", &self.shaping.config)
+            .merge(&mut *self.endpoint);
+    }
+}
+
+#[async_trait]
+impl Endpoint for ShapedEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        self.endpoint.endpoint_type()
+    }
+
+    async fn get_queues(
+        &mut self,
+        config: Vec<QueueConfig<'_>>,
+        rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn Queue>>,
+    ) -> anyhow::Result<()> {
+        let mut timers = Vec::new();
+        let mut rx_lens = Vec::new();
+        let config = config
+            .into_iter()
+            .map(|config| {
+                timers.push(PolledTimer::new(config.driver.as_ref()));
+                let lens = Arc::new(Mutex::new(Vec::new()));
+                rx_lens.push(lens.clone());
+                QueueConfig {
+                    pool: Box::new(ShapedPool {
+                        pool: config.pool,
+                        lens,
+                    }),
+                    ..config
+                }
+            })
+            .collect();
+
+        let mut inner = Vec::new();
+        self.endpoint.get_queues(config, rss, &mut inner).await?;
+        queues.extend(inner.into_iter().zip(timers).zip(rx_lens).map(
+            |((queue, timer), rx_lens)| {
+                Box::new(ShapedQueue::new(
+                    queue,
+                    self.shaping.clone(),
+                    timer,
+                    rx_lens,
+                )) as _
+            },
+        ));
+        Ok(())
+    }
+
+    async fn stop(&mut self) {
+        self.endpoint.stop().await
+    }
+
+    fn is_ordered(&self) -> bool {
+        // Dropped transmits complete before earlier, delayed ones.
+        false
+    }
+
+    fn tx_offload_support(&self) -> TxOffloadSupport {
+        self.endpoint.tx_offload_support()
+    }
+
+    fn rx_lro_support(&self) -> bool {
+        self.endpoint.rx_lro_support()
+    }
+
+    fn multiqueue_support(&self) -> MultiQueueSupport {
+        self.endpoint.multiqueue_support()
+    }
+
+    async fn set_data_path_to_guest_vf(&self, use_vf: bool) -> anyhow::Result<()> {
+        self.endpoint.set_data_path_to_guest_vf(use_vf).await
+    }
+
+    async fn get_data_path_to_guest_vf(&self) -> anyhow::Result<bool> {
+        self.endpoint.get_data_path_to_guest_vf().await
+    }
+
+    async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
+        self.endpoint.wait_for_endpoint_action().await
+    }
+
+    fn link_speed(&self) -> u64 {
+        self.shaping
+            .config
+            .rate_bps
+            .unwrap_or(u64::MAX)
+            .min(self.endpoint.link_speed())
+    }
+}
+
+/// Shaping state shared by all the queues of an endpoint, so that the rate
+/// limit applies to the link as a whole.
+struct Shaping {
+    config: ShapingConfig,
+    /// Packets are dropped when a random `u32` is below this value.
+    loss_threshold: u64,
+    tx: Mutex<Link>,
+    rx: Mutex<Link>,
+}
+
+/// One direction of the emulated link.
+#[derive(Default)]
+struct Link {
+    /// The time at which the link finishes sending the previously scheduled
+    /// packet.
+    busy_until: Option<Instant>,
+}
+
+impl Shaping {
+    fn new(config: ShapingConfig) -> Self {
+        Self {
+            loss_threshold: (config.loss.clamp(0.0, 1.0) * (1u64 << 32) as f64) as u64,
+            config,
+            tx: Default::default(),
+            rx: Default::default(),
+        }
+    }
+
+    fn should_drop(&self) -> bool {
+        self.loss_threshold != 0 && self.drops(getrandom::u32().unwrap_or(u32::MAX))
+    }
+
+    /// Returns whether a packet is dropped for the random value `sample`.
+    fn drops(&self, sample: u32) -> bool {
+        u64::from(sample) < self.loss_threshold
+    }
+
+    /// Schedules a packet of `len` bytes on `link`, returning the time at which
+    /// it should be delivered.
+    fn schedule(&self, link: &Mutex<Link>, len: usize) -> Instant {
+        self.schedule_at(link, len, Instant::now())
+    }
+
+    /// Schedules a packet of `len` bytes on `link` at time `now`.
+    ///
+    /// The link sends one packet at a time, so a burst of packets is spread
+    /// out at the link rate. An idle link does not accumulate credit for a
+    /// later burst.
+    fn schedule_at(&self, link: &Mutex<Link>, len: usize, now: Instant) -> Instant {
+        let mut sent = now;
+        if let Some(rate_bps) = self.config.rate_bps {
+            let mut link = link.lock();
+            let start = link.busy_until.map_or(now, |t| t.max(now));
+            let nanos = (len as u128 * 8 * 1_000_000_000) / rate_bps.max(1) as u128;
+            sent = start.saturating_add(Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX)));
+            link.busy_until = Some(sent);
+        }
+        sent.saturating_add(self.config.latency)
+    }
+}
+
+/// Wraps the receive buffer pool to record the length of each received
+/// packet, which is needed to apply the rate limit to receives.
+struct ShapedPool {
+    pool: Box<dyn BufferAccess>,
+    lens: Arc<Mutex<Vec<usize>>>,
+}
+
+impl ShapedPool {
+    fn record_len(&self, id: RxId, len: usize) {
+        let mut lens = self.lens.lock();
+        let i = id.0 as usize;
+        if lens.len() <= i {
+            lens.resize(i + 1, 0);
+        }
+        lens[i] = len;
+    }
+}
+
+impl BufferAccess for ShapedPool {
+    fn guest_memory(&self) -> &GuestMemory {
+        self.pool.guest_memory()
+    }
+
+    fn write_data(&mut self, id: RxId, data: &[u8]) {
+        self.pool.write_data(id, data)
+    }
+
+    fn guest_addresses(&mut self, id: RxId) -> &[RxBufferSegment] {
+        self.pool.guest_addresses(id)
+    }
+
+    fn capacity(&self, id: RxId) -> u32 {
+        self.pool.capacity(id)
+    }
+
+    fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
+        self.record_len(id, metadata.len);
+        self.pool.write_header(id, metadata)
+    }
+
+    fn write_packet(&mut self, id: RxId, metadata: &RxMetadata, data: &[u8]) {
+        self.record_len(id, metadata.len);
+        self.pool.write_packet(id, metadata, data)
+    }
+
+    fn lro_enabled(&self) -> bool {
+        self.pool.lro_enabled()
+    }
+}
+
+#[derive(Inspect, Default)]
+struct Stats {
+    rx_dropped: Counter,
+    tx_dropped: Counter,
+    tx_errors: Counter,
+}
+
+struct ShapedQueue {
+    queue: Box<dyn Queue>,
+    shaping: Arc<Shaping>,
+    timer: PolledTimer,
+    rx_lens: Arc<Mutex<Vec<usize>>>,
+    rx_scratch: Vec<RxId>,
+    /// Receives from the inner queue, waiting to be delivered to the guest.
+    rx_delayed: VecDeque<(Instant, RxId)>,
+    /// Transmits from the guest, waiting to be sent to the inner queue.
+    tx_delayed: VecDeque<(Instant, Vec<TxSegment>)>,
+    /// Transmits that have completed but not yet been reported via `tx_poll`.
+    tx_done: VecDeque<TxId>,
+    stats: Stats,
+}
+
+impl InspectMut for ShapedQueue {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond()
+            .field("rx_delayed", self.rx_delayed.len())
+            .field("tx_delayed", self.tx_delayed.len())
+            .field("stats", &self.stats)
+            .merge(&mut *self.queue);
+    }
+}
+
+impl ShapedQueue {
+    fn new(
+        queue: Box<dyn Queue>,
+        shaping: Arc<Shaping>,
+        timer: PolledTimer,
+        rx_lens: Arc<Mutex<Vec<usize>>>,
+    ) -> Self {
+        Self {
+            queue,
+            shaping,
+            timer,
+            rx_lens,
+            rx_scratch: vec![RxId(0); 64],
+            rx_delayed: VecDeque::new(),
+            tx_delayed: VecDeque::new(),
+            tx_done: VecDeque::new(),
+            stats: Default::default(),
+        }
+    }
+
+    /// Moves receives from the inner queue to the delay line, dropping some.
+    fn pull_rx(&mut self) -> anyhow::Result<()> {
+        loop {
+            let n = self.queue.rx_poll(&mut self.rx_scratch)?;
+            for &id in &self.rx_scratch[..n] {
+                if self.shaping.should_drop() {
+                    self.stats.rx_dropped.increment();
+                    self.queue.rx_avail(&[id]);
+                    continue;
+                }
+                let len = self.rx_lens.lock().get(id.0 as usize).copied().unwrap_or(0);
+                let time = self.shaping.schedule(&self.shaping.rx, len);
+                self.rx_delayed.push_back((time, id));
+            }
+            if n < self.rx_scratch.len() {
+                break Ok(());
+            }
+        }
+    }
+
+    /// Sends transmits whose delay has elapsed to the inner queue.
+    fn flush_tx(&mut self) {
+        let now = Instant::now();
+        while let Some((time, segments)) = self.tx_delayed.front() {
+            if *time > now {
+                break;
+            }
+            let (metadata, _, _) = next_packet(segments);
+            let id = metadata.id;
+            match self.queue.tx_avail(segments) {
+                // The inner queue is full. It will signal readiness when there
+                // is space again.
+                Ok((_, 0)) => break,
+                Ok((sync, _)) => {
+                    if sync {
+                        self.tx_done.push_back(id);
+                    }
+                }
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "shaped transmit failed"
+                    );
+                    self.stats.tx_errors.increment();
+                    self.tx_done.push_back(id);
+                }
+            }
+            self.tx_delayed.pop_front();
+        }
+    }
+}
+
+impl Queue for ShapedQueue {
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut ready = self.queue.poll_ready(cx).is_ready();
+        if ready {
+            if let Err(err) = self.pull_rx() {
+                tracelimit::warn_ratelimited!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "shaped receive failed"
+                );
+            }
+        }
+        self.flush_tx();
+        let now = Instant::now();
+        ready |= !self.tx_done.is_empty() || self.rx_delayed.front().is_some_and(|x| x.0 <= now);
+        if ready {
+            return Poll::Ready(());
+        }
+
+        // Wake up when the next delayed packet is due.
+        let next = [
+            self.rx_delayed.front().map(|x| x.0),
+            self.tx_delayed.front().map(|x| x.0),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(next) = next {
+            if self.timer.poll_until(cx, next).is_ready() {
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    }
+
+    fn rx_avail(&mut self, done: &[RxId]) {
+        self.queue.rx_avail(done)
+    }
+
+    fn rx_poll(&mut self, packets: &mut [RxId]) -> anyhow::Result<usize> {
+        self.pull_rx()?;
+        let now = Instant::now();
+        let mut n = 0;
+        while n < packets.len() {
+            match self.rx_delayed.front() {
+                Some(&(time, id)) if time <= now => {
+                    packets[n] = id;
+                    n += 1;
+                    self.rx_delayed.pop_front();
+                }
+                _ => break,
+            }
+        }
+        Ok(n)
+    }
+
+    fn tx_avail(&mut self, mut segments: &[TxSegment]) -> anyhow::Result<(bool, usize)> {
+        let n = segments.len();
+        while !segments.is_empty() {
+            let (metadata, this, rest) = next_packet(segments);
+            segments = rest;
+            if self.shaping.should_drop() {
+                self.stats.tx_dropped.increment();
+                self.tx_done.push_back(metadata.id);
+                continue;
+            }
+            let time = self.shaping.schedule(&self.shaping.tx, metadata.len);
+            self.tx_delayed.push_back((time, this.to_vec()));
+        }
+        self.flush_tx();
+        Ok((false, n))
+    }
+
+    fn tx_poll(&mut self, done: &mut [TxId]) -> Result<usize, TxError> {
+        let mut n = 0;
+        while n < done.len() {
+            let Some(id) = self.tx_done.pop_front() else {
+                break;
+            };
+            done[n] = id;
+            n += 1;
+        }
+        n += self.queue.tx_poll(&mut done[n..])?;
+        Ok(n)
+    }
+
+    fn buffer_access(&mut self) -> Option<&mut dyn BufferAccess> {
+        self.queue.buffer_access()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use net_backend::TxMetadata;
+    use net_backend::TxSegmentType;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+
+    /// 8 Mbps, or one byte per microsecond.
+    const RATE_BPS: u64 = 8_000_000;
+
+    fn start() -> Instant {
+        Instant::from_nanos(1_000_000_000)
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_rate_spreads_burst() {
+        let shaping = Shaping::new(ShapingConfig {
+            rate_bps: Some(RATE_BPS),
+            ..Default::default()
+        });
+        let t0 = start();
+        // A burst of packets leaves the link one at a time.
+        for i in 1..=3 {
+            assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t0), t0 + ms(i));
+        }
+        // The other direction is independent.
+        assert_eq!(shaping.schedule_at(&shaping.rx, 1000, t0), t0 + ms(1));
+    }
+
+    #[test]
+    fn test_rate_refill() {
+        let shaping = Shaping::new(ShapingConfig {
+            rate_bps: Some(RATE_BPS),
+            ..Default::default()
+        });
+        let t0 = start();
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t0), t0 + ms(1));
+
+        // A packet sent while the link is busy waits for it.
+        assert_eq!(
+            shaping.schedule_at(&shaping.tx, 1000, t0 + Duration::from_micros(500)),
+            t0 + ms(2)
+        );
+
+        // Once the link is idle, packets are sent at the link rate from the
+        // current time, with no credit for the idle period.
+        let t1 = t0 + ms(10);
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t1), t1 + ms(1));
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t1), t1 + ms(2));
+    }
+
+    #[test]
+    fn test_latency() {
+        let t0 = start();
+        let shaping = Shaping::new(ShapingConfig {
+            latency: ms(5),
+            ..Default::default()
+        });
+        // Without a rate limit, packets are only delayed, not serialized.
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t0), t0 + ms(5));
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t0), t0 + ms(5));
+
+        let shaping = Shaping::new(ShapingConfig {
+            rate_bps: Some(RATE_BPS),
+            latency: ms(5),
+            ..Default::default()
+        });
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t0), t0 + ms(6));
+        assert_eq!(shaping.schedule_at(&shaping.tx, 1000, t0), t0 + ms(7));
+    }
+
+    #[test]
+    fn test_loss() {
+        let shaping = |loss| {
+            Shaping::new(ShapingConfig {
+                loss,
+                ..Default::default()
+            })
+        };
+
+        let none = shaping(0.0);
+        assert!(!none.drops(0));
+        assert!(!none.should_drop());
+
+        let half = shaping(0.5);
+        assert!(half.drops(u32::MAX / 4));
+        assert!(!half.drops(u32::MAX / 4 * 3));
+
+        // Out of range values are clamped.
+        assert!(shaping(1.0).drops(u32::MAX));
+        assert!(shaping(2.0).drops(u32::MAX));
+        assert!(!shaping(-1.0).drops(0));
+    }
+
+    /// A queue that records transmitted packets and completes them
+    /// synchronously.
+    struct RecordingQueue {
+        sent: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl InspectMut for RecordingQueue {
+        fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+            req.ignore();
+        }
+    }
+
+    impl Queue for RecordingQueue {
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<()> {
+            Poll::Pending
+        }
+
+        fn rx_avail(&mut self, _done: &[RxId]) {}
+
+        fn rx_poll(&mut self, _packets: &mut [RxId]) -> anyhow::Result<usize> {
+            Ok(0)
+        }
+
+        fn tx_avail(&mut self, segments: &[TxSegment]) -> anyhow::Result<(bool, usize)> {
+            let (metadata, _, _) = next_packet(segments);
+            self.sent.lock().push(metadata.id.0);
+            Ok((true, segments.len()))
+        }
+
+        fn tx_poll(&mut self, _done: &mut [TxId]) -> Result<usize, TxError> {
+            Ok(0)
+        }
+
+        fn buffer_access(&mut self) -> Option<&mut dyn BufferAccess> {
+            None
+        }
+    }
+
+    /// Returns a shaped queue, and the packets sent to the inner queue.
+    fn shaped_queue(
+        driver: &DefaultDriver,
+        config: ShapingConfig,
+    ) -> (ShapedQueue, Arc<Mutex<Vec<u32>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let queue = ShapedQueue::new(
+            Box::new(RecordingQueue { sent: sent.clone() }),
+            Arc::new(Shaping::new(config)),
+            PolledTimer::new(driver),
+            Default::default(),
+        );
+        (queue, sent)
+    }
+
+    fn packet(id: u32) -> TxSegment {
+        TxSegment {
+            ty: TxSegmentType::Head(TxMetadata {
+                id: TxId(id),
+                segment_count: 1,
+                len: 1000,
+                ..Default::default()
+            }),
+            gpa: 0,
+            len: 1000,
+        }
+    }
+
+    fn completions(queue: &mut ShapedQueue) -> Vec<u32> {
+        let mut done = [TxId(0); 8];
+        let n = queue.tx_poll(&mut done).unwrap();
+        done[..n].iter().map(|id| id.0).collect()
+    }
+
+    #[async_test]
+    async fn test_tx_dropped(driver: DefaultDriver) {
+        let (mut queue, sent) = shaped_queue(
+            &driver,
+            ShapingConfig {
+                loss: 1.0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(queue.tx_avail(&[packet(1), packet(2)]).unwrap(), (false, 2));
+        // Dropped packets complete immediately without being sent.
+        assert_eq!(completions(&mut queue), [1, 2]);
+        assert_eq!(queue.stats.tx_dropped.get(), 2);
+        assert!(queue.tx_delayed.is_empty());
+        assert!(sent.lock().is_empty());
+    }
+
+    #[async_test]
+    async fn test_tx_queued(driver: DefaultDriver) {
+        let (mut queue, sent) = shaped_queue(
+            &driver,
+            ShapingConfig {
+                latency: Duration::from_secs(3600),
+                ..Default::default()
+            },
+        );
+        assert_eq!(queue.tx_avail(&[packet(1)]).unwrap(), (false, 1));
+        // Delayed packets are held, not dropped, until they are due.
+        assert!(completions(&mut queue).is_empty());
+        assert_eq!(queue.tx_delayed.len(), 1);
+        assert_eq!(queue.stats.tx_dropped.get(), 0);
+        assert!(sent.lock().is_empty());
+    }
+
+    #[async_test]
+    async fn test_tx_unshaped(driver: DefaultDriver) {
+        let (mut queue, sent) = shaped_queue(&driver, ShapingConfig::default());
+        assert_eq!(queue.tx_avail(&[packet(1)]).unwrap(), (false, 1));
+        assert!(queue.tx_delayed.is_empty());
+        assert_eq!(*sent.lock(), [1]);
+        assert_eq!(completions(&mut queue), [1]);
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::ShapedEndpoint;
+use crate::ShapingConfig;
+use async_trait::async_trait;
+use net_backend::resolve::ResolveEndpointParams;
+use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::shaped::ShapedHandle;
+use std::time::Duration;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::NetEndpointHandleKind;
+
+/// A resolver for [`ShapedEndpoint`].
+pub struct ShapedResolver;
+
+declare_static_async_resolver! {
+    ShapedResolver,
+    (NetEndpointHandleKind, ShapedHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<NetEndpointHandleKind, ShapedHandle> for ShapedResolver {
+    type Output = ResolvedEndpoint;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: ShapedHandle,
+        input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        if !(0.0..=100.0).contains(&resource.loss_percent) {
+            anyhow::bail!("invalid loss percentage {}", resource.loss_percent);
+        }
+        let endpoint = resolver.resolve(resource.endpoint, input).await?;
+        let config = ShapingConfig {
+            rate_bps: resource.rate_bps,
+            latency: Duration::from_millis(resource.latency_ms),
+            loss: resource.loss_percent / 100.0,
+        };
+        Ok(ShapedEndpoint::new(endpoint.0, config).into())
+    }
+}
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! A TAP interface based endpoint.
+//! A TAP (or macvtap) interface based endpoint.
 
 #![cfg(unix)]
 #![expect(missing_docs)]
@@ -21,11 +21,15 @@
 use net_backend::RxMetadata;
 use net_backend::TxError;
 use net_backend::TxId;
+use net_backend::TxOffloadSupport;
 use net_backend::TxSegment;
 use net_backend::linearize;
+use net_backend::next_packet;
+use net_backend::offload::software_offload;
 use pal_async::driver::Driver;
 use parking_lot::Mutex;
 use std::collections::VecDeque;
+use std::fs::File;
 use std::io::ErrorKind;
 use std::io::Write;
 use std::pin::Pin;
@@ -52,6 +56,14 @@ pub fn new(name: &str) -> Result<Self, Error> {
             tap: Arc::new(Mutex::new(Some(tap))),
         })
     }
+
+    /// Creates an endpoint from an opened macvtap device node (`/dev/tapN`).
+    pub fn from_macvtap(file: File) -> Result<Self, Error> {
+        let tap = tap::Tap::from_macvtap(file).map_err(Error::TapInterface)?;
+        Ok(Self {
+            tap: Arc::new(Mutex::new(Some(tap))),
+        })
+    }
 }
 
 impl InspectMut for TapEndpoint {
@@ -91,6 +103,16 @@ async fn stop(&mut self) {
     fn is_ordered(&self) -> bool {
         true
     }
+
+    fn tx_offload_support(&self) -> TxOffloadSupport {
+        // Offloads are performed in software before writing to the TAP.
+        TxOffloadSupport {
+            ipv4_header: true,
+            tcp: true,
+            udp: true,
+            tso: true,
+        }
+    }
 }
 
 struct TapQueue {
@@ -142,6 +164,34 @@ fn new(
     }
 }
 
+fn write_packet(tap: &mut tap::PolledTap, packet: &[u8]) {
+    match tap.write(packet) {
+        Ok(bytes_written) => {
+            assert_eq!(
+                bytes_written,
+                packet.len(),
+                "TAP should never partial write"
+            );
+        }
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {
+            // dropped packet: buffer is full
+
+            // TODO: return partial transmit here. This relies on
+            // remembering this condition and polling for POLLOUT in
+            // poll_ready().
+        }
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => {
+            // dropped packet: interface is not up
+        }
+        Err(err) => {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "write to TAP interface failed"
+            );
+        }
+    }
+}
+
 impl Queue for TapQueue {
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
         if !self.inner.rx_ready.is_empty() {
@@ -203,31 +253,18 @@ fn tx_avail(&mut self, mut segments: &[TxSegment]) -> anyhow::Result<(bool, usiz
         // Synchronously send packets received from the guest to host's network.
         if let Some(tap) = self.tap.as_mut() {
             while !segments.is_empty() {
-                let packet = linearize(self.inner.pool.as_ref(), &mut segments)?;
-                match tap.write(&packet) {
-                    Ok(bytes_written) => {
-                        assert_eq!(
-                            bytes_written,
-                            packet.len(),
-                            "TAP should never partial write"
-                        );
-                    }
-                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
-                        // dropped packet: buffer is full
-
-                        // TODO: return partial transmit here. This relies on
-                        // remembering this condition and polling for POLLOUT in
-                        // poll_ready().
-                    }
-                    Err(err) if err.raw_os_error() == Some(libc::EIO) => {
-                        // dropped packet: interface is not up
-                    }
-                    Err(err) => {
-                        tracing::warn!(
-                            error = &err as &dyn std::error::Error,
-                            "write to TAP interface failed"
-                        );
-                    }
+                let (metadata, _, _) = next_packet(segments);
+                let metadata = metadata.clone();
+                let mut packet = linearize(self.inner.pool.as_ref(), &mut segments)?;
+                // The TAP device is opened without a virtio net header, so
+                // any requested offloads must be performed here.
+                if let Err(err) =
+                    software_offload(&metadata, &mut packet, |packet| write_packet(tap, packet))
+                {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "dropping tx packet: offload failed"
+                    );
                 }
             }
         }
@@ -4,6 +4,7 @@
 use crate::TapEndpoint;
 use net_backend::resolve::ResolveEndpointParams;
 use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::macvtap::MacvtapHandle;
 use net_backend_resources::tap::TapHandle;
 use vm_resource::ResolveResource;
 use vm_resource::declare_static_resolver;
@@ -29,3 +30,24 @@ fn resolve(
         Ok(endpoint.into())
     }
 }
+
+pub struct MacvtapResolver;
+
+declare_static_resolver! {
+    MacvtapResolver,
+    (NetEndpointHandleKind, MacvtapHandle),
+}
+
+impl ResolveResource<NetEndpointHandleKind, MacvtapHandle> for MacvtapResolver {
+    type Output = ResolvedEndpoint;
+    type Error = super::Error;
+
+    fn resolve(
+        &self,
+        resource: MacvtapHandle,
+        _input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        let endpoint = TapEndpoint::from_macvtap(resource.file)?;
+        Ok(endpoint.into())
+    }
+}
@@ -94,6 +94,22 @@ fn open_tap_interface(tap_name: &str) -> Result<File, Error> {
         }
     }
 
+    /// Wraps an opened macvtap device node (`/dev/tapN`).
+    pub fn from_macvtap(file: File) -> Result<Self, Error> {
+        // macvtap devices prepend a virtio-net header to each packet by
+        // default. Clear that flag so that packets are plain Ethernet frames,
+        // as with TAP.
+        let mut ifreq: gen_if::ifreq = Default::default();
+        ifreq.ifr_ifru.ifru_flags = (gen_if_tun::IFF_TAP | gen_if_tun::IFF_NO_PI) as c_short;
+
+        // SAFETY: calling the ioctl according to implementation requirements.
+        unsafe {
+            tun_set_iff(file.as_raw_fd(), &ifreq)
+                .map_err(|_e| Error::SetTapAttributes(io::Error::last_os_error()))?;
+        };
+        Ok(Self { tap: file })
+    }
+
     pub fn polled(self, driver: &(impl Driver + ?Sized)) -> io::Result<PolledTap> {
         Ok(PolledTap {
             tap: PolledPipe::new(driver, self.tap)?,
@@ -46,6 +46,7 @@
 use inspect::SensitivityLevel;
 use inspect_counters::Counter;
 use inspect_counters::Histogram;
+use inspect_counters::LatencyHistogram;
 use mesh::rpc::Rpc;
 use net_backend::Endpoint;
 use net_backend::EndpointAction;
@@ -62,6 +63,7 @@
 use rx_bufs::RxBuffers;
 use rx_bufs::SubAllocationInUse;
 use std::cmp;
+use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::future::pending;
@@ -434,6 +436,9 @@ struct ActiveState {
     pending_tx_completions: VecDeque<PendingTxCompletion>,
 
     rx_bufs: RxBuffers,
+    /// When each receive request, by its first buffer ID, was sent to the
+    /// guest, if latency histograms are enabled.
+    rx_sent: HashMap<u32, std::time::Instant>,
 
     stats: QueueStats,
 }
@@ -449,6 +454,11 @@ struct QueueStats {
     tx_checksum_packets: Counter,
     tx_packets_per_wake: Histogram<10>,
     rx_packets_per_wake: Histogram<10>,
+    /// Time from receiving a transmit request to completing it.
+    tx_latency: LatencyHistogram,
+    /// Time from sending received packets to the guest to the guest returning
+    /// their buffers.
+    rx_latency: LatencyHistogram,
 }
 
 #[derive(Debug)]
@@ -909,6 +919,7 @@ fn new(primary: Option<PrimaryChannelState>, recv_buffer_count: u32) -> Self {
             free_tx_packets: (0..TX_PACKET_QUOTA as u32).rev().map(TxId).collect(),
             pending_tx_completions: VecDeque::new(),
             rx_bufs: RxBuffers::new(recv_buffer_count),
+            rx_sent: HashMap::new(),
             stats: Default::default(),
         }
     }
@@ -956,6 +967,7 @@ fn restore(
 struct PendingTxPacket {
     pending_packet_count: usize,
     transaction_id: u64,
+    start: Option<std::time::Instant>,
 }
 
 /// The maximum batch size.
@@ -4958,6 +4970,9 @@ fn process_endpoint_rx(
             None => {
                 // packet was sent
                 state.stats.rx_packets.add(n as u64);
+                if let Some(start) = LatencyHistogram::start() {
+                    state.rx_sent.insert(data.rx_ready[0].0, start);
+                }
             }
             Some(_) => {
                 // Ring buffer is full. Drop the packets and free the rx
@@ -5108,6 +5123,7 @@ fn process_ring_buffer(
                 PacketData::RndisPacket(_) => {
                     assert!(data.tx_segments.is_empty());
                     let id = state.free_tx_packets.pop().unwrap();
+                    state.pending_tx_packets[id.0 as usize].start = LatencyHistogram::start();
                     let result: Result<usize, WorkerError> =
                         self.handle_rndis(buffers, id, state, &packet, &mut data.tx_segments);
                     let num_packets = match result {
@@ -5376,6 +5392,7 @@ fn complete_tx_packet(
     ) -> Result<(), WorkerError> {
         let tx_packet = &mut state.pending_tx_packets[id.0 as usize];
         assert_eq!(tx_packet.pending_packet_count, 0);
+        state.stats.tx_latency.record(tx_packet.start.take());
         if self.pending_send_size == 0
             && self.try_send_tx_packet(tx_packet.transaction_id, status)?
         {
@@ -5403,6 +5420,9 @@ fn release_recv_buffers(
         // The transaction ID specifies the first rx buffer ID.
         let first_id: u32 = transaction_id.try_into().ok()?;
         let ids = self.rx_bufs.free(first_id)?;
+        if let Some(start) = self.rx_sent.remove(&first_id) {
+            self.stats.rx_latency.add_sample(start.elapsed());
+        }
         for id in ids {
             if !rx_buffer_range.send_if_remote(id) {
                 if id >= RX_RESERVED_CONTROL_BUFFERS {
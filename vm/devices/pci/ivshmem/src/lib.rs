@@ -0,0 +1,385 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An inter-VM shared memory (ivshmem) PCI device.
+//!
+//! The device maps a host file into the guest through BAR2, so that a host
+//! process (or another VM) mapping the same file can exchange data with the
+//! guest without going through a network stack. It uses the same IDs and
+//! register layout as QEMU's `ivshmem-plain` and `ivshmem-doorbell` devices,
+//! so existing guest software written for those works unchanged.
+//!
+//! Optionally, the device has a doorbell connection to a host peer over a
+//! Unix socket. Each doorbell write by the guest is sent to the peer as a
+//! little-endian `u32`, and each `u32` received from the peer is ORed into the
+//! interrupt status register, raising the device's INTx interrupt if it is
+//! not masked.
+
+#![forbid(unsafe_code)]
+
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::poll_device::PollDevice;
+use futures::AsyncRead;
+use guestmem::MappedMemoryRegion;
+use guestmem::MemoryMapper;
+use inspect::InspectMut;
+use pal_async::socket::PolledSocket;
+use pci_core::PciInterruptPin;
+use pci_core::cfg_space_emu::BarMemoryKind;
+use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
+use pci_core::cfg_space_emu::DeviceBars;
+use pci_core::cfg_space_emu::IntxInterrupt;
+use pci_core::spec::hwid::ClassCode;
+use pci_core::spec::hwid::HardwareIds;
+use pci_core::spec::hwid::ProgrammingInterface;
+use pci_core::spec::hwid::Subclass;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use thiserror::Error;
+use unix_socket::UnixStream;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
+use vmcore::save_restore::SaveRestore;
+use vmcore::save_restore::SavedStateNotSupported;
+use vmcore::vm_task::VmTaskDriverSource;
+
+const VENDOR_ID_REDHAT_QUMRANET: u16 = 0x1af4;
+const DEVICE_ID_IVSHMEM: u16 = 0x1110;
+
+/// The size of the register BAR.
+const BAR0_LEN: u64 = 0x100;
+
+// Register offsets within BAR0.
+const REG_INTR_MASK: u16 = 0x0;
+const REG_INTR_STATUS: u16 = 0x4;
+const REG_IV_POSITION: u16 = 0x8;
+const REG_DOORBELL: u16 = 0xc;
+
+/// The smallest supported shared memory size.
+pub const MIN_SIZE: u64 = 0x1000;
+
+/// An error returned by [`IvshmemDevice::new`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The size is not a power of two of at least [`MIN_SIZE`] bytes.
+    #[error("shared memory size {0:#x} must be a power of two of at least 4KiB")]
+    InvalidSize(u64),
+    /// The backing file is smaller than the shared memory region.
+    #[error("backing file is {file_len:#x} bytes, smaller than the {size:#x} byte region")]
+    FileTooSmall {
+        /// The length of the file.
+        file_len: u64,
+        /// The requested size of the region.
+        size: u64,
+    },
+    /// The file could not be mapped.
+    #[error("failed to map the backing file")]
+    Map(#[source] io::Error),
+    /// The doorbell socket could not be registered with the driver.
+    #[error("failed to set up the doorbell socket")]
+    Doorbell(#[source] io::Error),
+}
+
+/// The doorbell connection to the host peer.
+struct Doorbell {
+    socket: PolledSocket<UnixStream>,
+    buf: [u8; 4],
+    filled: usize,
+}
+
+impl Doorbell {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u32>> {
+        while self.filled < self.buf.len() {
+            let buf = &mut self.buf[self.filled..];
+            let n = ready!(Pin::new(&mut self.socket).poll_read(cx, buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.filled += n;
+        }
+        self.filled = 0;
+        Poll::Ready(Ok(u32::from_le_bytes(self.buf)))
+    }
+
+    fn send(&self, value: u32) -> io::Result<()> {
+        let n = self.socket.get().write(&value.to_le_bytes())?;
+        if n != 4 {
+            // The peer would see a torn message, so the stream is unusable.
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        Ok(())
+    }
+}
+
+/// An ivshmem PCI device.
+#[derive(InspectMut)]
+pub struct IvshmemDevice {
+    cfg_space: ConfigSpaceType0Emulator,
+    #[inspect(skip)]
+    interrupt: Arc<IntxInterrupt>,
+    #[inspect(hex)]
+    size: u64,
+    #[inspect(with = "Option::is_some")]
+    doorbell: Option<Doorbell>,
+    #[inspect(hex)]
+    intr_mask: u32,
+    #[inspect(hex)]
+    intr_status: u32,
+    #[inspect(skip)]
+    _region: Arc<dyn MappedMemoryRegion>,
+}
+
+impl IvshmemDevice {
+    /// Returns a new device that exposes the first `size` bytes of `file` to
+    /// the guest.
+    ///
+    /// If `doorbell` is provided, doorbell writes and interrupts are
+    /// exchanged with the host peer on the other end of the socket.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        interrupt: LineInterrupt,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        mapper: &dyn MemoryMapper,
+        file: File,
+        size: u64,
+        doorbell: Option<UnixStream>,
+    ) -> Result<Self, Error> {
+        if !size.is_power_of_two() || size < MIN_SIZE {
+            return Err(Error::InvalidSize(size));
+        }
+        let file_len = file.metadata().map_err(Error::Map)?.len();
+        if file_len < size {
+            return Err(Error::FileTooSmall { file_len, size });
+        }
+
+        let mappable =
+            sparse_mmap::new_mappable_from_file(&file, true, false).map_err(Error::Map)?;
+        let (control, region) = mapper
+            .new_region(size as usize, "ivshmem".into())
+            .map_err(Error::Map)?;
+        region
+            .map(0, &mappable, 0, size as usize, true)
+            .map_err(Error::Map)?;
+
+        let doorbell = doorbell
+            .map(|socket| {
+                Ok(Doorbell {
+                    socket: PolledSocket::new(&driver_source.simple(), socket)?,
+                    buf: [0; 4],
+                    filled: 0,
+                })
+            })
+            .transpose()
+            .map_err(Error::Doorbell)?;
+
+        let bars = DeviceBars::new()
+            .bar0(
+                BAR0_LEN,
+                BarMemoryKind::Intercept(register_mmio.new_io_region("bar0", BAR0_LEN)),
+            )
+            .bar2(size, BarMemoryKind::SharedMem(control));
+
+        let mut cfg_space = ConfigSpaceType0Emulator::new(
+            HardwareIds {
+                vendor_id: VENDOR_ID_REDHAT_QUMRANET,
+                device_id: DEVICE_ID_IVSHMEM,
+                revision_id: 1,
+                prog_if: ProgrammingInterface::NONE,
+                sub_class: Subclass::MEMORY_CONTROLLER_RAM,
+                base_class: ClassCode::MEMORY_CONTROLLER,
+                type0_sub_vendor_id: VENDOR_ID_REDHAT_QUMRANET,
+                type0_sub_system_id: DEVICE_ID_IVSHMEM,
+            },
+            Vec::new(),
+            bars,
+        );
+        let interrupt = cfg_space.set_interrupt_pin(PciInterruptPin::IntA, interrupt);
+
+        Ok(Self {
+            cfg_space,
+            interrupt,
+            size,
+            doorbell,
+            intr_mask: 0,
+            intr_status: 0,
+            _region: region,
+        })
+    }
+
+    fn update_interrupt(&self) {
+        self.interrupt
+            .set_level(self.intr_status & self.intr_mask != 0);
+    }
+
+    fn ring_doorbell(&mut self, value: u32) {
+        let Some(doorbell) = &self.doorbell else {
+            tracelimit::warn_ratelimited!(value, "ivshmem doorbell rung without a peer");
+            return;
+        };
+        match doorbell.send(value) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                tracelimit::warn_ratelimited!(value, "ivshmem peer not reading, dropping doorbell");
+            }
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "ivshmem doorbell connection failed"
+                );
+                self.doorbell = None;
+            }
+        }
+    }
+
+    fn read_register(&mut self, offset: u16) -> u32 {
+        match offset {
+            REG_INTR_MASK => self.intr_mask,
+            REG_INTR_STATUS => {
+                // Reading the status acknowledges the interrupt.
+                let status = std::mem::take(&mut self.intr_status);
+                self.update_interrupt();
+                status
+            }
+            // The guest is always peer 0.
+            REG_IV_POSITION => 0,
+            _ => {
+                tracelimit::warn_ratelimited!(offset, "ivshmem read from unknown register");
+                0
+            }
+        }
+    }
+
+    fn write_register(&mut self, offset: u16, value: u32) {
+        match offset {
+            REG_INTR_MASK => {
+                self.intr_mask = value;
+                self.update_interrupt();
+            }
+            REG_INTR_STATUS => {
+                self.intr_status = value;
+                self.update_interrupt();
+            }
+            REG_DOORBELL => self.ring_doorbell(value),
+            _ => {
+                tracelimit::warn_ratelimited!(offset, value, "ivshmem write to unknown register");
+            }
+        }
+    }
+
+    fn register_offset(&self, addr: u64, len: usize) -> Result<u16, IoError> {
+        let Some((0, offset)) = self.cfg_space.find_bar(addr) else {
+            return Err(IoError::InvalidRegister);
+        };
+        if len != 4 {
+            return Err(IoError::InvalidAccessSize);
+        }
+        if offset % 4 != 0 {
+            return Err(IoError::UnalignedAccess);
+        }
+        Ok(offset)
+    }
+}
+
+impl ChangeDeviceState for IvshmemDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.cfg_space.reset();
+        self.intr_mask = 0;
+        self.intr_status = 0;
+        self.update_interrupt();
+    }
+}
+
+impl ChipsetDevice for IvshmemDevice {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for IvshmemDevice {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        while let Some(doorbell) = &mut self.doorbell {
+            match doorbell.poll_recv(cx) {
+                Poll::Ready(Ok(value)) => {
+                    self.intr_status |= value;
+                    self.update_interrupt();
+                }
+                Poll::Ready(Err(err)) => {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "ivshmem doorbell connection closed"
+                    );
+                    self.doorbell = None;
+                }
+                Poll::Pending => break,
+            }
+        }
+    }
+}
+
+impl MmioIntercept for IvshmemDevice {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        let offset = match self.register_offset(addr, data.len()) {
+            Ok(offset) => offset,
+            Err(err) => return IoResult::Err(err),
+        };
+        let value = self.read_register(offset);
+        data.copy_from_slice(&value.to_le_bytes());
+        IoResult::Ok
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        let offset = match self.register_offset(addr, data.len()) {
+            Ok(offset) => offset,
+            Err(err) => return IoResult::Err(err),
+        };
+        self.write_register(offset, u32::from_le_bytes(data.try_into().unwrap()));
+        IoResult::Ok
+    }
+}
+
+impl PciConfigSpace for IvshmemDevice {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        self.cfg_space.read_u32(offset, value)
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        self.cfg_space.write_u32(offset, value)
+    }
+}
+
+impl SaveRestore for IvshmemDevice {
+    type SavedState = SavedStateNotSupported;
+
+    fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+        Err(SaveError::NotSupported)
+    }
+
+    fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+        match state {}
+    }
+}
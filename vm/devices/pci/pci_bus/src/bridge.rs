@@ -0,0 +1,250 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Emulation of a PCI-to-PCI bridge's type 1 config space header.
+//!
+//! The bridge does not own any devices. Instead, [`GenericPciBus`] consults
+//! each bridge's bus number registers to route config space accesses for
+//! secondary buses to the devices that were added behind that bridge.
+//!
+//! The memory and I/O window registers are stored but not enforced: devices
+//! behind the bridge register their BARs with the chipset directly.
+//!
+//! [`GenericPciBus`]: crate::GenericPciBus
+
+use inspect::Inspect;
+
+/// Microsoft's PCI vendor ID.
+const VENDOR_ID: u16 = 0x1414;
+/// The device ID of the emulated bridge.
+const DEVICE_ID: u16 = 0x00b0;
+/// Class code 0x06 (bridge), subclass 0x04 (PCI-to-PCI), prog if 0x00.
+const CLASS_CODE: u32 = 0x060400;
+/// Header type 1, for bridges.
+const HEADER_TYPE_BRIDGE: u32 = 0x01 << 16;
+
+mod reg {
+    pub const DEVICE_VENDOR: u16 = 0x00;
+    pub const STATUS_COMMAND: u16 = 0x04;
+    pub const CLASS_REVISION: u16 = 0x08;
+    pub const BIST_HEADER: u16 = 0x0c;
+    pub const BUS_NUMBERS: u16 = 0x18;
+    pub const SEC_STATUS_IO: u16 = 0x1c;
+    pub const MEMORY: u16 = 0x20;
+    pub const PREFETCH: u16 = 0x24;
+    pub const PREFETCH_BASE_UPPER: u16 = 0x28;
+    pub const PREFETCH_LIMIT_UPPER: u16 = 0x2c;
+    pub const BRIDGE_CONTROL_INTERRUPT: u16 = 0x3c;
+}
+
+/// I/O enable, memory enable, bus master, parity error response, and SERR#
+/// enable.
+const COMMAND_MASK: u32 = 0x0147;
+/// The base and limit of the 16-bit I/O window, in 4KB units.
+const IO_MASK: u32 = 0xf0f0;
+/// The base and limit of a memory window, in 1MB units.
+const MEMORY_MASK: u32 = 0xfff0_fff0;
+/// Both the base and limit of the prefetchable window decode 64 bits.
+const PREFETCH_64BIT: u32 = 0x0001_0001;
+/// The interrupt line and the low byte of the bridge control register.
+/// The bridge has no interrupt pin of its own.
+const BRIDGE_CONTROL_INTERRUPT_MASK: u32 = 0x00ff_00ff;
+
+/// The config space of a PCI-to-PCI bridge.
+#[derive(Debug, Inspect)]
+pub(crate) struct PciBridge {
+    /// The bus number that the devices behind the bridge were added with.
+    pub secondary_bus_at_reset: u8,
+    /// The highest bus number behind the bridge, including buses behind
+    /// nested bridges.
+    pub subordinate_bus_at_reset: u8,
+    /// The bus number of the bridge itself.
+    pub primary_bus_at_reset: u8,
+    state: PciBridgeState,
+}
+
+#[derive(Debug, Copy, Clone, Inspect)]
+struct PciBridgeState {
+    #[inspect(hex)]
+    command: u32,
+    #[inspect(hex)]
+    bus_numbers: u32,
+    #[inspect(hex)]
+    io: u32,
+    #[inspect(hex)]
+    memory: u32,
+    #[inspect(hex)]
+    prefetch: u32,
+    #[inspect(hex)]
+    prefetch_base_upper: u32,
+    #[inspect(hex)]
+    prefetch_limit_upper: u32,
+    #[inspect(hex)]
+    bridge_control_interrupt: u32,
+}
+
+impl PciBridge {
+    /// Creates a bridge on `primary_bus` leading to `secondary_bus`.
+    ///
+    /// The bus numbers start out as firmware would have assigned them, so
+    /// that guests that do not renumber buses find the devices behind the
+    /// bridge.
+    pub fn new(primary_bus: u8, secondary_bus: u8) -> Self {
+        let mut bridge = Self {
+            secondary_bus_at_reset: secondary_bus,
+            subordinate_bus_at_reset: secondary_bus,
+            primary_bus_at_reset: primary_bus,
+            state: PciBridgeState {
+                command: 0,
+                bus_numbers: 0,
+                io: 0,
+                memory: 0,
+                prefetch: 0,
+                prefetch_base_upper: 0,
+                prefetch_limit_upper: 0,
+                bridge_control_interrupt: 0,
+            },
+        };
+        bridge.reset();
+        bridge
+    }
+
+    /// The secondary bus number currently programmed into the bridge.
+    pub fn secondary_bus(&self) -> u8 {
+        (self.state.bus_numbers >> 8) as u8
+    }
+
+    /// Records a newly added bridge behind this one.
+    pub fn extend_subordinate_bus(&mut self, bus: u8) {
+        if bus > self.subordinate_bus_at_reset {
+            self.subordinate_bus_at_reset = bus;
+            self.state.bus_numbers = (self.state.bus_numbers & !0x00ff_0000) | ((bus as u32) << 16);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = PciBridgeState {
+            command: 0,
+            bus_numbers: self.primary_bus_at_reset as u32
+                | (self.secondary_bus_at_reset as u32) << 8
+                | (self.subordinate_bus_at_reset as u32) << 16,
+            io: 0,
+            memory: 0,
+            prefetch: 0,
+            prefetch_base_upper: 0,
+            prefetch_limit_upper: 0,
+            bridge_control_interrupt: 0,
+        };
+    }
+
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        let state = &self.state;
+        match offset {
+            reg::DEVICE_VENDOR => (DEVICE_ID as u32) << 16 | VENDOR_ID as u32,
+            reg::STATUS_COMMAND => state.command,
+            reg::CLASS_REVISION => CLASS_CODE << 8,
+            reg::BIST_HEADER => HEADER_TYPE_BRIDGE,
+            reg::BUS_NUMBERS => state.bus_numbers,
+            reg::SEC_STATUS_IO => state.io,
+            reg::MEMORY => state.memory,
+            reg::PREFETCH => state.prefetch | PREFETCH_64BIT,
+            reg::PREFETCH_BASE_UPPER => state.prefetch_base_upper,
+            reg::PREFETCH_LIMIT_UPPER => state.prefetch_limit_upper,
+            reg::BRIDGE_CONTROL_INTERRUPT => state.bridge_control_interrupt,
+            // No BARs, capabilities, expansion ROM, or 32-bit I/O window.
+            _ => 0,
+        }
+    }
+
+    pub fn write_u32(&mut self, offset: u16, value: u32) {
+        let state = &mut self.state;
+        match offset {
+            reg::STATUS_COMMAND => state.command = value & COMMAND_MASK,
+            reg::BUS_NUMBERS => state.bus_numbers = value,
+            reg::SEC_STATUS_IO => state.io = value & IO_MASK,
+            reg::MEMORY => state.memory = value & MEMORY_MASK,
+            reg::PREFETCH => state.prefetch = value & MEMORY_MASK,
+            reg::PREFETCH_BASE_UPPER => state.prefetch_base_upper = value,
+            reg::PREFETCH_LIMIT_UPPER => state.prefetch_limit_upper = value,
+            reg::BRIDGE_CONTROL_INTERRUPT => {
+                state.bridge_control_interrupt = value & BRIDGE_CONTROL_INTERRUPT_MASK
+            }
+            _ => {}
+        }
+    }
+
+    pub fn save(&self) -> Vec<u32> {
+        let state = &self.state;
+        vec![
+            state.command,
+            state.bus_numbers,
+            state.io,
+            state.memory,
+            state.prefetch,
+            state.prefetch_base_upper,
+            state.prefetch_limit_upper,
+            state.bridge_control_interrupt,
+        ]
+    }
+
+    pub fn restore(&mut self, registers: &[u32]) -> Option<()> {
+        let &[
+            command,
+            bus_numbers,
+            io,
+            memory,
+            prefetch,
+            prefetch_base_upper,
+            prefetch_limit_upper,
+            bridge_control_interrupt,
+        ] = registers
+        else {
+            return None;
+        };
+        self.state = PciBridgeState {
+            command,
+            bus_numbers,
+            io,
+            memory,
+            prefetch,
+            prefetch_base_upper,
+            prefetch_limit_upper,
+            bridge_control_interrupt,
+        };
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bridge_registers() {
+        let mut bridge = PciBridge::new(0, 1);
+        assert_eq!(bridge.read_u32(reg::CLASS_REVISION), 0x0604_0000);
+        assert_eq!(bridge.read_u32(reg::BIST_HEADER), 0x0001_0000);
+        assert_eq!(bridge.read_u32(reg::BUS_NUMBERS), 0x0001_0100);
+        assert_eq!(bridge.secondary_bus(), 1);
+
+        bridge.extend_subordinate_bus(3);
+        assert_eq!(bridge.read_u32(reg::BUS_NUMBERS), 0x0003_0100);
+
+        // The guest renumbers the buses.
+        bridge.write_u32(reg::BUS_NUMBERS, 0x0005_0400);
+        assert_eq!(bridge.secondary_bus(), 4);
+
+        bridge.write_u32(reg::MEMORY, !0);
+        assert_eq!(bridge.read_u32(reg::MEMORY), MEMORY_MASK);
+        bridge.write_u32(reg::PREFETCH, 0);
+        assert_eq!(bridge.read_u32(reg::PREFETCH), PREFETCH_64BIT);
+
+        let saved = bridge.save();
+        bridge.reset();
+        assert_eq!(bridge.read_u32(reg::BUS_NUMBERS), 0x0003_0100);
+        assert_eq!(bridge.read_u32(reg::MEMORY), 0);
+        bridge.restore(&saved).unwrap();
+        assert_eq!(bridge.secondary_bus(), 4);
+        assert_eq!(bridge.read_u32(reg::MEMORY), MEMORY_MASK);
+    }
+}
@@ -12,11 +12,15 @@
 //! etc...
 //!
 //! Incoming config space accesses are then routed to connected
-//! [`GenericPciBusDevice`] devices.
+//! [`GenericPciBusDevice`] devices, including devices on secondary buses
+//! behind emulated PCI-to-PCI bridges.
 
 #![forbid(unsafe_code)]
 
+mod bridge;
+
 use bitfield_struct::bitfield;
+use bridge::PciBridge;
 use chipset_device::ChipsetDevice;
 use chipset_device::io::IoError;
 use chipset_device::io::IoResult;
@@ -25,6 +29,9 @@
 use chipset_device::io::deferred::DeferredWrite;
 use chipset_device::io::deferred::defer_read;
 use chipset_device::io::deferred::defer_write;
+use chipset_device::mmio::ControlMmioIntercept;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
 use chipset_device::pio::ControlPortIoIntercept;
 use chipset_device::pio::PortIoIntercept;
 use chipset_device::pio::RegisterPortIoIntercept;
@@ -35,6 +42,7 @@
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use thiserror::Error;
 use vmcore::device_state::ChangeDeviceState;
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
@@ -49,6 +57,12 @@ pub mod standard_x86_io_ports {
     pub const DATA_END: u16 = 0xCFF;
 }
 
+/// The config space register holding the header type, in bits 16..24.
+const HEADER_TYPE_REGISTER: u16 = 0xc;
+
+/// The multi-function bit of the header type, within its register.
+const HEADER_TYPE_MULTI_FUNCTION: u32 = 0x80 << 16;
+
 /// An abstract interface for a PCI device accessed via the [`GenericPciBus`].
 ///
 /// This trait is nearly identical to [`chipset_device::pci::PciConfigSpace`],
@@ -112,6 +126,9 @@ struct GenericPciBusState {
 // If a fully sized write is issued and gets deferred, that does not result in a
 // `DeferredAction::Write`. Instead it is simply returned up the stack to let our
 // caller handle it, as we don't need to perform any extra work after completion.
+//
+// The actions record the target address and register rather than re-reading
+// them from the address register, as accesses may come in via ECAM as well.
 #[derive(Inspect)]
 #[inspect(tag = "kind")]
 enum DeferredAction {
@@ -121,8 +138,10 @@ enum DeferredAction {
         #[inspect(skip)]
         bus_read: DeferredRead,
         read_len: usize,
-        io_port: u16,
+        byte_offset: u8,
         address: PciAddr,
+        register: u16,
+        fixup: u32,
     },
     ReadForWrite {
         #[inspect(skip)]
@@ -130,9 +149,10 @@ enum DeferredAction {
         #[inspect(skip)]
         bus_write: DeferredWrite,
         write_len: usize,
-        io_port: u16,
+        byte_offset: u8,
         new_value: u32,
         address: PciAddr,
+        register: u16,
     },
     Write {
         #[inspect(skip)]
@@ -141,6 +161,7 @@ enum DeferredAction {
         bus_write: DeferredWrite,
         value: u32,
         address: PciAddr,
+        register: u16,
     },
 }
 
@@ -150,8 +171,12 @@ pub struct GenericPciBus {
     // Runtime glue
     pio_addr: Box<dyn ControlPortIoIntercept>,
     pio_data: Box<dyn ControlPortIoIntercept>,
+    #[inspect(with = "|x| x.as_ref().and_then(|x| x.addr())")]
+    ecam: Option<Box<dyn ControlMmioIntercept>>,
     #[inspect(with = "|x| inspect::iter_by_key(x).map_value(|(name, _)| name)")]
     pci_devices: BTreeMap<PciAddr, (Arc<str>, Box<dyn GenericPciBusDevice>)>,
+    #[inspect(with = "|x| inspect::iter_by_key(x).map_value(|(_, bridge)| bridge)")]
+    bridges: BTreeMap<PciAddr, (Arc<str>, PciBridge)>,
 
     // Async bookkeeping
     #[inspect(with = "|x| x.is_some()")]
@@ -176,7 +201,9 @@ pub fn new(
         GenericPciBus {
             pio_addr: addr_control,
             pio_data: data_control,
+            ecam: None,
             pci_devices: BTreeMap::new(),
+            bridges: BTreeMap::new(),
 
             waker: None,
             deferred_action: None,
@@ -187,6 +214,23 @@ pub fn new(
         }
     }
 
+    /// Additionally expose the bus's config space via an ECAM window at `base`,
+    /// covering buses `0..=end_bus`.
+    ///
+    /// ECAM is the only way for a guest to reach the extended (0x100..0x1000)
+    /// portion of a device's config space.
+    pub fn with_ecam(
+        mut self,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        base: u64,
+        end_bus: u8,
+    ) -> Self {
+        let mut control = register_mmio.new_io_region("ecam", ecam_len(end_bus));
+        control.map(base);
+        self.ecam = Some(control);
+        self
+    }
+
     /// Try to add a PCI device, returning (device, existing_device_name) if the
     /// slot is already occupied.
     pub fn add_pci_device<D: GenericPciBusDevice>(
@@ -203,8 +247,8 @@ pub fn add_pci_device<D: GenericPciBusDevice>(
             function,
         };
 
-        if let Some((name, _)) = self.pci_devices.get(&key) {
-            return Err((dev, name.clone()));
+        if let Some(name) = self.slot_owner(key) {
+            return Err((dev, name));
         }
 
         self.pci_devices
@@ -212,6 +256,104 @@ pub fn add_pci_device<D: GenericPciBusDevice>(
         Ok(())
     }
 
+    /// Add a PCI-to-PCI bridge at `bus:device.function`, leading to
+    /// `secondary_bus`.
+    ///
+    /// Devices added on `secondary_bus` are reached through the bridge, at
+    /// whatever secondary bus number the guest programs into it. `bus` must
+    /// be 0 or the secondary bus of a bridge that was already added.
+    pub fn add_pci_bridge(
+        &mut self,
+        bus: u8,
+        device: u8,
+        function: u8,
+        name: impl AsRef<str>,
+        secondary_bus: u8,
+    ) -> Result<(), AddBridgeError> {
+        let key = PciAddr {
+            bus,
+            device,
+            function,
+        };
+
+        if let Some(name) = self.slot_owner(key) {
+            return Err(AddBridgeError::SlotInUse(name));
+        }
+        if secondary_bus <= bus
+            || self
+                .bridges
+                .values()
+                .any(|(_, bridge)| bridge.secondary_bus_at_reset == secondary_bus)
+        {
+            return Err(AddBridgeError::InvalidSecondaryBus(secondary_bus));
+        }
+
+        // Extend the bus ranges of the bridges leading to this one.
+        let mut parent_bus = bus;
+        while parent_bus != 0 {
+            let (_, parent) = self
+                .bridges
+                .values_mut()
+                .find(|(_, bridge)| bridge.secondary_bus_at_reset == parent_bus)
+                .ok_or(AddBridgeError::NoParentBridge(bus))?;
+            parent.extend_subordinate_bus(secondary_bus);
+            parent_bus = parent.primary_bus_at_reset;
+        }
+
+        self.bridges.insert(
+            key,
+            (name.as_ref().into(), PciBridge::new(bus, secondary_bus)),
+        );
+        Ok(())
+    }
+
+    /// Returns the name of the device or bridge at `address`, if any.
+    fn slot_owner(&self, address: PciAddr) -> Option<Arc<str>> {
+        self.pci_devices
+            .get(&address)
+            .map(|(name, _)| name)
+            .or_else(|| self.bridges.get(&address).map(|(name, _)| name))
+            .cloned()
+    }
+
+    /// Translates the bus number of a config space access, as currently
+    /// programmed into the bridges by the guest, into the bus number that
+    /// devices were added with. Returns `None` if no bridge claims the bus.
+    fn route(&self, address: PciAddr) -> Option<PciAddr> {
+        if address.bus == 0 {
+            return Some(address);
+        }
+        self.bridges
+            .values()
+            .find(|(_, bridge)| bridge.secondary_bus() == address.bus)
+            .map(|(_, bridge)| PciAddr {
+                bus: bridge.secondary_bus_at_reset,
+                ..address
+            })
+    }
+
+    /// Returns the bits to set in a config space read of `register` at
+    /// `address`.
+    ///
+    /// Function 0 of a device with other functions reports the multi-function
+    /// bit in its header type, so that the guest scans the other functions.
+    fn read_fixup(&self, address: PciAddr, register: u16) -> u32 {
+        if address.function == 0
+            && register == HEADER_TYPE_REGISTER
+            && (1..8).any(|function| {
+                self.slot_owner(PciAddr {
+                    function,
+                    ..address
+                })
+                .is_some()
+            })
+        {
+            HEADER_TYPE_MULTI_FUNCTION
+        } else {
+            0
+        }
+    }
+
     /// Handle a read from the ADDR register
     fn handle_addr_read(&self, value: &mut u32) -> IoResult {
         *value = self.state.pio_addr_reg.0;
@@ -230,27 +372,26 @@ fn handle_addr_write(&mut self, addr: u32) -> IoResult {
         IoResult::Ok
     }
 
-    /// Handle a read from the DATA register
-    fn handle_data_read(&mut self, value: &mut u32) -> IoResult {
-        tracing::trace!(%self.state.pio_addr_reg, "data read");
+    /// Handle a dword read of `register` in the config space of `address`.
+    fn handle_cfg_read(&mut self, address: PciAddr, register: u16, value: &mut u32) -> IoResult {
+        let fixup = self.read_fixup(address, register);
 
-        if !self.state.pio_addr_reg.enabled() {
-            tracelimit::warn_ratelimited!("addr enable bit is set to disabled");
-            *value = !0;
+        if let Some((_, bridge)) = self.bridges.get(&address) {
+            *value = bridge.read_u32(register) | fixup;
             return IoResult::Ok;
         }
 
-        let address = self.state.pio_addr_reg.address();
-
         match self.pci_devices.get_mut(&address) {
             Some((name, device)) => {
-                let offset = self.state.pio_addr_reg.register().into();
-                let res = device.pci_cfg_read(offset, value);
+                let res = device.pci_cfg_read(register, value);
                 if let Some(result) = res {
+                    if matches!(result, IoResult::Ok) {
+                        *value |= fixup;
+                    }
                     tracing::trace!(
                         device = &**name,
                         %address,
-                        offset,
+                        offset = register,
                         value,
                         "cfg space read"
                     );
@@ -262,7 +403,7 @@ fn handle_data_read(&mut self, value: &mut u32) -> IoResult {
                     tracelimit::warn_ratelimited!(
                         device = &**name,
                         %address,
-                        offset,
+                        offset = register,
                         "cfg space read failed, device went away"
                     );
                     *value = !0;
@@ -277,25 +418,21 @@ fn handle_data_read(&mut self, value: &mut u32) -> IoResult {
         }
     }
 
-    /// Handler a write to the DATA register
-    fn handle_data_write(&mut self, data: u32) -> IoResult {
-        tracing::trace!(%self.state.pio_addr_reg, "data write");
-
-        if !self.state.pio_addr_reg.enabled() {
-            tracelimit::warn_ratelimited!("addr enable bit is set to disabled");
+    /// Handle a dword write of `register` in the config space of `address`.
+    fn handle_cfg_write(&mut self, address: PciAddr, register: u16, data: u32) -> IoResult {
+        if let Some((_, bridge)) = self.bridges.get_mut(&address) {
+            bridge.write_u32(register, data);
             return IoResult::Ok;
         }
 
-        let address = self.state.pio_addr_reg.address();
         match self.pci_devices.get_mut(&address) {
             Some((name, device)) => {
-                let offset = self.state.pio_addr_reg.register().into();
-                let res = device.pci_cfg_write(offset, data);
+                let res = device.pci_cfg_write(register, data);
                 if let Some(result) = res {
                     tracing::trace!(
                         device = &**name,
                         %address,
-                        offset,
+                        offset = register,
                         data,
                         "cfg space write"
                     );
@@ -307,7 +444,7 @@ fn handle_data_write(&mut self, data: u32) -> IoResult {
                     tracelimit::warn_ratelimited!(
                         device = &**name,
                         %address,
-                        offset,
+                        offset = register,
                         "cfg space write failed, device went away"
                     );
                     IoResult::Ok
@@ -320,25 +457,161 @@ fn handle_data_write(&mut self, data: u32) -> IoResult {
         }
     }
 
-    fn trace_error(&self, e: IoError, operation: &'static str) {
+    /// Perform a (possibly sub-dword) config space read, shared by the port IO
+    /// and ECAM access mechanisms.
+    fn cfg_read(
+        &mut self,
+        address: PciAddr,
+        register: u16,
+        byte_offset: u8,
+        data: &mut [u8],
+    ) -> IoResult {
+        let Some(address) = self.route(address) else {
+            tracing::trace!(%address, "no bridge for bus - returning F's");
+            data.fill(!0);
+            return IoResult::Ok;
+        };
+
+        let mut value = 0;
+        match self.handle_cfg_read(address, register, &mut value) {
+            IoResult::Ok => {
+                let value = shift_read_value(byte_offset, data.len(), value);
+                data.copy_from_slice(&value.as_bytes()[..data.len()]);
+                IoResult::Ok
+            }
+            IoResult::Err(e) => {
+                self.trace_error(address, register, e, "read");
+                // Regardless of the pci error that occurred we return all zeros.
+                // This is technically device-specific behavior, but it's what all
+                // hyper-v devices do and it's worked for us so far.
+                data.zero();
+                IoResult::Ok
+            }
+            IoResult::Defer(deferred_device_read) => {
+                let (bus_read, bus_token) = defer_read();
+                assert!(self.deferred_action.is_none());
+                self.deferred_action = Some(DeferredAction::Read {
+                    deferred_device_read,
+                    bus_read,
+                    read_len: data.len(),
+                    byte_offset,
+                    address,
+                    register,
+                    fixup: self.read_fixup(address, register),
+                });
+                if let Some(waker) = self.waker.take() {
+                    waker.wake();
+                }
+                IoResult::Defer(bus_token)
+            }
+        }
+    }
+
+    /// Perform a (possibly sub-dword) config space write, shared by the port
+    /// IO and ECAM access mechanisms.
+    fn cfg_write(
+        &mut self,
+        address: PciAddr,
+        register: u16,
+        byte_offset: u8,
+        data: &[u8],
+    ) -> IoResult {
+        let Some(address) = self.route(address) else {
+            tracing::debug!(%address, "no bridge for bus");
+            return IoResult::Ok;
+        };
+
+        let new_value = {
+            let mut temp: u32 = 0;
+            temp.as_mut_bytes()[..data.len()].copy_from_slice(data);
+            temp
+        };
+
+        let merged_value = if data.len() == 4 {
+            new_value
+        } else {
+            // If the access isn't a double word, read in the old data
+            // to form a full word.
+            //
+            // Note that this isn't *really* correct, because reading
+            // bits may have a side-effect. Also, writing to bits that
+            // weren't actually written to may have side-effects...
+            //
+            // However, this technique appears to work fine for
+            // everything we've encountered so far ¯\_(ツ)_/¯
+            let mut old_value = 0;
+            match self.handle_cfg_read(address, register, &mut old_value) {
+                IoResult::Ok => {
+                    combine_old_new_values(byte_offset, old_value, new_value, data.len())
+                }
+                IoResult::Err(e) => {
+                    self.trace_error(address, register, e, "read for undersized write");
+                    // Regardless of the pci error that occurred, we return all zeros.
+                    // This is technically device-specific behavior, but it's what all
+                    // hyper-v devices do and it's worked for us so far.
+                    0
+                }
+                IoResult::Defer(deferred_device_read) => {
+                    let (bus_write, bus_token) = defer_write();
+                    assert!(self.deferred_action.is_none());
+                    self.deferred_action = Some(DeferredAction::ReadForWrite {
+                        deferred_device_read,
+                        bus_write,
+                        write_len: data.len(),
+                        byte_offset,
+                        new_value,
+                        address,
+                        register,
+                    });
+                    if let Some(waker) = self.waker.take() {
+                        waker.wake();
+                    }
+                    return IoResult::Defer(bus_token);
+                }
+            }
+        };
+
+        let write_result = self.handle_cfg_write(address, register, merged_value);
+        match write_result {
+            IoResult::Err(e) => {
+                self.trace_error(address, register, e, "write");
+                IoResult::Ok
+            }
+            IoResult::Ok | IoResult::Defer(_) => {
+                // If the write was successful we're all set.
+                // If the write is deferred we have no extra work to do after
+                // it resolves, unlike with read, so we can just return it and
+                // let the motherboard poll.
+                write_result
+            }
+        }
+    }
+
+    fn trace_error(&self, address: PciAddr, register: u16, e: IoError, operation: &'static str) {
         let error = match e {
             IoError::InvalidRegister => "offset not supported",
             IoError::InvalidAccessSize => "invalid access size",
             IoError::UnalignedAccess => "unaligned access",
         };
         tracelimit::warn_ratelimited!(
-            address = %self.state.pio_addr_reg.address(),
-            offset = self.state.pio_addr_reg.register(),
+            %address,
+            offset = register,
             "pci config space {} operation error: {}",
             operation,
             error
         );
     }
 
-    fn trace_recv_error(&self, e: mesh::RecvError, operation: &'static str) {
+    fn trace_recv_error(
+        &self,
+        address: PciAddr,
+        register: u16,
+        e: mesh::RecvError,
+        operation: &'static str,
+    ) {
         tracelimit::warn_ratelimited!(
-            address = %self.state.pio_addr_reg.address(),
-            offset = self.state.pio_addr_reg.register(),
+            %address,
+            offset = register,
             "pci config space {} operation recv error: {:?}",
             operation,
             e,
@@ -353,6 +626,9 @@ async fn stop(&mut self) {}
 
     async fn reset(&mut self) {
         self.state.pio_addr_reg = AddressRegister::new();
+        for (_, bridge) in self.bridges.values_mut() {
+            bridge.reset();
+        }
     }
 }
 
@@ -361,13 +637,40 @@ fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
         Some(self)
     }
 
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        if self.ecam.is_some() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
     fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
         Some(self)
     }
 }
 
-fn shift_read_value(io_port: u16, len: usize, value: u32) -> u32 {
-    let shift = (io_port & 0x3) * 8;
+/// Error returned by [`GenericPciBus::add_pci_bridge`].
+#[derive(Debug, Error)]
+pub enum AddBridgeError {
+    /// The slot is already occupied.
+    #[error("slot is already occupied by {0}")]
+    SlotInUse(Arc<str>),
+    /// The secondary bus is in use or is not above the bridge's own bus.
+    #[error("invalid secondary bus {0}")]
+    InvalidSecondaryBus(u8),
+    /// No bridge leads to the bridge's bus.
+    #[error("no bridge leads to bus {0}")]
+    NoParentBridge(u8),
+}
+
+/// Returns the length of an ECAM window covering buses `0..=end_bus`.
+pub fn ecam_len(end_bus: u8) -> u64 {
+    (end_bus as u64 + 1) << 20
+}
+
+fn shift_read_value(byte_offset: u8, len: usize, value: u32) -> u32 {
+    let shift = (byte_offset & 0x3) * 8;
     match len {
         4 => value,
         2 => value >> shift & 0xFFFF,
@@ -376,65 +679,55 @@ fn shift_read_value(io_port: u16, len: usize, value: u32) -> u32 {
     }
 }
 
-fn combine_old_new_values(io_port: u16, old_value: u32, new_value: u32, len: usize) -> u32 {
-    let shift = (io_port & 0x3) * 8;
+fn combine_old_new_values(byte_offset: u8, old_value: u32, new_value: u32, len: usize) -> u32 {
+    let shift = (byte_offset & 0x3) * 8;
     let mask = (1 << (len * 8)) - 1;
     (old_value & !(mask << shift)) | (new_value << shift)
 }
 
+/// Validates the size and alignment of a config space access.
+fn check_access(offset: u64, len: usize) -> Result<(), IoError> {
+    if !matches!(len, 1 | 2 | 4) {
+        return Err(IoError::InvalidAccessSize);
+    }
+
+    if !(len == 4 && offset & 3 == 0 || len == 2 && offset & 1 == 0 || len == 1) {
+        return Err(IoError::UnalignedAccess);
+    }
+
+    Ok(())
+}
+
 impl PortIoIntercept for GenericPciBus {
     fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
-        if !matches!(data.len(), 1 | 2 | 4) {
-            return IoResult::Err(IoError::InvalidAccessSize);
-        }
-
-        if !(data.len() == 4 && io_port & 3 == 0
-            || data.len() == 2 && io_port & 1 == 0
-            || data.len() == 1)
-        {
-            return IoResult::Err(IoError::UnalignedAccess);
+        if let Err(e) = check_access(io_port.into(), data.len()) {
+            return IoResult::Err(e);
         }
 
-        let mut value = 0;
-        let res = match io_port {
-            _ if self.pio_addr.offset_of(io_port).is_some() => self.handle_addr_read(&mut value),
-            _ if self.pio_data.offset_of(io_port).is_some() => self.handle_data_read(&mut value),
-            _ => {
-                return IoResult::Err(IoError::InvalidRegister);
-            }
-        };
-
-        tracing::trace!(?io_port, ?res, ?data, "io port read");
+        tracing::trace!(?io_port, len = data.len(), "io port read");
 
-        match res {
-            IoResult::Ok => {
-                let value = shift_read_value(io_port, data.len(), value);
+        match io_port {
+            _ if self.pio_addr.offset_of(io_port).is_some() => {
+                let mut value = 0;
+                self.handle_addr_read(&mut value).unwrap();
+                let value = shift_read_value(io_port as u8, data.len(), value);
                 data.copy_from_slice(&value.as_bytes()[..data.len()]);
                 IoResult::Ok
             }
-            IoResult::Err(e) => {
-                self.trace_error(e, "read");
-                // Regardless of the pci error that occurred we return all zeros.
-                // This is technically device-specific behavior, but it's what all
-                // hyper-v devices do and it's worked for us so far.
-                data.zero();
-                IoResult::Ok
-            }
-            IoResult::Defer(deferred_device_read) => {
-                let (bus_read, bus_token) = defer_read();
-                assert!(self.deferred_action.is_none());
-                self.deferred_action = Some(DeferredAction::Read {
-                    deferred_device_read,
-                    bus_read,
-                    read_len: data.len(),
-                    io_port,
-                    address: self.state.pio_addr_reg.address(),
-                });
-                if let Some(waker) = self.waker.take() {
-                    waker.wake();
+            _ if self.pio_data.offset_of(io_port).is_some() => {
+                tracing::trace!(%self.state.pio_addr_reg, "data read");
+
+                if !self.state.pio_addr_reg.enabled() {
+                    tracelimit::warn_ratelimited!("addr enable bit is set to disabled");
+                    data.fill(!0);
+                    return IoResult::Ok;
                 }
-                IoResult::Defer(bus_token)
+
+                let address = self.state.pio_addr_reg.address();
+                let register = self.state.pio_addr_reg.register().into();
+                self.cfg_read(address, register, io_port as u8, data)
             }
+            _ => IoResult::Err(IoError::InvalidRegister),
         }
     }
 
@@ -443,16 +736,16 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
             return IoResult::Err(IoError::InvalidAccessSize);
         }
 
-        let new_value = {
-            let mut temp: u32 = 0;
-            temp.as_mut_bytes()[..data.len()].copy_from_slice(data);
-            temp
-        };
-
-        tracing::trace!(?io_port, data = ?new_value, "io port write");
+        tracing::trace!(?io_port, ?data, "io port write");
 
         match io_port {
             _ if self.pio_addr.offset_of(io_port).is_some() => {
+                let new_value = {
+                    let mut temp: u32 = 0;
+                    temp.as_mut_bytes()[..data.len()].copy_from_slice(data);
+                    temp
+                };
+
                 // In theory, only 4-byte accesses are valid here, but
                 // RedHat Linux modifies the bottom byte of the PCI
                 // configuration address by using a 1-byte access
@@ -471,69 +764,59 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
                 self.handle_addr_write(v)
             }
             _ if self.pio_data.offset_of(io_port).is_some() => {
-                let merged_value = if data.len() == 4 {
-                    new_value
-                } else {
-                    // If the access isn't a double word, read in the old data
-                    // to form a full word.
-                    //
-                    // Note that this isn't *really* correct, because reading
-                    // bits may have a side-effect. Also, writing to bits that
-                    // weren't actually written to may have side-effects...
-                    //
-                    // However, this technique appears to work fine for
-                    // everything we've encountered so far ¯\_(ツ)_/¯
-                    let mut old_value = 0;
-                    match self.handle_data_read(&mut old_value) {
-                        IoResult::Ok => {
-                            combine_old_new_values(io_port, old_value, new_value, data.len())
-                        }
-                        IoResult::Err(e) => {
-                            self.trace_error(e, "read for undersized write");
-                            // Regardless of the pci error that occurred, we return all zeros.
-                            // This is technically device-specific behavior, but it's what all
-                            // hyper-v devices do and it's worked for us so far.
-                            0
-                        }
-                        IoResult::Defer(deferred_device_read) => {
-                            let (bus_write, bus_token) = defer_write();
-                            assert!(self.deferred_action.is_none());
-                            self.deferred_action = Some(DeferredAction::ReadForWrite {
-                                deferred_device_read,
-                                bus_write,
-                                write_len: data.len(),
-                                io_port,
-                                new_value,
-                                address: self.state.pio_addr_reg.address(),
-                            });
-                            if let Some(waker) = self.waker.take() {
-                                waker.wake();
-                            }
-                            return IoResult::Defer(bus_token);
-                        }
-                    }
-                };
+                tracing::trace!(%self.state.pio_addr_reg, "data write");
 
-                let write_result = self.handle_data_write(merged_value);
-                match write_result {
-                    IoResult::Err(e) => {
-                        self.trace_error(e, "write");
-                        IoResult::Ok
-                    }
-                    IoResult::Ok | IoResult::Defer(_) => {
-                        // If the write was successful we're all set.
-                        // If the write is deferred we have no extra work to do after
-                        // it resolves, unlike with read, so we can just return it and
-                        // let the motherboard poll.
-                        write_result
-                    }
+                if !self.state.pio_addr_reg.enabled() {
+                    tracelimit::warn_ratelimited!("addr enable bit is set to disabled");
+                    return IoResult::Ok;
                 }
+
+                let address = self.state.pio_addr_reg.address();
+                let register = self.state.pio_addr_reg.register().into();
+                self.cfg_write(address, register, io_port as u8, data)
             }
             _ => IoResult::Err(IoError::InvalidRegister),
         }
     }
 }
 
+/// Decodes an offset into the ECAM window into a function address, a dword
+/// aligned register, and the byte offset within that register.
+fn decode_ecam_offset(offset: u64) -> (PciAddr, u16, u8) {
+    let address = PciAddr {
+        bus: (offset >> 20) as u8,
+        device: ((offset >> 15) & 0x1f) as u8,
+        function: ((offset >> 12) & 0x7) as u8,
+    };
+    (address, (offset & 0xffc) as u16, (offset & 0x3) as u8)
+}
+
+impl MmioIntercept for GenericPciBus {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        let Some(offset) = self.ecam.as_ref().and_then(|ecam| ecam.offset_of(addr)) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+        if let Err(e) = check_access(offset, data.len()) {
+            return IoResult::Err(e);
+        }
+
+        let (address, register, byte_offset) = decode_ecam_offset(offset);
+        self.cfg_read(address, register, byte_offset, data)
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        let Some(offset) = self.ecam.as_ref().and_then(|ecam| ecam.offset_of(addr)) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+        if let Err(e) = check_access(offset, data.len()) {
+            return IoResult::Err(e);
+        }
+
+        let (address, register, byte_offset) = decode_ecam_offset(offset);
+        self.cfg_write(address, register, byte_offset, data)
+    }
+}
+
 impl PollDevice for GenericPciBus {
     fn poll_device(&mut self, cx: &mut Context<'_>) {
         self.waker = Some(cx.waker().clone());
@@ -543,28 +826,32 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                     mut deferred_device_read,
                     bus_read,
                     read_len,
-                    io_port,
+                    byte_offset,
                     address,
+                    register,
+                    fixup,
                 } => {
                     let mut buf = 0;
                     if let Poll::Ready(res) = deferred_device_read.poll_read(cx, buf.as_mut_bytes())
                     {
                         let value = match res {
-                            Ok(()) => buf,
+                            Ok(()) => buf | fixup,
                             Err(e) => {
-                                self.trace_recv_error(e, "deferred read");
+                                self.trace_recv_error(address, register, e, "deferred read");
                                 0
                             }
                         };
-                        let value = shift_read_value(io_port, read_len, value);
+                        let value = shift_read_value(byte_offset, read_len, value);
                         bus_read.complete(&value.as_bytes()[..read_len]);
                     } else {
                         self.deferred_action = Some(DeferredAction::Read {
                             deferred_device_read,
                             bus_read,
                             read_len,
-                            io_port,
+                            byte_offset,
                             address,
+                            register,
+                            fixup,
                         });
                     }
                 }
@@ -572,9 +859,10 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                     mut deferred_device_read,
                     bus_write,
                     write_len,
-                    io_port,
+                    byte_offset,
                     new_value,
                     address,
+                    register,
                 } => {
                     let mut buf = 0;
                     if let Poll::Ready(res) = deferred_device_read.poll_read(cx, buf.as_mut_bytes())
@@ -582,18 +870,23 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                         let old_value = match res {
                             Ok(()) => buf,
                             Err(e) => {
-                                self.trace_recv_error(e, "deferred read for write");
+                                self.trace_recv_error(
+                                    address,
+                                    register,
+                                    e,
+                                    "deferred read for write",
+                                );
                                 0
                             }
                         };
                         let merged_value =
-                            combine_old_new_values(io_port, old_value, new_value, write_len);
-                        match self.handle_data_write(merged_value) {
+                            combine_old_new_values(byte_offset, old_value, new_value, write_len);
+                        match self.handle_cfg_write(address, register, merged_value) {
                             IoResult::Ok => {
                                 bus_write.complete();
                             }
                             IoResult::Err(e) => {
-                                self.trace_error(e, "write");
+                                self.trace_error(address, register, e, "write");
                                 bus_write.complete();
                             }
                             IoResult::Defer(deferred_device_write) => {
@@ -602,6 +895,7 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                                     bus_write,
                                     value: merged_value,
                                     address,
+                                    register,
                                 });
                                 cx.waker().wake_by_ref();
                             }
@@ -611,9 +905,10 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                             deferred_device_read,
                             bus_write,
                             write_len,
-                            io_port,
+                            byte_offset,
                             new_value,
                             address,
+                            register,
                         });
                     }
                 }
@@ -622,12 +917,13 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                     bus_write,
                     value,
                     address,
+                    register,
                 } => {
                     if let Poll::Ready(res) = deferred_device_write.poll_write(cx) {
                         match res {
                             Ok(()) => {}
                             Err(e) => {
-                                self.trace_recv_error(e, "deferred write");
+                                self.trace_recv_error(address, register, e, "deferred write");
                             }
                         }
                         bus_write.complete();
@@ -637,6 +933,7 @@ fn poll_device(&mut self, cx: &mut Context<'_>) {
                             bus_write,
                             value,
                             address,
+                            register,
                         });
                     }
                 }
@@ -697,6 +994,21 @@ mod state {
         pub struct SavedState {
             #[mesh(1)]
             pub pio_addr_reg: u32,
+            #[mesh(2)]
+            pub bridges: Vec<SavedBridgeState>,
+        }
+
+        #[derive(Protobuf)]
+        #[mesh(package = "pci.bus")]
+        pub struct SavedBridgeState {
+            #[mesh(1)]
+            pub bus: u8,
+            #[mesh(2)]
+            pub device: u8,
+            #[mesh(3)]
+            pub function: u8,
+            #[mesh(4)]
+            pub registers: Vec<u32>,
         }
     }
 
@@ -706,6 +1018,10 @@ enum GenericPciBusRestoreError {
         AddressNonZeroReserved,
         #[error("saved address contained non-dword aligned register bits")]
         AddressNotDwordAligned,
+        #[error("saved state for unknown bridge {0}")]
+        UnknownBridge(PciAddr),
+        #[error("invalid saved registers for bridge {0}")]
+        InvalidBridgeRegisters(PciAddr),
     }
 
     impl SaveRestore for GenericPciBus {
@@ -716,13 +1032,26 @@ fn save(&mut self) -> Result<Self::SavedState, SaveError> {
 
             let saved_state = state::SavedState {
                 pio_addr_reg: pio_addr_reg.into(),
+                bridges: self
+                    .bridges
+                    .iter()
+                    .map(|(address, (_, bridge))| state::SavedBridgeState {
+                        bus: address.bus,
+                        device: address.device,
+                        function: address.function,
+                        registers: bridge.save(),
+                    })
+                    .collect(),
             };
 
             Ok(saved_state)
         }
 
         fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
-            let state::SavedState { pio_addr_reg } = state;
+            let state::SavedState {
+                pio_addr_reg,
+                bridges,
+            } = state;
 
             self.state = GenericPciBusState {
                 pio_addr_reg: pio_addr_reg.into(),
@@ -743,6 +1072,30 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
                 }
             }
 
+            for state::SavedBridgeState {
+                bus,
+                device,
+                function,
+                registers,
+            } in bridges
+            {
+                let address = PciAddr {
+                    bus,
+                    device,
+                    function,
+                };
+                let (_, bridge) = self.bridges.get_mut(&address).ok_or_else(|| {
+                    RestoreError::InvalidSavedState(
+                        GenericPciBusRestoreError::UnknownBridge(address).into(),
+                    )
+                })?;
+                bridge.restore(&registers).ok_or_else(|| {
+                    RestoreError::InvalidSavedState(
+                        GenericPciBusRestoreError::InvalidBridgeRegisters(address).into(),
+                    )
+                })?;
+            }
+
             Ok(())
         }
     }
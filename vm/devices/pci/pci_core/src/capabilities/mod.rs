@@ -9,7 +9,9 @@
 use vmcore::save_restore::ProtobufSaveRestore;
 
 pub mod msix;
+pub mod pci_express;
 pub mod read_only;
+pub mod sriov;
 
 /// A generic PCI configuration space capability structure.
 pub trait PciCapability: Send + Sync + Inspect + ProtobufSaveRestore {
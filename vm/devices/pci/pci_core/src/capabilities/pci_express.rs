@@ -0,0 +1,201 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! PCI Express Capability.
+//!
+//! This describes an endpoint function. The only function it implements is
+//! Function Level Reset (FLR), which the device performs when it sees an
+//! [`FlrRequest`].
+
+use super::PciCapability;
+use crate::spec::caps::CapabilityId;
+use crate::spec::caps::pci_express::CAPABILITY_VERSION;
+use crate::spec::caps::pci_express::DEVICE_CAPS_FLR;
+use crate::spec::caps::pci_express::DEVICE_CTL_INITIATE_FLR;
+use crate::spec::caps::pci_express::PciExpressCapabilityHeader;
+use inspect::Inspect;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// The Device Control register's value at reset: a 512 byte maximum read
+/// request size, with relaxed ordering and no snoop enabled.
+const DEVICE_CTL_DEFAULT: u16 = 0x2810;
+
+/// A function level reset requested by the guest through a
+/// [`PciExpressCapability`].
+///
+/// The device keeps a clone and checks it with [`take`](Self::take) after each
+/// configuration space write.
+#[derive(Debug, Clone, Default)]
+pub struct FlrRequest(Arc<AtomicBool>);
+
+impl FlrRequest {
+    /// Returns a new request, initially not pending.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether a function level reset has been requested since the
+    /// last call, clearing the request.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// A PCI Express capability for an endpoint.
+#[derive(Debug, Inspect)]
+pub struct PciExpressCapability {
+    #[inspect(skip)]
+    flr: Option<FlrRequest>,
+    #[inspect(hex)]
+    device_control: u16,
+    #[inspect(hex)]
+    device_control_2: u16,
+}
+
+impl PciExpressCapability {
+    /// Returns a new capability. If `flr` is set, the capability reports
+    /// support for function level reset and sets the request when the guest
+    /// initiates one.
+    pub fn new(flr: Option<FlrRequest>) -> Self {
+        Self {
+            flr,
+            device_control: DEVICE_CTL_DEFAULT,
+            device_control_2: 0,
+        }
+    }
+}
+
+impl PciCapability for PciExpressCapability {
+    fn label(&self) -> &str {
+        "pci-express"
+    }
+
+    fn len(&self) -> usize {
+        0x3c
+    }
+
+    fn read_u32(&self, offset: u16) -> u32 {
+        match PciExpressCapabilityHeader(offset) {
+            PciExpressCapabilityHeader::PCIE_CAPS => {
+                // Device/port type 0: PCI Express endpoint.
+                CapabilityId::PCI_EXPRESS.0 as u32 | (CAPABILITY_VERSION as u32) << 16
+            }
+            PciExpressCapabilityHeader::DEVICE_CAPS => {
+                if self.flr.is_some() {
+                    DEVICE_CAPS_FLR
+                } else {
+                    0
+                }
+            }
+            PciExpressCapabilityHeader::DEVICE_CTL_STS => self.device_control as u32,
+            PciExpressCapabilityHeader::DEVICE_CTL_STS_2 => self.device_control_2 as u32,
+            _ => 0,
+        }
+    }
+
+    fn write_u32(&mut self, offset: u16, val: u32) {
+        match PciExpressCapabilityHeader(offset) {
+            PciExpressCapabilityHeader::DEVICE_CTL_STS => {
+                // The status bits are all clear, so ignore writes to clear
+                // them.
+                let control = val as u16;
+                if control & DEVICE_CTL_INITIATE_FLR != 0 {
+                    if let Some(flr) = &self.flr {
+                        flr.0.store(true, Ordering::Relaxed);
+                    } else {
+                        tracelimit::warn_ratelimited!("function level reset not supported");
+                    }
+                }
+                self.device_control = control & !DEVICE_CTL_INITIATE_FLR;
+            }
+            PciExpressCapabilityHeader::DEVICE_CTL_STS_2 => {
+                self.device_control_2 = val as u16;
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(
+                    offset,
+                    val,
+                    "write to read-only pci express capability register"
+                );
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.device_control = DEVICE_CTL_DEFAULT;
+        self.device_control_2 = 0;
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Debug, Protobuf, SavedStateRoot)]
+        #[mesh(package = "pci.caps.pci_express")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub device_control: u16,
+            #[mesh(2)]
+            pub device_control_2: u16,
+        }
+    }
+
+    impl SaveRestore for PciExpressCapability {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            Ok(state::SavedState {
+                device_control: self.device_control,
+                device_control_2: self.device_control_2,
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                device_control,
+                device_control_2,
+            } = state;
+            self.device_control = device_control;
+            self.device_control_2 = device_control_2;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flr() {
+        let flr = FlrRequest::new();
+        let mut cap = PciExpressCapability::new(Some(flr.clone()));
+        assert_eq!(cap.read_u32(0) & 0xff, CapabilityId::PCI_EXPRESS.0 as u32);
+        assert_ne!(cap.read_u32(4) & DEVICE_CAPS_FLR, 0);
+
+        cap.write_u32(8, 0x2010);
+        assert!(!flr.take());
+        assert_eq!(cap.read_u32(8), 0x2010);
+
+        // The initiate bit triggers the reset and always reads as zero.
+        cap.write_u32(8, 0x2010 | DEVICE_CTL_INITIATE_FLR as u32);
+        assert!(flr.take());
+        assert!(!flr.take());
+        assert_eq!(cap.read_u32(8), 0x2010);
+
+        cap.reset();
+        assert_eq!(cap.read_u32(8), DEVICE_CTL_DEFAULT as u32);
+
+        let cap = PciExpressCapability::new(None);
+        assert_eq!(cap.read_u32(4) & DEVICE_CAPS_FLR, 0);
+    }
+}
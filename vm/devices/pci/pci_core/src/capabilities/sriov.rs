@@ -0,0 +1,460 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Single Root I/O Virtualization (SR-IOV) Extended Capability.
+//!
+//! The physical function exposes a [`SriovCapability`] in its extended
+//! configuration space. Each virtual function is a separate device on the same
+//! bus, at the function numbers following the physical function's, which holds
+//! a [`VirtualFunction`]. Virtual functions have no BARs of their own: they
+//! register their BAR regions through the [`VirtualFunction`], which maps them
+//! at the addresses the guest programs into the physical function's VF BARs
+//! while VF Enable and VF Memory Space Enable are set.
+
+use super::PciCapability;
+use crate::bar_mapping::BarMappings;
+use crate::spec::cfg_space;
+use crate::spec::ext_caps::ExtendedCapabilityId;
+use crate::spec::ext_caps::sriov::CAPABILITY_LEN;
+use crate::spec::ext_caps::sriov::CAPABILITY_VERSION;
+use crate::spec::ext_caps::sriov::CONTROL_ARI_CAPABLE;
+use crate::spec::ext_caps::sriov::CONTROL_VF_ENABLE;
+use crate::spec::ext_caps::sriov::CONTROL_VF_MSE;
+use crate::spec::ext_caps::sriov::PAGE_SIZE_4K;
+use crate::spec::ext_caps::sriov::SriovCapabilityHeader;
+use chipset_device::mmio::ControlMmioIntercept;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// The maximum number of virtual functions.
+///
+/// ARI is not supported, so the virtual functions are functions 1 through 7 of
+/// the physical function's device.
+pub const MAX_VFS: u16 = 7;
+
+/// The routing ID offset of the first virtual function from the physical
+/// function.
+const FIRST_VF_OFFSET: u16 = 1;
+
+/// The routing ID offset between consecutive virtual functions.
+const VF_STRIDE: u16 = 1;
+
+const CONTROL_MASK: u16 = CONTROL_VF_ENABLE | CONTROL_VF_MSE | CONTROL_ARI_CAPABLE;
+
+#[derive(Inspect)]
+struct SriovState {
+    // Fixed configuration
+    total_vfs: u16,
+    #[inspect(hex)]
+    vf_device_id: u16,
+    #[inspect(with = r#"|x| inspect::AsHex(inspect::iter_by_index(x).prefix("bar"))"#)]
+    bar_masks: [u32; 6],
+
+    // Runtime glue
+    #[inspect(skip)]
+    vf_regions: Vec<[Option<Box<dyn ControlMmioIntercept>>; 6]>,
+
+    // Runtime book-keeping
+    /// The BARs of the first virtual function. Those of the others follow at
+    /// multiples of the BAR size. Empty unless the VF BARs are enabled.
+    active_bars: BarMappings,
+
+    // Volatile state
+    #[inspect(hex)]
+    control: u16,
+    num_vfs: u16,
+    #[inspect(hex)]
+    system_page_size: u32,
+    #[inspect(with = r#"|x| inspect::AsHex(inspect::iter_by_index(x).prefix("bar"))"#)]
+    bar_addresses: [u32; 6],
+}
+
+impl SriovState {
+    fn reset(&mut self) {
+        self.control = 0;
+        self.num_vfs = 0;
+        self.system_page_size = PAGE_SIZE_4K;
+        self.bar_addresses = [0; 6];
+        self.update_mappings();
+    }
+
+    fn vf_memory_enabled(&self) -> bool {
+        self.control & (CONTROL_VF_ENABLE | CONTROL_VF_MSE) == CONTROL_VF_ENABLE | CONTROL_VF_MSE
+    }
+
+    /// Maps the BAR regions of the enabled virtual functions at the addresses
+    /// programmed into the VF BARs, or unmaps them all if the VF BARs are
+    /// disabled.
+    fn update_mappings(&mut self) {
+        for region in self.vf_regions.iter_mut().flatten().flatten() {
+            region.unmap();
+        }
+
+        if !self.vf_memory_enabled() {
+            self.active_bars = Default::default();
+            return;
+        }
+
+        self.active_bars = BarMappings::parse(&self.bar_addresses, &self.bar_masks);
+        for (index, regions) in self
+            .vf_regions
+            .iter_mut()
+            .enumerate()
+            .take(self.num_vfs.into())
+        {
+            for mapping in self.active_bars.iter() {
+                if let Some(region) = &mut regions[mapping.index as usize] {
+                    region.map(mapping.base_address + index as u64 * mapping.len);
+                }
+            }
+        }
+    }
+}
+
+/// An SR-IOV extended capability, for a physical function.
+#[derive(Inspect)]
+pub struct SriovCapability {
+    #[inspect(flatten)]
+    state: Arc<Mutex<SriovState>>,
+}
+
+impl SriovCapability {
+    /// Returns a new capability for `total_vfs` virtual functions with device
+    /// ID `vf_device_id`, along with a handle for each virtual function.
+    ///
+    /// Each virtual function has the 64-bit memory BARs given by `bars`, as
+    /// `(bar index, len)`.
+    pub fn new(
+        vf_device_id: u16,
+        total_vfs: u16,
+        bars: &[(u8, u64)],
+    ) -> (Self, Vec<VirtualFunction>) {
+        assert!(
+            (1..=MAX_VFS).contains(&total_vfs),
+            "unsupported number of virtual functions"
+        );
+
+        let mut bar_masks = [0; 6];
+        for &(bar_index, len) in bars {
+            let bar_index = bar_index as usize;
+            // use 64-bit aware BARs
+            assert!(bar_index < 5);
+            // Round up to a power of 2 of at least one page, as for the BARs
+            // of a regular function.
+            const MIN_BAR_SIZE: u64 = 4096;
+            let len = std::cmp::max(len.next_power_of_two(), MIN_BAR_SIZE);
+            let mask64 = !(len - 1);
+            bar_masks[bar_index] = cfg_space::BarEncodingBits::from_bits(mask64 as u32)
+                .with_type_64_bit(true)
+                .into_bits();
+            bar_masks[bar_index + 1] = (mask64 >> 32) as u32;
+        }
+
+        let state = Arc::new(Mutex::new(SriovState {
+            total_vfs,
+            vf_device_id,
+            bar_masks,
+            vf_regions: (0..total_vfs).map(|_| Default::default()).collect(),
+            active_bars: Default::default(),
+            control: 0,
+            num_vfs: 0,
+            system_page_size: PAGE_SIZE_4K,
+            bar_addresses: [0; 6],
+        }));
+
+        let vfs = (0..total_vfs)
+            .map(|index| VirtualFunction {
+                state: state.clone(),
+                index,
+            })
+            .collect();
+
+        (Self { state }, vfs)
+    }
+}
+
+impl PciCapability for SriovCapability {
+    fn label(&self) -> &str {
+        "sriov"
+    }
+
+    fn len(&self) -> usize {
+        CAPABILITY_LEN
+    }
+
+    fn read_u32(&self, offset: u16) -> u32 {
+        let state = self.state.lock();
+        match SriovCapabilityHeader(offset) {
+            SriovCapabilityHeader::HEADER => {
+                ExtendedCapabilityId::SRIOV.0 as u32 | CAPABILITY_VERSION << 16
+            }
+            SriovCapabilityHeader::SRIOV_CAPS => 0,
+            // The status register is all zero: VF migration is not supported.
+            SriovCapabilityHeader::CONTROL_STATUS => state.control as u32,
+            SriovCapabilityHeader::INITIAL_TOTAL_VFS => {
+                (state.total_vfs as u32) << 16 | state.total_vfs as u32
+            }
+            // The function dependency link is zero: the physical function is
+            // function 0.
+            SriovCapabilityHeader::NUM_VFS => state.num_vfs as u32,
+            SriovCapabilityHeader::VF_OFFSET_STRIDE => {
+                (VF_STRIDE as u32) << 16 | FIRST_VF_OFFSET as u32
+            }
+            SriovCapabilityHeader::VF_DEVICE_ID => (state.vf_device_id as u32) << 16,
+            SriovCapabilityHeader::SUPPORTED_PAGE_SIZES => PAGE_SIZE_4K,
+            SriovCapabilityHeader::SYSTEM_PAGE_SIZE => state.system_page_size,
+            SriovCapabilityHeader::VF_BAR0
+            | SriovCapabilityHeader::VF_BAR1
+            | SriovCapabilityHeader::VF_BAR2
+            | SriovCapabilityHeader::VF_BAR3
+            | SriovCapabilityHeader::VF_BAR4
+            | SriovCapabilityHeader::VF_BAR5 => {
+                state.bar_addresses[(offset - SriovCapabilityHeader::VF_BAR0.0) as usize / 4]
+            }
+            SriovCapabilityHeader::MIGRATION_STATE => 0,
+            _ => {
+                tracelimit::warn_ratelimited!(offset, "unexpected sriov capability read");
+                0
+            }
+        }
+    }
+
+    fn write_u32(&mut self, offset: u16, val: u32) {
+        let mut state = self.state.lock();
+        match SriovCapabilityHeader(offset) {
+            SriovCapabilityHeader::CONTROL_STATUS => {
+                let control = val as u16 & CONTROL_MASK;
+                if control & CONTROL_VF_ENABLE != 0 && state.num_vfs == 0 {
+                    tracelimit::warn_ratelimited!("enabling zero virtual functions");
+                }
+                if control != state.control {
+                    tracing::debug!(control, num_vfs = state.num_vfs, "sriov control");
+                    state.control = control;
+                    state.update_mappings();
+                }
+            }
+            SriovCapabilityHeader::NUM_VFS => {
+                // NumVFs may only be changed while the VFs are disabled.
+                if state.control & CONTROL_VF_ENABLE == 0 {
+                    state.num_vfs = (val as u16).min(state.total_vfs);
+                } else {
+                    tracelimit::warn_ratelimited!("attempt to set NumVFs while VFs are enabled");
+                }
+            }
+            SriovCapabilityHeader::SYSTEM_PAGE_SIZE => {
+                if val != PAGE_SIZE_4K {
+                    tracelimit::warn_ratelimited!(val, "unsupported sriov system page size");
+                }
+                state.system_page_size = PAGE_SIZE_4K;
+            }
+            SriovCapabilityHeader::VF_BAR0
+            | SriovCapabilityHeader::VF_BAR1
+            | SriovCapabilityHeader::VF_BAR2
+            | SriovCapabilityHeader::VF_BAR3
+            | SriovCapabilityHeader::VF_BAR4
+            | SriovCapabilityHeader::VF_BAR5 => {
+                if state.control & CONTROL_VF_MSE == 0 {
+                    let bar_index = (offset - SriovCapabilityHeader::VF_BAR0.0) as usize / 4;
+                    let mut bar_value = val & state.bar_masks[bar_index];
+                    if bar_index & 1 == 0 && state.bar_masks[bar_index] != 0 {
+                        bar_value = cfg_space::BarEncodingBits::from_bits(bar_value)
+                            .with_type_64_bit(true)
+                            .into_bits();
+                    }
+                    state.bar_addresses[bar_index] = bar_value;
+                }
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(
+                    offset,
+                    val,
+                    "write to read-only sriov capability register"
+                );
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state.lock().reset();
+    }
+}
+
+/// A handle to one of the virtual functions of a [`SriovCapability`], held by
+/// the device implementing the virtual function.
+#[derive(Clone)]
+pub struct VirtualFunction {
+    state: Arc<Mutex<SriovState>>,
+    index: u16,
+}
+
+impl std::fmt::Debug for VirtualFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualFunction")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl VirtualFunction {
+    /// The zero-based index of the virtual function.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// The function number of the virtual function, relative to the physical
+    /// function.
+    pub fn function_offset(&self) -> u16 {
+        FIRST_VF_OFFSET + self.index * VF_STRIDE
+    }
+
+    /// Returns whether the physical function has enabled this virtual
+    /// function. A disabled virtual function does not respond to
+    /// configuration space accesses.
+    pub fn is_enabled(&self) -> bool {
+        let state = self.state.lock();
+        state.control & CONTROL_VF_ENABLE != 0 && self.index < state.num_vfs
+    }
+
+    /// Sets the MMIO region backing VF BAR `bar`, which must have been
+    /// declared when creating the capability with at least the region's
+    /// length.
+    pub fn set_bar(&self, bar: u8, region: Box<dyn ControlMmioIntercept>) {
+        let mut state = self.state.lock();
+        let bar = bar as usize;
+        assert!(state.bar_masks[bar] != 0, "undeclared VF BAR");
+        state.vf_regions[self.index as usize][bar] = Some(region);
+        state.update_mappings();
+    }
+
+    /// Finds a BAR + offset by address, as
+    /// [`ConfigSpaceType0Emulator::find_bar`](crate::cfg_space_emu::ConfigSpaceType0Emulator::find_bar)
+    /// does for a regular function.
+    pub fn find_bar(&self, address: u64) -> Option<(u8, u16)> {
+        let state = self.state.lock();
+        if self.index >= state.num_vfs {
+            return None;
+        }
+        for mapping in state.active_bars.iter() {
+            let base = mapping.base_address + self.index as u64 * mapping.len;
+            if address >= base && address - base < mapping.len {
+                return Some((mapping.index, (address - base).try_into().unwrap()));
+            }
+        }
+        None
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Debug, Protobuf, SavedStateRoot)]
+        #[mesh(package = "pci.caps.sriov")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub control: u16,
+            #[mesh(2)]
+            pub num_vfs: u16,
+            #[mesh(3)]
+            pub bar_addresses: [u32; 6],
+        }
+    }
+
+    impl SaveRestore for SriovCapability {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let state = self.state.lock();
+            Ok(state::SavedState {
+                control: state.control,
+                num_vfs: state.num_vfs,
+                bar_addresses: state.bar_addresses,
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                control,
+                num_vfs,
+                bar_addresses,
+            } = state;
+            let mut state = self.state.lock();
+            state.control = control & CONTROL_MASK;
+            state.num_vfs = num_vfs.min(state.total_vfs);
+            state.bar_addresses = bar_addresses;
+            state.update_mappings();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chipset_device::mmio::ExternallyManagedMmioIntercepts;
+    use chipset_device::mmio::RegisterMmioIntercept;
+
+    #[test]
+    fn test_sriov() {
+        let (mut cap, vfs) = SriovCapability::new(0x1234, 3, &[(0, 0x2000), (4, 0x100)]);
+        assert_eq!(vfs.len(), 3);
+        assert_eq!(
+            cap.read_u32(0),
+            ExtendedCapabilityId::SRIOV.0 as u32 | 1 << 16
+        );
+        assert_eq!(cap.read_u32(0xc), 3 << 16 | 3);
+        assert_eq!(cap.read_u32(0x14), 1 << 16 | 1);
+        assert_eq!(cap.read_u32(0x18), 0x1234 << 16);
+        assert_eq!(vfs[2].function_offset(), 3);
+
+        let mut register = ExternallyManagedMmioIntercepts;
+        for vf in &vfs {
+            vf.set_bar(0, register.new_io_region("bar0", 0x2000));
+        }
+
+        // Size the BARs.
+        cap.write_u32(0x24, !0);
+        cap.write_u32(0x28, !0);
+        assert_eq!(cap.read_u32(0x24), !0x1fff | 0x4);
+        assert_eq!(cap.read_u32(0x28), !0);
+        cap.write_u32(0x2c, !0);
+        assert_eq!(cap.read_u32(0x2c), 0);
+        cap.write_u32(0x34, !0);
+        assert_eq!(cap.read_u32(0x34), !0xfff | 0x4);
+
+        cap.write_u32(0x24, 0x1000_0000);
+        cap.write_u32(0x28, 0);
+        cap.write_u32(0x34, 0x2000_0000);
+        cap.write_u32(0x38, 0);
+        cap.write_u32(0x10, 2);
+
+        assert!(!vfs[0].is_enabled());
+        assert_eq!(vfs[0].find_bar(0x1000_0000), None);
+
+        cap.write_u32(8, (CONTROL_VF_ENABLE | CONTROL_VF_MSE).into());
+        assert!(vfs[0].is_enabled());
+        assert!(vfs[1].is_enabled());
+        assert!(!vfs[2].is_enabled());
+        assert_eq!(vfs[0].find_bar(0x1000_0010), Some((0, 0x10)));
+        assert_eq!(vfs[1].find_bar(0x1000_0010), None);
+        assert_eq!(vfs[1].find_bar(0x1000_2010), Some((0, 0x10)));
+        assert_eq!(vfs[1].find_bar(0x2000_1008), Some((4, 8)));
+        assert_eq!(vfs[2].find_bar(0x1000_4000), None);
+
+        // NumVFs is fixed while the VFs are enabled.
+        cap.write_u32(0x10, 3);
+        assert_eq!(cap.read_u32(0x10), 2);
+
+        cap.reset();
+        assert!(!vfs[0].is_enabled());
+        assert_eq!(vfs[0].find_bar(0x1000_0010), None);
+        assert_eq!(cap.read_u32(0x24), 0);
+    }
+}
@@ -10,6 +10,7 @@
 use crate::bar_mapping::BarMappings;
 use crate::capabilities::PciCapability;
 use crate::spec::cfg_space;
+use crate::spec::ext_caps::EXTENDED_CAPABILITIES_START;
 use crate::spec::hwid::HardwareIds;
 use chipset_device::io::IoError;
 use chipset_device::io::IoResult;
@@ -127,12 +128,15 @@ pub struct ConfigSpaceType0Emulator {
     bar_masks: [u32; 6],
     hardware_ids: HardwareIds,
     multi_function_bit: bool,
+    virtual_function: bool,
 
     // Runtime glue
     #[inspect(with = r#"|x| inspect::iter_by_index(x).prefix("bar")"#)]
     mapped_memory: [Option<BarMemoryKind>; 6],
     #[inspect(with = "|x| inspect::iter_by_key(x.iter().map(|cap| (cap.label(), cap)))")]
     capabilities: Vec<Box<dyn PciCapability>>,
+    #[inspect(with = "|x| inspect::iter_by_key(x.iter().map(|cap| (cap.label(), cap)))")]
+    extended_capabilities: Vec<Box<dyn PciCapability>>,
     intx_interrupt: Option<Arc<IntxInterrupt>>,
 
     // Runtime book-keeping
@@ -267,11 +271,13 @@ pub fn new(
             bar_masks,
             hardware_ids,
             multi_function_bit: false,
+            virtual_function: false,
 
             active_bars: Default::default(),
 
             mapped_memory,
             capabilities,
+            extended_capabilities: Vec::new(),
             intx_interrupt: None,
 
             state: ConfigSpaceType0EmulatorState {
@@ -289,6 +295,29 @@ pub fn with_multi_function_bit(mut self, bit: bool) -> Self {
         self
     }
 
+    /// Add capabilities to the PCI Express extended configuration space,
+    /// starting at offset 0x100.
+    ///
+    /// The capabilities report their extended capability ID and version in the
+    /// low 20 bits of their first register; the next capability offset is
+    /// filled in by the emulator.
+    pub fn with_extended_capabilities(mut self, capabilities: Vec<Box<dyn PciCapability>>) -> Self {
+        self.extended_capabilities = capabilities;
+        self
+    }
+
+    /// If the device is an SR-IOV virtual function, its Vendor ID and Device
+    /// ID registers read as all ones, as the guest learns them from the
+    /// physical function's SR-IOV capability instead.
+    ///
+    /// A virtual function's own BARs are read-only zero (i.e: constructed with
+    /// an empty [`DeviceBars`]), as they are programmed through the physical
+    /// function.
+    pub fn with_virtual_function(mut self, virtual_function: bool) -> Self {
+        self.virtual_function = virtual_function;
+        self
+    }
+
     /// If using legacy INT#x interrupts: wire a LineInterrupt to one of the 4
     /// INT#x pins, returning an object that manages configuration space bits
     /// when the device sets the interrupt level.
@@ -313,7 +342,11 @@ pub fn reset(&mut self) {
 
         self.sync_command_register(self.state.command);
 
-        for cap in &mut self.capabilities {
+        for cap in self
+            .capabilities
+            .iter_mut()
+            .chain(&mut self.extended_capabilities)
+        {
             cap.reset();
         }
 
@@ -322,10 +355,13 @@ pub fn reset(&mut self) {
         }
     }
 
-    fn get_capability_index_and_offset(&self, offset: u16) -> Option<(usize, u16)> {
+    fn get_capability_index_and_offset(
+        capabilities: &[Box<dyn PciCapability>],
+        offset: u16,
+    ) -> Option<(usize, u16)> {
         let mut cap_offset = 0;
-        for i in 0..self.capabilities.len() {
-            let cap_size = self.capabilities[i].len() as u16;
+        for (i, cap) in capabilities.iter().enumerate() {
+            let cap_size = cap.len() as u16;
             if offset < cap_offset + cap_size {
                 return Some((i, offset - cap_offset));
             }
@@ -340,7 +376,11 @@ pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
 
         *value = match HeaderType00(offset) {
             HeaderType00::DEVICE_VENDOR => {
-                (self.hardware_ids.device_id as u32) << 16 | self.hardware_ids.vendor_id as u32
+                if self.virtual_function {
+                    !0
+                } else {
+                    (self.hardware_ids.device_id as u32) << 16 | self.hardware_ids.vendor_id as u32
+                }
             }
             HeaderType00::STATUS_COMMAND => {
                 let mut status =
@@ -406,7 +446,7 @@ pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
             // rest of the range is reserved for extended device capabilities
             _ if (0x40..0x100).contains(&offset) => {
                 if let Some((cap_index, cap_offset)) =
-                    self.get_capability_index_and_offset(offset - 0x40)
+                    Self::get_capability_index_and_offset(&self.capabilities, offset - 0x40)
                 {
                     let mut value = self.capabilities[cap_index].read_u32(cap_offset);
                     if cap_offset == 0 {
@@ -424,8 +464,28 @@ pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
                     return IoResult::Err(IoError::InvalidRegister);
                 }
             }
+            _ if (0x100..0x1000).contains(&offset) && !self.extended_capabilities.is_empty() => {
+                if let Some((cap_index, cap_offset)) = Self::get_capability_index_and_offset(
+                    &self.extended_capabilities,
+                    offset - EXTENDED_CAPABILITIES_START,
+                ) {
+                    let mut value = self.extended_capabilities[cap_index].read_u32(cap_offset);
+                    if cap_offset == 0 {
+                        let next = if cap_index < self.extended_capabilities.len() - 1 {
+                            offset as u32 + self.extended_capabilities[cap_index].len() as u32
+                        } else {
+                            0
+                        };
+                        assert!(value & 0xfff0_0000 == 0);
+                        value |= next << 20;
+                    }
+                    value
+                } else {
+                    // Unimplemented extended config space reads as zero.
+                    0
+                }
+            }
             _ if (0x100..0x1000).contains(&offset) => {
-                // TODO: properly support extended pci express configuration space
                 if offset == 0x100 {
                     tracelimit::warn_ratelimited!(offset, "unexpected pci express probe");
                     0x000ffff
@@ -535,7 +595,7 @@ pub fn write_u32(&mut self, offset: u16, val: u32) -> IoResult {
             // rest of the range is reserved for extended device capabilities
             _ if (0x40..0x100).contains(&offset) => {
                 if let Some((cap_index, cap_offset)) =
-                    self.get_capability_index_and_offset(offset - 0x40)
+                    Self::get_capability_index_and_offset(&self.capabilities, offset - 0x40)
                 {
                     self.capabilities[cap_index].write_u32(cap_offset, val);
                 } else {
@@ -548,7 +608,13 @@ pub fn write_u32(&mut self, offset: u16, val: u32) -> IoResult {
                 }
             }
             _ if (0x100..0x1000).contains(&offset) => {
-                // TODO: properly support extended pci express configuration space
+                if let Some((cap_index, cap_offset)) = Self::get_capability_index_and_offset(
+                    &self.extended_capabilities,
+                    offset - EXTENDED_CAPABILITIES_START,
+                ) {
+                    self.extended_capabilities[cap_index].write_u32(cap_offset, val);
+                    return IoResult::Ok;
+                }
                 tracelimit::warn_ratelimited!(
                     offset,
                     value = val,
@@ -626,6 +692,7 @@ fn save(&mut self) -> Result<Self::SavedState, SaveError> {
                 capabilities: self
                     .capabilities
                     .iter_mut()
+                    .chain(&mut self.extended_capabilities)
                     .map(|cap| {
                         let id = cap.label().to_owned();
                         Ok((id, cap.save()?))
@@ -665,7 +732,11 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
                 // yes, yes, this is O(n^2), but devices never have more than a
                 // handful of caps, so it's totally fine.
                 let mut restored = false;
-                for cap in self.capabilities.iter_mut() {
+                for cap in self
+                    .capabilities
+                    .iter_mut()
+                    .chain(&mut self.extended_capabilities)
+                {
                     if cap.label() == id {
                         cap.restore(entry)?;
                         restored = true;
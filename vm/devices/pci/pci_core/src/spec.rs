@@ -123,15 +123,27 @@ pub enum Subclass: u8 {
             // Other values: 0x01 - 0x08, 0x80
             NETWORK_CONTROLLER_ETHERNET = 0x00,
 
+            // Memory Controller (Class code: 0x05)
+            // Other values: 0x01, 0x80
+            MEMORY_CONTROLLER_RAM = 0x00,
+
             // Bridge (Class code: 0x06)
             // Other values: 0x02 - 0x0A
             BRIDGE_HOST = 0x00,
             BRIDGE_ISA = 0x01,
             BRIDGE_OTHER = 0x80,
 
+            // Simple Communication Controller (Class code: 0x07)
+            // Other values: 0x01 - 0x05, 0x80
+            SIMPLE_COMMUNICATION_CONTROLLER_SERIAL = 0x00,
+
             // Base System Peripheral (Class code: 0x08)
             // Other values: 0x00 - 0x06
             BASE_SYSTEM_PERIPHERAL_OTHER = 0x80,
+
+            // Serial Bus Controller (Class code: 0x0C)
+            // Other values: 0x00 - 0x02, 0x04 - 0x09, 0x80
+            SERIAL_BUS_CONTROLLER_USB = 0x03,
         }
     }
 
@@ -165,6 +177,14 @@ pub enum ProgrammingInterface: u8{
 
             // Ethernet Controller (Class code: 0x02, Subclass: 0x00)
             NETWORK_CONTROLLER_ETHERNET_GDMA = 0x01,
+
+            // Serial Controller (Class code: 0x07, Subclass: 0x00)
+            // Other values: 0x00, 0x01, 0x03 - 0x06
+            SIMPLE_COMMUNICATION_CONTROLLER_SERIAL_16550 = 0x02,
+
+            // USB Controller (Class code: 0x0C, Subclass: 0x03)
+            // Other values: 0x00, 0x10, 0x20, 0x40, 0x80, 0xFE
+            SERIAL_BUS_CONTROLLER_USB_XHCI = 0x30,
         }
     }
 
@@ -340,6 +360,7 @@ pub mod caps {
         pub enum CapabilityId: u8 {
             #![expect(missing_docs)] // self explanatory variants
             VENDOR_SPECIFIC = 0x09,
+            PCI_EXPRESS     = 0x10,
             MSIX            = 0x11,
         }
     }
@@ -374,4 +395,107 @@ pub enum MsixTableEntryIdx: u16 {
             }
         }
     }
+
+    /// PCI Express
+    #[expect(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod pci_express {
+        open_enum::open_enum! {
+            /// Offsets into the PCI Express Capability Structure
+            ///
+            /// Source: PCI Express Base Specification, 7.5.3
+            pub enum PciExpressCapabilityHeader: u16 {
+                PCIE_CAPS        = 0x00,
+                DEVICE_CAPS      = 0x04,
+                DEVICE_CTL_STS   = 0x08,
+                LINK_CAPS        = 0x0C,
+                LINK_CTL_STS     = 0x10,
+                SLOT_CAPS        = 0x14,
+                SLOT_CTL_STS     = 0x18,
+                ROOT_CTL_CAPS    = 0x1C,
+                ROOT_STS         = 0x20,
+                DEVICE_CAPS_2    = 0x24,
+                DEVICE_CTL_STS_2 = 0x28,
+                LINK_CAPS_2      = 0x2C,
+                LINK_CTL_STS_2   = 0x30,
+                SLOT_CAPS_2      = 0x34,
+                SLOT_CTL_STS_2   = 0x38,
+            }
+        }
+
+        /// The capability version reported in the PCI Express Capabilities
+        /// register.
+        pub const CAPABILITY_VERSION: u16 = 2;
+
+        /// Device Capabilities: Function Level Reset Capability.
+        pub const DEVICE_CAPS_FLR: u32 = 1 << 28;
+
+        /// Device Control: Initiate Function Level Reset.
+        pub const DEVICE_CTL_INITIATE_FLR: u16 = 1 << 15;
+    }
+}
+
+/// Extended capabilities, which live in the PCI Express extended configuration
+/// space (0x100..0x1000).
+pub mod ext_caps {
+    open_enum::open_enum! {
+        /// Extended Capability IDs
+        ///
+        /// Source: PCI Express Base Specification, 7.6.3
+        ///
+        /// NOTE: this is a non-exhaustive list, so don't be afraid to add new
+        /// variants on an as-needed basis!
+        pub enum ExtendedCapabilityId: u16 {
+            #![expect(missing_docs)] // self explanatory variants
+            SRIOV = 0x0010,
+        }
+    }
+
+    /// Offset of the first extended capability.
+    pub const EXTENDED_CAPABILITIES_START: u16 = 0x100;
+
+    /// Single Root I/O Virtualization
+    #[expect(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod sriov {
+        open_enum::open_enum! {
+            /// Offsets into the SR-IOV Extended Capability Structure
+            ///
+            /// Source: PCI Express Base Specification, 9.3.3
+            pub enum SriovCapabilityHeader: u16 {
+                HEADER               = 0x00,
+                SRIOV_CAPS           = 0x04,
+                CONTROL_STATUS       = 0x08,
+                INITIAL_TOTAL_VFS    = 0x0C,
+                NUM_VFS              = 0x10,
+                VF_OFFSET_STRIDE     = 0x14,
+                VF_DEVICE_ID         = 0x18,
+                SUPPORTED_PAGE_SIZES = 0x1C,
+                SYSTEM_PAGE_SIZE     = 0x20,
+                VF_BAR0              = 0x24,
+                VF_BAR1              = 0x28,
+                VF_BAR2              = 0x2C,
+                VF_BAR3              = 0x30,
+                VF_BAR4              = 0x34,
+                VF_BAR5              = 0x38,
+                MIGRATION_STATE      = 0x3C,
+            }
+        }
+
+        /// The capability version reported in the extended capability header.
+        pub const CAPABILITY_VERSION: u32 = 1;
+
+        /// The length of the capability structure.
+        pub const CAPABILITY_LEN: usize = 0x40;
+
+        /// SR-IOV Control: VF Enable.
+        pub const CONTROL_VF_ENABLE: u16 = 1 << 0;
+
+        /// SR-IOV Control: VF Memory Space Enable.
+        pub const CONTROL_VF_MSE: u16 = 1 << 3;
+
+        /// SR-IOV Control: ARI Capable Hierarchy.
+        pub const CONTROL_ARI_CAPABLE: u16 = 1 << 4;
+
+        /// Supported Page Sizes: 4KB, the only page size that is supported.
+        pub const PAGE_SIZE_4K: u32 = 1 << 0;
+    }
 }
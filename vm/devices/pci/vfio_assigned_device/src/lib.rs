@@ -0,0 +1,1127 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Assignment of host PCI devices to the guest with VFIO.
+//!
+//! Config space accesses are forwarded to the host's vfio-pci driver, which
+//! virtualizes the registers that are unsafe to expose, except for the BARs,
+//! the expansion ROM, the interrupt line, and the MSI-X control bits, which
+//! are emulated here. BARs are mapped directly into the guest when possible.
+//! BARs that hold the MSI-X table or PBA are trapped instead, and accesses
+//! outside those structures are forwarded to the device.
+//!
+//! The MSI-X table is emulated, with each vector backed by a host vector that
+//! signals an eventfd. Each signal is delivered as the MSI programmed in the
+//! guest's table entry, or left pending in the PBA if the vector is masked.
+//! MSI is hidden from the guest and INTx is not supported, so the device must
+//! support MSI-X.
+//!
+//! All guest RAM is mapped for DMA when the device is created, with guest
+//! physical addresses as the I/O virtual addresses, so the device can only
+//! be used with guest memory that is entirely mapped in this process.
+
+#![cfg(target_os = "linux")]
+// UNSAFETY: Mapping guest memory for device DMA.
+#![expect(unsafe_code)]
+
+use anyhow::Context as _;
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::ControlMmioIntercept;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::poll_device::PollDevice;
+use guestmem::GuestMemory;
+use guestmem::MappableGuestMemory;
+use guestmem::MappedMemoryRegion;
+use guestmem::MemoryMapper;
+use inspect::Inspect;
+use inspect::InspectMut;
+use memory_range::MemoryRange;
+use pal_async::wait::PolledWait;
+use pal_event::Event;
+use pci_core::msi::MsiControl;
+use pci_core::msi::MsiInterruptTarget;
+use pci_core::spec::caps::CapabilityId;
+use pci_core::spec::cfg_space;
+use pci_core::spec::cfg_space::HeaderType00;
+use std::io;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use vfio_bindings::bindings::vfio::VFIO_PCI_CONFIG_REGION_INDEX;
+use vfio_bindings::bindings::vfio::VFIO_PCI_MSIX_IRQ_INDEX;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
+use vmcore::save_restore::SaveRestore;
+use vmcore::save_restore::SavedStateNotSupported;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// The MSI capability ID, which is hidden from the guest.
+const CAP_ID_MSI: u8 = 0x05;
+
+/// The size of an MSI-X table entry.
+const MSIX_ENTRY_SIZE: u64 = 16;
+
+/// The MSI-X enable and function mask bits, in the capability's first dword.
+const MSIX_ENABLE: u32 = 1 << 31;
+const MSIX_FUNCTION_MASK: u32 = 1 << 30;
+
+/// The vector control mask bit in an MSI-X table entry.
+const MSIX_VECTOR_MASKED: u32 = 1;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// A host PCI device opened with VFIO.
+pub struct VfioDevice {
+    /// The device's PCI address, such as `0000:01:00.0`.
+    pub pci_id: String,
+    /// The container, with the type 1 IOMMU set.
+    pub container: vfio_sys::Container,
+    /// The device's IOMMU group, attached to `container`.
+    pub group: vfio_sys::Group,
+    /// The device.
+    pub device: vfio_sys::Device,
+}
+
+/// A host PCI device assigned to the guest.
+#[derive(InspectMut)]
+pub struct VfioAssignedDevice {
+    pci_id: String,
+    #[inspect(skip)]
+    _container: vfio_sys::Container,
+    #[inspect(skip)]
+    _group: vfio_sys::Group,
+    #[inspect(skip)]
+    device: vfio_sys::Device,
+    #[inspect(skip)]
+    driver: VmTaskDriver,
+    #[inspect(skip)]
+    config_offset: u64,
+    can_reset: bool,
+    #[inspect(iter_by_index)]
+    bars: Vec<Bar>,
+    #[inspect(skip)]
+    bar_regs: BarRegisters,
+    mmio_enabled: bool,
+    interrupt_line: u8,
+    /// Capability list pointers rewritten to skip hidden capabilities.
+    #[inspect(skip)]
+    cap_ptr_overrides: Vec<(u16, u8)>,
+    msix: Msix,
+}
+
+#[derive(Inspect)]
+struct Bar {
+    index: u8,
+    #[inspect(hex)]
+    len: u64,
+    #[inspect(skip)]
+    region_offset: u64,
+    #[inspect(hex)]
+    mapped_at: Option<u64>,
+    #[inspect(rename = "trapped", with = "BarKind::is_trapped")]
+    kind: BarKind,
+}
+
+enum BarKind {
+    Mapped(Box<dyn MappableGuestMemory>, Arc<dyn MappedMemoryRegion>),
+    Trapped(Box<dyn ControlMmioIntercept>),
+}
+
+impl BarKind {
+    fn is_trapped(&self) -> bool {
+        matches!(self, BarKind::Trapped(_))
+    }
+}
+
+#[derive(Inspect)]
+struct Msix {
+    #[inspect(hex)]
+    cap_offset: u16,
+    table_bar: u8,
+    #[inspect(hex)]
+    table_offset: u64,
+    pba_bar: u8,
+    #[inspect(hex)]
+    pba_offset: u64,
+    enabled: bool,
+    function_mask: bool,
+    #[inspect(iter_by_index)]
+    vectors: Vec<MsixVector>,
+}
+
+#[derive(Inspect)]
+struct MsixVector {
+    #[inspect(hex)]
+    address: u64,
+    #[inspect(hex)]
+    data: u32,
+    masked: bool,
+    pending: bool,
+    signaled: u64,
+    /// The address and data the interrupt is enabled with, if it can be
+    /// delivered.
+    #[inspect(skip)]
+    enabled_with: Option<(u64, u32)>,
+    #[inspect(skip)]
+    control: Box<dyn MsiControl>,
+    #[inspect(skip)]
+    event: Option<PolledWait<Event>>,
+}
+
+impl MsixVector {
+    fn new(control: Box<dyn MsiControl>) -> Self {
+        Self {
+            address: 0,
+            data: 0,
+            masked: true,
+            pending: false,
+            signaled: 0,
+            enabled_with: None,
+            control,
+            event: None,
+        }
+    }
+
+    /// Enables or disables the interrupt to match the guest's state, and
+    /// delivers it if it is pending and now unmasked.
+    fn sync(&mut self, active: bool) {
+        let target = (active && !self.masked).then_some((self.address, self.data));
+        if self.enabled_with != target {
+            match target {
+                Some((address, data)) => self.control.enable(address, data),
+                None => self.control.disable(),
+            }
+            self.enabled_with = target;
+        }
+        if self.pending && target.is_some() {
+            self.pending = false;
+            self.signal();
+        }
+    }
+
+    fn signal(&mut self) {
+        match self.enabled_with {
+            Some((address, data)) => {
+                self.control.signal(address, data);
+                self.signaled += 1;
+            }
+            None => self.pending = true,
+        }
+    }
+}
+
+impl Msix {
+    fn active(&self) -> bool {
+        self.enabled && !self.function_mask
+    }
+
+    fn sync_vectors(&mut self) {
+        let active = self.active();
+        for vector in &mut self.vectors {
+            vector.sync(active);
+        }
+    }
+
+    fn table_len(&self) -> u64 {
+        self.vectors.len() as u64 * MSIX_ENTRY_SIZE
+    }
+
+    fn pba_len(&self) -> u64 {
+        (self.vectors.len() as u64).div_ceil(64) * 8
+    }
+
+    fn read_table(&self, offset: u64) -> u32 {
+        let vector = &self.vectors[(offset / MSIX_ENTRY_SIZE) as usize];
+        match offset % MSIX_ENTRY_SIZE {
+            0 => vector.address as u32,
+            4 => (vector.address >> 32) as u32,
+            8 => vector.data,
+            12 => {
+                if vector.masked {
+                    MSIX_VECTOR_MASKED
+                } else {
+                    0
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_table(&mut self, offset: u64, value: u32) {
+        let active = self.active();
+        let vector = &mut self.vectors[(offset / MSIX_ENTRY_SIZE) as usize];
+        match offset % MSIX_ENTRY_SIZE {
+            0 => vector.address = (vector.address & !0xffff_ffff) | value as u64,
+            4 => vector.address = (vector.address & 0xffff_ffff) | (value as u64) << 32,
+            8 => vector.data = value,
+            12 => vector.masked = value & MSIX_VECTOR_MASKED != 0,
+            _ => unreachable!(),
+        }
+        vector.sync(active);
+    }
+
+    /// Returns the part of BAR `bar` that an access of `len` bytes at
+    /// `offset` falls in.
+    fn bar_area(&self, bar: u8, offset: u64, len: usize) -> Result<BarArea, IoError> {
+        let end = offset + len as u64;
+        let (is_table, start, area_len) = if bar == self.table_bar
+            && offset < self.table_offset + self.table_len()
+            && end > self.table_offset
+        {
+            (true, self.table_offset, self.table_len())
+        } else if bar == self.pba_bar
+            && offset < self.pba_offset + self.pba_len()
+            && end > self.pba_offset
+        {
+            (false, self.pba_offset, self.pba_len())
+        } else {
+            return Ok(BarArea::Device);
+        };
+        // Only naturally aligned accesses within the structure are supported.
+        if offset % 4 != 0 || (len != 4 && len != 8) || offset < start || end > start + area_len {
+            return Err(IoError::InvalidAccessSize);
+        }
+        Ok(if is_table {
+            BarArea::MsixTable(offset - start)
+        } else {
+            BarArea::MsixPba(offset - start)
+        })
+    }
+
+    fn read_pba(&self, offset: u64) -> u32 {
+        let first = (offset * 8) as usize;
+        self.vectors
+            .iter()
+            .skip(first)
+            .take(32)
+            .enumerate()
+            .fold(0, |bits, (i, vector)| bits | (vector.pending as u32) << i)
+    }
+}
+
+/// The part of a BAR that an access falls in.
+#[derive(Debug, PartialEq)]
+enum BarArea {
+    MsixTable(u64),
+    MsixPba(u64),
+    Device,
+}
+
+/// The emulated BAR registers.
+#[derive(Default)]
+struct BarRegisters {
+    regs: [u32; 6],
+    masks: [u32; 6],
+    flags: [u32; 6],
+}
+
+impl BarRegisters {
+    /// Sets up BAR `index` as a memory BAR of `size` bytes, a power of two,
+    /// with the encoding bits `flags` reported by the device.
+    fn set_memory_bar(&mut self, index: usize, flags: u32, size: u64) {
+        let mask = !(size - 1);
+        self.masks[index] = mask as u32;
+        self.flags[index] = flags & 0xf;
+        if self.is_64bit(index) {
+            self.masks[index + 1] = (mask >> 32) as u32;
+        }
+    }
+
+    fn is_64bit(&self, index: usize) -> bool {
+        cfg_space::BarEncodingBits::from(self.flags[index]).type_64_bit()
+    }
+
+    fn read(&self, index: usize) -> u32 {
+        self.regs[index] | self.flags[index]
+    }
+
+    fn write(&mut self, index: usize, value: u32) {
+        self.regs[index] = value & self.masks[index];
+    }
+
+    /// Returns the address the guest programmed into BAR `index`.
+    fn address(&self, index: usize) -> u64 {
+        let mut address = self.regs[index] as u64;
+        if self.is_64bit(index) {
+            address |= (self.regs[index + 1] as u64) << 32;
+        }
+        address
+    }
+
+    fn reset(&mut self) {
+        self.regs = [0; 6];
+    }
+}
+
+/// Returns the capability pointers to rewrite, as (config space offset,
+/// value), to remove the MSI capability from the list of `caps`, given as
+/// (offset, capability ID) in list order.
+fn cap_ptr_overrides(caps: &[(u16, u8)]) -> Vec<(u16, u8)> {
+    let visible = caps
+        .iter()
+        .filter(|&&(_, id)| id != CAP_ID_MSI)
+        .map(|&(offset, _)| offset);
+    std::iter::once(HeaderType00::RESERVED_CAP_PTR.0)
+        .chain(visible.clone().map(|offset| offset + 1))
+        .zip(visible.map(|offset| offset as u8).chain([0]))
+        .collect()
+}
+
+/// Applies `overrides` to `value`, the dword read from config space at
+/// `offset`.
+fn apply_cap_ptr_overrides(overrides: &[(u16, u8)], offset: u16, value: &mut [u8; 4]) {
+    for &(ptr, target) in overrides {
+        if let Some(byte) = ptr.checked_sub(offset).filter(|&i| i < 4) {
+            value[byte as usize] = target;
+        }
+    }
+}
+
+impl VfioAssignedDevice {
+    /// Assigns `vfio`'s device to the guest, mapping the guest's `ram` for
+    /// DMA.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        mapper: &dyn MemoryMapper,
+        msi_target: &dyn MsiInterruptTarget,
+        guest_memory: &GuestMemory,
+        ram: &[MemoryRange],
+        vfio: VfioDevice,
+    ) -> anyhow::Result<Self> {
+        let VfioDevice {
+            pci_id,
+            container,
+            group,
+            device,
+        } = vfio;
+
+        let info = device.info()?;
+        anyhow::ensure!(info.flags.pci(), "{pci_id} is not a PCI device");
+        let can_reset = info.flags.reset();
+        if can_reset {
+            device.reset()?;
+        }
+
+        let (base, len) = guest_memory
+            .full_mapping()
+            .context("guest memory is not mapped contiguously")?;
+        for range in ram {
+            anyhow::ensure!(
+                range.end() <= len as u64,
+                "guest ram {range} is outside the guest memory mapping"
+            );
+            // SAFETY: The range is guest RAM inside the guest memory mapping,
+            // which the guest may also access at any time.
+            unsafe {
+                container.map_dma(range.start(), base.add(range.start() as usize), range.len())?;
+            }
+        }
+
+        let config_offset = device.region_info(VFIO_PCI_CONFIG_REGION_INDEX)?.offset;
+        let read_config = |offset: u16, buf: &mut [u8]| {
+            device
+                .as_ref()
+                .read_exact_at(buf, config_offset + offset as u64)
+                .with_context(|| format!("failed to read config space at {offset:#x}"))
+        };
+        let read_u8 = |offset| -> anyhow::Result<u8> {
+            let mut v = [0];
+            read_config(offset, &mut v)?;
+            Ok(v[0])
+        };
+        let read_u32 = |offset| -> anyhow::Result<u32> {
+            let mut v = [0; 4];
+            read_config(offset, &mut v)?;
+            Ok(u32::from_le_bytes(v))
+        };
+
+        // Walk the capability list to find MSI-X and hide MSI.
+        let status =
+            cfg_space::Status::from((read_u32(HeaderType00::STATUS_COMMAND.0)? >> 16) as u16);
+        let mut caps = Vec::new();
+        if status.capabilities_list() {
+            let mut ptr = read_u8(HeaderType00::RESERVED_CAP_PTR.0)? & !3;
+            // Bound the walk in case the list has a loop.
+            while ptr != 0 && caps.len() < 48 {
+                caps.push((ptr as u16, read_u8(ptr as u16)?));
+                ptr = read_u8(ptr as u16 + 1)? & !3;
+            }
+        }
+        let cap_ptr_overrides = cap_ptr_overrides(&caps);
+
+        let msix_cap = caps
+            .iter()
+            .find(|&&(_, id)| id == CapabilityId::MSIX.0)
+            .map(|&(offset, _)| offset)
+            .context("device does not support MSI-X")?;
+        let vector_count = ((read_u32(msix_cap)? >> 16) & 0x7ff) as usize + 1;
+        let host_vectors = device.irq_info(VFIO_PCI_MSIX_IRQ_INDEX)?.count as usize;
+        anyhow::ensure!(
+            host_vectors >= vector_count,
+            "device has {vector_count} MSI-X vectors but vfio supports {host_vectors}"
+        );
+        let table = read_u32(msix_cap + 4)?;
+        let pba = read_u32(msix_cap + 8)?;
+        let msix = Msix {
+            cap_offset: msix_cap,
+            table_bar: (table & 7) as u8,
+            table_offset: (table & !7) as u64,
+            pba_bar: (pba & 7) as u8,
+            pba_offset: (pba & !7) as u64,
+            enabled: false,
+            function_mask: false,
+            vectors: (0..vector_count)
+                .map(|_| MsixVector::new(msi_target.new_interrupt()))
+                .collect(),
+        };
+
+        let mut bars = Vec::new();
+        let mut bar_regs = BarRegisters::default();
+        let mut index = 0u32;
+        while index < 6 {
+            let bar_offset = HeaderType00::BAR0.0 + index as u16 * 4;
+            let raw = cfg_space::BarEncodingBits::from(read_u32(bar_offset)?);
+            let region = device.region_info(index)?;
+            let is_64bit = !raw.use_pio() && raw.type_64_bit();
+            let this = index;
+            index += if is_64bit { 2 } else { 1 };
+            if region.size == 0 {
+                continue;
+            }
+            if raw.use_pio() {
+                tracing::warn!(
+                    pci_id = pci_id.as_str(),
+                    bar = this,
+                    "i/o port bars are not supported"
+                );
+                continue;
+            }
+            anyhow::ensure!(
+                !is_64bit || this < 5,
+                "bar {this} is 64-bit but is the last bar"
+            );
+            anyhow::ensure!(
+                region.size.is_power_of_two() && region.size >= 16,
+                "bar {this} has invalid size {:#x}",
+                region.size
+            );
+
+            bar_regs.set_memory_bar(this as usize, raw.into(), region.size);
+
+            let holds_msix = this as u8 == msix.table_bar || this as u8 == msix.pba_bar;
+            let name = format!("vfio-{pci_id}-bar{this}");
+            let kind = if !holds_msix && region.flags.mmap() && region.size % PAGE_SIZE == 0 {
+                let (control, mapping) = mapper.new_region(region.size as usize, name)?;
+                mapping.map(0, &device, region.offset, region.size as usize, true)?;
+                BarKind::Mapped(control, mapping)
+            } else {
+                BarKind::Trapped(register_mmio.new_io_region(&name, region.size))
+            };
+            bars.push(Bar {
+                index: this as u8,
+                len: region.size,
+                region_offset: region.offset,
+                mapped_at: None,
+                kind,
+            });
+        }
+
+        for (bar, offset, len, what) in [
+            (msix.table_bar, msix.table_offset, msix.table_len(), "table"),
+            (msix.pba_bar, msix.pba_offset, msix.pba_len(), "pba"),
+        ] {
+            anyhow::ensure!(
+                bars.iter()
+                    .any(|b| b.index == bar && offset + len <= b.len && b.kind.is_trapped()),
+                "msi-x {what} is not inside a memory bar"
+            );
+        }
+
+        tracing::info!(
+            pci_id = pci_id.as_str(),
+            bars = bars.len(),
+            vector_count,
+            "assigned vfio device"
+        );
+
+        Ok(Self {
+            pci_id,
+            _container: container,
+            _group: group,
+            device,
+            driver: driver_source.simple(),
+            config_offset,
+            can_reset,
+            bars,
+            bar_regs,
+            mmio_enabled: false,
+            interrupt_line: 0,
+            cap_ptr_overrides,
+            msix,
+        })
+    }
+
+    fn read_config(&self, offset: u16, buf: &mut [u8]) -> io::Result<()> {
+        self.device
+            .as_ref()
+            .read_exact_at(buf, self.config_offset + offset as u64)
+    }
+
+    fn write_config(&self, offset: u16, buf: &[u8]) -> io::Result<()> {
+        self.device
+            .as_ref()
+            .write_all_at(buf, self.config_offset + offset as u64)
+    }
+
+    fn read_device_config(&self, offset: u16) -> u32 {
+        let mut value = [0; 4];
+        if let Err(err) = self.read_config(offset, &mut value) {
+            tracelimit::warn_ratelimited!(
+                pci_id = self.pci_id.as_str(),
+                offset,
+                error = &err as &dyn std::error::Error,
+                "config space read failed"
+            );
+            return !0;
+        }
+        apply_cap_ptr_overrides(&self.cap_ptr_overrides, offset, &mut value);
+        u32::from_le_bytes(value)
+    }
+
+    fn write_device_config(&self, offset: u16, buf: &[u8]) {
+        if let Err(err) = self.write_config(offset, buf) {
+            tracelimit::warn_ratelimited!(
+                pci_id = self.pci_id.as_str(),
+                offset,
+                error = &err as &dyn std::error::Error,
+                "config space write failed"
+            );
+        }
+    }
+
+    /// Maps or unmaps the BARs to match the guest's programming.
+    fn update_bars(&mut self) {
+        let addresses: Vec<_> = self
+            .bars
+            .iter()
+            .map(|bar| {
+                let address = self.bar_regs.address(bar.index as usize);
+                (self.mmio_enabled && address != 0).then_some(address)
+            })
+            .collect();
+
+        for (bar, address) in self.bars.iter_mut().zip(addresses) {
+            if bar.mapped_at == address {
+                continue;
+            }
+            if bar.mapped_at.take().is_some() {
+                match &mut bar.kind {
+                    BarKind::Mapped(control, _) => control.unmap_from_guest(),
+                    BarKind::Trapped(control) => control.unmap(),
+                }
+            }
+            let Some(address) = address else {
+                continue;
+            };
+            match &mut bar.kind {
+                BarKind::Mapped(control, _) => {
+                    if let Err(err) = control.map_to_guest(address, true) {
+                        tracelimit::error_ratelimited!(
+                            pci_id = self.pci_id.as_str(),
+                            bar = bar.index,
+                            address,
+                            error = &err as &dyn std::error::Error,
+                            "failed to map bar"
+                        );
+                        continue;
+                    }
+                }
+                BarKind::Trapped(control) => control.map(address),
+            }
+            bar.mapped_at = Some(address);
+        }
+    }
+
+    /// Sets up or tears down the host vectors as the guest enables or
+    /// disables MSI-X.
+    fn set_msix_enabled(&mut self, enabled: bool) {
+        if self.msix.enabled == enabled {
+            return;
+        }
+        self.msix.enabled = enabled;
+        for vector in &mut self.msix.vectors {
+            vector.event = None;
+            vector.pending = false;
+        }
+        let r = if enabled {
+            self.enable_host_vectors()
+        } else {
+            self.device.set_msix_eventfds(&[])
+        };
+        if let Err(err) = r {
+            tracing::error!(
+                pci_id = self.pci_id.as_str(),
+                enabled,
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to configure msi-x"
+            );
+        }
+    }
+
+    fn enable_host_vectors(&mut self) -> anyhow::Result<()> {
+        for vector in &mut self.msix.vectors {
+            vector.event = Some(PolledWait::new(&self.driver, Event::new())?);
+        }
+        let fds: Vec<_> = self
+            .msix
+            .vectors
+            .iter()
+            .map(|vector| vector.event.as_ref().unwrap().get().as_fd())
+            .collect();
+        self.device.set_msix_eventfds(&fds)
+    }
+
+    /// Returns the index into `bars` and the offset for a trapped access.
+    fn find_bar(&self, addr: u64, len: usize) -> Option<(usize, u64)> {
+        self.bars.iter().enumerate().find_map(|(i, bar)| {
+            let offset = addr.checked_sub(bar.mapped_at?)?;
+            (offset + len as u64 <= bar.len).then_some((i, offset))
+        })
+    }
+}
+
+impl ChangeDeviceState for VfioAssignedDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.set_msix_enabled(false);
+        if self.can_reset {
+            if let Err(err) = self.device.reset() {
+                tracing::warn!(
+                    pci_id = self.pci_id.as_str(),
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "failed to reset device"
+                );
+            }
+        }
+        self.bar_regs.reset();
+        self.mmio_enabled = false;
+        self.interrupt_line = 0;
+        self.update_bars();
+        self.msix.function_mask = false;
+        for vector in &mut self.msix.vectors {
+            vector.address = 0;
+            vector.data = 0;
+            vector.masked = true;
+        }
+        self.msix.sync_vectors();
+    }
+}
+
+impl ChipsetDevice for VfioAssignedDevice {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for VfioAssignedDevice {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        for vector in &mut self.msix.vectors {
+            while let Some(event) = &mut vector.event {
+                match event.poll_wait(cx) {
+                    Poll::Ready(Ok(())) => vector.signal(),
+                    Poll::Ready(Err(err)) => {
+                        tracing::error!(
+                            pci_id = self.pci_id.as_str(),
+                            error = &err as &dyn std::error::Error,
+                            "msi-x eventfd failed"
+                        );
+                        vector.event = None;
+                    }
+                    Poll::Pending => break,
+                }
+            }
+        }
+    }
+}
+
+impl MmioIntercept for VfioAssignedDevice {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        let Some((bar, offset)) = self.find_bar(addr, data.len()) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+        let bar = &self.bars[bar];
+        match self.msix.bar_area(bar.index, offset, data.len()) {
+            Ok(BarArea::MsixTable(offset)) => {
+                for (i, chunk) in data.chunks_exact_mut(4).enumerate() {
+                    let value = self.msix.read_table(offset + i as u64 * 4);
+                    chunk.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            Ok(BarArea::MsixPba(offset)) => {
+                for (i, chunk) in data.chunks_exact_mut(4).enumerate() {
+                    let value = self.msix.read_pba(offset + i as u64 * 4);
+                    chunk.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            Ok(BarArea::Device) => {
+                if let Err(err) = self
+                    .device
+                    .as_ref()
+                    .read_exact_at(data, bar.region_offset + offset)
+                {
+                    tracelimit::warn_ratelimited!(
+                        pci_id = self.pci_id.as_str(),
+                        bar = bar.index,
+                        offset,
+                        error = &err as &dyn std::error::Error,
+                        "bar read failed"
+                    );
+                    data.fill(!0);
+                }
+            }
+            Err(err) => return IoResult::Err(err),
+        }
+        IoResult::Ok
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        let Some((bar, offset)) = self.find_bar(addr, data.len()) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+        let bar = &self.bars[bar];
+        match self.msix.bar_area(bar.index, offset, data.len()) {
+            Ok(BarArea::MsixTable(offset)) => {
+                for (i, chunk) in data.chunks_exact(4).enumerate() {
+                    let value = u32::from_le_bytes(chunk.try_into().unwrap());
+                    self.msix.write_table(offset + i as u64 * 4, value);
+                }
+            }
+            // The PBA is read-only.
+            Ok(BarArea::MsixPba(_)) => {}
+            Ok(BarArea::Device) => {
+                if let Err(err) = self
+                    .device
+                    .as_ref()
+                    .write_all_at(data, bar.region_offset + offset)
+                {
+                    tracelimit::warn_ratelimited!(
+                        pci_id = self.pci_id.as_str(),
+                        bar = bar.index,
+                        offset,
+                        error = &err as &dyn std::error::Error,
+                        "bar write failed"
+                    );
+                }
+            }
+            Err(err) => return IoResult::Err(err),
+        }
+        IoResult::Ok
+    }
+}
+
+impl PciConfigSpace for VfioAssignedDevice {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        *value = match HeaderType00(offset) {
+            HeaderType00::BAR0
+            | HeaderType00::BAR1
+            | HeaderType00::BAR2
+            | HeaderType00::BAR3
+            | HeaderType00::BAR4
+            | HeaderType00::BAR5 => {
+                let index = ((offset - HeaderType00::BAR0.0) / 4) as usize;
+                self.bar_regs.read(index)
+            }
+            // Expansion ROMs are not supported.
+            HeaderType00::EXPANSION_ROM_BASE => 0,
+            // Report no INTx pin.
+            HeaderType00::LATENCY_INTERRUPT => {
+                (self.read_device_config(offset) & 0xffff_0000) | self.interrupt_line as u32
+            }
+            _ if offset == self.msix.cap_offset => {
+                let mut value =
+                    self.read_device_config(offset) & !(MSIX_ENABLE | MSIX_FUNCTION_MASK);
+                if self.msix.enabled {
+                    value |= MSIX_ENABLE;
+                }
+                if self.msix.function_mask {
+                    value |= MSIX_FUNCTION_MASK;
+                }
+                value
+            }
+            _ => self.read_device_config(offset),
+        };
+        IoResult::Ok
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        match HeaderType00(offset) {
+            HeaderType00::STATUS_COMMAND => {
+                // Don't write back the status, whose error bits are write-1 to
+                // clear.
+                let command = value as u16;
+                self.write_device_config(offset, &command.to_le_bytes());
+                self.mmio_enabled = cfg_space::Command::from(command).mmio_enabled();
+                self.update_bars();
+            }
+            HeaderType00::BAR0
+            | HeaderType00::BAR1
+            | HeaderType00::BAR2
+            | HeaderType00::BAR3
+            | HeaderType00::BAR4
+            | HeaderType00::BAR5 => {
+                let index = ((offset - HeaderType00::BAR0.0) / 4) as usize;
+                self.bar_regs.write(index, value);
+                self.update_bars();
+            }
+            HeaderType00::EXPANSION_ROM_BASE => {}
+            HeaderType00::LATENCY_INTERRUPT => self.interrupt_line = value as u8,
+            _ if offset == self.msix.cap_offset => {
+                // vfio-pci ignores writes to the control bits, since MSI-X is
+                // enabled on the host through the vfio interface instead.
+                self.msix.function_mask = value & MSIX_FUNCTION_MASK != 0;
+                self.set_msix_enabled(value & MSIX_ENABLE != 0);
+                self.msix.sync_vectors();
+            }
+            _ => self.write_device_config(offset, &value.to_le_bytes()),
+        }
+        IoResult::Ok
+    }
+}
+
+impl SaveRestore for VfioAssignedDevice {
+    type SavedState = SavedStateNotSupported;
+
+    fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+        Err(SaveError::NotSupported)
+    }
+
+    fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+        match state {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pci_core::test_helpers::TestPciInterruptController;
+
+    fn new_msix(msi: &TestPciInterruptController, vector_count: usize) -> Msix {
+        Msix {
+            cap_offset: 0x70,
+            table_bar: 0,
+            table_offset: 0x2000,
+            pba_bar: 0,
+            pba_offset: 0x3000,
+            enabled: false,
+            function_mask: false,
+            vectors: (0..vector_count)
+                .map(|_| MsixVector::new(msi.new_interrupt()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_bar_registers() {
+        let mut regs = BarRegisters::default();
+        regs.set_memory_bar(0, 0, 0x1000);
+        // 64-bit prefetchable BARs.
+        regs.set_memory_bar(2, 0xc, 0x100000);
+        regs.set_memory_bar(4, 0xc, 0x200000000);
+
+        // Sizing: the guest writes all ones and reads back the size.
+        for index in 0..6 {
+            regs.write(index, !0);
+        }
+        assert_eq!(regs.read(0), 0xfffff000);
+        assert_eq!(regs.read(1), 0);
+        assert_eq!(regs.read(2), 0xfff0000c);
+        assert_eq!(regs.read(3), 0xffffffff);
+        assert_eq!(regs.read(4), 0xc);
+        assert_eq!(regs.read(5), 0xfffffffe);
+
+        regs.write(0, 0xfe000123);
+        assert_eq!(regs.read(0), 0xfe000000);
+        assert_eq!(regs.address(0), 0xfe000000);
+        regs.write(2, 0xc0100000);
+        regs.write(3, 1);
+        assert_eq!(regs.address(2), 0x1c0100000);
+        regs.write(4, 0);
+        regs.write(5, 0x3);
+        assert_eq!(regs.address(4), 0x200000000);
+
+        regs.reset();
+        assert_eq!(regs.read(0), 0);
+        assert_eq!(regs.read(2), 0xc);
+        assert_eq!(regs.address(2), 0);
+    }
+
+    #[test]
+    fn test_cap_ptr_overrides() {
+        const CAP_ID_PM: u8 = 0x01;
+        let cap_ptr = HeaderType00::RESERVED_CAP_PTR.0;
+
+        // MSI is removed from the middle of the list.
+        let overrides = cap_ptr_overrides(&[
+            (0x40, CAP_ID_PM),
+            (0x50, CAP_ID_MSI),
+            (0x70, CapabilityId::MSIX.0),
+        ]);
+        assert_eq!(overrides, [(cap_ptr, 0x40), (0x41, 0x70), (0x71, 0)]);
+
+        // MSI is removed from the start of the list.
+        let overrides = cap_ptr_overrides(&[(0x50, CAP_ID_MSI), (0x70, CapabilityId::MSIX.0)]);
+        assert_eq!(overrides, [(cap_ptr, 0x70), (0x71, 0)]);
+
+        assert_eq!(cap_ptr_overrides(&[]), [(cap_ptr, 0)]);
+    }
+
+    #[test]
+    fn test_apply_cap_ptr_overrides() {
+        let overrides = [(0x34, 0x40), (0x41, 0x70), (0x71, 0)];
+
+        let mut value = [0x50, 0, 0, 0];
+        apply_cap_ptr_overrides(&overrides, 0x34, &mut value);
+        assert_eq!(value, [0x40, 0, 0, 0]);
+
+        let mut value = [0x01, 0x50, 0x03, 0xc8];
+        apply_cap_ptr_overrides(&overrides, 0x40, &mut value);
+        assert_eq!(value, [0x01, 0x70, 0x03, 0xc8]);
+
+        let mut value = [0x11, 0x00, 0x07, 0x80];
+        apply_cap_ptr_overrides(&overrides, 0x70, &mut value);
+        assert_eq!(value, [0x11, 0x00, 0x07, 0x80]);
+
+        let mut value = [1, 2, 3, 4];
+        apply_cap_ptr_overrides(&overrides, 0x44, &mut value);
+        assert_eq!(value, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_msix_bar_area() {
+        let msi = TestPciInterruptController::new();
+        // A 64-byte table at 0x2000 and an 8-byte PBA at 0x3000.
+        let msix = new_msix(&msi, 4);
+
+        let area = |bar, offset, len| msix.bar_area(bar, offset, len);
+        assert!(matches!(area(0, 0x2000, 4), Ok(BarArea::MsixTable(0))));
+        assert!(matches!(area(0, 0x2018, 8), Ok(BarArea::MsixTable(0x18))));
+        assert!(matches!(area(0, 0x3000, 8), Ok(BarArea::MsixPba(0))));
+        assert!(matches!(area(0, 0x3004, 4), Ok(BarArea::MsixPba(4))));
+        assert!(matches!(area(0, 0x1000, 4), Ok(BarArea::Device)));
+        assert!(matches!(area(0, 0x2040, 4), Ok(BarArea::Device)));
+        assert!(matches!(area(0, 0x3008, 1), Ok(BarArea::Device)));
+        assert!(matches!(area(1, 0x2000, 4), Ok(BarArea::Device)));
+
+        // Unaligned, narrow, and straddling accesses are rejected.
+        assert!(area(0, 0x2002, 4).is_err());
+        assert!(area(0, 0x2000, 2).is_err());
+        assert!(area(0, 0x203c, 8).is_err());
+        assert!(area(0, 0x1ffc, 8).is_err());
+    }
+
+    #[test]
+    fn test_msix_table() {
+        let msi = TestPciInterruptController::new();
+        let mut msix = new_msix(&msi, 4);
+
+        assert_eq!(msix.read_table(0xc), MSIX_VECTOR_MASKED);
+        msix.write_table(0x10, 0xfee00000);
+        msix.write_table(0x14, 0x1);
+        msix.write_table(0x18, 0x41);
+        msix.write_table(0x1c, 0);
+        assert_eq!(msix.read_table(0x10), 0xfee00000);
+        assert_eq!(msix.read_table(0x14), 0x1);
+        assert_eq!(msix.read_table(0x18), 0x41);
+        assert_eq!(msix.read_table(0x1c), 0);
+        assert_eq!(msix.vectors[1].address, 0x1fee00000);
+
+        // The other vectors are untouched.
+        assert_eq!(msix.read_table(0x0), 0);
+        assert_eq!(msix.read_table(0x2c), MSIX_VECTOR_MASKED);
+    }
+
+    #[test]
+    fn test_msix_interrupt_mapping() {
+        let msi = TestPciInterruptController::new();
+        let mut msix = new_msix(&msi, 4);
+
+        // Unmasking a vector while MSI-X is disabled does not enable it, and
+        // its signals are left pending.
+        msix.write_table(0x10, 0xfee00000);
+        msix.write_table(0x18, 0x41);
+        msix.write_table(0x1c, 0);
+        assert_eq!(msix.vectors[1].enabled_with, None);
+        msix.vectors[1].signal();
+        assert_eq!(msi.get_next_interrupt(), None);
+        assert_eq!(msix.read_pba(0), 0b10);
+
+        // Enabling MSI-X enables the vector and delivers the pending signal.
+        msix.enabled = true;
+        msix.sync_vectors();
+        assert_eq!(msix.vectors[1].enabled_with, Some((0xfee00000, 0x41)));
+        assert_eq!(msi.get_next_interrupt(), Some((0xfee00000, 0x41)));
+        assert_eq!(msix.read_pba(0), 0);
+        msix.vectors[1].signal();
+        assert_eq!(msi.get_next_interrupt(), Some((0xfee00000, 0x41)));
+        assert_eq!(msix.vectors[1].signaled, 2);
+
+        // Vectors that are still masked stay disabled.
+        assert_eq!(msix.vectors[0].enabled_with, None);
+
+        // Reprogramming an unmasked vector takes effect immediately.
+        msix.write_table(0x10, 0xfee01000);
+        assert_eq!(msix.vectors[1].enabled_with, Some((0xfee01000, 0x41)));
+
+        // The function mask disables all vectors.
+        msix.function_mask = true;
+        msix.sync_vectors();
+        assert_eq!(msix.vectors[1].enabled_with, None);
+        msix.vectors[1].signal();
+        assert_eq!(msi.get_next_interrupt(), None);
+        assert_eq!(msix.read_pba(0), 0b10);
+
+        // A pending signal on a masked vector is held until it is unmasked,
+        // and then delivered with the data programmed at that point.
+        msix.write_table(0x1c, MSIX_VECTOR_MASKED);
+        msix.function_mask = false;
+        msix.sync_vectors();
+        assert_eq!(msi.get_next_interrupt(), None);
+        msix.write_table(0x18, 0x42);
+        msix.write_table(0x1c, 0);
+        assert_eq!(msi.get_next_interrupt(), Some((0xfee01000, 0x42)));
+        assert_eq!(msix.read_pba(0), 0);
+    }
+
+    #[test]
+    fn test_msix_pba() {
+        let msi = TestPciInterruptController::new();
+        let mut msix = new_msix(&msi, 40);
+        assert_eq!(msix.pba_len(), 8);
+
+        msix.vectors[0].signal();
+        msix.vectors[33].signal();
+        msix.vectors[39].signal();
+        assert_eq!(msix.read_pba(0), 1);
+        assert_eq!(msix.read_pba(4), 0b1000_0010);
+    }
+}
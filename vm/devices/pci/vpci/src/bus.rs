@@ -16,11 +16,14 @@
 use device_emulators::ReadWriteRequestType;
 use device_emulators::read_as_u32_chunks;
 use device_emulators::write_as_u32_chunks;
+use futures::lock::Mutex;
 use guid::Guid;
 use hvdef::HV_PAGE_SIZE;
+use inspect::Inspect;
 use inspect::InspectMut;
 use std::sync::Arc;
 use thiserror::Error;
+use vmbus_channel::bus::ParentBus;
 use vmbus_channel::simple::SimpleDeviceHandle;
 use vmbus_channel::simple::offer_simple_device;
 use vmcore::device_state::ChangeDeviceState;
@@ -42,8 +45,33 @@
 pub struct VpciBus {
     #[inspect(mut, flatten)]
     bus_device: VpciBusDevice,
-    #[inspect(flatten)]
-    channel: SimpleDeviceHandle<VpciChannel>,
+    #[inspect(flatten, with = "inspect_channel")]
+    channel: Arc<Mutex<BusChannel>>,
+}
+
+/// The bus's vmbus channel, which is either offered to the guest or revoked.
+struct BusChannel {
+    offered: Option<SimpleDeviceHandle<VpciChannel>>,
+    revoked: Option<VpciChannel>,
+    running: bool,
+}
+
+impl Inspect for BusChannel {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        resp.field("offered", self.offered.is_some());
+        if let Some(offered) = &self.offered {
+            resp.merge(offered);
+        }
+    }
+}
+
+fn inspect_channel(channel: &Arc<Mutex<BusChannel>>) -> impl Inspect + '_ {
+    inspect::adhoc(|req| {
+        if let Some(channel) = channel.try_lock() {
+            channel.inspect(req);
+        }
+    })
 }
 
 /// The chipset device portion of the VPCI bus.
@@ -70,6 +98,24 @@ pub enum CreateBusError {
     Offer(#[source] anyhow::Error),
 }
 
+/// An error revoking or offering a VPCI bus's device at runtime.
+#[derive(Debug, Error)]
+pub enum HotPlugError {
+    /// The device is already offered to the guest.
+    #[error("device is already offered")]
+    AlreadyOffered,
+    /// The device is already revoked.
+    #[error("device is already revoked")]
+    AlreadyRevoked,
+    /// The vmbus server stopped while the device was being revoked, so it
+    /// cannot be offered again.
+    #[error("device was lost while being revoked")]
+    Lost,
+    /// The vmbus channel offer failed.
+    #[error("failed to offer vpci vmbus channel")]
+    Offer(#[source] anyhow::Error),
+}
+
 impl VpciBusDevice {
     /// Returns a new VPCI bus device, along with the vmbus channel used for bus
     /// communications.
@@ -118,22 +164,99 @@ pub async fn new(
 
         Ok(Self {
             bus_device: bus,
-            channel,
+            channel: Arc::new(Mutex::new(BusChannel {
+                offered: Some(channel),
+                revoked: None,
+                running: false,
+            })),
         })
     }
+
+    /// Returns a handle for revoking the device from the guest and offering
+    /// it again while the VM is running.
+    pub fn hot_plug_handle(&self) -> VpciHotPlugHandle {
+        VpciHotPlugHandle {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+/// A handle for revoking a VPCI bus's device from the guest and offering it
+/// again, as when the host removes and restores an SR-IOV virtual function.
+///
+/// Revoking the bus's vmbus channel removes the device from the guest's view
+/// of the bus. The chipset device stays in place, so the same device, with
+/// the same instance ID, is offered again.
+///
+/// This must not be used concurrently with changes to the VM's state, which
+/// is the case when both are driven by the VM worker.
+#[derive(Clone)]
+pub struct VpciHotPlugHandle {
+    channel: Arc<Mutex<BusChannel>>,
+}
+
+impl VpciHotPlugHandle {
+    /// Returns whether the device is currently offered to the guest.
+    pub async fn is_offered(&self) -> bool {
+        self.channel.lock().await.offered.is_some()
+    }
+
+    /// Revokes the device from the guest, waiting for the guest to release
+    /// it.
+    pub async fn revoke_device(&self) -> Result<(), HotPlugError> {
+        let mut channel = self.channel.lock().await;
+        let offered = channel.offered.take().ok_or(HotPlugError::AlreadyRevoked)?;
+        channel.revoked = Some(offered.revoke().await.ok_or(HotPlugError::Lost)?);
+        Ok(())
+    }
+
+    /// Offers the device to the guest again after
+    /// [`revoke_device`](Self::revoke_device).
+    pub async fn offer_device(
+        &self,
+        driver_source: &VmTaskDriverSource,
+        vmbus: &dyn ParentBus,
+    ) -> Result<(), HotPlugError> {
+        let mut channel = self.channel.lock().await;
+        if channel.offered.is_some() {
+            return Err(HotPlugError::AlreadyOffered);
+        }
+        let revoked = channel.revoked.take().ok_or(HotPlugError::Lost)?;
+        let offered = offer_simple_device(driver_source, vmbus, revoked)
+            .await
+            .map_err(HotPlugError::Offer)?;
+        if channel.running {
+            offered.start();
+        }
+        channel.offered = Some(offered);
+        Ok(())
+    }
 }
 
 impl ChangeDeviceState for VpciBus {
     fn start(&mut self) {
-        self.channel.start();
+        let mut channel = self
+            .channel
+            .try_lock()
+            .expect("no concurrent revoke or offer");
+        channel.running = true;
+        if let Some(offered) = &channel.offered {
+            offered.start();
+        }
     }
 
     async fn stop(&mut self) {
-        self.channel.stop().await;
+        let mut channel = self.channel.lock().await;
+        channel.running = false;
+        if let Some(offered) = &channel.offered {
+            offered.stop().await;
+        }
     }
 
     async fn reset(&mut self) {
-        self.channel.reset().await;
+        if let Some(offered) = &self.channel.lock().await.offered {
+            offered.reset().await;
+        }
     }
 }
 
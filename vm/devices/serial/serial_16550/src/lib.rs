@@ -5,6 +5,7 @@
 
 #![forbid(unsafe_code)]
 
+pub mod pci;
 pub mod resolver;
 mod spec;
 
@@ -0,0 +1,243 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A multi-port PCI serial card.
+//!
+//! The card exposes up to four 16550 UARTs through a single memory BAR, eight
+//! bytes apart, sharing the card's INTx interrupt. It uses the Red Hat PCI
+//! serial device IDs (the same ones used by QEMU's `pci-serial` devices), so
+//! guests such as Linux bind a generic 8250 driver to it without any extra
+//! configuration.
+
+use crate::ConfigurationError;
+use crate::Serial16550;
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::poll_device::PollDevice;
+use inspect::InspectMut;
+use pci_core::PciInterruptPin;
+use pci_core::cfg_space_emu::BarMemoryKind;
+use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
+use pci_core::cfg_space_emu::DeviceBars;
+use pci_core::cfg_space_emu::IntxInterrupt;
+use pci_core::spec::hwid::ClassCode;
+use pci_core::spec::hwid::HardwareIds;
+use pci_core::spec::hwid::ProgrammingInterface;
+use pci_core::spec::hwid::Subclass;
+use serial_16550_resources::MmioOrIoPort;
+use serial_core::SerialIo;
+use std::sync::Arc;
+use std::task::Context;
+use thiserror::Error;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+use vmcore::line_interrupt::LineSet;
+use vmcore::line_interrupt::LineSetTarget;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
+use vmcore::save_restore::SaveRestore;
+use vmcore::save_restore::SavedStateNotSupported;
+
+const VENDOR_ID_REDHAT: u16 = 0x1b36;
+const DEVICE_ID_REDHAT_SERIAL: u16 = 0x0002;
+const DEVICE_ID_REDHAT_SERIAL2: u16 = 0x0003;
+const DEVICE_ID_REDHAT_SERIAL4: u16 = 0x0004;
+
+/// The size of each port's register bank within the BAR.
+const PORT_STRIDE: u64 = 8;
+
+/// The maximum number of ports on a single card.
+pub const MAX_PORTS: usize = 4;
+
+/// An error returned by [`Serial16550PciCard::new`].
+#[derive(Debug, Error)]
+pub enum PciCardError {
+    /// The card only comes in one, two, and four port variants.
+    #[error("unsupported port count: {0}")]
+    UnsupportedPortCount(usize),
+    /// A port could not be configured.
+    #[error("failed to configure serial port")]
+    Configuration(#[source] ConfigurationError),
+}
+
+/// Forwards the ports' (shared) interrupt line to the card's INTx pin.
+struct IntxTarget(Arc<IntxInterrupt>);
+
+impl LineSetTarget for IntxTarget {
+    fn set_irq(&self, _vector: u32, high: bool) {
+        self.0.set_level(high);
+    }
+}
+
+/// A PCI serial card with multiple 16550 UARTs.
+pub struct Serial16550PciCard {
+    cfg_space: ConfigSpaceType0Emulator,
+    ports: Vec<Serial16550>,
+}
+
+impl Serial16550PciCard {
+    /// Returns a new card with one port per entry in `io`, which must have
+    /// one, two, or four entries.
+    ///
+    /// `debug_name` is used to improve tracing statements.
+    pub fn new(
+        debug_name: &str,
+        interrupt: LineInterrupt,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        io: Vec<Box<dyn SerialIo>>,
+        wait_for_rts: bool,
+    ) -> Result<Self, PciCardError> {
+        let device_id = match io.len() {
+            1 => DEVICE_ID_REDHAT_SERIAL,
+            2 => DEVICE_ID_REDHAT_SERIAL2,
+            4 => DEVICE_ID_REDHAT_SERIAL4,
+            n => return Err(PciCardError::UnsupportedPortCount(n)),
+        };
+
+        let bar_len = PORT_STRIDE * io.len() as u64;
+        let bars = DeviceBars::new().bar0(
+            bar_len,
+            BarMemoryKind::Intercept(register_mmio.new_io_region("bar0", bar_len)),
+        );
+
+        let mut cfg_space = ConfigSpaceType0Emulator::new(
+            HardwareIds {
+                vendor_id: VENDOR_ID_REDHAT,
+                device_id,
+                revision_id: 1,
+                prog_if: ProgrammingInterface::SIMPLE_COMMUNICATION_CONTROLLER_SERIAL_16550,
+                sub_class: Subclass::SIMPLE_COMMUNICATION_CONTROLLER_SERIAL,
+                base_class: ClassCode::SIMPLE_COMMUNICATION_CONTROLLER,
+                type0_sub_vendor_id: VENDOR_ID_REDHAT,
+                type0_sub_system_id: 0,
+            },
+            Vec::new(),
+            bars,
+        );
+
+        // Each port gets its own line on the same vector, so the card's
+        // interrupt is asserted while any port's interrupt is.
+        let intx = cfg_space.set_interrupt_pin(PciInterruptPin::IntA, interrupt);
+        let lines = LineSet::new();
+        lines.add_target(0..=0, 0, "intx", Arc::new(IntxTarget(intx)));
+
+        let ports = io
+            .into_iter()
+            .enumerate()
+            .map(|(i, io)| {
+                let interrupt = lines
+                    .new_line(0, format!("port{i}"))
+                    .expect("ports are limited to MAX_PORTS");
+                // The port is only accessed through the BAR, so the base
+                // address is just the offset within it.
+                Serial16550::new(
+                    format!("{debug_name}-{i}"),
+                    MmioOrIoPort::Mmio(PORT_STRIDE * i as u64),
+                    1,
+                    interrupt,
+                    io,
+                    wait_for_rts,
+                )
+                .map_err(PciCardError::Configuration)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { cfg_space, ports })
+    }
+
+    fn port(&mut self, addr: u64) -> Option<(&mut Serial16550, u64)> {
+        let (0, offset) = self.cfg_space.find_bar(addr)? else {
+            return None;
+        };
+        let offset = offset as u64;
+        let port = self.ports.get_mut((offset / PORT_STRIDE) as usize)?;
+        Some((port, offset % PORT_STRIDE))
+    }
+}
+
+impl InspectMut for Serial16550PciCard {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        resp.field("cfg_space", &self.cfg_space);
+        for (i, port) in self.ports.iter_mut().enumerate() {
+            resp.field_mut(&i.to_string(), port);
+        }
+    }
+}
+
+impl ChangeDeviceState for Serial16550PciCard {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.cfg_space.reset();
+        for port in &mut self.ports {
+            port.reset().await;
+        }
+    }
+}
+
+impl ChipsetDevice for Serial16550PciCard {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for Serial16550PciCard {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        for port in &mut self.ports {
+            port.poll_device(cx);
+        }
+    }
+}
+
+impl MmioIntercept for Serial16550PciCard {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        match self.port(addr) {
+            Some((port, offset)) => port.read(offset, data),
+            None => IoResult::Err(IoError::InvalidRegister),
+        }
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        match self.port(addr) {
+            Some((port, offset)) => port.write(offset, data),
+            None => IoResult::Err(IoError::InvalidRegister),
+        }
+    }
+}
+
+impl PciConfigSpace for Serial16550PciCard {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        self.cfg_space.read_u32(offset, value)
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        self.cfg_space.write_u32(offset, value)
+    }
+}
+
+impl SaveRestore for Serial16550PciCard {
+    type SavedState = SavedStateNotSupported;
+
+    fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+        Err(SaveError::NotSupported)
+    }
+
+    fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+        match state {}
+    }
+}
@@ -6,6 +6,10 @@
 //! This is a zero-configuration, output-only serial device, which should only
 //! be used for debugging (hence the name). It offers no flow control
 //! mechanisms, or any method of reading data into the Guest.
+//!
+//! In addition to (or instead of) a serial backend, the output can be routed
+//! into the host's `tracing` stream, one event per line, so that firmware
+//! debug output is interleaved with the VMM's own logs.
 
 #![forbid(unsafe_code)]
 
@@ -19,6 +23,8 @@
 use futures::AsyncWrite;
 use inspect::InspectMut;
 use serial_core::SerialIo;
+use serial_debugcon_resources::DebugconTraceConfig;
+use serial_debugcon_resources::DebugconTraceLevel;
 use std::collections::VecDeque;
 use std::io::ErrorKind;
 use std::ops::RangeInclusive;
@@ -34,6 +40,9 @@
 // all at once.
 const TX_BUFFER_MAX: usize = 1024 * 1024; // 1MB
 
+// Lines longer than this are split across multiple tracing events.
+const TRACE_LINE_MAX: usize = 1024;
+
 /// A debugcon serial port emulator.
 #[derive(InspectMut)]
 pub struct SerialDebugcon {
@@ -45,26 +54,73 @@ pub struct SerialDebugcon {
     // Runtime glue
     #[inspect(mut)]
     io: Box<dyn SerialIo>,
+    #[inspect(with = "|x| x.as_ref().map(|x| &x.target)")]
+    trace: Option<DebugconTraceConfig>,
 
     // Volatile state
     #[inspect(with = "VecDeque::len")]
     tx_buffer: VecDeque<u8>,
+    #[inspect(with = "Vec::len")]
+    trace_line: Vec<u8>,
     #[inspect(skip)]
     tx_waker: Option<Waker>,
 }
 
 impl SerialDebugcon {
     /// Returns a new emulator instance.
-    pub fn new(port: u16, io: Box<dyn SerialIo>) -> Self {
+    ///
+    /// If `trace` is set, each line of output is also emitted as a tracing
+    /// event.
+    pub fn new(port: u16, io: Box<dyn SerialIo>, trace: Option<DebugconTraceConfig>) -> Self {
         Self {
             io_port: port,
             io_region: ("debugcon", port..=port),
             io,
+            trace,
             tx_buffer: VecDeque::new(),
+            trace_line: Vec::new(),
             tx_waker: None,
         }
     }
 
+    /// Accumulates `byte` into the current trace line, emitting the line when
+    /// it is complete.
+    fn trace_byte(&mut self, byte: u8) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+        match byte {
+            b'\n' => {}
+            b'\r' => return,
+            _ => {
+                self.trace_line.push(byte);
+                if self.trace_line.len() < TRACE_LINE_MAX {
+                    return;
+                }
+            }
+        }
+        let line = String::from_utf8_lossy(&self.trace_line);
+        let inner_target = trace.target.as_str();
+        match trace.level {
+            DebugconTraceLevel::Error => {
+                tracing::error!(target: "debugcon", inner_target, "{line}")
+            }
+            DebugconTraceLevel::Warn => {
+                tracing::warn!(target: "debugcon", inner_target, "{line}")
+            }
+            DebugconTraceLevel::Info => {
+                tracing::info!(target: "debugcon", inner_target, "{line}")
+            }
+            DebugconTraceLevel::Debug => {
+                tracing::debug!(target: "debugcon", inner_target, "{line}")
+            }
+            DebugconTraceLevel::Trace => {
+                tracing::trace!(target: "debugcon", inner_target, "{line}")
+            }
+        }
+        self.trace_line.clear();
+    }
+
     /// Synchronize interrupt and waker state with device state.
     fn sync(&mut self) {
         // Wake to poll if there are any bytes to write.
@@ -109,6 +165,7 @@ async fn stop(&mut self) {}
 
     async fn reset(&mut self) {
         self.tx_buffer.clear();
+        self.trace_line.clear();
     }
 }
 
@@ -156,6 +213,8 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
             return IoResult::Err(IoError::InvalidAccessSize);
         }
 
+        self.trace_byte(data[0]);
+
         if self.tx_buffer.len() >= TX_BUFFER_MAX {
             tracing::debug!("debugcon buffer overrun, dropping output data");
             return IoResult::Ok;
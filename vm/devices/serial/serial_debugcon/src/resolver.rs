@@ -56,7 +56,7 @@ async fn resolve(
             .await
             .map_err(ResolveDebugconError::ResolveBackend)?;
 
-        let device = SerialDebugcon::new(resource.port, io.0.into_io());
+        let device = SerialDebugcon::new(resource.port, io.0.into_io(), resource.trace);
         Ok(device.into())
     }
 }
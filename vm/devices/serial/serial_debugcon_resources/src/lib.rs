@@ -18,6 +18,32 @@ pub struct SerialDebugconDeviceHandle {
     pub port: u16,
     /// The IO backend.
     pub io: Resource<SerialBackendHandle>,
+    /// If set, also emit the output as host tracing events.
+    pub trace: Option<DebugconTraceConfig>,
+}
+
+/// Configuration for routing debugcon output to the host's tracing stream.
+///
+/// Each line of output is emitted as an event with the `debugcon` target (so
+/// that it can be filtered like any other OpenVMM log), with the configured
+/// target recorded in the event's `inner_target` field.
+#[derive(Debug, Clone, MeshPayload)]
+pub struct DebugconTraceConfig {
+    /// The level to emit events at.
+    pub level: DebugconTraceLevel,
+    /// The name to record as the event's inner target.
+    pub target: String,
+}
+
+/// The level of debugcon tracing events.
+#[expect(missing_docs)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, MeshPayload)]
+pub enum DebugconTraceLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
 }
 
 impl ResourceId<ChipsetDeviceHandleKind> for SerialDebugconDeviceHandle {
@@ -0,0 +1,257 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! TCP client serial backend.
+//!
+//! Rather than waiting for an incoming connection, this backend connects out
+//! to a remote listener (such as a console concentrator). If the connection
+//! cannot be established or is later dropped, the backend retries
+//! periodically until it succeeds.
+
+use futures::AsyncRead;
+use futures::AsyncWrite;
+use inspect::InspectMut;
+use mesh::MeshPayload;
+use pal_async::driver::Driver;
+use pal_async::interest::PollEvents;
+use pal_async::socket::PollReady;
+use pal_async::socket::PolledSocket;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use serial_core::SerialIo;
+use serial_core::resources::ResolveSerialBackendParams;
+use serial_core::resources::ResolvedSerialBackend;
+use socket2::Domain;
+use socket2::Socket;
+use socket2::Type;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use vm_resource::ResolveResource;
+use vm_resource::Resource;
+use vm_resource::ResourceId;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::SerialBackendHandle;
+
+#[derive(Debug, MeshPayload)]
+pub struct TcpClientSerialConfig {
+    /// The remote address to connect to, as `<ip>:<port>`.
+    pub addr: String,
+    /// How long to wait between connection attempts.
+    pub retry: Duration,
+}
+
+impl ResourceId<SerialBackendHandle> for TcpClientSerialConfig {
+    const ID: &'static str = "tcp_client";
+}
+
+pub struct TcpClientSerialResolver;
+declare_static_resolver!(
+    TcpClientSerialResolver,
+    (SerialBackendHandle, TcpClientSerialConfig)
+);
+
+impl ResolveResource<SerialBackendHandle, TcpClientSerialConfig> for TcpClientSerialResolver {
+    type Output = ResolvedSerialBackend;
+    type Error = io::Error;
+
+    fn resolve(
+        &self,
+        rsrc: TcpClientSerialConfig,
+        input: ResolveSerialBackendParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(TcpClientSerialBackend::new(input.driver, rsrc)?.into())
+    }
+}
+
+type ConnectFuture = Pin<Box<dyn Future<Output = io::Result<PolledSocket<Socket>>> + Send>>;
+
+pub struct TcpClientSerialBackend {
+    driver: Box<dyn Driver>,
+    addr: SocketAddr,
+    retry: Duration,
+    timer: PolledTimer,
+    current: Option<PolledSocket<Socket>>,
+    connecting: Option<ConnectFuture>,
+    retry_at: Option<Instant>,
+}
+
+impl InspectMut for TcpClientSerialBackend {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond()
+            .display("addr", &self.addr)
+            .field("retry_ms", self.retry.as_millis() as u64)
+            .field_with("state", || {
+                if self.current.is_some() {
+                    "connected"
+                } else if self.connecting.is_some() {
+                    "connecting"
+                } else {
+                    "waiting"
+                }
+            });
+    }
+}
+
+impl TcpClientSerialBackend {
+    pub fn new(driver: Box<dyn Driver>, config: TcpClientSerialConfig) -> io::Result<Self> {
+        let addr = config
+            .addr
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        Ok(Self {
+            timer: PolledTimer::new(&driver),
+            driver,
+            addr,
+            retry: config.retry,
+            current: None,
+            connecting: None,
+            retry_at: None,
+        })
+    }
+
+    pub fn into_config(self) -> TcpClientSerialConfig {
+        TcpClientSerialConfig {
+            addr: self.addr.to_string(),
+            retry: self.retry,
+        }
+    }
+
+    fn start_connect(&self) -> io::Result<ConnectFuture> {
+        let socket = Socket::new(Domain::for_address(self.addr), Type::STREAM, None)?;
+        let mut socket = PolledSocket::new(&self.driver, socket)?;
+        let addr = self.addr.into();
+        Ok(Box::pin(async move {
+            socket.connect(&addr).await?;
+            Ok(socket)
+        }))
+    }
+
+    /// Drops the current connection if `r` indicates the remote end has gone
+    /// away, reporting success to the caller so that output is discarded
+    /// until the connection is reestablished.
+    fn check_disconnect<T>(&mut self, r: io::Result<T>, disconnected: T) -> io::Result<T> {
+        match r {
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                tracing::debug!(addr = %self.addr, "serial connection dropped");
+                self.current = None;
+                Ok(disconnected)
+            }
+            r => r,
+        }
+    }
+}
+
+impl From<TcpClientSerialBackend> for Resource<SerialBackendHandle> {
+    fn from(value: TcpClientSerialBackend) -> Self {
+        Resource::new(value.into_config())
+    }
+}
+
+impl SerialIo for TcpClientSerialBackend {
+    fn is_connected(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn poll_connect(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.current.is_none() {
+            if let Some(retry_at) = self.retry_at {
+                ready!(self.timer.poll_until(cx, retry_at));
+                self.retry_at = None;
+            }
+            let r = match &mut self.connecting {
+                Some(connecting) => ready!(connecting.as_mut().poll(cx)),
+                None => match self.start_connect() {
+                    Ok(connecting) => {
+                        self.connecting = Some(connecting);
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                },
+            };
+            self.connecting = None;
+            match r {
+                Ok(socket) => {
+                    tracing::info!(addr = %self.addr, "serial connected");
+                    self.current = Some(socket);
+                }
+                Err(err) => {
+                    tracing::debug!(
+                        addr = %self.addr,
+                        error = &err as &dyn std::error::Error,
+                        "serial connection failed, retrying"
+                    );
+                    self.retry_at = Some(Instant::now() + self.retry);
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_disconnect(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(current) = &mut self.current {
+            ready!(current.poll_ready(cx, PollEvents::RDHUP));
+            tracing::debug!(addr = %self.addr, "serial connection closed");
+            self.current = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for TcpClientSerialBackend {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let Some(current) = &mut self.current else {
+            return Poll::Ready(Ok(0));
+        };
+        let r = ready!(Pin::new(current).poll_read(cx, buf));
+        let r = self.check_disconnect(r, 0);
+        if matches!(r, Ok(0)) {
+            self.current = None;
+        }
+        Poll::Ready(r)
+    }
+}
+
+impl AsyncWrite for TcpClientSerialBackend {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let Some(current) = &mut self.current else {
+            return Poll::Ready(Ok(buf.len()));
+        };
+        let r = ready!(Pin::new(current).poll_write(cx, buf));
+        Poll::Ready(self.check_disconnect(r, buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(current) = &mut self.current else {
+            return Poll::Ready(Ok(()));
+        };
+        let r = ready!(Pin::new(current).poll_flush(cx));
+        Poll::Ready(self.check_disconnect(r, ()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(current) = &mut self.current else {
+            return Poll::Ready(Ok(()));
+        };
+        let r = ready!(Pin::new(current).poll_close(cx));
+        Poll::Ready(self.check_disconnect(r, ()))
+    }
+}
@@ -1,11 +1,15 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! Serial port backends based on sockets and Windows named pipes.
+//! Serial port backends based on sockets (listening or connecting), Windows
+//! named pipes, and Unix pseudo-terminals.
 
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
+pub mod client;
 pub mod net;
+#[cfg(unix)]
+pub mod pty;
 #[cfg(windows)]
 pub mod windows;
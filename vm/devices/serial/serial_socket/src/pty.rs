@@ -0,0 +1,203 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Unix pseudo-terminal serial backend.
+//!
+//! The backend owns the primary side of the pseudo-terminal. It is considered
+//! connected while some process (such as `screen` or `minicom`) has the
+//! secondary side open. Since there is no notification when the secondary side
+//! is reopened after a hangup, the backend polls for it periodically.
+
+use futures::AsyncRead;
+use futures::AsyncWrite;
+use inspect::InspectMut;
+use mesh::MeshPayload;
+use pal::unix::pty;
+use pal_async::driver::Driver;
+use pal_async::pipe::PolledPipe;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use serial_core::SerialIo;
+use serial_core::resources::ResolveSerialBackendParams;
+use serial_core::resources::ResolvedSerialBackend;
+use std::fs::File;
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use vm_resource::ResolveResource;
+use vm_resource::Resource;
+use vm_resource::ResourceId;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::SerialBackendHandle;
+
+/// How often to check whether the secondary side has been opened or closed.
+const HANGUP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, MeshPayload)]
+pub struct OpenPtySerialConfig {
+    /// The primary side of the pseudo-terminal.
+    pub primary: File,
+}
+
+impl From<File> for OpenPtySerialConfig {
+    fn from(primary: File) -> Self {
+        Self { primary }
+    }
+}
+
+impl ResourceId<SerialBackendHandle> for OpenPtySerialConfig {
+    const ID: &'static str = "pty";
+}
+
+pub struct PtySerialResolver;
+declare_static_resolver!(
+    PtySerialResolver,
+    (SerialBackendHandle, OpenPtySerialConfig)
+);
+
+impl ResolveResource<SerialBackendHandle, OpenPtySerialConfig> for PtySerialResolver {
+    type Output = ResolvedSerialBackend;
+    type Error = io::Error;
+
+    fn resolve(
+        &self,
+        rsrc: OpenPtySerialConfig,
+        input: ResolveSerialBackendParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(PtySerialBackend::new(input.driver, rsrc)?.into())
+    }
+}
+
+#[derive(InspectMut)]
+pub struct PtySerialBackend {
+    #[inspect(skip)]
+    primary: PolledPipe,
+    #[inspect(skip)]
+    timer: PolledTimer,
+    connected: bool,
+}
+
+impl PtySerialBackend {
+    pub fn new(driver: Box<dyn Driver>, config: OpenPtySerialConfig) -> io::Result<Self> {
+        let connected = !pty::is_hung_up(&config.primary)?;
+        Ok(Self {
+            primary: PolledPipe::new(&driver, config.primary)?,
+            timer: PolledTimer::new(&driver),
+            connected,
+        })
+    }
+
+    pub fn into_config(self) -> OpenPtySerialConfig {
+        OpenPtySerialConfig {
+            primary: self.primary.into_inner(),
+        }
+    }
+
+    /// Waits for the secondary side's hangup state to be `hung_up`.
+    fn poll_hangup_state(&mut self, cx: &mut Context<'_>, hung_up: bool) -> Poll<io::Result<()>> {
+        loop {
+            if pty::is_hung_up(self.primary.get())? == hung_up {
+                break Poll::Ready(Ok(()));
+            }
+            ready!(
+                self.timer
+                    .poll_until(cx, Instant::now() + HANGUP_POLL_INTERVAL)
+            );
+        }
+    }
+
+    /// Handles the result of an I/O to the primary side, treating a hangup as
+    /// a disconnect.
+    fn check_hangup<T>(&mut self, r: io::Result<T>, disconnected: T) -> io::Result<T> {
+        match r {
+            Err(err) if pty::is_hangup_error(&err) => {
+                tracing::debug!("pty hung up");
+                self.connected = false;
+                Ok(disconnected)
+            }
+            r => r,
+        }
+    }
+}
+
+impl From<PtySerialBackend> for Resource<SerialBackendHandle> {
+    fn from(value: PtySerialBackend) -> Self {
+        Resource::new(value.into_config())
+    }
+}
+
+impl SerialIo for PtySerialBackend {
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn poll_connect(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.connected {
+            ready!(self.poll_hangup_state(cx, false))?;
+            tracing::debug!("pty connected");
+            self.connected = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_disconnect(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.connected {
+            ready!(self.poll_hangup_state(cx, true))?;
+            tracing::debug!("pty hung up");
+            self.connected = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for PtySerialBackend {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.connected {
+            return Poll::Ready(Ok(0));
+        }
+        let r = ready!(Pin::new(&mut self.primary).poll_read(cx, buf));
+        let r = self.check_hangup(r, 0);
+        if matches!(r, Ok(0)) {
+            self.connected = false;
+        }
+        Poll::Ready(r)
+    }
+}
+
+impl AsyncWrite for PtySerialBackend {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.connected {
+            // Drop output while no terminal is attached.
+            return Poll::Ready(Ok(buf.len()));
+        }
+        let r = ready!(Pin::new(&mut self.primary).poll_write(cx, buf));
+        Poll::Ready(self.check_hangup(r, buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.connected {
+            return Poll::Ready(Ok(()));
+        }
+        let r = ready!(Pin::new(&mut self.primary).poll_flush(cx));
+        Poll::Ready(self.check_hangup(r, ()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.connected {
+            return Poll::Ready(Ok(()));
+        }
+        let r = ready!(Pin::new(&mut self.primary).poll_close(cx));
+        Poll::Ready(self.check_hangup(r, ()))
+    }
+}
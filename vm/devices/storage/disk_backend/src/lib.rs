@@ -13,12 +13,15 @@
 
 pub mod pr;
 pub mod resolve;
+mod stats;
 pub mod sync_wrapper;
 
 use guestmem::AccessError;
 use inspect::Inspect;
 use scsi_buffers::RequestBuffers;
 use stackfuture::StackFuture;
+use stats::DiskStats;
+use stats::Op;
 use std::fmt::Debug;
 use std::future::Future;
 use std::future::ready;
@@ -175,6 +178,11 @@ fn wait_resize(&self, sector_count: u64) -> impl Future<Output = u64> + Send {
 ///
 /// This type is cheap to clone, for sharing the disk among multiple concurrent
 /// users.
+///
+/// IO issued through this type is counted in the `stats` node of the disk's
+/// inspect output, including latency histograms, queue depth, and errors.
+/// Since layered and wrapper disks issue IO through the `Disk`s they wrap,
+/// each layer reports its own statistics.
 #[derive(Inspect, Clone)]
 #[inspect(extra = "Self::inspect_extra")]
 pub struct Disk(#[inspect(flatten)] Arc<DiskInner>);
@@ -204,6 +212,7 @@ struct DiskInner<T: ?Sized = dyn DynDisk> {
     is_read_only: bool,
     unmap_behavior: UnmapBehavior,
     optimal_unmap_sectors: u32,
+    stats: DiskStats,
     disk: T,
 }
 
@@ -242,6 +251,7 @@ pub fn new(disk: impl 'static + DiskIo) -> Result<Self, InvalidDisk> {
             is_read_only: disk.is_read_only(),
             optimal_unmap_sectors: disk.optimal_unmap_sectors(),
             unmap_behavior: disk.unmap_behavior(),
+            stats: DiskStats::default(),
             disk,
         })))
     }
@@ -296,7 +306,11 @@ pub fn unmap(
         count: u64,
         block_level_only: bool,
     ) -> impl use<'_> + Future<Output = Result<(), DiskError>> + Send {
-        self.0.disk.unmap(sector, count, block_level_only)
+        self.0.stats.track(
+            Op::Unmap,
+            0,
+            self.0.disk.unmap(sector, count, block_level_only),
+        )
     }
 
     /// Returns the behavior of the unmap operation.
@@ -331,7 +345,11 @@ pub fn read_vectored<'a>(
         buffers: &'a RequestBuffers<'_>,
         sector: u64,
     ) -> impl use<'a> + Future<Output = Result<(), DiskError>> + Send {
-        self.0.disk.read_vectored(buffers, sector)
+        self.0.stats.track(
+            Op::Read,
+            buffers.len() as u64,
+            self.0.disk.read_vectored(buffers, sector),
+        )
     }
 
     /// Issues an asynchronous write-gather operation to the disk.
@@ -351,12 +369,16 @@ pub fn write_vectored<'a>(
         sector: u64,
         fua: bool,
     ) -> impl use<'a> + Future<Output = Result<(), DiskError>> + Send {
-        self.0.disk.write_vectored(buffers, sector, fua)
+        self.0.stats.track(
+            Op::Write,
+            buffers.len() as u64,
+            self.0.disk.write_vectored(buffers, sector, fua),
+        )
     }
 
     /// Issues an asynchronous flush operation to the disk.
     pub fn sync_cache(&self) -> impl use<'_> + Future<Output = Result<(), DiskError>> + Send {
-        self.0.disk.sync_cache()
+        self.0.stats.track(Op::Flush, 0, self.0.disk.sync_cache())
     }
 
     /// Waits for the disk sector size to be different than the specified value.
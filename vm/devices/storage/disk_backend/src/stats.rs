@@ -0,0 +1,129 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! IO statistics tracked for each [`Disk`](crate::Disk).
+
+use crate::DiskError;
+use inspect::Inspect;
+use inspect_counters::LatencyHistogram;
+use inspect_counters::SharedCounter;
+use std::future::Future;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// IO statistics for a disk.
+///
+/// Counters are cumulative, so rates (such as IOPS and throughput) can be
+/// computed by sampling them over time.
+#[derive(Debug, Default, Inspect)]
+pub(crate) struct DiskStats {
+    read: OpStats,
+    write: OpStats,
+    flush: OpStats,
+    unmap: OpStats,
+    /// The number of IOs currently in flight.
+    #[inspect(with = "|x| x.load(Ordering::Relaxed)")]
+    queue_depth: AtomicU64,
+    /// The largest number of IOs that have been in flight at once.
+    #[inspect(with = "|x| x.load(Ordering::Relaxed)")]
+    max_queue_depth: AtomicU64,
+}
+
+/// The kind of IO operation being tracked.
+#[derive(Copy, Clone)]
+pub(crate) enum Op {
+    Read,
+    Write,
+    Flush,
+    Unmap,
+}
+
+impl DiskStats {
+    /// Runs `io`, recording its size, latency, and result.
+    pub async fn track(
+        &self,
+        op: Op,
+        bytes: u64,
+        io: impl Future<Output = Result<(), DiskError>>,
+    ) -> Result<(), DiskError> {
+        let stats = match op {
+            Op::Read => &self.read,
+            Op::Write => &self.write,
+            Op::Flush => &self.flush,
+            Op::Unmap => &self.unmap,
+        };
+        let _in_flight = InFlight::new(self);
+        let start = Instant::now();
+        let r = io.await;
+        stats.complete(bytes, start.elapsed(), r.is_ok());
+        r
+    }
+}
+
+/// Tracks an in-flight IO, including if the IO is dropped before completion.
+struct InFlight<'a>(&'a DiskStats);
+
+impl<'a> InFlight<'a> {
+    fn new(stats: &'a DiskStats) -> Self {
+        let depth = stats.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        stats.max_queue_depth.fetch_max(depth, Ordering::Relaxed);
+        Self(stats)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default, Inspect)]
+struct OpStats {
+    count: SharedCounter,
+    bytes: SharedCounter,
+    errors: SharedCounter,
+    /// The latency of completed IOs, including the total time spent in them
+    /// for computing the average latency.
+    latency: LatencyHistogram,
+}
+
+impl OpStats {
+    fn complete(&self, bytes: u64, latency: Duration, ok: bool) {
+        self.count.increment();
+        if ok {
+            self.bytes.add(bytes);
+        } else {
+            self.errors.increment();
+        }
+        self.latency.add_sample(latency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiskStats;
+    use super::Op;
+    use crate::DiskError;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_track() {
+        let stats = DiskStats::default();
+        futures::executor::block_on(async {
+            stats.track(Op::Read, 4096, async { Ok(()) }).await.unwrap();
+            stats
+                .track(Op::Read, 4096, async { Err(DiskError::InvalidInput) })
+                .await
+                .unwrap_err();
+        });
+        assert_eq!(stats.read.count.get(), 2);
+        assert_eq!(stats.read.bytes.get(), 4096);
+        assert_eq!(stats.read.errors.get(), 1);
+        assert_eq!(stats.read.latency.count(), 2);
+        assert_eq!(stats.write.count.get(), 0);
+        assert_eq!(stats.queue_depth.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.max_queue_depth.load(Ordering::Relaxed), 1);
+    }
+}
@@ -9,6 +9,7 @@
 
 use mesh::Cell;
 use mesh::MeshPayload;
+use mesh::rpc::FailableRpc;
 use std::time::Duration;
 use vm_resource::IntoResource;
 use vm_resource::Resource;
@@ -27,6 +28,15 @@ impl ResourceId<DiskHandleKind> for FileDiskHandle {
     const ID: &'static str = "file";
 }
 
+/// Disk handle for a read-only disk backed by a file in the zstd seekable
+/// format.
+#[derive(MeshPayload)]
+pub struct ZstdDiskHandle(pub std::fs::File);
+
+impl ResourceId<DiskHandleKind> for ZstdDiskHandle {
+    const ID: &'static str = "zstd";
+}
+
 /// Disk handle for a disk that emulates persistent reservation support.
 #[derive(MeshPayload)]
 pub struct DiskWithReservationsHandle(pub Resource<DiskHandleKind>);
@@ -35,6 +45,22 @@ impl ResourceId<DiskHandleKind> for DiskWithReservationsHandle {
     const ID: &'static str = "prwrap";
 }
 
+/// Disk handle for a disk that emulates persistent reservation support, with
+/// the reservation state stored in a file shared with other VMs.
+#[derive(MeshPayload)]
+pub struct DiskWithSharedReservationsHandle {
+    /// The underlying disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// The file storing the reservation state.
+    pub store: std::fs::File,
+    /// The host ID identifying this VM to the other VMs sharing the disk.
+    pub host_id: u64,
+}
+
+impl ResourceId<DiskHandleKind> for DiskWithSharedReservationsHandle {
+    const ID: &'static str = "prshared";
+}
+
 /// Disk handle for a delay disk.
 #[derive(MeshPayload)]
 pub struct DelayDiskHandle {
@@ -48,6 +74,101 @@ impl ResourceId<DiskHandleKind> for DelayDiskHandle {
     const ID: &'static str = "delay";
 }
 
+/// Disk handle for a disk that limits the rate of I/O to an underlying disk.
+#[derive(MeshPayload)]
+pub struct ThrottleDiskHandle {
+    /// The underlying disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// The maximum number of read and write operations per second.
+    pub iops: Option<u64>,
+    /// The maximum number of bytes read and written per second.
+    pub bytes_per_second: Option<u64>,
+    /// The number of operations that can be issued at once after the disk
+    /// has been idle. Defaults to `iops`.
+    pub burst: Option<u64>,
+}
+
+impl ResourceId<DiskHandleKind> for ThrottleDiskHandle {
+    const ID: &'static str = "throttle";
+}
+
+/// Disk handle for a disk that caches writes in memory and writes them back
+/// to an underlying disk in batches.
+#[derive(MeshPayload)]
+pub struct WriteBackCacheDiskHandle {
+    /// The underlying disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// The maximum number of bytes of dirty data to hold before writing it
+    /// back.
+    pub cache_size: u64,
+    /// If set, dirty data is written back periodically at this interval, in
+    /// addition to when the cache fills or the guest flushes.
+    pub flush_interval: Option<Duration>,
+}
+
+impl ResourceId<DiskHandleKind> for WriteBackCacheDiskHandle {
+    const ID: &'static str = "writeback";
+}
+
+/// Disk handle for a disk that presents a different sector size than an
+/// underlying disk.
+#[derive(MeshPayload)]
+pub struct SectorSizeDiskHandle {
+    /// The underlying disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// The logical sector size to present. Must be a multiple of the underlying
+    /// disk's sector size. Defaults to the underlying disk's sector size.
+    pub sector_size: Option<u32>,
+    /// The physical sector size to present. Defaults to the larger of the
+    /// logical sector size and the underlying disk's physical sector size.
+    pub physical_sector_size: Option<u32>,
+}
+
+impl ResourceId<DiskHandleKind> for SectorSizeDiskHandle {
+    const ID: &'static str = "sector_size";
+}
+
+/// Disk handle for a read-only disk whose contents are verified against a
+/// dm-verity hash tree.
+#[derive(MeshPayload)]
+pub struct VerityDiskHandle {
+    /// The underlying data disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// The hash tree, in the format produced by `veritysetup format` (with a
+    /// superblock at offset zero).
+    pub hash_tree: Resource<DiskHandleKind>,
+    /// The expected root hash of the tree.
+    pub root_hash: Vec<u8>,
+}
+
+impl ResourceId<DiskHandleKind> for VerityDiskHandle {
+    const ID: &'static str = "verity";
+}
+
+/// Disk handle for a disk that can be snapshotted while in use.
+#[derive(MeshPayload)]
+pub struct SnapshotDiskHandle {
+    /// The underlying disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// Runtime request channel.
+    pub requests: mesh::Receiver<SnapshotDiskRequest>,
+}
+
+impl ResourceId<DiskHandleKind> for SnapshotDiskHandle {
+    const ID: &'static str = "snapshot";
+}
+
+/// A runtime request to a snapshot disk.
+#[derive(MeshPayload)]
+pub enum SnapshotDiskRequest {
+    /// Quiesce I/O, freeze the disk's current contents, and redirect all
+    /// subsequent I/O to the provided diff layer stacked on top of the frozen
+    /// disk.
+    ///
+    /// Returns the number of snapshots that have been taken of the disk.
+    Snapshot(FailableRpc<Resource<DiskLayerHandleKind>, u64>),
+}
+
 /// Disk handle for a fixed VHD1 disk.
 #[derive(MeshPayload)]
 pub struct FixedVhd1DiskHandle(pub std::fs::File);
@@ -56,6 +177,20 @@ impl ResourceId<DiskHandleKind> for FixedVhd1DiskHandle {
     const ID: &'static str = "fixed_vhd1";
 }
 
+/// Disk handle for a dynamic or differencing VHDX disk.
+#[derive(MeshPayload)]
+pub struct VhdxDiskHandle {
+    /// The VHDX file.
+    pub file: std::fs::File,
+    /// The parent disk, for a differencing VHDX. This is always opened
+    /// read-only.
+    pub parent: Option<Resource<DiskHandleKind>>,
+}
+
+impl ResourceId<DiskHandleKind> for VhdxDiskHandle {
+    const ID: &'static str = "vhdx";
+}
+
 /// Disk configuration for a striped disk.
 #[derive(MeshPayload)]
 pub struct StripedDiskHandle {
@@ -71,6 +206,20 @@ impl ResourceId<DiskHandleKind> for StripedDiskHandle {
     const ID: &'static str = "striped";
 }
 
+/// Disk configuration for a disk that is reachable through multiple paths.
+///
+/// Writes are mirrored to all paths, and individual paths can be failed at
+/// runtime via inspect to test failover.
+#[derive(MeshPayload)]
+pub struct MultipathDiskHandle {
+    /// The disks backing each path. They must have the same geometry.
+    pub paths: Vec<Resource<DiskHandleKind>>,
+}
+
+impl ResourceId<DiskHandleKind> for MultipathDiskHandle {
+    const ID: &'static str = "multipath";
+}
+
 /// Configuration for a disk that is automatically formatted (if it is not
 /// already formatted) while being resolved.
 // DEVNOTE: this disk type supports a Azure-specific feature in Microsoft's
@@ -93,19 +242,35 @@ impl ResourceId<DiskHandleKind> for AutoFormattedDiskHandle {
 
 // blob
 
-/// Handle for a read-only disk backed by a blob served over HTTP.
+/// Handle for a disk backed by a blob served over HTTP.
+///
+/// The disk is read-only unless `page_blob` is set.
 #[derive(MeshPayload)]
 pub struct BlobDiskHandle {
     /// The URL to the disk.
     pub url: String,
     /// The format of the blob.
     pub format: BlobDiskFormat,
+    /// Options for accessing the blob as an Azure page blob, which supports
+    /// writes.
+    pub page_blob: Option<PageBlobOptions>,
 }
 
 impl ResourceId<DiskHandleKind> for BlobDiskHandle {
     const ID: &'static str = "blob";
 }
 
+/// Options for a disk backed by an Azure page blob.
+#[derive(MeshPayload)]
+pub struct PageBlobOptions {
+    /// An OAuth bearer token to authenticate requests with. Not needed if the
+    /// URL includes a SAS token.
+    pub bearer_token: Option<String>,
+    /// The maximum number of bytes of written data to cache locally before
+    /// writing it back to the blob.
+    pub max_dirty_bytes: u64,
+}
+
 /// The format of a disk blob.
 #[derive(MeshPayload)]
 pub enum BlobDiskFormat {
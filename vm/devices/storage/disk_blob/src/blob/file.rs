@@ -46,31 +46,68 @@ async fn read(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
         Ok(())
     }
 
+    async fn write(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        if offset + buf.len() as u64 > self.len {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let file = self.file.clone();
+        let data = buf.to_vec();
+        blocking::unblock(move || {
+            file.write_all_at(&data, offset)?;
+            file.sync_data()
+        })
+        .await
+    }
+
     fn len(&self) -> u64 {
         self.len
     }
 }
 
-/// A unified extension trait for [`std::fs::File`] for reading at a
-/// given offset.
+/// A unified extension trait for [`std::fs::File`] for reading and writing
+/// at a given offset.
 ///
 /// The semantics are slightly different between Windows and Unix--on Windows,
 /// each operation updates the current file pointer, whereas on Unix it does
 /// not.
-trait ReadAt {
+trait ReadWriteAt {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(windows)]
-impl ReadAt for File {
+impl ReadWriteAt for File {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
         std::os::windows::fs::FileExt::seek_read(self, buf, offset)
     }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_write(self, buf, offset)
+    }
 }
 
 #[cfg(unix)]
-impl ReadAt for File {
+impl ReadWriteAt for File {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
         std::os::unix::fs::FileExt::read_at(self, buf, offset)
     }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::write_at(self, buf, offset)
+    }
 }
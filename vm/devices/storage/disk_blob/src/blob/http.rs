@@ -13,10 +13,11 @@
 use async_trait::async_trait;
 use http::uri::Scheme;
 use http_body_util::BodyExt;
-use http_body_util::Empty;
+use http_body_util::Full;
 use hyper::Request;
 use hyper::StatusCode;
 use hyper::Uri;
+use hyper::body::Incoming;
 use hyper_tls::HttpsConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::client::legacy::connect::HttpConnector;
@@ -24,18 +25,37 @@
 use inspect::Inspect;
 use once_cell::sync::OnceCell;
 use std::fmt::Debug;
+use std::future::Future;
 use std::io;
+use std::io::Cursor;
+use std::time::Duration;
+
+type Body = Full<Cursor<Vec<u8>>>;
+
+/// The Azure Storage REST API version to use for page blob requests.
+const AZURE_STORAGE_VERSION: &str = "2021-08-06";
+
+/// The maximum number of bytes in a single Put Page request.
+pub const MAX_PAGE_WRITE: usize = 4 * 1024 * 1024;
+
+/// The number of times to retry a failed request.
+const MAX_RETRIES: u32 = 5;
+/// The delay before the first retry. This doubles for each subsequent retry.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 /// A blob backed by an HTTP/HTTPS connection.
 #[derive(Debug, Inspect)]
 pub struct HttpBlob {
     #[inspect(skip)]
-    client: Client<HttpsConnector<HttpConnector>, Empty<&'static [u8]>>,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
     #[inspect(debug)]
     version: http::Version,
     #[inspect(display)]
     uri: Uri,
     len: u64,
+    page_blob: bool,
+    #[inspect(skip)]
+    bearer_token: Option<String>,
     #[inspect(skip)]
     tokio_handle: tokio::runtime::Handle,
 }
@@ -43,8 +63,30 @@ pub struct HttpBlob {
 static TOKIO_RUNTIME: OnceCell<tokio::runtime::Runtime> = OnceCell::new();
 
 impl HttpBlob {
-    /// Connects to `url` and returns an object to access it as a blob.
+    /// Connects to `url` and returns an object to access it as a read-only
+    /// blob.
     pub async fn new(url: &str) -> anyhow::Result<Self> {
+        Self::connect(url, false, None).await
+    }
+
+    /// Connects to the Azure page blob at `url` and returns an object to
+    /// access it as a writable blob.
+    ///
+    /// Requests are authenticated with `bearer_token`, if provided. Otherwise,
+    /// `url` should include a SAS token granting write access.
+    pub async fn new_page_blob(url: &str, bearer_token: Option<String>) -> anyhow::Result<Self> {
+        let blob = Self::connect(url, true, bearer_token).await?;
+        if blob.len % 512 != 0 {
+            anyhow::bail!("page blob length is not a multiple of 512");
+        }
+        Ok(blob)
+    }
+
+    async fn connect(
+        url: &str,
+        is_page_blob: bool,
+        bearer_token: Option<String>,
+    ) -> anyhow::Result<Self> {
         let mut uri: Uri = url.parse()?;
 
         let connector = HttpsConnector::new();
@@ -63,19 +105,16 @@ pub async fn new(url: &str) -> anyhow::Result<Self> {
                 anyhow::bail!("too many redirects");
             }
 
-            let response = handle
-                .spawn(
-                    client.request(
-                        Request::builder()
-                            .uri(&uri)
-                            .method("HEAD")
-                            .body(Empty::new())
-                            .unwrap(),
-                    ),
+            let response = send_with_retry(&handle, || {
+                client.request(
+                    request_builder(&uri, is_page_blob, bearer_token.as_deref())
+                        .method("HEAD")
+                        .body(Body::default())
+                        .unwrap(),
                 )
-                .await
-                .unwrap()
-                .context("failed to query blob size")?;
+            })
+            .await
+            .context("failed to query blob size")?;
 
             let next_uri: Uri = match response.status() {
                 StatusCode::OK => break response,
@@ -98,6 +137,9 @@ pub async fn new(url: &str) -> anyhow::Result<Self> {
             if uri.scheme() == Some(&Scheme::HTTPS) && next_uri.scheme() != Some(&Scheme::HTTPS) {
                 anyhow::bail!("https redirected to http");
             }
+            if bearer_token.is_some() && next_uri.authority() != uri.authority() {
+                anyhow::bail!("refusing to follow redirect to another host with credentials");
+            }
 
             uri = next_uri;
             redirect_count += 1;
@@ -112,6 +154,15 @@ pub async fn new(url: &str) -> anyhow::Result<Self> {
             .parse()
             .context("couldn't parse blob length")?;
 
+        if is_page_blob
+            && response
+                .headers()
+                .get("x-ms-blob-type")
+                .is_none_or(|v| v != "PageBlob")
+        {
+            anyhow::bail!("blob is not a page blob");
+        }
+
         let version = response.version();
 
         Ok(Self {
@@ -119,31 +170,97 @@ pub async fn new(url: &str) -> anyhow::Result<Self> {
             version,
             uri,
             len,
+            page_blob: is_page_blob,
+            bearer_token,
             tokio_handle: handle,
         })
     }
+
+    fn request_builder(&self) -> http::request::Builder {
+        request_builder(&self.uri, self.page_blob, self.bearer_token.as_deref())
+    }
+}
+
+/// Returns a request builder for `uri`, with authentication and versioning
+/// headers for page blob requests.
+fn request_builder(
+    uri: &Uri,
+    page_blob: bool,
+    bearer_token: Option<&str>,
+) -> http::request::Builder {
+    let mut builder = Request::builder().uri(uri);
+    if page_blob {
+        builder = builder.header("x-ms-version", AZURE_STORAGE_VERSION);
+    }
+    if let Some(token) = bearer_token {
+        builder = builder.header(hyper::header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    builder
+}
+
+/// Returns true if a request that failed with `status` may succeed if
+/// retried.
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Sends the request returned by `f` on the tokio runtime, retrying with
+/// exponential backoff on connection failures and transient server errors.
+async fn send_with_retry<F>(
+    handle: &tokio::runtime::Handle,
+    mut f: impl FnMut() -> F,
+) -> io::Result<hyper::Response<Incoming>>
+where
+    F: 'static
+        + Send
+        + Future<Output = Result<hyper::Response<Incoming>, hyper_util::client::legacy::Error>>,
+{
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        let result = handle.spawn(f()).await.unwrap();
+        let err = match result {
+            Ok(response) if !is_transient(response.status()) => return Ok(response),
+            Ok(response) => io::Error::other(response.status().to_string()),
+            Err(err) => io::Error::other(err),
+        };
+        if attempt == MAX_RETRIES {
+            return Err(err);
+        }
+        attempt += 1;
+        tracing::debug!(
+            error = &err as &dyn std::error::Error,
+            attempt,
+            "retrying blob request"
+        );
+        handle.spawn(tokio::time::sleep(delay)).await.unwrap();
+        delay *= 2;
+    }
 }
 
 #[async_trait]
 impl Blob for HttpBlob {
     async fn read(&self, mut buf: &mut [u8], offset: u64) -> io::Result<()> {
-        let mut response = self
-            .tokio_handle
-            .spawn(
-                self.client.request(
-                    Request::builder()
-                        .uri(&self.uri)
-                        .header(
-                            hyper::header::RANGE,
-                            format!("bytes={}-{}", offset, offset + buf.len() as u64 - 1,),
-                        )
-                        .body(Empty::new())
-                        .unwrap(),
-                ),
+        let mut response = send_with_retry(&self.tokio_handle, || {
+            self.client.request(
+                self.request_builder()
+                    .header(
+                        hyper::header::RANGE,
+                        format!("bytes={}-{}", offset, offset + buf.len() as u64 - 1,),
+                    )
+                    .body(Body::default())
+                    .unwrap(),
             )
-            .await
-            .unwrap()
-            .map_err(io::Error::other)?;
+        })
+        .await?;
 
         if !response.status().is_success() {
             return Err(io::Error::other(response.status().to_string()));
@@ -169,6 +286,47 @@ async fn read(&self, mut buf: &mut [u8], offset: u64) -> io::Result<()> {
         Ok(())
     }
 
+    async fn write(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        if !self.page_blob {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        if offset % 512 != 0 || buf.len() % 512 != 0 || offset + buf.len() as u64 > self.len {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let mut uri = self.uri.to_string();
+        uri.push(if self.uri.query().is_some() { '&' } else { '?' });
+        uri.push_str("comp=page");
+        let uri: Uri = uri.parse().map_err(io::Error::other)?;
+
+        for (i, chunk) in buf.chunks(MAX_PAGE_WRITE).enumerate() {
+            let offset = offset + (i * MAX_PAGE_WRITE) as u64;
+            let response = send_with_retry(&self.tokio_handle, || {
+                self.client.request(
+                    request_builder(&uri, self.page_blob, self.bearer_token.as_deref())
+                        .method("PUT")
+                        .header("x-ms-page-write", "update")
+                        .header(
+                            "x-ms-range",
+                            format!("bytes={}-{}", offset, offset + chunk.len() as u64 - 1),
+                        )
+                        .header(hyper::header::CONTENT_LENGTH, chunk.len())
+                        .body(Full::new(Cursor::new(chunk.to_vec())))
+                        .unwrap(),
+                )
+            })
+            .await?;
+
+            if response.status() != StatusCode::CREATED {
+                return Err(io::Error::other(format!(
+                    "failed to write pages: {}",
+                    response.status()
+                )));
+            }
+        }
+        Ok(())
+    }
+
     fn len(&self) -> u64 {
         self.len
     }
@@ -9,7 +9,7 @@
 use async_trait::async_trait;
 use inspect::Inspect;
 
-/// Trait for a read-only blob.
+/// Trait for a blob.
 #[async_trait]
 pub trait Blob: Inspect {
     /// Reads data at `offset` into `buf`.
@@ -19,6 +19,18 @@ pub trait Blob: Inspect {
     /// success.
     async fn read(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()>;
 
+    /// Writes `buf` to the blob at `offset`. `buf` and `offset` are aligned to
+    /// 512 bytes.
+    ///
+    /// Once this returns successfully, the data must be durable.
+    ///
+    /// Blobs that do not support writes return
+    /// [`std::io::ErrorKind::Unsupported`].
+    async fn write(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        let _ = (buf, offset);
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
     /// Returns the length of the blob in bytes.
     fn len(&self) -> u64;
 }
@@ -0,0 +1,227 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A local write-back cache of dirty blob data.
+//!
+//! Writes to remote blobs are slow and expensive, so writes are collected in
+//! memory and written back in large, contiguous requests when the guest
+//! flushes, when a write requests FUA, or when too much dirty data has
+//! accumulated.
+
+use crate::blob::Blob;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::io;
+
+/// The granularity of the cache.
+pub(crate) const BLOCK_SIZE: usize = 512;
+
+/// The maximum number of bytes to write back in a single request.
+const MAX_WRITE_BACK: usize = 4 * 1024 * 1024;
+
+type Block = Box<[u8; BLOCK_SIZE]>;
+
+/// A write-back cache of dirty blocks.
+#[derive(Inspect)]
+pub(crate) struct WriteCache {
+    #[inspect(flatten)]
+    blocks: Mutex<DirtyBlocks>,
+    #[inspect(skip)]
+    flush_lock: futures::lock::Mutex<()>,
+    max_dirty_blocks: usize,
+}
+
+#[derive(Inspect)]
+struct DirtyBlocks {
+    #[inspect(with = "BTreeMap::len", rename = "dirty_blocks")]
+    map: BTreeMap<u64, DirtyBlock>,
+    /// Incremented on each write, so that a flush can tell whether a block
+    /// was rewritten while it was being written back.
+    generation: u64,
+}
+
+struct DirtyBlock {
+    generation: u64,
+    data: Block,
+}
+
+impl WriteCache {
+    /// Returns a new cache that writes back once `max_dirty_bytes` of data is
+    /// dirty.
+    pub fn new(max_dirty_bytes: u64) -> Self {
+        Self {
+            blocks: Mutex::new(DirtyBlocks {
+                map: BTreeMap::new(),
+                generation: 0,
+            }),
+            flush_lock: Default::default(),
+            max_dirty_blocks: (max_dirty_bytes / BLOCK_SIZE as u64).max(1) as usize,
+        }
+    }
+
+    /// Reads `buf` from `offset`, reading dirty data from the cache and the
+    /// rest from `blob`.
+    pub async fn read(
+        &self,
+        blob: &(dyn Blob + Send + Sync),
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<()> {
+        // Snapshot the dirty blocks before reading the blob. Blocks are only
+        // removed from the cache after they have been written back, so any
+        // block missing from the snapshot is up to date in the blob.
+        let first = offset / BLOCK_SIZE as u64;
+        let end = (offset + buf.len() as u64).div_ceil(BLOCK_SIZE as u64);
+        let dirty: Vec<(u64, Block)> = self
+            .blocks
+            .lock()
+            .map
+            .range(first..end)
+            .map(|(&block, b)| (block, b.data.clone()))
+            .collect();
+
+        blob.read(buf, offset).await?;
+
+        for (block, data) in dirty {
+            let block_offset = block * BLOCK_SIZE as u64;
+            let start = block_offset.max(offset);
+            let end = (block_offset + BLOCK_SIZE as u64).min(offset + buf.len() as u64);
+            buf[(start - offset) as usize..(end - offset) as usize].copy_from_slice(
+                &data[(start - block_offset) as usize..(end - block_offset) as usize],
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` to the cache at `offset`, which must be block aligned.
+    ///
+    /// Writes back dirty data to `blob` if the cache is full.
+    pub async fn write(
+        &self,
+        blob: &(dyn Blob + Send + Sync),
+        buf: &[u8],
+        offset: u64,
+    ) -> io::Result<()> {
+        assert_eq!(offset % BLOCK_SIZE as u64, 0);
+        assert_eq!(buf.len() % BLOCK_SIZE, 0);
+        let full = {
+            let mut blocks = self.blocks.lock();
+            let blocks = &mut *blocks;
+            blocks.generation += 1;
+            for (i, data) in buf.chunks_exact(BLOCK_SIZE).enumerate() {
+                blocks.map.insert(
+                    offset / BLOCK_SIZE as u64 + i as u64,
+                    DirtyBlock {
+                        generation: blocks.generation,
+                        data: Box::new(data.try_into().unwrap()),
+                    },
+                );
+            }
+            blocks.map.len() >= self.max_dirty_blocks
+        };
+        if full {
+            self.flush(blob).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes all dirty data back to `blob`.
+    pub async fn flush(&self, blob: &(dyn Blob + Send + Sync)) -> io::Result<()> {
+        let _flush = self.flush_lock.lock().await;
+        let dirty: Vec<(u64, u64, Block)> = self
+            .blocks
+            .lock()
+            .map
+            .iter()
+            .map(|(&block, b)| (block, b.generation, b.data.clone()))
+            .collect();
+
+        const MAX_RUN: usize = MAX_WRITE_BACK / BLOCK_SIZE;
+        let mut remaining = dirty.as_slice();
+        while let Some(&(first, _, _)) = remaining.first() {
+            let len = remaining
+                .iter()
+                .take(MAX_RUN)
+                .enumerate()
+                .take_while(|&(i, &(block, _, _))| block == first + i as u64)
+                .count();
+            let (run, rest) = remaining.split_at(len);
+            remaining = rest;
+
+            let data: Vec<u8> = run
+                .iter()
+                .flat_map(|(_, _, data)| data.iter().copied())
+                .collect();
+            blob.write(&data, first * BLOCK_SIZE as u64).await?;
+
+            // Drop the blocks that were not rewritten while being written
+            // back.
+            let mut blocks = self.blocks.lock();
+            for &(block, generation, _) in run {
+                if blocks
+                    .map
+                    .get(&block)
+                    .is_some_and(|b| b.generation == generation)
+                {
+                    blocks.map.remove(&block);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::file::FileBlob;
+    use pal_async::async_test;
+    use std::io::Write;
+
+    fn blob(len: usize) -> FileBlob {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&vec![0xff; len]).unwrap();
+        FileBlob::new(file).unwrap()
+    }
+
+    async fn read(blob: &FileBlob, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        blob.read(&mut buf, offset).await.unwrap();
+        buf
+    }
+
+    #[async_test]
+    async fn write_back() {
+        let blob = blob(0x10000);
+        let cache = WriteCache::new(0x100000);
+
+        cache.write(&blob, &[1; 1024], 512).await.unwrap();
+        cache.write(&blob, &[2; 512], 0x8000).await.unwrap();
+
+        // The data is cached but not yet written back.
+        assert_eq!(read(&blob, 512, 1024).await, [0xff; 1024]);
+        let mut buf = vec![0; 2048];
+        cache.read(&blob, &mut buf, 0).await.unwrap();
+        assert_eq!(buf[..512], [0xff; 512]);
+        assert_eq!(buf[512..1536], [1; 1024]);
+        assert_eq!(buf[1536..], [0xff; 512]);
+
+        cache.flush(&blob).await.unwrap();
+        assert_eq!(read(&blob, 512, 1024).await, [1; 1024]);
+        assert_eq!(read(&blob, 0x8000, 512).await, [2; 512]);
+        assert!(cache.blocks.lock().map.is_empty());
+    }
+
+    #[async_test]
+    async fn write_back_when_full() {
+        let blob = blob(0x10000);
+        let cache = WriteCache::new(2048);
+
+        cache.write(&blob, &[3; 1024], 0).await.unwrap();
+        assert_eq!(read(&blob, 0, 1024).await, [0xff; 1024]);
+        cache.write(&blob, &[4; 1024], 4096).await.unwrap();
+        assert_eq!(read(&blob, 0, 1024).await, [3; 1024]);
+        assert_eq!(read(&blob, 4096, 1024).await, [4; 1024]);
+    }
+}
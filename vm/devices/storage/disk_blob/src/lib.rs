@@ -7,12 +7,15 @@
 #![forbid(unsafe_code)]
 
 pub mod blob;
+mod cache;
 pub mod resolver;
 
 use blob::Blob;
+use cache::WriteCache;
 use disk_backend::DiskError;
 use disk_backend::DiskIo;
 use disk_backend::UnmapBehavior;
+use guestmem::MemoryRead;
 use guestmem::MemoryWrite;
 use inspect::Inspect;
 use scsi_buffers::RequestBuffers;
@@ -24,7 +27,9 @@
 
 const DEFAULT_SECTOR_SIZE: u32 = 512;
 
-/// A read-only disk backed by a blob.
+/// A disk backed by a blob.
+///
+/// The disk is read-only unless it has a write cache.
 #[derive(Inspect)]
 pub struct BlobDisk {
     blob: Arc<dyn Blob + Send + Sync>,
@@ -32,6 +37,7 @@ pub struct BlobDisk {
     sector_size: u32,
     sector_shift: u32,
     disk_id: Option<[u8; 16]>,
+    write_cache: Option<WriteCache>,
 }
 
 #[derive(Debug, Error)]
@@ -113,8 +119,19 @@ fn new_inner(
             sector_size: DEFAULT_SECTOR_SIZE,
             sector_shift: DEFAULT_SECTOR_SIZE.trailing_zeros(),
             disk_id,
+            write_cache: None,
         }
     }
+
+    /// Makes the disk writable. Writes are cached locally until the guest
+    /// flushes or more than `max_dirty_bytes` of data has been written, and
+    /// then written back to the blob.
+    ///
+    /// The blob must support writes.
+    pub fn with_write_cache(mut self, max_dirty_bytes: u64) -> Self {
+        self.write_cache = Some(WriteCache::new(max_dirty_bytes));
+        self
+    }
 }
 
 impl DiskIo for BlobDisk {
@@ -139,11 +156,11 @@ fn physical_sector_size(&self) -> u32 {
     }
 
     fn is_fua_respected(&self) -> bool {
-        false
+        self.write_cache.is_some()
     }
 
     fn is_read_only(&self) -> bool {
-        true
+        self.write_cache.is_none()
     }
 
     async fn read_vectored(
@@ -152,10 +169,13 @@ async fn read_vectored(
         sector: u64,
     ) -> Result<(), DiskError> {
         let mut buf = vec![0; buffers.len()];
-        self.blob
-            .read(&mut buf, sector << self.sector_shift)
-            .await
-            .map_err(DiskError::Io)?;
+        let offset = sector << self.sector_shift;
+        if let Some(cache) = &self.write_cache {
+            cache.read(self.blob.as_ref(), &mut buf, offset).await
+        } else {
+            self.blob.read(&mut buf, offset).await
+        }
+        .map_err(DiskError::Io)?;
 
         buffers.writer().write(&buf)?;
         Ok(())
@@ -163,15 +183,42 @@ async fn read_vectored(
 
     async fn write_vectored(
         &self,
-        _buffers: &RequestBuffers<'_>,
-        _sector: u64,
-        _fua: bool,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
     ) -> Result<(), DiskError> {
-        Err(DiskError::ReadOnly)
+        let Some(cache) = &self.write_cache else {
+            return Err(DiskError::ReadOnly);
+        };
+        let sector_count = (buffers.len() >> self.sector_shift) as u64;
+        if sector
+            .checked_add(sector_count)
+            .is_none_or(|end| end > self.sector_count)
+        {
+            return Err(DiskError::IllegalBlock);
+        }
+
+        let mut buf = vec![0; buffers.len()];
+        buffers.reader().read(&mut buf)?;
+        cache
+            .write(self.blob.as_ref(), &buf, sector << self.sector_shift)
+            .await
+            .map_err(DiskError::Io)?;
+
+        if fua {
+            cache
+                .flush(self.blob.as_ref())
+                .await
+                .map_err(DiskError::Io)?;
+        }
+        Ok(())
     }
 
     async fn sync_cache(&self) -> Result<(), DiskError> {
-        Err(DiskError::ReadOnly)
+        let Some(cache) = &self.write_cache else {
+            return Err(DiskError::ReadOnly);
+        };
+        cache.flush(self.blob.as_ref()).await.map_err(DiskError::Io)
     }
 
     async fn unmap(
@@ -31,16 +31,26 @@ async fn resolve(
         rsrc: BlobDiskHandle,
         params: ResolveDiskParameters<'_>,
     ) -> Result<Self::Output, Self::Error> {
-        if !params.read_only {
-            anyhow::bail!("writable blob disks not supported");
-        }
+        let (blob, max_dirty_bytes) = match rsrc.page_blob {
+            Some(options) => (
+                HttpBlob::new_page_blob(&rsrc.url, options.bearer_token).await?,
+                Some(options.max_dirty_bytes),
+            ),
+            None => (HttpBlob::new(&rsrc.url).await?, None),
+        };
 
-        let blob = HttpBlob::new(&rsrc.url).await?;
-        let disk = match rsrc.format {
+        let mut disk = match rsrc.format {
             BlobDiskFormat::Flat => BlobDisk::new(blob),
             BlobDiskFormat::FixedVhd1 => BlobDisk::new_fixed_vhd1(blob).await?,
         };
 
+        if !params.read_only {
+            let Some(max_dirty_bytes) = max_dirty_bytes else {
+                anyhow::bail!("writable blob disks must be page blobs");
+            };
+            disk = disk.with_write_cache(max_dirty_bytes);
+        }
+
         Ok(ResolvedDisk::new(disk)?)
     }
 }
@@ -3,11 +3,17 @@
 
 //! A disk device wrapper that provides confidentiality (but not authentication)
 //! via encryption.
+//!
+//! The payload can either be encrypted directly with a raw key (compatible
+//! with dm-crypt in plain mode), or be the data segment of a LUKS2 container.
 
 #![forbid(unsafe_code)]
 
+mod luks2;
 pub mod resolver;
 
+pub use luks2::Luks2Error;
+
 use block_crypto::XtsAes256;
 use disk_backend::Disk;
 use disk_backend::DiskError;
@@ -27,6 +33,20 @@ pub struct CryptDisk {
     inner: Disk,
     #[inspect(skip)]
     cipher: XtsAes256,
+    /// The byte offset of the encrypted payload on the inner disk.
+    #[inspect(hex)]
+    data_offset: u64,
+    /// The length of the payload in bytes, or `None` if it extends to the end
+    /// of the inner disk.
+    data_len: Option<u64>,
+    sector_size: u32,
+    sector_shift: u32,
+    /// The size of each independently encrypted unit.
+    unit_size: u32,
+    /// The tweak of the first unit of the payload.
+    tweak_base: u64,
+    /// The shift to convert a payload byte offset into a tweak offset.
+    tweak_shift: u32,
 }
 
 /// An error that occurred while creating a new encrypted disk.
@@ -38,25 +58,113 @@ pub enum NewDiskError {
     /// The key size is invalid.
     #[error("invalid key size for cipher")]
     InvalidKeySize,
+    /// The LUKS2 container could not be opened.
+    #[error("failed to open LUKS2 container")]
+    Luks2(#[source] Luks2Error),
 }
 
 impl CryptDisk {
     /// Creates a new encrypted disk device wrapping `inner`, using the provided
     /// cipher and key.
-    pub fn new(
+    ///
+    /// For [`Cipher::Luks2`](disk_crypt_resources::Cipher::Luks2), `key` is
+    /// the passphrase, and the container header is read from `inner` to
+    /// unlock the master key.
+    pub async fn new(
         cipher: disk_crypt_resources::Cipher,
         key: &[u8],
         inner: Disk,
     ) -> Result<Self, NewDiskError> {
         match cipher {
-            disk_crypt_resources::Cipher::XtsAes256 => {}
+            disk_crypt_resources::Cipher::XtsAes256 => {
+                let sector_size = inner.sector_size();
+                let sector_shift = inner.sector_shift();
+                Self::with_layout(
+                    inner,
+                    key.try_into().map_err(|_| NewDiskError::InvalidKeySize)?,
+                    0,
+                    None,
+                    sector_size,
+                    0,
+                    sector_shift,
+                )
+            }
+            disk_crypt_resources::Cipher::Luks2 => {
+                let volume = luks2::unlock(&inner, key)
+                    .await
+                    .map_err(NewDiskError::Luks2)?;
+                Self::with_layout(
+                    inner,
+                    &volume.key,
+                    volume.offset,
+                    volume.len,
+                    volume.sector_size,
+                    volume.iv_tweak,
+                    luks2::IV_SHIFT,
+                )
+            }
         }
-        let cipher = XtsAes256::new(
-            key.try_into().map_err(|_| NewDiskError::InvalidKeySize)?,
-            inner.sector_size(),
-        )
-        .map_err(NewDiskError::Crypto)?;
-        Ok(Self { inner, cipher })
+    }
+
+    fn with_layout(
+        inner: Disk,
+        key: &[u8; XtsAes256::KEY_LEN],
+        data_offset: u64,
+        data_len: Option<u64>,
+        unit_size: u32,
+        tweak_base: u64,
+        tweak_shift: u32,
+    ) -> Result<Self, NewDiskError> {
+        let cipher = XtsAes256::new(key, unit_size).map_err(NewDiskError::Crypto)?;
+        // Each sector must contain whole encryption units and whole inner
+        // sectors.
+        let sector_size = unit_size.max(inner.sector_size());
+        Ok(Self {
+            inner,
+            cipher,
+            data_offset,
+            data_len,
+            sector_size,
+            sector_shift: sector_size.trailing_zeros(),
+            unit_size,
+            tweak_base,
+            tweak_shift,
+        })
+    }
+
+    /// Returns the payload sector count given the inner disk's sector count.
+    fn payload_sector_count(&self, inner_sector_count: u64) -> u64 {
+        let len = self.data_len.unwrap_or_else(|| {
+            (inner_sector_count << self.inner.sector_shift()).saturating_sub(self.data_offset)
+        });
+        len >> self.sector_shift
+    }
+
+    /// Returns the inner disk sector for payload sector `sector`.
+    fn inner_sector(&self, sector: u64) -> u64 {
+        (self.data_offset + (sector << self.sector_shift)) >> self.inner.sector_shift()
+    }
+
+    /// Returns the number of inner disk sectors per payload sector, as a
+    /// shift.
+    fn inner_shift(&self) -> u32 {
+        self.sector_shift - self.inner.sector_shift()
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> Result<(), DiskError> {
+        if self.data_len.is_some()
+            && sector
+                .checked_add(len as u64 >> self.sector_shift)
+                .is_none_or(|end| end > self.sector_count())
+        {
+            return Err(DiskError::IllegalBlock);
+        }
+        Ok(())
+    }
+
+    /// Returns the tweak for the unit at payload byte offset `offset`.
+    fn tweak(&self, offset: u64) -> u128 {
+        (self.tweak_base + (offset >> self.tweak_shift)).into()
     }
 }
 
@@ -66,11 +174,11 @@ fn disk_type(&self) -> &str {
     }
 
     fn sector_count(&self) -> u64 {
-        self.inner.sector_count()
+        self.payload_sector_count(self.inner.sector_count())
     }
 
     fn sector_size(&self) -> u32 {
-        self.inner.sector_size()
+        self.sector_size
     }
 
     fn disk_id(&self) -> Option<[u8; 16]> {
@@ -78,7 +186,7 @@ fn sector_size(&self) -> u32 {
     }
 
     fn physical_sector_size(&self) -> u32 {
-        self.inner.physical_sector_size()
+        self.inner.physical_sector_size().max(self.sector_size)
     }
 
     fn is_fua_respected(&self) -> bool {
@@ -100,20 +208,26 @@ async fn read_vectored(
         buffers: &RequestBuffers<'_>,
         sector: u64,
     ) -> Result<(), DiskError> {
+        self.check_range(sector, buffers.len())?;
+
         // Read the encrypted data into the guest buffer. There is no harm
         // in letting the guest transiently see the encrypted data.
-        self.inner.read_vectored(buffers, sector).await?;
+        self.inner
+            .read_vectored(buffers, self.inner_sector(sector))
+            .await?;
 
-        // Decrypt the data a sector at a time.
+        // Decrypt the data a unit at a time.
         let mut ctx = self.cipher.decrypt().map_err(crypto_error)?;
-        let mut buf = vec![0; self.sector_size() as usize];
+        let mut buf = vec![0; self.unit_size as usize];
         let mut reader = buffers.reader();
         let mut writer = buffers.writer();
-        for i in 0..buffers.len() >> self.inner.sector_shift() {
+        let mut offset = sector << self.sector_shift;
+        for _ in 0..buffers.len() / self.unit_size as usize {
             reader.read(&mut buf)?;
-            ctx.cipher((sector + i as u64).into(), &mut buf)
+            ctx.cipher(self.tweak(offset), &mut buf)
                 .map_err(crypto_error)?;
             writer.write(&buf)?;
+            offset += self.unit_size as u64;
         }
         Ok(())
     }
@@ -127,6 +241,8 @@ async fn write_vectored(
         // Allocate a buffer to stage the encrypted data, since we cannot
         // modify the guest buffer or rely on it being stable.
         //
+        self.check_range(sector, buffers.len())?;
+
         // TODO: use a pool with a maximum size, or consider using memory
         // from the global bounce buffer (which could be pre-pinned to avoid
         // extra copies).
@@ -134,23 +250,23 @@ async fn write_vectored(
         let buf = mem.inner_buf_mut().unwrap();
         let staged = OwnedRequestBuffers::linear(0, buffers.len(), true);
 
-        // Encrypt the data a sector at a time.
+        // Encrypt the data a unit at a time.
         let mut ctx = self.cipher.encrypt().map_err(crypto_error)?;
         let mut reader = buffers.reader();
-        let sector_size = self.inner.sector_size() as usize;
+        let unit_size = self.unit_size as usize;
+        let payload_offset = sector << self.sector_shift;
         let mut offset = 0;
-        let mut tweak = sector;
         while offset < buffers.len() {
-            let this_buf = &mut buf[offset..][..sector_size];
+            let this_buf = &mut buf[offset..][..unit_size];
             reader.read(this_buf)?;
-            ctx.cipher(tweak.into(), this_buf).map_err(crypto_error)?;
-            offset += sector_size;
-            tweak += 1;
+            ctx.cipher(self.tweak(payload_offset + offset as u64), this_buf)
+                .map_err(crypto_error)?;
+            offset += unit_size;
         }
 
         // Write the encrypted data.
         self.inner
-            .write_vectored(&staged.buffer(&mem), sector, fua)
+            .write_vectored(&staged.buffer(&mem), self.inner_sector(sector), fua)
             .await?;
         Ok(())
     }
@@ -161,7 +277,14 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
 
     /// Waits for the disk sector size to be different than the specified value.
     async fn wait_resize(&self, sector_count: u64) -> u64 {
-        self.inner.wait_resize(sector_count).await
+        let mut inner_sector_count = self.inner.sector_count();
+        loop {
+            let new_sector_count = self.payload_sector_count(inner_sector_count);
+            if new_sector_count != sector_count {
+                break new_sector_count;
+            }
+            inner_sector_count = self.inner.wait_resize(inner_sector_count).await;
+        }
     }
 
     fn unmap(
@@ -170,7 +293,11 @@ fn unmap(
         count: u64,
         block_level_only: bool,
     ) -> impl std::future::Future<Output = Result<(), DiskError>> + Send {
-        self.inner.unmap(sector, count, block_level_only)
+        self.inner.unmap(
+            self.inner_sector(sector),
+            count << self.inner_shift(),
+            block_level_only,
+        )
     }
 
     fn unmap_behavior(&self) -> UnmapBehavior {
@@ -183,7 +310,7 @@ fn unmap_behavior(&self) -> UnmapBehavior {
     }
 
     fn optimal_unmap_sectors(&self) -> u32 {
-        self.inner.optimal_unmap_sectors()
+        (self.inner.optimal_unmap_sectors() >> self.inner_shift()).max(1)
     }
 }
 
@@ -207,6 +334,7 @@ async fn test_basic_read_write() {
             key.as_flattened(),
             disklayer_ram::ram_disk(0x200000, false).unwrap(),
         )
+        .await
         .unwrap();
         let disk = Disk::new(disk).unwrap();
         let buffers = OwnedRequestBuffers::linear(0, 0x10000, true);
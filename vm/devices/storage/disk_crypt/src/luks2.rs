@@ -0,0 +1,770 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for unlocking LUKS2 containers, as produced by `cryptsetup
+//! luksFormat --type luks2`.
+//!
+//! Only the subset of the format needed to unlock a data segment is
+//! implemented: the binary header (primary or secondary), the JSON metadata,
+//! PBKDF2 and Argon2 keyslots, the LUKS1 anti-forensic splitter, and PBKDF2
+//! digests. Containers with a reencryption in progress, authenticated
+//! (integrity) segments, and ciphers other than aes-xts-plain64 are rejected.
+
+use block_crypto::XtsAes256;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use guestmem::GuestMemory;
+use scsi_buffers::OwnedRequestBuffers;
+use serde::Deserialize;
+use sha2::Digest as _;
+use std::collections::BTreeMap;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::KnownLayout;
+
+type U16BE = zerocopy::U16<zerocopy::BE>;
+type U64BE = zerocopy::U64<zerocopy::BE>;
+
+const MAGIC_PRIMARY: [u8; 6] = *b"LUKS\xba\xbe";
+const MAGIC_SECONDARY: [u8; 6] = *b"SKUL\xba\xbe";
+const BINARY_HEADER_SIZE: usize = 4096;
+
+/// The valid header sizes, which are also the locations at which the
+/// secondary header may be found.
+const HEADER_SIZES: [u64; 9] = [
+    0x4000, 0x8000, 0x10000, 0x20000, 0x40000, 0x80000, 0x100000, 0x200000, 0x400000,
+];
+
+/// The IV for aes-xts-plain64 is always in units of 512 bytes, regardless of
+/// the encryption sector size.
+pub(crate) const IV_SHIFT: u32 = 9;
+
+/// The on-disk binary header, preceding the JSON metadata area.
+#[repr(C)]
+#[derive(FromBytes, Immutable, KnownLayout)]
+struct BinaryHeader {
+    magic: [u8; 6],
+    version: U16BE,
+    hdr_size: U64BE,
+    seqid: U64BE,
+    _label: [u8; 48],
+    checksum_alg: [u8; 32],
+    _salt: [u8; 64],
+    _uuid: [u8; 40],
+    _subsystem: [u8; 48],
+    hdr_offset: U64BE,
+    _padding: [u8; 184],
+    csum: [u8; 64],
+    _padding4096: [u8; 3584],
+}
+
+const _: () = assert!(size_of::<BinaryHeader>() == BINARY_HEADER_SIZE);
+
+/// An error that occurred while opening a LUKS2 container.
+#[derive(Debug, Error)]
+pub enum Luks2Error {
+    /// Failed to read from the underlying disk.
+    #[error("failed to read LUKS2 header")]
+    Io(#[source] DiskError),
+    /// Neither the primary nor the secondary header is valid.
+    #[error("no valid LUKS2 header found")]
+    NoHeader,
+    /// The JSON metadata could not be parsed.
+    #[error("invalid LUKS2 metadata")]
+    Metadata(#[source] serde_json::Error),
+    /// The container is valid but uses a feature that is not supported.
+    #[error("unsupported LUKS2 container: {0}")]
+    Unsupported(&'static str),
+    /// The container metadata is inconsistent.
+    #[error("invalid LUKS2 container: {0}")]
+    Invalid(&'static str),
+    /// The Argon2 key derivation failed.
+    #[error("argon2 key derivation failed")]
+    Argon2(#[source] argon2::Error),
+    /// An error occurred during cryptographic operations.
+    #[error("crypto error")]
+    Crypto(#[source] block_crypto::Error),
+    /// No keyslot could be unlocked with the provided passphrase.
+    #[error("no keyslot could be unlocked with the provided passphrase")]
+    InvalidPassphrase,
+}
+
+/// An unlocked LUKS2 data segment.
+pub(crate) struct Volume {
+    /// The master key for the data segment.
+    pub key: [u8; XtsAes256::KEY_LEN],
+    /// The byte offset of the data segment on the disk.
+    pub offset: u64,
+    /// The length of the data segment in bytes, or `None` if it extends to
+    /// the end of the disk.
+    pub len: Option<u64>,
+    /// The encryption sector size.
+    pub sector_size: u32,
+    /// The IV of the first sector of the data segment, in 512-byte units.
+    pub iv_tweak: u64,
+}
+
+/// Reads the LUKS2 header from `disk` and unlocks the data segment's master
+/// key using `passphrase`.
+pub(crate) async fn unlock(disk: &Disk, passphrase: &[u8]) -> Result<Volume, Luks2Error> {
+    let metadata = read_metadata(disk).await?;
+
+    let mut segments = metadata.segments.iter();
+    let (segment_id, segment) = match (segments.next(), segments.next()) {
+        (Some(segment), None) => segment,
+        (None, _) => return Err(Luks2Error::Invalid("no data segment")),
+        (Some(_), Some(_)) => {
+            return Err(Luks2Error::Unsupported(
+                "multiple segments (reencryption in progress?)",
+            ));
+        }
+    };
+    let Segment::Crypt(segment) = segment else {
+        return Err(Luks2Error::Unsupported("non-crypt data segment"));
+    };
+    if segment.encryption != "aes-xts-plain64" {
+        return Err(Luks2Error::Unsupported(
+            "data segment cipher (only aes-xts-plain64 is supported)",
+        ));
+    }
+    if segment.integrity.is_some() {
+        return Err(Luks2Error::Unsupported("authenticated data segment"));
+    }
+    if !segment.sector_size.is_power_of_two() || !(512..=4096).contains(&segment.sector_size) {
+        return Err(Luks2Error::Invalid("data segment sector size"));
+    }
+    if segment.offset % segment.sector_size as u64 != 0
+        || segment.offset % disk.sector_size() as u64 != 0
+    {
+        return Err(Luks2Error::Invalid("data segment offset"));
+    }
+
+    let digest = metadata
+        .digests
+        .values()
+        .find_map(|digest| match digest {
+            Digest::Pbkdf2(digest) if digest.segments.contains(segment_id) => Some(digest),
+            _ => None,
+        })
+        .ok_or(Luks2Error::Unsupported("no pbkdf2 digest for data segment"))?;
+
+    // Try keyslots in priority order, skipping those that are only to be used
+    // when explicitly requested (priority 0).
+    let mut keyslots = digest
+        .keyslots
+        .iter()
+        .filter_map(|id| match metadata.keyslots.get(id)? {
+            Keyslot::Luks2(keyslot) if keyslot.priority != Some(0) => Some(keyslot),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    keyslots.sort_by_key(|keyslot| std::cmp::Reverse(keyslot.priority.unwrap_or(1)));
+
+    let mut areas = Vec::new();
+    for keyslot in keyslots {
+        let material_len = keyslot
+            .key_size
+            .checked_mul(keyslot.af.stripes as usize)
+            .filter(|&len| keyslot.key_size != 0 && len as u64 <= keyslot.area.size)
+            .ok_or(Luks2Error::Invalid("keyslot area too small"))?;
+        let len = material_len.next_multiple_of(disk.sector_size() as usize);
+        let area = read(disk, keyslot.area.offset, len)
+            .await
+            .map_err(Luks2Error::Io)?;
+        areas.push((keyslot.clone(), area));
+    }
+
+    // Key derivation is deliberately expensive, so run it off the executor.
+    let passphrase = passphrase.to_vec();
+    let digest = digest.clone();
+    let key = blocking::unblock(move || {
+        for (keyslot, mut area) in areas {
+            if let Some(key) = unlock_keyslot(&passphrase, &keyslot, &mut area, &digest)? {
+                return Ok(key);
+            }
+        }
+        Err(Luks2Error::InvalidPassphrase)
+    })
+    .await?;
+
+    Ok(Volume {
+        key,
+        offset: segment.offset,
+        len: segment.size,
+        sector_size: segment.sector_size,
+        iv_tweak: segment.iv_tweak,
+    })
+}
+
+/// Reads and validates the binary headers, returning the metadata from the
+/// most recent valid one.
+async fn read_metadata(disk: &Disk) -> Result<Metadata, Luks2Error> {
+    let primary = read_header(disk, 0, MAGIC_PRIMARY).await?;
+
+    // The secondary header immediately follows the primary. If the primary
+    // is corrupt, probe all the possible locations.
+    let secondary_offsets = match &primary {
+        Some((hdr_size, _, _)) => &[*hdr_size][..],
+        None => &HEADER_SIZES[..],
+    };
+    let mut secondary = None;
+    for &offset in secondary_offsets {
+        // Ignore errors, since the probe may run past the end of the disk.
+        if let Ok(Some(header)) = read_header(disk, offset, MAGIC_SECONDARY).await {
+            secondary = Some(header);
+            break;
+        }
+    }
+
+    let (_, _, json) = match (primary, secondary) {
+        (Some(primary), Some(secondary)) => {
+            if secondary.1 > primary.1 {
+                secondary
+            } else {
+                primary
+            }
+        }
+        (Some(header), None) | (None, Some(header)) => header,
+        (None, None) => return Err(Luks2Error::NoHeader),
+    };
+
+    // The JSON area is padded with zeroes.
+    let len = json.iter().position(|&b| b == 0).unwrap_or(json.len());
+    serde_json::from_slice(&json[..len]).map_err(Luks2Error::Metadata)
+}
+
+/// Reads the header at `offset`, returning the header size, sequence ID, and
+/// JSON area if it is valid.
+async fn read_header(
+    disk: &Disk,
+    offset: u64,
+    magic: [u8; 6],
+) -> Result<Option<(u64, u64, Vec<u8>)>, Luks2Error> {
+    let buf = read(disk, offset, BINARY_HEADER_SIZE)
+        .await
+        .map_err(Luks2Error::Io)?;
+    let header = BinaryHeader::read_from_bytes(&buf).unwrap();
+    let hdr_size = header.hdr_size.get();
+    if header.magic != magic
+        || header.version.get() != 2
+        || header.hdr_offset.get() != offset
+        || !HEADER_SIZES.contains(&hdr_size)
+    {
+        return Ok(None);
+    }
+    let checksum_alg = header.checksum_alg.split(|&b| b == 0).next().unwrap();
+    if checksum_alg != b"sha256" {
+        return Err(Luks2Error::Unsupported("header checksum algorithm"));
+    }
+
+    let mut buf = read(disk, offset, hdr_size as usize)
+        .await
+        .map_err(Luks2Error::Io)?;
+    let csum_offset = std::mem::offset_of!(BinaryHeader, csum);
+    buf[csum_offset..][..header.csum.len()].fill(0);
+    if sha2::Sha256::digest(&buf)[..] != header.csum[..32] {
+        return Ok(None);
+    }
+    buf.drain(..BINARY_HEADER_SIZE);
+    Ok(Some((hdr_size, header.seqid.get(), buf)))
+}
+
+/// Reads `len` bytes at byte offset `offset`, which must both be aligned to
+/// the disk's sector size.
+async fn read(disk: &Disk, offset: u64, len: usize) -> Result<Vec<u8>, DiskError> {
+    if offset % disk.sector_size() as u64 != 0 || len % disk.sector_size() as usize != 0 {
+        return Err(DiskError::InvalidInput);
+    }
+    let mut mem = GuestMemory::allocate(len);
+    let buffers = OwnedRequestBuffers::linear(0, len, true);
+    disk.read_vectored(&buffers.buffer(&mem), offset >> disk.sector_shift())
+        .await?;
+    Ok(mem.inner_buf_mut().unwrap().to_vec())
+}
+
+/// Attempts to unlock `keyslot` with `passphrase`, returning the master key
+/// if it matches `digest`.
+fn unlock_keyslot(
+    passphrase: &[u8],
+    keyslot: &Luks2Keyslot,
+    area: &mut [u8],
+    digest: &Pbkdf2Digest,
+) -> Result<Option<[u8; XtsAes256::KEY_LEN]>, Luks2Error> {
+    if keyslot.key_size != XtsAes256::KEY_LEN {
+        return Ok(None);
+    }
+    let area_info = &keyslot.area;
+    if area_info.kind != "raw" {
+        return Err(Luks2Error::Unsupported("keyslot area type"));
+    }
+    if area_info.encryption != "aes-xts-plain64" || area_info.key_size != XtsAes256::KEY_LEN {
+        return Err(Luks2Error::Unsupported(
+            "keyslot cipher (only aes-xts-plain64 is supported)",
+        ));
+    }
+
+    let mut area_key = [0; XtsAes256::KEY_LEN];
+    keyslot.kdf.derive(passphrase, &mut area_key)?;
+
+    // Decrypt the key material. The keyslot area always uses 512-byte
+    // sectors, numbered from the start of the area.
+    let cipher = XtsAes256::new(&area_key, 512).map_err(Luks2Error::Crypto)?;
+    let mut ctx = cipher.decrypt().map_err(Luks2Error::Crypto)?;
+    for (i, sector) in area.chunks_exact_mut(512).enumerate() {
+        ctx.cipher(i as u128, sector).map_err(Luks2Error::Crypto)?;
+    }
+
+    let material = &area[..keyslot.key_size * keyslot.af.stripes as usize];
+    let key = af_merge(material, keyslot.key_size, keyslot.af.hash)?;
+    if !digest.verify(&key)? {
+        return Ok(None);
+    }
+    Ok(Some(key.try_into().unwrap()))
+}
+
+/// Recovers the key from the anti-forensic split `material`, as in
+/// `AF_merge` from cryptsetup.
+fn af_merge(material: &[u8], key_size: usize, hash: Hash) -> Result<Vec<u8>, Luks2Error> {
+    let mut key = vec![0; key_size];
+    let mut stripes = material.chunks_exact(key_size);
+    let last = stripes
+        .next_back()
+        .ok_or(Luks2Error::Invalid("no anti-forensic stripes"))?;
+    for stripe in stripes {
+        key.iter_mut().zip(stripe).for_each(|(k, s)| *k ^= s);
+        hash.diffuse(&mut key)?;
+    }
+    key.iter_mut().zip(last).for_each(|(k, s)| *k ^= s);
+    Ok(key)
+}
+
+/// The LUKS2 JSON metadata. Unknown sections (tokens, config) are ignored.
+#[derive(Deserialize)]
+struct Metadata {
+    keyslots: BTreeMap<String, Keyslot>,
+    segments: BTreeMap<String, Segment>,
+    digests: BTreeMap<String, Digest>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Keyslot {
+    #[serde(rename = "luks2")]
+    Luks2(Luks2Keyslot),
+    /// Other keyslot types (such as reencryption keyslots) cannot be used to
+    /// unlock the volume.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone, Deserialize)]
+struct Luks2Keyslot {
+    key_size: usize,
+    area: KeyslotArea,
+    kdf: Kdf,
+    af: AntiForensic,
+    #[serde(default)]
+    priority: Option<u32>,
+}
+
+#[derive(Clone, Deserialize)]
+struct KeyslotArea {
+    #[serde(rename = "type")]
+    kind: String,
+    encryption: String,
+    key_size: usize,
+    #[serde(with = "string_u64")]
+    offset: u64,
+    #[serde(with = "string_u64")]
+    size: u64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Kdf {
+    Pbkdf2 {
+        hash: Hash,
+        iterations: u32,
+        #[serde(with = "base64_vec")]
+        salt: Vec<u8>,
+    },
+    Argon2i {
+        time: u32,
+        memory: u32,
+        cpus: u32,
+        #[serde(with = "base64_vec")]
+        salt: Vec<u8>,
+    },
+    Argon2id {
+        time: u32,
+        memory: u32,
+        cpus: u32,
+        #[serde(with = "base64_vec")]
+        salt: Vec<u8>,
+    },
+}
+
+impl Kdf {
+    fn derive(&self, passphrase: &[u8], out: &mut [u8]) -> Result<(), Luks2Error> {
+        let (algorithm, time, memory, cpus, salt) = match self {
+            Kdf::Pbkdf2 {
+                hash,
+                iterations,
+                salt,
+            } => return hash.pbkdf2(passphrase, salt, *iterations, out),
+            Kdf::Argon2i {
+                time,
+                memory,
+                cpus,
+                salt,
+            } => (argon2::Algorithm::Argon2i, time, memory, cpus, salt),
+            Kdf::Argon2id {
+                time,
+                memory,
+                cpus,
+                salt,
+            } => (argon2::Algorithm::Argon2id, time, memory, cpus, salt),
+        };
+        // LUKS2 specifies the memory cost in KiB, as does the argon2 crate.
+        let params = argon2::Params::new(*memory, *time, *cpus, Some(out.len()))
+            .map_err(Luks2Error::Argon2)?;
+        argon2::Argon2::new(algorithm, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase, salt, out)
+            .map_err(Luks2Error::Argon2)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct AntiForensic {
+    stripes: u32,
+    hash: Hash,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Segment {
+    #[serde(rename = "crypt")]
+    Crypt(CryptSegment),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct CryptSegment {
+    #[serde(with = "string_u64")]
+    offset: u64,
+    #[serde(with = "segment_size")]
+    size: Option<u64>,
+    #[serde(with = "string_u64")]
+    iv_tweak: u64,
+    encryption: String,
+    sector_size: u32,
+    #[serde(default)]
+    integrity: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Digest {
+    #[serde(rename = "pbkdf2")]
+    Pbkdf2(Pbkdf2Digest),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone, Deserialize)]
+struct Pbkdf2Digest {
+    keyslots: Vec<String>,
+    segments: Vec<String>,
+    hash: Hash,
+    iterations: u32,
+    #[serde(with = "base64_vec")]
+    salt: Vec<u8>,
+    #[serde(with = "base64_vec")]
+    digest: Vec<u8>,
+}
+
+impl Pbkdf2Digest {
+    fn verify(&self, key: &[u8]) -> Result<bool, Luks2Error> {
+        let mut digest = vec![0; self.digest.len()];
+        self.hash
+            .pbkdf2(key, &self.salt, self.iterations, &mut digest)?;
+        Ok(digest == self.digest)
+    }
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Hash {
+    Sha256,
+    Sha512,
+    #[serde(other)]
+    Other,
+}
+
+impl Hash {
+    fn pbkdf2(
+        self,
+        password: &[u8],
+        salt: &[u8],
+        iterations: u32,
+        out: &mut [u8],
+    ) -> Result<(), Luks2Error> {
+        match self {
+            Hash::Sha256 => pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password, salt, iterations, out),
+            Hash::Sha512 => pbkdf2::pbkdf2_hmac::<sha2::Sha512>(password, salt, iterations, out),
+            Hash::Other => return Err(Luks2Error::Unsupported("hash algorithm")),
+        }
+        Ok(())
+    }
+
+    fn diffuse(self, buf: &mut [u8]) -> Result<(), Luks2Error> {
+        match self {
+            Hash::Sha256 => diffuse::<sha2::Sha256>(buf),
+            Hash::Sha512 => diffuse::<sha2::Sha512>(buf),
+            Hash::Other => return Err(Luks2Error::Unsupported("hash algorithm")),
+        }
+        Ok(())
+    }
+}
+
+/// The anti-forensic diffusion function: each digest-sized block (including
+/// a trailing partial block) is replaced by the hash of its big-endian index
+/// followed by its contents.
+fn diffuse<D: sha2::Digest>(buf: &mut [u8]) {
+    for (i, block) in buf.chunks_mut(D::output_size()).enumerate() {
+        let mut hasher = D::new();
+        hasher.update((i as u32).to_be_bytes());
+        hasher.update(&*block);
+        let hash = hasher.finalize();
+        block.copy_from_slice(&hash[..block.len()]);
+    }
+}
+
+// The helpers below deserialize to owned strings, since the JSON may escape
+// characters (such as `/` in base64), which prevents borrowing.
+
+/// de/serialize a `u64` stored as a decimal string.
+mod string_u64 {
+    use serde::Deserialize;
+    use serde::Deserializer;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// de/serialize a segment size, which is either a decimal string or
+/// `"dynamic"`.
+mod segment_size {
+    use serde::Deserialize;
+    use serde::Deserializer;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        let s = String::deserialize(d)?;
+        if s == "dynamic" {
+            return Ok(None);
+        }
+        s.parse().map(Some).map_err(serde::de::Error::custom)
+    }
+}
+
+/// de/serialize a `Vec<u8>` stored as a base64 string.
+mod base64_vec {
+    use base64::Engine;
+    use serde::Deserialize;
+    use serde::Deserializer;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        base64::engine::general_purpose::STANDARD
+            .decode(String::deserialize(d)?)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BinaryHeader;
+    use super::Hash;
+    use super::Luks2Error;
+    use super::MAGIC_PRIMARY;
+    use super::MAGIC_SECONDARY;
+    use super::af_merge;
+    use super::diffuse;
+    use super::unlock;
+    use crate::CryptDisk;
+    use base64::Engine;
+    use block_crypto::XtsAes256;
+    use disk_backend::Disk;
+    use disk_backend::DiskIo;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use sha2::Digest;
+    use std::mem::offset_of;
+
+    const HDR_SIZE: usize = 0x4000;
+    const AREA_OFFSET: u64 = 0x8000;
+    const DATA_OFFSET: u64 = 0x10000;
+
+    async fn write(disk: &Disk, offset: u64, data: &[u8]) {
+        let mut mem = GuestMemory::allocate(data.len());
+        mem.inner_buf_mut().unwrap().copy_from_slice(data);
+        let buffers = OwnedRequestBuffers::linear(0, data.len(), true);
+        disk.write_vectored(&buffers.buffer(&mem), offset >> disk.sector_shift(), false)
+            .await
+            .unwrap();
+    }
+
+    /// Formats a minimal LUKS2 container with a single PBKDF2 keyslot (with
+    /// one anti-forensic stripe) protecting `key`.
+    async fn format(disk: &Disk, passphrase: &[u8], key: &[u8; 64]) {
+        let kdf_salt = [1; 32];
+        let digest_salt = [2; 32];
+
+        let mut area_key = [0; 64];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase, &kdf_salt, 1000, &mut area_key);
+        let mut area = key.to_vec();
+        area.resize(512, 0);
+        XtsAes256::new(&area_key, 512)
+            .unwrap()
+            .encrypt()
+            .unwrap()
+            .cipher(0, &mut area)
+            .unwrap();
+
+        let mut digest = [0; 32];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(key, &digest_salt, 1000, &mut digest);
+
+        let b64 = |v: &[u8]| base64::engine::general_purpose::STANDARD.encode(v);
+        let json = serde_json::json!({
+            "keyslots": {
+                "0": {
+                    "type": "luks2",
+                    "key_size": 64,
+                    "area": {
+                        "type": "raw",
+                        "offset": AREA_OFFSET.to_string(),
+                        "size": "4096",
+                        "encryption": "aes-xts-plain64",
+                        "key_size": 64,
+                    },
+                    "kdf": {
+                        "type": "pbkdf2",
+                        "hash": "sha256",
+                        "iterations": 1000,
+                        "salt": b64(&kdf_salt),
+                    },
+                    "af": { "type": "luks1", "stripes": 1, "hash": "sha256" },
+                },
+            },
+            "tokens": {},
+            "segments": {
+                "0": {
+                    "type": "crypt",
+                    "offset": DATA_OFFSET.to_string(),
+                    "size": "dynamic",
+                    "iv_tweak": "0",
+                    "encryption": "aes-xts-plain64",
+                    "sector_size": 4096,
+                },
+            },
+            "digests": {
+                "0": {
+                    "type": "pbkdf2",
+                    "keyslots": ["0"],
+                    "segments": ["0"],
+                    "hash": "sha256",
+                    "iterations": 1000,
+                    "salt": b64(&digest_salt),
+                    "digest": b64(&digest),
+                },
+            },
+            "config": { "json_size": "12288", "keyslots_size": "32768" },
+        });
+        let json = serde_json::to_vec(&json).unwrap();
+
+        for (offset, magic) in [(0, MAGIC_PRIMARY), (HDR_SIZE, MAGIC_SECONDARY)] {
+            let mut hdr = vec![0; HDR_SIZE];
+            let mut put =
+                |field: usize, data: &[u8]| hdr[field..][..data.len()].copy_from_slice(data);
+            put(offset_of!(BinaryHeader, magic), &magic);
+            put(offset_of!(BinaryHeader, version), &2u16.to_be_bytes());
+            put(
+                offset_of!(BinaryHeader, hdr_size),
+                &(HDR_SIZE as u64).to_be_bytes(),
+            );
+            put(offset_of!(BinaryHeader, seqid), &1u64.to_be_bytes());
+            put(offset_of!(BinaryHeader, checksum_alg), b"sha256");
+            put(
+                offset_of!(BinaryHeader, hdr_offset),
+                &(offset as u64).to_be_bytes(),
+            );
+            put(size_of::<BinaryHeader>(), &json);
+            let csum = sha2::Sha256::digest(&hdr);
+            hdr[offset_of!(BinaryHeader, csum)..][..csum.len()].copy_from_slice(&csum);
+            write(disk, offset as u64, &hdr).await;
+        }
+        write(disk, AREA_OFFSET, &area).await;
+    }
+
+    #[async_test]
+    async fn test_unlock() {
+        let disk = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let key = [0x42; 64];
+        format(&disk, b"hunter2", &key).await;
+
+        let volume = unlock(&disk, b"hunter2").await.unwrap();
+        assert_eq!(volume.key, key);
+        assert_eq!(volume.offset, DATA_OFFSET);
+        assert_eq!(volume.len, None);
+        assert_eq!(volume.sector_size, 4096);
+
+        assert!(matches!(
+            unlock(&disk, b"hunter3").await,
+            Err(Luks2Error::InvalidPassphrase)
+        ));
+
+        // The secondary header is used if the primary is corrupt.
+        write(&disk, 0, &[0; 4096]).await;
+        assert_eq!(unlock(&disk, b"hunter2").await.unwrap().key, key);
+
+        let crypt = CryptDisk::new(disk_crypt_resources::Cipher::Luks2, b"hunter2", disk)
+            .await
+            .unwrap();
+        assert_eq!(crypt.sector_size(), 4096);
+        assert_eq!(crypt.sector_count(), (0x100000 - DATA_OFFSET) / 4096);
+    }
+
+    #[test]
+    fn test_diffuse_partial_block() {
+        // A 40-byte buffer spans one full SHA-256 block and one partial one.
+        let mut buf = [0x5a; 40];
+        diffuse::<sha2::Sha256>(&mut buf);
+
+        let mut expected = Vec::new();
+        expected.extend(
+            sha2::Sha256::new()
+                .chain_update([0, 0, 0, 0])
+                .chain_update([0x5a; 32])
+                .finalize(),
+        );
+        expected.extend(
+            &sha2::Sha256::new()
+                .chain_update([0, 0, 0, 1])
+                .chain_update([0x5a; 8])
+                .finalize()[..8],
+        );
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_af_merge_single_stripe() {
+        // With a single stripe, the material is the key.
+        let material = (0..64).collect::<Vec<u8>>();
+        assert_eq!(af_merge(&material, 64, Hash::Sha256).unwrap(), material);
+    }
+}
@@ -60,6 +60,7 @@ async fn resolve(
             .map_err(DiskResolveError::ResolveInner)?;
 
         let disk = CryptDisk::new(resource.cipher, &resource.key, inner.0)
+            .await
             .map_err(DiskResolveError::NewDisk)?;
         ResolvedDisk::new(disk).map_err(DiskResolveError::InvalidDisk)
     }
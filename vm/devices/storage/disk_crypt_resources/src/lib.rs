@@ -17,7 +17,9 @@ pub struct DiskCryptHandle {
     pub disk: Resource<DiskHandleKind>,
     /// The cipher to use for encryption.
     pub cipher: Cipher,
-    /// The key. This must be appropriately sized for the cipher.
+    /// The key. For raw ciphers, this must be appropriately sized for the
+    /// cipher. For [`Cipher::Luks2`], this is the passphrase (or key file
+    /// contents) used to unlock one of the container's keyslots.
     pub key: Vec<u8>,
 }
 
@@ -33,4 +35,10 @@ pub enum Cipher {
     ///
     /// This requires a 512-bit key.
     XtsAes256,
+    /// A LUKS2 container. The cipher, data offset, and master key are taken
+    /// from the container's header, with the master key unlocked from a
+    /// keyslot by the provided passphrase.
+    ///
+    /// Only aes-xts-plain64 data segments are supported.
+    Luks2,
 }
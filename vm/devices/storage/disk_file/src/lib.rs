@@ -135,6 +135,26 @@ pub async fn write(
         Ok(())
     }
 
+    /// Deallocates the storage for the given sectors, if supported by the
+    /// platform and file system.
+    pub async fn unmap(&self, sector: u64, count: u64) -> Result<(), DiskError> {
+        if self.metadata.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let offset = sector << self.sector_shift;
+        let len = count << self.sector_shift;
+        if offset + len > self.metadata.disk_size {
+            return Err(DiskError::IllegalBlock);
+        }
+        let file = self.file.clone();
+        match unblock(move || file.punch_hole(offset, len)).await {
+            Ok(()) => Ok(()),
+            // Unmap is only a hint, so it's fine to leave the data in place.
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => Ok(()),
+            Err(err) => Err(DiskError::Io(err)),
+        }
+    }
+
     pub async fn flush(&self) -> Result<(), DiskError> {
         let file = self.file.clone();
         unblock(move || file.sync_all())
@@ -196,14 +216,24 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
 
     async fn unmap(
         &self,
-        _sector: u64,
-        _count: u64,
+        sector: u64,
+        count: u64,
         _block_level_only: bool,
     ) -> Result<(), DiskError> {
-        Ok(())
+        FileDisk::unmap(self, sector, count).await
     }
 
     fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
-        disk_backend::UnmapBehavior::Ignored
+        // Punched holes read as zero, but the file system may not support
+        // them, in which case the data is left in place.
+        if cfg!(target_os = "linux") {
+            disk_backend::UnmapBehavior::Unspecified
+        } else {
+            disk_backend::UnmapBehavior::Ignored
+        }
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        (self.metadata.physical_sector_size >> self.sector_shift).max(1)
     }
 }
@@ -15,6 +15,12 @@
 pub trait ReadWriteAt {
     fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize>;
     fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+    /// Deallocates the storage for the given range, which then reads as
+    /// zero, without changing the file size.
+    ///
+    /// Fails with [`std::io::ErrorKind::Unsupported`] if the platform or file
+    /// system cannot do this.
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<()>;
 }
 
 #[cfg(windows)]
@@ -25,6 +31,9 @@ fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
         std::os::windows::fs::FileExt::seek_read(self, buf, offset)
     }
+    fn punch_hole(&self, _offset: u64, _len: u64) -> Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
 }
 
 #[cfg(unix)]
@@ -36,4 +45,31 @@ fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
         std::os::unix::fs::FileExt::read_at(self, buf, offset)
     }
+
+    #[cfg(target_os = "linux")]
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<()> {
+        use nix::errno::Errno;
+        use nix::fcntl::FallocateFlags;
+        use std::os::unix::prelude::*;
+
+        let to_off_t = |v: u64| {
+            v.try_into()
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))
+        };
+        nix::fcntl::fallocate(
+            self.as_raw_fd(),
+            FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+            to_off_t(offset)?,
+            to_off_t(len)?,
+        )
+        .map_err(|err| match err {
+            Errno::EOPNOTSUPP => std::io::ErrorKind::Unsupported.into(),
+            err => err.into(),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn punch_hole(&self, _offset: u64, _len: u64) -> Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
 }
@@ -0,0 +1,422 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk that is reachable through multiple paths, any of which can be
+//! failed at runtime to test failover.
+//!
+//! Writes are mirrored to every path that is up, and reads are served by the
+//! first path that is up and has not missed any writes to the range being
+//! read. Set `paths/<n>/failed` via inspect to fail or restore a path.
+
+#![forbid(unsafe_code)]
+
+/// Provides a multipath disk.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use futures::future::join_all;
+use inspect::Inspect;
+use inspect_counters::SharedCounter;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use thiserror::Error;
+
+/// A disk that mirrors IO across multiple paths.
+#[derive(Inspect)]
+pub struct MultipathDisk {
+    #[inspect(iter_by_index)]
+    paths: Vec<Path>,
+    sector_shift: u32,
+}
+
+#[derive(Inspect)]
+struct Path {
+    disk: Disk,
+    /// Set to simulate the failure of this path.
+    #[inspect(with = "inspect::AtomicMut")]
+    failed: AtomicBool,
+    /// Sector ranges that this path has missed writes to and so cannot be
+    /// read from.
+    #[inspect(with = "|x| x.lock().len()")]
+    stale: Mutex<RangeSet>,
+    errors: SharedCounter,
+}
+
+impl Path {
+    fn is_up(&self) -> bool {
+        !self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// An error returned when creating a [`MultipathDisk`].
+#[derive(Debug, Error)]
+pub enum NewMultipathDiskError {
+    /// No paths were provided.
+    #[error("at least one path is required")]
+    NoPaths,
+    /// The paths have different geometries.
+    #[error("path {0} does not match the sector size and count of the first path")]
+    MismatchedPath(usize),
+}
+
+impl MultipathDisk {
+    /// Returns a new disk reachable through `paths`.
+    ///
+    /// All paths must have the same sector size and sector count.
+    pub fn new(paths: Vec<Disk>) -> Result<Self, NewMultipathDiskError> {
+        let first = paths.first().ok_or(NewMultipathDiskError::NoPaths)?;
+        let sector_shift = first.sector_shift();
+        let sector_count = first.sector_count();
+        for (i, path) in paths.iter().enumerate() {
+            if path.sector_shift() != sector_shift || path.sector_count() != sector_count {
+                return Err(NewMultipathDiskError::MismatchedPath(i));
+            }
+        }
+        Ok(Self {
+            paths: paths
+                .into_iter()
+                .map(|disk| Path {
+                    disk,
+                    failed: AtomicBool::new(false),
+                    stale: Mutex::new(RangeSet::default()),
+                    errors: SharedCounter::new(),
+                })
+                .collect(),
+            sector_shift,
+        })
+    }
+
+    fn first(&self) -> &Disk {
+        &self.paths[0].disk
+    }
+
+    /// Issues `io` to every path that is up. If it succeeds on any path, the
+    /// sector range is marked stale on the paths where it did not.
+    async fn mirror<'a, F>(&'a self, start: u64, end: u64, io: F) -> Result<(), DiskError>
+    where
+        F: AsyncFn(&'a Disk) -> Result<(), DiskError>,
+    {
+        let results = join_all(self.paths.iter().map(async |path| {
+            if path.is_up() {
+                Some(io(&path.disk).await)
+            } else {
+                None
+            }
+        }))
+        .await;
+
+        if !results.iter().any(|r| matches!(r, Some(Ok(())))) {
+            for (path, result) in self.paths.iter().zip(&results) {
+                if result.as_ref().is_some_and(|r| r.is_err()) {
+                    path.errors.increment();
+                }
+            }
+            return Err(results
+                .into_iter()
+                .find_map(|r| r.and_then(|r| r.err()))
+                .unwrap_or_else(no_path));
+        }
+
+        // At least one path has the new data, so any path that did not
+        // succeed is now out of date for this range.
+        for (path, result) in self.paths.iter().zip(results) {
+            match result {
+                Some(Ok(())) => path.stale.lock().remove(start, end),
+                Some(Err(err)) => {
+                    path.errors.increment();
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "write failed on path"
+                    );
+                    path.stale.lock().insert(start, end);
+                }
+                None => path.stale.lock().insert(start, end),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn no_path() -> DiskError {
+    DiskError::Io(std::io::Error::other("all paths have failed"))
+}
+
+impl DiskIo for MultipathDisk {
+    fn disk_type(&self) -> &str {
+        "multipath"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.first().sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.first().sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.first().disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.first().physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.paths.iter().all(|path| path.disk.is_fua_respected())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.paths.iter().any(|path| path.disk.is_read_only())
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        // Paths may unmap differently, so only promise anything if they all
+        // agree.
+        let behavior = self.first().unmap_behavior();
+        if self
+            .paths
+            .iter()
+            .all(|path| path.disk.unmap_behavior() == behavior)
+        {
+            behavior
+        } else {
+            UnmapBehavior::Unspecified
+        }
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.first().optimal_unmap_sectors()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let end = sector + (buffers.len() >> self.sector_shift) as u64;
+        let mut first_err = None;
+        for path in &self.paths {
+            if !path.is_up() || path.stale.lock().intersects(sector, end) {
+                continue;
+            }
+            match path.disk.read_vectored(buffers, sector).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    path.errors.increment();
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "read failed on path, failing over"
+                    );
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        Err(first_err.unwrap_or_else(no_path))
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        let end = sector + (buffers.len() >> self.sector_shift) as u64;
+        self.mirror(sector, end, async |disk| {
+            disk.write_vectored(buffers, sector, fua).await
+        })
+        .await
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        let results = join_all(
+            self.paths
+                .iter()
+                .filter(|path| path.is_up())
+                .map(|path| path.disk.sync_cache()),
+        )
+        .await;
+        // Succeed if any path flushed, since each path that is up has all the
+        // data that has been written while it was up.
+        if results.iter().any(|r| r.is_ok()) {
+            return Ok(());
+        }
+        Err(results
+            .into_iter()
+            .find_map(|r| r.err())
+            .unwrap_or_else(no_path))
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        self.mirror(sector, sector + count, async |disk| {
+            disk.unmap(sector, count, block_level_only).await
+        })
+        .await
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.first().wait_resize(sector_count).await
+    }
+}
+
+/// A set of non-overlapping, non-adjacent half-open ranges.
+#[derive(Debug, Default)]
+struct RangeSet(BTreeMap<u64, u64>);
+
+impl RangeSet {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+        // Merge with any ranges that overlap or touch the new one.
+        let touching = self
+            .0
+            .range(..=end)
+            .rev()
+            .take_while(|&(_, &e)| e >= start)
+            .map(|(&s, &e)| (s, e))
+            .collect::<Vec<_>>();
+        for (s, e) in touching {
+            self.0.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.0.insert(start, end);
+    }
+
+    fn remove(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let overlapping = self
+            .0
+            .range(..end)
+            .rev()
+            .take_while(|&(_, &e)| e > start)
+            .map(|(&s, &e)| (s, e))
+            .collect::<Vec<_>>();
+        for (s, e) in overlapping {
+            self.0.remove(&s);
+            if s < start {
+                self.0.insert(s, start);
+            }
+            if e > end {
+                self.0.insert(end, e);
+            }
+        }
+    }
+
+    fn intersects(&self, start: u64, end: u64) -> bool {
+        self.0
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, &e)| e > start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultipathDisk;
+    use super::RangeSet;
+    use disk_backend::Disk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    #[test]
+    fn test_range_set() {
+        let mut set = RangeSet::default();
+        set.insert(10, 20);
+        set.insert(30, 40);
+        set.insert(20, 30);
+        assert_eq!(set.0.iter().collect::<Vec<_>>(), [(&10, &40)]);
+        set.remove(15, 25);
+        assert_eq!(set.0.iter().collect::<Vec<_>>(), [(&10, &15), (&25, &40)]);
+        assert!(set.intersects(14, 16));
+        assert!(!set.intersects(15, 25));
+        assert!(set.intersects(0, 100));
+        set.remove(0, 100);
+        assert_eq!(set.len(), 0);
+    }
+
+    async fn write(disk: &Disk, sector: u64, value: u8) -> Result<(), disk_backend::DiskError> {
+        let mut mem = GuestMemory::allocate(512);
+        mem.inner_buf_mut().unwrap().fill(value);
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, 512, false).buffer(&mem),
+            sector,
+            false,
+        )
+        .await
+    }
+
+    async fn read(disk: &Disk, sector: u64) -> Result<u8, disk_backend::DiskError> {
+        let mut mem = GuestMemory::allocate(512);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, 512, true).buffer(&mem),
+            sector,
+        )
+        .await?;
+        Ok(mem.inner_buf_mut().unwrap()[0])
+    }
+
+    async fn set_path_failed(disk: &Disk, index: usize, failed: bool) {
+        inspect::update(
+            &format!("disk/paths/{index}/failed"),
+            &failed.to_string(),
+            disk,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[async_test]
+    async fn test_failover() {
+        let a = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let b = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let disk = Disk::new(MultipathDisk::new(vec![a.clone(), b.clone()]).unwrap()).unwrap();
+
+        // Writes are mirrored.
+        write(&disk, 0, 1).await.unwrap();
+        assert_eq!(read(&a, 0).await.unwrap(), 1);
+        assert_eq!(read(&b, 0).await.unwrap(), 1);
+
+        // Writes while path 0 is down only go to path 1.
+        set_path_failed(&disk, 0, true).await;
+        write(&disk, 1, 2).await.unwrap();
+        assert_eq!(read(&disk, 1).await.unwrap(), 2);
+        assert_eq!(read(&a, 1).await.unwrap(), 0);
+
+        // With both paths down, IO fails.
+        set_path_failed(&disk, 1, true).await;
+        read(&disk, 0).await.unwrap_err();
+        write(&disk, 0, 3).await.unwrap_err();
+
+        // Once restored, path 0 is not used for the sectors it missed.
+        set_path_failed(&disk, 0, false).await;
+        assert_eq!(read(&disk, 0).await.unwrap(), 1);
+        read(&disk, 1).await.unwrap_err();
+        set_path_failed(&disk, 1, false).await;
+        assert_eq!(read(&disk, 1).await.unwrap(), 2);
+
+        // Rewriting the missed sectors brings path 0 back in sync.
+        write(&disk, 1, 4).await.unwrap();
+        set_path_failed(&disk, 1, true).await;
+        assert_eq!(read(&disk, 1).await.unwrap(), 4);
+    }
+}
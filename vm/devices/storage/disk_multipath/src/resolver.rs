@@ -0,0 +1,40 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::MultipathDisk;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::MultipathDiskHandle;
+use futures::future::try_join_all;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for [`MultipathDisk`].
+pub struct MultipathDiskResolver;
+declare_static_async_resolver!(MultipathDiskResolver, (DiskHandleKind, MultipathDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, MultipathDiskHandle> for MultipathDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: MultipathDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let paths = try_join_all(
+            rsrc.paths
+                .into_iter()
+                .map(async |path| resolver.resolve(path, input).await.map(|r| r.0)),
+        )
+        .await?;
+
+        ResolvedDisk::new(MultipathDisk::new(paths)?)
+            .map_err(|e| anyhow::anyhow!("failed to create the multipath disk: {}", e))
+    }
+}
@@ -4,11 +4,18 @@
 //! Provides a basic implementation of SCSI persistent reservations on top of
 //! any other disk type.
 //!
-//! Since these reservations are stored locally in memory, this is not useful
-//! for actually sharing a disk between VMs. This is just useful for testing.
+//! [`DiskWithReservations`] stores reservations locally in memory, so it is
+//! not useful for actually sharing a disk between VMs. This is just useful for
+//! testing. [`DiskWithSharedReservations`] stores them in a file, so that
+//! multiple VMs sharing a disk can arbitrate access to it.
 
 #![expect(missing_docs)]
-#![forbid(unsafe_code)]
+
+mod shared;
+
+pub use shared::DiskWithSharedReservations;
+pub use shared::DiskWithSharedReservationsResolver;
+pub use shared::ResolveSharedPrDiskError;
 
 use async_trait::async_trait;
 use disk_backend::Disk;
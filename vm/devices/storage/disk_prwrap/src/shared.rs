@@ -0,0 +1,711 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Persistent reservations whose state is stored in a file, so that multiple
+//! VMs sharing a disk can arbitrate access to it.
+
+use async_trait::async_trait;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::pr;
+use disk_backend::pr::ReservationType;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::DiskWithSharedReservationsHandle;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::sync::Arc;
+use thiserror::Error;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+pub struct DiskWithSharedReservationsResolver;
+declare_static_async_resolver!(
+    DiskWithSharedReservationsResolver,
+    (DiskHandleKind, DiskWithSharedReservationsHandle)
+);
+
+#[derive(Debug, Error)]
+pub enum ResolveSharedPrDiskError {
+    #[error("failed to resolve inner disk")]
+    Resolve(#[source] ResolveError),
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, DiskWithSharedReservationsHandle>
+    for DiskWithSharedReservationsResolver
+{
+    type Output = ResolvedDisk;
+    type Error = ResolveSharedPrDiskError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: DiskWithSharedReservationsHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver
+            .resolve(rsrc.disk, input)
+            .await
+            .map_err(ResolveSharedPrDiskError::Resolve)?;
+
+        ResolvedDisk::new(DiskWithSharedReservations::new(
+            inner.0,
+            rsrc.store,
+            rsrc.host_id,
+        ))
+        .map_err(ResolveSharedPrDiskError::InvalidDisk)
+    }
+}
+
+/// A disk wrapper that adds persistent reservations support to any disk type,
+/// storing the reservation state in a file.
+///
+/// Each wrapper is a separate initiator, identified by its host ID. Multiple
+/// VMs attached to the same disk and the same state file see each other's
+/// registrations and reservations, as they would on a shared LUN. The state
+/// file is locked for each operation, so the VMs can run in different
+/// processes.
+///
+/// Unlike [`DiskWithReservations`](crate::DiskWithReservations), reads and
+/// writes from initiators that do not have access under the current
+/// reservation fail with a reservation conflict.
+#[derive(Inspect)]
+pub struct DiskWithSharedReservations {
+    inner: Disk,
+    #[inspect(skip)]
+    store: Arc<Mutex<File>>,
+    #[inspect(hex)]
+    host_id: u64,
+}
+
+impl DiskWithSharedReservations {
+    /// Wraps `inner` with persistent reservations support, storing the
+    /// reservation state in `store` and identifying this initiator with
+    /// `host_id`.
+    pub fn new(inner: Disk, store: File, host_id: u64) -> Self {
+        Self {
+            inner,
+            store: Arc::new(Mutex::new(store)),
+            host_id,
+        }
+    }
+
+    /// Reads the current state.
+    async fn state(&self) -> Result<SharedState, DiskError> {
+        let store = self.store.clone();
+        blocking::unblock(move || {
+            let file = store.lock();
+            let _lock = sys::lock(&file, false)?;
+            read_state(&file)
+        })
+        .await
+        .map_err(DiskError::Io)
+    }
+
+    /// Updates the state with `f`. The state is only written back if `f`
+    /// succeeds.
+    async fn update<T: 'static + Send>(
+        &self,
+        f: impl 'static + Send + FnOnce(&mut SharedState) -> Result<T, DiskError>,
+    ) -> Result<T, DiskError> {
+        let store = self.store.clone();
+        blocking::unblock(move || {
+            let file = store.lock();
+            let _lock = sys::lock(&file, true).map_err(DiskError::Io)?;
+            let mut state = read_state(&file).map_err(DiskError::Io)?;
+            let r = f(&mut state)?;
+            write_state(&file, &state).map_err(DiskError::Io)?;
+            Ok(r)
+        })
+        .await
+    }
+
+    async fn check_access(&self, write: bool) -> Result<(), DiskError> {
+        if !self.state().await?.allows(self.host_id, write) {
+            return Err(DiskError::ReservationConflict);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SharedState {
+    generation: u32,
+    persist_through_power_loss: bool,
+    registrations: Vec<Registration>,
+    reservation: Option<Reservation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Registration {
+    host_id: u64,
+    key: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Reservation {
+    holder: u64,
+    reservation_type: StoredReservationType,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum StoredReservationType {
+    WriteExclusive,
+    ExclusiveAccess,
+    WriteExclusiveRegistrantsOnly,
+    ExclusiveAccessRegistrantsOnly,
+    WriteExclusiveAllRegistrants,
+    ExclusiveAccessAllRegistrants,
+}
+
+impl From<ReservationType> for StoredReservationType {
+    fn from(value: ReservationType) -> Self {
+        match value {
+            ReservationType::WriteExclusive => Self::WriteExclusive,
+            ReservationType::ExclusiveAccess => Self::ExclusiveAccess,
+            ReservationType::WriteExclusiveRegistrantsOnly => Self::WriteExclusiveRegistrantsOnly,
+            ReservationType::ExclusiveAccessRegistrantsOnly => Self::ExclusiveAccessRegistrantsOnly,
+            ReservationType::WriteExclusiveAllRegistrants => Self::WriteExclusiveAllRegistrants,
+            ReservationType::ExclusiveAccessAllRegistrants => Self::ExclusiveAccessAllRegistrants,
+        }
+    }
+}
+
+impl From<StoredReservationType> for ReservationType {
+    fn from(value: StoredReservationType) -> Self {
+        match value {
+            StoredReservationType::WriteExclusive => Self::WriteExclusive,
+            StoredReservationType::ExclusiveAccess => Self::ExclusiveAccess,
+            StoredReservationType::WriteExclusiveRegistrantsOnly => {
+                Self::WriteExclusiveRegistrantsOnly
+            }
+            StoredReservationType::ExclusiveAccessRegistrantsOnly => {
+                Self::ExclusiveAccessRegistrantsOnly
+            }
+            StoredReservationType::WriteExclusiveAllRegistrants => {
+                Self::WriteExclusiveAllRegistrants
+            }
+            StoredReservationType::ExclusiveAccessAllRegistrants => {
+                Self::ExclusiveAccessAllRegistrants
+            }
+        }
+    }
+}
+
+impl StoredReservationType {
+    fn all_registrants(self) -> bool {
+        matches!(
+            self,
+            Self::WriteExclusiveAllRegistrants | Self::ExclusiveAccessAllRegistrants
+        )
+    }
+
+    fn exclusive_access(self) -> bool {
+        matches!(
+            self,
+            Self::ExclusiveAccess
+                | Self::ExclusiveAccessRegistrantsOnly
+                | Self::ExclusiveAccessAllRegistrants
+        )
+    }
+}
+
+impl SharedState {
+    fn key(&self, host_id: u64) -> Option<u64> {
+        self.registrations
+            .iter()
+            .find(|r| r.host_id == host_id)
+            .map(|r| r.key)
+    }
+
+    /// Fails unless `host_id` is registered with `key`.
+    fn check_key(&self, host_id: u64, key: u64) -> Result<(), DiskError> {
+        if key == 0 || self.key(host_id) != Some(key) {
+            return Err(DiskError::ReservationConflict);
+        }
+        Ok(())
+    }
+
+    fn holds_reservation(&self, host_id: u64) -> bool {
+        self.reservation.as_ref().is_some_and(|r| {
+            if r.reservation_type.all_registrants() {
+                self.key(host_id).is_some()
+            } else {
+                r.holder == host_id
+            }
+        })
+    }
+
+    /// Returns whether `host_id` may read (or write, if `write`) the disk.
+    fn allows(&self, host_id: u64, write: bool) -> bool {
+        let Some(reservation) = &self.reservation else {
+            return true;
+        };
+        if !write && !reservation.reservation_type.exclusive_access() {
+            return true;
+        }
+        match reservation.reservation_type {
+            StoredReservationType::WriteExclusive | StoredReservationType::ExclusiveAccess => {
+                reservation.holder == host_id
+            }
+            _ => self.key(host_id).is_some(),
+        }
+    }
+
+    fn unregister(&mut self, host_id: u64) {
+        let held = self.holds_reservation(host_id);
+        self.registrations.retain(|r| r.host_id != host_id);
+        if let Some(reservation) = &self.reservation {
+            // An all registrants reservation is held until the last
+            // registrant goes away. Other reservations are released when the
+            // holder unregisters.
+            if (reservation.reservation_type.all_registrants() && self.registrations.is_empty())
+                || (!reservation.reservation_type.all_registrants() && held)
+            {
+                self.reservation = None;
+            }
+        }
+    }
+}
+
+fn read_state(mut file: &File) -> io::Result<SharedState> {
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut data)?;
+    if data.is_empty() {
+        return Ok(SharedState::default());
+    }
+    serde_json::from_slice(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_state(mut file: &File, state: &SharedState) -> io::Result<()> {
+    let data = serde_json::to_vec_pretty(state)?;
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&data)?;
+    file.sync_data()
+}
+
+impl DiskIo for DiskWithSharedReservations {
+    fn disk_type(&self) -> &str {
+        "prshared"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.inner.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        self.check_access(true).await?;
+        self.inner.unmap(sector, count, block_level_only).await
+    }
+
+    fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
+        self.inner.unmap_behavior()
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.inner.optimal_unmap_sectors()
+    }
+
+    fn pr(&self) -> Option<&dyn pr::PersistentReservation> {
+        Some(self)
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.check_access(false).await?;
+        self.inner.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        self.check_access(true).await?;
+        self.inner.write_vectored(buffers, sector, fua).await
+    }
+
+    fn sync_cache(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.sync_cache()
+    }
+}
+
+#[async_trait]
+impl pr::PersistentReservation for DiskWithSharedReservations {
+    fn capabilities(&self) -> pr::ReservationCapabilities {
+        pr::ReservationCapabilities {
+            write_exclusive: true,
+            exclusive_access: true,
+            write_exclusive_registrants_only: true,
+            exclusive_access_registrants_only: true,
+            write_exclusive_all_registrants: true,
+            exclusive_access_all_registrants: true,
+            persist_through_power_loss: true,
+        }
+    }
+
+    async fn report(&self) -> Result<pr::ReservationReport, DiskError> {
+        let state = self.state().await?;
+        let report = pr::ReservationReport {
+            generation: state.generation,
+            reservation_type: state
+                .reservation
+                .as_ref()
+                .map(|r| r.reservation_type.into()),
+            persist_through_power_loss: state.persist_through_power_loss,
+            controllers: state
+                .registrations
+                .iter()
+                .map(|r| pr::RegisteredController {
+                    key: r.key,
+                    host_id: r.host_id.to_be_bytes().to_vec(),
+                    controller_id: 0,
+                    holds_reservation: state.holds_reservation(r.host_id),
+                })
+                .collect(),
+        };
+        Ok(report)
+    }
+
+    async fn register(
+        &self,
+        current_key: Option<u64>,
+        new_key: u64,
+        ptpl: Option<bool>,
+    ) -> Result<(), DiskError> {
+        let host_id = self.host_id;
+        self.update(move |state| {
+            if let Some(current_key) = current_key {
+                if state.key(host_id).unwrap_or(0) != current_key {
+                    return Err(DiskError::ReservationConflict);
+                }
+            }
+            if new_key == 0 {
+                state.unregister(host_id);
+            } else if let Some(r) = state
+                .registrations
+                .iter_mut()
+                .find(|r| r.host_id == host_id)
+            {
+                r.key = new_key;
+            } else {
+                state.registrations.push(Registration {
+                    host_id,
+                    key: new_key,
+                });
+            }
+            if let Some(ptpl) = ptpl {
+                state.persist_through_power_loss = ptpl;
+            }
+            state.generation = state.generation.wrapping_add(1);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn reserve(&self, key: u64, reservation_type: ReservationType) -> Result<(), DiskError> {
+        let host_id = self.host_id;
+        let reservation_type = reservation_type.into();
+        self.update(move |state| {
+            state.check_key(host_id, key)?;
+            match &state.reservation {
+                Some(r) => {
+                    if !state.holds_reservation(host_id) || r.reservation_type != reservation_type {
+                        return Err(DiskError::ReservationConflict);
+                    }
+                }
+                None => {
+                    state.reservation = Some(Reservation {
+                        holder: host_id,
+                        reservation_type,
+                    });
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release(&self, key: u64, reservation_type: ReservationType) -> Result<(), DiskError> {
+        let host_id = self.host_id;
+        let reservation_type = reservation_type.into();
+        self.update(move |state| {
+            state.check_key(host_id, key)?;
+            if let Some(r) = &state.reservation {
+                // Releasing a reservation held by someone else is a no-op.
+                if state.holds_reservation(host_id) {
+                    if r.reservation_type != reservation_type {
+                        return Err(DiskError::InvalidInput);
+                    }
+                    state.reservation = None;
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear(&self, key: u64) -> Result<(), DiskError> {
+        let host_id = self.host_id;
+        self.update(move |state| {
+            state.check_key(host_id, key)?;
+            state.registrations.clear();
+            state.reservation = None;
+            state.generation = state.generation.wrapping_add(1);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn preempt(
+        &self,
+        current_key: u64,
+        preempt_key: u64,
+        reservation_type: ReservationType,
+        _abort: bool,
+    ) -> Result<(), DiskError> {
+        let host_id = self.host_id;
+        let reservation_type = reservation_type.into();
+        self.update(move |state| {
+            state.check_key(host_id, current_key)?;
+            let preempts_holder = state.reservation.as_ref().is_some_and(|r| {
+                r.reservation_type.all_registrants() || state.key(r.holder) == Some(preempt_key)
+            });
+            let before = state.registrations.len();
+            state
+                .registrations
+                .retain(|r| r.host_id == host_id || r.key != preempt_key);
+            if preempts_holder {
+                state.reservation = Some(Reservation {
+                    holder: host_id,
+                    reservation_type,
+                });
+            } else if state.registrations.len() == before {
+                return Err(DiskError::ReservationConflict);
+            }
+            state.generation = state.generation.wrapping_add(1);
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use nix::fcntl::FlockArg;
+    use nix::fcntl::flock;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::prelude::*;
+
+    /// A lock on a file, released on drop.
+    pub struct FileLock<'a>(&'a File);
+
+    /// Locks `file`, waiting for any conflicting locks to be released.
+    ///
+    /// The lock is released if the process exits, so a VM that crashes does
+    /// not leave the state file locked.
+    pub fn lock(file: &File, exclusive: bool) -> io::Result<FileLock<'_>> {
+        let arg = if exclusive {
+            FlockArg::LockExclusive
+        } else {
+            FlockArg::LockShared
+        };
+        flock(file.as_raw_fd(), arg)?;
+        Ok(FileLock(file))
+    }
+
+    impl Drop for FileLock<'_> {
+        fn drop(&mut self) {
+            let _ = flock(self.0.as_raw_fd(), FlockArg::Unlock);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    // UNSAFETY: calling LockFileEx and UnlockFileEx.
+    #![expect(unsafe_code)]
+
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::prelude::*;
+    use windows_sys::Win32::Storage::FileSystem::LOCKFILE_EXCLUSIVE_LOCK;
+    use windows_sys::Win32::Storage::FileSystem::LockFileEx;
+    use windows_sys::Win32::Storage::FileSystem::UnlockFileEx;
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    /// A lock on a file, released on drop.
+    pub struct FileLock<'a>(&'a File);
+
+    /// Locks `file`, waiting for any conflicting locks to be released.
+    ///
+    /// The lock is released if the process exits, so a VM that crashes does
+    /// not leave the state file locked.
+    pub fn lock(file: &File, exclusive: bool) -> io::Result<FileLock<'_>> {
+        let flags = if exclusive {
+            LOCKFILE_EXCLUSIVE_LOCK
+        } else {
+            0
+        };
+        // SAFETY: the handle is valid, and the file was opened for
+        // synchronous IO, so the call completes before `overlapped` is
+        // dropped.
+        let r = unsafe {
+            let mut overlapped: OVERLAPPED = std::mem::zeroed();
+            LockFileEx(file.as_raw_handle(), flags, 0, !0, !0, &mut overlapped)
+        };
+        if r == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FileLock(file))
+    }
+
+    impl Drop for FileLock<'_> {
+        fn drop(&mut self) {
+            // SAFETY: the handle is valid, and the lock is held.
+            unsafe {
+                let mut overlapped: OVERLAPPED = std::mem::zeroed();
+                UnlockFileEx(self.0.as_raw_handle(), 0, !0, !0, &mut overlapped);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiskWithSharedReservations;
+    use disk_backend::Disk;
+    use disk_backend::DiskError;
+    use disk_backend::pr::ReservationType;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    async fn write(disk: &Disk) -> Result<(), DiskError> {
+        let mem = GuestMemory::allocate(512);
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, 512, false).buffer(&mem),
+            0,
+            false,
+        )
+        .await
+    }
+
+    #[async_test]
+    async fn test_shared_reservations() {
+        let inner = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let store = tempfile::tempfile().unwrap();
+        let a = Disk::new(DiskWithSharedReservations::new(
+            inner.clone(),
+            store.try_clone().unwrap(),
+            1,
+        ))
+        .unwrap();
+        let b = Disk::new(DiskWithSharedReservations::new(inner, store, 2)).unwrap();
+        let (a_pr, b_pr) = (a.pr().unwrap(), b.pr().unwrap());
+
+        // Both initiators register, and A takes a write exclusive reservation.
+        a_pr.register(None, 0xa, None).await.unwrap();
+        b_pr.register(None, 0xb, None).await.unwrap();
+        a_pr.reserve(0xa, ReservationType::WriteExclusive)
+            .await
+            .unwrap();
+        assert!(matches!(
+            b_pr.reserve(0xb, ReservationType::WriteExclusive).await,
+            Err(DiskError::ReservationConflict)
+        ));
+
+        let report = b_pr.report().await.unwrap();
+        assert_eq!(report.generation, 2);
+        assert_eq!(
+            report.reservation_type,
+            Some(ReservationType::WriteExclusive)
+        );
+        assert_eq!(report.controllers.len(), 2);
+        assert!(report.controllers[0].holds_reservation);
+        assert!(!report.controllers[1].holds_reservation);
+
+        // Only the holder can write.
+        write(&a).await.unwrap();
+        assert!(matches!(
+            write(&b).await,
+            Err(DiskError::ReservationConflict)
+        ));
+
+        // B preempts A, removing A's registration.
+        b_pr.preempt(0xb, 0xa, ReservationType::WriteExclusive, false)
+            .await
+            .unwrap();
+        write(&b).await.unwrap();
+        assert!(matches!(
+            write(&a).await,
+            Err(DiskError::ReservationConflict)
+        ));
+        assert!(matches!(
+            a_pr.reserve(0xa, ReservationType::WriteExclusive).await,
+            Err(DiskError::ReservationConflict)
+        ));
+
+        let report = a_pr.report().await.unwrap();
+        assert_eq!(report.controllers.len(), 1);
+        assert_eq!(report.controllers[0].key, 0xb);
+        assert!(report.controllers[0].holds_reservation);
+
+        // Unregistering releases the reservation.
+        b_pr.register(Some(0xb), 0, None).await.unwrap();
+        let report = a_pr.report().await.unwrap();
+        assert!(report.reservation_type.is_none());
+        assert!(report.controllers.is_empty());
+        write(&a).await.unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk wrapper that presents a different logical and physical sector size
+//! than its backing disk, for emulating 4Kn and 512e drives on any backing
+//! store.
+
+#![forbid(unsafe_code)]
+
+/// Provides a disk with an overridden sector size.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use inspect::Inspect;
+use scsi_buffers::RequestBuffers;
+use thiserror::Error;
+
+/// A disk that presents a logical sector size that is a multiple of the inner
+/// disk's, and an arbitrary physical sector size.
+#[derive(Inspect)]
+pub struct SectorSizeDisk {
+    inner: Disk,
+    sector_size: u32,
+    physical_sector_size: u32,
+    /// The log2 of the number of inner sectors per outer sector.
+    shift: u32,
+}
+
+/// An error returned by [`SectorSizeDisk::new`].
+#[derive(Debug, Error)]
+pub enum NewSectorSizeDiskError {
+    /// The sector size is not a power of two of at least 512 bytes.
+    #[error("invalid sector size {0}")]
+    InvalidSectorSize(u32),
+    /// The sector size is smaller than the backing disk's sector size.
+    #[error("sector size {sector_size} is smaller than the backing disk's sector size {inner}")]
+    SmallerThanInner {
+        /// The requested sector size.
+        sector_size: u32,
+        /// The backing disk's sector size.
+        inner: u32,
+    },
+    /// The physical sector size is not a power of two of at least the logical
+    /// sector size.
+    #[error("invalid physical sector size {0}")]
+    InvalidPhysicalSectorSize(u32),
+}
+
+impl SectorSizeDisk {
+    /// Creates a new disk wrapping `inner`.
+    ///
+    /// If `sector_size` is `None`, the inner disk's logical sector size is
+    /// used. If `physical_sector_size` is `None`, the larger of the logical
+    /// sector size and the inner disk's physical sector size is used.
+    pub fn new(
+        inner: Disk,
+        sector_size: Option<u32>,
+        physical_sector_size: Option<u32>,
+    ) -> Result<Self, NewSectorSizeDiskError> {
+        let sector_size = sector_size.unwrap_or(inner.sector_size());
+        if !sector_size.is_power_of_two() || sector_size < 512 {
+            return Err(NewSectorSizeDiskError::InvalidSectorSize(sector_size));
+        }
+        if sector_size < inner.sector_size() {
+            // FUTURE: support this with read-modify-write.
+            return Err(NewSectorSizeDiskError::SmallerThanInner {
+                sector_size,
+                inner: inner.sector_size(),
+            });
+        }
+        let physical_sector_size =
+            physical_sector_size.unwrap_or(inner.physical_sector_size().max(sector_size));
+        if !physical_sector_size.is_power_of_two() || physical_sector_size < sector_size {
+            return Err(NewSectorSizeDiskError::InvalidPhysicalSectorSize(
+                physical_sector_size,
+            ));
+        }
+        let shift = sector_size.trailing_zeros() - inner.sector_shift();
+        Ok(Self {
+            inner,
+            sector_size,
+            physical_sector_size,
+            shift,
+        })
+    }
+}
+
+impl DiskIo for SectorSizeDisk {
+    fn disk_type(&self) -> &str {
+        "sector_size"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count() >> self.shift
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.physical_sector_size
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.inner.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn pr(&self) -> Option<&dyn disk_backend::pr::PersistentReservation> {
+        self.inner.pr()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.inner
+            .read_vectored(buffers, sector << self.shift)
+            .await
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        self.inner
+            .write_vectored(buffers, sector << self.shift, fua)
+            .await
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.inner.sync_cache().await
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.inner.wait_resize(sector_count << self.shift).await >> self.shift
+    }
+
+    fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> impl std::future::Future<Output = Result<(), DiskError>> + Send {
+        self.inner
+            .unmap(sector << self.shift, count << self.shift, block_level_only)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        self.inner.unmap_behavior()
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        (self.inner.optimal_unmap_sectors() >> self.shift).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SectorSizeDisk;
+    use disk_backend::Disk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    #[async_test]
+    async fn emulate_4kn() {
+        let mem = GuestMemory::allocate(4096);
+        let inner = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let disk =
+            Disk::new(SectorSizeDisk::new(inner.clone(), Some(4096), None).unwrap()).unwrap();
+        assert_eq!(disk.sector_size(), 4096);
+        assert_eq!(disk.physical_sector_size(), 4096);
+        assert_eq!(disk.sector_count(), 0x100000 / 4096);
+
+        mem.write_at(0, &[0xa5; 4096]).unwrap();
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, 4096, false).buffer(&mem),
+            2,
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The write landed at the corresponding 512-byte sectors of the inner
+        // disk.
+        mem.write_at(0, &[0; 4096]).unwrap();
+        inner
+            .read_vectored(&OwnedRequestBuffers::linear(0, 4096, true).buffer(&mem), 16)
+            .await
+            .unwrap();
+        let mut buf = [0; 4096];
+        mem.read_at(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0xa5));
+    }
+
+    #[test]
+    fn emulate_512e() {
+        let inner = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let disk = Disk::new(SectorSizeDisk::new(inner, None, Some(4096)).unwrap()).unwrap();
+        assert_eq!(disk.sector_size(), 512);
+        assert_eq!(disk.physical_sector_size(), 4096);
+        assert_eq!(disk.sector_count(), 0x100000 / 512);
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        let inner = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        assert!(SectorSizeDisk::new(inner.clone(), Some(1000), None).is_err());
+        assert!(SectorSizeDisk::new(inner.clone(), Some(256), None).is_err());
+        assert!(SectorSizeDisk::new(inner, Some(4096), Some(512)).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::SectorSizeDisk;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::SectorSizeDiskHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for SectorSizeDisk.
+pub struct SectorSizeDiskResolver;
+declare_static_async_resolver!(
+    SectorSizeDiskResolver,
+    (DiskHandleKind, SectorSizeDiskHandle)
+);
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, SectorSizeDiskHandle> for SectorSizeDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: SectorSizeDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver.resolve(rsrc.disk, input).await?;
+
+        let disk = SectorSizeDisk::new(inner.0, rsrc.sector_size, rsrc.physical_sector_size)?;
+        ResolvedDisk::new(disk)
+            .map_err(|e| anyhow::anyhow!("failed to create the sector size disk: {}", e))
+    }
+}
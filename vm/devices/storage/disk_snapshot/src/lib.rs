@@ -0,0 +1,378 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk wrapper that supports taking snapshots of a disk while it is in use.
+//!
+//! Taking a snapshot quiesces I/O to the disk, freezes the disk's current
+//! contents, and redirects all subsequent I/O to a new diff layer stacked on
+//! top of the frozen disk. The frozen disk's backing store can then be copied
+//! without stopping the VM.
+
+#![forbid(unsafe_code)]
+
+/// Provides a disk that can be snapshotted at runtime.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::InvalidDisk;
+use disk_backend::UnmapBehavior;
+use disk_layered::DiskLayer;
+use disk_layered::InvalidLayeredDisk;
+use disk_layered::LayerConfiguration;
+use disk_layered::LayeredDisk;
+use event_listener::Event;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// A disk whose I/O can be redirected to a new diff layer at runtime.
+#[derive(Clone, Inspect)]
+pub struct SnapshotDisk {
+    #[inspect(flatten)]
+    inner: Arc<Inner>,
+}
+
+#[derive(Inspect)]
+struct Inner {
+    /// The original disk, used for metadata that must not change across
+    /// snapshots.
+    base: Disk,
+    #[inspect(flatten)]
+    state: Mutex<SnapshotState>,
+    /// Signaled when `in_flight` drops to zero or when `quiescing` is cleared.
+    #[inspect(skip)]
+    event: Event,
+}
+
+#[derive(Inspect)]
+struct SnapshotState {
+    current: Disk,
+    snapshot_count: u64,
+    in_flight: usize,
+    quiescing: bool,
+}
+
+/// An error returned by [`SnapshotDisk::snapshot`].
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    /// The disk is read-only.
+    #[error("cannot snapshot a read-only disk")]
+    ReadOnly,
+    /// Flushing the disk before freezing it failed.
+    #[error("failed to flush disk")]
+    Flush(#[source] DiskError),
+    /// The diff layer could not be stacked on the frozen disk.
+    #[error("failed to attach diff layer")]
+    Layer(#[source] InvalidLayeredDisk),
+    /// The new disk could not be created.
+    #[error("invalid disk")]
+    InvalidDisk(#[source] InvalidDisk),
+}
+
+impl SnapshotDisk {
+    /// Creates a new snapshot disk wrapping `disk`.
+    pub fn new(disk: Disk) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                base: disk.clone(),
+                state: Mutex::new(SnapshotState {
+                    current: disk,
+                    snapshot_count: 0,
+                    in_flight: 0,
+                    quiescing: false,
+                }),
+                event: Event::new(),
+            }),
+        }
+    }
+
+    /// Takes a snapshot of the disk.
+    ///
+    /// Waits for in-flight I/O to complete while holding off new I/O, flushes
+    /// the disk, and then stacks `layer` on top of the disk's current
+    /// contents. All subsequent I/O goes to the new layered disk, so the
+    /// previous contents remain unchanged until the disk is released.
+    ///
+    /// Returns the number of snapshots that have been taken of this disk.
+    pub async fn snapshot(&self, layer: DiskLayer) -> Result<u64, SnapshotError> {
+        if self.inner.base.is_read_only() {
+            return Err(SnapshotError::ReadOnly);
+        }
+
+        let quiesced = self.quiesce().await;
+        let disk = async {
+            quiesced
+                .current
+                .sync_cache()
+                .await
+                .map_err(SnapshotError::Flush)?;
+
+            let disk = LayeredDisk::new(
+                false,
+                vec![
+                    LayerConfiguration {
+                        layer,
+                        write_through: false,
+                        read_cache: false,
+                    },
+                    LayerConfiguration {
+                        layer: DiskLayer::from_disk(quiesced.current.clone()),
+                        write_through: false,
+                        read_cache: false,
+                    },
+                ],
+            )
+            .await
+            .map_err(SnapshotError::Layer)?;
+
+            Disk::new(disk).map_err(SnapshotError::InvalidDisk)
+        }
+        .await?;
+
+        let mut state = self.inner.state.lock();
+        state.current = disk;
+        state.snapshot_count += 1;
+        Ok(state.snapshot_count)
+    }
+
+    /// Blocks new I/O and waits for in-flight I/O to drain.
+    ///
+    /// I/O resumes when the returned guard is dropped.
+    async fn quiesce(&self) -> Quiesced<'_> {
+        let current = loop {
+            let listener = {
+                let mut state = self.inner.state.lock();
+                state.quiescing = true;
+                if state.in_flight == 0 {
+                    break state.current.clone();
+                }
+                self.inner.event.listen()
+            };
+            listener.await;
+        };
+        Quiesced {
+            inner: &self.inner,
+            current,
+        }
+    }
+
+    /// Waits until I/O is allowed, then returns the disk to issue it to.
+    async fn enter(&self) -> ActiveIo<'_> {
+        loop {
+            let listener = {
+                let mut state = self.inner.state.lock();
+                if !state.quiescing {
+                    state.in_flight += 1;
+                    return ActiveIo {
+                        inner: &self.inner,
+                        disk: state.current.clone(),
+                    };
+                }
+                self.inner.event.listen()
+            };
+            listener.await;
+        }
+    }
+
+    fn current(&self) -> Disk {
+        self.inner.state.lock().current.clone()
+    }
+}
+
+/// Guard for an I/O issued while the disk is not quiescing.
+struct ActiveIo<'a> {
+    inner: &'a Inner,
+    disk: Disk,
+}
+
+impl Drop for ActiveIo<'_> {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.lock();
+        state.in_flight -= 1;
+        if state.in_flight == 0 && state.quiescing {
+            self.inner.event.notify(usize::MAX);
+        }
+    }
+}
+
+/// Guard held while I/O is quiesced for a snapshot.
+struct Quiesced<'a> {
+    inner: &'a Inner,
+    current: Disk,
+}
+
+impl Drop for Quiesced<'_> {
+    fn drop(&mut self) {
+        self.inner.state.lock().quiescing = false;
+        self.inner.event.notify(usize::MAX);
+    }
+}
+
+impl DiskIo for SnapshotDisk {
+    fn disk_type(&self) -> &str {
+        "snapshot"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.current().sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.base.sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.base.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.base.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.inner.base.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.base.is_read_only()
+    }
+
+    fn pr(&self) -> Option<&dyn disk_backend::pr::PersistentReservation> {
+        self.inner.base.pr()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let io = self.enter().await;
+        io.disk.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        let io = self.enter().await;
+        io.disk.write_vectored(buffers, sector, fua).await
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        let io = self.enter().await;
+        io.disk.sync_cache().await
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.current().wait_resize(sector_count).await
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        let io = self.enter().await;
+        io.disk.unmap(sector, count, block_level_only).await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        // Once a diff layer is stacked on top, unmapped sectors may read back
+        // from the frozen disk rather than as zeroes.
+        match self.inner.base.unmap_behavior() {
+            UnmapBehavior::Zeroes => UnmapBehavior::Unspecified,
+            behavior => behavior,
+        }
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.inner.base.optimal_unmap_sectors()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SnapshotDisk;
+    use disk_backend::Disk;
+    use disk_layered::DiskLayer;
+    use disklayer_ram::RamDiskLayer;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    const SECTOR_SIZE: usize = 512;
+
+    async fn write(mem: &GuestMemory, disk: &Disk, sector: u64, value: u8) {
+        mem.write_at(0, &[value; SECTOR_SIZE]).unwrap();
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, SECTOR_SIZE, false).buffer(mem),
+            sector,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn read(mem: &GuestMemory, disk: &Disk, sector: u64) -> u8 {
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, SECTOR_SIZE, true).buffer(mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        let mut buf = [0; SECTOR_SIZE];
+        mem.read_at(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == buf[0]));
+        buf[0]
+    }
+
+    #[async_test]
+    async fn snapshot_freezes_contents() {
+        let mem = GuestMemory::allocate(SECTOR_SIZE);
+        let base = disklayer_ram::ram_disk(0x10000, false).unwrap();
+        let snapshot = SnapshotDisk::new(base.clone());
+        let disk = Disk::new(snapshot.clone()).unwrap();
+
+        write(&mem, &disk, 1, 1).await;
+        write(&mem, &disk, 2, 1).await;
+
+        let count = snapshot
+            .snapshot(DiskLayer::new(RamDiskLayer::new(0x10000).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+
+        write(&mem, &disk, 2, 2).await;
+        assert_eq!(read(&mem, &disk, 1).await, 1);
+        assert_eq!(read(&mem, &disk, 2).await, 2);
+
+        // The base disk still has the contents from the time of the snapshot.
+        assert_eq!(read(&mem, &base, 1).await, 1);
+        assert_eq!(read(&mem, &base, 2).await, 1);
+
+        let count = snapshot
+            .snapshot(DiskLayer::new(RamDiskLayer::new(0x10000).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        write(&mem, &disk, 1, 3).await;
+        assert_eq!(read(&mem, &disk, 1).await, 3);
+        assert_eq!(read(&mem, &disk, 2).await, 2);
+        assert_eq!(read(&mem, &base, 1).await, 1);
+    }
+
+    #[async_test]
+    async fn snapshot_read_only() {
+        let disk = SnapshotDisk::new(disklayer_ram::ram_disk(0x10000, true).unwrap());
+        disk.snapshot(DiskLayer::new(RamDiskLayer::new(0x10000).unwrap()))
+            .await
+            .unwrap_err();
+    }
+}
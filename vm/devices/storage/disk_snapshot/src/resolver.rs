@@ -0,0 +1,86 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::SnapshotDisk;
+use anyhow::Context;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::SnapshotDiskHandle;
+use disk_backend_resources::SnapshotDiskRequest;
+use disk_layered::resolve::ResolveDiskLayerParameters;
+use futures::StreamExt;
+use pal_async::task::Spawn;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// A resolver for SnapshotDisk.
+pub struct SnapshotDiskResolver;
+declare_static_async_resolver!(SnapshotDiskResolver, (DiskHandleKind, SnapshotDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, SnapshotDiskHandle> for SnapshotDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: SnapshotDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver.resolve(rsrc.disk, input).await?;
+        let disk = SnapshotDisk::new(inner.0);
+
+        input
+            .driver_source
+            .simple()
+            .spawn(
+                "disk-snapshot-requests",
+                handle_requests(
+                    input.driver_source.clone(),
+                    disk.clone(),
+                    resolver.clone(),
+                    rsrc.requests,
+                ),
+            )
+            .detach();
+
+        ResolvedDisk::new(disk)
+            .map_err(|e| anyhow::anyhow!("failed to create the snapshot disk: {}", e))
+    }
+}
+
+async fn handle_requests(
+    driver_source: VmTaskDriverSource,
+    disk: SnapshotDisk,
+    resolver: ResourceResolver,
+    mut requests: mesh::Receiver<SnapshotDiskRequest>,
+) {
+    while let Some(req) = requests.next().await {
+        match req {
+            SnapshotDiskRequest::Snapshot(rpc) => {
+                rpc.handle_failable(async |layer| {
+                    let layer = resolver
+                        .resolve(
+                            layer,
+                            ResolveDiskLayerParameters {
+                                read_only: false,
+                                driver_source: &driver_source,
+                            },
+                        )
+                        .await
+                        .context("failed to resolve diff layer")?;
+
+                    disk.snapshot(layer.0)
+                        .await
+                        .context("failed to snapshot disk")
+                })
+                .await
+            }
+        }
+    }
+}
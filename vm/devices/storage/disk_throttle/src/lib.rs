@@ -0,0 +1,247 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk device wrapper that limits the rate of I/O operations and the
+//! bandwidth of a disk.
+
+#![forbid(unsafe_code)]
+
+/// Provides a disk with throttled I/O.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use inspect::Inspect;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::time::Duration;
+use std::time::Instant;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// A disk that limits the rate of I/O issued to an inner disk.
+#[derive(Inspect)]
+pub struct ThrottleDisk {
+    inner: Disk,
+    #[inspect(flatten)]
+    state: Mutex<ThrottleState>,
+    #[inspect(skip)]
+    driver: VmTaskDriver,
+}
+
+#[derive(Inspect)]
+struct ThrottleState {
+    iops: Option<TokenBucket>,
+    bandwidth: Option<TokenBucket>,
+}
+
+impl ThrottleDisk {
+    /// Creates a new disk that limits I/O to `inner` to `iops` operations per
+    /// second and `bytes_per_second` bytes per second.
+    ///
+    /// Up to `burst` operations (default: one second's worth) can be issued
+    /// at once after the disk has been idle. Bandwidth can always burst up to
+    /// one second's worth of data.
+    pub fn new(
+        inner: Disk,
+        iops: Option<u64>,
+        bytes_per_second: Option<u64>,
+        burst: Option<u64>,
+        driver_source: &VmTaskDriverSource,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            inner,
+            state: Mutex::new(ThrottleState {
+                iops: iops.map(|iops| TokenBucket::new(iops, burst.unwrap_or(iops), now)),
+                bandwidth: bytes_per_second.map(|bps| TokenBucket::new(bps, bps, now)),
+            }),
+            driver: driver_source.simple(),
+        }
+    }
+
+    /// Waits until an I/O of `len` bytes can be issued.
+    async fn throttle(&self, len: usize) {
+        let delay = {
+            let mut state = self.state.lock();
+            let now = Instant::now();
+            let iops_delay = state
+                .iops
+                .as_mut()
+                .map_or(Duration::ZERO, |b| b.reserve(now, 1));
+            let bandwidth_delay = state
+                .bandwidth
+                .as_mut()
+                .map_or(Duration::ZERO, |b| b.reserve(now, len as u64));
+            iops_delay.max(bandwidth_delay)
+        };
+        if !delay.is_zero() {
+            PolledTimer::new(&self.driver).sleep(delay).await;
+        }
+    }
+}
+
+/// A token bucket that refills at a fixed rate.
+///
+/// Reservations can take the bucket negative. The caller then waits until the
+/// debt has been repaid, which keeps requests in order and allows requests
+/// larger than the bucket's capacity.
+#[derive(Inspect)]
+struct TokenBucket {
+    rate: u64,
+    capacity: u64,
+    tokens: f64,
+    #[inspect(skip)]
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, capacity: u64, now: Instant) -> Self {
+        let rate = rate.max(1);
+        let capacity = capacity.max(1);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity as f64,
+            last: now,
+        }
+    }
+
+    /// Takes `cost` tokens from the bucket, returning how long the caller must
+    /// wait before proceeding.
+    fn reserve(&mut self, now: Instant, cost: u64) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate as f64)
+            .min(self.capacity as f64)
+            - cost as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+impl DiskIo for ThrottleDisk {
+    fn disk_type(&self) -> &str {
+        "throttle"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.inner.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn pr(&self) -> Option<&dyn disk_backend::pr::PersistentReservation> {
+        self.inner.pr()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.throttle(buffers.len()).await;
+        self.inner.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        self.throttle(buffers.len()).await;
+        self.inner.write_vectored(buffers, sector, fua).await
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.inner.sync_cache().await
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.inner.wait_resize(sector_count).await
+    }
+
+    fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> impl std::future::Future<Output = Result<(), DiskError>> + Send {
+        self.inner.unmap(sector, count, block_level_only)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        self.inner.unmap_behavior()
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.inner.optimal_unmap_sectors()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenBucket;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    fn assert_near(actual: Duration, expected: Duration) {
+        assert!(
+            actual.abs_diff(expected) < Duration::from_micros(1),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn burst_then_throttle() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 5, start);
+        for _ in 0..5 {
+            assert_eq!(bucket.reserve(start, 1), Duration::ZERO);
+        }
+        assert_near(bucket.reserve(start, 1), Duration::from_millis(100));
+        assert_near(bucket.reserve(start, 1), Duration::from_millis(200));
+
+        // After two seconds, the debt is repaid and the bucket has refilled to
+        // its capacity.
+        let later = start + Duration::from_secs(2);
+        for _ in 0..5 {
+            assert_eq!(bucket.reserve(later, 1), Duration::ZERO);
+        }
+        assert!(bucket.reserve(later, 1) > Duration::ZERO);
+    }
+
+    #[test]
+    fn large_request() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, 1000, start);
+        assert_near(bucket.reserve(start, 3000), Duration::from_secs(2));
+        let later = start + Duration::from_secs(1);
+        assert_near(bucket.reserve(later, 0), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,551 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A read-only disk wrapper that verifies every read against a dm-verity
+//! hash tree, failing the I/O if the data does not match.
+//!
+//! The hash tree must be in the format produced by `veritysetup format`,
+//! with the superblock at offset zero of the hash tree disk.
+
+#![forbid(unsafe_code)]
+
+/// Provides a disk verified against a hash tree.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::MediumErrorDetails;
+use disk_backend::UnmapBehavior;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_buffers::RequestBuffers;
+use sha2::Digest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::KnownLayout;
+use zerocopy::LE;
+use zerocopy::U16;
+use zerocopy::U32;
+use zerocopy::U64;
+
+const SIGNATURE: [u8; 8] = *b"verity\0\0";
+const SUPERBLOCK_SIZE: usize = 512;
+
+/// The maximum number of verified hash blocks to cache.
+const MAX_CACHED_HASH_BLOCKS: usize = 1024;
+
+/// The veritysetup superblock.
+#[repr(C)]
+#[derive(FromBytes, Immutable, KnownLayout)]
+struct Superblock {
+    signature: [u8; 8],
+    version: U32<LE>,
+    hash_type: U32<LE>,
+    _uuid: [u8; 16],
+    algorithm: [u8; 32],
+    data_block_size: U32<LE>,
+    hash_block_size: U32<LE>,
+    data_blocks: U64<LE>,
+    salt_size: U16<LE>,
+    _pad1: [u8; 6],
+    salt: [u8; 256],
+    _pad2: [u8; 168],
+}
+
+const _: () = assert!(size_of::<Superblock>() == SUPERBLOCK_SIZE);
+
+/// A disk whose reads are verified against a hash tree.
+#[derive(Inspect)]
+pub struct VerityDisk {
+    inner: Disk,
+    hash_tree: Disk,
+    #[inspect(skip)]
+    root_hash: Vec<u8>,
+    #[inspect(debug)]
+    algorithm: HashAlgorithm,
+    /// Whether the salt is hashed before (version 1) or after (version 0,
+    /// Chrome OS) the data.
+    salt_first: bool,
+    #[inspect(skip)]
+    salt: Vec<u8>,
+    data_block_size: u32,
+    hash_block_size: u32,
+    data_blocks: u64,
+    /// The log2 of the number of hashes in each hash block.
+    hashes_per_block_shift: u32,
+    /// The index of the first hash block of each level of the tree, starting
+    /// with the level that contains the data block hashes.
+    #[inspect(skip)]
+    level_start: Vec<u64>,
+    /// Hash blocks that have already been verified.
+    #[inspect(with = "|x| x.lock().len()")]
+    verified: Mutex<HashMap<u64, Arc<[u8]>>>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn digest_size(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+}
+
+/// An error returned by [`VerityDisk::new`].
+#[derive(Debug, Error)]
+pub enum NewVerityDiskError {
+    /// Failed to read the superblock.
+    #[error("failed to read the hash tree superblock")]
+    Io(#[source] DiskError),
+    /// The superblock is invalid.
+    #[error("invalid hash tree superblock: {0}")]
+    InvalidSuperblock(&'static str),
+    /// The hash algorithm is not supported.
+    #[error("unsupported hash algorithm {0:?}")]
+    UnsupportedAlgorithm(String),
+    /// The root hash is the wrong size for the hash algorithm.
+    #[error("root hash is {len} bytes, expected {expected}")]
+    InvalidRootHash {
+        /// The root hash length.
+        len: usize,
+        /// The digest size of the hash algorithm.
+        expected: usize,
+    },
+    /// The data disk is smaller than the hashed data.
+    #[error("data disk is smaller than the hashed data")]
+    DataTooSmall,
+}
+
+impl VerityDisk {
+    /// Creates a new disk wrapping `inner`, verified against the hash tree
+    /// in `hash_tree` with root hash `root_hash`.
+    pub async fn new(
+        inner: Disk,
+        hash_tree: Disk,
+        root_hash: &[u8],
+    ) -> Result<Self, NewVerityDiskError> {
+        let buf = read(
+            &hash_tree,
+            0,
+            SUPERBLOCK_SIZE.max(hash_tree.sector_size() as usize),
+        )
+        .await
+        .map_err(NewVerityDiskError::Io)?;
+        let (sb, _) = Superblock::read_from_prefix(&buf).unwrap();
+        if sb.signature != SIGNATURE {
+            return Err(NewVerityDiskError::InvalidSuperblock("bad signature"));
+        }
+        if sb.version.get() != 1 {
+            return Err(NewVerityDiskError::InvalidSuperblock("unknown version"));
+        }
+        let salt_first = match sb.hash_type.get() {
+            0 => false,
+            1 => true,
+            _ => return Err(NewVerityDiskError::InvalidSuperblock("unknown hash type")),
+        };
+        let algorithm = sb.algorithm.split(|&b| b == 0).next().unwrap();
+        let algorithm = match algorithm {
+            b"sha256" => HashAlgorithm::Sha256,
+            b"sha512" => HashAlgorithm::Sha512,
+            _ => {
+                return Err(NewVerityDiskError::UnsupportedAlgorithm(
+                    String::from_utf8_lossy(algorithm).into_owned(),
+                ));
+            }
+        };
+        if root_hash.len() != algorithm.digest_size() {
+            return Err(NewVerityDiskError::InvalidRootHash {
+                len: root_hash.len(),
+                expected: algorithm.digest_size(),
+            });
+        }
+
+        let data_block_size = sb.data_block_size.get();
+        let hash_block_size = sb.hash_block_size.get();
+        if !data_block_size.is_power_of_two() || data_block_size < inner.sector_size() {
+            return Err(NewVerityDiskError::InvalidSuperblock("data block size"));
+        }
+        if !hash_block_size.is_power_of_two()
+            || hash_block_size < hash_tree.sector_size()
+            || (hash_block_size as usize) < algorithm.digest_size()
+        {
+            return Err(NewVerityDiskError::InvalidSuperblock("hash block size"));
+        }
+        let salt = sb
+            .salt
+            .get(..sb.salt_size.get() as usize)
+            .ok_or(NewVerityDiskError::InvalidSuperblock("salt size"))?
+            .to_vec();
+
+        let data_blocks = sb.data_blocks.get();
+        let data_len = data_blocks
+            .checked_mul(data_block_size.into())
+            .ok_or(NewVerityDiskError::InvalidSuperblock("data block count"))?;
+        if data_len > inner.sector_count() << inner.sector_shift() {
+            return Err(NewVerityDiskError::DataTooSmall);
+        }
+
+        // Compute the tree layout as dm-verity does. The tree follows the
+        // superblock, starting with the topmost level.
+        let hashes_per_block_shift = (hash_block_size as usize / algorithm.digest_size()).ilog2();
+        let mut levels = 0;
+        if data_blocks != 0 {
+            while hashes_per_block_shift * levels < 64
+                && (data_blocks - 1) >> (hashes_per_block_shift * levels) != 0
+            {
+                levels += 1;
+            }
+        }
+        let mut position = SUPERBLOCK_SIZE.div_ceil(hash_block_size as usize) as u64;
+        let mut level_start = vec![0; levels as usize];
+        for (i, start) in level_start.iter_mut().enumerate().rev() {
+            *start = position;
+            let shift = hashes_per_block_shift * (i as u32 + 1);
+            position += data_blocks.div_ceil(1u64.checked_shl(shift).unwrap_or(u64::MAX));
+        }
+
+        Ok(Self {
+            inner,
+            hash_tree,
+            root_hash: root_hash.to_vec(),
+            algorithm,
+            salt_first,
+            salt,
+            data_block_size,
+            hash_block_size,
+            data_blocks,
+            hashes_per_block_shift,
+            level_start,
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        fn hash<D: Digest>(salt: &[u8], salt_first: bool, data: &[u8]) -> Vec<u8> {
+            let mut hasher = D::new();
+            if salt_first {
+                hasher.update(salt);
+                hasher.update(data);
+            } else {
+                hasher.update(data);
+                hasher.update(salt);
+            }
+            hasher.finalize().to_vec()
+        }
+        match self.algorithm {
+            HashAlgorithm::Sha256 => hash::<sha2::Sha256>(&self.salt, self.salt_first, data),
+            HashAlgorithm::Sha512 => hash::<sha2::Sha512>(&self.salt, self.salt_first, data),
+        }
+    }
+
+    /// Returns the hash block at `index`, verifying it against `expected` if
+    /// it has not been verified already.
+    async fn hash_block(&self, index: u64, expected: &[u8]) -> Result<Arc<[u8]>, DiskError> {
+        if let Some(block) = self.verified.lock().get(&index) {
+            return Ok(block.clone());
+        }
+        let block = read(
+            &self.hash_tree,
+            index * self.hash_block_size as u64,
+            self.hash_block_size as usize,
+        )
+        .await?;
+        if self.hash(&block) != expected {
+            tracelimit::error_ratelimited!(index, "verity hash block mismatch");
+            return Err(verification_error());
+        }
+        let block: Arc<[u8]> = block.into();
+        let mut verified = self.verified.lock();
+        if verified.len() >= MAX_CACHED_HASH_BLOCKS {
+            verified.clear();
+        }
+        verified.insert(index, block.clone());
+        Ok(block)
+    }
+
+    /// Verifies the contents of data block `index`.
+    async fn verify_data_block(&self, index: u64, data: &[u8]) -> Result<(), DiskError> {
+        let digest_size = self.algorithm.digest_size();
+        let mask = (1 << self.hashes_per_block_shift) - 1;
+
+        // Walk the tree from the root, verifying each hash block against the
+        // hash in its parent.
+        let mut expected = self.root_hash.clone();
+        for (level, &start) in self.level_start.iter().enumerate().rev() {
+            let level = level as u32;
+            let block = self
+                .hash_block(
+                    start + (index >> (self.hashes_per_block_shift * (level + 1))),
+                    &expected,
+                )
+                .await?;
+            let offset =
+                ((index >> (self.hashes_per_block_shift * level)) & mask) as usize * digest_size;
+            expected = block[offset..][..digest_size].to_vec();
+        }
+
+        if self.hash(data) != expected {
+            tracelimit::error_ratelimited!(index, "verity data block mismatch");
+            return Err(verification_error());
+        }
+        Ok(())
+    }
+}
+
+/// Reads `len` bytes at byte offset `offset`, which must both be aligned to
+/// the disk's sector size.
+async fn read(disk: &Disk, offset: u64, len: usize) -> Result<Vec<u8>, DiskError> {
+    let mut mem = GuestMemory::allocate(len);
+    disk.read_vectored(
+        &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+        offset >> disk.sector_shift(),
+    )
+    .await?;
+    Ok(mem.inner_buf_mut().unwrap().to_vec())
+}
+
+fn verification_error() -> DiskError {
+    DiskError::MediumError(
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "verity hash mismatch"),
+        MediumErrorDetails::UnrecoveredReadError,
+    )
+}
+
+impl DiskIo for VerityDisk {
+    fn disk_type(&self) -> &str {
+        "verity"
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data_blocks * self.data_block_size as u64) >> self.inner.sector_shift()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.inner.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let start = sector << self.inner.sector_shift();
+        let end = start + buffers.len() as u64;
+        if end > self.data_blocks * self.data_block_size as u64 {
+            return Err(DiskError::IllegalBlock);
+        }
+
+        // Read whole data blocks into a bounce buffer, so that the guest never
+        // sees unverified data.
+        let block_size = self.data_block_size as u64;
+        let first_block = start / block_size;
+        let block_count = end.div_ceil(block_size) - first_block;
+        let data = read(
+            &self.inner,
+            first_block * block_size,
+            (block_count * block_size) as usize,
+        )
+        .await?;
+
+        for (i, block) in data.chunks_exact(block_size as usize).enumerate() {
+            self.verify_data_block(first_block + i as u64, block)
+                .await?;
+        }
+
+        buffers
+            .writer()
+            .write(&data[(start - first_block * block_size) as usize..][..buffers.len()])?;
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        _buffers: &RequestBuffers<'_>,
+        _sector: u64,
+        _fua: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        Ok(())
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SUPERBLOCK_SIZE;
+    use super::VerityDisk;
+    use disk_backend::Disk;
+    use disk_backend::DiskError;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use sha2::Digest;
+
+    const DATA_BLOCK_SIZE: usize = 4096;
+    const HASH_BLOCK_SIZE: usize = 512;
+    const SALT: &[u8] = b"salt";
+
+    fn hash(data: &[u8]) -> Vec<u8> {
+        sha2::Sha256::new()
+            .chain_update(SALT)
+            .chain_update(data)
+            .finalize()
+            .to_vec()
+    }
+
+    /// Builds a hash tree in veritysetup format for `data`, returning the
+    /// tree and the root hash.
+    fn build_tree(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut hashes = data.chunks(DATA_BLOCK_SIZE).map(hash).collect::<Vec<_>>();
+        let mut levels = Vec::new();
+        while hashes.len() > 1 {
+            let blocks = hashes
+                .chunks(HASH_BLOCK_SIZE / 32)
+                .map(|hashes| {
+                    let mut block = hashes.concat();
+                    block.resize(HASH_BLOCK_SIZE, 0);
+                    block
+                })
+                .collect::<Vec<_>>();
+            hashes = blocks.iter().map(|block| hash(block)).collect();
+            levels.push(blocks.concat());
+        }
+
+        let mut tree = vec![0; SUPERBLOCK_SIZE];
+        tree[..8].copy_from_slice(b"verity\0\0");
+        tree[8..12].copy_from_slice(&1u32.to_le_bytes());
+        tree[12..16].copy_from_slice(&1u32.to_le_bytes());
+        tree[32..38].copy_from_slice(b"sha256");
+        tree[64..68].copy_from_slice(&(DATA_BLOCK_SIZE as u32).to_le_bytes());
+        tree[68..72].copy_from_slice(&(HASH_BLOCK_SIZE as u32).to_le_bytes());
+        tree[72..80].copy_from_slice(&((data.len() / DATA_BLOCK_SIZE) as u64).to_le_bytes());
+        tree[80..82].copy_from_slice(&(SALT.len() as u16).to_le_bytes());
+        tree[88..][..SALT.len()].copy_from_slice(SALT);
+        for level in levels.iter().rev() {
+            tree.extend(level);
+        }
+        (tree, hashes.pop().unwrap())
+    }
+
+    async fn write(disk: &Disk, data: &[u8]) {
+        let mut mem = GuestMemory::allocate(data.len());
+        mem.inner_buf_mut().unwrap().copy_from_slice(data);
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, data.len(), false).buffer(&mem),
+            0,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn read(disk: &Disk, sector: u64, len: usize) -> Result<Vec<u8>, DiskError> {
+        let mut mem = GuestMemory::allocate(len);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+            sector,
+        )
+        .await?;
+        Ok(mem.inner_buf_mut().unwrap().to_vec())
+    }
+
+    #[async_test]
+    async fn verify_reads() {
+        // 64 data blocks with 16 hashes per hash block gives a two-level tree.
+        let data = (0..64 * DATA_BLOCK_SIZE)
+            .map(|i| (i / 7) as u8)
+            .collect::<Vec<_>>();
+        let (tree, root_hash) = build_tree(&data);
+
+        let inner = disklayer_ram::ram_disk(data.len() as u64, false).unwrap();
+        write(&inner, &data).await;
+        let hash_tree = disklayer_ram::ram_disk(0x10000, false).unwrap();
+        let mut padded = tree.clone();
+        padded.resize(0x10000, 0);
+        write(&hash_tree, &padded).await;
+
+        let disk = Disk::new(
+            VerityDisk::new(inner.clone(), hash_tree.clone(), &root_hash)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(disk.is_read_only());
+        assert_eq!(disk.sector_count(), data.len() as u64 / 512);
+
+        // Unaligned reads spanning multiple data blocks are verified and
+        // returned.
+        let buf = read(&disk, 7, 3 * DATA_BLOCK_SIZE).await.unwrap();
+        assert_eq!(buf, data[7 * 512..][..3 * DATA_BLOCK_SIZE]);
+
+        // Corrupt a data block. Reads of that block fail; others succeed.
+        let mut mem = GuestMemory::allocate(512);
+        mem.inner_buf_mut().unwrap().fill(0xff);
+        inner
+            .write_vectored(
+                &OwnedRequestBuffers::linear(0, 512, false).buffer(&mem),
+                (10 * DATA_BLOCK_SIZE / 512) as u64 + 1,
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            read(&disk, (10 * DATA_BLOCK_SIZE / 512) as u64, 512).await,
+            Err(DiskError::MediumError(..))
+        ));
+        assert!(
+            read(&disk, (11 * DATA_BLOCK_SIZE / 512) as u64, 512)
+                .await
+                .is_ok()
+        );
+
+        // A wrong root hash fails verification of every block.
+        let mut bad_root = root_hash.clone();
+        bad_root[0] ^= 1;
+        let disk = Disk::new(VerityDisk::new(inner, hash_tree, &bad_root).await.unwrap()).unwrap();
+        assert!(read(&disk, 0, 512).await.is_err());
+    }
+}
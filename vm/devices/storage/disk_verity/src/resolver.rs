@@ -0,0 +1,41 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::VerityDisk;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::VerityDiskHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for VerityDisk.
+pub struct VerityDiskResolver;
+declare_static_async_resolver!(VerityDiskResolver, (DiskHandleKind, VerityDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, VerityDiskHandle> for VerityDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: VerityDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        // Both disks are only ever read.
+        let input = ResolveDiskParameters {
+            read_only: true,
+            driver_source: input.driver_source,
+        };
+        let inner = resolver.resolve(rsrc.disk, input).await?;
+        let hash_tree = resolver.resolve(rsrc.hash_tree, input).await?;
+
+        let disk = VerityDisk::new(inner.0, hash_tree.0, &rsrc.root_hash).await?;
+        ResolvedDisk::new(disk)
+            .map_err(|e| anyhow::anyhow!("failed to create the verity disk: {}", e))
+    }
+}
@@ -236,7 +236,9 @@ fn chk_win32(err: u32) -> std::io::Result<()> {
 }
 
 impl Vhd {
-    fn open(path: &Path, read_only: bool) -> std::io::Result<Self> {
+    /// Opens the virtual disk at `path`. If `snapshot` is set, `path` must be
+    /// a VHD Set file, and the disk as of that snapshot is opened read-only.
+    fn open(path: &Path, read_only: bool, snapshot: Option<Guid>) -> std::io::Result<Self> {
         let file = unsafe {
             let mut storage_type = std::mem::zeroed();
             // Use a unique ID for each open to avoid virtual disk sharing
@@ -244,11 +246,12 @@ fn open(path: &Path, read_only: bool) -> std::io::Result<Self> {
             // to support failover.
             let resiliency_guid = Guid::new_random();
             let mut parameters = virtdisk::OPEN_VIRTUAL_DISK_PARAMETERS {
-                Version: 2,
+                Version: 3,
                 u: virtdisk::OPEN_VIRTUAL_DISK_PARAMETERS_u {
-                    Version2: virtdisk::OPEN_VIRTUAL_DISK_PARAMETERS_2 {
+                    Version3: virtdisk::OPEN_VIRTUAL_DISK_PARAMETERS_3 {
                         ReadOnly: read_only.into(),
                         ResiliencyGuid: resiliency_guid.into(),
+                        SnapshotId: snapshot.unwrap_or(Guid::ZERO).into(),
                         ..std::mem::zeroed()
                     },
                 },
@@ -393,8 +396,24 @@ pub enum Error {
 
 impl VhdmpDisk {
     /// Opens a VHD for use with [`Self::new()`].
+    ///
+    /// This supports VHD, VHDX, and VHD Set (.vhds) files, as well as
+    /// differencing disks (such as the .avhdx files of a Hyper-V checkpoint
+    /// chain), whose parents are opened automatically.
     pub fn open_vhd(path: &Path, read_only: bool) -> Result<Vhd, Error> {
-        let vhd = Vhd::open(path, read_only).map_err(Error::Open)?;
+        Self::open_inner(path, read_only, None)
+    }
+
+    /// Opens the disk contents as of checkpoint `snapshot` of the VHD Set
+    /// file at `path`, for use with [`Self::new()`].
+    ///
+    /// Checkpoints are always opened read-only.
+    pub fn open_vhd_set_snapshot(path: &Path, snapshot: Guid) -> Result<Vhd, Error> {
+        Self::open_inner(path, true, Some(snapshot))
+    }
+
+    fn open_inner(path: &Path, read_only: bool, snapshot: Option<Guid>) -> Result<Vhd, Error> {
+        let vhd = Vhd::open(path, read_only, snapshot).map_err(Error::Open)?;
 
         // N.B. This must be attached here and not later in a worker process
         //      since this operation may require impersonation, which is
@@ -0,0 +1,237 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Creation of new dynamic and differencing VHDX files.
+
+use crate::OpenError;
+use crate::io::write_all_at;
+use guid::Guid;
+use std::fs::File;
+use vhdx_defs::FileIdentifier;
+use vhdx_defs::FileParameters;
+use vhdx_defs::Header;
+use vhdx_defs::MB;
+use vhdx_defs::MetadataTableEntry;
+use vhdx_defs::MetadataTableHeader;
+use vhdx_defs::ParentLocatorEntry;
+use vhdx_defs::ParentLocatorHeader;
+use vhdx_defs::RegionTableEntry;
+use vhdx_defs::RegionTableHeader;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+const LOG_OFFSET: u64 = MB;
+const METADATA_OFFSET: u64 = 2 * MB;
+const METADATA_LENGTH: u32 = MB as u32;
+const BAT_OFFSET: u64 = 3 * MB;
+
+const LOGICAL_SECTOR_SIZE: u32 = 512;
+const PHYSICAL_SECTOR_SIZE: u32 = 4096;
+
+/// The parent of a new differencing disk.
+pub(crate) struct Parent<'a> {
+    pub data_write_guid: Guid,
+    pub relative_path: &'a str,
+}
+
+/// Writes the structures for a new, empty VHDX to `file`.
+pub(crate) fn create(
+    file: &File,
+    disk_size: u64,
+    parent: Option<Parent<'_>>,
+) -> Result<(), OpenError> {
+    if disk_size == 0 || disk_size % LOGICAL_SECTOR_SIZE as u64 != 0 {
+        return Err(OpenError::InvalidDiskSize(disk_size));
+    }
+
+    let block_size = vhdx_defs::DEFAULT_BLOCK_SIZE;
+    let metadata = crate::parse::Metadata {
+        block_size,
+        has_parent: parent.is_some(),
+        disk_size,
+        disk_id: Guid::new_random(),
+        logical_sector_size: LOGICAL_SECTOR_SIZE,
+        physical_sector_size: PHYSICAL_SECTOR_SIZE,
+        parent_locator: None,
+    };
+    let bat_length = (metadata.bat_entry_count() * 8).next_multiple_of(MB);
+    let bat_length: u32 = bat_length
+        .try_into()
+        .map_err(|_| OpenError::InvalidDiskSize(disk_size))?;
+
+    file.set_len(0)?;
+    file.set_len(BAT_OFFSET + bat_length as u64)?;
+
+    let mut identifier = FileIdentifier::new_zeroed();
+    identifier.signature = FileIdentifier::SIGNATURE;
+    for (c, d) in "OpenVMM".encode_utf16().zip(&mut identifier.creator) {
+        *d = c;
+    }
+    write_all_at(
+        file,
+        identifier.as_bytes(),
+        vhdx_defs::FILE_IDENTIFIER_OFFSET,
+    )?;
+
+    let file_write_guid = Guid::new_random();
+    let data_write_guid = Guid::new_random();
+    for (sequence_number, offset) in vhdx_defs::HEADER_OFFSETS.into_iter().enumerate() {
+        let mut header = Header::new_zeroed();
+        header.signature = Header::SIGNATURE;
+        header.sequence_number = sequence_number as u64;
+        header.file_write_guid = file_write_guid;
+        header.data_write_guid = data_write_guid;
+        header.log_version = Header::LOG_VERSION;
+        header.version = Header::VERSION;
+        header.log_length = vhdx_defs::DEFAULT_LOG_SIZE;
+        header.log_offset = LOG_OFFSET;
+        header.checksum = vhdx_defs::compute_checksum(header.as_bytes());
+        write_all_at(file, header.as_bytes(), offset)?;
+    }
+
+    let regions = [
+        RegionTableEntry {
+            guid: vhdx_defs::REGION_BAT,
+            file_offset: BAT_OFFSET,
+            length: bat_length,
+            flags: RegionTableEntry::FLAG_REQUIRED,
+        },
+        RegionTableEntry {
+            guid: vhdx_defs::REGION_METADATA,
+            file_offset: METADATA_OFFSET,
+            length: METADATA_LENGTH,
+            flags: RegionTableEntry::FLAG_REQUIRED,
+        },
+    ];
+    let mut region_table = vec![0; vhdx_defs::REGION_TABLE_SIZE];
+    RegionTableHeader {
+        signature: RegionTableHeader::SIGNATURE,
+        checksum: 0,
+        entry_count: regions.len() as u32,
+        reserved: 0,
+    }
+    .write_to_prefix(&mut region_table)
+    .unwrap();
+    region_table[size_of::<RegionTableHeader>()..][..size_of_val(&regions)]
+        .copy_from_slice(regions.as_bytes());
+    let checksum = vhdx_defs::compute_checksum(&region_table);
+    region_table[4..8].copy_from_slice(&checksum.to_le_bytes());
+    for offset in vhdx_defs::REGION_TABLE_OFFSETS {
+        write_all_at(file, &region_table, offset)?;
+    }
+
+    write_all_at(file, &build_metadata(&metadata, parent), METADATA_OFFSET)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn build_metadata(metadata: &crate::parse::Metadata, parent: Option<Parent<'_>>) -> Vec<u8> {
+    let flags = if parent.is_some() {
+        FileParameters::FLAG_HAS_PARENT
+    } else {
+        0
+    };
+    let mut items: Vec<(Guid, u32, Vec<u8>)> = vec![
+        (
+            vhdx_defs::METADATA_FILE_PARAMETERS,
+            MetadataTableEntry::FLAG_IS_REQUIRED,
+            FileParameters {
+                block_size: metadata.block_size,
+                flags,
+            }
+            .as_bytes()
+            .to_vec(),
+        ),
+        (
+            vhdx_defs::METADATA_VIRTUAL_DISK_SIZE,
+            MetadataTableEntry::FLAG_IS_REQUIRED | MetadataTableEntry::FLAG_IS_VIRTUAL_DISK,
+            metadata.disk_size.as_bytes().to_vec(),
+        ),
+        (
+            vhdx_defs::METADATA_VIRTUAL_DISK_ID,
+            MetadataTableEntry::FLAG_IS_REQUIRED | MetadataTableEntry::FLAG_IS_VIRTUAL_DISK,
+            metadata.disk_id.as_bytes().to_vec(),
+        ),
+        (
+            vhdx_defs::METADATA_LOGICAL_SECTOR_SIZE,
+            MetadataTableEntry::FLAG_IS_REQUIRED | MetadataTableEntry::FLAG_IS_VIRTUAL_DISK,
+            metadata.logical_sector_size.as_bytes().to_vec(),
+        ),
+        (
+            vhdx_defs::METADATA_PHYSICAL_SECTOR_SIZE,
+            MetadataTableEntry::FLAG_IS_REQUIRED | MetadataTableEntry::FLAG_IS_VIRTUAL_DISK,
+            metadata.physical_sector_size.as_bytes().to_vec(),
+        ),
+    ];
+    if let Some(parent) = parent {
+        items.push((
+            vhdx_defs::METADATA_PARENT_LOCATOR,
+            MetadataTableEntry::FLAG_IS_REQUIRED,
+            build_parent_locator(&[
+                (
+                    vhdx_defs::PARENT_KEY_LINKAGE,
+                    &format!("{{{}}}", parent.data_write_guid),
+                ),
+                (vhdx_defs::PARENT_KEY_RELATIVE_PATH, parent.relative_path),
+            ]),
+        ));
+    }
+
+    let mut buf = vec![0; METADATA_LENGTH as usize];
+    MetadataTableHeader {
+        signature: MetadataTableHeader::SIGNATURE,
+        reserved: 0,
+        entry_count: items.len() as u16,
+        reserved2: [0; 5],
+    }
+    .write_to_prefix(&mut buf)
+    .unwrap();
+
+    let mut item_offset = MetadataTableHeader::TABLE_SIZE;
+    for (i, (item_id, flags, data)) in items.into_iter().enumerate() {
+        let entry = MetadataTableEntry {
+            item_id,
+            offset: item_offset,
+            length: data.len() as u32,
+            flags,
+            reserved: 0,
+        };
+        let entry_offset = size_of::<MetadataTableHeader>() + i * size_of::<MetadataTableEntry>();
+        entry.write_to_prefix(&mut buf[entry_offset..]).unwrap();
+        buf[item_offset as usize..][..data.len()].copy_from_slice(&data);
+        item_offset += (data.len() as u32).next_multiple_of(8);
+    }
+    buf
+}
+
+fn build_parent_locator(entries: &[(&str, &str)]) -> Vec<u8> {
+    let strings_offset =
+        size_of::<ParentLocatorHeader>() + entries.len() * size_of::<ParentLocatorEntry>();
+    let mut buf = vec![0; strings_offset];
+    ParentLocatorHeader {
+        locator_type: vhdx_defs::PARENT_LOCATOR_VHDX,
+        reserved: 0,
+        key_value_count: entries.len() as u16,
+    }
+    .write_to_prefix(&mut buf)
+    .unwrap();
+
+    let push_string = |buf: &mut Vec<u8>, s: &str| {
+        let offset = buf.len();
+        buf.extend(s.encode_utf16().flat_map(u16::to_le_bytes));
+        (offset as u32, (buf.len() - offset) as u16)
+    };
+    for (i, (key, value)) in entries.iter().enumerate() {
+        let (key_offset, key_length) = push_string(&mut buf, key);
+        let (value_offset, value_length) = push_string(&mut buf, value);
+        let entry = ParentLocatorEntry {
+            key_offset,
+            value_offset,
+            key_length,
+            value_length,
+        };
+        let offset = size_of::<ParentLocatorHeader>() + i * size_of::<ParentLocatorEntry>();
+        entry.write_to_prefix(&mut buf[offset..]).unwrap();
+    }
+    buf
+}
@@ -0,0 +1,1001 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A VHDX disk implementation, supporting dynamic and differencing VHDX files.
+//!
+//! Fixed VHDX files are also supported, since they are just dynamic VHDX files
+//! with all blocks allocated up front.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod create;
+mod io;
+mod log;
+mod parse;
+
+pub use parse::ParentLocator;
+
+use async_trait::async_trait;
+use blocking::unblock;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::VhdxDiskHandle;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use guid::Guid;
+use inspect::Inspect;
+use io::read_exact_at;
+use io::write_all_at;
+use log::LogWriter;
+use log::Page;
+use log::Replay;
+use parking_lot::RwLock;
+use parse::VhdxFile;
+use scsi_buffers::RequestBuffers;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use thiserror::Error;
+use vhdx_defs::BatEntry;
+use vhdx_defs::Header;
+use vhdx_defs::LOG_SECTOR_SIZE;
+use vhdx_defs::bitmap_state;
+use vhdx_defs::block_state;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+use zerocopy::IntoBytes;
+
+pub struct VhdxResolver;
+declare_static_async_resolver!(VhdxResolver, (DiskHandleKind, VhdxDiskHandle));
+
+#[derive(Debug, Error)]
+pub enum ResolveVhdxDiskError {
+    #[error("failed to resolve parent disk")]
+    Parent(#[source] ResolveError),
+    #[error("failed to open VHDX")]
+    Open(#[source] OpenError),
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, VhdxDiskHandle> for VhdxResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveVhdxDiskError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: VhdxDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let parent = if let Some(parent) = rsrc.parent {
+            let parent = resolver
+                .resolve(
+                    parent,
+                    ResolveDiskParameters {
+                        read_only: true,
+                        driver_source: input.driver_source,
+                    },
+                )
+                .await
+                .map_err(ResolveVhdxDiskError::Parent)?;
+            Some(parent.0)
+        } else {
+            None
+        };
+        let disk = VhdxDisk::open(rsrc.file, parent, input.read_only)
+            .map_err(ResolveVhdxDiskError::Open)?;
+        ResolvedDisk::new(disk).map_err(ResolveVhdxDiskError::InvalidDisk)
+    }
+}
+
+/// An error encountered while opening or creating a VHDX.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OpenError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("not a VHDX file")]
+    InvalidSignature,
+    #[error("no valid VHDX header")]
+    NoValidHeader,
+    #[error("unsupported VHDX version: {0}")]
+    UnsupportedVersion(u16),
+    #[error("invalid VHDX log: {0}")]
+    InvalidLog(&'static str),
+    #[error("the VHDX log must be replayed, but the file is read-only")]
+    LogReplayRequired,
+    #[error("no valid VHDX region table")]
+    NoValidRegionTable,
+    #[error("unknown required region {0}")]
+    UnknownRequiredRegion(Guid),
+    #[error("missing {0} region")]
+    MissingRegion(&'static str),
+    #[error("invalid VHDX metadata table")]
+    InvalidMetadataTable,
+    #[error("unknown required metadata item {0}")]
+    UnknownRequiredMetadata(Guid),
+    #[error("missing {0} metadata item")]
+    MissingMetadata(&'static str),
+    #[error("invalid VHDX metadata: {0}")]
+    InvalidMetadata(&'static str),
+    #[error("invalid VHDX disk size: {0}")]
+    InvalidDiskSize(u64),
+    #[error("invalid parent locator")]
+    InvalidParentLocator,
+    #[error("differencing VHDX opened without a parent")]
+    MissingParent,
+    #[error("parent provided for a VHDX that is not a differencing disk")]
+    UnexpectedParent,
+    #[error("parent disk geometry does not match the differencing disk")]
+    ParentMismatch,
+}
+
+/// Information about a VHDX file, used to locate and validate its parent.
+#[derive(Debug)]
+pub struct VhdxInfo {
+    /// The size of the virtual disk in bytes.
+    pub disk_size: u64,
+    /// The GUID that changes each time the virtual disk contents are
+    /// modified. A differencing disk's parent locator refers to its parent by
+    /// this GUID.
+    pub data_write_guid: Guid,
+    /// The parent locator, for a differencing disk.
+    pub parent_locator: Option<ParentLocator>,
+}
+
+/// Reads information about the VHDX file `file`.
+pub fn info(file: &File) -> Result<VhdxInfo, OpenError> {
+    let vhdx = VhdxFile::parse(file)?;
+    Ok(VhdxInfo {
+        disk_size: vhdx.metadata.disk_size,
+        data_write_guid: vhdx.header.data_write_guid,
+        parent_locator: vhdx.metadata.parent_locator,
+    })
+}
+
+/// An open VHDX disk.
+#[derive(Inspect)]
+pub struct VhdxDisk {
+    #[inspect(skip)]
+    file: Arc<File>,
+    disk_size: u64,
+    block_size: u32,
+    chunk_ratio: u64,
+    sector_size: u32,
+    physical_sector_size: u32,
+    disk_id: Guid,
+    read_only: bool,
+    parent: Option<Disk>,
+    bat_offset: u64,
+    #[inspect(skip)]
+    bat: RwLock<Vec<BatEntry>>,
+    #[inspect(skip)]
+    write_started: AtomicBool,
+    #[inspect(skip)]
+    write_state: futures::lock::Mutex<WriteState>,
+}
+
+/// State used to update the file's metadata.
+struct WriteState {
+    header: Header,
+    header_index: usize,
+    /// The log writer, available once the headers have been updated for the
+    /// first write.
+    log: Option<LogWriter>,
+    /// The end of the allocated portion of the file, where new blocks are
+    /// allocated.
+    file_end: u64,
+}
+
+/// A portion of a request that falls within a single payload block.
+struct BlockRange {
+    block: u64,
+    /// The byte offset within the block.
+    offset: u64,
+    len: usize,
+    /// The byte offset within the request buffers.
+    buffer_offset: usize,
+}
+
+impl VhdxDisk {
+    /// Formats `file` as a new dynamic VHDX of `disk_size` bytes.
+    pub fn create(file: &File, disk_size: u64) -> Result<(), OpenError> {
+        create::create(file, disk_size, None)
+    }
+
+    /// Formats `file` as a new differencing VHDX whose parent is the VHDX
+    /// `parent`, located at `parent_relative_path` relative to `file`'s
+    /// directory.
+    pub fn create_differencing(
+        file: &File,
+        parent: &File,
+        parent_relative_path: &str,
+    ) -> Result<(), OpenError> {
+        let parent = VhdxFile::parse(parent)?;
+        create::create(
+            file,
+            parent.metadata.disk_size,
+            Some(create::Parent {
+                data_write_guid: parent.header.data_write_guid,
+                relative_path: parent_relative_path,
+            }),
+        )
+    }
+
+    /// Opens a VHDX file. `parent` must be provided for a differencing disk.
+    ///
+    /// If the file was not closed cleanly, its log is replayed, which
+    /// requires write access.
+    pub fn open(file: File, parent: Option<Disk>, read_only: bool) -> Result<Self, OpenError> {
+        let mut vhdx = VhdxFile::parse(&file)?;
+        let replay = Replay::scan(&file, &vhdx.header)?;
+        if read_only {
+            if replay.is_needed(&file)? {
+                return Err(OpenError::LogReplayRequired);
+            }
+        } else if vhdx.header.log_guid != Guid::ZERO {
+            replay.apply(&file)?;
+            update_header(&file, &mut vhdx.header, &mut vhdx.header_index, |header| {
+                header.log_guid = Guid::ZERO;
+            })?;
+            vhdx = VhdxFile::parse(&file)?;
+        }
+
+        let metadata = &vhdx.metadata;
+        match &parent {
+            Some(parent) => {
+                if !metadata.has_parent {
+                    return Err(OpenError::UnexpectedParent);
+                }
+                if parent.sector_size() != metadata.logical_sector_size
+                    || parent.sector_count() * parent.sector_size() as u64 != metadata.disk_size
+                {
+                    return Err(OpenError::ParentMismatch);
+                }
+            }
+            None => {
+                if metadata.has_parent {
+                    return Err(OpenError::MissingParent);
+                }
+            }
+        }
+
+        let mut bat = vec![BatEntry(0); vhdx.bat_length as usize / size_of::<BatEntry>()];
+        read_exact_at(&file, bat.as_mut_bytes(), vhdx.bat_offset)?;
+        let file_end = file
+            .metadata()?
+            .len()
+            .next_multiple_of(vhdx_defs::ALIGNMENT);
+
+        Ok(Self {
+            disk_size: metadata.disk_size,
+            block_size: metadata.block_size,
+            chunk_ratio: metadata.chunk_ratio(),
+            sector_size: metadata.logical_sector_size,
+            physical_sector_size: metadata.physical_sector_size,
+            disk_id: metadata.disk_id,
+            read_only,
+            parent,
+            bat_offset: vhdx.bat_offset,
+            bat: RwLock::new(bat),
+            write_started: AtomicBool::new(false),
+            write_state: futures::lock::Mutex::new(WriteState {
+                header: vhdx.header,
+                header_index: vhdx.header_index,
+                log: None,
+                file_end,
+            }),
+            file: Arc::new(file),
+        })
+    }
+
+    fn bat_index(&self, block: u64) -> usize {
+        (block + block / self.chunk_ratio) as usize
+    }
+
+    fn bitmap_bat_index(&self, block: u64) -> usize {
+        let chunk = block / self.chunk_ratio;
+        (chunk * (self.chunk_ratio + 1) + self.chunk_ratio) as usize
+    }
+
+    /// Returns the index of the sector bitmap bit for the first sector of
+    /// `range`.
+    fn bitmap_bit(&self, range: &BlockRange) -> u64 {
+        let sectors_per_block = (self.block_size / self.sector_size) as u64;
+        (range.block % self.chunk_ratio) * sectors_per_block
+            + range.offset / self.sector_size as u64
+    }
+
+    fn block_ranges(&self, sector: u64, len: usize) -> impl Iterator<Item = BlockRange> {
+        let block_size = self.block_size as u64;
+        let start = sector * self.sector_size as u64;
+        let end = start + len as u64;
+        let mut offset = start;
+        std::iter::from_fn(move || {
+            if offset >= end {
+                return None;
+            }
+            let block = offset / block_size;
+            let block_offset = offset % block_size;
+            let this_len = (block_size - block_offset).min(end - offset);
+            let range = BlockRange {
+                block,
+                offset: block_offset,
+                len: this_len as usize,
+                buffer_offset: (offset - start) as usize,
+            };
+            offset += this_len;
+            Some(range)
+        })
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> Result<(), DiskError> {
+        let end = sector
+            .checked_mul(self.sector_size as u64)
+            .and_then(|start| start.checked_add(len as u64));
+        if end.is_none_or(|end| end > self.disk_size) {
+            return Err(DiskError::IllegalBlock);
+        }
+        Ok(())
+    }
+
+    async fn read_file(&self, buffers: &RequestBuffers<'_>, offset: u64) -> Result<(), DiskError> {
+        let file = self.file.clone();
+        let mut buf = vec![0; buffers.len()];
+        let buf = unblock(move || read_exact_at(&file, &mut buf, offset).map(|()| buf))
+            .await
+            .map_err(DiskError::Io)?;
+        buffers.writer().write(&buf)?;
+        Ok(())
+    }
+
+    async fn write_file(&self, buffers: &RequestBuffers<'_>, offset: u64) -> Result<(), DiskError> {
+        let file = self.file.clone();
+        let mut buf = vec![0; buffers.len()];
+        buffers.reader().read(&mut buf)?;
+        unblock(move || write_all_at(&file, &buf, offset))
+            .await
+            .map_err(DiskError::Io)
+    }
+
+    async fn flush(&self) -> Result<(), DiskError> {
+        let file = self.file.clone();
+        unblock(move || file.sync_all())
+            .await
+            .map_err(DiskError::Io)
+    }
+
+    /// Reads from the parent disk, or zeroes the buffers if there is no
+    /// parent.
+    async fn read_parent(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        offset: u64,
+    ) -> Result<(), DiskError> {
+        match &self.parent {
+            Some(parent) => {
+                parent
+                    .read_vectored(buffers, offset / self.sector_size as u64)
+                    .await
+            }
+            None => {
+                buffers.writer().zero(buffers.len())?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads the sector bitmap bits covering `range`, starting at bit index
+    /// `bit % 8` of the returned buffer.
+    async fn read_bitmap(&self, range: &BlockRange) -> Result<(u64, Vec<u8>), DiskError> {
+        let entry = self.bat.read()[self.bitmap_bat_index(range.block)];
+        if entry.state() != bitmap_state::PRESENT {
+            return Err(DiskError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "partially present block without a sector bitmap",
+            )));
+        }
+        let bit = self.bitmap_bit(range);
+        let count = range.len as u64 / self.sector_size as u64;
+        let byte_len = (bit % 8 + count).div_ceil(8) as usize;
+        let offset = entry.file_offset() + bit / 8;
+        let file = self.file.clone();
+        let buf = unblock(move || {
+            let mut buf = vec![0; byte_len];
+            read_exact_at(&file, &mut buf, offset).map(|()| buf)
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        Ok((bit, buf))
+    }
+
+    /// Reads a range of a partially present block, reading the present sectors
+    /// from the file and the rest from the parent.
+    async fn read_partial(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        range: &BlockRange,
+        entry: BatEntry,
+    ) -> Result<(), DiskError> {
+        let (bit, bitmap) = self.read_bitmap(range).await?;
+        let first = (bit % 8) as usize;
+        let count = range.len / self.sector_size as usize;
+        let is_present = |i: usize| bitmap[(first + i) / 8] & (1 << ((first + i) % 8)) != 0;
+        let mut i = 0;
+        while i < count {
+            let present = is_present(i);
+            let run = (i..count).take_while(|&j| is_present(j) == present).count();
+            let buffer_offset = i * self.sector_size as usize;
+            let len = run * self.sector_size as usize;
+            let buffers = buffers.subrange(buffer_offset, len);
+            let block_offset = range.offset + buffer_offset as u64;
+            if present {
+                self.read_file(&buffers, entry.file_offset() + block_offset)
+                    .await?;
+            } else {
+                self.read_parent(
+                    &buffers,
+                    range.block * self.block_size as u64 + block_offset,
+                )
+                .await?;
+            }
+            i += run;
+        }
+        Ok(())
+    }
+
+    /// Updates the headers before the first write to the file, as required
+    /// by the format.
+    async fn begin_write(&self) -> Result<(), DiskError> {
+        if self.write_started.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let mut state = self.write_state.lock().await;
+        if state.log.is_none() {
+            let file = self.file.clone();
+            let mut header = state.header;
+            let mut header_index = state.header_index;
+            let (header, header_index) = unblock(move || {
+                update_header(&file, &mut header, &mut header_index, |header| {
+                    header.file_write_guid = Guid::new_random();
+                    header.data_write_guid = Guid::new_random();
+                    header.log_guid = Guid::new_random();
+                })
+                .map(|()| (header, header_index))
+            })
+            .await
+            .map_err(DiskError::Io)?;
+            state.log = Some(LogWriter::new(&header, header.log_guid));
+            state.header = header;
+            state.header_index = header_index;
+            self.write_started.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Writes `pages` through the log, then to their final locations.
+    async fn log_pages(
+        &self,
+        state: &mut WriteState,
+        pages: Vec<(u64, Box<Page>)>,
+    ) -> Result<(), DiskError> {
+        let log = state.log.as_mut().expect("writes have begun");
+        let (log_offset, entry) = log.build_entry(
+            &pages.iter().map(|(o, p)| (*o, &**p)).collect::<Vec<_>>(),
+            state.file_end,
+        );
+        let file = self.file.clone();
+        unblock(move || {
+            write_all_at(&file, &entry, log_offset)?;
+            file.sync_all()?;
+            for (offset, page) in &pages {
+                write_all_at(&file, &page[..], *offset)?;
+            }
+            file.sync_all()
+        })
+        .await
+        .map_err(DiskError::Io)
+    }
+
+    /// Returns the BAT pages containing `updates`, with the updates applied.
+    fn bat_pages(&self, updates: &[(usize, BatEntry)]) -> Vec<(u64, Box<Page>)> {
+        const ENTRIES_PER_PAGE: usize = LOG_SECTOR_SIZE / size_of::<BatEntry>();
+        let bat = self.bat.read();
+        let mut pages: Vec<(u64, Box<Page>)> = Vec::new();
+        for &(index, entry) in updates {
+            let page_index = index / ENTRIES_PER_PAGE;
+            let offset = self.bat_offset + (page_index * LOG_SECTOR_SIZE) as u64;
+            let i = match pages.iter().position(|(o, _)| *o == offset) {
+                Some(i) => i,
+                None => {
+                    let mut page = Box::new([0; LOG_SECTOR_SIZE]);
+                    let start = page_index * ENTRIES_PER_PAGE;
+                    page.copy_from_slice(bat[start..start + ENTRIES_PER_PAGE].as_bytes());
+                    pages.push((offset, page));
+                    pages.len() - 1
+                }
+            };
+            let page = &mut pages[i].1;
+            let entry_offset = (index % ENTRIES_PER_PAGE) * size_of::<BatEntry>();
+            page[entry_offset..entry_offset + size_of::<BatEntry>()]
+                .copy_from_slice(entry.as_bytes());
+        }
+        pages
+    }
+
+    /// Returns the sector bitmap pages for `range` with the range's bits set,
+    /// or `None` if they are all already set.
+    async fn bitmap_pages(
+        &self,
+        range: &BlockRange,
+        bitmap_offset: u64,
+    ) -> Result<Option<Vec<(u64, Box<Page>)>>, DiskError> {
+        let bit = self.bitmap_bit(range);
+        let count = range.len as u64 / self.sector_size as u64;
+        let first_page = bit / 8 / LOG_SECTOR_SIZE as u64;
+        let last_page = (bit + count - 1) / 8 / LOG_SECTOR_SIZE as u64;
+        let file = self.file.clone();
+        let mut pages = unblock(move || {
+            (first_page..=last_page)
+                .map(|page_index| {
+                    let offset = bitmap_offset + page_index * LOG_SECTOR_SIZE as u64;
+                    let mut page = Box::new([0; LOG_SECTOR_SIZE]);
+                    read_exact_at(&file, &mut page[..], offset).map(|()| (offset, page))
+                })
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .await
+        .map_err(DiskError::Io)?;
+
+        let mut changed = false;
+        for b in bit..bit + count {
+            let page = &mut pages[(b / 8 / LOG_SECTOR_SIZE as u64 - first_page) as usize].1;
+            let byte = &mut page[(b / 8) as usize % LOG_SECTOR_SIZE];
+            let mask = 1 << (b % 8);
+            changed |= *byte & mask == 0;
+            *byte |= mask;
+        }
+        Ok(changed.then_some(pages))
+    }
+
+    /// Marks the sectors in `range` of an allocated, partially present block
+    /// as present.
+    async fn set_present(&self, range: &BlockRange) -> Result<(), DiskError> {
+        let mut state = self.write_state.lock().await;
+        let bitmap = self.bat.read()[self.bitmap_bat_index(range.block)];
+        if let Some(pages) = self.bitmap_pages(range, bitmap.file_offset()).await? {
+            self.log_pages(&mut state, pages).await?;
+        }
+        Ok(())
+    }
+
+    /// Extends the file to allocate `len` bytes, returning the offset of the
+    /// allocation.
+    async fn allocate(&self, state: &mut WriteState, len: u64) -> Result<u64, DiskError> {
+        let offset = state.file_end;
+        let file_end = offset + len;
+        let file = self.file.clone();
+        unblock(move || file.set_len(file_end))
+            .await
+            .map_err(DiskError::Io)?;
+        state.file_end = file_end;
+        Ok(offset)
+    }
+
+    /// Writes to a range of a block that is not yet allocated.
+    async fn allocate_and_write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        range: &BlockRange,
+    ) -> Result<(), DiskError> {
+        let mut state = self.write_state.lock().await;
+        let index = self.bat_index(range.block);
+        let entry = self.bat.read()[index];
+        match entry.state() {
+            block_state::FULLY_PRESENT => {
+                drop(state);
+                return self
+                    .write_file(buffers, entry.file_offset() + range.offset)
+                    .await;
+            }
+            block_state::PARTIALLY_PRESENT => {
+                drop(state);
+                self.write_file(buffers, entry.file_offset() + range.offset)
+                    .await?;
+                self.flush().await?;
+                return self.set_present(range).await;
+            }
+            _ => {}
+        }
+
+        // Sectors of a not-present block in a differencing disk that are not
+        // being written must continue to come from the parent, so track
+        // which sectors are present in the sector bitmap. Otherwise, the rest
+        // of the block reads as zero, which a newly allocated block does.
+        let partial = entry.state() == block_state::NOT_PRESENT && self.parent.is_some();
+
+        let block_offset = self.allocate(&mut state, self.block_size as u64).await?;
+        self.write_file(buffers, block_offset + range.offset)
+            .await?;
+
+        let mut updates = Vec::new();
+        let mut pages = Vec::new();
+        if partial {
+            let bitmap_index = self.bitmap_bat_index(range.block);
+            let mut bitmap = self.bat.read()[bitmap_index];
+            if bitmap.state() != bitmap_state::PRESENT {
+                let offset = self
+                    .allocate(&mut state, vhdx_defs::SECTOR_BITMAP_BLOCK_SIZE)
+                    .await?;
+                bitmap = BatEntry::new(bitmap_state::PRESENT, offset);
+                updates.push((bitmap_index, bitmap));
+            }
+            pages.extend(
+                self.bitmap_pages(range, bitmap.file_offset())
+                    .await?
+                    .unwrap_or_default(),
+            );
+            updates.push((
+                index,
+                BatEntry::new(block_state::PARTIALLY_PRESENT, block_offset),
+            ));
+        } else {
+            updates.push((
+                index,
+                BatEntry::new(block_state::FULLY_PRESENT, block_offset),
+            ));
+        }
+
+        // Ensure the data is durable before the BAT refers to it.
+        self.flush().await?;
+        pages.extend(self.bat_pages(&updates));
+        self.log_pages(&mut state, pages).await?;
+
+        let mut bat = self.bat.write();
+        for (index, entry) in updates {
+            bat[index] = entry;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an updated copy of `header` to the non-current header location and
+/// makes it current.
+fn update_header(
+    file: &File,
+    header: &mut Header,
+    header_index: &mut usize,
+    f: impl FnOnce(&mut Header),
+) -> std::io::Result<()> {
+    let mut new_header = *header;
+    f(&mut new_header);
+    new_header.sequence_number += 1;
+    new_header.checksum = 0;
+    new_header.checksum = vhdx_defs::compute_checksum(new_header.as_bytes());
+    let new_index = 1 - *header_index;
+    write_all_at(
+        file,
+        new_header.as_bytes(),
+        vhdx_defs::HEADER_OFFSETS[new_index],
+    )?;
+    file.sync_all()?;
+    *header = new_header;
+    *header_index = new_index;
+    Ok(())
+}
+
+impl DiskIo for VhdxDisk {
+    fn disk_type(&self) -> &str {
+        "vhdx"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.disk_size / self.sector_size as u64
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        Some(self.disk_id.into())
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.physical_sector_size
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.check_range(sector, buffers.len())?;
+        for range in self.block_ranges(sector, buffers.len()) {
+            let buffers = buffers.subrange(range.buffer_offset, range.len);
+            let entry = self.bat.read()[self.bat_index(range.block)];
+            match entry.state() {
+                block_state::FULLY_PRESENT => {
+                    self.read_file(&buffers, entry.file_offset() + range.offset)
+                        .await?
+                }
+                block_state::PARTIALLY_PRESENT => {
+                    self.read_partial(&buffers, &range, entry).await?
+                }
+                block_state::NOT_PRESENT => {
+                    self.read_parent(
+                        &buffers,
+                        range.block * self.block_size as u64 + range.offset,
+                    )
+                    .await?
+                }
+                _ => buffers.writer().zero(range.len)?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        self.check_range(sector, buffers.len())?;
+        self.begin_write().await?;
+        for range in self.block_ranges(sector, buffers.len()) {
+            let buffers = buffers.subrange(range.buffer_offset, range.len);
+            let entry = self.bat.read()[self.bat_index(range.block)];
+            match entry.state() {
+                block_state::FULLY_PRESENT => {
+                    self.write_file(&buffers, entry.file_offset() + range.offset)
+                        .await?
+                }
+                block_state::PARTIALLY_PRESENT => {
+                    self.write_file(&buffers, entry.file_offset() + range.offset)
+                        .await?;
+                    self.flush().await?;
+                    self.set_present(&range).await?;
+                }
+                _ => self.allocate_and_write(&buffers, &range).await?,
+            }
+        }
+        if fua {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.flush().await
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Ok(())
+    }
+
+    fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
+        disk_backend::UnmapBehavior::Ignored
+    }
+}
+
+impl ParentLocator {
+    /// Returns the paths where the parent of the VHDX at `path` may be
+    /// found, in order of preference.
+    pub fn parent_paths(&self, path: &Path) -> Vec<std::path::PathBuf> {
+        let mut paths = Vec::new();
+        if let Some(relative) = self.get(vhdx_defs::PARENT_KEY_RELATIVE_PATH) {
+            let relative = if cfg!(windows) {
+                relative.to_owned()
+            } else {
+                relative.replace('\\', "/")
+            };
+            let dir = path.parent().unwrap_or(Path::new("."));
+            paths.push(dir.join(relative));
+        }
+        if cfg!(windows) {
+            for key in [
+                vhdx_defs::PARENT_KEY_VOLUME_PATH,
+                vhdx_defs::PARENT_KEY_ABSOLUTE_WIN32_PATH,
+            ] {
+                if let Some(path) = self.get(key) {
+                    paths.push(path.into());
+                }
+            }
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VhdxDisk;
+    use disk_backend::Disk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use std::fs::File;
+    use vhdx_defs::MB;
+
+    const DISK_SIZE: u64 = 100 * MB;
+
+    async fn write(disk: &Disk, sector: u64, data: &[u8]) {
+        let mem = GuestMemory::allocate(data.len());
+        mem.write_at(0, data).unwrap();
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, data.len(), false).buffer(&mem),
+            sector,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn read(disk: &Disk, sector: u64, len: usize) -> Vec<u8> {
+        let mem = GuestMemory::allocate(len);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        let mut buf = vec![0; len];
+        mem.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    fn pattern(seed: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+    }
+
+    fn new_disk(file: &File) -> Disk {
+        VhdxDisk::create(file, DISK_SIZE).unwrap();
+        Disk::new(VhdxDisk::open(file.try_clone().unwrap(), None, false).unwrap()).unwrap()
+    }
+
+    #[async_test]
+    async fn read_write() {
+        let file = tempfile::tempfile().unwrap();
+        let disk = new_disk(&file);
+        assert_eq!(disk.sector_count(), DISK_SIZE / 512);
+
+        assert!(read(&disk, 0, 4096).await.iter().all(|&b| b == 0));
+
+        // Write across a block boundary.
+        let sector = (32 * MB - 4096) / 512;
+        let data = pattern(1, 8192);
+        write(&disk, sector, &data).await;
+        assert_eq!(read(&disk, sector, 8192).await, data);
+        assert!(read(&disk, sector + 16, 4096).await.iter().all(|&b| b == 0));
+        assert!(read(&disk, sector - 8, 4096).await.iter().all(|&b| b == 0));
+
+        // Overwrite part of an allocated block.
+        let data2 = pattern(2, 1024);
+        write(&disk, sector + 2, &data2).await;
+        let mut expected = data.clone();
+        expected[1024..2048].copy_from_slice(&data2);
+        assert_eq!(read(&disk, sector, 8192).await, expected);
+    }
+
+    #[async_test]
+    async fn reopen() {
+        let file = tempfile::tempfile().unwrap();
+        let disk = new_disk(&file);
+        let data = pattern(3, 65536);
+        write(&disk, 1000, &data).await;
+        write(&disk, (DISK_SIZE - 65536) / 512, &data).await;
+        drop(disk);
+
+        let disk =
+            Disk::new(VhdxDisk::open(file.try_clone().unwrap(), None, true).unwrap()).unwrap();
+        assert_eq!(read(&disk, 1000, 65536).await, data);
+        assert_eq!(read(&disk, (DISK_SIZE - 65536) / 512, 65536).await, data);
+        assert!(read(&disk, 0, 4096).await.iter().all(|&b| b == 0));
+    }
+
+    #[async_test]
+    async fn read_only() {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDisk::create(&file, DISK_SIZE).unwrap();
+        let disk = Disk::new(VhdxDisk::open(file, None, true).unwrap()).unwrap();
+        let mem = GuestMemory::allocate(512);
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, 512, false).buffer(&mem),
+            0,
+            false,
+        )
+        .await
+        .unwrap_err();
+    }
+
+    #[async_test]
+    async fn differencing() {
+        let dir = tempfile::tempdir().unwrap();
+        let parent_file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(dir.path().join("parent.vhdx"))
+            .unwrap();
+        let parent = new_disk(&parent_file);
+        let parent_data = pattern(4, 2 * MB as usize);
+        write(&parent, 0, &parent_data).await;
+        drop(parent);
+
+        let child_file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(dir.path().join("child.vhdx"))
+            .unwrap();
+        VhdxDisk::create_differencing(&child_file, &parent_file, "parent.vhdx").unwrap();
+
+        let info = super::info(&child_file).unwrap();
+        let locator = info.parent_locator.unwrap();
+        assert_eq!(
+            locator.parent_paths(&dir.path().join("child.vhdx")),
+            [dir.path().join("parent.vhdx")]
+        );
+        assert!(
+            locator
+                .linkage()
+                .eq([super::info(&parent_file).unwrap().data_write_guid])
+        );
+
+        let open_child = |read_only| {
+            let parent =
+                Disk::new(VhdxDisk::open(parent_file.try_clone().unwrap(), None, true).unwrap())
+                    .unwrap();
+            Disk::new(
+                VhdxDisk::open(child_file.try_clone().unwrap(), Some(parent), read_only).unwrap(),
+            )
+            .unwrap()
+        };
+
+        let child = open_child(false);
+        assert_eq!(read(&child, 0, parent_data.len()).await, parent_data);
+
+        let child_data = pattern(5, 4096);
+        write(&child, 16, &child_data).await;
+        write(&child, 1024, &child_data).await;
+        drop(child);
+
+        let child = open_child(true);
+        let mut expected = parent_data.clone();
+        expected[16 * 512..][..4096].copy_from_slice(&child_data);
+        expected[1024 * 512..][..4096].copy_from_slice(&child_data);
+        assert_eq!(read(&child, 0, expected.len()).await, expected);
+
+        assert!(matches!(
+            VhdxDisk::open(child_file.try_clone().unwrap(), None, true),
+            Err(super::OpenError::MissingParent)
+        ));
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VHDX metadata log.
+//!
+//! Updates to VHDX metadata (the BAT and sector bitmaps) are first written to
+//! a circular log, so that a crash part way through an update can be
+//! recovered by replaying the log the next time the file is opened.
+//!
+//! This implementation applies and flushes each log entry before writing the
+//! next one, so every entry it writes is its own tail, and replay only ever
+//! needs to apply the most recent entry.
+
+use crate::OpenError;
+use crate::io::read_exact_at;
+use crate::io::write_all_at;
+use guid::Guid;
+use std::collections::HashMap;
+use std::fs::File;
+use vhdx_defs::Header;
+use vhdx_defs::LOG_DESCRIPTOR_SIZE;
+use vhdx_defs::LOG_SECTOR_SIZE;
+use vhdx_defs::LogDataDescriptor;
+use vhdx_defs::LogDataSector;
+use vhdx_defs::LogEntryHeader;
+use vhdx_defs::LogZeroDescriptor;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// A 4KB page of metadata to write through the log.
+pub(crate) type Page = [u8; LOG_SECTOR_SIZE];
+
+/// An update described by a log entry.
+enum Update {
+    Data { file_offset: u64, data: Box<Page> },
+    Zero { file_offset: u64, len: u64 },
+}
+
+struct Entry {
+    sequence_number: u64,
+    length: u32,
+    tail: u32,
+    last_file_offset: u64,
+    updates: Vec<Update>,
+}
+
+/// The result of scanning the log for entries to replay.
+pub(crate) struct Replay {
+    entries: Vec<Entry>,
+}
+
+impl Replay {
+    /// Finds the active sequence of log entries described by `header`.
+    pub fn scan(file: &File, header: &Header) -> Result<Self, OpenError> {
+        if header.log_guid == Guid::ZERO {
+            return Ok(Self {
+                entries: Vec::new(),
+            });
+        }
+
+        let mut log = vec![0; header.log_length as usize];
+        read_exact_at(file, &mut log, header.log_offset)?;
+
+        let entries: HashMap<u32, Entry> = (0..log.len())
+            .step_by(LOG_SECTOR_SIZE)
+            .filter_map(|offset| {
+                parse_entry(&log[offset..], &header.log_guid).map(|entry| (offset as u32, entry))
+            })
+            .collect();
+
+        // Find the valid sequence with the highest sequence number. A
+        // sequence is a run of entries with incrementing sequence numbers,
+        // and is valid if the last entry's tail is within the sequence.
+        let mut best: Option<(u64, Vec<u32>)> = None;
+        for (&start, entry) in &entries {
+            let mut offsets = vec![start];
+            let mut last = entry;
+            loop {
+                let next = (offsets.last().unwrap() + last.length) % header.log_length;
+                match entries.get(&next) {
+                    Some(e)
+                        if e.sequence_number == last.sequence_number + 1
+                            && !offsets.contains(&next) =>
+                    {
+                        offsets.push(next);
+                        last = e;
+                    }
+                    _ => break,
+                }
+            }
+            let Some(tail_index) = offsets.iter().position(|&o| o == last.tail) else {
+                continue;
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(seq, _)| last.sequence_number > *seq)
+            {
+                best = Some((last.sequence_number, offsets.split_off(tail_index)));
+            }
+        }
+
+        let mut entries = entries;
+        let entries = best
+            .map(|(_, offsets)| {
+                offsets
+                    .into_iter()
+                    .map(|offset| entries.remove(&offset).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self { entries })
+    }
+
+    /// Returns true if applying the log would change the file.
+    pub fn is_needed(&self, file: &File) -> Result<bool, OpenError> {
+        let file_len = file.metadata()?.len();
+        for entry in &self.entries {
+            if file_len < entry.last_file_offset {
+                return Ok(true);
+            }
+            for update in &entry.updates {
+                let (file_offset, expected): (u64, &[u8]) = match update {
+                    Update::Data { file_offset, data } => (*file_offset, &data[..]),
+                    Update::Zero { file_offset, len } => {
+                        let mut buf = vec![0; *len as usize];
+                        read_exact_at(file, &mut buf, *file_offset)?;
+                        if buf.iter().any(|&b| b != 0) {
+                            return Ok(true);
+                        }
+                        continue;
+                    }
+                };
+                let mut buf = vec![0; expected.len()];
+                read_exact_at(file, &mut buf, file_offset)?;
+                if buf != expected {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Applies the log entries to the file and flushes it.
+    pub fn apply(&self, file: &File) -> Result<(), OpenError> {
+        for entry in &self.entries {
+            for update in &entry.updates {
+                match update {
+                    Update::Data { file_offset, data } => {
+                        write_all_at(file, &data[..], *file_offset)?;
+                    }
+                    Update::Zero { file_offset, len } => {
+                        write_all_at(file, &vec![0; *len as usize], *file_offset)?;
+                    }
+                }
+            }
+            if file.metadata()?.len() < entry.last_file_offset {
+                file.set_len(entry.last_file_offset)?;
+            }
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Parses and validates the log entry at the start of `data`.
+fn parse_entry(data: &[u8], log_guid: &Guid) -> Option<Entry> {
+    let (header, _) = LogEntryHeader::read_from_prefix(data).ok()?;
+    if header.signature != LogEntryHeader::SIGNATURE
+        || header.log_guid != *log_guid
+        || header.entry_length as usize % LOG_SECTOR_SIZE != 0
+        || header.entry_length == 0
+    {
+        return None;
+    }
+    let data = data.get(..header.entry_length as usize)?;
+    if header.checksum != vhdx_defs::compute_checksum(data) {
+        return None;
+    }
+
+    let descriptors_len = descriptor_area_len(header.descriptor_count as usize);
+    let descriptors = data.get(size_of::<LogEntryHeader>()..descriptors_len)?;
+    let mut sectors = data
+        .get(descriptors_len..)?
+        .chunks_exact(LOG_SECTOR_SIZE)
+        .map(|s| LogDataSector::read_from_bytes(s).unwrap());
+
+    let mut updates = Vec::new();
+    for descriptor in descriptors
+        .chunks_exact(LOG_DESCRIPTOR_SIZE)
+        .take(header.descriptor_count as usize)
+    {
+        let signature = u32::read_from_prefix(descriptor).ok()?.0;
+        if signature == LogDataDescriptor::SIGNATURE {
+            let desc = LogDataDescriptor::read_from_bytes(descriptor).ok()?;
+            let sector = sectors.next()?;
+            if desc.sequence_number != header.sequence_number
+                || sector.signature != LogDataSector::SIGNATURE
+                || sector.sequence_high != (header.sequence_number >> 32) as u32
+                || sector.sequence_low != header.sequence_number as u32
+            {
+                return None;
+            }
+            let mut page = Box::new([0; LOG_SECTOR_SIZE]);
+            page[..8].copy_from_slice(&desc.leading_bytes);
+            page[8..LOG_SECTOR_SIZE - 4].copy_from_slice(&sector.data);
+            page[LOG_SECTOR_SIZE - 4..].copy_from_slice(&desc.trailing_bytes);
+            updates.push(Update::Data {
+                file_offset: desc.file_offset,
+                data: page,
+            });
+        } else if signature == LogZeroDescriptor::SIGNATURE {
+            let desc = LogZeroDescriptor::read_from_bytes(descriptor).ok()?;
+            if desc.sequence_number != header.sequence_number {
+                return None;
+            }
+            updates.push(Update::Zero {
+                file_offset: desc.file_offset,
+                len: desc.zero_length,
+            });
+        } else {
+            return None;
+        }
+    }
+
+    Some(Entry {
+        sequence_number: header.sequence_number,
+        length: header.entry_length,
+        tail: header.tail,
+        last_file_offset: header.last_file_offset,
+        updates,
+    })
+}
+
+fn descriptor_area_len(count: usize) -> usize {
+    (size_of::<LogEntryHeader>() + count * LOG_DESCRIPTOR_SIZE).next_multiple_of(LOG_SECTOR_SIZE)
+}
+
+/// Writes entries to the log.
+pub(crate) struct LogWriter {
+    log_offset: u64,
+    log_length: u32,
+    log_guid: Guid,
+    head: u32,
+    sequence_number: u64,
+}
+
+impl LogWriter {
+    /// Returns a writer for an empty log identified by `log_guid`.
+    pub fn new(header: &Header, log_guid: Guid) -> Self {
+        Self {
+            log_offset: header.log_offset,
+            log_length: header.log_length,
+            log_guid,
+            head: 0,
+            sequence_number: 1,
+        }
+    }
+
+    /// Builds a log entry that writes `pages` to their file offsets, returning
+    /// the entry's file offset and contents.
+    ///
+    /// `file_size` is the size of the file once the update is applied.
+    pub fn build_entry(&mut self, pages: &[(u64, &Page)], file_size: u64) -> (u64, Vec<u8>) {
+        let descriptors_len = descriptor_area_len(pages.len());
+        let entry_length = descriptors_len + pages.len() * LOG_SECTOR_SIZE;
+        assert!(
+            entry_length <= self.log_length as usize,
+            "log entry too large"
+        );
+        if self.head as usize + entry_length > self.log_length as usize {
+            self.head = 0;
+        }
+
+        let sequence_number = self.sequence_number;
+        self.sequence_number += 1;
+
+        let mut entry = vec![0; entry_length];
+        let header = LogEntryHeader {
+            signature: LogEntryHeader::SIGNATURE,
+            checksum: 0,
+            entry_length: entry_length as u32,
+            tail: self.head,
+            sequence_number,
+            descriptor_count: pages.len() as u32,
+            reserved: 0,
+            log_guid: self.log_guid,
+            flushed_file_offset: file_size,
+            last_file_offset: file_size,
+        };
+        header.write_to_prefix(&mut entry).unwrap();
+
+        for (i, &(file_offset, page)) in pages.iter().enumerate() {
+            let descriptor = LogDataDescriptor {
+                signature: LogDataDescriptor::SIGNATURE,
+                trailing_bytes: page[LOG_SECTOR_SIZE - 4..].try_into().unwrap(),
+                leading_bytes: page[..8].try_into().unwrap(),
+                file_offset,
+                sequence_number,
+            };
+            let offset = size_of::<LogEntryHeader>() + i * LOG_DESCRIPTOR_SIZE;
+            descriptor.write_to_prefix(&mut entry[offset..]).unwrap();
+
+            let mut sector = LogDataSector::new_zeroed();
+            sector.signature = LogDataSector::SIGNATURE;
+            sector.sequence_high = (sequence_number >> 32) as u32;
+            sector.data.copy_from_slice(&page[8..LOG_SECTOR_SIZE - 4]);
+            sector.sequence_low = sequence_number as u32;
+            let offset = descriptors_len + i * LOG_SECTOR_SIZE;
+            entry[offset..offset + LOG_SECTOR_SIZE].copy_from_slice(sector.as_bytes());
+        }
+
+        let checksum = vhdx_defs::compute_checksum(&entry);
+        entry[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+        let offset = self.log_offset + self.head as u64;
+        self.head += entry_length as u32;
+        (offset, entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_latest_entry() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x300000).unwrap();
+        let mut header = Header::new_zeroed();
+        header.log_offset = 0x100000;
+        header.log_length = 0x100000;
+        header.log_guid = Guid::new_random();
+
+        let mut writer = LogWriter::new(&header, header.log_guid);
+        for i in 0..300u32 {
+            let page = [i as u8; LOG_SECTOR_SIZE];
+            let (offset, entry) =
+                writer.build_entry(&[(0x200000 + (i as u64 % 4) * 0x1000, &page)], 0x300000);
+            write_all_at(&file, &entry, offset).unwrap();
+        }
+
+        let replay = Replay::scan(&file, &header).unwrap();
+        assert_eq!(replay.entries.len(), 1);
+        assert_eq!(replay.entries[0].sequence_number, 300);
+        assert!(replay.is_needed(&file).unwrap());
+        replay.apply(&file).unwrap();
+        assert!(!replay.is_needed(&file).unwrap());
+
+        let mut buf = [0; LOG_SECTOR_SIZE];
+        read_exact_at(&file, &mut buf, 0x203000).unwrap();
+        assert!(buf.iter().all(|&b| b == 43));
+    }
+
+    #[test]
+    fn ignore_other_log_guid() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x300000).unwrap();
+        let mut header = Header::new_zeroed();
+        header.log_offset = 0x100000;
+        header.log_length = 0x100000;
+        header.log_guid = Guid::new_random();
+
+        let mut writer = LogWriter::new(&header, Guid::new_random());
+        let (offset, entry) = writer.build_entry(&[(0x200000, &[1; LOG_SECTOR_SIZE])], 0x300000);
+        write_all_at(&file, &entry, offset).unwrap();
+
+        let replay = Replay::scan(&file, &header).unwrap();
+        assert!(replay.entries.is_empty());
+    }
+}
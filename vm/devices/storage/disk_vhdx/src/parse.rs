@@ -0,0 +1,321 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Parsing of the VHDX headers, region table, and metadata.
+
+use crate::OpenError;
+use crate::io::read_exact_at;
+use guid::Guid;
+use std::fs::File;
+use vhdx_defs::FileIdentifier;
+use vhdx_defs::FileParameters;
+use vhdx_defs::Header;
+use vhdx_defs::MetadataTableEntry;
+use vhdx_defs::MetadataTableHeader;
+use vhdx_defs::ParentLocatorEntry;
+use vhdx_defs::ParentLocatorHeader;
+use vhdx_defs::RegionTableEntry;
+use vhdx_defs::RegionTableHeader;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The parsed, static layout of a VHDX file.
+pub(crate) struct VhdxFile {
+    /// The current header.
+    pub header: Header,
+    /// The index of the current header in [`vhdx_defs::HEADER_OFFSETS`].
+    pub header_index: usize,
+    pub bat_offset: u64,
+    pub bat_length: u32,
+    pub metadata: Metadata,
+}
+
+pub(crate) struct Metadata {
+    pub block_size: u32,
+    pub has_parent: bool,
+    pub disk_size: u64,
+    pub disk_id: Guid,
+    pub logical_sector_size: u32,
+    pub physical_sector_size: u32,
+    pub parent_locator: Option<ParentLocator>,
+}
+
+impl Metadata {
+    pub fn chunk_ratio(&self) -> u64 {
+        vhdx_defs::chunk_ratio(self.logical_sector_size, self.block_size)
+    }
+
+    /// The number of BAT entries required for the disk.
+    pub fn bat_entry_count(&self) -> u64 {
+        let chunk_ratio = self.chunk_ratio();
+        let data_blocks = self.disk_size.div_ceil(self.block_size as u64);
+        if self.has_parent {
+            data_blocks.div_ceil(chunk_ratio) * (chunk_ratio + 1)
+        } else {
+            data_blocks + data_blocks.saturating_sub(1) / chunk_ratio
+        }
+    }
+}
+
+/// The key/value pairs locating a differencing disk's parent.
+#[derive(Debug, Clone)]
+pub struct ParentLocator {
+    entries: Vec<(String, String)>,
+}
+
+impl ParentLocator {
+    /// Returns the value for `key`, if present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the data write GUIDs that the parent may have.
+    pub fn linkage(&self) -> impl Iterator<Item = Guid> + '_ {
+        [
+            vhdx_defs::PARENT_KEY_LINKAGE,
+            vhdx_defs::PARENT_KEY_LINKAGE2,
+        ]
+        .into_iter()
+        .filter_map(|key| self.get(key)?.parse().ok())
+    }
+}
+
+impl VhdxFile {
+    pub fn parse(file: &File) -> Result<Self, OpenError> {
+        let mut identifier = FileIdentifier::new_zeroed();
+        read_exact_at(
+            file,
+            identifier.as_mut_bytes(),
+            vhdx_defs::FILE_IDENTIFIER_OFFSET,
+        )?;
+        if identifier.signature != FileIdentifier::SIGNATURE {
+            return Err(OpenError::InvalidSignature);
+        }
+
+        let (header, header_index) = read_header(file)?;
+        let regions = read_region_table(file)?;
+
+        let mut bat = None;
+        let mut metadata = None;
+        for entry in regions {
+            match entry.guid {
+                vhdx_defs::REGION_BAT => bat = Some(entry),
+                vhdx_defs::REGION_METADATA => metadata = Some(entry),
+                guid if entry.flags & RegionTableEntry::FLAG_REQUIRED != 0 => {
+                    return Err(OpenError::UnknownRequiredRegion(guid));
+                }
+                _ => {}
+            }
+        }
+        let bat = bat.ok_or(OpenError::MissingRegion("BAT"))?;
+        let metadata_region = metadata.ok_or(OpenError::MissingRegion("metadata"))?;
+        let metadata = read_metadata(file, &metadata_region)?;
+
+        if metadata.bat_entry_count() * 8 > bat.length as u64 {
+            return Err(OpenError::InvalidMetadata("BAT region too small"));
+        }
+
+        Ok(Self {
+            header,
+            header_index,
+            bat_offset: bat.file_offset,
+            bat_length: bat.length,
+            metadata,
+        })
+    }
+}
+
+/// Returns the current header, which is the valid one with the highest
+/// sequence number.
+fn read_header(file: &File) -> Result<(Header, usize), OpenError> {
+    let mut current: Option<(Header, usize)> = None;
+    for (index, &offset) in vhdx_defs::HEADER_OFFSETS.iter().enumerate() {
+        let mut header = Header::new_zeroed();
+        read_exact_at(file, header.as_mut_bytes(), offset)?;
+        if header.signature != Header::SIGNATURE
+            || header.checksum != vhdx_defs::compute_checksum(header.as_bytes())
+        {
+            continue;
+        }
+        if current
+            .as_ref()
+            .is_none_or(|(cur, _)| header.sequence_number > cur.sequence_number)
+        {
+            current = Some((header, index));
+        }
+    }
+    let (header, index) = current.ok_or(OpenError::NoValidHeader)?;
+    if header.version != Header::VERSION {
+        return Err(OpenError::UnsupportedVersion(header.version));
+    }
+    if header.log_version != Header::LOG_VERSION
+        || header.log_offset % vhdx_defs::ALIGNMENT != 0
+        || header.log_length as u64 % vhdx_defs::ALIGNMENT != 0
+    {
+        return Err(OpenError::InvalidLog("invalid log location"));
+    }
+    Ok((header, index))
+}
+
+fn read_region_table(file: &File) -> Result<Vec<RegionTableEntry>, OpenError> {
+    let mut buf = vec![0; vhdx_defs::REGION_TABLE_SIZE];
+    for offset in vhdx_defs::REGION_TABLE_OFFSETS {
+        read_exact_at(file, &mut buf, offset)?;
+        let (header, rest) = RegionTableHeader::read_from_prefix(&buf).unwrap();
+        if header.signature != RegionTableHeader::SIGNATURE
+            || header.checksum != vhdx_defs::compute_checksum(&buf)
+            || header.entry_count > RegionTableHeader::MAX_ENTRIES
+        {
+            continue;
+        }
+        let entries = read_array::<RegionTableEntry>(rest, header.entry_count as usize)
+            .ok_or(OpenError::NoValidRegionTable)?;
+        for entry in &entries {
+            if entry.file_offset % vhdx_defs::ALIGNMENT != 0
+                || entry.length as u64 % vhdx_defs::ALIGNMENT != 0
+            {
+                return Err(OpenError::InvalidMetadata("misaligned region"));
+            }
+        }
+        return Ok(entries);
+    }
+    Err(OpenError::NoValidRegionTable)
+}
+
+fn read_metadata(file: &File, region: &RegionTableEntry) -> Result<Metadata, OpenError> {
+    let mut buf = vec![0; region.length as usize];
+    read_exact_at(file, &mut buf, region.file_offset)?;
+    let (header, rest) =
+        MetadataTableHeader::read_from_prefix(&buf).map_err(|_| OpenError::InvalidMetadataTable)?;
+    if header.signature != MetadataTableHeader::SIGNATURE
+        || header.entry_count > MetadataTableHeader::MAX_ENTRIES
+    {
+        return Err(OpenError::InvalidMetadataTable);
+    }
+    let entries = read_array::<MetadataTableEntry>(rest, header.entry_count as usize)
+        .ok_or(OpenError::InvalidMetadataTable)?;
+
+    let item = |entry: &MetadataTableEntry| {
+        buf.get(entry.offset as usize..)
+            .and_then(|b| b.get(..entry.length as usize))
+            .ok_or(OpenError::InvalidMetadataTable)
+    };
+
+    let mut file_parameters = None;
+    let mut disk_size = None;
+    let mut disk_id = None;
+    let mut logical_sector_size = None;
+    let mut physical_sector_size = None;
+    let mut parent_locator = None;
+    for entry in &entries {
+        if entry.flags & MetadataTableEntry::FLAG_IS_USER != 0 {
+            continue;
+        }
+        let data = item(entry)?;
+        match entry.item_id {
+            vhdx_defs::METADATA_FILE_PARAMETERS => {
+                file_parameters = Some(read_item::<FileParameters>(data)?);
+            }
+            vhdx_defs::METADATA_VIRTUAL_DISK_SIZE => disk_size = Some(read_item::<u64>(data)?),
+            vhdx_defs::METADATA_VIRTUAL_DISK_ID => disk_id = Some(read_item::<Guid>(data)?),
+            vhdx_defs::METADATA_LOGICAL_SECTOR_SIZE => {
+                logical_sector_size = Some(read_item::<u32>(data)?);
+            }
+            vhdx_defs::METADATA_PHYSICAL_SECTOR_SIZE => {
+                physical_sector_size = Some(read_item::<u32>(data)?);
+            }
+            vhdx_defs::METADATA_PARENT_LOCATOR => {
+                parent_locator = Some(read_parent_locator(data)?);
+            }
+            guid if entry.flags & MetadataTableEntry::FLAG_IS_REQUIRED != 0 => {
+                return Err(OpenError::UnknownRequiredMetadata(guid));
+            }
+            _ => {}
+        }
+    }
+
+    let file_parameters = file_parameters.ok_or(OpenError::MissingMetadata("file parameters"))?;
+    let metadata = Metadata {
+        block_size: file_parameters.block_size,
+        has_parent: file_parameters.flags & FileParameters::FLAG_HAS_PARENT != 0,
+        disk_size: disk_size.ok_or(OpenError::MissingMetadata("virtual disk size"))?,
+        disk_id: disk_id.ok_or(OpenError::MissingMetadata("virtual disk id"))?,
+        logical_sector_size: logical_sector_size
+            .ok_or(OpenError::MissingMetadata("logical sector size"))?,
+        physical_sector_size: physical_sector_size
+            .ok_or(OpenError::MissingMetadata("physical sector size"))?,
+        parent_locator,
+    };
+
+    if !metadata.block_size.is_power_of_two()
+        || !(vhdx_defs::MIN_BLOCK_SIZE..=vhdx_defs::MAX_BLOCK_SIZE).contains(&metadata.block_size)
+    {
+        return Err(OpenError::InvalidMetadata("invalid block size"));
+    }
+    if !matches!(metadata.logical_sector_size, 512 | 4096)
+        || !matches!(metadata.physical_sector_size, 512 | 4096)
+    {
+        return Err(OpenError::InvalidMetadata("invalid sector size"));
+    }
+    if metadata.disk_size == 0 || metadata.disk_size % metadata.logical_sector_size as u64 != 0 {
+        return Err(OpenError::InvalidDiskSize(metadata.disk_size));
+    }
+    if metadata.has_parent != metadata.parent_locator.is_some() {
+        return Err(OpenError::InvalidParentLocator);
+    }
+    Ok(metadata)
+}
+
+/// Reads `count` unaligned `T`s from the start of `data`.
+fn read_array<T: FromBytes>(data: &[u8], count: usize) -> Option<Vec<T>> {
+    data.get(..count * size_of::<T>())?
+        .chunks_exact(size_of::<T>())
+        .map(|chunk| T::read_from_bytes(chunk).ok())
+        .collect()
+}
+
+fn read_item<T: FromBytes>(data: &[u8]) -> Result<T, OpenError> {
+    T::read_from_prefix(data)
+        .map(|(v, _)| v)
+        .map_err(|_| OpenError::InvalidMetadata("metadata item too small"))
+}
+
+fn read_parent_locator(data: &[u8]) -> Result<ParentLocator, OpenError> {
+    let (header, rest) =
+        ParentLocatorHeader::read_from_prefix(data).map_err(|_| OpenError::InvalidParentLocator)?;
+    if header.locator_type != vhdx_defs::PARENT_LOCATOR_VHDX {
+        return Err(OpenError::InvalidParentLocator);
+    }
+    let entries = read_array::<ParentLocatorEntry>(rest, header.key_value_count as usize)
+        .ok_or(OpenError::InvalidParentLocator)?;
+
+    let string = |offset: u32, len: u16| {
+        let bytes = data
+            .get(offset as usize..)
+            .and_then(|b| b.get(..len as usize))
+            .ok_or(OpenError::InvalidParentLocator)?;
+        if bytes.len() % 2 != 0 {
+            return Err(OpenError::InvalidParentLocator);
+        }
+        let utf16: Vec<_> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16(&utf16).map_err(|_| OpenError::InvalidParentLocator)
+    };
+
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            Ok((
+                string(entry.key_offset, entry.key_length)?,
+                string(entry.value_offset, entry.value_length)?,
+            ))
+        })
+        .collect::<Result<_, OpenError>>()?;
+    Ok(ParentLocator { entries })
+}
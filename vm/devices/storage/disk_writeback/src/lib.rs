@@ -0,0 +1,444 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk wrapper that caches guest writes in memory and writes them back to
+//! the underlying disk in batches, coalescing adjacent sectors into larger
+//! writes.
+//!
+//! Dirty data is written back when the cache fills, when the guest issues a
+//! FLUSH (or a FUA write), and optionally on a periodic timer. Data that has
+//! not been written back is lost if the VM is torn down without a guest
+//! flush, just as with a volatile drive cache.
+
+#![forbid(unsafe_code)]
+
+/// Provides a disk with a write-back cache.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use guestmem::GuestMemory;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use inspect_counters::Counter;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_buffers::RequestBuffers;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// The maximum size of a single coalesced write-back.
+const MAX_WRITE_BACK_SIZE: usize = 1 << 20;
+
+/// A disk with a write-back cache.
+#[derive(Inspect)]
+pub struct WriteBackCacheDisk {
+    #[inspect(flatten)]
+    state: Arc<CacheState>,
+    #[inspect(with = "|x| inspect::AsDebug(x.as_ref().map(|(d, _)| d))")]
+    flush_interval: Option<(Duration, Task<()>)>,
+}
+
+#[derive(Inspect)]
+struct CacheState {
+    inner: Disk,
+    capacity: u64,
+    #[inspect(flatten)]
+    dirty: Mutex<Dirty>,
+    /// Serializes write-backs, so that older data is never written over newer
+    /// data.
+    #[inspect(skip)]
+    write_back_lock: futures::lock::Mutex<()>,
+}
+
+#[derive(Default, Inspect)]
+struct Dirty {
+    #[inspect(skip)]
+    sectors: BTreeMap<u64, DirtySector>,
+    #[inspect(rename = "dirty_bytes")]
+    bytes: u64,
+    #[inspect(skip)]
+    next_seq: u64,
+    write_backs: Counter,
+    writes_issued: Counter,
+}
+
+impl Dirty {
+    fn insert(&mut self, sector: u64, data: Arc<[u8]>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let len = data.len() as u64;
+        if self
+            .sectors
+            .insert(sector, DirtySector { data, seq })
+            .is_none()
+        {
+            self.bytes += len;
+        }
+    }
+
+    fn remove_range(&mut self, start: u64, end: u64) {
+        let sectors = self
+            .sectors
+            .range(start..end)
+            .map(|(&sector, _)| sector)
+            .collect::<Vec<_>>();
+        for sector in sectors {
+            let entry = self.sectors.remove(&sector).unwrap();
+            self.bytes -= entry.data.len() as u64;
+        }
+    }
+}
+
+#[derive(Clone)]
+struct DirtySector {
+    data: Arc<[u8]>,
+    /// Incremented on each write, to detect sectors that were rewritten while
+    /// being written back.
+    seq: u64,
+}
+
+impl WriteBackCacheDisk {
+    /// Creates a new disk caching up to `cache_size` bytes of writes to
+    /// `inner`.
+    ///
+    /// If `flush_interval` is set, dirty data is also written back
+    /// periodically.
+    pub fn new(
+        inner: Disk,
+        cache_size: u64,
+        flush_interval: Option<Duration>,
+        driver_source: &VmTaskDriverSource,
+    ) -> Self {
+        let state = Arc::new(CacheState {
+            inner,
+            capacity: cache_size,
+            dirty: Mutex::new(Dirty::default()),
+            write_back_lock: futures::lock::Mutex::new(()),
+        });
+        let flush_interval = flush_interval.map(|interval| {
+            let driver = driver_source.simple();
+            let state = Arc::downgrade(&state);
+            let task = driver.clone().spawn("disk-writeback", async move {
+                let mut timer = PolledTimer::new(&driver);
+                loop {
+                    timer.sleep(interval).await;
+                    let Some(state) = state.upgrade() else {
+                        break;
+                    };
+                    if let Err(err) = state.write_back(false).await {
+                        tracelimit::error_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "periodic write-back failed"
+                        );
+                    }
+                }
+            });
+            (interval, task)
+        });
+        Self {
+            state,
+            flush_interval,
+        }
+    }
+}
+
+impl CacheState {
+    /// Writes back all dirty data to the inner disk.
+    async fn write_back(&self, fua: bool) -> Result<(), DiskError> {
+        let _guard = self.write_back_lock.lock().await;
+        let entries = self
+            .dirty
+            .lock()
+            .sectors
+            .iter()
+            .map(|(&sector, entry)| (sector, entry.clone()))
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // Coalesce runs of contiguous sectors into single writes.
+        let sector_size = self.inner.sector_size() as usize;
+        let mut runs: Vec<(u64, Vec<u8>)> = Vec::new();
+        for (sector, entry) in &entries {
+            match runs.last_mut() {
+                Some((start, buf))
+                    if *start + (buf.len() / sector_size) as u64 == *sector
+                        && buf.len() < MAX_WRITE_BACK_SIZE =>
+                {
+                    buf.extend_from_slice(&entry.data);
+                }
+                _ => runs.push((*sector, entry.data.to_vec())),
+            }
+        }
+
+        let writes_issued = runs.len() as u64;
+        futures::future::try_join_all(
+            runs.into_iter()
+                .map(|(sector, buf)| async move { self.write_inner(sector, &buf, fua).await }),
+        )
+        .await?;
+
+        // Drop the entries that were written back, unless they were rewritten
+        // in the meantime.
+        let mut dirty = self.dirty.lock();
+        for (sector, entry) in entries {
+            if dirty
+                .sectors
+                .get(&sector)
+                .is_some_and(|current| current.seq == entry.seq)
+            {
+                dirty.sectors.remove(&sector);
+                dirty.bytes -= entry.data.len() as u64;
+            }
+        }
+        dirty.write_backs.increment();
+        dirty.writes_issued.add(writes_issued);
+        Ok(())
+    }
+
+    async fn write_inner(&self, sector: u64, buf: &[u8], fua: bool) -> Result<(), DiskError> {
+        let mut mem = GuestMemory::allocate(buf.len());
+        mem.inner_buf_mut().unwrap().copy_from_slice(buf);
+        self.inner
+            .write_vectored(
+                &OwnedRequestBuffers::linear(0, buf.len(), false).buffer(&mem),
+                sector,
+                fua,
+            )
+            .await
+    }
+}
+
+impl DiskIo for WriteBackCacheDisk {
+    fn disk_type(&self) -> &str {
+        "writeback"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.state.inner.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.state.inner.sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.state.inner.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.state.inner.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.state.inner.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.state.inner.is_read_only()
+    }
+
+    fn pr(&self) -> Option<&dyn disk_backend::pr::PersistentReservation> {
+        self.state.inner.pr()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let shift = self.state.inner.sector_shift();
+        let count = (buffers.len() >> shift) as u64;
+
+        // Snapshot the dirty sectors before reading the inner disk. Any
+        // sector written back after this point is still in the snapshot, so
+        // the result is consistent regardless of concurrent write-backs.
+        let overlay = self
+            .state
+            .dirty
+            .lock()
+            .sectors
+            .range(sector..sector + count)
+            .map(|(&sector, entry)| (sector, entry.data.clone()))
+            .collect::<Vec<_>>();
+
+        if overlay.len() as u64 != count {
+            self.state.inner.read_vectored(buffers, sector).await?;
+        }
+        for (dirty_sector, data) in overlay {
+            buffers
+                .subrange(((dirty_sector - sector) << shift) as usize, data.len())
+                .writer()
+                .write(&data)?;
+        }
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        let shift = self.state.inner.sector_shift();
+        let count = (buffers.len() >> shift) as u64;
+        if self.is_read_only() {
+            return Err(DiskError::ReadOnly);
+        }
+        if sector
+            .checked_add(count)
+            .is_none_or(|end| end > self.sector_count())
+        {
+            return Err(DiskError::IllegalBlock);
+        }
+
+        let mut buf = vec![0; buffers.len()];
+        buffers.reader().read(&mut buf)?;
+
+        // Writes too large to cache bypass it, after discarding any older
+        // cached data for the range.
+        if buf.len() as u64 > self.state.capacity {
+            let _guard = self.state.write_back_lock.lock().await;
+            self.state.dirty.lock().remove_range(sector, sector + count);
+            return self.state.write_inner(sector, &buf, fua).await;
+        }
+
+        let full = {
+            let mut dirty = self.state.dirty.lock();
+            for (i, data) in buf.chunks_exact(1 << shift).enumerate() {
+                dirty.insert(sector + i as u64, data.into());
+            }
+            dirty.bytes > self.state.capacity
+        };
+        if fua || full {
+            self.state.write_back(fua).await?;
+        }
+        Ok(())
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.state.write_back(false).await?;
+        self.state.inner.sync_cache().await
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.state.inner.wait_resize(sector_count).await
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        // Discard cached data for the range so that it is not written back
+        // over the unmapped sectors.
+        let _guard = self.state.write_back_lock.lock().await;
+        self.state
+            .dirty
+            .lock()
+            .remove_range(sector, sector.saturating_add(count));
+        self.state
+            .inner
+            .unmap(sector, count, block_level_only)
+            .await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        self.state.inner.unmap_behavior()
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.state.inner.optimal_unmap_sectors()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteBackCacheDisk;
+    use disk_backend::Disk;
+    use guestmem::GuestMemory;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+
+    async fn write(disk: &Disk, sector: u64, data: &[u8], fua: bool) {
+        let mut mem = GuestMemory::allocate(data.len());
+        mem.inner_buf_mut().unwrap().copy_from_slice(data);
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, data.len(), false).buffer(&mem),
+            sector,
+            fua,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn read(disk: &Disk, sector: u64, len: usize) -> Vec<u8> {
+        let mut mem = GuestMemory::allocate(len);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        mem.inner_buf_mut().unwrap().to_vec()
+    }
+
+    #[async_test]
+    async fn write_back(driver: DefaultDriver) {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+        let inner = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        let disk = Disk::new(WriteBackCacheDisk::new(
+            inner.clone(),
+            0x4000,
+            None,
+            &driver_source,
+        ))
+        .unwrap();
+
+        // Cached writes are visible through the cache but not yet in the
+        // inner disk.
+        write(&disk, 4, &[1; 1024], false).await;
+        write(&disk, 6, &[2; 1024], false).await;
+        assert_eq!(read(&disk, 3, 2048).await, {
+            let mut v = vec![0; 512];
+            v.extend([1; 1024]);
+            v.extend([2; 512]);
+            v
+        });
+        assert_eq!(read(&inner, 4, 512).await, [0; 512]);
+
+        // A flush writes back all dirty data.
+        disk.sync_cache().await.unwrap();
+        assert_eq!(read(&inner, 4, 2048).await, [[1; 1024], [2; 1024]].concat());
+
+        // FUA writes are written back immediately.
+        write(&disk, 100, &[3; 512], true).await;
+        assert_eq!(read(&inner, 100, 512).await, [3; 512]);
+
+        // Filling the cache writes it back.
+        write(&disk, 200, &[4; 0x4000], false).await;
+        assert_eq!(read(&inner, 200, 512).await, [0; 512]);
+        write(&disk, 300, &[5; 512], false).await;
+        assert_eq!(read(&inner, 200, 0x4000).await, [4; 0x4000]);
+
+        // Writes larger than the cache bypass it, replacing cached data.
+        write(&disk, 400, &[6; 512], false).await;
+        write(&disk, 400, &[7; 0x8000], false).await;
+        disk.sync_cache().await.unwrap();
+        assert_eq!(read(&inner, 400, 512).await, [7; 512]);
+    }
+}
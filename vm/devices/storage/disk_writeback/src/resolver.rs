@@ -0,0 +1,42 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::WriteBackCacheDisk;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::WriteBackCacheDiskHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for WriteBackCacheDisk.
+pub struct WriteBackCacheDiskResolver;
+declare_static_async_resolver!(
+    WriteBackCacheDiskResolver,
+    (DiskHandleKind, WriteBackCacheDiskHandle)
+);
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, WriteBackCacheDiskHandle> for WriteBackCacheDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: WriteBackCacheDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver.resolve(rsrc.disk, input).await?;
+
+        ResolvedDisk::new(WriteBackCacheDisk::new(
+            inner.0,
+            rsrc.cache_size,
+            rsrc.flush_interval,
+            input.driver_source,
+        ))
+        .map_err(|e| anyhow::anyhow!("failed to create the write-back cache disk: {}", e))
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A read-only disk backed by a file in the [zstd seekable format], which
+//! splits the image into independently compressed frames followed by a seek
+//! table. Frames are decompressed on demand and kept in an LRU cache.
+//!
+//! Seek table checksums are not validated, but zstd validates each frame's
+//! content checksum if present.
+//!
+//! [zstd seekable format]:
+//!     https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use blocking::unblock;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+
+const SEEKABLE_MAGIC: u32 = 0x8f92eab1;
+const SKIPPABLE_MAGIC: u32 = 0x184d2a5e;
+const FOOTER_SIZE: usize = 9;
+const SKIPPABLE_HEADER_SIZE: usize = 8;
+const SECTOR_SIZE: u32 = 512;
+
+/// The default maximum number of decompressed bytes to cache.
+const DEFAULT_CACHE_SIZE: usize = 64 << 20;
+
+/// A read-only disk backed by a zstd seekable file.
+#[derive(Inspect)]
+pub struct ZstdDisk {
+    #[inspect(skip)]
+    file: Arc<File>,
+    #[inspect(with = "Vec::len")]
+    frames: Vec<Frame>,
+    disk_size: u64,
+    cache: Mutex<FrameCache>,
+}
+
+struct Frame {
+    compressed_offset: u64,
+    compressed_size: u32,
+    decompressed_offset: u64,
+    decompressed_size: u32,
+}
+
+/// An LRU cache of decompressed frames.
+#[derive(Inspect)]
+struct FrameCache {
+    capacity: usize,
+    size: usize,
+    hits: u64,
+    misses: u64,
+    /// The cached frames, from least to most recently used.
+    #[inspect(skip)]
+    entries: Vec<(usize, Arc<[u8]>)>,
+}
+
+impl FrameCache {
+    fn get(&mut self, index: usize) -> Option<Arc<[u8]>> {
+        let Some(i) = self.entries.iter().position(|(n, _)| *n == index) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        let entry = self.entries.remove(i);
+        let data = entry.1.clone();
+        self.entries.push(entry);
+        Some(data)
+    }
+
+    fn insert(&mut self, index: usize, data: Arc<[u8]>) {
+        if self.entries.iter().any(|(n, _)| *n == index) {
+            return;
+        }
+        self.size += data.len();
+        self.entries.push((index, data));
+        while self.size > self.capacity && self.entries.len() > 1 {
+            let (_, evicted) = self.entries.remove(0);
+            self.size -= evicted.len();
+        }
+    }
+}
+
+/// An error returned by [`ZstdDisk::open`].
+#[derive(Debug, Error)]
+pub enum NewZstdDiskError {
+    /// An I/O error occurred reading the seek table.
+    #[error("failed to read seek table")]
+    Io(#[source] io::Error),
+    /// The file does not end with a valid seek table.
+    #[error("invalid seek table: {0}")]
+    InvalidSeekTable(&'static str),
+    /// The decompressed size is not a multiple of the sector size.
+    #[error("decompressed size {0:#x} is not a multiple of the sector size")]
+    UnalignedSize(u64),
+}
+
+impl ZstdDisk {
+    /// Opens a zstd seekable file as a disk.
+    pub fn open(file: File) -> Result<Self, NewZstdDiskError> {
+        let file_len = file.metadata().map_err(NewZstdDiskError::Io)?.len();
+        let mut footer = [0; FOOTER_SIZE];
+        let footer_offset = file_len
+            .checked_sub(FOOTER_SIZE as u64)
+            .ok_or(NewZstdDiskError::InvalidSeekTable("file too small"))?;
+        read_exact_at(&file, &mut footer, footer_offset).map_err(NewZstdDiskError::Io)?;
+        let frame_count = u32::from_le_bytes(footer[..4].try_into().unwrap());
+        let descriptor = footer[4];
+        if u32::from_le_bytes(footer[5..].try_into().unwrap()) != SEEKABLE_MAGIC {
+            return Err(NewZstdDiskError::InvalidSeekTable("bad footer magic"));
+        }
+        if descriptor & 0x7c != 0 {
+            return Err(NewZstdDiskError::InvalidSeekTable("reserved bits set"));
+        }
+        let entry_size = if descriptor & 0x80 != 0 { 12 } else { 8 };
+
+        let table_size = frame_count as u64 * entry_size + FOOTER_SIZE as u64;
+        let table_offset = file_len
+            .checked_sub(table_size + SKIPPABLE_HEADER_SIZE as u64)
+            .ok_or(NewZstdDiskError::InvalidSeekTable("file too small"))?;
+        let mut table = vec![0; SKIPPABLE_HEADER_SIZE + table_size as usize - FOOTER_SIZE];
+        read_exact_at(&file, &mut table, table_offset).map_err(NewZstdDiskError::Io)?;
+        if u32::from_le_bytes(table[..4].try_into().unwrap()) != SKIPPABLE_MAGIC
+            || u32::from_le_bytes(table[4..8].try_into().unwrap()) as u64 != table_size
+        {
+            return Err(NewZstdDiskError::InvalidSeekTable("bad skippable frame"));
+        }
+
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        let mut compressed_offset = 0;
+        let mut decompressed_offset = 0;
+        for entry in table[SKIPPABLE_HEADER_SIZE..].chunks_exact(entry_size as usize) {
+            let compressed_size = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let decompressed_size = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            frames.push(Frame {
+                compressed_offset,
+                compressed_size,
+                decompressed_offset,
+                decompressed_size,
+            });
+            compressed_offset += compressed_size as u64;
+            decompressed_offset += decompressed_size as u64;
+        }
+        if compressed_offset != table_offset {
+            return Err(NewZstdDiskError::InvalidSeekTable(
+                "frame sizes do not match file size",
+            ));
+        }
+        if decompressed_offset % SECTOR_SIZE as u64 != 0 {
+            return Err(NewZstdDiskError::UnalignedSize(decompressed_offset));
+        }
+
+        Ok(Self {
+            file: Arc::new(file),
+            frames,
+            disk_size: decompressed_offset,
+            cache: Mutex::new(FrameCache {
+                capacity: DEFAULT_CACHE_SIZE,
+                size: 0,
+                hits: 0,
+                misses: 0,
+                entries: Vec::new(),
+            }),
+        })
+    }
+
+    /// Returns the decompressed contents of frame `index`.
+    async fn frame_data(&self, index: usize) -> Result<Arc<[u8]>, DiskError> {
+        if let Some(data) = self.cache.lock().get(index) {
+            return Ok(data);
+        }
+        let frame = &self.frames[index];
+        let file = self.file.clone();
+        let offset = frame.compressed_offset;
+        let compressed_size = frame.compressed_size as usize;
+        let decompressed_size = frame.decompressed_size as usize;
+        let data = unblock(move || -> io::Result<Vec<u8>> {
+            let mut compressed = vec![0; compressed_size];
+            read_exact_at(&file, &mut compressed, offset)?;
+            let data = zstd::bulk::decompress(&compressed, decompressed_size)?;
+            if data.len() != decompressed_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "decompressed frame size does not match seek table",
+                ));
+            }
+            Ok(data)
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        let data: Arc<[u8]> = data.into();
+        self.cache.lock().insert(index, data.clone());
+        Ok(data)
+    }
+}
+
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+impl DiskIo for ZstdDisk {
+    fn disk_type(&self) -> &str {
+        "zstd"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.disk_size / SECTOR_SIZE as u64
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        false
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let mut offset = sector * SECTOR_SIZE as u64;
+        let end = offset + buffers.len() as u64;
+        if end > self.disk_size {
+            return Err(DiskError::IllegalBlock);
+        }
+        let mut writer = buffers.writer();
+        while offset < end {
+            let index = self.frames.partition_point(|frame| {
+                frame.decompressed_offset + frame.decompressed_size as u64 <= offset
+            });
+            let frame = &self.frames[index];
+            let data = self.frame_data(index).await?;
+            let start = (offset - frame.decompressed_offset) as usize;
+            let len = (data.len() - start).min((end - offset) as usize);
+            writer.write(&data[start..][..len])?;
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        _buffers: &RequestBuffers<'_>,
+        _sector: u64,
+        _fua: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        Ok(())
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SEEKABLE_MAGIC;
+    use super::SKIPPABLE_MAGIC;
+    use super::ZstdDisk;
+    use disk_backend::Disk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use std::io::Write;
+
+    /// Writes `data` to a new seekable file, compressing each `frame_size`
+    /// chunk as an independent frame.
+    fn seekable_file(data: &[u8], frame_size: usize, checksums: bool) -> std::fs::File {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut table = Vec::new();
+        let chunks = data.chunks(frame_size);
+        let frame_count = chunks.len() as u32;
+        for chunk in chunks {
+            let compressed = zstd::bulk::compress(chunk, 3).unwrap();
+            file.write_all(&compressed).unwrap();
+            table.extend((compressed.len() as u32).to_le_bytes());
+            table.extend((chunk.len() as u32).to_le_bytes());
+            if checksums {
+                table.extend([0; 4]);
+            }
+        }
+        table.extend(frame_count.to_le_bytes());
+        table.push(if checksums { 0x80 } else { 0 });
+        table.extend(SEEKABLE_MAGIC.to_le_bytes());
+        file.write_all(&SKIPPABLE_MAGIC.to_le_bytes()).unwrap();
+        file.write_all(&(table.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&table).unwrap();
+        file
+    }
+
+    #[async_test]
+    async fn read_across_frames() {
+        let data = (0..0x30000u32)
+            .map(|i| (i / 1000) as u8)
+            .collect::<Vec<_>>();
+        for checksums in [false, true] {
+            let disk = Disk::new(ZstdDisk::open(seekable_file(&data, 0x7000, checksums)).unwrap())
+                .unwrap();
+            assert!(disk.is_read_only());
+            assert_eq!(disk.sector_count(), data.len() as u64 / 512);
+
+            let mut mem = GuestMemory::allocate(0x10000);
+            disk.read_vectored(
+                &OwnedRequestBuffers::linear(0, 0x10000, true).buffer(&mem),
+                0x35,
+            )
+            .await
+            .unwrap();
+            assert_eq!(mem.inner_buf_mut().unwrap(), &data[0x35 * 512..][..0x10000]);
+
+            // Reads past the end fail.
+            assert!(
+                disk.read_vectored(
+                    &OwnedRequestBuffers::linear(0, 0x1000, true).buffer(&mem),
+                    disk.sector_count() - 1,
+                )
+                .await
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_seek_table() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[0; 4096]).unwrap();
+        assert!(ZstdDisk::open(file).is_err());
+    }
+}
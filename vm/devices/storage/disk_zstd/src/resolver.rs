@@ -0,0 +1,41 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::NewZstdDiskError;
+use crate::ZstdDisk;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::ZstdDiskHandle;
+use thiserror::Error;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for [`ZstdDisk`].
+pub struct ZstdDiskResolver;
+declare_static_resolver!(ZstdDiskResolver, (DiskHandleKind, ZstdDiskHandle));
+
+/// An error that occurred while resolving a [`ZstdDiskHandle`].
+#[derive(Debug, Error)]
+pub enum ResolveZstdDiskError {
+    /// Failed to open the disk.
+    #[error("failed to open zstd disk")]
+    Open(#[source] NewZstdDiskError),
+    /// The disk is invalid.
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+impl ResolveResource<DiskHandleKind, ZstdDiskHandle> for ZstdDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveZstdDiskError;
+
+    fn resolve(
+        &self,
+        rsrc: ZstdDiskHandle,
+        _input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        ResolvedDisk::new(ZstdDisk::open(rsrc.0).map_err(ResolveZstdDiskError::Open)?)
+            .map_err(ResolveZstdDiskError::InvalidDisk)
+    }
+}
@@ -62,6 +62,85 @@ struct RamState {
     #[inspect(skip)] // handled in inspect_extra()
     sector_count: u64,
     zero_after: u64,
+    /// Non-present sectors before `zero_after` that read as zero because they
+    /// have been unmapped.
+    #[inspect(rename = "zero_ranges", with = "|x| x.0.len()")]
+    zero: ZeroRanges,
+}
+
+impl RamState {
+    /// Returns the ranges within `start..end` whose non-present sectors read
+    /// as zero rather than falling through to the next layer.
+    fn zero_ranges(&self, start: u64, end: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let unmapped = (start < self.zero_after.min(end))
+            .then(|| self.zero.intersect(start, self.zero_after.min(end)))
+            .into_iter()
+            .flatten();
+        let truncated = (self.zero_after < end).then(|| (start.max(self.zero_after), end));
+        unmapped.chain(truncated)
+    }
+}
+
+/// A set of sector ranges, stored as a map from the start of each range to its
+/// end.
+#[derive(Default)]
+struct ZeroRanges(BTreeMap<u64, u64>);
+
+impl ZeroRanges {
+    fn contains(&self, sector: u64) -> bool {
+        self.0
+            .range(..=sector)
+            .next_back()
+            .is_some_and(|(_, &end)| sector < end)
+    }
+
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        // Merge with a range that overlaps or abuts the new one from below.
+        if let Some((&s, &e)) = self.0.range(..=start).next_back() {
+            if e >= start {
+                start = s;
+                end = end.max(e);
+            }
+        }
+        // Merge with any ranges that start within the new one.
+        while let Some((&s, &e)) = self.0.range(start..=end).next() {
+            self.0.remove(&s);
+            end = end.max(e);
+        }
+        self.0.insert(start, end);
+    }
+
+    fn remove(&mut self, start: u64, end: u64) {
+        // Trim a range that starts below `start`.
+        if let Some((&s, &e)) = self.0.range(..start).next_back() {
+            if e > start {
+                self.0.insert(s, start);
+                if e > end {
+                    self.0.insert(end, e);
+                }
+            }
+        }
+        // Trim or remove any ranges that start within `start..end`.
+        while let Some((&s, &e)) = self.0.range(start..end).next() {
+            self.0.remove(&s);
+            if e > end {
+                self.0.insert(end, e);
+            }
+        }
+    }
+
+    /// Returns the parts of the ranges that intersect `start..end`.
+    fn intersect(&self, start: u64, end: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let first = self
+            .0
+            .range(..=start)
+            .next_back()
+            .filter(|&(_, &e)| e > start);
+        first
+            .into_iter()
+            .chain(self.0.range(start + 1..end))
+            .map(move |(&s, &e)| (s.max(start), e.min(end)))
+    }
 }
 
 impl RamDiskLayer {
@@ -126,6 +205,7 @@ pub fn new(size: u64) -> Result<Self, Error> {
                 data: BTreeMap::new(),
                 sector_count,
                 zero_after: sector_count,
+                zero: ZeroRanges::default(),
             }),
             sector_count: sector_count.into(),
             resize_event: Default::default(),
@@ -147,6 +227,7 @@ fn resize(&self, new_sector_count: u64) -> anyhow::Result<()> {
             // FUTURE: remove uses of .sector_count() in the IO path,
             // eliminating the need for this.
             self.sector_count.store(new_sector_count, Ordering::Relaxed);
+            state.zero.remove(new_sector_count, u64::MAX);
             state.data.split_off(&new_sector_count)
         };
         self.resize_event.notify(usize::MAX);
@@ -162,6 +243,7 @@ fn write_maybe_overwrite(
         let count = buffers.len() / SECTOR_SIZE as usize;
         tracing::trace!(sector, count, "write");
         let mut state = self.state.write();
+        let state = &mut *state;
         if sector + count as u64 > state.sector_count {
             return Err(DiskError::IllegalBlock);
         }
@@ -171,7 +253,11 @@ fn write_maybe_overwrite(
             let mut reader = buf.reader();
             match state.data.entry(cur as u64) {
                 Entry::Vacant(entry) => {
-                    entry.insert(Sector(reader.read_plain()?));
+                    // An unmapped sector is present (as zero), so it must not
+                    // be overwritten.
+                    if overwrite || !state.zero.contains(cur as u64) {
+                        entry.insert(Sector(reader.read_plain()?));
+                    }
                 }
                 Entry::Occupied(mut entry) => {
                     if overwrite {
@@ -180,6 +266,9 @@ fn write_maybe_overwrite(
                 }
             }
         }
+        if overwrite {
+            state.zero.remove(sector, sector + count as u64);
+        }
         Ok(())
     }
 }
@@ -247,15 +336,16 @@ async fn read(
         while last < end {
             let r = range.next();
             let next = r.map(|(&s, _)| s).unwrap_or(end);
-            if next > last && next > state.zero_after {
-                // Some non-present sectors need to be zeroed, since they are
-                // after the zero-after point (due to a resize).
-                let zero_start = last.max(state.zero_after);
-                let zero_count = next - zero_start;
-                let offset = (zero_start - sector) as usize * SECTOR_SIZE as usize;
-                let len = zero_count as usize * SECTOR_SIZE as usize;
-                buffers.subrange(offset, len).writer().zero(len)?;
-                marker.set_range(zero_start..next);
+            if next > last {
+                // Some non-present sectors need to be zeroed, since they have
+                // been unmapped or are after the zero-after point (due to a
+                // resize or unmap).
+                for (zero_start, zero_end) in state.zero_ranges(last, next) {
+                    let offset = (zero_start - sector) as usize * SECTOR_SIZE as usize;
+                    let len = (zero_end - zero_start) as usize * SECTOR_SIZE as usize;
+                    buffers.subrange(offset, len).writer().zero(len)?;
+                    marker.set_range(zero_start..zero_end);
+                }
             }
             if let Some((&s, buf)) = r {
                 let offset = (s - sector) as usize * SECTOR_SIZE as usize;
@@ -309,23 +399,28 @@ async fn unmap(
     ) -> Result<(), DiskError> {
         tracing::trace!(sector_offset, sector_count, "unmap");
         let mut state = self.state.write();
-        if sector_offset + sector_count > state.sector_count {
+        let end = sector_offset + sector_count;
+        if end > state.sector_count {
             return Err(DiskError::IllegalBlock);
         }
-        if !next_is_zero {
-            // This would create a hole of zeroes, which we cannot represent in
-            // the tree. Ignore the unmap.
-            if sector_offset + sector_count < state.zero_after {
-                return Ok(());
-            }
+        if next_is_zero {
+            // Non-present sectors will read as zero from the next layer, so
+            // there is no need to track them.
+            state.zero.remove(sector_offset, end);
+        } else if end < state.zero_after {
+            // Remember that the sectors are zero so that reads do not fall
+            // through to the next layer.
+            state.zero.insert(sector_offset, end);
+        } else {
             // The unmap is within or will extend the not-present-is-zero
-            // region, so allow it.
+            // region.
             state.zero_after = state.zero_after.min(sector_offset);
+            let zero_after = state.zero_after;
+            state.zero.remove(zero_after, u64::MAX);
         }
         // Sadly, there appears to be no way to remove a range of entries
         // from a btree map.
         let mut next_sector = sector_offset;
-        let end = sector_offset + sector_count;
         while next_sector < end {
             let Some((&sector, _)) = state.data.range_mut(next_sector..).next() else {
                 break;
@@ -340,9 +435,7 @@ async fn unmap(
     }
 
     fn unmap_behavior(&self) -> UnmapBehavior {
-        // This layer zeroes if the lower layer is zero, but otherwise does
-        // nothing, so we must report unspecified.
-        UnmapBehavior::Unspecified
+        UnmapBehavior::Zeroes
     }
 
     fn optimal_unmap_sectors(&self) -> u32 {
@@ -388,6 +481,7 @@ pub fn ram_disk(size: u64, read_only: bool) -> anyhow::Result<Disk> {
 mod tests {
     use super::RamDiskLayer;
     use super::SECTOR_SIZE;
+    use super::ZeroRanges;
     use disk_backend::DiskIo;
     use disk_layered::DiskLayer;
     use disk_layered::LayerConfiguration;
@@ -420,6 +514,12 @@ fn check(mem: &GuestMemory, sector: u64, start: usize, count: usize, high: u8) {
         }
     }
 
+    fn check_zero(mem: &GuestMemory, start: usize, count: usize) {
+        let mut buf = vec![0u8; count * SECTOR_USIZE];
+        mem.read_at(start as u64 * SECTOR_U64, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+    }
+
     async fn read(mem: &GuestMemory, disk: &mut impl DiskIo, sector: u64, count: usize) {
         disk.read_vectored(
             &OwnedRequestBuffers::linear(0, count * SECTOR_USIZE, true).buffer(mem),
@@ -537,19 +637,50 @@ async fn test_unmap() {
         const SECTORS: usize = SIZE / SECTOR_USIZE;
 
         let (guest_mem, mut upper) = prep_disk(SIZE).await;
-        upper.unmap(0, SECTORS as u64 - 1, false).await.unwrap();
+        upper.unmap(1, SECTORS as u64 / 2 - 1, false).await.unwrap();
         read(&guest_mem, &mut upper, 0, SECTORS).await;
-        check(&guest_mem, 0, 0, SECTORS, 0);
+        check(&guest_mem, 0, 0, 1, 0);
+        check_zero(&guest_mem, 1, SECTORS / 2 - 1);
+        check(&guest_mem, SECTORS as u64 / 2, SECTORS / 2, SECTORS / 2, 0);
         upper
             .unmap(SECTORS as u64 / 2, SECTORS as u64 / 2, false)
             .await
             .unwrap();
         read(&guest_mem, &mut upper, 0, SECTORS).await;
-        check(&guest_mem, 0, 0, SECTORS / 2, 0);
-        for s in SECTORS / 2..SECTORS {
-            let mut buf = [0u8; SECTOR_USIZE];
-            guest_mem.read_at(s as u64 * SECTOR_U64, &mut buf).unwrap();
-            assert_eq!(buf, [0u8; SECTOR_USIZE]);
-        }
+        check(&guest_mem, 0, 0, 1, 0);
+        check_zero(&guest_mem, 1, SECTORS - 1);
+    }
+
+    #[async_test]
+    async fn test_write_after_unmap() {
+        const SIZE: usize = 1024 * 1024;
+
+        let (guest_mem, mut upper) = prep_disk(SIZE).await;
+        upper.unmap(8, 8, false).await.unwrap();
+        write(&guest_mem, &mut upper, 10, 2, 1).await;
+        read(&guest_mem, &mut upper, 7, 10).await;
+        check(&guest_mem, 7, 0, 1, 0);
+        check_zero(&guest_mem, 1, 2);
+        check(&guest_mem, 10, 3, 2, 1);
+        check_zero(&guest_mem, 5, 4);
+        check(&guest_mem, 16, 9, 1, 0);
+    }
+
+    #[test]
+    fn test_zero_ranges() {
+        let mut zero = ZeroRanges::default();
+        zero.insert(10, 20);
+        zero.insert(30, 40);
+        zero.insert(20, 25);
+        assert_eq!(Vec::from_iter(zero.0.clone()), [(10, 25), (30, 40)]);
+        zero.insert(24, 31);
+        assert_eq!(Vec::from_iter(zero.0.clone()), [(10, 40)]);
+        zero.remove(15, 20);
+        assert_eq!(Vec::from_iter(zero.0.clone()), [(10, 15), (20, 40)]);
+        assert!(zero.contains(14));
+        assert!(!zero.contains(15));
+        assert_eq!(Vec::from_iter(zero.intersect(12, 30)), [(12, 15), (20, 30)]);
+        zero.remove(0, 35);
+        assert_eq!(Vec::from_iter(zero.0.clone()), [(35, 40)]);
     }
 }
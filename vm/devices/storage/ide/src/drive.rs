@@ -17,8 +17,11 @@
 use ide_resources::IdePath;
 use inspect::Inspect;
 use inspect::InspectMut;
+use inspect_counters::Counter;
+use inspect_counters::LatencyHistogram;
 use std::fmt::Debug;
 use std::task::Context;
+use std::time::Instant;
 
 #[derive(Debug, Copy, Clone, Inspect)]
 pub enum DriveRegister {
@@ -32,6 +35,32 @@ pub enum DriveRegister {
     AlternateStatusDeviceControl,
 }
 
+/// IO statistics for a drive. A drive runs one IO at a time, so there is no
+/// queue depth to track.
+#[derive(Debug, Default, Inspect)]
+struct IoStats {
+    ios_completed: Counter,
+    ios_failed: Counter,
+    /// Time from issuing an IO to the backing disk to its completion.
+    latency: LatencyHistogram,
+    #[inspect(skip)]
+    start: Option<Instant>,
+}
+
+impl IoStats {
+    fn start(&mut self) {
+        self.start = LatencyHistogram::start();
+    }
+
+    fn complete(&mut self, ok: bool) {
+        self.latency.record(self.start.take());
+        self.ios_completed.increment();
+        if !ok {
+            self.ios_failed.increment();
+        }
+    }
+}
+
 #[derive(InspectMut)]
 #[inspect(tag = "device_type")]
 #[inspect(extra = "DiskDrive::inspect_status")]
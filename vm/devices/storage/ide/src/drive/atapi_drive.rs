@@ -4,6 +4,7 @@
 //! Implements atapi commands handler of optical drive.
 
 use super::DriveRegister;
+use super::IoStats;
 use crate::DmaType;
 use crate::protocol;
 use crate::protocol::DeviceControlReg;
@@ -252,6 +253,7 @@ pub(crate) struct AtapiDrive {
 
     #[inspect(skip)]
     io: Option<Io>,
+    io_stats: IoStats,
     #[inspect(skip)]
     waker: Option<Waker>,
 }
@@ -544,6 +546,7 @@ pub fn new(scsi_disk: Arc<dyn AsyncScsiDisk>, disk_path: IdePath) -> Self {
             disk_path,
             command_buffer: CommandBuffer::new(),
             io: None,
+            io_stats: IoStats::default(),
             waker: None,
         }
     }
@@ -829,6 +832,7 @@ fn set_io(&mut self, request: scsi_core::Request) {
             scsi_disk.execute_scsi(&buffers, &request).await
         };
         self.io = Some(Io(Box::pin(fut)));
+        self.io_stats.start();
         // Ensure poll_device gets called again.
         if let Some(waker) = self.waker.take() {
             waker.wake();
@@ -839,6 +843,8 @@ pub fn poll_device(&mut self, cx: &mut Context<'_>) {
         if let Some(io) = self.io.as_mut() {
             if let Poll::Ready(result) = io.0.as_mut().poll(cx) {
                 self.io = None;
+                self.io_stats
+                    .complete(result.scsi_status == scsi::ScsiStatus::GOOD);
                 self.process_atapi_command_result(result);
 
                 // Wait until the command that initiated this IO is completed
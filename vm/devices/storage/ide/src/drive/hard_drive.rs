@@ -5,6 +5,7 @@
 //! [`Disk`].
 
 use super::DriveRegister;
+use super::IoStats;
 use crate::DmaType;
 use crate::NewDeviceError;
 use crate::protocol;
@@ -250,6 +251,7 @@ pub(crate) struct HardDrive {
 
     #[inspect(with = "Option::is_some")]
     io: Option<Io>,
+    io_stats: IoStats,
     #[inspect(skip)]
     waker: Option<Waker>,
 }
@@ -494,6 +496,7 @@ pub fn new(disk: Disk, disk_path: IdePath) -> Result<Self, NewDeviceError> {
             read_only,
             command_buffer: CommandBuffer::new(),
             io: None,
+            io_stats: IoStats::default(),
             waker: None,
         })
     }
@@ -610,6 +613,7 @@ pub fn poll_device(&mut self, cx: &mut Context<'_>) {
         if let Some(io) = self.io.as_mut() {
             if let Poll::Ready(result) = io.0.as_mut().poll(cx) {
                 self.io = None;
+                self.io_stats.complete(result.is_ok());
                 self.handle_io_completion(result);
 
                 // Wait until the command that initiated this IO is completed
@@ -1039,6 +1043,18 @@ fn identify_device(&mut self) {
                 self.geometry.total_sectors as u32
             };
 
+        // Report multiple logical sectors per physical sector (bit 13) and the
+        // log2 of the ratio (bits 3:0), e.g. for 512e disks.
+        let logical_per_physical = (self.disk.physical_sector_size()
+            / protocol::HARD_DRIVE_SECTOR_BYTES)
+            .max(1)
+            .trailing_zeros() as u16;
+        let default_sector_size_config = if logical_per_physical != 0 {
+            0x6000 | logical_per_physical
+        } else {
+            0x4000
+        };
+
         let features = protocol::IdeFeatures {
             config_bits: 0x045A,
             cylinders,
@@ -1084,7 +1100,7 @@ fn identify_device(&mut self) {
             command_set_enabled2: 0x3400,  // support flushing
             command_set_default: 0x4040,   // write fua support for default write hardening
             total_sectors_48_bit: self.geometry.total_sectors.into(),
-            default_sector_size_config: 0x4000, // describes the sector size related info. Reflect the underlying device sector size and logical:physical ratio
+            default_sector_size_config, // describes the sector size related info. Reflect the underlying device sector size and logical:physical ratio
             logical_block_alignment: 0x4000, // describes alignment of logical blocks within physical block
             ..FromZeros::new_zeroed()
         };
@@ -1378,6 +1394,7 @@ fn set_io<F, Fut>(&mut self, f: F)
         let fut = (f)(self.disk.clone());
         assert!(self.io.is_none());
         self.io = Some(Io(Box::pin(fut)));
+        self.io_stats.start();
         // Ensure poll_device gets called again.
         if let Some(waker) = self.waker.take() {
             waker.wake();
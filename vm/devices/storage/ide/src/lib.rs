@@ -780,6 +780,12 @@ fn perform_dma_memory_phase(&mut self) {
 
             assert!(bytes_to_transfer != 0);
 
+            let _span = tracing::trace_span!(
+                "ide_dma",
+                gpa = dma.transfer_base_addr,
+                len = bytes_to_transfer
+            )
+            .entered();
             drive.dma_transfer(
                 &self.guest_memory,
                 dma.transfer_base_addr,
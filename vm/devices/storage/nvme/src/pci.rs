@@ -31,6 +31,9 @@
 use inspect::InspectMut;
 use parking_lot::Mutex;
 use pci_core::capabilities::msix::MsixEmulator;
+use pci_core::capabilities::pci_express::PciExpressCapability;
+use pci_core::capabilities::sriov::SriovCapability;
+use pci_core::capabilities::sriov::VirtualFunction;
 use pci_core::cfg_space_emu::BarMemoryKind;
 use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
 use pci_core::cfg_space_emu::DeviceBars;
@@ -52,6 +55,9 @@ pub struct NvmeController {
     cfg_space: ConfigSpaceType0Emulator,
     #[inspect(skip)]
     msix: MsixEmulator,
+    /// Set if the controller is an SR-IOV virtual function.
+    #[inspect(with = "|x| x.as_ref().map(|vf| vf.index())")]
+    virtual_function: Option<VirtualFunction>,
 
     registers: RegState,
     #[inspect(skip)]
@@ -105,6 +111,25 @@ pub struct NvmeControllerCaps {
     pub subsystem_id: Guid,
 }
 
+/// The device ID of the controller's physical (or only) function.
+const DEVICE_ID: u16 = 0x00a9;
+
+/// The device ID of the controller's SR-IOV virtual functions.
+const VF_DEVICE_ID: u16 = 0x00aa;
+
+fn hardware_ids(device_id: u16) -> HardwareIds {
+    HardwareIds {
+        vendor_id: VENDOR_ID,
+        device_id,
+        revision_id: 0,
+        prog_if: ProgrammingInterface::MASS_STORAGE_CONTROLLER_NON_VOLATILE_MEMORY_NVME,
+        sub_class: Subclass::MASS_STORAGE_CONTROLLER_NON_VOLATILE_MEMORY,
+        base_class: ClassCode::MASS_STORAGE_CONTROLLER,
+        type0_sub_vendor_id: 0,
+        type0_sub_system_id: 0,
+    }
+}
+
 impl NvmeController {
     /// Creates a new NVMe controller.
     pub fn new(
@@ -125,21 +150,98 @@ pub fn new(
                 BarMemoryKind::Intercept(register_mmio.new_io_region("msix", msix.bar_len())),
             );
 
+        let cfg_space =
+            ConfigSpaceType0Emulator::new(hardware_ids(DEVICE_ID), vec![Box::new(msix_cap)], bars);
+
+        Self::with_cfg_space(driver_source, guest_memory, cfg_space, msix, None, caps)
+    }
+
+    /// Creates a new NVMe controller that is the SR-IOV physical function of
+    /// `total_vfs` virtual functions.
+    ///
+    /// Returns the controller along with the handles to create the virtual
+    /// functions with [`NvmeController::new_virtual_function`], which must use
+    /// the same `caps.msix_count`.
+    pub fn new_physical_function(
+        driver_source: &VmTaskDriverSource,
+        guest_memory: GuestMemory,
+        register_msi: &mut dyn RegisterMsi,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        caps: NvmeControllerCaps,
+        total_vfs: u16,
+    ) -> (Self, Vec<VirtualFunction>) {
+        let (msix, msix_cap) = MsixEmulator::new(4, caps.msix_count, register_msi);
+        let bars = DeviceBars::new()
+            .bar0(
+                BAR0_LEN,
+                BarMemoryKind::Intercept(register_mmio.new_io_region("bar0", BAR0_LEN)),
+            )
+            .bar4(
+                msix.bar_len(),
+                BarMemoryKind::Intercept(register_mmio.new_io_region("msix", msix.bar_len())),
+            );
+
+        // The virtual functions have the same BAR layout as the physical
+        // function.
+        let (sriov, vfs) = SriovCapability::new(
+            VF_DEVICE_ID,
+            total_vfs,
+            &[(0, BAR0_LEN), (4, msix.bar_len())],
+        );
+
+        // SR-IOV is only discoverable on PCI Express functions.
         let cfg_space = ConfigSpaceType0Emulator::new(
-            HardwareIds {
-                vendor_id: VENDOR_ID,
-                device_id: 0x00a9,
-                revision_id: 0,
-                prog_if: ProgrammingInterface::MASS_STORAGE_CONTROLLER_NON_VOLATILE_MEMORY_NVME,
-                sub_class: Subclass::MASS_STORAGE_CONTROLLER_NON_VOLATILE_MEMORY,
-                base_class: ClassCode::MASS_STORAGE_CONTROLLER,
-                type0_sub_vendor_id: 0,
-                type0_sub_system_id: 0,
-            },
-            vec![Box::new(msix_cap)],
+            hardware_ids(DEVICE_ID),
+            vec![
+                Box::new(PciExpressCapability::new(None)) as _,
+                Box::new(msix_cap) as _,
+            ],
             bars,
-        );
+        )
+        .with_extended_capabilities(vec![Box::new(sriov)]);
+
+        let controller =
+            Self::with_cfg_space(driver_source, guest_memory, cfg_space, msix, None, caps);
+        (controller, vfs)
+    }
+
+    /// Creates a new NVMe controller that is an SR-IOV virtual function of a
+    /// controller created with [`NvmeController::new_physical_function`].
+    pub fn new_virtual_function(
+        driver_source: &VmTaskDriverSource,
+        guest_memory: GuestMemory,
+        register_msi: &mut dyn RegisterMsi,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        caps: NvmeControllerCaps,
+        vf: VirtualFunction,
+    ) -> Self {
+        let (msix, msix_cap) = MsixEmulator::new(4, caps.msix_count, register_msi);
+
+        // The BARs are programmed through the physical function.
+        vf.set_bar(0, register_mmio.new_io_region("bar0", BAR0_LEN));
+        vf.set_bar(4, register_mmio.new_io_region("msix", msix.bar_len()));
+
+        let cfg_space = ConfigSpaceType0Emulator::new(
+            hardware_ids(VF_DEVICE_ID),
+            vec![
+                Box::new(PciExpressCapability::new(None)) as _,
+                Box::new(msix_cap) as _,
+            ],
+            DeviceBars::new(),
+        )
+        .with_virtual_function(true);
+
+        Self::with_cfg_space(driver_source, guest_memory, cfg_space, msix, Some(vf), caps)
+    }
 
+    fn with_cfg_space(
+        driver_source: &VmTaskDriverSource,
+        guest_memory: GuestMemory,
+        cfg_space: ConfigSpaceType0Emulator,
+        msix: MsixEmulator,
+        virtual_function: Option<VirtualFunction>,
+        caps: NvmeControllerCaps,
+    ) -> Self {
         let interrupts = (0..caps.msix_count)
             .map(|i| msix.interrupt(i).unwrap())
             .collect();
@@ -158,6 +260,7 @@ pub fn new(
         Self {
             cfg_space,
             msix,
+            virtual_function,
             registers: RegState::new(),
             workers: admin,
             qe_sizes,
@@ -424,6 +527,7 @@ async fn reset(&mut self) {
         let Self {
             cfg_space,
             msix: _,
+            virtual_function: _,
             registers,
             qe_sizes,
             workers,
@@ -445,9 +549,19 @@ fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
     }
 }
 
+impl NvmeController {
+    fn find_bar(&self, addr: u64) -> Option<(u8, u16)> {
+        if let Some(vf) = &self.virtual_function {
+            vf.find_bar(addr)
+        } else {
+            self.cfg_space.find_bar(addr)
+        }
+    }
+}
+
 impl MmioIntercept for NvmeController {
     fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
-        match self.cfg_space.find_bar(addr) {
+        match self.find_bar(addr) {
             Some((0, offset)) => self.read_bar0(offset, data),
             Some((4, offset)) => {
                 read_as_u32_chunks(offset, data, |offset| self.msix.read_u32(offset));
@@ -458,7 +572,7 @@ fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
     }
 
     fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
-        match self.cfg_space.find_bar(addr) {
+        match self.find_bar(addr) {
             Some((0, offset)) => self.write_bar0(offset, data),
             Some((4, offset)) => {
                 write_as_u32_chunks(offset, data, |offset, ty| match ty {
@@ -477,10 +591,26 @@ fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
 
 impl PciConfigSpace for NvmeController {
     fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        // A disabled virtual function is not present on the bus.
+        if self
+            .virtual_function
+            .as_ref()
+            .is_some_and(|vf| !vf.is_enabled())
+        {
+            *value = !0;
+            return IoResult::Ok;
+        }
         self.cfg_space.read_u32(offset, value)
     }
 
     fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        if self
+            .virtual_function
+            .as_ref()
+            .is_some_and(|vf| !vf.is_enabled())
+        {
+            return IoResult::Ok;
+        }
         self.cfg_space.write_u32(offset, value)
     }
 }
@@ -217,6 +217,72 @@ async fn test_basic_registers(driver: DefaultDriver) {
     assert_eq!(qword, 0x1000);
 }
 
+#[async_test]
+async fn test_sriov_virtual_functions(driver: DefaultDriver) {
+    let gm = test_memory();
+    let vm_task_driver = &VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+    let mut msi_interrupt_set = MsiInterruptSet::new();
+    let caps = NvmeControllerCaps {
+        msix_count: 64,
+        max_io_queues: 64,
+        subsystem_id: Guid::new_random(),
+    };
+    let (mut pf, vfs) = NvmeController::new_physical_function(
+        vm_task_driver,
+        gm.clone(),
+        &mut msi_interrupt_set,
+        &mut TestNvmeMmioRegistration {},
+        caps,
+        2,
+    );
+    let mut vfs = vfs
+        .into_iter()
+        .map(|vf| {
+            NvmeController::new_virtual_function(
+                vm_task_driver,
+                gm.clone(),
+                &mut msi_interrupt_set,
+                &mut TestNvmeMmioRegistration {},
+                caps,
+                vf,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // The SR-IOV capability is the first extended capability.
+    let mut dword = 0u32;
+    pf.pci_cfg_read(0x100, &mut dword).unwrap();
+    assert_eq!(dword & 0xffff, 0x10);
+    pf.pci_cfg_read(0x10c, &mut dword).unwrap();
+    assert_eq!(dword >> 16, 2);
+
+    // The VFs are not present until they are enabled.
+    vfs[0].pci_cfg_read(8, &mut dword).unwrap();
+    assert_eq!(dword, !0);
+
+    // Program the VF BARs, then enable the VFs and their memory space.
+    pf.pci_cfg_write(0x124, 0x1000_0000).unwrap();
+    pf.pci_cfg_write(0x128, 0).unwrap();
+    pf.pci_cfg_write(0x134, 0x2000_0000).unwrap();
+    pf.pci_cfg_write(0x138, 0).unwrap();
+    pf.pci_cfg_write(0x110, 2).unwrap();
+    pf.pci_cfg_write(0x108, 0x9).unwrap();
+
+    // The VFs report their IDs only through the PF.
+    vfs[1].pci_cfg_read(0, &mut dword).unwrap();
+    assert_eq!(dword, !0);
+    vfs[1].pci_cfg_read(8, &mut dword).unwrap();
+    assert_eq!(dword >> 8, 0x010802);
+
+    // Each VF decodes its own slice of the VF BARs.
+    vfs[0].mmio_read(0x1000_0000, dword.as_mut_bytes()).unwrap();
+    assert_eq!(dword, 0xFF0100FF);
+    vfs[1]
+        .mmio_read(0x1000_0000 + BAR0_LEN, dword.as_mut_bytes())
+        .unwrap();
+    assert_eq!(dword, 0xFF0100FF);
+}
+
 #[async_test]
 async fn test_invalid_configuration(driver: DefaultDriver) {
     let gm = test_memory();
@@ -326,3 +392,95 @@ async fn test_send_identify(driver: DefaultDriver) {
     let cqe = read_completion_from_queue(&gm, &dm1, 0);
     assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
 }
+
+#[async_test]
+async fn test_namespace_change_notification(driver: DefaultDriver) {
+    let acq = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let asq = PrpRange::new(vec![1], 0, PAGE_SIZE64).unwrap();
+    let log_gpa = 2 * PAGE_SIZE64;
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &acq,
+        64,
+        &asq,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+    let client = nvmec.client();
+
+    let mut slot = 0;
+    let mut submit = |nvmec: &mut NvmeController, mut command: spec::Command| {
+        command.cdw0.set_cid(slot as u16);
+        write_command_to_queue(&gm, &asq, slot, &command);
+        slot += 1;
+        nvmec.write_bar0(0x1000, (slot as u32).as_bytes()).unwrap();
+        slot - 1
+    };
+
+    let async_event_request = || {
+        let mut command = spec::Command::new_zeroed();
+        command
+            .cdw0
+            .set_opcode(spec::AdminOpcode::ASYNCHRONOUS_EVENT_REQUEST.0);
+        command
+    };
+
+    let get_changed_namespace_list = || {
+        let mut command = spec::Command::new_zeroed();
+        command.cdw0.set_opcode(spec::AdminOpcode::GET_LOG_PAGE.0);
+        command.cdw10 = spec::Cdw10GetLogPage::new()
+            .with_lid(spec::LogPageIdentifier::CHANGED_NAMESPACE_LIST.0)
+            .with_numdl_z(1023)
+            .into();
+        command.nsid = !0;
+        command.dptr[0] = log_gpa;
+        command
+    };
+
+    for add in [true, false] {
+        // Post an asynchronous event request. It should not complete until
+        // the namespace changes.
+        let aer_slot = submit(&mut nvmec, async_event_request());
+        assert!(int_controller.get_next_interrupt().is_none());
+
+        if add {
+            client
+                .add_namespace(1, disklayer_ram::ram_disk(0x100000, false).unwrap())
+                .await
+                .unwrap();
+        } else {
+            assert!(client.remove_namespace(1).await);
+        }
+
+        wait_for_msi(driver.clone(), &int_controller, 1000, 0xfeed0000, 0x1111).await;
+        let cqe = read_completion_from_queue(&gm, &acq, aer_slot);
+        assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+        assert_eq!(cqe.cid, aer_slot as u16);
+        let dw0 = spec::AsynchronousEventRequestDw0::from(cqe.dw0);
+        assert_eq!(dw0.event_type(), spec::AsynchronousEventType::NOTICE.0);
+        assert_eq!(
+            dw0.information(),
+            spec::AsynchronousEventInformationNotice::NAMESPACE_ATTRIBUTE_CHANGED.0
+        );
+        assert_eq!(
+            dw0.log_page_identifier(),
+            spec::LogPageIdentifier::CHANGED_NAMESPACE_LIST.0
+        );
+
+        // The changed namespace list should report the namespace.
+        let log_slot = submit(&mut nvmec, get_changed_namespace_list());
+        wait_for_msi(driver.clone(), &int_controller, 1000, 0xfeed0000, 0x1111).await;
+        let cqe = read_completion_from_queue(&gm, &acq, log_slot);
+        assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+        assert_eq!(gm.read_plain::<[u32; 2]>(log_gpa).unwrap(), [1, 0]);
+    }
+
+    // The namespace is gone.
+    assert!(!client.remove_namespace(1).await);
+}
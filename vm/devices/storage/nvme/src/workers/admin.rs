@@ -291,12 +291,6 @@ async fn remove_namespace(&mut self, nsid: u32) {
 
         // Notify the guest driver of the change.
         self.add_changed_namespace(nsid);
-
-        self.poll_namespace_change
-            .remove(&nsid)
-            .unwrap()
-            .cancel()
-            .await;
     }
 }
 
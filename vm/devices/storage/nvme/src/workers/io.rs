@@ -17,16 +17,21 @@
 use futures_concurrency::future::Race;
 use guestmem::GuestMemory;
 use inspect::Inspect;
+use inspect_counters::Counter;
+use inspect_counters::LatencyHistogram;
 use std::collections::BTreeMap;
 use std::future::Future;
 use std::future::pending;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use task_control::AsyncRun;
 use task_control::Cancelled;
 use task_control::InspectTask;
 use task_control::StopTask;
 use thiserror::Error;
+use tracing::Instrument;
 use unicycle::FuturesUnordered;
 use vmcore::interrupt::Interrupt;
 
@@ -47,7 +52,18 @@ pub struct IoState {
     #[inspect(skip)]
     ios: FuturesUnordered<Pin<Box<dyn Future<Output = IoResult> + Send>>>,
     io_count: usize,
+    /// The largest number of IOs that have been in flight at once.
+    max_io_count: usize,
+    ios_completed: Counter,
+    /// IOs that completed with an error status.
+    ios_failed: Counter,
     queue_state: IoQueueState,
+    /// Time from fetching a command from the submission queue to posting its
+    /// completion.
+    latency: LatencyHistogram,
+    /// Time from fetching a command to the namespace's backing disk completing
+    /// it, which excludes the time to post the completion.
+    backend_latency: LatencyHistogram,
 }
 
 #[derive(Inspect)]
@@ -76,7 +92,12 @@ pub fn new(
             namespaces,
             ios: FuturesUnordered::new(),
             io_count: 0,
+            max_io_count: 0,
+            ios_completed: Counter::new(),
+            ios_failed: Counter::new(),
             queue_state: IoQueueState::Active,
+            latency: LatencyHistogram::new(),
+            backend_latency: LatencyHistogram::new(),
         }
     }
 
@@ -104,6 +125,8 @@ struct IoResult {
     opcode: nvm::NvmOpcode,
     result: Result<CommandResult, NvmeError>,
     advance_evt_idx: bool,
+    start: Option<Instant>,
+    backend_latency: Option<Duration>,
 }
 
 impl AsyncRun<IoState> for IoHandler {
@@ -196,7 +219,7 @@ enum Event {
             };
 
             let event = (next_sqe, next_io_completion).race().await;
-            let (cid, result) = match event {
+            let (cid, result, start) = match event {
                 Event::Io(io_result) => {
                     if io_result.advance_evt_idx {
                         let result = state.sq.advance_evt_idx(&self.mem);
@@ -205,6 +228,9 @@ enum Event {
                         }
                     }
                     state.io_count -= 1;
+                    if let Some(latency) = io_result.backend_latency {
+                        state.backend_latency.add_sample(latency);
+                    }
                     let result = match io_result.result {
                         Ok(cr) => cr,
                         Err(err) => {
@@ -218,11 +244,12 @@ enum Event {
                             err.into()
                         }
                     };
-                    (io_result.cid, result)
+                    (io_result.cid, result, io_result.start)
                 }
                 Event::Sq(r) => {
                     let command = r?;
                     let cid = command.cdw0.cid();
+                    let start = LatencyHistogram::start();
 
                     if let Some(ns) = state.namespaces.get(&command.nsid) {
                         let ns = ns.clone();
@@ -241,18 +268,31 @@ enum Event {
                             }
                             advance_evt_idx = false;
                         }
-                        let io = Box::pin(async move {
-                            let result = ns.nvm_command(MAX_DATA_TRANSFER_SIZE, &command).await;
-                            IoResult {
-                                nsid: command.nsid,
-                                opcode: nvm::NvmOpcode(command.cdw0.opcode()),
-                                cid,
-                                result,
-                                advance_evt_idx,
+                        let span = tracing::trace_span!(
+                            "nvme_io",
+                            sqid = self.sqid,
+                            cid,
+                            opcode = command.cdw0.opcode()
+                        );
+                        let io = Box::pin(
+                            async move {
+                                let result = ns.nvm_command(MAX_DATA_TRANSFER_SIZE, &command).await;
+                                let backend_latency = start.map(|start| start.elapsed());
+                                IoResult {
+                                    nsid: command.nsid,
+                                    opcode: nvm::NvmOpcode(command.cdw0.opcode()),
+                                    cid,
+                                    result,
+                                    advance_evt_idx,
+                                    start,
+                                    backend_latency,
+                                }
                             }
-                        });
+                            .instrument(span),
+                        );
                         state.ios.push(io);
                         state.io_count += 1;
+                        state.max_io_count = state.max_io_count.max(state.io_count);
                         continue;
                     }
 
@@ -260,7 +300,7 @@ enum Event {
                     if result.is_err() {
                         tracelimit::warn_ratelimited!("failure to advance evt_idx");
                     }
-                    (cid, spec::Status::INVALID_NAMESPACE_OR_FORMAT.into())
+                    (cid, spec::Status::INVALID_NAMESPACE_OR_FORMAT.into(), start)
                 }
             };
 
@@ -276,6 +316,11 @@ enum Event {
                 assert!(deleting);
                 tracelimit::warn_ratelimited!("dropped i/o completion during queue deletion");
             }
+            state.latency.record(start);
+            state.ios_completed.increment();
+            if result.status != spec::Status::SUCCESS {
+                state.ios_failed.increment();
+            }
             state
                 .cq
                 .catch_up_evt_idx(false, state.io_count as u32, &self.mem)?;
@@ -7,6 +7,7 @@
 
 use guid::Guid;
 use mesh::MeshPayload;
+use mesh::rpc::FailableRpc;
 use vm_resource::Resource;
 use vm_resource::ResourceId;
 use vm_resource::kind::DiskHandleKind;
@@ -23,6 +24,8 @@ pub struct NvmeControllerHandle {
     pub max_io_queues: u16,
     /// The initial set of namespaces.
     pub namespaces: Vec<NamespaceDefinition>,
+    /// Runtime request channel.
+    pub requests: Option<mesh::Receiver<NvmeControllerRequest>>,
 }
 
 impl ResourceId<PciDeviceHandleKind> for NvmeControllerHandle {
@@ -39,3 +42,12 @@ pub struct NamespaceDefinition {
     /// The backing disk resource.
     pub disk: Resource<DiskHandleKind>,
 }
+
+/// A runtime request to an NVMe controller.
+#[derive(MeshPayload)]
+pub enum NvmeControllerRequest {
+    /// Add a namespace to the controller.
+    AddNamespace(FailableRpc<NamespaceDefinition, ()>),
+    /// Remove the namespace with the given ID from the controller.
+    RemoveNamespace(FailableRpc<u32, ()>),
+}
@@ -34,6 +34,7 @@
 use inspect::InspectMut;
 use inspect_counters::Counter;
 use inspect_counters::Histogram;
+use inspect_counters::LatencyHistogram;
 use oversized_box::OversizedBox;
 use parking_lot::Mutex;
 use parking_lot::RwLock;
@@ -59,12 +60,14 @@
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Instant;
 use storvsp_resources::ScsiPath;
 use task_control::AsyncRun;
 use task_control::InspectTask;
 use task_control::StopTask;
 use task_control::TaskControl;
 use thiserror::Error;
+use tracing::Instrument;
 use tracing_helpers::ErrorValueExt;
 use unicycle::FuturesUnordered;
 use vmbus_async::queue;
@@ -172,10 +175,16 @@ struct WorkerInner {
 struct WorkerStats {
     ios_submitted: Counter,
     ios_completed: Counter,
+    /// IOs that completed with an SRB status other than success.
+    ios_failed: Counter,
+    /// The largest number of IOs that have been in flight at once.
+    max_queue_depth: usize,
     wakes: Counter,
     wakes_spurious: Counter,
     per_wake_submissions: Histogram<10>,
     per_wake_completions: Histogram<10>,
+    /// Time from receiving an IO request to sending its completion.
+    request_latency: LatencyHistogram,
 }
 
 #[repr(u16)]
@@ -247,8 +256,10 @@ enum InitState {
 /// The amount of space reserved for a ScsiOpFuture.
 ///
 /// This was chosen by running `cargo test -p storvsp -- --no-capture` and looking at the required
-/// size that was given in the failure message
-const SCSI_REQUEST_STACK_SIZE: usize = scsi_core::ASYNC_SCSI_DISK_STACK_SIZE + 272;
+/// size that was given in the failure message, plus room for the `storvsp_io`
+/// tracing span the future is instrumented with.
+const SCSI_REQUEST_STACK_SIZE: usize =
+    scsi_core::ASYNC_SCSI_DISK_STACK_SIZE + 272 + size_of::<tracing::Span>();
 
 struct ScsiRequest {
     request_id: usize,
@@ -1242,6 +1253,7 @@ fn handle_completion<M: RingMem>(
     ) -> Result<(), WorkerError> {
         let state = self.scsi_requests_states.remove(request_id);
         let request_size = state.request.request_size;
+        let start = state.start;
 
         // Push the request into the pool to avoid reallocating later.
         assert_eq!(
@@ -1250,6 +1262,9 @@ fn handle_completion<M: RingMem>(
         );
         self.full_request_pool.push(state.request);
 
+        if result.srb_status != SrbStatus::SUCCESS {
+            self.stats.ios_failed.increment();
+        }
         let status = convert_srb_status_to_nt_status(result.srb_status);
         let mut payload = [0; 0x14];
         if let Some(sense) = result.sense_data {
@@ -1277,6 +1292,7 @@ fn handle_completion<M: RingMem>(
             status,
             response.as_bytes(),
         )?;
+        self.stats.request_latency.record(start);
         Ok(())
     }
 
@@ -1349,17 +1365,31 @@ fn push_scsi_request(&mut self, transaction_id: u64, full_request: Arc<ScsiReque
         let scsi_request_state = ScsiRequestState {
             transaction_id,
             request: full_request.clone(),
+            start: LatencyHistogram::start(),
         };
         let request_id = self.scsi_requests_states.insert(scsi_request_state);
+        self.stats.max_queue_depth = self
+            .stats
+            .max_queue_depth
+            .max(self.scsi_requests_states.len());
         let future = self
             .future_pool
             .pop()
             .unwrap_or_else(|| OversizedBox::new(()));
-        let future = OversizedBox::refill(future, async move {
-            scsi_queue
-                .execute_scsi(&full_request.external_data, &full_request.request)
-                .await
-        });
+        let span = tracing::trace_span!(
+            "storvsp_io",
+            transaction_id,
+            op = full_request.request.payload[0]
+        );
+        let future = OversizedBox::refill(
+            future,
+            async move {
+                scsi_queue
+                    .execute_scsi(&full_request.external_data, &full_request.request)
+                    .await
+            }
+            .instrument(span),
+        );
         let request = ScsiRequest::new(request_id, oversized_box::coerce!(future));
         self.scsi_requests.push(request);
     }
@@ -1385,6 +1415,8 @@ fn drop(&mut self) {
 struct ScsiRequestState {
     transaction_id: u64,
     request: Arc<ScsiRequestAndRange>,
+    /// When the request was received, if latency histograms are enabled.
+    start: Option<Instant>,
 }
 
 #[derive(Debug)]
@@ -201,6 +201,7 @@ fn save(v: &ScsiRequestState) -> Self {
         let &ScsiRequestState {
             transaction_id,
             ref request,
+            start: _,
         } = v;
         Self {
             transaction_id,
@@ -233,6 +234,7 @@ fn restore(&self) -> Result<ScsiRequestState, StorvspRestoreError> {
                 request: protocol_request,
                 request_size: request.len(),
             }),
+            start: None,
         })
     }
 }
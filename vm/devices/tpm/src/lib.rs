@@ -14,6 +14,7 @@
 
 pub mod ak_cert;
 pub mod logger;
+mod pcr_log;
 mod recover;
 pub mod resolver;
 mod tpm20proto;
@@ -37,6 +38,7 @@
 use logger::TpmLogger;
 use ms_tpm_20_ref::MsTpm20RefPlatform;
 use parking_lot::Mutex;
+use pcr_log::PcrLog;
 use std::future::Future;
 use std::ops::RangeInclusive;
 use std::pin::Pin;
@@ -48,6 +50,7 @@
 use tpm_helper::TpmCommandError;
 use tpm_helper::TpmEngineHelper;
 use tpm_helper::TpmHelperError;
+use tpm_resources::TpmPcrSeed;
 use tpm_resources::TpmRegisterLayout;
 use tpm20proto::CommandCodeEnum;
 use tpm20proto::NV_INDEX_RANGE_BASE_PLATFORM_MANUFACTURER;
@@ -103,6 +106,13 @@
 // 2 seconds
 const REPORT_TIMER_PERIOD: std::time::Duration = std::time::Duration::new(2, 0);
 
+/// The PCR banks implemented by the TPM (SHA-1, SHA-256, and SHA-384), in the
+/// bitmap format used by the PPI `SET_PCR_BANKS` operation.
+const SUPPORTED_PCR_BANKS: u32 = 0b111;
+
+/// The PPI result reported when an operation is refused: "BIOS failure".
+const PPI_RESULT_FAILURE: u32 = 0xffff_fff1;
+
 // 16kB: vtpmservice provisions a 16kB blob for the vTPM; HCL/OpenHCL provisions a 32k blob
 const LEGACY_VTPM_SIZE: usize = 16384;
 
@@ -220,6 +230,9 @@ pub struct Tpm {
     // Static config
     register_layout: TpmRegisterLayout,
     refresh_tpm_seeds: bool,
+    pcr_banks: Option<u32>,
+    #[inspect(skip)]
+    pcr_seeds: Vec<TpmPcrSeed>,
     #[inspect(skip)]
     io_region: Option<(&'static str, RangeInclusive<u16>)>, // Valid only on HypervX64
     #[inspect(skip)]
@@ -258,6 +271,9 @@ pub struct Tpm {
     // and `TPM_NV_INDEX_ATTESTATION_REPORT` nv indexes
     auth_value: Option<u64>,
     keys: Option<TpmKeys>,
+
+    // Diagnostics
+    pcr_log: PcrLog,
 }
 
 #[derive(Error, Debug)]
@@ -304,6 +320,10 @@ pub enum TpmErrorKind {
     ClearPlatformHierarchy(#[source] TpmHelperError),
     #[error("failed to set pcr banks")]
     SetPcrBanks(#[source] TpmHelperError),
+    #[error("failed to allocate the configured pcr banks: {0:#x}")]
+    AllocatePcrBanks(u32),
+    #[error("failed to extend pcr seed")]
+    ExtendPcrSeed(#[source] TpmHelperError),
 }
 
 struct TpmPlatformCallbacks {
@@ -343,6 +363,8 @@ pub async fn new(
         ak_cert_type: TpmAkCertType,
         guest_secret_key: Option<Vec<u8>>,
         logger: Option<Arc<dyn TpmLogger>>,
+        pcr_banks: Option<u32>,
+        pcr_seeds: Vec<TpmPcrSeed>,
     ) -> Result<Self, TpmError> {
         tracing::info!("initializing TPM");
 
@@ -394,6 +416,8 @@ pub async fn new(
         let mut tpm = Tpm {
             register_layout,
             refresh_tpm_seeds,
+            pcr_banks,
+            pcr_seeds,
             io_region,
             mmio_region,
 
@@ -420,6 +444,8 @@ pub async fn new(
             ppi_state: PpiState::new(),
             auth_value: None,
             keys: None,
+
+            pcr_log: PcrLog::default(),
         };
 
         if !is_restoring {
@@ -530,6 +556,15 @@ async fn on_first_boot(&mut self, guest_secret_key: Option<Vec<u8>>) -> Result<(
             }
         }
 
+        // Allocate the configured PCR banks. The allocation persists in the
+        // NVRAM, and guest requests to change it are refused.
+        if let Some(pcr_banks) = self.pcr_banks {
+            let response_code = self.set_tpm_pcr_banks(SUPPORTED_PCR_BANKS, pcr_banks)?;
+            if response_code != tpm20proto::ResponseCode::Success as u32 {
+                return Err(TpmErrorKind::AllocatePcrBanks(response_code).into());
+            }
+        }
+
         if matches!(
             self.ak_cert_type,
             TpmAkCertType::Trusted(_) | TpmAkCertType::HwAttested(_)
@@ -593,6 +628,8 @@ async fn on_first_boot(&mut self, guest_secret_key: Option<Vec<u8>>) -> Result<(
             }
         }
 
+        self.extend_pcr_seeds()?;
+
         // clear tpm hierarchy control
         self.tpm_engine_helper
             .hierarchy_control(TPM20_RH_PLATFORM, TPM20_RH_PLATFORM, false)
@@ -752,6 +789,13 @@ fn execute_pending_ppi(&mut self) -> Result<(), TpmError> {
                 .tpm_engine_helper
                 .clear_tpm_platform_context()
                 .map_err(TpmErrorKind::ClearTpmPlatformContext)?,
+            PpiOperation::SET_PCR_BANKS if self.pcr_banks.is_some() => {
+                tracelimit::warn_ratelimited!(
+                    CVM_ALLOWED,
+                    "refusing to change the configured pcr banks"
+                );
+                PPI_RESULT_FAILURE
+            }
             PpiOperation::SET_PCR_BANKS => self.set_tpm_pcr_banks(
                 self.ppi_state.tpm_capability_hash_alg_bitmap,
                 self.ppi_state.ppi_set_operation_arg3_integer2,
@@ -821,6 +865,30 @@ fn set_tpm_pcr_banks(
         Ok(response_code)
     }
 
+    /// Extends the configured PCR seeds, which must be done each time the TPM
+    /// starts up, before the guest's own measurements.
+    fn extend_pcr_seeds(&mut self) -> Result<(), TpmError> {
+        for seed in &self.pcr_seeds {
+            self.tpm_engine_helper
+                .pcr_event(seed.pcr, &seed.event_data)
+                .map_err(|error| TpmHelperError::TpmCommandError {
+                    command_debug_info: CommandDebugInfo {
+                        command_code: CommandCodeEnum::PCR_Event,
+                        auth_handle: None,
+                        nv_index: None,
+                    },
+                    error,
+                })
+                .map_err(TpmErrorKind::ExtendPcrSeed)?;
+            self.pcr_log.log_host_event(
+                seed.pcr,
+                seed.event_data.len() as u16,
+                &self.tpm_engine_helper.reply_buffer,
+            );
+        }
+        Ok(())
+    }
+
     /// Create a new request needed by AK cert request callout.
     ///
     /// This function can only be called when `ak_cert_type` is `Trusted` or `HwAttested`.
@@ -1085,6 +1153,8 @@ async fn reset(&mut self) {
         self.tpm_engine_helper
             .initialize_tpm_engine()
             .expect("failed to send TPM startup commands");
+        self.extend_pcr_seeds()
+            .expect("failed to extend TPM PCR seeds");
         pal_async::local::block_on(self.flush_pending_nvram())
             .expect("failed to flush nvram on reset");
     }
@@ -1276,6 +1346,8 @@ fn mmio_write(&mut self, address: u64, data: &[u8]) -> IoResult {
                         }
                     }
 
+                    let pcr_command = pcr_log::parse_command(&self.command_buffer);
+
                     if let Err(e) = self.tpm_engine_helper.tpm_engine.execute_command(
                         &mut self.command_buffer,
                         &mut self.tpm_engine_helper.reply_buffer,
@@ -1288,6 +1360,11 @@ fn mmio_write(&mut self, address: u64, data: &[u8]) -> IoResult {
                         return IoResult::Ok;
                     }
 
+                    if let Some(pcr_command) = pcr_command {
+                        self.pcr_log
+                            .log_guest_command(pcr_command, &self.tpm_engine_helper.reply_buffer);
+                    }
+
                     tracing::debug!(
                         response_code = ?tpm20proto::protocol::common::ReplyHeader::ref_from_prefix(
                         &self.tpm_engine_helper.reply_buffer,
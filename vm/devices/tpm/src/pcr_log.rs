@@ -0,0 +1,189 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A log of the PCR measurements made through the vTPM.
+//!
+//! The guest's own event log lives in guest memory, so the device keeps a
+//! record of what was actually extended into each PCR, and when. This is
+//! diagnostic state for inspect and is not saved across servicing.
+
+use crate::tpm20proto::AlgIdEnum;
+use crate::tpm20proto::CommandCodeEnum;
+use crate::tpm20proto::ResponseCode;
+use inspect::Inspect;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// The maximum number of entries kept, after which the oldest are dropped.
+const MAX_ENTRIES: usize = 1024;
+
+/// The size of the command and reply headers.
+const HEADER_SIZE: usize = 10;
+
+#[derive(Inspect, Default)]
+pub(crate) struct PcrLog {
+    /// The number of entries dropped because the log was full.
+    dropped: u64,
+    #[inspect(iter_by_index)]
+    entries: VecDeque<PcrLogEntry>,
+}
+
+#[derive(Inspect)]
+struct PcrLogEntry {
+    #[inspect(debug)]
+    time: SystemTime,
+    pcr: u32,
+    /// `guest` or `host` (a pre-seeded measurement).
+    source: &'static str,
+    command: &'static str,
+    /// The size of the event data, for `PCR_Event`.
+    event_size: Option<u16>,
+    #[inspect(iter_by_key)]
+    digests: Vec<(&'static str, String)>,
+}
+
+/// A PCR command parsed before execution, to be logged if it succeeds.
+#[derive(Debug)]
+pub(crate) enum PcrCommand {
+    Extend {
+        pcr: u32,
+        digests: Vec<(&'static str, String)>,
+    },
+    Event {
+        pcr: u32,
+        event_size: u16,
+    },
+}
+
+impl PcrLog {
+    /// Logs `command` if `reply` indicates that it succeeded.
+    pub fn log_guest_command(&mut self, command: PcrCommand, reply: &[u8]) {
+        if read_u32(reply, 6) != Some(ResponseCode::Success as u32) {
+            return;
+        }
+        match command {
+            PcrCommand::Extend { pcr, digests } => {
+                self.push(pcr, "guest", "PCR_Extend", None, digests);
+            }
+            PcrCommand::Event { pcr, event_size } => {
+                let digests = parse_event_reply(reply).unwrap_or_default();
+                self.push(pcr, "guest", "PCR_Event", Some(event_size), digests);
+            }
+        }
+    }
+
+    /// Logs a successful host `PCR_Event` with the given reply.
+    pub fn log_host_event(&mut self, pcr: u32, event_size: u16, reply: &[u8]) {
+        let digests = parse_event_reply(reply).unwrap_or_default();
+        self.push(pcr, "host", "PCR_Event", Some(event_size), digests);
+    }
+
+    fn push(
+        &mut self,
+        pcr: u32,
+        source: &'static str,
+        command: &'static str,
+        event_size: Option<u16>,
+        digests: Vec<(&'static str, String)>,
+    ) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(PcrLogEntry {
+            time: SystemTime::now(),
+            pcr,
+            source,
+            command,
+            event_size,
+            digests,
+        });
+    }
+}
+
+/// Parses a guest command, returning `None` if it is not a well-formed
+/// `PCR_Extend` or `PCR_Event`.
+pub(crate) fn parse_command(command: &[u8]) -> Option<PcrCommand> {
+    let command_code = CommandCodeEnum::from_u32(read_u32(command, 6)?)?;
+    let pcr = read_u32(command, HEADER_SIZE)?;
+    let auth_size = read_u32(command, HEADER_SIZE + 4)? as usize;
+    let params = command.get(HEADER_SIZE + 8 + auth_size..)?;
+    match command_code {
+        CommandCodeEnum::PCR_Extend => Some(PcrCommand::Extend {
+            pcr,
+            digests: parse_digests(params)?,
+        }),
+        CommandCodeEnum::PCR_Event => Some(PcrCommand::Event {
+            pcr,
+            event_size: read_u16(params, 0)?,
+        }),
+        _ => None,
+    }
+}
+
+/// Parses the digests from a successful `PCR_Event` reply.
+pub(crate) fn parse_event_reply(reply: &[u8]) -> Option<Vec<(&'static str, String)>> {
+    // The digests follow the header and the parameter size.
+    parse_digests(reply.get(HEADER_SIZE + 4..)?)
+}
+
+/// Parses a `TPML_DIGEST_VALUES`.
+fn parse_digests(mut data: &[u8]) -> Option<Vec<(&'static str, String)>> {
+    let count = read_u32(data, 0)?;
+    data = &data[4..];
+    let mut digests = Vec::new();
+    for _ in 0..count {
+        let (name, size) = match AlgIdEnum::from_u16(read_u16(data, 0)?)? {
+            AlgIdEnum::SHA => ("sha1", 20),
+            AlgIdEnum::SHA256 => ("sha256", 32),
+            AlgIdEnum::SHA384 => ("sha384", 48),
+            AlgIdEnum::SHA512 => ("sha512", 64),
+            AlgIdEnum::SM3_256 => ("sm3_256", 32),
+            _ => return None,
+        };
+        let digest = data.get(2..2 + size)?;
+        digests.push((name, hex::encode(digest)));
+        data = &data[2 + size..];
+    }
+    Some(digests)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_extend() {
+        let mut command = Vec::new();
+        command.extend_from_slice(&0x8002u16.to_be_bytes()); // TPM_ST_SESSIONS
+        command.extend_from_slice(&0u32.to_be_bytes());
+        command.extend_from_slice(&(CommandCodeEnum::PCR_Extend as u32).to_be_bytes());
+        command.extend_from_slice(&7u32.to_be_bytes());
+        command.extend_from_slice(&9u32.to_be_bytes());
+        command.extend_from_slice(&[0x40, 0, 0, 9, 0, 0, 0, 0, 0]); // TPM_RS_PW
+        command.extend_from_slice(&1u32.to_be_bytes());
+        command.extend_from_slice(&(AlgIdEnum::SHA256 as u16).to_be_bytes());
+        command.extend_from_slice(&[0xab; 32]);
+
+        let PcrCommand::Extend { pcr, digests } = parse_command(&command).unwrap() else {
+            panic!()
+        };
+        assert_eq!(pcr, 7);
+        assert_eq!(digests, [("sha256", "ab".repeat(32))]);
+
+        // Truncated digests are rejected.
+        assert!(parse_command(&command[..command.len() - 1]).is_none());
+    }
+}
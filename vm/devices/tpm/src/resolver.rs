@@ -117,6 +117,8 @@ async fn resolve(
             ak_cert_type,
             resource.guest_secret_key,
             logger,
+            resource.pcr_banks,
+            resource.pcr_seeds,
         )
         .await
         .map_err(ResolveTpmError::Tpm)?;
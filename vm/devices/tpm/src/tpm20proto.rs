@@ -56,6 +56,8 @@ pub enum TpmProtoError {
     NvWriteData(#[source] InvalidInput),
     #[error("input pcr_allocation to PcrAllocate is invalid")]
     PcrAllocatePcrAllocation(#[source] InvalidInput),
+    #[error("input event_data to PcrEvent is invalid")]
+    PcrEventData(#[source] InvalidInput),
     #[error("input data to Import is invalid")]
     ImportData(#[source] InvalidInput),
 }
@@ -2261,6 +2263,91 @@ fn payload_size(&self) -> usize {
         }
     }
 
+    // === Pcr Event === //
+
+    #[repr(C)]
+    #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+    pub struct PcrEventCmd {
+        header: CmdHeader,
+        pcr_handle: u32_be,
+        // Authorization area
+        auth_size: u32_be,
+        auth: common::CmdAuth,
+        // Parameters
+        event_data: Tpm2bBuffer,
+    }
+
+    impl PcrEventCmd {
+        pub fn new(
+            session: SessionTag,
+            pcr_handle: u32,
+            auth: common::CmdAuth,
+            event_data: &[u8],
+        ) -> Result<Self, TpmProtoError> {
+            let event_data = Tpm2bBuffer::new(event_data).map_err(TpmProtoError::PcrEventData)?;
+
+            let mut cmd = Self {
+                header: CmdHeader::new::<Self>(session, CommandCodeEnum::PCR_Event.into()),
+                pcr_handle: pcr_handle.into(),
+                auth_size: (size_of::<common::CmdAuth>() as u32).into(),
+                auth,
+                event_data,
+            };
+
+            cmd.header.size = new_u32_be(cmd.payload_size() as u32);
+
+            Ok(cmd)
+        }
+
+        pub fn serialize(&self) -> Vec<u8> {
+            let mut buffer = Vec::new();
+
+            buffer.extend_from_slice(self.header.as_bytes());
+            buffer.extend_from_slice(self.pcr_handle.as_bytes());
+            buffer.extend_from_slice(self.auth_size.as_bytes());
+            buffer.extend_from_slice(self.auth.as_bytes());
+            buffer.extend_from_slice(&self.event_data.serialize());
+
+            buffer
+        }
+
+        pub fn payload_size(&self) -> usize {
+            let mut payload_size = 0;
+
+            payload_size += size_of_val(&self.header);
+            payload_size += size_of_val(&self.pcr_handle);
+            payload_size += size_of_val(&self.auth_size);
+            payload_size += size_of_val(&self.auth);
+            payload_size += self.event_data.payload_size();
+
+            payload_size
+        }
+    }
+
+    /// The fixed part of the `PCR_Event` reply. The digests that follow are
+    /// parsed by the PCR log.
+    #[repr(C)]
+    #[derive(Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+    pub struct PcrEventReply {
+        pub header: ReplyHeader,
+    }
+
+    impl TpmCommand for PcrEventCmd {
+        type Reply = PcrEventReply;
+    }
+
+    impl TpmReply for PcrEventReply {
+        type Command = PcrEventCmd;
+
+        fn deserialize(bytes: &[u8]) -> Option<Self> {
+            Some(Self::read_from_prefix(bytes).ok()?.0) // TODO: zerocopy: tpm better error? (https://github.com/microsoft/openvmm/issues/759)
+        }
+
+        fn payload_size(&self) -> usize {
+            size_of::<Self>()
+        }
+    }
+
     // === ChangeSeed === //
 
     #[repr(C)]
@@ -1279,6 +1279,40 @@ pub fn pcr_allocate(
         }
     }
 
+    /// Helper function to send PCR_Event command, which extends the PCR in
+    /// every active bank with the digest of `event_data`.
+    ///
+    /// On success, the digests are left in the reply buffer.
+    ///
+    /// # Arguments
+    /// * `pcr` - The index of the PCR to extend.
+    /// * `event_data` - The event data to measure.
+    ///
+    pub fn pcr_event(&mut self, pcr: u32, event_data: &[u8]) -> Result<(), TpmCommandError> {
+        use tpm20proto::protocol::PcrEventCmd;
+
+        let session_tag = SessionTagEnum::Sessions;
+        let cmd = PcrEventCmd::new(
+            session_tag.into(),
+            pcr,
+            CmdAuth::new(TPM20_RS_PW, 0, 0, 0),
+            event_data,
+        )
+        .map_err(TpmCommandError::TpmCommandCreationFailed)?;
+
+        self.tpm_engine
+            .execute_command(&mut cmd.serialize(), &mut self.reply_buffer)
+            .map_err(TpmCommandError::TpmExecuteCommand)?;
+
+        match PcrEventCmd::base_validate_reply(&self.reply_buffer, session_tag) {
+            Err(error) => Err(TpmCommandError::InvalidResponse(error))?,
+            Ok((res, false)) => Err(TpmCommandError::TpmCommandFailed {
+                response_code: res.header.response_code.get(),
+            })?,
+            Ok((_res, true)) => Ok(()),
+        }
+    }
+
     /// Helper function to send ChangeEPS and ChangePPS commands.
     ///
     /// # Arguments
@@ -2957,6 +2991,28 @@ fn test_change_seed() {
         }
     }
 
+    #[test]
+    fn test_pcr_event() {
+        let mut tpm_engine_helper = create_tpm_engine_helper();
+        restart_tpm_engine(&mut tpm_engine_helper, false, true);
+
+        // Positive test
+        let result = tpm_engine_helper.pcr_event(8, b"openvmm");
+        assert!(result.is_ok());
+        let log_entry = crate::pcr_log::parse_event_reply(&tpm_engine_helper.reply_buffer);
+        assert!(!log_entry.unwrap().is_empty());
+
+        // Negative test
+        let result = tpm_engine_helper.pcr_event(0x100, b"openvmm");
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        if let TpmCommandError::TpmCommandFailed { response_code } = err {
+            assert_ne!(response_code, ResponseCode::Success as u32);
+        } else {
+            panic!()
+        }
+    }
+
     #[test]
     fn test_pcr_allocate() {
         let mut tpm_engine_helper = create_tpm_engine_helper();
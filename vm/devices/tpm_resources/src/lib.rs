@@ -30,6 +30,22 @@ pub struct TpmDeviceHandle {
     pub guest_secret_key: Option<Vec<u8>>,
     /// Optional logger to send event to the host
     pub logger: Option<Resource<TpmLoggerKind>>,
+    /// PCR banks to allocate, as a bitmap of SHA-1 (bit 0), SHA-256 (bit 1),
+    /// and SHA-384 (bit 2). If set, guest requests to change the allocation
+    /// are refused.
+    pub pcr_banks: Option<u32>,
+    /// Measurements to extend into PCRs each time the TPM starts, before the
+    /// firmware's own.
+    pub pcr_seeds: Vec<TpmPcrSeed>,
+}
+
+/// A measurement to extend into a PCR when the TPM starts.
+#[derive(MeshPayload, Clone, Debug)]
+pub struct TpmPcrSeed {
+    /// The PCR index.
+    pub pcr: u32,
+    /// The event data, whose digest in each bank is extended into the PCR.
+    pub event_data: Vec<u8>,
 }
 
 impl ResourceId<ChipsetDeviceHandleKind> for TpmDeviceHandle {
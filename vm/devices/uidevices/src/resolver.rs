@@ -58,7 +58,7 @@ async fn resolve(
             .map_err(VideoError::Framebuffer)?;
         let device = SimpleDeviceWrapper::new(
             input.driver_source.simple(),
-            Video::new(framebuffer.0).map_err(VideoError::Video)?,
+            Video::new(framebuffer.0, resource.resolution_recv).map_err(VideoError::Video)?,
         );
         Ok(device.into())
     }
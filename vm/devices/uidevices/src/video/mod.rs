@@ -149,15 +149,57 @@ fn parse_packet(buf: &[u8]) -> Result<Request, Error> {
     Ok(request)
 }
 
+/// The size of VRAM, matching the MMIO space requested in the offer.
+const VRAM_SIZE: usize = 8 * 1024 * 1024;
+
 /// Vmbus synthetic video device.
 pub struct Video {
     control: Box<dyn FramebufferControl>,
+    preferred_resolution: PreferredResolution,
 }
 
 impl Video {
     /// Creates a new video device.
-    pub fn new(control: Box<dyn FramebufferControl>) -> anyhow::Result<Self> {
-        Ok(Self { control })
+    ///
+    /// `resolution_recv` receives display resolutions requested by the host,
+    /// which are reported to the guest as its preferred resolution the next
+    /// time it queries the supported resolutions.
+    pub fn new(
+        control: Box<dyn FramebufferControl>,
+        resolution_recv: Option<mesh::Receiver<(u16, u16)>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            control,
+            preferred_resolution: PreferredResolution {
+                recv: resolution_recv,
+                resolution: None,
+            },
+        })
+    }
+}
+
+/// The display resolution most recently requested by the host.
+struct PreferredResolution {
+    recv: Option<mesh::Receiver<(u16, u16)>>,
+    resolution: Option<(u16, u16)>,
+}
+
+impl PreferredResolution {
+    fn get(&mut self) -> Option<(u16, u16)> {
+        if let Some(recv) = &mut self.recv {
+            while let Ok((width, height)) = recv.try_recv() {
+                if width as usize * height as usize * 4 <= VRAM_SIZE {
+                    self.resolution = Some((width, height));
+                } else {
+                    tracelimit::warn_ratelimited!(
+                        width,
+                        height,
+                        "requested resolution does not fit in vram"
+                    );
+                }
+            }
+        }
+        self.resolution
     }
 }
 
@@ -300,6 +342,12 @@ fn offer(&self) -> OfferParams {
 
     fn inspect(&mut self, req: inspect::Request<'_>, task: Option<&mut VideoChannel>) {
         let mut resp = req.respond();
+        resp.field(
+            "preferred_resolution",
+            self.preferred_resolution
+                .get()
+                .map(|(width, height)| format!("{width}x{height}")),
+        );
         if let Some(this) = task {
             let (version, state) = match &this.state {
                 ChannelState::ReadVersion => (None, "read_version"),
@@ -342,7 +390,10 @@ async fn run(
         channel: &mut VideoChannel,
     ) -> Result<(), task_control::Cancelled> {
         stop.until_stopped(async {
-            match channel.process(&mut self.control).await {
+            match channel
+                .process(&mut self.control, &mut self.preferred_resolution)
+                .await
+            {
                 Ok(()) => {}
                 Err(err) => tracing::error!(error = &err as &dyn std::error::Error, "video error"),
             }
@@ -406,6 +457,7 @@ async fn send_packet<T: IntoBytes + ?Sized + Immutable + KnownLayout>(
     async fn process(
         &mut self,
         framebuffer: &mut Box<dyn FramebufferControl>,
+        preferred_resolution: &mut PreferredResolution,
     ) -> Result<(), Error> {
         let mut channel = &mut self.channel;
         loop {
@@ -560,17 +612,25 @@ async fn process(
                             } else {
                                 const RESOLUTIONS: &[(u16, u16)] = &[(1024, 768), (1280, 1024)];
 
+                                // Offer the host's requested resolution first,
+                                // as the default.
+                                let mut resolutions = RESOLUTIONS.to_vec();
+                                if let Some(preferred) = preferred_resolution.get() {
+                                    resolutions.retain(|&r| r != preferred);
+                                    resolutions.insert(0, preferred);
+                                }
+
                                 let mut packet = Vec::new();
                                 packet.extend_from_slice(
                                     protocol::SupportedResolutionsResponseMessage {
                                         edid_block: protocol::EDID_BLOCK,
-                                        resolution_count: RESOLUTIONS.len().try_into().unwrap(),
+                                        resolution_count: resolutions.len().try_into().unwrap(),
                                         default_resolution_index: 0,
                                         is_standard: 0,
                                     }
                                     .as_bytes(),
                                 );
-                                for r in RESOLUTIONS {
+                                for r in &resolutions {
                                     packet.extend_from_slice(
                                         protocol::ScreenInfo {
                                             width: r.0.into(),
@@ -40,6 +40,9 @@ impl ResourceId<VmbusDeviceHandleKind> for SynthMouseHandle {
 pub struct SynthVideoHandle {
     /// The framebuffer memory to map into the guest for rendering.
     pub framebuffer: Resource<FramebufferHandleKind>,
+    /// Requests from the host UI for a display resolution, as (width,
+    /// height), which is reported to the guest as its preferred resolution.
+    pub resolution_recv: Option<mesh::Receiver<(u16, u16)>>,
 }
 
 impl ResourceId<VmbusDeviceHandleKind> for SynthVideoHandle {
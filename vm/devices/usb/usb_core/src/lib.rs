@@ -0,0 +1,95 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Core USB device traits and types, shared between USB host controller
+//! emulators and the emulated USB devices attached to them.
+
+#![forbid(unsafe_code)]
+
+pub mod spec;
+
+use async_trait::async_trait;
+use inspect::Inspect;
+use inspect::InspectMut;
+use spec::SetupPacket;
+use thiserror::Error;
+use vm_resource::CanResolveTo;
+use vm_resource::kind::UsbDeviceHandleKind;
+use vmcore::vm_task::VmTaskDriverSource;
+
+impl CanResolveTo<ResolvedUsbDevice> for UsbDeviceHandleKind {
+    type Input<'a> = ResolveUsbDeviceHandleParams<'a>;
+}
+
+/// A resolved [`UsbDevice`].
+pub struct ResolvedUsbDevice(pub Box<dyn UsbDevice>);
+
+impl<T: 'static + UsbDevice> From<T> for ResolvedUsbDevice {
+    fn from(value: T) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+/// Parameters used when resolving [`UsbDeviceHandleKind`].
+pub struct ResolveUsbDeviceHandleParams<'a> {
+    /// The VM task driver source.
+    pub driver_source: &'a VmTaskDriverSource,
+}
+
+/// The speed at which a USB device operates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+pub enum UsbSpeed {
+    /// USB 1.x full speed (12 Mb/s).
+    Full,
+    /// USB 1.x low speed (1.5 Mb/s).
+    Low,
+    /// USB 2.0 high speed (480 Mb/s).
+    High,
+}
+
+/// An error returned by a USB device for a transfer.
+#[derive(Debug, Error)]
+pub enum UsbError {
+    /// The device responded with a STALL handshake, halting the endpoint.
+    #[error("endpoint stalled")]
+    Stall,
+    /// The device responded with a NAK handshake: it has no data to return,
+    /// or cannot accept data, yet. The host controller retries the transfer.
+    ///
+    /// This is only valid for bulk and interrupt endpoints. Since the retry is
+    /// immediate, devices should wait for a while, such as the endpoint's
+    /// polling interval, before returning it.
+    #[error("endpoint not ready")]
+    Nak,
+}
+
+/// An emulated USB device.
+///
+/// The host controller issues at most one transfer to a device at a time, so
+/// transfer methods take `&mut self`. The device address is managed by the
+/// host controller; `SET_ADDRESS` requests are not forwarded to the device.
+#[async_trait]
+pub trait UsbDevice: Send + InspectMut {
+    /// Returns the speed of the device.
+    fn speed(&self) -> UsbSpeed;
+
+    /// Resets the device to its default, unconfigured state, as after a bus
+    /// reset.
+    fn reset(&mut self);
+
+    /// Handles a control transfer on the default control endpoint.
+    ///
+    /// For host-to-device requests, `data` contains the data stage. For
+    /// device-to-host requests, `data` is empty and the device returns up to
+    /// `setup.length` bytes of data.
+    async fn control(&mut self, setup: SetupPacket, data: &[u8]) -> Result<Vec<u8>, UsbError>;
+
+    /// Handles a device-to-host transfer of up to `len` bytes on
+    /// `endpoint`.
+    ///
+    /// Returning fewer than `len` bytes ends the transfer with a short packet.
+    async fn transfer_in(&mut self, endpoint: u8, len: usize) -> Result<Vec<u8>, UsbError>;
+
+    /// Handles a host-to-device transfer on `endpoint`.
+    async fn transfer_out(&mut self, endpoint: u8, data: &[u8]) -> Result<(), UsbError>;
+}
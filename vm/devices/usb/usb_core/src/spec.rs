@@ -0,0 +1,208 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions from chapter 9 of the USB 2.0 specification.
+
+#![expect(missing_docs)]
+
+use inspect::Inspect;
+use open_enum::open_enum;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+type U16LE = zerocopy::U16<zerocopy::LE>;
+
+/// The setup packet of a control transfer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes, Inspect)]
+pub struct SetupPacket {
+    #[inspect(hex)]
+    pub request_type: u8,
+    #[inspect(hex)]
+    pub request: u8,
+    #[inspect(hex)]
+    pub value: u16,
+    #[inspect(hex)]
+    pub index: u16,
+    pub length: u16,
+}
+
+const _: () = assert!(size_of::<SetupPacket>() == 8);
+
+pub const REQUEST_TYPE_DIRECTION_IN: u8 = 0x80;
+pub const REQUEST_TYPE_TYPE_MASK: u8 = 0x60;
+pub const REQUEST_TYPE_RECIPIENT_MASK: u8 = 0x1f;
+
+open_enum! {
+    pub enum RequestKind: u8 {
+        STANDARD = 0x00,
+        CLASS = 0x20,
+        VENDOR = 0x40,
+    }
+}
+
+open_enum! {
+    pub enum Recipient: u8 {
+        DEVICE = 0,
+        INTERFACE = 1,
+        ENDPOINT = 2,
+        OTHER = 3,
+    }
+}
+
+impl SetupPacket {
+    /// Returns true if the data stage of the request is device-to-host.
+    pub fn is_in(&self) -> bool {
+        self.request_type & REQUEST_TYPE_DIRECTION_IN != 0
+    }
+
+    pub fn kind(&self) -> RequestKind {
+        RequestKind(self.request_type & REQUEST_TYPE_TYPE_MASK)
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(self.request_type & REQUEST_TYPE_RECIPIENT_MASK)
+    }
+}
+
+open_enum! {
+    /// Standard request codes (table 9-4).
+    pub enum StandardRequest: u8 {
+        GET_STATUS = 0,
+        CLEAR_FEATURE = 1,
+        SET_FEATURE = 3,
+        SET_ADDRESS = 5,
+        GET_DESCRIPTOR = 6,
+        SET_DESCRIPTOR = 7,
+        GET_CONFIGURATION = 8,
+        SET_CONFIGURATION = 9,
+        GET_INTERFACE = 10,
+        SET_INTERFACE = 11,
+        SYNCH_FRAME = 12,
+    }
+}
+
+open_enum! {
+    /// Descriptor types (table 9-5).
+    pub enum DescriptorType: u8 {
+        DEVICE = 1,
+        CONFIGURATION = 2,
+        STRING = 3,
+        INTERFACE = 4,
+        ENDPOINT = 5,
+        DEVICE_QUALIFIER = 6,
+        OTHER_SPEED_CONFIGURATION = 7,
+        INTERFACE_POWER = 8,
+        BOS = 15,
+    }
+}
+
+/// The feature selector for the endpoint halt feature (table 9-6).
+pub const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+/// The direction bit of an endpoint address.
+pub const ENDPOINT_DIRECTION_IN: u8 = 0x80;
+
+pub const ENDPOINT_ATTRIBUTES_CONTROL: u8 = 0;
+pub const ENDPOINT_ATTRIBUTES_ISOCHRONOUS: u8 = 1;
+pub const ENDPOINT_ATTRIBUTES_BULK: u8 = 2;
+pub const ENDPOINT_ATTRIBUTES_INTERRUPT: u8 = 3;
+
+pub const CONFIGURATION_ATTRIBUTES_RESERVED: u8 = 0x80;
+pub const CONFIGURATION_ATTRIBUTES_SELF_POWERED: u8 = 0x40;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct DeviceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub usb_version: U16LE,
+    pub device_class: u8,
+    pub device_subclass: u8,
+    pub device_protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: U16LE,
+    pub product_id: U16LE,
+    pub device_version: U16LE,
+    pub manufacturer_index: u8,
+    pub product_index: u8,
+    pub serial_number_index: u8,
+    pub num_configurations: u8,
+}
+
+const _: () = assert!(size_of::<DeviceDescriptor>() == 18);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct DeviceQualifierDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub usb_version: U16LE,
+    pub device_class: u8,
+    pub device_subclass: u8,
+    pub device_protocol: u8,
+    pub max_packet_size0: u8,
+    pub num_configurations: u8,
+    pub reserved: u8,
+}
+
+const _: () = assert!(size_of::<DeviceQualifierDescriptor>() == 10);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ConfigurationDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub total_length: U16LE,
+    pub num_interfaces: u8,
+    pub configuration_value: u8,
+    pub configuration_index: u8,
+    pub attributes: u8,
+    /// In units of 2 mA.
+    pub max_power: u8,
+}
+
+const _: () = assert!(size_of::<ConfigurationDescriptor>() == 9);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct InterfaceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub interface_number: u8,
+    pub alternate_setting: u8,
+    pub num_endpoints: u8,
+    pub interface_class: u8,
+    pub interface_subclass: u8,
+    pub interface_protocol: u8,
+    pub interface_index: u8,
+}
+
+const _: () = assert!(size_of::<InterfaceDescriptor>() == 9);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct EndpointDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub endpoint_address: u8,
+    pub attributes: u8,
+    pub max_packet_size: U16LE,
+    pub interval: u8,
+}
+
+const _: () = assert!(size_of::<EndpointDescriptor>() == 7);
+
+/// Encodes `s` as a string descriptor.
+pub fn string_descriptor(s: &str) -> Vec<u8> {
+    let mut v = vec![0, DescriptorType::STRING.0];
+    v.extend(s.encode_utf16().flat_map(|c| c.to_le_bytes()));
+    v[0] = v.len() as u8;
+    v
+}
+
+/// The string descriptor at index zero, listing the supported language IDs
+/// (US English only).
+pub const STRING_DESCRIPTOR_LANGUAGES: [u8; 4] = [4, DescriptorType::STRING.0, 0x09, 0x04];
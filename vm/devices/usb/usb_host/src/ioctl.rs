@@ -0,0 +1,111 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Linux usbfs IOCTLs, from `include/uapi/linux/usbdevice_fs.h`.
+
+#![expect(non_camel_case_types)]
+
+use nix::ioctl_none;
+use nix::ioctl_readwrite;
+use nix::ioctl_write_ptr_bad;
+use nix::request_code_read;
+use std::ffi::c_int;
+use std::ffi::c_uint;
+use std::ffi::c_void;
+
+const USBDEVFS_MAGIC: u8 = b'U';
+
+#[repr(C)]
+pub struct usbdevfs_ctrltransfer {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+    /// In milliseconds.
+    pub timeout: u32,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct usbdevfs_bulktransfer {
+    pub ep: c_uint,
+    pub len: c_uint,
+    /// In milliseconds.
+    pub timeout: c_uint,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct usbdevfs_setinterface {
+    pub interface: c_uint,
+    pub altsetting: c_uint,
+}
+
+#[repr(C)]
+pub struct usbdevfs_ioctl {
+    pub ifno: c_int,
+    pub ioctl_code: c_int,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct usbdevfs_disconnect_claim {
+    pub interface: c_uint,
+    pub flags: c_uint,
+    pub driver: [u8; 256],
+}
+
+/// The values returned by [`usbdevfs_get_speed`], from `enum usb_device_speed`.
+pub const USB_SPEED_LOW: c_int = 1;
+pub const USB_SPEED_FULL: c_int = 2;
+pub const USB_SPEED_HIGH: c_int = 3;
+
+/// The `ioctl_code` for [`usbdevfs_ioctl`] that binds kernel drivers to the
+/// interface again.
+// #define USBDEVFS_CONNECT _IO('U', 23)
+pub const USBDEVFS_CONNECT: c_int = nix::request_code_none!(USBDEVFS_MAGIC, 23) as c_int;
+
+// #define USBDEVFS_CONTROL _IOWR('U', 0, struct usbdevfs_ctrltransfer)
+ioctl_readwrite!(usbdevfs_control, USBDEVFS_MAGIC, 0, usbdevfs_ctrltransfer);
+// #define USBDEVFS_BULK _IOWR('U', 2, struct usbdevfs_bulktransfer)
+ioctl_readwrite!(usbdevfs_bulk, USBDEVFS_MAGIC, 2, usbdevfs_bulktransfer);
+
+// The following are defined with _IOR but pass their argument to the kernel.
+
+// #define USBDEVFS_SETINTERFACE _IOR('U', 4, struct usbdevfs_setinterface)
+ioctl_write_ptr_bad!(
+    usbdevfs_setinterface,
+    request_code_read!(USBDEVFS_MAGIC, 4, size_of::<usbdevfs_setinterface>()),
+    usbdevfs_setinterface
+);
+// #define USBDEVFS_SETCONFIGURATION _IOR('U', 5, unsigned int)
+ioctl_write_ptr_bad!(
+    usbdevfs_setconfiguration,
+    request_code_read!(USBDEVFS_MAGIC, 5, size_of::<c_uint>()),
+    c_uint
+);
+// #define USBDEVFS_RELEASEINTERFACE _IOR('U', 16, unsigned int)
+ioctl_write_ptr_bad!(
+    usbdevfs_releaseinterface,
+    request_code_read!(USBDEVFS_MAGIC, 16, size_of::<c_uint>()),
+    c_uint
+);
+// #define USBDEVFS_IOCTL _IOWR('U', 18, struct usbdevfs_ioctl)
+ioctl_readwrite!(usbdevfs_ioctl, USBDEVFS_MAGIC, 18, usbdevfs_ioctl);
+// #define USBDEVFS_RESET _IO('U', 20)
+ioctl_none!(usbdevfs_reset, USBDEVFS_MAGIC, 20);
+// #define USBDEVFS_CLEAR_HALT _IOR('U', 21, unsigned int)
+ioctl_write_ptr_bad!(
+    usbdevfs_clear_halt,
+    request_code_read!(USBDEVFS_MAGIC, 21, size_of::<c_uint>()),
+    c_uint
+);
+// #define USBDEVFS_DISCONNECT_CLAIM _IOR('U', 27, struct usbdevfs_disconnect_claim)
+ioctl_write_ptr_bad!(
+    usbdevfs_disconnect_claim,
+    request_code_read!(USBDEVFS_MAGIC, 27, size_of::<usbdevfs_disconnect_claim>()),
+    usbdevfs_disconnect_claim
+);
+// #define USBDEVFS_GET_SPEED _IO('U', 31)
+ioctl_none!(usbdevfs_get_speed, USBDEVFS_MAGIC, 31);
@@ -0,0 +1,523 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Passthrough of host USB devices to the guest, via Linux usbfs.
+//!
+//! The device's interfaces are detached from their host drivers and claimed
+//! for the lifetime of the device. Transfers are forwarded to the host device
+//! synchronously, one at a time, with the host controller emulator retrying
+//! IN transfers that time out. Only devices whose interfaces are all HID class
+//! are supported for now.
+
+#![cfg(target_os = "linux")]
+// UNSAFETY: Calling usbfs ioctls.
+#![expect(unsafe_code)]
+
+mod ioctl;
+pub mod resolver;
+
+use async_trait::async_trait;
+use inspect::InspectMut;
+use nix::errno::Errno;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::prelude::*;
+use std::sync::Arc;
+use thiserror::Error;
+use usb_core::UsbDevice;
+use usb_core::UsbError;
+use usb_core::UsbSpeed;
+use usb_core::spec;
+use usb_core::spec::DescriptorType;
+use usb_core::spec::Recipient;
+use usb_core::spec::RequestKind;
+use usb_core::spec::SetupPacket;
+use usb_core::spec::StandardRequest;
+use zerocopy::FromBytes;
+
+const INTERFACE_CLASS_HID: u8 = 0x03;
+
+/// The timeout for control and OUT transfers.
+const TRANSFER_TIMEOUT_MS: u32 = 5000;
+/// The timeout for IN transfers, after which the transfer is NAKed so that
+/// the host controller can issue other transfers to the device. This bounds
+/// how long a pending interrupt IN transfer delays, for example, a
+/// `SET_REPORT` request.
+const IN_TIMEOUT_MS: u32 = 50;
+
+/// An error returned when opening a host USB device.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The device descriptors could not be read from the device node.
+    #[error("failed to read device descriptors")]
+    ReadDescriptors(#[source] std::io::Error),
+    /// The device descriptors were malformed.
+    #[error("invalid device descriptors")]
+    InvalidDescriptors,
+    /// The device has an interface of an unsupported class.
+    #[error("interface {interface} has class {class:#x}, only HID devices are supported")]
+    NotHid {
+        /// The interface number.
+        interface: u8,
+        /// The interface class.
+        class: u8,
+    },
+    /// The device speed could not be queried.
+    #[error("failed to query device speed")]
+    Speed(#[source] Errno),
+    /// The device operates at a speed the host controller does not support.
+    #[error("unsupported device speed {0}")]
+    UnsupportedSpeed(i32),
+    /// The active configuration could not be queried.
+    #[error("failed to query active configuration")]
+    Configuration(#[source] Errno),
+}
+
+/// A configuration of the host device.
+#[derive(Debug, PartialEq)]
+struct Configuration {
+    value: u8,
+    interfaces: Vec<u8>,
+}
+
+/// A host USB device passed through to the guest.
+#[derive(InspectMut)]
+pub struct UsbHostDevice {
+    #[inspect(skip)]
+    file: Arc<File>,
+    speed: UsbSpeed,
+    #[inspect(skip)]
+    configurations: Vec<Configuration>,
+    /// The interfaces currently claimed from the host.
+    #[inspect(iter_by_index)]
+    claimed: Vec<u8>,
+    reset_pending: bool,
+}
+
+impl UsbHostDevice {
+    /// Creates a new device for the usbfs node `file`, claiming the
+    /// interfaces of its active configuration.
+    pub fn new(mut file: File) -> Result<Self, Error> {
+        // Reading the device node returns the device descriptor followed by
+        // all the configuration descriptors.
+        let mut descriptors = Vec::new();
+        file.read_to_end(&mut descriptors)
+            .map_err(Error::ReadDescriptors)?;
+        let configurations = parse_descriptors(&descriptors)?;
+
+        // SAFETY: The FD is owned by `file`, and this IOCTL takes no
+        // arguments.
+        let speed = unsafe { ioctl::usbdevfs_get_speed(file.as_raw_fd()) }.map_err(Error::Speed)?;
+        let speed = match speed {
+            ioctl::USB_SPEED_LOW => UsbSpeed::Low,
+            ioctl::USB_SPEED_FULL => UsbSpeed::Full,
+            ioctl::USB_SPEED_HIGH => UsbSpeed::High,
+            speed => return Err(Error::UnsupportedSpeed(speed)),
+        };
+
+        let mut active = [0];
+        control(
+            &file,
+            SetupPacket {
+                request_type: spec::REQUEST_TYPE_DIRECTION_IN,
+                request: StandardRequest::GET_CONFIGURATION.0,
+                value: 0,
+                index: 0,
+                length: 1,
+            },
+            &mut active,
+        )
+        .map_err(Error::Configuration)?;
+
+        let claimed = configurations
+            .iter()
+            .find(|config| config.value == active[0])
+            .map(|config| claim_interfaces(&file, &config.interfaces))
+            .unwrap_or_default();
+
+        Ok(Self {
+            file: Arc::new(file),
+            speed,
+            configurations,
+            claimed,
+            reset_pending: false,
+        })
+    }
+
+    /// Runs `f` against the device node on a blocking thread.
+    async fn run<T: 'static + Send>(
+        &self,
+        f: impl 'static + Send + FnOnce(&File) -> nix::Result<T>,
+    ) -> nix::Result<T> {
+        let file = self.file.clone();
+        blocking::unblock(move || f(&file)).await
+    }
+
+    /// Resets the host device if the guest has reset the port since the last
+    /// transfer.
+    async fn flush_reset(&mut self) {
+        if std::mem::take(&mut self.reset_pending) {
+            let r = self.run(|file| {
+                // SAFETY: The FD is owned by `file`, and this IOCTL takes no
+                // arguments.
+                unsafe { ioctl::usbdevfs_reset(file.as_raw_fd()) }
+            });
+            if let Err(err) = r.await {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to reset host usb device"
+                );
+            }
+        }
+    }
+
+    async fn set_configuration(&mut self, value: u8) -> Result<(), UsbError> {
+        let interfaces = if value == 0 {
+            Vec::new()
+        } else {
+            self.configurations
+                .iter()
+                .find(|config| config.value == value)
+                .ok_or(UsbError::Stall)?
+                .interfaces
+                .clone()
+        };
+        // The kernel does not allow changing the configuration while any
+        // interface is claimed.
+        let claimed = std::mem::take(&mut self.claimed);
+        let r = self
+            .run(move |file| {
+                release_interfaces(file, &claimed);
+                let value: u32 = value.into();
+                // SAFETY: The FD is owned by `file`, and `value` is valid for
+                // the duration of the IOCTL.
+                unsafe { ioctl::usbdevfs_setconfiguration(file.as_raw_fd(), &value)? };
+                Ok(claim_interfaces(file, &interfaces))
+            })
+            .await;
+        self.claimed = r.map_err(|err| transfer_error(err, "set configuration"))?;
+        Ok(())
+    }
+}
+
+impl Drop for UsbHostDevice {
+    fn drop(&mut self) {
+        release_interfaces(&self.file, &self.claimed);
+        for &interface in &self.claimed {
+            let mut req = ioctl::usbdevfs_ioctl {
+                ifno: interface.into(),
+                ioctl_code: ioctl::USBDEVFS_CONNECT,
+                data: std::ptr::null_mut(),
+            };
+            // SAFETY: The FD is owned by `self.file`, and `req` is valid for
+            // the duration of the IOCTL.
+            let r = unsafe { ioctl::usbdevfs_ioctl(self.file.as_raw_fd(), &mut req) };
+            if let Err(err) = r {
+                tracing::debug!(
+                    interface,
+                    error = &err as &dyn std::error::Error,
+                    "failed to reattach host driver"
+                );
+            }
+        }
+    }
+}
+
+/// Parses the descriptors read from a usbfs device node into the device's
+/// configurations.
+fn parse_descriptors(mut data: &[u8]) -> Result<Vec<Configuration>, Error> {
+    let mut configurations = Vec::<Configuration>::new();
+    while !data.is_empty() {
+        let len = data[0] as usize;
+        if len < 2 || len > data.len() {
+            return Err(Error::InvalidDescriptors);
+        }
+        let (desc, rest) = data.split_at(len);
+        data = rest;
+        match DescriptorType(desc[1]) {
+            DescriptorType::CONFIGURATION => {
+                let (config, _) = spec::ConfigurationDescriptor::read_from_prefix(desc)
+                    .map_err(|_| Error::InvalidDescriptors)?;
+                configurations.push(Configuration {
+                    value: config.configuration_value,
+                    interfaces: Vec::new(),
+                });
+            }
+            DescriptorType::INTERFACE => {
+                let (interface, _) = spec::InterfaceDescriptor::read_from_prefix(desc)
+                    .map_err(|_| Error::InvalidDescriptors)?;
+                let config = configurations.last_mut().ok_or(Error::InvalidDescriptors)?;
+                if interface.interface_class != INTERFACE_CLASS_HID {
+                    return Err(Error::NotHid {
+                        interface: interface.interface_number,
+                        class: interface.interface_class,
+                    });
+                }
+                // Alternate settings repeat the interface number.
+                if !config.interfaces.contains(&interface.interface_number) {
+                    config.interfaces.push(interface.interface_number);
+                }
+            }
+            _ => {}
+        }
+    }
+    if configurations.is_empty() {
+        return Err(Error::InvalidDescriptors);
+    }
+    Ok(configurations)
+}
+
+/// Detaches any host driver from `interfaces` and claims them, returning the
+/// interfaces that were claimed.
+fn claim_interfaces(file: &File, interfaces: &[u8]) -> Vec<u8> {
+    let mut claimed = Vec::new();
+    for &interface in interfaces {
+        let req = ioctl::usbdevfs_disconnect_claim {
+            interface: interface.into(),
+            flags: 0,
+            driver: [0; 256],
+        };
+        // SAFETY: The FD is owned by `file`, and `req` is valid for the
+        // duration of the IOCTL.
+        let r = unsafe { ioctl::usbdevfs_disconnect_claim(file.as_raw_fd(), &req) };
+        match r {
+            Ok(_) => claimed.push(interface),
+            Err(err) => tracing::warn!(
+                interface,
+                error = &err as &dyn std::error::Error,
+                "failed to claim host usb interface"
+            ),
+        }
+    }
+    claimed
+}
+
+fn release_interfaces(file: &File, interfaces: &[u8]) {
+    for &interface in interfaces {
+        let interface: u32 = interface.into();
+        // SAFETY: The FD is owned by `file`, and `interface` is valid for the
+        // duration of the IOCTL.
+        let _ = unsafe { ioctl::usbdevfs_releaseinterface(file.as_raw_fd(), &interface) };
+    }
+}
+
+/// Issues the control request `setup` to the device, returning the number of
+/// bytes transferred to or from `data`.
+fn control(file: &File, setup: SetupPacket, data: &mut [u8]) -> nix::Result<usize> {
+    let mut req = ioctl::usbdevfs_ctrltransfer {
+        request_type: setup.request_type,
+        request: setup.request,
+        value: setup.value,
+        index: setup.index,
+        length: data.len() as u16,
+        timeout: TRANSFER_TIMEOUT_MS,
+        data: data.as_mut_ptr().cast(),
+    };
+    // SAFETY: The FD is owned by `file`, and `req` and the buffer it points
+    // to are valid for the duration of the IOCTL.
+    let n = unsafe { ioctl::usbdevfs_control(file.as_raw_fd(), &mut req)? };
+    Ok(n as usize)
+}
+
+/// Issues a bulk or interrupt transfer on `endpoint`, returning the number of
+/// bytes transferred to or from `data`.
+fn bulk(file: &File, endpoint: u8, data: &mut [u8], timeout: u32) -> nix::Result<usize> {
+    let mut req = ioctl::usbdevfs_bulktransfer {
+        ep: endpoint.into(),
+        len: data.len() as u32,
+        timeout,
+        data: data.as_mut_ptr().cast(),
+    };
+    // SAFETY: The FD is owned by `file`, and `req` and the buffer it points
+    // to are valid for the duration of the IOCTL.
+    let n = unsafe { ioctl::usbdevfs_bulk(file.as_raw_fd(), &mut req)? };
+    Ok(n as usize)
+}
+
+/// Converts a failed host transfer to the error to report to the guest.
+fn transfer_error(err: Errno, operation: &str) -> UsbError {
+    if err != Errno::EPIPE {
+        tracelimit::warn_ratelimited!(
+            operation,
+            error = &err as &dyn std::error::Error,
+            "host usb transfer failed"
+        );
+    }
+    UsbError::Stall
+}
+
+#[async_trait]
+impl UsbDevice for UsbHostDevice {
+    fn speed(&self) -> UsbSpeed {
+        self.speed
+    }
+
+    fn reset(&mut self) {
+        // Defer the reset to the next transfer, since it blocks.
+        self.reset_pending = true;
+    }
+
+    async fn control(&mut self, setup: SetupPacket, data: &[u8]) -> Result<Vec<u8>, UsbError> {
+        self.flush_reset().await;
+        if setup.kind() == RequestKind::STANDARD {
+            match (setup.recipient(), StandardRequest(setup.request)) {
+                (Recipient::DEVICE, StandardRequest::SET_CONFIGURATION) => {
+                    self.set_configuration(setup.value as u8).await?;
+                    return Ok(Vec::new());
+                }
+                (Recipient::INTERFACE, StandardRequest::SET_INTERFACE) => {
+                    let req = ioctl::usbdevfs_setinterface {
+                        interface: setup.index.into(),
+                        altsetting: setup.value.into(),
+                    };
+                    self.run(move |file| {
+                        // SAFETY: The FD is owned by `file`, and `req` is
+                        // valid for the duration of the IOCTL.
+                        unsafe { ioctl::usbdevfs_setinterface(file.as_raw_fd(), &req) }
+                    })
+                    .await
+                    .map_err(|err| transfer_error(err, "set interface"))?;
+                    return Ok(Vec::new());
+                }
+                // Clear the halt through the kernel so that it resets the
+                // host-side data toggle, too.
+                (Recipient::ENDPOINT, StandardRequest::CLEAR_FEATURE)
+                    if setup.value == spec::FEATURE_ENDPOINT_HALT =>
+                {
+                    let endpoint: u32 = setup.index.into();
+                    self.run(move |file| {
+                        // SAFETY: The FD is owned by `file`, and `endpoint`
+                        // is valid for the duration of the IOCTL.
+                        unsafe { ioctl::usbdevfs_clear_halt(file.as_raw_fd(), &endpoint) }
+                    })
+                    .await
+                    .map_err(|err| transfer_error(err, "clear halt"))?;
+                    return Ok(Vec::new());
+                }
+                _ => {}
+            }
+        }
+
+        let is_in = setup.is_in();
+        let mut buf = if is_in {
+            vec![0; setup.length as usize]
+        } else {
+            data.to_vec()
+        };
+        self.run(move |file| {
+            let n = control(file, setup, &mut buf)?;
+            if is_in {
+                buf.truncate(n);
+            } else {
+                buf.clear();
+            }
+            Ok(buf)
+        })
+        .await
+        .map_err(|err| transfer_error(err, "control"))
+    }
+
+    async fn transfer_in(&mut self, endpoint: u8, len: usize) -> Result<Vec<u8>, UsbError> {
+        self.flush_reset().await;
+        let mut buf = vec![0; len];
+        let r = self
+            .run(move |file| {
+                let n = bulk(file, endpoint, &mut buf, IN_TIMEOUT_MS)?;
+                buf.truncate(n);
+                Ok(buf)
+            })
+            .await;
+        match r {
+            Ok(buf) => Ok(buf),
+            // The device has no data yet. Any data that arrived just as the
+            // transfer timed out is lost, but for HID devices this is
+            // unlikely since each report fits in a single packet.
+            Err(Errno::ETIMEDOUT) => Err(UsbError::Nak),
+            Err(err) => Err(transfer_error(err, "in")),
+        }
+    }
+
+    async fn transfer_out(&mut self, endpoint: u8, data: &[u8]) -> Result<(), UsbError> {
+        self.flush_reset().await;
+        let mut buf = data.to_vec();
+        self.run(move |file| bulk(file, endpoint, &mut buf, TRANSFER_TIMEOUT_MS))
+            .await
+            .map_err(|err| transfer_error(err, "out"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zerocopy::IntoBytes;
+
+    fn descriptors(interface_classes: &[u8]) -> Vec<u8> {
+        let mut v = vec![18, DescriptorType::DEVICE.0];
+        v.resize(18, 0);
+        v.extend_from_slice(
+            spec::ConfigurationDescriptor {
+                length: 9,
+                descriptor_type: DescriptorType::CONFIGURATION.0,
+                total_length: (9 + 15 * interface_classes.len() as u16).into(),
+                num_interfaces: interface_classes.len() as u8,
+                configuration_value: 1,
+                configuration_index: 0,
+                attributes: spec::CONFIGURATION_ATTRIBUTES_RESERVED,
+                max_power: 50,
+            }
+            .as_bytes(),
+        );
+        for (i, &class) in interface_classes.iter().enumerate() {
+            v.extend_from_slice(
+                spec::InterfaceDescriptor {
+                    length: 9,
+                    descriptor_type: DescriptorType::INTERFACE.0,
+                    interface_number: i as u8,
+                    alternate_setting: 0,
+                    num_endpoints: 0,
+                    interface_class: class,
+                    interface_subclass: 0,
+                    interface_protocol: 0,
+                    interface_index: 0,
+                }
+                .as_bytes(),
+            );
+            // A class-specific descriptor, as for the HID descriptor.
+            v.extend_from_slice(&[6, 0x21, 0, 0, 0, 0]);
+        }
+        v
+    }
+
+    #[test]
+    fn parse_hid() {
+        let configurations =
+            parse_descriptors(&descriptors(&[INTERFACE_CLASS_HID, INTERFACE_CLASS_HID])).unwrap();
+        assert_eq!(
+            configurations,
+            [Configuration {
+                value: 1,
+                interfaces: vec![0, 1],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_not_hid() {
+        let err = parse_descriptors(&descriptors(&[INTERFACE_CLASS_HID, 0x08])).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::NotHid {
+                interface: 1,
+                class: 0x08
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let mut data = descriptors(&[INTERFACE_CLASS_HID]);
+        data.pop();
+        parse_descriptors(&data).unwrap_err();
+    }
+}
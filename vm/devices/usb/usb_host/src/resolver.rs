@@ -0,0 +1,34 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for host USB devices.
+
+use crate::Error;
+use crate::UsbHostDevice;
+use usb_core::ResolveUsbDeviceHandleParams;
+use usb_core::ResolvedUsbDevice;
+use usb_resources::UsbHostDeviceHandle;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::UsbDeviceHandleKind;
+
+/// A resolver for [`UsbHostDeviceHandle`].
+pub struct UsbHostResolver;
+
+declare_static_resolver! {
+    UsbHostResolver,
+    (UsbDeviceHandleKind, UsbHostDeviceHandle),
+}
+
+impl ResolveResource<UsbDeviceHandleKind, UsbHostDeviceHandle> for UsbHostResolver {
+    type Output = ResolvedUsbDevice;
+    type Error = Error;
+
+    fn resolve(
+        &self,
+        resource: UsbHostDeviceHandle,
+        _input: ResolveUsbDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(UsbHostDevice::new(resource.file)?.into())
+    }
+}
@@ -0,0 +1,36 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resources for emulated USB devices.
+
+#![forbid(unsafe_code)]
+
+use mesh::MeshPayload;
+use vm_resource::Resource;
+use vm_resource::ResourceId;
+use vm_resource::kind::ScsiDeviceHandleKind;
+use vm_resource::kind::UsbDeviceHandleKind;
+
+/// Resource handle for a USB mass storage device using the bulk-only
+/// transport.
+#[derive(MeshPayload)]
+pub struct UsbStorageHandle {
+    /// The SCSI device that backs the storage device's single LUN.
+    pub device: Resource<ScsiDeviceHandleKind>,
+}
+
+impl ResourceId<UsbDeviceHandleKind> for UsbStorageHandle {
+    const ID: &'static str = "usb_storage";
+}
+
+/// Resource handle for a host USB device passed through to the guest.
+#[derive(MeshPayload)]
+pub struct UsbHostDeviceHandle {
+    /// The host device's usbfs node (`/dev/bus/usb/<bus>/<device>`), opened
+    /// for reading and writing.
+    pub file: std::fs::File,
+}
+
+impl ResourceId<UsbDeviceHandleKind> for UsbHostDeviceHandle {
+    const ID: &'static str = "usb_host";
+}
@@ -0,0 +1,616 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A USB mass storage device using the bulk-only transport (BOT), backed by
+//! an emulated SCSI device.
+//!
+//! The device presents a single LUN and reports itself as removable media, so
+//! that guests and firmware treat it like a USB flash drive.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use inspect::InspectMut;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_core::AsyncScsiDisk;
+use scsi_core::Request;
+use scsi_defs::ScsiOp;
+use scsi_defs::ScsiStatus;
+use std::sync::Arc;
+use usb_core::UsbDevice;
+use usb_core::UsbError;
+use usb_core::UsbSpeed;
+use usb_core::spec;
+use usb_core::spec::DescriptorType;
+use usb_core::spec::Recipient;
+use usb_core::spec::RequestKind;
+use usb_core::spec::SetupPacket;
+use usb_core::spec::StandardRequest;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+type U32LE = zerocopy::U32<zerocopy::LE>;
+
+const VENDOR_ID: u16 = 0x1414;
+const PRODUCT_ID: u16 = 0x0c10;
+
+const BULK_IN_ENDPOINT: u8 = 0x81;
+const BULK_OUT_ENDPOINT: u8 = 0x02;
+const MAX_PACKET_SIZE0: u8 = 64;
+const BULK_MAX_PACKET_SIZE: u16 = 512;
+
+const STRING_MANUFACTURER: u8 = 1;
+const STRING_PRODUCT: u8 = 2;
+const STRING_SERIAL_NUMBER: u8 = 3;
+
+const MANUFACTURER: &str = "OpenVMM";
+const PRODUCT: &str = "USB Storage";
+/// The BOT specification requires a serial number of at least 12 hex digits.
+const SERIAL_NUMBER: &str = "000000000001";
+
+const INTERFACE_CLASS_MASS_STORAGE: u8 = 0x08;
+const INTERFACE_SUBCLASS_SCSI: u8 = 0x06;
+const INTERFACE_PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+const REQUEST_BULK_ONLY_RESET: u8 = 0xff;
+
+/// The largest data transfer supported for a single command.
+const MAX_DATA_TRANSFER: usize = 1024 * 1024;
+
+const CBW_SIGNATURE: u32 = 0x43425355;
+const CSW_SIGNATURE: u32 = 0x53425355;
+const CBW_FLAGS_DATA_IN: u8 = 0x80;
+
+/// Command block wrapper, sent by the host to start a command.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+struct CommandBlockWrapper {
+    signature: U32LE,
+    tag: U32LE,
+    data_transfer_length: U32LE,
+    flags: u8,
+    lun: u8,
+    cb_length: u8,
+    cb: [u8; 16],
+}
+
+const _: () = assert!(size_of::<CommandBlockWrapper>() == 31);
+
+/// Command status wrapper, sent by the device to complete a command.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+struct CommandStatusWrapper {
+    signature: U32LE,
+    tag: U32LE,
+    data_residue: U32LE,
+    status: u8,
+}
+
+const _: () = assert!(size_of::<CommandStatusWrapper>() == 13);
+
+const CSW_STATUS_PASSED: u8 = 0;
+const CSW_STATUS_FAILED: u8 = 1;
+
+/// The bulk-only transport state machine.
+enum BotState {
+    /// Waiting for a command block wrapper.
+    Command,
+    /// Receiving the data for an OUT command.
+    DataOut {
+        cbw: CommandBlockWrapper,
+        data: Vec<u8>,
+    },
+    /// Sending the data for an IN command.
+    DataIn {
+        expected: usize,
+        data: Vec<u8>,
+        offset: usize,
+        csw: CommandStatusWrapper,
+    },
+    /// Waiting to send the command status wrapper.
+    Status(CommandStatusWrapper),
+}
+
+impl BotState {
+    fn name(&self) -> &'static str {
+        match self {
+            BotState::Command => "command",
+            BotState::DataOut { .. } => "data_out",
+            BotState::DataIn { .. } => "data_in",
+            BotState::Status(_) => "status",
+        }
+    }
+}
+
+/// A USB mass storage device.
+#[derive(InspectMut)]
+pub struct UsbStorageDevice {
+    scsi: Arc<dyn AsyncScsiDisk>,
+    #[inspect(skip)]
+    bounce: GuestMemory,
+    configuration: u8,
+    #[inspect(with = "BotState::name")]
+    state: BotState,
+}
+
+impl UsbStorageDevice {
+    /// Creates a new USB storage device backed by `scsi`.
+    pub fn new(scsi: Arc<dyn AsyncScsiDisk>) -> Self {
+        Self {
+            scsi,
+            bounce: GuestMemory::allocate(MAX_DATA_TRANSFER),
+            configuration: 0,
+            state: BotState::Command,
+        }
+    }
+
+    fn device_descriptor() -> spec::DeviceDescriptor {
+        spec::DeviceDescriptor {
+            length: size_of::<spec::DeviceDescriptor>() as u8,
+            descriptor_type: DescriptorType::DEVICE.0,
+            usb_version: 0x0200.into(),
+            device_class: 0,
+            device_subclass: 0,
+            device_protocol: 0,
+            max_packet_size0: MAX_PACKET_SIZE0,
+            vendor_id: VENDOR_ID.into(),
+            product_id: PRODUCT_ID.into(),
+            device_version: 0x0100.into(),
+            manufacturer_index: STRING_MANUFACTURER,
+            product_index: STRING_PRODUCT,
+            serial_number_index: STRING_SERIAL_NUMBER,
+            num_configurations: 1,
+        }
+    }
+
+    fn configuration_descriptor() -> Vec<u8> {
+        let total_length = size_of::<spec::ConfigurationDescriptor>()
+            + size_of::<spec::InterfaceDescriptor>()
+            + 2 * size_of::<spec::EndpointDescriptor>();
+
+        let mut v = Vec::with_capacity(total_length);
+        v.extend_from_slice(
+            spec::ConfigurationDescriptor {
+                length: size_of::<spec::ConfigurationDescriptor>() as u8,
+                descriptor_type: DescriptorType::CONFIGURATION.0,
+                total_length: (total_length as u16).into(),
+                num_interfaces: 1,
+                configuration_value: 1,
+                configuration_index: 0,
+                attributes: spec::CONFIGURATION_ATTRIBUTES_RESERVED
+                    | spec::CONFIGURATION_ATTRIBUTES_SELF_POWERED,
+                max_power: 50,
+            }
+            .as_bytes(),
+        );
+        v.extend_from_slice(
+            spec::InterfaceDescriptor {
+                length: size_of::<spec::InterfaceDescriptor>() as u8,
+                descriptor_type: DescriptorType::INTERFACE.0,
+                interface_number: 0,
+                alternate_setting: 0,
+                num_endpoints: 2,
+                interface_class: INTERFACE_CLASS_MASS_STORAGE,
+                interface_subclass: INTERFACE_SUBCLASS_SCSI,
+                interface_protocol: INTERFACE_PROTOCOL_BULK_ONLY,
+                interface_index: 0,
+            }
+            .as_bytes(),
+        );
+        for endpoint_address in [BULK_IN_ENDPOINT, BULK_OUT_ENDPOINT] {
+            v.extend_from_slice(
+                spec::EndpointDescriptor {
+                    length: size_of::<spec::EndpointDescriptor>() as u8,
+                    descriptor_type: DescriptorType::ENDPOINT.0,
+                    endpoint_address,
+                    attributes: spec::ENDPOINT_ATTRIBUTES_BULK,
+                    max_packet_size: BULK_MAX_PACKET_SIZE.into(),
+                    interval: 0,
+                }
+                .as_bytes(),
+            );
+        }
+        assert_eq!(v.len(), total_length);
+        v
+    }
+
+    fn get_descriptor(&self, setup: &SetupPacket) -> Result<Vec<u8>, UsbError> {
+        let ty = DescriptorType((setup.value >> 8) as u8);
+        let index = setup.value as u8;
+        let data = match ty {
+            DescriptorType::DEVICE => Self::device_descriptor().as_bytes().to_vec(),
+            DescriptorType::CONFIGURATION if index == 0 => Self::configuration_descriptor(),
+            DescriptorType::DEVICE_QUALIFIER => spec::DeviceQualifierDescriptor {
+                length: size_of::<spec::DeviceQualifierDescriptor>() as u8,
+                descriptor_type: DescriptorType::DEVICE_QUALIFIER.0,
+                usb_version: 0x0200.into(),
+                device_class: 0,
+                device_subclass: 0,
+                device_protocol: 0,
+                max_packet_size0: MAX_PACKET_SIZE0,
+                num_configurations: 1,
+                reserved: 0,
+            }
+            .as_bytes()
+            .to_vec(),
+            DescriptorType::STRING => match index {
+                0 => spec::STRING_DESCRIPTOR_LANGUAGES.to_vec(),
+                STRING_MANUFACTURER => spec::string_descriptor(MANUFACTURER),
+                STRING_PRODUCT => spec::string_descriptor(PRODUCT),
+                STRING_SERIAL_NUMBER => spec::string_descriptor(SERIAL_NUMBER),
+                _ => return Err(UsbError::Stall),
+            },
+            _ => {
+                tracing::debug!(?ty, index, "unsupported descriptor");
+                return Err(UsbError::Stall);
+            }
+        };
+        Ok(data)
+    }
+
+    fn standard_request(&mut self, setup: &SetupPacket) -> Result<Vec<u8>, UsbError> {
+        let data = match StandardRequest(setup.request) {
+            StandardRequest::GET_DESCRIPTOR => self.get_descriptor(setup)?,
+            StandardRequest::GET_STATUS => {
+                let status: u16 = match setup.recipient() {
+                    // Self powered.
+                    Recipient::DEVICE => 1,
+                    Recipient::INTERFACE => 0,
+                    Recipient::ENDPOINT => 0,
+                    _ => return Err(UsbError::Stall),
+                };
+                status.to_le_bytes().to_vec()
+            }
+            StandardRequest::GET_CONFIGURATION => vec![self.configuration],
+            StandardRequest::SET_CONFIGURATION => {
+                let configuration = setup.value as u8;
+                if configuration > 1 {
+                    return Err(UsbError::Stall);
+                }
+                self.configuration = configuration;
+                self.state = BotState::Command;
+                Vec::new()
+            }
+            StandardRequest::GET_INTERFACE => vec![0],
+            StandardRequest::SET_INTERFACE => {
+                if setup.value != 0 {
+                    return Err(UsbError::Stall);
+                }
+                Vec::new()
+            }
+            // Endpoint halt is tracked by the host controller.
+            StandardRequest::CLEAR_FEATURE | StandardRequest::SET_FEATURE => Vec::new(),
+            request => {
+                tracing::debug!(?request, "unsupported standard request");
+                return Err(UsbError::Stall);
+            }
+        };
+        Ok(data)
+    }
+
+    fn class_request(&mut self, setup: &SetupPacket) -> Result<Vec<u8>, UsbError> {
+        match setup.request {
+            REQUEST_GET_MAX_LUN if setup.is_in() => Ok(vec![0]),
+            REQUEST_BULK_ONLY_RESET if !setup.is_in() => {
+                self.state = BotState::Command;
+                Ok(Vec::new())
+            }
+            request => {
+                tracing::debug!(request, "unsupported class request");
+                Err(UsbError::Stall)
+            }
+        }
+    }
+
+    /// Handles a new command block wrapper.
+    async fn command(&mut self, data: &[u8]) -> Result<(), UsbError> {
+        let Ok(cbw) = CommandBlockWrapper::read_from_bytes(data) else {
+            tracelimit::warn_ratelimited!(len = data.len(), "invalid cbw length");
+            return Err(UsbError::Stall);
+        };
+        if cbw.signature.get() != CBW_SIGNATURE {
+            tracelimit::warn_ratelimited!(signature = cbw.signature.get(), "invalid cbw signature");
+            return Err(UsbError::Stall);
+        }
+
+        let expected = cbw.data_transfer_length.get() as usize;
+        if expected > MAX_DATA_TRANSFER || cbw.lun != 0 {
+            tracelimit::warn_ratelimited!(expected, lun = cbw.lun, "unsupported cbw");
+            return Err(UsbError::Stall);
+        }
+
+        self.state = if expected == 0 {
+            let (_, csw) = self.execute(&cbw, &[]).await;
+            BotState::Status(csw)
+        } else if cbw.flags & CBW_FLAGS_DATA_IN != 0 {
+            let (data, csw) = self.execute(&cbw, &[]).await;
+            BotState::DataIn {
+                expected,
+                data,
+                offset: 0,
+                csw,
+            }
+        } else {
+            BotState::DataOut {
+                cbw,
+                data: Vec::with_capacity(expected),
+            }
+        };
+        Ok(())
+    }
+
+    /// Executes the SCSI command in `cbw`, returning any data to send to the
+    /// host and the command status.
+    async fn execute(
+        &mut self,
+        cbw: &CommandBlockWrapper,
+        data_out: &[u8],
+    ) -> (Vec<u8>, CommandStatusWrapper) {
+        let expected = cbw.data_transfer_length.get() as usize;
+        let is_in = cbw.flags & CBW_FLAGS_DATA_IN != 0;
+        if !is_in {
+            self.bounce.write_at(0, data_out).unwrap();
+        }
+
+        let mut cdb = [0; 16];
+        let len = (cbw.cb_length as usize).min(cdb.len());
+        cdb[..len].copy_from_slice(&cbw.cb[..len]);
+        let request = Request { cdb, srb_flags: 0 };
+
+        let buffers = OwnedRequestBuffers::linear(0, expected, is_in);
+        let result = self
+            .scsi
+            .execute_scsi(&buffers.buffer(&self.bounce), &request)
+            .await;
+
+        let tx = result.tx.min(expected);
+        let status = if result.scsi_status == ScsiStatus::GOOD {
+            CSW_STATUS_PASSED
+        } else {
+            CSW_STATUS_FAILED
+        };
+
+        let mut data = Vec::new();
+        if is_in {
+            data = vec![0; tx];
+            self.bounce.read_at(0, &mut data).unwrap();
+            // Report removable media so that the guest treats the device like
+            // a flash drive.
+            if request.scsiop() == ScsiOp::INQUIRY && cdb[1] & 1 == 0 && tx > 1 {
+                data[1] |= 0x80;
+            }
+        }
+
+        (data, csw(cbw, expected - tx, status))
+    }
+}
+
+fn csw(cbw: &CommandBlockWrapper, residue: usize, status: u8) -> CommandStatusWrapper {
+    CommandStatusWrapper {
+        signature: CSW_SIGNATURE.into(),
+        tag: cbw.tag,
+        data_residue: (residue as u32).into(),
+        status,
+    }
+}
+
+#[async_trait]
+impl UsbDevice for UsbStorageDevice {
+    fn speed(&self) -> UsbSpeed {
+        UsbSpeed::High
+    }
+
+    fn reset(&mut self) {
+        self.configuration = 0;
+        self.state = BotState::Command;
+    }
+
+    async fn control(&mut self, setup: SetupPacket, _data: &[u8]) -> Result<Vec<u8>, UsbError> {
+        let mut data = match setup.kind() {
+            RequestKind::STANDARD => self.standard_request(&setup)?,
+            RequestKind::CLASS if setup.recipient() == Recipient::INTERFACE => {
+                self.class_request(&setup)?
+            }
+            _ => return Err(UsbError::Stall),
+        };
+        data.truncate(setup.length as usize);
+        Ok(data)
+    }
+
+    async fn transfer_in(&mut self, endpoint: u8, len: usize) -> Result<Vec<u8>, UsbError> {
+        if endpoint != BULK_IN_ENDPOINT {
+            return Err(UsbError::Stall);
+        }
+        match &mut self.state {
+            BotState::Command | BotState::DataOut { .. } => Err(UsbError::Stall),
+            BotState::DataIn {
+                expected,
+                data,
+                offset,
+                csw,
+            } => {
+                let n = (data.len() - *offset).min(len);
+                if n == 0 {
+                    // The host expects more data than the command produced.
+                    // Stall to end the data phase; the host will clear the
+                    // halt and read the status.
+                    self.state = BotState::Status(*csw);
+                    return Err(UsbError::Stall);
+                }
+                let chunk = data[*offset..*offset + n].to_vec();
+                *offset += n;
+                if *offset == data.len() && (n < len || *offset == *expected) {
+                    self.state = BotState::Status(*csw);
+                }
+                Ok(chunk)
+            }
+            BotState::Status(csw) => {
+                let mut data = csw.as_bytes().to_vec();
+                data.truncate(len);
+                self.state = BotState::Command;
+                Ok(data)
+            }
+        }
+    }
+
+    async fn transfer_out(&mut self, endpoint: u8, data: &[u8]) -> Result<(), UsbError> {
+        if endpoint != BULK_OUT_ENDPOINT {
+            return Err(UsbError::Stall);
+        }
+        match &mut self.state {
+            BotState::Command => self.command(data).await,
+            BotState::DataOut { cbw, data: buf } => {
+                let expected = cbw.data_transfer_length.get() as usize;
+                let n = data.len().min(expected - buf.len());
+                buf.extend_from_slice(&data[..n]);
+                if buf.len() == expected {
+                    let cbw = *cbw;
+                    let buf = std::mem::take(buf);
+                    let (_, csw) = self.execute(&cbw, &buf).await;
+                    self.state = BotState::Status(csw);
+                }
+                Ok(())
+            }
+            BotState::DataIn { .. } | BotState::Status(_) => Err(UsbError::Stall),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::async_test;
+    use scsidisk::SimpleScsiDisk;
+
+    fn new_device() -> UsbStorageDevice {
+        let disk = disklayer_ram::ram_disk(0x100000, false).unwrap();
+        UsbStorageDevice::new(Arc::new(SimpleScsiDisk::new(disk, Default::default())))
+    }
+
+    fn cbw(tag: u32, len: u32, is_in: bool, cb: &[u8]) -> Vec<u8> {
+        let mut cbw = CommandBlockWrapper {
+            signature: CBW_SIGNATURE.into(),
+            tag: tag.into(),
+            data_transfer_length: len.into(),
+            flags: if is_in { CBW_FLAGS_DATA_IN } else { 0 },
+            lun: 0,
+            cb_length: cb.len() as u8,
+            cb: [0; 16],
+        };
+        cbw.cb[..cb.len()].copy_from_slice(cb);
+        cbw.as_bytes().to_vec()
+    }
+
+    async fn status(device: &mut UsbStorageDevice, tag: u32) -> CommandStatusWrapper {
+        let data = device.transfer_in(BULK_IN_ENDPOINT, 13).await.unwrap();
+        let csw = CommandStatusWrapper::read_from_bytes(&data).unwrap();
+        assert_eq!(csw.signature.get(), CSW_SIGNATURE);
+        assert_eq!(csw.tag.get(), tag);
+        csw
+    }
+
+    #[async_test]
+    async fn descriptors() {
+        let mut device = new_device();
+        let data = device
+            .control(
+                SetupPacket {
+                    request_type: spec::REQUEST_TYPE_DIRECTION_IN,
+                    request: StandardRequest::GET_DESCRIPTOR.0,
+                    value: (DescriptorType::DEVICE.0 as u16) << 8,
+                    index: 0,
+                    length: 64,
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+        let desc = spec::DeviceDescriptor::read_from_bytes(&data).unwrap();
+        assert_eq!(desc.vendor_id.get(), VENDOR_ID);
+
+        // The configuration descriptor is truncated to the requested length.
+        let data = device
+            .control(
+                SetupPacket {
+                    request_type: spec::REQUEST_TYPE_DIRECTION_IN,
+                    request: StandardRequest::GET_DESCRIPTOR.0,
+                    value: (DescriptorType::CONFIGURATION.0 as u16) << 8,
+                    index: 0,
+                    length: 9,
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 9);
+        assert_eq!(u16::from_le_bytes([data[2], data[3]]), 32);
+    }
+
+    #[async_test]
+    async fn write_read() {
+        let mut device = new_device();
+
+        // WRITE(10) one sector at LBA 1.
+        let write = [0x2a, 0, 0, 0, 0, 1, 0, 0, 1, 0];
+        device
+            .transfer_out(BULK_OUT_ENDPOINT, &cbw(1, 512, false, &write))
+            .await
+            .unwrap();
+        device
+            .transfer_out(BULK_OUT_ENDPOINT, &[0xa5; 256])
+            .await
+            .unwrap();
+        device
+            .transfer_out(BULK_OUT_ENDPOINT, &[0x5a; 256])
+            .await
+            .unwrap();
+        let csw = status(&mut device, 1).await;
+        assert_eq!(csw.status, CSW_STATUS_PASSED);
+        assert_eq!(csw.data_residue.get(), 0);
+
+        // READ(10) it back.
+        let read = [0x28, 0, 0, 0, 0, 1, 0, 0, 1, 0];
+        device
+            .transfer_out(BULK_OUT_ENDPOINT, &cbw(2, 512, true, &read))
+            .await
+            .unwrap();
+        let data = device.transfer_in(BULK_IN_ENDPOINT, 512).await.unwrap();
+        assert_eq!(&data[..256], &[0xa5; 256]);
+        assert_eq!(&data[256..], &[0x5a; 256]);
+        let csw = status(&mut device, 2).await;
+        assert_eq!(csw.status, CSW_STATUS_PASSED);
+    }
+
+    #[async_test]
+    async fn short_inquiry() {
+        let mut device = new_device();
+        let inquiry = [0x12, 0, 0, 0, 36, 0];
+        device
+            .transfer_out(BULK_OUT_ENDPOINT, &cbw(3, 64, true, &inquiry))
+            .await
+            .unwrap();
+        let data = device.transfer_in(BULK_IN_ENDPOINT, 64).await.unwrap();
+        assert_eq!(data.len(), 36);
+        // Removable media.
+        assert_eq!(data[1] & 0x80, 0x80);
+        let csw = status(&mut device, 3).await;
+        assert_eq!(csw.status, CSW_STATUS_PASSED);
+        assert_eq!(csw.data_residue.get(), 28);
+    }
+
+    #[async_test]
+    async fn invalid_cbw() {
+        let mut device = new_device();
+        device
+            .transfer_out(BULK_OUT_ENDPOINT, &[0; 31])
+            .await
+            .unwrap_err();
+        device.transfer_in(BULK_IN_ENDPOINT, 13).await.unwrap_err();
+    }
+}
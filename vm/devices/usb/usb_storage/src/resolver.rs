@@ -0,0 +1,58 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for USB mass storage devices.
+
+use crate::UsbStorageDevice;
+use async_trait::async_trait;
+use scsi_core::ResolveScsiDeviceHandleParams;
+use thiserror::Error;
+use usb_core::ResolveUsbDeviceHandleParams;
+use usb_core::ResolvedUsbDevice;
+use usb_resources::UsbStorageHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::UsbDeviceHandleKind;
+
+/// A resolver for [`UsbStorageHandle`].
+pub struct UsbStorageResolver;
+
+declare_static_async_resolver! {
+    UsbStorageResolver,
+    (UsbDeviceHandleKind, UsbStorageHandle),
+}
+
+/// An error returned by [`UsbStorageResolver`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The backing SCSI device could not be resolved.
+    #[error("failed to resolve scsi device")]
+    Scsi(#[source] ResolveError),
+}
+
+#[async_trait]
+impl AsyncResolveResource<UsbDeviceHandleKind, UsbStorageHandle> for UsbStorageResolver {
+    type Output = ResolvedUsbDevice;
+    type Error = Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: UsbStorageHandle,
+        input: ResolveUsbDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let scsi = resolver
+            .resolve(
+                resource.device,
+                ResolveScsiDeviceHandleParams {
+                    driver_source: input.driver_source,
+                },
+            )
+            .await
+            .map_err(Error::Scsi)?;
+
+        Ok(UsbStorageDevice::new(scsi.0).into())
+    }
+}
@@ -0,0 +1,1881 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An emulated xHCI USB host controller.
+//!
+//! The controller exposes a single interrupter and a root hub with USB 2.0
+//! ports, one for each attached [`UsbDevice`]. Devices are always connected;
+//! hot plug is not supported.
+
+#![forbid(unsafe_code)]
+
+pub mod spec;
+
+use chipset_device::ChipsetDevice;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::poll_device::PollDevice;
+use device_emulators::read_as_u32_chunks;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pci_core::PciInterruptPin;
+use pci_core::cfg_space_emu::BarMemoryKind;
+use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
+use pci_core::cfg_space_emu::DeviceBars;
+use pci_core::cfg_space_emu::IntxInterrupt;
+use pci_core::spec::hwid::ClassCode;
+use pci_core::spec::hwid::HardwareIds;
+use pci_core::spec::hwid::ProgrammingInterface;
+use pci_core::spec::hwid::Subclass;
+use spec::CompletionCode;
+use spec::Context;
+use spec::EndpointState;
+use spec::EndpointType;
+use spec::SlotState;
+use spec::Trb;
+use spec::TrbType;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context as TaskContext;
+use std::task::Poll;
+use std::task::Waker;
+use thiserror::Error;
+use usb_core::UsbDevice;
+use usb_core::UsbError;
+use usb_core::UsbSpeed;
+use usb_core::spec::SetupPacket;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
+use vmcore::save_restore::SaveRestore;
+use vmcore::save_restore::SavedStateNotSupported;
+use zerocopy::FromBytes;
+
+const VENDOR_ID: u16 = 0x1414;
+const DEVICE_ID: u16 = 0x00ac;
+
+const HCI_VERSION: u16 = 0x0110;
+
+const BAR0_LEN: u64 = 0x4000;
+const CAP_LENGTH: u16 = 0x40;
+const OPERATIONAL_BASE: u16 = CAP_LENGTH;
+const EXTENDED_CAPABILITIES_BASE: u16 = 0x800;
+const RUNTIME_BASE: u16 = 0x1000;
+const DOORBELL_BASE: u16 = 0x2000;
+
+/// The maximum number of devices (and therefore root hub ports) supported
+/// by the controller.
+pub const MAX_PORTS: usize = 8;
+const MAX_SLOTS: u8 = 32;
+/// The maximum event ring segment table size, as a power of two.
+const ERST_MAX: u8 = 4;
+
+/// The maximum number of TRBs in a single TD, to bound the work done for a
+/// malicious ring.
+const MAX_TD_TRBS: usize = 1024;
+/// The maximum number of consecutive link TRBs followed when fetching a TRB.
+const MAX_LINK_TRBS: usize = 16;
+/// The maximum number of bytes in a single TD.
+const MAX_TRANSFER_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The number of device context indexes (endpoints), excluding the slot
+/// context.
+const ENDPOINT_COUNT: usize = 31;
+
+/// An emulated xHCI controller.
+#[derive(InspectMut)]
+pub struct XhciController {
+    cfg_space: ConfigSpaceType0Emulator,
+    #[inspect(skip)]
+    interrupt: Arc<IntxInterrupt>,
+    #[inspect(skip)]
+    guest_memory: GuestMemory,
+    #[inspect(skip)]
+    waker: Option<Waker>,
+
+    registers: Registers,
+    command_ring: CommandRing,
+    #[inspect(mut, with = "inspect_ports")]
+    ports: Vec<Port>,
+    #[inspect(iter_by_index)]
+    slots: Vec<Option<Slot>>,
+}
+
+#[derive(Inspect)]
+struct Registers {
+    usbcmd: spec::UsbCmd,
+    usbsts: spec::UsbSts,
+    #[inspect(hex)]
+    dnctrl: u32,
+    #[inspect(hex)]
+    dcbaap: u64,
+    #[inspect(hex)]
+    config: u32,
+    interrupter: Interrupter,
+}
+
+impl Registers {
+    fn new() -> Self {
+        Self {
+            usbcmd: spec::UsbCmd::new(),
+            usbsts: spec::UsbSts::new().with_hc_halted(true),
+            dnctrl: 0,
+            dcbaap: 0,
+            config: 0,
+            interrupter: Interrupter::new(),
+        }
+    }
+}
+
+#[derive(Inspect)]
+struct Interrupter {
+    iman: spec::Iman,
+    #[inspect(hex)]
+    imod: u32,
+    erstsz: u32,
+    #[inspect(hex)]
+    erstba: u64,
+    #[inspect(hex)]
+    erdp: u64,
+    event_handler_busy: bool,
+    event_ring: EventRing,
+}
+
+impl Interrupter {
+    fn new() -> Self {
+        Self {
+            iman: spec::Iman::new(),
+            imod: 0x4000,
+            erstsz: 0,
+            erstba: 0,
+            erdp: 0,
+            event_handler_busy: false,
+            event_ring: EventRing::default(),
+        }
+    }
+}
+
+/// The producer state of an event ring.
+#[derive(Copy, Clone, Default, Inspect)]
+struct EventRing {
+    configured: bool,
+    #[inspect(hex)]
+    enqueue: u64,
+    #[inspect(hex)]
+    segment_end: u64,
+    segment_index: u32,
+    cycle: bool,
+}
+
+#[derive(Debug, Error)]
+enum EventRingError {
+    #[error("event ring is not configured")]
+    NotConfigured,
+    #[error("event ring is full")]
+    Full,
+    #[error("invalid event ring segment")]
+    InvalidSegment,
+    #[error("failed to access event ring")]
+    Memory(#[from] GuestMemoryError),
+}
+
+impl EventRing {
+    /// Loads the segment at `index` of the event ring segment table.
+    fn load_segment(
+        &mut self,
+        guest_memory: &GuestMemory,
+        erstba: u64,
+        index: u32,
+    ) -> Result<(), EventRingError> {
+        let segment: spec::EventRingSegment = guest_memory.read_plain(
+            erstba.wrapping_add(index as u64 * size_of::<spec::EventRingSegment>() as u64),
+        )?;
+        if segment.size == 0 {
+            return Err(EventRingError::InvalidSegment);
+        }
+        self.enqueue = segment.base & !0x3f;
+        self.segment_end = self.enqueue.wrapping_add(segment.size as u64 * 16);
+        self.segment_index = index;
+        Ok(())
+    }
+
+    /// Writes `trb` to the ring, unless the ring is full.
+    fn push(
+        &mut self,
+        guest_memory: &GuestMemory,
+        erstba: u64,
+        erstsz: u32,
+        dequeue: u64,
+        mut trb: Trb,
+    ) -> Result<(), EventRingError> {
+        if !self.configured {
+            return Err(EventRingError::NotConfigured);
+        }
+        let mut next = *self;
+        next.enqueue = next.enqueue.wrapping_add(16);
+        if next.enqueue == next.segment_end {
+            let index = (next.segment_index + 1) % erstsz.max(1);
+            if index == 0 {
+                next.cycle = !next.cycle;
+            }
+            next.load_segment(guest_memory, erstba, index)?;
+        }
+        if next.enqueue == dequeue {
+            return Err(EventRingError::Full);
+        }
+        trb.control = (trb.control & !spec::TRB_CYCLE) | self.cycle as u32;
+        guest_memory.write_plain(self.enqueue, &trb)?;
+        *self = next;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+enum RingError {
+    #[error("failed to access ring")]
+    Memory(#[from] GuestMemoryError),
+    #[error("too many consecutive link TRBs")]
+    LinkLoop,
+    #[error("too many TRBs in TD")]
+    TdTooLong,
+}
+
+/// The consumer state of a command or transfer ring.
+#[derive(Copy, Clone, Default, Inspect)]
+struct Ring {
+    #[inspect(hex)]
+    dequeue: u64,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new(dequeue: u64) -> Self {
+        Self {
+            dequeue: dequeue & !0xf,
+            cycle: dequeue & spec::ENDPOINT_CONTEXT_DCS != 0,
+        }
+    }
+
+    /// Fetches the next TRB, following link TRBs. Returns `None` if the ring
+    /// is empty.
+    fn next(&mut self, guest_memory: &GuestMemory) -> Result<Option<TdTrb>, RingError> {
+        for _ in 0..MAX_LINK_TRBS {
+            let trb: Trb = guest_memory.read_plain(self.dequeue)?;
+            if trb.cycle() != self.cycle {
+                return Ok(None);
+            }
+            if trb.trb_type() == TrbType::LINK {
+                self.dequeue = trb.parameter & !0xf;
+                if trb.flag(spec::TRB_TOGGLE_CYCLE) {
+                    self.cycle = !self.cycle;
+                }
+                continue;
+            }
+            let gpa = self.dequeue;
+            self.dequeue = self.dequeue.wrapping_add(16);
+            return Ok(Some(TdTrb { gpa, trb }));
+        }
+        Err(RingError::LinkLoop)
+    }
+}
+
+#[derive(Default, Inspect)]
+struct CommandRing {
+    ring: Ring,
+    running: bool,
+}
+
+/// A TRB fetched from a ring, with its guest physical address.
+#[derive(Copy, Clone)]
+struct TdTrb {
+    gpa: u64,
+    trb: Trb,
+}
+
+/// A transfer descriptor: a chain of TRBs that make up a single transfer.
+struct Td {
+    trbs: Vec<TdTrb>,
+    /// The ring state after the TD.
+    next: Ring,
+}
+
+impl Td {
+    /// Reads the next complete TD from `ring`, if there is one.
+    fn read(ring: &Ring, guest_memory: &GuestMemory) -> Result<Option<Self>, RingError> {
+        let mut next = *ring;
+        let mut trbs = Vec::new();
+        loop {
+            let Some(trb) = next.next(guest_memory)? else {
+                return Ok(None);
+            };
+            trbs.push(trb);
+            if !trb.trb.flag(spec::TRB_CHAIN) {
+                break;
+            }
+            if trbs.len() >= MAX_TD_TRBS {
+                return Err(RingError::TdTooLong);
+            }
+        }
+        Ok(Some(Self { trbs, next }))
+    }
+
+    /// Returns the data TRBs of the TD.
+    fn data_trbs(&self) -> impl Iterator<Item = &TdTrb> {
+        self.trbs.iter().filter(|t| {
+            matches!(
+                t.trb.trb_type(),
+                TrbType::NORMAL | TrbType::DATA_STAGE | TrbType::ISOCH
+            )
+        })
+    }
+
+    /// Returns the total number of bytes described by the TD.
+    fn len(&self) -> usize {
+        self.data_trbs()
+            .map(|t| t.trb.transfer_length() as usize)
+            .sum()
+    }
+
+    /// The TRB to report errors against: the first data TRB, or the first
+    /// TRB if there are no data TRBs.
+    fn error_trb(&self) -> &TdTrb {
+        self.data_trbs().next().unwrap_or(&self.trbs[0])
+    }
+
+    /// Reads the data for a host-to-device transfer from guest memory.
+    fn gather(&self, guest_memory: &GuestMemory) -> Result<Vec<u8>, GuestMemoryError> {
+        let mut data = Vec::with_capacity(self.len());
+        for t in self.data_trbs() {
+            let len = t.trb.transfer_length() as usize;
+            if t.trb.flag(spec::TRB_IDT) {
+                data.extend_from_slice(&t.trb.parameter.to_le_bytes()[..len.min(8)]);
+            } else {
+                let start = data.len();
+                data.resize(start + len, 0);
+                guest_memory.read_at(t.trb.parameter, &mut data[start..])?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Writes the data from a device-to-host transfer to guest memory.
+    fn scatter(&self, guest_memory: &GuestMemory, mut data: &[u8]) -> Result<(), GuestMemoryError> {
+        for t in self.data_trbs() {
+            if data.is_empty() {
+                break;
+            }
+            let n = (t.trb.transfer_length() as usize).min(data.len());
+            guest_memory.write_at(t.trb.parameter, &data[..n])?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+}
+
+#[derive(Inspect)]
+struct Slot {
+    state: SlotState,
+    /// The root hub port index, once the device has been addressed.
+    port: Option<usize>,
+    #[inspect(hex)]
+    output_context: u64,
+    #[inspect(iter_by_index)]
+    endpoints: [Option<Endpoint>; ENDPOINT_COUNT],
+}
+
+impl Slot {
+    fn new() -> Self {
+        Self {
+            state: SlotState::DISABLED_ENABLED,
+            port: None,
+            output_context: 0,
+            endpoints: Default::default(),
+        }
+    }
+
+    fn endpoint_mut(&mut self, dci: u8) -> Option<&mut Endpoint> {
+        self.endpoints
+            .get_mut((dci as usize).wrapping_sub(1))?
+            .as_mut()
+    }
+
+    fn context_address(&self, dci: u8) -> u64 {
+        self.output_context + spec::CONTEXT_SIZE * dci as u64
+    }
+
+    /// Updates the slot state and device address in the output slot context.
+    fn write_slot_context(
+        &self,
+        guest_memory: &GuestMemory,
+        slot_id: u8,
+        mut context: Context,
+    ) -> Result<(), GuestMemoryError> {
+        let address = if self.state == SlotState::DEFAULT {
+            0
+        } else {
+            slot_id
+        };
+        context[3] = spec::SlotContext3::new()
+            .with_usb_device_address(address)
+            .with_slot_state(self.state.0)
+            .into();
+        guest_memory.write_plain(self.output_context, &context)
+    }
+
+    fn read_slot_context(&self, guest_memory: &GuestMemory) -> Result<Context, GuestMemoryError> {
+        guest_memory.read_plain(self.output_context)
+    }
+
+    /// Updates the endpoint state and dequeue pointer in the output endpoint
+    /// context.
+    fn write_endpoint_context(
+        &self,
+        guest_memory: &GuestMemory,
+        dci: u8,
+        context: Option<Context>,
+    ) -> Result<(), GuestMemoryError> {
+        let gpa = self.context_address(dci);
+        let mut context = match context {
+            Some(context) => context,
+            None => guest_memory.read_plain(gpa)?,
+        };
+        let state = match &self.endpoints[dci as usize - 1] {
+            Some(ep) => {
+                let dequeue = ep.ring.dequeue | ep.ring.cycle as u64;
+                context[2] = dequeue as u32;
+                context[3] = (dequeue >> 32) as u32;
+                ep.state
+            }
+            None => EndpointState::DISABLED,
+        };
+        context[0] = spec::EndpointContext0::from(context[0])
+            .with_endpoint_state(state.0)
+            .into();
+        guest_memory.write_plain(gpa, &context)
+    }
+}
+
+#[derive(Inspect)]
+struct Endpoint {
+    endpoint_type: EndpointType,
+    state: EndpointState,
+    ring: Ring,
+    doorbell: bool,
+    control: Option<ControlTransfer>,
+}
+
+impl Endpoint {
+    fn new(context: &Context) -> Self {
+        Self {
+            endpoint_type: EndpointType(spec::EndpointContext1::from(context[1]).endpoint_type()),
+            state: EndpointState::RUNNING,
+            ring: Ring::new(context[2] as u64 | ((context[3] as u64) << 32)),
+            doorbell: false,
+            control: None,
+        }
+    }
+}
+
+/// The state of an in-progress control transfer on a control endpoint.
+#[derive(Inspect)]
+struct ControlTransfer {
+    setup: SetupPacket,
+    #[inspect(skip)]
+    result: Option<Result<Vec<u8>, UsbError>>,
+}
+
+#[derive(InspectMut)]
+struct Port {
+    portsc: spec::PortSc,
+    speed: Option<UsbSpeed>,
+    #[inspect(mut)]
+    device: Option<DeviceState>,
+    /// Incremented on port and controller reset to discard the results of
+    /// in-flight transfers.
+    generation: u64,
+    reset_pending: bool,
+}
+
+impl Port {
+    fn new(device: Option<Box<dyn UsbDevice>>) -> Self {
+        let mut port = Self {
+            portsc: spec::PortSc::new(),
+            speed: device.as_ref().map(|device| device.speed()),
+            device: device.map(DeviceState::Idle),
+            generation: 0,
+            reset_pending: false,
+        };
+        port.reset();
+        port
+    }
+
+    /// Resets the port registers to their state after a controller reset.
+    fn reset(&mut self) {
+        self.generation += 1;
+        let mut portsc = spec::PortSc::new().with_port_power(true);
+        match self.speed {
+            Some(speed) => {
+                portsc.set_current_connect_status(true);
+                portsc.set_connect_status_change(true);
+                portsc.set_port_link_state(spec::LinkState::POLLING.0);
+                portsc.set_port_speed(match speed {
+                    UsbSpeed::Full => spec::SPEED_FULL,
+                    UsbSpeed::Low => spec::SPEED_LOW,
+                    UsbSpeed::High => spec::SPEED_HIGH,
+                });
+            }
+            None => portsc.set_port_link_state(spec::LinkState::RX_DETECT.0),
+        }
+        self.portsc = portsc;
+    }
+}
+
+fn inspect_ports(ports: &mut [Port]) -> impl '_ + InspectMut {
+    inspect::adhoc_mut(|req| {
+        let mut resp = req.respond();
+        for (i, port) in ports.iter_mut().enumerate() {
+            resp.field_mut(&(i + 1).to_string(), port);
+        }
+    })
+}
+
+type TransferFuture =
+    Pin<Box<dyn Future<Output = (Box<dyn UsbDevice>, Result<Vec<u8>, UsbError>)> + Send>>;
+
+enum DeviceState {
+    Idle(Box<dyn UsbDevice>),
+    Busy(Transfer),
+}
+
+impl InspectMut for DeviceState {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        match self {
+            DeviceState::Idle(device) => device.inspect_mut(req),
+            DeviceState::Busy(_) => req.value("busy"),
+        }
+    }
+}
+
+/// A transfer in flight on a device.
+struct Transfer {
+    io: TransferFuture,
+    slot_id: u8,
+    dci: u8,
+    generation: u64,
+    kind: TransferKind,
+}
+
+enum TransferKind {
+    /// The setup stage of a control transfer. The result is reported by the
+    /// subsequent data or status stage.
+    ControlSetup,
+    /// A device-to-host TD.
+    In(Td),
+    /// A host-to-device TD of the given length.
+    Out(Td, usize),
+}
+
+enum TransferRequest {
+    Control(SetupPacket, Vec<u8>),
+    In(u8, usize),
+    Out(u8, Vec<u8>),
+}
+
+#[derive(Debug, Error)]
+enum CommandError {
+    #[error("command failed: {0:?}")]
+    Completion(CompletionCode),
+    #[error("failed to access context")]
+    Memory(#[from] GuestMemoryError),
+}
+
+impl From<CompletionCode> for CommandError {
+    fn from(value: CompletionCode) -> Self {
+        Self::Completion(value)
+    }
+}
+
+/// Returns the USB endpoint address for device context index `dci`.
+fn endpoint_address(dci: u8) -> u8 {
+    let number = dci / 2;
+    if dci & 1 != 0 && number != 0 {
+        number | usb_core::spec::ENDPOINT_DIRECTION_IN
+    } else {
+        number
+    }
+}
+
+fn slot_mut(slots: &mut [Option<Slot>], slot_id: u8) -> Result<&mut Slot, CompletionCode> {
+    slots
+        .get_mut((slot_id as usize).wrapping_sub(1))
+        .and_then(|slot| slot.as_mut())
+        .ok_or(CompletionCode::SLOT_NOT_ENABLED_ERROR)
+}
+
+/// Error returned by [`XhciController::new`].
+#[derive(Debug, Error)]
+pub enum NewControllerError {
+    /// Too many devices were attached.
+    #[error("too many usb devices: {0}, max {MAX_PORTS}")]
+    TooManyDevices(usize),
+}
+
+impl XhciController {
+    /// Creates a new xHCI controller with `devices` attached to its root hub
+    /// ports.
+    pub fn new(
+        guest_memory: GuestMemory,
+        interrupt: LineInterrupt,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        devices: Vec<Box<dyn UsbDevice>>,
+    ) -> Result<Self, NewControllerError> {
+        if devices.len() > MAX_PORTS {
+            return Err(NewControllerError::TooManyDevices(devices.len()));
+        }
+
+        let bars = DeviceBars::new().bar0(
+            BAR0_LEN,
+            BarMemoryKind::Intercept(register_mmio.new_io_region("bar0", BAR0_LEN)),
+        );
+
+        let mut cfg_space = ConfigSpaceType0Emulator::new(
+            HardwareIds {
+                vendor_id: VENDOR_ID,
+                device_id: DEVICE_ID,
+                revision_id: 0,
+                prog_if: ProgrammingInterface::SERIAL_BUS_CONTROLLER_USB_XHCI,
+                sub_class: Subclass::SERIAL_BUS_CONTROLLER_USB,
+                base_class: ClassCode::SERIAL_BUS_CONTROLLER,
+                type0_sub_vendor_id: 0,
+                type0_sub_system_id: 0,
+            },
+            Vec::new(),
+            bars,
+        );
+
+        let interrupt = cfg_space.set_interrupt_pin(PciInterruptPin::IntA, interrupt);
+
+        // Always expose at least one port so that the root hub is not empty.
+        let port_count = devices.len().max(1);
+        let mut devices = devices.into_iter();
+        let ports = (0..port_count).map(|_| Port::new(devices.next())).collect();
+
+        Ok(Self {
+            cfg_space,
+            interrupt,
+            guest_memory,
+            waker: None,
+            registers: Registers::new(),
+            command_ring: CommandRing::default(),
+            ports,
+            slots: (0..MAX_SLOTS).map(|_| None).collect(),
+        })
+    }
+
+    fn running(&self) -> bool {
+        !self.registers.usbsts.hc_halted()
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Sets the host controller error bit and halts the controller, after
+    /// an unrecoverable error such as a guest memory access failure.
+    fn fatal_error(&mut self, err: &(dyn std::error::Error + 'static)) {
+        tracelimit::error_ratelimited!(error = err, "xhci controller error");
+        self.registers.usbsts.set_host_controller_error(true);
+        self.registers.usbsts.set_hc_halted(true);
+        self.command_ring.running = false;
+    }
+
+    fn update_interrupt(&self) {
+        let iman = self.registers.interrupter.iman;
+        self.interrupt.set_level(
+            iman.interrupt_pending()
+                && iman.interrupt_enable()
+                && self.registers.usbcmd.interrupter_enable(),
+        );
+    }
+
+    fn post_event(&mut self, trb: Trb) {
+        let interrupter = &mut self.registers.interrupter;
+        match interrupter.event_ring.push(
+            &self.guest_memory,
+            interrupter.erstba,
+            interrupter.erstsz,
+            interrupter.erdp & !0xf,
+            trb,
+        ) {
+            Ok(()) => {
+                interrupter.iman.set_interrupt_pending(true);
+                interrupter.event_handler_busy = true;
+                self.registers.usbsts.set_event_interrupt(true);
+                self.update_interrupt();
+            }
+            Err(EventRingError::Memory(err)) => self.fatal_error(&err),
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    trb_type = ?trb.trb_type(),
+                    "dropping event"
+                );
+            }
+        }
+    }
+
+    fn transfer_event(
+        &mut self,
+        pointer: u64,
+        code: CompletionCode,
+        length: u32,
+        slot_id: u8,
+        dci: u8,
+        event_data: bool,
+    ) {
+        self.post_event(Trb::new(
+            TrbType::TRANSFER_EVENT,
+            pointer,
+            ((code.0 as u32) << 24) | (length & 0xffffff),
+            ((slot_id as u32) << 24)
+                | ((dci as u32) << 16)
+                | if event_data { spec::TRB_EVENT_DATA } else { 0 },
+        ));
+    }
+
+    fn port_changed(&mut self, index: usize) {
+        if self.ports[index].portsc.into_bits() & spec::PORTSC_CHANGE_MASK == 0 {
+            return;
+        }
+        self.registers.usbsts.set_port_change_detect(true);
+        if self.running() {
+            self.post_event(Trb::new(
+                TrbType::PORT_STATUS_CHANGE_EVENT,
+                ((index + 1) as u64) << 24,
+                (CompletionCode::SUCCESS.0 as u32) << 24,
+                0,
+            ));
+        }
+    }
+
+    /// Resets the controller, as for USBCMD.HCRST.
+    fn reset_controller(&mut self) {
+        self.registers = Registers::new();
+        self.command_ring = CommandRing::default();
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        for port in &mut self.ports {
+            port.reset();
+            if matches!(port.device, Some(DeviceState::Busy(_))) {
+                port.reset_pending = true;
+            }
+        }
+        self.update_interrupt();
+    }
+
+    fn reset_port(&mut self, index: usize) {
+        let port = &mut self.ports[index];
+        port.portsc.set_port_reset(false);
+        let Some(device) = &mut port.device else {
+            return;
+        };
+        port.generation += 1;
+        match device {
+            DeviceState::Idle(device) => device.reset(),
+            DeviceState::Busy(_) => port.reset_pending = true,
+        }
+        port.portsc.set_port_enabled(true);
+        port.portsc.set_port_link_state(spec::LinkState::U0.0);
+        port.portsc.set_port_reset_change(true);
+        self.port_changed(index);
+    }
+
+    fn set_usbcmd(&mut self, value: u32) {
+        let usbcmd = spec::UsbCmd::from(value);
+        if usbcmd.host_controller_reset() {
+            self.reset_controller();
+            return;
+        }
+        let old = self.registers.usbcmd;
+        self.registers.usbcmd = usbcmd
+            .with_light_host_controller_reset(false)
+            .with_controller_save_state(false)
+            .with_controller_restore_state(false);
+
+        if usbcmd.controller_restore_state() {
+            // Saved state is not supported, so restoring always fails. The
+            // driver will reinitialize the controller.
+            self.registers.usbsts.set_save_restore_error(true);
+        }
+
+        if usbcmd.run_stop() && !old.run_stop() {
+            if !self.registers.usbsts.host_controller_error() {
+                self.registers.usbsts.set_hc_halted(false);
+                for index in 0..self.ports.len() {
+                    self.port_changed(index);
+                }
+                self.wake();
+            }
+        } else if !usbcmd.run_stop() && old.run_stop() {
+            self.registers.usbsts.set_hc_halted(true);
+            self.command_ring.running = false;
+        }
+        self.update_interrupt();
+    }
+
+    fn read_u32(&self, offset: u16) -> u32 {
+        match offset {
+            0..CAP_LENGTH => self.read_capability(spec::CapabilityRegister(offset)),
+            OPERATIONAL_BASE..EXTENDED_CAPABILITIES_BASE => {
+                self.read_operational(offset - OPERATIONAL_BASE)
+            }
+            EXTENDED_CAPABILITIES_BASE..RUNTIME_BASE => {
+                self.read_extended_capability(offset - EXTENDED_CAPABILITIES_BASE)
+            }
+            RUNTIME_BASE..DOORBELL_BASE => self.read_runtime(offset - RUNTIME_BASE),
+            _ => 0,
+        }
+    }
+
+    fn write_u32(&mut self, offset: u16, value: u32) {
+        match offset {
+            OPERATIONAL_BASE..EXTENDED_CAPABILITIES_BASE => {
+                self.write_operational(offset - OPERATIONAL_BASE, value)
+            }
+            RUNTIME_BASE..DOORBELL_BASE => self.write_runtime(offset - RUNTIME_BASE, value),
+            DOORBELL_BASE.. => self.write_doorbell((offset - DOORBELL_BASE) / 4, value),
+            _ => {
+                tracelimit::warn_ratelimited!(offset, value, "write to read-only register");
+            }
+        }
+    }
+
+    fn read_capability(&self, reg: spec::CapabilityRegister) -> u32 {
+        match reg {
+            spec::CapabilityRegister::CAPLENGTH_HCIVERSION => {
+                CAP_LENGTH as u32 | ((HCI_VERSION as u32) << 16)
+            }
+            spec::CapabilityRegister::HCSPARAMS1 => spec::HcsParams1::new()
+                .with_max_slots(MAX_SLOTS)
+                .with_max_interrupters(1)
+                .with_max_ports(self.ports.len() as u8)
+                .into(),
+            spec::CapabilityRegister::HCSPARAMS2 => {
+                spec::HcsParams2::new().with_erst_max(ERST_MAX).into()
+            }
+            spec::CapabilityRegister::HCCPARAMS1 => spec::HccParams1::new()
+                .with_ac64(true)
+                .with_nss(true)
+                .with_xecp(EXTENDED_CAPABILITIES_BASE / 4)
+                .into(),
+            spec::CapabilityRegister::DBOFF => DOORBELL_BASE.into(),
+            spec::CapabilityRegister::RTSOFF => RUNTIME_BASE.into(),
+            _ => 0,
+        }
+    }
+
+    /// Reads the extended capability list, which contains a single supported
+    /// protocol capability describing the USB 2.0 ports.
+    fn read_extended_capability(&self, offset: u16) -> u32 {
+        match offset {
+            0x0 => spec::EXTENDED_CAPABILITY_SUPPORTED_PROTOCOL as u32 | (0x02 << 24),
+            0x4 => spec::PROTOCOL_NAME_USB,
+            0x8 => 1 | ((self.ports.len() as u32) << 8),
+            _ => 0,
+        }
+    }
+
+    fn read_operational(&self, offset: u16) -> u32 {
+        if offset >= spec::PORT_REGISTERS_OFFSET {
+            let index =
+                ((offset - spec::PORT_REGISTERS_OFFSET) / spec::PORT_REGISTERS_SIZE) as usize;
+            let reg = spec::PortRegister(
+                (offset - spec::PORT_REGISTERS_OFFSET) % spec::PORT_REGISTERS_SIZE,
+            );
+            return match (self.ports.get(index), reg) {
+                (Some(port), spec::PortRegister::PORTSC) => port.portsc.into(),
+                _ => 0,
+            };
+        }
+        match spec::OperationalRegister(offset) {
+            spec::OperationalRegister::USBCMD => self.registers.usbcmd.into(),
+            spec::OperationalRegister::USBSTS => self.registers.usbsts.into(),
+            // 4KB pages only.
+            spec::OperationalRegister::PAGESIZE => 1,
+            spec::OperationalRegister::DNCTRL => self.registers.dnctrl,
+            // Only the command ring running bit is readable.
+            spec::OperationalRegister::CRCR_LO => spec::Crcr::new()
+                .with_command_ring_running(self.command_ring.running)
+                .into_bits() as u32,
+            spec::OperationalRegister::DCBAAP_LO => self.registers.dcbaap as u32,
+            spec::OperationalRegister::DCBAAP_HI => (self.registers.dcbaap >> 32) as u32,
+            spec::OperationalRegister::CONFIG => self.registers.config,
+            _ => 0,
+        }
+    }
+
+    fn write_operational(&mut self, offset: u16, value: u32) {
+        if offset >= spec::PORT_REGISTERS_OFFSET {
+            let index =
+                ((offset - spec::PORT_REGISTERS_OFFSET) / spec::PORT_REGISTERS_SIZE) as usize;
+            let reg = spec::PortRegister(
+                (offset - spec::PORT_REGISTERS_OFFSET) % spec::PORT_REGISTERS_SIZE,
+            );
+            if index < self.ports.len() && reg == spec::PortRegister::PORTSC {
+                self.write_portsc(index, value);
+            }
+            return;
+        }
+        match spec::OperationalRegister(offset) {
+            spec::OperationalRegister::USBCMD => self.set_usbcmd(value),
+            spec::OperationalRegister::USBSTS => {
+                self.registers.usbsts = spec::UsbSts::from(
+                    self.registers.usbsts.into_bits() & !(value & spec::USBSTS_RW1C_MASK),
+                );
+            }
+            spec::OperationalRegister::DNCTRL => self.registers.dnctrl = value & 0xffff,
+            spec::OperationalRegister::CRCR_LO => {
+                let crcr = spec::Crcr::from(value as u64);
+                if self.command_ring.running {
+                    if crcr.command_stop() || crcr.command_abort() {
+                        self.command_ring.running = false;
+                        self.post_event(Trb::new(
+                            TrbType::COMMAND_COMPLETION_EVENT,
+                            self.command_ring.ring.dequeue,
+                            (CompletionCode::COMMAND_RING_STOPPED.0 as u32) << 24,
+                            0,
+                        ));
+                    }
+                } else {
+                    let ring = &mut self.command_ring.ring;
+                    ring.dequeue = (ring.dequeue & !0xffff_ffff) | (value & !0x3f) as u64;
+                    ring.cycle = crcr.ring_cycle_state();
+                }
+            }
+            spec::OperationalRegister::CRCR_HI => {
+                if !self.command_ring.running {
+                    let ring = &mut self.command_ring.ring;
+                    ring.dequeue = (ring.dequeue & 0xffff_ffff) | ((value as u64) << 32);
+                }
+            }
+            spec::OperationalRegister::DCBAAP_LO => {
+                self.registers.dcbaap =
+                    (self.registers.dcbaap & !0xffff_ffff) | (value & !0x3f) as u64;
+            }
+            spec::OperationalRegister::DCBAAP_HI => {
+                self.registers.dcbaap =
+                    (self.registers.dcbaap & 0xffff_ffff) | ((value as u64) << 32);
+            }
+            spec::OperationalRegister::CONFIG => self.registers.config = value & 0x3ff,
+            _ => {
+                tracelimit::warn_ratelimited!(
+                    offset,
+                    value,
+                    "unsupported operational register write"
+                );
+            }
+        }
+    }
+
+    fn write_portsc(&mut self, index: usize, value: u32) {
+        let port = &mut self.ports[index];
+        let write = spec::PortSc::from(value);
+        let mut bits = port.portsc.into_bits();
+        bits &= !(value & spec::PORTSC_CHANGE_MASK);
+        bits = (bits & !spec::PORTSC_RW_MASK) | (value & spec::PORTSC_RW_MASK);
+        let mut portsc = spec::PortSc::from(bits);
+        if write.port_enabled() && portsc.port_enabled() {
+            // Writing one disables the port.
+            portsc.set_port_enabled(false);
+            portsc.set_port_link_state(spec::LinkState::POLLING.0);
+        }
+        if write.port_link_state_write_strobe() && portsc.port_enabled() {
+            let state = spec::LinkState(write.port_link_state());
+            match state {
+                spec::LinkState::U0 | spec::LinkState::U3 | spec::LinkState::RESUME => {
+                    portsc.set_port_link_state(state.0);
+                }
+                _ => {
+                    tracelimit::warn_ratelimited!(?state, "unsupported port link state");
+                }
+            }
+        }
+        port.portsc = portsc;
+        if write.port_reset() && portsc.current_connect_status() {
+            self.reset_port(index);
+        }
+    }
+
+    fn read_runtime(&self, offset: u16) -> u32 {
+        let interrupter = &self.registers.interrupter;
+        match offset {
+            // MFINDEX is not implemented; isochronous transfers are not
+            // supported.
+            0..spec::INTERRUPTER_REGISTERS_OFFSET => 0,
+            _ => match spec::InterrupterRegister(offset - spec::INTERRUPTER_REGISTERS_OFFSET) {
+                spec::InterrupterRegister::IMAN => interrupter.iman.into(),
+                spec::InterrupterRegister::IMOD => interrupter.imod,
+                spec::InterrupterRegister::ERSTSZ => interrupter.erstsz,
+                spec::InterrupterRegister::ERSTBA_LO => interrupter.erstba as u32,
+                spec::InterrupterRegister::ERSTBA_HI => (interrupter.erstba >> 32) as u32,
+                spec::InterrupterRegister::ERDP_LO => spec::Erdp::from(interrupter.erdp)
+                    .with_event_handler_busy(interrupter.event_handler_busy)
+                    .into_bits() as u32,
+                spec::InterrupterRegister::ERDP_HI => (interrupter.erdp >> 32) as u32,
+                _ => 0,
+            },
+        }
+    }
+
+    fn write_runtime(&mut self, offset: u16, value: u32) {
+        if !(spec::INTERRUPTER_REGISTERS_OFFSET
+            ..spec::INTERRUPTER_REGISTERS_OFFSET + spec::INTERRUPTER_REGISTERS_SIZE)
+            .contains(&offset)
+        {
+            tracelimit::warn_ratelimited!(offset, value, "unsupported runtime register write");
+            return;
+        }
+        let interrupter = &mut self.registers.interrupter;
+        match spec::InterrupterRegister(offset - spec::INTERRUPTER_REGISTERS_OFFSET) {
+            spec::InterrupterRegister::IMAN => {
+                let iman = spec::Iman::from(value);
+                if iman.interrupt_pending() {
+                    interrupter.iman.set_interrupt_pending(false);
+                }
+                interrupter
+                    .iman
+                    .set_interrupt_enable(iman.interrupt_enable());
+                self.update_interrupt();
+            }
+            spec::InterrupterRegister::IMOD => interrupter.imod = value,
+            spec::InterrupterRegister::ERSTSZ => {
+                interrupter.erstsz = (value & 0xffff).min(1 << ERST_MAX);
+            }
+            spec::InterrupterRegister::ERSTBA_LO | spec::InterrupterRegister::ERSTBA_HI => {
+                if offset & 4 == 0 {
+                    interrupter.erstba =
+                        (interrupter.erstba & !0xffff_ffff) | (value & !0x3f) as u64;
+                } else {
+                    interrupter.erstba =
+                        (interrupter.erstba & 0xffff_ffff) | ((value as u64) << 32);
+                }
+                // Writing ERSTBA initializes the event ring.
+                let mut event_ring = EventRing {
+                    configured: interrupter.erstsz != 0,
+                    cycle: true,
+                    ..Default::default()
+                };
+                if event_ring.configured {
+                    if let Err(err) =
+                        event_ring.load_segment(&self.guest_memory, interrupter.erstba, 0)
+                    {
+                        tracelimit::warn_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to initialize event ring"
+                        );
+                        event_ring.configured = false;
+                    }
+                }
+                interrupter.event_ring = event_ring;
+            }
+            spec::InterrupterRegister::ERDP_LO => {
+                let erdp = spec::Erdp::from(value as u64);
+                if erdp.event_handler_busy() {
+                    interrupter.event_handler_busy = false;
+                }
+                interrupter.erdp = (interrupter.erdp & !0xffff_ffff)
+                    | (erdp.with_event_handler_busy(false).into_bits() & 0xffff_ffff);
+            }
+            spec::InterrupterRegister::ERDP_HI => {
+                interrupter.erdp = (interrupter.erdp & 0xffff_ffff) | ((value as u64) << 32);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_doorbell(&mut self, index: u16, value: u32) {
+        let doorbell = spec::Doorbell::from(value);
+        if !self.running() {
+            tracelimit::warn_ratelimited!(index, value, "doorbell while halted");
+            return;
+        }
+        if index == 0 {
+            if doorbell.target() == 0 {
+                self.command_ring.running = true;
+                self.wake();
+            }
+            return;
+        }
+        let dci = doorbell.target();
+        let Some(ep) = self
+            .slots
+            .get_mut(index as usize - 1)
+            .and_then(|slot| slot.as_mut())
+            .and_then(|slot| slot.endpoint_mut(dci))
+        else {
+            tracelimit::warn_ratelimited!(index, dci, "doorbell for disabled endpoint");
+            return;
+        };
+        ep.doorbell = true;
+        self.wake();
+    }
+
+    fn slot_busy(&self, slot_id: u8) -> bool {
+        self.ports.iter().any(|port| {
+            matches!(&port.device, Some(DeviceState::Busy(transfer))
+                if transfer.slot_id == slot_id && transfer.generation == port.generation)
+        })
+    }
+
+    /// Processes commands from the command ring. Returns true if any
+    /// commands were completed.
+    fn process_commands(&mut self) -> bool {
+        let mut progress = false;
+        while self.command_ring.running && self.running() {
+            let mut ring = self.command_ring.ring;
+            let command = match ring.next(&self.guest_memory) {
+                Ok(Some(command)) => command,
+                Ok(None) => break,
+                Err(err) => {
+                    self.fatal_error(&err);
+                    break;
+                }
+            };
+            let trb = command.trb;
+            let slot_id = trb.slot_id();
+            if !matches!(
+                trb.trb_type(),
+                TrbType::ENABLE_SLOT_COMMAND | TrbType::NO_OP_COMMAND
+            ) && self.slot_busy(slot_id)
+            {
+                // Wait for the slot's in-flight transfer to complete.
+                break;
+            }
+            self.command_ring.ring = ring;
+            let (code, slot_id) = match self.handle_command(&trb) {
+                Ok(slot_id) => (CompletionCode::SUCCESS, slot_id),
+                Err(CommandError::Completion(code)) => {
+                    tracing::debug!(trb_type = ?trb.trb_type(), ?code, "command failed");
+                    (code, slot_id)
+                }
+                Err(err @ CommandError::Memory(_)) => {
+                    self.fatal_error(&err);
+                    break;
+                }
+            };
+            self.post_event(Trb::new(
+                TrbType::COMMAND_COMPLETION_EVENT,
+                command.gpa,
+                (code.0 as u32) << 24,
+                (slot_id as u32) << 24,
+            ));
+            progress = true;
+        }
+        progress
+    }
+
+    /// Handles a command, returning the slot ID for the completion event.
+    fn handle_command(&mut self, trb: &Trb) -> Result<u8, CommandError> {
+        let slot_id = trb.slot_id();
+        let input_context = trb.parameter & !0xf;
+        match trb.trb_type() {
+            TrbType::NO_OP_COMMAND => Ok(0),
+            TrbType::ENABLE_SLOT_COMMAND => {
+                let max_slots = (self.registers.config & 0xff).min(MAX_SLOTS.into()) as usize;
+                let index = self.slots[..max_slots]
+                    .iter()
+                    .position(|slot| slot.is_none())
+                    .ok_or(CompletionCode::NO_SLOTS_AVAILABLE_ERROR)?;
+                self.slots[index] = Some(Slot::new());
+                Ok(index as u8 + 1)
+            }
+            TrbType::DISABLE_SLOT_COMMAND => {
+                slot_mut(&mut self.slots, slot_id)?;
+                self.slots[slot_id as usize - 1] = None;
+                Ok(slot_id)
+            }
+            TrbType::ADDRESS_DEVICE_COMMAND => {
+                slot_mut(&mut self.slots, slot_id)?;
+                let block_set_address = trb.flag(spec::TRB_BSR);
+                let slot_context: Context = self
+                    .guest_memory
+                    .read_plain(input_context + spec::CONTEXT_SIZE)?;
+                let ep0_context: Context = self
+                    .guest_memory
+                    .read_plain(input_context + spec::CONTEXT_SIZE * 2)?;
+                let output_context: u64 = self
+                    .guest_memory
+                    .read_plain::<u64>(self.registers.dcbaap + 8 * slot_id as u64)?
+                    & !0x3f;
+                let port_number = spec::SlotContext1::from(slot_context[1]).root_hub_port_number();
+                let port = (port_number as usize).wrapping_sub(1);
+                let port_enabled = self
+                    .ports
+                    .get(port)
+                    .is_some_and(|port| port.device.is_some() && port.portsc.port_enabled());
+
+                let slot = slot_mut(&mut self.slots, slot_id)?;
+                match slot.state {
+                    SlotState::DISABLED_ENABLED => {}
+                    SlotState::DEFAULT if !block_set_address => {}
+                    _ => return Err(CompletionCode::CONTEXT_STATE_ERROR.into()),
+                }
+                if !port_enabled {
+                    return Err(CompletionCode::USB_TRANSACTION_ERROR.into());
+                }
+                slot.port = Some(port);
+                slot.output_context = output_context;
+                slot.state = if block_set_address {
+                    SlotState::DEFAULT
+                } else {
+                    SlotState::ADDRESSED
+                };
+                slot.endpoints = Default::default();
+                slot.endpoints[0] = Some(Endpoint::new(&ep0_context));
+                slot.write_slot_context(&self.guest_memory, slot_id, slot_context)?;
+                slot.write_endpoint_context(&self.guest_memory, 1, Some(ep0_context))?;
+                Ok(slot_id)
+            }
+            TrbType::CONFIGURE_ENDPOINT_COMMAND => {
+                let guest_memory = &self.guest_memory;
+                let deconfigure = trb.flag(spec::TRB_DC);
+                let slot = slot_mut(&mut self.slots, slot_id)?;
+                if !matches!(slot.state, SlotState::ADDRESSED | SlotState::CONFIGURED) {
+                    return Err(CompletionCode::CONTEXT_STATE_ERROR.into());
+                }
+                let (drop_flags, add_flags) = if deconfigure {
+                    (!0, 0)
+                } else {
+                    let control: Context = guest_memory.read_plain(input_context)?;
+                    (control[0], control[1])
+                };
+                for dci in 2..=ENDPOINT_COUNT as u8 {
+                    let bit = 1 << dci;
+                    if (drop_flags | add_flags) & bit != 0 {
+                        slot.endpoints[dci as usize - 1] = None;
+                    }
+                    if add_flags & bit != 0 {
+                        let context: Context = guest_memory
+                            .read_plain(input_context + spec::CONTEXT_SIZE * (dci as u64 + 1))?;
+                        slot.endpoints[dci as usize - 1] = Some(Endpoint::new(&context));
+                        slot.write_endpoint_context(guest_memory, dci, Some(context))?;
+                    } else if drop_flags & bit != 0 {
+                        slot.write_endpoint_context(guest_memory, dci, None)?;
+                    }
+                }
+                let mut slot_context = slot.read_slot_context(guest_memory)?;
+                if add_flags & 1 != 0 {
+                    let input: Context =
+                        guest_memory.read_plain(input_context + spec::CONTEXT_SIZE)?;
+                    slot_context[0] = input[0];
+                }
+                slot.state = if slot.endpoints[1..].iter().any(|ep| ep.is_some()) {
+                    SlotState::CONFIGURED
+                } else {
+                    SlotState::ADDRESSED
+                };
+                slot.write_slot_context(guest_memory, slot_id, slot_context)?;
+                Ok(slot_id)
+            }
+            TrbType::EVALUATE_CONTEXT_COMMAND => {
+                let guest_memory = &self.guest_memory;
+                let slot = slot_mut(&mut self.slots, slot_id)?;
+                if slot.state == SlotState::DISABLED_ENABLED {
+                    return Err(CompletionCode::CONTEXT_STATE_ERROR.into());
+                }
+                let control: Context = guest_memory.read_plain(input_context)?;
+                if control[1] & 1 != 0 {
+                    let input: Context =
+                        guest_memory.read_plain(input_context + spec::CONTEXT_SIZE)?;
+                    let mut output = slot.read_slot_context(guest_memory)?;
+                    // Max exit latency and interrupter target.
+                    output[1] = (output[1] & !0xffff) | (input[1] & 0xffff);
+                    output[2] = (output[2] & 0x3fffff) | (input[2] & !0x3fffff);
+                    slot.write_slot_context(guest_memory, slot_id, output)?;
+                }
+                if control[1] & 2 != 0 {
+                    let input: Context =
+                        guest_memory.read_plain(input_context + spec::CONTEXT_SIZE * 2)?;
+                    let gpa = slot.context_address(1);
+                    let mut output: Context = guest_memory.read_plain(gpa)?;
+                    // Max packet size.
+                    output[1] = (output[1] & 0xffff) | (input[1] & !0xffff);
+                    guest_memory.write_plain(gpa, &output)?;
+                }
+                Ok(slot_id)
+            }
+            TrbType::RESET_ENDPOINT_COMMAND
+            | TrbType::STOP_ENDPOINT_COMMAND
+            | TrbType::SET_TR_DEQUEUE_POINTER_COMMAND => {
+                let guest_memory = &self.guest_memory;
+                let dci = trb.endpoint_id();
+                let slot = slot_mut(&mut self.slots, slot_id)?;
+                let ep = slot
+                    .endpoint_mut(dci)
+                    .ok_or(CompletionCode::ENDPOINT_NOT_ENABLED_ERROR)?;
+                match trb.trb_type() {
+                    TrbType::RESET_ENDPOINT_COMMAND => {
+                        if ep.state != EndpointState::HALTED {
+                            return Err(CompletionCode::CONTEXT_STATE_ERROR.into());
+                        }
+                        ep.state = EndpointState::STOPPED;
+                    }
+                    TrbType::STOP_ENDPOINT_COMMAND => {
+                        if ep.state != EndpointState::RUNNING {
+                            return Err(CompletionCode::CONTEXT_STATE_ERROR.into());
+                        }
+                        ep.state = EndpointState::STOPPED;
+                    }
+                    _ => {
+                        if !matches!(ep.state, EndpointState::STOPPED | EndpointState::ERROR) {
+                            return Err(CompletionCode::CONTEXT_STATE_ERROR.into());
+                        }
+                        ep.ring = Ring::new(trb.parameter);
+                    }
+                }
+                ep.control = None;
+                ep.doorbell = false;
+                slot.write_endpoint_context(guest_memory, dci, None)?;
+                Ok(slot_id)
+            }
+            TrbType::RESET_DEVICE_COMMAND => {
+                let guest_memory = &self.guest_memory;
+                let slot = slot_mut(&mut self.slots, slot_id)?;
+                if !matches!(slot.state, SlotState::ADDRESSED | SlotState::CONFIGURED) {
+                    return Err(CompletionCode::CONTEXT_STATE_ERROR.into());
+                }
+                for dci in 2..=ENDPOINT_COUNT as u8 {
+                    if slot.endpoints[dci as usize - 1].take().is_some() {
+                        slot.write_endpoint_context(guest_memory, dci, None)?;
+                    }
+                }
+                let mut slot_context = slot.read_slot_context(guest_memory)?;
+                slot_context[0] = spec::SlotContext0::from(slot_context[0])
+                    .with_context_entries(1)
+                    .into();
+                slot.state = SlotState::DEFAULT;
+                slot.write_slot_context(guest_memory, slot_id, slot_context)?;
+                Ok(slot_id)
+            }
+            trb_type => {
+                tracelimit::warn_ratelimited!(?trb_type, "unsupported command");
+                Err(CompletionCode::TRB_ERROR.into())
+            }
+        }
+    }
+
+    /// Starts transfers on endpoints whose doorbells have been rung. Returns
+    /// true if any TDs were started or completed.
+    fn process_transfers(&mut self) -> bool {
+        let mut progress = false;
+        for slot_index in 0..self.slots.len() {
+            for dci in 1..=ENDPOINT_COUNT as u8 {
+                loop {
+                    if !self.running() {
+                        return progress;
+                    }
+                    let Some(slot) = &mut self.slots[slot_index] else {
+                        break;
+                    };
+                    let Some(port) = slot.port else {
+                        break;
+                    };
+                    let Some(ep) = slot.endpoint_mut(dci) else {
+                        break;
+                    };
+                    if !ep.doorbell || ep.state != EndpointState::RUNNING {
+                        break;
+                    }
+                    if !matches!(self.ports[port].device, Some(DeviceState::Idle(_))) {
+                        break;
+                    }
+                    let td = match Td::read(&ep.ring, &self.guest_memory) {
+                        Ok(Some(td)) => td,
+                        Ok(None) => {
+                            ep.doorbell = false;
+                            break;
+                        }
+                        Err(err) => {
+                            self.fatal_error(&err);
+                            return progress;
+                        }
+                    };
+                    if let Err(err) = self.start_td(slot_index as u8 + 1, dci, port, td) {
+                        self.fatal_error(&err);
+                        return progress;
+                    }
+                    progress = true;
+                }
+            }
+        }
+        progress
+    }
+
+    /// Starts processing `td` from endpoint `dci` of slot `slot_id`.
+    fn start_td(
+        &mut self,
+        slot_id: u8,
+        dci: u8,
+        port: usize,
+        td: Td,
+    ) -> Result<(), GuestMemoryError> {
+        let ep = self.slots[slot_id as usize - 1]
+            .as_mut()
+            .unwrap()
+            .endpoint_mut(dci)
+            .unwrap();
+        let first = td.trbs[0].trb;
+        let len = td.len();
+        if len > MAX_TRANSFER_LENGTH {
+            tracelimit::warn_ratelimited!(len, "transfer too long");
+            ep.ring = td.next;
+            let last = td.trbs.last().unwrap();
+            self.transfer_event(last.gpa, CompletionCode::TRB_ERROR, 0, slot_id, dci, false);
+            return Ok(());
+        }
+
+        let request = match (ep.endpoint_type, first.trb_type()) {
+            (EndpointType::CONTROL, TrbType::SETUP_STAGE) => {
+                let setup = SetupPacket::read_from_bytes(&first.parameter.to_le_bytes())
+                    .expect("setup packet is 8 bytes");
+                ep.ring = td.next;
+                // Device-to-host and no-data requests execute immediately.
+                // Host-to-device requests wait for the data stage.
+                let execute = setup.is_in() || first.transfer_type() == spec::TRANSFER_TYPE_NO_DATA;
+                ep.control = Some(ControlTransfer {
+                    setup,
+                    result: None,
+                });
+                if first.flag(spec::TRB_IOC) {
+                    self.transfer_event(
+                        td.trbs[0].gpa,
+                        CompletionCode::SUCCESS,
+                        0,
+                        slot_id,
+                        dci,
+                        false,
+                    );
+                }
+                if execute {
+                    self.start_transfer(
+                        port,
+                        slot_id,
+                        dci,
+                        TransferKind::ControlSetup,
+                        TransferRequest::Control(setup, Vec::new()),
+                    );
+                }
+                return Ok(());
+            }
+            (EndpointType::CONTROL, TrbType::DATA_STAGE | TrbType::STATUS_STAGE) => {
+                let Some(control) = &mut ep.control else {
+                    tracelimit::warn_ratelimited!("control transfer stage without setup");
+                    ep.ring = td.next;
+                    self.transfer_event(
+                        td.trbs[0].gpa,
+                        CompletionCode::TRB_ERROR,
+                        0,
+                        slot_id,
+                        dci,
+                        false,
+                    );
+                    return Ok(());
+                };
+                if first.trb_type() == TrbType::DATA_STAGE && !control.setup.is_in() {
+                    TransferRequest::Control(control.setup, td.gather(&self.guest_memory)?)
+                } else {
+                    let result = if first.trb_type() == TrbType::DATA_STAGE {
+                        control.result.replace(Ok(Vec::new()))
+                    } else {
+                        ep.control.take().and_then(|control| control.result)
+                    };
+                    match result {
+                        Some(Ok(mut data)) => {
+                            data.truncate(len);
+                            td.scatter(&self.guest_memory, &data)?;
+                            self.complete_td(slot_id, dci, td, data.len());
+                        }
+                        // NAKs are not valid for control transfers.
+                        Some(Err(UsbError::Stall | UsbError::Nak)) => {
+                            self.halt_endpoint(slot_id, dci, &td)?
+                        }
+                        None => {
+                            // The setup stage was never executed.
+                            ep.ring = td.next;
+                            self.transfer_event(
+                                td.trbs[0].gpa,
+                                CompletionCode::TRB_ERROR,
+                                0,
+                                slot_id,
+                                dci,
+                                false,
+                            );
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+            (
+                EndpointType::BULK_IN | EndpointType::INTERRUPT_IN,
+                TrbType::NORMAL | TrbType::EVENT_DATA | TrbType::NO_OP,
+            ) => TransferRequest::In(endpoint_address(dci), len),
+            (
+                EndpointType::BULK_OUT | EndpointType::INTERRUPT_OUT,
+                TrbType::NORMAL | TrbType::EVENT_DATA | TrbType::NO_OP,
+            ) => TransferRequest::Out(endpoint_address(dci), td.gather(&self.guest_memory)?),
+            (endpoint_type, trb_type) => {
+                tracelimit::warn_ratelimited!(?endpoint_type, ?trb_type, "unsupported transfer");
+                ep.ring = td.next;
+                self.transfer_event(
+                    td.trbs[0].gpa,
+                    CompletionCode::TRB_ERROR,
+                    0,
+                    slot_id,
+                    dci,
+                    false,
+                );
+                return Ok(());
+            }
+        };
+
+        if len == 0 && !matches!(request, TransferRequest::Control(..)) {
+            // Nothing to transfer, e.g. for a TD of only no-op TRBs.
+            self.complete_td(slot_id, dci, td, 0);
+            return Ok(());
+        }
+
+        let kind = match &request {
+            TransferRequest::In(..) => TransferKind::In(td),
+            TransferRequest::Out(_, data) | TransferRequest::Control(_, data) => {
+                TransferKind::Out(td, data.len())
+            }
+        };
+        self.start_transfer(port, slot_id, dci, kind, request);
+        Ok(())
+    }
+
+    fn start_transfer(
+        &mut self,
+        port: usize,
+        slot_id: u8,
+        dci: u8,
+        kind: TransferKind,
+        request: TransferRequest,
+    ) {
+        let port = &mut self.ports[port];
+        let Some(DeviceState::Idle(mut device)) = port.device.take() else {
+            unreachable!("device must be idle")
+        };
+        let io: TransferFuture = Box::pin(async move {
+            let result = match request {
+                TransferRequest::Control(setup, data) => device.control(setup, &data).await,
+                TransferRequest::In(endpoint, len) => device.transfer_in(endpoint, len).await,
+                TransferRequest::Out(endpoint, data) => device
+                    .transfer_out(endpoint, &data)
+                    .await
+                    .map(|()| Vec::new()),
+            };
+            (device, result)
+        });
+        port.device = Some(DeviceState::Busy(Transfer {
+            io,
+            slot_id,
+            dci,
+            generation: port.generation,
+            kind,
+        }));
+    }
+
+    /// Completes an in-flight transfer on `port`.
+    fn complete_transfer(
+        &mut self,
+        port: usize,
+        transfer: Transfer,
+        result: Result<Vec<u8>, UsbError>,
+    ) -> Result<(), GuestMemoryError> {
+        if transfer.generation != self.ports[port].generation {
+            return Ok(());
+        }
+        let Transfer {
+            io: _,
+            slot_id,
+            dci,
+            generation: _,
+            kind,
+        } = transfer;
+        let Some(ep) = self.slots[slot_id as usize - 1]
+            .as_mut()
+            .and_then(|slot| slot.endpoint_mut(dci))
+        else {
+            return Ok(());
+        };
+        if ep.state != EndpointState::RUNNING {
+            return Ok(());
+        }
+        match kind {
+            TransferKind::ControlSetup => {
+                if let Some(control) = &mut ep.control {
+                    control.result = Some(result);
+                }
+            }
+            TransferKind::In(td) => match result {
+                Ok(mut data) => {
+                    data.truncate(td.len());
+                    td.scatter(&self.guest_memory, &data)?;
+                    self.complete_td(slot_id, dci, td, data.len());
+                }
+                Err(UsbError::Stall) => self.halt_endpoint(slot_id, dci, &td)?,
+                // Leave the TD on the ring to retry it.
+                Err(UsbError::Nak) => {}
+            },
+            TransferKind::Out(td, len) => match result {
+                Ok(_) => {
+                    if let Some(control) = &mut ep.control {
+                        control.result = Some(Ok(Vec::new()));
+                    }
+                    self.complete_td(slot_id, dci, td, len);
+                }
+                Err(UsbError::Stall) => self.halt_endpoint(slot_id, dci, &td)?,
+                Err(UsbError::Nak) => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// Consumes `td` and reports its completion, given that `transferred`
+    /// bytes were transferred.
+    fn complete_td(&mut self, slot_id: u8, dci: u8, td: Td, transferred: usize) {
+        let ep = self.slots[slot_id as usize - 1]
+            .as_mut()
+            .unwrap()
+            .endpoint_mut(dci)
+            .unwrap();
+        ep.ring = td.next;
+
+        let mut remaining = transferred;
+        let mut event_data_length = 0;
+        let mut short = false;
+        let mut short_reported = false;
+        for t in &td.trbs {
+            let trb = &t.trb;
+            match trb.trb_type() {
+                TrbType::NORMAL | TrbType::DATA_STAGE | TrbType::ISOCH => {
+                    if short {
+                        continue;
+                    }
+                    let len = trb.transfer_length() as usize;
+                    let n = remaining.min(len);
+                    remaining -= n;
+                    event_data_length += n as u32;
+                    if n < len {
+                        short = true;
+                        if trb.flag(spec::TRB_ISP) || trb.flag(spec::TRB_IOC) {
+                            short_reported = true;
+                            self.transfer_event(
+                                t.gpa,
+                                CompletionCode::SHORT_PACKET,
+                                (len - n) as u32,
+                                slot_id,
+                                dci,
+                                false,
+                            );
+                        }
+                    } else if trb.flag(spec::TRB_IOC) {
+                        self.transfer_event(t.gpa, CompletionCode::SUCCESS, 0, slot_id, dci, false);
+                    }
+                }
+                TrbType::EVENT_DATA => {
+                    if trb.flag(spec::TRB_IOC) {
+                        let code = if short {
+                            short_reported = true;
+                            CompletionCode::SHORT_PACKET
+                        } else {
+                            CompletionCode::SUCCESS
+                        };
+                        self.transfer_event(
+                            trb.parameter,
+                            code,
+                            event_data_length,
+                            slot_id,
+                            dci,
+                            true,
+                        );
+                    }
+                    event_data_length = 0;
+                    if short {
+                        // Execution resumes after the event data TRB
+                        // following a short packet.
+                        break;
+                    }
+                }
+                _ => {
+                    if !short && trb.flag(spec::TRB_IOC) {
+                        self.transfer_event(t.gpa, CompletionCode::SUCCESS, 0, slot_id, dci, false);
+                    }
+                }
+            }
+        }
+        if short && !short_reported {
+            let last = td.trbs.last().unwrap();
+            if last.trb.flag(spec::TRB_IOC) {
+                self.transfer_event(
+                    last.gpa,
+                    CompletionCode::SHORT_PACKET,
+                    last.trb.transfer_length(),
+                    slot_id,
+                    dci,
+                    false,
+                );
+            }
+        }
+    }
+
+    /// Halts an endpoint after the device stalled `td`. The TD is left on the
+    /// ring for the driver to skip with a Set TR Dequeue Pointer command.
+    fn halt_endpoint(&mut self, slot_id: u8, dci: u8, td: &Td) -> Result<(), GuestMemoryError> {
+        let slot = self.slots[slot_id as usize - 1].as_mut().unwrap();
+        let ep = slot.endpoint_mut(dci).unwrap();
+        ep.state = EndpointState::HALTED;
+        ep.control = None;
+        ep.doorbell = false;
+        slot.write_endpoint_context(&self.guest_memory, dci, None)?;
+        let trb = td.error_trb();
+        self.transfer_event(
+            trb.gpa,
+            CompletionCode::STALL_ERROR,
+            trb.trb.transfer_length(),
+            slot_id,
+            dci,
+            false,
+        );
+        Ok(())
+    }
+}
+
+impl ChangeDeviceState for XhciController {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.cfg_space.reset();
+        self.reset_controller();
+    }
+}
+
+impl ChipsetDevice for XhciController {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl MmioIntercept for XhciController {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        match self.cfg_space.find_bar(addr) {
+            Some((0, offset)) => {
+                read_as_u32_chunks(offset, data, |offset| self.read_u32(offset));
+                IoResult::Ok
+            }
+            _ => IoResult::Err(IoError::InvalidRegister),
+        }
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        match self.cfg_space.find_bar(addr) {
+            Some((0, offset)) => {
+                if data.len() != 4 && data.len() != 8 {
+                    return IoResult::Err(IoError::InvalidAccessSize);
+                }
+                if offset & 3 != 0 {
+                    return IoResult::Err(IoError::UnalignedAccess);
+                }
+                // 64-bit writes are split into two 32-bit writes, low dword
+                // first.
+                for (i, chunk) in data.chunks_exact(4).enumerate() {
+                    self.write_u32(
+                        offset + i as u16 * 4,
+                        u32::from_ne_bytes(chunk.try_into().unwrap()),
+                    );
+                }
+                IoResult::Ok
+            }
+            _ => IoResult::Err(IoError::InvalidRegister),
+        }
+    }
+}
+
+impl PciConfigSpace for XhciController {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        self.cfg_space.read_u32(offset, value)
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        self.cfg_space.write_u32(offset, value)
+    }
+}
+
+impl PollDevice for XhciController {
+    fn poll_device(&mut self, cx: &mut TaskContext<'_>) {
+        self.waker = Some(cx.waker().clone());
+        loop {
+            let mut progress = false;
+            for index in 0..self.ports.len() {
+                let port = &mut self.ports[index];
+                let Some(DeviceState::Busy(transfer)) = &mut port.device else {
+                    continue;
+                };
+                let Poll::Ready((mut device, result)) = transfer.io.as_mut().poll(cx) else {
+                    continue;
+                };
+                if port.reset_pending {
+                    device.reset();
+                    port.reset_pending = false;
+                }
+                let Some(DeviceState::Busy(transfer)) =
+                    port.device.replace(DeviceState::Idle(device))
+                else {
+                    unreachable!()
+                };
+                if let Err(err) = self.complete_transfer(index, transfer, result) {
+                    self.fatal_error(&err);
+                }
+                progress = true;
+            }
+            if self.running() {
+                progress |= self.process_commands();
+                progress |= self.process_transfers();
+            }
+            if !progress {
+                break;
+            }
+        }
+    }
+}
+
+impl SaveRestore for XhciController {
+    type SavedState = SavedStateNotSupported;
+
+    fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+        Err(SaveError::NotSupported)
+    }
+
+    fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+        match state {}
+    }
+}
@@ -0,0 +1,590 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions from the xHCI specification, revision 1.2.
+
+#![expect(missing_docs)]
+
+use bitfield_struct::bitfield;
+use inspect::Inspect;
+use open_enum::open_enum;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+open_enum! {
+    /// Capability register offsets.
+    pub enum CapabilityRegister: u16 {
+        CAPLENGTH_HCIVERSION = 0x00,
+        HCSPARAMS1 = 0x04,
+        HCSPARAMS2 = 0x08,
+        HCSPARAMS3 = 0x0c,
+        HCCPARAMS1 = 0x10,
+        DBOFF = 0x14,
+        RTSOFF = 0x18,
+        HCCPARAMS2 = 0x1c,
+    }
+}
+
+open_enum! {
+    /// Operational register offsets, relative to the operational base.
+    pub enum OperationalRegister: u16 {
+        USBCMD = 0x00,
+        USBSTS = 0x04,
+        PAGESIZE = 0x08,
+        DNCTRL = 0x14,
+        CRCR_LO = 0x18,
+        CRCR_HI = 0x1c,
+        DCBAAP_LO = 0x30,
+        DCBAAP_HI = 0x34,
+        CONFIG = 0x38,
+    }
+}
+
+/// The offset of the port register sets, relative to the operational base.
+pub const PORT_REGISTERS_OFFSET: u16 = 0x400;
+/// The size of each port register set.
+pub const PORT_REGISTERS_SIZE: u16 = 0x10;
+
+open_enum! {
+    /// Port register offsets, relative to the port register set.
+    pub enum PortRegister: u16 {
+        PORTSC = 0x0,
+        PORTPMSC = 0x4,
+        PORTLI = 0x8,
+        PORTHLPMC = 0xc,
+    }
+}
+
+open_enum! {
+    /// Runtime register offsets, relative to the runtime base.
+    pub enum RuntimeRegister: u16 {
+        MFINDEX = 0x00,
+    }
+}
+
+/// The offset of the interrupter register sets, relative to the runtime
+/// base.
+pub const INTERRUPTER_REGISTERS_OFFSET: u16 = 0x20;
+/// The size of each interrupter register set.
+pub const INTERRUPTER_REGISTERS_SIZE: u16 = 0x20;
+
+open_enum! {
+    /// Interrupter register offsets, relative to the interrupter register set.
+    pub enum InterrupterRegister: u16 {
+        IMAN = 0x00,
+        IMOD = 0x04,
+        ERSTSZ = 0x08,
+        ERSTBA_LO = 0x10,
+        ERSTBA_HI = 0x14,
+        ERDP_LO = 0x18,
+        ERDP_HI = 0x1c,
+    }
+}
+
+#[bitfield(u32)]
+pub struct HcsParams1 {
+    pub max_slots: u8,
+    #[bits(11)]
+    pub max_interrupters: u16,
+    #[bits(5)]
+    pub reserved: u8,
+    pub max_ports: u8,
+}
+
+#[bitfield(u32)]
+pub struct HcsParams2 {
+    #[bits(4)]
+    pub ist: u8,
+    #[bits(4)]
+    pub erst_max: u8,
+    #[bits(13)]
+    pub reserved: u16,
+    #[bits(5)]
+    pub max_scratchpad_buffers_hi: u8,
+    pub spr: bool,
+    #[bits(5)]
+    pub max_scratchpad_buffers_lo: u8,
+}
+
+#[bitfield(u32)]
+pub struct HccParams1 {
+    pub ac64: bool,
+    pub bnc: bool,
+    pub csz: bool,
+    pub ppc: bool,
+    pub pind: bool,
+    pub lhrc: bool,
+    pub ltc: bool,
+    pub nss: bool,
+    pub pae: bool,
+    pub spc: bool,
+    pub sec: bool,
+    pub cfc: bool,
+    #[bits(4)]
+    pub max_psa_size: u8,
+    /// The offset of the first extended capability, in dwords.
+    pub xecp: u16,
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct UsbCmd {
+    pub run_stop: bool,
+    pub host_controller_reset: bool,
+    pub interrupter_enable: bool,
+    pub host_system_error_enable: bool,
+    #[bits(3)]
+    pub reserved: u8,
+    pub light_host_controller_reset: bool,
+    pub controller_save_state: bool,
+    pub controller_restore_state: bool,
+    pub enable_wrap_event: bool,
+    pub enable_u3_mfindex_stop: bool,
+    pub reserved2: bool,
+    pub cem_enable: bool,
+    pub extended_tbc_enable: bool,
+    pub extended_tbc_trb_status_enable: bool,
+    pub vtio_enable: bool,
+    #[bits(15)]
+    pub reserved3: u16,
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct UsbSts {
+    pub hc_halted: bool,
+    pub reserved: bool,
+    pub host_system_error: bool,
+    pub event_interrupt: bool,
+    pub port_change_detect: bool,
+    #[bits(3)]
+    pub reserved2: u8,
+    pub save_state_status: bool,
+    pub restore_state_status: bool,
+    pub save_restore_error: bool,
+    pub controller_not_ready: bool,
+    pub host_controller_error: bool,
+    #[bits(19)]
+    pub reserved3: u32,
+}
+
+/// The write-1-to-clear bits of USBSTS.
+pub const USBSTS_RW1C_MASK: u32 = UsbSts::new()
+    .with_host_system_error(true)
+    .with_event_interrupt(true)
+    .with_port_change_detect(true)
+    .with_save_restore_error(true)
+    .into_bits();
+
+#[bitfield(u64)]
+pub struct Crcr {
+    pub ring_cycle_state: bool,
+    pub command_stop: bool,
+    pub command_abort: bool,
+    pub command_ring_running: bool,
+    #[bits(2)]
+    pub reserved: u8,
+    #[bits(58)]
+    pub pointer_hi: u64,
+}
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct PortSc {
+    pub current_connect_status: bool,
+    pub port_enabled: bool,
+    pub reserved: bool,
+    pub over_current_active: bool,
+    pub port_reset: bool,
+    #[bits(4)]
+    pub port_link_state: u8,
+    pub port_power: bool,
+    #[bits(4)]
+    pub port_speed: u8,
+    #[bits(2)]
+    pub port_indicator: u8,
+    pub port_link_state_write_strobe: bool,
+    pub connect_status_change: bool,
+    pub port_enabled_change: bool,
+    pub warm_port_reset_change: bool,
+    pub over_current_change: bool,
+    pub port_reset_change: bool,
+    pub port_link_state_change: bool,
+    pub port_config_error_change: bool,
+    pub cold_attach_status: bool,
+    pub wake_on_connect_enable: bool,
+    pub wake_on_disconnect_enable: bool,
+    pub wake_on_over_current_enable: bool,
+    #[bits(2)]
+    pub reserved2: u8,
+    pub device_removable: bool,
+    pub warm_port_reset: bool,
+}
+
+/// The change bits of PORTSC, which are write-1-to-clear.
+pub const PORTSC_CHANGE_MASK: u32 = PortSc::new()
+    .with_connect_status_change(true)
+    .with_port_enabled_change(true)
+    .with_warm_port_reset_change(true)
+    .with_over_current_change(true)
+    .with_port_reset_change(true)
+    .with_port_link_state_change(true)
+    .with_port_config_error_change(true)
+    .into_bits();
+
+/// The read/write bits of PORTSC.
+pub const PORTSC_RW_MASK: u32 = PortSc::new()
+    .with_port_indicator(3)
+    .with_wake_on_connect_enable(true)
+    .with_wake_on_disconnect_enable(true)
+    .with_wake_on_over_current_enable(true)
+    .into_bits();
+
+open_enum! {
+    /// Port link states.
+    pub enum LinkState: u8 {
+        U0 = 0,
+        U1 = 1,
+        U2 = 2,
+        U3 = 3,
+        DISABLED = 4,
+        RX_DETECT = 5,
+        INACTIVE = 6,
+        POLLING = 7,
+        RECOVERY = 8,
+        HOT_RESET = 9,
+        COMPLIANCE_MODE = 10,
+        TEST_MODE = 11,
+        RESUME = 15,
+    }
+}
+
+/// Default protocol speed IDs.
+pub const SPEED_FULL: u8 = 1;
+pub const SPEED_LOW: u8 = 2;
+pub const SPEED_HIGH: u8 = 3;
+
+#[derive(Inspect)]
+#[bitfield(u32)]
+pub struct Iman {
+    pub interrupt_pending: bool,
+    pub interrupt_enable: bool,
+    #[bits(30)]
+    pub reserved: u32,
+}
+
+#[bitfield(u64)]
+pub struct Erdp {
+    #[bits(3)]
+    pub dequeue_erst_segment_index: u8,
+    pub event_handler_busy: bool,
+    #[bits(60)]
+    pub pointer_hi: u64,
+}
+
+#[bitfield(u32)]
+pub struct Doorbell {
+    pub target: u8,
+    pub reserved: u8,
+    pub stream_id: u16,
+}
+
+/// The extended capability ID for the supported protocol capability.
+pub const EXTENDED_CAPABILITY_SUPPORTED_PROTOCOL: u8 = 2;
+
+/// The name string of the supported protocol capability for USB ports.
+pub const PROTOCOL_NAME_USB: u32 = u32::from_le_bytes(*b"USB ");
+
+/// A transfer request block.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes, Inspect)]
+pub struct Trb {
+    #[inspect(hex)]
+    pub parameter: u64,
+    #[inspect(hex)]
+    pub status: u32,
+    #[inspect(hex)]
+    pub control: u32,
+}
+
+const _: () = assert!(size_of::<Trb>() == 16);
+
+pub const TRB_CYCLE: u32 = 1 << 0;
+/// The toggle cycle flag, for link TRBs.
+pub const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+/// The evaluate next TRB flag, for transfer TRBs.
+pub const TRB_ENT: u32 = 1 << 1;
+/// The interrupt-on-short-packet flag.
+pub const TRB_ISP: u32 = 1 << 2;
+/// The event data flag, for transfer events.
+pub const TRB_EVENT_DATA: u32 = 1 << 2;
+pub const TRB_CHAIN: u32 = 1 << 4;
+/// The interrupt-on-completion flag.
+pub const TRB_IOC: u32 = 1 << 5;
+/// The immediate data flag.
+pub const TRB_IDT: u32 = 1 << 6;
+/// The block set address request flag, for address device commands.
+pub const TRB_BSR: u32 = 1 << 9;
+/// The deconfigure flag, for configure endpoint commands.
+pub const TRB_DC: u32 = 1 << 9;
+/// The direction flag, for data and status stage TRBs.
+pub const TRB_DIR_IN: u32 = 1 << 16;
+
+const TRB_TYPE_SHIFT: u32 = 10;
+const TRB_TYPE_MASK: u32 = 0x3f;
+
+/// The mask of the transfer length in the status field of transfer TRBs.
+pub const TRB_TRANSFER_LENGTH_MASK: u32 = 0x1ffff;
+
+impl Trb {
+    pub fn trb_type(&self) -> TrbType {
+        TrbType(((self.control >> TRB_TYPE_SHIFT) & TRB_TYPE_MASK) as u8)
+    }
+
+    pub fn cycle(&self) -> bool {
+        self.control & TRB_CYCLE != 0
+    }
+
+    pub fn flag(&self, flag: u32) -> bool {
+        self.control & flag != 0
+    }
+
+    /// The slot ID, for commands and events that target a slot.
+    pub fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    /// The endpoint ID (device context index), for commands and events that
+    /// target an endpoint.
+    pub fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+
+    /// The transfer length, for transfer TRBs.
+    pub fn transfer_length(&self) -> u32 {
+        self.status & TRB_TRANSFER_LENGTH_MASK
+    }
+
+    /// The transfer type, for setup stage TRBs.
+    pub fn transfer_type(&self) -> u8 {
+        ((self.control >> 16) & 3) as u8
+    }
+
+    pub fn new(trb_type: TrbType, parameter: u64, status: u32, control: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: (control & !(TRB_TYPE_MASK << TRB_TYPE_SHIFT))
+                | ((trb_type.0 as u32) << TRB_TYPE_SHIFT),
+        }
+    }
+}
+
+/// Setup stage transfer types.
+pub const TRANSFER_TYPE_NO_DATA: u8 = 0;
+pub const TRANSFER_TYPE_OUT: u8 = 2;
+pub const TRANSFER_TYPE_IN: u8 = 3;
+
+open_enum! {
+    #[derive(Inspect)]
+    #[inspect(debug)]
+    pub enum TrbType: u8 {
+        NORMAL = 1,
+        SETUP_STAGE = 2,
+        DATA_STAGE = 3,
+        STATUS_STAGE = 4,
+        ISOCH = 5,
+        LINK = 6,
+        EVENT_DATA = 7,
+        NO_OP = 8,
+        ENABLE_SLOT_COMMAND = 9,
+        DISABLE_SLOT_COMMAND = 10,
+        ADDRESS_DEVICE_COMMAND = 11,
+        CONFIGURE_ENDPOINT_COMMAND = 12,
+        EVALUATE_CONTEXT_COMMAND = 13,
+        RESET_ENDPOINT_COMMAND = 14,
+        STOP_ENDPOINT_COMMAND = 15,
+        SET_TR_DEQUEUE_POINTER_COMMAND = 16,
+        RESET_DEVICE_COMMAND = 17,
+        FORCE_EVENT_COMMAND = 18,
+        NEGOTIATE_BANDWIDTH_COMMAND = 19,
+        SET_LATENCY_TOLERANCE_VALUE_COMMAND = 20,
+        GET_PORT_BANDWIDTH_COMMAND = 21,
+        FORCE_HEADER_COMMAND = 22,
+        NO_OP_COMMAND = 23,
+        GET_EXTENDED_PROPERTY_COMMAND = 24,
+        SET_EXTENDED_PROPERTY_COMMAND = 25,
+        TRANSFER_EVENT = 32,
+        COMMAND_COMPLETION_EVENT = 33,
+        PORT_STATUS_CHANGE_EVENT = 34,
+        BANDWIDTH_REQUEST_EVENT = 35,
+        DOORBELL_EVENT = 36,
+        HOST_CONTROLLER_EVENT = 37,
+        DEVICE_NOTIFICATION_EVENT = 38,
+        MFINDEX_WRAP_EVENT = 39,
+    }
+}
+
+open_enum! {
+    #[derive(Inspect)]
+    #[inspect(debug)]
+    pub enum CompletionCode: u8 {
+        INVALID = 0,
+        SUCCESS = 1,
+        DATA_BUFFER_ERROR = 2,
+        BABBLE_DETECTED_ERROR = 3,
+        USB_TRANSACTION_ERROR = 4,
+        TRB_ERROR = 5,
+        STALL_ERROR = 6,
+        RESOURCE_ERROR = 7,
+        BANDWIDTH_ERROR = 8,
+        NO_SLOTS_AVAILABLE_ERROR = 9,
+        INVALID_STREAM_TYPE_ERROR = 10,
+        SLOT_NOT_ENABLED_ERROR = 11,
+        ENDPOINT_NOT_ENABLED_ERROR = 12,
+        SHORT_PACKET = 13,
+        RING_UNDERRUN = 14,
+        RING_OVERRUN = 15,
+        VF_EVENT_RING_FULL_ERROR = 16,
+        PARAMETER_ERROR = 17,
+        BANDWIDTH_OVERRUN_ERROR = 18,
+        CONTEXT_STATE_ERROR = 19,
+        NO_PING_RESPONSE_ERROR = 20,
+        EVENT_RING_FULL_ERROR = 21,
+        INCOMPATIBLE_DEVICE_ERROR = 22,
+        MISSED_SERVICE_ERROR = 23,
+        COMMAND_RING_STOPPED = 24,
+        COMMAND_ABORTED = 25,
+        STOPPED = 26,
+        STOPPED_LENGTH_INVALID = 27,
+        STOPPED_SHORT_PACKET = 28,
+    }
+}
+
+/// An event ring segment table entry.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct EventRingSegment {
+    pub base: u64,
+    pub size: u32,
+    pub reserved: u32,
+}
+
+/// The size of a context data structure, when HCCPARAMS1.CSZ is clear.
+pub const CONTEXT_SIZE: u64 = 32;
+
+/// A slot, endpoint, or input control context.
+pub type Context = [u32; 8];
+
+#[bitfield(u32)]
+pub struct SlotContext0 {
+    #[bits(20)]
+    pub route_string: u32,
+    #[bits(4)]
+    pub speed: u8,
+    pub reserved: bool,
+    pub mtt: bool,
+    pub hub: bool,
+    #[bits(5)]
+    pub context_entries: u8,
+}
+
+#[bitfield(u32)]
+pub struct SlotContext1 {
+    pub max_exit_latency: u16,
+    pub root_hub_port_number: u8,
+    pub number_of_ports: u8,
+}
+
+#[bitfield(u32)]
+pub struct SlotContext2 {
+    pub tt_hub_slot_id: u8,
+    pub tt_port_number: u8,
+    #[bits(2)]
+    pub ttt: u8,
+    #[bits(4)]
+    pub reserved: u8,
+    #[bits(10)]
+    pub interrupter_target: u16,
+}
+
+#[bitfield(u32)]
+pub struct SlotContext3 {
+    pub usb_device_address: u8,
+    #[bits(19)]
+    pub reserved: u32,
+    #[bits(5)]
+    pub slot_state: u8,
+}
+
+open_enum! {
+    #[derive(Inspect)]
+    #[inspect(debug)]
+    pub enum SlotState: u8 {
+        DISABLED_ENABLED = 0,
+        DEFAULT = 1,
+        ADDRESSED = 2,
+        CONFIGURED = 3,
+    }
+}
+
+#[bitfield(u32)]
+pub struct EndpointContext0 {
+    #[bits(3)]
+    pub endpoint_state: u8,
+    #[bits(5)]
+    pub reserved: u8,
+    #[bits(2)]
+    pub mult: u8,
+    #[bits(5)]
+    pub max_primary_streams: u8,
+    pub linear_stream_array: bool,
+    pub interval: u8,
+    pub max_esit_payload_hi: u8,
+}
+
+#[bitfield(u32)]
+pub struct EndpointContext1 {
+    pub reserved: bool,
+    #[bits(2)]
+    pub error_count: u8,
+    #[bits(3)]
+    pub endpoint_type: u8,
+    pub reserved2: bool,
+    pub host_initiate_disable: bool,
+    pub max_burst_size: u8,
+    pub max_packet_size: u16,
+}
+
+open_enum! {
+    #[derive(Inspect)]
+    #[inspect(debug)]
+    pub enum EndpointState: u8 {
+        DISABLED = 0,
+        RUNNING = 1,
+        HALTED = 2,
+        STOPPED = 3,
+        ERROR = 4,
+    }
+}
+
+open_enum! {
+    #[derive(Inspect)]
+    #[inspect(debug)]
+    pub enum EndpointType: u8 {
+        NOT_VALID = 0,
+        ISOCH_OUT = 1,
+        BULK_OUT = 2,
+        INTERRUPT_OUT = 3,
+        CONTROL = 4,
+        ISOCH_IN = 5,
+        BULK_IN = 6,
+        INTERRUPT_IN = 7,
+    }
+}
+
+/// The dequeue cycle state bit of the TR dequeue pointer in an endpoint
+/// context.
+pub const ENDPOINT_CONTEXT_DCS: u64 = 1;
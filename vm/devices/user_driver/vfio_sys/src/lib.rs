@@ -16,12 +16,15 @@
 use std::io::BufReader;
 use std::os::unix::prelude::*;
 use std::path::Path;
+use vfio_bindings::bindings::vfio::VFIO_DMA_MAP_FLAG_READ;
+use vfio_bindings::bindings::vfio::VFIO_DMA_MAP_FLAG_WRITE;
 use vfio_bindings::bindings::vfio::VFIO_IRQ_SET_ACTION_TRIGGER;
 use vfio_bindings::bindings::vfio::VFIO_IRQ_SET_DATA_EVENTFD;
 use vfio_bindings::bindings::vfio::VFIO_IRQ_SET_DATA_NONE;
 use vfio_bindings::bindings::vfio::VFIO_PCI_MSIX_IRQ_INDEX;
 use vfio_bindings::bindings::vfio::vfio_device_info;
 use vfio_bindings::bindings::vfio::vfio_group_status;
+use vfio_bindings::bindings::vfio::vfio_iommu_type1_dma_map;
 use vfio_bindings::bindings::vfio::vfio_irq_info;
 use vfio_bindings::bindings::vfio::vfio_irq_set;
 use vfio_bindings::bindings::vfio::vfio_region_info;
@@ -34,6 +37,7 @@ mod ioctl {
     use vfio_bindings::bindings::vfio::VFIO_TYPE;
     use vfio_bindings::bindings::vfio::vfio_device_info;
     use vfio_bindings::bindings::vfio::vfio_group_status;
+    use vfio_bindings::bindings::vfio::vfio_iommu_type1_dma_map;
     use vfio_bindings::bindings::vfio::vfio_irq_info;
     use vfio_bindings::bindings::vfio::vfio_irq_set;
     use vfio_bindings::bindings::vfio::vfio_region_info;
@@ -76,6 +80,15 @@ mod ioctl {
         request_code_none!(VFIO_TYPE, VFIO_BASE + 10),
         vfio_irq_set
     );
+    nix::ioctl_none_bad!(
+        vfio_device_reset,
+        request_code_none!(VFIO_TYPE, VFIO_BASE + 11)
+    );
+    nix::ioctl_write_ptr_bad!(
+        vfio_iommu_map_dma,
+        request_code_none!(VFIO_TYPE, VFIO_BASE + 13),
+        vfio_iommu_type1_dma_map
+    );
     nix::ioctl_write_ptr_bad!(
         vfio_group_set_keep_alive,
         request_code_none!(VFIO_TYPE, VFIO_PRIVATE_BASE),
@@ -106,11 +119,49 @@ pub fn set_iommu(&self, iommu: IommuType) -> anyhow::Result<()> {
         }
         Ok(())
     }
+
+    /// Maps `size` bytes at `vaddr` in this process for device DMA at `iova`.
+    ///
+    /// The pages are pinned until the container is closed.
+    ///
+    /// # Safety
+    /// The caller must ensure that the assigned devices reading and writing
+    /// the memory at any time does not violate memory safety, as with guest
+    /// memory.
+    pub unsafe fn map_dma(&self, iova: u64, vaddr: *mut u8, size: u64) -> anyhow::Result<()> {
+        let map = vfio_iommu_type1_dma_map {
+            argsz: size_of::<vfio_iommu_type1_dma_map>() as u32,
+            flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+            vaddr: vaddr as u64,
+            iova,
+            size,
+        };
+        // SAFETY: The file descriptor is valid and a correctly constructed struct is being passed.
+        // The caller guarantees that device access to the memory is safe.
+        unsafe {
+            ioctl::vfio_iommu_map_dma(self.file.as_raw_fd(), &map)
+                .with_context(|| format!("failed to map {size:#x} bytes for dma at {iova:#x}"))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<File> for Container {
+    fn from(file: File) -> Self {
+        Self { file }
+    }
+}
+
+impl From<Container> for File {
+    fn from(container: Container) -> Self {
+        container.file
+    }
 }
 
 #[repr(u32)]
 pub enum IommuType {
     NoIommu = vfio_bindings::bindings::vfio::VFIO_NOIOMMU_IOMMU,
+    Type1v2 = vfio_bindings::bindings::vfio::VFIO_TYPE1v2_IOMMU,
 }
 
 pub struct Group {
@@ -209,6 +260,18 @@ pub fn set_keep_alive(&self, device_id: &str) -> anyhow::Result<()> {
     }
 }
 
+impl From<File> for Group {
+    fn from(file: File) -> Self {
+        Self { file }
+    }
+}
+
+impl From<Group> for File {
+    fn from(group: Group) -> Self {
+        group.file
+    }
+}
+
 #[bitfield(u32)]
 pub struct GroupStatus {
     pub viable: bool,
@@ -231,8 +294,8 @@ pub struct DeviceInfo {
 
 #[bitfield(u32)]
 pub struct DeviceFlags {
-    reset: bool,
-    pci: bool,
+    pub reset: bool,
+    pub pci: bool,
     platform: bool,
     amba: bool,
     ccw: bool,
@@ -251,9 +314,9 @@ pub struct RegionInfo {
 
 #[bitfield(u32)]
 pub struct RegionFlags {
-    read: bool,
-    write: bool,
-    mmap: bool,
+    pub read: bool,
+    pub write: bool,
+    pub mmap: bool,
     caps: bool,
 
     #[bits(28)]
@@ -400,6 +463,63 @@ struct VfioIrqSetWithArray {
         }
         Ok(())
     }
+
+    /// Enables MSI-X with one eventfd per vector, or disables it if
+    /// `eventfds` is empty.
+    ///
+    /// Unlike [`Self::map_msix`], this supports any number of vectors.
+    pub fn set_msix_eventfds(&self, eventfds: &[BorrowedFd<'_>]) -> anyhow::Result<()> {
+        // Build the header followed by the fds in a u32 buffer to keep the
+        // header aligned.
+        let header_len = size_of::<vfio_irq_set>() / 4;
+        let mut buf = vec![0u32; header_len + eventfds.len()];
+        let header = vfio_irq_set {
+            argsz: (buf.len() * 4) as u32,
+            flags: VFIO_IRQ_SET_ACTION_TRIGGER
+                | if eventfds.is_empty() {
+                    VFIO_IRQ_SET_DATA_NONE
+                } else {
+                    VFIO_IRQ_SET_DATA_EVENTFD
+                },
+            index: VFIO_PCI_MSIX_IRQ_INDEX,
+            start: 0,
+            count: eventfds.len() as u32,
+            data: Default::default(),
+        };
+        for (fd, slot) in eventfds.iter().zip(&mut buf[header_len..]) {
+            *slot = fd.as_raw_fd() as u32;
+        }
+        // SAFETY: The buffer is large enough and sufficiently aligned for the
+        // header, the file descriptor is valid, and the buffer then holds a
+        // correctly constructed header followed by `count` fds.
+        unsafe {
+            buf.as_mut_ptr().cast::<vfio_irq_set>().write(header);
+            ioctl::vfio_device_set_irqs(self.file.as_raw_fd(), buf.as_ptr().cast())
+                .context("failed to set msi-x eventfds")?;
+        }
+        Ok(())
+    }
+
+    /// Resets the device.
+    pub fn reset(&self) -> anyhow::Result<()> {
+        // SAFETY: The file descriptor is valid.
+        unsafe {
+            ioctl::vfio_device_reset(self.file.as_raw_fd()).context("failed to reset device")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<File> for Device {
+    fn from(file: File) -> Self {
+        Self { file }
+    }
+}
+
+impl From<Device> for File {
+    fn from(device: Device) -> Self {
+        device.file
+    }
 }
 
 impl AsRef<File> for Device {
@@ -490,6 +490,16 @@ pub trait VirtioDevice: Send {
     fn write_registers_u32(&mut self, offset: u16, val: u32);
     fn enable(&mut self, resources: Resources);
     fn disable(&mut self);
+
+    /// Polls for device-initiated changes to the device configuration.
+    ///
+    /// Returns `Poll::Ready` when the configuration has changed and the
+    /// driver needs to be notified. The transport polls this whenever the
+    /// waker in `cx` is woken.
+    fn poll_config_change(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let _ = cx;
+        Poll::Pending
+    }
 }
 
 pub struct QueueResources {
@@ -11,6 +11,7 @@
 use chipset_device::ChipsetDevice;
 use chipset_device::io::IoResult;
 use chipset_device::mmio::MmioIntercept;
+use chipset_device::poll_device::PollDevice;
 use device_emulators::ReadWriteRequestType;
 use device_emulators::read_as_u32_chunks;
 use device_emulators::write_as_u32_chunks;
@@ -20,6 +21,7 @@
 use std::fmt;
 use std::ops::RangeInclusive;
 use std::sync::Arc;
+use std::task::Context;
 use vmcore::device_state::ChangeDeviceState;
 use vmcore::interrupt::Interrupt;
 use vmcore::line_interrupt::LineInterrupt;
@@ -477,6 +479,18 @@ impl ChipsetDevice for VirtioMmioDevice {
     fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
         Some(self)
     }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for VirtioMmioDevice {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        while self.device.poll_config_change(cx).is_ready() {
+            self.update_config_generation();
+        }
+    }
 }
 
 impl SaveRestore for VirtioMmioDevice {
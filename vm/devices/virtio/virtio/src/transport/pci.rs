@@ -17,6 +17,7 @@
 use chipset_device::mmio::MmioIntercept;
 use chipset_device::mmio::RegisterMmioIntercept;
 use chipset_device::pci::PciConfigSpace;
+use chipset_device::poll_device::PollDevice;
 use device_emulators::ReadWriteRequestType;
 use device_emulators::read_as_u32_chunks;
 use device_emulators::write_as_u32_chunks;
@@ -40,6 +41,7 @@
 use pci_core::spec::hwid::Subclass;
 use std::io;
 use std::sync::Arc;
+use std::task::Context;
 use vmcore::device_state::ChangeDeviceState;
 use vmcore::interrupt::Interrupt;
 use vmcore::line_interrupt::LineInterrupt;
@@ -603,6 +605,18 @@ fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
     fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
         Some(self)
     }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for VirtioPciDevice {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        while self.device.poll_config_change(cx).is_ready() {
+            self.update_config_generation();
+        }
+    }
 }
 
 impl SaveRestore for VirtioPciDevice {
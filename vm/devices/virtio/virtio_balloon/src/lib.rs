@@ -0,0 +1,327 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A virtio-balloon device, which lets the host reclaim memory from the guest.
+//!
+//! The host sets a target balloon size, and the guest driver inflates the
+//! balloon by giving pages to the device, which releases their backing memory.
+//! With free page reporting, the guest also reports pages it is not using, so
+//! that memory can be reclaimed from idle guests without a target being set.
+
+#![forbid(unsafe_code)]
+
+use async_trait::async_trait;
+use guestmem::DiscardRam;
+use guestmem::GuestMemory;
+use memory_range::MemoryRange;
+use pal_async::task::Spawn;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use task_control::TaskControl;
+use virtio::DeviceTraits;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueueCallbackWork;
+use virtio::VirtioQueueState;
+use virtio::VirtioQueueWorker;
+use virtio::VirtioQueueWorkerContext;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+const VIRTIO_DEVICE_TYPE_BALLOON: u16 = 5;
+
+const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u64 = 1 << 2;
+const VIRTIO_BALLOON_F_REPORTING: u64 = 1 << 5;
+
+/// The balloon operates on 4KB pages, regardless of the guest's page size.
+pub const BALLOON_PAGE_SIZE: u64 = 4096;
+
+/// The queues, in order. The reporting queue follows the deflate queue since
+/// the stats and free page hinting queues are not offered.
+#[derive(Copy, Clone, Debug)]
+enum QueueKind {
+    Inflate,
+    Deflate,
+    Reporting,
+}
+
+const QUEUES: [QueueKind; 3] = [QueueKind::Inflate, QueueKind::Deflate, QueueKind::Reporting];
+
+/// A virtio-balloon device.
+pub struct VirtioBalloonDevice {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    ram: Arc<Ram>,
+    state: Arc<Mutex<BalloonState>>,
+    target: mesh::Receiver<u32>,
+    workers: Vec<TaskControl<VirtioQueueWorker, VirtioQueueState>>,
+    exit_event: event_listener::Event,
+}
+
+#[derive(Default)]
+struct BalloonState {
+    /// The number of pages the host wants in the balloon.
+    num_pages: u32,
+    /// The number of pages the guest reports are in the balloon.
+    actual: u32,
+}
+
+/// The guest RAM that pages can be released from.
+struct Ram {
+    ranges: Vec<MemoryRange>,
+    discard: Box<dyn DiscardRam>,
+}
+
+impl Ram {
+    fn discard(&self, range: MemoryRange) {
+        for ram in &self.ranges {
+            let range = range.intersection(ram);
+            if range.is_empty() {
+                continue;
+            }
+            if let Err(err) = self.discard.discard_ram(range.start(), range.len()) {
+                tracing::debug!(
+                    %range,
+                    error = &err as &dyn std::error::Error,
+                    "failed to discard balloon memory"
+                );
+            }
+        }
+    }
+
+    /// Discards the whole pages within the `len` bytes at `addr`.
+    fn discard_bytes(&self, addr: u64, len: u64) {
+        let start = addr.checked_next_multiple_of(BALLOON_PAGE_SIZE);
+        let end = addr
+            .checked_add(len)
+            .map(|end| end & !(BALLOON_PAGE_SIZE - 1));
+        if let (Some(start), Some(end)) = (start, end) {
+            if start < end {
+                self.discard(MemoryRange::new(start..end));
+            }
+        }
+    }
+}
+
+impl VirtioBalloonDevice {
+    /// Creates a new device that releases pages within the guest RAM `ranges`
+    /// via `discard`.
+    ///
+    /// The target balloon size, in [`BALLOON_PAGE_SIZE`] pages, starts at zero
+    /// and is updated by sending new targets over `target`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        ranges: Vec<MemoryRange>,
+        discard: Box<dyn DiscardRam>,
+        target: mesh::Receiver<u32>,
+    ) -> Self {
+        Self {
+            driver: driver_source.simple(),
+            memory,
+            ram: Arc::new(Ram { ranges, discard }),
+            state: Default::default(),
+            target,
+            workers: Vec::new(),
+            exit_event: event_listener::Event::new(),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioBalloonDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: VIRTIO_DEVICE_TYPE_BALLOON,
+            device_features: VIRTIO_BALLOON_F_DEFLATE_ON_OOM | VIRTIO_BALLOON_F_REPORTING,
+            max_queues: QUEUES.len() as u16,
+            // num_pages, actual, free_page_hint_cmd_id, poison_val
+            device_register_length: 16,
+            shared_memory: Default::default(),
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        let state = self.state.lock();
+        match offset {
+            0 => state.num_pages,
+            4 => state.actual,
+            _ => 0,
+        }
+    }
+
+    fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            4 => self.state.lock().actual = val,
+            _ => tracing::warn!(offset, val, "unexpected virtio-balloon config write"),
+        }
+    }
+
+    fn enable(&mut self, resources: Resources) {
+        assert!(self.workers.is_empty());
+        for (kind, queue) in QUEUES.into_iter().zip(resources.queues) {
+            if !queue.params.enable {
+                continue;
+            }
+            let worker = BalloonWorker {
+                kind,
+                mem: self.memory.clone(),
+                ram: self.ram.clone(),
+            };
+            let worker = VirtioQueueWorker::new(self.driver.clone(), Box::new(worker));
+            self.workers.push(worker.into_running_task(
+                format!("virtio-balloon-{kind:?}"),
+                self.memory.clone(),
+                resources.features,
+                queue,
+                self.exit_event.listen(),
+            ));
+        }
+    }
+
+    fn disable(&mut self) {
+        self.exit_event.notify(usize::MAX);
+        let mut workers = std::mem::take(&mut self.workers);
+        self.driver
+            .spawn("shutdown-virtio-balloon-queues".to_owned(), async move {
+                for worker in &mut workers {
+                    worker.stop().await;
+                }
+            })
+            .detach();
+    }
+
+    fn poll_config_change(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut changed = false;
+        while let Poll::Ready(Ok(num_pages)) = self.target.poll_recv(cx) {
+            self.state.lock().num_pages = num_pages;
+            changed = true;
+        }
+        if changed {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct BalloonWorker {
+    kind: QueueKind,
+    mem: GuestMemory,
+    ram: Arc<Ram>,
+}
+
+impl BalloonWorker {
+    /// Releases the pages whose page frame numbers are in `pfns`.
+    fn inflate(&self, pfns: &[u8]) {
+        let mut pfns = pfns
+            .chunks_exact(4)
+            .map(|pfn| u32::from_le_bytes(pfn.try_into().unwrap()) as u64)
+            .collect::<Vec<_>>();
+        pfns.sort_unstable();
+
+        // Discard runs of contiguous pages together.
+        let mut run: Option<(u64, u64)> = None;
+        for pfn in pfns {
+            match &mut run {
+                Some((_, end)) if *end == pfn => *end += 1,
+                _ => {
+                    if let Some((start, end)) = run.replace((pfn, pfn + 1)) {
+                        self.discard_pages(start, end);
+                    }
+                }
+            }
+        }
+        if let Some((start, end)) = run {
+            self.discard_pages(start, end);
+        }
+    }
+
+    fn discard_pages(&self, start: u64, end: u64) {
+        self.ram.discard(MemoryRange::from_4k_gpn_range(start..end));
+    }
+}
+
+#[async_trait]
+impl VirtioQueueWorkerContext for BalloonWorker {
+    async fn process_work(&mut self, work: anyhow::Result<VirtioQueueCallbackWork>) -> bool {
+        let mut work = match work {
+            Ok(work) => work,
+            Err(err) => {
+                tracing::error!(err = err.as_ref() as &dyn std::error::Error, "queue error");
+                return false;
+            }
+        };
+
+        match self.kind {
+            QueueKind::Inflate => {
+                let mut pfns = vec![0; work.get_payload_length(false) as usize];
+                match work.read(&self.mem, &mut pfns) {
+                    Ok(n) => self.inflate(&pfns[..n]),
+                    Err(err) => {
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            "invalid descriptor"
+                        );
+                    }
+                }
+            }
+            // The pages are backed again on demand when the guest touches
+            // them, so there is nothing to do.
+            QueueKind::Deflate => {}
+            QueueKind::Reporting => {
+                // Each buffer is a range of free memory.
+                for payload in work.payload.iter().filter(|p| p.writeable) {
+                    self.ram
+                        .discard_bytes(payload.address, payload.length.into());
+                }
+            }
+        }
+        work.complete(0);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDiscard(Arc<Mutex<Vec<(u64, u64)>>>);
+
+    impl DiscardRam for TestDiscard {
+        fn discard_ram(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+            self.0.lock().push((gpa, len));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn inflate_coalesces_pages() {
+        let discarded = Arc::new(Mutex::new(Vec::new()));
+        let worker = BalloonWorker {
+            kind: QueueKind::Inflate,
+            mem: GuestMemory::empty(),
+            ram: Arc::new(Ram {
+                ranges: vec![
+                    MemoryRange::new(0..0x4000),
+                    MemoryRange::new(0x10000..0x20000),
+                ],
+                discard: Box::new(TestDiscard(discarded.clone())),
+            }),
+        };
+
+        let pfns = [0x11u32, 2, 0x10, 3, 4, 0x12, 0x30];
+        let pfns = pfns
+            .iter()
+            .flat_map(|pfn| pfn.to_le_bytes())
+            .collect::<Vec<_>>();
+        worker.inflate(&pfns);
+
+        // Page 4 and page 0x30 are outside RAM and are ignored.
+        assert_eq!(
+            discarded.lock().as_slice(),
+            [(0x2000, 0x2000), (0x10000, 0x3000)]
+        );
+    }
+}
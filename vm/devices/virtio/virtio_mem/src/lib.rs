@@ -0,0 +1,405 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A virtio-mem device, which lets guest RAM be grown (and shrunk) at runtime.
+//!
+//! The device owns a region of guest physical address space beyond the end of
+//! the VM's boot-time RAM. The host sets a requested size, and the guest driver
+//! plugs or unplugs memory blocks within the region until the plugged size
+//! matches it.
+
+#![forbid(unsafe_code)]
+
+mod spec;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use guestmem::MappableGuestMemory;
+use guestmem::MappedMemoryRegion;
+use guestmem::MemoryMapper;
+use pal_async::task::Spawn;
+use parking_lot::Mutex;
+use spec::*;
+use std::ops::Range;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use task_control::TaskControl;
+use virtio::DeviceTraits;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueueCallbackWork;
+use virtio::VirtioQueueState;
+use virtio::VirtioQueueWorker;
+use virtio::VirtioQueueWorkerContext;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The size of the memory blocks the guest plugs and unplugs.
+pub const BLOCK_SIZE: u64 = 2 * 1024 * 1024;
+
+/// A virtio-mem device.
+pub struct VirtioMemDevice {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    state: Arc<Mutex<MemoryState>>,
+    resize: mesh::Receiver<u64>,
+    worker: Option<TaskControl<VirtioQueueWorker, VirtioQueueState>>,
+    exit_event: event_listener::Event,
+    _control: Box<dyn MappableGuestMemory>,
+}
+
+struct MemoryState {
+    region: Arc<dyn MappedMemoryRegion>,
+    backing: sparse_mmap::Mappable,
+    addr: u64,
+    requested_size: u64,
+    plugged: Vec<bool>,
+}
+
+impl VirtioMemDevice {
+    /// Creates a new device managing `region_size` bytes of memory at guest
+    /// physical address `addr`.
+    ///
+    /// The requested size starts at zero. Sizes sent over `resize` change the
+    /// requested size, and must be multiples of [`BLOCK_SIZE`] no larger than
+    /// `region_size`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        mapper: &dyn MemoryMapper,
+        addr: u64,
+        region_size: u64,
+        resize: mesh::Receiver<u64>,
+    ) -> anyhow::Result<Self> {
+        assert!(addr % BLOCK_SIZE == 0 && region_size % BLOCK_SIZE == 0);
+        let len = region_size.try_into().context("region too big")?;
+        let backing =
+            sparse_mmap::alloc_shared_memory(len).context("failed to allocate backing memory")?;
+        let (mut control, region) = mapper
+            .new_region(len, "virtio-mem".into())
+            .context("failed to create memory region")?;
+        control
+            .map_to_guest(addr, true)
+            .context("failed to map memory region")?;
+
+        Ok(Self {
+            driver: driver_source.simple(),
+            memory,
+            state: Arc::new(Mutex::new(MemoryState {
+                region,
+                backing,
+                addr,
+                requested_size: 0,
+                plugged: vec![false; (region_size / BLOCK_SIZE) as usize],
+            })),
+            resize,
+            worker: None,
+            exit_event: event_listener::Event::new(),
+            _control: control,
+        })
+    }
+}
+
+impl MemoryState {
+    fn region_size(&self) -> u64 {
+        self.plugged.len() as u64 * BLOCK_SIZE
+    }
+
+    fn plugged_size(&self) -> u64 {
+        self.plugged.iter().filter(|&&plugged| plugged).count() as u64 * BLOCK_SIZE
+    }
+
+    fn config(&self) -> VirtioMemConfig {
+        VirtioMemConfig {
+            block_size: BLOCK_SIZE.into(),
+            node_id: 0u16.into(),
+            padding: [0; 6],
+            addr: self.addr.into(),
+            region_size: self.region_size().into(),
+            usable_region_size: self.region_size().into(),
+            plugged_size: self.plugged_size().into(),
+            requested_size: self.requested_size.into(),
+        }
+    }
+
+    /// Returns the block indexes for the request, or `None` if the request
+    /// is outside the usable region.
+    fn blocks(&self, req: &VirtioMemRequest) -> Option<Range<usize>> {
+        let addr = req.addr.get().checked_sub(self.addr)?;
+        let count = req.nb_blocks.get() as usize;
+        if addr % BLOCK_SIZE != 0 || count == 0 {
+            return None;
+        }
+        let start = (addr / BLOCK_SIZE) as usize;
+        let end = start.checked_add(count)?;
+        (end <= self.plugged.len()).then_some(start..end)
+    }
+
+    fn set_plugged(&mut self, blocks: Range<usize>, plugged: bool) -> std::io::Result<()> {
+        let offset = blocks.start * BLOCK_SIZE as usize;
+        let len = blocks.len() * BLOCK_SIZE as usize;
+        if plugged {
+            self.region
+                .map(offset, &self.backing, offset as u64, len, true)?;
+        } else {
+            // TODO: release the backing memory to the host.
+            self.region.unmap(offset, len)?;
+        }
+        self.plugged[blocks].fill(plugged);
+        Ok(())
+    }
+
+    fn handle_request(&mut self, req: &VirtioMemRequest) -> VirtioMemResponse {
+        let mut resp = VirtioMemResponse::new_zeroed();
+        let result = match req.ty.get() {
+            VIRTIO_MEM_REQ_PLUG => match self.blocks(req) {
+                Some(blocks) if !self.plugged[blocks.clone()].contains(&true) => {
+                    if self.plugged_size() + blocks.len() as u64 * BLOCK_SIZE > self.requested_size
+                    {
+                        VIRTIO_MEM_RESP_NACK
+                    } else {
+                        self.set_plugged(blocks, true)
+                            .map_or(VIRTIO_MEM_RESP_ERROR, |()| VIRTIO_MEM_RESP_ACK)
+                    }
+                }
+                _ => VIRTIO_MEM_RESP_ERROR,
+            },
+            VIRTIO_MEM_REQ_UNPLUG => match self.blocks(req) {
+                Some(blocks) if !self.plugged[blocks.clone()].contains(&false) => self
+                    .set_plugged(blocks, false)
+                    .map_or(VIRTIO_MEM_RESP_ERROR, |()| VIRTIO_MEM_RESP_ACK),
+                _ => VIRTIO_MEM_RESP_ERROR,
+            },
+            VIRTIO_MEM_REQ_UNPLUG_ALL => self
+                .set_plugged(0..self.plugged.len(), false)
+                .map_or(VIRTIO_MEM_RESP_ERROR, |()| VIRTIO_MEM_RESP_ACK),
+            VIRTIO_MEM_REQ_STATE => match self.blocks(req) {
+                Some(blocks) => {
+                    let blocks = &self.plugged[blocks];
+                    resp.state = if !blocks.contains(&false) {
+                        VIRTIO_MEM_STATE_PLUGGED
+                    } else if !blocks.contains(&true) {
+                        VIRTIO_MEM_STATE_UNPLUGGED
+                    } else {
+                        VIRTIO_MEM_STATE_MIXED
+                    }
+                    .into();
+                    VIRTIO_MEM_RESP_ACK
+                }
+                None => VIRTIO_MEM_RESP_ERROR,
+            },
+            ty => {
+                tracing::warn!(ty, "unsupported virtio-mem request");
+                VIRTIO_MEM_RESP_ERROR
+            }
+        };
+        resp.ty = result.into();
+        resp
+    }
+}
+
+impl VirtioDevice for VirtioMemDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: VIRTIO_DEVICE_TYPE_MEM,
+            device_features: 0,
+            max_queues: 1,
+            device_register_length: size_of::<VirtioMemConfig>() as u32,
+            shared_memory: Default::default(),
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        let config = self.state.lock().config();
+        let offset = offset as usize;
+        config
+            .as_bytes()
+            .get(offset..offset + 4)
+            .map_or(0, |v| u32::from_le_bytes(v.try_into().unwrap()))
+    }
+
+    fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        tracing::warn!(offset, val, "write to read-only virtio-mem config");
+    }
+
+    fn enable(&mut self, mut resources: Resources) {
+        assert!(self.worker.is_none());
+        if !resources.queues[0].params.enable {
+            return;
+        }
+
+        let worker = MemWorker {
+            mem: self.memory.clone(),
+            state: self.state.clone(),
+        };
+        let worker = VirtioQueueWorker::new(self.driver.clone(), Box::new(worker));
+        self.worker = Some(worker.into_running_task(
+            "virtio-mem-queue".to_string(),
+            self.memory.clone(),
+            resources.features,
+            resources.queues.remove(0),
+            self.exit_event.listen(),
+        ));
+    }
+
+    fn disable(&mut self) {
+        self.exit_event.notify(usize::MAX);
+        if let Some(mut worker) = self.worker.take() {
+            self.driver
+                .spawn("shutdown-virtio-mem-queue".to_owned(), async move {
+                    worker.stop().await;
+                })
+                .detach();
+        }
+    }
+
+    fn poll_config_change(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut changed = false;
+        while let Poll::Ready(Ok(size)) = self.resize.poll_recv(cx) {
+            let mut state = self.state.lock();
+            if size % BLOCK_SIZE != 0 || size > state.region_size() {
+                tracing::warn!(size, "invalid virtio-mem requested size");
+                continue;
+            }
+            state.requested_size = size;
+            changed = true;
+        }
+        if changed {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct MemWorker {
+    mem: GuestMemory,
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[async_trait]
+impl VirtioQueueWorkerContext for MemWorker {
+    async fn process_work(&mut self, work: anyhow::Result<VirtioQueueCallbackWork>) -> bool {
+        let mut work = match work {
+            Ok(work) => work,
+            Err(err) => {
+                tracing::error!(err = err.as_ref() as &dyn std::error::Error, "queue error");
+                return false;
+            }
+        };
+
+        let mut req = VirtioMemRequest::new_zeroed();
+        let resp = match work.read(&self.mem, req.as_mut_bytes()) {
+            Ok(n) if n == size_of::<VirtioMemRequest>() => self.state.lock().handle_request(&req),
+            Ok(n) => {
+                tracing::error!(n, "short virtio-mem request");
+                let mut resp = VirtioMemResponse::new_zeroed();
+                resp.ty = VIRTIO_MEM_RESP_ERROR.into();
+                resp
+            }
+            Err(err) => {
+                tracing::error!(error = &err as &dyn std::error::Error, "invalid descriptor");
+                let mut resp = VirtioMemResponse::new_zeroed();
+                resp.ty = VIRTIO_MEM_RESP_ERROR.into();
+                resp
+            }
+        };
+        let _ = work.write(&self.mem, resp.as_bytes());
+        work.complete(size_of::<VirtioMemResponse>() as u32);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sparse_mmap::AsMappableRef;
+
+    struct NullRegion;
+
+    impl MappedMemoryRegion for NullRegion {
+        fn map(
+            &self,
+            _offset: usize,
+            _section: &dyn AsMappableRef,
+            _file_offset: u64,
+            _len: usize,
+            _writable: bool,
+        ) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn unmap(&self, _offset: usize, _len: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const ADDR: u64 = 0x1_0000_0000;
+
+    fn request(ty: u16, block: u64, nb_blocks: u16) -> VirtioMemRequest {
+        VirtioMemRequest {
+            ty: ty.into(),
+            padding: [0; 6],
+            addr: (ADDR + block * BLOCK_SIZE).into(),
+            nb_blocks: nb_blocks.into(),
+            padding2: [0; 6],
+        }
+    }
+
+    #[test]
+    fn plug_unplug() {
+        let mut state = MemoryState {
+            region: Arc::new(NullRegion),
+            backing: sparse_mmap::alloc_shared_memory(0x1000).unwrap(),
+            addr: ADDR,
+            requested_size: 2 * BLOCK_SIZE,
+            plugged: vec![false; 4],
+        };
+
+        let ty = |state: &mut MemoryState, req| state.handle_request(&req).ty.get();
+        let block_state = |state: &mut MemoryState, block, nb_blocks| {
+            let resp = state.handle_request(&request(VIRTIO_MEM_REQ_STATE, block, nb_blocks));
+            assert_eq!(resp.ty.get(), VIRTIO_MEM_RESP_ACK);
+            resp.state.get()
+        };
+
+        // Plugging beyond the requested size is refused.
+        assert_eq!(
+            ty(&mut state, request(VIRTIO_MEM_REQ_PLUG, 0, 3)),
+            VIRTIO_MEM_RESP_NACK
+        );
+        assert_eq!(
+            ty(&mut state, request(VIRTIO_MEM_REQ_PLUG, 1, 2)),
+            VIRTIO_MEM_RESP_ACK
+        );
+        assert_eq!(state.plugged_size(), 2 * BLOCK_SIZE);
+        assert_eq!(block_state(&mut state, 1, 2), VIRTIO_MEM_STATE_PLUGGED);
+        assert_eq!(block_state(&mut state, 0, 2), VIRTIO_MEM_STATE_MIXED);
+        assert_eq!(block_state(&mut state, 3, 1), VIRTIO_MEM_STATE_UNPLUGGED);
+
+        // Out of range and partially plugged requests fail.
+        assert_eq!(
+            ty(&mut state, request(VIRTIO_MEM_REQ_STATE, 3, 2)),
+            VIRTIO_MEM_RESP_ERROR
+        );
+        assert_eq!(
+            ty(&mut state, request(VIRTIO_MEM_REQ_UNPLUG, 0, 2)),
+            VIRTIO_MEM_RESP_ERROR
+        );
+
+        assert_eq!(
+            ty(&mut state, request(VIRTIO_MEM_REQ_UNPLUG, 2, 1)),
+            VIRTIO_MEM_RESP_ACK
+        );
+        assert_eq!(state.plugged_size(), BLOCK_SIZE);
+        assert_eq!(
+            ty(&mut state, request(VIRTIO_MEM_REQ_UNPLUG_ALL, 0, 0)),
+            VIRTIO_MEM_RESP_ACK
+        );
+        assert_eq!(state.plugged_size(), 0);
+    }
+}
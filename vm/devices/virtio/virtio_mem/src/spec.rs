@@ -0,0 +1,58 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! virtio-mem definitions, from section 5.15 of the virtio specification.
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::little_endian::U16 as U16LE;
+use zerocopy::little_endian::U64 as U64LE;
+
+pub const VIRTIO_DEVICE_TYPE_MEM: u16 = 24;
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioMemConfig {
+    pub block_size: U64LE,
+    pub node_id: U16LE,
+    pub padding: [u8; 6],
+    pub addr: U64LE,
+    pub region_size: U64LE,
+    pub usable_region_size: U64LE,
+    pub plugged_size: U64LE,
+    pub requested_size: U64LE,
+}
+
+pub const VIRTIO_MEM_REQ_PLUG: u16 = 0;
+pub const VIRTIO_MEM_REQ_UNPLUG: u16 = 1;
+pub const VIRTIO_MEM_REQ_UNPLUG_ALL: u16 = 2;
+pub const VIRTIO_MEM_REQ_STATE: u16 = 3;
+
+/// The plug, unplug, and state requests all share the same layout.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioMemRequest {
+    pub ty: U16LE,
+    pub padding: [u8; 6],
+    pub addr: U64LE,
+    pub nb_blocks: U16LE,
+    pub padding2: [u8; 6],
+}
+
+pub const VIRTIO_MEM_RESP_ACK: u16 = 0;
+pub const VIRTIO_MEM_RESP_NACK: u16 = 1;
+pub const VIRTIO_MEM_RESP_ERROR: u16 = 3;
+
+pub const VIRTIO_MEM_STATE_PLUGGED: u16 = 0;
+pub const VIRTIO_MEM_STATE_UNPLUGGED: u16 = 1;
+pub const VIRTIO_MEM_STATE_MIXED: u16 = 2;
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioMemResponse {
+    pub ty: U16LE,
+    pub padding: [u8; 6],
+    pub state: U16LE,
+}
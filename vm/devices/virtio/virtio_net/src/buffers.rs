@@ -1,10 +1,15 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use crate::NetworkFeatures;
 use crate::VirtioNetHeader;
+use crate::VirtioNetHeaderFlags;
+use crate::VirtioNetHeaderGso;
+use crate::VirtioNetHeaderGsoProtocol;
 use crate::header_size;
 use guestmem::GuestMemory;
 use net_backend::BufferAccess;
+use net_backend::L3Protocol;
 use net_backend::RxBufferSegment;
 use net_backend::RxId;
 use net_backend::RxMetadata;
@@ -24,15 +29,17 @@ struct RxPacket {
 #[derive(Clone)]
 pub struct VirtioWorkPool {
     mem: GuestMemory,
+    features: NetworkFeatures,
     rx_packets: Arc<Vec<Mutex<RxPacket>>>,
     buffer_segments: Vec<RxBufferSegment>,
 }
 
 impl VirtioWorkPool {
     /// Create a new instance.
-    pub fn new(mem: GuestMemory, queue_size: u16) -> Self {
+    pub fn new(mem: GuestMemory, features: NetworkFeatures, queue_size: u16) -> Self {
         Self {
             mem,
+            features,
             rx_packets: Arc::new(
                 (0..queue_size)
                     .map(|_| Mutex::new(RxPacket::default()))
@@ -106,20 +113,34 @@ fn guest_addresses(&mut self, id: RxId) -> &[RxBufferSegment] {
     fn capacity(&self, id: RxId) -> u32 {
         let locked_packet = self.rx_packets[id.0 as usize].lock();
         let work = locked_packet.work.as_ref().expect("invalid buffer index");
-        work.get_payload_length(true) as u32
+        // The virtio net header precedes the packet data.
+        (work.get_payload_length(true) as u32).saturating_sub(header_size() as u32)
     }
 
     fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
         assert_eq!(metadata.offset, 0);
         assert!(metadata.len > 0);
 
-        // let flags = if let RxChecksumState::Good = metadata.ip_checksum {
-        //     VirtioNetHeaderFlags::VIRTIO_NET_HDR_F_DATA_VALID.bits()
-        // } else {
-        //     0
-        // };
+        let flags = VirtioNetHeaderFlags::new()
+            .with_data_valid(self.features.guest_csum() && metadata.l4_checksum.is_valid());
+        let mut gso = VirtioNetHeaderGso::new();
+        let mut hdr_len = 0;
+        let mut gso_size = 0;
+        if let Some(lro) = metadata.lro.filter(|_| self.lro_enabled()) {
+            gso.set_protocol(match lro.l3_protocol {
+                L3Protocol::Ipv4 => VirtioNetHeaderGsoProtocol::TCPV4,
+                L3Protocol::Ipv6 => VirtioNetHeaderGsoProtocol::TCPV6,
+                L3Protocol::Unknown => VirtioNetHeaderGsoProtocol::NONE,
+            });
+            hdr_len = lro.header_len;
+            gso_size = lro.segment_size;
+        }
 
         let virtio_net_header = VirtioNetHeader {
+            flags: flags.into(),
+            gso_type: gso.into(),
+            hdr_len,
+            gso_size,
             num_buffers: 1,
             ..FromZeros::new_zeroed()
         };
@@ -133,4 +154,8 @@ fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
             );
         }
     }
+
+    fn lro_enabled(&self) -> bool {
+        self.features.guest_csum() && self.features.guest_tso4() && self.features.guest_tso6()
+    }
 }
@@ -14,16 +14,19 @@
 use futures::StreamExt;
 use futures_concurrency::future::Race;
 use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
 use inspect::Inspect;
 use inspect::InspectMut;
 use inspect_counters::Counter;
 use inspect_counters::Histogram;
 use net_backend::Endpoint;
 use net_backend::EndpointAction;
+use net_backend::L3Protocol;
 use net_backend::QueueConfig;
 use net_backend::RxId;
 use net_backend::TxId;
 use net_backend::TxMetadata;
+use net_backend::TxOffloadSupport;
 use net_backend::TxSegment;
 use net_backend::TxSegmentType;
 use net_backend_resources::mac_address::MacAddress;
@@ -46,6 +49,7 @@
 use vmcore::vm_task::VmTaskDriver;
 use vmcore::vm_task::VmTaskDriverSource;
 use zerocopy::FromBytes;
+use zerocopy::FromZeros;
 use zerocopy::Immutable;
 use zerocopy::IntoBytes;
 use zerocopy::KnownLayout;
@@ -184,10 +188,24 @@ fn header_size() -> usize {
     offset_of!(VirtioNetHeader, hash_value)
 }
 
+/// The number of bytes to read from the start of a transmit packet to parse
+/// the virtio net header and the Ethernet, IP, and TCP headers.
+const TX_OFFLOAD_HEADER_READ_LEN: usize = 256;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+/// The checksum field offsets within the TCP and UDP headers.
+const TCP_CHECKSUM_OFFSET: u16 = 16;
+const UDP_CHECKSUM_OFFSET: u16 = 6;
+
 struct Adapter {
     driver: VmTaskDriver,
     max_queues: u16,
     tx_fast_completions: bool,
+    tx_offloads: TxOffloadSupport,
+    rx_lro: bool,
     mac_address: MacAddress,
 }
 
@@ -206,10 +224,20 @@ fn drop(&mut self) {}
 
 impl VirtioDevice for Device {
     fn traits(&self) -> DeviceTraits {
-        // TODO: Add network features based on endpoint capabilities (NetworkFeatures::VIRTIO_NET_F_*)
+        let tx_offloads = &self.adapter.tx_offloads;
+        let csum = tx_offloads.tcp && tx_offloads.udp;
+        let tso = csum && tx_offloads.ipv4_header && tx_offloads.tso;
+        let features = NetworkFeatures::new()
+            .with_mac(true)
+            .with_csum(csum)
+            .with_host_tso4(tso)
+            .with_host_tso6(tso)
+            .with_guest_csum(true)
+            .with_guest_tso4(self.adapter.rx_lro)
+            .with_guest_tso6(self.adapter.rx_lro);
         DeviceTraits {
             device_id: 1,
-            device_features: NetworkFeatures::new().with_mac(true).into(),
+            device_features: features.into(),
             max_queues: 2 * self.registers.max_virtqueue_pairs,
             device_register_length: size_of::<NetConfig>() as u32,
             shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
@@ -300,6 +328,8 @@ fn enable(&mut self, resources: Resources) {
                 rx_queue_size,
                 tx_queue: tx_queue.unwrap(),
                 tx_queue_size,
+                features: NetworkFeatures::from(resources.features),
+                mem: self.memory.clone(),
             });
         }
 
@@ -383,6 +413,7 @@ struct QueueStats {
     spurious_wakes: Counter,
     rx_packets: Counter,
     tx_packets: Counter,
+    tx_invalid_offload: Counter,
     tx_packets_per_wake: Histogram<10>,
     rx_packets_per_wake: Histogram<10>,
 }
@@ -395,10 +426,15 @@ struct ActiveState {
 }
 
 impl ActiveState {
-    fn new(mem: GuestMemory, rx_queue_size: u16, tx_queue_size: u16) -> Self {
+    fn new(
+        mem: GuestMemory,
+        features: NetworkFeatures,
+        rx_queue_size: u16,
+        tx_queue_size: u16,
+    ) -> Self {
         Self {
             pending_tx_packets: (0..tx_queue_size).map(|_| None).collect(),
-            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size),
+            pending_rx_packets: VirtioWorkPool::new(mem, features, rx_queue_size),
             data: ProcessingData::new(rx_queue_size, tx_queue_size),
             stats: Default::default(),
         }
@@ -438,6 +474,8 @@ pub fn build(
             driver,
             max_queues,
             tx_fast_completions: endpoint.tx_fast_completions(),
+            tx_offloads: endpoint.tx_offload_support(),
+            rx_lro: endpoint.rx_lro_support(),
             mac_address,
         });
 
@@ -513,6 +551,7 @@ fn insert_worker(&mut self, virtio_state: VirtioState, idx: usize) {
 
         let active_state = ActiveState::new(
             self.memory.clone(),
+            virtio_state.features,
             virtio_state.rx_queue_size,
             virtio_state.tx_queue_size,
         );
@@ -715,6 +754,8 @@ struct VirtioState {
     rx_queue_size: u16,
     tx_queue: VirtioQueue,
     tx_queue_size: u16,
+    features: NetworkFeatures,
+    mem: GuestMemory,
 }
 
 #[derive(Debug, Error)]
@@ -739,6 +780,16 @@ fn from(value: task_control::Cancelled) -> Self {
 enum PacketError {
     #[error("Empty packet")]
     Empty,
+    #[error("failed to read packet headers")]
+    HeaderRead(#[source] GuestMemoryError),
+    #[error("packet headers are truncated")]
+    Truncated,
+    #[error("unsupported checksum offload at offset {0}")]
+    UnsupportedChecksum(u16),
+    #[error("unsupported ethertype {0:#x}")]
+    UnsupportedEthertype(u16),
+    #[error("unsupported segmentation offload {0:#x}")]
+    UnsupportedGso(u8),
 }
 
 struct Worker {
@@ -867,12 +918,22 @@ fn queue_tx_packet(&mut self, mut work: VirtioQueueCallbackWork) -> Result<(), W
             return Err(WorkerError::Packet(PacketError::Empty));
         }
         let idx = work.descriptor_index();
-        segments[0].ty = TxSegmentType::Head(TxMetadata {
+        let mut metadata = TxMetadata {
             id: TxId(idx.into()),
             segment_count: segments.len(),
             len: work.get_payload_length(false) as usize - header_size(),
             ..Default::default()
-        });
+        };
+        if let Err(err) = self.parse_tx_offloads(&work, &mut metadata) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "dropping tx packet with invalid offload request"
+            );
+            self.active_state.stats.tx_invalid_offload.increment();
+            work.complete(0);
+            return Ok(());
+        }
+        segments[0].ty = TxSegmentType::Head(metadata);
         let state = &mut self.active_state;
         state.data.tx_segments.append(&mut segments);
         assert!(state.pending_tx_packets[idx as usize].is_none());
@@ -880,6 +941,81 @@ fn queue_tx_packet(&mut self, mut work: VirtioQueueCallbackWork) -> Result<(), W
         Ok(())
     }
 
+    /// Fills in the offloads requested by the packet's virtio net header.
+    fn parse_tx_offloads(
+        &self,
+        work: &VirtioQueueCallbackWork,
+        metadata: &mut TxMetadata,
+    ) -> Result<(), PacketError> {
+        if !self.virtio_state.features.csum() {
+            return Ok(());
+        }
+        let mut buf = [0; TX_OFFLOAD_HEADER_READ_LEN];
+        let n = work
+            .read(&self.virtio_state.mem, &mut buf)
+            .map_err(PacketError::HeaderRead)?;
+        let (header_bytes, packet) = buf[..n]
+            .split_at_checked(header_size())
+            .ok_or(PacketError::Truncated)?;
+        let mut header = VirtioNetHeader::new_zeroed();
+        header.as_mut_bytes()[..header_size()].copy_from_slice(header_bytes);
+        let flags = VirtioNetHeaderFlags::from(header.flags);
+        let gso = VirtioNetHeaderGso::from(header.gso_type);
+        if !flags.needs_csum() {
+            if gso.protocol() != VirtioNetHeaderGsoProtocol::NONE {
+                return Err(PacketError::UnsupportedGso(header.gso_type));
+            }
+            return Ok(());
+        }
+
+        let read_u16 = |offset: usize| {
+            packet
+                .get(offset..offset + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or(PacketError::Truncated)
+        };
+        let (l2_len, ethertype) = match read_u16(12)? {
+            ETHERTYPE_VLAN => (18, read_u16(16)?),
+            ethertype => (14, ethertype),
+        };
+        let l3_protocol = match ethertype {
+            ETHERTYPE_IPV4 => L3Protocol::Ipv4,
+            ETHERTYPE_IPV6 => L3Protocol::Ipv6,
+            ethertype => return Err(PacketError::UnsupportedEthertype(ethertype)),
+        };
+        let l3_len = header
+            .csum_start
+            .checked_sub(l2_len)
+            .ok_or(PacketError::Truncated)?;
+        metadata.l3_protocol = l3_protocol;
+        metadata.l2_len = l2_len as u8;
+        metadata.l3_len = l3_len;
+        match header.csum_offset {
+            TCP_CHECKSUM_OFFSET => metadata.offload_tcp_checksum = true,
+            UDP_CHECKSUM_OFFSET => metadata.offload_udp_checksum = true,
+            offset => return Err(PacketError::UnsupportedChecksum(offset)),
+        }
+
+        match (gso.protocol(), l3_protocol) {
+            (VirtioNetHeaderGsoProtocol::NONE, _) => {}
+            (VirtioNetHeaderGsoProtocol::TCPV4, L3Protocol::Ipv4)
+            | (VirtioNetHeaderGsoProtocol::TCPV6, L3Protocol::Ipv6)
+                if metadata.offload_tcp_checksum =>
+            {
+                // The TCP data offset is the high nibble of byte 12.
+                let data_offset = *packet
+                    .get(header.csum_start as usize + 12)
+                    .ok_or(PacketError::Truncated)?;
+                metadata.offload_tcp_segmentation = true;
+                metadata.offload_ip_header_checksum = l3_protocol == L3Protocol::Ipv4;
+                metadata.l4_len = (data_offset >> 4) * 4;
+                metadata.max_tcp_segment_size = header.gso_size;
+            }
+            _ => return Err(PacketError::UnsupportedGso(header.gso_type)),
+        }
+        Ok(())
+    }
+
     fn process_virtio_rx(
         &mut self,
         epqueue: &mut dyn net_backend::Queue,
@@ -101,3 +101,30 @@ impl ResourceId<VirtioDeviceHandle> for VirtioNetHandle {
         const ID: &'static str = "virtio-net";
     }
 }
+
+pub mod snd {
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioSoundHandle {
+        pub backend: VirtioSoundBackend,
+    }
+
+    /// Where the audio played by the guest goes.
+    #[derive(MeshPayload)]
+    pub enum VirtioSoundBackend {
+        /// Discard the audio.
+        Null,
+        /// Write the audio to a WAV file.
+        Wav { file: std::fs::File },
+        /// Write raw 16-bit stereo PCM at 48kHz to a pipe, such as the input
+        /// of a host audio player.
+        Pipe { pipe: std::fs::File },
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioSoundHandle {
+        const ID: &'static str = "virtio-snd";
+    }
+}
@@ -0,0 +1,564 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A virtio-snd device with a single PCM output stream.
+//!
+//! The stream plays 16-bit stereo PCM at 48kHz (guests convert other formats
+//! themselves), and the audio goes to an [`AudioSink`], which may discard it,
+//! write it to a file, or pipe it to a host audio player. Buffers are completed at the rate the audio would
+//! play, so that the guest's view of playback progress tracks real time.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+mod spec;
+mod wav;
+
+pub use wav::WavSink;
+
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use pal_async::task::Spawn;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use spec::*;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use task_control::TaskControl;
+use virtio::DeviceTraits;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueueCallbackWork;
+use virtio::VirtioQueueState;
+use virtio::VirtioQueueWorker;
+use virtio::VirtioQueueWorkerContext;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+const CHANNELS: u8 = 2;
+const RATE: u32 = 48000;
+const BYTES_PER_FRAME: u32 = CHANNELS as u32 * 2;
+
+/// The largest control request or PCM info size accepted from the guest.
+const MAX_CONTROL_LEN: usize = 1024;
+
+/// A destination for the audio played by the guest.
+pub trait AudioSink: Send {
+    /// Writes interleaved 16-bit little-endian stereo PCM frames at 48kHz.
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Flushes the audio written so far. Called when playback stops.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An [`AudioSink`] that discards the audio.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _data: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An [`AudioSink`] that writes the raw PCM frames to a pipe, such as the
+/// input of a host audio player.
+///
+/// Writes block while the pipe is full, so a player that consumes the audio
+/// in real time also paces the stream.
+pub struct PipeSink(pub File);
+
+impl AudioSink for PipeSink {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.0.write_all(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// The queues, in order.
+#[derive(Copy, Clone, Debug)]
+enum QueueKind {
+    Control,
+    Event,
+    Tx,
+    Rx,
+}
+
+const QUEUES: [QueueKind; 4] = [
+    QueueKind::Control,
+    QueueKind::Event,
+    QueueKind::Tx,
+    QueueKind::Rx,
+];
+
+/// A virtio-snd device.
+pub struct VirtioSoundDevice {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    stream: Arc<Mutex<Stream>>,
+    workers: Vec<TaskControl<VirtioQueueWorker, VirtioQueueState>>,
+    exit_event: event_listener::Event,
+}
+
+impl VirtioSoundDevice {
+    /// Creates a new device that plays audio to `sink`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        sink: Box<dyn AudioSink>,
+    ) -> Self {
+        Self {
+            driver: driver_source.simple(),
+            memory,
+            stream: Arc::new(Mutex::new(Stream::new(sink))),
+            workers: Vec::new(),
+            exit_event: event_listener::Event::new(),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioSoundDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: VIRTIO_DEVICE_TYPE_SOUND,
+            device_features: 0,
+            max_queues: QUEUES.len() as u16,
+            device_register_length: size_of::<VirtioSoundConfig>() as u32,
+            shared_memory: Default::default(),
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        let config = VirtioSoundConfig {
+            jacks: 0.into(),
+            streams: 1.into(),
+            chmaps: 0.into(),
+        };
+        let offset = offset as usize;
+        config
+            .as_bytes()
+            .get(offset..offset + 4)
+            .map_or(0, |v| u32::from_le_bytes(v.try_into().unwrap()))
+    }
+
+    fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        tracing::warn!(offset, val, "unexpected virtio-snd config write");
+    }
+
+    fn enable(&mut self, resources: Resources) {
+        assert!(self.workers.is_empty());
+        for (kind, queue) in QUEUES.into_iter().zip(resources.queues) {
+            if !queue.params.enable {
+                continue;
+            }
+            let worker = SoundWorker {
+                kind,
+                mem: self.memory.clone(),
+                stream: self.stream.clone(),
+                timer: PolledTimer::new(&self.driver),
+                events: Vec::new(),
+            };
+            let worker = VirtioQueueWorker::new(self.driver.clone(), Box::new(worker));
+            self.workers.push(worker.into_running_task(
+                format!("virtio-snd-{kind:?}"),
+                self.memory.clone(),
+                resources.features,
+                queue,
+                self.exit_event.listen(),
+            ));
+        }
+    }
+
+    fn disable(&mut self) {
+        self.exit_event.notify(usize::MAX);
+        self.stream.lock().reset();
+        let mut workers = std::mem::take(&mut self.workers);
+        self.driver
+            .spawn("shutdown-virtio-snd-queues".to_owned(), async move {
+                for worker in &mut workers {
+                    worker.stop().await;
+                }
+            })
+            .detach();
+    }
+}
+
+/// The state of the PCM stream, per section 5.14.6.6.1 of the specification.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StreamState {
+    Unconfigured,
+    Configured,
+    Prepared,
+    Running,
+}
+
+/// The playback position, used to complete buffers in real time.
+struct Clock {
+    start: Instant,
+    bytes: u64,
+}
+
+impl Clock {
+    /// Returns when the audio written so far will have finished playing.
+    fn deadline(&self) -> Instant {
+        let nanos = self.bytes * 1_000_000_000 / (RATE * BYTES_PER_FRAME) as u64;
+        self.start.saturating_add(Duration::from_nanos(nanos))
+    }
+}
+
+struct Stream {
+    state: StreamState,
+    sink: Box<dyn AudioSink>,
+    clock: Option<Clock>,
+}
+
+fn status(code: u32) -> Vec<u8> {
+    code.to_le_bytes().to_vec()
+}
+
+impl Stream {
+    fn new(sink: Box<dyn AudioSink>) -> Self {
+        Self {
+            state: StreamState::Unconfigured,
+            sink,
+            clock: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stop();
+        self.state = StreamState::Unconfigured;
+    }
+
+    fn stop(&mut self) {
+        self.clock = None;
+        if let Err(err) = self.sink.flush() {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to flush audio"
+            );
+        }
+    }
+
+    /// Handles a control request, returning the response.
+    fn control(&mut self, request: &[u8]) -> Vec<u8> {
+        let Ok((hdr, _)) = VirtioSoundHeader::read_from_prefix(request) else {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        };
+        match hdr.code.get() {
+            VIRTIO_SND_R_PCM_INFO => self.pcm_info(request),
+            VIRTIO_SND_R_PCM_SET_PARAMS => self.set_params(request),
+            code @ (VIRTIO_SND_R_PCM_PREPARE
+            | VIRTIO_SND_R_PCM_RELEASE
+            | VIRTIO_SND_R_PCM_START
+            | VIRTIO_SND_R_PCM_STOP) => self.transition(code, request),
+            // There are no jacks or channel maps to query.
+            VIRTIO_SND_R_JACK_INFO | VIRTIO_SND_R_CHMAP_INFO => status(VIRTIO_SND_S_BAD_MSG),
+            code => {
+                tracelimit::warn_ratelimited!(code, "unsupported virtio-snd request");
+                status(VIRTIO_SND_S_NOT_SUPP)
+            }
+        }
+    }
+
+    fn pcm_info(&self, request: &[u8]) -> Vec<u8> {
+        let Ok((query, _)) = VirtioSoundQueryInfo::read_from_prefix(request) else {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        };
+        let count = query.count.get();
+        let size = query.size.get() as usize;
+        if query
+            .start_id
+            .get()
+            .checked_add(count)
+            .is_none_or(|end| end > 1)
+            || !(size_of::<VirtioSoundPcmInfo>()..=MAX_CONTROL_LEN).contains(&size)
+        {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        }
+        let mut response = status(VIRTIO_SND_S_OK);
+        for _ in 0..count {
+            let info = VirtioSoundPcmInfo {
+                hda_fn_nid: 0.into(),
+                features: 0.into(),
+                formats: (1 << VIRTIO_SND_PCM_FMT_S16).into(),
+                rates: (1 << VIRTIO_SND_PCM_RATE_48000).into(),
+                direction: VIRTIO_SND_D_OUTPUT,
+                channels_min: CHANNELS,
+                channels_max: CHANNELS,
+                padding: [0; 5],
+            };
+            let len = response.len();
+            response.extend_from_slice(info.as_bytes());
+            response.resize(len + size, 0);
+        }
+        response
+    }
+
+    fn set_params(&mut self, request: &[u8]) -> Vec<u8> {
+        let Ok((params, _)) = VirtioSoundPcmSetParams::read_from_prefix(request) else {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        };
+        if params.hdr.stream_id.get() != 0 || self.state == StreamState::Running {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        }
+        if params.features.get() != 0
+            || params.channels != CHANNELS
+            || params.format != VIRTIO_SND_PCM_FMT_S16
+            || params.rate != VIRTIO_SND_PCM_RATE_48000
+        {
+            return status(VIRTIO_SND_S_NOT_SUPP);
+        }
+        self.state = StreamState::Configured;
+        status(VIRTIO_SND_S_OK)
+    }
+
+    fn transition(&mut self, code: u32, request: &[u8]) -> Vec<u8> {
+        let Ok((hdr, _)) = VirtioSoundPcmHeader::read_from_prefix(request) else {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        };
+        if hdr.stream_id.get() != 0 {
+            return status(VIRTIO_SND_S_BAD_MSG);
+        }
+        let state = match (code, self.state) {
+            (VIRTIO_SND_R_PCM_PREPARE, StreamState::Configured | StreamState::Prepared) => {
+                StreamState::Prepared
+            }
+            (VIRTIO_SND_R_PCM_RELEASE, StreamState::Configured | StreamState::Prepared) => {
+                StreamState::Configured
+            }
+            (VIRTIO_SND_R_PCM_START, StreamState::Prepared) => StreamState::Running,
+            (VIRTIO_SND_R_PCM_STOP, StreamState::Running) => {
+                self.stop();
+                StreamState::Prepared
+            }
+            (code, state) => {
+                tracelimit::warn_ratelimited!(code, ?state, "invalid pcm state transition");
+                return status(VIRTIO_SND_S_BAD_MSG);
+            }
+        };
+        self.state = state;
+        status(VIRTIO_SND_S_OK)
+    }
+
+    /// Plays `data`, returning the status and when the audio will have
+    /// finished playing.
+    fn play(&mut self, data: &[u8], now: Instant) -> (u32, Instant) {
+        // Buffers may be queued before the start request is processed.
+        if !matches!(self.state, StreamState::Prepared | StreamState::Running) {
+            return (VIRTIO_SND_S_BAD_MSG, now);
+        }
+        let clock = self.clock.get_or_insert(Clock {
+            start: now,
+            bytes: 0,
+        });
+        // If the guest fell behind, restart the clock rather than completing
+        // the next buffers in a burst.
+        if clock.deadline() < now {
+            *clock = Clock {
+                start: now,
+                bytes: 0,
+            };
+        }
+        clock.bytes += data.len() as u64;
+        let deadline = clock.deadline();
+        match self.sink.write(data) {
+            Ok(()) => (VIRTIO_SND_S_OK, deadline),
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write audio"
+                );
+                (VIRTIO_SND_S_IO_ERR, deadline)
+            }
+        }
+    }
+}
+
+struct SoundWorker {
+    kind: QueueKind,
+    mem: GuestMemory,
+    stream: Arc<Mutex<Stream>>,
+    timer: PolledTimer,
+    /// Buffers on the event queue. There are no events to report, so these
+    /// are held until the device is disabled.
+    events: Vec<VirtioQueueCallbackWork>,
+}
+
+impl SoundWorker {
+    fn control(&self, work: &mut VirtioQueueCallbackWork) -> Result<u32, virtio::VirtioWriteError> {
+        let len = (work.get_payload_length(false) as usize).min(MAX_CONTROL_LEN);
+        let mut request = vec![0; len];
+        let n = work.read(&self.mem, &mut request)?;
+        let response = self.stream.lock().control(&request[..n]);
+        work.write(&self.mem, &response)?;
+        Ok(response.len() as u32)
+    }
+
+    async fn tx(
+        &mut self,
+        work: &mut VirtioQueueCallbackWork,
+    ) -> Result<u32, virtio::VirtioWriteError> {
+        let len = work.get_payload_length(false) as usize;
+        let mut data = vec![0; len];
+        let n = work.read(&self.mem, &mut data)?;
+        let (status, deadline) = match VirtioSoundPcmXfer::read_from_prefix(&data[..n]) {
+            Ok((xfer, pcm)) if xfer.stream_id.get() == 0 => {
+                self.stream.lock().play(pcm, Instant::now())
+            }
+            _ => (VIRTIO_SND_S_BAD_MSG, Instant::now()),
+        };
+        self.timer.sleep_until(deadline).await;
+        let status = VirtioSoundPcmStatus {
+            status: status.into(),
+            latency_bytes: 0.into(),
+        };
+        work.write(&self.mem, status.as_bytes())?;
+        Ok(size_of::<VirtioSoundPcmStatus>() as u32)
+    }
+}
+
+#[async_trait]
+impl VirtioQueueWorkerContext for SoundWorker {
+    async fn process_work(&mut self, work: anyhow::Result<VirtioQueueCallbackWork>) -> bool {
+        let mut work = match work {
+            Ok(work) => work,
+            Err(err) => {
+                tracing::error!(err = err.as_ref() as &dyn std::error::Error, "queue error");
+                return false;
+            }
+        };
+
+        let r = match self.kind {
+            QueueKind::Control => self.control(&mut work),
+            QueueKind::Event => {
+                self.events.push(work);
+                return true;
+            }
+            QueueKind::Tx => self.tx(&mut work).await,
+            // There are no input streams.
+            QueueKind::Rx => Ok(0),
+        };
+        match r {
+            Ok(n) => work.complete(n),
+            Err(err) => {
+                tracing::error!(error = &err as &dyn std::error::Error, "invalid descriptor");
+                work.complete(0);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm_request(code: u32) -> Vec<u8> {
+        VirtioSoundPcmHeader {
+            hdr: VirtioSoundHeader { code: code.into() },
+            stream_id: 0.into(),
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    fn set_params(rate: u8) -> Vec<u8> {
+        VirtioSoundPcmSetParams {
+            hdr: VirtioSoundPcmHeader {
+                hdr: VirtioSoundHeader {
+                    code: VIRTIO_SND_R_PCM_SET_PARAMS.into(),
+                },
+                stream_id: 0.into(),
+            },
+            buffer_bytes: 0x4000.into(),
+            period_bytes: 0x1000.into(),
+            features: 0.into(),
+            channels: CHANNELS,
+            format: VIRTIO_SND_PCM_FMT_S16,
+            rate,
+            padding: 0,
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    #[test]
+    fn pcm_info() {
+        let mut stream = Stream::new(Box::new(NullSink));
+        let query = VirtioSoundQueryInfo {
+            hdr: VirtioSoundHeader {
+                code: VIRTIO_SND_R_PCM_INFO.into(),
+            },
+            start_id: 0.into(),
+            count: 1.into(),
+            size: 40.into(),
+        };
+        let response = stream.control(query.as_bytes());
+        assert_eq!(response.len(), 44);
+        assert_eq!(response[..4], VIRTIO_SND_S_OK.to_le_bytes());
+        let (info, _) = VirtioSoundPcmInfo::read_from_prefix(&response[4..]).unwrap();
+        assert_eq!(info.direction, VIRTIO_SND_D_OUTPUT);
+
+        // Only one stream.
+        let query = VirtioSoundQueryInfo {
+            count: 2.into(),
+            ..query
+        };
+        let response = stream.control(query.as_bytes());
+        assert_eq!(response, VIRTIO_SND_S_BAD_MSG.to_le_bytes());
+    }
+
+    #[test]
+    fn state_transitions() {
+        let mut stream = Stream::new(Box::new(NullSink));
+        let ok = VIRTIO_SND_S_OK.to_le_bytes();
+        let bad = VIRTIO_SND_S_BAD_MSG.to_le_bytes();
+
+        assert_eq!(stream.control(&pcm_request(VIRTIO_SND_R_PCM_PREPARE)), bad);
+        assert_eq!(
+            stream.control(&set_params(VIRTIO_SND_PCM_RATE_48000 - 1)),
+            VIRTIO_SND_S_NOT_SUPP.to_le_bytes()
+        );
+        assert_eq!(stream.control(&set_params(VIRTIO_SND_PCM_RATE_48000)), ok);
+        assert_eq!(stream.control(&pcm_request(VIRTIO_SND_R_PCM_START)), bad);
+        assert_eq!(stream.control(&pcm_request(VIRTIO_SND_R_PCM_PREPARE)), ok);
+        assert_eq!(stream.control(&pcm_request(VIRTIO_SND_R_PCM_START)), ok);
+        assert_eq!(stream.control(&set_params(VIRTIO_SND_PCM_RATE_48000)), bad);
+        assert_eq!(stream.control(&pcm_request(VIRTIO_SND_R_PCM_STOP)), ok);
+        assert_eq!(stream.control(&pcm_request(VIRTIO_SND_R_PCM_RELEASE)), ok);
+        assert_eq!(stream.state, StreamState::Configured);
+    }
+
+    #[test]
+    fn play_paces_buffers() {
+        let mut stream = Stream::new(Box::new(NullSink));
+        stream.control(&set_params(VIRTIO_SND_PCM_RATE_48000));
+        stream.control(&pcm_request(VIRTIO_SND_R_PCM_PREPARE));
+        stream.control(&pcm_request(VIRTIO_SND_R_PCM_START));
+
+        // 10ms of audio per buffer.
+        let buf = vec![0; 480 * BYTES_PER_FRAME as usize];
+        let start = Instant::from_nanos(1_000_000_000);
+        let (status, deadline) = stream.play(&buf, start);
+        assert_eq!(status, VIRTIO_SND_S_OK);
+        assert_eq!(deadline - start, Duration::from_millis(10));
+        let (_, deadline) = stream.play(&buf, start);
+        assert_eq!(deadline - start, Duration::from_millis(20));
+
+        // After an underrun, the clock restarts.
+        let now = start.saturating_add(Duration::from_secs(1));
+        let (_, deadline) = stream.play(&buf, now);
+        assert_eq!(deadline - now, Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Defines the resource resolver for virtio-snd devices.
+
+use crate::AudioSink;
+use crate::NullSink;
+use crate::PipeSink;
+use crate::VirtioSoundDevice;
+use crate::WavSink;
+use anyhow::Context;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::snd::VirtioSoundBackend;
+use virtio_resources::snd::VirtioSoundHandle;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+
+/// Resolver for virtio-snd devices.
+pub struct VirtioSoundResolver;
+
+declare_static_resolver! {
+    VirtioSoundResolver,
+    (VirtioDeviceHandle, VirtioSoundHandle),
+}
+
+impl ResolveResource<VirtioDeviceHandle, VirtioSoundHandle> for VirtioSoundResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = anyhow::Error;
+
+    fn resolve(
+        &self,
+        resource: VirtioSoundHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let sink: Box<dyn AudioSink> = match resource.backend {
+            VirtioSoundBackend::Null => Box::new(NullSink),
+            VirtioSoundBackend::Wav { file } => {
+                Box::new(WavSink::new(file).context("failed to write wav header")?)
+            }
+            VirtioSoundBackend::Pipe { pipe } => Box::new(PipeSink(pipe)),
+        };
+        let device = VirtioSoundDevice::new(input.driver_source, input.guest_memory.clone(), sink);
+        Ok(device.into())
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! virtio-snd definitions, from section 5.14 of the virtio specification.
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::little_endian::U32 as U32LE;
+use zerocopy::little_endian::U64 as U64LE;
+
+pub const VIRTIO_DEVICE_TYPE_SOUND: u16 = 25;
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundConfig {
+    pub jacks: U32LE,
+    pub streams: U32LE,
+    pub chmaps: U32LE,
+}
+
+// Request codes.
+pub const VIRTIO_SND_R_JACK_INFO: u32 = 1;
+pub const VIRTIO_SND_R_PCM_INFO: u32 = 0x0100;
+pub const VIRTIO_SND_R_PCM_SET_PARAMS: u32 = 0x0101;
+pub const VIRTIO_SND_R_PCM_PREPARE: u32 = 0x0102;
+pub const VIRTIO_SND_R_PCM_RELEASE: u32 = 0x0103;
+pub const VIRTIO_SND_R_PCM_START: u32 = 0x0104;
+pub const VIRTIO_SND_R_PCM_STOP: u32 = 0x0105;
+pub const VIRTIO_SND_R_CHMAP_INFO: u32 = 0x0200;
+
+// Status codes.
+pub const VIRTIO_SND_S_OK: u32 = 0x8000;
+pub const VIRTIO_SND_S_BAD_MSG: u32 = 0x8001;
+pub const VIRTIO_SND_S_NOT_SUPP: u32 = 0x8002;
+pub const VIRTIO_SND_S_IO_ERR: u32 = 0x8003;
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundHeader {
+    pub code: U32LE,
+}
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundQueryInfo {
+    pub hdr: VirtioSoundHeader,
+    pub start_id: U32LE,
+    pub count: U32LE,
+    pub size: U32LE,
+}
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundPcmHeader {
+    pub hdr: VirtioSoundHeader,
+    pub stream_id: U32LE,
+}
+
+pub const VIRTIO_SND_D_OUTPUT: u8 = 0;
+
+// PCM sample formats, as bit indexes in `formats`.
+pub const VIRTIO_SND_PCM_FMT_S16: u8 = 5;
+
+// PCM frame rates, as bit indexes in `rates`.
+pub const VIRTIO_SND_PCM_RATE_48000: u8 = 7;
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundPcmInfo {
+    pub hda_fn_nid: U32LE,
+    pub features: U32LE,
+    pub formats: U64LE,
+    pub rates: U64LE,
+    pub direction: u8,
+    pub channels_min: u8,
+    pub channels_max: u8,
+    pub padding: [u8; 5],
+}
+
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundPcmSetParams {
+    pub hdr: VirtioSoundPcmHeader,
+    pub buffer_bytes: U32LE,
+    pub period_bytes: U32LE,
+    pub features: U32LE,
+    pub channels: u8,
+    pub format: u8,
+    pub rate: u8,
+    pub padding: u8,
+}
+
+/// The header of a buffer on the TX or RX queue.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundPcmXfer {
+    pub stream_id: U32LE,
+}
+
+/// The status at the end of a buffer on the TX or RX queue.
+#[repr(C)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioSoundPcmStatus {
+    pub status: U32LE,
+    pub latency_bytes: U32LE,
+}
@@ -0,0 +1,108 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An audio sink that writes a WAV file.
+
+use crate::AudioSink;
+use crate::BYTES_PER_FRAME;
+use crate::CHANNELS;
+use crate::RATE;
+use std::fs::File;
+use std::io;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+const HEADER_LEN: u32 = 44;
+
+/// An [`AudioSink`] that writes the audio to a WAV file.
+///
+/// The sizes in the header are updated whenever the stream stops and when the
+/// sink is dropped, so the file is playable even if the VM exits uncleanly
+/// after playback ends.
+pub struct WavSink {
+    file: File,
+    data_len: u32,
+}
+
+impl WavSink {
+    /// Creates a new sink, writing the WAV header to the start of `file`.
+    pub fn new(mut file: File) -> io::Result<Self> {
+        file.set_len(0)?;
+        file.write_all(&header(0))?;
+        Ok(Self { file, data_len: 0 })
+    }
+
+    fn update_header(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header(self.data_len))?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        // The sizes in the header are 32 bits.
+        let len = (data.len() as u32).min(u32::MAX - HEADER_LEN - self.data_len);
+        self.file.write_all(&data[..len as usize])?;
+        self.data_len += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.update_header()
+    }
+}
+
+impl Drop for WavSink {
+    fn drop(&mut self) {
+        let _ = self.update_header();
+    }
+}
+
+/// Returns the header for a file with `data_len` bytes of audio.
+fn header(data_len: u32) -> Vec<u8> {
+    let mut v = Vec::with_capacity(HEADER_LEN as usize);
+    v.extend_from_slice(b"RIFF");
+    v.extend_from_slice(&(HEADER_LEN - 8 + data_len).to_le_bytes());
+    v.extend_from_slice(b"WAVEfmt ");
+    v.extend_from_slice(&16u32.to_le_bytes());
+    // PCM
+    v.extend_from_slice(&1u16.to_le_bytes());
+    v.extend_from_slice(&u16::from(CHANNELS).to_le_bytes());
+    v.extend_from_slice(&RATE.to_le_bytes());
+    v.extend_from_slice(&(RATE * BYTES_PER_FRAME).to_le_bytes());
+    v.extend_from_slice(&(BYTES_PER_FRAME as u16).to_le_bytes());
+    // Bits per sample
+    v.extend_from_slice(&16u16.to_le_bytes());
+    v.extend_from_slice(b"data");
+    v.extend_from_slice(&data_len.to_le_bytes());
+    assert_eq!(v.len(), HEADER_LEN as usize);
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn wav_sizes() {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut sink = WavSink::new(file.try_clone().unwrap()).unwrap();
+        sink.write(&[1; 400]).unwrap();
+        sink.flush().unwrap();
+        sink.write(&[2; 100]).unwrap();
+        drop(sink);
+
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 544);
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 536);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 500);
+        assert_eq!(&data[44..444], &[1; 400]);
+    }
+}
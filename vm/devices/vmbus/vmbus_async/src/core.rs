@@ -87,6 +87,10 @@ pub struct ReadState {
     pub ptrs: ring::IncomingOffset,
     pub polls: Counter,
     pub signals: Counter,
+    /// Packets read that requested a completion.
+    pub transactions: Counter,
+    /// Completion packets read.
+    pub completions: Counter,
     ready: bool,
     masked: bool,
 }
@@ -97,6 +101,8 @@ pub fn new(ptrs: ring::IncomingOffset) -> Self {
             ptrs,
             polls: Counter::new(),
             signals: Counter::new(),
+            transactions: Counter::new(),
+            completions: Counter::new(),
             ready: false,
             // It's safe to assume interrupts are initially masked, since
             // setting the mask is an optimization but clearing it is required
@@ -171,6 +177,12 @@ pub struct WriteState {
     pub ptrs: OutgoingOffset,
     pub signals: Counter,
     pub polls: Counter,
+    /// The number of times the ring was found to be full.
+    pub full: Counter,
+    /// Packets written that requested a completion.
+    pub transactions: Counter,
+    /// Completion packets written.
+    pub completions: Counter,
     ready: bool,
     pending_size: usize,
 }
@@ -181,6 +193,9 @@ pub fn new(ptrs: OutgoingOffset) -> Self {
             ptrs,
             signals: Counter::new(),
             polls: Counter::new(),
+            full: Counter::new(),
+            transactions: Counter::new(),
+            completions: Counter::new(),
             ready: false,
             pending_size: 0,
         }
@@ -230,6 +245,7 @@ pub fn poll_ready<M: RingMem>(
     /// known to be full.
     pub fn clear_ready(&mut self) {
         self.ready = false;
+        self.full.increment();
     }
 
     /// Clears the request for a wakeup when the ring is ready.
@@ -122,6 +122,9 @@ pub enum ExternalDataError {
 pub struct ReadBatch<'a, M: RingMem> {
     core: &'a Core<M>,
     read: &'a mut ReadState,
+    /// Transactions and completions read but not yet committed.
+    transactions: u64,
+    completions: u64,
 }
 
 /// The packet iterator for [`ReadBatch`].
@@ -133,6 +136,13 @@ fn next_priv(&mut self) -> Result<Option<IncomingPacket<'a, M>>, Error> {
         match self.core.in_ring().read(&mut ptrs) {
             Ok(packet) => {
                 let packet = IncomingPacket::parse(self.core.in_ring(), packet)?;
+                match &packet {
+                    IncomingPacket::Data(data) if data.transaction_id().is_some() => {
+                        self.transactions += 1
+                    }
+                    IncomingPacket::Data(_) => {}
+                    IncomingPacket::Completion(_) => self.completions += 1,
+                }
                 self.read.ptrs = ptrs;
                 Ok(Some(packet))
             }
@@ -169,6 +179,8 @@ fn next(&mut self) -> Option<Self::Item> {
 impl<M: RingMem> Drop for ReadBatch<'_, M> {
     fn drop(&mut self) {
         self.read.clear_poll(self.core);
+        self.read.transactions.add(self.transactions);
+        self.read.completions.add(self.completions);
         if self.core.in_ring().commit_read(&mut self.read.ptrs) {
             self.core.signal();
             self.read.signals.increment();
@@ -204,6 +216,8 @@ impl<M: RingMem> PacketRef<'_, M> {
     /// needs to be performed again next time the packet is read.
     pub fn revert(&mut self) {
         self.batch.read.ptrs.revert();
+        self.batch.transactions = 0;
+        self.batch.completions = 0;
     }
 }
 
@@ -419,6 +433,8 @@ pub fn poll_read_batch<'b>(
                 break ReadBatch {
                     core: self.core,
                     read: self.read,
+                    transactions: 0,
+                    completions: 0,
                 };
             } else {
                 self.read.clear_ready();
@@ -438,6 +454,8 @@ pub fn try_read_batch(&mut self) -> Result<ReadBatch<'_, M>, TryReadError> {
             Ok(ReadBatch {
                 core: self.core,
                 read: self.read,
+                transactions: 0,
+                completions: 0,
             })
         } else {
             self.read.clear_ready();
@@ -490,6 +508,8 @@ fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Outp
         Poll::Ready(Ok(ReadBatch {
             core: this.core,
             read: this.read,
+            transactions: 0,
+            completions: 0,
         }))
     }
 }
@@ -660,6 +680,11 @@ pub fn try_write(&mut self, packet: &OutgoingPacket<'_, '_>) -> Result<(), TryWr
                 }
                 self.write.clear_poll(self.core);
                 self.write.ptrs = ptrs;
+                match packet.packet_type {
+                    OutgoingPacketType::InBandNoCompletion => {}
+                    OutgoingPacketType::Completion => self.write.completions.increment(),
+                    _ => self.write.transactions.increment(),
+                }
                 Ok(())
             }
             Err(ring::WriteError::Full(n)) => {
@@ -714,7 +739,21 @@ fn inspect(&self, req: inspect::Request<'_>) {
         req.respond()
             .merge(&self.core)
             .field("incoming_ring", &self.read)
-            .field("outgoing_ring", &self.write);
+            .field("outgoing_ring", &self.write)
+            .field(
+                "outstanding_incoming_transactions",
+                self.read
+                    .transactions
+                    .get()
+                    .saturating_sub(self.write.completions.get()),
+            )
+            .field(
+                "outstanding_outgoing_transactions",
+                self.write
+                    .transactions
+                    .get()
+                    .saturating_sub(self.read.completions.get()),
+            );
     }
 }
 
@@ -825,6 +864,61 @@ async fn test_gpa_direct() {
             .unwrap();
     }
 
+    #[async_test]
+    async fn test_outstanding_transactions() {
+        let (mut host_queue, mut guest_queue) = connected_queues(16384);
+
+        for transaction_id in 0..2 {
+            guest_queue
+                .split()
+                .1
+                .write(OutgoingPacket {
+                    transaction_id,
+                    packet_type: OutgoingPacketType::InBandWithCompletion,
+                    payload: &[&[0; 8]],
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            host_queue
+                .split()
+                .0
+                .read_batch()
+                .await
+                .unwrap()
+                .packets()
+                .count(),
+            2
+        );
+        host_queue
+            .split()
+            .1
+            .write(OutgoingPacket {
+                transaction_id: 0,
+                packet_type: OutgoingPacketType::Completion,
+                payload: &[],
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            guest_queue
+                .split()
+                .0
+                .read_batch()
+                .await
+                .unwrap()
+                .packets()
+                .count(),
+            1
+        );
+
+        assert_eq!(host_queue.read.transactions.get(), 2);
+        assert_eq!(host_queue.write.completions.get(), 1);
+        assert_eq!(guest_queue.write.transactions.get(), 2);
+        assert_eq!(guest_queue.read.completions.get(), 1);
+    }
+
     #[async_test]
     async fn test_gpa_direct_empty_external_data() {
         use guestmem::ranges::PagedRange;
@@ -471,6 +471,7 @@ fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
         const TIMESYNC_IC: Guid = guid::guid!("9527e630-d0ae-497b-adce-e80ab0175caf");
         const HEARTBEAT_IC: Guid = guid::guid!("57164f39-9115-4e78-ab55-382f3bd5422d");
         const RDV_IC: Guid = guid::guid!("276aacf4-ac15-426c-98dd-7521ad3f01fe");
+        const FCOPY_IC: Guid = guid::guid!("34d14be3-dee4-41c8-9ae7-6b174977c192");
 
         const INHERITED_ACTIVATION: Guid = guid::guid!("3375baf4-9e15-4b30-b765-67acb10d607b");
 
@@ -485,6 +486,7 @@ fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
             TIMESYNC_IC => "timesync_ic",
             HEARTBEAT_IC => "heartbeat_ic",
             RDV_IC => "rdv_ic",
+            FCOPY_IC => "fcopy_ic",
             INHERITED_ACTIVATION => "inherited_activation",
             NET => "net",
             SCSI => "scsi",
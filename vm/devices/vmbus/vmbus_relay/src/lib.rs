@@ -28,6 +28,7 @@
 use guid::Guid;
 use inspect::Inspect;
 use inspect::InspectMut;
+use inspect_counters::Counter;
 use mesh::rpc::FailableRpc;
 use mesh::rpc::Rpc;
 use mesh::rpc::RpcSend;
@@ -174,14 +175,19 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 }
 
 /// State needed to relay host-to-guest interrupts.
+#[derive(Inspect)]
 struct InterruptRelay {
     /// Event signaled when the host sends an interrupt.
+    #[inspect(skip)]
     notify: PolledNotify,
     /// Interrupt used to signal the guest.
+    #[inspect(skip)]
     interrupt: Interrupt,
     /// Event flag used to signal the guest.
     /// FUTURE: remove once this moves into `vmbus_client` saved state.
     event_flag: u16,
+    /// The number of interrupts relayed to the guest.
+    interrupts: Counter,
 }
 
 enum RelayChannelRequest {
@@ -263,7 +269,6 @@ struct RelayChannel {
     /// connection, which sets this to true only if the guest uses the channel bitmap.
     use_interrupt_relay: Arc<AtomicBool>,
     /// State used to relay host-to-guest interrupts.
-    #[inspect(with = "Option::is_some")]
     interrupt_relay: Option<InterruptRelay>,
     /// Futures waiting for GPADL teardown to complete before responding to
     /// `vmbus_server`.
@@ -315,6 +320,7 @@ async fn handle_open_channel(&mut self, open_request: &OpenRequest) -> Result<Op
                 notify,
                 interrupt: open_request.interrupt.clone(),
                 event_flag: opened.redirected_event_flag.unwrap(),
+                interrupts: Counter::new(),
             });
         }
 
@@ -501,7 +507,9 @@ async fn run(mut self) {
                 _r = relay_event => {
                     // Needed to avoid conflicting interrupt_relay borrow.
                     drop(relay_event);
-                    self.channel.interrupt_relay.as_ref().unwrap().interrupt.deliver();
+                    let interrupt_relay = self.channel.interrupt_relay.as_mut().unwrap();
+                    interrupt_relay.interrupt.deliver();
+                    interrupt_relay.interrupts.increment();
                 }
             }
         }
@@ -10,6 +10,7 @@
 use crate::RelayTask;
 use anyhow::Context as _;
 use anyhow::Result;
+use inspect_counters::Counter;
 use mesh::payload::Protobuf;
 use mesh::rpc::RpcSend;
 use pal_event::Event;
@@ -219,6 +220,7 @@ pub(crate) async fn handle_restore(&mut self, state: Channel) -> Result<()> {
                     event_flag: event_flag.unwrap(),
                     notify,
                     interrupt: request.interrupt,
+                    interrupts: Counter::new(),
                 });
             }
 
@@ -1195,9 +1195,45 @@ struct InnerRing<M: RingMem> {
 
 impl<M: RingMem> Inspect for InnerRing<M> {
     fn inspect(&self, req: inspect::Request<'_>) {
-        req.respond()
-            .hex("ring_size", self.size)
+        let mut resp = req.respond();
+        resp.hex("ring_size", self.size)
             .field("control", self.control());
+        self.inspect_packets(&mut resp);
+    }
+}
+
+/// The most packets reported when inspecting the contents of a ring buffer.
+const MAX_INSPECT_PACKETS: usize = 64;
+
+/// The most payload bytes reported for each packet when inspecting the
+/// contents of a ring buffer.
+const MAX_INSPECT_PAYLOAD: usize = 32;
+
+/// A packet in a ring buffer, as reported by inspect.
+#[derive(Debug, Inspect)]
+struct PacketSummary {
+    #[inspect(hex)]
+    offset: u32,
+    #[inspect(hex)]
+    packet_type: u16,
+    #[inspect(hex)]
+    flags: u16,
+    #[inspect(hex)]
+    transaction_id: u64,
+    len: u32,
+    /// The start of the payload.
+    data: Vec<u8>,
+}
+
+/// Inspects the packets in a ring buffer that have been written but not yet
+/// read, without creating an IncomingRing or OutgoingRing structure.
+///
+/// The ring is not modified, so this is safe to call while either endpoint is
+/// using the ring. But for the same reason, the result is only a snapshot, and
+/// packets that are concurrently read may be reported as corrupt.
+pub fn inspect_ring_packets<M: RingMem>(mem: M, response: &mut inspect::Response<'_>) {
+    if let Ok(ring) = InnerRing::new(mem) {
+        ring.inspect_packets(response);
     }
 }
 
@@ -1265,6 +1301,69 @@ fn control(&self) -> Control<'_> {
         Control(self.mem.control())
     }
 
+    fn inspect_packets(&self, resp: &mut inspect::Response<'_>) {
+        let control = self.control();
+        let (Ok(inp), Ok(outp)) = (
+            self.validate(control.inp().load(Ordering::Acquire)),
+            self.validate(control.outp().load(Ordering::Relaxed)),
+        ) else {
+            resp.field("packets_error", Error::InvalidRingPointer.to_string());
+            return;
+        };
+        let pending = if inp == outp {
+            0
+        } else {
+            self.available(inp, outp)
+        };
+        resp.field("bytes_pending", pending)
+            .field("bytes_free", self.free(inp, outp));
+        let (packets, err) = self.pending_packets(inp, outp);
+        resp.field("packet_count", packets.len())
+            .child("packets", |req| {
+                let mut resp = req.respond();
+                for (i, packet) in packets.iter().enumerate() {
+                    resp.field(&i.to_string(), packet);
+                }
+            });
+        if let Some(err) = err {
+            resp.field("packets_error", err.to_string());
+        }
+    }
+
+    /// Parses the descriptors of the packets between `outp` and `inp`, up to
+    /// `MAX_INSPECT_PACKETS`.
+    fn pending_packets(&self, inp: u32, mut outp: u32) -> (Vec<PacketSummary>, Option<Error>) {
+        let mut packets = Vec::new();
+        while outp != inp && packets.len() < MAX_INSPECT_PACKETS {
+            let avail = self.available(inp, outp);
+            let mut desc = PacketDescriptor::new_zeroed();
+            if avail < size_of_val(&desc) as u32 {
+                return (packets, Some(Error::InvalidDataAvailable));
+            }
+            self.mem.read_aligned(outp as usize, desc.as_mut_bytes());
+            let len = desc.length8 as u32 * 8;
+            let data_offset = desc.data_offset8 as u32 * 8;
+            if desc.length8 < desc.data_offset8
+                || desc.data_offset8 < 2
+                || avail < len + size_of::<Footer>() as u32
+            {
+                return (packets, Some(Error::InvalidDescriptorLengths));
+            }
+            let mut data = vec![0; ((len - data_offset) as usize).min(MAX_INSPECT_PAYLOAD)];
+            self.mem.read_at((outp + data_offset) as usize, &mut data);
+            packets.push(PacketSummary {
+                offset: outp,
+                packet_type: desc.packet_type,
+                flags: desc.flags,
+                transaction_id: desc.transaction_id,
+                len,
+                data,
+            });
+            outp = self.add_pointer(outp, len + size_of::<Footer>() as u32);
+        }
+        (packets, None)
+    }
+
     fn len(&self) -> u32 {
         self.size
     }
@@ -1408,4 +1507,36 @@ fn test_pending_send_size() {
         assert!(read_simple(&mut in_ring).1);
         assert!(!read_simple(&mut in_ring).1);
     }
+
+    #[test]
+    fn test_pending_packets() {
+        let rmem = FlatRingMem::new(16384);
+        let mut in_ring = IncomingRing::new(&rmem).unwrap();
+        let mut out_ring = OutgoingRing::new(&rmem).unwrap();
+
+        write_simple(&mut out_ring, &[1; 8]).unwrap();
+        write_simple(&mut out_ring, &[2; 100]).unwrap();
+        read_simple(&mut in_ring);
+
+        let control = in_ring.inner.control();
+        let (packets, err) = in_ring.inner.pending_packets(
+            control.inp().load(Ordering::Relaxed),
+            control.outp().load(Ordering::Relaxed),
+        );
+        assert!(err.is_none());
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].offset, 32);
+        assert_eq!(packets[0].packet_type, PACKET_TYPE_IN_BAND);
+        assert_eq!(packets[0].len, 120);
+        assert_eq!(packets[0].data, [2; MAX_INSPECT_PAYLOAD]);
+
+        // Corrupt the descriptor's length.
+        rmem.write_at(32 + 4, &[0xff, 0xff]);
+        let (packets, err) = in_ring.inner.pending_packets(
+            control.inp().load(Ordering::Relaxed),
+            control.outp().load(Ordering::Relaxed),
+        );
+        assert!(packets.is_empty());
+        assert!(matches!(err, Some(Error::InvalidDescriptorLengths)));
+    }
 }
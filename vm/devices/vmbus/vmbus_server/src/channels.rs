@@ -3341,6 +3341,7 @@ pub fn handle_synic_message(&mut self, message: SynicMessage) -> Result<(), Chan
 
         let version = self.inner.state.get_version();
         let msg = Message::parse(&message.data, version)?;
+        let _span = tracing::trace_span!("vmbus_message", ?msg, message.trusted).entered();
         tracing::trace!(?msg, message.trusted, "received vmbus message");
         // Do not allow untrusted messages if the connection was established
         // using a trusted message.
@@ -6,6 +6,10 @@
 //! This supports the [hybrid vsock connection model][1] established by
 //! Firecracker, extended to support Hyper-V sockets as well.
 //!
+//! Individual vsock ports can also be bridged to host VM sockets (`AF_VSOCK`
+//! on Linux, Hyper-V sockets on Windows), so that host agents that speak vsock
+//! can talk to the guest without a relay process.
+//!
 //! [1]: <https://github.com/firecracker-microvm/firecracker/blob/7b2e87dc65fc45162303e5708b83c379cf1b0426/docs/vsock.md>
 
 use super::Guid;
@@ -18,6 +22,7 @@
 use futures_concurrency::stream::Merge;
 use mesh::CancelContext;
 use pal_async::driver::SpawnDriver;
+use pal_async::socket::AsSockRef;
 use pal_async::socket::PolledSocket;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
@@ -33,6 +38,7 @@
 use vmbus_channel::bus::ChannelType;
 use vmbus_channel::bus::OfferParams;
 use vmbus_channel::bus::ParentBus;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
 use vmbus_channel::offer::Offer;
 use vmbus_core::HvsockConnectRequest;
 use vmbus_core::HvsockConnectResult;
@@ -42,6 +48,19 @@ pub struct HvsockRelay {
     host_send: mesh::Sender<RelayRequest>,
     _relay_task: Task<()>,
     _listener_task: Option<Task<()>>,
+    _bridge_listener_tasks: Vec<Task<()>>,
+}
+
+/// Configuration for bridging vsock ports to host VM sockets (`AF_VSOCK` on
+/// Linux, Hyper-V sockets on Windows).
+#[derive(Debug, Clone, Default)]
+pub struct VmSocketBridge {
+    /// Guest vsock ports whose connections are bridged to the same port on the
+    /// host's loopback VM socket address, instead of the hybrid vsock path.
+    pub connect_ports: Vec<u32>,
+    /// Host VM socket ports to listen on, each paired with the guest vsock
+    /// port that accepted connections are relayed to.
+    pub listen_ports: Vec<(u32, u32)>,
 }
 
 enum RelayRequest {
@@ -62,7 +81,13 @@ pub fn new(
         guest: HvsockRelayChannelHalf,
         hybrid_vsock_path: Option<PathBuf>,
         hybrid_vsock_listener: Option<UnixListener>,
+        bridge: VmSocketBridge,
     ) -> anyhow::Result<Self> {
+        #[cfg(not(any(windows, target_os = "linux")))]
+        if !bridge.connect_ports.is_empty() || !bridge.listen_ports.is_empty() {
+            anyhow::bail!("vm socket bridging is not supported on this platform");
+        }
+
         let inner = Arc::new(RelayInner {
             vmbus,
             driver: Box::new(driver),
@@ -73,6 +98,7 @@ pub fn new(
             inner: inner.clone(),
             tasks: Default::default(),
             hybrid_vsock_path,
+            bridge_ports: bridge.connect_ports,
         };
 
         let (host_send, host_recv) = mesh::channel();
@@ -93,6 +119,30 @@ pub fn new(
             None
         };
 
+        #[cfg(any(windows, target_os = "linux"))]
+        let _bridge_listener_tasks = bridge
+            .listen_ports
+            .iter()
+            .map(|&(host_port, guest_port)| {
+                let listener =
+                    vmsocket::VmListener::bind(vmsocket::VmAddress::vsock_any(host_port))
+                        .with_context(|| {
+                            format!("failed to listen on host vm socket port {host_port}")
+                        })?;
+                let listener = PolledSocket::new(inner.driver.as_ref(), listener)?;
+                Ok(inner.driver.spawn(
+                    format!("vmsocket-listener-{host_port}"),
+                    ListenerWorker {
+                        inner: inner.clone(),
+                        host_send: host_send.clone(),
+                    }
+                    .run_bridge(listener, guest_port),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        #[cfg(not(any(windows, target_os = "linux")))]
+        let _bridge_listener_tasks = Vec::new();
+
         let task = inner
             .driver
             .spawn("hvsock relay", worker.run(guest.request_receive, host_recv));
@@ -102,6 +152,7 @@ pub fn new(
             inner,
             _relay_task: task,
             _listener_task,
+            _bridge_listener_tasks,
         })
     }
 
@@ -174,39 +225,70 @@ async fn run(self, mut listener: PolledSocket<UnixListener>) {
         }
     }
 
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn run_bridge(self, mut listener: PolledSocket<vmsocket::VmListener>, guest_port: u32) {
+        let service_id = Guid {
+            data1: guest_port,
+            ..VSOCK_TEMPLATE
+        };
+        loop {
+            let connection = match listener.accept().await {
+                Ok((connection, address)) => {
+                    tracing::debug!(%address, guest_port, "accepted host vm socket connection");
+                    connection
+                }
+                Err(err) => {
+                    tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        guest_port,
+                        "failed to accept host vm socket connection, shutting down listener"
+                    );
+                    break;
+                }
+            };
+            let r = async {
+                let socket = PolledSocket::new(
+                    self.inner.driver.as_ref(),
+                    socket2::Socket::from(connection),
+                )?;
+                let (offer, _instance_id, pipe) =
+                    self.inner.offer_connect_channel(service_id).await?;
+                anyhow::Ok(
+                    self.inner
+                        .driver
+                        .spawn("vmsocket connection relay", async move {
+                            // Keep the offer alive until the relay completes.
+                            let _offer = offer;
+                            if let Err(err) = relay_connected(pipe, socket).await {
+                                tracing::error!(
+                                    %service_id,
+                                    error = &err as &dyn std::error::Error,
+                                    "connection relay failed"
+                                );
+                            }
+                        }),
+                )
+            }
+            .await;
+            match r {
+                Ok(task) => {
+                    self.host_send.send(RelayRequest::AddTask(task));
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        guest_port,
+                        "bridged connection failed"
+                    );
+                }
+            }
+        }
+    }
+
     async fn spawn_relay(&self, connection: UnixStream) -> anyhow::Result<Task<()>> {
         let mut socket = PolledSocket::new(self.inner.driver.as_ref(), connection)?;
         let (service_id, format) = read_hybrid_vsock_connect(&mut socket).await?;
-
-        let instance_id = Guid::new_random();
-        let mut offer = Offer::new(
-            self.inner.driver.as_ref(),
-            self.inner.vmbus.as_ref(),
-            OfferParams {
-                interface_name: "hvsocket_connect".into(),
-                interface_id: service_id,
-                instance_id,
-                channel_type: ChannelType::HvSocket {
-                    is_connect: true,
-                    is_for_container: false,
-                    silo_id: Guid::ZERO,
-                },
-                ..Default::default()
-            },
-        )
-        .await
-        .context("failed to offer channel")?;
-
-        let channel = CancelContext::new()
-            .with_timeout(Duration::from_secs(2))
-            .until_cancelled(offer.accept(self.inner.driver.as_ref()))
-            .await?
-            .context("failed to accept channel")?
-            .channel;
-
-        let pipe = BytePipe::new(channel).context("failed to create vmbus pipe")?;
-
-        tracing::debug!(%service_id, endpoint_id = %instance_id, "connected host to guest");
+        let (offer, instance_id, pipe) = self.inner.offer_connect_channel(service_id).await?;
 
         let task = self
             .inner
@@ -313,6 +395,14 @@ fn drop(&mut self) {
 // AF_HYPERV service ID.
 static VSOCK_TEMPLATE: Guid = guid::guid!("00000000-facb-11e6-bd58-64006a7986d3");
 
+/// The `AF_VSOCK` CID for connecting to the local host.
+#[cfg(target_os = "linux")]
+const VMADDR_CID_LOCAL: u32 = 1;
+
+/// The Hyper-V socket VM ID for connecting to the local host.
+#[cfg(windows)]
+const HV_GUID_LOOPBACK: Guid = guid::guid!("e0e16197-dd56-4a10-9195-5ee7a155a838");
+
 fn vsock_port(service_id: &Guid) -> Option<u32> {
     let stripped_id = Guid {
         data1: 0,
@@ -326,6 +416,7 @@ struct HvsockRelayWorker {
     tasks: FuturesUnordered<Task<()>>,
     inner: Arc<RelayInner>,
     hybrid_vsock_path: Option<PathBuf>,
+    bridge_ports: Vec<u32>,
 }
 
 impl HvsockRelayWorker {
@@ -371,6 +462,32 @@ fn handle_connect_from_guest(&mut self, request: HvsockConnectRequest) {
             send: self.guest_send.clone(),
             request,
         };
+        #[cfg(any(windows, target_os = "linux"))]
+        if let Some(port) =
+            vsock_port(&request.service_id).filter(|port| self.bridge_ports.contains(port))
+        {
+            let inner = self.inner.clone();
+            let task = self.inner.driver.spawn(
+                format!("vmsocket accept {}:{}", port, request.endpoint_id),
+                async move {
+                    match inner.relay_guest_connect_to_vm_socket(pending, port).await {
+                        Ok(()) => {
+                            tracing::debug!(request = ?&request, "relay done");
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                request = ?&request,
+                                err = err.as_ref() as &dyn std::error::Error,
+                                "relay error"
+                            );
+                        }
+                    }
+                },
+            );
+            self.tasks.push(task);
+            return;
+        }
+
         let (path, is_specific_path) = {
             if let Some(hybrid_vsock_path) = &self.hybrid_vsock_path {
                 (hybrid_vsock_path.to_owned(), false)
@@ -421,7 +538,37 @@ async fn relay_guest_connect_to_host(
         let socket = self
             .connect_to_host_uds(request, path, is_specific_path)
             .await?;
+        self.relay_guest_connect(pending, socket).await
+    }
 
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn relay_guest_connect_to_vm_socket(
+        &self,
+        pending: PendingConnection,
+        port: u32,
+    ) -> anyhow::Result<()> {
+        #[cfg(windows)]
+        let address = vmsocket::VmAddress::hyperv_vsock(HV_GUID_LOOPBACK, port);
+        #[cfg(target_os = "linux")]
+        let address = vmsocket::VmAddress::vsock(VMADDR_CID_LOCAL, port);
+
+        let socket = vmsocket::VmSocket::new().context("failed to create vm socket")?;
+        let mut socket = PolledSocket::new(self.driver.as_ref(), socket2::Socket::from(socket))?;
+        socket
+            .connect(&address.into())
+            .await
+            .with_context(|| format!("failed to connect to host vm socket port {port}"))?;
+        self.relay_guest_connect(pending, socket).await
+    }
+
+    /// Offers a channel for the guest's pending connect request, and relays
+    /// it to `socket` once the guest opens it.
+    async fn relay_guest_connect<S: AsSockRef + std::io::Read + std::io::Write>(
+        &self,
+        pending: PendingConnection,
+        socket: PolledSocket<S>,
+    ) -> anyhow::Result<()> {
+        let request = &pending.request;
         let mut offer = Offer::new(
             self.driver.as_ref(),
             self.vmbus.as_ref(),
@@ -501,6 +648,44 @@ async fn connect_to_host_uds(
         Ok(socket)
     }
 
+    /// Offers a channel to connect to the guest's `service_id` listener, and
+    /// waits briefly for the guest to open it.
+    async fn offer_connect_channel(
+        &self,
+        service_id: Guid,
+    ) -> anyhow::Result<(Offer, Guid, BytePipe<GpadlRingMem>)> {
+        let instance_id = Guid::new_random();
+        let mut offer = Offer::new(
+            self.driver.as_ref(),
+            self.vmbus.as_ref(),
+            OfferParams {
+                interface_name: "hvsocket_connect".into(),
+                interface_id: service_id,
+                instance_id,
+                channel_type: ChannelType::HvSocket {
+                    is_connect: true,
+                    is_for_container: false,
+                    silo_id: Guid::ZERO,
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .context("failed to offer channel")?;
+
+        let channel = CancelContext::new()
+            .with_timeout(Duration::from_secs(2))
+            .until_cancelled(offer.accept(self.driver.as_ref()))
+            .await?
+            .context("failed to accept channel")?
+            .channel;
+
+        let pipe = BytePipe::new(channel).context("failed to create vmbus pipe")?;
+
+        tracing::debug!(%service_id, endpoint_id = %instance_id, "connected host to guest");
+        Ok((offer, instance_id, pipe))
+    }
+
     async fn connect_to_guest(&self, service_id: Guid) -> anyhow::Result<(UnixStream, Task<()>)> {
         let instance_id = Guid::new_random();
         let mut offer = Offer::new(
@@ -553,9 +738,9 @@ async fn connect_to_guest(&self, service_id: Guid) -> anyhow::Result<(UnixStream
     }
 }
 
-async fn relay_connected<T: RingMem + Unpin>(
+async fn relay_connected<T: RingMem + Unpin, S: AsSockRef + std::io::Read + std::io::Write>(
     channel: BytePipe<T>,
-    socket: PolledSocket<UnixStream>,
+    socket: PolledSocket<S>,
 ) -> std::io::Result<()> {
     let (channel_read, mut channel_write) = channel.split();
     let (socket_read, mut socket_write) = socket.split();
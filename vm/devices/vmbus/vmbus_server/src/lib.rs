@@ -1942,6 +1942,11 @@ fn inspect_ring(req: inspect::Request<'_>, gpadl: &AlignedGpadlView, gm: &GuestM
     if let Ok(pages) = lock_page_with_subrange(gm, gpadl.gpns()[0] * PAGE_SIZE as u64) {
         ring::inspect_ring(pages.pages()[0], &mut resp);
     }
+
+    // Report the packets that have not been read yet.
+    if let Ok(mem) = GpadlRingMem::new(gpadl.clone(), gm) {
+        ring::inspect_ring_packets(&mem, &mut resp);
+    }
 }
 
 /// Helper to create a subrange before locking a single page.
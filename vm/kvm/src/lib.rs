@@ -36,6 +36,7 @@ mod ioctl {
     #[cfg(target_arch = "x86_64")]
     ioctl_readwrite!(kvm_get_supported_cpuid, KVMIO, 0x05, kvm_cpuid2);
     ioctl_write_int_bad!(kvm_create_vcpu, request_code_none!(KVMIO, 0x41));
+    ioctl_write_ptr!(kvm_get_dirty_log, KVMIO, 0x42, kvm_dirty_log);
     ioctl_write_ptr!(
         kvm_set_user_memory_region,
         KVMIO,
@@ -109,6 +110,8 @@ pub enum Error {
     SignalMsi(#[source] nix::Error),
     #[error("SetMemoryRegion")]
     SetMemoryRegion(#[source] nix::Error),
+    #[error("GetDirtyLog")]
+    GetDirtyLog(#[source] nix::Error),
     #[error("CreateVm")]
     CreateVm(#[source] nix::Error),
     #[error("EnableCap({0})")]
@@ -177,6 +180,8 @@ pub enum Error {
     UnknownExit(u32),
     #[error("unknown Hyper-V exit {0:#x}")]
     UnknownHvExit(u32),
+    #[error("unknown system event {0:#x}")]
+    UnknownSystemEvent(u32),
     #[error("ioeventfd")]
     IoEventFd(#[source] nix::Error),
     #[error("irqfd")]
@@ -476,10 +481,18 @@ pub unsafe fn set_user_memory_region(
         size: usize,
         addr: u64,
         readonly: bool,
+        log_dirty: bool,
     ) -> Result<()> {
+        let mut flags = 0;
+        if readonly {
+            flags |= KVM_MEM_READONLY;
+        }
+        if log_dirty {
+            flags |= KVM_MEM_LOG_DIRTY_PAGES;
+        }
         let region = kvm_userspace_memory_region {
             slot,
-            flags: if readonly { KVM_MEM_READONLY } else { 0 },
+            flags,
             guest_phys_addr: addr,
             memory_size: size as u64,
             userspace_addr: data as usize as u64,
@@ -491,6 +504,28 @@ pub unsafe fn set_user_memory_region(
         Ok(())
     }
 
+    /// Gets the bitmap of pages in a memory slot written since the last call,
+    /// and clears it. The slot must have been registered with `log_dirty`.
+    ///
+    /// # Safety
+    /// The caller must ensure that `bitmap` has a bit for each page in the
+    /// slot.
+    pub unsafe fn get_dirty_log(&self, slot: u32, bitmap: &mut [u64]) -> Result<()> {
+        let log = kvm_dirty_log {
+            slot,
+            padding1: 0,
+            __bindgen_anon_1: kvm_dirty_log__bindgen_ty_1 {
+                dirty_bitmap: bitmap.as_mut_ptr().cast(),
+            },
+        };
+        // SAFETY: the caller guarantees the bitmap is large enough for the
+        // slot.
+        unsafe {
+            ioctl::kvm_get_dirty_log(self.vm.as_raw_fd(), &log).map_err(Error::GetDirtyLog)?;
+        }
+        Ok(())
+    }
+
     pub fn set_gsi_routes(&self, routes: &[(u32, RoutingEntry)]) -> Result<()> {
         const MAX_ROUTES: usize = 2048;
         assert!(routes.len() <= MAX_ROUTES);
@@ -1345,6 +1380,14 @@ pub fn run(&mut self) -> Result<Exit<'_>, Error> {
                     _ => return Err(Error::UnknownHvExit(hyperv.type_)),
                 }
             }
+            KVM_EXIT_SYSTEM_EVENT => {
+                // SAFETY: this is the active union field.
+                let system_event = unsafe { &self.run_data().__bindgen_anon_1.system_event };
+                match system_event.type_ {
+                    KVM_SYSTEM_EVENT_CRASH => Exit::GuestCrash,
+                    _ => return Err(Error::UnknownSystemEvent(system_event.type_)),
+                }
+            }
             KVM_EXIT_IOAPIC_EOI => {
                 // SAFETY: this is the active union field.
                 let eoi = unsafe { &mut self.run_data().__bindgen_anon_1.eoi };
@@ -1491,6 +1534,9 @@ pub enum Exit<'a> {
     Eoi {
         irq: u8,
     },
+    /// The guest wrote the Hyper-V crash control MSR with the notify bit set.
+    /// The crash parameters are in the crash parameter MSRs.
+    GuestCrash,
 }
 
 /// Set up a signal used to cause KVM run_vp to return.
@@ -8,4 +8,5 @@
 
 pub mod linux;
 pub mod paravisor;
+pub mod pvh;
 pub mod shim;
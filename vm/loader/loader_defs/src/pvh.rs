@@ -0,0 +1,70 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! PVH boot protocol definitions.
+//!
+//! These structures are defined by Xen in
+//! `xen/include/public/arch-x86/hvm/start_info.h`, and are used to boot ELF
+//! kernels that advertise a 32-bit PVH entry point via an ELF note. See the
+//! [`x86/HVM direct boot ABI`](https://xenbits.xen.org/docs/unstable/misc/pvh.html).
+
+#![expect(missing_docs)]
+#![expect(non_camel_case_types)]
+
+use static_assertions::const_assert_eq;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The name of the ELF notes describing Xen/PVH properties of an image.
+pub const XEN_ELFNOTE_NAME: &[u8] = b"Xen";
+/// The ELF note type containing the 32-bit physical PVH entry point.
+pub const XEN_ELFNOTE_PHYS32_ENTRY: u32 = 18;
+
+pub const XEN_HVM_START_MAGIC_VALUE: u32 = 0x336ec578;
+
+/// The version of [`hvm_start_info`] that includes the memory map.
+pub const XEN_HVM_START_INFO_VERSION: u32 = 1;
+
+/// Passed to the guest in `ebx` at the PVH entry point.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct hvm_start_info {
+    pub magic: u32,
+    pub version: u32,
+    pub flags: u32,
+    pub nr_modules: u32,
+    pub modlist_paddr: u64,
+    pub cmdline_paddr: u64,
+    pub rsdp_paddr: u64,
+    pub memmap_paddr: u64,
+    pub memmap_entries: u32,
+    pub reserved: u32,
+}
+
+const_assert_eq!(size_of::<hvm_start_info>(), 56);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct hvm_modlist_entry {
+    pub paddr: u64,
+    pub size: u64,
+    pub cmdline_paddr: u64,
+    pub reserved: u64,
+}
+
+const_assert_eq!(size_of::<hvm_modlist_entry>(), 32);
+
+/// A memory map entry. `typ` uses the same values as the e820 map (see
+/// [`crate::linux::E820_RAM`] and friends).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct hvm_memmap_table_entry {
+    pub addr: u64,
+    pub size: u64,
+    pub typ: u32,
+    pub reserved: u32,
+}
+
+const_assert_eq!(size_of::<hvm_memmap_table_entry>(), 24);
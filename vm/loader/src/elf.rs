@@ -14,10 +14,12 @@
 use crate::importer::GuestArchKind;
 use crate::importer::ImageLoad;
 use hvdef::HV_PAGE_SIZE;
+use loader_defs::pvh;
 use object::ReadCache;
 use object::ReadRef;
 use object::elf;
 use object::read::elf::FileHeader;
+use object::read::elf::ProgramHeader;
 use std::io::Read;
 use std::io::Seek;
 use thiserror::Error;
@@ -63,6 +65,10 @@ pub enum Error {
     ImportPages(#[source] anyhow::Error),
     #[error("failed to seek to offset of kernel image")]
     SeekKernelImage,
+    #[error("failed to parse ELF note")]
+    InvalidNote(#[source] object::read::Error),
+    #[error("invalid PVH entry point note")]
+    InvalidPvhEntry,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -263,3 +269,191 @@ pub fn load_static_elf<F, R: GuestArch>(
         entrypoint: entry - reloc_bias,
     })
 }
+
+/// Returns the 32-bit PVH entry point advertised by the image's
+/// `XEN_ELFNOTE_PHYS32_ENTRY` note, if it has one.
+pub fn pvh_entrypoint<F>(kernel_image: &mut F) -> Result<Option<u64>>
+where
+    F: Read + Seek,
+{
+    let reader = ReadCache::new(kernel_image);
+    let ehdr: &elf::FileHeader64<LE> = reader.read_at(0).map_err(|_| Error::ReadFileHeader)?;
+    if !ehdr.is_supported() {
+        return Err(Error::InvalidFileHeader);
+    }
+    if ehdr.is_big_endian() {
+        return Err(Error::BigEndianElfOnLittle);
+    }
+
+    let phdrs = ehdr
+        .program_headers(LE, &reader)
+        .map_err(Error::InvalidProgramHeader)?;
+
+    for phdr in phdrs {
+        let Some(mut notes) = phdr.notes(LE, &reader).map_err(Error::InvalidNote)? else {
+            continue;
+        };
+        while let Some(note) = notes.next().map_err(Error::InvalidNote)? {
+            if note.name() != pvh::XEN_ELFNOTE_NAME
+                || note.n_type(LE) != pvh::XEN_ELFNOTE_PHYS32_ENTRY
+            {
+                continue;
+            }
+            // The entry point is emitted as a pointer-sized value, but it must
+            // be reachable from 32-bit protected mode.
+            let entry = match *note.desc() {
+                [a, b, c, d] => u32::from_le_bytes([a, b, c, d]).into(),
+                [a, b, c, d, e, f, g, h] => u64::from_le_bytes([a, b, c, d, e, f, g, h]),
+                _ => return Err(Error::InvalidPvhEntry),
+            };
+            if entry > u32::MAX.into() {
+                return Err(Error::InvalidPvhEntry);
+            }
+            return Ok(Some(entry));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::importer::BootPageAcceptance;
+    use crate::importer::X86Register;
+    use crate::test_helpers::Segment;
+    use crate::test_helpers::TestImporter;
+    use crate::test_helpers::build_elf;
+    use crate::test_helpers::load_segment;
+    use crate::test_helpers::note_segment;
+    use std::io::Cursor;
+
+    const KERNEL_BASE: u64 = 0x100000;
+    const PVH_ENTRY: u64 = KERNEL_BASE + 0x40;
+
+    fn pvh_note(desc: &[u8]) -> Segment {
+        note_segment(pvh::XEN_ELFNOTE_NAME, pvh::XEN_ELFNOTE_PHYS32_ENTRY, desc)
+    }
+
+    fn pvh_entry(image: &[u8]) -> Result<Option<u64>> {
+        pvh_entrypoint(&mut Cursor::new(image))
+    }
+
+    fn load(image: &[u8]) -> Result<LoadInfo> {
+        load_static_elf::<_, X86Register>(
+            &mut TestImporter::default(),
+            &mut Cursor::new(image),
+            KERNEL_BASE,
+            0,
+            false,
+            BootPageAcceptance::Exclusive,
+            "test",
+        )
+    }
+
+    #[test]
+    fn test_pvh_entrypoint() {
+        // The entry point may be either 32 or 64 bits.
+        let image = build_elf(
+            KERNEL_BASE,
+            &[
+                pvh_note(&(PVH_ENTRY as u32).to_le_bytes()),
+                load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000),
+            ],
+        );
+        assert_eq!(pvh_entry(&image).unwrap(), Some(PVH_ENTRY));
+        let image = build_elf(
+            KERNEL_BASE,
+            &[
+                load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000),
+                pvh_note(&PVH_ENTRY.to_le_bytes()),
+            ],
+        );
+        assert_eq!(pvh_entry(&image).unwrap(), Some(PVH_ENTRY));
+
+        // Other notes are skipped.
+        let image = build_elf(
+            KERNEL_BASE,
+            &[
+                note_segment(b"GNU", pvh::XEN_ELFNOTE_PHYS32_ENTRY, &[0; 4]),
+                note_segment(pvh::XEN_ELFNOTE_NAME, 1, &[0; 4]),
+                pvh_note(&(PVH_ENTRY as u32).to_le_bytes()),
+            ],
+        );
+        assert_eq!(pvh_entry(&image).unwrap(), Some(PVH_ENTRY));
+
+        // An image without the note is not a PVH kernel, but can still be
+        // loaded.
+        let image = build_elf(
+            KERNEL_BASE,
+            &[load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000)],
+        );
+        assert_eq!(pvh_entry(&image).unwrap(), None);
+        let info = load(&image).unwrap();
+        assert_eq!(info.entrypoint, KERNEL_BASE);
+        assert_eq!(info.minimum_address_used, KERNEL_BASE);
+        assert_eq!(info.next_available_address, KERNEL_BASE + 0x2000);
+    }
+
+    #[test]
+    fn test_pvh_entrypoint_invalid() {
+        // The entry point must be a 32- or 64-bit value...
+        let image = build_elf(KERNEL_BASE, &[pvh_note(&[0; 3])]);
+        assert!(matches!(pvh_entry(&image), Err(Error::InvalidPvhEntry)));
+
+        // ...that is reachable from 32-bit mode.
+        let image = build_elf(KERNEL_BASE, &[pvh_note(&(1u64 << 32).to_le_bytes())]);
+        assert!(matches!(pvh_entry(&image), Err(Error::InvalidPvhEntry)));
+
+        // The note segment must be within the image.
+        let mut image = build_elf(KERNEL_BASE, &[pvh_note(&(PVH_ENTRY as u32).to_le_bytes())]);
+        image.truncate(image.len() - 2);
+        assert!(matches!(pvh_entry(&image), Err(Error::InvalidNote(_))));
+
+        // As must the headers.
+        let image = build_elf(KERNEL_BASE, &[]);
+        assert!(matches!(
+            pvh_entry(&image[..image.len() - 1]),
+            Err(Error::ReadFileHeader)
+        ));
+        let mut image = build_elf(KERNEL_BASE, &[pvh_note(&[0; 4])]);
+        image[0] = 0;
+        assert!(matches!(pvh_entry(&image), Err(Error::InvalidFileHeader)));
+        let image = build_elf(KERNEL_BASE, &[pvh_note(&[0; 4])]);
+        assert!(matches!(
+            pvh_entry(&image[..size_of::<elf::FileHeader64<LE>>() + 8]),
+            Err(Error::InvalidProgramHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_segments_out_of_bounds() {
+        // The segment data must be within the image.
+        let mut image = build_elf(
+            KERNEL_BASE,
+            &[load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000)],
+        );
+        image.truncate(image.len() - 1);
+        assert!(matches!(load(&image), Err(Error::ReadKernelImage)));
+
+        // Segments must be above the start address.
+        let image = build_elf(
+            KERNEL_BASE,
+            &[load_segment(KERNEL_BASE - 0x1000, &[0xcc; 0x100], 0x2000)],
+        );
+        assert!(matches!(
+            load(&image),
+            Err(Error::InvalidProgramHeaderMemoryOffset { .. })
+        ));
+
+        // As must the entry point.
+        let image = build_elf(
+            KERNEL_BASE - 1,
+            &[load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000)],
+        );
+        assert!(matches!(
+            load(&image),
+            Err(Error::InvalidEntryAddress { .. })
+        ));
+    }
+}
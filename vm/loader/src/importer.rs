@@ -131,6 +131,7 @@ pub enum X86Register {
     Efer(u64),
     Pat(u64),
     Rbp(u64),
+    Rbx(u64),
     Rip(u64),
     Rsi(u64),
     Rsp(u64),
@@ -228,6 +229,7 @@ fn from(value: X86Register) -> Self {
             X86Register::Efer(v) => igvm_reg::Efer(v),
             X86Register::Pat(v) => igvm_reg::Pat(v),
             X86Register::Rbp(v) => igvm_reg::Rbp(v),
+            X86Register::Rbx(_) => panic!("rbx not supported by igvm"),
             X86Register::Rip(v) => igvm_reg::Rip(v),
             X86Register::Rsi(v) => igvm_reg::Rsi(v),
             X86Register::Rsp(v) => igvm_reg::Rsp(v),
@@ -12,4 +12,6 @@
 pub mod linux;
 pub mod paravisor;
 pub mod pcat;
+#[cfg(test)]
+mod test_helpers;
 pub mod uefi;
@@ -9,6 +9,8 @@
 use crate::importer::BootPageAcceptance;
 use crate::importer::GuestArch;
 use crate::importer::ImageLoad;
+use crate::importer::SegmentRegister;
+use crate::importer::TableRegister;
 use crate::importer::X86Register;
 use aarch64defs::Cpsr64;
 use aarch64defs::IntermPhysAddrSize;
@@ -20,6 +22,7 @@
 use bitfield_struct::bitfield;
 use hvdef::HV_PAGE_SIZE;
 use loader_defs::linux as defs;
+use loader_defs::pvh;
 use page_table::IdentityMapSize;
 use page_table::x64::align_up_to_large_page_size;
 use page_table::x64::align_up_to_page_size;
@@ -27,6 +30,11 @@
 use std::ffi::CString;
 use thiserror::Error;
 use vm_topology::memory::MemoryLayout;
+use x86defs::GdtEntry;
+use x86defs::SegmentAttributes;
+use x86defs::X64_BUSY_TSS_SEGMENT_ATTRIBUTES;
+use x86defs::X64_DEFAULT_CODE_SEGMENT_ATTRIBUTES;
+use x86defs::X64_DEFAULT_DATA_SEGMENT_ATTRIBUTES;
 use zerocopy::FromBytes;
 use zerocopy::FromZeros;
 use zerocopy::Immutable;
@@ -60,40 +68,108 @@ pub fn build_zero_page(
         ..FromZeros::new_zeroed()
     };
 
+    let e820 = build_e820_map(mem_layout, acpi_base, acpi_len);
+    for (entry, &(addr, size, typ)) in p.e820_map.iter_mut().zip(&e820) {
+        *entry = defs::e820entry {
+            addr: addr.into(),
+            size: size.into(),
+            typ: typ.into(),
+        };
+    }
+    p.e820_entries = e820.len() as u8;
+
+    p
+}
+
+/// Returns the e820 memory map, as `(address, size, type)` tuples.
+/// TODO: support different acpi_base other than 0xe0000
+fn build_e820_map(
+    mem_layout: &MemoryLayout,
+    acpi_base: u64,
+    acpi_len: usize,
+) -> Vec<(u64, u64, u32)> {
     let mut ram = mem_layout.ram().iter().cloned();
     let range = ram.next().expect("at least one ram range");
     assert_eq!(range.range.start(), 0);
     assert!(range.range.end() >= 0x100000);
     // TODO: support better e820 building, for now acpi_base must be 0xe0000
     assert_eq!(acpi_base, 0xe0000);
-    p.e820_map[0] = defs::e820entry {
-        addr: 0.into(),
-        size: 0xe0000.into(),
-        typ: defs::E820_RAM.into(),
-    };
-    let aligned_acpi_len = (acpi_len + 0xfff) & !0xfff;
-    p.e820_map[1] = defs::e820entry {
-        addr: 0xe0000.into(),
-        size: (aligned_acpi_len as u64).into(),
-        typ: defs::E820_ACPI.into(),
-    };
-    p.e820_map[2] = defs::e820entry {
-        addr: (0xe0000 + aligned_acpi_len as u64).into(),
-        size: (range.range.end() - 0xe0000 - aligned_acpi_len as u64).into(),
-        typ: defs::E820_RAM.into(),
+    let aligned_acpi_len = ((acpi_len + 0xfff) & !0xfff) as u64;
+    let mut map = vec![
+        (0, 0xe0000, defs::E820_RAM),
+        (0xe0000, aligned_acpi_len, defs::E820_ACPI),
+        (
+            0xe0000 + aligned_acpi_len,
+            range.range.end() - 0xe0000 - aligned_acpi_len,
+            defs::E820_RAM,
+        ),
+    ];
+    map.extend(ram.map(|range| (range.range.start(), range.range.len(), defs::E820_RAM)));
+    map
+}
+
+/// Construct the PVH start info page from the following parameters.
+///
+/// The page contains the [`pvh::hvm_start_info`] structure, followed by the
+/// module list (containing the initrd, if any), followed by the memory map.
+pub fn build_pvh_start_info(
+    address: u64,
+    mem_layout: &MemoryLayout,
+    acpi_base: u64,
+    acpi_len: usize,
+    rdsp_address: u64,
+    cmdline_config: &CommandLineConfig<'_>,
+    initrd: Option<&InitrdInfo>,
+) -> Vec<u8> {
+    const MODLIST_OFFSET: usize = size_of::<pvh::hvm_start_info>();
+    const MEMMAP_OFFSET: usize = MODLIST_OFFSET + size_of::<pvh::hvm_modlist_entry>();
+
+    let memmap: Vec<_> = build_e820_map(mem_layout, acpi_base, acpi_len)
+        .into_iter()
+        .map(|(addr, size, typ)| pvh::hvm_memmap_table_entry {
+            addr,
+            size,
+            typ,
+            reserved: 0,
+        })
+        .collect();
+    assert!(MEMMAP_OFFSET + memmap.as_bytes().len() <= HV_PAGE_SIZE as usize);
+
+    let start_info = pvh::hvm_start_info {
+        magic: pvh::XEN_HVM_START_MAGIC_VALUE,
+        version: pvh::XEN_HVM_START_INFO_VERSION,
+        flags: 0,
+        nr_modules: initrd.is_some() as u32,
+        modlist_paddr: if initrd.is_some() {
+            address + MODLIST_OFFSET as u64
+        } else {
+            0
+        },
+        cmdline_paddr: if cmdline_config.cmdline.as_bytes().is_empty() {
+            0
+        } else {
+            cmdline_config.address
+        },
+        rsdp_paddr: rdsp_address,
+        memmap_paddr: address + MEMMAP_OFFSET as u64,
+        memmap_entries: memmap.len() as u32,
+        reserved: 0,
     };
-    let mut n = 3;
-    for range in ram {
-        p.e820_map[n] = defs::e820entry {
-            addr: range.range.start().into(),
-            size: range.range.len().into(),
-            typ: defs::E820_RAM.into(),
-        };
-        n += 1;
-    }
-    p.e820_entries = n as u8;
 
-    p
+    let mut page = vec![0; HV_PAGE_SIZE as usize];
+    start_info.write_to_prefix(&mut page).unwrap();
+    if let Some(initrd) = initrd {
+        pvh::hvm_modlist_entry {
+            paddr: initrd.gpa,
+            size: initrd.size,
+            cmdline_paddr: 0,
+            reserved: 0,
+        }
+        .write_to_prefix(&mut page[MODLIST_OFFSET..])
+        .unwrap();
+    }
+    memmap.write_to_prefix(&mut page[MEMMAP_OFFSET..]).unwrap();
+    page
 }
 
 #[derive(Debug, Error)]
@@ -294,21 +370,9 @@ pub fn load_kernel_and_initrd_x64<F>(
     })
 }
 
-/// Load the configuration info and registers for the Linux kernel based on the provided LoadInfo.
-///
-/// # Arguments
-/// * `importer` - The importer to use.
-/// * `load_info` - The kernel load info that contains information on where the kernel and initrd are.
-/// * `command_line` - The kernel command line.
-/// * `zero_page` - The kernel zero page.
-/// * `registers` - X86Register config.
-pub fn load_config(
-    importer: &mut impl ImageLoad<X86Register>,
-    load_info: &LoadInfo,
-    command_line: CommandLineConfig<'_>,
-    zero_page: ZeroPageConfig<'_>,
-    acpi: AcpiConfig<'_>,
-    registers: RegisterConfig,
+fn import_command_line(
+    importer: &mut dyn ImageLoad<X86Register>,
+    command_line: &CommandLineConfig<'_>,
 ) -> Result<(), Error> {
     tracing::trace!(command_line.address);
     // Only import the cmdline if it actually contains something.
@@ -327,27 +391,13 @@ pub fn load_config(
             )
             .map_err(Error::Importer)?;
     }
+    Ok(())
+}
 
-    check_address_alignment(registers.gdt_address)?;
-    import_default_gdt(importer, registers.gdt_address / HV_PAGE_SIZE).map_err(Error::Importer)?;
-    check_address_alignment(registers.page_table_address)?;
-    let page_table = build_page_tables_64(
-        registers.page_table_address,
-        0,
-        IdentityMapSize::Size4Gb,
-        None,
-    );
-    assert!(page_table.len() as u64 % HV_PAGE_SIZE == 0);
-    importer
-        .import_pages(
-            registers.page_table_address / HV_PAGE_SIZE,
-            page_table.len() as u64 / HV_PAGE_SIZE,
-            "linux-pagetables",
-            BootPageAcceptance::Exclusive,
-            &page_table,
-        )
-        .map_err(Error::Importer)?;
-
+fn import_acpi(
+    importer: &mut dyn ImageLoad<X86Register>,
+    acpi: &AcpiConfig<'_>,
+) -> Result<(), Error> {
     // NOTE: A whole page is given to the RDSP for simplicity.
     check_address_alignment(acpi.rdsp_address)?;
     check_address_alignment(acpi.tables_address)?;
@@ -370,6 +420,48 @@ pub fn load_config(
             acpi.tables,
         )
         .map_err(Error::Importer)?;
+    Ok(())
+}
+
+/// Load the configuration info and registers for the Linux kernel based on the provided LoadInfo.
+///
+/// # Arguments
+/// * `importer` - The importer to use.
+/// * `load_info` - The kernel load info that contains information on where the kernel and initrd are.
+/// * `command_line` - The kernel command line.
+/// * `zero_page` - The kernel zero page.
+/// * `registers` - X86Register config.
+pub fn load_config(
+    importer: &mut impl ImageLoad<X86Register>,
+    load_info: &LoadInfo,
+    command_line: CommandLineConfig<'_>,
+    zero_page: ZeroPageConfig<'_>,
+    acpi: AcpiConfig<'_>,
+    registers: RegisterConfig,
+) -> Result<(), Error> {
+    import_command_line(importer, &command_line)?;
+
+    check_address_alignment(registers.gdt_address)?;
+    import_default_gdt(importer, registers.gdt_address / HV_PAGE_SIZE).map_err(Error::Importer)?;
+    check_address_alignment(registers.page_table_address)?;
+    let page_table = build_page_tables_64(
+        registers.page_table_address,
+        0,
+        IdentityMapSize::Size4Gb,
+        None,
+    );
+    assert!(page_table.len() as u64 % HV_PAGE_SIZE == 0);
+    importer
+        .import_pages(
+            registers.page_table_address / HV_PAGE_SIZE,
+            page_table.len() as u64 / HV_PAGE_SIZE,
+            "linux-pagetables",
+            BootPageAcceptance::Exclusive,
+            &page_table,
+        )
+        .map_err(Error::Importer)?;
+
+    import_acpi(importer, &acpi)?;
 
     check_address_alignment(zero_page.address)?;
     let boot_params = build_zero_page(
@@ -421,6 +513,140 @@ pub fn load_config(
     Ok(())
 }
 
+/// Import a GDT for entering a PVH kernel, with a flat 32-bit code segment
+/// as entry 1, a flat data segment as entry 2, and a TSS as entry 3.
+fn import_pvh_gdt(
+    importer: &mut dyn ImageLoad<X86Register>,
+    gdt_address: u64,
+) -> Result<(), Error> {
+    const CODE32_ATTRIBUTES: SegmentAttributes = X64_DEFAULT_CODE_SEGMENT_ATTRIBUTES
+        .with_long(false)
+        .with_default(true);
+
+    let entry = |attributes: SegmentAttributes, limit: u32| {
+        let attributes = attributes.as_bits();
+        GdtEntry {
+            limit_low: limit as u16,
+            attr_low: attributes as u8,
+            attr_high: (attributes >> 8) as u8 | ((limit >> 16) & 0xf) as u8,
+            ..GdtEntry::new_zeroed()
+        }
+    };
+    let gdt = [
+        GdtEntry::new_zeroed(),
+        entry(CODE32_ATTRIBUTES, 0xfffff),
+        entry(X64_DEFAULT_DATA_SEGMENT_ATTRIBUTES, 0xfffff),
+        entry(X64_BUSY_TSS_SEGMENT_ATTRIBUTES, 0x67),
+    ];
+
+    check_address_alignment(gdt_address)?;
+    importer
+        .import_pages(
+            gdt_address / HV_PAGE_SIZE,
+            1,
+            "pvh-gdt",
+            BootPageAcceptance::Exclusive,
+            gdt.as_bytes(),
+        )
+        .map_err(Error::Importer)?;
+
+    let segment = |index: usize, attributes: SegmentAttributes, limit: u32| SegmentRegister {
+        selector: (index * size_of::<GdtEntry>()) as u16,
+        base: 0,
+        limit,
+        attributes: attributes.as_bits(),
+    };
+    let ds = segment(2, X64_DEFAULT_DATA_SEGMENT_ATTRIBUTES, 0xffffffff);
+    for register in [
+        X86Register::Gdtr(TableRegister {
+            base: gdt_address,
+            limit: size_of_val(&gdt) as u16 - 1,
+        }),
+        X86Register::Cs(segment(1, CODE32_ATTRIBUTES, 0xffffffff)),
+        X86Register::Ds(ds),
+        X86Register::Es(ds),
+        X86Register::Fs(ds),
+        X86Register::Gs(ds),
+        X86Register::Ss(ds),
+        X86Register::Tr(segment(3, X64_BUSY_TSS_SEGMENT_ATTRIBUTES, 0x67)),
+    ] {
+        importer
+            .import_vp_register(register)
+            .map_err(Error::Importer)?;
+    }
+    Ok(())
+}
+
+/// Load the configuration info and registers for entering an ELF kernel via
+/// its PVH entry point, based on the provided LoadInfo.
+///
+/// The kernel is entered in 32-bit protected mode with paging disabled, with
+/// `ebx` pointing to the start info page, as described by the PVH boot ABI.
+///
+/// # Arguments
+/// * `importer` - The importer to use.
+/// * `load_info` - The kernel load info, whose entrypoint is the PVH entry point.
+/// * `command_line` - The kernel command line.
+/// * `start_info` - The location of the start info page, and the memory map
+///   parameters.
+/// * `acpi` - The acpi config.
+/// * `gdt_address` - The address of the GDT.
+pub fn load_config_pvh(
+    importer: &mut impl ImageLoad<X86Register>,
+    load_info: &LoadInfo,
+    command_line: CommandLineConfig<'_>,
+    start_info: ZeroPageConfig<'_>,
+    acpi: AcpiConfig<'_>,
+    gdt_address: u64,
+) -> Result<(), Error> {
+    import_command_line(importer, &command_line)?;
+    import_pvh_gdt(importer, gdt_address)?;
+    import_acpi(importer, &acpi)?;
+
+    check_address_alignment(start_info.address)?;
+    let page = build_pvh_start_info(
+        start_info.address,
+        start_info.mem_layout,
+        start_info.acpi_base_address,
+        start_info.acpi_len,
+        acpi.rdsp_address,
+        &command_line,
+        load_info.initrd.as_ref(),
+    );
+    importer
+        .import_pages(
+            start_info.address / HV_PAGE_SIZE,
+            1,
+            "pvh-start-info",
+            BootPageAcceptance::Exclusive,
+            &page,
+        )
+        .map_err(Error::Importer)?;
+
+    let mut import_reg = |register| {
+        importer
+            .import_vp_register(register)
+            .map_err(Error::Importer)
+    };
+
+    import_reg(X86Register::Cr0(x86defs::X64_CR0_PE | x86defs::X64_CR0_ET))?;
+    import_reg(X86Register::Cr3(0))?;
+    import_reg(X86Register::Cr4(0))?;
+    import_reg(X86Register::Efer(0))?;
+    import_reg(X86Register::Pat(x86defs::X86X_MSR_DEFAULT_PAT))?;
+
+    // Set rip to the entry point and rbx to the start info page.
+    import_reg(X86Register::Rip(load_info.kernel.entrypoint))?;
+    import_reg(X86Register::Rbx(start_info.address))?;
+
+    // Match the MTRR setup of the Linux boot protocol path.
+    import_reg(X86Register::MtrrDefType(0xc00))?;
+    import_reg(X86Register::MtrrFix64k00000(0x0606060606060606))?;
+    import_reg(X86Register::MtrrFix16k80000(0x0606060606060606))?;
+
+    Ok(())
+}
+
 /// Load a Linux kernel into VTL0.
 ///
 /// # Arguments
@@ -431,9 +657,15 @@ pub fn load_config(
 ///   It cannot contain an entrypoint or program headers that refer to memory below this address.
 /// * `initrd` - The initrd config, optional.
 /// * `command_line` - The kernel command line.
-/// * `zero_page` - The kernel zero page.
+/// * `zero_page` - The kernel zero page. For PVH kernels, the start info page
+///   is placed here instead.
 /// * `acpi` - The acpi config.
-/// * `registers` - X86Register config.
+/// * `registers` - X86Register config. Page tables are not used for PVH kernels.
+///
+/// If the image has a PVH entry point note (`XEN_ELFNOTE_PHYS32_ENTRY`), it is
+/// booted via the PVH boot ABI instead of the 64-bit Linux boot protocol. This
+/// allows booting kernels that don't implement the Linux boot protocol, such
+/// as unikernels.
 pub fn load_x86<F>(
     importer: &mut impl ImageLoad<X86Register>,
     kernel_image: &mut F,
@@ -447,17 +679,31 @@ pub fn load_x86<F>(
 where
     F: std::io::Read + std::io::Seek,
 {
-    let load_info =
+    let pvh_entrypoint = crate::elf::pvh_entrypoint(kernel_image).map_err(Error::ElfLoader)?;
+    let mut load_info =
         load_kernel_and_initrd_x64(importer, kernel_image, kernel_minimum_start_address, initrd)?;
 
-    load_config(
-        importer,
-        &load_info,
-        command_line,
-        zero_page,
-        acpi,
-        registers,
-    )?;
+    if let Some(entrypoint) = pvh_entrypoint {
+        tracing::debug!(entrypoint, "booting kernel via PVH entry point");
+        load_info.kernel.entrypoint = entrypoint;
+        load_config_pvh(
+            importer,
+            &load_info,
+            command_line,
+            zero_page,
+            acpi,
+            registers.gdt_address,
+        )?;
+    } else {
+        load_config(
+            importer,
+            &load_info,
+            command_line,
+            zero_page,
+            acpi,
+            registers,
+        )?;
+    }
 
     Ok(load_info)
 }
@@ -736,3 +982,153 @@ pub fn set_direct_boot_registers_arm64(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TestImporter;
+    use crate::test_helpers::build_elf;
+    use crate::test_helpers::load_segment;
+    use crate::test_helpers::note_segment;
+    use memory_range::MemoryRange;
+    use std::io::Cursor;
+
+    const KERNEL_BASE: u64 = 0x100000;
+    const PVH_ENTRY: u64 = KERNEL_BASE + 0x40;
+    const ZERO_PAGE: u64 = 0x7000;
+    const CMDLINE: u64 = 0x8000;
+    const GDT: u64 = 0x9000;
+    const ACPI_BASE: u64 = 0xe0000;
+
+    /// Loads `image` with an initrd, returning the importer.
+    fn load(image: &[u8]) -> (LoadInfo, TestImporter) {
+        let mem_layout = MemoryLayout::new(
+            0x1_0000_0000,
+            &[MemoryRange::new(0xc000_0000..0x1_0000_0000)],
+            None,
+        )
+        .unwrap();
+        let cmdline = CString::new("console=ttyS0").unwrap();
+        let mut importer = TestImporter::default();
+        let load_info = load_x86(
+            &mut importer,
+            &mut Cursor::new(image),
+            KERNEL_BASE,
+            Some(InitrdConfig {
+                initrd_address: InitrdAddressType::AfterKernel,
+                initrd: &[0xaa; 0x1800],
+            }),
+            CommandLineConfig {
+                address: CMDLINE,
+                cmdline: &cmdline,
+            },
+            ZeroPageConfig {
+                address: ZERO_PAGE,
+                mem_layout: &mem_layout,
+                acpi_base_address: ACPI_BASE,
+                acpi_len: 0x2000,
+            },
+            AcpiConfig {
+                rdsp_address: ACPI_BASE,
+                rdsp: &[0; 36],
+                tables_address: ACPI_BASE + 0x1000,
+                tables: &[0; 0x100],
+            },
+            RegisterConfig {
+                gdt_address: GDT,
+                page_table_address: 0x10000,
+            },
+        )
+        .unwrap();
+        (load_info, importer)
+    }
+
+    #[test]
+    fn test_load_x86_pvh() {
+        let image = build_elf(
+            KERNEL_BASE,
+            &[
+                load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000),
+                note_segment(
+                    pvh::XEN_ELFNOTE_NAME,
+                    pvh::XEN_ELFNOTE_PHYS32_ENTRY,
+                    &(PVH_ENTRY as u32).to_le_bytes(),
+                ),
+            ],
+        );
+        let (load_info, importer) = load(&image);
+        assert_eq!(load_info.kernel.entrypoint, PVH_ENTRY);
+        let initrd = load_info.initrd.unwrap();
+
+        // The kernel is entered in 32-bit protected mode without paging.
+        for register in [
+            X86Register::Rip(PVH_ENTRY),
+            X86Register::Rbx(ZERO_PAGE),
+            X86Register::Cr0(x86defs::X64_CR0_PE | x86defs::X64_CR0_ET),
+            X86Register::Efer(0),
+        ] {
+            assert!(importer.registers.contains(&register), "{register:?}");
+        }
+        assert!(importer.registers.iter().any(|register| matches!(
+            register,
+            X86Register::Cs(cs) if cs.selector == 8 && cs.limit == 0xffffffff
+        )));
+        assert!(importer.pages("pvh-gdt").is_some());
+        assert!(importer.pages("linux-pagetables").is_none());
+        assert!(importer.pages("linux-zeropage").is_none());
+
+        let page = importer.pages("pvh-start-info").unwrap();
+        assert_eq!(page.page_base, ZERO_PAGE / HV_PAGE_SIZE);
+        let (start_info, _) = pvh::hvm_start_info::read_from_prefix(&page.data).unwrap();
+        assert_eq!(start_info.magic, pvh::XEN_HVM_START_MAGIC_VALUE);
+        assert_eq!(start_info.version, pvh::XEN_HVM_START_INFO_VERSION);
+        assert_eq!(start_info.cmdline_paddr, CMDLINE);
+        assert_eq!(start_info.rsdp_paddr, ACPI_BASE);
+
+        let offset = |paddr: u64| (paddr - ZERO_PAGE) as usize;
+        assert_eq!(start_info.nr_modules, 1);
+        let (module, _) = pvh::hvm_modlist_entry::read_from_prefix(
+            &page.data[offset(start_info.modlist_paddr)..],
+        )
+        .unwrap();
+        assert_eq!((module.paddr, module.size), (initrd.gpa, 0x1800));
+
+        let (memmap, _) = <[pvh::hvm_memmap_table_entry]>::ref_from_prefix_with_elems(
+            &page.data[offset(start_info.memmap_paddr)..],
+            start_info.memmap_entries as usize,
+        )
+        .unwrap();
+        let memmap = memmap
+            .iter()
+            .map(|entry| (entry.addr, entry.size, entry.typ))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            memmap,
+            [
+                (0, ACPI_BASE, defs::E820_RAM),
+                (ACPI_BASE, 0x2000, defs::E820_ACPI),
+                (
+                    ACPI_BASE + 0x2000,
+                    0xc000_0000 - ACPI_BASE - 0x2000,
+                    defs::E820_RAM
+                ),
+                (0x1_0000_0000, 0x4000_0000, defs::E820_RAM),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_x86_without_pvh_note() {
+        let image = build_elf(
+            KERNEL_BASE,
+            &[load_segment(KERNEL_BASE, &[0xcc; 0x100], 0x2000)],
+        );
+        let (load_info, importer) = load(&image);
+        assert_eq!(load_info.kernel.entrypoint, KERNEL_BASE);
+        assert!(importer.registers.contains(&X86Register::Rip(KERNEL_BASE)));
+        assert!(importer.registers.contains(&X86Register::Rsi(ZERO_PAGE)));
+        assert!(importer.pages("linux-pagetables").is_some());
+        assert!(importer.pages("linux-zeropage").is_some());
+        assert!(importer.pages("pvh-start-info").is_none());
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for unit tests.
+
+use crate::importer::BootPageAcceptance;
+use crate::importer::IgvmParameterType;
+use crate::importer::ImageLoad;
+use crate::importer::IsolationConfig;
+use crate::importer::IsolationType;
+use crate::importer::ParameterAreaIndex;
+use crate::importer::StartupMemoryType;
+use crate::importer::X86Register;
+use object::LittleEndian as LE;
+use object::U16;
+use object::U32;
+use object::U64;
+use object::elf;
+use object::pod::bytes_of;
+
+/// A segment of an ELF image built by [`build_elf`].
+pub struct Segment {
+    pub p_type: u32,
+    pub paddr: u64,
+    pub memsz: u64,
+    pub data: Vec<u8>,
+}
+
+/// Returns a loadable segment at `paddr` containing `data`.
+pub fn load_segment(paddr: u64, data: &[u8], memsz: u64) -> Segment {
+    Segment {
+        p_type: elf::PT_LOAD,
+        paddr,
+        memsz,
+        data: data.to_vec(),
+    }
+}
+
+/// Returns a note segment containing a single note.
+pub fn note_segment(name: &[u8], n_type: u32, desc: &[u8]) -> Segment {
+    let pad = |data: &mut Vec<u8>| data.resize(data.len().next_multiple_of(4), 0);
+    let header = elf::NoteHeader64::<LE> {
+        n_namesz: U32::new(LE, name.len() as u32 + 1),
+        n_descsz: U32::new(LE, desc.len() as u32),
+        n_type: U32::new(LE, n_type),
+    };
+    let mut data = bytes_of(&header).to_vec();
+    data.extend_from_slice(name);
+    data.push(0);
+    pad(&mut data);
+    data.extend_from_slice(desc);
+    pad(&mut data);
+    Segment {
+        p_type: elf::PT_NOTE,
+        paddr: 0,
+        memsz: 0,
+        data,
+    }
+}
+
+/// Builds a 64-bit x86 ELF executable with entry point `entry`. The segment
+/// data follows the headers in order, so truncating the image truncates the
+/// last segment.
+pub fn build_elf(entry: u64, segments: &[Segment]) -> Vec<u8> {
+    let phoff = size_of::<elf::FileHeader64<LE>>();
+    let phentsize = size_of::<elf::ProgramHeader64<LE>>();
+    let header = elf::FileHeader64::<LE> {
+        e_ident: elf::Ident {
+            magic: elf::ELFMAG,
+            class: elf::ELFCLASS64,
+            data: elf::ELFDATA2LSB,
+            version: elf::EV_CURRENT,
+            os_abi: elf::ELFOSABI_NONE,
+            abi_version: 0,
+            padding: [0; 7],
+        },
+        e_type: U16::new(LE, elf::ET_EXEC),
+        e_machine: U16::new(LE, elf::EM_X86_64),
+        e_version: U32::new(LE, elf::EV_CURRENT.into()),
+        e_entry: U64::new(LE, entry),
+        e_phoff: U64::new(LE, phoff as u64),
+        e_shoff: U64::new(LE, 0),
+        e_flags: U32::new(LE, 0),
+        e_ehsize: U16::new(LE, phoff as u16),
+        e_phentsize: U16::new(LE, phentsize as u16),
+        e_phnum: U16::new(LE, segments.len() as u16),
+        e_shentsize: U16::new(LE, 0),
+        e_shnum: U16::new(LE, 0),
+        e_shstrndx: U16::new(LE, 0),
+    };
+
+    let mut image = bytes_of(&header).to_vec();
+    let mut offset = (phoff + phentsize * segments.len()).next_multiple_of(8);
+    for segment in segments {
+        let phdr = elf::ProgramHeader64::<LE> {
+            p_type: U32::new(LE, segment.p_type),
+            p_flags: U32::new(LE, elf::PF_R | elf::PF_X),
+            p_offset: U64::new(LE, offset as u64),
+            p_vaddr: U64::new(LE, segment.paddr),
+            p_paddr: U64::new(LE, segment.paddr),
+            p_filesz: U64::new(LE, segment.data.len() as u64),
+            p_memsz: U64::new(LE, segment.memsz),
+            p_align: U64::new(LE, if segment.p_type == elf::PT_NOTE { 4 } else { 8 }),
+        };
+        image.extend_from_slice(bytes_of(&phdr));
+        offset = (offset + segment.data.len()).next_multiple_of(8);
+    }
+    for segment in segments {
+        image.resize(image.len().next_multiple_of(8), 0);
+        image.extend_from_slice(&segment.data);
+    }
+    image
+}
+
+/// A page imported by [`TestImporter`].
+pub struct ImportedPages {
+    pub page_base: u64,
+    pub page_count: u64,
+    pub tag: String,
+    pub data: Vec<u8>,
+}
+
+/// An importer that records the pages and registers that are imported.
+#[derive(Default)]
+pub struct TestImporter {
+    pub pages: Vec<ImportedPages>,
+    pub registers: Vec<X86Register>,
+}
+
+impl TestImporter {
+    /// Returns the data imported with `tag`.
+    pub fn pages(&self, tag: &str) -> Option<&ImportedPages> {
+        self.pages.iter().find(|pages| pages.tag == tag)
+    }
+}
+
+impl ImageLoad<X86Register> for TestImporter {
+    fn isolation_config(&self) -> IsolationConfig {
+        IsolationConfig {
+            paravisor_present: false,
+            isolation_type: IsolationType::None,
+            shared_gpa_boundary_bits: None,
+        }
+    }
+
+    fn create_parameter_area(
+        &mut self,
+        _page_base: u64,
+        _page_count: u32,
+        _debug_tag: &str,
+    ) -> anyhow::Result<ParameterAreaIndex> {
+        unimplemented!()
+    }
+
+    fn create_parameter_area_with_data(
+        &mut self,
+        _page_base: u64,
+        _page_count: u32,
+        _debug_tag: &str,
+        _initial_data: &[u8],
+    ) -> anyhow::Result<ParameterAreaIndex> {
+        unimplemented!()
+    }
+
+    fn import_parameter(
+        &mut self,
+        _parameter_area: ParameterAreaIndex,
+        _byte_offset: u32,
+        _parameter_type: IgvmParameterType,
+    ) -> anyhow::Result<()> {
+        unimplemented!()
+    }
+
+    fn import_pages(
+        &mut self,
+        page_base: u64,
+        page_count: u64,
+        debug_tag: &str,
+        _acceptance: BootPageAcceptance,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        assert!(data.len() as u64 <= page_count * hvdef::HV_PAGE_SIZE);
+        self.pages.push(ImportedPages {
+            page_base,
+            page_count,
+            tag: debug_tag.to_owned(),
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn import_vp_register(&mut self, register: X86Register) -> anyhow::Result<()> {
+        self.registers.push(register);
+        Ok(())
+    }
+
+    fn verify_startup_memory_available(
+        &mut self,
+        _page_base: u64,
+        _page_count: u64,
+        _memory_type: StartupMemoryType,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn set_vp_context_page(&mut self, _page_base: u64) -> anyhow::Result<()> {
+        unimplemented!()
+    }
+
+    fn relocation_region(
+        &mut self,
+        _gpa: u64,
+        _size_bytes: u64,
+        _relocation_alignment: u64,
+        _minimum_relocation_gpa: u64,
+        _maximum_relocation_gpa: u64,
+        _apply_rip_offset: bool,
+        _apply_gdtr_offset: bool,
+        _vp_index: u16,
+    ) -> anyhow::Result<()> {
+        unimplemented!()
+    }
+
+    fn page_table_relocation(
+        &mut self,
+        _page_table_gpa: u64,
+        _size_pages: u64,
+        _used_pages: u64,
+        _vp_index: u16,
+    ) -> anyhow::Result<()> {
+        unimplemented!()
+    }
+
+    fn set_imported_regions_config_page(&mut self, _page_base: u64) {
+        unimplemented!()
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! VHDX file format definitions, per the [MS-VHDX] specification.
+//!
+//! [MS-VHDX]: https://learn.microsoft.com/openspecs/windows_protocols/ms-vhdx
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+#![no_std]
+
+use guid::Guid;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+pub const KB: u64 = 1024;
+pub const MB: u64 = 1024 * KB;
+
+/// All structure offsets and lengths in the file are aligned to this.
+pub const ALIGNMENT: u64 = MB;
+
+pub const FILE_IDENTIFIER_OFFSET: u64 = 0;
+pub const HEADER_OFFSETS: [u64; 2] = [64 * KB, 128 * KB];
+pub const HEADER_SIZE: usize = 4 * KB as usize;
+pub const REGION_TABLE_OFFSETS: [u64; 2] = [192 * KB, 256 * KB];
+pub const REGION_TABLE_SIZE: usize = 64 * KB as usize;
+
+/// The size of the sector bitmap block for each chunk.
+pub const SECTOR_BITMAP_BLOCK_SIZE: u64 = MB;
+
+pub const MIN_BLOCK_SIZE: u32 = MB as u32;
+pub const MAX_BLOCK_SIZE: u32 = 256 * MB as u32;
+pub const DEFAULT_BLOCK_SIZE: u32 = 32 * MB as u32;
+pub const DEFAULT_LOG_SIZE: u32 = MB as u32;
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct FileIdentifier {
+    pub signature: u64,
+    /// UTF-16 name of the application that created the file.
+    pub creator: [u16; 256],
+}
+
+impl FileIdentifier {
+    pub const SIGNATURE: u64 = u64::from_le_bytes(*b"vhdxfile");
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Header {
+    pub signature: u32,
+    pub checksum: u32,
+    pub sequence_number: u64,
+    pub file_write_guid: Guid,
+    pub data_write_guid: Guid,
+    pub log_guid: Guid,
+    pub log_version: u16,
+    pub version: u16,
+    pub log_length: u32,
+    pub log_offset: u64,
+    pub reserved: [u8; 4016],
+}
+
+const _: () = assert!(size_of::<Header>() == HEADER_SIZE);
+
+impl Header {
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"head");
+    pub const VERSION: u16 = 1;
+    pub const LOG_VERSION: u16 = 0;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RegionTableHeader {
+    pub signature: u32,
+    pub checksum: u32,
+    pub entry_count: u32,
+    pub reserved: u32,
+}
+
+impl RegionTableHeader {
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"regi");
+    pub const MAX_ENTRIES: u32 = 2047;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RegionTableEntry {
+    pub guid: Guid,
+    pub file_offset: u64,
+    pub length: u32,
+    pub flags: u32,
+}
+
+impl RegionTableEntry {
+    pub const FLAG_REQUIRED: u32 = 1;
+}
+
+pub const REGION_BAT: Guid = guid::guid!("2dc27766-f623-4200-9d64-115e9bfd4a08");
+pub const REGION_METADATA: Guid = guid::guid!("8b7ca206-4790-4b9a-b8fe-575f050f886e");
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct MetadataTableHeader {
+    pub signature: u64,
+    pub reserved: u16,
+    pub entry_count: u16,
+    pub reserved2: [u32; 5],
+}
+
+impl MetadataTableHeader {
+    pub const SIGNATURE: u64 = u64::from_le_bytes(*b"metadata");
+    pub const MAX_ENTRIES: u16 = 2047;
+    /// Metadata items are stored after the 64KB table.
+    pub const TABLE_SIZE: u32 = 64 * KB as u32;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct MetadataTableEntry {
+    pub item_id: Guid,
+    /// Offset of the item, relative to the start of the metadata region.
+    pub offset: u32,
+    pub length: u32,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+impl MetadataTableEntry {
+    pub const FLAG_IS_USER: u32 = 1;
+    pub const FLAG_IS_VIRTUAL_DISK: u32 = 2;
+    pub const FLAG_IS_REQUIRED: u32 = 4;
+}
+
+pub const METADATA_FILE_PARAMETERS: Guid = guid::guid!("caa16737-fa36-4d43-b3b6-33f0aa44e76b");
+pub const METADATA_VIRTUAL_DISK_SIZE: Guid = guid::guid!("2fa54224-cd1b-4876-b211-5dbed83bf4b8");
+pub const METADATA_VIRTUAL_DISK_ID: Guid = guid::guid!("beca12ab-b2e6-4523-93ef-c309e000c746");
+pub const METADATA_LOGICAL_SECTOR_SIZE: Guid = guid::guid!("8141bf1d-a96f-4709-ba47-f233a8faab5f");
+pub const METADATA_PHYSICAL_SECTOR_SIZE: Guid = guid::guid!("cda348c7-445d-4471-9cc9-e9885251c556");
+pub const METADATA_PARENT_LOCATOR: Guid = guid::guid!("a8d35f2d-b30b-454d-abf7-d3d84834ab0c");
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct FileParameters {
+    pub block_size: u32,
+    pub flags: u32,
+}
+
+impl FileParameters {
+    pub const FLAG_LEAVE_BLOCKS_ALLOCATED: u32 = 1;
+    pub const FLAG_HAS_PARENT: u32 = 2;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ParentLocatorHeader {
+    pub locator_type: Guid,
+    pub reserved: u16,
+    pub key_value_count: u16,
+}
+
+/// The locator type for VHDX parents.
+pub const PARENT_LOCATOR_VHDX: Guid = guid::guid!("b04aefb7-d19e-4a81-b789-25b8e9445913");
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ParentLocatorEntry {
+    /// Offset of the UTF-16 key, relative to the start of the locator.
+    pub key_offset: u32,
+    /// Offset of the UTF-16 value, relative to the start of the locator.
+    pub value_offset: u32,
+    pub key_length: u16,
+    pub value_length: u16,
+}
+
+pub const PARENT_KEY_LINKAGE: &str = "parent_linkage";
+pub const PARENT_KEY_LINKAGE2: &str = "parent_linkage2";
+pub const PARENT_KEY_RELATIVE_PATH: &str = "relative_path";
+pub const PARENT_KEY_VOLUME_PATH: &str = "volume_path";
+pub const PARENT_KEY_ABSOLUTE_WIN32_PATH: &str = "absolute_win32_path";
+
+/// A block allocation table entry.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct BatEntry(pub u64);
+
+impl BatEntry {
+    const STATE_MASK: u64 = 0x7;
+    const FILE_OFFSET_MB_SHIFT: u32 = 20;
+
+    pub fn new(state: u8, file_offset: u64) -> Self {
+        assert!(file_offset % MB == 0);
+        Self((file_offset / MB) << Self::FILE_OFFSET_MB_SHIFT | state as u64)
+    }
+
+    pub fn state(&self) -> u8 {
+        (self.0 & Self::STATE_MASK) as u8
+    }
+
+    pub fn file_offset(&self) -> u64 {
+        (self.0 >> Self::FILE_OFFSET_MB_SHIFT) * MB
+    }
+}
+
+/// Payload block states.
+pub mod block_state {
+    pub const NOT_PRESENT: u8 = 0;
+    pub const UNDEFINED: u8 = 1;
+    pub const ZERO: u8 = 2;
+    pub const UNMAPPED: u8 = 3;
+    pub const FULLY_PRESENT: u8 = 6;
+    pub const PARTIALLY_PRESENT: u8 = 7;
+}
+
+/// Sector bitmap block states.
+pub mod bitmap_state {
+    pub const NOT_PRESENT: u8 = 0;
+    pub const PRESENT: u8 = 6;
+}
+
+/// Returns the number of payload blocks covered by each sector bitmap block.
+pub fn chunk_ratio(logical_sector_size: u32, block_size: u32) -> u64 {
+    ((1 << 23) * logical_sector_size as u64) / block_size as u64
+}
+
+pub const LOG_SECTOR_SIZE: usize = 4 * KB as usize;
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogEntryHeader {
+    pub signature: u32,
+    pub checksum: u32,
+    pub entry_length: u32,
+    pub tail: u32,
+    pub sequence_number: u64,
+    pub descriptor_count: u32,
+    pub reserved: u32,
+    pub log_guid: Guid,
+    pub flushed_file_offset: u64,
+    pub last_file_offset: u64,
+}
+
+impl LogEntryHeader {
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"loge");
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogDataDescriptor {
+    pub signature: u32,
+    pub trailing_bytes: [u8; 4],
+    pub leading_bytes: [u8; 8],
+    pub file_offset: u64,
+    pub sequence_number: u64,
+}
+
+impl LogDataDescriptor {
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"desc");
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogZeroDescriptor {
+    pub signature: u32,
+    pub reserved: u32,
+    pub zero_length: u64,
+    pub file_offset: u64,
+    pub sequence_number: u64,
+}
+
+impl LogZeroDescriptor {
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"zero");
+}
+
+pub const LOG_DESCRIPTOR_SIZE: usize = 32;
+const _: () = assert!(size_of::<LogDataDescriptor>() == LOG_DESCRIPTOR_SIZE);
+const _: () = assert!(size_of::<LogZeroDescriptor>() == LOG_DESCRIPTOR_SIZE);
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogDataSector {
+    pub signature: u32,
+    pub sequence_high: u32,
+    pub data: [u8; 4084],
+    pub sequence_low: u32,
+}
+
+const _: () = assert!(size_of::<LogDataSector>() == LOG_SECTOR_SIZE);
+
+impl LogDataSector {
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"data");
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Computes the CRC-32C (Castagnoli) checksum used throughout the format.
+pub fn crc32c(data: &[u8]) -> u32 {
+    !crc32c_update(!0, data)
+}
+
+/// Computes the checksum of a structure whose 32-bit checksum field is at
+/// byte offset 4, treating the field as zero.
+pub fn compute_checksum(data: &[u8]) -> u32 {
+    let crc = crc32c_update(!0, &data[..4]);
+    let crc = crc32c_update(crc, &[0; 4]);
+    !crc32c_update(crc, &data[8..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    }
+
+    #[test]
+    fn checksum_skips_field() {
+        let mut data = [0x5au8; 64];
+        let expected = {
+            let mut data = data;
+            data[4..8].fill(0);
+            crc32c(&data)
+        };
+        data[4..8].copy_from_slice(&0x12345678u32.to_le_bytes());
+        assert_eq!(compute_checksum(&data), expected);
+    }
+
+    #[test]
+    fn bat_entry() {
+        let entry = BatEntry::new(block_state::FULLY_PRESENT, 3 * MB);
+        assert_eq!(entry.state(), block_state::FULLY_PRESENT);
+        assert_eq!(entry.file_offset(), 3 * MB);
+        assert_eq!(entry.0, (3 << 20) | 6);
+    }
+}
@@ -2488,6 +2488,16 @@ fn register_doorbell(
     ) -> io::Result<Box<dyn Send + Sync>>;
 }
 
+/// Trait implemented to release guest RAM back to the host.
+pub trait DiscardRam: Send + Sync {
+    /// Discards the contents of the RAM in `len` bytes at `gpa`, releasing the
+    /// backing memory to the host. The guest reads zeroes from the range
+    /// afterward.
+    ///
+    /// Portions of the range that are not RAM are ignored.
+    fn discard_ram(&self, gpa: u64, len: u64) -> io::Result<()>;
+}
+
 /// Trait to map a ROM at one or more locations in guest memory.
 pub trait MapRom: Send + Sync {
     /// Maps the specified portion of the ROM into guest memory at `gpa`.
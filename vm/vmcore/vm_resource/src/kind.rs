@@ -81,6 +81,13 @@ impl ResourceKind for ScsiDeviceHandleKind {
     const NAME: &'static str = "scsi_device";
 }
 
+/// A resource kind for USB devices.
+pub enum UsbDeviceHandleKind {}
+
+impl ResourceKind for UsbDeviceHandleKind {
+    const NAME: &'static str = "usb_device";
+}
+
 /// A resource kind for framebuffer memory that can be mapped into a VM.
 pub enum FramebufferHandleKind {}
 
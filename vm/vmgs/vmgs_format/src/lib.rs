@@ -46,6 +46,7 @@ pub enum FileId: u32 {
         GUEST_WATCHDOG = 10,
         HW_KEY_PROTECTOR = 11,
         GUEST_SECRET_KEY = 13,
+        HIBERNATE_STATE = 14,
 
         EXTENDED_FILE_TABLE = 63,
     }
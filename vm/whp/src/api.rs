@@ -163,6 +163,14 @@ pub fn WHvSetVirtualProcessorState(
     ) -> HRESULT;
 
 
+    pub fn WHvQueryGpaRangeDirtyBitmap(
+        partition: WHV_PARTITION_HANDLE,
+        guest_address: u64,
+        range_size_in_bytes: u64,
+        bitmap: *mut u64,
+        bitmap_size_in_bytes: u32,
+    ) -> HRESULT;
+
     pub fn WHvAdviseGpaRange(
         partition: WHV_PARTITION_HANDLE,
         ranges: *const WHV_MEMORY_RANGE_ENTRY,
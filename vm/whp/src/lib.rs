@@ -556,6 +556,36 @@ pub fn unmap_range(&self, addr: u64, size: u64) -> Result<()> {
         unsafe { check_hresult(api::WHvUnmapGpaRange(self.handle, addr, size)) }
     }
 
+    /// Gets the dirty state of each page in a range mapped with
+    /// [`abi::WHvMapGpaRangeFlagTrackDirtyPages`] and marks them clean.
+    ///
+    /// Bit `n` of `bitmap` is set if the page at `addr + n * 4096` was
+    /// written. If `bitmap` is `None`, the pages are just marked clean.
+    pub fn query_gpa_range_dirty_bitmap(
+        &self,
+        addr: u64,
+        size: u64,
+        bitmap: Option<&mut [u64]>,
+    ) -> Result<()> {
+        let (ptr, len) = match bitmap {
+            Some(bitmap) => {
+                assert!(bitmap.len() as u64 * 64 >= size.div_ceil(4096));
+                (bitmap.as_mut_ptr(), size_of_val(bitmap))
+            }
+            None => (null_mut(), 0),
+        };
+        // SAFETY: `ptr` is either null or valid for writes of `len` bytes.
+        unsafe {
+            check_hresult(api::WHvQueryGpaRangeDirtyBitmap(
+                self.handle,
+                addr,
+                size,
+                ptr,
+                len.try_into().unwrap(),
+            ))
+        }
+    }
+
     pub fn populate_ranges(
         &self,
         ranges: &[abi::WHV_MEMORY_RANGE_ENTRY],
@@ -14,6 +14,7 @@
 use chipset::ioapic;
 use chipset::psp;
 use inspect::Inspect;
+use memory_range::MemoryRange;
 use std::collections::BTreeMap;
 use vm_topology::memory::MemoryLayout;
 use vm_topology::processor::ArchTopology;
@@ -29,6 +30,9 @@ pub struct BuiltAcpiTables {
     pub rdsp: Vec<u8>,
     /// The remaining tables pointed to by the RDSP.
     pub tables: Vec<u8>,
+    /// The guest physical address of the FACS, which holds the guest's
+    /// waking vector.
+    pub facs: u64,
 }
 
 /// Builder to construct a set of [`BuiltAcpiTables`]
@@ -52,6 +56,16 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     pub with_pit: bool,
     /// If a psp is present.
     pub with_psp: bool,
+    /// If the S3 (suspend-to-RAM) sleep state is advertised to the guest.
+    pub with_s3: bool,
+    /// If the S4 (hibernate) sleep state is advertised to the guest.
+    pub with_s4: bool,
+    /// The ECAM window of the PCI bus, starting at bus 0, if any.
+    ///
+    /// If set, then the MCFG table will be generated.
+    pub pci_ecam: Option<MemoryRange>,
+    /// An additional SSDT to include in the tables, if any.
+    pub ssdt: Option<&'a [u8]>,
     /// base address of dynamic power management device registers
     pub pm_base: u16,
     /// ACPI IRQ number
@@ -380,6 +394,28 @@ pub fn build_acpi_tables<F>(&self, gpa: u64, add_devices_to_dsdt: F) -> BuiltAcp
             b"\\_S0",
             &dsdt::Package(vec![0, 0]),
         ));
+        if self.with_s3 {
+            // Name(\_S3, Package(2){2, 2})
+            let slp_typ = dsdt::encode_integer(chipset::pm::SLEEP_TYPE_S3.into());
+            dsdt_data.add_object(&dsdt::NamedObject::new(
+                b"\\_S3",
+                &dsdt::StructuredPackage {
+                    elem_count: 2,
+                    elem_data: [slp_typ.as_slice(), slp_typ.as_slice()].concat(),
+                },
+            ));
+        }
+        if self.with_s4 {
+            // Name(\_S4, Package(2){1, 1})
+            let slp_typ = dsdt::encode_integer(chipset::pm::SLEEP_TYPE_S4.into());
+            dsdt_data.add_object(&dsdt::NamedObject::new(
+                b"\\_S4",
+                &dsdt::StructuredPackage {
+                    elem_count: 2,
+                    elem_data: [slp_typ.as_slice(), slp_typ.as_slice()].concat(),
+                },
+            ));
+        }
         // Name(\_S5, Package(2){0, 0})
         dsdt_data.add_object(&dsdt::NamedObject::new(
             b"\\_S5",
@@ -420,6 +456,7 @@ pub fn build_acpi_tables_custom_dsdt(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiT
     fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
         let mut b = acpi::builder::Builder::new(gpa + 0x1000, OEM_INFO);
 
+        let facs = b.append_facs(&acpi_spec::facs::Facs::new());
         let dsdt = b.append_raw(dsdt);
 
         b.append(&acpi::builder::Table::new(
@@ -434,6 +471,7 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
                     | acpi_spec::fadt::FADT_TMR_VAL_EXT
                     | acpi_spec::fadt::FADT_RESET_REG_SUP
                     | acpi_spec::fadt::FADT_USE_PLATFORM_CLOCK,
+                x_firmware_ctrl: facs,
                 x_dsdt: dsdt,
                 sci_int: self.acpi_irq as u16,
                 p_lvl2_lat: 101,  // disable C2
@@ -532,10 +570,26 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
         if self.cache_topology.is_some() {
             self.with_pptt(|t| b.append(t));
         }
+        if let Some(ecam) = self.pci_ecam {
+            // Each bus takes 1MB of ECAM space.
+            let end_bus = ((ecam.len() >> 20) - 1).try_into().unwrap_or(u8::MAX);
+            b.append(&acpi::builder::Table::new_dyn(
+                acpi_spec::mcfg::MCFG_REVISION,
+                None,
+                &acpi_spec::mcfg::McfgHeader::new(),
+                &[
+                    acpi_spec::mcfg::McfgSegmentBusRange::new(ecam.start(), 0, 0, end_bus)
+                        .as_bytes(),
+                ],
+            ));
+        }
+        if let Some(ssdt) = self.ssdt {
+            b.append_raw(ssdt);
+        }
 
         let (rdsp, tables) = b.build();
 
-        BuiltAcpiTables { rdsp, tables }
+        BuiltAcpiTables { rdsp, tables, facs }
     }
 
     /// Helper method to construct an MADT without constructing the rest of
@@ -564,11 +618,11 @@ pub fn build_pptt(&self) -> Vec<u8> {
 mod test {
     use super::*;
     use acpi_spec::madt::MadtParser;
-    use memory_range::MemoryRange;
     use virt::VpIndex;
     use virt::VpInfo;
     use vm_topology::processor::TopologyBuilder;
     use vm_topology::processor::x86::X86VpInfo;
+    use zerocopy::FromBytes;
 
     const KB: u64 = 1024;
     const MB: u64 = 1024 * KB;
@@ -596,6 +650,10 @@ fn new_builder<'a>(
             with_pic: false,
             with_pit: false,
             with_psp: false,
+            with_s3: false,
+            with_s4: false,
+            pci_ecam: None,
+            ssdt: None,
             pm_base: 1234,
             acpi_irq: 2,
         }
@@ -641,4 +699,21 @@ fn test_basic_madt_cpu() {
             apic_ids.iter().map(|e| Some(*e)).collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn test_facs() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(1).unwrap();
+        let builder = new_builder(&mem, &topology);
+        let gpa = 0xe0000;
+        let tables = builder.build_acpi_tables(gpa, |_, _| {});
+
+        assert_eq!(tables.facs % acpi_spec::facs::FACS_ALIGNMENT as u64, 0);
+        let offset = (tables.facs - (gpa + 0x1000)) as usize;
+        let facs = acpi_spec::facs::Facs::read_from_prefix(&tables.tables[offset..])
+            .unwrap()
+            .0;
+        assert_eq!(facs.signature, *b"FACS");
+        assert_eq!(facs.firmware_waking_vector, 0);
+    }
 }
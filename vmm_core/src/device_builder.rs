@@ -17,9 +17,13 @@
 use vmcore::vm_task::VmTaskDriverSource;
 use vmcore::vpci_msi::VpciInterruptMapper;
 use vmotherboard::ChipsetBuilder;
+use vpci::bus::VpciHotPlugHandle;
 
 /// Resolves a PCI device resource, builds the corresponding device, and builds
 /// a VPCI bus to host it.
+///
+/// Returns a handle for revoking the device from the guest and offering it
+/// again.
 pub async fn build_vpci_device(
     driver_source: &VmTaskDriverSource,
     resolver: &ResourceResolver,
@@ -36,7 +40,7 @@ pub async fn build_vpci_device(
         Arc<dyn MsiInterruptTarget>,
         VpciInterruptMapper,
     )>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<VpciHotPlugHandle> {
     let device_name = format!("{}:vpci-{instance_id}", resource.id());
 
     let mut msi_set = MsiInterruptSet::new();
@@ -64,7 +68,7 @@ pub async fn build_vpci_device(
             .await?
     };
 
-    {
+    let bus = {
         let device_id = (instance_id.data2 as u64) << 16 | (instance_id.data3 as u64 & 0xfff8);
         let vpci_bus_name = format!("vpci:{instance_id}");
         chipset_builder
@@ -91,8 +95,9 @@ pub async fn build_vpci_device(
 
                 anyhow::Ok(bus)
             })
-            .await?;
-    }
+            .await?
+    };
 
-    Ok(())
+    let handle = bus.lock().hot_plug_handle();
+    Ok(handle)
 }
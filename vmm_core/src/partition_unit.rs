@@ -35,6 +35,7 @@
 use thiserror::Error;
 use virt::InitialRegs;
 use virt::PageVisibility;
+use virt::VpIndex;
 use vm_topology::processor::ProcessorTopology;
 use vmcore::save_restore::ProtobufSaveRestore;
 use vmcore::save_restore::RestoreError;
@@ -79,8 +80,9 @@ struct PartitionUnitRunner {
     topology: ProcessorTopology,
     initial_regs: Option<Arc<InitialRegs>>,
 
+    /// The debuggers for VTL0 and VTL2.
     #[cfg(feature = "gdb")]
-    debugger_state: debug::DebuggerState,
+    debuggers: Vec<debug::DebuggerState>,
 }
 
 impl InspectMut for PartitionUnitRunner {
@@ -121,6 +123,8 @@ enum PartitionRequest {
     ),
     StopVps(Rpc<(), ()>),
     StartVps,
+    InjectMachineCheck(Rpc<VpIndex, anyhow::Result<()>>),
+    GetRegisters(Rpc<VpIndex, anyhow::Result<virt::vp::Registers>>),
 }
 
 pub struct PartitionUnitParams<'a> {
@@ -134,6 +138,8 @@ pub struct PartitionUnitParams<'a> {
     /// other reason).
     pub client_notify_send: mesh::Sender<HaltReason>,
     pub debugger_rpc: Option<Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
+    /// A second debugger, which debugs VTL2 independently of the first.
+    pub vtl2_debugger_rpc: Option<Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
 }
 
 /// The halt reason receiver to pass to put in [`PartitionUnitParams`].
@@ -153,6 +159,8 @@ pub enum Error {
     NameInUse(NameInUse),
     #[error("missing guest memory required for gdb support")]
     MissingGuestMemory,
+    #[error("debugging vtl2 requires vtl2 to be enabled")]
+    Vtl2NotEnabled,
 }
 
 /// Error returned by [`PartitionUnit::set_initial_regs()`].
@@ -183,10 +191,20 @@ pub fn new(
         params: PartitionUnitParams<'_>,
     ) -> Result<(Self, Vec<VpRunner>), Error> {
         #[cfg(not(feature = "gdb"))]
-        if params.debugger_rpc.is_some() {
+        if params.debugger_rpc.is_some() || params.vtl2_debugger_rpc.is_some() {
             return Err(Error::DebuggingNotSupported);
         }
 
+        #[cfg(feature = "gdb")]
+        {
+            if params.vtl_guest_memory[0].is_none() {
+                return Err(Error::MissingGuestMemory);
+            }
+            if params.vtl2_debugger_rpc.is_some() && params.vtl_guest_memory[2].is_none() {
+                return Err(Error::Vtl2NotEnabled);
+            }
+        }
+
         let mut vp_set = VpSet::new(params.vtl_guest_memory.map(|m| m.cloned()), params.halt_vps);
         let vps = params
             .processor_topology
@@ -209,12 +227,18 @@ pub fn new(
             topology: params.processor_topology.clone(),
             initial_regs: None,
             #[cfg(feature = "gdb")]
-            debugger_state: debug::DebuggerState::new(
-                params.vtl_guest_memory[0]
-                    .ok_or(Error::MissingGuestMemory)?
-                    .clone(),
-                params.debugger_rpc,
-            ),
+            debuggers: vec![
+                debug::DebuggerState::new(
+                    params.vtl_guest_memory.map(|m| m.cloned()),
+                    params.debugger_rpc,
+                    Vtl::Vtl0,
+                ),
+                debug::DebuggerState::new(
+                    params.vtl_guest_memory.map(|m| m.cloned()),
+                    params.vtl2_debugger_rpc,
+                    Vtl::Vtl2,
+                ),
+            ],
         };
 
         let handle = builder
@@ -285,6 +309,25 @@ pub async fn set_initial_page_visibility(
             .await
             .unwrap()
     }
+
+    /// Injects a machine check exception into VTL0 of the specified VP.
+    ///
+    /// No machine check banks are reported, so the guest will typically treat
+    /// this as an unrecoverable error.
+    pub async fn inject_machine_check(&mut self, vp: VpIndex) -> anyhow::Result<()> {
+        self.req_send
+            .call(PartitionRequest::InjectMachineCheck, vp)
+            .await
+            .unwrap()
+    }
+
+    /// Gets the VTL0 registers of the specified VP.
+    pub async fn registers(&mut self, vp: VpIndex) -> anyhow::Result<virt::vp::Registers> {
+        self.req_send
+            .call(PartitionRequest::GetRegisters, vp)
+            .await
+            .unwrap()
+    }
 }
 
 impl PartitionUnitRunner {
@@ -296,11 +339,11 @@ enum Event {
                 Halt(InternalHaltReason),
                 Request(PartitionRequest),
                 #[cfg(feature = "gdb")]
-                Debug(vmm_core_defs::debug_rpc::DebugRequest),
+                Debug(usize, vmm_core_defs::debug_rpc::DebugRequest),
             }
 
             #[cfg(feature = "gdb")]
-            let debug = self.debugger_state.wait_rpc();
+            let debug = debug::wait_rpc(&mut self.debuggers);
             #[cfg(not(feature = "gdb"))]
             let debug = std::future::pending();
 
@@ -311,7 +354,8 @@ enum Event {
                 request = debug.fuse() => {
                     #[cfg(feature = "gdb")]
                     {
-                        Event::Debug(request)
+                        let (index, request) = request;
+                        Event::Debug(index, request)
                     }
                     #[cfg(not(feature = "gdb"))]
                     {
@@ -359,10 +403,17 @@ enum Event {
                         self.vp_stop_count -= 1;
                         self.try_start();
                     }
+                    PartitionRequest::InjectMachineCheck(rpc) => {
+                        rpc.handle(async |vp| self.vp_set.inject_machine_check(vp).await)
+                            .await
+                    }
+                    PartitionRequest::GetRegisters(rpc) => {
+                        rpc.handle(async |vp| self.vp_set.registers(vp).await).await
+                    }
                 },
                 #[cfg(feature = "gdb")]
-                Event::Debug(request) => {
-                    self.handle_gdb(request).await;
+                Event::Debug(index, request) => {
+                    self.handle_gdb(index, request).await;
                 }
             }
         }
@@ -381,13 +432,13 @@ async fn handle_halt(&mut self, reason: InternalHaltReason) {
                 if self.halt_reason.is_none() {
                     self.halt_reason = Some(reason.clone());
 
-                    // Report the halt to the debugger.
+                    // Report the halt to the debuggers.
                     #[cfg(feature = "gdb")]
-                    let reported = self.debugger_state.report_halt_to_debugger(&reason);
+                    let reported = self.report_halt_to_debuggers(&reason);
                     #[cfg(not(feature = "gdb"))]
                     let reported = false;
 
-                    // If the debugger is not attached, then report the halt
+                    // If no debugger is attached, then report the halt
                     // to the client.
                     if !reported {
                         self.client_notify_send.send(reason);
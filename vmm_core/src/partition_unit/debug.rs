@@ -10,6 +10,7 @@
 use anyhow::Context;
 use futures::StreamExt;
 use guestmem::GuestMemory;
+use hvdef::Vtl;
 use virt::VpIndex;
 use vmm_core_defs::HaltReason;
 use vmm_core_defs::debug_rpc::DebugRequest;
@@ -17,24 +18,40 @@
 use vmm_core_defs::debug_rpc::GuestAddress;
 
 pub struct DebuggerState {
-    guest_memory: GuestMemory,
+    vtl_guest_memory: [Option<GuestMemory>; 3],
     debug_notify_halt: Option<mesh::OneshotSender<DebugStopReason>>,
     rpc: Option<mesh::Receiver<DebugRequest>>,
     attached: bool,
     halt_reported: bool,
+    /// The VTL the debugger accesses when it attaches.
+    default_vtl: Vtl,
+    /// The VTL whose state the debugger accesses.
+    vtl: Vtl,
 }
 
 impl DebuggerState {
-    pub fn new(guest_memory: GuestMemory, rpc: Option<mesh::Receiver<DebugRequest>>) -> Self {
+    pub fn new(
+        vtl_guest_memory: [Option<GuestMemory>; 3],
+        rpc: Option<mesh::Receiver<DebugRequest>>,
+        default_vtl: Vtl,
+    ) -> Self {
         Self {
-            guest_memory,
+            vtl_guest_memory,
             debug_notify_halt: None,
             rpc,
             attached: false,
             halt_reported: false,
+            default_vtl,
+            vtl: default_vtl,
         }
     }
 
+    fn guest_memory(&self) -> anyhow::Result<&GuestMemory> {
+        self.vtl_guest_memory[self.vtl as usize]
+            .as_ref()
+            .with_context(|| format!("no guest memory for {:?}", self.vtl))
+    }
+
     pub async fn wait_rpc(&mut self) -> DebugRequest {
         if let Some(rpc) = &mut self.rpc {
             if !futures::stream::FusedStream::is_terminated(&rpc) {
@@ -53,7 +70,9 @@ pub fn report_halt_to_debugger(&mut self, reason: &HaltReason) -> bool {
             tracing::debug!("halt reported to debugger");
             self.halt_reported = true;
             notify.send(match reason {
-                HaltReason::PowerOff | HaltReason::Hibernate => DebugStopReason::PowerOff,
+                HaltReason::PowerOff | HaltReason::Hibernate | HaltReason::Suspend => {
+                    DebugStopReason::PowerOff
+                }
                 HaltReason::Reset => DebugStopReason::Reset,
                 HaltReason::TripleFault { vp, .. }
                 | HaltReason::InvalidVmState { vp }
@@ -70,45 +89,121 @@ pub fn report_halt_to_debugger(&mut self, reason: &HaltReason) -> bool {
     }
 }
 
+/// Waits for a request from any of `debuggers`, returning the index of the
+/// debugger that sent it.
+pub async fn wait_rpc(debuggers: &mut [DebuggerState]) -> (usize, DebugRequest) {
+    let waits = debuggers
+        .iter_mut()
+        .enumerate()
+        .map(|(i, debugger)| Box::pin(async move { (i, debugger.wait_rpc().await) }));
+    futures::future::select_all(waits).await.0
+}
+
 impl PartitionUnitRunner {
-    pub async fn handle_gdb(&mut self, req: DebugRequest) {
+    /// Reports a halt to every attached debugger. Returns true if any debugger
+    /// is attached to handle it.
+    pub fn report_halt_to_debuggers(&mut self, reason: &HaltReason) -> bool {
+        let mut reported = false;
+        for debugger in &mut self.debuggers {
+            reported |= debugger.report_halt_to_debugger(reason);
+        }
+        reported
+    }
+
+    pub async fn handle_gdb(&mut self, index: usize, req: DebugRequest) {
         match req {
             DebugRequest::Attach => {
-                tracing::info!("debugger attached");
-                self.debugger_state.attached = true;
+                let debugger = &mut self.debuggers[index];
+                tracing::info!(vtl = ?debugger.vtl, "debugger attached");
+                debugger.attached = true;
             }
             DebugRequest::Detach => {
-                tracing::info!("debugger detached");
-                self.debugger_state.debug_notify_halt = None;
-                if let Err(err) = self.vp_set.clear_debug_state().await {
+                let debugger = &mut self.debuggers[index];
+                tracing::info!(vtl = ?debugger.vtl, "debugger detached");
+                debugger.debug_notify_halt = None;
+                debugger.attached = false;
+                let halt_reported = std::mem::take(&mut debugger.halt_reported);
+                let vtl = std::mem::replace(&mut debugger.vtl, debugger.default_vtl);
+                if let Err(err) = self.vp_set.clear_debug_state(vtl).await {
                     tracing::error!(
                         error = err.as_ref() as &dyn std::error::Error,
                         "failed to clear debug state"
                     );
                 }
-                // Report the halt reason to the client since the debugger won't
-                // be there to get it.
                 if let Some(reason) = self.halt_reason.clone() {
-                    self.client_notify_send.send(reason);
+                    if !self.debuggers.iter().any(|d| d.attached) {
+                        // Report the halt reason to the client since no
+                        // debugger will be there to get it.
+                        self.client_notify_send.send(reason);
+                    } else if halt_reported && !self.debuggers.iter().any(|d| d.halt_reported) {
+                        // The other debuggers are running, so resume the VM
+                        // as if this one had.
+                        self.clear_halt();
+                    }
                 }
-                self.debugger_state.attached = false;
             }
             DebugRequest::Resume { response } => {
                 tracing::debug!("debugger resumed");
-                self.debugger_state.debug_notify_halt = Some(response);
-                if self.debugger_state.halt_reported {
-                    self.clear_halt();
-                    self.debugger_state.halt_reported = false;
+                let debugger = &mut self.debuggers[index];
+                debugger.debug_notify_halt = Some(response);
+                if debugger.halt_reported {
+                    debugger.halt_reported = false;
+                    // The VM resumes once every debugger that saw the halt has
+                    // resumed.
+                    if !self.debuggers.iter().any(|d| d.halt_reported) {
+                        self.clear_halt();
+                    }
                 } else if let Some(reason) = self.halt_reason.as_ref() {
-                    self.debugger_state.report_halt_to_debugger(reason);
+                    debugger.report_halt_to_debugger(reason);
                 }
             }
             DebugRequest::Break => {
                 tracing::debug!("debug break requested");
                 self.vp_set.halt(HaltReason::DebugBreak { vp: None });
             }
+            DebugRequest::SetVtl(rpc) => {
+                rpc.handle_failable(async |vtl| {
+                    let vtl = Vtl::try_from(vtl).ok().context("invalid vtl")?;
+                    if vtl == self.debuggers[index].vtl {
+                        return Ok(());
+                    }
+                    // Each VTL has one set of debug registers, so two
+                    // debuggers cannot share a VTL without clobbering each
+                    // other's breakpoints.
+                    if self
+                        .debuggers
+                        .iter()
+                        .enumerate()
+                        .any(|(i, d)| i != index && d.attached && d.vtl == vtl)
+                    {
+                        anyhow::bail!("{vtl:?} is being debugged by another debugger");
+                    }
+                    self.vp_set.check_debug_vtl(vtl).await?;
+                    // Breakpoints are reapplied to the new VTL when the
+                    // debugger resumes.
+                    self.vp_set
+                        .clear_debug_state(self.debuggers[index].vtl)
+                        .await?;
+                    tracing::info!(?vtl, "debugger switched vtl");
+                    self.debuggers[index].vtl = vtl;
+                    anyhow::Ok(())
+                })
+                .await
+            }
+            DebugRequest::HardwareDebugSupported(rpc) => {
+                let vtl = self.debuggers[index].vtl;
+                // Backends without hardware debugging fail to even clear the
+                // debug state.
+                rpc.handle(async |()| self.vp_set.clear_debug_state(vtl).await.is_ok())
+                    .await
+            }
             DebugRequest::SetDebugState { vp, state } => {
-                if let Err(err) = self.vp_set.set_debug_state(VpIndex::new(vp), state).await {
+                let vtl = self.debuggers[index].vtl;
+                if let Err(err) = self
+                    .vp_set
+                    .set_debug_state(VpIndex::new(vp), vtl, state)
+                    .await
+                {
                     tracing::error!(
                         vp,
                         error = err.as_ref() as &dyn std::error::Error,
@@ -117,26 +212,31 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
                 }
             }
             DebugRequest::GetVpState(rpc) => {
-                rpc.handle_failable(async |vp| self.vp_set.get_vp_state(VpIndex::new(vp)).await)
-                    .await
+                let vtl = self.debuggers[index].vtl;
+                rpc.handle_failable(async |vp| {
+                    self.vp_set.get_vp_state(VpIndex::new(vp), vtl).await
+                })
+                .await
             }
             DebugRequest::SetVpState(rpc) => {
+                let vtl = self.debuggers[index].vtl;
                 rpc.handle_failable(async |(vp, state)| {
-                    self.vp_set.set_vp_state(VpIndex::new(vp), state).await
+                    self.vp_set.set_vp_state(VpIndex::new(vp), vtl, state).await
                 })
                 .await
             }
             DebugRequest::ReadMemory(rpc) => {
+                let vtl = self.debuggers[index].vtl;
                 rpc.handle_failable(async |(addr, len)| match addr {
                     GuestAddress::Gva { vp, gva } => {
                         self.vp_set
-                            .read_virtual_memory(VpIndex::new(vp), gva, len)
+                            .read_virtual_memory(VpIndex::new(vp), vtl, gva, len)
                             .await
                     }
                     GuestAddress::Gpa(gpa) => {
                         let mut buf = vec![0; len];
-                        self.debugger_state
-                            .guest_memory
+                        self.debuggers[index]
+                            .guest_memory()?
                             .read_at(gpa, &mut buf)
                             .context("failed to read guest memory")?;
                         Ok(buf)
@@ -145,15 +245,15 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
                 .await
             }
             DebugRequest::WriteMemory(rpc) => {
+                let vtl = self.debuggers[index].vtl;
                 rpc.handle_failable(async |(addr, data)| match addr {
                     GuestAddress::Gva { vp, gva } => {
                         self.vp_set
-                            .write_virtual_memory(VpIndex::new(vp), gva, data)
+                            .write_virtual_memory(VpIndex::new(vp), vtl, gva, data)
                             .await
                     }
-                    GuestAddress::Gpa(gpa) => self
-                        .debugger_state
-                        .guest_memory
+                    GuestAddress::Gpa(gpa) => self.debuggers[index]
+                        .guest_memory()?
                         .write_at(gpa, &data)
                         .context("failed to write guest memory"),
                 })
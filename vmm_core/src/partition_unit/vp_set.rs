@@ -31,6 +31,8 @@
 use std::task::Context;
 use std::task::Poll;
 use std::task::Waker;
+use std::time::Duration;
+use std::time::Instant;
 use thiserror::Error;
 use tracing::instrument;
 use virt::InitialRegs;
@@ -65,6 +67,9 @@ async fn run_vp(
     /// Inspect the VP.
     fn inspect_vp(&mut self, gm: &[Option<GuestMemory>; NUM_VTLS], req: inspect::Request<'_>);
 
+    /// Returns the total time the VP has spent running the guest, if known.
+    fn guest_run_time(&self) -> Option<Duration>;
+
     /// Sets the register state at first boot.
     fn set_initial_regs(
         &mut self,
@@ -73,6 +78,12 @@ fn set_initial_regs(
         to_set: RegistersToSet,
     ) -> Result<(), RegisterSetError>;
 
+    /// Injects a machine check exception.
+    fn inject_machine_check(&mut self, vtl: Vtl) -> anyhow::Result<()>;
+
+    /// Gets the general purpose and control registers.
+    fn registers(&mut self, vtl: Vtl) -> anyhow::Result<virt::vp::Registers>;
+
     #[cfg(feature = "gdb")]
     fn debug(&mut self) -> &mut dyn DebugVp;
 }
@@ -90,6 +101,8 @@ pub enum RegistersToSet {
 
 #[cfg(feature = "gdb")]
 trait DebugVp {
+    fn vtl_enabled(&self, vtl: Vtl) -> bool;
+
     fn set_debug_state(
         &mut self,
         vtl: Vtl,
@@ -206,6 +219,10 @@ fn inspect_vp(
         }
     }
 
+    fn guest_run_time(&self) -> Option<Duration> {
+        self.vp.guest_run_time()
+    }
+
     fn set_initial_regs(
         &mut self,
         vtl: Vtl,
@@ -247,6 +264,44 @@ fn set_initial_regs(
         Ok(())
     }
 
+    #[cfg(guest_arch = "x86_64")]
+    fn inject_machine_check(&mut self, vtl: Vtl) -> anyhow::Result<()> {
+        let mut access = self.vp.access_state(vtl);
+        let activity = access.activity()?;
+        if activity.pending_interruption.is_some() || activity.pending_event.is_some() {
+            anyhow::bail!("an event is already pending");
+        }
+        let mp_state = match activity.mp_state {
+            // Like any other exception, a machine check wakes a halted
+            // processor.
+            virt::x86::vp::MpState::Running
+            | virt::x86::vp::MpState::Halted
+            | virt::x86::vp::MpState::Idle => virt::x86::vp::MpState::Running,
+            virt::x86::vp::MpState::WaitForSipi => {
+                anyhow::bail!("processor has not been started")
+            }
+        };
+        access.set_activity(&virt::x86::vp::Activity {
+            mp_state,
+            pending_interruption: Some(virt::x86::vp::PendingInterruption::Exception {
+                vector: x86defs::Exception::MACHINE_CHECK.0,
+                error_code: None,
+            }),
+            ..activity
+        })?;
+        access.commit()?;
+        Ok(())
+    }
+
+    #[cfg(guest_arch = "aarch64")]
+    fn inject_machine_check(&mut self, _vtl: Vtl) -> anyhow::Result<()> {
+        anyhow::bail!("machine check injection is not supported on aarch64")
+    }
+
+    fn registers(&mut self, vtl: Vtl) -> anyhow::Result<virt::vp::Registers> {
+        Ok(self.vp.access_state(vtl).registers()?)
+    }
+
     #[cfg(feature = "gdb")]
     fn debug(&mut self) -> &mut dyn DebugVp {
         self
@@ -413,6 +468,10 @@ fn trace_fault(
 
 #[cfg(feature = "gdb")]
 impl<T: Processor, U> DebugVp for BoundVp<'_, T, U> {
+    fn vtl_enabled(&self, vtl: Vtl) -> bool {
+        self.vp.vtl_inspectable(vtl)
+    }
+
     fn set_debug_state(
         &mut self,
         vtl: Vtl,
@@ -777,6 +836,7 @@ pub fn add(&mut self, vp: TargetVpInfo) -> VpRunner {
                 vp: vp.as_ref().vp_index,
                 inner: self.inner.clone(),
                 state: VpState::Stopped,
+                run_time: RunTime::default(),
             },
         }
     }
@@ -909,6 +969,24 @@ pub async fn set_initial_regs(
 
         Ok(())
     }
+
+    /// Injects a machine check exception into VTL0 of a single VP.
+    pub async fn inject_machine_check(&self, vp: VpIndex) -> anyhow::Result<()> {
+        self.vps[vp.index() as usize]
+            .send
+            .call(|x| VpEvent::State(StateEvent::InjectMachineCheck(x)), ())
+            .await
+            .map_err(RunnerGoneError)?
+    }
+
+    /// Gets the VTL0 registers of a single VP.
+    pub async fn registers(&self, vp: VpIndex) -> anyhow::Result<virt::vp::Registers> {
+        self.vps[vp.index() as usize]
+            .send
+            .call(|x| VpEvent::State(StateEvent::GetRegisters(x)), ())
+            .await
+            .map_err(RunnerGoneError)?
+    }
 }
 
 /// Error returned when registers could not be set on a VP.
@@ -922,29 +1000,44 @@ pub async fn set_initial_regs(
 
 #[cfg(feature = "gdb")]
 impl VpSet {
+    /// Check that `vtl` is enabled on all VPs, so that it can be debugged.
+    pub async fn check_debug_vtl(&self, vtl: Vtl) -> anyhow::Result<()> {
+        for vp in &self.vps {
+            vp.send
+                .call(
+                    |x| VpEvent::State(StateEvent::Debug(DebugEvent::CheckVtl(x))),
+                    vtl,
+                )
+                .await
+                .map_err(RunnerGoneError)??;
+        }
+        Ok(())
+    }
+
     /// Set the debug state for a single VP.
     pub async fn set_debug_state(
         &self,
         vp: VpIndex,
+        vtl: Vtl,
         state: virt::x86::DebugState,
     ) -> anyhow::Result<()> {
         self.vps[vp.index() as usize]
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::SetDebugState(x))),
-                Some(state),
+                (vtl, Some(state)),
             )
             .await
             .map_err(RunnerGoneError)?
     }
 
     /// Clear the debug state for all VPs.
-    pub async fn clear_debug_state(&self) -> anyhow::Result<()> {
+    pub async fn clear_debug_state(&self, vtl: Vtl) -> anyhow::Result<()> {
         for vp in &self.vps {
             vp.send
                 .call(
                     |x| VpEvent::State(StateEvent::Debug(DebugEvent::SetDebugState(x))),
-                    None,
+                    (vtl, None),
                 )
                 .await
                 .map_err(RunnerGoneError)??;
@@ -955,24 +1048,29 @@ pub async fn clear_debug_state(&self) -> anyhow::Result<()> {
     pub async fn set_vp_state(
         &self,
         vp: VpIndex,
+        vtl: Vtl,
         state: Box<DebuggerVpState>,
     ) -> anyhow::Result<()> {
         self.vps[vp.index() as usize]
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::SetVpState(x))),
-                state,
+                (vtl, state),
             )
             .await
             .map_err(RunnerGoneError)?
     }
 
-    pub async fn get_vp_state(&self, vp: VpIndex) -> anyhow::Result<Box<DebuggerVpState>> {
+    pub async fn get_vp_state(
+        &self,
+        vp: VpIndex,
+        vtl: Vtl,
+    ) -> anyhow::Result<Box<DebuggerVpState>> {
         self.vps[vp.index() as usize]
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::GetVpState(x))),
-                (),
+                vtl,
             )
             .await
             .map_err(RunnerGoneError)?
@@ -981,6 +1079,7 @@ pub async fn get_vp_state(&self, vp: VpIndex) -> anyhow::Result<Box<DebuggerVpSt
     pub async fn read_virtual_memory(
         &self,
         vp: VpIndex,
+        vtl: Vtl,
         gva: u64,
         len: usize,
     ) -> anyhow::Result<Vec<u8>> {
@@ -988,7 +1087,7 @@ pub async fn read_virtual_memory(
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::ReadVirtualMemory(x))),
-                (gva, len),
+                (vtl, gva, len),
             )
             .await
             .map_err(RunnerGoneError)?
@@ -997,6 +1096,7 @@ pub async fn read_virtual_memory(
     pub async fn write_virtual_memory(
         &self,
         vp: VpIndex,
+        vtl: Vtl,
         gva: u64,
         data: Vec<u8>,
     ) -> anyhow::Result<()> {
@@ -1004,7 +1104,7 @@ pub async fn write_virtual_memory(
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::WriteVirtualMemory(x))),
-                (gva, data),
+                (vtl, gva, data),
             )
             .await
             .map_err(RunnerGoneError)?
@@ -1024,6 +1124,8 @@ enum StateEvent {
     SetInitialRegs(Rpc<(Vtl, Arc<InitialRegs>, RegistersToSet), Result<(), RegisterSetError>>),
     Save(Rpc<(), Result<SavedStateBlob, SaveError>>),
     Restore(Rpc<SavedStateBlob, Result<(), RestoreError>>),
+    InjectMachineCheck(Rpc<(), anyhow::Result<()>>),
+    GetRegisters(Rpc<(), anyhow::Result<virt::vp::Registers>>),
     #[cfg(feature = "gdb")]
     Debug(DebugEvent),
 }
@@ -1031,11 +1133,12 @@ enum StateEvent {
 #[cfg(feature = "gdb")]
 #[derive(Debug)]
 enum DebugEvent {
-    SetDebugState(Rpc<Option<virt::x86::DebugState>, anyhow::Result<()>>),
-    SetVpState(Rpc<Box<DebuggerVpState>, anyhow::Result<()>>),
-    GetVpState(Rpc<(), anyhow::Result<Box<DebuggerVpState>>>),
-    ReadVirtualMemory(Rpc<(u64, usize), anyhow::Result<Vec<u8>>>),
-    WriteVirtualMemory(Rpc<(u64, Vec<u8>), anyhow::Result<()>>),
+    CheckVtl(Rpc<Vtl, anyhow::Result<()>>),
+    SetDebugState(Rpc<(Vtl, Option<virt::x86::DebugState>), anyhow::Result<()>>),
+    SetVpState(Rpc<(Vtl, Box<DebuggerVpState>), anyhow::Result<()>>),
+    GetVpState(Rpc<Vtl, anyhow::Result<Box<DebuggerVpState>>>),
+    ReadVirtualMemory(Rpc<(Vtl, u64, usize), anyhow::Result<Vec<u8>>>),
+    WriteVirtualMemory(Rpc<(Vtl, u64, Vec<u8>), anyhow::Result<()>>),
 }
 
 /// An object used to dispatch a virtual processor.
@@ -1075,6 +1178,72 @@ struct RunnerInner {
     vp: VpIndex,
     inner: Arc<Inner>,
     state: VpState,
+    run_time: RunTime,
+}
+
+/// Where a VP's time has gone while it was running.
+#[derive(Default)]
+struct RunTime {
+    /// Time spent polling `run_vp`, running the guest and handling its exits.
+    busy: Duration,
+    /// Time `run_vp` spent pending, such as while waiting for an interrupt
+    /// with the guest halted.
+    idle: Duration,
+}
+
+impl RunTime {
+    fn inspect(&self, guest: Option<Duration>, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        resp.counter("running_ns", self.busy.as_nanos() as u64)
+            .counter("idle_ns", self.idle.as_nanos() as u64);
+        if let Some(guest) = guest {
+            resp.counter("guest_ns", guest.as_nanos() as u64)
+                .counter("host_ns", self.busy.saturating_sub(guest).as_nanos() as u64);
+        }
+        if let Some(steal) = thread_steal_time() {
+            resp.counter("steal_ns", steal.as_nanos() as u64);
+        }
+    }
+}
+
+/// Returns the time that the current thread, which runs the VP, has spent
+/// runnable but waiting for a host CPU.
+#[cfg(target_os = "linux")]
+fn thread_steal_time() -> Option<Duration> {
+    // The second field is the time spent waiting on a run queue, in
+    // nanoseconds.
+    let schedstat = std::fs::read_to_string("/proc/thread-self/schedstat").ok()?;
+    let wait = schedstat.split_ascii_whitespace().nth(1)?.parse().ok()?;
+    Some(Duration::from_nanos(wait))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_steal_time() -> Option<Duration> {
+    None
+}
+
+/// Wraps the `run_vp` future to account for its time in [`RunTime`].
+struct TimedRun<'a, F> {
+    run_vp: F,
+    run_time: &'a mut RunTime,
+    pending_since: Option<Instant>,
+}
+
+impl<F: Future + Unpin> Future for TimedRun<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let start = Instant::now();
+        if let Some(pending_since) = this.pending_since {
+            this.run_time.idle += start - pending_since;
+        }
+        let r = Pin::new(&mut this.run_vp).poll(cx);
+        let end = Instant::now();
+        this.run_time.busy += end - start;
+        this.pending_since = Some(end);
+        r
+    }
 }
 
 #[derive(Copy, Clone, Debug, Inspect, PartialEq, Eq)]
@@ -1153,10 +1322,13 @@ enum Event {
 
                 let stop = StopVpSource::new();
 
-                let run_vp = vp
-                    .run_vp(&self.inner.inner.vtl_guest_memory, stop.checker())
-                    .into_stream()
-                    .map(Event::VpStopped);
+                let run_vp = TimedRun {
+                    run_vp: vp.run_vp(&self.inner.inner.vtl_guest_memory, stop.checker()),
+                    run_time: &mut self.inner.run_time,
+                    pending_since: None,
+                }
+                .into_stream()
+                .map(Event::VpStopped);
 
                 let halt = self
                     .inner
@@ -1254,8 +1426,13 @@ impl RunnerInner {
     fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
         match event {
             StateEvent::Inspect(deferred) => {
+                let guest_run_time = vp.guest_run_time();
                 deferred.respond(|resp| {
                     resp.field("state", self.state)
+                        .field(
+                            "run_time",
+                            inspect::adhoc(|req| self.run_time.inspect(guest_run_time, req)),
+                        )
                         .merge(inspect::adhoc_mut(|req| {
                             vp.inspect_vp(&self.inner.vtl_guest_memory, req)
                         }));
@@ -1266,37 +1443,45 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
             }
             StateEvent::Save(rpc) => rpc.handle_sync(|()| vp.save()),
             StateEvent::Restore(rpc) => rpc.handle_sync(|data| vp.restore(data)),
+            StateEvent::InjectMachineCheck(rpc) => {
+                rpc.handle_sync(|()| vp.inject_machine_check(Vtl::Vtl0))
+            }
+            StateEvent::GetRegisters(rpc) => rpc.handle_sync(|()| vp.registers(Vtl::Vtl0)),
             #[cfg(feature = "gdb")]
             StateEvent::Debug(event) => match event {
-                DebugEvent::SetDebugState(rpc) => {
-                    rpc.handle_sync(|state| vp.debug().set_debug_state(Vtl::Vtl0, state.as_ref()))
-                }
+                DebugEvent::CheckVtl(rpc) => rpc.handle_sync(|vtl| {
+                    debug_vtl(vp, vtl)?;
+                    Ok(())
+                }),
+                DebugEvent::SetDebugState(rpc) => rpc.handle_sync(|(vtl, state)| {
+                    debug_vtl(vp, vtl)?.set_debug_state(vtl, state.as_ref())
+                }),
                 DebugEvent::SetVpState(rpc) => {
-                    rpc.handle_sync(|state| vp.debug().set_vp_state(Vtl::Vtl0, &state))
+                    rpc.handle_sync(|(vtl, state)| debug_vtl(vp, vtl)?.set_vp_state(vtl, &state))
                 }
                 DebugEvent::GetVpState(rpc) => {
-                    rpc.handle_sync(|()| vp.debug().get_vp_state(Vtl::Vtl0))
+                    rpc.handle_sync(|vtl| debug_vtl(vp, vtl)?.get_vp_state(vtl))
                 }
-                DebugEvent::ReadVirtualMemory(rpc) => rpc.handle_sync(|(gva, len)| {
+                DebugEvent::ReadVirtualMemory(rpc) => rpc.handle_sync(|(vtl, gva, len)| {
                     let mut buf = vec![0; len];
                     vp_state::read_virtual_memory(
-                        self.inner.vtl_guest_memory[0]
+                        self.inner.vtl_guest_memory[vtl as usize]
                             .as_ref()
-                            .context("no guest memory for vtl0")?,
-                        vp.debug(),
-                        Vtl::Vtl0,
+                            .with_context(|| format!("no guest memory for {vtl:?}"))?,
+                        debug_vtl(vp, vtl)?,
+                        vtl,
                         gva,
                         &mut buf,
                     )?;
                     Ok(buf)
                 }),
-                DebugEvent::WriteVirtualMemory(rpc) => rpc.handle_sync(|(gva, buf)| {
+                DebugEvent::WriteVirtualMemory(rpc) => rpc.handle_sync(|(vtl, gva, buf)| {
                     vp_state::write_virtual_memory(
-                        self.inner.vtl_guest_memory[0]
+                        self.inner.vtl_guest_memory[vtl as usize]
                             .as_ref()
-                            .context("no guest memory for vtl0")?,
-                        vp.debug(),
-                        Vtl::Vtl0,
+                            .with_context(|| format!("no guest memory for {vtl:?}"))?,
+                        debug_vtl(vp, vtl)?,
+                        vtl,
                         gva,
                         &buf,
                     )?;
@@ -1307,6 +1492,16 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
     }
 }
 
+/// Returns the debug interface for `vp`, failing if `vtl` is not enabled.
+#[cfg(feature = "gdb")]
+fn debug_vtl(vp: &mut dyn ControlVp, vtl: Vtl) -> anyhow::Result<&mut dyn DebugVp> {
+    let debug = vp.debug();
+    if !debug.vtl_enabled(vtl) {
+        anyhow::bail!("{vtl:?} is not enabled");
+    }
+    Ok(debug)
+}
+
 #[cfg(feature = "gdb")]
 mod vp_state {
     use super::DebugVp;
@@ -10,6 +10,7 @@
 use std::sync::Arc;
 use virt::VpIndex;
 use virt::io::CpuIo;
+use vmm_core_defs::GuestCrash;
 use vmm_core_defs::HaltReason;
 use vmotherboard::Chipset;
 
@@ -18,6 +19,7 @@
 pub struct ChipsetPlusSynic {
     pub synic_ports: Arc<SynicPorts>,
     pub chipset: Arc<Chipset>,
+    pub guest_crash_send: Option<mesh::Sender<GuestCrash>>,
 }
 
 impl ChipsetPlusSynic {
@@ -26,8 +28,15 @@ pub fn new(synic_ports: Arc<SynicPorts>, chipset: Arc<Chipset>) -> Self {
         Self {
             synic_ports,
             chipset,
+            guest_crash_send: None,
         }
     }
+
+    /// Forward guest crash reports to `send`.
+    pub fn with_guest_crash_send(mut self, send: Option<mesh::Sender<GuestCrash>>) -> Self {
+        self.guest_crash_send = send;
+        self
+    }
 }
 
 impl CpuIo for ChipsetPlusSynic {
@@ -93,6 +102,36 @@ fn write_io(
     ) -> impl std::future::Future<Output = ()> {
         self.chipset.io_write(vp.index(), port, data)
     }
+
+    fn report_guest_crash(
+        &self,
+        vp: VpIndex,
+        vtl: Vtl,
+        parameters: [u64; 5],
+        message: Option<&[u8]>,
+    ) {
+        if let Some(send) = &self.guest_crash_send {
+            send.send(GuestCrash {
+                vp: vp.index(),
+                vtl: vtl.into(),
+                parameters: parameters.to_vec(),
+                message: message
+                    .map(|m| String::from_utf8_lossy(m).trim_end_matches('\0').to_owned()),
+            });
+        }
+    }
+
+    fn is_tracing_access(&self) -> bool {
+        self.chipset.is_tracing_access()
+    }
+
+    fn trace_exit_rip(&self, vp: VpIndex, rip: u64) {
+        self.chipset.trace_exit_rip(vp.index(), rip)
+    }
+
+    fn trace_msr(&self, vp: VpIndex, msr: u32, write: bool, value: u64) {
+        self.chipset.trace_msr(vp.index(), msr, write, value)
+    }
 }
 
 impl vmotherboard::PowerEventHandler for Halt {
@@ -101,6 +140,7 @@ fn on_power_event(&self, evt: vmotherboard::PowerEvent) {
             vmotherboard::PowerEvent::PowerOff => HaltReason::PowerOff,
             vmotherboard::PowerEvent::Reset => HaltReason::Reset,
             vmotherboard::PowerEvent::Hibernate => HaltReason::Hibernate,
+            vmotherboard::PowerEvent::Suspend => HaltReason::Suspend,
         };
         self.halt(reason)
     }
@@ -4,6 +4,7 @@
 mod partition_memory_map;
 
 pub use partition_memory_map::PartitionMemoryMap;
+pub use partition_memory_map::merge_dirty_bitmap;
 pub use vm_topology::processor::VpIndex;
 
 use crate::CpuidLeaf;
@@ -32,6 +33,7 @@
 use std::sync::atomic::Ordering;
 use std::task::Poll;
 use std::task::Waker;
+use std::time::Duration;
 use vm_topology::memory::MemoryLayout;
 use vm_topology::processor::ProcessorTopology;
 use vmcore::monitor::MonitorId;
@@ -434,6 +436,12 @@ fn vtl_inspectable(&self, vtl: Vtl) -> bool {
     }
 
     fn access_state(&mut self, vtl: Vtl) -> Self::StateAccess<'_>;
+
+    /// Returns the total time this VP has spent in the hypervisor's call to
+    /// run the guest, or `None` if the backend does not track it.
+    fn guest_run_time(&self) -> Option<Duration> {
+        None
+    }
 }
 
 /// A source for [`StopVp`].
@@ -43,6 +43,31 @@ fn pin_range(&self, _addr: u64, _size: u64) -> Result<(), anyhow::Error> {
         Ok(())
     }
 
+    /// Enables or disables tracking of the pages written by the partition,
+    /// for all mapped ranges. When enabled, all pages start clean.
+    fn set_dirty_tracking(&self, _enable: bool) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("dirty page tracking is not supported"))
+    }
+
+    /// Queries which pages in the given guest physical address range have
+    /// been written since dirty tracking was enabled or they were last
+    /// queried, and marks them clean.
+    ///
+    /// On return, bit `n` of `bitmap` is set if the page at `addr + n * 4096`
+    /// is dirty. `bitmap` must have a bit for each page in the range.
+    ///
+    /// `addr` and `size` must be page aligned. The specified range may
+    /// overlap zero, one, or many ranges mapped with `map_range`. Any
+    /// overlapped ranges must be completely contained in the specified range.
+    fn query_and_clear_dirty(
+        &self,
+        _addr: u64,
+        _size: u64,
+        _bitmap: &mut [u64],
+    ) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("dirty page tracking is not supported"))
+    }
+
     /// Maps a range residing in a remote process.
     ///
     /// This may fail if the range overlaps any other mapped range.
@@ -61,3 +86,40 @@ unsafe fn map_remote_range(
         exec: bool,
     ) -> Result<(), anyhow::Error>;
 }
+
+/// Sets the bits in `bitmap` for the dirty pages in `range_bitmap`, which
+/// describes `page_count` pages starting at page `first_page` of `bitmap`.
+///
+/// Used by implementations of [`PartitionMemoryMap::query_and_clear_dirty`]
+/// that query each mapped range separately.
+pub fn merge_dirty_bitmap(
+    bitmap: &mut [u64],
+    first_page: u64,
+    range_bitmap: &[u64],
+    page_count: u64,
+) {
+    for (i, &word) in range_bitmap.iter().enumerate() {
+        let mut word = word;
+        while word != 0 {
+            let page = i as u64 * 64 + u64::from(word.trailing_zeros());
+            word &= word - 1;
+            if page >= page_count {
+                break;
+            }
+            let n = first_page + page;
+            bitmap[(n / 64) as usize] |= 1 << (n % 64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge_dirty_bitmap;
+
+    #[test]
+    fn test_merge_dirty_bitmap() {
+        let mut bitmap = [0; 3];
+        merge_dirty_bitmap(&mut bitmap, 60, &[0b1011, 1 << 63 | 1], 70);
+        assert_eq!(bitmap, [1 << 60 | 1 << 61 | 1 << 63, 1 << 60, 0]);
+    }
+}
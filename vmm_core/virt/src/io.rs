@@ -47,4 +47,39 @@ fn post_synic_message(
     /// Programmed IO write.
     #[must_use]
     fn write_io(&self, vp: VpIndex, port: u16, data: &[u8]) -> impl Future<Output = ()>;
+
+    /// Report that the guest signaled a crash via the Hyper-V guest crash
+    /// registers.
+    ///
+    /// `parameters` holds the values of the P0-P4 crash registers, and
+    /// `message` the contents of the crash message buffer, if the guest
+    /// provided one.
+    fn report_guest_crash(
+        &self,
+        vp: VpIndex,
+        vtl: Vtl,
+        parameters: [u64; 5],
+        message: Option<&[u8]>,
+    ) {
+        let _ = (vp, vtl, parameters, message);
+    }
+
+    /// Returns whether guest access tracing is enabled.
+    ///
+    /// Backends that must query the hypervisor for the instruction pointer
+    /// can check this before calling [`Self::trace_exit_rip`].
+    fn is_tracing_access(&self) -> bool {
+        false
+    }
+
+    /// Notes the instruction pointer of the exit being handled, so that guest
+    /// access tracing can report which instruction made each access.
+    fn trace_exit_rip(&self, vp: VpIndex, rip: u64) {
+        let _ = (vp, rip);
+    }
+
+    /// Report an MSR access handled by the VMM, for guest access tracing.
+    fn trace_msr(&self, vp: VpIndex, msr: u32, write: bool, value: u64) {
+        let _ = (vp, msr, write, value);
+    }
 }
@@ -832,7 +832,7 @@ fn set_debug_state(
         _vtl: Vtl,
         _state: Option<&virt::x86::DebugState>,
     ) -> Result<(), Self::Error> {
-        Ok(())
+        Err(anyhow::anyhow!("guest debugging is not supported").into())
     }
 
     async fn run_vp(
@@ -34,6 +34,7 @@
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use tracing::Instrument;
 use virt::NeedsYield;
 use virt::PartitionCapabilities;
 use virt::ProtoPartitionConfig;
@@ -389,7 +390,7 @@ fn set_debug_state(
         _vtl: Vtl,
         _state: Option<&DebugState>,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(KvmError::NotSupported)
     }
 
     async fn run_vp(
@@ -423,15 +424,24 @@ async fn run_vp(
 
                 let exit = exit.map_err(|err| VpHaltReason::Hypervisor(KvmRunVpError::Run(err)))?;
                 pending_exit = true;
+                // The span is closed at the end of the iteration, so it times
+                // the whole exit, not just the device emulation that runs
+                // inside it.
+                let span =
+                    tracing::trace_span!("vm_exit", vp = self.vpindex.index(), reason = ?exit);
                 match exit {
                     kvm::Exit::Interrupted => {
                         pending_exit = false;
                     }
                     kvm::Exit::MmioWrite { address, data } => {
-                        dev.write_mmio(self.vpindex, address, data).await
+                        dev.write_mmio(self.vpindex, address, data)
+                            .instrument(span.clone())
+                            .await
                     }
                     kvm::Exit::MmioRead { address, data } => {
-                        dev.read_mmio(self.vpindex, address, data).await
+                        dev.read_mmio(self.vpindex, address, data)
+                            .instrument(span.clone())
+                            .await
                     }
                     kvm::Exit::Shutdown => {
                         return Err(VpHaltReason::TripleFault { vtl: Vtl::Vtl0 });
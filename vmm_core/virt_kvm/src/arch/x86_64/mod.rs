@@ -31,6 +31,7 @@
 use hvdef::hypercall::Control;
 use inspect::Inspect;
 use inspect::InspectMut;
+use inspect_counters::SharedCounter;
 use kvm::KVM_CPUID_FLAG_SIGNIFCANT_INDEX;
 use kvm::kvm_ioeventfd_flag_nr_datamatch;
 use kvm::kvm_ioeventfd_flag_nr_deassign;
@@ -46,10 +47,13 @@
 use std::sync::Arc;
 use std::sync::Weak;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::task::Poll;
 use std::time::Duration;
+use std::time::Instant;
 use thiserror::Error;
+use tracing::Instrument;
 use virt::CpuidLeaf;
 use virt::CpuidLeafSet;
 use virt::Hv1;
@@ -221,7 +225,8 @@ fn new_partition<'a>(
                     split_u128(u128::from(
                         HvFeatures::new()
                             .with_privileges(privileges)
-                            .with_frequency_regs_available(true),
+                            .with_frequency_regs_available(true)
+                            .with_guest_crash_regs_available(true),
                     )),
                 ),
                 CpuidLeaf::new(
@@ -309,12 +314,20 @@ fn build(
 
                 // Set the VP index. Also, KVM incorrectly initializes SCONTROL
                 // to 0. Set it to 1 on each processor.
+                //
+                // The crash control MSR is partition-wide, but KVM only
+                // allows setting it through a VP. It tells the guest that
+                // crashes can be reported through the crash MSRs.
                 vp.set_msrs(&[
                     (
                         hvdef::HV_X64_MSR_VP_INDEX,
                         vp_info.base.vp_index.index().into(),
                     ),
                     (hvdef::HV_X64_MSR_SCONTROL, 1),
+                    (
+                        hvdef::HV_X64_MSR_GUEST_CRASH_CTL,
+                        hvdef::GuestCrashCtl::new().with_crash_notify(true).into(),
+                    ),
                 ])?;
             }
 
@@ -404,6 +417,8 @@ fn build(
                     vp_info,
                     synic_message_queue: MessageQueues::new(),
                     siefp: Default::default(),
+                    exits: Default::default(),
+                    guest_time_ns: 0.into(),
                 })
                 .collect(),
             gsi_routing: Mutex::new(gsi_routing),
@@ -449,6 +464,24 @@ pub struct KvmVpInner {
     synic_message_queue: MessageQueues,
     #[inspect(hex, with = "|x| u64::from(*x.read())")]
     siefp: RwLock<HvSynicSimpSiefp>,
+    exits: ExitStats,
+    /// The time spent in `KVM_RUN`, in nanoseconds.
+    #[inspect(skip)]
+    guest_time_ns: AtomicU64,
+}
+
+#[derive(Debug, Default, Inspect)]
+struct ExitStats {
+    interrupted: SharedCounter,
+    interrupt_window: SharedCounter,
+    io: SharedCounter,
+    mmio: SharedCounter,
+    msr: SharedCounter,
+    synic: SharedCounter,
+    hypercall: SharedCounter,
+    debug: SharedCounter,
+    eoi: SharedCounter,
+    other: SharedCounter,
 }
 
 impl KvmVpInner {
@@ -709,6 +742,72 @@ fn write_sint_message(&mut self, sint: u8, msg: &HvMessage) -> Result<bool, Gues
         self.partition.gm.write_plain(simp, &msg.header.typ)?;
         Ok(true)
     }
+
+    /// Reports a crash that the guest signaled through the Hyper-V crash
+    /// MSRs, which KVM emulates in the kernel.
+    fn report_guest_crash(&self, dev: &impl CpuIo) -> Result<(), KvmRunVpError> {
+        let mut parameters = [0; hvdef::HV_X64_GUEST_CRASH_PARAMETER_MSRS];
+        self.kvm
+            .get_msrs(
+                &[
+                    hvdef::HV_X64_MSR_GUEST_CRASH_P0,
+                    hvdef::HV_X64_MSR_GUEST_CRASH_P1,
+                    hvdef::HV_X64_MSR_GUEST_CRASH_P2,
+                    hvdef::HV_X64_MSR_GUEST_CRASH_P3,
+                    hvdef::HV_X64_MSR_GUEST_CRASH_P4,
+                ],
+                &mut parameters,
+            )
+            .map_err(KvmRunVpError::CrashParameters)?;
+        tracing::warn!(?parameters, "Guest signaled crash register");
+
+        // KVM does not report whether the guest set the crash message bit, so
+        // read the message whenever P4 holds a length.
+        let [.., addr, len] = parameters;
+        let mut message = None;
+        if len != 0 {
+            let mut bytes = vec![0u8; std::cmp::min(len as usize, hvdef::HV_PAGE_SIZE_USIZE)];
+            match self.partition.gm.read_at(addr, &mut bytes) {
+                Ok(()) => {
+                    let txt = String::from_utf8_lossy(&bytes);
+                    tracelimit::warn_ratelimited!(txt = txt.as_ref(), "guest reported crash");
+                    message = Some(bytes);
+                }
+                Err(err) => {
+                    tracelimit::error_ratelimited!(
+                        addr,
+                        error = &err as &dyn std::error::Error,
+                        "failed to read crash message"
+                    );
+                }
+            }
+        } else {
+            tracing::warn!("guest reported crash but did not provide message");
+        }
+
+        dev.report_guest_crash(self.vpindex, Vtl::Vtl0, parameters, message.as_deref());
+        Ok(())
+    }
+}
+
+/// Reports the RIP of the current exit for guest access tracing.
+///
+/// KVM does not include the registers in the exit, so they are only queried
+/// when tracing is enabled. For port I/O, KVM has already advanced RIP past
+/// the instruction.
+fn trace_exit_rip(kvm: &kvm::Processor<'_>, vp: VpIndex, dev: &impl CpuIo) {
+    if !dev.is_tracing_access() {
+        return;
+    }
+    match kvm.get_regs() {
+        Ok(regs) => dev.trace_exit_rip(vp, regs.rip),
+        Err(err) => {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to get rip for access tracing"
+            );
+        }
+    }
 }
 
 struct KvmMsi {
@@ -1090,11 +1189,47 @@ async fn run_vp(
                     self.runner.complete_exit()
                 } else {
                     // Run the VP.
-                    self.runner.run()
+                    let start = Instant::now();
+                    let exit = self.runner.run();
+                    self.inner
+                        .guest_time_ns
+                        .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                    exit
                 };
 
                 let exit = exit.map_err(|err| VpHaltReason::Hypervisor(KvmRunVpError::Run(err)))?;
                 pending_exit = true;
+                let exits = &self.inner.exits;
+                match &exit {
+                    kvm::Exit::Interrupted => exits.interrupted.increment(),
+                    kvm::Exit::InterruptWindow => exits.interrupt_window.increment(),
+                    kvm::Exit::IoIn { .. } | kvm::Exit::IoOut { .. } => exits.io.increment(),
+                    kvm::Exit::MmioRead { .. } | kvm::Exit::MmioWrite { .. } => {
+                        exits.mmio.increment()
+                    }
+                    kvm::Exit::MsrRead { .. } | kvm::Exit::MsrWrite { .. } => exits.msr.increment(),
+                    kvm::Exit::SynicUpdate { .. } => exits.synic.increment(),
+                    kvm::Exit::HvHypercall { .. } => exits.hypercall.increment(),
+                    kvm::Exit::Debug { .. } => exits.debug.increment(),
+                    kvm::Exit::Eoi { .. } => exits.eoi.increment(),
+                    _ => exits.other.increment(),
+                }
+                // The span is closed at the end of the iteration, so it times
+                // the whole exit, not just the device emulation that runs
+                // inside it.
+                let span =
+                    tracing::trace_span!("vm_exit", vp = self.vpindex.index(), reason = ?exit);
+                if matches!(
+                    exit,
+                    kvm::Exit::IoIn { .. }
+                        | kvm::Exit::IoOut { .. }
+                        | kvm::Exit::MmioRead { .. }
+                        | kvm::Exit::MmioWrite { .. }
+                        | kvm::Exit::MsrRead { .. }
+                        | kvm::Exit::MsrWrite { .. }
+                ) {
+                    trace_exit_rip(&self.kvm, self.vpindex, dev);
+                }
                 match exit {
                     kvm::Exit::Interrupted => {
                         tracing::trace!("interrupted");
@@ -1106,24 +1241,33 @@ async fn run_vp(
                     }
                     kvm::Exit::IoIn { port, data, size } => {
                         for data in data.chunks_mut(size as usize) {
-                            dev.read_io(self.vpindex, port, data).await;
+                            dev.read_io(self.vpindex, port, data)
+                                .instrument(span.clone())
+                                .await;
                         }
                     }
                     kvm::Exit::IoOut { port, data, size } => {
                         for data in data.chunks(size as usize) {
-                            dev.write_io(self.vpindex, port, data).await;
+                            dev.write_io(self.vpindex, port, data)
+                                .instrument(span.clone())
+                                .await;
                         }
                     }
                     kvm::Exit::MmioWrite { address, data } => {
-                        dev.write_mmio(self.vpindex, address, data).await
+                        dev.write_mmio(self.vpindex, address, data)
+                            .instrument(span.clone())
+                            .await
                     }
                     kvm::Exit::MmioRead { address, data } => {
-                        dev.read_mmio(self.vpindex, address, data).await
+                        dev.read_mmio(self.vpindex, address, data)
+                            .instrument(span.clone())
+                            .await
                     }
                     kvm::Exit::MsrRead { index, data, error } => {
                         if MYSTERY_MSRS.contains(&index) {
                             tracelimit::warn_ratelimited!(index, "stubbed out mystery MSR read");
                             *data = 0;
+                            dev.trace_msr(self.vpindex, index, false, *data);
                         } else {
                             tracelimit::error_ratelimited!(index, "unrecognized msr read");
                             *error = 1;
@@ -1132,6 +1276,7 @@ async fn run_vp(
                     kvm::Exit::MsrWrite { index, data, error } => {
                         if MYSTERY_MSRS.contains(&index) {
                             tracelimit::warn_ratelimited!(index, "stubbed out mystery MSR write");
+                            dev.trace_msr(self.vpindex, index, true, data);
                         } else {
                             tracelimit::error_ratelimited!(index, data, "unrecognized msr write");
                             *error = 1;
@@ -1191,6 +1336,10 @@ async fn run_vp(
                     kvm::Exit::Eoi { irq } => {
                         dev.handle_eoi(irq.into());
                     }
+                    kvm::Exit::GuestCrash => {
+                        self.report_guest_crash(dev)
+                            .map_err(VpHaltReason::Hypervisor)?;
+                    }
                     kvm::Exit::InternalError { error, .. } => {
                         return Err(VpHaltReason::Hypervisor(KvmRunVpError::InternalError(
                             error,
@@ -1223,6 +1372,12 @@ fn access_state(&mut self, vtl: Vtl) -> Self::StateAccess<'_> {
         assert_eq!(vtl, Vtl::Vtl0);
         self.partition.vp_state_access(self.vpindex)
     }
+
+    fn guest_run_time(&self) -> Option<Duration> {
+        Some(Duration::from_nanos(
+            self.inner.guest_time_ns.load(Ordering::Relaxed),
+        ))
+    }
 }
 
 impl virt::Synic for KvmPartition {
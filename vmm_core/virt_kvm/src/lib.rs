@@ -24,6 +24,7 @@
 
 pub use arch::Kvm;
 use arch::KvmVpInner;
+use hvdef::HV_PAGE_SIZE;
 use hvdef::Vtl;
 use std::sync::atomic::Ordering;
 use virt::VpIndex;
@@ -53,6 +54,7 @@ pub enum KvmError {
 struct KvmMemoryRange {
     host_addr: *mut u8,
     range: MemoryRange,
+    readonly: bool,
 }
 
 unsafe impl Sync for KvmMemoryRange {}
@@ -62,6 +64,7 @@ unsafe impl Send for KvmMemoryRange {}
 struct KvmMemoryRangeState {
     #[inspect(flatten, iter_by_index)]
     ranges: Vec<Option<KvmMemoryRange>>,
+    dirty_tracking: bool,
 }
 
 #[derive(Inspect)]
@@ -99,6 +102,8 @@ pub enum KvmRunVpError {
     Run(#[source] kvm::Error),
     #[error("failed to inject an extint interrupt")]
     ExtintInterrupt(#[source] kvm::Error),
+    #[error("failed to get the guest crash parameters")]
+    CrashParameters(#[source] kvm::Error),
 }
 
 #[cfg_attr(guest_arch = "aarch64", expect(dead_code))]
@@ -161,12 +166,19 @@ unsafe fn map_region(
         }
         let slot_to_use = slot_to_use.unwrap();
         unsafe {
-            self.kvm
-                .set_user_memory_region(slot_to_use as u32, data, size, addr, readonly)?
+            self.kvm.set_user_memory_region(
+                slot_to_use as u32,
+                data,
+                size,
+                addr,
+                readonly,
+                state.dirty_tracking,
+            )?
         };
         state.ranges[slot_to_use] = Some(KvmMemoryRange {
             host_addr: data,
             range: MemoryRange::new(addr..addr + size as u64),
+            readonly,
         });
         Ok(())
     }
@@ -208,6 +220,7 @@ fn unmap_range(&self, addr: u64, size: u64) -> Result<(), virt::Error> {
                         0,
                         0,
                         false,
+                        false,
                     )?;
                 }
                 *entry = None;
@@ -220,4 +233,64 @@ fn unmap_range(&self, addr: u64, size: u64) -> Result<(), virt::Error> {
         }
         Ok(())
     }
+
+    fn set_dirty_tracking(&self, enable: bool) -> Result<(), virt::Error> {
+        let mut state = self.memory.lock();
+        for (slot, entry) in state.ranges.iter().enumerate() {
+            let Some(kvm_range) = entry else { continue };
+            // SAFETY: re-registering an existing slot with the same memory,
+            // only changing its flags.
+            unsafe {
+                self.kvm.set_user_memory_region(
+                    slot as u32,
+                    kvm_range.host_addr,
+                    kvm_range.range.len() as usize,
+                    kvm_range.range.start(),
+                    kvm_range.readonly,
+                    enable,
+                )?;
+            }
+        }
+        state.dirty_tracking = enable;
+        Ok(())
+    }
+
+    fn query_and_clear_dirty(
+        &self,
+        addr: u64,
+        size: u64,
+        bitmap: &mut [u64],
+    ) -> Result<(), virt::Error> {
+        let range = MemoryRange::new(addr..addr + size);
+        let page_count = size / HV_PAGE_SIZE;
+        anyhow::ensure!(
+            bitmap.len() as u64 * 64 >= page_count,
+            "dirty bitmap too small"
+        );
+        bitmap.fill(0);
+
+        let state = self.memory.lock();
+        anyhow::ensure!(state.dirty_tracking, "dirty page tracking is not enabled");
+        for (slot, entry) in state.ranges.iter().enumerate() {
+            let Some(kvm_range) = entry else { continue };
+            if !range.overlaps(&kvm_range.range) {
+                continue;
+            }
+            anyhow::ensure!(
+                range.contains(&kvm_range.range),
+                "can only query the dirty state of whole ranges"
+            );
+            let slot_pages = kvm_range.range.len() / HV_PAGE_SIZE;
+            let mut slot_bitmap = vec![0; slot_pages.div_ceil(64) as usize];
+            // SAFETY: the bitmap has a bit for each page in the slot.
+            unsafe { self.kvm.get_dirty_log(slot as u32, &mut slot_bitmap)? };
+            virt::merge_dirty_bitmap(
+                bitmap,
+                (kvm_range.range.start() - addr) / HV_PAGE_SIZE,
+                &slot_bitmap,
+                slot_pages,
+            );
+        }
+        Ok(())
+    }
 }
@@ -16,6 +16,7 @@
 use guestmem::GuestMemory;
 use hv1_emulator::message_queues::MessageQueues;
 use hv1_hypercall::X64RegisterIo;
+use hvdef::HV_PAGE_SIZE;
 use hvdef::HvDeliverabilityNotificationsRegister;
 use hvdef::HvError;
 use hvdef::HvMessage;
@@ -27,8 +28,11 @@
 use hvdef::hypercall::HvRegisterAssoc;
 use inspect::Inspect;
 use inspect::InspectMut;
+use mshv_bindings::MSHV_GPAP_ACCESS_OP_CLEAR;
+use mshv_bindings::MSHV_GPAP_ACCESS_OP_SET;
 use mshv_bindings::MSHV_SET_MEM_BIT_EXECUTABLE;
 use mshv_bindings::MSHV_SET_MEM_BIT_WRITABLE;
+use mshv_bindings::hv_intercept_parameters;
 use mshv_bindings::hv_message;
 use mshv_bindings::hv_register_assoc;
 use mshv_bindings::hv_register_value;
@@ -55,6 +59,7 @@
 use std::sync::Once;
 use std::sync::Weak;
 use thiserror::Error;
+use tracing::Instrument;
 use virt::Hv1;
 use virt::NeedsYield;
 use virt::PartitionAccessState;
@@ -250,6 +255,7 @@ fn build(
                 vps: self.vps,
                 irq_routes: Default::default(),
                 caps,
+                debug_intercept: Mutex::new(false),
             }),
         };
 
@@ -281,6 +287,9 @@ struct MshvPartitionInner {
     vps: Vec<MshvVpInner>,
     irq_routes: virt::irqcon::IrqRoutes,
     caps: virt::PartitionCapabilities,
+    /// Whether #DB is intercepted for a debugger. Intercepts cannot be
+    /// removed once installed.
+    debug_intercept: Mutex<bool>,
 }
 
 #[derive(Debug)]
@@ -394,6 +403,25 @@ fn now(&self) -> ReferenceTimeResult {
 }
 
 impl MshvPartitionInner {
+    /// Intercepts debug exceptions so that a debugger's breakpoints and single
+    /// steps exit to the VMM.
+    fn install_debug_intercept(&self) -> Result<(), Error> {
+        let mut installed = self.debug_intercept.lock();
+        if !*installed {
+            self.vmfd
+                .install_intercept(mshv_install_intercept {
+                    access_type_mask: HV_INTERCEPT_ACCESS_MASK_EXECUTE,
+                    intercept_type: hvdef::hypercall::HvInterceptType::HvInterceptTypeException.0,
+                    intercept_parameter: hv_intercept_parameters {
+                        exception_vector: x86defs::Exception::DEBUG.0.into(),
+                    },
+                })
+                .map_err(Error::InstallIntercept)?;
+            *installed = true;
+        }
+        Ok(())
+    }
+
     fn vp(&self, vp_index: VpIndex) -> &MshvVpInner {
         &self.vps[vp_index.index() as usize]
     }
@@ -437,6 +465,7 @@ fn bind(&mut self) -> Result<Self::Processor<'_>, Self::Error> {
             partition: &self.partition,
             inner: &self.partition.vps[self.vpindex.index() as usize],
             vpindex: self.vpindex,
+            debug_state: None,
         })
     }
 }
@@ -445,6 +474,8 @@ pub struct MshvProcessor<'a> {
     partition: &'a MshvPartitionInner,
     inner: &'a MshvVpInner,
     vpindex: VpIndex,
+    /// The breakpoints and single step state set by a debugger.
+    debug_state: Option<virt::x86::DebugState>,
 }
 
 impl MshvProcessor<'_> {
@@ -478,6 +509,7 @@ async fn handle_io_port_intercept(
         devices: &impl CpuIo,
     ) -> Result<(), VpHaltReason<MshvError>> {
         let info = message.to_ioport_info().unwrap();
+        devices.trace_exit_rip(self.vpindex, info.header.rip);
         let access_info = info.access_info;
         // SAFETY: This union only contains one field.
         let port_access_info = unsafe { access_info.__bindgen_anon_1 };
@@ -519,12 +551,89 @@ async fn handle_io_port_intercept(
         Ok(())
     }
 
+    /// Reports a debug exception caused by the debugger's breakpoints or
+    /// single stepping, and reflects any other exception to the guest.
+    fn handle_exception_intercept(
+        &self,
+        message: &hv_message,
+    ) -> Result<(), VpHaltReason<MshvError>> {
+        let info = message.to_exception_info().unwrap();
+        if info.exception_vector == u16::from(x86defs::Exception::DEBUG.0) {
+            if let Some(reason) = self
+                .debug_halt_reason(info.exception_parameter, info.header.rflags)
+                .map_err(VpHaltReason::Hypervisor)?
+            {
+                return Err(reason);
+            }
+        }
+
+        // SAFETY: This union only contains one field.
+        let exception_info = unsafe { info.exception_info.__bindgen_anon_1 };
+        let event = hvdef::HvX64PendingExceptionEvent::new()
+            .with_event_pending(true)
+            .with_event_type(hvdef::HV_X64_PENDING_EVENT_EXCEPTION)
+            .with_deliver_error_code(exception_info.error_code_valid() != 0)
+            .with_error_code(info.error_code)
+            .with_vector(info.exception_vector)
+            .with_exception_parameter(info.exception_parameter);
+        // SAFETY: `HvRegisterAssoc` and `hv_register_assoc` have the same layout.
+        let reg = unsafe {
+            std::mem::transmute::<HvRegisterAssoc, hv_register_assoc>(HvRegisterAssoc::from((
+                HvX64RegisterName::PendingEvent0,
+                u128::from(event),
+            )))
+        };
+        self.inner
+            .vcpufd
+            .set_reg(&[reg])
+            .map_err(VpHaltReason::Hypervisor)
+    }
+
+    /// Returns the halt reason for a debug exception with the pending debug
+    /// conditions `dr6`, or `None` if the debugger did not cause it.
+    fn debug_halt_reason(
+        &self,
+        dr6: u64,
+        rflags: u64,
+    ) -> Result<Option<VpHaltReason<MshvError>>, MshvError> {
+        let Some(state) = self.debug_state else {
+            return Ok(None);
+        };
+        let mut rflags = RFlags::from(rflags);
+        let reason = if dr6 & x86defs::DR6_BREAKPOINT_MASK != 0 {
+            let Some(breakpoint) = state.breakpoints[dr6.trailing_zeros() as usize] else {
+                return Ok(None);
+            };
+            if breakpoint.ty == virt::x86::BreakpointType::Execute {
+                // Set the resume flag so that the instruction runs when the VP
+                // resumes, as the guest's own handler would.
+                rflags.set_resume(true);
+            }
+            VpHaltReason::HwBreak(breakpoint)
+        } else if dr6 & x86defs::DR6_SINGLE_STEP != 0 && state.single_step {
+            VpHaltReason::SingleStep
+        } else {
+            return Ok(None);
+        };
+        let arr_reg_name_value = [
+            (mshv_bindings::hv_register_name_HV_X64_REGISTER_DR6, 0),
+            (
+                mshv_bindings::hv_register_name_HV_X64_REGISTER_RFLAGS,
+                rflags.into(),
+            ),
+        ];
+        set_registers_64!(self.inner.vcpufd, arr_reg_name_value)?;
+        Ok(Some(reason))
+    }
+
     async fn handle_mmio_intercept(
         &self,
         message: &hv_message,
         devices: &impl CpuIo,
     ) -> Result<(), VpHaltReason<MshvError>> {
-        let execution_state = message.to_memory_info().unwrap().header.execution_state;
+        let header = message.to_memory_info().unwrap().header;
+        devices.trace_exit_rip(self.vpindex, header.rip);
+        let execution_state = header.execution_state;
         // SAFETY: This union only contains one field.
         let mmio_execution_state = unsafe { execution_state.__bindgen_anon_1 };
         let interruption_pending = mmio_execution_state.interruption_pending() != 0;
@@ -1223,6 +1332,64 @@ fn unmap_range(&self, addr: u64, size: u64) -> Result<(), virt::Error> {
         state.ranges[slot] = None;
         Ok(())
     }
+
+    fn set_dirty_tracking(&self, enable: bool) -> Result<(), virt::Error> {
+        let state = self.memory.lock();
+        if enable {
+            self.vmfd.enable_dirty_page_tracking()?;
+        } else {
+            // The hypervisor requires all pages to be marked dirty before
+            // tracking is disabled.
+            for range in state.ranges.iter().flatten() {
+                self.vmfd.get_dirty_log(
+                    range.guest_pfn / HV_PAGE_SIZE,
+                    range.size as usize,
+                    MSHV_GPAP_ACCESS_OP_SET as u8,
+                )?;
+            }
+            self.vmfd.disable_dirty_page_tracking()?;
+        }
+        Ok(())
+    }
+
+    fn query_and_clear_dirty(
+        &self,
+        addr: u64,
+        size: u64,
+        bitmap: &mut [u64],
+    ) -> Result<(), virt::Error> {
+        let page_count = size / HV_PAGE_SIZE;
+        anyhow::ensure!(
+            bitmap.len() as u64 * 64 >= page_count,
+            "dirty bitmap too small"
+        );
+        bitmap.fill(0);
+
+        let state = self.memory.lock();
+        for range in state.ranges.iter().flatten() {
+            let start = range.guest_pfn;
+            let end = start + range.size;
+            if end <= addr || start >= addr + size {
+                continue;
+            }
+            anyhow::ensure!(
+                start >= addr && end <= addr + size,
+                "can only query the dirty state of whole ranges"
+            );
+            let range_bitmap = self.vmfd.get_dirty_log(
+                start / HV_PAGE_SIZE,
+                range.size as usize,
+                MSHV_GPAP_ACCESS_OP_CLEAR as u8,
+            )?;
+            virt::merge_dirty_bitmap(
+                bitmap,
+                (start - addr) / HV_PAGE_SIZE,
+                &range_bitmap,
+                range.size / HV_PAGE_SIZE,
+            );
+        }
+        Ok(())
+    }
 }
 
 // TODO: implementation
@@ -1379,9 +1546,57 @@ impl virt::Processor for MshvProcessor<'_> {
     fn set_debug_state(
         &mut self,
         _vtl: Vtl,
-        _state: Option<&virt::x86::DebugState>,
+        state: Option<&virt::x86::DebugState>,
     ) -> Result<(), Self::Error> {
-        Err(Error::NotSupported)
+        if state.is_some() {
+            self.partition.install_debug_intercept()?;
+        }
+
+        let mut db = [0; 4];
+        let mut dr7 = 0;
+        if let Some(state) = state {
+            for (i, bp) in state.breakpoints.iter().enumerate() {
+                if let Some(bp) = bp {
+                    db[i] = bp.address;
+                    dr7 |= bp.dr7_bits(i);
+                }
+            }
+        }
+
+        let mut regs = [hv_register_assoc {
+            name: mshv_bindings::hv_register_name_HV_X64_REGISTER_RFLAGS,
+            value: hv_register_value { reg64: 0 },
+            ..Default::default()
+        }];
+        self.inner
+            .vcpufd
+            .get_reg(&mut regs)
+            .map_err(Error::Register)?;
+        // SAFETY: the value has been written by the kernel.
+        let mut rflags = RFlags::from(unsafe { regs[0].value.reg64 });
+        // Leave the trap flag alone if the debugger never set it, since the
+        // guest may be single stepping itself.
+        if state.is_some_and(|state| state.single_step)
+            || self.debug_state.is_some_and(|state| state.single_step)
+        {
+            rflags.set_trap(state.is_some_and(|state| state.single_step));
+        }
+
+        let arr_reg_name_value = [
+            (mshv_bindings::hv_register_name_HV_X64_REGISTER_DR0, db[0]),
+            (mshv_bindings::hv_register_name_HV_X64_REGISTER_DR1, db[1]),
+            (mshv_bindings::hv_register_name_HV_X64_REGISTER_DR2, db[2]),
+            (mshv_bindings::hv_register_name_HV_X64_REGISTER_DR3, db[3]),
+            (mshv_bindings::hv_register_name_HV_X64_REGISTER_DR7, dr7),
+            (
+                mshv_bindings::hv_register_name_HV_X64_REGISTER_RFLAGS,
+                rflags.into(),
+            ),
+        ];
+        set_registers_64!(self.inner.vcpufd, arr_reg_name_value).map_err(Error::Register)?;
+
+        self.debug_state = state.copied();
+        Ok(())
     }
 
     async fn run_vp(
@@ -1402,29 +1617,47 @@ async fn run_vp(
             stop.check()?;
 
             match vcpufd.run() {
-                Ok(exit) => match HvMessageType(exit.header.message_type) {
-                    HvMessageType::HvMessageTypeUnrecoverableException => {
-                        return Err(VpHaltReason::TripleFault { vtl: Vtl::Vtl0 });
-                    }
-                    HvMessageType::HvMessageTypeX64IoPortIntercept => {
-                        self.handle_io_port_intercept(&exit, dev).await?;
-                    }
-                    HvMessageType::HvMessageTypeUnmappedGpa
-                    | HvMessageType::HvMessageTypeGpaIntercept => {
-                        self.handle_mmio_intercept(&exit, dev).await?;
-                    }
-                    HvMessageType::HvMessageTypeSynicSintDeliverable => {
-                        tracing::trace!("SYNIC_SINT_DELIVERABLE");
-                        self.handle_synic_deliverable_exit(&exit, dev)?;
-                    }
-                    HvMessageType::HvMessageTypeHypercallIntercept => {
-                        tracing::trace!("HYPERCALL_INTERCEPT");
-                        self.handle_hypercall_intercept(&exit, dev)?;
-                    }
-                    exit => {
-                        panic!("Unhandled vcpu exit code {exit:?}");
+                Ok(exit) => {
+                    let message_type = HvMessageType(exit.header.message_type);
+                    // The span is closed at the end of the iteration, so it
+                    // times the whole exit, not just the device emulation
+                    // that runs inside it.
+                    let span = tracing::trace_span!(
+                        "vm_exit",
+                        vp = self.vpindex.index(),
+                        reason = ?message_type
+                    );
+                    match message_type {
+                        HvMessageType::HvMessageTypeUnrecoverableException => {
+                            return Err(VpHaltReason::TripleFault { vtl: Vtl::Vtl0 });
+                        }
+                        HvMessageType::HvMessageTypeX64IoPortIntercept => {
+                            self.handle_io_port_intercept(&exit, dev)
+                                .instrument(span.clone())
+                                .await?;
+                        }
+                        HvMessageType::HvMessageTypeUnmappedGpa
+                        | HvMessageType::HvMessageTypeGpaIntercept => {
+                            self.handle_mmio_intercept(&exit, dev)
+                                .instrument(span.clone())
+                                .await?;
+                        }
+                        HvMessageType::HvMessageTypeSynicSintDeliverable => {
+                            tracing::trace!("SYNIC_SINT_DELIVERABLE");
+                            self.handle_synic_deliverable_exit(&exit, dev)?;
+                        }
+                        HvMessageType::HvMessageTypeHypercallIntercept => {
+                            tracing::trace!("HYPERCALL_INTERCEPT");
+                            self.handle_hypercall_intercept(&exit, dev)?;
+                        }
+                        HvMessageType::HvMessageTypeExceptionIntercept => {
+                            self.handle_exception_intercept(&exit)?;
+                        }
+                        exit => {
+                            panic!("Unhandled vcpu exit code {exit:?}");
+                        }
                     }
-                },
+                }
 
                 Err(e) => match e.errno() {
                     libc::EAGAIN | libc::EINTR => {}
@@ -48,6 +48,7 @@
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::task::Waker;
+use std::time::Duration;
 use thiserror::Error;
 use virt::IsolationType;
 use virt::NeedsYield;
@@ -118,6 +119,8 @@ struct VtlPartition {
     vplcs: Vec<Vplc>,
     #[inspect(with = "|x| inspect::adhoc(|req| inspect::iter_by_index(&*x.read()).inspect(req))")]
     ranges: RwLock<Vec<memory::MappedRange>>,
+    /// Whether writes to `ranges` are being reported as dirty pages.
+    dirty_tracking: AtomicBool,
 
     /// Virtual PCI device interrupt remapping table. This is used instead of
     /// the hypervisor's implementation so that system-level privileges (needed
@@ -131,6 +134,11 @@ struct VtlPartition {
     lapic: LocalApicKind,
 
     hypervisor_enlightened: bool,
+
+    /// The exceptions that exit when no debugger is using the partition.
+    #[cfg(guest_arch = "x86_64")]
+    #[inspect(hex)]
+    exception_exit_bitmap: u64,
 }
 
 #[derive(Inspect)]
@@ -180,6 +188,8 @@ struct RunState {
     finish_reset_vtl0: bool,
     #[inspect(skip)]
     finish_reset_vtl2: bool,
+    #[inspect(hex, iter_by_index)]
+    crash_params: [u64; 5],
     crash_msg_address: Option<u64>,
     crash_msg_len: Option<usize>,
     #[inspect(flatten)]
@@ -187,6 +197,9 @@ struct RunState {
     #[inspect(mut)]
     halted: bool,
     exits: vp::ExitStats,
+    /// The time spent running the guest, from the VMM's point of view.
+    #[inspect(skip)]
+    guest_time: Duration,
     vmtime: VmTimeAccess,
 }
 
@@ -205,6 +218,10 @@ struct PerVtlRunState {
     deliverability_notifications: HvDeliverabilityNotificationsRegister,
     // Only used when `hv` is `None`.
     vp_assist_page: u64,
+    /// The breakpoints and single step state set by a debugger.
+    #[cfg(guest_arch = "x86_64")]
+    #[inspect(debug)]
+    debug_state: Option<virt::x86::DebugState>,
 }
 
 impl PerVtlRunState {
@@ -225,6 +242,8 @@ pub fn new(lapic: &LocalApicKind, hv: &Hv1State, vp_info: &TargetVpInfo, vtl: Vt
             },
             deliverability_notifications: 0.into(),
             vp_assist_page: 0,
+            #[cfg(guest_arch = "x86_64")]
+            debug_state: None,
         }
     }
 
@@ -278,6 +297,7 @@ fn reset(&mut self, vtl2_scrub: bool, is_bsp: bool) {
             ref mut runnable_vtls,
             ref mut vtl2_deliverability_notifications,
             enabled_vtls,
+            ref mut crash_params,
             ref mut crash_msg_address,
             ref mut crash_msg_len,
             ref mut vtls,
@@ -285,6 +305,7 @@ fn reset(&mut self, vtl2_scrub: bool, is_bsp: bool) {
             finish_reset_vtl0: ref mut reset_vtl0,
             finish_reset_vtl2: ref mut reset_vtl2,
             exits: _,
+            guest_time: _,
             vtl2_wakeup_vmtime: _,
             vmtime: _,
         } = self;
@@ -292,6 +313,7 @@ fn reset(&mut self, vtl2_scrub: bool, is_bsp: bool) {
         *runnable_vtls = enabled_vtls;
         *active_vtl = runnable_vtls.highest_set().unwrap();
         *vtl2_deliverability_notifications = Default::default();
+        *crash_params = [0; 5];
         *crash_msg_address = None;
         *crash_msg_len = None;
         if !vtl2_scrub {
@@ -314,6 +336,10 @@ fn reset(&mut self, is_bsp: bool) {
             hv,
             deliverability_notifications,
             vp_assist_page,
+            // Owned by the debugger, which reapplies it when it next resumes
+            // the VM.
+            #[cfg(guest_arch = "x86_64")]
+                debug_state: _,
         } = self;
 
         #[cfg(guest_arch = "x86_64")]
@@ -845,6 +871,7 @@ fn build(
                         vtl2_deliverability_notifications: Default::default(),
                         finish_reset_vtl0: true,
                         finish_reset_vtl2: partition.inner.vtl2.is_some(),
+                        crash_params: [0; 5],
                         crash_msg_address: None,
                         crash_msg_len: None,
                         halted: false,
@@ -870,6 +897,7 @@ fn build(
                             .vmtime
                             .access(format!("vp-{}", vp.index.index())),
                         exits: Default::default(),
+                        guest_time: Duration::ZERO,
                     }),
                 }
             })
@@ -1191,6 +1219,8 @@ fn new(config: &ProtoPartitionConfig<'_>, vtl: Vtl) -> Result<Self, Error> {
             ))
             .for_op("set processor count")?;
 
+        #[cfg(guest_arch = "x86_64")]
+        let mut exception_exit_bitmap = 0;
         #[cfg(guest_arch = "x86_64")]
         {
             use vm_topology::processor::x86::ApicMode;
@@ -1220,13 +1250,17 @@ fn new(config: &ProtoPartitionConfig<'_>, vtl: Vtl) -> Result<Self, Error> {
                     .for_op("set msr exit bitmap")?;
                 // Enable #GP faults to get synic MSR accesses, for which which the
                 // hypervisor incorrectly fails to exit to the parent.
-                extended_exits |= whp::abi::WHV_EXTENDED_VM_EXITS::ExceptionExit;
-                whp_config
-                    .set_property(whp::PartitionProperty::ExceptionExitBitmap(
-                        1 << x86defs::Exception::GENERAL_PROTECTION_FAULT.0,
-                    ))
-                    .for_op("set exception exit bitmap")?;
+                exception_exit_bitmap |= 1 << x86defs::Exception::GENERAL_PROTECTION_FAULT.0;
             }
+
+            // Always enable exception exits so that a debugger can intercept
+            // #DB later.
+            extended_exits |= whp::abi::WHV_EXTENDED_VM_EXITS::ExceptionExit;
+            whp_config
+                .set_property(whp::PartitionProperty::ExceptionExitBitmap(
+                    exception_exit_bitmap,
+                ))
+                .for_op("set exception exit bitmap")?;
         }
 
         #[cfg(all(guest_arch = "aarch64", feature = "unstable_whp"))]
@@ -1432,9 +1466,12 @@ fn new(config: &ProtoPartitionConfig<'_>, vtl: Vtl) -> Result<Self, Error> {
                 apic_id_map,
             ),
             ranges: Default::default(),
+            dirty_tracking: AtomicBool::new(false),
             mapper,
             lapic,
             hypervisor_enlightened,
+            #[cfg(guest_arch = "x86_64")]
+            exception_exit_bitmap,
         })
     }
 
@@ -1495,6 +1532,16 @@ impl<'p> virt::Processor for WhpProcessor<'p> {
     where
         Self: 'a;
 
+    #[cfg(guest_arch = "x86_64")]
+    fn set_debug_state(
+        &mut self,
+        vtl: Vtl,
+        state: Option<&virt::x86::DebugState>,
+    ) -> Result<(), Self::Error> {
+        self.set_debug_state_x86(vtl, state)
+    }
+
+    #[cfg(guest_arch = "aarch64")]
     fn set_debug_state(
         &mut self,
         _vtl: Vtl,
@@ -1529,6 +1576,10 @@ fn flush_async_requests(&mut self) -> Result<(), Self::RunVpError> {
     fn access_state(&mut self, vtl: Vtl) -> Self::StateAccess<'_> {
         self.access_state(vtl)
     }
+
+    fn guest_run_time(&self) -> Option<Duration> {
+        Some(self.state.guest_time)
+    }
 }
 
 impl virt::Hv1 for WhpPartition {
@@ -15,6 +15,8 @@
 use std::fmt::Debug;
 use std::os::windows::prelude::*;
 use std::sync::Arc;
+use std::sync::OnceLock;
+use std::sync::atomic::Ordering;
 use virt::PageVisibility;
 
 #[derive(Debug, Inspect)]
@@ -24,6 +26,16 @@ pub(super) struct MappedRange {
     exec: bool,
 }
 
+/// Returns whether WHP can track the pages written to mapped ranges.
+fn dirty_tracking_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        whp::capabilities::features().is_ok_and(|features| {
+            features.is_set(whp::abi::WHV_CAPABILITY_FEATURES::DirtyPageTracking)
+        })
+    })
+}
+
 /// Trait implemented by underlying partition implementations for mapping and
 /// unmapping ranges.
 pub trait SimpleMemoryMap: Send + Sync {
@@ -54,6 +66,13 @@ unsafe fn map_range(
         let mut flags = whp::abi::WHvMapGpaRangeFlagRead;
         if writable {
             flags |= whp::abi::WHvMapGpaRangeFlagWrite;
+            // A range's flags can only be changed by remapping it, so track
+            // writes from the start rather than when dirty tracking is
+            // enabled. Until the dirty bitmap is first cleared, this costs at
+            // most one extra fault per page.
+            if dirty_tracking_supported() {
+                flags |= whp::abi::WHvMapGpaRangeFlagTrackDirtyPages;
+            }
         }
         if exec {
             flags |= whp::abi::WHvMapGpaRangeFlagExecute;
@@ -410,6 +429,73 @@ pub fn map_deferred(&self) -> Result<(), virt::Error> {
         self.mapper.map_deferred(&self.whp)
     }
 
+    fn set_dirty_tracking(&self, enable: bool) -> Result<(), virt::Error> {
+        if enable {
+            anyhow::ensure!(
+                dirty_tracking_supported(),
+                "dirty page tracking is not supported by this version of WHP"
+            );
+            // Writable ranges are always mapped with dirty tracking, so just
+            // mark all their pages clean.
+            for range in self.ranges.read().iter().filter(|r| r.writable) {
+                self.whp
+                    .query_gpa_range_dirty_bitmap(range.range.start(), range.range.len(), None)
+                    .context("failed to clear dirty bitmap")?;
+            }
+        }
+        self.dirty_tracking.store(enable, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn query_and_clear_dirty(
+        &self,
+        addr: u64,
+        size: u64,
+        bitmap: &mut [u64],
+    ) -> Result<(), virt::Error> {
+        let range = MemoryRange::new(addr..addr + size);
+        anyhow::ensure!(
+            bitmap.len() as u64 * 64 >= size / HV_PAGE_SIZE,
+            "dirty bitmap too small"
+        );
+        bitmap.fill(0);
+
+        anyhow::ensure!(
+            self.dirty_tracking.load(Ordering::Relaxed),
+            "dirty page tracking is not enabled"
+        );
+        for mapped in self.ranges.read().iter() {
+            if !range.overlaps(&mapped.range) {
+                continue;
+            }
+            anyhow::ensure!(
+                range.contains(&mapped.range),
+                "can only query the dirty state of whole ranges"
+            );
+            // Read-only ranges are not tracked, and the guest cannot dirty
+            // them.
+            if !mapped.writable {
+                continue;
+            }
+            let page_count = mapped.range.len() / HV_PAGE_SIZE;
+            let mut range_bitmap = vec![0; page_count.div_ceil(64) as usize];
+            self.whp
+                .query_gpa_range_dirty_bitmap(
+                    mapped.range.start(),
+                    mapped.range.len(),
+                    Some(&mut range_bitmap),
+                )
+                .context("failed to query dirty bitmap")?;
+            virt::merge_dirty_bitmap(
+                bitmap,
+                (mapped.range.start() - addr) / HV_PAGE_SIZE,
+                &range_bitmap,
+                page_count,
+            );
+        }
+        Ok(())
+    }
+
     fn unmap_range(&self, addr: u64, size: u64) -> Result<(), virt::Error> {
         let range = MemoryRange::new(addr..addr + size);
         let mut ranges = self.ranges.write();
@@ -516,6 +602,19 @@ fn pin_range(&self, addr: u64, size: u64) -> Result<(), virt::Error> {
             }])?;
         Ok(())
     }
+
+    fn set_dirty_tracking(&self, enable: bool) -> Result<(), virt::Error> {
+        self.vtlp().set_dirty_tracking(enable)
+    }
+
+    fn query_and_clear_dirty(
+        &self,
+        addr: u64,
+        size: u64,
+        bitmap: &mut [u64],
+    ) -> Result<(), virt::Error> {
+        self.vtlp().query_and_clear_dirty(addr, size, bitmap)
+    }
 }
 
 #[derive(Inspect)]
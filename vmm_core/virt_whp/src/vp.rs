@@ -19,7 +19,9 @@
 use std::mem::offset_of;
 use std::sync::atomic::Ordering;
 use std::task::Poll;
+use std::time::Instant;
 use thiserror::Error;
+use tracing::Instrument;
 use tracing_helpers::ErrorValueExt;
 use virt::StopVp;
 use virt::VpHaltReason;
@@ -407,9 +409,11 @@ pub(crate) async fn run_vp(
             let lazy_eoi = self.sync_lazy_eoi();
 
             let mut runner = self.current_whp().runner();
+            let start = Instant::now();
             let exit = runner
                 .run()
                 .map_err(|err| VpHaltReason::Hypervisor(WhpRunVpError::Run(err)))?;
+            self.state.guest_time += start.elapsed();
 
             // Clear lazy EOI before processing the exit.
             if lazy_eoi {
@@ -417,7 +421,9 @@ pub(crate) async fn run_vp(
             }
 
             // Process the actual exit.
-            self.handle_exit(dev, exit).await?;
+            let span =
+                tracing::trace_span!("vm_exit", vp = self.vp.index.index(), reason = ?exit.reason);
+            self.handle_exit(dev, exit).instrument(span).await?;
         }
     }
 
@@ -565,6 +571,7 @@ mod x86 {
     use super::WhpRunVpError;
     use crate::Hv1State;
     use crate::WhpProcessor;
+    use crate::WhpResultExt;
     use crate::emu;
     use crate::emu::WhpVpRefEmulation;
     use crate::memory::x86::GpaBackingType;
@@ -606,6 +613,8 @@ pub(super) async fn handle_exit(
         ) -> Result<(), VpHaltReason<WhpRunVpError>> {
             use whp::ExitReason;
 
+            dev.trace_exit_rip(self.vp.index, exit.vp_context.Rip);
+
             let stat = match exit.reason {
                 ExitReason::IoPortAccess(info) => {
                     self.handle_io_port(dev, info, exit).await?;
@@ -654,6 +663,9 @@ pub(super) async fn handle_exit(
                     &mut self.state.exits.halt
                 }
                 ExitReason::Exception(info) => {
+                    if info.ExceptionType.0 == x86defs::Exception::DEBUG.0 {
+                        self.handle_debug_exception(info)?;
+                    }
                     self.handle_exception(dev, info, exit)
                         .map_err(VpHaltReason::Hypervisor)?;
                     &mut self.state.exits.exception
@@ -1265,6 +1277,7 @@ fn msr_write(
             rdx: u64,
         ) -> Result<bool, WhpRunVpError> {
             let v = rax & 0xffffffff | rdx << 32;
+            dev.trace_msr(self.vp.index, msr, true, v);
             let r = self
                 .apic_msr_write(dev, msr, v)
                 .or_else_if_unknown(|| match msr {
@@ -1272,6 +1285,10 @@ fn msr_write(
                         if !self.send_unknown_msrs_to_vtl2() =>
                     {
                         tracing::warn!(msr, v, "Guest signaled crash register");
+                        if msr != hvdef::HV_X64_MSR_GUEST_CRASH_CTL {
+                            self.state.crash_params
+                                [(msr - hvdef::HV_X64_MSR_GUEST_CRASH_P0) as usize] = v;
+                        }
                         match msr {
                             hvdef::HV_X64_MSR_GUEST_CRASH_P3 => {
                                 self.state.crash_msg_address = Some(v)
@@ -1281,6 +1298,7 @@ fn msr_write(
                                     Some(std::cmp::min(v as usize, hvdef::HV_PAGE_SIZE_USIZE))
                             }
                             hvdef::HV_X64_MSR_GUEST_CRASH_CTL => {
+                                let mut message = None;
                                 if let (Some(addr), Some(len)) = (
                                     self.state.crash_msg_address.take(),
                                     self.state.crash_msg_len.take(),
@@ -1294,6 +1312,7 @@ fn msr_write(
                                                 txt = txt.as_ref(),
                                                 "guest reported crash"
                                             );
+                                            message = Some(bytes);
                                         }
                                         Err(err) => {
                                             tracelimit::error_ratelimited!(
@@ -1310,6 +1329,14 @@ fn msr_write(
                                         "guest reported crash but did not provide message"
                                     );
                                 }
+                                if hvdef::GuestCrashCtl::from(v).crash_notify() {
+                                    dev.report_guest_crash(
+                                        self.vp.index,
+                                        self.state.active_vtl,
+                                        std::mem::take(&mut self.state.crash_params),
+                                        message.as_deref(),
+                                    );
+                                }
                             }
                             _ => {}
                         }
@@ -1468,6 +1495,7 @@ fn msr_read(
             };
 
             if let Some(v) = v {
+                dev.trace_msr(self.vp.index, msr, false, v);
                 let rax = v & 0xffffffff;
                 let rdx = v >> 32;
                 let rip = exit.vp_context.Rip.wrapping_add(2);
@@ -1545,7 +1573,6 @@ fn handle_exception(
                     .with_exception_parameter(info.ExceptionParameter)
                     .with_error_code(info.ErrorCode)
                     .with_vector(info.ExceptionType.0.into())
-                    .with_vector(0xd)
                     .into()
             };
 
@@ -1556,6 +1583,110 @@ fn handle_exception(
             Ok(())
         }
 
+        /// Reports a debug exception caused by the debugger's breakpoints or
+        /// single stepping. Other debug exceptions are left to be reflected to
+        /// the guest.
+        fn handle_debug_exception(
+            &mut self,
+            info: &whp::abi::WHV_VP_EXCEPTION_CONTEXT,
+        ) -> Result<(), VpHaltReason<WhpRunVpError>> {
+            let Some(state) = self.state.vtls[self.state.active_vtl].debug_state else {
+                return Ok(());
+            };
+            let whp = self.current_whp();
+            let (dr6, rflags) =
+                get_registers!(whp, [whp::Register64::Dr6, whp::Register64::Rflags])
+                    .map_err(|err| VpHaltReason::Hypervisor(WhpRunVpError::EmulationState(err)))?;
+            // The pending debug conditions are reported in the exception
+            // parameter, and may not have reached DR6 yet.
+            let dr6 = dr6 | info.ExceptionParameter;
+            let mut rflags = x86defs::RFlags::from(rflags);
+            let reason = if dr6 & x86defs::DR6_BREAKPOINT_MASK != 0 {
+                let Some(breakpoint) = state.breakpoints[dr6.trailing_zeros() as usize] else {
+                    return Ok(());
+                };
+                if breakpoint.ty == virt::x86::BreakpointType::Execute {
+                    // Set the resume flag so that the instruction runs when
+                    // the VP resumes, as the guest's own handler would.
+                    rflags.set_resume(true);
+                }
+                VpHaltReason::HwBreak(breakpoint)
+            } else if dr6 & x86defs::DR6_SINGLE_STEP != 0 && state.single_step {
+                VpHaltReason::SingleStep
+            } else {
+                return Ok(());
+            };
+            set_registers!(
+                whp,
+                [
+                    (whp::Register64::Dr6, 0),
+                    (whp::Register64::Rflags, rflags.into())
+                ]
+            )
+            .map_err(|err| VpHaltReason::Hypervisor(WhpRunVpError::EmulationState(err)))?;
+            Err(reason)
+        }
+
+        pub(crate) fn set_debug_state_x86(
+            &mut self,
+            vtl: Vtl,
+            state: Option<&virt::x86::DebugState>,
+        ) -> Result<(), crate::Error> {
+            let whp = self.vp.whp(vtl);
+            let mut db = [0; 4];
+            let mut dr7 = 0;
+            if let Some(state) = state {
+                for (i, bp) in state.breakpoints.iter().enumerate() {
+                    if let Some(bp) = bp {
+                        db[i] = bp.address;
+                        dr7 |= bp.dr7_bits(i);
+                    }
+                }
+            }
+
+            let rflags = whp
+                .get_register(whp::Register64::Rflags)
+                .for_op("get rflags")?;
+            let mut rflags = x86defs::RFlags::from(rflags);
+            let debug_state = &mut self.state.vtls[vtl].debug_state;
+            // Leave the trap flag alone if the debugger never set it, since
+            // the guest may be single stepping itself.
+            if state.is_some_and(|state| state.single_step)
+                || debug_state.is_some_and(|state| state.single_step)
+            {
+                rflags.set_trap(state.is_some_and(|state| state.single_step));
+            }
+
+            set_registers!(
+                whp,
+                [
+                    (whp::Register64::Dr0, db[0]),
+                    (whp::Register64::Dr1, db[1]),
+                    (whp::Register64::Dr2, db[2]),
+                    (whp::Register64::Dr3, db[3]),
+                    (whp::Register64::Dr7, dr7),
+                    (whp::Register64::Rflags, rflags.into()),
+                ]
+            )
+            .for_op("set debug registers")?;
+
+            // The exception bitmap is shared by all VPs, but the debugger
+            // enables or disables debugging on all of them together.
+            let vtlp = self.vp.partition.vtlp(vtl);
+            let mut exception_exit_bitmap = vtlp.exception_exit_bitmap;
+            if state.is_some() {
+                exception_exit_bitmap |= 1 << x86defs::Exception::DEBUG.0;
+            }
+            vtlp.whp
+                .set_property(whp::PartitionProperty::ExceptionExitBitmap(
+                    exception_exit_bitmap,
+                ))
+                .for_op("set exception exit bitmap")?;
+
+            *debug_state = state.copied();
+            Ok(())
+        }
+
         /// Emulates an instruction due to a memory access exit.
         async fn emulate(
             &mut self,
@@ -20,10 +20,16 @@
 use chipset_resources::battery::BatteryDeviceHandleX64;
 use chipset_resources::battery::HostBatteryUpdate;
 use chipset_resources::i8042::I8042DeviceHandle;
+use chipset_resources::pci_hotplug::PciHotPlugDeviceHandleAArch64;
+use chipset_resources::pci_hotplug::PciHotPlugDeviceHandleX64;
+use chipset_resources::pci_hotplug::PciHotPlugRequest;
+use chipset_resources::pvpanic::PvPanicDeviceHandle;
+use chipset_resources::pvpanic::PvPanicEvent;
 use input_core::MultiplexedInputHandle;
 use missing_dev_resources::MissingDevHandle;
 use serial_16550_resources::Serial16550DeviceHandle;
 use serial_core::resources::DisconnectedSerialBackendHandle;
+use serial_debugcon_resources::DebugconTraceConfig;
 use serial_debugcon_resources::SerialDebugconDeviceHandle;
 use serial_pl011_resources::SerialPl011DeviceHandle;
 use std::iter::zip;
@@ -43,10 +49,13 @@ pub struct VmManifestBuilder {
     proxy_vga: bool,
     stub_floppy: bool,
     battery_status_recv: Option<mesh::Receiver<HostBatteryUpdate>>,
+    pci_hotplug: Option<(mesh::Receiver<PciHotPlugRequest>, mesh::Sender<u8>)>,
+    pvpanic: Option<mesh::Sender<PvPanicEvent>>,
     framebuffer: bool,
     guest_watchdog: bool,
     psp: bool,
     debugcon: Option<(Resource<SerialBackendHandle>, u16)>,
+    debugcon_trace: Option<DebugconTraceConfig>,
 }
 
 /// The VM's base chipset type, which determines the set of core devices (such
@@ -99,6 +108,8 @@ enum ErrorInner {
     UnsupportedSerialCount,
     #[error("unsupported debugcon architecture")]
     UnsupportedDebugconArch,
+    #[error("unsupported pvpanic architecture")]
+    UnsupportedPvPanicArch,
     #[error("wait for RTS not supported with this serial type")]
     WaitForRtsNotSupported,
 }
@@ -115,10 +126,13 @@ pub fn new(ty: BaseChipsetType, arch: MachineArch) -> Self {
             proxy_vga: false,
             stub_floppy: false,
             battery_status_recv: None,
+            pci_hotplug: None,
+            pvpanic: None,
             framebuffer: false,
             guest_watchdog: false,
             psp: false,
             debugcon: None,
+            debugcon_trace: None,
         }
     }
 
@@ -153,6 +167,14 @@ pub fn with_debugcon(mut self, serial: Resource<SerialBackendHandle>, port: u16)
         self
     }
 
+    /// Also emit the debugcon device's output as host tracing events.
+    ///
+    /// Has no effect unless [`Self::with_debugcon`] is also called.
+    pub fn with_debugcon_trace(mut self, trace: DebugconTraceConfig) -> Self {
+        self.debugcon_trace = Some(trace);
+        self
+    }
+
     /// Enable the proxy VGA device.
     ///
     /// This is used for Underhill VMs that are emulating Hyper-V generation 1
@@ -169,6 +191,28 @@ pub fn with_battery(mut self, battery_status_recv: mesh::Receiver<HostBatteryUpd
         self
     }
 
+    /// Enable the ACPI PCI hot-plug controller.
+    ///
+    /// Hot-plug requests are received on `request_recv`, and the PCI device
+    /// numbers of slots ejected by the guest are sent to `eject_send`.
+    pub fn with_pci_hotplug(
+        mut self,
+        request_recv: mesh::Receiver<PciHotPlugRequest>,
+        eject_send: mesh::Sender<u8>,
+    ) -> Self {
+        self.pci_hotplug = Some((request_recv, eject_send));
+        self
+    }
+
+    /// Enable the pvpanic device, which reports guest panics to
+    /// `event_send`.
+    ///
+    /// This is only supported on x86_64.
+    pub fn with_pvpanic(mut self, event_send: mesh::Sender<PvPanicEvent>) -> Self {
+        self.pvpanic = Some(event_send);
+        self
+    }
+
     /// Enable the stub floppy device instead of the full floppy device
     /// implementation.
     ///
@@ -214,12 +258,20 @@ pub fn build(self) -> Result<VmChipsetResult, Error> {
 
         if let Some((backend, port)) = self.debugcon {
             if matches!(self.arch, MachineArch::X86_64) {
-                result.attach_debugcon(port, backend);
+                result.attach_debugcon(port, backend, self.debugcon_trace);
             } else {
                 return Err(ErrorInner::UnsupportedDebugconArch.into());
             }
         }
 
+        if let Some(event_send) = self.pvpanic {
+            if matches!(self.arch, MachineArch::X86_64) {
+                result.attach_pvpanic(event_send);
+            } else {
+                return Err(ErrorInner::UnsupportedPvPanicArch.into());
+            }
+        }
+
         match self.ty {
             BaseChipsetType::HypervGen1 => {
                 if self.arch != MachineArch::X86_64 {
@@ -358,6 +410,9 @@ pub fn build(self) -> Result<VmChipsetResult, Error> {
                 }
             }
         }
+        if let Some((request_recv, eject_send)) = self.pci_hotplug {
+            result.attach_pci_hotplug(self.arch, request_recv, eject_send);
+        }
         Ok(result)
     }
 }
@@ -396,6 +451,31 @@ fn attach_battery(
         self
     }
 
+    fn attach_pci_hotplug(
+        &mut self,
+        arch: MachineArch,
+        request_recv: mesh::Receiver<PciHotPlugRequest>,
+        eject_send: mesh::Sender<u8>,
+    ) -> &mut Self {
+        self.chipset_devices.push(ChipsetDeviceHandle {
+            name: "pci_hotplug".to_owned(),
+            resource: match arch {
+                MachineArch::X86_64 => PciHotPlugDeviceHandleX64 {
+                    request_recv,
+                    eject_send,
+                }
+                .into_resource(),
+                MachineArch::Aarch64 => PciHotPlugDeviceHandleAArch64 {
+                    request_recv,
+                    eject_send,
+                }
+                .into_resource(),
+            },
+        });
+
+        self
+    }
+
     fn maybe_attach_arch_serial(
         &mut self,
         arch: MachineArch,
@@ -429,10 +509,28 @@ fn maybe_attach_arch_serial(
         Ok(self)
     }
 
-    fn attach_debugcon(&mut self, port: u16, backend: Resource<SerialBackendHandle>) -> &mut Self {
+    fn attach_debugcon(
+        &mut self,
+        port: u16,
+        backend: Resource<SerialBackendHandle>,
+        trace: Option<DebugconTraceConfig>,
+    ) -> &mut Self {
         self.chipset_devices.push(ChipsetDeviceHandle {
             name: format!("debugcon-{port:#x?}"),
-            resource: SerialDebugconDeviceHandle { port, io: backend }.into_resource(),
+            resource: SerialDebugconDeviceHandle {
+                port,
+                io: backend,
+                trace,
+            }
+            .into_resource(),
+        });
+        self
+    }
+
+    fn attach_pvpanic(&mut self, event_send: mesh::Sender<PvPanicEvent>) -> &mut Self {
+        self.chipset_devices.push(ChipsetDeviceHandle {
+            name: "pvpanic".to_owned(),
+            resource: PvPanicDeviceHandle { event_send }.into_resource(),
         });
         self
     }
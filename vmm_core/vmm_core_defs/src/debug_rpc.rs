@@ -12,6 +12,7 @@
 
 use mesh::MeshPayload;
 use mesh::rpc::FailableRpc;
+use mesh::rpc::Rpc;
 use virt::x86::SegmentRegister;
 
 #[derive(Debug, MeshPayload)]
@@ -26,6 +27,12 @@ pub enum DebugRequest {
     },
     /// Debugger is requesting a manual break.
     Break,
+    /// Switch the VTL whose registers, memory, and breakpoints the debugger
+    /// accesses. The VTL is reset to VTL0 when the debugger detaches.
+    SetVtl(FailableRpc<u8, ()>),
+    /// Query whether the VPs support hardware breakpoints, watchpoints, and
+    /// single stepping in the current VTL.
+    HardwareDebugSupported(Rpc<(), bool>),
     /// Sets the hardware debugger state for a VP.
     SetDebugState { vp: u32, state: DebugState },
     /// Fetch the specified vp's register state.
@@ -19,6 +19,7 @@ pub enum HaltReason {
     PowerOff,
     Reset,
     Hibernate,
+    Suspend,
     DebugBreak {
         #[inspect(rename = "failing_vp")]
         vp: Option<u32>,
@@ -49,3 +50,16 @@ pub enum HaltReason {
         breakpoint: virt::x86::HardwareBreakpoint,
     },
 }
+
+/// A crash reported by the guest through the Hyper-V guest crash registers.
+#[derive(Debug, Clone, Protobuf)]
+pub struct GuestCrash {
+    /// The VP that wrote the crash control register.
+    pub vp: u32,
+    /// The VTL that wrote the crash control register.
+    pub vtl: u8,
+    /// The values of the P0-P4 crash parameter registers.
+    pub parameters: Vec<u64>,
+    /// The crash message, if the guest provided one.
+    pub message: Option<String>,
+}
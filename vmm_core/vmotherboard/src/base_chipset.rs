@@ -7,6 +7,8 @@
 use crate::ChipsetDeviceHandle;
 use crate::PowerEvent;
 use crate::chipset::ChipsetBuilder;
+use crate::chipset::GuestAccessTrace;
+use crate::chipset::IoTrace;
 use crate::chipset::backing::arc_mutex::device::AddDeviceError;
 use crate::chipset::backing::arc_mutex::services::ArcMutexChipsetServices;
 use chipset::*;
@@ -15,6 +17,7 @@
 use chipset_device_resources::ConfigureChipsetDevice;
 use chipset_device_resources::GPE0_LINE_SET;
 use chipset_device_resources::IRQ_LINE_SET;
+use chipset_device_resources::RTC_ALARM_LINE_SET;
 use chipset_device_resources::ResolveChipsetDeviceHandleParams;
 use closeable_mutex::CloseableMutex;
 use cvm_tracing::CVM_ALLOWED;
@@ -78,6 +81,8 @@ pub struct BaseChipsetBuilder<'a> {
     device_handles: Vec<ChipsetDeviceHandle>,
     expected_manifest: Option<options::BaseChipsetManifest>,
     fallback_mmio_device: Option<Arc<CloseableMutex<dyn chipset_device::ChipsetDevice>>>,
+    io_trace: Option<IoTrace>,
+    access_trace: Option<GuestAccessTrace>,
     flags: BaseChipsetBuilderFlags,
 }
 
@@ -98,6 +103,8 @@ pub fn new(
             device_handles: Vec::new(),
             expected_manifest: None,
             fallback_mmio_device: None,
+            io_trace: None,
+            access_trace: None,
             flags: BaseChipsetBuilderFlags {
                 // Legacy OSes have a propensity to blindly access large numbers
                 // of unknown IO ports during boot (e.g: as part of ISA OnP
@@ -161,6 +168,19 @@ pub fn with_fallback_mmio_device(
         self
     }
 
+    /// Record the results of guest port I/O and MMIO accesses to a trace, or
+    /// compare them against one.
+    pub fn with_io_trace(mut self, io_trace: Option<IoTrace>) -> Self {
+        self.io_trace = io_trace;
+        self
+    }
+
+    /// Trace guest port I/O, MMIO, and MSR accesses.
+    pub fn with_access_trace(mut self, access_trace: Option<GuestAccessTrace>) -> Self {
+        self.access_trace = access_trace;
+        self
+    }
+
     /// Create a new base chipset. Returns a [`ChipsetBuilder`] which can be
     /// extended with additional devices, alongside a collection of
     /// [`BaseChipsetDeviceInterfaces`] that will need to be wired up by the
@@ -177,6 +197,8 @@ pub async fn build(
             device_handles,
             expected_manifest,
             fallback_mmio_device,
+            io_trace,
+            access_trace,
             flags,
         } = self;
 
@@ -198,6 +220,8 @@ pub async fn build(
             flags.trace_unknown_pio,
             flags.trace_unknown_mmio,
             fallback_mmio_device,
+            io_trace,
+            access_trace,
         );
 
         // oh boy, time to build all the devices!
@@ -261,10 +285,25 @@ pub async fn build(
             bus_id,
             pio_addr,
             pio_data,
+            ecam,
+            bridges,
         }) = deps_generic_pci_bus
         {
-            let pci = builder.arc_mutex_device("pci_bus").add(|services| {
-                pci_bus::GenericPciBus::new(&mut services.register_pio(), pio_addr, pio_data)
+            let pci = builder.arc_mutex_device("pci_bus").try_add(|services| {
+                let mut bus =
+                    pci_bus::GenericPciBus::new(&mut services.register_pio(), pio_addr, pio_data);
+                if let Some((base, end_bus)) = ecam {
+                    bus = bus.with_ecam(&mut services.register_mmio(), base, end_bus);
+                }
+                for options::dev::GenericPciBridgeDeps {
+                    name,
+                    bdf: (bus_number, device, function),
+                    secondary_bus,
+                } in bridges
+                {
+                    bus.add_pci_bridge(bus_number, device, function, name, secondary_bus)?;
+                }
+                Ok::<_, pci_bus::AddBridgeError>(bus)
             })?;
 
             builder.register_weak_mutex_pci_bus(bus_id, Box::new(pci));
@@ -480,6 +519,7 @@ pub async fn build(
                 cmos_rtc::Rtc::new(
                     time_source,
                     services.new_line(IRQ_LINE_SET, "interrupt", irq),
+                    services.new_line(RTC_ALARM_LINE_SET, "alarm", 0),
                     services.register_vmtime(),
                     century_reg_idx,
                     initial_cmos,
@@ -522,6 +562,7 @@ pub async fn build(
                 let req = match action {
                     pm::PowerAction::PowerOff => PowerEvent::PowerOff,
                     pm::PowerAction::Hibernate => PowerEvent::Hibernate,
+                    pm::PowerAction::Suspend => PowerEvent::Suspend,
                     pm::PowerAction::Reboot => PowerEvent::Reset,
                 };
                 power.on_power_event(req);
@@ -532,6 +573,9 @@ pub async fn build(
             acpi_irq,
             pio_base: pio_dynamic_reg_base,
             pm_timer_assist,
+            wake_recv,
+            gpe_recv,
+            wake_request_send,
         }) = deps_hyperv_power_management
         {
             builder.arc_mutex_device("pm").add(|services| {
@@ -544,10 +588,12 @@ pub async fn build(
                         default_pio_dynamic: pio_dynamic_reg_base,
                     }),
                     pm_timer_assist,
+                    wake_recv,
+                    gpe_recv,
+                    wake_request_send,
                 );
-                for range in pm.valid_lines() {
-                    services.add_line_target(GPE0_LINE_SET, range.clone(), *range.start());
-                }
+                services.add_line_target(GPE0_LINE_SET, pm::GPE0_LINES, 0);
+                services.add_line_target(RTC_ALARM_LINE_SET, 0..=0, pm::RTC_ALARM_LINE);
                 pm
             })?;
         }
@@ -1147,6 +1193,13 @@ pub struct HyperVPowerManagementDeps {
             pub pio_base: u16,
             /// Interface to enable/disable PM timer assist
             pub pm_timer_assist: Option<Box<dyn pm::PmTimerAssist>>,
+            /// Channel to receive host-initiated wake events
+            pub wake_recv: Option<mesh::Receiver<chipset_resources::pm::WakeEvent>>,
+            /// Channel to receive host-injected general-purpose events
+            pub gpe_recv: Option<mesh::Receiver<u32>>,
+            /// Channel to request that the VMM wake the guest from a sleep
+            /// state, when the RTC alarm fires
+            pub wake_request_send: Option<mesh::Sender<chipset_resources::pm::WakeEvent>>,
         }
 
         /// AMD Platform Security Processor (PSP)
@@ -1208,6 +1261,21 @@ pub struct GenericPciBusDeps {
             pub pio_addr: u16,
             /// Port io address of the 32-bit PCI DATA register
             pub pio_data: u16,
+            /// Base address and last bus number of the ECAM window, if any
+            pub ecam: Option<(u64, u8)>,
+            /// PCI-to-PCI bridges, each on bus 0 or on the secondary bus of
+            /// an earlier bridge
+            pub bridges: Vec<GenericPciBridgeDeps>,
+        }
+
+        /// PCI-to-PCI bridge on the generic PCI bus
+        pub struct GenericPciBridgeDeps {
+            /// Name of the bridge
+            pub name: String,
+            /// Bus, device, and function of the bridge
+            pub bdf: (u8, u8, u8),
+            /// Bus number of the bus behind the bridge
+            pub secondary_bus: u8,
         }
 
         /// PIIX4 PCI Bus
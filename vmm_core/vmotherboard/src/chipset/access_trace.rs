@@ -0,0 +1,276 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tracing of guest port I/O, MMIO, and MSR accesses.
+//!
+//! The filter is a comma-separated list of terms, and an access is traced if
+//! it matches any of them:
+//!
+//! ```text
+//! all                      every access
+//! pio, mmio, msr           every access of that kind
+//! pio=<addr>[-<end>]       accesses to an inclusive address range
+//! mmio=<addr>[-<end>]
+//! msr=<msr>[-<end>]
+//! dev=<name>               port I/O and MMIO accesses to a chipset device
+//! ```
+//!
+//! Each traced access is logged, or written to a file one line per access:
+//!
+//! ```text
+//! <vp> <rip> <pio|mmio|msr> <r|w> <address> <value> [device]
+//! ```
+//!
+//! The RIP is that of the instruction that caused the exit, or `-` if the
+//! hypervisor backend does not report it.
+
+use super::IoKind;
+use anyhow::Context as _;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::LineWriter;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// The kind of a traced access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum AccessKind {
+    Pio,
+    Mmio,
+    Msr,
+}
+
+impl AccessKind {
+    fn name(&self) -> &'static str {
+        match self {
+            AccessKind::Pio => "pio",
+            AccessKind::Mmio => "mmio",
+            AccessKind::Msr => "msr",
+        }
+    }
+}
+
+impl From<&IoKind> for AccessKind {
+    fn from(kind: &IoKind) -> Self {
+        match kind {
+            IoKind::Pio => AccessKind::Pio,
+            IoKind::Mmio => AccessKind::Mmio,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FilterTerm {
+    All,
+    Kind(AccessKind, Option<RangeInclusive<u64>>),
+    Device(String),
+}
+
+impl FilterTerm {
+    fn matches(&self, kind: AccessKind, address: u64, device: Option<&str>) -> bool {
+        match self {
+            FilterTerm::All => true,
+            FilterTerm::Kind(k, range) => {
+                *k == kind && range.as_ref().is_none_or(|r| r.contains(&address))
+            }
+            FilterTerm::Device(name) => device == Some(name.as_str()),
+        }
+    }
+}
+
+fn parse_filter(filter: &str) -> anyhow::Result<Vec<FilterTerm>> {
+    filter
+        .split(',')
+        .map(|term| {
+            let term = term.trim();
+            let (name, value) = match term.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (term, None),
+            };
+            let kind = match name {
+                "all" if value.is_none() => return Ok(FilterTerm::All),
+                "dev" => {
+                    let name = value.filter(|v| !v.is_empty()).context("missing device")?;
+                    return Ok(FilterTerm::Device(name.to_owned()));
+                }
+                "pio" => AccessKind::Pio,
+                "mmio" => AccessKind::Mmio,
+                "msr" => AccessKind::Msr,
+                _ => anyhow::bail!("invalid filter term: {term}"),
+            };
+            let range = value
+                .map(|value| {
+                    let (start, end) = value.split_once('-').unwrap_or((value, value));
+                    let start = parse_u64(start)?;
+                    let end = parse_u64(end)?;
+                    if end < start {
+                        anyhow::bail!("invalid range: {value}");
+                    }
+                    Ok(start..=end)
+                })
+                .transpose()
+                .with_context(|| format!("invalid filter term: {term}"))?;
+            Ok(FilterTerm::Kind(kind, range))
+        })
+        .collect()
+}
+
+fn parse_u64(s: &str) -> anyhow::Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("invalid number: {s}"))
+}
+
+/// Traces guest port I/O, MMIO, and MSR accesses that match a filter.
+#[derive(Inspect)]
+pub struct GuestAccessTrace {
+    #[inspect(skip)]
+    filter: Vec<FilterTerm>,
+    #[inspect(skip)]
+    file: Option<Mutex<LineWriter<File>>>,
+    #[inspect(skip)]
+    rips: Mutex<HashMap<u32, u64>>,
+    traced: AtomicU64,
+}
+
+impl GuestAccessTrace {
+    /// Traces accesses matching `filter`, writing them to `file` or, if it is
+    /// `None`, logging them.
+    pub fn new(filter: &str, file: Option<File>) -> anyhow::Result<Self> {
+        Ok(Self {
+            filter: parse_filter(filter)?,
+            file: file.map(|file| Mutex::new(LineWriter::new(file))),
+            rips: Default::default(),
+            traced: AtomicU64::new(0),
+        })
+    }
+
+    /// Notes the RIP of the exit that `vp` is handling, which is reported
+    /// with any accesses it makes.
+    pub fn set_rip(&self, vp: u32, rip: u64) {
+        self.rips.lock().insert(vp, rip);
+    }
+
+    /// Traces a completed port I/O or MMIO access.
+    pub(super) fn io_access(
+        &self,
+        vp: u32,
+        kind: &IoKind,
+        device: &str,
+        address: u64,
+        write: bool,
+        data: &[u8],
+    ) {
+        let value = if data.len() <= 8 {
+            let mut value = [0; 8];
+            value[..data.len()].copy_from_slice(data);
+            format!("{:#x}", u64::from_le_bytes(value))
+        } else {
+            let mut value = String::new();
+            for b in data {
+                write!(value, "{b:02x}").unwrap();
+            }
+            value
+        };
+        self.access(vp, kind.into(), Some(device), address, write, &value);
+    }
+
+    /// Traces an MSR access.
+    pub fn msr_access(&self, vp: u32, msr: u32, write: bool, value: u64) {
+        self.access(
+            vp,
+            AccessKind::Msr,
+            None,
+            msr.into(),
+            write,
+            &format!("{value:#x}"),
+        );
+    }
+
+    fn access(
+        &self,
+        vp: u32,
+        kind: AccessKind,
+        device: Option<&str>,
+        address: u64,
+        write: bool,
+        value: &str,
+    ) {
+        if !self
+            .filter
+            .iter()
+            .any(|term| term.matches(kind, address, device))
+        {
+            return;
+        }
+        self.traced.fetch_add(1, Ordering::Relaxed);
+        let rip = self.rips.lock().get(&vp).copied();
+        let direction = if write { "w" } else { "r" };
+        if let Some(file) = &self.file {
+            let rip = rip.map_or_else(|| "-".into(), |rip| format!("{rip:#x}"));
+            let mut line = format!(
+                "{vp} {rip} {} {direction} {address:#x} {value}",
+                kind.name()
+            );
+            if let Some(device) = device {
+                line += " ";
+                line += device;
+            }
+            let r = writeln!(file.lock(), "{line}");
+            if let Err(err) = r {
+                tracelimit::error_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write guest access trace"
+                );
+            }
+        } else {
+            tracing::info!(
+                vp,
+                rip,
+                kind = kind.name(),
+                direction,
+                address,
+                value,
+                device,
+                "guest access"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccessKind;
+    use super::FilterTerm;
+    use super::parse_filter;
+
+    #[test]
+    fn test_parse_filter() {
+        let filter = parse_filter("pio=0x60-0x64, mmio, msr=0x1b, dev=rtc").unwrap();
+        assert_eq!(
+            filter,
+            [
+                FilterTerm::Kind(AccessKind::Pio, Some(0x60..=0x64)),
+                FilterTerm::Kind(AccessKind::Mmio, None),
+                FilterTerm::Kind(AccessKind::Msr, Some(0x1b..=0x1b)),
+                FilterTerm::Device("rtc".into()),
+            ]
+        );
+        assert!(filter[0].matches(AccessKind::Pio, 0x64, None));
+        assert!(!filter[0].matches(AccessKind::Pio, 0x65, None));
+        assert!(!filter[0].matches(AccessKind::Mmio, 0x60, None));
+        assert!(filter[3].matches(AccessKind::Pio, 0x70, Some("rtc")));
+
+        assert_eq!(parse_filter("all").unwrap(), [FilterTerm::All]);
+        assert!(parse_filter("pio=0x64-0x60").is_err());
+        assert!(parse_filter("dev=").is_err());
+        assert!(parse_filter("cpuid").is_err());
+    }
+}
@@ -18,6 +18,8 @@
 use crate::DebugEventHandler;
 use crate::VmmChipsetDevice;
 use crate::chipset::Chipset;
+use crate::chipset::GuestAccessTrace;
+use crate::chipset::IoTrace;
 use crate::chipset::io_ranges::IoRanges;
 use chipset_device::ChipsetDevice;
 use chipset_device_resources::LineSetId;
@@ -91,6 +93,8 @@ pub(crate) fn new(
         trace_unknown_pio: bool,
         trace_unknown_mmio: bool,
         fallback_mmio_device: Option<Arc<CloseableMutex<dyn ChipsetDevice>>>,
+        io_trace: Option<IoTrace>,
+        access_trace: Option<GuestAccessTrace>,
     ) -> Self {
         let (send, chipset_recv) = mesh::channel();
         let chipset_unit = units.add("chipset").build(send).unwrap();
@@ -103,6 +107,8 @@ pub(crate) fn new(
                 pic: None,
                 eoi_handler: None,
                 debug_event_handler,
+                io_trace,
+                access_trace,
             },
 
             bus_resolver: BusResolver::default(),
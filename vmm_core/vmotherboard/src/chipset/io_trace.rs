@@ -0,0 +1,282 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Traces of the results of guest port I/O and MMIO accesses, for finding where
+//! two runs of a guest diverge.
+//!
+//! Devices that report host state, such as timers, the RTC, and status
+//! registers that change as backing I/O completes, make the guest behave
+//! differently from one run to the next. In record mode, every access that
+//! reaches the chipset is written to a trace, one line per access:
+//!
+//! ```text
+//! <vp> <seq> <pio|mmio> <r|w> <address> <data>
+//! ```
+//!
+//! where `seq` counts the VP's accesses and `address` and `data` are hex.
+//!
+//! In compare mode, each VP's accesses are matched in order against the trace.
+//! Devices still see every access, so their internal state evolves as before,
+//! but reads return the recorded data. The first access that does not match
+//! (a different address, access size, or written value) is reported, and the
+//! comparison stops, since the guest's execution has diverged.
+//!
+//! This is not deterministic replay. Interrupt timing, the TSC, and accesses
+//! handled without an exit to the VMM are not recorded, as the hypervisor
+//! backends cannot count guest instructions to deliver them at the same point,
+//! so a run can diverge from the trace even when nothing is wrong.
+
+use super::IoKind;
+use super::IoType;
+use anyhow::Context as _;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::LineWriter;
+use std::io::Write;
+
+/// Records the results of guest port I/O and MMIO accesses, or compares them
+/// against an earlier recording.
+#[derive(Inspect)]
+pub struct IoTrace {
+    #[inspect(flatten)]
+    mode: Mode,
+}
+
+#[derive(Inspect)]
+#[inspect(tag = "mode")]
+enum Mode {
+    #[inspect(transparent)]
+    Record(Mutex<Recorder>),
+    #[inspect(transparent)]
+    Compare(Mutex<Comparer>),
+}
+
+#[derive(Inspect)]
+struct Recorder {
+    #[inspect(skip)]
+    file: LineWriter<File>,
+    #[inspect(iter_by_key)]
+    seq: HashMap<u32, u64>,
+    failed: bool,
+}
+
+#[derive(Inspect)]
+struct Comparer {
+    #[inspect(skip)]
+    records: HashMap<u32, VecDeque<Record>>,
+    matched: u64,
+    #[inspect(debug)]
+    divergence: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Record {
+    vp: u32,
+    seq: u64,
+    mmio: bool,
+    write: bool,
+    address: u64,
+    data: Vec<u8>,
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {:#x} ",
+            self.vp,
+            self.seq,
+            if self.mmio { "mmio" } else { "pio" },
+            if self.write { "w" } else { "r" },
+            self.address
+        )?;
+        for b in &self.data {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Record {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut fields = s.split_ascii_whitespace();
+        let mut next = || fields.next().context("missing field");
+        let vp = next()?.parse().context("invalid vp")?;
+        let seq = next()?.parse().context("invalid sequence number")?;
+        let mmio = match next()? {
+            "pio" => false,
+            "mmio" => true,
+            _ => anyhow::bail!("invalid access kind"),
+        };
+        let write = match next()? {
+            "r" => false,
+            "w" => true,
+            _ => anyhow::bail!("invalid access direction"),
+        };
+        let address = next()?;
+        let address = u64::from_str_radix(address.strip_prefix("0x").unwrap_or(address), 16)
+            .context("invalid address")?;
+        let data = next()?;
+        if data.len() % 2 != 0 {
+            anyhow::bail!("invalid data");
+        }
+        let data = (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&data[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .context("invalid data")?;
+        Ok(Self {
+            vp,
+            seq,
+            mmio,
+            write,
+            address,
+            data,
+        })
+    }
+}
+
+impl IoTrace {
+    /// Records accesses to `file`.
+    pub fn record(file: File) -> Self {
+        Self {
+            mode: Mode::Record(Mutex::new(Recorder {
+                file: LineWriter::new(file),
+                seq: HashMap::new(),
+                failed: false,
+            })),
+        }
+    }
+
+    /// Compares accesses against those recorded in `file`, returning the
+    /// recorded data for reads.
+    pub fn compare(file: File) -> anyhow::Result<Self> {
+        let mut records = HashMap::<_, VecDeque<_>>::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context("failed to read i/o trace")?;
+            let record: Record = line
+                .parse()
+                .with_context(|| format!("invalid i/o trace record on line {}", i + 1))?;
+            records.entry(record.vp).or_default().push_back(record);
+        }
+        Ok(Self {
+            mode: Mode::Compare(Mutex::new(Comparer {
+                records,
+                matched: 0,
+                divergence: None,
+            })),
+        })
+    }
+
+    /// Records or compares a completed access.
+    pub(super) fn access(&self, vp: u32, kind: &IoKind, address: u64, io_type: &mut IoType<'_>) {
+        let mmio = matches!(kind, IoKind::Mmio);
+        match &self.mode {
+            Mode::Record(recorder) => {
+                let mut recorder = recorder.lock();
+                if recorder.failed {
+                    return;
+                }
+                let seq = recorder.seq.entry(vp).or_default();
+                let record = Record {
+                    vp,
+                    seq: *seq,
+                    mmio,
+                    write: matches!(io_type, IoType::Write(_)),
+                    address,
+                    data: io_type.bytes().to_vec(),
+                };
+                *seq += 1;
+                if let Err(err) = writeln!(recorder.file, "{record}") {
+                    tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to write i/o trace, stopping recording"
+                    );
+                    recorder.failed = true;
+                }
+            }
+            Mode::Compare(comparer) => {
+                let mut comparer = comparer.lock();
+                if comparer.divergence.is_some() {
+                    return;
+                }
+                let Comparer {
+                    records,
+                    matched,
+                    divergence,
+                } = &mut *comparer;
+                let record = records.get_mut(&vp).and_then(|r| r.pop_front());
+                let matches = record.as_ref().is_some_and(|record| {
+                    record.mmio == mmio
+                        && record.address == address
+                        && record.data.len() == io_type.bytes().len()
+                        && match io_type {
+                            IoType::Read(_) => !record.write,
+                            IoType::Write(data) => record.write && record.data == *data,
+                        }
+                });
+                if matches {
+                    if let IoType::Read(data) = io_type {
+                        data.copy_from_slice(&record.unwrap().data);
+                    }
+                    *matched += 1;
+                } else {
+                    let mut actual = String::new();
+                    for b in io_type.bytes() {
+                        write!(actual, "{b:02x}").unwrap();
+                    }
+                    let expected = record.map_or_else(|| "end of trace".into(), |r| r.to_string());
+                    let message = format!(
+                        "vp {vp} {} {} {address:#x} {actual}, expected {expected}",
+                        if mmio { "mmio" } else { "pio" },
+                        if matches!(io_type, IoType::Write(_)) {
+                            "w"
+                        } else {
+                            "r"
+                        },
+                    );
+                    tracing::error!(
+                        matched = *matched,
+                        divergence = message.as_str(),
+                        "guest diverged from the i/o trace, stopping the comparison"
+                    );
+                    *divergence = Some(message);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Record;
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = Record {
+            vp: 1,
+            seq: 42,
+            mmio: false,
+            write: false,
+            address: 0x61,
+            data: vec![0x20],
+        };
+        assert_eq!(record.to_string(), "1 42 pio r 0x61 20");
+        assert_eq!(record.to_string().parse::<Record>().unwrap(), record);
+
+        let record: Record = "0 7 mmio w 0xfee000b0 00000000".parse().unwrap();
+        assert!(record.mmio && record.write);
+        assert_eq!(record.data, [0; 4]);
+
+        assert!("0 7 mmio x 0xfee000b0 00".parse::<Record>().is_err());
+        assert!("0 7 mmio w 0xfee000b0 0".parse::<Record>().is_err());
+    }
+}
@@ -3,13 +3,17 @@
 
 //! Notable Exports: [`Chipset`], [`ChipsetBuilder`]
 
+mod access_trace;
 pub mod backing;
 mod builder;
 mod io_ranges;
+mod io_trace;
 mod line_sets;
 
+pub use self::access_trace::GuestAccessTrace;
 pub use self::builder::ChipsetBuilder;
 pub use self::builder::ChipsetDevices;
+pub use self::io_trace::IoTrace;
 
 use self::io_ranges::IoRanges;
 use self::io_ranges::LookupResult;
@@ -39,6 +43,9 @@ pub struct Chipset {
 
     #[inspect(skip)]
     debug_event_handler: Arc<dyn DebugEventHandler>,
+
+    io_trace: Option<IoTrace>,
+    access_trace: Option<GuestAccessTrace>,
 }
 
 enum IoType<'a> {
@@ -129,6 +136,21 @@ async fn handle_io_result(
             }
         };
 
+        if let Some(io_trace) = &self.io_trace {
+            io_trace.access(vp, &kind, address, &mut io_type);
+        }
+
+        if let Some(access_trace) = &self.access_trace {
+            access_trace.io_access(
+                vp,
+                &kind,
+                &lookup.dev_name,
+                address,
+                matches!(io_type, IoType::Write(_)),
+                io_type.bytes(),
+            );
+        }
+
         match r {
             Ok(()) => {
                 if let Some(range_name) = &lookup.trace {
@@ -211,6 +233,26 @@ pub async fn mmio_write(&self, vp: u32, address: u64, data: &[u8]) {
         .await
     }
 
+    /// Returns whether access tracing is enabled.
+    pub fn is_tracing_access(&self) -> bool {
+        self.access_trace.is_some()
+    }
+
+    /// Notes the RIP of the exit that `vp` is handling, which access tracing
+    /// reports with the accesses it makes.
+    pub fn trace_exit_rip(&self, vp: u32, rip: u64) {
+        if let Some(access_trace) = &self.access_trace {
+            access_trace.set_rip(vp, rip);
+        }
+    }
+
+    /// Traces an MSR access handled by the VMM, if access tracing is enabled.
+    pub fn trace_msr(&self, vp: u32, msr: u32, write: bool, value: u64) {
+        if let Some(access_trace) = &self.access_trace {
+            access_trace.msr_access(vp, msr, write, value);
+        }
+    }
+
     /// Check if a MMIO device exists at the given address
     pub fn is_mmio(&self, addr: u64) -> bool {
         self.mmio_ranges.is_occupied(addr)
@@ -19,6 +19,8 @@
 pub use self::base_chipset::options;
 pub use self::chipset::Chipset;
 pub use self::chipset::ChipsetDevices;
+pub use self::chipset::GuestAccessTrace;
+pub use self::chipset::IoTrace;
 
 // API wart: future changes should avoid exposing the `ChipsetBuilder`, and move
 // _all_ device instantiation into `vmotherboard` itself.
@@ -59,6 +61,8 @@ pub enum PowerEvent {
     Reset,
     /// Initiate Hibernate
     Hibernate,
+    /// Initiate Suspend (S3)
+    Suspend,
 }
 
 /// Handler for device-triggered power events.
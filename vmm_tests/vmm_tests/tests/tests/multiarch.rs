@@ -9,9 +9,13 @@
 use hyperv_ic_resources::kvp::KvpRpc;
 use jiff::SignedDuration;
 use mesh::rpc::RpcSend;
+use net_backend_resources::mac_address::MacAddress;
+use net_hub::Hub;
+use pal_async::DefaultDriver;
 use petri::MemoryConfig;
 use petri::PetriGuestStateLifetime;
 use petri::PetriVmBuilder;
+use petri::PetriVmGroup;
 use petri::PetriVmmBackend;
 use petri::ProcessorTopology;
 use petri::ResolvedArtifact;
@@ -19,6 +23,7 @@
 use petri::ShutdownKind;
 use petri::openvmm::NIC_MAC_ADDRESS;
 use petri::openvmm::OpenVmmPetriBackend;
+use petri::pipette::cmd;
 use petri_artifacts_common::tags::MachineArch;
 use petri_artifacts_common::tags::OsFlavor;
 use petri_artifacts_vmm_test::artifacts::test_vmgs::VMGS_WITH_BOOT_ENTRY;
@@ -48,6 +53,7 @@ async fn frontpage<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyhow::Res
 /// Basic boot test.
 #[vmm_test(
     openvmm_linux_direct_x64,
+    openvmm_linux_direct_aarch64,
     openvmm_openhcl_linux_direct_x64,
     openvmm_pcat_x64(vhd(windows_datacenter_core_2022_x64)),
     openvmm_pcat_x64(vhd(ubuntu_2204_server_x64)),
@@ -278,7 +284,8 @@ async fn kvp_ic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<(
     uefi_x64(vhd(ubuntu_2204_server_x64)),
     // uefi_aarch64(vhd(windows_11_enterprise_aarch64)),
     uefi_aarch64(vhd(ubuntu_2404_server_aarch64)),
-    linux_direct_x64
+    linux_direct_x64,
+    linux_direct_aarch64
 )]
 async fn timesync_ic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
     let (vm, agent) = config
@@ -320,6 +327,7 @@ async fn timesync_ic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Res
 // TODO: Reenable guests that use the framebuffer once #74 is fixed.
 #[openvmm_test(
     openvmm_linux_direct_x64,
+    openvmm_linux_direct_aarch64,
     openvmm_openhcl_linux_direct_x64,
     // openvmm_pcat_x64(vhd(windows_datacenter_core_2022_x64)),
     // openvmm_pcat_x64(vhd(ubuntu_2204_server_x64)),
@@ -354,6 +362,7 @@ async fn reboot(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<(), anyho
 // in our ubuntu image
 #[vmm_test(
     openvmm_linux_direct_x64,
+    openvmm_linux_direct_aarch64,
     openvmm_openhcl_linux_direct_x64,
     openvmm_pcat_x64(vhd(freebsd_13_2_x64)),
     openvmm_pcat_x64(iso(freebsd_13_2_x64)),
@@ -386,6 +395,7 @@ async fn boot_no_agent<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyhow:
 // Basic vp "heavy" boot test without agent with 16 VPs.
 #[vmm_test(
     openvmm_linux_direct_x64,
+    openvmm_linux_direct_aarch64,
     openvmm_openhcl_linux_direct_x64,
     openvmm_pcat_x64(vhd(freebsd_13_2_x64)),
     openvmm_pcat_x64(iso(freebsd_13_2_x64)),
@@ -542,6 +552,7 @@ async fn boot_no_agent_single_proc<T: PetriVmmBackend>(
 // TODO: Reenable guests that use the framebuffer once #74 is fixed.
 #[openvmm_test(
     openvmm_linux_direct_x64,
+    openvmm_linux_direct_aarch64,
     openvmm_openhcl_linux_direct_x64,
     // openvmm_pcat_x64(vhd(windows_datacenter_core_2022_x64)),
     // openvmm_pcat_x64(vhd(ubuntu_2204_server_x64)),
@@ -593,6 +604,7 @@ async fn guest_test_uefi<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyho
 /// Test transferring a file to the guest.
 #[vmm_test(
     openvmm_linux_direct_x64,
+    openvmm_linux_direct_aarch64,
     openvmm_openhcl_linux_direct_x64,
     // openvmm_uefi_aarch64(vhd(windows_11_enterprise_aarch64)),
     openvmm_uefi_aarch64(vhd(ubuntu_2404_server_aarch64)),
@@ -622,8 +634,50 @@ async fn file_transfer_test<T: PetriVmmBackend>(
     Ok(())
 }
 
+/// Test networking between two VMs whose NICs share a hub.
+#[openvmm_test(linux_direct_x64, linux_direct_aarch64)]
+async fn guest_to_guest_network(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+    _: (),
+    driver: DefaultDriver,
+) -> anyhow::Result<()> {
+    let vms = [("a", "10.0.0.1"), ("b", "10.0.0.2")];
+
+    let hub = Hub::new(driver);
+    let mut group = PetriVmGroup::new(config);
+    let mut agents = Vec::new();
+    for (i, (name, ip)) in vms.into_iter().enumerate() {
+        let mac_address = MacAddress::new([0x00, 0x15, 0x5D, 0x12, 0x13, i as u8]);
+        let port = hub.new_port();
+        let agent = group
+            .run(name, move |b| {
+                b.modify_backend(move |c| c.with_hub_nic(port, mac_address))
+            })
+            .await?;
+        let sh = agent.unix_shell();
+        cmd!(sh, "ip addr add {ip}/24 dev eth0").run().await?;
+        cmd!(sh, "ip link set eth0 up").run().await?;
+        agents.push(agent);
+    }
+
+    let sh = agents[0].unix_shell();
+    let peer = vms[1].1;
+    cmd!(sh, "ping -c 3 -W 5 {peer}").run().await?;
+
+    for ((name, _), agent) in vms.into_iter().zip(agents) {
+        agent.power_off().await?;
+        assert_eq!(group.vm(name).wait_for_halt().await?, HaltReason::PowerOff);
+    }
+    group.teardown().await?;
+    Ok(())
+}
+
 /// Boot Linux and have it write the visible memory size.
-#[openvmm_test(linux_direct_x64, uefi_aarch64(vhd(ubuntu_2404_server_aarch64)))]
+#[openvmm_test(
+    linux_direct_x64,
+    linux_direct_aarch64,
+    uefi_aarch64(vhd(ubuntu_2404_server_aarch64))
+)]
 async fn five_gb(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<(), anyhow::Error> {
     let configured_size = 5 * SIZE_1_GB;
     let expected_size = configured_size - configured_size / 10; // 10% buffer; TODO-figure out where this goes
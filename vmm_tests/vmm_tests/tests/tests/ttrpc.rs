@@ -103,6 +103,7 @@ fn test_ttrpc_interface(
                             ..Default::default()
                         }),
                         log_id: String::new(),
+                        resume_path: String::new(),
                     },
                 )
                 .await
@@ -5,6 +5,7 @@
 
 mod openhcl_linux_direct;
 mod openhcl_uefi;
+mod windows_uefi;
 
 use anyhow::Context;
 use petri::ApicMode;
@@ -323,6 +324,71 @@ async fn battery_capacity(
     Ok(())
 }
 
+/// Change the battery and AC power state at runtime and check that the guest
+/// sees the change.
+#[openvmm_test(
+    openhcl_uefi_x64(vhd(ubuntu_2204_server_x64)),
+    uefi_x64(vhd(ubuntu_2204_server_x64))
+)]
+async fn battery_runtime_update(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+) -> Result<(), anyhow::Error> {
+    let (mut vm, agent) = config.modify_backend(|b| b.with_battery()).run().await?;
+    vm.wait_for_successful_boot_event().await?;
+
+    vm.set_battery_charge(40).await?;
+    vm.set_ac_online(false).await?;
+
+    // The guest rereads the battery state when it handles the ACPI
+    // notification, so poll for the update.
+    let sh = agent.unix_shell();
+    let mut updated = false;
+    for _ in 0..30 {
+        let uevent = cmd!(sh, "cat /sys/class/power_supply/BAT1/uevent")
+            .read()
+            .await?;
+        tracing::info!(uevent, "guest battery state");
+        if uevent.lines().any(|l| l == "POWER_SUPPLY_CAPACITY=40")
+            && uevent
+                .lines()
+                .any(|l| l == "POWER_SUPPLY_STATUS=Discharging")
+        {
+            updated = true;
+            break;
+        }
+        mesh::CancelContext::new()
+            .with_timeout(std::time::Duration::from_secs(1))
+            .cancelled()
+            .await;
+    }
+    anyhow::ensure!(updated, "guest never saw the battery update");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Power the guest off, then schedule a wake and check that the VM powers
+/// back on.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn scheduled_wake_power_on(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+) -> Result<(), anyhow::Error> {
+    let (mut vm, agent) = config.run().await?;
+    vm.wait_for_successful_boot_event().await?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_halt().await?, HaltReason::PowerOff);
+    vm.backend()
+        .schedule_wake(Some(std::time::Duration::from_secs(1)))
+        .await?;
+
+    let agent = vm.wait_for_agent().await?;
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
 fn configure_for_sidecar<T: PetriVmmBackend>(
     config: PetriVmBuilder<T>,
     proc_count: u32,
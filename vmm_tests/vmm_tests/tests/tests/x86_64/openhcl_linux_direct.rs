@@ -150,6 +150,7 @@ fn new_test_vtl2_nvme_device(
                 disk: layer.into_resource(),
                 read_only: false,
             }],
+            requests: None,
         }
         .into_resource(),
     }
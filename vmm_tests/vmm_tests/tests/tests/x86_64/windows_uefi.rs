@@ -0,0 +1,97 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Baseline integration tests for Windows x86_64 UEFI guests on OpenVMM.
+//!
+//! These boot unattended using the IMC hive that petri injects to install
+//! pipette, and check what the guest sees of the VM's configuration.
+
+use anyhow::Context;
+use petri::MemoryConfig;
+use petri::PetriVmBuilder;
+use petri::ProcessorTopology;
+use petri::SIZE_1_GB;
+use petri::ShutdownKind;
+use petri::openvmm::OpenVmmPetriBackend;
+use petri::pipette::PipetteClient;
+use petri::pipette::cmd;
+use vmm_core_defs::HaltReason;
+use vmm_test_macros::openvmm_test;
+
+/// Runs a PowerShell command in the guest and returns its trimmed output.
+async fn powershell(agent: &PipetteClient, command: &str) -> anyhow::Result<String> {
+    let sh = agent.windows_shell();
+    let output = cmd!(sh, "powershell.exe -NoProfile -Command {command}")
+        .read()
+        .await?;
+    Ok(output.trim().to_owned())
+}
+
+/// Check that the guest sees the configured processors and memory.
+#[openvmm_test(uefi_x64(vhd(windows_datacenter_core_2022_x64)))]
+async fn windows_topology(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (vm, agent) = config
+        .with_processor_topology(ProcessorTopology {
+            vp_count: 4,
+            ..Default::default()
+        })
+        .with_memory(MemoryConfig {
+            startup_bytes: 4 * SIZE_1_GB,
+            ..Default::default()
+        })
+        .run()
+        .await?;
+
+    let processors: u32 = powershell(
+        &agent,
+        "(Get-CimInstance Win32_ComputerSystem).NumberOfLogicalProcessors",
+    )
+    .await?
+    .parse()
+    .context("failed to parse processor count")?;
+    assert_eq!(processors, 4);
+
+    // Some memory is reserved by the firmware and the kernel, so the guest
+    // reports a bit less than the VM has.
+    let memory: u64 = powershell(
+        &agent,
+        "(Get-CimInstance Win32_ComputerSystem).TotalPhysicalMemory",
+    )
+    .await?
+    .parse()
+    .context("failed to parse memory size")?;
+    assert!(
+        (3 * SIZE_1_GB..=4 * SIZE_1_GB).contains(&memory),
+        "unexpected memory size {memory:#x}"
+    );
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Check that the guest shuts down when asked by the shutdown IC, after
+/// pipette has started.
+#[openvmm_test(uefi_x64(vhd(windows_datacenter_core_2022_x64)))]
+async fn windows_shutdown_ic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (mut vm, agent) = config.run().await?;
+    agent.ping().await?;
+    vm.send_enlightened_shutdown(ShutdownKind::Shutdown).await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Check that the guest's secure boot state matches the VM's configuration.
+#[openvmm_test(uefi_x64(vhd(windows_datacenter_core_2022_x64)))]
+async fn windows_secure_boot_state(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+) -> anyhow::Result<()> {
+    let (vm, agent) = config.with_secure_boot().run().await?;
+
+    let enabled = powershell(&agent, "Confirm-SecureBootUEFI").await?;
+    assert_eq!(enabled, "True");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
@@ -26,20 +26,30 @@ pub struct VmProxy {
 
     pub vps: Box<[Vp]>,
     pub breakpoints: [Option<HardwareBreakpoint>; 4],
+    /// The VTL being debugged.
+    pub vtl: u8,
+    /// Whether the VTL being debugged supports hardware breakpoints,
+    /// watchpoints, and single stepping.
+    pub hw_debug: bool,
+    /// The VTL to debug when a client connects.
+    default_vtl: u8,
 }
 
 impl VmProxy {
-    pub fn new(req_chan: mesh::Sender<DebugRequest>, vp_count: u32) -> Self {
+    pub fn new(req_chan: mesh::Sender<DebugRequest>, vp_count: u32, vtl: u8) -> Self {
         Self {
             req_chan,
             vps: vec![Vp::default(); vp_count as usize].into(),
             stop_chan: None,
             breakpoints: [None; 4],
+            vtl,
+            default_vtl: vtl,
+            hw_debug: false,
         }
     }
 
-    pub fn into_params(self) -> (mesh::Sender<DebugRequest>, u32) {
-        (self.req_chan, self.vps.len() as u32)
+    pub fn into_params(self) -> (mesh::Sender<DebugRequest>, u32, u8) {
+        (self.req_chan, self.vps.len() as u32, self.default_vtl)
     }
 
     pub fn send_req(&mut self, req: DebugRequest) {
@@ -63,7 +73,28 @@ pub fn vp_to_tid(&self, vp: u32) -> Tid {
         NonZeroUsize::new(vp as usize + 1).unwrap()
     }
 
-    #[expect(dead_code)] // TODO: add monitor command to inspect physical memory?
+    /// Switches back to the VTL to debug when a client connects.
+    pub fn reset_vtl(&mut self) -> anyhow::Result<()> {
+        self.set_vtl(self.default_vtl)
+    }
+
+    /// Switches the VTL whose registers, memory, and breakpoints the debugger
+    /// accesses.
+    pub fn set_vtl(&mut self, vtl: u8) -> anyhow::Result<()> {
+        block_on(self.req_chan.call_failable(DebugRequest::SetVtl, vtl))
+            .context("failed to switch vtl")?;
+        self.vtl = vtl;
+        self.hw_debug = block_on(self.req_chan.call(DebugRequest::HardwareDebugSupported, ()))
+            .context("failed to query hardware debugging support")?;
+        if !self.hw_debug {
+            tracing::info!(
+                vtl,
+                "hardware breakpoints and single stepping are not supported"
+            );
+        }
+        Ok(())
+    }
+
     fn read_guest_physical_memory(&mut self, gpa: u64, data: &mut [u8]) -> anyhow::Result<()> {
         let buf = block_on(self.req_chan.call_failable(
             DebugRequest::ReadMemory,
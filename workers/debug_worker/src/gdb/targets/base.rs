@@ -148,7 +148,10 @@ fn write_register(&mut self, tid: Tid, reg_id: T::RegId, val: &[u8]) -> TargetRe
 
 impl<T: TargetArch> MultiThreadResume for VmTarget<'_, T> {
     fn resume(&mut self) -> Result<(), Self::Error> {
-        for (vp_index, vp) in self.0.vps.iter().enumerate() {
+        // Without hardware debugging support there are no breakpoints or
+        // single steps to apply.
+        let vps = if self.0.hw_debug { &*self.0.vps } else { &[] };
+        for (vp_index, vp) in vps.iter().enumerate() {
             let state = DebugState {
                 single_step: vp.single_step,
                 breakpoints: self.breakpoints,
@@ -187,7 +190,7 @@ fn set_resume_action_continue(
 
     #[inline(always)]
     fn support_single_step(&mut self) -> Option<MultiThreadSingleStepOps<'_, Self>> {
-        Some(self)
+        if self.0.hw_debug { Some(self) } else { None }
     }
 }
 
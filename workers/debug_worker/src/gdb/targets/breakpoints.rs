@@ -24,14 +24,14 @@ fn support_sw_breakpoint(
     fn support_hw_breakpoint(
         &mut self,
     ) -> Option<target::ext::breakpoints::HwBreakpointOps<'_, Self>> {
-        Some(self)
+        if self.hw_debug { Some(self) } else { None }
     }
 
     #[inline(always)]
     fn support_hw_watchpoint(
         &mut self,
     ) -> Option<target::ext::breakpoints::HwWatchpointOps<'_, Self>> {
-        Some(self)
+        if self.hw_debug { Some(self) } else { None }
     }
 }
 
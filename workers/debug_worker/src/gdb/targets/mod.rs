@@ -12,6 +12,7 @@
 
 mod base;
 mod breakpoints;
+mod monitor;
 mod target_aarch64;
 mod target_i8086;
 mod target_x86_64_qemu;
@@ -140,6 +141,13 @@ fn support_target_description_xml_override(
         T::support_target_description_xml_override(self)
     }
 
+    #[inline(always)]
+    fn support_monitor_cmd(
+        &mut self,
+    ) -> Option<gdbstub::target::ext::monitor_cmd::MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
+
     #[inline(always)]
     fn support_breakpoints(
         &mut self,
@@ -0,0 +1,85 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! `monitor` commands, which GDB passes through to the stub.
+
+use super::TargetArch;
+use super::VmTarget;
+use gdbstub::outputln;
+use gdbstub::target::ext::monitor_cmd::ConsoleOutput;
+use gdbstub::target::ext::monitor_cmd::MonitorCmd;
+
+const HELP: &str = "\
+vtl               -- show the VTL being debugged
+vtl <0|1|2>       -- debug another VTL, such as OpenHCL in VTL2
+phys <addr> [len] -- dump guest physical memory (default 64 bytes)";
+
+/// The most guest physical memory to dump at once.
+const MAX_PHYS_LEN: u64 = 0x1000;
+
+impl<T: TargetArch> MonitorCmd for VmTarget<'_, T> {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let Ok(cmd) = std::str::from_utf8(cmd) else {
+            outputln!(out, "invalid command");
+            return Ok(());
+        };
+        let mut args = cmd.split_ascii_whitespace();
+        match args.next() {
+            None | Some("help") => outputln!(out, "{HELP}"),
+            Some("vtl") => match args.next() {
+                None => outputln!(out, "debugging vtl{}", self.vtl),
+                Some(vtl) => match vtl.trim_start_matches("vtl").parse::<u8>() {
+                    Ok(vtl) => match self.set_vtl(vtl) {
+                        Ok(()) => {
+                            outputln!(out, "debugging vtl{vtl}");
+                            // GDB caches registers while the target is
+                            // stopped.
+                            outputln!(out, "run `flushregs` to refresh the registers");
+                        }
+                        Err(err) => outputln!(out, "{err:#}"),
+                    },
+                    Err(_) => outputln!(out, "invalid vtl: {vtl}"),
+                },
+            },
+            Some("phys") => {
+                let Some(addr) = args.next().and_then(parse_u64) else {
+                    outputln!(out, "usage: phys <addr> [len]");
+                    return Ok(());
+                };
+                let len = match args.next().map(parse_u64) {
+                    None => 64,
+                    Some(Some(len)) if len <= MAX_PHYS_LEN => len,
+                    Some(_) => {
+                        outputln!(out, "invalid length, the maximum is {MAX_PHYS_LEN:#x}");
+                        return Ok(());
+                    }
+                };
+                let mut data = vec![0; len as usize];
+                if let Err(err) = self.read_guest_physical_memory(addr, &mut data) {
+                    outputln!(out, "{err:#}");
+                    return Ok(());
+                }
+                for (i, line) in data.chunks(16).enumerate() {
+                    let mut text = format!("{:016x}:", addr.wrapping_add(i as u64 * 16));
+                    for b in line {
+                        text += &format!(" {b:02x}");
+                    }
+                    outputln!(out, "{text}");
+                }
+            }
+            Some(cmd) => outputln!(out, "unknown command: {cmd}\n{HELP}"),
+        }
+        Ok(())
+    }
+}
+
+fn parse_u64(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
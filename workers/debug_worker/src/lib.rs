@@ -84,7 +84,7 @@ fn new(params: Self::Parameters) -> anyhow::Result<Self> {
         Ok(Self {
             listener: params.listener,
             state: State::Listening {
-                vm_proxy: VmProxy::new(params.req_chan, params.vp_count),
+                vm_proxy: VmProxy::new(params.req_chan, params.vp_count, params.vtl),
             },
             initial_arch: match params.target_arch {
                 debug_worker_defs::TargetArch::X86_64 => Architecture::X86_64,
@@ -135,7 +135,7 @@ fn run(self, mut rpc_recv: mesh::Receiver<WorkerRpc<Self::Parameters>>) -> anyho
                             };
 
                             let state = {
-                                let (req_chan, vp_count) = vm_proxy.into_params();
+                                let (req_chan, vp_count, vtl) = vm_proxy.into_params();
                                 DebuggerParameters {
                                     listener: server.listener.into_inner(),
                                     req_chan,
@@ -149,6 +149,7 @@ fn run(self, mut rpc_recv: mesh::Receiver<WorkerRpc<Self::Parameters>>) -> anyho
                                             debug_worker_defs::TargetArch::Aarch64
                                         }
                                     },
+                                    vtl,
                                 }
                             };
                             rpc.complete(Ok(state));
@@ -281,6 +282,8 @@ async fn run_state_machine<T: TargetArch>(
     use gdbstub::stub::state_machine::GdbStubStateMachine;
 
     vm_target.send_req(DebugRequest::Attach);
+    // A previous connection may have switched to another VTL.
+    vm_target.reset_vtl().map_err(GdbStubError::TargetError)?;
     let (init_break_send, init_break_recv) = mesh::oneshot();
     vm_target.send_req(DebugRequest::Resume {
         response: init_break_send,
@@ -18,6 +18,8 @@ pub struct DebuggerParameters<T> {
     pub req_chan: mesh::Sender<DebugRequest>,
     pub vp_count: u32,
     pub target_arch: TargetArch,
+    /// The VTL to debug when a client connects.
+    pub vtl: u8,
 }
 
 #[derive(Debug, Copy, Clone, Protobuf)]
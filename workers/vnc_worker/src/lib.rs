@@ -97,6 +97,7 @@ fn new_inner(params: VncParameters<T>) -> anyhow::Result<Self> {
                 ),
                 input: VncInput {
                     send: params.input_send,
+                    resolution_send: params.resolution_send,
                 },
             },
         })
@@ -145,6 +146,7 @@ fn run_inner(
                     listener: server.listener.into_inner(),
                     framebuffer: view.0.access(),
                     input_send: input.send,
+                    resolution_send: input.resolution_send,
                 };
                 rpc.complete(Ok(state));
             }
@@ -245,6 +247,7 @@ fn inspect(&self, req: inspect::Request<'_>) {
 
 struct VncInput {
     send: mesh::Sender<InputData>,
+    resolution_send: Option<mesh::Sender<(u16, u16)>>,
 }
 
 impl vnc::Input for VncInput {
@@ -260,6 +263,15 @@ fn mouse(&mut self, button_mask: u8, x: u16, y: u16) {
         self.send
             .send(InputData::Mouse(MouseData { button_mask, x, y }));
     }
+
+    fn set_desktop_size(&mut self, width: u16, height: u16) -> bool {
+        if let Some(send) = &self.resolution_send {
+            send.send((width, height));
+            true
+        } else {
+            false
+        }
+    }
 }
 
 struct ViewWrapper(framebuffer::View);
@@ -69,6 +69,69 @@ pub fn update(&self) {
 pub trait Input {
     fn key(&mut self, scancode: u16, is_down: bool);
     fn mouse(&mut self, button_mask: u8, x: u16, y: u16);
+
+    /// Asks the guest to change its display resolution to match the client's
+    /// window. Returns false if the resolution cannot be changed.
+    fn set_desktop_size(&mut self, width: u16, height: u16) -> bool {
+        let _ = (width, height);
+        false
+    }
+}
+
+/// Encodes a framebuffer update telling the client the new desktop size.
+///
+/// If the client supports the extended desktop size encoding, `extended` is
+/// the reason for the change and the status of the client's request, if any.
+fn desktop_size_update(width: u16, height: u16, extended: Option<(u16, u16)>) -> Vec<u8> {
+    let mut msg = rfb::FramebufferUpdate {
+        message_type: rfb::SC_MESSAGE_TYPE_FRAMEBUFFER_UPDATE,
+        padding: 0,
+        rectangle_count: 1.into(),
+    }
+    .as_bytes()
+    .to_vec();
+    if let Some((reason, status)) = extended {
+        msg.extend_from_slice(
+            rfb::Rectangle {
+                x: reason.into(),
+                y: status.into(),
+                width: width.into(),
+                height: height.into(),
+                encoding_type: rfb::ENCODING_TYPE_EXTENDED_DESKTOP_SIZE.into(),
+            }
+            .as_bytes(),
+        );
+        msg.extend_from_slice(
+            rfb::ExtendedDesktopSize {
+                screen_count: 1,
+                padding: [0; 3],
+            }
+            .as_bytes(),
+        );
+        msg.extend_from_slice(
+            rfb::Screen {
+                id: 0.into(),
+                x: 0.into(),
+                y: 0.into(),
+                width: width.into(),
+                height: height.into(),
+                flags: 0.into(),
+            }
+            .as_bytes(),
+        );
+    } else {
+        msg.extend_from_slice(
+            rfb::Rectangle {
+                x: 0.into(),
+                y: 0.into(),
+                width: width.into(),
+                height: height.into(),
+                encoding_type: rfb::ENCODING_TYPE_DESKTOP_SIZE.into(),
+            }
+            .as_bytes(),
+        );
+    }
+    msg
 }
 
 impl<F: Framebuffer, I: Input> Server<F, I> {
@@ -167,6 +230,7 @@ async fn run_internal(&mut self) -> Result<(), Error> {
         socket.write_all(name).await?;
 
         let mut ready_for_update = false;
+        let mut extended_desktop_size = false;
         let mut scancode_state = scancode::State::new();
         loop {
             let mut socket_ready = false;
@@ -195,28 +259,15 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                     // Send the new desktop size.
                     width = new_width;
                     height = new_height;
-                    socket
-                        .write_all(
-                            rfb::FramebufferUpdate {
-                                message_type: rfb::SC_MESSAGE_TYPE_FRAMEBUFFER_UPDATE,
-                                padding: 0,
-                                rectangle_count: 1.into(),
-                            }
-                            .as_bytes(),
-                        )
-                        .await?;
-                    socket
-                        .write_all(
-                            rfb::Rectangle {
-                                x: 0.into(),
-                                y: 0.into(),
-                                width: width.into(),
-                                height: height.into(),
-                                encoding_type: rfb::ENCODING_TYPE_DESKTOP_SIZE.into(),
-                            }
-                            .as_bytes(),
-                        )
-                        .await?;
+                    let msg = desktop_size_update(
+                        width,
+                        height,
+                        extended_desktop_size.then_some((
+                            rfb::DESKTOP_SIZE_REASON_SERVER,
+                            rfb::DESKTOP_SIZE_STATUS_OK,
+                        )),
+                    );
+                    socket.write_all(&msg).await?;
                 } else {
                     // Send the update. Just update the whole framebuffer for now.
                     socket
@@ -319,11 +370,30 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         let mut encodings: Vec<zerocopy::U32<zerocopy::BE>> =
                             vec![0.into(); input.encoding_count.get().into()];
                         socket.read_exact(encodings.as_mut_bytes()).await?;
-                        if !encodings.contains(&rfb::ENCODING_TYPE_DESKTOP_SIZE.into()) {
+                        let had_extended_desktop_size = extended_desktop_size;
+                        extended_desktop_size =
+                            encodings.contains(&rfb::ENCODING_TYPE_EXTENDED_DESKTOP_SIZE.into());
+                        if !extended_desktop_size
+                            && !encodings.contains(&rfb::ENCODING_TYPE_DESKTOP_SIZE.into())
+                        {
                             // Can't really operate without being able to change the desktop size dynamically.
                             return Err(Error::DesktopResizeNotSupported);
                         }
 
+                        if extended_desktop_size && !had_extended_desktop_size {
+                            // Describe the screen layout, which also tells the
+                            // client that it can request a new desktop size.
+                            let msg = desktop_size_update(
+                                width,
+                                height,
+                                Some((
+                                    rfb::DESKTOP_SIZE_REASON_SERVER,
+                                    rfb::DESKTOP_SIZE_STATUS_OK,
+                                )),
+                            );
+                            socket.write_all(&msg).await?;
+                        }
+
                         if encodings.contains(&rfb::ENCODING_TYPE_QEMU_EXTENDED_KEY_EVENT.into()) {
                             // Request qemu extended key events.
                             let mut msg = rfb::FramebufferUpdate {
@@ -388,8 +458,9 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                                 });
                             }
 
-                            // make sure that the clipboard only contains printable ASCII chars
-                            if self.clipboard.chars().all(|c| (' '..='~').contains(&c)) {
+                            // make sure that the clipboard only contains
+                            // characters that can be typed on a US keyboard
+                            if self.clipboard.chars().all(scancode::is_typeable) {
                                 for c in self.clipboard.as_bytes() {
                                     let i = &mut self.input;
                                     scancode_state.emit_ascii_char(*c, true, |scancode, down| {
@@ -438,7 +509,37 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         let mut text_latin1 = vec![0; input.length.get() as usize];
                         socket.read_exact(&mut text_latin1).await?;
                         // Latin1 characters map to the first 256 characters of Unicode (roughly).
-                        self.clipboard = text_latin1.iter().copied().map(|c| c as char).collect();
+                        let text: String = text_latin1.iter().copied().map(|c| c as char).collect();
+                        // Type line breaks as a single enter key press.
+                        self.clipboard = text.replace("\r\n", "\n").replace('\r', "\n");
+                    }
+                    rfb::CS_MESSAGE_SET_DESKTOP_SIZE => {
+                        let mut input = rfb::SetDesktopSize::new_zeroed();
+                        socket.read_exact(&mut input.as_mut_bytes()[1..]).await?;
+                        let mut screens =
+                            vec![rfb::Screen::new_zeroed(); input.screen_count.into()];
+                        socket.read_exact(screens.as_mut_bytes()).await?;
+
+                        // Only a single screen covering the whole desktop is
+                        // supported.
+                        let (new_width, new_height) = (input.width.get(), input.height.get());
+                        let status = if screens.len() != 1 || new_width == 0 || new_height == 0 {
+                            rfb::DESKTOP_SIZE_STATUS_INVALID
+                        } else if self.input.set_desktop_size(new_width, new_height) {
+                            rfb::DESKTOP_SIZE_STATUS_OK
+                        } else {
+                            rfb::DESKTOP_SIZE_STATUS_PROHIBITED
+                        };
+
+                        // The guest changes its resolution asynchronously, so
+                        // report the current size. The client is sent the new
+                        // size once the guest switches to it.
+                        let msg = desktop_size_update(
+                            width,
+                            height,
+                            Some((rfb::DESKTOP_SIZE_REASON_CLIENT, status)),
+                        );
+                        socket.write_all(&msg).await?;
                     }
                     rfb::CS_MESSAGE_QEMU => {
                         let mut input = rfb::QemuMessageHeader::new_zeroed();
@@ -95,6 +95,7 @@ pub struct PixelFormat {
 pub const CS_MESSAGE_KEY_EVENT: u8 = 4;
 pub const CS_MESSAGE_POINTER_EVENT: u8 = 5;
 pub const CS_MESSAGE_CLIENT_CUT_TEXT: u8 = 6;
+pub const CS_MESSAGE_SET_DESKTOP_SIZE: u8 = 251;
 pub const CS_MESSAGE_QEMU: u8 = 255;
 
 #[repr(C)]
@@ -127,6 +128,7 @@ pub struct SetEncodings {
 
 pub const ENCODING_TYPE_DESKTOP_SIZE: u32 = -223i32 as u32;
 pub const ENCODING_TYPE_QEMU_EXTENDED_KEY_EVENT: u32 = -258i32 as u32;
+pub const ENCODING_TYPE_EXTENDED_DESKTOP_SIZE: u32 = -308i32 as u32;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
@@ -166,6 +168,29 @@ pub struct ClientCutText {
     // text: [u8; N],
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct SetDesktopSize {
+    pub message_type: u8,
+    pub padding: u8,
+    pub width: u16_be,
+    pub height: u16_be,
+    pub screen_count: u8,
+    pub padding2: u8,
+    // screens: [Screen; N],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Screen {
+    pub id: u32_be,
+    pub x: u16_be,
+    pub y: u16_be,
+    pub width: u16_be,
+    pub height: u16_be,
+    pub flags: u32_be,
+}
+
 // Server to client messages
 
 pub const SC_MESSAGE_TYPE_FRAMEBUFFER_UPDATE: u8 = 0;
@@ -193,6 +218,23 @@ pub struct Rectangle {
     // data: ...
 }
 
+/// The data of an extended desktop size rectangle, whose x position is the
+/// reason for the change and y position is the status of a client request.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ExtendedDesktopSize {
+    pub screen_count: u8,
+    pub padding: [u8; 3],
+    // screens: [Screen; N],
+}
+
+pub const DESKTOP_SIZE_REASON_SERVER: u16 = 0;
+pub const DESKTOP_SIZE_REASON_CLIENT: u16 = 1;
+
+pub const DESKTOP_SIZE_STATUS_OK: u16 = 0;
+pub const DESKTOP_SIZE_STATUS_PROHIBITED: u16 = 1;
+pub const DESKTOP_SIZE_STATUS_INVALID: u16 = 3;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
 pub struct SetColorMapEntries {
@@ -199,6 +199,12 @@ fn keysym_to_scancode(keysym: u16) -> Option<u32> {
     }
 }
 
+/// Returns whether `c` can be typed with [`State::emit_ascii_char`]: it is in
+/// the ASCII printable range (' ' to '~'), a tab, or a newline.
+pub fn is_typeable(c: char) -> bool {
+    (' '..='~').contains(&c) || c == '\t' || c == '\n'
+}
+
 /// Scancode tracking state.
 pub struct State {
     lshift: bool,
@@ -215,9 +221,14 @@ pub fn new() -> Self {
     }
 
     /// Emits scancodes (by calling `f`) corresponding to the provided ASCII char.
-    /// Panics if `c` is outside of the ASCII printable range (' ' to '~').
+    /// Panics if `c` is not [typeable](is_typeable).
     pub fn emit_ascii_char<F: FnMut(u16, bool)>(&mut self, c: u8, down: bool, f: F) {
-        self.emit_us_scancode(ASCII_TO_US[(c - ASCII_PRINT_START as u8) as usize], down, f)
+        let scancode = match c {
+            b'\t' => keysym_to_scancode(KEYSYM_TAB).unwrap(),
+            b'\n' => keysym_to_scancode(KEYSYM_RETURN_OR_ENTER).unwrap(),
+            c => ASCII_TO_US[(c - ASCII_PRINT_START as u8) as usize],
+        };
+        self.emit_us_scancode(scancode, down, f)
     }
 
     /// Emits scancodes (by calling `f`) corresponding to the provided US keyboard scancode.
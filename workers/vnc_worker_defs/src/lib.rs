@@ -17,6 +17,9 @@ pub struct VncParameters<T> {
     pub framebuffer: framebuffer::FramebufferAccess,
     /// A channel to send input to.
     pub input_send: mesh::Sender<input_core::InputData>,
+    /// A channel to send display resolution requests to, as (width, height),
+    /// when the client resizes its window.
+    pub resolution_send: Option<mesh::Sender<(u16, u16)>>,
 }
 
 pub const VNC_WORKER_TCP: WorkerId<VncParameters<TcpListener>> = WorkerId::new("VncWorkerTcp");